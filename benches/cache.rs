@@ -0,0 +1,102 @@
+//! 缓存基准
+//!
+//! 单线程的命中/未命中/插入开销，以及多线程同时读写时的争用情况
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rat_quickdns::resolver::cache::DnsCache;
+use rat_quickdns::{DnsResponseWrapper, QClass, Query, RecordType};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 预热的域名数量
+const WARM_ENTRIES: usize = 1_000;
+
+fn query(index: usize) -> Query {
+    Query {
+        name: format!("host-{}.example.com", index),
+        qtype: RecordType::A,
+        qclass: QClass::IN,
+    }
+}
+
+fn warm_cache() -> (Arc<DnsCache>, Vec<Query>) {
+    let cache = Arc::new(DnsCache::new(Duration::from_secs(3600)));
+    let queries: Vec<Query> = (0..WARM_ENTRIES).map(query).collect();
+    for (index, query) in queries.iter().enumerate() {
+        let response = DnsResponseWrapper::create_a_response(
+            0,
+            &query.name,
+            &[Ipv4Addr::from(0xC000_0200 + index as u32)],
+            300,
+        );
+        cache.insert(query.clone(), response);
+    }
+    (cache, queries)
+}
+
+fn bench_single_thread(c: &mut Criterion) {
+    let (cache, queries) = warm_cache();
+    let miss = query(WARM_ENTRIES + 1);
+    let response = DnsResponseWrapper::create_a_response(0, &miss.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+
+    let mut group = c.benchmark_group("cache");
+    group.bench_function("hit", |b| {
+        let mut index = 0;
+        b.iter(|| {
+            index = (index + 1) % queries.len();
+            black_box(cache.get(&queries[index]))
+        })
+    });
+    group.bench_function("miss", |b| b.iter(|| black_box(cache.get(&miss))));
+    group.bench_function("insert", |b| {
+        b.iter(|| cache.insert(miss.clone(), response.clone()))
+    });
+    group.finish();
+}
+
+/// `threads` 个线程同时运行，每个线程9次读1次写
+fn bench_contention(c: &mut Criterion) {
+    let (cache, queries) = warm_cache();
+    let queries = Arc::new(queries);
+
+    let mut group = c.benchmark_group("cache_contention");
+    for threads in [2, 4, 8] {
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &threads| {
+            b.iter_custom(|iters| {
+                let per_thread = iters / threads as u64 + 1;
+                let start = Instant::now();
+                let workers: Vec<_> = (0..threads)
+                    .map(|worker| {
+                        let cache = cache.clone();
+                        let queries = queries.clone();
+                        std::thread::spawn(move || {
+                            for i in 0..per_thread as usize {
+                                let query = &queries[(worker * 7919 + i) % queries.len()];
+                                if i % 10 == 9 {
+                                    let response = DnsResponseWrapper::create_a_response(
+                                        0,
+                                        &query.name,
+                                        &[Ipv4Addr::new(192, 0, 2, 1)],
+                                        300,
+                                    );
+                                    cache.insert(query.clone(), response);
+                                } else {
+                                    black_box(cache.get(query));
+                                }
+                            }
+                        })
+                    })
+                    .collect();
+                for worker in workers {
+                    worker.join().unwrap();
+                }
+                start.elapsed()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_single_thread, bench_contention);
+criterion_main!(benches);
@@ -0,0 +1,93 @@
+//! 报文编解码基准
+//!
+//! 覆盖单条A记录、20条TXT记录以及使用名称压缩指针的响应，全部在内存中完成
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rat_quickdns::transport::UdpTransport;
+use rat_quickdns::{DnsResponseWrapper, Flags, QClass, Query, QueryPriority, RecordType, Request, Response};
+use std::net::Ipv4Addr;
+
+fn a_response() -> Response {
+    DnsResponseWrapper::create_a_response(0x1234, "www.example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+}
+
+fn txt_response() -> Response {
+    let texts: Vec<String> = (0..20)
+        .map(|i| format!("record-{:02} v=spf1 include:_spf{}.example.com ~all", i, i))
+        .collect();
+    let mut response = DnsResponseWrapper::create_txt_response(0x1234, "example.com", &texts[..1], 300);
+    let template = response.answers[0].clone();
+    response.answers = texts
+        .into_iter()
+        .map(|text| {
+            let mut record = template.clone();
+            record.data = rat_quickdns::RecordData::TXT(vec![text]);
+            record
+        })
+        .collect();
+    response
+}
+
+/// 手工构造的响应：问题为 www.example.com，10条A记录的名称都是指向问题名的压缩指针
+fn compressed_response_bytes() -> Vec<u8> {
+    let mut bytes = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 10, 0, 0, 0, 0];
+    for label in ["www", "example", "com"] {
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label.as_bytes());
+    }
+    bytes.extend_from_slice(&[0, 0, 1, 0, 1]);
+    for i in 0..10u8 {
+        bytes.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0x01, 0x2C, 0, 4, 192, 0, 2, i]);
+    }
+    bytes
+}
+
+fn request() -> Request {
+    Request {
+        id: 0x1234,
+        flags: Flags::default(),
+        query: Query {
+            name: "www.example.com".to_string(),
+            qtype: RecordType::A,
+            qclass: QClass::IN,
+        },
+        client_address: None,
+        enable_edns: false,
+        dnssec_ok: false,
+        wire_capture_limit: None,
+        priority: QueryPriority::Normal,
+    }
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    let request = request();
+    group.bench_function("request", |b| {
+        b.iter(|| UdpTransport::serialize_request(black_box(&request)).unwrap())
+    });
+    for (name, response) in [("a_response", a_response()), ("txt_20_records", txt_response())] {
+        group.bench_function(name, |b| {
+            b.iter(|| UdpTransport::serialize_response(black_box(&response)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    let cases = [
+        ("a_response", UdpTransport::serialize_response(&a_response()).unwrap()),
+        ("txt_20_records", UdpTransport::serialize_response(&txt_response()).unwrap()),
+        ("compressed_10_records", compressed_response_bytes()),
+    ];
+    for (name, bytes) in cases {
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| UdpTransport::deserialize_response(black_box(&bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode, bench_decode);
+criterion_main!(benches);
@@ -0,0 +1,109 @@
+//! 上游监控基准
+//!
+//! 16个线程同时记录查询结果：全部记到同一个上游，以及分散到16个上游。
+//! 每个上游独立加锁，分散时各线程互不等待，耗时应接近单线程。
+//! 另外比较关闭详细统计（`with_detailed_stats(false)`）时记录网络错误的开销
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rat_quickdns::resolver::health::{UpstreamConfig, UpstreamMonitor};
+use rat_quickdns::{DnsError, NetworkErrorKind};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 16;
+
+fn monitor() -> Arc<UpstreamMonitor> {
+    monitor_with_detailed_stats(true)
+}
+
+fn monitor_with_detailed_stats(detailed_stats: bool) -> Arc<UpstreamMonitor> {
+    Arc::new(UpstreamMonitor::with_config(
+        Duration::from_secs(30),
+        UpstreamConfig {
+            min_success_rate: 0.5,
+            max_avg_response_time: Duration::from_secs(5),
+            max_consecutive_failures: 5,
+            recovery_success_count: 3,
+            stats_window_size: 100,
+            max_unavailable_duration: Duration::from_secs(300),
+        },
+    ).with_detailed_stats(detailed_stats))
+}
+
+fn bench_record(c: &mut Criterion) {
+    let mut group = c.benchmark_group("monitor_record");
+    group.bench_function("single_thread", |b| {
+        let monitor = monitor();
+        let mut i = 0u64;
+        b.iter(|| {
+            i += 1;
+            if i % 10 == 0 {
+                monitor.record_failure("upstream-0");
+            } else {
+                monitor.record_success("upstream-0", Duration::from_millis(20));
+            }
+        })
+    });
+
+    for upstreams in [1, THREADS] {
+        group.bench_with_input(BenchmarkId::new("16_threads", upstreams), &upstreams, |b, &upstreams| {
+            let monitor = monitor();
+            let names: Arc<Vec<String>> = Arc::new((0..upstreams).map(|i| format!("upstream-{}", i)).collect());
+            b.iter_custom(|iters| {
+                let per_thread = iters / THREADS as u64 + 1;
+                let start = Instant::now();
+                let workers: Vec<_> = (0..THREADS)
+                    .map(|worker| {
+                        let monitor = monitor.clone();
+                        let names = names.clone();
+                        std::thread::spawn(move || {
+                            let name = &names[worker % names.len()];
+                            for i in 0..per_thread {
+                                if i % 10 == 0 {
+                                    monitor.record_failure(name);
+                                } else {
+                                    monitor.record_success(name, Duration::from_millis(20));
+                                }
+                            }
+                        })
+                    })
+                    .collect();
+                for worker in workers {
+                    worker.join().unwrap();
+                }
+                start.elapsed()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_detailed_stats(c: &mut Criterion) {
+    let mut group = c.benchmark_group("monitor_detailed_stats");
+    let error = DnsError::Network {
+        kind: NetworkErrorKind::TimedOut,
+        upstream: "192.0.2.53:53".to_string(),
+        message: String::new(),
+    };
+    for detailed_stats in [true, false] {
+        let label = if detailed_stats { "enabled" } else { "disabled" };
+        group.bench_function(label, |b| {
+            let monitor = monitor_with_detailed_stats(detailed_stats);
+            let peer = "192.0.2.53:53".parse().unwrap();
+            let mut i = 0u64;
+            b.iter(|| {
+                i += 1;
+                if i % 2 == 0 {
+                    monitor.record_error("upstream-0", &error);
+                } else {
+                    monitor.record_success("upstream-0", Duration::from_millis(20));
+                    monitor.record_peer("upstream-0", peer);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_record, bench_detailed_stats);
+criterion_main!(benches);
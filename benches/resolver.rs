@@ -0,0 +1,157 @@
+//! 解析器基准（需要 `test-util` 特性）
+//!
+//! - 不同查询策略在 N 个模拟传输下的调度开销
+//! - 经由进程内UDP服务器的端到端查询延迟
+//! - 开启与关闭统计收集时每次查询的开销
+//!
+//! 全部使用本地模拟上游，不访问外部网络
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rat_quickdns::resolver::CoreResolverConfig;
+use rat_quickdns::transport::mock::MockTransport;
+use rat_quickdns::transport::{TransportConfig, UdpTransport};
+use rat_quickdns::builder::DnsRecordType;
+use rat_quickdns::{CoreResolver, DnsQueryRequest, DnsResolverBuilder, DnsResponseWrapper, QClass, QueryStrategy, RecordType};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::runtime::Runtime;
+
+fn config(strategy: QueryStrategy) -> CoreResolverConfig {
+    CoreResolverConfig::new(
+        strategy,
+        Duration::from_secs(2),
+        0,
+        false,
+        Duration::from_secs(3600),
+        false,
+        Duration::from_secs(30),
+        53,
+        16,
+        true,
+        4096,
+        false,
+        rat_logger::LevelFilter::Off,
+        false,
+    )
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build tokio runtime")
+}
+
+fn mock_resolver(strategy: QueryStrategy, transports: usize) -> CoreResolver {
+    let mut resolver = CoreResolver::new(config(strategy));
+    for i in 0..transports {
+        let mock = MockTransport::new().with_a("bench.example.com", &[Ipv4Addr::new(192, 0, 2, i as u8)], 300);
+        resolver.add_named_transport(format!("mock-{}", i), Arc::new(mock));
+    }
+    resolver
+}
+
+fn bench_strategy_overhead(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("strategy_overhead");
+    for strategy in [QueryStrategy::Fifo, QueryStrategy::Smart, QueryStrategy::RoundRobin] {
+        for transports in [1, 3, 8] {
+            let resolver = mock_resolver(strategy, transports);
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", strategy), transports),
+                &resolver,
+                |b, resolver| {
+                    b.iter(|| {
+                        rt.block_on(resolver.query("bench.example.com", RecordType::A, QClass::IN))
+                            .unwrap()
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+/// 启动进程内UDP DNS服务器，对任何A查询回应一条记录
+async fn spawn_udp_server() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let Ok((len, peer)) = socket.recv_from(&mut buf).await else {
+                continue;
+            };
+            let Ok(request) = UdpTransport::deserialize_request(&buf[..len]) else {
+                continue;
+            };
+            let response = DnsResponseWrapper::create_a_response(
+                request.id,
+                &request.query.name,
+                &[Ipv4Addr::new(192, 0, 2, 53)],
+                300,
+            );
+            if let Ok(bytes) = UdpTransport::serialize_response(&response) {
+                let _ = socket.send_to(&bytes, peer).await;
+            }
+        }
+    });
+    addr
+}
+
+fn bench_end_to_end_udp(c: &mut Criterion) {
+    let rt = runtime();
+    let addr = rt.block_on(spawn_udp_server());
+    let mut resolver = CoreResolver::new(config(QueryStrategy::Fifo));
+    resolver.add_udp_transport(TransportConfig {
+        server: addr.ip().to_string(),
+        port: addr.port(),
+        timeout: Duration::from_secs(2),
+        tcp_fast_open: false,
+        tcp_nodelay: true,
+        pool_size: 1,
+        buffer_size: 4096,
+        record_limits: None,
+    });
+
+    c.bench_function("end_to_end_udp", |b| {
+        b.iter(|| {
+            rt.block_on(resolver.query("bench.example.com", RecordType::A, QClass::IN))
+                .unwrap()
+        })
+    });
+}
+
+/// 经过完整构建器解析器的查询：关闭缓存、开启上游监控，只比较统计收集的开销
+fn bench_stats_overhead(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("stats_overhead");
+    for enable_stats in [true, false] {
+        let resolver = rt.block_on(async {
+            let mock = MockTransport::new().with_a("bench.example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+            DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+                .disable_logger_init()
+                .with_cache(false)
+                .with_upstream_monitoring(true)
+                .with_stats(enable_stats)
+                .add_mock_upstream("mock", mock)
+                .unwrap()
+                .build()
+                .await
+                .unwrap()
+        });
+        let label = if enable_stats { "enabled" } else { "disabled" };
+        group.bench_function(label, |b| {
+            b.iter(|| {
+                rt.block_on(resolver.query(DnsQueryRequest::new("bench.example.com", DnsRecordType::A)))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_strategy_overhead, bench_end_to_end_udp, bench_stats_overhead);
+criterion_main!(benches);
@@ -0,0 +1,131 @@
+//! TCP分帧基准（需要 `test-util` 特性）
+//!
+//! - 请求分帧：先编码再拼接长度前缀（原做法）与编码到预留前缀的复用缓冲区的对比，
+//!   运行前打印两种做法每条请求的内存分配次数
+//! - 经由进程内TCP服务器的端到端查询
+//!
+//! 全部使用本地模拟上游，不访问外部网络
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rat_quickdns::transport::test_server::TestDnsServer;
+use rat_quickdns::transport::{TcpTransport, Transport, TransportConfig};
+use rat_quickdns::{Flags, QClass, Query, QueryPriority, RecordType, Request};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+/// 只统计当前线程在计数期间的分配次数
+struct CountingAllocator;
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = COUNTING.try_with(|counting| {
+            if counting.get() {
+                let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            }
+        });
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const QUERIES: u64 = 1000;
+
+fn request() -> Request {
+    Request {
+        id: 0x1234,
+        flags: Flags::default(),
+        query: Query {
+            name: "www.example.com".to_string(),
+            qtype: RecordType::A,
+            qclass: QClass::IN,
+        },
+        client_address: None,
+        enable_edns: true,
+        dnssec_ok: false,
+        wire_capture_limit: None,
+        priority: QueryPriority::Normal,
+    }
+}
+
+/// 原做法：编码出报文后另建一个带长度前缀的缓冲区，报文再复制一遍
+fn frame_by_prepending(request: &Request) -> Vec<u8> {
+    let message = TcpTransport::encode_request(request).unwrap();
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    framed.extend_from_slice(&message);
+    framed
+}
+
+/// `f` 执行 `QUERIES` 次的平均分配次数
+fn allocations_per_query(mut f: impl FnMut()) -> f64 {
+    ALLOCATIONS.with(|count| count.set(0));
+    COUNTING.with(|counting| counting.set(true));
+    for _ in 0..QUERIES {
+        f();
+    }
+    COUNTING.with(|counting| counting.set(false));
+    ALLOCATIONS.with(Cell::get) as f64 / QUERIES as f64
+}
+
+fn bench_frame_request(c: &mut Criterion) {
+    let request = request();
+    let mut buffer = Vec::new();
+    let prepending = allocations_per_query(|| {
+        black_box(frame_by_prepending(black_box(&request)));
+    });
+    let reused = allocations_per_query(|| {
+        TcpTransport::encode_request_framed(black_box(&request), &mut buffer).unwrap();
+        black_box(&buffer);
+    });
+    println!("每条请求的分配次数: 拼接长度前缀 {:.2}, 复用预留前缀的缓冲区 {:.2}", prepending, reused);
+
+    let mut group = c.benchmark_group("frame_request");
+    group.bench_function("prepend_copy", |b| b.iter(|| frame_by_prepending(black_box(&request))));
+    group.bench_function("reserved_prefix", |b| {
+        b.iter(|| {
+            TcpTransport::encode_request_framed(black_box(&request), &mut buffer).unwrap();
+            buffer.len()
+        })
+    });
+    group.finish();
+}
+
+fn bench_tcp_query(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build tokio runtime");
+    let server = runtime.block_on(TestDnsServer::start()).unwrap();
+    server.add_a("www.example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+    let transport = TcpTransport::new(TransportConfig {
+        server: "127.0.0.1".to_string(),
+        port: server.port(),
+        timeout: Duration::from_secs(2),
+        tcp_fast_open: false,
+        tcp_nodelay: true,
+        pool_size: 4,
+        buffer_size: 4096,
+        record_limits: None,
+    });
+    let request = request();
+
+    c.bench_function("tcp_query", |b| {
+        b.iter(|| runtime.block_on(transport.send(black_box(&request))).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_frame_request, bench_tcp_query);
+criterion_main!(benches);
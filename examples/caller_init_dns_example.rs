@@ -80,16 +80,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         // 使用DNS专用日志宏
         dns_query!(domain, "A");
 
-        let request = rat_quickdns::builder::types::DnsQueryRequest {
-            query_id: Some(format!("query-{}", domain)),
-            domain: domain.to_string(),
-            record_type: rat_quickdns::builder::types::DnsRecordType::A,
-            enable_edns: true,
-            client_address: None,
-            timeout_ms: None,
-            disable_cache: false,
-            enable_dnssec: false,
-        };
+        let request = rat_quickdns::builder::types::DnsQueryRequest::new(domain.to_string(), rat_quickdns::builder::types::DnsRecordType::A).with_query_id(format!("query-{}", domain));
 
         match resolver.query(request).await {
             Ok(response) => {
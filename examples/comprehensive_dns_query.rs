@@ -318,19 +318,9 @@ async fn perform_query(
 ) -> (bool, Duration, Option<String>) {
     let start = Instant::now();
     
-    // 将 RecordType 转换为 DnsRecordType
-    let dns_record_type = match test_case.record_type {
-        RecordType::A => rat_quickdns::builder::types::DnsRecordType::A,
-        RecordType::AAAA => rat_quickdns::builder::types::DnsRecordType::AAAA,
-        RecordType::CNAME => rat_quickdns::builder::types::DnsRecordType::CNAME,
-        RecordType::MX => rat_quickdns::builder::types::DnsRecordType::MX,
-        RecordType::TXT => rat_quickdns::builder::types::DnsRecordType::TXT,
-        RecordType::NS => rat_quickdns::builder::types::DnsRecordType::NS,
-        RecordType::SOA => rat_quickdns::builder::types::DnsRecordType::SOA,
-        RecordType::PTR => rat_quickdns::builder::types::DnsRecordType::PTR,
-        RecordType::SRV => rat_quickdns::builder::types::DnsRecordType::SRV,
-        _ => rat_quickdns::builder::types::DnsRecordType::A, // 默认为A记录
-    };
+    // 将 RecordType 转换为 DnsRecordType，不支持的类型按A记录查询
+    let dns_record_type = DnsRecordType::try_from(test_case.record_type)
+        .unwrap_or(DnsRecordType::A);
     
     let request = rat_quickdns::builder::types::DnsQueryRequest::new(
         &test_case.domain,
@@ -0,0 +1,69 @@
+//! 配置文件热加载示例
+//!
+//! 用法: cargo run --example config_reload -- <配置文件.toml> [轮询间隔秒数]
+//!
+//! 按TOML格式的严格配置（字段同 `tests/fixtures/strict_config.toml`）构建解析器，之后定期检查文件的修改时间，
+//! 文件变化时调用 [`SmartDnsResolver::reload`] 重新加载并打印结果。配置无效时保留原配置继续运行，
+//! 修正文件后下一次检查会再次加载
+
+use rat_quickdns::{DnsResolverBuilder, SmartDnsResolver, StrictDnsConfig};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+fn load(path: &Path) -> Result<(StrictDnsConfig, SystemTime), Box<dyn std::error::Error>> {
+    let modified = std::fs::metadata(path)?.modified()?;
+    let config = toml::from_str(&std::fs::read_to_string(path)?)?;
+    Ok((config, modified))
+}
+
+async fn reload(resolver: &SmartDnsResolver, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let (config, _) = load(path)?;
+    let report = resolver.reload(config).await?;
+    if report.is_unchanged() {
+        println!("配置没有变化");
+        return Ok(());
+    }
+    println!(
+        "已重新加载: 新增 {:?}，移除 {:?}，换用新连接 {:?}，修改 {:?}，设置变化 {:?}{}",
+        report.added, report.removed, report.reconnected, report.updated, report.changed_settings,
+        if report.cache_cleared { "，缓存已清空" } else { "" }
+    );
+    for change in &report.changes {
+        println!("  {}", change);
+    }
+    if !report.undrained.is_empty() {
+        println!("  移除时仍有查询未完成: {:?}", report.undrained);
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().ok_or("用法: config_reload <配置文件.toml> [轮询间隔秒数]")?;
+    let path = Path::new(&path);
+    let interval = Duration::from_secs(args.next().map(|s| s.parse()).transpose()?.unwrap_or(2));
+
+    let (config, mut last_modified) = load(path)?;
+    let resolver = DnsResolverBuilder::from_strict_config(&config, true, "global")?
+        .build()
+        .await?;
+    println!("已从 {} 加载 {} 个上游", path.display(), resolver.effective_config().upstreams.len());
+    println!("每 {:?} 检查一次修改时间，Ctrl+C 退出", interval);
+
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        // 文件正在被替换时可能暂时不存在，下一次再检查
+        let Ok(modified) = std::fs::metadata(path).and_then(|metadata| metadata.modified()) else {
+            continue;
+        };
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+        if let Err(e) = reload(&resolver, path).await {
+            println!("重新加载失败，保留原配置: {}", e);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! 多上游一致性比对示例
+//!
+//! 向国内和海外的多个上游同时查询同一域名，打印各上游答案的对比表，
+//! 并标出与多数答案不一致的上游（可能存在DNS污染或CDN调度差异）
+
+use rat_quickdns::{DnsResolverBuilder, Preset, QueryStrategy};
+use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let domains: Vec<String> = std::env::args().skip(1).collect();
+    let domains = if domains.is_empty() {
+        vec!["www.google.com".to_string(), "github.com".to_string()]
+    } else {
+        domains
+    };
+
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Smart, true, "global".to_string())
+        .with_preset(Preset::ChinaMainland)?
+        .with_preset(Preset::Global)?
+        .with_timeout(Duration::from_secs(3))
+        .with_concurrent_queries(4)
+        .with_silent_logger_init()
+        .build()
+        .await?;
+
+    for domain in domains {
+        let report = resolver
+            .query_all(&DnsQueryRequest::new(domain.clone(), DnsRecordType::A), false)
+            .await?;
+
+        println!("\n=== {} ===", domain);
+        println!("{:<18} {:<6} {:>8}  {}", "上游", "协议", "耗时", "答案");
+        for answer in &report.answers {
+            let (mark, text) = match &answer.result {
+                Ok(set) if Some(set) == report.consensus.majority.as_ref() => (" ", set.join(", ")),
+                Ok(set) => ("!", set.join(", ")),
+                Err(e) => ("x", format!("错误: {}", e)),
+            };
+            println!(
+                "{}{:<17} {:<6} {:>6}ms  {}",
+                mark,
+                answer.upstream,
+                answer.protocol,
+                answer.latency.as_millis(),
+                text
+            );
+        }
+
+        if report.consensus.is_unanimous() {
+            println!("所有成功的上游答案一致");
+        }
+        for dissent in &report.consensus.dissenting {
+            println!(
+                "⚠️  {} 与多数答案不一致: 缺少 [{}], 多出 [{}]",
+                dissent.upstream,
+                dissent.missing.join(", "),
+                dissent.extra.join(", ")
+            );
+        }
+    }
+
+    Ok(())
+}
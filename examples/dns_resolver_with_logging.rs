@@ -34,16 +34,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     .await?;
     
     println!("执行DNS查询（详细日志模式）...");
-    let request = DnsQueryRequest {
-        domain: "www.baidu.com".to_string(),
-        record_type: DnsRecordType::A,
-        query_id: Some("verbose-test".to_string()),
-        enable_edns: true,
-        client_address: None,
-        timeout_ms: None,
-        disable_cache: false,
-        enable_dnssec: false,
-    };
+    let request = DnsQueryRequest::new("www.baidu.com", DnsRecordType::A).with_query_id("verbose-test");
     
     match verbose_resolver.query(request).await {
         Ok(response) => {
@@ -72,16 +63,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await?;
     
     println!("执行DNS查询（静默日志模式）...");
-    let request2 = DnsQueryRequest {
-        domain: "www.google.com".to_string(),
-        record_type: DnsRecordType::A,
-        query_id: Some("quiet-test".to_string()),
-        enable_edns: true,
-        client_address: None,
-        timeout_ms: None,
-        disable_cache: false,
-        enable_dnssec: false,
-    };
+    let request2 = DnsQueryRequest::new("www.google.com", DnsRecordType::A).with_query_id("quiet-test");
     match quiet_resolver.query(request2).await {
         Ok(response) => {
             println!("✓ 查询成功，获得 {} 条记录", response.records.len());
@@ -110,16 +92,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await?;
     
     println!("执行DNS查询（自定义日志配置）...");
-    let request3 = DnsQueryRequest {
-        domain: "www.github.com".to_string(),
-        record_type: DnsRecordType::A,
-        query_id: Some("custom-test".to_string()),
-        enable_edns: true,
-        client_address: None,
-        timeout_ms: None,
-        disable_cache: false,
-        enable_dnssec: false,
-    };
+    let request3 = DnsQueryRequest::new("www.github.com", DnsRecordType::A).with_query_id("custom-test");
     match custom_resolver.query(request3).await {
         Ok(response) => {
             println!("✓ 查询成功，获得 {} 条记录", response.records.len());
@@ -147,16 +120,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await?;
     
     println!("执行DNS查询（标准日志格式）...");
-    let request4 = DnsQueryRequest {
-        domain: "www.stackoverflow.com".to_string(),
-        record_type: DnsRecordType::A,
-        query_id: Some("standard-test".to_string()),
-        enable_edns: true,
-        client_address: None,
-        timeout_ms: None,
-        disable_cache: false,
-        enable_dnssec: false,
-    };
+    let request4 = DnsQueryRequest::new("www.stackoverflow.com", DnsRecordType::A).with_query_id("standard-test");
     match standard_resolver.query(request4).await {
         Ok(response) => {
             println!("✓ 查询成功，获得 {} 条记录", response.records.len());
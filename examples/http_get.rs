@@ -0,0 +1,65 @@
+//! 用解析器的答案按主机名建立连接并发送HTTP请求（需要 `connect` 和 `test-util` 特性）
+//!
+//! 模拟上游把 `www.example.test` 解析为 `::1` 和 `127.0.0.1`，本地HTTP服务只监听IPv4：
+//! `connect_with_resolver` 先尝试IPv6，被拒绝后改连IPv4。不访问外部网络。
+//!
+//! ```bash
+//! cargo run --features connect,test-util --example http_get
+//! ```
+
+use rat_quickdns::builder::{connect_with_resolver, ConnectOptions};
+use rat_quickdns::transport::mock::MockTransport;
+use rat_quickdns::{DnsResolverBuilder, QueryStrategy};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const HOST: &str = "www.example.test";
+
+/// 只处理一个请求的本地HTTP服务
+async fn serve_once(listener: TcpListener) -> std::io::Result<()> {
+    let (mut stream, peer) = listener.accept().await?;
+    let mut request = vec![0u8; 1024];
+    let len = stream.read(&mut request).await?;
+    let first_line = String::from_utf8_lossy(&request[..len]).lines().next().unwrap_or_default().to_string();
+    println!("服务端收到来自 {} 的请求: {}", peer, first_line);
+    let body = "hello from rat_quickdns\n";
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let port = listener.local_addr()?.port();
+    let server = tokio::spawn(serve_once(listener));
+
+    let mock = MockTransport::new()
+        .with_a(HOST, &[Ipv4Addr::LOCALHOST], 300)
+        .with_aaaa(HOST, &[Ipv6Addr::LOCALHOST], 300);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("mock", mock)?
+        .build()
+        .await?;
+
+    let opts = ConnectOptions::default()
+        .with_per_attempt_timeout(Duration::from_secs(3))
+        .with_happy_eyeballs_delay(Duration::from_millis(250));
+    let mut stream = connect_with_resolver(&resolver, HOST, port, opts).await?;
+    println!("已连接 {} ({})", HOST, stream.peer_addr()?);
+
+    let request = format!("GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", HOST);
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    println!("{}", response);
+
+    server.await??;
+    Ok(())
+}
@@ -0,0 +1,66 @@
+//! 用hyper把解析器指标暴露为Prometheus抓取端点（需要 `test-util` 特性）
+//!
+//! 模拟上游应答几个查询后，在 `127.0.0.1:9464/metrics` 返回 [`SmartDnsResolver::render_openmetrics`] 的输出，
+//! 按Ctrl-C退出。不访问外部网络。
+//!
+//! ```bash
+//! cargo run --features test-util --example metrics_http
+//! curl http://127.0.0.1:9464/metrics
+//! ```
+
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use rat_quickdns::builder::{DnsQueryRequest, DnsRecordType, OPENMETRICS_CONTENT_TYPE};
+use rat_quickdns::transport::mock::MockTransport;
+use rat_quickdns::{DnsResolverBuilder, QueryStrategy, SmartDnsResolver};
+use std::convert::Infallible;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// `/metrics` 返回指标，其他路径返回404
+async fn handle(resolver: Arc<SmartDnsResolver>, request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    if request.uri().path() != "/metrics" {
+        let mut response = Response::new(Full::new(Bytes::from_static(b"not found\n")));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+    let body = resolver.render_openmetrics().await;
+    let response = Response::builder()
+        .header("Content-Type", OPENMETRICS_CONTENT_TYPE)
+        .body(Full::new(Bytes::from(body)))
+        .expect("static response parts are valid");
+    Ok(response)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mock = MockTransport::new().with_a("www.example.test", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+    let resolver = Arc::new(
+        DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .add_mock_upstream("mock", mock)?
+            .build()
+            .await?,
+    );
+    for _ in 0..3 {
+        resolver.query(DnsQueryRequest::new("www.example.test", DnsRecordType::A)).await?;
+    }
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 9464)).await?;
+    println!("指标地址: http://{}/metrics", listener.local_addr()?);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let resolver = resolver.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| handle(resolver.clone(), request));
+            if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+                eprintln!("连接出错: {}", e);
+            }
+        });
+    }
+}
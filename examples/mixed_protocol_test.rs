@@ -30,16 +30,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("DoH解析器已创建");
     
     // 测试 DoH 解析
-    let request = DnsQueryRequest {
-        query_id: None,
-        domain: "example.com".to_string(),
-        record_type: DnsRecordType::A,
-        enable_edns: true,
-        client_address: None,
-        timeout_ms: None,
-        disable_cache: false,
-        enable_dnssec: false,
-    };
+    let request = DnsQueryRequest::new("example.com", DnsRecordType::A);
     
     match doh_resolver.query(request).await {
         Ok(response) => {
@@ -70,16 +61,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     println!("DoT解析器已创建");
     
-    let request = DnsQueryRequest {
-        query_id: None,
-        domain: "example.com".to_string(),
-        record_type: DnsRecordType::A,
-        enable_edns: true,
-        client_address: None,
-        timeout_ms: None,
-        disable_cache: false,
-        enable_dnssec: false,
-    };
+    let request = DnsQueryRequest::new("example.com", DnsRecordType::A);
     
     match dot_resolver.query(request).await {
         Ok(response) => {
@@ -122,16 +104,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     for domain in &test_domains {
         println!("混合协议查询域名: {}", domain);
-        let request = DnsQueryRequest {
-            query_id: None,
-            domain: domain.to_string(),
-            record_type: DnsRecordType::A,
-            enable_edns: true,
-            client_address: None,
-            timeout_ms: None,
-            disable_cache: false,
-            enable_dnssec: false,
-        };
+        let request = DnsQueryRequest::new(domain.to_string(), DnsRecordType::A);
         
         match mixed_resolver.query(request).await {
             Ok(response) => {
@@ -0,0 +1,78 @@
+//! 线路格式报文回放示例
+//!
+//! 用法: cargo run --example replay_wire -- [--tcp] <文件>
+//!
+//! 用 `UdpTransport::deserialize_response` 解析保存下来的原始响应报文并打印各段内容，
+//! 用于排查新收录的报文和编写 `tests/fixtures/wire/` 下的描述文件。
+//! 从TCP流中直接保存的报文带2字节长度前缀，加 `--tcp` 去掉前缀后再解析
+
+use rat_quickdns::transport::UdpTransport;
+use rat_quickdns::{ClientAddress, Record};
+
+fn print_section(title: &str, records: &[Record]) {
+    if records.is_empty() {
+        return;
+    }
+    println!(";; {} ({}):", title, records.len());
+    for record in records {
+        let name = if record.name.is_empty() { "." } else { &record.name };
+        println!("{}\t{}\t{:?}\t{}\t{:?}", name, record.ttl, record.class, record.rtype, record.data);
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut tcp = false;
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--tcp" => tcp = true,
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.ok_or("用法: replay_wire [--tcp] <文件>")?;
+
+    let mut data = std::fs::read(&path)?;
+    if tcp {
+        if data.len() < 2 {
+            return Err("TCP报文缺少长度前缀".into());
+        }
+        let length = u16::from_be_bytes([data[0], data[1]]) as usize;
+        data.drain(..2);
+        if length != data.len() {
+            println!(";; 警告: 长度前缀为 {} 字节，实际报文 {} 字节", length, data.len());
+        }
+    }
+
+    let response = UdpTransport::deserialize_response(&data)?;
+    let flags = &response.flags;
+    println!(";; {} 字节, ID {}, RCODE {}", data.len(), response.id, flags.rcode);
+    println!(
+        ";; 标志: qr={} aa={} tc={} rd={} ra={} ad={} cd={}",
+        flags.qr, flags.aa, flags.tc, flags.rd, flags.ra, flags.ad, flags.cd
+    );
+
+    if let Some(edns) = UdpTransport::parse_edns(&data)? {
+        println!(
+            ";; EDNS: 版本 {}, 载荷 {} 字节, DO={}",
+            edns.version, edns.udp_payload_size, edns.dnssec_ok
+        );
+        for option in &edns.options {
+            match ClientAddress::decode(&option.data) {
+                Ok(subnet) if option.code == 8 => println!(
+                    ";;   客户端子网: {}/{} 作用域 /{}",
+                    subnet.address, subnet.source_prefix_length, subnet.scope_prefix_length
+                ),
+                _ => println!(";;   选项 {}: {} 字节", option.code, option.data.len()),
+            }
+        }
+    }
+
+    println!(";; 问题 ({}):", response.queries.len());
+    for query in &response.queries {
+        println!("{}\t{:?}\t{}", query.name, query.qclass, query.qtype);
+    }
+    print_section("回答", &response.answers);
+    print_section("权威", &response.authorities);
+    print_section("附加", &response.additionals);
+    Ok(())
+}
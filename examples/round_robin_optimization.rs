@@ -175,16 +175,7 @@ impl RoundRobinOptimizationDemo {
         for i in 0..iterations {
             let domain = self.test_domains[i % self.test_domains.len()];
             
-            let request = DnsQueryRequest {
-                query_id: Some(format!("test-{}", i)),
-                domain: domain.to_string(),
-                record_type: DnsRecordType::A,
-                enable_edns: true,
-                client_address: None,
-                timeout_ms: None,
-                disable_cache: false,
-                enable_dnssec: false,
-            };
+            let request = DnsQueryRequest::new(domain.to_string(), DnsRecordType::A).with_query_id(format!("test-{}", i));
             
             let query_start = Instant::now();
             match resolver.query(request).await {
@@ -0,0 +1,61 @@
+//! 共享解析器示例
+//!
+//! 应用启动时构建一个解析器并安装为默认解析器，两个互不依赖的模块都从注册表取用它，
+//! 共用同一份缓存和上游监控任务，而不是各自构建解析器
+
+use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+use rat_quickdns::{registry, DnsResolverBuilder, QueryStrategy};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 模拟一个只关心IPv4地址的HTTP客户端库
+mod http_client {
+    use super::*;
+
+    pub async fn connect(host: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let resolver = registry::default().ok_or("应用没有安装默认解析器")?;
+        let addresses = resolver.lookup_ip(host, DnsRecordType::A).await?;
+        println!("[http_client] 连接 {} -> {:?}", host, addresses);
+        Ok(())
+    }
+}
+
+/// 模拟一个检查邮件域名的库
+mod mail_checker {
+    use super::*;
+
+    pub async fn check(domain: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let resolver = registry::default().ok_or("应用没有安装默认解析器")?;
+        let response = resolver.query(DnsQueryRequest::new(domain, DnsRecordType::MX)).await?;
+        println!("[mail_checker] {} 有 {} 条MX记录", domain, response.records_of_type(DnsRecordType::MX).len());
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, true, "global".to_string())
+        .add_udp_upstream("阿里DNS", "223.5.5.5")
+        .add_udp_upstream("腾讯DNS", "119.29.29.29")
+        .with_timeout(Duration::from_secs(3))
+        .with_cache(true)
+        .with_silent_logger_init()
+        .build()
+        .await?;
+    registry::install_default(Arc::new(resolver))?;
+
+    http_client::connect("example.com").await?;
+    mail_checker::check("example.com").await?;
+    // 第二个模块再查同一域名时命中的是第一个模块写入的缓存
+    http_client::connect("example.com").await?;
+
+    let resolver = registry::default().ok_or("默认解析器丢失")?;
+    if let Some(cache) = resolver.cache_stats() {
+        println!("共享缓存: {:?}", cache);
+    }
+
+    // 关闭后解析器从注册表中移除，之后再取得的是 None
+    resolver.shutdown().await;
+    assert!(registry::default().is_none());
+    Ok(())
+}
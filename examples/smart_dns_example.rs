@@ -44,16 +44,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     for domain in &test_domains {
         println!("解析域名: {}", domain);
-        let request = DnsQueryRequest {
-            query_id: None,
-            domain: domain.to_string(),
-            record_type: DnsRecordType::A,
-            enable_edns: true,
-            client_address: None,
-            timeout_ms: None,
-            disable_cache: false,
-            enable_dnssec: false,
-        };
+        let request = DnsQueryRequest::new(domain.to_string(), DnsRecordType::A);
         match fifo_resolver.query(request).await {
             Ok(response) => {
                 if response.success {
@@ -92,16 +83,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 测试智能解析
     for domain in &test_domains {
         println!("智能解析域名: {}", domain);
-        let request = DnsQueryRequest {
-                query_id: None,
-                domain: domain.to_string(),
-                record_type: DnsRecordType::A,
-                enable_edns: true,
-                client_address: None,
-                timeout_ms: None,
-                disable_cache: false,
-                enable_dnssec: false,
-            };
+        let request = DnsQueryRequest::new(domain.to_string(), DnsRecordType::A);
         match smart_resolver.query(request).await {
             Ok(response) => {
                 if response.success {
@@ -140,16 +122,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let batch_domains = vec!["google.com", "github.com", "stackoverflow.com", "rust-lang.org"];
     
     for domain in &batch_domains {
-        let request = DnsQueryRequest {
-                 query_id: None,
-                 domain: domain.to_string(),
-                 record_type: DnsRecordType::A,
-                 enable_edns: true,
-                 client_address: None,
-                 timeout_ms: None,
-                 disable_cache: false,
-                 enable_dnssec: false,
-             };
+        let request = DnsQueryRequest::new(domain.to_string(), DnsRecordType::A);
         match smart_resolver.query(request).await {
             Ok(response) => {
                 if response.success {
@@ -173,16 +146,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     for (name, record_type) in &record_types {
         println!("查询 example.com 的 {} 记录:", name);
-        let request = DnsQueryRequest {
-                 query_id: None,
-                 domain: "example.com".to_string(),
-                 record_type: *record_type,
-                 enable_edns: true,
-                 client_address: None,
-                 timeout_ms: None,
-                 disable_cache: false,
-                 enable_dnssec: false,
-             };
+        let request = DnsQueryRequest::new("example.com", *record_type);
         match smart_resolver.query(request).await {
             Ok(response) => {
                 if response.success {
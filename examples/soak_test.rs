@@ -0,0 +1,202 @@
+//! 模拟上游压力测试（需要 `test-util` 特性）
+//!
+//! 以固定QPS持续向模拟上游发起查询，结束后打印吞吐、延迟分位数和内存分配统计，
+//! 最后一行为便于回归比对的单行摘要。不访问外部网络。
+//!
+//! ```bash
+//! cargo run --release --features test-util --example soak_test -- \
+//!     --qps 50000 --duration 10 --upstreams 3 --workers 256 --strategy fifo
+//! ```
+
+use rat_quickdns::builder::LatencyHistogram;
+use rat_quickdns::resolver::CoreResolverConfig;
+use rat_quickdns::transport::mock::MockTransport;
+use rat_quickdns::{CoreResolver, QClass, QueryStrategy, RecordType};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// 统计分配次数和字节数的全局分配器
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// 压测参数
+struct SoakOptions {
+    qps: u64,
+    duration: Duration,
+    upstreams: usize,
+    workers: usize,
+    strategy: QueryStrategy,
+    cache: bool,
+}
+
+impl SoakOptions {
+    fn from_args() -> Result<Self, String> {
+        let mut options = Self {
+            qps: 50_000,
+            duration: Duration::from_secs(10),
+            upstreams: 3,
+            workers: 256,
+            strategy: QueryStrategy::Fifo,
+            cache: false,
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(flag) = args.next() {
+            if flag == "--cache" {
+                options.cache = true;
+                continue;
+            }
+            let value = args.next().ok_or_else(|| format!("{} 缺少参数值", flag))?;
+            let number = || value.parse::<u64>().map_err(|e| format!("{} 的值无效: {}", flag, e));
+            match flag.as_str() {
+                "--qps" => options.qps = number()?.max(1),
+                "--duration" => options.duration = Duration::from_secs(number()?),
+                "--upstreams" => options.upstreams = number()?.max(1) as usize,
+                "--workers" => options.workers = number()?.max(1) as usize,
+                "--strategy" => {
+                    options.strategy = match value.to_ascii_lowercase().as_str() {
+                        "fifo" => QueryStrategy::Fifo,
+                        "smart" => QueryStrategy::Smart,
+                        "round_robin" | "roundrobin" => QueryStrategy::RoundRobin,
+                        other => return Err(format!("未知策略: {}", other)),
+                    }
+                },
+                other => return Err(format!("未知参数: {}", other)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn build_resolver(options: &SoakOptions) -> CoreResolver {
+    let config = CoreResolverConfig::new(
+        options.strategy,
+        Duration::from_secs(2),
+        0,
+        options.cache,
+        Duration::from_secs(3600),
+        false,
+        Duration::from_secs(30),
+        53,
+        options.workers,
+        true,
+        4096,
+        false,
+        rat_logger::LevelFilter::Off,
+        false,
+    );
+    let mut resolver = CoreResolver::new(config);
+    for i in 0..options.upstreams {
+        let mock = MockTransport::new()
+            .with_endpoint(format!("mock-{}", i))
+            .with_a("soak.example.com", &[Ipv4Addr::new(192, 0, 2, i as u8)], 300);
+        resolver.add_named_transport(format!("mock-{}", i), Arc::new(mock));
+    }
+    resolver
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = SoakOptions::from_args()?;
+    let resolver = Arc::new(build_resolver(&options));
+    let histogram = Arc::new(LatencyHistogram::new());
+    let issued = Arc::new(AtomicU64::new(0));
+    let errors = Arc::new(AtomicU64::new(0));
+    let total = options.qps * options.duration.as_secs();
+    let interval = Duration::from_secs_f64(1.0 / options.qps as f64);
+
+    println!(
+        "压测开始: 目标 {} qps，持续 {:?}，{} 个模拟上游，{} 个并发任务，策略 {:?}，缓存 {}",
+        options.qps, options.duration, options.upstreams, options.workers, options.strategy,
+        if options.cache { "开启" } else { "关闭" }
+    );
+
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes_before = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+
+    // 每个任务领取下一个查询序号，按序号计算计划发出时间，领先于计划时等待
+    let workers: Vec<_> = (0..options.workers)
+        .map(|_| {
+            let resolver = resolver.clone();
+            let histogram = histogram.clone();
+            let issued = issued.clone();
+            let errors = errors.clone();
+            tokio::spawn(async move {
+                loop {
+                    let sequence = issued.fetch_add(1, Ordering::Relaxed);
+                    if sequence >= total {
+                        break;
+                    }
+                    let scheduled = start + interval.mul_f64(sequence as f64);
+                    if scheduled > Instant::now() {
+                        tokio::time::sleep_until(scheduled).await;
+                    }
+                    let sent = Instant::now();
+                    match resolver.query("soak.example.com", RecordType::A, QClass::IN).await {
+                        Ok(_) => histogram.record(sent.elapsed()),
+                        Err(_) => { errors.fetch_add(1, Ordering::Relaxed); },
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.await?;
+    }
+
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    let bytes = ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes_before;
+    let summary = histogram.summary();
+    let completed = summary.count + errors.load(Ordering::Relaxed);
+    let achieved = completed as f64 / elapsed.as_secs_f64();
+
+    println!("完成查询: {}（失败 {}），耗时 {:.2?}", completed, errors.load(Ordering::Relaxed), elapsed);
+    println!("实际吞吐: {:.0} qps（目标 {} qps）", achieved, options.qps);
+    println!(
+        "延迟: p50 {:?}  p95 {:?}  p99 {:?}  max {:?}  平均 {:?}",
+        summary.p50, summary.p95, summary.p99, histogram.max(), summary.mean
+    );
+    println!(
+        "内存分配: 每次查询 {:.1} 次 / {:.0} 字节，共 {} 次 / {} 字节",
+        allocations as f64 / completed.max(1) as f64,
+        bytes as f64 / completed.max(1) as f64,
+        allocations,
+        bytes
+    );
+    println!(
+        "SOAK qps={:.0} p50_us={} p95_us={} p99_us={} errors={} allocs_per_query={:.1}",
+        achieved,
+        summary.p50.as_micros(),
+        summary.p95.as_micros(),
+        summary.p99.as_micros(),
+        errors.load(Ordering::Relaxed),
+        allocations as f64 / completed.max(1) as f64
+    );
+
+    if achieved < options.qps as f64 * 0.95 {
+        return Err(format!("未达到目标吞吐: {:.0} < {} qps", achieved, options.qps).into());
+    }
+    Ok(())
+}
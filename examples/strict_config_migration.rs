@@ -0,0 +1,67 @@
+//! 严格配置迁移示例
+//!
+//! 本示例展示如何把 StrictDnsConfig 转换为 DnsResolverBuilder，
+//! 以及三种查询策略（Fifo/Smart/RoundRobin）在两种配置入口中的统一用法
+//!
+//! 迁移要点:
+//! - QueryStrategy 只有一个定义：rat_quickdns::QueryStrategy（即 builder::strategy::QueryStrategy）
+//! - StrictDnsConfig 通过 DnsResolverBuilder::from_strict_config 转换，不再需要手写上游列表
+//! - 批量查询使用 SmartDnsResolver::batch_query
+
+use rat_quickdns::{DnsResolverBuilder, QueryStrategy, StrictDnsConfig};
+use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+use rat_quickdns::config::strict::UpstreamSpec as StrictUpstreamSpec;
+use std::time::Duration;
+
+fn strict_config(strategy: QueryStrategy) -> Result<StrictDnsConfig, rat_quickdns::ConfigError> {
+    StrictDnsConfig::builder()
+        .strategy(strategy)
+        .timeout(Duration::from_secs(5))
+        .retry_count(2)
+        .enable_cache(true)
+        .cache_ttl(Duration::from_secs(3600))
+        .enable_upstream_monitoring(false)
+        .upstream_monitoring_interval(Duration::from_secs(30))
+        .port(53)
+        .concurrent_queries(10)
+        .buffer_size(4096)
+        .enable_stats(true)
+        .emergency_threshold(0.3)
+        .add_upstream(StrictUpstreamSpec::new("223.5.5.5:53".to_string(), "udp".to_string(), 1))
+        .add_upstream(StrictUpstreamSpec::new("119.29.29.29:53".to_string(), "udp".to_string(), 1))
+        .build()
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("=== 严格配置迁移示例 ===");
+
+    for strategy in [QueryStrategy::Fifo, QueryStrategy::Smart, QueryStrategy::RoundRobin] {
+        println!("\n策略: {:?} - {}", strategy, strategy.description());
+
+        let config = strict_config(strategy)?;
+        let resolver = DnsResolverBuilder::from_strict_config(&config, true, "CN")?
+            .with_silent_logger_init()
+            .build()
+            .await?;
+
+        let requests = vec![
+            DnsQueryRequest::new("example.com", DnsRecordType::A),
+            DnsQueryRequest::new("github.com", DnsRecordType::A),
+        ];
+
+        for result in resolver.batch_query(requests).await {
+            match result {
+                Ok(response) if response.success => {
+                    println!("  {} -> {:?}", response.domain, response.ip_addresses());
+                }
+                Ok(response) => {
+                    println!("  {} 查询失败: {}", response.domain, response.error.unwrap_or_default());
+                }
+                Err(e) => println!("  查询错误: {}", e),
+            }
+        }
+    }
+
+    Ok(())
+}
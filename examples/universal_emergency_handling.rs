@@ -85,16 +85,7 @@ impl UniversalEmergencyDemo {
         
         // 尝试查询（应该触发应急处理）
         println!("\n🔍 尝试查询 {} (A记录)...", self.test_domain);
-        let request = DnsQueryRequest {
-            query_id: None,
-            domain: self.test_domain.clone(),
-            record_type: DnsRecordType::A,
-            enable_edns: true,
-            client_address: None,
-            timeout_ms: None,
-            disable_cache: false,
-            enable_dnssec: false,
-        };
+        let request = DnsQueryRequest::new(self.test_domain.clone(), DnsRecordType::A);
         
         let start_time = Instant::now();
         match resolver.query(request).await {
@@ -168,16 +159,7 @@ impl UniversalEmergencyDemo {
         
         // 尝试查询（应该成功，因为有健康的服务器）
         println!("\n🔍 尝试查询 {} (A记录)...", self.test_domain);
-        let request = DnsQueryRequest {
-            query_id: None,
-            domain: self.test_domain.clone(),
-            record_type: DnsRecordType::A,
-            enable_edns: true,
-            client_address: None,
-            timeout_ms: None,
-            disable_cache: false,
-            enable_dnssec: false,
-        };
+        let request = DnsQueryRequest::new(self.test_domain.clone(), DnsRecordType::A);
         
         let start_time = Instant::now();
         match resolver.query(request).await {
@@ -217,16 +199,7 @@ impl UniversalEmergencyDemo {
             // 等待健康检查
             sleep(Duration::from_secs(2)).await;
             
-            let request = DnsQueryRequest {
-                query_id: None,
-                domain: "nonexistent-domain-12345.invalid".to_string(),
-                record_type: DnsRecordType::A,
-                enable_edns: true,
-                client_address: None,
-                timeout_ms: None,
-                disable_cache: false,
-                enable_dnssec: false,
-            };
+            let request = DnsQueryRequest::new("nonexistent-domain-12345.invalid", DnsRecordType::A);
             
             match resolver.query(request).await {
                 Ok(_) => println!("意外成功"),
@@ -0,0 +1,41 @@
+//! 区域变更监视示例
+//!
+//! 用法: cargo run --example zone_watch -- [区域] [轮询间隔秒数]
+//!
+//! 定期查询区域的SOA序列号，序列号增大时打印变更事件（下游可在此清理缓存）
+
+use futures::StreamExt;
+use rat_quickdns::{DnsResolverBuilder, Preset, QueryStrategy};
+use rat_quickdns::builder::ZoneWatcher;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let zone = args.next().unwrap_or_else(|| "example.com".to_string());
+    let interval = Duration::from_secs(args.next().map(|s| s.parse()).transpose()?.unwrap_or(60));
+
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, true, "global".to_string())
+        .with_preset(Preset::Global)?
+        .with_timeout(Duration::from_secs(3))
+        .with_silent_logger_init()
+        .build()
+        .await?;
+    let watcher = ZoneWatcher::new(Arc::new(resolver));
+
+    println!("{} 当前SOA序列号: {}", zone, watcher.get_zone_serial(&zone).await?);
+    println!("每 {:?} 检查一次，Ctrl+C 退出", interval);
+
+    let changes = watcher.watch_zone(&zone, interval)?;
+    futures::pin_mut!(changes);
+    while let Some(change) = changes.next().await {
+        let observed_at = change.observed_at.duration_since(UNIX_EPOCH)?.as_secs();
+        println!(
+            "[{}] {} 序列号变化: {} -> {}",
+            observed_at, change.zone, change.old_serial, change.new_serial
+        );
+    }
+
+    Ok(())
+}
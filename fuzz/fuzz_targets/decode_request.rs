@@ -0,0 +1,17 @@
+//! 解码任意字节作为bincode编码的查询请求和批量请求：不能panic，不能按声明长度无限分配
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rat_quickdns::builder::encoded::{decode_batch, decode_request};
+use rat_quickdns::EncodedRequestLimits;
+
+fuzz_target!(|data: &[u8]| {
+    let limits = EncodedRequestLimits::default();
+    if let Ok(request) = decode_request(data, &limits) {
+        assert!(rat_quickdns::utils::validate_domain(&request.domain).is_ok());
+    }
+    if let Ok(requests) = decode_batch(data, &limits) {
+        assert!(requests.len() <= limits.max_batch_len);
+    }
+});
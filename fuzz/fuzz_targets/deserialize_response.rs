@@ -0,0 +1,16 @@
+//! 任意字节作为响应报文解析：只允许返回错误，不允许panic或长时间运行
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rat_quickdns::transport::UdpTransport;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(response) = UdpTransport::deserialize_response(data) {
+        // 解析成功的报文重新编码后应当仍能解析
+        if let Ok(encoded) = UdpTransport::serialize_response(&response) {
+            let _ = UdpTransport::deserialize_response(&encoded);
+        }
+    }
+    let _ = UdpTransport::parse_edns(data);
+});
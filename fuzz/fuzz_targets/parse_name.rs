@@ -0,0 +1,15 @@
+//! 从任意位置解析域名：第一个字节是起始偏移，其余是报文
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rat_quickdns::transport::UdpTransport;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&start, packet)) = data.split_first() else {
+        return;
+    };
+    if let Ok((_, end)) = UdpTransport::parse_name(packet, start as usize) {
+        assert!(end <= packet.len());
+    }
+});
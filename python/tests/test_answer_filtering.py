@@ -0,0 +1,103 @@
+#!/usr/bin/env python3
+# -*- coding: utf-8 -*-
+
+"""
+rat-quickdns-py 应答类型筛选测试
+
+本地UDP服务器在AAAA应答里混入A记录，模拟有问题的上游，检查返回的只有IPv6地址且去掉的记录被计数。
+"""
+
+import socket
+import struct
+import sys
+import os
+import threading
+import unittest
+
+# 添加项目根目录到Python路径
+sys.path.insert(0, os.path.abspath(os.path.join(os.path.dirname(__file__), '..')))
+
+try:
+    import rat_quickdns_py as dns
+except ImportError:
+    print("错误: 无法导入rat_quickdns_py模块")
+    print("请确保已安装该模块或正确设置了PYTHONPATH")
+    sys.exit(1)
+
+
+def polluted_reply(query):
+    """对任意查询应答一条AAAA记录和一条A记录"""
+    end = 12
+    while query[end] != 0:
+        end += query[end] + 1
+    question = query[12:end + 5]
+    header = query[:2] + struct.pack(">HHHHH", 0x8180, 1, 2, 0, 0)
+    aaaa = struct.pack(">HHHIH", 0xC00C, 28, 1, 300, 16) + socket.inet_pton(socket.AF_INET6, "2001:db8::1")
+    a = struct.pack(">HHHIH", 0xC00C, 1, 1, 300, 4) + socket.inet_aton("192.0.2.9")
+    return header + question + aaaa + a
+
+
+class PollutedServer(threading.Thread):
+    """只在本机监听的UDP服务器"""
+
+    def __init__(self):
+        super().__init__(daemon=True)
+        self.sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+        self.sock.bind(("127.0.0.1", 0))
+        self.sock.settimeout(0.1)
+        self.running = True
+
+    @property
+    def address(self):
+        return "127.0.0.1:%d" % self.sock.getsockname()[1]
+
+    def run(self):
+        while self.running:
+            try:
+                query, peer = self.sock.recvfrom(4096)
+            except socket.timeout:
+                continue
+            self.sock.sendto(polluted_reply(query), peer)
+
+    def stop(self):
+        self.running = False
+        self.join()
+        self.sock.close()
+
+
+class TestAnswerFiltering(unittest.TestCase):
+    """测试类型不符的应答记录被去掉"""
+
+    def setUp(self):
+        self.server = PollutedServer()
+        self.server.start()
+
+    def tearDown(self):
+        self.server.stop()
+
+    def build(self, lenient=False):
+        builder = dns.DnsResolverBuilder()
+        builder.add_udp_upstream("Polluted", self.server.address)
+        builder.timeout(2.0)
+        builder.with_silent_logger_init()
+        builder.with_lenient_answer_types(lenient)
+        return builder.build()
+
+    def test_aaaa_answer_polluted_with_a_records(self):
+        """resolve_aaaa只返回IPv6地址，去掉的A记录计入统计（命中缓存的也计入）"""
+        with self.build() as resolver:
+            self.assertEqual(resolver.resolve_aaaa("polluted.example"), ["2001:db8::1"])
+            response = resolver.query("polluted.example", "AAAA")
+            self.assertEqual([record.type for record in response.records], ["AAAA"])
+            self.assertEqual(resolver.get_stats()["dropped_mismatched_records"], 2)
+
+    def test_lenient_keeps_records_but_addresses_match_family(self):
+        """宽松模式原样返回记录，地址仍只取查询的地址族"""
+        with self.build(lenient=True) as resolver:
+            self.assertEqual(len(resolver.query("polluted.example", "AAAA").records), 2)
+            self.assertEqual(resolver.resolve_aaaa("polluted.example"), ["2001:db8::1"])
+            self.assertEqual(resolver.get_stats()["dropped_mismatched_records"], 0)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,93 @@
+#!/usr/bin/env python3
+# -*- coding: utf-8 -*-
+
+"""
+rat-quickdns-py 构建器测试
+
+覆盖客户端子网、上游权重、重试次数和上游监控间隔等构建器配置项，
+只检查参数校验和构建结果，不发起网络查询。
+"""
+
+import unittest
+import sys
+import os
+
+# 添加项目根目录到Python路径
+sys.path.insert(0, os.path.abspath(os.path.join(os.path.dirname(__file__), '..')))
+
+try:
+    import rat_quickdns_py as dns
+    from rat_quickdns_py import QueryStrategy
+except ImportError:
+    print("错误: 无法导入rat_quickdns_py模块")
+    print("请确保已安装该模块或正确设置了PYTHONPATH")
+    sys.exit(1)
+
+
+class TestBuilder(unittest.TestCase):
+    """测试构建器的配置项"""
+
+    def setUp(self):
+        """测试前准备"""
+        self.builder = dns.DnsResolverBuilder()
+        self.builder.query_strategy(QueryStrategy.FIFO)
+        self.builder.with_silent_logger_init()
+
+    def test_default_client_ip(self):
+        """测试默认客户端子网"""
+        self.builder.with_default_client_ip("202.96.128.86")
+        self.builder.with_default_client_ip("202.96.128.86", 16)
+        self.builder.with_default_client_ip("240e:1f:1::1")
+        self.builder.with_default_client_ip("240e:1f:1::1", 48)
+
+        with self.assertRaises(ValueError):
+            self.builder.with_default_client_ip("not-an-ip")
+        with self.assertRaises(ValueError):
+            self.builder.with_default_client_ip("202.96.128.86", 33)
+        with self.assertRaises(ValueError):
+            self.builder.with_default_client_ip("240e:1f:1::1", 129)
+        with self.assertRaises(ValueError):
+            self.builder.with_default_client_ip("202.96.128.86", -1)
+
+    def test_upstream_weights(self):
+        """测试各类上游的权重参数"""
+        self.builder.add_udp_upstream("阿里DNS", "223.5.5.5")
+        self.builder.add_udp_upstream("腾讯DNS", "119.29.29.29", weight=3)
+        self.builder.add_tcp_upstream("阿里TCP", "223.5.5.5", 2)
+        self.builder.add_dot_upstream("阿里DoT", "dns.alidns.com", weight=2)
+        self.builder.add_doh_upstream("阿里DoH", "https://dns.alidns.com/dns-query", weight=5)
+
+        for weight in (0, -1, 2 ** 32):
+            with self.assertRaises(ValueError):
+                self.builder.add_udp_upstream("无效权重", "1.1.1.1", weight=weight)
+
+        with self.assertRaises(ValueError):
+            self.builder.add_doh_upstream("明文DoH", "http://dns.alidns.com/dns-query")
+
+    def test_retry_count(self):
+        """测试重试次数"""
+        self.builder.with_retry_count(0)
+        self.builder.with_retry_count(3)
+        with self.assertRaises(ValueError):
+            self.builder.with_retry_count(-1)
+
+    def test_upstream_monitoring(self):
+        """测试上游监控间隔"""
+        self.builder.with_upstream_monitoring(30)
+        self.builder.with_upstream_monitoring(0.5)
+        for interval in (0, -5.0, float("inf"), float("nan")):
+            with self.assertRaises(ValueError):
+                self.builder.with_upstream_monitoring(interval)
+
+    def test_build_with_new_options(self):
+        """测试组合配置后可以成功构建"""
+        self.builder.add_udp_upstream("阿里DNS", "223.5.5.5", weight=2)
+        self.builder.with_default_client_ip("202.96.128.86", 24)
+        self.builder.with_retry_count(1)
+        self.builder.with_upstream_monitoring(60.0)
+        resolver = self.builder.build()
+        self.assertIsNotNone(resolver)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,145 @@
+#!/usr/bin/env python3
+# -*- coding: utf-8 -*-
+
+"""
+rat-quickdns-py 异常类型测试
+
+本地UDP服务器按需要应答NXDOMAIN、SERVFAIL、REFUSED或不应答，检查抛出的异常类型、结构化属性，
+原来捕获RuntimeError的代码仍然有效，以及异常可以用pickle往返。
+"""
+
+import pickle
+import socket
+import struct
+import sys
+import os
+import threading
+import unittest
+
+# 添加项目根目录到Python路径
+sys.path.insert(0, os.path.abspath(os.path.join(os.path.dirname(__file__), '..')))
+
+try:
+    import rat_quickdns_py as dns
+except ImportError:
+    print("错误: 无法导入rat_quickdns_py模块")
+    print("请确保已安装该模块或正确设置了PYTHONPATH")
+    sys.exit(1)
+
+
+def rcode_reply(query, rcode):
+    """对任意查询应答给定的响应码，不带记录"""
+    end = 12
+    while query[end] != 0:
+        end += query[end] + 1
+    question = query[12:end + 5]
+    header = query[:2] + struct.pack(">HHHHH", 0x8180 | rcode, 1, 0, 0, 0)
+    return header + question
+
+
+class RcodeServer(threading.Thread):
+    """只在本机监听的UDP服务器，`rcode` 为None时不应答"""
+
+    def __init__(self, rcode):
+        super().__init__(daemon=True)
+        self.rcode = rcode
+        self.sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+        self.sock.bind(("127.0.0.1", 0))
+        self.sock.settimeout(0.1)
+        self.running = True
+
+    @property
+    def address(self):
+        return "127.0.0.1:%d" % self.sock.getsockname()[1]
+
+    def run(self):
+        while self.running:
+            try:
+                query, peer = self.sock.recvfrom(4096)
+            except socket.timeout:
+                continue
+            if self.rcode is not None:
+                self.sock.sendto(rcode_reply(query, self.rcode), peer)
+
+    def stop(self):
+        self.running = False
+        self.join()
+        self.sock.close()
+
+
+class TestErrorHierarchy(unittest.TestCase):
+    """测试解析错误按原因映射为不同的异常类型"""
+
+    def resolve_with(self, rcode, domain="missing.example"):
+        """用只有一个本地上游的解析器解析A记录，返回抛出的异常"""
+        server = RcodeServer(rcode)
+        server.start()
+        try:
+            builder = dns.DnsResolverBuilder()
+            builder.add_udp_upstream("Local", server.address)
+            builder.timeout(0.5)
+            builder.with_silent_logger_init()
+            with builder.build() as resolver:
+                with self.assertRaises(dns.DnsError) as context:
+                    resolver.resolve_a(domain)
+                return context.exception
+        finally:
+            server.stop()
+
+    def test_hierarchy(self):
+        """所有子类继承 DnsError，基类暂时仍继承 RuntimeError"""
+        self.assertIs(dns.DnsError, dns.DnsResolutionError)
+        self.assertTrue(issubclass(dns.DnsError, RuntimeError))
+        for cls in (dns.NxDomainError, dns.NoRecordsError, dns.DnsTimeoutError, dns.ServFailError,
+                    dns.RefusedError, dns.NetworkError, dns.ConfigError, dns.AllUpstreamsFailedError):
+            self.assertTrue(issubclass(cls, dns.DnsError), cls)
+        self.assertFalse(issubclass(dns.DnsTimeoutError, TimeoutError))
+
+    def test_nxdomain_caught_by_subclass(self):
+        """except NxDomainError 捕获NXDOMAIN应答，属性已填好"""
+        error = self.resolve_with(3)
+        try:
+            raise error
+        except dns.NxDomainError as e:
+            self.assertEqual(e.domain, "missing.example")
+            self.assertEqual(e.record_type, "A")
+            self.assertEqual(e.rcode, "NXDOMAIN")
+            self.assertIsNone(e.phase)
+            self.assertIsNone(e.failure_report)
+        self.assertIn("missing.example", str(error))
+
+    def test_runtime_error_still_catches(self):
+        """原来捕获 RuntimeError 的代码不受影响"""
+        error = self.resolve_with(3)
+        with self.assertRaises(RuntimeError) as context:
+            raise error
+        self.assertIsInstance(context.exception, dns.NxDomainError)
+
+    def test_rcode_classes(self):
+        """SERVFAIL和REFUSED各自对应异常类型"""
+        error = self.resolve_with(2)
+        self.assertIsInstance(error, dns.ServFailError)
+        self.assertEqual(error.rcode, "SERVFAIL")
+        error = self.resolve_with(5)
+        self.assertIsInstance(error, dns.RefusedError)
+        self.assertEqual(error.rcode, "REFUSED")
+
+    def test_timeout(self):
+        """上游不应答时为 DnsTimeoutError"""
+        error = self.resolve_with(None)
+        self.assertIsInstance(error, dns.DnsTimeoutError)
+        self.assertEqual(error.domain, "missing.example")
+        self.assertIsNone(error.rcode)
+
+    def test_pickle_round_trip(self):
+        """pickle往返后类型、消息和属性不变"""
+        error = self.resolve_with(3)
+        restored = pickle.loads(pickle.dumps(error))
+        self.assertIs(type(restored), dns.NxDomainError)
+        self.assertEqual(str(restored), str(error))
+        for attr in ("domain", "record_type", "upstream", "rcode", "phase", "failure_report"):
+            self.assertEqual(getattr(restored, attr), getattr(error, attr), attr)
+
+
+if __name__ == '__main__':
+    unittest.main()
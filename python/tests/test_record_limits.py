@@ -0,0 +1,130 @@
+#!/usr/bin/env python3
+# -*- coding: utf-8 -*-
+
+"""
+rat-quickdns-py 记录数上限测试
+
+本地UDP服务器对任意查询应答50条A记录，检查超出上限时截断并标记，或整个响应被拒绝。
+"""
+
+import socket
+import struct
+import sys
+import os
+import threading
+import unittest
+
+# 添加项目根目录到Python路径
+sys.path.insert(0, os.path.abspath(os.path.join(os.path.dirname(__file__), '..')))
+
+try:
+    import rat_quickdns_py as dns
+except ImportError:
+    print("错误: 无法导入rat_quickdns_py模块")
+    print("请确保已安装该模块或正确设置了PYTHONPATH")
+    sys.exit(1)
+
+
+ANSWER_COUNT = 50
+
+
+def flood_reply(query):
+    """对任意查询应答 ANSWER_COUNT 条A记录"""
+    end = 12
+    while query[end] != 0:
+        end += query[end] + 1
+    question = query[12:end + 5]
+    header = query[:2] + struct.pack(">HHHHH", 0x8180, 1, ANSWER_COUNT, 0, 0)
+    answers = b"".join(
+        struct.pack(">HHHIH", 0xC00C, 1, 1, 300, 4) + socket.inet_aton("192.0.2.%d" % (i + 1))
+        for i in range(ANSWER_COUNT)
+    )
+    return header + question + answers
+
+
+class FloodServer(threading.Thread):
+    """只在本机监听的UDP服务器"""
+
+    def __init__(self):
+        super().__init__(daemon=True)
+        self.sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+        self.sock.bind(("127.0.0.1", 0))
+        self.sock.settimeout(0.1)
+        self.running = True
+        self.queries = 0
+
+    @property
+    def address(self):
+        return "127.0.0.1:%d" % self.sock.getsockname()[1]
+
+    def run(self):
+        while self.running:
+            try:
+                query, peer = self.sock.recvfrom(4096)
+            except socket.timeout:
+                continue
+            self.queries += 1
+            self.sock.sendto(flood_reply(query), peer)
+
+    def stop(self):
+        self.running = False
+        self.join()
+        self.sock.close()
+
+
+class TestRecordLimits(unittest.TestCase):
+    """测试超出记录数上限的响应"""
+
+    def setUp(self):
+        self.server = FloodServer()
+        self.server.start()
+
+    def tearDown(self):
+        self.server.stop()
+
+    def build(self, reject=False, cache_truncated=False):
+        builder = dns.DnsResolverBuilder()
+        builder.add_udp_upstream("Flood", self.server.address)
+        builder.timeout(2.0)
+        builder.cache(True)
+        builder.with_silent_logger_init()
+        builder.with_record_limits(max_answer_records=10, max_total_records=20, reject=reject)
+        builder.with_cache_truncated_responses(cache_truncated)
+        return builder.build()
+
+    def test_truncated_and_not_cached_by_default(self):
+        """超出上限时只返回前10条并标记，截断的答案不写入缓存"""
+        with self.build() as resolver:
+            response = resolver.query("flood.example", "A")
+            self.assertTrue(response.success)
+            self.assertTrue(response.records_truncated)
+            self.assertEqual(len(response.records), 10)
+            self.assertTrue(response.to_dict()["records_truncated"])
+            resolver.query("flood.example", "A")
+            self.assertEqual(self.server.queries, 2)
+
+    def test_truncated_answers_can_be_cached(self):
+        """允许缓存时第二次查询命中缓存"""
+        with self.build(cache_truncated=True) as resolver:
+            resolver.query("flood.example", "A")
+            self.assertEqual(len(resolver.query("flood.example", "A").records), 10)
+            self.assertEqual(self.server.queries, 1)
+
+    def test_reject_mode(self):
+        """拒绝模式下查询失败"""
+        with self.build(reject=True) as resolver:
+            response = resolver.query("flood.example", "A")
+            self.assertFalse(response.success)
+            self.assertFalse(response.records_truncated)
+
+    def test_invalid_limits(self):
+        """回答记录数上限超过总数上限时抛出ValueError"""
+        builder = dns.DnsResolverBuilder()
+        with self.assertRaises(ValueError):
+            builder.with_record_limits(max_answer_records=100, max_total_records=50)
+        with self.assertRaises(ValueError):
+            builder.with_record_limits(max_cname_chain=0)
+
+
+if __name__ == "__main__":
+    unittest.main()
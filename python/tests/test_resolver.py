@@ -10,6 +10,7 @@ rat-quickdns-py 解析器测试
 import unittest
 import sys
 import os
+import time
 
 # 添加项目根目录到Python路径
 sys.path.insert(0, os.path.abspath(os.path.join(os.path.dirname(__file__), '..')))
@@ -54,10 +55,12 @@ class TestResolver(unittest.TestCase):
         except Exception as e:
             self.fail(f"解析有效域名失败: {e}")
 
-        # 测试无效域名（应该返回空列表）
-        invalid_ips = self.resolver.resolve(self.invalid_domain)
-        self.assertIsInstance(invalid_ips, list)
-        self.assertEqual(len(invalid_ips), 0)
+        # 测试不存在的域名（应该抛出NxDomainError，而不是返回空列表）
+        with self.assertRaises(dns.NxDomainError):
+            self.resolver.resolve(self.invalid_domain)
+        self.assertTrue(issubclass(dns.NxDomainError, dns.DnsResolutionError))
+        self.assertTrue(issubclass(dns.NoRecordsError, dns.DnsResolutionError))
+        self.assertTrue(issubclass(dns.DnsResolutionError, RuntimeError))
     
     def test_resolve_a(self):
         """测试A记录解析"""
@@ -111,6 +114,86 @@ class TestResolver(unittest.TestCase):
         default_value = ["0.0.0.0"]
         self.assertEqual(results[2].unwrap_or(default_value), empty_ips)
 
+    def test_query_a_records(self):
+        """query返回带TTL的完整A记录"""
+        response = self.resolver.query(self.valid_domain)
+        self.assertTrue(response.success)
+        self.assertIsNone(response.error)
+        self.assertEqual(response.type, "A")
+        self.assertEqual(response.rcode, 0)
+        self.assertIsNotNone(response.server_used)
+        self.assertGreaterEqual(response.duration_ms, 0)
+        self.assertTrue(response.query_id)
+
+        records = [r for r in response.records if r.type == "A"]
+        self.assertTrue(len(records) > 0)
+        for record in records:
+            self.assertTrue(dns.is_valid_ipv4(record.value))
+            self.assertIsInstance(record.ttl, int)
+            self.assertGreaterEqual(record.ttl, 0)
+            self.assertIsNone(record.priority)
+            self.assertEqual(record.to_dict(), {"name": record.name, "type": "A", "ttl": record.ttl, "value": record.value})
+        self.assertEqual(records[0], records[0])
+        self.assertIn("ttl=", repr(records[0]))
+        self.assertEqual(sorted(r.value for r in records), sorted(self.resolver.resolve_a(self.valid_domain)))
+
+    def test_query_mx_records(self):
+        """MX记录的优先级和交换主机分开给出"""
+        response = self.resolver.query("gmail.com", "MX")
+        records = [r for r in response.records if r.type == "MX"]
+        self.assertTrue(len(records) > 0)
+        for record in records:
+            self.assertIsInstance(record.priority, int)
+            self.assertIsNone(record.port)
+            self.assertGreater(record.ttl, 0)
+            self.assertFalse(record.value.split()[0].isdigit())
+            self.assertEqual(record.to_dict()["priority"], record.priority)
+        simple = self.resolver.resolve_mx("gmail.com")
+        self.assertEqual(sorted(simple), sorted(f"{r.priority} {r.value}" for r in records))
+
+    def test_query_txt_records(self):
+        """TXT记录的值与resolve_txt一致"""
+        response = self.resolver.query("google.com", "txt")
+        records = [r for r in response.records if r.type == "TXT"]
+        self.assertTrue(len(records) > 0)
+        for record in records:
+            self.assertGreater(record.ttl, 0)
+            self.assertIsNone(record.priority)
+        self.assertEqual(sorted(r.value for r in records), sorted(self.resolver.resolve_txt("google.com")))
+
+    def test_warm_cache(self):
+        """预热报告的计数"""
+        report = self.resolver.warm_cache([self.valid_domain, f"{self.valid_domain},AAAA"], max_concurrency=2, deadline=10.0)
+        self.assertEqual(report["attempted"], 2)
+        self.assertEqual(report["succeeded"] + len(report["failed"]), 2)
+        self.assertEqual(report["skipped"], 0)
+        self.assertFalse(report["deadline_exceeded"])
+        with self.assertRaises(ValueError):
+            self.resolver.warm_cache([f"{self.valid_domain},BOGUS"])
+        with self.assertRaises(ValueError):
+            self.resolver.warm_cache([self.valid_domain], max_concurrency=0)
+
+    def test_query_unknown_type(self):
+        """未知记录类型抛出ValueError"""
+        with self.assertRaises(ValueError):
+            self.resolver.query(self.valid_domain, "BOGUS")
+        response = self.resolver.query(self.invalid_domain)
+        self.assertEqual([r for r in response.records if r.type == "A"], [])
+
+    def test_explain_selection(self):
+        """选择解释列出每个候选的评分分量"""
+        explanation = self.resolver.explain_selection()
+        self.assertEqual([c["name"] for c in explanation["candidates"]], ["Cloudflare", "Google"])
+        self.assertIn(explanation["selected"], ["Cloudflare", "Google"])
+        self.assertIn("final_score", explanation["candidates"][0]["score"])
+
+    def test_effective_config(self):
+        """实际生效的配置包含所有上游和解析器设置"""
+        config = self.resolver.get_effective_config()
+        self.assertEqual([u["name"] for u in config["upstreams"]], ["Cloudflare", "Google"])
+        self.assertEqual(config["resolver"]["timeout_ms"], 5000)
+        self.assertEqual(config["upstreams"][0]["port"], 53)
+
 
 class TestQueryStrategy(unittest.TestCase):
     """测试不同查询策略"""
@@ -221,6 +304,118 @@ class TestUtilityFunctions(unittest.TestCase):
         self.assertIsNone(result)
 
 
+class TestResolverLifecycle(unittest.TestCase):
+    """测试解析器的关闭和上下文管理"""
+    
+    def make_builder(self):
+        builder = dns.DnsResolverBuilder()
+        builder.add_udp_upstream("Local", "127.0.0.1:53")
+        builder.timeout(1.0)
+        return builder
+    
+    def test_closed_resolver_raises(self):
+        """关闭后调用查询方法抛出RuntimeError，重复关闭无副作用"""
+        resolver = self.make_builder().build()
+        resolver.close()
+        resolver.close()
+        with self.assertRaises(RuntimeError):
+            resolver.resolve("example.com")
+        with self.assertRaises(RuntimeError):
+            resolver.get_stats()
+    
+    def test_upstream_labels(self):
+        """上游标签随状态、有效配置和指标白名单输出，无效标签被拒绝"""
+        builder = self.make_builder()
+        builder.with_upstream_labels("Local", {"provider": "internal", "datacenter": "sha-1"})
+        builder.with_metrics_upstream_labels(["provider"])
+        with self.assertRaises(ValueError):
+            builder.with_upstream_labels("Local", {"bad-key": "x"})
+        with self.assertRaises(ValueError):
+            builder.with_upstream_labels("Missing", {"provider": "x"})
+        with builder.build() as resolver:
+            status = resolver.get_upstream_status()
+            self.assertEqual(status[0]["labels"], {"provider": "internal", "datacenter": "sha-1"})
+            config = resolver.get_effective_config()
+            self.assertEqual(config["upstreams"][0]["labels"]["datacenter"], "sha-1")
+            self.assertEqual(config["resolver"]["metrics_upstream_labels"], ["provider"])
+            resolver.add_upstream("Backup", "udp", "127.0.0.2", labels={"provider": "backup"})
+            self.assertEqual(resolver.get_upstream_status()[1]["labels"], {"provider": "backup"})
+    
+    def test_context_manager_closes(self):
+        """with语句退出时关闭解析器"""
+        with self.make_builder().build() as resolver:
+            self.assertEqual(repr(resolver), "DnsResolver()")
+        self.assertEqual(repr(resolver), "DnsResolver(closed)")
+        with self.assertRaises(RuntimeError):
+            resolver.resolve("example.com")
+    
+    @unittest.skipUnless(os.path.isdir("/proc/self/task"), "需要Linux的/proc统计线程数")
+    def test_repeated_build_and_close_does_not_leak_threads(self):
+        """反复创建、关闭解析器时线程数保持有界"""
+        def thread_count():
+            return len(os.listdir("/proc/self/task"))
+        
+        baseline = thread_count()
+        builder = self.make_builder()
+        peak = baseline
+        for i in range(100):
+            if i % 2 == 0:
+                with builder.build() as resolver:
+                    peak = max(peak, thread_count())
+            else:
+                resolver = builder.build()
+                peak = max(peak, thread_count())
+                resolver.close()
+        
+        # 同一时刻只有一个运行时；全部关闭后工作线程陆续退出
+        self.assertLessEqual(peak, baseline + (os.cpu_count() or 1) + 8)
+        for _ in range(50):
+            if thread_count() <= baseline + 2:
+                break
+            time.sleep(0.1)
+        self.assertLessEqual(thread_count(), baseline + 2)
+    
+    def strict_config(self, upstreams, timeout_secs=1):
+        return {
+            "strategy": "Smart",
+            "default_timeout": {"secs": timeout_secs, "nanos": 0},
+            "retry_count": 1,
+            "enable_cache": True,
+            "max_cache_ttl": {"secs": 600, "nanos": 0},
+            "enable_upstream_monitoring": False,
+            "upstream_monitoring_interval": {"secs": 30, "nanos": 0},
+            "port": 53,
+            "concurrent_queries": 8,
+            "buffer_size": 4096,
+            "enable_stats": True,
+            "emergency_threshold": 0.3,
+            "upstreams": [
+                {"name": name, "address": address, "protocol": "udp", "weight": 1, "enabled": True}
+                for name, address in upstreams
+            ],
+        }
+    
+    def test_reload(self):
+        """重新加载报告新增、移除的上游和改变的设置，无效配置不修改解析器"""
+        import json
+        config = self.strict_config([("Local", "127.0.0.1:53"), ("Old", "127.0.0.2:53")])
+        builder = dns.DnsResolverBuilder.from_config_json(json.dumps(config))
+        with builder.build() as resolver:
+            config = self.strict_config([("Local", "127.0.0.1:53"), ("New", "127.0.0.3:53")], timeout_secs=2)
+            report = resolver.reload(json.dumps(config))
+            self.assertEqual(report["added"], ["New"])
+            self.assertEqual(report["removed"], ["Old"])
+            self.assertEqual(report["changed_settings"], ["timeout_ms"])
+            self.assertEqual([u["name"] for u in resolver.get_effective_config()["upstreams"]], ["Local", "New"])
+            
+            config["upstreams"] = []
+            with self.assertRaises(ValueError):
+                resolver.reload(json.dumps(config))
+            with self.assertRaises(ValueError):
+                resolver.reload("not json")
+            self.assertEqual(resolver.get_effective_config()["resolver"]["timeout_ms"], 2000)
+
+
 class TestPresetBuilders(unittest.TestCase):
     """测试预设构建器"""
     
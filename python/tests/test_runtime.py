@@ -0,0 +1,187 @@
+#!/usr/bin/env python3
+# -*- coding: utf-8 -*-
+
+"""
+rat-quickdns-py 运行时测试
+
+覆盖共享运行时的配置、fork之后的行为和回调模式。查询的都是IP字面量，
+由解析器在本地应答，不依赖网络。
+"""
+
+import gc
+import os
+import signal
+import sys
+import threading
+import time
+import unittest
+
+# 添加项目根目录到Python路径
+sys.path.insert(0, os.path.abspath(os.path.join(os.path.dirname(__file__), '..')))
+
+try:
+    import rat_quickdns_py as dns
+except ImportError:
+    print("错误: 无法导入rat_quickdns_py模块")
+    print("请确保已安装该模块或正确设置了PYTHONPATH")
+    sys.exit(1)
+
+
+def make_builder():
+    builder = dns.DnsResolverBuilder()
+    builder.add_udp_upstream("Local", "127.0.0.1:53")
+    builder.timeout(1.0)
+    builder.with_silent_logger_init()
+    return builder
+
+
+class TestConfigureRuntime(unittest.TestCase):
+    """测试运行时配置"""
+
+    def test_invalid_arguments(self):
+        """工作线程数为0或与当前线程运行时同时指定时抛出ValueError"""
+        with self.assertRaises(ValueError):
+            dns.configure_runtime(worker_threads=0)
+        with self.assertRaises(ValueError):
+            dns.configure_runtime(worker_threads=2, use_current_thread=True)
+
+    def test_cannot_reconfigure_while_resolvers_are_open(self):
+        """还有未关闭的解析器时不能更改配置"""
+        with make_builder().build():
+            with self.assertRaises(RuntimeError):
+                dns.configure_runtime(worker_threads=2)
+
+    def test_current_thread_runtime(self):
+        """当前线程运行时同样可以查询和回调"""
+        gc.collect()
+        try:
+            dns.configure_runtime(use_current_thread=True)
+        except RuntimeError:
+            self.skipTest("其他测试的解析器尚未回收")
+        try:
+            with make_builder().build() as resolver:
+                self.assertEqual(resolver.resolve("192.0.2.1"), ["192.0.2.1"])
+                done = threading.Event()
+                results = []
+
+                def callback(addresses, error):
+                    results.append((addresses, error))
+                    done.set()
+
+                resolver.resolve_with_callback("192.0.2.1", callback)
+                self.assertTrue(done.wait(5))
+                self.assertEqual(results, [(["192.0.2.1"], None)])
+        finally:
+            dns.configure_runtime()
+
+
+class TestCallbackBridge(unittest.TestCase):
+    """测试回调模式"""
+
+    def setUp(self):
+        self.resolver = make_builder().build()
+
+    def tearDown(self):
+        self.resolver.close()
+
+    def test_callback_receives_addresses_or_error(self):
+        """调用立即返回，结果或异常由回调交回"""
+        done = threading.Semaphore(0)
+        results = {}
+
+        def collect(key):
+            def callback(addresses, error):
+                results[key] = (addresses, error, threading.current_thread() is threading.main_thread())
+                done.release()
+            return callback
+
+        self.assertIsNone(self.resolver.resolve_with_callback("192.0.2.1", collect("v4")))
+        self.resolver.resolve_with_callback("2001:db8::1", collect("v6"), record_type="AAAA")
+        self.resolver.resolve_with_callback("192.0.2.1", collect("nodata"), record_type="AAAA")
+        for _ in range(3):
+            self.assertTrue(done.acquire(timeout=5))
+
+        self.assertEqual(results["v4"], (["192.0.2.1"], None, False))
+        self.assertEqual(results["v6"], (["2001:db8::1"], None, False))
+        addresses, error, _ = results["nodata"]
+        self.assertIsNone(addresses)
+        self.assertIsInstance(error, dns.NoRecordsError)
+
+    def test_invalid_arguments(self):
+        """只支持地址记录，回调必须可调用"""
+        with self.assertRaises(ValueError):
+            self.resolver.resolve_with_callback("example.com", lambda *_: None, record_type="MX")
+        with self.assertRaises(TypeError):
+            self.resolver.resolve_with_callback("example.com", None)
+
+
+@unittest.skipUnless(hasattr(os, "fork"), "需要os.fork")
+class TestFork(unittest.TestCase):
+    """测试fork之后的行为：旧解析器抛出明确的错误，新解析器使用重建的运行时，都不会挂起"""
+
+    def run_in_child(self, body, timeout=20):
+        """在子进程中执行 `body`，返回它写回的字符串；子进程超时未退出时判为失败"""
+        read_fd, write_fd = os.pipe()
+        pid = os.fork()
+        if pid == 0:
+            os.close(read_fd)
+            try:
+                outcome = body()
+            except BaseException as e:
+                outcome = "exception: " + repr(e)
+            os.write(write_fd, outcome.encode())
+            os._exit(0)
+
+        os.close(write_fd)
+        deadline = time.monotonic() + timeout
+        while os.waitpid(pid, os.WNOHANG) == (0, 0):
+            if time.monotonic() > deadline:
+                os.kill(pid, signal.SIGKILL)
+                os.waitpid(pid, 0)
+                self.fail("子进程挂起")
+            time.sleep(0.05)
+        with os.fdopen(read_fd, "rb") as pipe:
+            return pipe.read().decode()
+
+    def test_query_after_fork(self):
+        """子进程中旧解析器抛出RuntimeError，重新构建的解析器正常查询；父进程不受影响"""
+        inherited = make_builder().build()
+        self.assertEqual(inherited.resolve("192.0.2.1"), ["192.0.2.1"])
+
+        def child():
+            try:
+                inherited.resolve("192.0.2.1")
+                old = "resolved"
+            except RuntimeError as e:
+                old = str(e)
+            # 关闭旧解析器不会等待父进程的运行时
+            inherited.close()
+            with make_builder().build() as resolver:
+                new = ",".join(resolver.resolve("192.0.2.1"))
+            return old + "|" + new
+
+        old, _, new = self.run_in_child(child).partition("|")
+        self.assertIn("before fork()", old)
+        self.assertEqual(new, "192.0.2.1")
+
+        self.assertEqual(inherited.resolve("192.0.2.1"), ["192.0.2.1"])
+        inherited.close()
+
+    def test_child_without_touching_inherited_resolver(self):
+        """子进程不碰继承来的解析器时，直接构建新解析器即可查询"""
+        resolver = make_builder().build()
+        resolver.resolve("192.0.2.1")
+
+        def child():
+            resolver_in_child = make_builder().build()
+            try:
+                return ",".join(resolver_in_child.resolve_aaaa("2001:db8::1"))
+            finally:
+                resolver_in_child.close()
+
+        self.assertEqual(self.run_in_child(child), "2001:db8::1")
+        resolver.close()
+
+
+if __name__ == "__main__":
+    unittest.main()
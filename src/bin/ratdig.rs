@@ -0,0 +1,293 @@
+//! ratdig：类似dig的命令行查询工具（需要 `cli` 特性）
+//!
+//! ```bash
+//! cargo run --features cli --bin ratdig -- example.com AAAA @udp://223.5.5.5 @doh://dns.alidns.com/dns-query
+//! cargo run --features cli --bin ratdig -- example.com MX -s dot://1.1.1.1 --json --stats
+//! ```
+//!
+//! 上游写作 `@地址` 或 `-s 地址`，可重复：`udp://`、`tcp://`、`dot://`（或`tls://`）加 `主机[:端口]`，
+//! `doh://主机/路径` 或完整的 `https://` URL；不带协议前缀时按UDP处理。只有一个上游且没有 `--stats` 时
+//! 通过 [`rat_quickdns::oneshot`] 直接查询，不构建解析器。
+//!
+//! 退出码：0 成功（NOERROR），1 参数或配置错误，2 SERVFAIL，3 NXDOMAIN，4 超时，5 其他失败
+
+use rat_quickdns::builder::types::DnsRecordType;
+use rat_quickdns::oneshot::{self, OneshotOptions, OneshotUpstream};
+use rat_quickdns::{DnsQueryRequest, DnsQueryResponse, DnsResolverBuilder, LoggerInitStrategy, QueryStrategy, ResponseCode, SmartDnsResolver};
+use std::net::IpAddr;
+use std::process::ExitCode;
+use std::time::Duration;
+
+const EXIT_OK: u8 = 0;
+const EXIT_USAGE: u8 = 1;
+const EXIT_SERVFAIL: u8 = 2;
+const EXIT_NXDOMAIN: u8 = 3;
+const EXIT_TIMEOUT: u8 = 4;
+const EXIT_FAILURE: u8 = 5;
+
+const USAGE: &str = "\
+用法: ratdig <域名> [记录类型] [@上游 ...] [选项]
+
+选项:
+  -t, --type <类型>         记录类型（A、AAAA、MX、TXT、NS、CNAME、SOA、SRV、PTR……），默认A
+  -s, --server <上游>       上游服务器，可重复；等同于 @上游
+      --strategy <策略>     fifo（并发取最快）、smart、round_robin 或 sequential（按顺序逐个尝试），默认fifo
+      --timeout <毫秒>      单次查询超时，默认5000
+      --subnet <IP/前缀>    携带EDNS客户端子网（ECS），如 203.0.113.0/24
+      --dnssec              请求DNSSEC记录
+      --json                以JSON输出响应
+      --stats               查询后输出解析器统计和上游状态
+  -v, --verbose             输出调试日志
+  -h, --help                显示帮助";
+
+/// 命令行参数
+#[derive(Debug)]
+struct Options {
+    domain: String,
+    record_type: DnsRecordType,
+    upstreams: Vec<OneshotUpstream>,
+    strategy: QueryStrategy,
+    timeout: Duration,
+    subnet: Option<(IpAddr, u8)>,
+    dnssec: bool,
+    json: bool,
+    stats: bool,
+    verbose: bool,
+}
+
+/// 解析结果：正常参数或仅显示帮助
+enum Command {
+    Query(Box<Options>),
+    Help,
+}
+
+fn parse_record_type(value: &str) -> Result<DnsRecordType, String> {
+    DnsRecordType::from_str(value).ok_or_else(|| format!("未知记录类型: {}", value))
+}
+
+fn parse_subnet(value: &str) -> Result<(IpAddr, u8), String> {
+    let (ip, prefix) = value.split_once('/').ok_or_else(|| format!("子网需要写作 IP/前缀: {}", value))?;
+    let ip: IpAddr = ip.parse().map_err(|e| format!("子网地址无效 {}: {}", ip, e))?;
+    let prefix: u8 = prefix.parse().map_err(|e| format!("子网前缀无效 {}: {}", prefix, e))?;
+    Ok((ip, prefix))
+}
+
+fn parse_upstream(value: &str) -> Result<OneshotUpstream, String> {
+    OneshotUpstream::parse(value).map_err(|e| format!("上游地址无效: {}", e))
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut domain = None;
+    let mut record_type = None;
+    let mut upstreams = Vec::new();
+    let mut strategy = QueryStrategy::Fifo;
+    let mut timeout = Duration::from_millis(5000);
+    let mut subnet = None;
+    let (mut dnssec, mut json, mut stats, mut verbose) = (false, false, false, false);
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or_else(|| format!("{} 缺少参数值", flag));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-t" | "--type" => record_type = Some(parse_record_type(&value(&arg)?)?),
+            "-s" | "--server" => upstreams.push(parse_upstream(&value(&arg)?)?),
+            "--strategy" => {
+                strategy = match value(&arg)?.to_ascii_lowercase().as_str() {
+                    "fifo" => QueryStrategy::Fifo,
+                    "smart" => QueryStrategy::Smart,
+                    "round_robin" | "roundrobin" => QueryStrategy::RoundRobin,
+                    "sequential" => QueryStrategy::Sequential,
+                    other => return Err(format!("未知策略: {}", other)),
+                }
+            },
+            "--timeout" => {
+                let millis: u64 = value(&arg)?.parse().map_err(|e| format!("--timeout 的值无效: {}", e))?;
+                if millis == 0 {
+                    return Err("--timeout 必须大于0".to_string());
+                }
+                timeout = Duration::from_millis(millis);
+            },
+            "--subnet" => subnet = Some(parse_subnet(&value(&arg)?)?),
+            "--dnssec" => dnssec = true,
+            "--json" => json = true,
+            "--stats" => stats = true,
+            "-v" | "--verbose" => verbose = true,
+            flag if flag.starts_with('-') && flag.len() > 1 => return Err(format!("未知参数: {}", flag)),
+            server if server.starts_with('@') => upstreams.push(parse_upstream(server.trim_start_matches('@'))?),
+            positional if domain.is_none() => domain = Some(positional.to_string()),
+            positional if record_type.is_none() => record_type = Some(parse_record_type(positional)?),
+            positional => return Err(format!("多余的参数: {}", positional)),
+        }
+    }
+
+    let domain = domain.ok_or("缺少要查询的域名")?;
+    if upstreams.is_empty() {
+        return Err("至少需要一个上游，例如 @udp://223.5.5.5".to_string());
+    }
+    Ok(Command::Query(Box::new(Options {
+        domain,
+        record_type: record_type.unwrap_or(DnsRecordType::A),
+        upstreams,
+        strategy,
+        timeout,
+        subnet,
+        dnssec,
+        json,
+        stats,
+        verbose,
+    })))
+}
+
+async fn build_resolver(options: &Options) -> rat_quickdns::Result<SmartDnsResolver> {
+    let logger = if options.verbose { LoggerInitStrategy::Debug } else { LoggerInitStrategy::None };
+    let mut builder = DnsResolverBuilder::new(options.strategy, true, "global".to_string())
+        .with_logger_init_strategy(logger)
+        .with_timeout(options.timeout)
+        .with_retry_count(0);
+    for (index, upstream) in options.upstreams.iter().enumerate() {
+        builder = builder.add_upstream(upstream.to_spec(format!("{}-{}", upstream.scheme(), index + 1)))?;
+    }
+    if let Some((ip, prefix)) = options.subnet {
+        builder = builder.with_default_client_ip(ip, prefix)?;
+    }
+    builder.build().await
+}
+
+/// 向单个上游发送一次查询，不经过缓存和上游监控
+async fn query_oneshot(options: &Options, upstream: &OneshotUpstream) -> rat_quickdns::Result<DnsQueryResponse> {
+    if options.verbose {
+        rat_quickdns::logger::install_dns_logger(rat_logger::LevelFilter::Debug)?;
+    }
+    let mut oneshot_options = OneshotOptions {
+        timeout: options.timeout,
+        dnssec: options.dnssec,
+        ..OneshotOptions::default()
+    };
+    if let Some((ip, prefix)) = options.subnet {
+        oneshot_options = oneshot_options.with_client_subnet(ip, prefix)?;
+    }
+    oneshot::query_upstream(upstream, &options.domain, options.record_type, &oneshot_options).await
+}
+
+/// 按响应码和错误决定退出码；失败且耗时达到超时时间时视为超时
+fn exit_code(response: &DnsQueryResponse, timeout: Duration) -> u8 {
+    match response.rcode {
+        Some(0) => EXIT_OK,
+        Some(2) => EXIT_SERVFAIL,
+        Some(3) => EXIT_NXDOMAIN,
+        Some(_) => EXIT_FAILURE,
+        None => {
+            let mentions_timeout = response.error.as_deref()
+                .is_some_and(|error| error.to_ascii_lowercase().contains("timeout"));
+            if mentions_timeout || u128::from(response.duration_ms) >= timeout.as_millis() {
+                EXIT_TIMEOUT
+            } else {
+                EXIT_FAILURE
+            }
+        },
+    }
+}
+
+fn print_pretty(response: &DnsQueryResponse) {
+    let status = match (response.rcode, &response.error) {
+        (Some(rcode), _) => ResponseCode::from(rcode).name(),
+        (None, Some(error)) => format!("FAILED ({})", error),
+        (None, None) => "FAILED".to_string(),
+    };
+    println!(";; {} {}  status: {}", response.domain, response.record_type.as_str(), status);
+    if let (Some(server), Some(protocol)) = (&response.server_used, &response.protocol_used) {
+        println!(";; server: {} ({})  time: {} ms", server, protocol, response.duration_ms);
+    } else {
+        println!(";; time: {} ms", response.duration_ms);
+    }
+    for record in response.records.iter().chain(&response.dnssec_records) {
+        println!("{}\t{}\tIN\t{}\t{}", record.name, record.ttl, record.record_type.as_str(), record.value);
+    }
+}
+
+async fn print_stats(resolver: &SmartDnsResolver) {
+    let stats = resolver.get_stats().await;
+    println!();
+    println!(";; 统计: 查询 {}，成功 {}，失败 {}，成功率 {:.1}%",
+        stats.total_queries, stats.successful_queries, stats.failed_queries, stats.success_rate() * 100.0);
+    println!(";; 延迟: p50 {:?}  p95 {:?}  p99 {:?}", stats.p50_latency, stats.p95_latency, stats.p99_latency);
+    for upstream in resolver.get_upstream_status().await {
+        println!(";; 上游 {} {} ({:?}): {}，查询 {}，成功率 {:.1}%，平均延迟 {:?}",
+            upstream.name,
+            upstream.server,
+            upstream.transport_type,
+            if upstream.is_available { "可用" } else { "不可用" },
+            upstream.total_queries,
+            upstream.success_rate * 100.0,
+            upstream.avg_latency);
+    }
+}
+
+async fn stats_json(resolver: &SmartDnsResolver) -> serde_json::Value {
+    let mut stats = resolver.get_stats().await.to_json_value();
+    let upstreams: Vec<_> = resolver.get_upstream_status().await.iter()
+        .map(|upstream| upstream.to_json_value())
+        .collect();
+    stats["upstreams"] = serde_json::Value::Array(upstreams);
+    stats
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Command::Query(options)) => options,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return ExitCode::from(EXIT_OK);
+        },
+        Err(e) => {
+            eprintln!("ratdig: {}\n\n{}", e, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        },
+    };
+
+    // 只有一个上游且不需要统计时直接发送一次查询，不构建解析器
+    let (response, resolver) = match (options.upstreams.as_slice(), options.stats) {
+        ([upstream], false) => match query_oneshot(&options, upstream).await {
+            Ok(response) => (response, None),
+            Err(e) => {
+                eprintln!("ratdig: 查询失败: {}", e);
+                return ExitCode::from(EXIT_USAGE);
+            },
+        },
+        _ => {
+            let resolver = match build_resolver(&options).await {
+                Ok(resolver) => resolver,
+                Err(e) => {
+                    eprintln!("ratdig: 创建解析器失败: {}", e);
+                    return ExitCode::from(EXIT_USAGE);
+                },
+            };
+            let mut request = DnsQueryRequest::new(options.domain.clone(), options.record_type);
+            request.enable_dnssec = options.dnssec;
+            match resolver.query(request).await {
+                Ok(response) => (response, Some(resolver)),
+                Err(e) => {
+                    eprintln!("ratdig: 查询失败: {}", e);
+                    return ExitCode::from(EXIT_FAILURE);
+                },
+            }
+        },
+    };
+
+    if options.json {
+        let mut output = serde_json::to_value(&response).expect("DnsQueryResponse is serializable");
+        if let Some(resolver) = resolver.as_ref().filter(|_| options.stats) {
+            output = serde_json::json!({ "response": output, "stats": stats_json(resolver).await });
+        }
+        println!("{}", serde_json::to_string_pretty(&output).expect("JSON value is serializable"));
+    } else {
+        print_pretty(&response);
+        if let Some(resolver) = resolver.as_ref().filter(|_| options.stats) {
+            print_stats(resolver).await;
+        }
+    }
+
+    ExitCode::from(exit_code(&response, options.timeout))
+}
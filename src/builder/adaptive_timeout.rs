@@ -0,0 +1,138 @@
+//! 按观测延迟自适应的上游超时
+//!
+//! 一个全局的 `default_timeout` 对不同上游总是不合适：p99只有10ms的本地上游等满5秒才判定失败，
+//! 卫星链路上的DoH上游又可能被同样的超时掐断。开启自适应超时后，每个上游每次发送的超时为
+//! `clamp(p99 × multiplier, floor, ceiling)`，p99取自决策引擎中该上游成功查询的延迟直方图。
+//! 超时由后台任务每隔 `interval` 重新计算一次，不在每次查询时计算；样本数不足 `min_samples` 时
+//! 沿用上游单独配置的超时或解析器的默认超时。无论哪种超时，单次发送都还受请求的 `timeout_ms` 限制
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::histogram::LatencyHistogram;
+use crate::error::{DnsError, Result};
+
+/// 自适应超时配置
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveTimeoutConfig {
+    /// p99延迟的倍数，不小于1
+    pub multiplier: f64,
+    /// 超时下限
+    pub floor: Duration,
+    /// 超时上限，可以超过配置的超时，让慢而可用的上游不被掐断
+    pub ceiling: Duration,
+    /// 至少要有的成功查询样本数，不足时沿用配置的超时
+    pub min_samples: u64,
+    /// 重新计算的间隔
+    pub interval: Duration,
+}
+
+impl Default for AdaptiveTimeoutConfig {
+    fn default() -> Self {
+        Self {
+            multiplier: 3.0,
+            floor: Duration::from_millis(100),
+            ceiling: Duration::from_secs(10),
+            min_samples: 50,
+            interval: Duration::from_secs(30),
+        }
+    }
+}
+
+impl AdaptiveTimeoutConfig {
+    /// 创建配置，重新计算的间隔为30秒
+    pub fn new(multiplier: f64, floor: Duration, ceiling: Duration, min_samples: u64) -> Self {
+        Self { multiplier, floor, ceiling, min_samples, ..Self::default() }
+    }
+
+    /// 检查配置，倍数小于1、下限为零、上限小于下限、样本数或间隔为零时返回 [`DnsError::InvalidConfig`]
+    pub fn validate(&self) -> Result<()> {
+        if !(self.multiplier >= 1.0 && self.multiplier.is_finite()) {
+            return Err(DnsError::InvalidConfig(format!(
+                "Adaptive timeout multiplier must be a finite number of at least 1, got {}", self.multiplier
+            )));
+        }
+        if self.floor.is_zero() {
+            return Err(DnsError::InvalidConfig("Adaptive timeout floor cannot be zero".to_string()));
+        }
+        if self.ceiling < self.floor {
+            return Err(DnsError::InvalidConfig(format!(
+                "Adaptive timeout ceiling ({:?}) must not be shorter than the floor ({:?})",
+                self.ceiling, self.floor
+            )));
+        }
+        if self.min_samples == 0 {
+            return Err(DnsError::InvalidConfig("Adaptive timeout min_samples must be at least 1".to_string()));
+        }
+        if self.interval.is_zero() {
+            return Err(DnsError::InvalidConfig("Adaptive timeout interval cannot be zero".to_string()));
+        }
+        Ok(())
+    }
+
+    /// 由延迟直方图得出的超时，样本不足时为 `None`
+    pub fn compute(&self, histogram: &LatencyHistogram) -> Option<Duration> {
+        if histogram.count() < self.min_samples {
+            return None;
+        }
+        let scaled = histogram.percentile(0.99).mul_f64(self.multiplier);
+        Some(scaled.clamp(self.floor, self.ceiling))
+    }
+
+    /// 上游实际使用的超时：样本足够时为自适应超时，否则为 `configured`
+    pub fn effective_timeout(&self, histogram: &LatencyHistogram, configured: Duration) -> Duration {
+        self.compute(histogram).unwrap_or(configured)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(samples: &[(u64, usize)]) -> LatencyHistogram {
+        let histogram = LatencyHistogram::new();
+        for &(ms, count) in samples {
+            for _ in 0..count {
+                histogram.record(Duration::from_millis(ms));
+            }
+        }
+        histogram
+    }
+
+    #[test]
+    fn test_effective_timeout_follows_p99_within_bounds() {
+        let config = AdaptiveTimeoutConfig::new(3.0, Duration::from_millis(50), Duration::from_secs(8), 100);
+        let configured = Duration::from_secs(5);
+
+        // 样本不足时沿用配置的超时
+        assert_eq!(config.effective_timeout(&histogram(&[(10, 99)]), configured), configured);
+
+        // 快速上游：p99约20ms，3倍后约60ms
+        let fast = config.effective_timeout(&histogram(&[(10, 90), (20, 10)]), configured);
+        assert!(fast >= Duration::from_millis(50) && fast <= Duration::from_millis(72), "{:?}", fast);
+
+        // 更快的上游被下限托住
+        assert_eq!(config.effective_timeout(&histogram(&[(2, 100)]), configured), Duration::from_millis(50));
+
+        // 慢而可用的上游可以超过配置的超时，但不超过上限
+        let slow = config.effective_timeout(&histogram(&[(2_000, 100)]), configured);
+        assert!(slow > configured && slow <= Duration::from_secs(8), "{:?}", slow);
+        assert_eq!(config.effective_timeout(&histogram(&[(4_000, 100)]), configured), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn test_invalid_config_rejected() {
+        let valid = AdaptiveTimeoutConfig::default();
+        assert!(valid.validate().is_ok());
+        for invalid in [
+            AdaptiveTimeoutConfig { multiplier: 0.5, ..valid },
+            AdaptiveTimeoutConfig { multiplier: f64::NAN, ..valid },
+            AdaptiveTimeoutConfig { floor: Duration::ZERO, ..valid },
+            AdaptiveTimeoutConfig { ceiling: Duration::from_millis(10), ..valid },
+            AdaptiveTimeoutConfig { min_samples: 0, ..valid },
+            AdaptiveTimeoutConfig { interval: Duration::ZERO, ..valid },
+        ] {
+            assert!(invalid.validate().is_err(), "{:?}", invalid);
+        }
+    }
+}
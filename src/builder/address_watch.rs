@@ -0,0 +1,365 @@
+//! 地址变更订阅
+//!
+//! 持有长连接的服务（gRPC通道、数据库连接池）需要在名称的地址集合变化时重新均衡连接，
+//! 而不是自己定期调用解析。[`SmartDnsResolver::watch`] 每隔一段时间不读缓存地重新解析，
+//! 与上一次的地址集合比较（不计顺序），只在变化时产生事件。同一名称、记录类型和间隔的订阅共用一个后台轮询任务，
+//! 最后一个订阅被丢弃时任务随之中止；任务只持有解析器的弱引用，解析器释放后所有订阅结束。
+
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use crate::runtime;
+use crate::time::SystemTime;
+
+use futures::stream::{BoxStream, Stream, StreamExt};
+use tokio::sync::broadcast;
+use crate::runtime::JoinHandle;
+
+use crate::error::{DnsError, Result};
+use crate::resolver::priority::QueryPriority;
+use crate::utils::{normalize_name, validate_domain};
+use crate::{dns_debug, dns_info, dns_warn};
+use super::resolver::SmartDnsResolver;
+use super::types::{DnsQueryRequest, DnsRecordType};
+use super::zone_watch::failure_backoff;
+
+/// 每个轮询任务为订阅缓存的未读事件数，消费过慢的订阅会跳过更早的事件
+const EVENT_BUFFER: usize = 16;
+
+/// 名称地址集合的一次变化
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressChange {
+    /// 订阅的名称（规范化后）
+    pub domain: String,
+    /// 订阅的记录类型（A或AAAA）
+    pub record_type: DnsRecordType,
+    /// 新出现的地址
+    pub added: Vec<IpAddr>,
+    /// 不再出现的地址
+    pub removed: Vec<IpAddr>,
+    /// 变化后的完整地址集合（已排序）；域名不存在或没有该类型的记录时为空
+    pub full_set: Vec<IpAddr>,
+    /// 观察到变化的时间
+    pub observed_at: SystemTime,
+}
+
+/// 地址订阅产生的事件
+#[derive(Debug, Clone)]
+pub enum AddressEvent {
+    /// 地址集合变化
+    Changed(AddressChange),
+    /// 一次轮询失败（超时、上游不可用等）。订阅继续，`retry_in` 后重试，
+    /// 之后的变化仍与最近一次成功解析的地址集合比较
+    Error {
+        /// 本次轮询的错误
+        error: DnsError,
+        /// 连续失败次数
+        consecutive_failures: u32,
+        /// 下一次轮询前的等待时间
+        retry_in: Duration,
+    },
+}
+
+/// 订阅的键：规范化的名称、记录类型和轮询间隔
+type WatchKey = (String, DnsRecordType, Duration);
+
+type PollTable = Mutex<HashMap<WatchKey, Weak<SharedPoll>>>;
+
+/// 解析器上正在进行的地址订阅，按键共用轮询任务
+#[derive(Default)]
+pub(super) struct AddressWatchers {
+    polls: Arc<PollTable>,
+}
+
+impl AddressWatchers {
+    /// 订阅 `domain` 的地址变化：已有相同键的轮询任务时加入它，否则启动一个
+    pub(super) fn subscribe(
+        &self,
+        resolver: &Arc<SmartDnsResolver>,
+        domain: &str,
+        record_type: DnsRecordType,
+        interval: Duration,
+    ) -> Result<AddressWatch> {
+        if interval.is_zero() {
+            return Err(DnsError::InvalidConfig("Address watch interval must be greater than zero".to_string()));
+        }
+        if !matches!(record_type, DnsRecordType::A | DnsRecordType::AAAA) {
+            return Err(DnsError::InvalidConfig(format!(
+                "Address watch only supports A and AAAA, got {}", record_type.as_str()
+            )));
+        }
+        validate_domain(domain)?;
+        let key = (normalize_name(domain), record_type, interval);
+
+        let mut polls = self.polls.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let existing = polls.get(&key).and_then(Weak::upgrade);
+        let receiver = existing.as_ref().and_then(|poll| poll.events.upgrade()).map(|sender| sender.subscribe());
+        // 任务已经退出的旧登记要在释放锁之后再丢弃：它的Drop会再次获取这把锁
+        let mut stale = None;
+        let (receiver, poll) = match (receiver, existing) {
+            (Some(receiver), Some(poll)) => (receiver, poll),
+            (_, existing) => {
+                stale = existing;
+                dns_info!("开始监视 {} 的{}地址，间隔 {:?}", key.0, record_type.as_str(), interval);
+                let (sender, receiver) = broadcast::channel(EVENT_BUFFER);
+                let poll = Arc::new(SharedPoll {
+                    key: key.clone(),
+                    events: sender.downgrade(),
+                    task: runtime::spawn(run_poll(Arc::downgrade(resolver), key.clone(), sender)),
+                    table: Arc::downgrade(&self.polls),
+                });
+                polls.insert(key, Arc::downgrade(&poll));
+                (receiver, poll)
+            }
+        };
+        drop(polls);
+        drop(stale);
+
+        let events = futures::stream::unfold((receiver, poll), |(mut receiver, poll)| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, (receiver, poll))),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        dns_warn!("{} 的地址订阅消费过慢，跳过了 {} 个事件", poll.key.0, skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(AddressWatch { events: events.boxed() })
+    }
+
+    /// 正在运行的轮询任务数
+    pub(super) fn active(&self) -> usize {
+        let polls = self.polls.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        polls.values().filter(|poll| poll.strong_count() > 0).count()
+    }
+}
+
+impl std::fmt::Debug for AddressWatchers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AddressWatchers").field("active", &self.active()).finish()
+    }
+}
+
+/// 一个轮询任务，由同一键的所有订阅共同持有；最后一个订阅丢弃时中止任务并注销
+struct SharedPoll {
+    key: WatchKey,
+    /// 供后来的订阅加入；发送端由轮询任务持有，任务退出后订阅随之结束
+    events: broadcast::WeakSender<AddressEvent>,
+    task: JoinHandle<()>,
+    table: Weak<PollTable>,
+}
+
+impl Drop for SharedPoll {
+    fn drop(&mut self) {
+        self.task.abort();
+        if let Some(table) = self.table.upgrade() {
+            let mut polls = table.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            // 同一键可能已经登记了新的任务，只移除已经失效的登记
+            if polls.get(&self.key).is_some_and(|poll| poll.strong_count() == 0) {
+                polls.remove(&self.key);
+            }
+        }
+        dns_debug!("停止监视 {} 的{}地址", self.key.0, self.key.1.as_str());
+    }
+}
+
+/// [`SmartDnsResolver::watch`] 返回的事件流，丢弃即取消订阅
+pub struct AddressWatch {
+    events: BoxStream<'static, AddressEvent>,
+}
+
+impl Stream for AddressWatch {
+    type Item = AddressEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<AddressEvent>> {
+        self.events.poll_next_unpin(cx)
+    }
+}
+
+impl std::fmt::Debug for AddressWatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AddressWatch").finish_non_exhaustive()
+    }
+}
+
+/// 轮询任务：第一次成功的解析只记录基准地址集合，之后集合变化时发送 [`AddressEvent::Changed`]
+async fn run_poll(resolver: Weak<SmartDnsResolver>, key: WatchKey, events: broadcast::Sender<AddressEvent>) {
+    let (domain, record_type, interval) = key;
+    let mut current: Option<BTreeSet<IpAddr>> = None;
+    let mut failures = 0u32;
+    loop {
+        let Some(live) = resolver.upgrade() else {
+            break;
+        };
+        let request = DnsQueryRequest::new(domain.as_str(), record_type)
+            .disable_cache()
+            .with_priority(QueryPriority::Bulk);
+        let result = live.lookup_ip_with(request).await;
+        drop(live);
+
+        let addresses = match result {
+            Ok(addresses) => addresses.into_iter().collect(),
+            // 域名不存在或没有该类型的记录也是一种地址集合：空集
+            Err(DnsError::NxDomain | DnsError::NoRecords { .. }) => BTreeSet::new(),
+            Err(error) => {
+                failures = failures.saturating_add(1);
+                let retry_in = failure_backoff(interval, failures);
+                dns_warn!("监视 {} 的{}地址失败（连续 {} 次），{:?} 后重试: {}", domain, record_type.as_str(), failures, retry_in, error);
+                let _ = events.send(AddressEvent::Error { error, consecutive_failures: failures, retry_in });
+                runtime::sleep(retry_in).await;
+                continue;
+            }
+        };
+        failures = 0;
+
+        match &current {
+            None => dns_debug!("{} 的{}基准地址: {:?}", domain, record_type.as_str(), addresses),
+            Some(previous) if *previous != addresses => {
+                let change = AddressChange {
+                    domain: domain.clone(),
+                    record_type,
+                    added: addresses.difference(previous).copied().collect(),
+                    removed: previous.difference(&addresses).copied().collect(),
+                    full_set: addresses.iter().copied().collect(),
+                    observed_at: SystemTime::now(),
+                };
+                dns_info!("{} 的{}地址变化: 新增 {:?}，移除 {:?}", domain, record_type.as_str(), change.added, change.removed);
+                let _ = events.send(AddressEvent::Changed(change));
+            }
+            Some(_) => {}
+        }
+        current = Some(addresses);
+        runtime::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{DnsResolverBuilder, QueryStrategy};
+    use crate::dns_response::DnsResponseWrapper;
+    use crate::transport::mock::MockTransport;
+    use crate::types::RecordType;
+    use std::net::Ipv4Addr;
+
+    const NAME: &str = "db.example.com";
+    const INTERVAL: Duration = Duration::from_millis(20);
+
+    fn answer(last_octets: &[u8]) -> crate::types::Response {
+        let ips: Vec<Ipv4Addr> = last_octets.iter().map(|&octet| Ipv4Addr::new(192, 0, 2, octet)).collect();
+        DnsResponseWrapper::create_a_response(0, NAME, &ips, 300)
+    }
+
+    fn ip(last_octet: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last_octet))
+    }
+
+    async fn resolver(mock: MockTransport) -> Arc<SmartDnsResolver> {
+        let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .with_cache(true)
+            .with_retry_count(0)
+            .add_mock_upstream("mock", mock)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        Arc::new(resolver)
+    }
+
+    async fn next_change(watch: &mut AddressWatch) -> AddressChange {
+        loop {
+            match tokio::time::timeout(Duration::from_secs(2), watch.next()).await.unwrap().unwrap() {
+                AddressEvent::Changed(change) => return change,
+                AddressEvent::Error { .. } => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_one_event_per_actual_change() {
+        let mock = MockTransport::new().with_response(NAME, RecordType::A, answer(&[1, 2]));
+        let resolver = resolver(mock.clone()).await;
+        let mut watch = resolver.watch(NAME, DnsRecordType::A, INTERVAL).unwrap();
+
+        // 地址不变（包括只是顺序变化）时没有事件
+        tokio::time::sleep(INTERVAL * 3).await;
+        mock.set_response(NAME, RecordType::A, answer(&[2, 1]));
+        assert!(tokio::time::timeout(INTERVAL * 5, watch.next()).await.is_err());
+
+        mock.set_response(NAME, RecordType::A, answer(&[2, 3]));
+        let change = next_change(&mut watch).await;
+        assert_eq!((change.added, change.removed, change.full_set), (vec![ip(3)], vec![ip(1)], vec![ip(2), ip(3)]));
+        assert_eq!(change.domain, NAME);
+        assert!(tokio::time::timeout(INTERVAL * 5, watch.next()).await.is_err());
+
+        // 每次轮询都绕过缓存
+        let polls = mock.call_count();
+        tokio::time::sleep(INTERVAL * 5).await;
+        assert!(mock.call_count() > polls);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_reported_and_watch_continues() {
+        let mock = MockTransport::new().with_response(NAME, RecordType::A, answer(&[1]));
+        let resolver = resolver(mock.clone()).await;
+        let mut watch = resolver.watch(NAME, DnsRecordType::A, INTERVAL).unwrap();
+        tokio::time::sleep(INTERVAL * 2).await;
+
+        mock.set_healthy(false);
+        match tokio::time::timeout(Duration::from_secs(2), watch.next()).await.unwrap().unwrap() {
+            AddressEvent::Error { consecutive_failures, retry_in, .. } => {
+                assert_eq!(consecutive_failures, 1);
+                assert_eq!(retry_in, INTERVAL * 2);
+            }
+            other => panic!("expected an error event, got {:?}", other),
+        }
+
+        // 恢复后与失败前的地址集合比较
+        mock.set_response(NAME, RecordType::A, answer(&[4]));
+        mock.set_healthy(true);
+        let change = next_change(&mut watch).await;
+        assert_eq!((change.added, change.removed), (vec![ip(4)], vec![ip(1)]));
+    }
+
+    #[tokio::test]
+    async fn test_watchers_share_one_poll_and_drop_cancels_it() {
+        let mock = MockTransport::new().with_response(NAME, RecordType::A, answer(&[1]));
+        let resolver = resolver(mock.clone()).await;
+        let mut first = resolver.watch(NAME, DnsRecordType::A, INTERVAL).unwrap();
+        let mut second = resolver.watch("DB.Example.com.", DnsRecordType::A, INTERVAL).unwrap();
+        assert_eq!(resolver.address_watch_count(), 1);
+        let other = resolver.watch(NAME, DnsRecordType::AAAA, INTERVAL).unwrap();
+        assert_eq!(resolver.address_watch_count(), 2);
+        drop(other);
+        assert_eq!(resolver.address_watch_count(), 1);
+
+        tokio::time::sleep(INTERVAL * 2).await;
+        mock.set_response(NAME, RecordType::A, answer(&[1, 2]));
+        assert_eq!(next_change(&mut first).await.added, vec![ip(2)]);
+        assert_eq!(next_change(&mut second).await.added, vec![ip(2)]);
+
+        // 丢弃一个订阅不影响另一个，丢弃最后一个后不再查询
+        drop(first);
+        assert_eq!(resolver.address_watch_count(), 1);
+        drop(second);
+        assert_eq!(resolver.address_watch_count(), 0);
+        let polls = mock.call_count();
+        tokio::time::sleep(INTERVAL * 5).await;
+        assert_eq!(mock.call_count(), polls);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_watches_are_rejected() {
+        let resolver = resolver(MockTransport::new()).await;
+        assert!(resolver.watch(NAME, DnsRecordType::A, Duration::ZERO).is_err());
+        assert!(resolver.watch(NAME, DnsRecordType::MX, INTERVAL).is_err());
+        assert!(resolver.watch("bad name", DnsRecordType::A, INTERVAL).is_err());
+        assert_eq!(resolver.address_watch_count(), 0);
+    }
+}
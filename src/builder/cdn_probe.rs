@@ -0,0 +1,254 @@
+//! CDN准确性探测
+//!
+//! 决策引擎的CDN准确性评分来自这里：定期通过每个上游查询调用方配置的探测域名，检查应答中的A记录
+//! 是否落在解析器所在区域应得到的地址段内（可选地再检查CNAME是否指向预期的CDN）。
+//! 没有配置探测时CDN准确性不参与上游评分，普通查询也不会改变这一评分。
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::engine::SmartDecisionEngine;
+use crate::error::{DnsError, Result};
+use crate::resolver::CoreResolver;
+use crate::types::{QClass, RecordData, RecordType, Response};
+use crate::dns_debug;
+
+/// 默认的CDN探测间隔
+pub const DEFAULT_CDN_PROBE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// IP地址段（如 `203.0.113.0/24`、`2001:db8::/32`）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpCidr {
+    /// 网络地址（主机位已清零）
+    pub network: IpAddr,
+    /// 前缀长度
+    pub prefix_len: u8,
+}
+
+impl IpCidr {
+    /// 创建地址段，前缀长度超出地址位数时返回 [`DnsError::InvalidConfig`]
+    pub fn new(address: IpAddr, prefix_len: u8) -> Result<Self> {
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        if prefix_len > bits {
+            return Err(DnsError::InvalidConfig(format!(
+                "Prefix length {} exceeds {} bits for {}", prefix_len, bits, address
+            )));
+        }
+        let network = match address {
+            IpAddr::V4(addr) => IpAddr::V4((u32::from(addr) & Self::mask_v4(prefix_len)).into()),
+            IpAddr::V6(addr) => IpAddr::V6((u128::from(addr) & Self::mask_v6(prefix_len)).into()),
+        };
+        Ok(Self { network, prefix_len })
+    }
+
+    /// 地址是否在该地址段内（IPv4与IPv6互不匹配）
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                u32::from(*addr) & Self::mask_v4(self.prefix_len) == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                u128::from(*addr) & Self::mask_v6(self.prefix_len) == u128::from(network)
+            }
+            _ => false,
+        }
+    }
+
+    fn mask_v4(prefix_len: u8) -> u32 {
+        u32::MAX.checked_shl(32 - u32::from(prefix_len)).unwrap_or(0)
+    }
+
+    fn mask_v6(prefix_len: u8) -> u128 {
+        u128::MAX.checked_shl(128 - u32::from(prefix_len)).unwrap_or(0)
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = DnsError;
+
+    /// 解析 `地址/前缀` 形式的地址段；只写地址时视为单个主机
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim();
+        let (address, prefix_len) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let address: IpAddr = address.parse()
+            .map_err(|_| DnsError::InvalidConfig(format!("Invalid CIDR address: {}", value)))?;
+        let prefix_len = match prefix_len {
+            Some(prefix) => prefix.parse()
+                .map_err(|_| DnsError::InvalidConfig(format!("Invalid CIDR prefix length: {}", value)))?,
+            None if address.is_ipv4() => 32,
+            None => 128,
+        };
+        Self::new(address, prefix_len)
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// 一个CDN探测：在 `region` 区域查询 `domain` 时，正确的应答应落在 `expected_cidrs` 内
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CdnProbe {
+    /// 探测域名（通常是接入了CDN、按解析来源调度的域名）
+    pub domain: String,
+    /// 该区域应得到的A记录地址段
+    pub expected_cidrs: Vec<IpCidr>,
+    /// 探测适用的区域，只有与解析器区域相同的探测才会执行
+    pub region: String,
+    /// 可选：应答中的CNAME目标应以其中之一结尾（如 `.cdn.example.net`），为空时不检查
+    #[serde(default)]
+    pub expected_cname_suffixes: Vec<String>,
+}
+
+impl CdnProbe {
+    /// 创建探测，地址段写作 `地址/前缀`；地址段无法解析时返回 [`DnsError::InvalidConfig`]
+    pub fn new(domain: impl Into<String>, region: impl Into<String>, expected_cidrs: &[&str]) -> Result<Self> {
+        let expected_cidrs = expected_cidrs.iter()
+            .map(|cidr| cidr.parse())
+            .collect::<Result<Vec<IpCidr>>>()?;
+        Ok(Self {
+            domain: domain.into(),
+            expected_cidrs,
+            region: region.into(),
+            expected_cname_suffixes: Vec::new(),
+        })
+    }
+
+    /// 要求应答中的CNAME目标以给定后缀之一结尾
+    pub fn with_cname_suffixes(mut self, suffixes: Vec<String>) -> Self {
+        self.expected_cname_suffixes = suffixes;
+        self
+    }
+
+    /// 检查配置，域名为空或没有地址段时返回 [`DnsError::InvalidConfig`]
+    pub fn validate(&self) -> Result<()> {
+        if self.domain.trim().is_empty() {
+            return Err(DnsError::InvalidConfig("CDN probe domain cannot be empty".to_string()));
+        }
+        if self.expected_cidrs.is_empty() {
+            return Err(DnsError::InvalidConfig(
+                format!("CDN probe '{}' requires at least one expected CIDR", self.domain)
+            ));
+        }
+        Ok(())
+    }
+
+    /// 应答是否是该区域的正确CDN应答：至少有一条A记录且全部落在预期地址段内，
+    /// 配置了CNAME后缀时还要有指向预期CDN的CNAME
+    pub fn is_accurate(&self, response: &Response) -> bool {
+        let addresses: Vec<IpAddr> = response.answers.iter()
+            .filter_map(|record| match &record.data {
+                RecordData::A(addr) => Some(IpAddr::V4(*addr)),
+                _ => None,
+            })
+            .collect();
+        if addresses.is_empty()
+            || !addresses.iter().all(|addr| self.expected_cidrs.iter().any(|cidr| cidr.contains(addr)))
+        {
+            return false;
+        }
+
+        self.expected_cname_suffixes.is_empty() || response.answers.iter().any(|record| match &record.data {
+            RecordData::CNAME(target) => {
+                let target = target.trim_end_matches('.').to_ascii_lowercase();
+                self.expected_cname_suffixes.iter().any(|suffix| {
+                    target.ends_with(&suffix.trim_end_matches('.').to_ascii_lowercase())
+                })
+            }
+            _ => false,
+        })
+    }
+}
+
+/// 通过每个启用的上游执行一轮探测，把判定结果计入决策引擎的性能指标
+///
+/// 查询失败记为该上游的一次失败，不影响CDN准确性评分
+pub(crate) async fn run_cdn_probes(
+    resolver: &CoreResolver,
+    engine: &SmartDecisionEngine,
+    probes: &[CdnProbe],
+    max_concurrency: usize,
+) -> Result<()> {
+    for probe in probes {
+        let results = resolver
+            .query_each(&probe.domain, RecordType::A, QClass::IN, None, max_concurrency)
+            .await?;
+        for (info, result) in results {
+            match result {
+                Ok(response) => {
+                    let accurate = probe.is_accurate(&response);
+                    dns_debug!("CDN探测 {} 经上游 {}: {}", probe.domain, info.name, if accurate { "符合预期" } else { "不符合预期" });
+                    engine.update_metrics(&info.name, info.duration, true, Some(accurate)).await;
+                }
+                Err(e) => {
+                    dns_debug!("CDN探测 {} 经上游 {} 失败: {}", probe.domain, info.name, e);
+                    engine.update_metrics(&info.name, info.duration, false, None).await;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Record;
+    use crate::dns_response::DnsResponseWrapper;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_cidr_parse_and_contains() {
+        let cidr: IpCidr = "203.0.113.77/24".parse().unwrap();
+        assert_eq!(cidr.to_string(), "203.0.113.0/24");
+        assert!(cidr.contains(&"203.0.113.1".parse().unwrap()));
+        assert!(!cidr.contains(&"203.0.114.1".parse().unwrap()));
+        assert!(!cidr.contains(&"2001:db8::1".parse().unwrap()));
+
+        let v6: IpCidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(&"2001:db8:ffff::1".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<IpCidr>().unwrap().contains(&"198.51.100.1".parse().unwrap()));
+        assert_eq!("192.0.2.1".parse::<IpCidr>().unwrap().prefix_len, 32);
+
+        assert!("192.0.2.0/33".parse::<IpCidr>().is_err());
+        assert!("not-an-ip/8".parse::<IpCidr>().is_err());
+    }
+
+    #[test]
+    fn test_probe_judges_addresses_and_cnames() {
+        let probe = CdnProbe::new("cdn.example", "CN", &["203.0.113.0/24"]).unwrap();
+        let inside = DnsResponseWrapper::create_a_response(1, "cdn.example", &[Ipv4Addr::new(203, 0, 113, 5)], 60);
+        let mixed = DnsResponseWrapper::create_a_response(
+            1, "cdn.example", &[Ipv4Addr::new(203, 0, 113, 5), Ipv4Addr::new(198, 51, 100, 5)], 60,
+        );
+        let empty = DnsResponseWrapper::create_a_response(1, "cdn.example", &[], 60);
+        assert!(probe.is_accurate(&inside));
+        assert!(!probe.is_accurate(&mixed));
+        assert!(!probe.is_accurate(&empty));
+
+        let probe = probe.with_cname_suffixes(vec![".edge.example.net".to_string()]);
+        assert!(!probe.is_accurate(&inside));
+        let mut aliased = inside.clone();
+        aliased.answers.insert(0, Record {
+            name: "cdn.example".to_string(),
+            rtype: RecordType::CNAME,
+            class: QClass::IN,
+            ttl: 60,
+            data: RecordData::CNAME("cn.edge.example.net.".to_string()),
+        });
+        assert!(probe.is_accurate(&aliased));
+
+        assert!(CdnProbe::new("cdn.example", "CN", &[]).unwrap().validate().is_err());
+        assert!(CdnProbe::new("", "CN", &["203.0.113.0/24"]).unwrap().validate().is_err());
+        assert!(CdnProbe::new("cdn.example", "CN", &["bogus"]).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! 按主机名建立TCP连接（Happy Eyeballs，RFC 8305）
+//!
+//! 代理等嵌入场景最常见的需求是"给定主机名和端口，用这个解析器的答案连上它"。
+//! [`connect_with_resolver`] 用 [`resolve_with_ttl`](SmartDnsResolver::resolve_with_ttl) 同时解析A和AAAA，
+//! 按RFC 8305第4节交替排列两个地址族（偏好的地址族在前）后依次发起连接：前一个尝试在
+//! `happy_eyeballs_delay` 内没有结果就开始下一个，某个尝试失败时立即开始下一个。第一个连上的
+//! 连接胜出，其余尝试随之取消；所有地址都失败时返回 [`DnsError::ConnectFailed`]，列出每个地址的失败。
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::net::TcpStream;
+
+use super::resolver::SmartDnsResolver;
+use crate::error::{ConnectAttemptError, DnsError, NetworkErrorKind, Result};
+use crate::transport::{AddressFamily, AfPreference};
+
+/// 默认的连接尝试间隔（RFC 8305建议的Connection Attempt Delay）
+pub const DEFAULT_HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
+
+/// 默认的单个地址连接时限
+pub const DEFAULT_PER_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// [`connect_with_resolver`] 的参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectOptions {
+    /// 单个地址的连接时限，超过时该地址记为 `TimedOut`
+    pub per_attempt_timeout: Duration,
+    /// 前一个尝试没有结果时，开始下一个尝试前等待的时间
+    pub happy_eyeballs_delay: Duration,
+    /// 连接哪个地址族、哪个在前
+    pub af_preference: AfPreference,
+}
+
+impl Default for ConnectOptions {
+    /// 单次时限5秒，尝试间隔250毫秒，优先IPv6（RFC 8305）
+    fn default() -> Self {
+        Self {
+            per_attempt_timeout: DEFAULT_PER_ATTEMPT_TIMEOUT,
+            happy_eyeballs_delay: DEFAULT_HAPPY_EYEBALLS_DELAY,
+            af_preference: AfPreference::PreferV6,
+        }
+    }
+}
+
+impl ConnectOptions {
+    /// 设置单个地址的连接时限
+    pub fn with_per_attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.per_attempt_timeout = timeout;
+        self
+    }
+
+    /// 设置连接尝试间隔
+    pub fn with_happy_eyeballs_delay(mut self, delay: Duration) -> Self {
+        self.happy_eyeballs_delay = delay;
+        self
+    }
+
+    /// 设置地址族偏好
+    pub fn with_af_preference(mut self, preference: AfPreference) -> Self {
+        self.af_preference = preference;
+        self
+    }
+}
+
+/// 用解析器的答案连接 `host:port`
+///
+/// `host` 是IP地址时直接连接，不做解析。解析失败时返回解析的错误；没有地址族偏好允许的地址、
+/// 或所有地址都没有连上时返回 [`DnsError::ConnectFailed`]，`attempts` 按尝试顺序给出每个地址的失败。
+pub async fn connect_with_resolver(
+    resolver: &SmartDnsResolver,
+    host: &str,
+    port: u16,
+    opts: ConnectOptions,
+) -> Result<TcpStream> {
+    let addresses = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => resolver.resolve_with_ttl(host).await?.addresses,
+    };
+    let targets = connection_order(addresses, opts.af_preference)
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port));
+    race(targets, &opts).await.map_err(|attempts| DnsError::ConnectFailed {
+        host: host.to_string(),
+        port,
+        attempts,
+    })
+}
+
+/// 按RFC 8305第4节排列地址：去掉偏好不允许的地址族，两个地址族交替，偏好的地址族在前，
+/// 同一地址族内保持解析结果的顺序
+pub fn connection_order(addresses: Vec<IpAddr>, preference: AfPreference) -> Vec<IpAddr> {
+    let preferred = preference.preferred();
+    let (first, second): (Vec<IpAddr>, Vec<IpAddr>) = addresses.into_iter()
+        .filter(|ip| preference.allows(AddressFamily::of(*ip)))
+        .partition(|ip| AddressFamily::of(*ip) == preferred);
+    let mut ordered = Vec::with_capacity(first.len() + second.len());
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// 依次错开发起连接，返回第一个连上的连接；都失败时按尝试顺序返回各地址的失败
+async fn race(
+    targets: impl IntoIterator<Item = SocketAddr>,
+    opts: &ConnectOptions,
+) -> std::result::Result<TcpStream, Vec<ConnectAttemptError>> {
+    let mut queue = targets.into_iter().enumerate().peekable();
+    let mut attempts = FuturesUnordered::new();
+    let mut failures = Vec::new();
+    loop {
+        if let Some((index, addr)) = queue.next() {
+            attempts.push(async move { (index, attempt(addr, opts.per_attempt_timeout).await) });
+        }
+        let outcome = if queue.peek().is_some() {
+            tokio::select! {
+                outcome = attempts.next() => outcome,
+                _ = tokio::time::sleep(opts.happy_eyeballs_delay) => continue,
+            }
+        } else {
+            attempts.next().await
+        };
+        match outcome {
+            // 返回时丢弃其余尝试，未完成的连接随之关闭
+            Some((_, Ok(stream))) => return Ok(stream),
+            Some((index, Err(failure))) => failures.push((index, failure)),
+            None => break,
+        }
+    }
+    failures.sort_by_key(|(index, _)| *index);
+    Err(failures.into_iter().map(|(_, failure)| failure).collect())
+}
+
+/// 在时限内连接单个地址
+async fn attempt(addr: SocketAddr, timeout: Duration) -> std::result::Result<TcpStream, ConnectAttemptError> {
+    match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => Ok(stream),
+        Ok(Err(e)) => Err(ConnectAttemptError {
+            addr,
+            kind: NetworkErrorKind::from_io(&e),
+            message: e.to_string(),
+        }),
+        Err(_) => Err(ConnectAttemptError {
+            addr,
+            kind: NetworkErrorKind::TimedOut,
+            message: format!("no connection within {:?}", timeout),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(list: &[&str]) -> Vec<IpAddr> {
+        list.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_connection_order_interleaves_families() {
+        let answers = ips(&["192.0.2.1", "192.0.2.2", "192.0.2.3", "2001:db8::1", "2001:db8::2"]);
+        assert_eq!(
+            connection_order(answers.clone(), AfPreference::PreferV6),
+            ips(&["2001:db8::1", "192.0.2.1", "2001:db8::2", "192.0.2.2", "192.0.2.3"])
+        );
+        assert_eq!(
+            connection_order(answers.clone(), AfPreference::PreferV4),
+            ips(&["192.0.2.1", "2001:db8::1", "192.0.2.2", "2001:db8::2", "192.0.2.3"])
+        );
+        assert_eq!(connection_order(answers.clone(), AfPreference::V6Only), ips(&["2001:db8::1", "2001:db8::2"]));
+        assert_eq!(connection_order(answers, AfPreference::V4Only), ips(&["192.0.2.1", "192.0.2.2", "192.0.2.3"]));
+        assert!(connection_order(ips(&["2001:db8::1"]), AfPreference::V4Only).is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+//! 多上游一致性比对模块
+//!
+//! 本模块为诊断用的 `query_all` 提供数据结构与比对逻辑：把每个上游的回答规范化为
+//! 有序的答案集合，找出多数上游给出的答案，并列出与之不一致的上游及差异。
+//! 同一域名在不同上游得到不同答案通常意味着DNS污染或劫持（CDN调度导致的差异除外）。
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use crate::types::{RecordData, RecordType, Response};
+use super::lookup::normalize_host;
+
+/// 单个上游的查询结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PerUpstreamAnswer {
+    /// 上游名称
+    pub upstream: String,
+    /// 传输协议
+    pub protocol: String,
+    /// 查询耗时
+    pub latency: Duration,
+    /// 规范化后的答案集合（已排序去重），或错误信息
+    pub result: std::result::Result<Vec<String>, String>,
+}
+
+/// 与多数答案不一致的上游
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dissent {
+    /// 上游名称
+    pub upstream: String,
+    /// 该上游返回的答案集合
+    pub answers: Vec<String>,
+    /// 多数答案中有、该上游没有的记录
+    pub missing: Vec<String>,
+    /// 该上游有、多数答案中没有的记录
+    pub extra: Vec<String>,
+}
+
+/// 一致性汇总
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsensusSummary {
+    /// 多数上游给出的答案集合；没有任何上游成功时为None
+    ///
+    /// 得票相同时取最先出现的答案集合
+    pub majority: Option<Vec<String>>,
+    /// 给出多数答案的上游
+    pub agreeing: Vec<String>,
+    /// 答案与多数不一致的上游
+    pub dissenting: Vec<Dissent>,
+    /// 查询失败的上游
+    pub failed: Vec<String>,
+}
+
+impl ConsensusSummary {
+    /// 所有成功的上游答案完全一致
+    pub fn is_unanimous(&self) -> bool {
+        self.majority.is_some() && self.dissenting.is_empty()
+    }
+
+    /// 多数答案是否获得了超过半数成功上游的支持
+    pub fn has_strict_majority(&self) -> bool {
+        let responded = self.agreeing.len() + self.dissenting.len();
+        self.majority.is_some() && self.agreeing.len() * 2 > responded
+    }
+}
+
+/// `query_all` 的完整报告
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryAllReport {
+    /// 按上游添加顺序排列的各上游结果
+    pub answers: Vec<PerUpstreamAnswer>,
+    /// 一致性汇总
+    pub consensus: ConsensusSummary,
+}
+
+impl QueryAllReport {
+    /// 由各上游结果生成报告
+    pub fn new(answers: Vec<PerUpstreamAnswer>) -> Self {
+        let consensus = summarize(&answers);
+        Self { answers, consensus }
+    }
+}
+
+/// 把响应中指定类型的回答记录规范化为有序去重的字符串集合
+///
+/// 只比较所查询类型的记录，CNAME链等中间记录不参与比对
+pub fn answer_set(response: &Response, record_type: RecordType) -> Vec<String> {
+    response.answers.iter()
+        .filter(|record| record.rtype == record_type)
+        .map(|record| canonical_value(&record.data))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+fn canonical_value(data: &RecordData) -> String {
+    match data {
+        RecordData::A(addr) => addr.to_string(),
+        RecordData::AAAA(addr) => addr.to_string(),
+        RecordData::CNAME(name) | RecordData::NS(name) | RecordData::PTR(name) => normalize_host(name),
+        RecordData::MX { priority, exchange } => format!("{} {}", priority, normalize_host(exchange)),
+        RecordData::TXT(parts) => parts.concat(),
+        RecordData::SRV { priority, weight, port, target } => {
+            format!("{} {} {} {}", priority, weight, port, normalize_host(target))
+        },
+        RecordData::SOA { mname, rname, serial, refresh, retry, expire, minimum } => format!(
+            "{} {} {} {} {} {} {}",
+            normalize_host(mname), normalize_host(rname), serial, refresh, retry, expire, minimum
+        ),
+        RecordData::Unknown(bytes) => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
+
+/// 计算各上游结果的一致性汇总
+pub fn summarize(answers: &[PerUpstreamAnswer]) -> ConsensusSummary {
+    // (答案集合, 票数)，保持首次出现顺序，得票相同时先出现者胜出
+    let mut tally: Vec<(&Vec<String>, usize)> = Vec::new();
+    let mut failed = Vec::new();
+
+    for answer in answers {
+        match &answer.result {
+            Ok(set) => match tally.iter_mut().find(|(candidate, _)| *candidate == set) {
+                Some((_, votes)) => *votes += 1,
+                None => tally.push((set, 1)),
+            },
+            Err(_) => failed.push(answer.upstream.clone()),
+        }
+    }
+
+    let majority = tally.iter()
+        .fold(None, |best: Option<(&Vec<String>, usize)>, &(set, votes)| match best {
+            Some((_, best_votes)) if best_votes >= votes => best,
+            _ => Some((set, votes)),
+        })
+        .map(|(set, _)| set.clone());
+
+    let mut agreeing = Vec::new();
+    let mut dissenting = Vec::new();
+    if let Some(majority) = &majority {
+        for answer in answers {
+            let Ok(set) = &answer.result else { continue };
+            if set == majority {
+                agreeing.push(answer.upstream.clone());
+            } else {
+                dissenting.push(Dissent {
+                    upstream: answer.upstream.clone(),
+                    answers: set.clone(),
+                    missing: majority.iter().filter(|v| !set.contains(v)).cloned().collect(),
+                    extra: set.iter().filter(|v| !majority.contains(v)).cloned().collect(),
+                });
+            }
+        }
+    }
+
+    ConsensusSummary { majority, agreeing, dissenting, failed }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(upstream: &str, result: std::result::Result<Vec<&str>, &str>) -> PerUpstreamAnswer {
+        PerUpstreamAnswer {
+            upstream: upstream.to_string(),
+            protocol: "UDP".to_string(),
+            latency: Duration::from_millis(10),
+            result: result
+                .map(|set| set.into_iter().map(str::to_string).collect())
+                .map_err(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_unanimous() {
+        let summary = summarize(&[
+            answer("阿里DNS", Ok(vec!["93.184.216.34"])),
+            answer("Cloudflare DNS", Ok(vec!["93.184.216.34"])),
+        ]);
+        assert!(summary.is_unanimous());
+        assert_eq!(summary.agreeing.len(), 2);
+    }
+
+    #[test]
+    fn test_tie_prefers_first_answer_set() {
+        let summary = summarize(&[
+            answer("a", Ok(vec!["192.0.2.1"])),
+            answer("b", Ok(vec!["192.0.2.2"])),
+        ]);
+        assert_eq!(summary.majority, Some(vec!["192.0.2.1".to_string()]));
+        assert!(!summary.has_strict_majority());
+    }
+
+    #[test]
+    fn test_all_failed() {
+        let summary = summarize(&[answer("a", Err("timeout"))]);
+        assert_eq!(summary.majority, None);
+        assert_eq!(summary.failed, vec!["a".to_string()]);
+        assert!(!summary.is_unanimous());
+    }
+}
@@ -0,0 +1,449 @@
+//! 加密上游发现（DDR，RFC 9462）
+//!
+//! 向明文DNS上游查询 `_dns.resolver.arpa` 的SVCB记录，得到该上游声明的DoH/DoT等价服务
+//! （designated resolver）。只有证书同时覆盖原上游IP的加密服务才会被采用，
+//! 防止明文链路上的攻击者把客户端引导到任意服务器。
+//!
+//! SVCB记录（类型64）按原始RDATA解析，只识别DDR用到的参数：
+//! `alpn`、`port`、`ipv4hint`、`ipv6hint` 和 `dohpath`。
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+#[cfg(feature = "dot")]
+use tokio::net::TcpStream;
+#[cfg(feature = "dot")]
+use tokio::time::timeout;
+#[cfg(feature = "dot")]
+use tokio_rustls::TlsConnector;
+#[cfg(feature = "dot")]
+use tokio_rustls::rustls::{ClientConfig, ServerName};
+
+use crate::error::{DnsError, Result};
+#[cfg(feature = "dot")]
+use crate::transport::TlsTransport;
+use crate::types::RecordType;
+use crate::upstream_handler::UpstreamSpec;
+
+/// DDR查询的域名
+pub const DDR_QUERY_NAME: &str = "_dns.resolver.arpa";
+
+/// SVCB记录类型
+pub const SVCB_RECORD_TYPE: RecordType = RecordType::Unknown(64);
+
+const KEY_MANDATORY: u16 = 0;
+const KEY_ALPN: u16 = 1;
+const KEY_NO_DEFAULT_ALPN: u16 = 2;
+const KEY_PORT: u16 = 3;
+const KEY_IPV4HINT: u16 = 4;
+const KEY_IPV6HINT: u16 = 6;
+const KEY_DOHPATH: u16 = 7;
+
+/// 解析SVCB记录时能够处理的参数
+const SUPPORTED_KEYS: [u16; 6] = [KEY_ALPN, KEY_NO_DEFAULT_ALPN, KEY_PORT, KEY_IPV4HINT, KEY_IPV6HINT, KEY_DOHPATH];
+
+/// SVCB记录
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SvcbRecord {
+    /// 优先级，0表示别名模式
+    pub priority: u16,
+    /// 目标名称（不带结尾的点）
+    pub target: String,
+    /// 支持的应用层协议
+    pub alpn: Vec<String>,
+    /// 服务端口
+    pub port: Option<u16>,
+    /// IPv4地址提示
+    pub ipv4_hints: Vec<Ipv4Addr>,
+    /// IPv6地址提示
+    pub ipv6_hints: Vec<Ipv6Addr>,
+    /// DoH路径模板（如 `/dns-query{?dns}`）
+    pub dohpath: Option<String>,
+}
+
+fn take<'a>(data: &'a [u8], offset: &mut usize, len: usize) -> Result<&'a [u8]> {
+    let end = offset.checked_add(len).filter(|end| *end <= data.len())
+        .ok_or_else(|| DnsError::Parse("SVCB记录长度无效".to_string()))?;
+    let slice = &data[*offset..end];
+    *offset = end;
+    Ok(slice)
+}
+
+fn take_u16(data: &[u8], offset: &mut usize) -> Result<u16> {
+    let bytes = take(data, offset, 2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+impl SvcbRecord {
+    /// 从RDATA解析
+    ///
+    /// 目标名称按规范不允许压缩；`mandatory` 中列出无法处理的参数时返回错误，
+    /// 调用方应忽略该记录。
+    pub fn parse(rdata: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let priority = take_u16(rdata, &mut offset)?;
+
+        let mut labels = Vec::new();
+        loop {
+            let len = take(rdata, &mut offset, 1)?[0] as usize;
+            if len == 0 {
+                break;
+            }
+            if len > 63 {
+                return Err(DnsError::Parse("SVCB目标名称不能使用压缩指针".to_string()));
+            }
+            let label = take(rdata, &mut offset, len)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+        }
+
+        let mut record = SvcbRecord {
+            priority,
+            target: labels.join("."),
+            ..Default::default()
+        };
+        let mut mandatory = Vec::new();
+        while offset < rdata.len() {
+            let key = take_u16(rdata, &mut offset)?;
+            let len = take_u16(rdata, &mut offset)? as usize;
+            let value = take(rdata, &mut offset, len)?;
+            match key {
+                KEY_MANDATORY => {
+                    mandatory = value.chunks(2)
+                        .map(|chunk| chunk.try_into().map(u16::from_be_bytes))
+                        .collect::<std::result::Result<_, _>>()
+                        .map_err(|_| DnsError::Parse("SVCB mandatory参数长度无效".to_string()))?;
+                },
+                KEY_ALPN => {
+                    let mut pos = 0;
+                    while pos < value.len() {
+                        let len = take(value, &mut pos, 1)?[0] as usize;
+                        record.alpn.push(String::from_utf8_lossy(take(value, &mut pos, len)?).into_owned());
+                    }
+                },
+                KEY_PORT => {
+                    let mut pos = 0;
+                    record.port = Some(take_u16(value, &mut pos)?);
+                },
+                KEY_IPV4HINT => {
+                    record.ipv4_hints = value.chunks(4)
+                        .map(|chunk| <[u8; 4]>::try_from(chunk).map(Ipv4Addr::from))
+                        .collect::<std::result::Result<_, _>>()
+                        .map_err(|_| DnsError::Parse("SVCB ipv4hint参数长度无效".to_string()))?;
+                },
+                KEY_IPV6HINT => {
+                    record.ipv6_hints = value.chunks(16)
+                        .map(|chunk| <[u8; 16]>::try_from(chunk).map(Ipv6Addr::from))
+                        .collect::<std::result::Result<_, _>>()
+                        .map_err(|_| DnsError::Parse("SVCB ipv6hint参数长度无效".to_string()))?;
+                },
+                KEY_DOHPATH => {
+                    record.dohpath = Some(String::from_utf8(value.to_vec())
+                        .map_err(|_| DnsError::Parse("SVCB dohpath不是有效的UTF-8".to_string()))?);
+                },
+                _ => {},
+            }
+        }
+
+        if let Some(key) = mandatory.iter().find(|key| !SUPPORTED_KEYS.contains(key)) {
+            return Err(DnsError::NotImplemented(format!("SVCB mandatory key {} is not supported", key)));
+        }
+        Ok(record)
+    }
+
+    /// 编码为RDATA（参数按键值升序排列）
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = self.priority.to_be_bytes().to_vec();
+        for label in self.target.split('.').filter(|label| !label.is_empty()) {
+            out.push(label.len() as u8);
+            out.extend_from_slice(label.as_bytes());
+        }
+        out.push(0);
+
+        let mut param = |key: u16, value: Vec<u8>| {
+            out.extend_from_slice(&key.to_be_bytes());
+            out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            out.extend_from_slice(&value);
+        };
+        if !self.alpn.is_empty() {
+            param(KEY_ALPN, self.alpn.iter()
+                .flat_map(|id| std::iter::once(id.len() as u8).chain(id.bytes()))
+                .collect());
+        }
+        if let Some(port) = self.port {
+            param(KEY_PORT, port.to_be_bytes().to_vec());
+        }
+        if !self.ipv4_hints.is_empty() {
+            param(KEY_IPV4HINT, self.ipv4_hints.iter().flat_map(|ip| ip.octets()).collect());
+        }
+        if !self.ipv6_hints.is_empty() {
+            param(KEY_IPV6HINT, self.ipv6_hints.iter().flat_map(|ip| ip.octets()).collect());
+        }
+        if let Some(path) = &self.dohpath {
+            param(KEY_DOHPATH, path.as_bytes().to_vec());
+        }
+        out
+    }
+}
+
+/// 加密上游的协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DesignatedProtocol {
+    /// DNS over HTTPS（alpn `h2`，需要 `dohpath`）
+    DoH,
+    /// DNS over TLS（alpn `dot`）
+    DoT,
+}
+
+/// 明文上游声明的加密等价服务
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesignatedResolver {
+    /// 协议
+    pub protocol: DesignatedProtocol,
+    /// 服务名称（证书需要覆盖该名称）
+    pub target: String,
+    /// 服务端口
+    pub port: u16,
+    /// DoH路径（已去掉URI模板部分）
+    pub path: Option<String>,
+    /// 地址提示
+    pub addresses: Vec<IpAddr>,
+    /// SVCB优先级，数值越小越优先
+    pub priority: u16,
+}
+
+impl DesignatedResolver {
+    /// 从SVCB记录提取可用的加密服务
+    ///
+    /// 别名模式记录、目标为根的记录以及只声明本库不支持的协议（如 `h3`、`doq`）的记录不产生结果；
+    /// 同时声明 `h2` 和 `dot` 的记录产生两项。
+    pub fn from_svcb(record: &SvcbRecord) -> Vec<Self> {
+        if record.priority == 0 || record.target.is_empty() {
+            return Vec::new();
+        }
+        let addresses: Vec<IpAddr> = record.ipv4_hints.iter().copied().map(IpAddr::V4)
+            .chain(record.ipv6_hints.iter().copied().map(IpAddr::V6))
+            .collect();
+        let path = record.dohpath.as_deref()
+            .map(|template| template.split('{').next().unwrap_or_default().to_string())
+            .filter(|path| path.starts_with('/'));
+
+        let mut resolvers = Vec::new();
+        if let Some(path) = path.filter(|_| record.alpn.iter().any(|id| id == "h2")) {
+            resolvers.push(Self {
+                protocol: DesignatedProtocol::DoH,
+                target: record.target.clone(),
+                port: record.port.unwrap_or(443),
+                path: Some(path),
+                addresses: addresses.clone(),
+                priority: record.priority,
+            });
+        }
+        if record.alpn.iter().any(|id| id == "dot") {
+            resolvers.push(Self {
+                protocol: DesignatedProtocol::DoT,
+                target: record.target.clone(),
+                port: record.port.unwrap_or(853),
+                path: None,
+                addresses,
+                priority: record.priority,
+            });
+        }
+        resolvers
+    }
+
+    /// DoH服务的URL
+    pub fn url(&self) -> Option<String> {
+        let path = self.path.as_ref()?;
+        Some(match self.port {
+            443 => format!("https://{}{}", self.target, path),
+            port => format!("https://{}:{}{}", self.target, port, path),
+        })
+    }
+
+    /// 转换为上游规格，有IPv4地址提示时直接连接该地址（证书仍按服务名称校验）
+    pub fn to_upstream_spec(&self, name: String, weight: u32) -> UpstreamSpec {
+        let mut spec = match self.protocol {
+            DesignatedProtocol::DoH => UpstreamSpec::doh(name, self.url().unwrap_or_default()),
+            DesignatedProtocol::DoT => UpstreamSpec::dot(name, format!("{}:{}", self.target, self.port)),
+        }.with_weight(weight);
+        spec.resolved_ip = self.addresses.iter()
+            .find(|ip| ip.is_ipv4())
+            .map(|ip| ip.to_string());
+        spec
+    }
+}
+
+/// 校验加密服务确实由原上游指定
+#[async_trait]
+pub trait DesignationVerifier: std::fmt::Debug + Send + Sync {
+    /// 校验通过返回 `Ok(())`
+    async fn verify(&self, original: IpAddr, designated: &DesignatedResolver) -> Result<()>;
+}
+
+/// 按RFC 9462的已验证发现规则校验：与加密服务完成TLS握手，
+/// 要求证书链可信且证书的subjectAltName包含原上游IP；未启用 `dot` 特性时校验总是失败
+#[derive(Debug, Clone)]
+pub struct TlsDesignationVerifier {
+    #[cfg_attr(not(feature = "dot"), allow(dead_code))]
+    timeout: Duration,
+}
+
+impl TlsDesignationVerifier {
+    /// 创建校验器，连接和握手分别受 `timeout` 限制
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+#[async_trait]
+impl DesignationVerifier for TlsDesignationVerifier {
+    #[cfg(feature = "dot")]
+    async fn verify(&self, original: IpAddr, designated: &DesignatedResolver) -> Result<()> {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(TlsTransport::load_root_certs()?)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(config));
+
+        let connect = match designated.addresses.first() {
+            Some(ip) => timeout(self.timeout, TcpStream::connect((*ip, designated.port))).await,
+            None => timeout(self.timeout, TcpStream::connect((designated.target.as_str(), designated.port))).await,
+        };
+        let stream = connect
+            .map_err(|_| DnsError::Timeout { phase: None })?
+            .map_err(|e| DnsError::network_io(designated.target.clone(), "Failed to connect", &e))?;
+
+        // 以原上游IP作为校验名称，证书不包含该IP时握手失败
+        timeout(self.timeout, connector.connect(ServerName::IpAddress(original), stream))
+            .await
+            .map_err(|_| DnsError::Timeout { phase: None })?
+            .map_err(|e| DnsError::Tls(format!(
+                "Certificate of {} does not designate {}: {}", designated.target, original, e
+            )))?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "dot"))]
+    async fn verify(&self, original: IpAddr, designated: &DesignatedResolver) -> Result<()> {
+        Err(DnsError::InvalidConfig(format!(
+            "Verifying that {} designates {} requires the dot feature", designated.target, original
+        )))
+    }
+}
+
+/// 加密上游发现的参数
+#[derive(Debug, Clone)]
+pub struct DdrOptions {
+    /// 发现的加密上游使用的权重
+    pub weight: u32,
+    /// 发现加密上游后是否停用原明文上游
+    pub disable_plaintext: bool,
+    /// 加密服务的校验方式
+    pub verifier: Arc<dyn DesignationVerifier>,
+}
+
+impl DdrOptions {
+    /// 权重1、保留明文上游、按TLS证书校验（超时5秒）
+    pub fn new() -> Self {
+        Self {
+            weight: 1,
+            disable_plaintext: false,
+            verifier: Arc::new(TlsDesignationVerifier::new(Duration::from_secs(5))),
+        }
+    }
+
+    /// 设置发现的加密上游的权重
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// 设置是否停用原明文上游
+    pub fn with_disable_plaintext(mut self, disable: bool) -> Self {
+        self.disable_plaintext = disable;
+        self
+    }
+
+    /// 替换校验方式
+    pub fn with_verifier(mut self, verifier: Arc<dyn DesignationVerifier>) -> Self {
+        self.verifier = verifier;
+        self
+    }
+}
+
+impl Default for DdrOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 一次发现的结果
+#[derive(Debug, Clone, Default)]
+pub struct DdrReport {
+    /// 新注册的加密上游名称
+    pub registered: Vec<String>,
+    /// 被停用的明文上游名称
+    pub disabled: Vec<String>,
+    /// 失败的明文上游及原因（发现失败不影响解析器使用）
+    pub failures: Vec<(String, String)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cloudflare_svcb() -> SvcbRecord {
+        SvcbRecord {
+            priority: 1,
+            target: "one.one.one.one".to_string(),
+            alpn: vec!["h2".to_string(), "h3".to_string()],
+            port: None,
+            ipv4_hints: vec![Ipv4Addr::new(1, 1, 1, 1)],
+            ipv6_hints: vec!["2606:4700:4700::1111".parse().unwrap()],
+            dohpath: Some("/dns-query{?dns}".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_svcb_roundtrip_and_designation() {
+        let record = cloudflare_svcb();
+        let parsed = SvcbRecord::parse(&record.encode()).unwrap();
+        assert_eq!(parsed, record);
+
+        let designated = DesignatedResolver::from_svcb(&parsed);
+        assert_eq!(designated.len(), 1);
+        assert_eq!(designated[0].url().as_deref(), Some("https://one.one.one.one/dns-query"));
+
+        let spec = designated[0].to_upstream_spec("cf-ddr-doh".to_string(), 3);
+        assert_eq!(spec.server, "https://one.one.one.one/dns-query");
+        assert_eq!(spec.resolved_ip.as_deref(), Some("1.1.1.1"));
+        assert_eq!(spec.weight, 3);
+
+        let dot = SvcbRecord { alpn: vec!["dot".to_string()], port: Some(8853), dohpath: None, ..record };
+        let designated = DesignatedResolver::from_svcb(&dot);
+        assert_eq!(designated[0].protocol, DesignatedProtocol::DoT);
+        assert_eq!(designated[0].to_upstream_spec("dot".to_string(), 1).server, "one.one.one.one:8853");
+    }
+
+    #[test]
+    fn test_svcb_rejects_malformed_and_unsupported() {
+        let bytes = cloudflare_svcb().encode();
+        assert!(SvcbRecord::parse(&bytes[..bytes.len() - 3]).is_err());
+
+        // 目标名称使用压缩指针
+        assert!(SvcbRecord::parse(&[0, 1, 0xC0, 0x0C]).is_err());
+
+        // mandatory中列出了ech（5）
+        let mut with_mandatory = vec![0, 1, 0, 0, 0, 0, 2, 0, 5];
+        with_mandatory.extend_from_slice(&[0, 5, 0, 1, 0xAA]);
+        assert!(matches!(SvcbRecord::parse(&with_mandatory), Err(DnsError::NotImplemented(_))));
+
+        // 别名模式和缺少dohpath的h2不产生可用服务
+        let alias = SvcbRecord { priority: 0, ..cloudflare_svcb() };
+        assert!(DesignatedResolver::from_svcb(&alias).is_empty());
+        let no_path = SvcbRecord { dohpath: None, ..cloudflare_svcb() };
+        assert!(DesignatedResolver::from_svcb(&no_path).is_empty());
+    }
+}
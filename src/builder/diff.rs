@@ -0,0 +1,344 @@
+//! 查询结果比较：同一名称的两次查询之间，应答记录有哪些变化
+//!
+//! 记录按（名称、类型、值）匹配，名称和域名值不区分大小写，与记录顺序无关。两次都有、只有TTL不同的
+//! 记录计入 `ttl_changes`；同一名称和类型下一边消失、另一边出现的值按规范顺序两两配对为 `changed`，
+//! 配不上对的计入 `removed` 或 `added`。只有TTL或顺序变化时 [`DnsDiffReport::is_significant`] 为假。
+
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use super::types::{DnsQueryResponse, DnsRecord, DnsRecordType, DnsRecordValue, RecordSort};
+use crate::error::{DnsError, Result};
+
+/// 同一名称和类型下记录值的变化
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordValueChange {
+    /// 记录名称（取新记录的写法）
+    pub name: String,
+    /// 记录类型
+    pub record_type: DnsRecordType,
+    /// 原来的值
+    pub old_value: DnsRecordValue,
+    /// 新的值
+    pub new_value: DnsRecordValue,
+    /// 原来的TTL
+    pub old_ttl: u32,
+    /// 新的TTL
+    pub new_ttl: u32,
+}
+
+/// 值相同、只有TTL不同的记录
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TtlChange {
+    /// 记录名称（取新记录的写法）
+    pub name: String,
+    /// 记录类型
+    pub record_type: DnsRecordType,
+    /// 记录值
+    pub value: DnsRecordValue,
+    /// 原来的TTL
+    pub old_ttl: u32,
+    /// 新的TTL
+    pub new_ttl: u32,
+}
+
+/// 响应码的变化，查询失败时响应码为 `None`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RcodeChange {
+    /// 原来的响应码
+    pub old: Option<u16>,
+    /// 新的响应码
+    pub new: Option<u16>,
+}
+
+/// 两次查询结果的差异，各列表按记录的规范顺序（[`RecordSort::ByTypeThenValue`]）排列
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsDiffReport {
+    /// 新结果的查询域名
+    pub domain: String,
+    /// 新结果的记录类型
+    pub record_type: DnsRecordType,
+    /// 新出现的记录
+    pub added: Vec<DnsRecord>,
+    /// 消失的记录
+    pub removed: Vec<DnsRecord>,
+    /// 值发生变化的记录
+    pub changed: Vec<RecordValueChange>,
+    /// 只有TTL变化的记录
+    pub ttl_changes: Vec<TtlChange>,
+    /// 记录集合相同但顺序不同
+    pub reordered: bool,
+    /// 响应码的变化，未变化时为 `None`
+    pub rcode_change: Option<RcodeChange>,
+}
+
+impl DnsDiffReport {
+    /// 记录集合或响应码是否有变化（只有TTL或顺序变化时为假）
+    pub fn is_significant(&self) -> bool {
+        !self.added.is_empty()
+            || !self.removed.is_empty()
+            || !self.changed.is_empty()
+            || self.rcode_change.is_some()
+    }
+
+    /// 两次结果是否完全相同（含TTL和顺序）
+    pub fn is_empty(&self) -> bool {
+        !self.is_significant() && self.ttl_changes.is_empty() && !self.reordered
+    }
+
+    /// 序列化为单行JSON，记录值的形状见 [`DnsRecordValue`]
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self)
+            .map_err(|e| DnsError::Parse(format!("Failed to serialize diff report: {}", e)))
+    }
+
+    /// 从 [`to_json`](Self::to_json) 输出的JSON文本解析
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| DnsError::Parse(format!("Invalid diff report JSON: {}", e)))
+    }
+}
+
+/// 查询结果比较
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DnsDiff;
+
+impl DnsDiff {
+    /// 比较同一查询先后两次的结果，只比较应答记录（`records`）和响应码
+    pub fn compare(old: &DnsQueryResponse, new: &DnsQueryResponse) -> DnsDiffReport {
+        let mut old_records = old.records.clone();
+        let mut new_records = new.records.clone();
+        RecordSort::ByTypeThenValue.sort(&mut old_records);
+        RecordSort::ByTypeThenValue.sort(&mut new_records);
+
+        // 两个有序列表归并：键相同的记录配对，其余按所在的一边分别收集
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let mut ttl_changes = Vec::new();
+        let mut same_order = old.records.len() == new.records.len();
+        let (mut old_iter, mut new_iter) = (old_records.into_iter().peekable(), new_records.into_iter().peekable());
+        loop {
+            let ordering = match (old_iter.peek(), new_iter.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(a), Some(b)) => record_key_cmp(a, b),
+            };
+            match ordering {
+                Ordering::Less => removed.extend(old_iter.next()),
+                Ordering::Greater => added.extend(new_iter.next()),
+                Ordering::Equal => {
+                    let (before, after) = (old_iter.next().unwrap(), new_iter.next().unwrap());
+                    if before.ttl != after.ttl {
+                        ttl_changes.push(TtlChange {
+                            name: after.name,
+                            record_type: after.record_type,
+                            value: after.value,
+                            old_ttl: before.ttl,
+                            new_ttl: after.ttl,
+                        });
+                    }
+                }
+            }
+        }
+        if !removed.is_empty() || !added.is_empty() {
+            same_order = false;
+        }
+        let reordered = same_order
+            && old.records.iter().zip(&new.records).any(|(a, b)| record_key_cmp(a, b) != Ordering::Equal);
+
+        let changed = pair_value_changes(&mut removed, &mut added);
+        let rcode_change = (old.rcode != new.rcode).then_some(RcodeChange { old: old.rcode, new: new.rcode });
+        DnsDiffReport {
+            domain: new.domain.clone(),
+            record_type: new.record_type,
+            added,
+            removed,
+            changed,
+            ttl_changes,
+            reordered,
+            rcode_change,
+        }
+    }
+}
+
+/// 记录的匹配键（名称、类型、值）按规范顺序比较，不比较TTL
+fn record_key_cmp(a: &DnsRecord, b: &DnsRecord) -> Ordering {
+    let type_code = |record: &DnsRecord| u16::from(crate::types::RecordType::from(record.record_type));
+    type_code(a).cmp(&type_code(b))
+        .then_with(|| a.value.canonical_cmp(&b.value))
+        .then_with(|| a.name.to_ascii_lowercase().cmp(&b.name.to_ascii_lowercase()))
+}
+
+/// 名称和类型相同的消失记录与新出现记录依次配对为值变化，配上对的从两个列表中移除
+fn pair_value_changes(removed: &mut Vec<DnsRecord>, added: &mut Vec<DnsRecord>) -> Vec<RecordValueChange> {
+    let mut changes = Vec::new();
+    let mut unmatched = Vec::new();
+    for before in removed.drain(..) {
+        let position = added.iter().position(|after| {
+            after.record_type == before.record_type && after.name.eq_ignore_ascii_case(&before.name)
+        });
+        match position {
+            Some(index) => {
+                let after = added.remove(index);
+                changes.push(RecordValueChange {
+                    name: after.name,
+                    record_type: after.record_type,
+                    old_value: before.value,
+                    new_value: after.value,
+                    old_ttl: before.ttl,
+                    new_ttl: after.ttl,
+                });
+            }
+            None => unmatched.push(before),
+        }
+    }
+    *removed = unmatched;
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    fn a(name: &str, ip: &str, ttl: u32) -> DnsRecord {
+        DnsRecord {
+            name: name.to_string(),
+            record_type: DnsRecordType::A,
+            value: DnsRecordValue::IpAddr(ip.parse::<IpAddr>().unwrap()),
+            ttl,
+        }
+    }
+
+    fn response(records: Vec<DnsRecord>) -> DnsQueryResponse {
+        DnsQueryResponse {
+            query_id: "diff".into(),
+            domain: "www.example.com".to_string(),
+            record_type: DnsRecordType::A,
+            success: true,
+            error: None,
+            records,
+            duration_ms: 1,
+            server_used: None,
+            upstream_peer: None,
+            protocol_used: None,
+            dnssec_status: None,
+            dnssec_records: Vec::new(),
+            emergency_mode: false,
+            rcode: Some(0),
+            authenticated_data: false,
+            valid_until: None,
+            timing: None,
+            wire_request: None,
+            wire_response: None,
+            wire_truncated: false,
+            degraded_security: false,
+            records_truncated: false,
+            negative_ttl: None,
+            zone_apex: None,
+            context: None,
+            error_code: None,
+            failure_report: None,
+            selection: None,
+        }
+    }
+
+    fn empty_report() -> DnsDiffReport {
+        DnsDiffReport {
+            domain: "www.example.com".to_string(),
+            record_type: DnsRecordType::A,
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+            ttl_changes: Vec::new(),
+            reordered: false,
+            rcode_change: None,
+        }
+    }
+
+    #[test]
+    fn test_added_record() {
+        let old = response(vec![a("www.example.com", "192.0.2.1", 300)]);
+        let new = response(vec![a("www.example.com", "192.0.2.2", 300), a("WWW.example.com", "192.0.2.1", 300)]);
+        let report = DnsDiff::compare(&old, &new);
+        assert_eq!(report, DnsDiffReport { added: vec![a("www.example.com", "192.0.2.2", 300)], ..empty_report() });
+        assert!(report.is_significant());
+    }
+
+    #[test]
+    fn test_removed_record() {
+        let old = response(vec![a("www.example.com", "192.0.2.1", 300), a("www.example.com", "192.0.2.2", 300)]);
+        let new = response(vec![a("www.example.com", "192.0.2.1", 300)]);
+        let report = DnsDiff::compare(&old, &new);
+        assert_eq!(report, DnsDiffReport { removed: vec![a("www.example.com", "192.0.2.2", 300)], ..empty_report() });
+        assert!(report.is_significant());
+    }
+
+    #[test]
+    fn test_value_change() {
+        let old = response(vec![a("www.example.com", "192.0.2.1", 300), a("www.example.com", "192.0.2.9", 300)]);
+        let new = response(vec![a("www.example.com", "198.51.100.1", 60), a("www.example.com", "192.0.2.1", 300)]);
+        let report = DnsDiff::compare(&old, &new);
+        assert_eq!(report, DnsDiffReport {
+            changed: vec![RecordValueChange {
+                name: "www.example.com".to_string(),
+                record_type: DnsRecordType::A,
+                old_value: DnsRecordValue::IpAddr("192.0.2.9".parse().unwrap()),
+                new_value: DnsRecordValue::IpAddr("198.51.100.1".parse().unwrap()),
+                old_ttl: 300,
+                new_ttl: 60,
+            }],
+            ..empty_report()
+        });
+        assert!(report.is_significant());
+    }
+
+    #[test]
+    fn test_ttl_only_change() {
+        let old = response(vec![a("www.example.com", "192.0.2.1", 300), a("www.example.com", "192.0.2.2", 300)]);
+        let new = response(vec![a("www.example.com", "192.0.2.1", 120), a("www.example.com", "192.0.2.2", 300)]);
+        let report = DnsDiff::compare(&old, &new);
+        assert_eq!(report, DnsDiffReport {
+            ttl_changes: vec![TtlChange {
+                name: "www.example.com".to_string(),
+                record_type: DnsRecordType::A,
+                value: DnsRecordValue::IpAddr("192.0.2.1".parse().unwrap()),
+                old_ttl: 300,
+                new_ttl: 120,
+            }],
+            ..empty_report()
+        });
+        assert!(!report.is_significant());
+        assert!(!report.is_empty());
+    }
+
+    #[test]
+    fn test_reordering_only() {
+        let old = response(vec![a("www.example.com", "192.0.2.1", 300), a("www.example.com", "192.0.2.2", 300)]);
+        let new = response(vec![a("www.example.com", "192.0.2.2", 300), a("www.example.com", "192.0.2.1", 300)]);
+        let report = DnsDiff::compare(&old, &new);
+        assert_eq!(report, DnsDiffReport { reordered: true, ..empty_report() });
+        assert!(!report.is_significant());
+        assert!(DnsDiff::compare(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_rcode_change_and_json_round_trip() {
+        let old = response(vec![a("www.example.com", "192.0.2.1", 300)]);
+        let mut new = response(Vec::new());
+        new.rcode = Some(3);
+        let report = DnsDiff::compare(&old, &new);
+        assert_eq!(report, DnsDiffReport {
+            removed: vec![a("www.example.com", "192.0.2.1", 300)],
+            rcode_change: Some(RcodeChange { old: Some(0), new: Some(3) }),
+            ..empty_report()
+        });
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["removed"][0]["value"], serde_json::json!({"IpAddr": "192.0.2.1"}));
+        assert_eq!(json["rcode_change"], serde_json::json!({"old": 0, "new": 3}));
+        assert_eq!(DnsDiffReport::from_json(&report.to_json().unwrap()).unwrap(), report);
+    }
+}
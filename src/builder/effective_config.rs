@@ -0,0 +1,492 @@
+//! 运行时实际生效的配置快照
+//!
+//! [`EffectiveConfig`] 汇总解析器级设置、套用默认值之后的各上游选项和编译时启用的特性，
+//! 用于问题反馈和比对两个环境的配置（[`EffectiveConfig::diff`]）。DoH认证类请求头和查询参数的值已隐去
+
+use crate::builder::encoded::EncodedRequestLimits;
+use crate::builder::types::RecordSort;
+use crate::resolver::CoreResolverConfig;
+use crate::resolver::rate_limit::RateLimitedBehavior;
+use crate::transport::doh_url::{is_sensitive_header, is_sensitive_param, redact_url};
+use crate::transport::{AfPreference, RecordLimits};
+use crate::types::EffectiveFeatures;
+use crate::upstream_handler::{UpstreamLabels, UpstreamSpec, UpstreamType};
+use crate::utils::{parse_simple_server_address, parse_url_components};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// 导出中代替敏感请求头值的文本
+pub const REDACTED: &str = "<redacted>";
+
+/// 运行时实际生效的配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EffectiveConfig {
+    /// 库版本
+    pub version: String,
+    /// 编译时启用的可选特性
+    pub features: Vec<String>,
+    /// 解析器级设置
+    pub resolver: ResolverSettings,
+    /// 所有上游（含只用于转发规则的上游），按配置顺序
+    pub upstreams: Vec<EffectiveUpstream>,
+}
+
+/// 构建前检查的结果，见 [`DnsResolverBuilder::validate_only`](super::DnsResolverBuilder::validate_only)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DryRunReport {
+    /// 构建后将会生效的配置，与 `SmartDnsResolver::effective_config` 的结果相同
+    pub effective_config: EffectiveConfig,
+    /// 不会让构建失败、但需要留意的情况，如合并掉的重复上游和需要联网才能确认的上游地址
+    pub warnings: Vec<String>,
+}
+
+/// 解析器级设置，时长均为毫秒或秒（见字段名后缀）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResolverSettings {
+    /// 查询策略
+    pub strategy: String,
+    /// 默认超时
+    pub timeout_ms: u64,
+    /// 重试次数
+    pub retry_count: usize,
+    /// 一次查询最多尝试的上游数
+    pub max_failover_attempts: usize,
+    /// 同时进行的上游发送数上限
+    pub concurrent_queries: usize,
+    /// 等待并发名额的时限，None表示一直等待
+    pub concurrency_wait_timeout_ms: Option<u64>,
+    /// 为交互式查询保留的并发名额比例
+    pub interactive_reserve: f64,
+    /// 是否启用缓存
+    pub enable_cache: bool,
+    /// 是否使用自定义缓存后端
+    pub custom_cache_backend: bool,
+    /// 缓存TTL上限
+    pub max_cache_ttl_secs: u64,
+    /// 响应TTL下限
+    pub min_ttl_secs: Option<u64>,
+    /// 响应TTL上限
+    pub max_ttl_secs: Option<u64>,
+    /// 含TTL为0记录的响应是否按1秒缓存
+    pub cache_zero_ttl: bool,
+    /// 携带ECS的查询如何使用缓存
+    pub ecs_cache_mode: String,
+    /// 过期答案的先行返回窗口
+    pub revalidate_window_ms: Option<u64>,
+    /// 否定应答的核实窗口
+    pub nxdomain_protection_window_ms: Option<u64>,
+    /// 上游限流时等待重试的时长上限（None表示立即故障转移）
+    pub rate_limited_max_wait_ms: Option<u64>,
+    /// 请求是否携带EDNS OPT记录
+    pub enable_edns: bool,
+    /// UDP查询是否携带DNS Cookie
+    pub enable_dns_cookies: bool,
+    /// 是否启用递归查询
+    pub recursion_desired: bool,
+    /// 响应缓冲区大小（字节）
+    pub buffer_size: usize,
+    /// DoH上游是否跟随同源重定向
+    pub doh_follow_redirects: bool,
+    /// DoT上游是否发送0-RTT早期数据
+    pub tls_early_data: bool,
+    /// TCP和DoT上游是否使用TCP Fast Open
+    pub tcp_fast_open: bool,
+    /// 是否启用上游监控
+    pub enable_upstream_monitoring: bool,
+    /// 上游监控间隔
+    pub upstream_monitoring_interval_ms: u64,
+    /// 每个上游保留的状态变化事件数
+    pub upstream_event_history: usize,
+    /// 是否配置了主动探测查询
+    pub health_probe: bool,
+    /// 上游静默时长上限
+    pub max_upstream_silence_ms: Option<u64>,
+    /// 可疑响应是否作为错误返回
+    pub strict_response_check: bool,
+    /// 是否接受没有问题段的响应
+    pub accept_questionless_responses: bool,
+    /// 是否保留类型与查询不符的应答记录
+    pub lenient_answer_types: bool,
+    /// 单个响应的记录数上限及超出时的处理方式
+    pub record_limits: RecordLimits,
+    /// 截断过的响应是否写入缓存
+    pub cache_truncated_responses: bool,
+    /// bincode编码查询接口的输入上限
+    pub encoded_request_limits: EncodedRequestLimits,
+    /// A/AAAA记录的排列方式
+    pub record_rotation: String,
+    /// 查询响应中记录的规范排序，null为保持上游给出的顺序
+    pub sort_records: Option<RecordSort>,
+    /// 加密上游证书校验失败时的降级方式
+    pub encrypted_fallback: String,
+    /// 加密上游握手失败的初始隔离时长（未开启隔离时为None）
+    pub tls_quarantine_initial_ms: Option<u64>,
+    /// 加密上游握手失败的隔离时长上限（未开启隔离时为None）
+    pub tls_quarantine_max_ms: Option<u64>,
+    /// 自适应超时的p99倍数（未开启自适应超时时为None，下同）
+    pub adaptive_timeout_multiplier: Option<f64>,
+    /// 自适应超时的下限
+    pub adaptive_timeout_floor_ms: Option<u64>,
+    /// 自适应超时的上限
+    pub adaptive_timeout_ceiling_ms: Option<u64>,
+    /// 开始使用自适应超时所需的样本数
+    pub adaptive_timeout_min_samples: Option<u64>,
+    /// 是否使用自定义随机数来源（含 `with_random_seed`）
+    pub custom_random_source: bool,
+    /// 全局内存预算（字节，None表示不限制）
+    pub memory_budget_bytes: Option<usize>,
+    /// 是否输出查询摘要日志
+    pub log_query_summary: bool,
+    /// 是否启用统计
+    pub enable_stats: bool,
+    /// 作为指标标签输出的上游标签键
+    pub metrics_upstream_labels: Vec<String>,
+    /// 按域名转发的规则数
+    pub domain_rules: usize,
+}
+
+/// 套用默认值之后的上游选项
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EffectiveUpstream {
+    /// 上游名称
+    pub name: String,
+    /// 传输类型
+    pub transport_type: String,
+    /// 配置的服务器地址（IP、域名或URL），URL中认证类查询参数的值为 [`REDACTED`]
+    pub server: String,
+    /// 实际连接的端口（自定义上游为None）
+    pub port: Option<u16>,
+    /// 预解析的IP地址
+    pub resolved_ip: Option<String>,
+    /// 查询超时
+    pub timeout_ms: u64,
+    /// 权重
+    pub weight: u32,
+    /// 优先层级
+    pub tier: u8,
+    /// 只发往该上游的记录类型（None表示不限制）
+    pub record_types: Option<Vec<String>>,
+    /// 期望区域
+    pub region: Option<String>,
+    /// 附加的HTTP请求头，认证类请求头的值为 [`REDACTED`]
+    pub headers: Vec<(String, String)>,
+    /// 附加的URL查询参数，认证类参数的值为 [`REDACTED`]
+    pub query_params: Vec<(String, String)>,
+    /// DoH使用的HTTP版本
+    pub http_version: String,
+    /// 客户端子网的发送方式
+    pub ecs_policy: String,
+    /// 同时发往该上游的查询上限（None表示不限制）
+    pub max_inflight: Option<usize>,
+    /// 达到在途上限时新查询的处理方式
+    pub queue_behavior: String,
+    /// 以主机名配置时连接哪个地址族，null为按解析结果的顺序
+    pub address_family: Option<AfPreference>,
+    /// 实际生效的EDNS、DO位和大小写随机化
+    pub request_features: EffectiveFeatures,
+    /// 上游标签
+    pub labels: UpstreamLabels,
+    /// 是否参与查询（运行时停用或传输创建失败时为false）
+    pub enabled: bool,
+    /// 是否只用于转发规则，不参与查询策略
+    pub route_only: bool,
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().min(u64::MAX as u128) as u64
+}
+
+impl ResolverSettings {
+    /// 从解析器配置提取设置
+    pub fn from_config(config: &CoreResolverConfig, domain_rules: usize) -> Self {
+        Self {
+            strategy: format!("{:?}", config.strategy),
+            timeout_ms: millis(config.default_timeout),
+            retry_count: config.retry_count,
+            max_failover_attempts: config.max_failover_attempts,
+            concurrent_queries: config.concurrent_queries,
+            concurrency_wait_timeout_ms: config.concurrency_wait_timeout.map(millis),
+            interactive_reserve: config.interactive_reserve,
+            enable_cache: config.enable_cache,
+            custom_cache_backend: config.cache_backend.is_some(),
+            max_cache_ttl_secs: config.max_cache_ttl.as_secs(),
+            min_ttl_secs: config.min_ttl.map(|ttl| ttl.as_secs()),
+            max_ttl_secs: config.max_ttl.map(|ttl| ttl.as_secs()),
+            cache_zero_ttl: config.cache_zero_ttl,
+            ecs_cache_mode: format!("{:?}", config.ecs_cache_mode),
+            revalidate_window_ms: config.revalidate_window.map(millis),
+            nxdomain_protection_window_ms: config.nxdomain_protection_window.map(millis),
+            rate_limited_max_wait_ms: match config.on_rate_limited {
+                RateLimitedBehavior::FailoverImmediately => None,
+                RateLimitedBehavior::WaitAndRetry { max_wait } => Some(millis(max_wait)),
+            },
+            enable_edns: config.enable_edns,
+            enable_dns_cookies: config.enable_dns_cookies,
+            recursion_desired: config.recursion_desired,
+            buffer_size: config.buffer_size,
+            doh_follow_redirects: config.doh_follow_redirects,
+            tls_early_data: config.tls_early_data,
+            tcp_fast_open: config.tcp_fast_open,
+            enable_upstream_monitoring: config.enable_upstream_monitoring,
+            upstream_monitoring_interval_ms: millis(config.upstream_monitoring_interval),
+            upstream_event_history: config.upstream_event_history,
+            health_probe: config.health_probe.is_some(),
+            max_upstream_silence_ms: config.max_upstream_silence.map(millis),
+            strict_response_check: config.strict_response_check,
+            accept_questionless_responses: config.accept_questionless_responses,
+            lenient_answer_types: config.lenient_answer_types,
+            record_limits: config.record_limits,
+            cache_truncated_responses: config.cache_truncated_responses,
+            encoded_request_limits: config.encoded_request_limits,
+            record_rotation: format!("{:?}", config.record_rotation),
+            sort_records: config.sort_records,
+            encrypted_fallback: format!("{:?}", config.encrypted_fallback),
+            tls_quarantine_initial_ms: config.tls_quarantine.map(|quarantine| millis(quarantine.initial)),
+            tls_quarantine_max_ms: config.tls_quarantine.map(|quarantine| millis(quarantine.max)),
+            adaptive_timeout_multiplier: config.adaptive_timeout.map(|adaptive| adaptive.multiplier),
+            adaptive_timeout_floor_ms: config.adaptive_timeout.map(|adaptive| millis(adaptive.floor)),
+            adaptive_timeout_ceiling_ms: config.adaptive_timeout.map(|adaptive| millis(adaptive.ceiling)),
+            adaptive_timeout_min_samples: config.adaptive_timeout.map(|adaptive| adaptive.min_samples),
+            custom_random_source: config.random_source.is_some(),
+            memory_budget_bytes: config.memory_budget,
+            log_query_summary: config.log_query_summary,
+            enable_stats: config.enable_stats,
+            metrics_upstream_labels: config.metrics_upstream_labels.clone(),
+            domain_rules,
+        }
+    }
+}
+
+impl EffectiveUpstream {
+    /// 按解析器配置补全上游规格中未写明的选项
+    pub fn from_spec(
+        spec: &UpstreamSpec,
+        config: &CoreResolverConfig,
+        request_features: EffectiveFeatures,
+        enabled: bool,
+        route_only: bool,
+    ) -> Self {
+        let port = match spec.transport_type {
+            UpstreamType::Udp | UpstreamType::Tcp => Some(parse_simple_server_address(&spec.server, 53).1),
+            UpstreamType::DoT => Some(parse_simple_server_address(&spec.server, 853).1),
+            UpstreamType::DoH => parse_url_components(&spec.server).ok().map(|(_, port)| port),
+            UpstreamType::Custom => None,
+        };
+        let headers = spec.headers.iter()
+            .map(|(name, value)| {
+                let value = if is_sensitive_header(name) { REDACTED.to_string() } else { value.clone() };
+                (name.clone(), value)
+            })
+            .collect();
+        let query_params = spec.query_params.iter()
+            .map(|(name, value)| {
+                let value = if is_sensitive_param(name) { REDACTED.to_string() } else { value.clone() };
+                (name.clone(), value)
+            })
+            .collect();
+        Self {
+            name: spec.name.clone(),
+            transport_type: format!("{:?}", spec.transport_type),
+            server: redact_url(&spec.server, REDACTED),
+            port,
+            resolved_ip: spec.resolved_ip.clone(),
+            timeout_ms: millis(spec.timeout.unwrap_or(config.default_timeout)),
+            weight: spec.weight,
+            tier: spec.tier,
+            record_types: spec.record_types.as_ref()
+                .map(|types| types.iter().map(ToString::to_string).collect()),
+            region: spec.region.clone(),
+            headers,
+            query_params,
+            http_version: format!("{:?}", spec.http_version),
+            ecs_policy: format!("{:?}", spec.ecs_policy),
+            max_inflight: spec.max_inflight,
+            queue_behavior: format!("{:?}", spec.queue_behavior),
+            address_family: spec.address_family.or(config.host_resolution.family_preference),
+            request_features,
+            labels: spec.labels.clone(),
+            enabled,
+            route_only,
+        }
+    }
+}
+
+impl EffectiveConfig {
+    /// 以当前库版本和编译特性创建快照
+    pub fn new(resolver: ResolverSettings, upstreams: Vec<EffectiveUpstream>) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            features: enabled_features(),
+            resolver,
+            upstreams,
+        }
+    }
+
+    /// 转为JSON对象
+    pub fn to_json_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    /// 序列化为单行JSON
+    pub fn to_json(&self) -> String {
+        self.to_json_value().to_string()
+    }
+
+    /// 从JSON读回快照，用于比对保存下来的其他环境的配置
+    pub fn from_json(json: &str) -> crate::Result<Self> {
+        serde_json::from_str(json).map_err(|e| crate::DnsError::Parse(format!("Invalid effective config: {}", e)))
+    }
+
+    /// 与另一份快照的差异，每行一项，如 `resolver.timeout_ms: 5000 -> 3000`
+    ///
+    /// 上游按名称对应，只在一侧存在的上游整体报告一行；两份相同时返回空列表
+    pub fn diff(&self, other: &EffectiveConfig) -> Vec<String> {
+        let ours = self.flatten();
+        let theirs = other.flatten();
+        let mut differences = Vec::new();
+        for upstream in &self.upstreams {
+            if !other.upstreams.iter().any(|u| u.name == upstream.name) {
+                differences.push(format!("upstream {} removed", upstream.name));
+            }
+        }
+        for upstream in &other.upstreams {
+            if !self.upstreams.iter().any(|u| u.name == upstream.name) {
+                differences.push(format!("upstream {} added", upstream.name));
+            }
+        }
+        for (path, value) in &ours {
+            match theirs.get(path) {
+                Some(other_value) if other_value != value => {
+                    differences.push(format!("{}: {} -> {}", path, value, other_value));
+                }
+                _ => {}
+            }
+        }
+        differences
+    }
+
+    /// 按路径展开为叶子值，上游以名称为键
+    fn flatten(&self) -> BTreeMap<String, String> {
+        let mut leaves = BTreeMap::new();
+        let value = self.to_json_value();
+        for key in ["version", "features", "resolver"] {
+            flatten_into(key.to_string(), &value[key], &mut leaves);
+        }
+        for upstream in &self.upstreams {
+            let value = serde_json::to_value(upstream).unwrap_or_default();
+            flatten_into(format!("upstreams[{}]", upstream.name), &value, &mut leaves);
+        }
+        leaves
+    }
+}
+
+fn flatten_into(path: String, value: &serde_json::Value, leaves: &mut BTreeMap<String, String>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, child) in map {
+                flatten_into(format!("{}.{}", path, key), child, leaves);
+            }
+        }
+        leaf => {
+            leaves.insert(path, leaf.to_string());
+        }
+    }
+}
+
+/// 编译时启用的可选特性
+fn enabled_features() -> Vec<String> {
+    let features = [
+        ("python-bindings", cfg!(feature = "python-bindings")),
+        ("orni_dns", cfg!(feature = "orni_dns")),
+        ("test-util", cfg!(feature = "test-util")),
+        ("cli", cfg!(feature = "cli")),
+        ("doh3", cfg!(feature = "doh3")),
+        ("http-resolver", cfg!(feature = "http-resolver")),
+        ("connect", cfg!(feature = "connect")),
+    ];
+    features.iter().filter(|(_, enabled)| *enabled).map(|(name, _)| name.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::QueryStrategy;
+
+    fn config() -> CoreResolverConfig {
+        CoreResolverConfig::new(
+            QueryStrategy::Smart,
+            Duration::from_secs(5),
+            2,
+            true,
+            Duration::from_secs(3600),
+            false,
+            Duration::from_secs(30),
+            53,
+            10,
+            true,
+            4096,
+            false,
+            crate::logger::LevelFilter::Off,
+            false,
+        )
+    }
+
+    fn snapshot(config: &CoreResolverConfig, specs: &[UpstreamSpec]) -> EffectiveConfig {
+        let upstreams = specs.iter()
+            .map(|spec| EffectiveUpstream::from_spec(spec, config, spec.features.effective(config.enable_edns), true, false))
+            .collect();
+        EffectiveConfig::new(ResolverSettings::from_config(config, 0), upstreams)
+    }
+
+    #[test]
+    fn test_upstream_defaults_are_filled_in() {
+        let specs = [
+            UpstreamSpec::udp("udp".to_string(), "192.0.2.53".to_string()),
+            UpstreamSpec::dot("dot".to_string(), "dns.example:8853".to_string()),
+            UpstreamSpec::doh("doh".to_string(), "https://dns.example/dns-query".to_string()),
+        ];
+        let snapshot = snapshot(&config(), &specs);
+        let ports: Vec<Option<u16>> = snapshot.upstreams.iter().map(|u| u.port).collect();
+        assert_eq!(ports, vec![Some(53), Some(8853), Some(443)]);
+        assert!(snapshot.upstreams.iter().all(|u| u.timeout_ms == 5000));
+        assert_eq!(snapshot.upstreams[2].transport_type, "DoH");
+    }
+
+    #[test]
+    fn test_diff_matches_upstreams_by_name() {
+        let config = config();
+        let staging = snapshot(&config, &[
+            UpstreamSpec::udp("primary".to_string(), "192.0.2.53".to_string()),
+            UpstreamSpec::udp("legacy".to_string(), "192.0.2.54".to_string()),
+        ]);
+        let mut prod_config = config.clone();
+        prod_config.default_timeout = Duration::from_secs(3);
+        let prod = snapshot(&prod_config, &[
+            UpstreamSpec::udp("primary".to_string(), "192.0.2.53".to_string()).with_tier(1),
+        ]);
+
+        assert!(staging.diff(&staging).is_empty());
+        let diff = staging.diff(&prod);
+        assert!(diff.contains(&"upstream legacy removed".to_string()), "{:?}", diff);
+        assert!(diff.contains(&"resolver.timeout_ms: 5000 -> 3000".to_string()), "{:?}", diff);
+        assert!(diff.contains(&"upstreams[primary].tier: 0 -> 1".to_string()), "{:?}", diff);
+        assert!(diff.contains(&"upstreams[primary].timeout_ms: 5000 -> 3000".to_string()), "{:?}", diff);
+        assert_eq!(prod.diff(&staging)[0], "upstream legacy added");
+    }
+
+    #[test]
+    fn test_sensitive_query_params_are_redacted() {
+        let spec = UpstreamSpec::doh("nextdns".to_string(), "https://dns.example/abc123?api_key=s3cr3t&region=eu".to_string())
+            .with_query_params(vec![
+                ("key".to_string(), "k-123".to_string()),
+                ("filter".to_string(), "family".to_string()),
+            ]);
+        let upstream = &snapshot(&config(), &[spec]).upstreams[0];
+        assert_eq!(upstream.server, "https://dns.example/abc123?api_key=<redacted>&region=eu");
+        assert_eq!(upstream.query_params, vec![
+            ("key".to_string(), REDACTED.to_string()),
+            ("filter".to_string(), "family".to_string()),
+        ]);
+    }
+}
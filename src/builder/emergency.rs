@@ -0,0 +1,228 @@
+//! 应急模式
+//!
+//! 按最近 N 次上游查询的结果计算整体成功率：低于 `threshold` 时进入应急模式，
+//! 回升到 `threshold + recovery_margin` 以上才退出，避免成功率在阈值附近抖动时
+//! 频繁切换。应急模式下解析器向所有上游并发查询（不再按健康状态和评分筛选），
+//! 并把缓存的TTL抬高到 `ttl_floor`，尽量减少对故障上游的依赖。
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::{DnsError, Result};
+
+/// 解析器运行模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolverMode {
+    /// 正常模式，按配置的查询策略选择上游
+    Normal,
+    /// 应急模式，向所有上游并发查询
+    Emergency,
+}
+
+/// 应急模式的判定参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmergencyPolicy {
+    /// 进入应急模式的成功率阈值（0.0-1.0）
+    pub threshold: f64,
+    /// 退出应急模式需要高出阈值的幅度
+    pub recovery_margin: f64,
+    /// 统计成功率的查询窗口大小
+    pub window: usize,
+    /// 窗口内至少有多少个样本才做判定
+    pub min_samples: usize,
+    /// 应急模式下响应TTL的下限
+    pub ttl_floor: Duration,
+}
+
+impl EmergencyPolicy {
+    /// 以给定阈值创建判定参数：窗口50次查询、至少10个样本、恢复幅度0.1、TTL下限300秒
+    pub fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            recovery_margin: 0.1,
+            window: 50,
+            min_samples: 10,
+            ttl_floor: Duration::from_secs(300),
+        }
+    }
+
+    /// 设置退出应急模式需要高出阈值的幅度
+    pub fn with_recovery_margin(mut self, margin: f64) -> Self {
+        self.recovery_margin = margin;
+        self
+    }
+
+    /// 设置统计窗口和最少样本数
+    pub fn with_window(mut self, window: usize, min_samples: usize) -> Self {
+        self.window = window;
+        self.min_samples = min_samples;
+        self
+    }
+
+    /// 设置应急模式下的TTL下限
+    pub fn with_ttl_floor(mut self, ttl_floor: Duration) -> Self {
+        self.ttl_floor = ttl_floor;
+        self
+    }
+
+    /// 校验参数
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.threshold) {
+            return Err(DnsError::InvalidConfig(
+                format!("Emergency threshold must be between 0.0 and 1.0, got {}", self.threshold)
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.recovery_margin) {
+            return Err(DnsError::InvalidConfig(
+                format!("Emergency recovery margin must be between 0.0 and 1.0, got {}", self.recovery_margin)
+            ));
+        }
+        if self.min_samples == 0 || self.min_samples > self.window {
+            return Err(DnsError::InvalidConfig(format!(
+                "Emergency window ({}) must hold at least min_samples ({}) and min_samples must be positive",
+                self.window, self.min_samples
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 应急状态快照
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmergencyState {
+    /// 当前模式
+    pub mode: ResolverMode,
+    /// 窗口内的成功率，样本不足时为 `None`
+    pub success_ratio: Option<f64>,
+    /// 窗口内的样本数
+    pub samples: usize,
+}
+
+#[derive(Debug)]
+struct MonitorState {
+    outcomes: VecDeque<bool>,
+    successes: usize,
+    mode: ResolverMode,
+}
+
+/// 滑动窗口成功率监控
+#[derive(Debug)]
+pub struct EmergencyMonitor {
+    policy: EmergencyPolicy,
+    state: Mutex<MonitorState>,
+}
+
+impl EmergencyMonitor {
+    /// 按判定参数创建监控
+    pub fn new(policy: EmergencyPolicy) -> Self {
+        Self {
+            state: Mutex::new(MonitorState {
+                outcomes: VecDeque::with_capacity(policy.window),
+                successes: 0,
+                mode: ResolverMode::Normal,
+            }),
+            policy,
+        }
+    }
+
+    /// 判定参数
+    pub fn policy(&self) -> &EmergencyPolicy {
+        &self.policy
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MonitorState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 记录一次查询结果，模式发生切换时返回新模式
+    pub fn record(&self, success: bool) -> Option<ResolverMode> {
+        let mut state = self.lock();
+        if state.outcomes.len() == self.policy.window && state.outcomes.pop_front() == Some(true) {
+            state.successes -= 1;
+        }
+        state.outcomes.push_back(success);
+        if success {
+            state.successes += 1;
+        }
+
+        if state.outcomes.len() < self.policy.min_samples {
+            return None;
+        }
+        let ratio = state.successes as f64 / state.outcomes.len() as f64;
+        let next = match state.mode {
+            ResolverMode::Normal if ratio < self.policy.threshold => ResolverMode::Emergency,
+            ResolverMode::Emergency if ratio > self.policy.threshold + self.policy.recovery_margin => ResolverMode::Normal,
+            current => current,
+        };
+        if next == state.mode {
+            return None;
+        }
+        state.mode = next;
+        Some(next)
+    }
+
+    /// 当前模式
+    pub fn mode(&self) -> ResolverMode {
+        self.lock().mode
+    }
+
+    /// 当前状态快照
+    pub fn state(&self) -> EmergencyState {
+        let state = self.lock();
+        let samples = state.outcomes.len();
+        EmergencyState {
+            mode: state.mode,
+            success_ratio: (samples >= self.policy.min_samples)
+                .then(|| state.successes as f64 / samples as f64),
+            samples,
+        }
+    }
+
+    /// 清空窗口并回到正常模式
+    pub fn reset(&self) {
+        let mut state = self.lock();
+        state.outcomes.clear();
+        state.successes = 0;
+        state.mode = ResolverMode::Normal;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enters_below_threshold_and_exits_with_hysteresis() {
+        let monitor = EmergencyMonitor::new(EmergencyPolicy::new(0.5).with_recovery_margin(0.25).with_window(4, 4));
+
+        // 样本不足时不做判定
+        for _ in 0..3 {
+            assert_eq!(monitor.record(false), None);
+        }
+        assert_eq!(monitor.state().success_ratio, None);
+        assert_eq!(monitor.record(false), Some(ResolverMode::Emergency));
+
+        // [F,F,S,S] 成功率回到阈值，但没超过恢复线
+        monitor.record(true);
+        monitor.record(true);
+        assert_eq!(monitor.state().success_ratio, Some(0.5));
+        assert_eq!(monitor.mode(), ResolverMode::Emergency);
+
+        // [S,S,S,S]
+        monitor.record(true);
+        assert_eq!(monitor.record(true), Some(ResolverMode::Normal));
+
+        monitor.record(false);
+        monitor.reset();
+        assert_eq!(monitor.state().samples, 0);
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        assert!(EmergencyPolicy::new(0.3).validate().is_ok());
+        assert!(EmergencyPolicy::new(1.5).validate().is_err());
+        assert!(EmergencyPolicy::new(0.3).with_window(5, 10).validate().is_err());
+        assert!(EmergencyPolicy::new(0.3).with_window(5, 0).validate().is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! bincode编码的查询接口的输入限制
+//!
+//! [`process_encoded_query`](super::SmartDnsResolver::process_encoded_query) 通常放在网络服务后面，
+//! 输入完全由对端控制。解码前先检查载荷大小，解码时限制按声明长度分配的总字节数，
+//! 解码后检查查询名称和各字段的长度、数量；任何一步不通过都返回带 [`RequestErrorCode`] 的失败响应，
+//! 调用方据此区分无效输入和解析失败。
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{DnsError, Result};
+use super::types::{DnsQueryRequest, RequestErrorCode, UpstreamFilter};
+
+/// 解码时按声明长度分配的字节数上限，载荷大小上限不能超过它
+pub const MAX_DECODE_BYTES: usize = 1 << 20;
+
+/// `query_id`、`client_address` 的最大长度（字节）
+pub const MAX_ID_LEN: usize = 128;
+
+/// 上下文中租户、追踪ID和标签值的最大长度（字节）
+pub const MAX_CONTEXT_VALUE_LEN: usize = 256;
+
+/// 上下文标签名的最大长度（字节）
+pub const MAX_TAG_KEY_LEN: usize = 64;
+
+/// 上下文标签的最大数量
+pub const MAX_TAGS: usize = 32;
+
+/// 上游筛选中上游名称的最大数量
+pub const MAX_FILTER_UPSTREAMS: usize = 64;
+
+/// 编码请求的大小和批量查询数上限
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncodedRequestLimits {
+    /// 单个编码请求（批量时为整批）的最大字节数，解码前检查
+    pub max_payload_bytes: usize,
+    /// 一个批量请求中的最大查询数，解码前按长度前缀检查
+    pub max_batch_len: usize,
+}
+
+impl Default for EncodedRequestLimits {
+    fn default() -> Self {
+        Self {
+            max_payload_bytes: Self::DEFAULT_MAX_PAYLOAD_BYTES,
+            max_batch_len: Self::DEFAULT_MAX_BATCH_LEN,
+        }
+    }
+}
+
+impl EncodedRequestLimits {
+    /// 默认的载荷大小上限
+    pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 64 * 1024;
+    /// 默认的批量查询数上限
+    pub const DEFAULT_MAX_BATCH_LEN: usize = 1000;
+
+    /// 检查上限，为0或载荷上限超过 [`MAX_DECODE_BYTES`] 时返回 [`DnsError::InvalidConfig`]
+    pub fn validate(&self) -> Result<()> {
+        if self.max_payload_bytes == 0 || self.max_payload_bytes > MAX_DECODE_BYTES {
+            return Err(DnsError::InvalidConfig(format!(
+                "max_payload_bytes must be between 1 and {}, got {}", MAX_DECODE_BYTES, self.max_payload_bytes
+            )));
+        }
+        if self.max_batch_len == 0 {
+            return Err(DnsError::InvalidConfig("max_batch_len must be at least 1".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// 无效输入：错误类别和说明
+pub type Rejection = (RequestErrorCode, DnsError);
+
+fn rejection(code: RequestErrorCode, message: String) -> Rejection {
+    (code, DnsError::Parse(message))
+}
+
+#[cfg(feature = "serde-api")]
+fn decode_config() -> impl bincode::config::Config {
+    bincode::config::standard().with_limit::<MAX_DECODE_BYTES>()
+}
+
+/// 解码错误的类别：超出分配上限算载荷过大，未知的记录类型单独区分
+#[cfg(feature = "serde-api")]
+fn decode_rejection(error: bincode::error::DecodeError) -> Rejection {
+    use bincode::error::DecodeError;
+    let code = match &error {
+        DecodeError::LimitExceeded => RequestErrorCode::PayloadTooLarge,
+        DecodeError::UnexpectedVariant { type_name, .. } if type_name.ends_with("DnsRecordType") => {
+            RequestErrorCode::InvalidRecordType
+        }
+        _ => RequestErrorCode::Malformed,
+    };
+    rejection(code, format!("Failed to decode query request: {}", error))
+}
+
+#[cfg(feature = "serde-api")]
+fn check_payload(encoded: &[u8], limits: &EncodedRequestLimits) -> std::result::Result<(), Rejection> {
+    if encoded.len() > limits.max_payload_bytes {
+        return Err(rejection(RequestErrorCode::PayloadTooLarge, format!(
+            "Encoded request is {} bytes, limit is {}", encoded.len(), limits.max_payload_bytes
+        )));
+    }
+    Ok(())
+}
+
+/// 解码单个请求并检查字段
+#[cfg(feature = "serde-api")]
+pub fn decode_request(encoded: &[u8], limits: &EncodedRequestLimits) -> std::result::Result<DnsQueryRequest, Rejection> {
+    check_payload(encoded, limits)?;
+    let (request, read): (DnsQueryRequest, usize) = bincode::decode_from_slice(encoded, decode_config())
+        .map_err(decode_rejection)?;
+    if read != encoded.len() {
+        return Err(rejection(RequestErrorCode::Malformed, format!(
+            "Encoded request has {} trailing bytes", encoded.len() - read
+        )));
+    }
+    validate_request(&request)?;
+    Ok(request)
+}
+
+/// 解码批量请求：先按长度前缀检查查询数，再解码整批；各请求的字段在调用方逐个检查
+#[cfg(feature = "serde-api")]
+pub fn decode_batch(encoded: &[u8], limits: &EncodedRequestLimits) -> std::result::Result<Vec<DnsQueryRequest>, Rejection> {
+    check_payload(encoded, limits)?;
+    let (len, _): (u64, usize) = bincode::decode_from_slice(encoded, decode_config()).map_err(decode_rejection)?;
+    if len > limits.max_batch_len as u64 {
+        return Err(rejection(RequestErrorCode::BatchTooLarge, format!(
+            "Batch has {} queries, limit is {}", len, limits.max_batch_len
+        )));
+    }
+    let (requests, read): (Vec<DnsQueryRequest>, usize) = bincode::decode_from_slice(encoded, decode_config())
+        .map_err(decode_rejection)?;
+    if read != encoded.len() {
+        return Err(rejection(RequestErrorCode::Malformed, format!(
+            "Encoded batch has {} trailing bytes", encoded.len() - read
+        )));
+    }
+    Ok(requests)
+}
+
+/// 检查解码出的请求：查询名称合法，各字段不超过长度和数量上限
+pub fn validate_request(request: &DnsQueryRequest) -> std::result::Result<(), Rejection> {
+    crate::utils::validate_domain(&request.domain).map_err(|e| (RequestErrorCode::InvalidDomain, e))?;
+
+    let too_large = |field: &str, len: usize, limit: usize| -> std::result::Result<(), Rejection> {
+        if len > limit {
+            return Err(rejection(RequestErrorCode::FieldTooLarge, format!("{} is {} long, limit is {}", field, len, limit)));
+        }
+        Ok(())
+    };
+    too_large("query_id", request.query_id.as_ref().map_or(0, String::len), MAX_ID_LEN)?;
+    too_large("client_address", request.client_address.as_ref().map_or(0, String::len), MAX_ID_LEN)?;
+    if let Some(context) = &request.context {
+        too_large("context.tenant", context.tenant.as_ref().map_or(0, String::len), MAX_CONTEXT_VALUE_LEN)?;
+        too_large("context.trace_id", context.trace_id.as_ref().map_or(0, String::len), MAX_CONTEXT_VALUE_LEN)?;
+        too_large("context.tags", context.tags.len(), MAX_TAGS)?;
+        for (key, value) in &context.tags {
+            too_large("context tag key", key.len(), MAX_TAG_KEY_LEN)?;
+            too_large("context tag value", value.len(), MAX_CONTEXT_VALUE_LEN)?;
+        }
+    }
+    if let Some(UpstreamFilter::Only(names) | UpstreamFilter::Exclude(names)) = &request.upstream_filter {
+        too_large("upstream_filter", names.len(), MAX_FILTER_UPSTREAMS)?;
+        for name in names {
+            too_large("upstream_filter name", name.len(), MAX_ID_LEN)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "serde-api"))]
+mod tests {
+    use super::*;
+    use crate::builder::types::{DnsRecordType, QueryContext};
+
+    fn encode<T: bincode::Encode>(value: T) -> Vec<u8> {
+        bincode::encode_to_vec(value, bincode::config::standard()).unwrap()
+    }
+
+    fn code_of<T: std::fmt::Debug>(result: std::result::Result<T, Rejection>) -> RequestErrorCode {
+        result.unwrap_err().0
+    }
+
+    #[test]
+    fn test_valid_request_round_trips() {
+        let request = DnsQueryRequest::new("_dmarc.example.com", DnsRecordType::TXT)
+            .with_context(QueryContext::for_tenant("team-a").with_tag("env", "prod"));
+        let decoded = decode_request(&encode(&request), &EncodedRequestLimits::default()).unwrap();
+        assert_eq!(decoded.domain, request.domain);
+        assert_eq!(decoded.context, request.context);
+    }
+
+    #[test]
+    fn test_oversized_and_malformed_payloads_are_rejected_before_allocating() {
+        let limits = EncodedRequestLimits::default();
+        let oversized = vec![0u8; limits.max_payload_bytes + 1];
+        assert_eq!(code_of(decode_request(&oversized, &limits)), RequestErrorCode::PayloadTooLarge);
+
+        // query_id为Some，随后声明一个长约2^60字节的字符串：按声明长度分配前就超出上限
+        let mut huge_string = vec![1u8, 0xfd];
+        huge_string.extend_from_slice(&(1u64 << 60).to_le_bytes());
+        assert_eq!(code_of(decode_request(&huge_string, &limits)), RequestErrorCode::PayloadTooLarge);
+
+        let mut trailing = encode(DnsQueryRequest::new("example.com", DnsRecordType::A));
+        trailing.push(0);
+        assert_eq!(code_of(decode_request(&trailing, &limits)), RequestErrorCode::Malformed);
+        assert_eq!(code_of(decode_request(&[0xff, 0xff], &limits)), RequestErrorCode::Malformed);
+        assert_eq!(code_of(decode_request(&[], &limits)), RequestErrorCode::Malformed);
+    }
+
+    #[test]
+    fn test_unknown_record_type_and_invalid_domain_are_distinguished() {
+        let limits = EncodedRequestLimits::default();
+        // 记录类型是第三个字段，紧跟在query_id（None）和域名之后
+        let mut encoded = encode(DnsQueryRequest::new("example.com", DnsRecordType::A));
+        let record_type_at = 1 + 1 + "example.com".len();
+        encoded[record_type_at] = 200;
+        assert_eq!(code_of(decode_request(&encoded, &limits)), RequestErrorCode::InvalidRecordType);
+
+        for domain in ["", "a..b", "bad name.example", &"a".repeat(64)] {
+            let encoded = encode(DnsQueryRequest::new(domain, DnsRecordType::A));
+            assert_eq!(code_of(decode_request(&encoded, &limits)), RequestErrorCode::InvalidDomain, "{:?}", domain);
+        }
+    }
+
+    #[test]
+    fn test_absurd_option_maps_are_capped() {
+        let limits = EncodedRequestLimits::default();
+        let mut context = QueryContext::for_tenant("team-a");
+        for i in 0..=MAX_TAGS {
+            context = context.with_tag(format!("k{}", i), "v");
+        }
+        let request = DnsQueryRequest::new("example.com", DnsRecordType::A).with_context(context);
+        assert_eq!(code_of(decode_request(&encode(&request), &limits)), RequestErrorCode::FieldTooLarge);
+
+        let context = QueryContext::default().with_tag("k".repeat(MAX_TAG_KEY_LEN + 1), "v");
+        let request = DnsQueryRequest::new("example.com", DnsRecordType::A).with_context(context);
+        assert_eq!(code_of(decode_request(&encode(&request), &limits)), RequestErrorCode::FieldTooLarge);
+
+        let names = (0..=MAX_FILTER_UPSTREAMS).map(|i| format!("u{}", i)).collect();
+        let request = DnsQueryRequest::new("example.com", DnsRecordType::A).with_upstream_filter(UpstreamFilter::Exclude(names));
+        assert_eq!(code_of(decode_request(&encode(&request), &limits)), RequestErrorCode::FieldTooLarge);
+    }
+
+    #[test]
+    fn test_batch_length_is_checked_before_decoding() {
+        let limits = EncodedRequestLimits { max_batch_len: 2, ..EncodedRequestLimits::default() };
+        let batch = vec![DnsQueryRequest::new("example.com", DnsRecordType::A); 2];
+        assert_eq!(decode_batch(&encode(&batch), &limits).unwrap().len(), 2);
+
+        let batch = vec![DnsQueryRequest::new("example.com", DnsRecordType::A); 3];
+        assert_eq!(code_of(decode_batch(&encode(&batch), &limits)), RequestErrorCode::BatchTooLarge);
+        // 只有长度前缀、声明了极多查询的载荷
+        assert_eq!(code_of(decode_batch(&encode(u64::MAX), &limits)), RequestErrorCode::BatchTooLarge);
+
+        assert!(EncodedRequestLimits::default().validate().is_ok());
+        assert!(EncodedRequestLimits { max_payload_bytes: MAX_DECODE_BYTES + 1, ..limits }.validate().is_err());
+        assert!(EncodedRequestLimits { max_batch_len: 0, ..limits }.validate().is_err());
+    }
+}
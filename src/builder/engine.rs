@@ -2,15 +2,23 @@
 //! 
 //! 本模块实现基于性能指标的智能上游服务器选择算法
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::path::Path;
+use std::time::Duration;
+use crate::time::Instant;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use crate::upstream_handler::UpstreamSpec;
 use crate::error::{DnsError, Result};
-use super::metrics::PerformanceMetrics;
+use crate::resolver::clock::{self, Clock};
+use crate::{dns_debug, dns_info, dns_warn};
+use super::metrics::{PerformanceMetrics, SerializedMetrics, unix_millis};
+use super::emergency::{EmergencyMonitor, EmergencyPolicy, EmergencyState, ResolverMode};
+use super::explain::{CandidateExplanation, ExclusionReason, ScoreBreakdown, SelectionExplanation};
+use super::adaptive_timeout::AdaptiveTimeoutConfig;
 
 /// 失败服务器信息
 #[derive(Debug, Clone)]
@@ -40,6 +48,10 @@ pub struct EmergencyResponseInfo {
     pub total_failures: u32,
     /// 应急消息
     pub emergency_message: String,
+    /// 是否处于应急模式（成功率低于配置的阈值）
+    pub emergency_mode: bool,
+    /// 最近查询窗口内的成功率，未配置应急阈值或样本不足时为 `None`
+    pub success_ratio: Option<f64>,
 }
 
 /// 智能决策引擎
@@ -56,6 +68,39 @@ pub struct SmartDecisionEngine {
     
     /// 当前区域
     current_region: String,
+    
+    /// 应急模式监控（未配置应急阈值时为None）
+    emergency: Option<EmergencyMonitor>,
+    
+    /// 运行时停用的上游名称
+    disabled: Arc<RwLock<HashSet<String>>>,
+    
+    /// 是否收集性能指标（关闭时不创建也不更新任何指标）
+    collect_metrics: bool,
+    
+    /// 是否配置了CDN探测（未配置时CDN准确性不参与评分）
+    cdn_verification: bool,
+    
+    /// 因命中缓存而没有更新性能指标的查询数
+    skipped_cache_hits: Arc<AtomicU64>,
+    
+    /// 解析器的默认超时（上游没有单独设置超时时使用）
+    default_timeout: Duration,
+    
+    /// 自适应超时配置（未开启时为None）
+    adaptive_timeout: Option<AdaptiveTimeoutConfig>,
+    
+    /// 最近一次重新计算得到的自适应超时，样本不足的上游不在其中
+    adaptive_timeouts: Arc<RwLock<HashMap<String, Duration>>>,
+    
+    /// 记录成功/失败时间、计算最近成功加成和导出快照使用的时间源
+    clock: Arc<dyn Clock>,
+}
+
+/// 上游是否可被选择：未被停用，且性能指标未判定其不可用
+fn is_selectable(spec: &UpstreamSpec, metrics: &HashMap<String, PerformanceMetrics>, disabled: &HashSet<String>) -> bool {
+    !disabled.contains(&spec.name)
+        && metrics.get(&spec.name).map(|m| m.is_available()).unwrap_or(true)
 }
 
 impl SmartDecisionEngine {
@@ -66,11 +111,154 @@ impl SmartDecisionEngine {
             metrics: Arc::new(RwLock::new(HashMap::new())),
             round_robin_index: Arc::new(RwLock::new(0)),
             current_region: region.into(),
+            emergency: None,
+            disabled: Arc::new(RwLock::new(HashSet::new())),
+            collect_metrics: true,
+            cdn_verification: false,
+            skipped_cache_hits: Arc::new(AtomicU64::new(0)),
+            default_timeout: Duration::from_secs(5),
+            adaptive_timeout: None,
+            adaptive_timeouts: Arc::new(RwLock::new(HashMap::new())),
+            clock: clock::real_clock(),
+        }
+    }
+    
+    /// 使用指定的时间源（默认为真实时钟）
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+    
+    /// 设置是否收集性能指标（默认收集）
+    /// 
+    /// 关闭时 [`update_metrics`](Self::update_metrics) 直接返回，不加锁也不计数；
+    /// 所有上游都视为可用，依赖指标的智能选择退化为按配置顺序选择
+    pub fn with_metrics_collection(mut self, enable: bool) -> Self {
+        self.collect_metrics = enable;
+        self
+    }
+    
+    /// 是否收集性能指标
+    pub fn collects_metrics(&self) -> bool {
+        self.collect_metrics
+    }
+    
+    /// 设置CDN准确性是否参与评分（默认不参与）
+    /// 
+    /// 只有配置了CDN探测、评分来自真实的探测结果时才应开启；
+    /// 不开启时CDN准确性的权重为零，评分只由成功率、延迟和连续失败决定
+    pub fn with_cdn_verification(mut self, enable: bool) -> Self {
+        self.cdn_verification = enable;
+        self
+    }
+    
+    /// CDN准确性是否参与评分
+    pub fn cdn_verification_enabled(&self) -> bool {
+        self.cdn_verification
+    }
+    
+    /// 设置解析器的默认超时和自适应超时配置（默认5秒，不自适应）
+    /// 
+    /// 开启自适应超时时由 [`recalculate_timeouts`](Self::recalculate_timeouts) 按延迟直方图计算各上游的超时，
+    /// 需要收集性能指标
+    pub fn with_timeouts(mut self, default_timeout: Duration, adaptive: Option<AdaptiveTimeoutConfig>) -> Self {
+        self.default_timeout = default_timeout;
+        self.adaptive_timeout = adaptive;
+        self
+    }
+    
+    /// 自适应超时配置，未开启时为 `None`
+    pub fn adaptive_timeout(&self) -> Option<&AdaptiveTimeoutConfig> {
+        self.adaptive_timeout.as_ref()
+    }
+    
+    /// 按当前的延迟直方图重新计算各上游的自适应超时，返回各上游（按配置顺序）实际使用的单次发送超时
+    /// 
+    /// 样本不足的上游沿用配置的超时；未开启自适应超时时返回空列表
+    pub async fn recalculate_timeouts(&self) -> Vec<(String, Duration)> {
+        let Some(adaptive) = &self.adaptive_timeout else {
+            return Vec::new();
+        };
+        let upstreams = self.upstreams.read().await;
+        let metrics = self.metrics.read().await;
+        let mut computed = HashMap::new();
+        let timeouts = upstreams.iter()
+            .map(|spec| {
+                let configured = spec.timeout.unwrap_or(self.default_timeout);
+                let timeout = match metrics.get(&spec.name).and_then(|metric| adaptive.compute(&metric.latency_histogram)) {
+                    Some(timeout) => {
+                        computed.insert(spec.name.clone(), timeout);
+                        timeout
+                    }
+                    None => configured,
+                };
+                (spec.name.clone(), timeout)
+            })
+            .collect();
+        *self.adaptive_timeouts.write().await = computed;
+        timeouts
+    }
+    
+    /// 上游实际使用的单次发送超时：最近一次计算出的自适应超时，没有时为配置的超时
+    fn effective_timeout(&self, spec: &UpstreamSpec, adaptive_timeouts: &HashMap<String, Duration>) -> Duration {
+        adaptive_timeouts.get(&spec.name).copied().unwrap_or_else(|| spec.timeout.unwrap_or(self.default_timeout))
+    }
+    
+    /// 指定上游实际使用的单次发送超时，上游不存在时为 `None`
+    pub async fn effective_timeout_of(&self, name: &str) -> Option<Duration> {
+        let upstreams = self.upstreams.read().await;
+        let adaptive_timeouts = self.adaptive_timeouts.read().await;
+        upstreams.iter()
+            .find(|spec| spec.name == name)
+            .map(|spec| self.effective_timeout(spec, &adaptive_timeouts))
+    }
+    
+    /// 启用应急模式判定
+    pub fn with_emergency_policy(mut self, policy: EmergencyPolicy) -> Self {
+        self.emergency = Some(EmergencyMonitor::new(policy));
+        self
+    }
+    
+    /// 应急模式判定参数，未启用时为 `None`
+    pub fn emergency_policy(&self) -> Option<&EmergencyPolicy> {
+        self.emergency.as_ref().map(|monitor| monitor.policy())
+    }
+    
+    /// 记录一次经过上游的查询结果，模式发生切换时返回新模式
+    pub fn record_query_outcome(&self, success: bool) -> Option<ResolverMode> {
+        let monitor = self.emergency.as_ref()?;
+        let transition = monitor.record(success);
+        if let Some(mode) = transition {
+            let state = monitor.state();
+            match mode {
+                ResolverMode::Emergency => dns_warn!(
+                    "🚨 最近 {} 次查询成功率 {:.0}% 低于阈值 {:.0}%，进入应急模式",
+                    state.samples,
+                    state.success_ratio.unwrap_or(0.0) * 100.0,
+                    monitor.policy().threshold * 100.0
+                ),
+                ResolverMode::Normal => dns_info!(
+                    "✅ 最近 {} 次查询成功率恢复到 {:.0}%，退出应急模式",
+                    state.samples,
+                    state.success_ratio.unwrap_or(0.0) * 100.0
+                ),
+            }
         }
+        transition
+    }
+    
+    /// 当前运行模式，未启用应急判定时始终为正常模式
+    pub fn resolver_mode(&self) -> ResolverMode {
+        self.emergency.as_ref().map_or(ResolverMode::Normal, |monitor| monitor.mode())
+    }
+    
+    /// 应急状态快照，未启用应急判定时为 `None`
+    pub fn emergency_state(&self) -> Option<EmergencyState> {
+        self.emergency.as_ref().map(|monitor| monitor.state())
     }
     
     /// 添加上游服务器
-    pub async fn add_upstream(&mut self, spec: UpstreamSpec) -> Result<()> {
+    pub async fn add_upstream(&self, spec: UpstreamSpec) -> Result<()> {
         let mut upstreams = self.upstreams.write().await;
         let mut metrics = self.metrics.write().await;
         
@@ -82,14 +270,16 @@ impl SmartDecisionEngine {
         }
         
         // 初始化性能指标
-        metrics.insert(spec.name.clone(), PerformanceMetrics::new());
+        if self.collect_metrics {
+            metrics.insert(spec.name.clone(), PerformanceMetrics::new());
+        }
         upstreams.push(spec);
         
         Ok(())
     }
     
-    /// 移除上游服务器
-    pub async fn remove_upstream(&mut self, name: &str) -> Result<()> {
+    /// 移除上游服务器（同时清除其性能指标和停用状态）
+    pub async fn remove_upstream(&self, name: &str) -> Result<()> {
         let mut upstreams = self.upstreams.write().await;
         let mut metrics = self.metrics.write().await;
         
@@ -100,10 +290,43 @@ impl SmartDecisionEngine {
         
         upstreams.remove(index);
         metrics.remove(name);
+        self.disabled.write().await.remove(name);
+        self.adaptive_timeouts.write().await.remove(name);
         
         Ok(())
     }
     
+    /// 替换同名上游的规格（权重、区域等），性能指标和停用状态保留
+    pub async fn update_upstream(&self, spec: UpstreamSpec) -> Result<()> {
+        let mut upstreams = self.upstreams.write().await;
+        let existing = upstreams.iter_mut()
+            .find(|s| s.name == spec.name)
+            .ok_or_else(|| DnsError::InvalidConfig(
+                format!("Upstream '{}' not found", spec.name)
+            ))?;
+        *existing = spec;
+        Ok(())
+    }
+    
+    /// 启用或停用上游服务器，停用的上游不会被选择，性能指标保留
+    pub async fn set_upstream_enabled(&self, name: &str, enabled: bool) -> Result<()> {
+        if !self.upstreams.read().await.iter().any(|s| s.name == name) {
+            return Err(DnsError::InvalidConfig(format!("Upstream '{}' not found", name)));
+        }
+        let mut disabled = self.disabled.write().await;
+        if enabled {
+            disabled.remove(name);
+        } else {
+            disabled.insert(name.to_string());
+        }
+        Ok(())
+    }
+    
+    /// 上游服务器是否处于启用状态
+    pub async fn is_upstream_enabled(&self, name: &str) -> bool {
+        !self.disabled.read().await.contains(name)
+    }
+    
     /// FIFO策略选择上游服务器
     /// 按照配置顺序依次选择第一个健康的服务器
     pub async fn select_fifo_upstream(&self) -> Option<UpstreamSpec> {
@@ -115,10 +338,9 @@ impl SmartDecisionEngine {
         }
         
         // 按配置顺序查找第一个可用的服务器
+        let disabled = self.disabled.read().await;
         for spec in upstreams.iter() {
-            if metrics.get(&spec.name)
-                .map(|m| m.is_available())
-                .unwrap_or(true) {
+            if is_selectable(spec, &metrics, &disabled) {
                 return Some(spec.clone());
             }
         }
@@ -143,13 +365,10 @@ impl SmartDecisionEngine {
         }
         
         // 过滤可用的上游服务器
+        let disabled = self.disabled.read().await;
         let available_upstreams: Vec<_> = upstreams
             .iter()
-            .filter(|spec| {
-                metrics.get(&spec.name)
-                    .map(|m| m.is_available())
-                    .unwrap_or(true)
-            })
+            .filter(|spec| is_selectable(spec, &metrics, &disabled))
             .collect();
         
         if available_upstreams.is_empty() {
@@ -184,14 +403,11 @@ impl SmartDecisionEngine {
         }
         
         // 过滤可用的上游服务器
+        let disabled = self.disabled.read().await;
         let available_upstreams: Vec<(usize, &UpstreamSpec)> = upstreams
             .iter()
             .enumerate()
-            .filter(|(_, spec)| {
-                metrics.get(&spec.name)
-                    .map(|m| m.is_available())
-                    .unwrap_or(true)
-            })
+            .filter(|(_, spec)| is_selectable(spec, &metrics, &disabled))
             .collect();
         
         if available_upstreams.is_empty() {
@@ -216,8 +432,9 @@ impl SmartDecisionEngine {
             return true;
         }
         
-        // 检查是否所有服务器都处于不可用状态
-        upstreams.iter().all(|spec| {
+        // 检查是否所有启用的服务器都处于不可用状态
+        let disabled = self.disabled.read().await;
+        upstreams.iter().filter(|spec| !disabled.contains(&spec.name)).all(|spec| {
             metrics.get(&spec.name)
                 .map(|m| !m.is_available())
                 .unwrap_or(false)
@@ -250,12 +467,24 @@ impl SmartDecisionEngine {
             }
         }
         
+        let state = self.emergency_state();
+        let emergency_mode = state.is_some_and(|state| state.mode == ResolverMode::Emergency);
+        let mut emergency_message = self.generate_emergency_message(&upstreams, &metrics).await;
+        if emergency_mode {
+            emergency_message.push_str(&format!(
+                "；应急模式已启用（最近查询成功率 {:.0}%），正向所有上游并发查询",
+                state.and_then(|state| state.success_ratio).unwrap_or(0.0) * 100.0
+            ));
+        }
+        
         EmergencyResponseInfo {
             all_servers_failed: failed_servers.len() == upstreams.len(),
             failed_servers,
             last_working_server,
             total_failures,
-            emergency_message: self.generate_emergency_message(&upstreams, &metrics).await,
+            emergency_message,
+            emergency_mode,
+            success_ratio: state.and_then(|state| state.success_ratio),
         }
     }
     
@@ -304,6 +533,11 @@ impl SmartDecisionEngine {
     
     /// 计算上游服务器综合评分
     fn calculate_upstream_score(&self, spec: &UpstreamSpec, metrics: &HashMap<String, PerformanceMetrics>) -> f64 {
+        self.score_breakdown(spec, metrics).final_score
+    }
+    
+    /// 上游服务器评分的各个分量，智能选择和选择解释共用
+    fn score_breakdown(&self, spec: &UpstreamSpec, metrics: &HashMap<String, PerformanceMetrics>) -> ScoreBreakdown {
         let base_score = spec.weight as f64;
         
         let default_metric = PerformanceMetrics::default();
@@ -325,8 +559,12 @@ impl SmartDecisionEngine {
         };
         let latency_component = base_score * 0.3 * latency_score;
         
-        // CDN准确性权重 (20%)
-        let cdn_component = base_score * 0.2 * metric.cdn_accuracy_score.max(0.7);
+        // CDN准确性权重 (20%)，没有CDN探测时为零
+        let cdn_component = if self.cdn_verification {
+            base_score * 0.2 * metric.cdn_accuracy_score
+        } else {
+            0.0
+        };
         
         // 连续失败惩罚 (10%)
         let failure_penalty = if metric.consecutive_failures > 3 {
@@ -336,38 +574,92 @@ impl SmartDecisionEngine {
         } else {
             1.0
         };
-        let penalty_component = base_score * 0.1 * failure_penalty;
-        
-        let mut total_score = success_component + latency_component + cdn_component + penalty_component;
+        let failure_component = base_score * 0.1 * failure_penalty;
         
         // 最近成功时间加成
-        if let Some(last_success) = metric.last_success_time {
-            let time_since_success = Instant::now().duration_since(last_success);
-            if time_since_success < Duration::from_secs(60) {
-                total_score *= 1.1;
-            }
-        }
+        let recent_success = metric.last_success_time
+            .is_some_and(|last_success| self.clock.now_instant().saturating_duration_since(last_success) < Duration::from_secs(60));
+        let recency_bonus = if recent_success { 1.1 } else { 1.0 };
         
         // 区域匹配加成
-        if spec.region.as_ref().map(|r| r == &self.current_region).unwrap_or(false) {
-            total_score *= 1.2;
-        }
+        let region_bonus = if spec.region.as_ref().map(|r| r == &self.current_region).unwrap_or(false) {
+            1.2
+        } else {
+            1.0
+        };
         
-        total_score
+        let final_score = (success_component + latency_component + cdn_component + failure_component) * recency_bonus * region_bonus;
+        ScoreBreakdown {
+            base_weight: base_score,
+            success_rate,
+            success_component,
+            latency_component,
+            cdn_component,
+            failure_component,
+            recency_bonus,
+            region_bonus,
+            final_score,
+        }
+    }
+    
+    /// 解释智能策略按当前性能指标会选择哪个上游：各候选的评分分量、被过滤的候选和选中的原因
+    /// 
+    /// 选择是确定的，解释中的 `selected` 就是此刻 [`select_smart_upstream`](Self::select_smart_upstream) 的结果；
+    /// 只在调用时计算，不影响查询
+    pub async fn explain_selection(&self) -> SelectionExplanation {
+        let upstreams = self.upstreams.read().await;
+        let metrics = self.metrics.read().await;
+        let disabled = self.disabled.read().await;
+        let adaptive_timeouts = self.adaptive_timeouts.read().await;
+        let candidates = upstreams.iter()
+            .map(|spec| {
+                let excluded = if disabled.contains(&spec.name) {
+                    Some(ExclusionReason::Disabled)
+                } else if !is_selectable(spec, &metrics, &disabled) {
+                    Some(ExclusionReason::Unavailable)
+                } else {
+                    None
+                };
+                CandidateExplanation {
+                    name: spec.name.clone(),
+                    excluded,
+                    score: self.score_breakdown(spec, &metrics),
+                    effective_timeout_ms: u64::try_from(self.effective_timeout(spec, &adaptive_timeouts).as_millis()).unwrap_or(u64::MAX),
+                    labels: spec.labels.clone(),
+                }
+            })
+            .collect();
+        SelectionExplanation::from_candidates(candidates)
     }
     
     /// 更新性能指标
-    pub async fn update_metrics(&self, upstream_name: &str, latency: Duration, success: bool, cdn_accurate: bool) {
+    /// 
+    /// `cdn_accurate` 是CDN探测对应答的判定，普通查询传 `None`
+    pub async fn update_metrics(&self, upstream_name: &str, latency: Duration, success: bool, cdn_accurate: Option<bool>) {
+        if !self.collect_metrics {
+            return;
+        }
         let mut metrics = self.metrics.write().await;
         if let Some(metric) = metrics.get_mut(upstream_name) {
+            let now = self.clock.now_instant();
             if success {
-                metric.record_success(latency, cdn_accurate);
+                metric.record_success_at(latency, cdn_accurate, now);
             } else {
-                metric.record_failure();
+                metric.record_failure_at(now);
             }
         }
     }
     
+    /// 记录一次命中缓存的查询：没有联系任何上游，不更新性能指标，只计数
+    pub fn record_cache_hit(&self) {
+        self.skipped_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+    
+    /// 因命中缓存而没有更新性能指标的查询数
+    pub fn skipped_cache_hit_updates(&self) -> u64 {
+        self.skipped_cache_hits.load(Ordering::Relaxed)
+    }
+    
     /// 获取所有上游服务器的性能指标
     pub async fn get_all_metrics(&self) -> HashMap<String, PerformanceMetrics> {
         self.metrics.read().await.clone()
@@ -378,12 +670,15 @@ impl SmartDecisionEngine {
         self.metrics.read().await.get(upstream_name).cloned()
     }
     
-    /// 重置所有性能指标
+    /// 重置所有性能指标（包括应急模式的成功率窗口）
     pub async fn reset_metrics(&self) {
         let mut metrics = self.metrics.write().await;
         for metric in metrics.values_mut() {
             metric.reset();
         }
+        if let Some(monitor) = &self.emergency {
+            monitor.reset();
+        }
     }
     
     /// 获取上游服务器列表
@@ -396,16 +691,82 @@ impl SmartDecisionEngine {
         let upstreams = self.upstreams.read().await;
         let metrics = self.metrics.read().await;
         
+        let disabled = self.disabled.read().await;
         upstreams
             .iter()
-            .filter(|spec| {
-                metrics.get(&spec.name)
-                    .map(|m| m.is_available())
-                    .unwrap_or(true)
-            })
+            .filter(|spec| is_selectable(spec, &metrics, &disabled))
             .count()
     }
     
+    /// 导出性能指标快照
+    pub async fn export_metrics(&self) -> SerializedMetrics {
+        let metrics = self.metrics.read().await;
+        self.build_snapshot(&metrics)
+    }
+
+    /// 非阻塞地导出性能指标快照（用于 Drop 等同步场景），锁被占用时返回 None
+    pub fn try_export_metrics(&self) -> Option<SerializedMetrics> {
+        let metrics = self.metrics.try_read().ok()?;
+        Some(self.build_snapshot(&metrics))
+    }
+
+    fn build_snapshot(&self, metrics: &HashMap<String, PerformanceMetrics>) -> SerializedMetrics {
+        let now_instant = self.clock.now_instant();
+        let now_unix_ms = unix_millis(self.clock.now_system());
+
+        SerializedMetrics {
+            snapshot_unix_ms: now_unix_ms,
+            upstreams: metrics
+                .iter()
+                .map(|(name, metric)| (name.clone(), metric.to_serialized(now_instant, now_unix_ms)))
+                .collect(),
+        }
+    }
+
+    /// 导入性能指标快照
+    ///
+    /// 只会覆盖当前已注册上游的指标，快照中已不存在的上游会被忽略；
+    /// 返回实际导入的上游数量
+    pub async fn import_metrics(&self, snapshot: SerializedMetrics) -> usize {
+        let now_instant = self.clock.now_instant();
+        let now = self.clock.now_system();
+        let now_unix_ms = unix_millis(now);
+        let age = snapshot.age_at(now);
+
+        let mut metrics = self.metrics.write().await;
+        let mut imported = 0;
+        for (name, data) in snapshot.upstreams.iter() {
+            if let Some(metric) = metrics.get_mut(name) {
+                *metric = PerformanceMetrics::from_serialized(data, age, now_instant, now_unix_ms);
+                imported += 1;
+            } else {
+                dns_debug!("忽略快照中未配置的上游: {}", name);
+            }
+        }
+
+        dns_info!("已导入 {} 个上游的性能指标快照 (快照年龄: {:?})", imported, age);
+        imported
+    }
+
+    /// 从文件加载并导入性能指标快照
+    pub async fn load_metrics_snapshot(&self, path: &Path) -> Result<usize> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| DnsError::Io(format!("Failed to read metrics snapshot {}: {}", path.display(), e)))?;
+        let snapshot = SerializedMetrics::from_json(&content)?;
+        Ok(self.import_metrics(snapshot).await)
+    }
+
+    /// 把性能指标快照写入文件（先写临时文件再重命名，避免写出半个文件）
+    pub fn write_metrics_snapshot(snapshot: &SerializedMetrics, path: &Path) -> Result<()> {
+        let json = snapshot.to_json()?;
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, json)
+            .map_err(|e| DnsError::Io(format!("Failed to write metrics snapshot {}: {}", tmp_path.display(), e)))?;
+        std::fs::rename(&tmp_path, path)
+            .map_err(|e| DnsError::Io(format!("Failed to move metrics snapshot to {}: {}", path.display(), e)))?;
+        Ok(())
+    }
+
     /// 设置当前区域
     pub fn set_region(&mut self, region: impl Into<String>) {
         self.current_region = region.into();
@@ -415,4 +776,189 @@ impl SmartDecisionEngine {
     pub fn current_region(&self) -> &str {
         &self.current_region
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::metrics::SerializedPerformanceMetrics;
+    use crate::time::SystemTime;
+
+    async fn engine_with(names: &[&str]) -> SmartDecisionEngine {
+        let engine = SmartDecisionEngine::new("CN");
+        for name in names {
+            engine.add_upstream(UpstreamSpec::udp(name.to_string(), "127.0.0.1:53".to_string())).await.unwrap();
+        }
+        engine
+    }
+
+    #[tokio::test]
+    async fn test_metrics_snapshot_round_trip() {
+        let engine = engine_with(&["a", "b"]).await;
+        engine.update_metrics("a", Duration::from_millis(20), true, None).await;
+        engine.update_metrics("b", Duration::from_millis(80), false, None).await;
+
+        let snapshot = engine.export_metrics().await;
+        let restored = engine_with(&["a", "b"]).await;
+        assert_eq!(restored.import_metrics(snapshot).await, 2);
+
+        let a = restored.get_metrics("a").await.unwrap();
+        assert_eq!(a.successful_queries, 1);
+        assert_eq!(a.avg_latency, Duration::from_millis(20));
+        assert!(a.last_success_time.is_some());
+        assert_eq!(restored.get_metrics("b").await.unwrap().failed_queries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_latency_histogram_tracks_successes_and_resets() {
+        let engine = engine_with(&["a"]).await;
+        for ms in [10, 20, 30, 40] {
+            engine.update_metrics("a", Duration::from_millis(ms), true, None).await;
+        }
+        engine.update_metrics("a", Duration::from_secs(5), false, None).await;
+
+        let summary = engine.get_metrics("a").await.unwrap().latency_histogram.summary();
+        assert_eq!(summary.count, 4);
+        assert_eq!(summary.mean, Duration::from_millis(25));
+        assert_eq!(summary.p99, Duration::from_millis(40));
+
+        engine.reset_metrics().await;
+        assert_eq!(engine.get_metrics("a").await.unwrap().latency_histogram.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cdn_accuracy_only_scores_with_verification() {
+        for verification in [false, true] {
+            let engine = engine_with(&["inaccurate", "accurate"]).await.with_cdn_verification(verification);
+            engine.update_metrics("inaccurate", Duration::from_millis(20), true, Some(false)).await;
+            engine.update_metrics("accurate", Duration::from_millis(20), true, Some(true)).await;
+
+            let metrics = engine.get_all_metrics().await;
+            let upstreams = engine.get_upstreams().await;
+            let inaccurate = engine.calculate_upstream_score(&upstreams[0], &metrics);
+            let accurate = engine.calculate_upstream_score(&upstreams[1], &metrics);
+            if verification {
+                assert!(accurate > inaccurate);
+                assert_eq!(engine.select_smart_upstream().await.unwrap().name, "accurate");
+            } else {
+                assert_eq!(accurate, inaccurate);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_selection_explanation_matches_smart_choice() {
+        use crate::builder::explain::{ExclusionReason, SelectionReason};
+
+        let engine = engine_with(&["slow", "fast", "off"]).await;
+        engine.update_metrics("slow", Duration::from_millis(400), true, None).await;
+        engine.update_metrics("slow", Duration::from_millis(400), false, None).await;
+        engine.update_metrics("fast", Duration::from_millis(20), true, None).await;
+        engine.set_upstream_enabled("off", false).await.unwrap();
+
+        let explanation = engine.explain_selection().await;
+        assert_eq!(explanation.selected.as_deref(), Some("fast"));
+        assert_eq!(explanation.selected, engine.select_smart_upstream().await.map(|spec| spec.name));
+        assert_eq!(explanation.reason, SelectionReason::HighestScore);
+        assert_eq!(explanation.runner_up.as_deref(), Some("slow"));
+        assert_eq!(explanation.candidates[2].excluded, Some(ExclusionReason::Disabled));
+
+        // 成功率0.5、平均延迟400ms、连续失败1次、刚成功过：(0.4*0.5 + 0.3*1000/500 + 0 + 0.1*0.8) * 1.1
+        let slow = &explanation.candidates[0].score;
+        assert_eq!(slow.success_rate, 0.5);
+        assert!((slow.latency_component - 0.6).abs() < 1e-9);
+        assert!((slow.failure_component - 0.08).abs() < 1e-9);
+        assert_eq!((slow.recency_bonus, slow.region_bonus), (1.1, 1.0));
+        let sum = slow.success_component + slow.latency_component + slow.cdn_component + slow.failure_component;
+        assert!((slow.final_score - sum * 1.1).abs() < 1e-9);
+
+        let upstreams = engine.get_upstreams().await;
+        let metrics = engine.get_all_metrics().await;
+        for (spec, candidate) in upstreams.iter().zip(&explanation.candidates) {
+            assert_eq!(engine.calculate_upstream_score(spec, &metrics), candidate.score.final_score);
+        }
+        assert!(explanation.to_string().starts_with("fast (score "), "{}", explanation);
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_timeouts_follow_latency() {
+        let adaptive = AdaptiveTimeoutConfig::new(3.0, Duration::from_millis(50), Duration::from_secs(8), 20);
+        let engine = engine_with(&["fast", "slow", "new"]).await.with_timeouts(Duration::from_secs(5), Some(adaptive));
+        for _ in 0..20 {
+            engine.update_metrics("fast", Duration::from_millis(20), true, None).await;
+            engine.update_metrics("slow", Duration::from_millis(2_000), true, None).await;
+        }
+        engine.update_metrics("new", Duration::from_millis(20), true, None).await;
+        assert_eq!(engine.effective_timeout_of("fast").await, Some(Duration::from_secs(5)));
+
+        let timeouts: HashMap<_, _> = engine.recalculate_timeouts().await.into_iter().collect();
+        assert!(timeouts["fast"] < Duration::from_millis(100), "{:?}", timeouts);
+        assert!(timeouts["slow"] > Duration::from_secs(5) && timeouts["slow"] <= Duration::from_secs(8), "{:?}", timeouts);
+        assert_eq!(timeouts["new"], Duration::from_secs(5));
+        assert_eq!(engine.effective_timeout_of("fast").await, Some(timeouts["fast"]));
+        assert_eq!(engine.effective_timeout_of("missing").await, None);
+
+        let explanation = engine.explain_selection().await;
+        assert_eq!(explanation.candidates[0].effective_timeout_ms, timeouts["fast"].as_millis() as u64);
+        assert_eq!(explanation.candidates[2].effective_timeout_ms, 5_000);
+
+        // 未开启自适应超时时不计算
+        let fixed = engine_with(&["fast"]).await;
+        assert!(fixed.recalculate_timeouts().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_selection_explanation_labels_ties_and_empty() {
+        use crate::builder::explain::SelectionReason;
+
+        let engine = engine_with(&["first", "second"]).await;
+        let explanation = engine.explain_selection().await;
+        assert_eq!(explanation.selected.as_deref(), Some("first"));
+        assert_eq!(explanation.reason, SelectionReason::ConfigOrder);
+
+        engine.set_upstream_enabled("first", false).await.unwrap();
+        assert_eq!(engine.explain_selection().await.reason, SelectionReason::OnlyCandidate);
+        engine.set_upstream_enabled("second", false).await.unwrap();
+        let none = engine.explain_selection().await;
+        assert_eq!((none.selected, none.reason), (None, SelectionReason::NoCandidate));
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_snapshot_file_rejected() {
+        let path = std::env::temp_dir().join(format!("rat_quickdns_metrics_{}.json", Uuid::new_v4()));
+        std::fs::write(&path, "garbage").unwrap();
+
+        let engine = engine_with(&["a"]).await;
+        assert!(engine.load_metrics_snapshot(&path).await.is_err());
+        assert_eq!(engine.get_metrics("a").await.unwrap().total_queries, 0);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_warm_snapshot_skips_dead_upstream() {
+        // 冷启动时 dead 排在第一位，FIFO会先选中它
+        let engine = engine_with(&["dead", "alive"]).await;
+        assert_eq!(engine.select_fifo_upstream().await.unwrap().name, "dead");
+
+        let mut upstreams = HashMap::new();
+        upstreams.insert("dead".to_string(), SerializedPerformanceMetrics {
+            total_queries: 20,
+            successful_queries: 0,
+            failed_queries: 20,
+            consecutive_failures: 20,
+            avg_latency_ms: 5000,
+            last_success_unix_ms: None,
+            last_failure_unix_ms: Some(unix_millis(SystemTime::now())),
+            cdn_accuracy_score: 0.0,
+            cdn_checks: 0,
+        });
+        let snapshot = SerializedMetrics {
+            snapshot_unix_ms: unix_millis(SystemTime::now()),
+            upstreams,
+        };
+        engine.import_metrics(snapshot).await;
+
+        assert_eq!(engine.select_fifo_upstream().await.unwrap().name, "alive");
+        assert_eq!(engine.select_smart_upstream().await.unwrap().name, "alive");
+    }
+}
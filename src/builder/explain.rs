@@ -0,0 +1,170 @@
+//! 智能策略选择上游的解释
+//!
+//! [`SmartDecisionEngine::explain_selection`](super::SmartDecisionEngine::explain_selection) 按当前的性能指标
+//! 重新计算各候选上游的评分，给出每一项的分量、候选是否被过滤以及为什么选中某个上游。
+//! 选择过程是确定的，同样的指标下智能策略的下一次选择与解释中的 `selected` 相同；
+//! 解释只在调用时计算，不改变查询路径
+
+#[cfg(feature = "serde-api")]
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::error::{DnsError, Result};
+
+/// 一个上游的评分分量
+///
+/// `final_score = (success_component + latency_component + cdn_component + failure_component) * recency_bonus * region_bonus`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub struct ScoreBreakdown {
+    /// 基础权重（上游配置的权重），各分量按它缩放
+    pub base_weight: f64,
+    /// 参与评分的成功率，没有查询记录的上游按0.8计
+    pub success_rate: f64,
+    /// 成功率分量（40%）
+    pub success_component: f64,
+    /// 延迟分量（30%）
+    pub latency_component: f64,
+    /// CDN准确性分量（20%），没有配置CDN探测时为0
+    pub cdn_component: f64,
+    /// 连续失败分量（10%），连续失败越多越小
+    pub failure_component: f64,
+    /// 最近60秒内成功过时为1.1，否则为1.0
+    pub recency_bonus: f64,
+    /// 上游区域与当前区域相同时为1.2，否则为1.0
+    pub region_bonus: f64,
+    /// 综合评分
+    pub final_score: f64,
+}
+
+/// 候选上游未参与评分比较的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+#[serde(rename_all = "snake_case")]
+pub enum ExclusionReason {
+    /// 运行时被停用
+    Disabled,
+    /// 性能指标判定不可用（连续失败过多）
+    Unavailable,
+}
+
+/// 一个候选上游的评分和过滤结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub struct CandidateExplanation {
+    /// 上游名称
+    pub name: String,
+    /// 未参与比较的原因，参与比较时为 `None`
+    pub excluded: Option<ExclusionReason>,
+    /// 评分分量（被过滤的上游同样计算，便于比较）
+    pub score: ScoreBreakdown,
+    /// 该上游单次发送实际使用的超时（毫秒）：开启自适应超时且样本足够时为自适应超时，否则为配置的超时
+    pub effective_timeout_ms: u64,
+    /// 上游标签，便于按供应商、机房等对照候选
+    pub labels: BTreeMap<String, String>,
+}
+
+/// 选中上游的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionReason {
+    /// 评分严格高于其他参与比较的上游
+    HighestScore,
+    /// 只有一个上游参与比较
+    OnlyCandidate,
+    /// 与其他上游评分相同，按配置顺序选中排在前面的
+    ConfigOrder,
+    /// 没有可选的上游
+    NoCandidate,
+}
+
+impl SelectionReason {
+    /// 小写名称，与序列化的值相同
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SelectionReason::HighestScore => "highest_score",
+            SelectionReason::OnlyCandidate => "only_candidate",
+            SelectionReason::ConfigOrder => "config_order",
+            SelectionReason::NoCandidate => "no_candidate",
+        }
+    }
+}
+
+/// 智能策略的一次上游选择：各候选的评分、被过滤的候选和选中的上游
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub struct SelectionExplanation {
+    /// 按配置顺序排列的全部候选
+    pub candidates: Vec<CandidateExplanation>,
+    /// 选中的上游，没有可选的上游时为 `None`
+    pub selected: Option<String>,
+    /// 选中的原因
+    pub reason: SelectionReason,
+    /// 参与比较的候选中评分第二的上游
+    pub runner_up: Option<String>,
+}
+
+impl SelectionExplanation {
+    /// 由按配置顺序排列的候选得出选择结果，与智能策略的选择规则相同：评分最高者胜出，评分相同时取配置在前的
+    pub(crate) fn from_candidates(candidates: Vec<CandidateExplanation>) -> Self {
+        let mut ranked: Vec<&CandidateExplanation> = candidates.iter()
+            .filter(|candidate| candidate.excluded.is_none())
+            .collect();
+        // 稳定排序，评分相同的候选保持配置顺序
+        ranked.sort_by(|a, b| b.score.final_score.partial_cmp(&a.score.final_score).unwrap_or(std::cmp::Ordering::Equal));
+        let reason = match ranked.as_slice() {
+            [] => SelectionReason::NoCandidate,
+            [_] => SelectionReason::OnlyCandidate,
+            [first, second, ..] if first.score.final_score == second.score.final_score => SelectionReason::ConfigOrder,
+            _ => SelectionReason::HighestScore,
+        };
+        let selected = ranked.first().map(|candidate| candidate.name.clone());
+        let runner_up = ranked.get(1).map(|candidate| candidate.name.clone());
+        Self { candidates, selected, reason, runner_up }
+    }
+
+    /// 选中上游的评分分量
+    pub fn selected_score(&self) -> Option<&ScoreBreakdown> {
+        let selected = self.selected.as_deref()?;
+        self.candidates.iter().find(|candidate| candidate.name == selected).map(|candidate| &candidate.score)
+    }
+
+    /// 序列化为JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self)
+            .map_err(|e| DnsError::Parse(format!("Failed to serialize selection explanation: {}", e)))
+    }
+}
+
+impl fmt::Display for SelectionExplanation {
+    /// 一行摘要，例如 `google (score 0.95, highest_score; runner-up cloudflare 0.81; excluded: backup=unavailable)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let score_of = |name: &str| self.candidates.iter()
+            .find(|candidate| candidate.name == name)
+            .map_or(0.0, |candidate| candidate.score.final_score);
+        match &self.selected {
+            Some(selected) => write!(f, "{} (score {:.2}, {}", selected, score_of(selected), self.reason.as_str())?,
+            None => write!(f, "none ({}", self.reason.as_str())?,
+        }
+        if let Some(runner_up) = &self.runner_up {
+            write!(f, "; runner-up {} {:.2}", runner_up, score_of(runner_up))?;
+        }
+        let mut excluded = self.candidates.iter()
+            .filter_map(|candidate| candidate.excluded.map(|reason| (&candidate.name, reason)))
+            .peekable();
+        if excluded.peek().is_some() {
+            write!(f, "; excluded:")?;
+            for (name, reason) in excluded {
+                let reason = match reason {
+                    ExclusionReason::Disabled => "disabled",
+                    ExclusionReason::Unavailable => "unavailable",
+                };
+                write!(f, " {}={}", name, reason)?;
+            }
+        }
+        write!(f, ")")
+    }
+}
@@ -0,0 +1,298 @@
+//! 延迟直方图
+//!
+//! 固定桶的对数刻度直方图：桶上界从 10µs 起每 4 个桶翻一倍，覆盖到约 141 秒，
+//! 更慢的样本落入溢出桶。相邻桶的比例约为 1.19，因此分位数的相对误差不超过约 19%，
+//! 内存占用固定，与样本数量无关。记录样本只做原子自增，不需要加锁。
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// 普通桶数量（另有一个溢出桶）
+const BUCKET_COUNT: usize = 96;
+
+/// 第一个桶的上界（微秒）
+const FIRST_BOUND_MICROS: f64 = 10.0;
+
+/// 每翻一倍所用的桶数
+const BUCKETS_PER_DOUBLING: f64 = 4.0;
+
+/// 第 `index` 个桶的上界（微秒）
+fn bucket_bound_micros(index: usize) -> u64 {
+    (FIRST_BOUND_MICROS * 2f64.powf(index as f64 / BUCKETS_PER_DOUBLING)).ceil() as u64
+}
+
+/// 样本所在的桶
+fn bucket_index(micros: u64) -> usize {
+    if micros <= FIRST_BOUND_MICROS as u64 {
+        return 0;
+    }
+    let estimate = ((micros as f64 / FIRST_BOUND_MICROS).log2() * BUCKETS_PER_DOUBLING).ceil() as usize;
+    // 浮点误差可能让估计值偏差一个桶，按精确上界校正
+    let mut index = estimate.min(BUCKET_COUNT);
+    while index > 0 && micros <= bucket_bound_micros(index - 1) {
+        index -= 1;
+    }
+    while index < BUCKET_COUNT && micros > bucket_bound_micros(index) {
+        index += 1;
+    }
+    index
+}
+
+/// 延迟分位数摘要
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LatencyPercentiles {
+    /// 样本数
+    pub count: u64,
+    /// 平均延迟（由精确的样本总和计算）
+    pub mean: Duration,
+    /// 中位数
+    pub p50: Duration,
+    /// 95分位
+    pub p95: Duration,
+    /// 99分位
+    pub p99: Duration,
+}
+
+/// 延迟直方图
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; BUCKET_COUNT + 1],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+    min_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl LatencyHistogram {
+    /// 创建空直方图
+    pub fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+            min_micros: AtomicU64::new(u64::MAX),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// 记录一个延迟样本
+    pub fn record(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket_index(micros)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+        self.min_micros.fetch_min(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// 样本数
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// 平均延迟，没有样本时为0
+    pub fn mean(&self) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_micros(self.sum_micros.load(Ordering::Relaxed) / count)
+    }
+
+    /// 最小样本，没有样本时为0
+    pub fn min(&self) -> Duration {
+        match self.min_micros.load(Ordering::Relaxed) {
+            u64::MAX => Duration::ZERO,
+            micros => Duration::from_micros(micros),
+        }
+    }
+
+    /// 最大样本，没有样本时为0
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_micros.load(Ordering::Relaxed))
+    }
+
+    /// 分位数（`p` 取 0.0-1.0），返回样本所在桶的上界，并限制在实际的最小、最大样本之间
+    pub fn percentile(&self, p: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((p.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let max = self.max_micros.load(Ordering::Relaxed);
+        let min = self.min_micros.load(Ordering::Relaxed);
+
+        let mut seen = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                let bound = if index == BUCKET_COUNT { max } else { bucket_bound_micros(index) };
+                return Duration::from_micros(bound.clamp(min.min(max), max));
+            }
+        }
+        Duration::from_micros(max)
+    }
+
+    /// 常用分位数摘要
+    pub fn summary(&self) -> LatencyPercentiles {
+        LatencyPercentiles {
+            count: self.count(),
+            mean: self.mean(),
+            p50: self.percentile(0.50),
+            p95: self.percentile(0.95),
+            p99: self.percentile(0.99),
+        }
+    }
+
+    /// 样本总和
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+    }
+
+    /// 不超过各个 `bounds` 的累计样本数，用于按外部给定的桶边界导出（如OpenMetrics）
+    ///
+    /// 只计入上界不超过该边界的内部桶，结果可能比精确值少，差距不超过一个内部桶；
+    /// `bounds` 应按升序给出，结果按同样顺序单调不减
+    pub fn cumulative_counts(&self, bounds: &[Duration]) -> Vec<u64> {
+        let counts: Vec<u64> = self.buckets[..BUCKET_COUNT].iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        let mut cumulative = Vec::with_capacity(bounds.len());
+        let (mut index, mut seen) = (0, 0);
+        for bound in bounds {
+            let bound_micros = u64::try_from(bound.as_micros()).unwrap_or(u64::MAX);
+            while index < BUCKET_COUNT && bucket_bound_micros(index) <= bound_micros {
+                seen += counts[index];
+                index += 1;
+            }
+            cumulative.push(seen);
+        }
+        cumulative
+    }
+
+    /// 把另一个直方图的样本并入当前直方图
+    pub fn merge(&self, other: &LatencyHistogram) {
+        for (bucket, theirs) in self.buckets.iter().zip(other.buckets.iter()) {
+            bucket.fetch_add(theirs.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        self.count.fetch_add(other.count(), Ordering::Relaxed);
+        self.sum_micros.fetch_add(other.sum_micros.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.min_micros.fetch_min(other.min_micros.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.max_micros.fetch_max(other.max_micros.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    /// 清空所有样本
+    pub fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.sum_micros.store(0, Ordering::Relaxed);
+        self.min_micros.store(u64::MAX, Ordering::Relaxed);
+        self.max_micros.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for LatencyHistogram {
+    fn clone(&self) -> Self {
+        let copy = Self::new();
+        copy.merge(self);
+        copy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 分位数允许的误差：一个桶的宽度
+    fn assert_close(actual: Duration, expected: Duration) {
+        let ratio = actual.as_secs_f64() / expected.as_secs_f64();
+        assert!((0.84..=1.19).contains(&ratio), "expected ~{:?}, got {:?}", expected, actual);
+    }
+
+    #[test]
+    fn test_bucket_index_matches_bounds() {
+        for micros in [0, 1, 10, 11, 12, 999, 1_000, 1_001, 50_000, 10_000_000] {
+            let index = bucket_index(micros);
+            assert!(micros <= bucket_bound_micros(index));
+            if index > 0 {
+                assert!(micros > bucket_bound_micros(index - 1));
+            }
+        }
+        assert_eq!(bucket_index(u64::MAX), BUCKET_COUNT);
+    }
+
+    #[test]
+    fn test_percentiles_of_uniform_sequence() {
+        let histogram = LatencyHistogram::new();
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.mean(), Duration::from_micros(50_500));
+        assert_close(histogram.percentile(0.50), Duration::from_millis(50));
+        assert_close(histogram.percentile(0.95), Duration::from_millis(95));
+        assert_close(histogram.percentile(0.99), Duration::from_millis(99));
+        assert_eq!(histogram.percentile(1.0), Duration::from_millis(100));
+        assert_close(histogram.percentile(0.0), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_tail_latency_not_hidden_by_mean() {
+        let histogram = LatencyHistogram::new();
+        for _ in 0..98 {
+            histogram.record(Duration::from_millis(5));
+        }
+        histogram.record(Duration::from_secs(2));
+        histogram.record(Duration::from_secs(2));
+
+        assert_close(histogram.percentile(0.50), Duration::from_millis(5));
+        assert_close(histogram.percentile(0.99), Duration::from_secs(2));
+        assert_eq!(histogram.mean(), Duration::from_micros((98 * 5_000 + 2 * 2_000_000) / 100));
+    }
+
+    #[test]
+    fn test_cumulative_counts_never_overcount() {
+        let histogram = LatencyHistogram::new();
+        for ms in [1, 4, 9, 40, 400, 4_000] {
+            histogram.record(Duration::from_millis(ms));
+        }
+        histogram.record(Duration::from_secs(1_000));
+
+        let bounds = [1, 5, 10, 50, 500, 5_000].map(Duration::from_millis);
+        let counts = histogram.cumulative_counts(&bounds);
+        assert_eq!(counts, vec![0, 2, 2, 4, 5, 6]);
+        assert!(counts.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(counts.iter().all(|&count| count <= histogram.count()));
+    }
+
+    #[test]
+    fn test_merge_and_reset() {
+        let a = LatencyHistogram::new();
+        let b = LatencyHistogram::new();
+        a.record(Duration::from_millis(10));
+        b.record(Duration::from_millis(30));
+
+        let merged = a.clone();
+        merged.merge(&b);
+        assert_eq!(merged.count(), 2);
+        assert_eq!(merged.mean(), Duration::from_millis(20));
+        assert_eq!(merged.min(), Duration::from_millis(10));
+        assert_eq!(merged.max(), Duration::from_millis(30));
+        assert_eq!(a.count(), 1);
+
+        assert_eq!(merged.sum(), Duration::from_millis(40));
+
+        merged.reset();
+        assert_eq!(merged.summary(), LatencyPercentiles::default());
+        assert_eq!(merged.min(), Duration::ZERO);
+    }
+}
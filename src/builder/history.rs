@@ -0,0 +1,318 @@
+//! 查询历史记录模块
+//!
+//! 本模块提供固定容量的查询历史环形缓冲区，用于事后排查“某个时间点解析异常”一类问题。
+//! 写入路径为O(1)：原子计数器分配槽位，每个槽位独立加锁，并发写入之间互不阻塞。
+//! 域名和上游名称经 [`QueryHistory::intern`] 共用同一份 `Arc<str>`，重复出现的名称不再分配内存。
+
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock};
+use std::time::Duration;
+use crate::time::SystemTime;
+
+use crate::error::{DnsError, Result};
+use crate::resolver::UpstreamFailover;
+use crate::resolver::memory::{MemoryBudget, MemoryComponent, MemoryMeter};
+use super::lookup::normalize_host;
+use super::strategy::QueryStrategy;
+use super::types::DnsRecordType;
+
+/// 查询结果概要
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryOutcome {
+    /// 查询成功，附带响应码
+    Success {
+        /// DNS响应码（含EDNS扩展的完整RCODE）
+        rcode: u16,
+    },
+    /// 查询失败（超时、所有上游不可用等）
+    Failed,
+}
+
+/// 单条查询历史
+#[derive(Debug, Clone)]
+pub struct QueryHistoryEntry {
+    /// 写入序号，单调递增，用于还原先后顺序
+    pub sequence: u64,
+    /// 查询完成时间
+    pub timestamp: SystemTime,
+    /// 查询的域名
+    pub domain: Arc<str>,
+    /// 记录类型
+    pub record_type: DnsRecordType,
+    /// 查询策略
+    pub strategy: QueryStrategy,
+    /// 实际给出响应的上游（命中缓存或失败时为None）
+    pub upstream: Option<Arc<str>>,
+    /// 查询结果
+    pub outcome: QueryOutcome,
+    /// 查询耗时
+    pub duration: Duration,
+    /// 是否命中缓存
+    pub cache_hit: bool,
+    /// 因SERVFAIL/REFUSED而放弃的上游及其响应码（按放弃顺序）
+    pub failovers: Vec<UpstreamFailover>,
+}
+
+impl QueryHistoryEntry {
+    /// 估算占用的内存：结构本身加上域名、上游名称和放弃的上游列表
+    fn approx_bytes(&self) -> usize {
+        size_of::<Self>()
+            + self.domain.len()
+            + self.upstream.as_ref().map_or(0, |upstream| upstream.len())
+            + self.failovers.len() * size_of::<UpstreamFailover>()
+    }
+}
+
+/// 固定容量的查询历史环形缓冲区
+#[derive(Debug)]
+pub struct QueryHistory {
+    slots: Box<[Mutex<Option<QueryHistoryEntry>>]>,
+    next_sequence: AtomicU64,
+    /// 各槽位中记录的估算内存之和
+    approx_bytes: AtomicUsize,
+    /// 配置了内存预算时向预算报告占用
+    memory: OnceLock<MemoryMeter>,
+    /// 记录中出现过的域名和上游名称，同名记录共用一份
+    names: RwLock<HashSet<Arc<str>>>,
+}
+
+impl QueryHistory {
+    /// 创建指定容量的查询历史，容量必须大于0
+    pub fn new(capacity: usize) -> Result<Self> {
+        if capacity == 0 {
+            return Err(DnsError::InvalidConfig("Query history capacity must be greater than zero".to_string()));
+        }
+        Ok(Self {
+            slots: (0..capacity).map(|_| Mutex::new(None)).collect(),
+            next_sequence: AtomicU64::new(0),
+            approx_bytes: AtomicUsize::new(0),
+            memory: OnceLock::new(),
+            names: RwLock::new(HashSet::new()),
+        })
+    }
+
+    /// 计入内存预算；超出预算时最先丢弃最旧的记录。重复调用时只有第一次生效
+    pub(crate) fn attach_memory_budget(self: &Arc<Self>, budget: &Arc<MemoryBudget>) {
+        if self.memory.set(MemoryMeter::new(budget.clone(), MemoryComponent::QueryHistory)).is_err() {
+            return;
+        }
+        let history = Arc::downgrade(self);
+        budget.register(MemoryComponent::QueryHistory, move |wanted| Some(history.upgrade()?.shrink(wanted)));
+        self.report_memory();
+    }
+
+    /// 估算占用的内存
+    pub fn approx_memory_bytes(&self) -> usize {
+        self.approx_bytes.load(Ordering::Relaxed)
+    }
+
+    /// 缓冲区容量
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// 取得名称的共用副本：已出现过的名称直接复用，否则复制一份登记下来
+    ///
+    /// 登记的名称超过容量的两倍时，丢弃已不被任何记录引用的名称
+    pub(crate) fn intern(&self, name: &str) -> Arc<str> {
+        if let Some(shared) = self.names.read().unwrap_or_else(|poisoned| poisoned.into_inner()).get(name) {
+            return shared.clone();
+        }
+        let mut names = self.names.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(shared) = names.get(name) {
+            return shared.clone();
+        }
+        if names.len() >= self.slots.len() * 2 {
+            names.retain(|shared| Arc::strong_count(shared) > 1);
+        }
+        let shared: Arc<str> = Arc::from(name);
+        names.insert(shared.clone());
+        shared
+    }
+
+    /// 写入一条历史记录，序号由缓冲区分配（传入的 `sequence` 会被覆盖）
+    ///
+    /// 容量写满后覆盖最旧的记录
+    pub fn record(&self, mut entry: QueryHistoryEntry) {
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+        entry.sequence = sequence;
+
+        let added = entry.approx_bytes();
+        let mut slot = self.lock_slot((sequence % self.slots.len() as u64) as usize);
+        // 并发写入同一槽位时，保留序号较新的记录
+        if slot.as_ref().is_none_or(|existing| existing.sequence < sequence) {
+            // 先计入新记录再扣除旧记录，其他线程取走该记录时不会减到负数
+            self.approx_bytes.fetch_add(added, Ordering::Relaxed);
+            let removed = slot.replace(entry).map_or(0, |old| old.approx_bytes());
+            self.approx_bytes.fetch_sub(removed, Ordering::Relaxed);
+            drop(slot);
+            self.report_memory();
+        }
+    }
+
+    /// 最近的n条记录，按时间倒序
+    pub fn recent(&self, n: usize) -> Vec<QueryHistoryEntry> {
+        let mut entries = self.collect(|_| true);
+        entries.truncate(n);
+        entries
+    }
+
+    /// 指定域名的全部记录（忽略大小写和末尾的点），按时间倒序
+    pub fn for_domain(&self, domain: &str) -> Vec<QueryHistoryEntry> {
+        let target = normalize_host(domain);
+        self.collect(|entry| normalize_host(&entry.domain) == target)
+    }
+
+    /// 清空历史记录
+    pub fn clear(&self) {
+        for index in 0..self.slots.len() {
+            self.take_slot(index);
+        }
+        self.report_memory();
+    }
+
+    /// 从最旧的记录开始丢弃，直到释放至少 `wanted` 字节或清空，返回释放的字节数
+    fn shrink(&self, wanted: usize) -> usize {
+        let mut occupied: Vec<(u64, usize)> = (0..self.slots.len())
+            .filter_map(|index| self.lock_slot(index).as_ref().map(|entry| (entry.sequence, index)))
+            .collect();
+        occupied.sort_unstable();
+        let mut freed = 0;
+        for (_, index) in occupied {
+            if freed >= wanted {
+                break;
+            }
+            freed += self.take_slot(index);
+        }
+        self.report_memory();
+        freed
+    }
+
+    /// 清空一个槽位，返回释放的字节数
+    fn take_slot(&self, index: usize) -> usize {
+        let mut slot = self.lock_slot(index);
+        let removed = slot.take().map_or(0, |entry| entry.approx_bytes());
+        self.approx_bytes.fetch_sub(removed, Ordering::Relaxed);
+        removed
+    }
+
+    fn report_memory(&self) {
+        if let Some(meter) = self.memory.get() {
+            meter.set(self.approx_memory_bytes());
+        }
+    }
+
+    fn collect(&self, filter: impl Fn(&QueryHistoryEntry) -> bool) -> Vec<QueryHistoryEntry> {
+        let mut entries: Vec<QueryHistoryEntry> = (0..self.slots.len())
+            .filter_map(|index| self.lock_slot(index).as_ref().filter(|e| filter(e)).cloned())
+            .collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.sequence));
+        entries
+    }
+
+    fn lock_slot(&self, index: usize) -> MutexGuard<'_, Option<QueryHistoryEntry>> {
+        // 槽位内容总是整体替换，即使持锁线程panic也不会留下半写入的记录
+        self.slots[index].lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(domain: &str) -> QueryHistoryEntry {
+        QueryHistoryEntry {
+            sequence: 0,
+            timestamp: SystemTime::now(),
+            domain: Arc::from(domain),
+            record_type: DnsRecordType::A,
+            strategy: QueryStrategy::Smart,
+            upstream: Some(Arc::from("阿里DNS")),
+            outcome: QueryOutcome::Success { rcode: 0 },
+            duration: Duration::from_millis(12),
+            cache_hit: false,
+            failovers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_zero_capacity_rejected() {
+        assert!(QueryHistory::new(0).is_err());
+    }
+
+    #[test]
+    fn test_capacity_wrap_around() {
+        let history = QueryHistory::new(3).unwrap();
+        for i in 0..5 {
+            history.record(entry(&format!("host{}.example.com", i)));
+        }
+
+        let recent = history.recent(10);
+        let domains: Vec<_> = recent.iter().map(|e| e.domain.as_ref()).collect();
+        assert_eq!(domains, vec!["host4.example.com", "host3.example.com", "host2.example.com"]);
+        assert_eq!(history.recent(1)[0].sequence, 4);
+
+        history.clear();
+        assert!(history.recent(10).is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        let history = Arc::new(QueryHistory::new(64).unwrap());
+        let threads: Vec<_> = (0..8)
+            .map(|t| {
+                let history = history.clone();
+                std::thread::spawn(move || {
+                    for i in 0..200 {
+                        history.record(entry(&format!("t{}-{}.example.com", t, i)));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let recent = history.recent(usize::MAX);
+        assert_eq!(recent.len(), 64);
+        // 每个槽位保存的都是完整记录，且序号互不重复
+        let mut sequences: Vec<_> = recent.iter().map(|e| e.sequence).collect();
+        sequences.dedup();
+        assert_eq!(sequences.len(), 64);
+        assert!(recent.iter().all(|e| e.domain.ends_with(".example.com")));
+        assert!(sequences.iter().all(|s| *s >= 1600 - 64));
+        assert_eq!(history.approx_memory_bytes(), recent.iter().map(QueryHistoryEntry::approx_bytes).sum::<usize>());
+    }
+
+    #[test]
+    fn test_interned_names_shared_and_pruned() {
+        let history = QueryHistory::new(2).unwrap();
+        let first = history.intern("example.com");
+        assert!(Arc::ptr_eq(&first, &history.intern("example.com")));
+
+        // 超过容量两倍时只丢弃不再被引用的名称
+        for i in 0..4 {
+            history.intern(&format!("host{}.example.com", i));
+        }
+        history.intern("other.org");
+        let names = history.names.read().unwrap();
+        assert!(names.contains("example.com"));
+        assert!(!names.contains("host0.example.com"));
+        assert!(names.contains("other.org"));
+    }
+
+    #[test]
+    fn test_filter_by_domain() {
+        let history = QueryHistory::new(10).unwrap();
+        history.record(entry("example.com"));
+        history.record(entry("other.org"));
+        history.record(entry("Example.COM."));
+
+        let matches = history.for_domain("example.com");
+        assert_eq!(matches.len(), 2);
+        assert!(matches[0].sequence > matches[1].sequence);
+        assert!(history.for_domain("missing.net").is_empty());
+    }
+}
@@ -0,0 +1,68 @@
+//! HTTP客户端的DNS解析适配器
+//!
+//! [`HttpResolver`] 让reqwest和hyper-util的 `HttpConnector` 通过 [`SmartDnsResolver`] 解析主机名：
+//! reqwest用 `ClientBuilder::dns_resolver(Arc::new(resolver))`，hyper-util用
+//! `HttpConnector::new_with_resolver(resolver)`。地址来自
+//! [`resolve_with_ttl`](SmartDnsResolver::resolve_with_ttl)（A和AAAA），端口为0，由客户端换成目标端口。
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use hyper_util::client::legacy::connect::dns::Name;
+
+use super::resolver::SmartDnsResolver;
+use crate::error::{DnsError, Result};
+
+/// 以 [`SmartDnsResolver`] 为DNS来源的解析适配器，克隆体共用同一个解析器
+#[derive(Debug, Clone)]
+pub struct HttpResolver {
+    resolver: Arc<SmartDnsResolver>,
+}
+
+impl HttpResolver {
+    /// 包装解析器
+    pub fn new(resolver: Arc<SmartDnsResolver>) -> Self {
+        Self { resolver }
+    }
+
+    /// 被包装的解析器
+    pub fn resolver(&self) -> &Arc<SmartDnsResolver> {
+        &self.resolver
+    }
+}
+
+/// 解析 `host` 的地址，端口为0
+async fn socket_addrs(resolver: Arc<SmartDnsResolver>, host: String) -> Result<std::vec::IntoIter<SocketAddr>> {
+    let resolved = resolver.resolve_with_ttl(&host).await?;
+    Ok(resolved.addresses.into_iter()
+        .map(|ip| SocketAddr::new(ip, 0))
+        .collect::<Vec<_>>()
+        .into_iter())
+}
+
+impl tower_service::Service<Name> for HttpResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = DnsError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        Box::pin(socket_addrs(self.resolver.clone(), name.as_str().to_string()))
+    }
+}
+
+impl reqwest::dns::Resolve for HttpResolver {
+    fn resolve(&self, name: hyper_014::client::connect::dns::Name) -> reqwest::dns::Resolving {
+        let lookup = socket_addrs(self.resolver.clone(), name.as_str().to_string());
+        Box::pin(async move {
+            let addrs: reqwest::dns::Addrs = Box::new(lookup.await?);
+            Ok(addrs)
+        })
+    }
+}
@@ -0,0 +1,340 @@
+//! 复合查询辅助模块
+//!
+//! 本模块提供SRV/MX等需要“二次解析”的查询所用的纯逻辑：
+//! 记录排序、加权随机选择，以及从附加段中提取胶水记录（glue）。
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use rand::Rng;
+
+use crate::types::{RecordData, Response};
+
+/// SRV查询结果中的单个目标
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvTarget {
+    /// 目标主机名
+    pub host: String,
+    /// 服务端口
+    pub port: u16,
+    /// 优先级（越小越优先）
+    pub priority: u16,
+    /// 权重（同优先级内的加权随机依据）
+    pub weight: u16,
+    /// 目标主机解析出的地址
+    pub addresses: Vec<IpAddr>,
+}
+
+/// MX查询结果中的单个邮件交换主机
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MxHost {
+    /// 邮件交换主机名
+    pub exchange: String,
+    /// 优先级（越小越优先）
+    pub priority: u16,
+    /// 邮件交换主机解析出的地址
+    pub addresses: Vec<IpAddr>,
+    /// 是否为隐式MX（域名没有MX记录时按RFC 5321回退到域名自身）
+    pub implicit: bool,
+}
+
+/// MX解析结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MxResolution {
+    /// 按优先级升序排列的邮件交换主机
+    Hosts(Vec<MxHost>),
+    /// 空MX（RFC 7505，"0 ."）：域名明确声明不接收邮件
+    NullMx,
+    /// 域名没有MX记录（且未启用或无法进行隐式MX回退）
+    NoMx,
+}
+
+/// MX解析选项
+#[derive(Debug, Clone, Copy)]
+pub struct MxLookupOptions {
+    /// 没有MX记录时是否回退到域名自身的A/AAAA记录（RFC 5321 第5.1节）
+    pub implicit_mx_fallback: bool,
+    /// 同时进行地址解析的邮件交换主机数上限
+    pub max_concurrency: usize,
+}
+
+impl MxLookupOptions {
+    /// 创建MX解析选项
+    pub fn new(implicit_mx_fallback: bool, max_concurrency: usize) -> Self {
+        Self { implicit_mx_fallback, max_concurrency }
+    }
+}
+
+/// 规范化主机名（小写、去掉末尾的点），用于胶水记录匹配，同 [`normalize_name`](crate::utils::normalize_name)
+pub fn normalize_host(name: &str) -> String {
+    crate::utils::normalize_name(name)
+}
+
+/// 判断目标是否为根域名（"."），SRV/MX中表示服务明确不可用
+pub fn is_root_target(name: &str) -> bool {
+    name.is_empty() || name == "."
+}
+
+/// 从响应的附加段提取胶水记录：主机名 -> A/AAAA地址
+pub fn glue_addresses(response: &Response) -> HashMap<String, Vec<IpAddr>> {
+    let mut glue: HashMap<String, Vec<IpAddr>> = HashMap::new();
+    for record in &response.additionals {
+        let address = match &record.data {
+            RecordData::A(addr) => IpAddr::V4(*addr),
+            RecordData::AAAA(addr) => IpAddr::V6(*addr),
+            _ => continue,
+        };
+        glue.entry(normalize_host(&record.name)).or_default().push(address);
+    }
+    glue
+}
+
+/// 从响应的回答段提取SRV目标（未解析地址）
+pub fn srv_targets(response: &Response) -> Vec<SrvTarget> {
+    response.answers.iter()
+        .filter_map(|record| match &record.data {
+            RecordData::SRV { priority, weight, port, target } => Some(SrvTarget {
+                host: target.clone(),
+                port: *port,
+                priority: *priority,
+                weight: *weight,
+                addresses: Vec::new(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// 从响应的回答段提取MX主机（未解析地址），按优先级升序排列
+///
+/// 排序是稳定的，同优先级的主机保持上游返回的顺序
+pub fn mx_hosts(response: &Response) -> Vec<MxHost> {
+    let mut hosts: Vec<MxHost> = response.answers.iter()
+        .filter_map(|record| match &record.data {
+            RecordData::MX { priority, exchange } => Some(MxHost {
+                exchange: exchange.clone(),
+                priority: *priority,
+                addresses: Vec::new(),
+                implicit: false,
+            }),
+            _ => None,
+        })
+        .collect();
+    hosts.sort_by_key(|h| h.priority);
+    hosts
+}
+
+/// 从响应的回答段提取TXT记录，每条记录的各个字符串连接为一个
+pub fn txt_strings(response: &Response) -> Vec<String> {
+    response.answers.iter()
+        .filter_map(|record| match &record.data {
+            RecordData::TXT(texts) => Some(texts.concat()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// 判断MX记录集是否为空MX（RFC 7505：唯一一条记录，交换主机为 "."）
+pub fn is_null_mx(hosts: &[MxHost]) -> bool {
+    hosts.len() == 1 && is_root_target(&hosts[0].exchange)
+}
+
+/// 按RFC 2782排序SRV目标
+///
+/// 先按优先级升序分组，组内按权重做加权随机排列：权重为0的记录排在组首，
+/// 每一轮以 `weight / 剩余权重和` 的概率选出下一个目标
+pub fn order_srv_targets<R: Rng + ?Sized>(mut targets: Vec<SrvTarget>, rng: &mut R) -> Vec<SrvTarget> {
+    targets.sort_by_key(|t| t.priority);
+
+    let mut ordered = Vec::with_capacity(targets.len());
+    let mut start = 0;
+    while start < targets.len() {
+        let priority = targets[start].priority;
+        let end = targets[start..].iter()
+            .position(|t| t.priority != priority)
+            .map(|p| start + p)
+            .unwrap_or(targets.len());
+
+        let mut group: Vec<SrvTarget> = targets[start..end].to_vec();
+        // RFC 2782: 权重为0的记录放在最前面，使其只有很小的被选中概率
+        group.sort_by_key(|t| t.weight != 0);
+
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|t| t.weight as u32).sum();
+            let index = if total == 0 {
+                0
+            } else {
+                let pick = rng.gen_range(0..=total);
+                let mut running = 0u32;
+                group.iter()
+                    .position(|t| {
+                        running += t.weight as u32;
+                        running >= pick
+                    })
+                    .unwrap_or(group.len() - 1)
+            };
+            ordered.push(group.remove(index));
+        }
+
+        start = end;
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Flags, QClass, Record, RecordType};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use std::net::Ipv4Addr;
+
+    fn target(host: &str, priority: u16, weight: u16) -> SrvTarget {
+        SrvTarget {
+            host: host.to_string(),
+            port: 5269,
+            priority,
+            weight,
+            addresses: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_srv_priority_ordering() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let ordered = order_srv_targets(vec![
+            target("c.example.com", 30, 10),
+            target("a.example.com", 10, 10),
+            target("b.example.com", 20, 10),
+        ], &mut rng);
+
+        let hosts: Vec<_> = ordered.iter().map(|t| t.host.as_str()).collect();
+        assert_eq!(hosts, vec!["a.example.com", "b.example.com", "c.example.com"]);
+    }
+
+    #[test]
+    fn test_srv_weight_distribution() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut heavy_first = 0;
+        let rounds = 10_000;
+        for _ in 0..rounds {
+            let ordered = order_srv_targets(vec![
+                target("light.example.com", 10, 10),
+                target("heavy.example.com", 10, 90),
+            ], &mut rng);
+            if ordered[0].host == "heavy.example.com" {
+                heavy_first += 1;
+            }
+        }
+
+        // 期望约90%，留出统计波动空间
+        let ratio = heavy_first as f64 / rounds as f64;
+        assert!(ratio > 0.85 && ratio < 0.95, "heavy ratio = {}", ratio);
+    }
+
+    #[test]
+    fn test_glue_extraction() {
+        let response = Response {
+            id: 1,
+            flags: Flags::default(),
+            queries: vec![],
+            answers: vec![Record {
+                name: "_xmpp-server._tcp.example.com".to_string(),
+                rtype: RecordType::SRV,
+                class: QClass::IN,
+                ttl: 300,
+                data: RecordData::SRV { priority: 5, weight: 0, port: 5269, target: "XMPP.example.com.".to_string() },
+            }],
+            authorities: vec![],
+            additionals: vec![Record {
+                name: "xmpp.example.com".to_string(),
+                rtype: RecordType::A,
+                class: QClass::IN,
+                ttl: 300,
+                data: RecordData::A(Ipv4Addr::new(192, 0, 2, 10)),
+            }],
+        };
+
+        let targets = srv_targets(&response);
+        assert_eq!(targets.len(), 1);
+        let glue = glue_addresses(&response);
+        assert_eq!(
+            glue.get(&normalize_host(&targets[0].host)),
+            Some(&vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10))])
+        );
+    }
+
+    fn mx_response(records: &[(u16, &str)], glue: &[(&str, Ipv4Addr)]) -> Response {
+        Response {
+            id: 1,
+            flags: Flags::default(),
+            queries: vec![],
+            answers: records.iter().map(|(priority, exchange)| Record {
+                name: "example.com".to_string(),
+                rtype: RecordType::MX,
+                class: QClass::IN,
+                ttl: 300,
+                data: RecordData::MX { priority: *priority, exchange: exchange.to_string() },
+            }).collect(),
+            authorities: vec![],
+            additionals: glue.iter().map(|(name, addr)| Record {
+                name: name.to_string(),
+                rtype: RecordType::A,
+                class: QClass::IN,
+                ttl: 300,
+                data: RecordData::A(*addr),
+            }).collect(),
+        }
+    }
+
+    #[test]
+    fn test_mx_hosts_ordering_and_glue() {
+        let response = mx_response(
+            &[(20, "mx2.example.com"), (10, "mx1.example.com"), (20, "mx3.example.com")],
+            &[("mx1.example.com.", Ipv4Addr::new(192, 0, 2, 25))],
+        );
+
+        let hosts = mx_hosts(&response);
+        let names: Vec<_> = hosts.iter().map(|h| h.exchange.as_str()).collect();
+        assert_eq!(names, vec!["mx1.example.com", "mx2.example.com", "mx3.example.com"]);
+        assert!(!is_null_mx(&hosts));
+
+        let glue = glue_addresses(&response);
+        assert_eq!(
+            glue.get(&normalize_host(&hosts[0].exchange)),
+            Some(&vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 25))])
+        );
+        assert!(glue.get(&normalize_host(&hosts[1].exchange)).is_none());
+    }
+
+    #[test]
+    fn test_null_mx() {
+        let hosts = mx_hosts(&mx_response(&[(0, ".")], &[]));
+        assert!(is_null_mx(&hosts));
+
+        // 空MX与其他MX记录混用不符合RFC 7505，不视为空MX
+        let hosts = mx_hosts(&mx_response(&[(0, "."), (10, "mx.example.com")], &[]));
+        assert!(!is_null_mx(&hosts));
+    }
+
+    #[test]
+    fn test_no_mx_records() {
+        let mut response = mx_response(&[], &[]);
+        response.answers.push(Record {
+            name: "example.com".to_string(),
+            rtype: RecordType::A,
+            class: QClass::IN,
+            ttl: 300,
+            data: RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        });
+
+        assert!(mx_hosts(&response).is_empty());
+    }
+
+    #[test]
+    fn test_root_target() {
+        assert!(is_root_target("."));
+        assert!(is_root_target(""));
+        assert!(!is_root_target("mail.example.com"));
+    }
+}
@@ -2,7 +2,11 @@
 //! 
 //! 本模块提供DNS上游服务器的性能指标收集、统计和分析功能
 
-use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::time::{Instant, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use super::histogram::LatencyHistogram;
 
 /// 上游服务器性能指标
 #[derive(Debug, Clone)]
@@ -28,8 +32,14 @@ pub struct PerformanceMetrics {
     /// 最后失败时间
     pub last_failure_time: Option<Instant>,
     
-    /// CDN准确性评分 (0.0-1.0)
+    /// CDN准确性评分 (0.0-1.0)，只由CDN探测更新；没有探测结果时保持默认值
     pub cdn_accuracy_score: f64,
+    
+    /// 计入CDN准确性评分的探测次数
+    pub cdn_checks: u64,
+    
+    /// 成功查询的延迟分布（只反映本进程内的样本，不写入快照）
+    pub latency_histogram: LatencyHistogram,
 }
 
 impl Default for PerformanceMetrics {
@@ -43,6 +53,8 @@ impl Default for PerformanceMetrics {
             last_success_time: None,
             last_failure_time: None,
             cdn_accuracy_score: 0.8, // 默认80%准确率
+            cdn_checks: 0,
+            latency_histogram: LatencyHistogram::new(),
         }
     }
 }
@@ -85,11 +97,19 @@ impl PerformanceMetrics {
     }
     
     /// 更新成功查询指标
-    pub fn record_success(&mut self, latency: Duration, cdn_accurate: bool) {
+    /// 
+    /// `cdn_accurate` 只有CDN探测才会给出；普通查询传 `None`，不影响CDN准确性评分
+    pub fn record_success(&mut self, latency: Duration, cdn_accurate: Option<bool>) {
+        self.record_success_at(latency, cdn_accurate, Instant::now());
+    }
+    
+    /// 更新成功查询指标，成功时间记为 `now`（由注入的时间源给出）
+    pub fn record_success_at(&mut self, latency: Duration, cdn_accurate: Option<bool>, now: Instant) {
         self.total_queries += 1;
         self.successful_queries += 1;
         self.consecutive_failures = 0;
-        self.last_success_time = Some(Instant::now());
+        self.last_success_time = Some(now);
+        self.latency_histogram.record(latency);
         
         // 更新平均延迟（指数移动平均）
         if self.total_queries == 1 {
@@ -102,18 +122,30 @@ impl PerformanceMetrics {
             self.avg_latency = Duration::from_millis(smoothed_latency_ms as u64);
         }
         
-        // 更新CDN准确性评分
-        let current_score = self.cdn_accuracy_score * (self.successful_queries - 1) as f64;
-        let new_score = if cdn_accurate { 1.0 } else { 0.0 };
-        self.cdn_accuracy_score = (current_score + new_score) / self.successful_queries as f64;
+        if let Some(accurate) = cdn_accurate {
+            self.record_cdn_check(accurate);
+        }
+    }
+    
+    /// 记录一次CDN探测结果，评分为全部探测结果的平均值（首次探测取代默认值）
+    pub fn record_cdn_check(&mut self, accurate: bool) {
+        let new_score = if accurate { 1.0 } else { 0.0 };
+        let current_score = self.cdn_accuracy_score * self.cdn_checks as f64;
+        self.cdn_checks += 1;
+        self.cdn_accuracy_score = (current_score + new_score) / self.cdn_checks as f64;
     }
     
     /// 更新失败查询指标
     pub fn record_failure(&mut self) {
+        self.record_failure_at(Instant::now());
+    }
+    
+    /// 更新失败查询指标，失败时间记为 `now`（由注入的时间源给出）
+    pub fn record_failure_at(&mut self, now: Instant) {
         self.total_queries += 1;
         self.failed_queries += 1;
         self.consecutive_failures += 1;
-        self.last_failure_time = Some(Instant::now());
+        self.last_failure_time = Some(now);
     }
     
     /// 重置指标
@@ -145,4 +177,199 @@ impl PerformanceMetrics {
             + cdn_score * cdn_weight
             + availability_score * availability_weight
     }
-}
\ No newline at end of file
+}
+/// 快照衰减周期：快照每陈旧一个周期，导入时置信度（查询计数与连续失败次数）减半
+pub const METRICS_SNAPSHOT_DECAY_PERIOD: Duration = Duration::from_secs(3600);
+
+/// 单个上游服务器的可序列化性能指标
+///
+/// `Instant` 无法跨进程持久化，因此时间字段以 Unix 毫秒（墙钟时间）保存
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SerializedPerformanceMetrics {
+    /// 总查询次数
+    pub total_queries: u64,
+    /// 成功查询次数
+    pub successful_queries: u64,
+    /// 失败查询次数
+    pub failed_queries: u64,
+    /// 连续失败次数
+    pub consecutive_failures: u32,
+    /// 平均延迟（毫秒）
+    pub avg_latency_ms: u64,
+    /// 最后成功时间（Unix 毫秒）
+    pub last_success_unix_ms: Option<u64>,
+    /// 最后失败时间（Unix 毫秒）
+    pub last_failure_unix_ms: Option<u64>,
+    /// CDN准确性评分
+    pub cdn_accuracy_score: f64,
+    /// 计入CDN准确性评分的探测次数（旧快照没有该字段时为0）
+    #[serde(default)]
+    pub cdn_checks: u64,
+}
+
+/// 决策引擎性能指标快照
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SerializedMetrics {
+    /// 快照生成时间（Unix 毫秒）
+    pub snapshot_unix_ms: u64,
+    /// 按上游名称索引的性能指标
+    pub upstreams: HashMap<String, SerializedPerformanceMetrics>,
+}
+
+impl SerializedMetrics {
+    /// 从JSON文本解析快照
+    pub fn from_json(json: &str) -> crate::Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| crate::DnsError::Parse(format!("Invalid metrics snapshot: {}", e)))
+    }
+
+    /// 序列化为JSON文本
+    pub fn to_json(&self) -> crate::Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| crate::DnsError::Parse(format!("Failed to serialize metrics snapshot: {}", e)))
+    }
+
+    /// 快照相对于给定墙钟时间的年龄
+    pub fn age_at(&self, now: SystemTime) -> Duration {
+        let now_ms = unix_millis(now);
+        Duration::from_millis(now_ms.saturating_sub(self.snapshot_unix_ms))
+    }
+}
+
+/// 将墙钟时间转换为 Unix 毫秒
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// 把单调时钟时间点换算成墙钟时间（Unix 毫秒）
+fn instant_to_unix_ms(instant: Instant, now_instant: Instant, now_unix_ms: u64) -> u64 {
+    let elapsed = now_instant.saturating_duration_since(instant).as_millis() as u64;
+    now_unix_ms.saturating_sub(elapsed)
+}
+
+/// 把墙钟时间（Unix 毫秒）换算回单调时钟时间点，无法表示时返回 None
+fn unix_ms_to_instant(unix_ms: u64, now_instant: Instant, now_unix_ms: u64) -> Option<Instant> {
+    let elapsed = Duration::from_millis(now_unix_ms.saturating_sub(unix_ms));
+    now_instant.checked_sub(elapsed)
+}
+
+impl PerformanceMetrics {
+    /// 转换为可序列化形式
+    pub fn to_serialized(&self, now_instant: Instant, now_unix_ms: u64) -> SerializedPerformanceMetrics {
+        SerializedPerformanceMetrics {
+            total_queries: self.total_queries,
+            successful_queries: self.successful_queries,
+            failed_queries: self.failed_queries,
+            consecutive_failures: self.consecutive_failures,
+            avg_latency_ms: self.avg_latency.as_millis() as u64,
+            last_success_unix_ms: self.last_success_time
+                .map(|t| instant_to_unix_ms(t, now_instant, now_unix_ms)),
+            last_failure_unix_ms: self.last_failure_time
+                .map(|t| instant_to_unix_ms(t, now_instant, now_unix_ms)),
+            cdn_accuracy_score: self.cdn_accuracy_score,
+            cdn_checks: self.cdn_checks,
+        }
+    }
+
+    /// 从可序列化形式恢复，并按快照年龄做衰减
+    ///
+    /// 每经过一个 [`METRICS_SNAPSHOT_DECAY_PERIOD`]，计数和连续失败次数减半，
+    /// 避免陈旧快照长期左右选择结果（例如把早已恢复的服务器一直判定为不可用）
+    pub fn from_serialized(
+        data: &SerializedPerformanceMetrics,
+        snapshot_age: Duration,
+        now_instant: Instant,
+        now_unix_ms: u64,
+    ) -> Self {
+        let halvings = (snapshot_age.as_secs() / METRICS_SNAPSHOT_DECAY_PERIOD.as_secs()).min(63) as u32;
+        let decay_u64 = |v: u64| v >> halvings;
+        let consecutive_failures = if halvings >= 32 { 0 } else { data.consecutive_failures >> halvings };
+
+        let successful_queries = decay_u64(data.successful_queries);
+        let failed_queries = decay_u64(data.failed_queries);
+
+        Self {
+            total_queries: successful_queries + failed_queries,
+            successful_queries,
+            failed_queries,
+            consecutive_failures,
+            avg_latency: Duration::from_millis(data.avg_latency_ms),
+            last_success_time: data.last_success_unix_ms
+                .and_then(|t| unix_ms_to_instant(t, now_instant, now_unix_ms)),
+            last_failure_time: data.last_failure_unix_ms
+                .and_then(|t| unix_ms_to_instant(t, now_instant, now_unix_ms)),
+            cdn_accuracy_score: data.cdn_accuracy_score.clamp(0.0, 1.0),
+            cdn_checks: decay_u64(data.cdn_checks),
+            latency_histogram: LatencyHistogram::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> SerializedPerformanceMetrics {
+        SerializedPerformanceMetrics {
+            total_queries: 40,
+            successful_queries: 30,
+            failed_queries: 10,
+            consecutive_failures: 12,
+            avg_latency_ms: 25,
+            last_success_unix_ms: Some(1_000_000),
+            last_failure_unix_ms: None,
+            cdn_accuracy_score: 0.9,
+            cdn_checks: 4,
+        }
+    }
+
+    #[test]
+    fn test_fresh_snapshot_not_decayed() {
+        let now = Instant::now();
+        let metric = PerformanceMetrics::from_serialized(&sample(), Duration::from_secs(60), now, 2_000_000);
+        assert_eq!(metric.total_queries, 40);
+        assert_eq!(metric.consecutive_failures, 12);
+        assert_eq!(metric.avg_latency, Duration::from_millis(25));
+        assert!(!metric.is_available());
+    }
+
+    #[test]
+    fn test_old_snapshot_decayed() {
+        let now = Instant::now();
+        let metric = PerformanceMetrics::from_serialized(&sample(), Duration::from_secs(3600 * 2), now, 2_000_000);
+        assert_eq!(metric.successful_queries, 7);
+        assert_eq!(metric.failed_queries, 2);
+        assert_eq!(metric.total_queries, 9);
+        assert_eq!(metric.consecutive_failures, 3);
+        assert!(metric.is_available());
+    }
+
+    #[test]
+    fn test_snapshot_json_round_trip() {
+        let mut upstreams = HashMap::new();
+        upstreams.insert("dns1".to_string(), sample());
+        let snapshot = SerializedMetrics { snapshot_unix_ms: 2_000_000, upstreams };
+
+        let json = snapshot.to_json().unwrap();
+        assert_eq!(SerializedMetrics::from_json(&json).unwrap(), snapshot);
+        assert!(SerializedMetrics::from_json("{not json").is_err());
+    }
+
+    #[test]
+    fn test_cdn_score_only_changes_on_checks() {
+        let mut metric = PerformanceMetrics::new();
+        metric.record_success(Duration::from_millis(10), None);
+        assert_eq!(metric.cdn_accuracy_score, 0.8);
+        assert_eq!(metric.cdn_checks, 0);
+
+        metric.record_success(Duration::from_millis(10), Some(false));
+        assert_eq!(metric.cdn_accuracy_score, 0.0);
+        metric.record_cdn_check(true);
+        metric.record_cdn_check(true);
+        metric.record_cdn_check(true);
+        assert_eq!(metric.cdn_checks, 4);
+        assert!((metric.cdn_accuracy_score - 0.75).abs() < 1e-9);
+    }
+}
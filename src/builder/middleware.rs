@@ -0,0 +1,341 @@
+//! 查询中间件
+//!
+//! 中间件按注册顺序包在解析过程外层：每个中间件收到请求和 [`Next`]，调用 `next.run(request)`
+//! 把请求交给后面的中间件，链的末端是实际的解析（域名转发、缓存、上游查询）。中间件可以在调用前
+//! 改写请求、调用后处理响应，也可以不调用 `next` 直接应答。请求的 `context` 带有调用方的租户等信息，
+//! 可用于按租户的策略（例如各租户不同的拦截列表）。
+//!
+//! 直接应答或返回错误的中间件所处理的查询不经过解析，不写入查询历史，也不计入性能指标。
+//! 中间件panic时该次查询以 [`DnsError::Server`] 失败，不影响其他查询。没有注册中间件时查询不经过中间件链。
+
+use std::any::Any;
+use std::fmt::Debug;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use crate::time::Instant;
+
+use async_trait::async_trait;
+use futures::FutureExt;
+
+use super::resolver::{ActiveResolver, SmartDnsResolver};
+use super::types::{DnsQueryRequest, DnsQueryResponse};
+use crate::error::{DnsError, Result};
+use crate::utils::normalize_name;
+use crate::{dns_debug, dns_error, dns_info, dns_warn};
+
+/// 查询中间件
+#[async_trait]
+pub trait QueryMiddleware: Send + Sync + Debug {
+    /// 处理一次查询，调用 `next.run(request)` 继续解析
+    async fn handle(&self, request: DnsQueryRequest, next: Next<'_>) -> Result<DnsQueryResponse>;
+
+    /// 日志和错误信息中使用的名称
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// 中间件链中尚未执行的部分
+pub struct Next<'a> {
+    resolver: &'a SmartDnsResolver,
+    /// 查询开始时的底层解析器和配置
+    active: &'a ActiveResolver,
+    chain: &'a [Arc<dyn QueryMiddleware>],
+    /// 解析出错时的原始错误，供 `lookup_ip` 等按错误类型区分结果
+    error: &'a Mutex<Option<DnsError>>,
+}
+
+impl<'a> Next<'a> {
+    pub(super) fn new(
+        resolver: &'a SmartDnsResolver,
+        active: &'a ActiveResolver,
+        chain: &'a [Arc<dyn QueryMiddleware>],
+        error: &'a Mutex<Option<DnsError>>,
+    ) -> Self {
+        Self { resolver, active, chain, error }
+    }
+
+    /// 把请求交给下一个中间件，没有下一个时执行解析
+    ///
+    /// 解析失败时返回 `success` 为 `false` 的响应，与 [`SmartDnsResolver::query`] 相同
+    pub async fn run(self, request: DnsQueryRequest) -> Result<DnsQueryResponse> {
+        let Some((middleware, rest)) = self.chain.split_first() else {
+            let (response, error) = self.resolver.resolve_request(self.active, request).await;
+            *self.error.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = error;
+            return Ok(response);
+        };
+
+        let next = Next { chain: rest, ..self };
+        match AssertUnwindSafe(middleware.handle(request, next)).catch_unwind().await {
+            Ok(result) => result,
+            Err(panic) => {
+                dns_error!("中间件 {} panic: {}", middleware.name(), panic_message(panic.as_ref()));
+                Err(DnsError::Server(format!("Middleware '{}' panicked", middleware.name())))
+            }
+        }
+    }
+}
+
+/// panic携带的信息
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic.downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic payload>")
+}
+
+/// 记录每次查询及其结果和耗时的中间件
+#[derive(Debug, Default, Clone)]
+pub struct LoggingMiddleware;
+
+impl LoggingMiddleware {
+    /// 创建日志中间件
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl QueryMiddleware for LoggingMiddleware {
+    async fn handle(&self, request: DnsQueryRequest, next: Next<'_>) -> Result<DnsQueryResponse> {
+        let start = Instant::now();
+        let (domain, record_type) = (request.domain.clone(), request.record_type);
+        dns_debug!("➡️ 查询 {} {}", domain, record_type.as_str());
+        let result = next.run(request).await;
+        match &result {
+            Ok(response) if response.success => dns_info!(
+                "⬅️ {} {}: {} 条记录，来源 {}，耗时 {:?}",
+                domain, record_type.as_str(), response.records.len(),
+                response.server_used.as_deref().unwrap_or("-"), start.elapsed()
+            ),
+            Ok(response) => dns_warn!(
+                "⬅️ {} {} 失败: {}，耗时 {:?}",
+                domain, record_type.as_str(), response.error.as_deref().unwrap_or("-"), start.elapsed()
+            ),
+            Err(e) => dns_warn!("⬅️ {} {} 出错: {}，耗时 {:?}", domain, record_type.as_str(), e, start.elapsed()),
+        }
+        result
+    }
+
+    fn name(&self) -> &str {
+        "logging"
+    }
+}
+
+/// 把查询的域名改写为另一个域名再解析的中间件
+///
+/// 规则按最长后缀匹配：`old.example` → `new.example` 同样把 `a.old.example` 改写为 `a.new.example`。
+/// 响应中的 `domain` 和名称为改写后域名的记录恢复为原域名，调用方看不到改写
+#[derive(Debug, Default, Clone)]
+pub struct DomainRewriteMiddleware {
+    /// （原域名后缀, 改写后的后缀），小写、不含末尾的点
+    rules: Vec<(String, String)>,
+}
+
+impl DomainRewriteMiddleware {
+    /// 创建没有规则的改写中间件
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加改写规则：`from` 及其子域名改写到 `to` 之下
+    pub fn rewrite(mut self, from: &str, to: &str) -> Self {
+        self.rules.push((normalize_name(from), normalize_name(to)));
+        self
+    }
+
+    /// 按最长后缀匹配改写域名，没有匹配的规则时返回 `None`
+    pub fn rewritten(&self, domain: &str) -> Option<String> {
+        let domain = normalize_name(domain);
+        self.rules.iter()
+            .filter_map(|(from, to)| {
+                let prefix = if domain == *from {
+                    ""
+                } else {
+                    domain.strip_suffix(from.as_str())?.strip_suffix('.')?
+                };
+                Some((from.len(), prefix, to))
+            })
+            .max_by_key(|(len, _, _)| *len)
+            .map(|(_, prefix, to)| if prefix.is_empty() { to.clone() } else { format!("{}.{}", prefix, to) })
+    }
+}
+
+#[async_trait]
+impl QueryMiddleware for DomainRewriteMiddleware {
+    async fn handle(&self, mut request: DnsQueryRequest, next: Next<'_>) -> Result<DnsQueryResponse> {
+        let Some(target) = self.rewritten(&request.domain) else {
+            return next.run(request).await;
+        };
+        dns_debug!("域名改写: {} -> {}", request.domain, target);
+        let original = std::mem::replace(&mut request.domain, target.clone());
+        let mut response = next.run(request).await?;
+        for record in &mut response.records {
+            if normalize_name(&record.name) == target {
+                record.name = original.clone();
+            }
+        }
+        response.domain = original;
+        Ok(response)
+    }
+
+    fn name(&self) -> &str {
+        "domain-rewrite"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::types::{DnsRecord, DnsRecordType};
+    use crate::builder::{DnsResolverBuilder, QueryStrategy};
+    use crate::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    /// 在调用 `next` 前后记下自己名称的中间件，`blocked` 为真时直接应答 `blocked.test`
+    #[derive(Debug)]
+    struct Recorder {
+        label: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+        blocks: bool,
+    }
+
+    #[async_trait]
+    impl QueryMiddleware for Recorder {
+        async fn handle(&self, request: DnsQueryRequest, next: Next<'_>) -> Result<DnsQueryResponse> {
+            if self.blocks && request.domain == "blocked.test" {
+                self.log.lock().unwrap().push(format!("{}!", self.label));
+                return Ok(DnsQueryResponse::local_answer(&request, vec![
+                    DnsRecord::a(request.domain.clone(), Ipv4Addr::UNSPECIFIED, 60),
+                ]));
+            }
+            self.log.lock().unwrap().push(format!("{}>", self.label));
+            let response = next.run(request).await;
+            self.log.lock().unwrap().push(format!("<{}", self.label));
+            response
+        }
+    }
+
+    /// 只对指定租户拦截 `blocked.test` 的中间件，以NXDOMAIN应答
+    #[derive(Debug)]
+    struct TenantBlocklist {
+        tenant: &'static str,
+    }
+
+    #[async_trait]
+    impl QueryMiddleware for TenantBlocklist {
+        async fn handle(&self, request: DnsQueryRequest, next: Next<'_>) -> Result<DnsQueryResponse> {
+            if request.tenant() == Some(self.tenant) && request.domain == "blocked.test" {
+                let mut response = DnsQueryResponse::local_answer(&request, Vec::new());
+                response.rcode = Some(3);
+                return Ok(response);
+            }
+            next.run(request).await
+        }
+    }
+
+    #[derive(Debug)]
+    struct Panicking;
+
+    #[async_trait]
+    impl QueryMiddleware for Panicking {
+        async fn handle(&self, _request: DnsQueryRequest, _next: Next<'_>) -> Result<DnsQueryResponse> {
+            panic!("middleware bug");
+        }
+    }
+
+    fn upstream() -> MockTransport {
+        MockTransport::new()
+            .with_a("svc.test", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+            .with_a("svc.new.test", &[Ipv4Addr::new(192, 0, 2, 2)], 300)
+    }
+
+    fn builder(mock: MockTransport) -> DnsResolverBuilder {
+        DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .add_mock_upstream("mock", mock)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_chain_runs_in_order_and_short_circuits() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let recorder = |label, blocks| Arc::new(Recorder { label, log: log.clone(), blocks });
+        let mock = upstream();
+        let resolver = builder(mock.clone())
+            .with_middleware(recorder("a", false))
+            .with_middleware(recorder("b", true))
+            .with_middleware(recorder("c", false))
+            .build()
+            .await
+            .unwrap();
+
+        let response = resolver.query(DnsQueryRequest::new("svc.test", DnsRecordType::A)).await.unwrap();
+        assert!(response.success);
+        assert_eq!(*log.lock().unwrap(), ["a>", "b>", "c>", "<c", "<b", "<a"]);
+        assert_eq!(mock.call_count(), 1);
+
+        log.lock().unwrap().clear();
+        let addresses = resolver.lookup_ip("blocked.test", DnsRecordType::A).await.unwrap();
+        assert_eq!(addresses, vec![std::net::IpAddr::from(Ipv4Addr::UNSPECIFIED)]);
+        assert_eq!(*log.lock().unwrap(), ["a>", "b!", "<a"]);
+        assert_eq!(mock.call_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_middleware_fails_only_that_query() {
+        let resolver = builder(upstream())
+            .with_middleware(Arc::new(LoggingMiddleware::new()))
+            .with_middleware(Arc::new(Panicking))
+            .build()
+            .await
+            .unwrap();
+
+        let response = resolver.query(DnsQueryRequest::new("svc.test", DnsRecordType::A)).await.unwrap();
+        assert!(!response.success);
+        assert!(response.error.unwrap().contains("panicked"));
+        assert!(matches!(resolver.lookup_ip("svc.test", DnsRecordType::A).await, Err(DnsError::Server(_))));
+    }
+
+    #[tokio::test]
+    async fn test_domain_rewrite_is_invisible_to_caller() {
+        let rewrite = DomainRewriteMiddleware::new().rewrite("old.test", "new.test").rewrite("test", "other");
+        assert_eq!(rewrite.rewritten("svc.OLD.test.").as_deref(), Some("svc.new.test"));
+        assert_eq!(rewrite.rewritten("fold.test").as_deref(), Some("fold.other"));
+        assert_eq!(rewrite.rewritten("example.com"), None);
+
+        let resolver = builder(upstream())
+            .with_middleware(Arc::new(DomainRewriteMiddleware::new().rewrite("old.test", "new.test")))
+            .build()
+            .await
+            .unwrap();
+        let response = resolver.query(DnsQueryRequest::new("svc.old.test", DnsRecordType::A)).await.unwrap();
+        assert_eq!(response.domain, "svc.old.test");
+        assert_eq!(response.records[0].name, "svc.old.test");
+        assert_eq!(response.ip_addresses(), vec![std::net::IpAddr::from(Ipv4Addr::new(192, 0, 2, 2))]);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_applies_per_tenant_policy() {
+        use crate::builder::types::QueryContext;
+
+        let mock = upstream().with_a("blocked.test", &[Ipv4Addr::new(192, 0, 2, 9)], 300);
+        let resolver = builder(mock.clone())
+            .with_middleware(Arc::new(TenantBlocklist { tenant: "kids" }))
+            .build()
+            .await
+            .unwrap();
+
+        let blocked = DnsQueryRequest::new("blocked.test", DnsRecordType::A).with_context(QueryContext::for_tenant("kids"));
+        let response = resolver.query(blocked).await.unwrap();
+        assert_eq!(response.rcode, Some(3));
+        assert_eq!(response.context, Some(QueryContext::for_tenant("kids")));
+        assert_eq!(mock.call_count(), 0);
+
+        let allowed = DnsQueryRequest::new("blocked.test", DnsRecordType::A).with_context(QueryContext::for_tenant("staff"));
+        let response = resolver.query(allowed).await.unwrap();
+        assert_eq!(response.ip_addresses(), vec![std::net::IpAddr::from(Ipv4Addr::new(192, 0, 2, 9))]);
+        assert_eq!(response.context.unwrap().tenant.as_deref(), Some("staff"));
+        assert_eq!(mock.call_count(), 1);
+    }
+}
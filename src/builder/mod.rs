@@ -9,18 +9,75 @@
 
 pub mod strategy;
 pub mod metrics;
+pub mod histogram;
+pub mod adaptive_timeout;
+pub mod emergency;
 pub mod engine;
+pub mod explain;
 pub mod resolver_builder;
 pub mod resolver;
 pub mod types;
+pub mod preset;
+pub mod lookup;
+pub mod history;
+pub mod consensus;
+pub mod ddr;
+pub mod zone_watch;
+pub mod address_watch;
+pub mod routing;
+pub mod effective_config;
+pub mod middleware;
+pub mod warmup;
+pub mod tenant;
+pub mod cdn_probe;
+pub mod stickiness;
+pub mod diff;
+pub mod encoded;
+pub mod openmetrics;
+pub mod scope;
+pub mod upstream;
+pub mod query_summary;
+pub mod reload;
+#[cfg(feature = "http-resolver")]
+pub mod http_resolver;
+#[cfg(feature = "connect")]
+pub mod connect;
 
 // 重新导出主要类型
 pub use strategy::QueryStrategy;
-pub use metrics::PerformanceMetrics;
+pub use metrics::{PerformanceMetrics, SerializedMetrics, SerializedPerformanceMetrics};
+pub use histogram::{LatencyHistogram, LatencyPercentiles};
+pub use adaptive_timeout::AdaptiveTimeoutConfig;
+pub use emergency::{EmergencyMonitor, EmergencyPolicy, EmergencyState, ResolverMode};
 pub use engine::SmartDecisionEngine;
+pub use explain::{CandidateExplanation, ExclusionReason, ScoreBreakdown, SelectionExplanation, SelectionReason};
 pub use resolver_builder::{DnsResolverBuilder, LoggerInitStrategy};
 pub use resolver::SmartDnsResolver;
 pub use types::*;
+pub use preset::Preset;
+pub use lookup::{MxHost, MxLookupOptions, MxResolution, SrvTarget};
+pub use history::{QueryHistory, QueryHistoryEntry, QueryOutcome};
+pub use consensus::{ConsensusSummary, Dissent, PerUpstreamAnswer, QueryAllReport};
+pub use zone_watch::{serial_is_newer, SoaChange, ZoneWatcher};
+pub use address_watch::{AddressChange, AddressEvent, AddressWatch};
+pub use routing::{DomainRoute, DomainRouter};
+pub use effective_config::{DryRunReport, EffectiveConfig, EffectiveUpstream, ResolverSettings};
+pub use middleware::{DomainRewriteMiddleware, LoggingMiddleware, Next, QueryMiddleware};
+pub use warmup::{load_warmup_list, parse_warmup_list, WarmOptions, WarmReport};
+pub use tenant::{TenantStats, OTHER_TENANTS};
+pub use cdn_probe::{CdnProbe, IpCidr, DEFAULT_CDN_PROBE_INTERVAL};
+pub use stickiness::{StickinessConfig, StickinessStats, StickyKey, DEFAULT_STICKY_CAPACITY};
+pub use diff::{DnsDiff, DnsDiffReport, RcodeChange, RecordValueChange, TtlChange};
+pub use encoded::EncodedRequestLimits;
+pub use openmetrics::{LATENCY_BUCKETS_SECONDS, OPENMETRICS_CONTENT_TYPE};
+pub use scope::{DnsLookup, ScopeOverrides, ScopedResolver};
+pub use upstream::Upstream;
+pub use reload::{ReloadReport, RELOADABLE_SETTINGS};
+#[cfg(feature = "http-resolver")]
+pub use http_resolver::HttpResolver;
+#[cfg(feature = "connect")]
+pub use connect::{connect_with_resolver, ConnectOptions};
+pub use ddr::{DdrOptions, DdrReport, DesignatedProtocol, DesignatedResolver, DesignationVerifier, SvcbRecord, TlsDesignationVerifier};
 
 // 为了向后兼容，保持原有的导出
 pub use resolver_builder::DnsResolverBuilder as Builder;
@@ -0,0 +1,422 @@
+//! OpenMetrics文本格式导出
+//!
+//! [`SmartDnsResolver::render_openmetrics`](super::SmartDnsResolver::render_openmetrics) 的输出可以直接作为
+//! Prometheus等采集端的抓取响应，响应头使用 [`OPENMETRICS_CONTENT_TYPE`]。指标名和标签名是兼容性约定，
+//! 只会新增、不会改名：
+//!
+//! | 指标 | 类型 | 标签 | 含义 |
+//! |------|------|------|------|
+//! | `rat_quickdns_queries_total` | counter | `record_type`, `outcome` | 查询次数（含缓存应答），`outcome` 为小写的响应码名称（`noerror`、`nxdomain`、`servfail` 等）或 `failed` |
+//! | `rat_quickdns_upstream_queries_total` | counter | `upstream`, `result` | 各上游的查询次数，`result` 为 `success` 或 `failure` |
+//! | `rat_quickdns_upstream_latency_seconds` | histogram | `upstream` | 各上游的查询延迟，桶边界见 [`LATENCY_BUCKETS_SECONDS`] |
+//! | `rat_quickdns_upstream_state` | gauge | `upstream`, `state` | 上游当前状态为 `state`（`available`、`unavailable`、`quarantined`）时为1，否则为0 |
+//! | `rat_quickdns_cache_hits_total` | counter | | 缓存命中次数 |
+//! | `rat_quickdns_cache_misses_total` | counter | | 缓存未命中次数 |
+//! | `rat_quickdns_cache_evictions_total` | counter | | 缓存过期清理次数 |
+//! | `rat_quickdns_cache_entries` | gauge | | 当前缓存条目数 |
+//! | `rat_quickdns_emergency_mode` | gauge | | 处于应急模式时为1 |
+//! | `rat_quickdns_offline` | gauge | | 处于离线模式时为1 |
+//! | `rat_quickdns_in_flight_sends` | gauge | | 正在发往上游的查询数 |
+//!
+//! 未启用缓存时不输出缓存指标。上游的查询次数和延迟来自决策引擎的性能指标，关闭统计时保持为0。
+//!
+//! 列入 [`with_metrics_upstream_labels`](super::DnsResolverBuilder::with_metrics_upstream_labels) 的上游标签键
+//! 作为额外的标签出现在各 `rat_quickdns_upstream_*` 指标上（排在 `upstream` 之后），上游没有该标签时取空字符串；
+//! 未列入的标签不输出，默认不输出任何上游标签。
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::histogram::LatencyHistogram;
+use super::types::DnsRecordType;
+use super::QueryOutcome;
+use crate::resolver::cache::CacheStats;
+use crate::types::ResponseCode;
+
+/// OpenMetrics抓取响应的 `Content-Type`
+pub const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// `rat_quickdns_upstream_latency_seconds` 的桶边界（秒），另有 `+Inf` 桶
+///
+/// 由内部的对数直方图换算，每个桶只计入确定不超过边界的样本，可能比精确值少，差距不超过约19%的延迟范围
+pub const LATENCY_BUCKETS_SECONDS: [f64; 13] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// 按记录类型和结果的查询计数
+#[derive(Debug, Default)]
+pub(crate) struct QueryCounters {
+    counts: Mutex<BTreeMap<(&'static str, String), u64>>,
+}
+
+impl QueryCounters {
+    /// 记录一次查询的结果
+    pub(crate) fn record(&self, record_type: DnsRecordType, outcome: &QueryOutcome) {
+        let outcome = match outcome {
+            QueryOutcome::Success { rcode } => ResponseCode::from(*rcode).name().to_ascii_lowercase(),
+            QueryOutcome::Failed => "failed".to_string(),
+        };
+        let mut counts = self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *counts.entry((record_type.as_str(), outcome)).or_default() += 1;
+    }
+
+    /// 当前计数，按记录类型和结果排序
+    pub(crate) fn snapshot(&self) -> Vec<((&'static str, String), u64)> {
+        let counts = self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        counts.iter().map(|(key, count)| (key.clone(), *count)).collect()
+    }
+}
+
+/// 上游在 `rat_quickdns_upstream_state` 中的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UpstreamState {
+    Available,
+    Unavailable,
+    Quarantined,
+}
+
+impl UpstreamState {
+    const ALL: [UpstreamState; 3] = [Self::Available, Self::Unavailable, Self::Quarantined];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Available => "available",
+            Self::Unavailable => "unavailable",
+            Self::Quarantined => "quarantined",
+        }
+    }
+}
+
+/// 单个上游的导出数据
+#[derive(Debug)]
+pub(crate) struct UpstreamSample {
+    pub(crate) name: String,
+    /// 列入白名单的上游标签，按白名单的顺序排在 `upstream` 之后
+    pub(crate) labels: Vec<(String, String)>,
+    pub(crate) successes: u64,
+    pub(crate) failures: u64,
+    pub(crate) latency: LatencyHistogram,
+    pub(crate) state: UpstreamState,
+}
+
+/// 一次导出所需的全部数据，由解析器收集后交给 [`render`]
+#[derive(Debug, Default)]
+pub(crate) struct MetricsSnapshot {
+    pub(crate) queries: Vec<((&'static str, String), u64)>,
+    pub(crate) upstreams: Vec<UpstreamSample>,
+    pub(crate) cache: Option<CacheStats>,
+    pub(crate) emergency_mode: bool,
+    pub(crate) offline: bool,
+    pub(crate) in_flight_sends: usize,
+}
+
+/// 按OpenMetrics文本格式输出快照，以 `# EOF` 结尾
+pub(crate) fn render(snapshot: &MetricsSnapshot) -> String {
+    let mut out = MetricsWriter::default();
+
+    out.family("rat_quickdns_queries", "counter", "DNS queries answered, by record type and outcome.");
+    for ((record_type, outcome), count) in &snapshot.queries {
+        out.sample("rat_quickdns_queries_total", &[("record_type", record_type), ("outcome", outcome)], count);
+    }
+
+    out.family("rat_quickdns_upstream_queries", "counter", "Queries sent to each upstream, by result.");
+    for upstream in &snapshot.upstreams {
+        let labels = upstream.series_labels();
+        out.sample("rat_quickdns_upstream_queries_total", &with(&labels, ("result", "success")), upstream.successes);
+        out.sample("rat_quickdns_upstream_queries_total", &with(&labels, ("result", "failure")), upstream.failures);
+    }
+
+    out.family("rat_quickdns_upstream_latency_seconds", "histogram", "Query latency of each upstream in seconds.");
+    let bounds = LATENCY_BUCKETS_SECONDS.map(Duration::from_secs_f64);
+    for upstream in &snapshot.upstreams {
+        let labels = upstream.series_labels();
+        let counts = upstream.latency.cumulative_counts(&bounds);
+        for (bound, count) in LATENCY_BUCKETS_SECONDS.iter().zip(counts) {
+            out.sample("rat_quickdns_upstream_latency_seconds_bucket", &with(&labels, ("le", &format!("{:?}", bound))), count);
+        }
+        out.sample("rat_quickdns_upstream_latency_seconds_bucket", &with(&labels, ("le", "+Inf")), upstream.latency.count());
+        out.sample("rat_quickdns_upstream_latency_seconds_sum", &labels, format!("{:?}", upstream.latency.sum().as_secs_f64()));
+        out.sample("rat_quickdns_upstream_latency_seconds_count", &labels, upstream.latency.count());
+    }
+
+    out.family("rat_quickdns_upstream_state", "gauge", "1 if the upstream is currently in the labelled state, 0 otherwise.");
+    for upstream in &snapshot.upstreams {
+        let labels = upstream.series_labels();
+        for state in UpstreamState::ALL {
+            out.sample("rat_quickdns_upstream_state", &with(&labels, ("state", state.label())), u8::from(upstream.state == state));
+        }
+    }
+
+    if let Some(cache) = &snapshot.cache {
+        out.family("rat_quickdns_cache_hits", "counter", "Cache lookups answered from the cache.");
+        out.sample("rat_quickdns_cache_hits_total", &[], cache.hits);
+        out.family("rat_quickdns_cache_misses", "counter", "Cache lookups that found no usable entry.");
+        out.sample("rat_quickdns_cache_misses_total", &[], cache.misses);
+        out.family("rat_quickdns_cache_evictions", "counter", "Cache entries removed after expiry.");
+        out.sample("rat_quickdns_cache_evictions_total", &[], cache.evictions);
+        out.family("rat_quickdns_cache_entries", "gauge", "Entries currently held in the cache.");
+        out.sample("rat_quickdns_cache_entries", &[], cache.current_size);
+    }
+
+    out.family("rat_quickdns_emergency_mode", "gauge", "1 while the resolver is in emergency mode.");
+    out.sample("rat_quickdns_emergency_mode", &[], u8::from(snapshot.emergency_mode));
+    out.family("rat_quickdns_offline", "gauge", "1 while the resolver is offline and answers only from the cache.");
+    out.sample("rat_quickdns_offline", &[], u8::from(snapshot.offline));
+    out.family("rat_quickdns_in_flight_sends", "gauge", "Queries currently being sent to upstreams.");
+    out.sample("rat_quickdns_in_flight_sends", &[], snapshot.in_flight_sends);
+
+    out.finish()
+}
+
+impl UpstreamSample {
+    /// 该上游各序列共有的标签：`upstream` 和列入白名单的上游标签
+    fn series_labels(&self) -> Vec<(&str, &str)> {
+        std::iter::once(("upstream", self.name.as_str()))
+            .chain(self.labels.iter().map(|(key, value)| (key.as_str(), value.as_str())))
+            .collect()
+    }
+}
+
+/// 在共有标签后追加一个标签
+fn with<'a>(labels: &[(&'a str, &'a str)], extra: (&'a str, &'a str)) -> Vec<(&'a str, &'a str)> {
+    labels.iter().copied().chain(std::iter::once(extra)).collect()
+}
+
+/// 转义标签值中的反斜杠、双引号和换行
+fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Default)]
+struct MetricsWriter {
+    out: String,
+}
+
+impl MetricsWriter {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    fn finish(mut self) -> String {
+        self.out.push_str("# EOF\n");
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// 解析出的一个样本：(样本名, 标签, 值)
+    type Sample = (String, Vec<(String, String)>, f64);
+
+    fn upstream(name: &str, latencies_ms: &[u64], state: UpstreamState) -> UpstreamSample {
+        let latency = LatencyHistogram::new();
+        for ms in latencies_ms {
+            latency.record(Duration::from_millis(*ms));
+        }
+        UpstreamSample { name: name.to_string(), labels: Vec::new(), successes: latencies_ms.len() as u64, failures: 1, latency, state }
+    }
+
+    /// 解析标签部分，返回未转义的标签值；格式不对时panic
+    fn parse_labels(text: &str) -> Vec<(String, String)> {
+        let mut labels = Vec::new();
+        let mut chars = text.chars().peekable();
+        while chars.peek().is_some() {
+            let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+            assert!(!key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'), "bad label name {:?}", key);
+            assert_eq!(chars.next(), Some('"'));
+            let mut value = String::new();
+            loop {
+                match chars.next().expect("unterminated label value") {
+                    '"' => break,
+                    '\\' => match chars.next() {
+                        Some('\\') => value.push('\\'),
+                        Some('"') => value.push('"'),
+                        Some('n') => value.push('\n'),
+                        other => panic!("bad escape {:?}", other),
+                    },
+                    '\n' => panic!("raw newline in label value"),
+                    c => value.push(c),
+                }
+            }
+            labels.push((key, value));
+            match chars.next() {
+                Some(',') | None => {}
+                other => panic!("unexpected {:?} after label value", other),
+            }
+        }
+        labels
+    }
+
+    /// 按OpenMetrics文本格式逐行检查，返回全部样本
+    fn parse(text: &str) -> Vec<Sample> {
+        assert!(text.ends_with("# EOF\n"), "missing # EOF terminator");
+        let mut families: HashMap<String, String> = HashMap::new();
+        let mut samples = Vec::new();
+        let lines: Vec<&str> = text.lines().collect();
+        for (index, line) in lines.iter().enumerate() {
+            if *line == "# EOF" {
+                assert_eq!(index, lines.len() - 1, "# EOF must be the last line");
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = rest.split_once(' ').unwrap();
+                assert!(["counter", "gauge", "histogram"].contains(&kind));
+                assert!(families.insert(name.to_string(), kind.to_string()).is_none(), "duplicate family {}", name);
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("# HELP ") {
+                let (name, help) = rest.split_once(' ').unwrap();
+                assert!(families.contains_key(name) && !help.is_empty());
+                continue;
+            }
+            assert!(!line.starts_with('#'), "unexpected comment {:?}", line);
+
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            let (name, labels) = match series.split_once('{') {
+                Some((name, labels)) => (name, parse_labels(labels.strip_suffix('}').expect("unterminated labels"))),
+                None => (series, Vec::new()),
+            };
+            let family = families.iter()
+                .find(|(family, kind)| match kind.as_str() {
+                    "counter" => name == format!("{}_total", family),
+                    "histogram" => ["_bucket", "_sum", "_count"].iter().any(|suffix| name == format!("{}{}", family, suffix)),
+                    _ => name == family.as_str(),
+                });
+            assert!(family.is_some(), "sample {} has no preceding TYPE", name);
+            let value: f64 = value.parse().unwrap_or_else(|_| panic!("bad value in {:?}", line));
+            samples.push((name.to_string(), labels, value));
+        }
+        samples
+    }
+
+    fn label<'a>(labels: &'a [(String, String)], key: &str) -> Option<&'a str> {
+        labels.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn test_render_is_well_formed() {
+        let counters = QueryCounters::default();
+        counters.record(DnsRecordType::A, &QueryOutcome::Success { rcode: 0 });
+        counters.record(DnsRecordType::A, &QueryOutcome::Success { rcode: 0 });
+        counters.record(DnsRecordType::AAAA, &QueryOutcome::Success { rcode: 3 });
+        counters.record(DnsRecordType::MX, &QueryOutcome::Failed);
+        let snapshot = MetricsSnapshot {
+            queries: counters.snapshot(),
+            upstreams: vec![upstream("primary", &[3, 40, 900], UpstreamState::Available)],
+            cache: Some(CacheStats { hits: 5, misses: 2, evictions: 1, current_size: 4, ..Default::default() }),
+            emergency_mode: false,
+            offline: true,
+            in_flight_sends: 2,
+        };
+        let text = render(&snapshot);
+        let samples = parse(&text);
+
+        let value = |name: &str, expected_labels: &[(&str, &str)]| {
+            samples.iter()
+                .find(|(n, labels, _)| n == name && expected_labels.iter().all(|(k, v)| label(labels, k) == Some(*v)))
+                .map(|(_, _, value)| *value)
+        };
+        assert_eq!(value("rat_quickdns_queries_total", &[("record_type", "A"), ("outcome", "noerror")]), Some(2.0));
+        assert_eq!(value("rat_quickdns_queries_total", &[("record_type", "AAAA"), ("outcome", "nxdomain")]), Some(1.0));
+        assert_eq!(value("rat_quickdns_queries_total", &[("record_type", "MX"), ("outcome", "failed")]), Some(1.0));
+        assert_eq!(value("rat_quickdns_upstream_queries_total", &[("upstream", "primary"), ("result", "success")]), Some(3.0));
+        assert_eq!(value("rat_quickdns_upstream_latency_seconds_count", &[("upstream", "primary")]), Some(3.0));
+        assert!((value("rat_quickdns_upstream_latency_seconds_sum", &[("upstream", "primary")]).unwrap() - 0.943).abs() < 1e-9);
+        assert_eq!(value("rat_quickdns_upstream_state", &[("state", "available")]), Some(1.0));
+        assert_eq!(value("rat_quickdns_upstream_state", &[("state", "quarantined")]), Some(0.0));
+        assert_eq!(value("rat_quickdns_cache_hits_total", &[]), Some(5.0));
+        assert_eq!(value("rat_quickdns_cache_entries", &[]), Some(4.0));
+        assert_eq!(value("rat_quickdns_offline", &[]), Some(1.0));
+        assert_eq!(value("rat_quickdns_in_flight_sends", &[]), Some(2.0));
+
+        let without_cache = render(&MetricsSnapshot::default());
+        parse(&without_cache);
+        assert!(!without_cache.contains("rat_quickdns_cache_"));
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let snapshot = MetricsSnapshot {
+            upstreams: vec![upstream("slow", &[1, 2, 20, 200, 2_000, 30_000], UpstreamState::Unavailable)],
+            ..Default::default()
+        };
+        let samples = parse(&render(&snapshot));
+        let buckets: Vec<(String, f64)> = samples.iter()
+            .filter(|(name, _, _)| name == "rat_quickdns_upstream_latency_seconds_bucket")
+            .map(|(_, labels, value)| (label(labels, "le").unwrap().to_string(), *value))
+            .collect();
+
+        assert_eq!(buckets.len(), LATENCY_BUCKETS_SECONDS.len() + 1);
+        let bounds: Vec<f64> = buckets[..LATENCY_BUCKETS_SECONDS.len()].iter().map(|(le, _)| le.parse().unwrap()).collect();
+        assert_eq!(bounds, LATENCY_BUCKETS_SECONDS.to_vec());
+        assert!(buckets.windows(2).all(|pair| pair[0].1 <= pair[1].1), "buckets not cumulative: {:?}", buckets);
+        assert_eq!(buckets.last().unwrap(), &("+Inf".to_string(), 6.0));
+        assert_eq!(buckets[LATENCY_BUCKETS_SECONDS.len() - 1].1, 5.0);
+        assert_eq!(buckets[0].1, 0.0);
+    }
+
+    #[test]
+    fn test_allowlisted_upstream_labels_on_every_upstream_series() {
+        let mut internal = upstream("internal", &[5], UpstreamState::Available);
+        internal.labels = vec![("provider".to_string(), "corp".to_string()), ("datacenter".to_string(), "sha-1".to_string())];
+        let mut public = upstream("public", &[5], UpstreamState::Available);
+        public.labels = vec![("provider".to_string(), "acme".to_string()), ("datacenter".to_string(), String::new())];
+        let snapshot = MetricsSnapshot { upstreams: vec![internal, public], ..Default::default() };
+        let samples = parse(&render(&snapshot));
+
+        let upstream_samples: Vec<&Sample> = samples.iter().filter(|(name, _, _)| name.starts_with("rat_quickdns_upstream_")).collect();
+        assert!(!upstream_samples.is_empty());
+        for (name, labels, _) in upstream_samples {
+            let keys: Vec<&str> = labels.iter().map(|(key, _)| key.as_str()).take(3).collect();
+            assert_eq!(keys, ["upstream", "provider", "datacenter"], "{}", name);
+            let expected = if label(labels, "upstream") == Some("internal") { "corp" } else { "acme" };
+            assert_eq!(label(labels, "provider"), Some(expected), "{}", name);
+        }
+        assert!(samples.iter().any(|(name, labels, value)| name == "rat_quickdns_upstream_queries_total"
+            && label(labels, "upstream") == Some("public") && label(labels, "datacenter") == Some("")
+            && label(labels, "result") == Some("success") && *value == 1.0));
+    }
+
+    #[test]
+    fn test_label_values_are_escaped() {
+        let name = "odd \"name\" with \\ and\nnewline";
+        let snapshot = MetricsSnapshot {
+            upstreams: vec![upstream(name, &[5], UpstreamState::Quarantined)],
+            ..Default::default()
+        };
+        let text = render(&snapshot);
+        assert!(text.contains(r#"upstream="odd \"name\" with \\ and\nnewline""#));
+
+        let samples = parse(&text);
+        let state = samples.iter()
+            .find(|(n, labels, value)| n == "rat_quickdns_upstream_state" && *value == 1.0 && label(labels, "upstream") == Some(name))
+            .expect("state sample for escaped upstream name");
+        assert_eq!(label(&state.1, "state"), Some("quarantined"));
+    }
+}
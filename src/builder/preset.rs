@@ -0,0 +1,177 @@
+//! 上游服务器预设模块
+//!
+//! 本模块提供显式的上游服务器预设。每个预设只描述一组具名的上游服务器及其权重，
+//! 不会修改查询策略、超时等任何其他配置。
+
+use crate::upstream_handler::UpstreamSpec;
+
+/// 上游服务器预设
+#[derive(Debug, Clone)]
+pub enum Preset {
+    /// 中国大陆：阿里、腾讯、114 的 UDP/DoH/DoT 服务器（区域 `CN`）
+    ///
+    /// | 名称 | 协议 | 地址 | 权重 |
+    /// |------|------|------|------|
+    /// | 阿里DNS | UDP | 223.5.5.5 | 10 |
+    /// | 腾讯DNS | UDP | 119.29.29.29 | 10 |
+    /// | 114DNS | UDP | 114.114.114.114 | 5 |
+    /// | 阿里DoH | DoH | https://dns.alidns.com/dns-query | 8 |
+    /// | 腾讯DoH | DoH | https://doh.pub/dns-query | 8 |
+    /// | 阿里DoT | DoT | 223.5.5.5 | 8 |
+    /// | 腾讯DoT | DoT | 1.12.12.12 | 8 |
+    ChinaMainland,
+
+    /// 全球：Cloudflare、Google、Quad9 的 UDP/DoH 服务器（区域 `global`）
+    ///
+    /// | 名称 | 协议 | 地址 | 权重 |
+    /// |------|------|------|------|
+    /// | Cloudflare DNS | UDP | 1.1.1.1 | 10 |
+    /// | Google DNS | UDP | 8.8.8.8 | 10 |
+    /// | Quad9 DNS | UDP | 9.9.9.9 | 5 |
+    /// | Cloudflare DoH | DoH | https://cloudflare-dns.com/dns-query | 8 |
+    /// | Google DoH | DoH | https://dns.google/dns-query | 8 |
+    Global,
+
+    /// 隐私优先：仅包含加密传输（DoH/DoT）的服务器（区域 `global`）
+    ///
+    /// | 名称 | 协议 | 地址 | 权重 |
+    /// |------|------|------|------|
+    /// | Cloudflare DoH | DoH | https://cloudflare-dns.com/dns-query | 10 |
+    /// | Quad9 DoH | DoH | https://dns.quad9.net/dns-query | 10 |
+    /// | Cloudflare DoT | DoT | 1.1.1.1 | 8 |
+    /// | Quad9 DoT | DoT | dns.quad9.net | 8 |
+    PrivacyFocused,
+
+    /// 自定义上游列表
+    Custom(Vec<UpstreamSpec>),
+}
+
+impl Preset {
+    /// 创建自定义预设
+    pub fn custom(upstreams: Vec<UpstreamSpec>) -> Self {
+        Self::Custom(upstreams)
+    }
+
+    /// 按名称查找内置预设（`china_mainland`、`global`、`privacy_focused`）
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "china_mainland" | "china" | "cn" => Some(Self::ChinaMainland),
+            "global" => Some(Self::Global),
+            "privacy_focused" | "privacy" => Some(Self::PrivacyFocused),
+            _ => None,
+        }
+    }
+
+    /// 预设名称
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ChinaMainland => "china_mainland",
+            Self::Global => "global",
+            Self::PrivacyFocused => "privacy_focused",
+            Self::Custom(_) => "custom",
+        }
+    }
+
+    /// 预设包含的上游服务器列表
+    pub fn upstreams(&self) -> Vec<UpstreamSpec> {
+        match self {
+            Self::ChinaMainland => {
+                let region = "CN".to_string();
+                vec![
+                    UpstreamSpec::udp("阿里DNS".to_string(), "223.5.5.5".to_string()).with_weight(10),
+                    UpstreamSpec::udp("腾讯DNS".to_string(), "119.29.29.29".to_string()).with_weight(10),
+                    UpstreamSpec::udp("114DNS".to_string(), "114.114.114.114".to_string()).with_weight(5),
+                    UpstreamSpec::doh("阿里DoH".to_string(), "https://dns.alidns.com/dns-query".to_string()).with_weight(8),
+                    UpstreamSpec::doh("腾讯DoH".to_string(), "https://doh.pub/dns-query".to_string()).with_weight(8),
+                    UpstreamSpec::dot("阿里DoT".to_string(), "223.5.5.5".to_string()).with_weight(8),
+                    UpstreamSpec::dot("腾讯DoT".to_string(), "1.12.12.12".to_string()).with_weight(8),
+                ]
+                .into_iter()
+                .map(|spec| spec.with_region(region.clone()))
+                .collect()
+            },
+            Self::Global => {
+                let region = "global".to_string();
+                vec![
+                    UpstreamSpec::udp("Cloudflare DNS".to_string(), "1.1.1.1".to_string()).with_weight(10),
+                    UpstreamSpec::udp("Google DNS".to_string(), "8.8.8.8".to_string()).with_weight(10),
+                    UpstreamSpec::udp("Quad9 DNS".to_string(), "9.9.9.9".to_string()).with_weight(5),
+                    UpstreamSpec::doh("Cloudflare DoH".to_string(), "https://cloudflare-dns.com/dns-query".to_string()).with_weight(8),
+                    UpstreamSpec::doh("Google DoH".to_string(), "https://dns.google/dns-query".to_string()).with_weight(8),
+                ]
+                .into_iter()
+                .map(|spec| spec.with_region(region.clone()))
+                .collect()
+            },
+            Self::PrivacyFocused => {
+                let region = "global".to_string();
+                vec![
+                    UpstreamSpec::doh("Cloudflare DoH".to_string(), "https://cloudflare-dns.com/dns-query".to_string()).with_weight(10),
+                    UpstreamSpec::doh("Quad9 DoH".to_string(), "https://dns.quad9.net/dns-query".to_string()).with_weight(10),
+                    UpstreamSpec::dot("Cloudflare DoT".to_string(), "1.1.1.1".to_string()).with_weight(8),
+                    UpstreamSpec::dot("Quad9 DoT".to_string(), "dns.quad9.net".to_string()).with_weight(8),
+                ]
+                .into_iter()
+                .map(|spec| spec.with_region(region.clone()))
+                .collect()
+            },
+            Self::Custom(upstreams) => upstreams.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upstream_handler::UpstreamType;
+
+    fn summary(preset: &Preset) -> Vec<(String, UpstreamType, String, u32)> {
+        preset.upstreams()
+            .into_iter()
+            .map(|s| (s.name, s.transport_type, s.server, s.weight))
+            .collect()
+    }
+
+    #[test]
+    fn test_china_mainland_preset() {
+        let list = summary(&Preset::ChinaMainland);
+        assert_eq!(list, vec![
+            ("阿里DNS".to_string(), UpstreamType::Udp, "223.5.5.5".to_string(), 10),
+            ("腾讯DNS".to_string(), UpstreamType::Udp, "119.29.29.29".to_string(), 10),
+            ("114DNS".to_string(), UpstreamType::Udp, "114.114.114.114".to_string(), 5),
+            ("阿里DoH".to_string(), UpstreamType::DoH, "https://dns.alidns.com/dns-query".to_string(), 8),
+            ("腾讯DoH".to_string(), UpstreamType::DoH, "https://doh.pub/dns-query".to_string(), 8),
+            ("阿里DoT".to_string(), UpstreamType::DoT, "223.5.5.5".to_string(), 8),
+            ("腾讯DoT".to_string(), UpstreamType::DoT, "1.12.12.12".to_string(), 8),
+        ]);
+        assert!(Preset::ChinaMainland.upstreams().iter().all(|s| s.region.as_deref() == Some("CN")));
+    }
+
+    #[test]
+    fn test_global_preset() {
+        let list = summary(&Preset::Global);
+        assert_eq!(list, vec![
+            ("Cloudflare DNS".to_string(), UpstreamType::Udp, "1.1.1.1".to_string(), 10),
+            ("Google DNS".to_string(), UpstreamType::Udp, "8.8.8.8".to_string(), 10),
+            ("Quad9 DNS".to_string(), UpstreamType::Udp, "9.9.9.9".to_string(), 5),
+            ("Cloudflare DoH".to_string(), UpstreamType::DoH, "https://cloudflare-dns.com/dns-query".to_string(), 8),
+            ("Google DoH".to_string(), UpstreamType::DoH, "https://dns.google/dns-query".to_string(), 8),
+        ]);
+    }
+
+    #[test]
+    fn test_privacy_preset_is_encrypted_only() {
+        let list = summary(&Preset::PrivacyFocused);
+        assert_eq!(list.len(), 4);
+        assert!(list.iter().all(|(_, t, _, _)| matches!(t, UpstreamType::DoH | UpstreamType::DoT)));
+    }
+
+    #[test]
+    fn test_custom_preset_and_lookup() {
+        let custom = Preset::custom(vec![UpstreamSpec::udp("内网DNS".to_string(), "10.0.0.53".to_string())]);
+        assert_eq!(custom.upstreams().len(), 1);
+        assert_eq!(custom.name(), "custom");
+        assert!(matches!(Preset::from_name("Global"), Some(Preset::Global)));
+        assert!(Preset::from_name("unknown").is_none());
+    }
+}
@@ -0,0 +1,178 @@
+//! 查询摘要日志
+//!
+//! 开启 [`with_query_summary_log`](super::DnsResolverBuilder::with_query_summary_log) 后，
+//! 每次查询（`query`、`lookup_ip` 等）结束时输出一行info日志，查询过程中原有的info日志降为debug：
+//!
+//! ```text
+//! query_summary query_id=7f3a9c domain=example.com type=A strategy=smart cache=miss upstream=google protocol=UDP peer=8.8.8.8:53 outcome=success rcode=NOERROR answers=2 duration_ms=14
+//! ```
+//!
+//! 行首固定为 `query_summary`，字段的名称和顺序固定，便于日志系统按 `key=value` 解析：
+//! - `query_id`、`domain`、`type`：查询ID、查询名称和记录类型
+//! - `strategy`：本次查询使用的策略（`fifo`、`smart`、`round_robin`、`sequential`、`quorum:N`）
+//! - `cache`：`hit` 命中缓存，`stale` 使用过期缓存应答，`miss` 查询了上游，`bypass` 本次查询不读缓存（IP地址字面量同样记为 `bypass`）
+//! - `upstream`、`protocol`、`peer`：给出应答的上游、协议和对端地址，应答来自缓存或本地时为 `-`
+//! - `outcome`：成功为 `success`；失败时为错误类别，与诊断中的尝试结果同名（`timeout`、`unreachable`、`tls`、`http`、`protocol`、`skipped`、`other`），
+//!   所有上游都失败时为 `all_failed`，法定人数未达成一致时为 `no_quorum`
+//! - `rcode`：响应码名称，没有响应时为 `-`
+//! - `answers`、`duration_ms`：应答记录数和查询耗时
+//!
+//! 取不到的值写作 `-`；含空白、`"` 或 `=` 的值加双引号，其中的 `"` 和 `\` 前加 `\`
+
+use std::fmt::Write;
+
+use super::resolver::CACHE_SOURCE;
+use super::types::{DnsQueryResponse, LITERAL_SOURCE, LOCAL_SOURCE};
+use super::QueryStrategy;
+use crate::resolver::diagnosis::AttemptOutcome;
+use crate::types::ResponseCode;
+use crate::DnsError;
+
+/// 摘要行的开头
+pub const SUMMARY_PREFIX: &str = "query_summary";
+
+/// 本次查询与缓存的关系
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CacheStatus {
+    Hit,
+    Stale,
+    Miss,
+    Bypass,
+}
+
+impl CacheStatus {
+    /// 由最终应答判断：应答来自缓存时区分是否过期，否则看本次查询是否读取缓存
+    pub(crate) fn of(response: &DnsQueryResponse, bypass: bool, served_stale: bool) -> Self {
+        match response.server_used.as_deref() {
+            Some(CACHE_SOURCE) if served_stale => CacheStatus::Stale,
+            Some(CACHE_SOURCE) => CacheStatus::Hit,
+            Some(LITERAL_SOURCE) => CacheStatus::Bypass,
+            _ if bypass => CacheStatus::Bypass,
+            _ => CacheStatus::Miss,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "hit",
+            CacheStatus::Stale => "stale",
+            CacheStatus::Miss => "miss",
+            CacheStatus::Bypass => "bypass",
+        }
+    }
+}
+
+/// 生成一次查询的摘要行
+pub(crate) fn summary_line(
+    response: &DnsQueryResponse,
+    strategy: QueryStrategy,
+    cache: CacheStatus,
+    error: Option<&DnsError>,
+) -> String {
+    // 应答不来自上游（缓存、字面量、中间件本地应答）时不写上游字段
+    let upstream = match response.server_used.as_deref() {
+        Some(CACHE_SOURCE | LITERAL_SOURCE | LOCAL_SOURCE) | None => None,
+        Some(server) => Some(server),
+    };
+    let protocol = upstream.and(response.protocol_used.as_deref());
+    let peer = upstream.and(response.upstream_peer).map(|peer| peer.to_string());
+    let rcode = response.rcode.map(|rcode| ResponseCode::from(rcode).name().to_string());
+
+    let mut line = String::from(SUMMARY_PREFIX);
+    let mut field = |key: &str, value: Option<&str>| {
+        let _ = write!(line, " {}={}", key, quote(value.unwrap_or("-")));
+    };
+    field("query_id", Some(&response.query_id.to_string()));
+    field("domain", Some(&response.domain));
+    field("type", Some(response.record_type.as_str()));
+    field("strategy", Some(&strategy_name(strategy)));
+    field("cache", Some(cache.as_str()));
+    field("upstream", upstream);
+    field("protocol", protocol);
+    field("peer", peer.as_deref());
+    field("outcome", Some(outcome(response, error)));
+    field("rcode", rcode.as_deref());
+    field("answers", Some(&response.records.len().to_string()));
+    field("duration_ms", Some(&response.duration_ms.to_string()));
+    line
+}
+
+fn strategy_name(strategy: QueryStrategy) -> String {
+    match strategy {
+        QueryStrategy::Fifo => "fifo".to_string(),
+        QueryStrategy::Smart => "smart".to_string(),
+        QueryStrategy::RoundRobin => "round_robin".to_string(),
+        QueryStrategy::Sequential => "sequential".to_string(),
+        QueryStrategy::Quorum { required } => format!("quorum:{}", required),
+    }
+}
+
+fn outcome(response: &DnsQueryResponse, error: Option<&DnsError>) -> &'static str {
+    match error {
+        None if response.success => "success",
+        None => AttemptOutcome::Other.as_str(),
+        Some(DnsError::AllUpstreamsFailed { .. }) => "all_failed",
+        Some(DnsError::QuorumNotReached { .. }) => "no_quorum",
+        Some(error) => AttemptOutcome::from_error(error).as_str(),
+    }
+}
+
+fn quote(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.is_empty() || value.chars().any(|c| c.is_whitespace() || c == '"' || c == '=') {
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+        format!("\"{}\"", escaped).into()
+    } else {
+        value.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::types::{DnsQueryRequest, DnsRecordType};
+
+    fn response(server: Option<&str>) -> DnsQueryResponse {
+        let mut response = DnsQueryResponse::for_request(&DnsQueryRequest::new("example.com", DnsRecordType::A));
+        response.duration_ms = 12;
+        response.query_id = "q1".into();
+        response.server_used = server.map(str::to_string);
+        response
+    }
+
+    #[test]
+    fn test_summary_line_fields_in_order() {
+        let mut answered = response(Some("google"));
+        answered.success = true;
+        answered.rcode = Some(0);
+        answered.protocol_used = Some("UDP".to_string());
+        answered.upstream_peer = Some("8.8.8.8:53".parse().unwrap());
+        let cache = CacheStatus::of(&answered, false, false);
+        assert_eq!(
+            summary_line(&answered, QueryStrategy::Smart, cache, None),
+            "query_summary query_id=q1 domain=example.com type=A strategy=smart cache=miss upstream=google \
+             protocol=UDP peer=8.8.8.8:53 outcome=success rcode=NOERROR answers=0 duration_ms=12"
+        );
+    }
+
+    #[test]
+    fn test_summary_line_for_cache_and_failure() {
+        let mut cached = response(Some(CACHE_SOURCE));
+        cached.success = true;
+        cached.protocol_used = Some(CACHE_SOURCE.to_string());
+        assert_eq!(CacheStatus::of(&cached, true, true), CacheStatus::Stale);
+        let line = summary_line(&cached, QueryStrategy::Quorum { required: 2 }, CacheStatus::Hit, None);
+        assert!(line.contains(" strategy=quorum:2 cache=hit upstream=- protocol=- peer=- outcome=success rcode=- "), "{}", line);
+
+        let failed = response(None);
+        assert_eq!(CacheStatus::of(&failed, true, false), CacheStatus::Bypass);
+        let line = summary_line(&failed, QueryStrategy::Fifo, CacheStatus::Miss, Some(&DnsError::Timeout { phase: None }));
+        assert!(line.contains(" upstream=- protocol=- peer=- outcome=timeout rcode=- "), "{}", line);
+    }
+
+    #[test]
+    fn test_values_with_separators_are_quoted() {
+        assert_eq!(quote("plain"), "plain");
+        assert_eq!(quote(""), "\"\"");
+        assert_eq!(quote("a b=\"c\\"), "\"a b=\\\"c\\\\\"");
+    }
+}
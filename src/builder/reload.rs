@@ -0,0 +1,166 @@
+//! 运行中的解析器重新加载配置
+//!
+//! [`SmartDnsResolver::reload`](super::SmartDnsResolver::reload) 按新的严格配置与当前生效配置的差异修改解析器：
+//! 两边都有且连接参数相同的上游沿用原传输（连接和性能指标保留），只在旧配置中的上游排空后移除，
+//! 新增的上游新建传输；解析器级设置随新的底层解析器一起替换。本模块是差异比较的部分和结果报告
+
+use super::effective_config::ResolverSettings;
+use crate::upstream_handler::UpstreamSpec;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// 可以通过重新加载修改的解析器级设置（生效配置中的字段名），其余设置改变时重新加载报错，需要重建解析器
+pub const RELOADABLE_SETTINGS: [&str; 10] = [
+    "strategy",
+    "timeout_ms",
+    "retry_count",
+    "min_ttl_secs",
+    "max_ttl_secs",
+    "cache_zero_ttl",
+    "cache_truncated_responses",
+    "accept_questionless_responses",
+    "record_rotation",
+    "metrics_upstream_labels",
+];
+
+/// 一次重新加载的结果
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReloadReport {
+    /// 新增的上游
+    pub added: Vec<String>,
+    /// 移除的上游
+    pub removed: Vec<String>,
+    /// 地址、协议或超时等连接参数改变、换用新传输的上游（按名称的性能指标和统计保留）
+    pub reconnected: Vec<String>,
+    /// 沿用原传输、只修改了权重、层级、ECS、标签等属性的上游
+    pub updated: Vec<String>,
+    /// 原样保留的上游
+    pub unchanged: Vec<String>,
+    /// 改变的解析器级设置（生效配置中的字段名）
+    pub changed_settings: Vec<String>,
+    /// 生效配置的逐项差异，格式同 [`EffectiveConfig::diff`](super::EffectiveConfig::diff)
+    pub changes: Vec<String>,
+    /// 是否清空了缓存（TTL上限收紧时，已缓存的条目可能超出新的上限）
+    pub cache_cleared: bool,
+    /// 移除时等待超过默认超时仍有查询未完成的上游（剩余查询照常结束）
+    pub undrained: Vec<String>,
+}
+
+impl ReloadReport {
+    /// 是否没有任何修改
+    pub fn is_unchanged(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.reconnected.is_empty()
+            && self.updated.is_empty()
+            && self.changed_settings.is_empty()
+    }
+}
+
+/// 两份解析器级设置中值不同的字段名，按字段名排序
+pub(crate) fn changed_settings(before: &ResolverSettings, after: &ResolverSettings) -> Vec<String> {
+    let before = serde_json::to_value(before).unwrap_or_default();
+    let after = serde_json::to_value(after).unwrap_or_default();
+    let (Some(before), Some(after)) = (before.as_object(), after.as_object()) else {
+        return Vec::new();
+    };
+    before.iter()
+        .filter(|(key, value)| after.get(key.as_str()) != Some(value))
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+/// 两个同名上游能否沿用同一个传输：协议、地址、请求头等连接参数相同，且实际超时不变
+///
+/// 权重、层级、ECS、请求特性、在途上限、SLO、记录类型和标签可以在原传输上修改
+pub(crate) fn same_connection(old: &UpstreamSpec, new: &UpstreamSpec, old_timeout: Duration, new_timeout: Duration) -> bool {
+    old.transport_type == new.transport_type
+        && old.server == new.server
+        && old.resolved_ip == new.resolved_ip
+        && old.headers == new.headers
+        && old.query_params == new.query_params
+        && old.http_version == new.http_version
+        && old.address_family == new.address_family
+        && old.tls_server_name == new.tls_server_name
+        && old.doh_method == new.doh_method
+        && old.timeout.unwrap_or(old_timeout) == new.timeout.unwrap_or(new_timeout)
+}
+
+/// 新的TTL上限是否比原来更紧（原来没有上限时设置任何上限都算），此时已缓存的条目可能超出上限
+pub(crate) fn tightens_ttl_ceiling(before: Option<Duration>, after: Option<Duration>) -> bool {
+    match (before, after) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(before), Some(after)) => after < before,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::CoreResolverConfig;
+    use crate::builder::QueryStrategy;
+
+    fn config() -> CoreResolverConfig {
+        CoreResolverConfig::new(
+            QueryStrategy::Smart,
+            Duration::from_secs(5),
+            2,
+            true,
+            Duration::from_secs(3600),
+            false,
+            Duration::from_secs(30),
+            53,
+            10,
+            true,
+            4096,
+            false,
+            crate::logger::LevelFilter::Off,
+            false,
+        )
+    }
+
+    #[test]
+    fn test_changed_settings_lists_differing_fields() {
+        let config = config();
+        let before = ResolverSettings::from_config(&config, 0);
+        assert!(changed_settings(&before, &before).is_empty());
+
+        let mut changed = config.clone();
+        changed.strategy = QueryStrategy::Sequential;
+        changed.concurrent_queries = 50;
+        let after = ResolverSettings::from_config(&changed, 0);
+        assert_eq!(changed_settings(&before, &after), vec!["concurrent_queries".to_string(), "strategy".to_string()]);
+        assert!(RELOADABLE_SETTINGS.contains(&"strategy"));
+        assert!(!RELOADABLE_SETTINGS.contains(&"concurrent_queries"));
+    }
+
+    #[test]
+    fn test_same_connection_ignores_attributes_but_not_address_or_timeout() {
+        let old = UpstreamSpec::udp("office".to_string(), "192.0.2.53".to_string());
+        let five = Duration::from_secs(5);
+        let reweighted = old.clone().with_weight(7).with_tier(1).with_label("provider", "internal");
+        assert!(same_connection(&old, &reweighted, five, five));
+
+        let moved = UpstreamSpec::udp("office".to_string(), "192.0.2.54".to_string());
+        assert!(!same_connection(&old, &moved, five, five));
+        let over_tcp = UpstreamSpec::tcp("office".to_string(), "192.0.2.53".to_string());
+        assert!(!same_connection(&old, &over_tcp, five, five));
+
+        // 沿用解析器默认超时的上游随默认超时改变，单独设置了超时的不受影响
+        assert!(!same_connection(&old, &old, five, Duration::from_secs(2)));
+        let pinned = old.clone().with_timeout(Duration::from_secs(3));
+        assert!(same_connection(&pinned, &pinned, five, Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_only_a_tighter_ttl_ceiling_invalidates_the_cache() {
+        let hour = Some(Duration::from_secs(3600));
+        let minute = Some(Duration::from_secs(60));
+        assert!(tightens_ttl_ceiling(hour, minute));
+        assert!(tightens_ttl_ceiling(None, hour));
+        assert!(!tightens_ttl_ceiling(minute, hour));
+        assert!(!tightens_ttl_ceiling(hour, None));
+        assert!(!tightens_ttl_ceiling(hour, hour));
+    }
+}
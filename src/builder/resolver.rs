@@ -2,47 +2,502 @@
 //! 
 //! 本模块实现了高性能DNS解析器的核心功能
 
-use std::sync::Arc;
-use std::time::Instant;
-use uuid::Uuid;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use crate::runtime;
+use crate::time::{Instant, SystemTime};
+use futures::StreamExt;
+use tracing::Instrument;
+use crate::runtime::JoinHandle;
 
 
-use crate::resolver::{CoreResolverConfig, CoreResolver};
-use crate::upstream_handler::UpstreamManager;
-use crate::utils::{parse_simple_server_address, parse_url_components, get_user_agent};
-use crate::error::{DnsError, Result};
-use crate::{dns_info, dns_debug};
+use crate::config::{SearchDomains, StrictDnsConfig};
+use crate::resolver::{failover_rcode, CoreResolverConfig, CoreResolver, TransportInfo, UpstreamFailover};
+use crate::resolver::answer_rewrite::RewriteRuleStats;
+use crate::resolver::ttl_override::TtlOverrideRule;
+use crate::resolver::encrypted_fallback::EncryptedFallbackStats;
+use crate::resolver::cache::{negative_ttl, CacheClearReport, CacheInvalidation, CacheStats, NegativeTtl};
+use crate::resolver::cache_insights::CacheInsights;
+use crate::resolver::memory::MemoryStats;
+use crate::resolver::health::{DetailedStats, UpstreamEvent, UpstreamStatus as HealthStatus};
+use crate::resolver::slo::SloStatus;
+use crate::resolver::quarantine::QuarantineStatus;
+use crate::resolver::priority::{self, PriorityStats, QueryPriority};
+use crate::resolver::offline::OfflineStats;
+use crate::resolver::diagnosis::{self, AttemptLog, UpstreamAttempt};
+use crate::resolver::random::RandomRng;
+use crate::resolver::scope;
+use crate::resolver::query_edns::{self, QueryEdns};
+use super::query_summary::{self, CacheStatus};
+use crate::resolver::rate_limit;
+use crate::transport::{Transport, UdpTransport, DnsCookieJar};
+#[cfg(feature = "tcp")]
+use crate::transport::{TcpTransport, FastOpenStats};
+#[cfg(feature = "dot")]
+use crate::transport::{TlsTransport, TlsSessionStats};
+#[cfg(feature = "doh")]
+use crate::transport::HttpsTransport;
+use crate::transport::{AddressFamilyStats, HostResolution, UdpPoolStats};
+use crate::upstream_handler::{UpstreamLabels, UpstreamManager, UpstreamSpec, UpstreamType, PLAIN_POOL_SIZE};
+#[cfg(any(feature = "dot", feature = "doh"))]
+use crate::upstream_handler::ENCRYPTED_POOL_SIZE;
+use crate::utils::{names_equal, parse_simple_server_address};
+#[cfg(feature = "doh")]
+use crate::utils::{parse_url_components, get_user_agent};
+use crate::error::{DnsError, NetworkErrorKind, Result};
+use crate::types::{EffectiveFeatures, SharedResponse};
+use crate::{dns_info, dns_debug, dns_warn};
+#[cfg(feature = "serde-api")]
+use super::encoded;
+use super::openmetrics::{self, MetricsSnapshot, QueryCounters, UpstreamSample, UpstreamState};
+use super::scope::{ScopeOverrides, ScopedResolver};
 use super::{
     strategy::QueryStrategy,
+    emergency::ResolverMode,
     engine::SmartDecisionEngine,
-    types::{DnsQueryRequest, DnsQueryResponse, DnsRecord, DnsRecordType},
+    explain::SelectionExplanation,
+    lookup::{self, MxHost, MxLookupOptions, MxResolution, SrvTarget},
+    history::{QueryHistory, QueryHistoryEntry, QueryOutcome},
+    consensus::{self, PerUpstreamAnswer, QueryAllReport},
+    ddr::{self, DdrOptions, DdrReport, DesignatedProtocol, DesignatedResolver, SvcbRecord},
+    histogram::{LatencyHistogram, LatencyPercentiles},
+    routing::{DomainRoute, DomainRouter},
+    middleware::{Next, QueryMiddleware},
+    effective_config::{EffectiveConfig, EffectiveUpstream, ResolverSettings},
+    reload::{self, ReloadReport, RELOADABLE_SETTINGS},
+    resolver_builder::{apply_strict_settings, DnsResolverBuilder},
+    warmup::{WarmOptions, WarmReport},
+    tenant::{TenantStats, TenantStatsTable},
+    cdn_probe::{self, CdnProbe},
+    stickiness::{StickinessStats, StickyPins},
+    diff::{DnsDiff, DnsDiffReport},
+    address_watch::{AddressWatch, AddressWatchers},
+    types::{DnsQueryRequest, DnsQueryResponse, DnsRecord, DnsRecordType, DnsRecordValue, QueryId, QueryIdFormat, ResolvedAddrs, TimingBreakdown, UpstreamFilter},
 };
 
-/// 高性能DNS解析器
+/// 命中缓存时 `server_used`/`protocol_used` 使用的来源标记
+pub const CACHE_SOURCE: &str = "cache";
+
+/// 按域名转发规则在本地应答时 `server_used`/`protocol_used` 使用的来源标记
+pub const LOCAL_SOURCE: &str = "local";
+
+/// 把一次按策略选中 `selected` 的查询结果计入决策引擎的性能指标
+/// 
+/// 成功时计入实际给出响应的传输；命中缓存时没有联系任何上游，缓存的响应时间
+/// 不反映上游的延迟，因此不更新指标，只计入跳过次数；离线模式拒绝的查询同样不计入
+async fn score_query(
+    engine: &SmartDecisionEngine,
+    selected: &str,
+    duration: Duration,
+    result: &Result<(SharedResponse, Option<TransportInfo>)>,
+) {
+    match result {
+        Ok((_, Some(info))) => engine.update_metrics(&info.name, duration, true, None).await,
+        Ok((_, None)) => engine.record_cache_hit(),
+        Err(DnsError::Offline { .. }) => {}
+        Err(_) => engine.update_metrics(selected, duration, false, None).await,
+    }
+}
+
+/// 只保留与查询类型一致的应答记录，返回保留的记录和去掉的条数
+/// 
+/// 保留查询类型的记录、从查询名出发的CNAME链上的CNAME，以及请求DNSSEC时的RRSIG；
+/// 有的上游在AAAA查询的应答里混入A记录或捎带无关类型，这些记录被去掉
+pub(crate) fn filter_answer_types(
+    records: Vec<DnsRecord>,
+    domain: &str,
+    record_type: DnsRecordType,
+    dnssec: bool,
+) -> (Vec<DnsRecord>, usize) {
+    // CNAME链上的名称，CNAME记录在应答中不一定按链的顺序排列
+    let mut chain = vec![domain];
+    while let Some(target) = records.iter().find_map(|record| match &record.value {
+        DnsRecordValue::Domain(target) if record.record_type == DnsRecordType::CNAME
+            && names_equal(&record.name, chain[chain.len() - 1])
+            && !chain.iter().any(|name| names_equal(name, target)) => Some(target.as_str()),
+        _ => None,
+    }) {
+        chain.push(target);
+    }
+    let keep = |record: &DnsRecord| {
+        record.record_type == record_type
+            || (record.record_type == DnsRecordType::CNAME && chain.iter().any(|name| names_equal(name, &record.name)))
+            || (dnssec && record.record_type == DnsRecordType::RRSIG)
+    };
+    let kept: Vec<DnsRecord> = records.iter().filter(|record| keep(record)).cloned().collect();
+    let dropped = records.len() - kept.len();
+    (kept, dropped)
+}
+
+/// 否定应答的区域顶点和否定缓存时间，命中缓存时不超过条目的剩余TTL
+pub(crate) fn response_negative_ttl(response: &SharedResponse, record_type: DnsRecordType) -> Option<NegativeTtl> {
+    let mut negative = negative_ttl(response, record_type.into())?;
+    if let Some(remaining) = response.ttl_override() {
+        negative.ttl = negative.ttl.min(remaining);
+    }
+    Some(negative)
+}
+
+/// 转换响应为记录，只复制应答段中用到的数据
+/// 
+/// 记录严格按应答段的顺序排列，无法表示的记录类型跳过、不改变其余记录的相对顺序
+pub(crate) fn response_records(response: &SharedResponse) -> Vec<DnsRecord> {
+    let mut records = Vec::new();
+    
+    for record in &response.answers {
+        let Ok(record_type) = DnsRecordType::try_from(record.rtype) else {
+            continue;
+        };
+        
+        let value = match &record.data {
+            crate::types::RecordData::A(addr) => DnsRecordValue::IpAddr((*addr).into()),
+            crate::types::RecordData::AAAA(addr) => DnsRecordValue::IpAddr((*addr).into()),
+            crate::types::RecordData::CNAME(name) => DnsRecordValue::Domain(name.clone()),
+            crate::types::RecordData::NS(name) => DnsRecordValue::Domain(name.clone()),
+            crate::types::RecordData::PTR(name) => DnsRecordValue::Domain(name.clone()),
+            crate::types::RecordData::TXT(texts) => DnsRecordValue::Txt(texts.clone()),
+            crate::types::RecordData::MX { priority, exchange } => {
+                DnsRecordValue::Mx { priority: *priority, exchange: exchange.clone() }
+            },
+            crate::types::RecordData::SRV { priority, weight, port, target } => {
+                DnsRecordValue::Srv { priority: *priority, weight: *weight, port: *port, target: target.clone() }
+            },
+            crate::types::RecordData::SOA { mname, rname, serial, refresh, retry, expire, minimum } => {
+                DnsRecordValue::Soa {
+                    mname: mname.clone(),
+                    rname: rname.clone(),
+                    serial: *serial,
+                    refresh: *refresh,
+                    retry: *retry,
+                    expire: *expire,
+                    minimum: *minimum,
+                }
+            },
+            _ => continue,
+        };
+        
+        records.push(DnsRecord {
+            name: record.name.clone(),
+            record_type,
+            value,
+            ttl: response.record_ttl(record),
+        });
+    }
+    
+    records
+}
+
+/// 重新计算自适应超时，并设置为各上游单次发送的超时
+async fn apply_adaptive_timeouts(resolver: &CoreResolver, engine: &SmartDecisionEngine) -> Vec<(String, Duration)> {
+    let timeouts = engine.recalculate_timeouts().await;
+    for (name, timeout) in &timeouts {
+        // 计算期间被移除的上游找不到传输，跳过
+        if let Err(e) = resolver.set_transport_attempt_timeout(name, Some(*timeout)) {
+            dns_debug!("跳过上游 {} 的自适应超时: {}", name, e);
+        }
+    }
+    timeouts
+}
+
+/// 按上游规格创建传输实例，自定义上游从 `custom_transports` 中按名称查找，
+/// 开启DNS Cookie时UDP传输共用 `cookies`
+pub(crate) fn create_transport(
+    spec: &UpstreamSpec,
+    config: &CoreResolverConfig,
+    custom_transports: &[(String, Arc<dyn Transport>)],
+    cookies: Option<&Arc<DnsCookieJar>>,
+) -> Result<Arc<dyn Transport>> {
+    // 上游单独设置的超时覆盖解析器的默认超时；开启自适应超时时单次发送的超时另行设置，
+    // 传输自身的超时放宽到自适应超时的上限，让慢而可用的上游可以超过配置的超时
+    let default_timeout = match &config.adaptive_timeout {
+        Some(adaptive) => spec.timeout.unwrap_or(config.default_timeout).max(adaptive.ceiling),
+        None => spec.timeout.unwrap_or(config.default_timeout),
+    };
+    // 上游单独设置的地址族偏好覆盖解析器的设置
+    let host_resolution = HostResolution {
+        family_preference: spec.address_family.or(config.host_resolution.family_preference),
+        ..config.host_resolution.clone()
+    };
+    // 传输的超时和耗时统计与解析器使用同一时间源
+    let clock = config.clock.clone().unwrap_or_else(crate::resolver::clock::real_clock);
+    match spec.transport_type {
+        crate::upstream_handler::UpstreamType::Udp => {
+            dns_debug!("开始创建UDP传输: {} ({})", spec.name, spec.server);
+            
+            // 使用公共函数解析服务器地址和端口
+            let (server, port) = parse_simple_server_address(&spec.server, 53);
+            dns_debug!("UDP地址解析: server={}, port={}", server, port);
+            
+            let transport_config = crate::transport::TransportConfig {
+                server,
+                port,
+                timeout: default_timeout,
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: PLAIN_POOL_SIZE,
+                buffer_size: config.buffer_size,
+                record_limits: Some(config.record_limits),
+            };
+            let mut transport = UdpTransport::new(transport_config).with_host_resolution(host_resolution).with_clock(clock);
+            if let Some(pool) = &config.udp_socket_pool {
+                transport = transport.with_socket_pool(pool.clone());
+            }
+            Ok(Arc::new(match cookies {
+                Some(jar) => transport.with_cookies(jar.clone()),
+                None => transport,
+            }))
+        },
+        #[cfg(feature = "tcp")]
+        crate::upstream_handler::UpstreamType::Tcp => {
+            dns_debug!("开始创建TCP传输: {} ({})", spec.name, spec.server);
+            
+            // 使用公共函数解析服务器地址和端口
+            let (server, port) = parse_simple_server_address(&spec.server, 53);
+            dns_debug!("TCP地址解析: server={}, port={}", server, port);
+            
+            let transport_config = crate::transport::TransportConfig {
+                server,
+                port,
+                timeout: default_timeout,
+                tcp_fast_open: config.tcp_fast_open,
+                tcp_nodelay: true,
+                pool_size: PLAIN_POOL_SIZE,
+                buffer_size: config.buffer_size,
+                record_limits: Some(config.record_limits),
+            };
+            Ok(Arc::new(TcpTransport::new(transport_config).with_host_resolution(host_resolution).with_clock(clock)))
+        },
+        #[cfg(feature = "doh")]
+        crate::upstream_handler::UpstreamType::DoH => {
+            dns_debug!("开始创建DoH传输: {} ({})", spec.name, crate::transport::doh_url::redact_url(&spec.server, "***"));
+            
+            // 验证HTTPS URL格式
+            if !spec.server.starts_with("https://") {
+                return Err(DnsError::InvalidConfig("DoH server must be HTTPS URL".to_string()));
+            }
+            
+            // 使用公共函数从URL中解析主机名和端口
+            let (hostname, port) = parse_url_components(&spec.server)?;
+            dns_debug!("DoH URL解析: hostname={}, port={}", hostname, port);
+            
+            // 优先连接预解析的IP地址，SNI、证书校验和Host仍使用URL中的主机名
+            let connect_addr = crate::upstream_handler::doh_connect_addr(spec, port)?;
+            dns_debug!("DoH连接服务器: {}", connect_addr.map_or_else(|| hostname.clone(), |address| address.to_string()));
+            
+            let https_config = crate::transport::HttpsConfig {
+                base: crate::transport::TransportConfig {
+                    server: hostname,
+                    port,
+                    timeout: default_timeout,
+                    tcp_fast_open: false,
+                    tcp_nodelay: true,
+                    pool_size: ENCRYPTED_POOL_SIZE,
+                    buffer_size: config.buffer_size,
+                    record_limits: Some(config.record_limits),
+                },
+                url: spec.server.clone(),
+                method: spec.doh_method,
+                user_agent: get_user_agent(),
+                extra_headers: spec.headers.clone(),
+                query_params: spec.query_params.clone(),
+                http_version: spec.http_version,
+                max_response_size: config.buffer_size,
+                follow_redirects: config.doh_follow_redirects,
+                connect_addr,
+            };
+            
+            match HttpsTransport::new(https_config) {
+                Ok(transport) => Ok(Arc::new(transport.with_clock(clock))),
+                Err(e) => {
+                    dns_debug!("❌ DoH传输创建失败: {} - 错误: {:?}", spec.name, e);
+                    Err(e)
+                }
+            }
+        },
+        #[cfg(feature = "dot")]
+        crate::upstream_handler::UpstreamType::DoT => {
+            dns_debug!("开始创建DoT传输: {} ({})", spec.name, spec.server);
+            
+            // 使用公共函数解析服务器地址和端口
+            let (server, port) = parse_simple_server_address(&spec.server, 853);
+            dns_debug!("DoT地址解析: server={}, port={}", server, port);
+            
+            // 优先使用预解析的IP地址进行连接，但SNI必须使用原始域名（或单独设置的名称）
+            let connection_server = spec.resolved_ip.as_ref().unwrap_or(&server);
+            let server_name = spec.tls_server_name.clone().unwrap_or_else(|| server.clone());
+            dns_debug!("DoT连接服务器: {}, SNI: {}", connection_server, server_name);
+            
+            let tls_config = crate::transport::TlsConfig {
+                base: crate::transport::TransportConfig {
+                    server: connection_server.clone(),
+                    port,
+                    timeout: default_timeout,
+                    tcp_fast_open: config.tcp_fast_open,
+                    tcp_nodelay: true,
+                    pool_size: ENCRYPTED_POOL_SIZE,
+                    buffer_size: config.buffer_size,
+                    record_limits: Some(config.record_limits),
+                },
+                server_name, // SNI使用原始域名，确保证书验证正确
+                verify_cert: true,
+                enable_tls_early_data: config.tls_early_data,
+            };
+            
+            match TlsTransport::new(tls_config) {
+                Ok(transport) => Ok(Arc::new(transport.with_host_resolution(host_resolution).with_clock(clock))),
+                Err(e) => {
+                    dns_debug!("❌ DoT传输创建失败: {} - 错误: {:?}", spec.name, e);
+                    Err(e)
+                }
+            }
+        },
+        crate::upstream_handler::UpstreamType::Custom => {
+            custom_transports.iter()
+                .find(|(name, _)| *name == spec.name)
+                .map(|(_, transport)| transport.clone())
+                .ok_or_else(|| DnsError::InvalidConfig(
+                    format!("Custom upstream '{}' has no transport instance", spec.name)
+                ))
+        },
+        #[cfg(not(all(feature = "tcp", feature = "dot", feature = "doh")))]
+        ref transport_type => {
+            let feature = transport_type.missing_feature().unwrap_or_default();
+            Err(crate::upstream_handler::feature_disabled(spec, feature))
+        },
+    }
+}
+
+/// 按上游规格检查构建时创建传输和登记传输选项的每一步，报告与构建相同的错误，但不绑定套接字
+/// 
+/// UDP传输创建时预先绑定源端口池、DoH的HTTP/3客户端创建时绑定QUIC端点，这两种情况只检查不涉及套接字的部分
+pub(crate) fn check_upstream(
+    spec: &UpstreamSpec,
+    config: &CoreResolverConfig,
+    custom_transports: &[(String, Arc<dyn Transport>)],
+) -> Result<()> {
+    match spec.transport_type {
+        UpstreamType::Udp => {}
+        #[cfg(feature = "doh")]
+        UpstreamType::DoH if spec.http_version.uses_http3() => {
+            let tcp_only = UpstreamSpec { http_version: crate::transport::HttpVersionPref::Auto, ..spec.clone() };
+            create_transport(&tcp_only, config, custom_transports, None)?;
+            HttpsTransport::check_http3(&spec.server)?;
+        }
+        _ => {
+            create_transport(spec, config, custom_transports, None)?;
+        }
+    }
+    spec.ecs_policy.validate()?;
+    spec.features.validate()?;
+    crate::resolver::check_max_inflight(&spec.name, spec.max_inflight)?;
+    spec.validate_options()?;
+    match &spec.slo {
+        Some(slo) => slo.validate(&spec.name),
+        None => Ok(()),
+    }
+}
+
+/// 底层解析器及其配置，[`SmartDnsResolver::reload`] 时整体替换
+/// 
+/// 与传输列表一样按写时复制的方式替换：每次查询开始时取一份快照并传给整个查询路径，替换后已经开始的查询按原设置完成
 #[derive(Debug)]
-pub struct SmartDnsResolver {
+pub(super) struct ActiveResolver {
     /// 底层解析器
     resolver: CoreResolver,
+    /// 生效的解析器配置
+    config: CoreResolverConfig,
+    /// 查询策略
+    query_strategy: QueryStrategy,
+}
+
+/// 按上游规格设置已注册传输的ECS、层级、请求特性、在途上限、SLO、记录类型和标签
+fn configure_transport(resolver: &CoreResolver, spec: &UpstreamSpec) -> Result<()> {
+    resolver.set_transport_ecs_policy(&spec.name, spec.ecs_policy.clone())?;
+    resolver.set_transport_tier(&spec.name, spec.tier)?;
+    resolver.set_transport_features(&spec.name, spec.features)?;
+    resolver.set_transport_max_inflight(&spec.name, spec.max_inflight, spec.queue_behavior)?;
+    resolver.set_transport_slo(&spec.name, spec.slo)?;
+    resolver.set_transport_record_types(&spec.name, spec.record_types.clone())?;
+    resolver.set_transport_labels(&spec.name, spec.labels.clone())
+}
+
+/// 高性能DNS解析器
+#[derive(Debug)]
+pub struct SmartDnsResolver {
+    /// 底层解析器及其配置（后台任务也持有，重新加载后它们使用新的解析器）
+    active: Arc<RwLock<Arc<ActiveResolver>>>,
+    
+    /// 上游管理器（运行时增删上游时同步修改）
+    upstream_manager: RwLock<UpstreamManager>,
     
-    /// 上游管理器
-    upstream_manager: UpstreamManager,
+    /// 运行时增删上游和重新加载配置互斥，避免重新加载时丢掉同时进行的修改
+    reconfigure: tokio::sync::Mutex<()>,
     
     /// 智能决策引擎（可选）
     decision_engine: Option<Arc<SmartDecisionEngine>>,
     
-    /// 查询策略
-    query_strategy: QueryStrategy,
-    
     /// 是否启用EDNS
     enable_edns: bool,
+    
+    /// 性能指标快照文件路径（启用持久化时）
+    metrics_snapshot_path: Option<PathBuf>,
+    
+    /// 查询历史记录（可选）
+    query_history: Option<Arc<QueryHistory>>,
+    
+    /// 调用方直接提供的自定义传输（按上游名称）
+    custom_transports: RwLock<Vec<(String, Arc<dyn Transport>)>>,
+    
+    /// 后台任务（定期保存性能指标快照等），关闭或释放解析器时中止
+    background_tasks: Mutex<Vec<JoinHandle<()>>>,
+    
+    /// 按域名转发的规则（可选），优先于查询策略
+    domain_router: Option<Arc<DomainRouter>>,
+    
+    /// 查询中间件，按注册顺序执行
+    middlewares: Vec<Arc<dyn QueryMiddleware>>,
+    
+    /// 查询前展开名称所用的搜索域（可选）
+    search_domains: Option<SearchDomains>,
+    
+    /// 按租户的查询计数（可选）
+    tenant_stats: Option<TenantStatsTable>,
+    
+    /// 本区域的CDN准确性探测（为空表示不探测）
+    cdn_probes: Arc<Vec<CdnProbe>>,
+    
+    /// 为未指定ID的查询分配查询ID的格式
+    query_id_format: QueryIdFormat,
+    
+    /// 粘性键到上游的记录表（未开启上游粘性时为None）
+    sticky_pins: Option<Arc<StickyPins>>,
+    
+    /// 因类型与查询不符而从应答中去掉的记录数
+    dropped_mismatched_records: AtomicU64,
+    
+    /// 正在进行的地址变更订阅
+    address_watches: AddressWatchers,
+    
+    /// 按记录类型和结果的查询计数，供OpenMetrics导出
+    query_counters: QueryCounters,
 }
 
 impl Drop for SmartDnsResolver {
     fn drop(&mut self) {
         dns_info!("Dropping SmartDnsResolver, cleaning up resources...");
-        // 注意：由于异步任务的特性，我们无法在Drop中直接取消它们
-        // 但我们可以记录清理日志，帮助调试
-        dns_debug!("SmartDnsResolver dropped with {} transports", self.resolver.transport_count());
+        // 关闭时保存一次性能指标快照；锁被占用时放弃，避免在Drop中阻塞
+        if let (Some(path), Some(engine)) = (&self.metrics_snapshot_path, &self.decision_engine)
+            && let Some(snapshot) = engine.try_export_metrics()
+            && let Err(e) = SmartDecisionEngine::write_metrics_snapshot(&snapshot, path)
+        {
+            dns_warn!("保存性能指标快照失败: {}", e);
+        }
+        self.abort_background_tasks();
+        dns_debug!("SmartDnsResolver dropped with {} transports", self.active().resolver.transport_count());
     }
 }
 
@@ -55,10 +510,11 @@ impl SmartDnsResolver {
         decision_engine: Option<Arc<SmartDecisionEngine>>,
         query_strategy: QueryStrategy,
         enable_edns: bool,
+        custom_transports: Vec<(String, Arc<dyn Transport>)>,
     ) -> Result<Self> {
-        // 提取需要的配置值，避免所有权问题
-        let default_timeout = config.default_timeout;
-        let mut resolver = CoreResolver::new(config);
+        let mut config = config;
+        config.enable_edns = enable_edns;
+        let mut resolver = CoreResolver::new(config.clone());
         
         
         let specs = upstream_manager.get_specs();
@@ -66,182 +522,1475 @@ impl SmartDnsResolver {
         
         // 根据上游管理器配置添加传输协议
         for spec in specs {
-            match spec.transport_type {
-                crate::upstream_handler::UpstreamType::Udp => {
-                    dns_debug!("开始创建UDP传输: {} ({})", spec.name, spec.server);
-                    
-                    // 使用公共函数解析服务器地址和端口
-                    let (server, port) = parse_simple_server_address(&spec.server, 53);
-                    dns_debug!("UDP地址解析: server={}, port={}", server, port);
-                    
-                    let transport_config = crate::transport::TransportConfig {
-                        server,
-                        port,
-                        timeout: default_timeout,
-                        tcp_fast_open: false,
-                        tcp_nodelay: true,
-                        pool_size: 10,
-                    };
-                    resolver.add_udp_transport(transport_config);
-                    dns_debug!("✅ UDP传输添加成功: {}", spec.name);
-                },
-                crate::upstream_handler::UpstreamType::Tcp => {
-                    dns_debug!("开始创建TCP传输: {} ({})", spec.name, spec.server);
-                    
-                    // 使用公共函数解析服务器地址和端口
-                    let (server, port) = parse_simple_server_address(&spec.server, 53);
-                    dns_debug!("TCP地址解析: server={}, port={}", server, port);
-                    
-                    let transport_config = crate::transport::TransportConfig {
-                        server,
-                        port,
-                        timeout: default_timeout,
-                        tcp_fast_open: false,
-                        tcp_nodelay: true,
-                        pool_size: 10,
-                    };
-                    resolver.add_tcp_transport(transport_config);
-                    dns_debug!("✅ TCP传输添加成功: {}", spec.name);
-                },
-                crate::upstream_handler::UpstreamType::DoH => {
-                    dns_debug!("开始创建DoH传输: {} ({})", spec.name, spec.server);
-                    
-                    // 验证HTTPS URL格式
-                    if !spec.server.starts_with("https://") {
-                        return Err(DnsError::InvalidConfig("DoH server must be HTTPS URL".to_string()));
-                    }
-                    
-                    // 使用公共函数从URL中解析主机名和端口
-                    let (hostname, port) = parse_url_components(&spec.server)?;
-                    dns_debug!("DoH URL解析: hostname={}, port={}", hostname, port);
-                    
-                    // 优先使用预解析的IP地址进行连接
-                    let connection_server = spec.resolved_ip.as_ref().unwrap_or(&hostname);
-                    dns_debug!("DoH连接服务器: {}", connection_server);
-                    
-                    let https_config = crate::transport::HttpsConfig {
-                        base: crate::transport::TransportConfig {
-                            server: connection_server.clone(),
-                            port,
-                            timeout: default_timeout,
-                            tcp_fast_open: false,
-                            tcp_nodelay: true,
-                            pool_size: 5,
-                        },
-                        url: spec.server.clone(),
-                        method: crate::transport::HttpMethod::POST,
-                        user_agent: get_user_agent(),
-                    };
-                    
-                    dns_debug!("调用resolver.add_https_transport...");
-                    match resolver.add_https_transport(https_config) {
-                        Ok(_) => dns_debug!("✅ DoH传输添加成功: {}", spec.name),
-                        Err(e) => {
-                            dns_debug!("❌ DoH传输添加失败: {} - 错误: {:?}", spec.name, e);
-                            return Err(e);
-                        }
-                    }
-                },
-                crate::upstream_handler::UpstreamType::DoT => {
-                    dns_debug!("开始创建DoT传输: {} ({})", spec.name, spec.server);
-                    
-                    // 使用公共函数解析服务器地址和端口
-                    let (server, port) = parse_simple_server_address(&spec.server, 853);
-                    dns_debug!("DoT地址解析: server={}, port={}", server, port);
-                    
-                    // 优先使用预解析的IP地址进行连接，但SNI必须使用原始域名
-                    let connection_server = spec.resolved_ip.as_ref().unwrap_or(&server);
-                    dns_debug!("DoT连接服务器: {}, SNI: {}", connection_server, server);
-                    
-                    let tls_config = crate::transport::TlsConfig {
-                        base: crate::transport::TransportConfig {
-                            server: connection_server.clone(),
-                            port,
-                            timeout: default_timeout,
-                            tcp_fast_open: false,
-                            tcp_nodelay: true,
-                            pool_size: 5,
-                        },
-                        server_name: server, // SNI使用原始域名，确保证书验证正确
-                        verify_cert: true,
-                    };
-                    
-                    dns_debug!("调用resolver.add_tls_transport...");
-                    match resolver.add_tls_transport(tls_config) {
-                        Ok(_) => dns_debug!("✅ DoT传输添加成功: {}", spec.name),
-                        Err(e) => {
-                            dns_debug!("❌ DoT传输添加失败: {} - 错误: {:?}", spec.name, e);
-                            return Err(e);
-                        }
-                    }
-                },
-            }
+            let transport = create_transport(spec, &config, &custom_transports, resolver.cookie_jar())?;
+            resolver.add_named_transport(spec.name.clone(), transport);
+            configure_transport(&resolver, spec)?;
+            dns_debug!("✅ {:?}传输添加成功: {}", spec.transport_type, spec.name);
         }
         
         dns_debug!("SmartDnsResolver::new - 所有传输创建完成，解析器构建成功");
         
+        let sticky_pins = config.stickiness.map(StickyPins::new).transpose()?.map(Arc::new);
         Ok(Self {
-            resolver,
-            upstream_manager,
+            active: Arc::new(RwLock::new(Arc::new(ActiveResolver { resolver, config, query_strategy }))),
+            upstream_manager: RwLock::new(upstream_manager),
+            reconfigure: tokio::sync::Mutex::new(()),
             decision_engine,
-            query_strategy,
             enable_edns,
+            metrics_snapshot_path: None,
+            query_history: None,
+            custom_transports: RwLock::new(custom_transports),
+            background_tasks: Mutex::new(Vec::new()),
+            domain_router: None,
+            middlewares: Vec::new(),
+            search_domains: None,
+            tenant_stats: None,
+            cdn_probes: Arc::new(Vec::new()),
+            query_id_format: QueryIdFormat::default(),
+            sticky_pins,
+            dropped_mismatched_records: AtomicU64::new(0),
+            address_watches: AddressWatchers::default(),
+            query_counters: QueryCounters::default(),
         })
     }
     
+    /// 当前的底层解析器及其配置，查询开始时取一份，整个查询都使用它
+    fn active(&self) -> Arc<ActiveResolver> {
+        self.active.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+    
+    /// 启用按域名转发：注册只用于转发规则的上游（默认上游已由构建器加入上游管理器）
+    pub(super) fn set_domain_router(&mut self, router: DomainRouter) -> Result<()> {
+        let active = self.active();
+        router.validate()?;
+        for spec in router.upstreams() {
+            let transport = create_transport(spec, &active.config, &[], active.resolver.cookie_jar())?;
+            active.resolver.register_routed_transport(spec.name.clone(), transport)?;
+            active.resolver.set_transport_ecs_policy(&spec.name, spec.ecs_policy.clone())?;
+            active.resolver.set_transport_features(&spec.name, spec.features)?;
+            active.resolver.set_transport_max_inflight(&spec.name, spec.max_inflight, spec.queue_behavior)?;
+            active.resolver.set_transport_slo(&spec.name, spec.slo)?;
+            active.resolver.set_transport_record_types(&spec.name, spec.record_types.clone())?;
+            active.resolver.set_transport_labels(&spec.name, spec.labels.clone())?;
+        }
+        dns_info!("按域名转发已启用: {} 条规则，{} 个转发上游", router.rule_count(), router.upstreams().len());
+        self.domain_router = Some(Arc::new(router));
+        Ok(())
+    }
+    
+    /// 设置查询中间件，按顺序执行
+    pub(super) fn set_middlewares(&mut self, middlewares: Vec<Arc<dyn QueryMiddleware>>) {
+        self.middlewares = middlewares;
+    }
+    
+    /// 查询前按搜索域展开名称
+    pub(super) fn set_search_domains(&mut self, search: SearchDomains) {
+        self.search_domains = Some(search);
+    }
+    
+    /// 设置为未指定ID的查询分配查询ID的格式
+    pub(super) fn set_query_id_format(&mut self, format: QueryIdFormat) {
+        self.query_id_format = format;
+    }
+    
+    /// 为未指定ID的查询分配查询ID的格式
+    pub fn query_id_format(&self) -> QueryIdFormat {
+        self.query_id_format
+    }
+    
+    /// 按请求上下文中的租户计数，最多单独统计 `max_tenants` 个租户
+    pub(super) fn enable_tenant_stats(&mut self, max_tenants: usize) -> Result<()> {
+        self.tenant_stats = Some(TenantStatsTable::new(max_tenants)?);
+        Ok(())
+    }
+    
+    /// 上游粘性记录表的条目数和命中率，未开启上游粘性时为 `None`
+    pub fn stickiness_stats(&self) -> Option<StickinessStats> {
+        self.sticky_pins.as_ref().map(|pins| pins.stats())
+    }
+    
+    /// 粘性键（客户端子网如 `203.0.113.0/24`，或租户名称）当前记住的上游
+    pub fn sticky_upstream(&self, key: &str) -> Option<String> {
+        self.sticky_pins.as_ref().and_then(|pins| pins.pinned(key))
+    }
+    
+    /// 清空上游粘性记录，之后每个粘性键的下一次查询重新按查询策略选择上游
+    pub fn clear_sticky_pins(&self) {
+        if let Some(pins) = &self.sticky_pins {
+            pins.clear();
+        }
+    }
+    
+    /// 按租户的查询数、失败数和缓存命中数，未开启按租户统计时为 `None`；
+    /// 超出租户数上限后出现的租户合并计入 [`OTHER_TENANTS`](super::tenant::OTHER_TENANTS)
+    pub fn tenant_stats(&self) -> Option<HashMap<String, TenantStats>> {
+        self.tenant_stats.as_ref().map(TenantStatsTable::snapshot)
+    }
+    
+    /// 查询前展开名称所用的搜索域，未启用时为 `None`
+    pub fn search_domains(&self) -> Option<&SearchDomains> {
+        self.search_domains.as_ref()
+    }
+    
+    /// 按域名转发的规则，未启用时为 `None`
+    pub fn domain_router(&self) -> Option<&DomainRouter> {
+        self.domain_router.as_deref()
+    }
+    
+    /// 启用性能指标快照持久化，并启动定期保存任务
+    /// 
+    /// 保存任务只持有决策引擎的弱引用，解析器释放后自动退出
+    pub(super) fn enable_metrics_snapshot(&mut self, path: PathBuf, interval: Duration) {
+        let Some(engine) = &self.decision_engine else {
+            return;
+        };
+        
+        let weak_engine = Arc::downgrade(engine);
+        let task_path = path.clone();
+        let task = runtime::spawn(async move {
+            let mut ticker = runtime::interval(interval);
+            ticker.tick().await; // 第一次tick立即返回，跳过
+            loop {
+                ticker.tick().await;
+                let Some(engine) = weak_engine.upgrade() else {
+                    break;
+                };
+                let snapshot = engine.export_metrics().await;
+                if let Err(e) = SmartDecisionEngine::write_metrics_snapshot(&snapshot, &task_path) {
+                    dns_warn!("定期保存性能指标快照失败: {}", e);
+                }
+            }
+        });
+        
+        self.background_tasks.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).push(task);
+        self.metrics_snapshot_path = Some(path);
+    }
+    
+    /// 启用CDN准确性探测：立即执行一轮，之后每隔 `interval` 执行一次
+    /// 
+    /// 探测任务只持有决策引擎的弱引用，解析器释放后自动退出；任务使用启用时的上游列表，
+    /// 之后运行时增加的上游可以通过 [`run_cdn_probes`](Self::run_cdn_probes) 探测
+    pub(super) fn enable_cdn_probes(&mut self, probes: Vec<CdnProbe>, interval: Duration) {
+        let Some(engine) = &self.decision_engine else {
+            return;
+        };
+        
+        self.cdn_probes = Arc::new(probes);
+        let weak_engine = Arc::downgrade(engine);
+        let probes = self.cdn_probes.clone();
+        let active = self.active.clone();
+        let task = runtime::spawn(async move {
+            let mut ticker = runtime::interval(interval);
+            loop {
+                ticker.tick().await;
+                let Some(engine) = weak_engine.upgrade() else {
+                    break;
+                };
+                let current = active.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+                let concurrency = current.config.concurrent_queries;
+                if let Err(e) = cdn_probe::run_cdn_probes(&current.resolver, &engine, &probes, concurrency).await {
+                    dns_warn!("CDN准确性探测失败: {}", e);
+                }
+            }
+        });
+        
+        self.background_tasks.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).push(task);
+    }
+    
+    /// 启用自适应超时：立即设置各上游单次发送的超时，之后每隔配置的间隔按延迟直方图重新计算
+    /// 
+    /// 任务只持有决策引擎的弱引用，解析器释放后自动退出；未开启自适应超时时不做任何事
+    pub(super) async fn enable_adaptive_timeouts(&mut self) {
+        let Some(engine) = &self.decision_engine else {
+            return;
+        };
+        let Some(interval) = engine.adaptive_timeout().map(|config| config.interval) else {
+            return;
+        };
+        
+        apply_adaptive_timeouts(&self.active().resolver, engine).await;
+        let weak_engine = Arc::downgrade(engine);
+        // 每次取当前的底层解析器，设置写入查询实际使用的传输列表
+        let active = self.active.clone();
+        let task = runtime::spawn(async move {
+            let mut ticker = runtime::interval(interval);
+            ticker.tick().await; // 第一次tick立即返回，构建时已经设置过
+            loop {
+                ticker.tick().await;
+                let Some(engine) = weak_engine.upgrade() else {
+                    break;
+                };
+                let current = active.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+                apply_adaptive_timeouts(&current.resolver, &engine).await;
+            }
+        });
+        
+        self.background_tasks.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).push(task);
+    }
+    
+    /// 立即按当前的延迟直方图重新计算各上游的自适应超时并生效，返回各上游（按配置顺序）实际使用的单次发送超时
+    /// 
+    /// 后台任务按 [`AdaptiveTimeoutConfig::interval`](super::AdaptiveTimeoutConfig::interval) 定期做同样的计算；
+    /// 未开启自适应超时时返回空列表
+    pub async fn recalculate_adaptive_timeouts(&self) -> Vec<(String, Duration)> {
+        match &self.decision_engine {
+            Some(engine) => apply_adaptive_timeouts(&self.active().resolver, engine).await,
+            None => Vec::new(),
+        }
+    }
+    
+    /// 本区域的CDN准确性探测，未配置时为空
+    pub fn cdn_probes(&self) -> &[CdnProbe] {
+        &self.cdn_probes
+    }
+    
+    /// 立即通过每个启用的上游执行一轮CDN准确性探测，结果计入决策引擎的性能指标
+    /// 
+    /// 未配置探测时不发送任何查询
+    pub async fn run_cdn_probes(&self) -> Result<()> {
+        match &self.decision_engine {
+            Some(engine) => cdn_probe::run_cdn_probes(&self.active().resolver, engine, &self.cdn_probes, self.active().config.concurrent_queries).await,
+            None => Ok(()),
+        }
+    }
+    
+    /// 解析以主机名配置的上游地址
+    /// 
+    /// 解析失败的上游只被标记为不可用，之后发送时重新解析；`strict` 为true时返回第一个失败的错误
+    pub(super) async fn prepare_upstreams(&self, strict: bool) -> Result<()> {
+        let failures = self.active().resolver.prepare_transports().await;
+        match failures.into_iter().next() {
+            Some((name, e)) if strict => Err(DnsError::InvalidConfig(
+                format!("Failed to resolve address of upstream '{}': {}", name, e)
+            )),
+            _ => Ok(()),
+        }
+    }
+    
+    /// 中止全部后台任务
+    fn abort_background_tasks(&self) {
+        let mut tasks = self.background_tasks.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for task in tasks.drain(..) {
+            task.abort();
+        }
+    }
+    
+    /// 关闭解析器：中止后台任务，启用了快照持久化时保存最后一次性能指标快照，并从 [`registry`](crate::registry) 中移除
+    /// 
+    /// 关闭后仍可查询，只是不再有后台任务；可以重复调用
+    pub async fn shutdown(&self) {
+        crate::registry::forget(self);
+        self.abort_background_tasks();
+        self.active().resolver.stop_cache_janitor();
+        self.active().resolver.stop_upstream_monitor_task();
+        if self.metrics_snapshot_path.is_some()
+            && let Err(e) = self.save_metrics_snapshot().await
+        {
+            dns_warn!("关闭时保存性能指标快照失败: {}", e);
+        }
+        dns_debug!("SmartDnsResolver已关闭");
+    }
+    
+    /// 立即保存性能指标快照（需要先通过构建器启用快照）
+    pub async fn save_metrics_snapshot(&self) -> Result<()> {
+        let path = self.metrics_snapshot_path.as_ref()
+            .ok_or_else(|| DnsError::InvalidConfig("Metrics snapshot path not configured".to_string()))?;
+        let engine = self.decision_engine.as_ref()
+            .ok_or_else(|| DnsError::InvalidConfig("Metrics snapshot requires decision engine".to_string()))?;
+        let snapshot = engine.export_metrics().await;
+        SmartDecisionEngine::write_metrics_snapshot(&snapshot, path)
+    }
+    
     /// 执行DNS查询
+    /// 
+    /// 请求选项在这里统一生效：`timeout_ms` 限制整个查询（含重试和故障转移）的时长，超时记为
+    /// [`DnsError::Timeout`]；`disable_cache` 和 `capture_wire` 跳过缓存；`client_address` 作为ECS子网；
+    /// `qclass` 决定查询类别；`upstream_filter` 限定参与查询的上游；`enable_edns` 为false时不携带OPT记录，`enable_dnssec` 设置DO位（尚未验证签名）。
+    /// 选项不合法（见 [`DnsQueryRequest::validate`]）或查询失败时错误写入响应的 `error`。
+    /// 查询名本身是IP地址（如 `192.0.2.1`、`[2001:db8::1]`）的A/AAAA查询在本地以该地址应答，
+    /// `server_used` 为 [`LITERAL_SOURCE`](crate::builder::types::LITERAL_SOURCE)
     pub async fn query(&self, request: DnsQueryRequest) -> Result<DnsQueryResponse> {
+        Ok(self.query_keeping_error(request).await.0)
+    }
+    
+    /// 解析域名的A或AAAA地址，“没有记录”和查询失败是不同的错误
+    /// 
+    /// 域名不存在返回 [`DnsError::NxDomain`]，域名存在但没有该类型的地址记录（NODATA，例如只有CNAME）
+    /// 返回 [`DnsError::NoRecords`]，超时、传输错误等按原错误返回；成功时至少有一个地址。
+    /// 与 [`query`](Self::query) 一样写入查询历史并计入性能指标
+    pub async fn lookup_ip(&self, domain: &str, record_type: DnsRecordType) -> Result<Vec<IpAddr>> {
+        self.lookup_ip_with(DnsQueryRequest::new(domain, record_type)).await
+    }
+    
+    /// 同 [`lookup_ip`](Self::lookup_ip)，但带完整的请求选项（例如上游筛选）；记录类型须为A或AAAA
+    pub async fn lookup_ip_with(&self, request: DnsQueryRequest) -> Result<Vec<IpAddr>> {
+        if !matches!(request.record_type, DnsRecordType::A | DnsRecordType::AAAA) {
+            return Err(DnsError::InvalidConfig(format!(
+                "lookup_ip only supports A and AAAA, got {}", request.record_type.as_str()
+            )));
+        }
+        let (response, error) = self.query_keeping_error(request).await;
+        match error {
+            Some(e) => Err(e),
+            None => response.addresses(),
+        }
+    }
+    
+    /// 并发解析域名的A和AAAA地址，同时给出这组地址还能使用多久，供连接池决定何时重新解析
+    /// 
+    /// 某一类型出错或没有记录时只用另一类型的地址；两者都没有地址时返回A查询的错误，
+    /// 错误的区分与 [`lookup_ip`](Self::lookup_ip) 相同
+    pub async fn resolve_with_ttl(&self, domain: &str) -> Result<ResolvedAddrs> {
+        let ((v4, v4_error), (v6, _)) = futures::future::join(
+            self.query_keeping_error(DnsQueryRequest::new(domain, DnsRecordType::A)),
+            self.query_keeping_error(DnsQueryRequest::new(domain, DnsRecordType::AAAA)),
+        ).await;
+        
+        let mut addresses = Vec::new();
+        let mut min_ttl: Option<u32> = None;
+        let mut from_cache = true;
+        for response in [&v4, &v6] {
+            let before = addresses.len();
+            for record in &response.records {
+                if let DnsRecordValue::IpAddr(ip) = record.value {
+                    addresses.push(ip);
+                    min_ttl = Some(min_ttl.map_or(record.ttl, |ttl| ttl.min(record.ttl)));
+                }
+            }
+            if addresses.len() > before {
+                from_cache &= response.server_used.as_deref() == Some(CACHE_SOURCE);
+            }
+        }
+        
+        match min_ttl {
+            Some(ttl) => Ok(ResolvedAddrs {
+                addresses,
+                valid_for: Duration::from_secs(u64::from(ttl)),
+                from_cache,
+            }),
+            None => Err(v4_error
+                .or_else(|| v4.addresses().err())
+                .unwrap_or_else(|| DnsError::NoRecords { domain: domain.to_string(), answer_types: Vec::new() })),
+        }
+    }
+    
+    /// 不经缓存重新查询，并与之前保存的结果比较
+    /// 
+    /// 返回新的响应和两者的差异（见 [`DnsDiff::compare`]），`baseline` 一般是同一请求先前的响应；
+    /// 查询失败时新响应中没有记录、带有错误信息，差异中表现为记录消失和响应码变化
+    pub async fn query_and_compare(
+        &self,
+        request: DnsQueryRequest,
+        baseline: &DnsQueryResponse,
+    ) -> Result<(DnsQueryResponse, DnsDiffReport)> {
+        let response = self.query(request.disable_cache()).await?;
+        let diff = DnsDiff::compare(baseline, &response);
+        Ok((response, diff))
+    }
+    
+    /// 不经缓存查询区域当前的SOA序列号
+    /// 
+    /// `zone` 须为区域顶点（SOA记录在应答段）。错误的区分与 [`lookup_ip`](Self::lookup_ip) 相同，
+    /// 同样写入查询历史并计入性能指标
+    pub async fn get_zone_serial(&self, zone: &str) -> Result<u32> {
+        let request = DnsQueryRequest::new(zone, DnsRecordType::SOA).disable_cache();
+        let (response, error) = self.query_keeping_error(request).await;
+        match error {
+            Some(e) => Err(e),
+            None => response.soa_serial(),
+        }
+    }
+    
+    /// 订阅名称地址集合的变化，供持有长连接的服务在地址变化时重新均衡
+    /// 
+    /// 每隔 `interval` 不读缓存地重新解析一次（按 [`QueryPriority::Bulk`]），与上一次的地址集合比较（不计顺序），
+    /// 变化时产生 [`AddressEvent::Changed`](super::address_watch::AddressEvent::Changed)；第一次成功的解析只记录基准集合，
+    /// 需要当前地址时先调用 [`lookup_ip`](Self::lookup_ip)。域名不存在或没有该类型的记录按空集合处理。
+    /// 轮询失败时产生 [`AddressEvent::Error`](super::address_watch::AddressEvent::Error)，订阅继续，
+    /// 等待时间从 `interval` 起逐次加倍，最长10分钟（`interval` 更长时为 `interval`）。
+    /// 
+    /// 名称（不区分大小写，忽略末尾的点）、类型和间隔都相同的订阅共用一个后台任务，后加入的订阅只收到加入之后的变化；
+    /// 丢弃最后一个订阅时任务中止。任务只持有解析器的弱引用，解析器释放后所有订阅结束。
+    /// 记录类型不是A或AAAA、名称无效或 `interval` 为零时返回错误
+    pub fn watch(self: &Arc<Self>, domain: &str, record_type: DnsRecordType, interval: Duration) -> Result<AddressWatch> {
+        self.address_watches.subscribe(self, domain, record_type, interval)
+    }
+    
+    /// 创建共用本解析器的传输、缓存、上游监控和决策引擎，只改变 `overrides` 中策略的作用域解析器
+    /// 
+    /// 创建只复制两个 `Arc`，可以按请求创建；作用域的限制在查询路径内部生效，见 [`super::scope`]。
+    /// 作用域只允许的上游都不可用（或名称不存在）时，查询以 [`DnsError::NoUpstreamAvailable`] 等错误失败，不会改用作用域外的上游
+    pub fn scoped(self: &Arc<Self>, overrides: ScopeOverrides) -> ScopedResolver {
+        ScopedResolver::new(self.clone(), overrides)
+    }
+    
+    /// 正在运行的地址订阅轮询任务数，见 [`watch`](Self::watch)
+    pub fn address_watch_count(&self) -> usize {
+        self.address_watches.active()
+    }
+    
+    /// 执行查询并整理为响应，同时返回查询失败时的原始错误
+    /// 
+    /// 查询名本身是IP地址的A/AAAA查询直接以该地址应答，见 [`DnsQueryResponse::literal_answer`]。
+    /// 未指定查询ID时在这里按配置的格式分配（不分配内存，响应输出时才格式化），整个查询（含搜索域展开和中间件）在名为 `dns_query` 的tracing span中进行，
+    /// span带有查询ID、策略和请求上下文的字段；开启按租户统计时按上下文中的租户计数
+    async fn query_keeping_error(&self, request: DnsQueryRequest) -> (DnsQueryResponse, Option<DnsError>) {
+        let request = match scope::current() {
+            Some(scope) => scope.apply_defaults(request),
+            None => request,
+        };
+        // 整个查询使用同一份底层解析器和配置，查询期间重新加载不影响本次查询
+        let active = self.active();
+        if !active.config.log_query_summary {
+            return self.query_identified(&active, request).await;
+        }
+        
+        // 摘要模式：查询过程中的info日志降为debug，结束后输出一行摘要
+        let strategy = scope::strategy().unwrap_or(active.query_strategy);
+        let bypass = request.disable_cache
+            || !active.config.enable_cache
+            || (request.upstream_filter.is_some() && !request.upstream_filter_reads_cache);
+        let notes = Arc::new(crate::logger::SummaryNotes::default());
+        let (response, error) = Box::pin(crate::logger::summary_scope(notes.clone(), self.query_identified(&active, request))).await;
+        let cache = CacheStatus::of(&response, bypass, notes.served_stale());
+        dns_info!("{}", query_summary::summary_line(&response, strategy, cache, error.as_ref()));
+        (response, error)
+    }
+    
+    /// 分配查询ID并执行查询，记录租户统计
+    async fn query_identified(&self, active: &ActiveResolver, request: DnsQueryRequest) -> (DnsQueryResponse, Option<DnsError>) {
+        let query_id = match &request.query_id {
+            Some(id) => QueryId::from(id.clone()),
+            None => QueryId::generate(self.query_id_format),
+        };
+        // 查询名本身是IP地址时直接应答，不经过搜索域、中间件、缓存和上游
+        if let Some(mut response) = DnsQueryResponse::literal_answer(&request) {
+            response.query_id = query_id;
+            return (response, None);
+        }
+        let context = request.context.clone().unwrap_or_default();
+        let priority = request.priority();
+        let span = tracing::info_span!(
+            "dns_query",
+            query_id = %query_id,
+            domain = %request.domain,
+            record_type = request.record_type.as_str(),
+            strategy = ?scope::strategy().unwrap_or(active.query_strategy),
+            priority = priority.as_str(),
+            tenant = context.tenant.as_deref(),
+            trace_id = context.trace_id.as_deref(),
+            tags = %context.tag_list(),
+        );
+        
+        let (mut response, error) = priority::scope(priority, async {
+            let (response, error) = self.query_with_search_domains(active, request).await;
+            tracing::info!(
+                success = response.success,
+                server = response.server_used.as_deref(),
+                rcode = response.rcode,
+                duration_ms = response.duration_ms,
+                "DNS查询完成"
+            );
+            (response, error)
+        }.instrument(span)).await;
+        
+        if response.query_id.is_empty() {
+            response.query_id = query_id;
+        }
+        if let (Some(table), Some(tenant)) = (&self.tenant_stats, &context.tenant) {
+            table.record(tenant, response.success, response.server_used.as_deref() == Some(CACHE_SOURCE));
+        }
+        (response, error)
+    }
+    
+    /// 执行查询，启用搜索域时依次查询展开后的名称
+    /// 
+    /// NXDOMAIN或没有记录的应答继续尝试下一个名称，全部如此时返回最后一个应答；查询失败立即返回
+    async fn query_with_search_domains(&self, active: &ActiveResolver, request: DnsQueryRequest) -> (DnsQueryResponse, Option<DnsError>) {
+        let candidates = match &self.search_domains {
+            Some(search) => search.candidates(&request.domain),
+            None => return self.query_through_middlewares(active, request).await,
+        };
+        
+        let last = candidates.len() - 1;
+        for (index, domain) in candidates.into_iter().enumerate() {
+            let mut candidate = request.clone();
+            candidate.domain = domain;
+            let (response, error) = self.query_through_middlewares(active, candidate).await;
+            let negative = response.success
+                && (response.rcode.map(crate::types::ResponseCode::from) == Some(crate::types::ResponseCode::NxDomain) || response.records.is_empty());
+            if index == last || !negative {
+                return (response, error);
+            }
+            dns_debug!("搜索域展开: {} 没有应答，尝试下一个名称", response.domain);
+        }
+        unreachable!("search candidates are never empty")
+    }
+    
+    /// 经过中间件链执行查询并整理为响应，同时返回查询失败时的原始错误
+    /// 
+    /// 在作用域中查询时，作用域的中间件先于解析器自身的中间件执行
+    async fn query_through_middlewares(&self, active: &ActiveResolver, request: DnsQueryRequest) -> (DnsQueryResponse, Option<DnsError>) {
+        let scoped: Vec<Arc<dyn QueryMiddleware>> = scope::current()
+            .map(|scope| scope.middlewares.clone())
+            .unwrap_or_default();
+        if self.middlewares.is_empty() && scoped.is_empty() {
+            return self.resolve_request(active, request).await;
+        }
+        let chain: Vec<Arc<dyn QueryMiddleware>> = scoped.into_iter().chain(self.middlewares.iter().cloned()).collect();
+        
+        let error = Mutex::new(None);
+        let original = request.clone();
+        match Next::new(self, active, &chain, &error).run(request).await {
+            Ok(response) if response.success => (response, None),
+            Ok(response) => {
+                // 中间件自行给出的失败响应没有原始错误，按服务器错误返回
+                let error = error.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner()).unwrap_or_else(|| {
+                    DnsError::Server(response.error.clone().unwrap_or_else(|| "Query failed".to_string()))
+                });
+                (response, Some(error))
+            }
+            Err(e) => (DnsQueryResponse::failed(&original, &e), Some(e)),
+        }
+    }
+    
+    /// 执行查询并整理为响应（中间件链的末端），同时返回查询失败时的原始错误
+    pub(super) async fn resolve_request(&self, active: &ActiveResolver, request: DnsQueryRequest) -> (DnsQueryResponse, Option<DnsError>) {
         let start_time = Instant::now();
-        let query_id = request.query_id.clone().unwrap_or_else(|| Uuid::new_v4().to_string());
+        let query_id = request.query_id.clone().map(QueryId::from).unwrap_or_default();
+        
+        // 根据策略选择上游服务器，应急模式下改为向所有上游并发查询
+        let emergency_mode = self.resolver_mode() == ResolverMode::Emergency;
+        let selection = if request.explain { self.explain_request(active, &request, emergency_mode).await } else { None };
+        let attempts = AttemptLog::default();
+        let edns = QueryEdns { edns: request.enable_edns, dnssec_ok: request.enable_dnssec };
+        let result = query_edns::scope(edns, Box::pin(diagnosis::scope(attempts.clone(), async {
+            match (request.validate(), request.timeout()) {
+                (Err(e), _) => Err(e),
+                (Ok(()), Some(limit)) => {
+                    // 等待限流的上游时不越过请求超时；查询放在堆上，避免调试构建中层层嵌套的future撑满栈
+                    let query = Box::pin(self.query_response_with_mode(active, &request, emergency_mode));
+                    let query = rate_limit::with_deadline(Instant::now() + limit, query);
+                    runtime::timeout(limit, query)
+                        .await
+                        .unwrap_or_else(|_| {
+                            dns_debug!("查询 {} 超过请求超时 {:?}", request.domain, limit);
+                            Err(DnsError::Timeout { phase: None })
+                        })
+                }
+                (Ok(()), None) => self.query_response_with_mode(active, &request, emergency_mode).await,
+            }
+        }))).await;
+        let result = result.and_then(|answer| Self::check_scope_dnssec(&request, answer));
         
-        // 根据策略选择上游服务器
-        let result = match self.query_strategy {
-            QueryStrategy::Fifo => self.query_fifo(&request).await,
-            QueryStrategy::Smart => self.query_smart(&request).await,
-            QueryStrategy::RoundRobin => self.query_round_robin(&request).await,
+        // 多个上游都没有可用的应答时，以汇总诊断代替最后一个错误；最终应答为SERVFAIL/REFUSED时同样返回该错误
+        let final_rcode = result.as_ref().ok().map(|(response, _)| response.rcode());
+        let report = diagnosis::failure_report(&request.domain, request.record_type.as_str(), final_rcode, result.is_err(), attempts.take());
+        let aggregate = report.as_ref()
+            .filter(|report| report.upstream_count() > 1 && !report.attempts.iter().any(UpstreamAttempt::answered_usably))
+            .map(|report| DnsError::AllUpstreamsFailed { report: Box::new(report.clone()) });
+        let (result, error) = match (result, aggregate) {
+            (Err(_), Some(aggregate)) => (Err(aggregate.clone()), Some(aggregate)),
+            (result, aggregate) => {
+                let error = result.as_ref().err().cloned().or(aggregate);
+                (result, error)
+            }
         };
         
+        let mut response = self.finish_query(active, request, query_id, start_time, emergency_mode, result).await;
+        response.failure_report = report;
+        response.selection = selection;
+        (response, error)
+    }
+    
+    /// 请求要求解释时，智能策略将按评分选择上游的解释；上游不按评分选择时为 `None`
+    /// 
+    /// 判断与 [`query_response_with_mode`](Self::query_response_with_mode) 的分支一致
+    async fn explain_request(&self, active: &ActiveResolver, request: &DnsQueryRequest, emergency_mode: bool) -> Option<SelectionExplanation> {
+        let engine = self.decision_engine.as_ref()?;
+        let by_score = active.query_strategy == QueryStrategy::Smart
+            && !emergency_mode
+            && request.upstream_filter.is_none()
+            && scope::current().is_none()
+            && self.domain_router.as_ref().is_none_or(|router| matches!(router.route_for(&request.domain), None | Some(DomainRoute::Default)))
+            && self.sticky_pins.as_deref().is_none_or(|pins| pins.key_for(request).is_none());
+        if !by_score {
+            return None;
+        }
+        Some(engine.explain_selection().await)
+    }
+    
+    /// 智能策略按当前性能指标会选择哪个上游及原因，没有决策引擎时为 `None`
+    /// 
+    /// 见 [`SmartDecisionEngine::explain_selection`]；需要某次查询实际使用的解释时用
+    /// [`DnsQueryRequest::with_explain`]
+    pub async fn explain_selection(&self) -> Option<SelectionExplanation> {
+        match &self.decision_engine {
+            Some(engine) => Some(engine.explain_selection().await),
+            None => None,
+        }
+    }
+    
+    /// 作用域要求DNSSEC时，拒绝AD位未置位的应答
+    fn check_scope_dnssec(
+        request: &DnsQueryRequest,
+        answer: (SharedResponse, Option<TransportInfo>),
+    ) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        let required = scope::current().is_some_and(|scope| scope.require_dnssec);
+        if required && !answer.0.authenticated_data() {
+            dns_debug!("作用域要求DNSSEC: {} 的应答未经验证（AD位未置位）", request.domain);
+            return Err(DnsError::DnssecRequired { name: request.domain.clone() });
+        }
+        Ok(answer)
+    }
+    
+    /// 把上游查询结果整理为响应：更新应急判定和性能指标、写入查询历史，
+    /// 失败时错误信息写入响应而不是返回错误
+    async fn finish_query(
+        &self,
+        active: &ActiveResolver,
+        request: DnsQueryRequest,
+        query_id: QueryId,
+        start_time: Instant,
+        emergency_mode: bool,
+        result: Result<(SharedResponse, Option<TransportInfo>)>,
+    ) -> DnsQueryResponse {
         let duration = start_time.elapsed();
         
+        // 应急判定只统计经过上游的查询，命中缓存不计入
+        if let Some(engine) = &self.decision_engine {
+            match &result {
+                Ok((_, Some(_))) => { engine.record_query_outcome(true); },
+                Ok((_, None)) => {},
+                Err(_) => { engine.record_query_outcome(false); },
+            }
+        }
+        
         match result {
-            Ok((response, server_used)) => {
-                // 更新性能指标
-                if let Some(engine) = &self.decision_engine {
-                    engine.update_metrics(&server_used, duration, true, true).await;
+            Ok((response, info)) => {
+                // 更新性能指标（命中缓存时没有上游参与，不计入）
+                if let (Some(engine), Some(info)) = (&self.decision_engine, &info) {
+                    engine.update_metrics(&info.name, duration, true, None).await;
+                }
+                let cache_hit = info.is_none();
+                let rcode = response.extended_rcode();
+                let outcome = QueryOutcome::Success { rcode };
+                let failovers = info.as_ref().map(|info| info.failovers.clone()).unwrap_or_default();
+                let degraded_security = info.as_ref().is_some_and(|info| info.degraded_security);
+                let records_truncated = info.as_ref().is_some_and(|info| info.records_truncated);
+                let (server_used, protocol_used, upstream_peer, timing, wire) = match info {
+                    Some(info) => (info.name, info.protocol, info.peer, info.timing.map(TimingBreakdown::from), info.wire),
+                    None => (CACHE_SOURCE.to_string(), CACHE_SOURCE.to_string(), None, None, None),
+                };
+                let wire_truncated = wire.as_ref().is_some_and(|wire| wire.truncated);
+                let (wire_request, wire_response) = match wire {
+                    Some(wire) => (Some(wire.request), Some(wire.response)),
+                    None => (None, None),
+                };
+                let mut negative = response_negative_ttl(&response, request.record_type);
+                let mut records = self.answer_records(active, &response, &request);
+                // 作用域的TTL钳制只作用于返回给调用方的结果，共享缓存中的TTL不变
+                if let Some(clamp) = scope::current().and_then(|scope| scope.ttl_clamp()) {
+                    records.iter_mut().for_each(|record| record.ttl = clamp.clamp(record.ttl));
+                    if let Some(negative) = negative.as_mut() {
+                        negative.ttl = clamp.clamp(negative.ttl);
+                    }
                 }
                 
-                Ok(DnsQueryResponse {
+                let mut response = DnsQueryResponse {
                     query_id,
                     domain: request.domain,
                     record_type: request.record_type,
                     success: true,
                     error: None,
-                    records: self.convert_response_to_records(response),
+                    records,
                     duration_ms: duration.as_millis() as u64,
                     server_used: Some(server_used),
+                    upstream_peer,
+                    protocol_used: Some(protocol_used),
                     dnssec_status: Some(crate::builder::types::DnssecStatus::Indeterminate),
                     dnssec_records: Vec::new(),
-                })
+                    emergency_mode,
+                    rcode: Some(rcode),
+                    authenticated_data: response.authenticated_data(),
+                    valid_until: None,
+                    timing,
+                    wire_request,
+                    wire_response,
+                    wire_truncated,
+                    degraded_security,
+                    records_truncated,
+                    negative_ttl: negative.as_ref().map(|negative| negative.ttl),
+                    zone_apex: negative.map(|negative| negative.zone_apex),
+                    context: request.context,
+                    error_code: None,
+                    failure_report: None,
+                    selection: None,
+                };
+                let now = active.config.clock.as_ref().map_or_else(SystemTime::now, |clock| clock.now_system());
+                response.stamp_valid_until(now);
+                self.record_history(active, &response, outcome, cache_hit, duration, failovers);
+                response
             },
             Err(e) => {
-                Ok(DnsQueryResponse {
+                let response = DnsQueryResponse {
                     query_id,
                     domain: request.domain,
                     record_type: request.record_type,
                     success: false,
-                    error: Some(format!("查询失败 (策略: {:?}): {}", self.query_strategy, e)),
+                    error: Some(self.enhance_error_with_emergency_info(active, e).await),
                     records: Vec::new(),
                     duration_ms: duration.as_millis() as u64,
                     server_used: None,
+                    upstream_peer: None,
+                    protocol_used: None,
                     dnssec_status: Some(crate::builder::types::DnssecStatus::Indeterminate),
                     dnssec_records: Vec::new(),
-                })
+                    emergency_mode,
+                    rcode: None,
+                    authenticated_data: false,
+                    valid_until: None,
+                    timing: None,
+                    wire_request: None,
+                    wire_response: None,
+                    wire_truncated: false,
+                    degraded_security: false,
+                    records_truncated: false,
+                    negative_ttl: None,
+                    zone_apex: None,
+                    context: request.context,
+                    error_code: None,
+                    failure_report: None,
+                    selection: None,
+                };
+                self.record_history(active, &response, QueryOutcome::Failed, false, duration, Vec::new());
+                response
+            }
+        }
+    }
+    
+    /// 一次查询同一域名的多种记录类型
+    /// 
+    /// 各类型并发查询，同时进行的查询数不超过配置的 `concurrent_queries`；重复的类型只查询一次。
+    /// Smart策略下只做一次上游选择，各类型都优先使用该上游，某个类型在该上游失败时单独
+    /// 按查询策略重试。每个类型的结果与 [`query`](Self::query) 相同，某个类型失败只体现在
+    /// 该类型响应的 `success`/`error` 中，不影响其他类型。
+    pub async fn query_multi_types(
+        &self,
+        domain: &str,
+        types: Vec<DnsRecordType>,
+    ) -> Result<HashMap<DnsRecordType, DnsQueryResponse>> {
+        if types.is_empty() {
+            return Err(DnsError::InvalidConfig("At least one record type is required".to_string()));
+        }
+        let mut unique = Vec::with_capacity(types.len());
+        for record_type in types {
+            if !unique.contains(&record_type) {
+                unique.push(record_type);
+            }
+        }
+        
+        let active = self.active();
+        let emergency_mode = self.resolver_mode() == ResolverMode::Emergency;
+        let shared_upstream = match (&self.decision_engine, active.query_strategy) {
+            (Some(engine), QueryStrategy::Smart) if !emergency_mode => engine.select_smart_upstream().await,
+            _ => None,
+        };
+        if let Some(spec) = &shared_upstream {
+            dns_debug!("{} 的 {} 种记录类型共用上游 {}", domain, unique.len(), spec.name);
+        }
+        
+        let queries = unique.into_iter().map(|record_type| {
+            let shared_upstream = shared_upstream.as_ref();
+            let active = &*active;
+            async move {
+                let request = DnsQueryRequest::new(domain, record_type);
+                let query_id = QueryId::generate(self.query_id_format);
+                let start_time = Instant::now();
+                let result = match shared_upstream {
+                    Some(spec) => self.query_preferring(active, &request, &spec.name).await,
+                    None => self.query_response_with_mode(active, &request, emergency_mode).await,
+                };
+                let response = self.finish_query(active, request, query_id, start_time, emergency_mode, result).await;
+                (record_type, response)
             }
+        });
+        
+        Ok(futures::stream::iter(queries)
+            .buffer_unordered(active.config.concurrent_queries.max(1))
+            .collect()
+            .await)
+    }
+    
+    /// 优先向 `upstream` 查询，失败时按查询策略改用其他上游，并按实际应答的上游更新指标
+    async fn query_preferring(
+        &self,
+        active: &ActiveResolver,
+        request: &DnsQueryRequest,
+        upstream: &str,
+    ) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        if let Some(result) = self.query_by_domain_route(active, request).await {
+            return result;
         }
+        self.query_preferring_upstream(active, request, upstream).await
+    }
+    
+    /// 同 [`query_preferring`](Self::query_preferring)，但不检查域名转发规则
+    async fn query_preferring_upstream(
+        &self,
+        active: &ActiveResolver,
+        request: &DnsQueryRequest,
+        upstream: &str,
+    ) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        let record_type = self.convert_record_type(request.record_type);
+        let client_ip = request.client_address.as_ref()
+            .and_then(|ip| ip.parse().ok());
+        let start_time = Instant::now();
+        let result = active.resolver
+            .query_preferring(&request.domain, record_type, request.qclass, client_ip, upstream)
+            .await
+            .map(|(response, info)| (response.into(), info));
+        if let Some(engine) = &self.decision_engine {
+            score_query(engine, upstream, start_time.elapsed(), &result).await;
+        }
+        self.fail_over(active, request, result).await
+    }
+    
+    /// 写入查询历史（未启用时不做任何事）
+    fn record_history(
+        &self,
+        active: &ActiveResolver,
+        response: &DnsQueryResponse,
+        outcome: QueryOutcome,
+        cache_hit: bool,
+        duration: Duration,
+        failovers: Vec<UpstreamFailover>,
+    ) {
+        self.query_counters.record(response.record_type, &outcome);
+        let Some(history) = &self.query_history else {
+            return;
+        };
+        let upstream = match &response.server_used {
+            Some(name) if !cache_hit => Some(history.intern(name)),
+            _ => None,
+        };
+        history.record(QueryHistoryEntry {
+            sequence: 0,
+            timestamp: SystemTime::now(),
+            domain: history.intern(&response.domain),
+            record_type: response.record_type,
+            strategy: active.query_strategy,
+            upstream,
+            outcome,
+            duration,
+            cache_hit,
+            failovers,
+        });
+    }
+    
+    /// 启用查询历史记录
+    pub(super) fn enable_query_history(&mut self, capacity: usize) -> Result<()> {
+        let history = Arc::new(QueryHistory::new(capacity)?);
+        if let Some(budget) = self.active().resolver.memory_budget() {
+            history.attach_memory_budget(budget);
+        }
+        self.query_history = Some(history);
+        Ok(())
+    }
+    
+    /// 最近的n条查询历史，按时间倒序；未启用查询历史时返回空列表
+    pub fn recent_queries(&self, n: usize) -> Vec<QueryHistoryEntry> {
+        self.query_history.as_ref().map(|h| h.recent(n)).unwrap_or_default()
+    }
+    
+    /// 指定域名的查询历史，按时间倒序；未启用查询历史时返回空列表
+    pub fn queries_for_domain(&self, domain: &str) -> Vec<QueryHistoryEntry> {
+        self.query_history.as_ref().map(|h| h.for_domain(domain)).unwrap_or_default()
+    }
+    
+    /// 清空查询历史
+    pub fn clear_history(&self) {
+        if let Some(history) = &self.query_history {
+            history.clear();
+        }
+    }
+    
+    /// 按当前策略执行查询，返回原始DNS响应和实际给出响应的传输（命中缓存时为None）
+    async fn query_response(&self, request: &DnsQueryRequest) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        let emergency_mode = self.resolver_mode() == ResolverMode::Emergency;
+        self.query_response_with_mode(&self.active(), request, emergency_mode).await
+    }
+    
+    /// 按指定模式执行查询：应急模式忽略查询策略，向所有上游并发查询
+    async fn query_response_with_mode(
+        &self,
+        active: &ActiveResolver,
+        request: &DnsQueryRequest,
+        emergency_mode: bool,
+    ) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        // 上游筛选由调用方明确指定，优先于域名转发、应急模式和上游粘性
+        if let Some(filter) = &request.upstream_filter {
+            return self.query_filtered(active, request, filter).await;
+        }
+        if let Some(result) = self.query_by_domain_route(active, request).await {
+            return result;
+        }
+        if emergency_mode {
+            return self.query_emergency(active, request).await;
+        }
+        let sticky = self.sticky_pins.as_deref()
+            .and_then(|pins| pins.key_for(request).map(|key| (pins, key)));
+        if let Some((pins, key)) = sticky {
+            return self.query_sticky(active, request, pins, key).await;
+        }
+        self.query_by_strategy(active, request).await
+    }
+    
+    /// 按查询策略选择上游查询，失败时故障转移
+    async fn query_by_strategy(&self, active: &ActiveResolver, request: &DnsQueryRequest) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        // 作用域内上游完全由核心解析器按作用域的限制和策略选择：决策引擎的选择可能落在作用域之外，
+        // 因此只按实际应答的上游记录指标
+        if scope::current().is_some() {
+            let result = self.query_selected_by_core(active, request).await;
+            return self.fail_over(active, request, result).await;
+        }
+        let result = match active.query_strategy {
+            QueryStrategy::Fifo => self.query_fifo(active, request).await,
+            QueryStrategy::Smart => self.query_smart(active, request).await,
+            QueryStrategy::RoundRobin => self.query_round_robin(active, request).await,
+            QueryStrategy::Sequential | QueryStrategy::Quorum { .. } => self.query_selected_by_core(active, request).await,
+        };
+        self.fail_over(active, request, result).await
+    }
+    
+    /// 带上游筛选的查询：核心解析器只在筛选后的上游中按查询策略选择，不转移到筛选外的上游；
+    /// 按实际应答的上游记录指标
+    async fn query_filtered(
+        &self,
+        active: &ActiveResolver,
+        request: &DnsQueryRequest,
+        filter: &UpstreamFilter,
+    ) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        let record_type = self.convert_record_type(request.record_type);
+        let client_ip = request.client_address.as_ref()
+            .and_then(|ip| ip.parse().ok());
+        let read_cache = request.upstream_filter_reads_cache && !request.disable_cache;
+        
+        let start_time = Instant::now();
+        let result = active.resolver
+            .query_filtered(filter, &request.domain, record_type, request.qclass, client_ip, read_cache, request.capture_wire)
+            .await;
+        if let Some(engine) = &self.decision_engine {
+            match &result {
+                Ok((_, Some(info))) => engine.update_metrics(&info.name, start_time.elapsed(), true, None).await,
+                Ok((_, None)) => engine.record_cache_hit(),
+                Err(_) => {}
+            }
+        }
+        result
+    }
+    
+    /// 带粘性键的查询：记住的上游仍可用时优先使用它，否则按查询策略选择；
+    /// 查询成功后记住实际应答的上游（命中缓存时不更新记录）
+    async fn query_sticky(
+        &self,
+        active: &ActiveResolver,
+        request: &DnsQueryRequest,
+        pins: &StickyPins,
+        key: String,
+    ) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        let available = active.resolver.available_transport_names();
+        let pinned = pins.preferred(&key, |upstream| available.iter().any(|name| name == upstream));
+        let result = match &pinned {
+            Some(upstream) => self.query_preferring_upstream(active, request, upstream).await,
+            None => self.query_by_strategy(active, request).await,
+        };
+        if let Ok((_, Some(info))) = &result {
+            if pinned.as_deref() != Some(info.name.as_str()) {
+                dns_debug!("上游粘性: {} 改为使用 {}", key, info.name);
+            }
+            pins.pin(key, &info.name);
+        }
+        result
+    }
+    
+    /// 按域名转发规则查询，域名没有规则（或规则为按查询策略）时返回 `None`
+    /// 
+    /// 规则指定的上游依次尝试：出错或应答SERVFAIL/REFUSED时换下一个，都不行时返回
+    /// 最后一个SERVFAIL/REFUSED应答（没有则返回最后的错误）。本地应答的规则直接给出NXDOMAIN。
+    /// 规则开启了QNAME最小化时，向每个上游发送完整查询前先逐级查询NS
+    async fn query_by_domain_route(
+        &self,
+        active: &ActiveResolver,
+        request: &DnsQueryRequest,
+    ) -> Option<Result<(SharedResponse, Option<TransportInfo>)>> {
+        let router = self.domain_router.as_ref()?;
+        let upstreams = match router.route_for(&request.domain)? {
+            DomainRoute::Default => return None,
+            DomainRoute::Local => return Some(Ok(self.local_answer(request))),
+            DomainRoute::Upstreams(upstreams) => upstreams,
+        };
+        let minimisation_apex = router.qname_minimisation_apex(&request.domain);
+        let record_type = self.convert_record_type(request.record_type);
+        let client_ip = request.client_address.as_ref()
+            .and_then(|ip| ip.parse().ok());
+        
+        let mut failovers = Vec::new();
+        let mut passed_over = None;
+        let mut last_error = None;
+        for upstream in upstreams {
+            if let Some(apex) = minimisation_apex {
+                let steps = active.resolver.minimise_towards(upstream, apex, &request.domain, request.qclass).await;
+                dns_debug!("QNAME最小化: {} 经 {} 发送了 {} 个中间查询", request.domain, upstream, steps);
+            }
+            let attempt: Result<(SharedResponse, Option<TransportInfo>)> = if request.capture_wire || request.disable_cache {
+                active.resolver
+                    .query_only_transport(upstream, &request.domain, record_type, request.qclass, client_ip, request.capture_wire)
+                    .await
+                    .map(|(response, info)| (response.into(), Some(info)))
+            } else {
+                active.resolver
+                    .query_via_transport(upstream, &request.domain, record_type, request.qclass, client_ip)
+                    .await
+                    .map(|(response, info)| (response.into(), info))
+            };
+            match attempt {
+                Ok((response, info)) => match (failover_rcode(&response), info) {
+                    (Some(rcode), Some(info)) => {
+                        dns_info!("🔁 转发上游 {} 对 {} 返回 {:?}", upstream, request.domain, rcode);
+                        failovers.push(UpstreamFailover { upstream: upstream.clone(), rcode });
+                        passed_over = Some((response, info));
+                    }
+                    (_, mut info) => {
+                        if let Some(info) = info.as_mut() {
+                            info.failovers = failovers;
+                        }
+                        return Some(Ok((response, info)));
+                    }
+                },
+                Err(e) => {
+                    dns_warn!("转发上游 {} 查询 {} 失败: {}", upstream, request.domain, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Some(match passed_over {
+            Some((response, mut info)) => {
+                failovers.pop();
+                info.failovers = failovers;
+                Ok((response, Some(info)))
+            }
+            None => Err(last_error.unwrap_or(DnsError::NoUpstreamAvailable { filter: None })),
+        })
+    }
+    
+    /// 本地应答：NXDOMAIN，不经过任何上游
+    fn local_answer(&self, request: &DnsQueryRequest) -> (SharedResponse, Option<TransportInfo>) {
+        let record_type = self.convert_record_type(request.record_type);
+        let response = crate::dns_response::DnsResponseWrapper::create_nxdomain_response(0, &request.domain, record_type);
+        let info = TransportInfo {
+            name: LOCAL_SOURCE.to_string(),
+            protocol: LOCAL_SOURCE.to_string(),
+            duration: Duration::ZERO,
+            timing: None,
+            wire: None,
+            client_subnet: None,
+            failovers: Vec::new(),
+            peer: None,
+            degraded_security: false,
+            records_truncated: false,
+        };
+        (response.into(), Some(info))
+    }
+    
+    /// 应答为SERVFAIL/REFUSED时改向尚未尝试过的上游查询
+    /// 
+    /// 并发策略中已经应答过SERVFAIL/REFUSED的上游也算已尝试；一次查询最多尝试
+    /// `max_failover_attempts` 个上游（不超过上游总数），换上游的查询不读缓存。
+    /// 没有可换的上游或重试出错时保留最后一个SERVFAIL/REFUSED应答；
+    /// 每次转移及其响应码记入最终应答的 [`TransportInfo::failovers`]
+    async fn fail_over(
+        &self,
+        active: &ActiveResolver,
+        request: &DnsQueryRequest,
+        result: Result<(SharedResponse, Option<TransportInfo>)>,
+    ) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        let (mut response, mut info) = match result {
+            Ok((response, Some(info))) => (response, info),
+            other => return other,
+        };
+        let record_type = self.convert_record_type(request.record_type);
+        let client_ip = request.client_address.as_ref()
+            .and_then(|ip| ip.parse().ok());
+        let max_attempts = active.config.max_failover_attempts.min(active.resolver.transport_count());
+        
+        let mut tried: Vec<String> = info.failovers.iter().map(|failover| failover.upstream.clone()).collect();
+        tried.push(info.name.clone());
+        while tried.len() < max_attempts {
+            let Some(rcode) = failover_rcode(&response) else {
+                break;
+            };
+            let Some(next) = active.resolver.available_transport_names().into_iter().find(|name| !tried.contains(name)) else {
+                break;
+            };
+            dns_info!("🔁 {} 对 {} 返回 {:?}，改向 {} 查询", info.name, request.domain, rcode, next);
+            tried.push(next.clone());
+            
+            let start_time = Instant::now();
+            let attempt = active.resolver
+                .query_only_transport(&next, &request.domain, record_type, request.qclass, client_ip, request.capture_wire)
+                .await;
+            if let Some(engine) = &self.decision_engine {
+                engine.update_metrics(&next, start_time.elapsed(), attempt.is_ok(), None).await;
+            }
+            match attempt {
+                Ok((next_response, mut next_info)) => {
+                    let mut failovers = std::mem::take(&mut info.failovers);
+                    failovers.push(UpstreamFailover { upstream: info.name.clone(), rcode });
+                    failovers.append(&mut next_info.failovers);
+                    next_info.failovers = failovers;
+                    response = next_response.into();
+                    info = next_info;
+                }
+                Err(e) => dns_warn!("故障转移到 {} 查询 {} 失败: {}", next, request.domain, e),
+            }
+        }
+        Ok((response, Some(info)))
+    }
+    
+    /// 查询指定上游的软件版本（CH类TXT `version.bind`），返回各条TXT记录的内容
+    /// 
+    /// 只发给该上游（包括已停用的上游），不经缓存。上游拒绝回答时返回相应的错误
+    /// （通常为 [`DnsError::Refused`]），应答为空时返回 [`DnsError::NoRecords`]
+    pub async fn query_server_version(&self, upstream_name: &str) -> Result<Vec<String>> {
+        self.query_chaos_txt(upstream_name, &["version.bind"]).await
+    }
+    
+    /// 查询指定上游的节点标识（CH类TXT `id.server`，不支持时改查 `hostname.bind`），
+    /// 用于确认任播地址背后实际应答的节点；错误同 [`query_server_version`](Self::query_server_version)
+    pub async fn query_server_id(&self, upstream_name: &str) -> Result<Vec<String>> {
+        self.query_chaos_txt(upstream_name, &["id.server", "hostname.bind"]).await
+    }
+    
+    /// 依次查询CH类TXT名称，返回第一个有TXT记录的应答
+    async fn query_chaos_txt(&self, upstream_name: &str, names: &[&str]) -> Result<Vec<String>> {
+        let active = self.active();
+        let mut last_error = None;
+        for name in names {
+            let response = active.resolver
+                .query_transport(upstream_name, name, crate::types::RecordType::TXT, crate::types::QClass::CH)
+                .await?;
+            let texts = lookup::txt_strings(&response);
+            let error = match response.rcode() {
+                crate::types::ResponseCode::NoError if !texts.is_empty() => return Ok(texts),
+                crate::types::ResponseCode::NoError => DnsError::NoRecords { domain: name.to_string(), answer_types: Vec::new() },
+                crate::types::ResponseCode::NxDomain => DnsError::NxDomain,
+                crate::types::ResponseCode::Refused => DnsError::Refused,
+                crate::types::ResponseCode::ServerFailure => DnsError::ServerFailure,
+                rcode => DnsError::Server(format!("{} answered {} for {} CH TXT", upstream_name, rcode.name(), name)),
+            };
+            dns_debug!("上游 {} 未回答 {} CH TXT: {}", upstream_name, name, error);
+            last_error = Some(error);
+        }
+        Err(last_error.unwrap_or_else(|| DnsError::InvalidConfig("No CHAOS names to query".to_string())))
+    }
+    
+    /// 解析SRV服务
+    /// 
+    /// 查询 `_service._proto.name` 的SRV记录，按RFC 2782排序（优先级升序、同优先级加权随机），
+    /// 然后并发解析每个目标的A/AAAA地址；上游在附加段提供了胶水记录时直接使用，不再额外查询。
+    /// 目标为 "." 表示该服务明确不可用，返回 [`DnsError::ServiceUnavailable`]。
+    pub async fn resolve_srv(&self, service: &str, proto: &str, name: &str) -> Result<Vec<SrvTarget>> {
+        let qname = format!(
+            "_{}._{}.{}",
+            service.trim_start_matches('_'),
+            proto.trim_start_matches('_'),
+            name.trim_end_matches('.')
+        );
+        let request = DnsQueryRequest::new(qname.clone(), DnsRecordType::SRV);
+        let (response, _) = self.query_response(&request).await?;
+        
+        let targets = lookup::srv_targets(&response);
+        if targets.len() == 1 && lookup::is_root_target(&targets[0].host) {
+            return Err(DnsError::ServiceUnavailable(format!("{} (SRV target is '.')", qname)));
+        }
+        
+        let glue = lookup::glue_addresses(&response);
+        let ordered = lookup::order_srv_targets(targets, &mut RandomRng(self.active().resolver.random_source().as_ref()));
+        
+        let resolved = ordered.into_iter().map(|mut target| {
+            let glue_hit = glue.get(&lookup::normalize_host(&target.host)).cloned();
+            async move {
+                target.addresses = match glue_hit {
+                    Some(addresses) => {
+                        dns_debug!("SRV目标 {} 使用附加段胶水记录", target.host);
+                        addresses
+                    },
+                    None => self.resolve_host_addresses(&target.host).await,
+                };
+                target
+            }
+        });
+        
+        Ok(futures::future::join_all(resolved).await)
+    }
+    
+    /// 解析MX记录及各邮件交换主机的地址
+    /// 
+    /// 结果按优先级升序排列；每个交换主机的A/AAAA地址并发解析，同时解析的主机数
+    /// 不超过 `options.max_concurrency`，上游在附加段提供了胶水记录时直接使用。
+    /// 空MX（"0 ."）返回 [`MxResolution::NullMx`]，以便与查询失败区分。
+    /// 没有MX记录且启用了 `implicit_mx_fallback` 时，按RFC 5321回退到域名自身的地址。
+    pub async fn resolve_mx_with_addresses(&self, domain: &str, options: MxLookupOptions) -> Result<MxResolution> {
+        if options.max_concurrency == 0 {
+            return Err(DnsError::InvalidConfig("MX地址解析并发数必须大于0".to_string()));
+        }
+        
+        let domain = domain.trim_end_matches('.');
+        let request = DnsQueryRequest::new(domain, DnsRecordType::MX);
+        let (response, _) = self.query_response(&request).await?;
+        
+        let hosts = lookup::mx_hosts(&response);
+        if lookup::is_null_mx(&hosts) {
+            dns_debug!("{} 声明了空MX，不接收邮件", domain);
+            return Ok(MxResolution::NullMx);
+        }
+        
+        if hosts.is_empty() {
+            if !options.implicit_mx_fallback {
+                return Ok(MxResolution::NoMx);
+            }
+            let addresses = self.resolve_host_addresses(domain).await;
+            if addresses.is_empty() {
+                return Ok(MxResolution::NoMx);
+            }
+            dns_debug!("{} 没有MX记录，使用隐式MX", domain);
+            return Ok(MxResolution::Hosts(vec![MxHost {
+                exchange: domain.to_string(),
+                priority: 0,
+                addresses,
+                implicit: true,
+            }]));
+        }
+        
+        let glue = lookup::glue_addresses(&response);
+        let resolved = hosts.into_iter()
+            .filter(|host| !lookup::is_root_target(&host.exchange))
+            .map(|mut host| {
+                let glue_hit = glue.get(&lookup::normalize_host(&host.exchange)).cloned();
+                async move {
+                    host.addresses = match glue_hit {
+                        Some(addresses) => {
+                            dns_debug!("MX主机 {} 使用附加段胶水记录", host.exchange);
+                            addresses
+                        },
+                        None => self.resolve_host_addresses(&host.exchange).await,
+                    };
+                    host
+                }
+            });
+        
+        let hosts = futures::stream::iter(resolved)
+            .buffered(options.max_concurrency)
+            .collect::<Vec<_>>()
+            .await;
+        
+        Ok(MxResolution::Hosts(hosts))
+    }
+    
+    /// 并发解析主机的A和AAAA地址，失败的记录类型被忽略
+    async fn resolve_host_addresses(&self, host: &str) -> Vec<IpAddr> {
+        let (v4, v6) = futures::future::join(
+            self.query(DnsQueryRequest::new(host, DnsRecordType::A)),
+            self.query(DnsQueryRequest::new(host, DnsRecordType::AAAA)),
+        ).await;
+        
+        let mut addresses = Vec::new();
+        for response in [v4, v6].into_iter().flatten() {
+            addresses.extend(response.ip_addresses());
+        }
+        addresses
+    }
+    
+    /// 向所有上游分别查询并比对答案（诊断模式）
+    /// 
+    /// 每个上游都会收到查询，同时进行的查询数不超过配置的 `concurrent_queries`；
+    /// 绕过缓存，只有 `update_metrics` 为true时才把各上游的结果计入决策引擎指标。
+    /// 返回各上游的答案以及多数答案、不一致上游的汇总，用于发现DNS污染。
+    pub async fn query_all(&self, request: &DnsQueryRequest, update_metrics: bool) -> Result<QueryAllReport> {
+        let record_type = self.convert_record_type(request.record_type);
+        let client_ip = request.client_address.as_ref()
+            .and_then(|ip| ip.parse().ok());
+        
+        let active = self.active();
+        let results = active.resolver.query_each(
+            &request.domain,
+            record_type,
+            request.qclass,
+            client_ip,
+            active.config.concurrent_queries,
+        ).await?;
+        
+        let mut answers = Vec::with_capacity(results.len());
+        for (info, result) in results {
+            if update_metrics
+                && let Some(engine) = &self.decision_engine
+            {
+                let success = result.is_ok();
+                engine.update_metrics(&info.name, info.duration, success, None).await;
+            }
+            answers.push(PerUpstreamAnswer {
+                upstream: info.name,
+                protocol: info.protocol,
+                latency: info.duration,
+                result: result
+                    .map(|response| consensus::answer_set(&response, record_type))
+                    .map_err(|e| e.to_string()),
+            });
+        }
+        
+        let report = QueryAllReport::new(answers);
+        if !report.consensus.dissenting.is_empty() {
+            dns_warn!(
+                "{} ({:?}) 的上游答案不一致: [{}]",
+                request.domain,
+                request.record_type,
+                report.consensus.dissenting.iter().map(|d| d.upstream.as_str()).collect::<Vec<_>>().join(", ")
+            );
+        }
+        Ok(report)
+    }
+    
+    /// 批量执行DNS查询
+    /// 
+    /// 所有请求并发执行，结果顺序与请求顺序一致；没有设置优先级的请求按 [`QueryPriority::Bulk`] 查询
+    pub async fn batch_query(&self, requests: Vec<DnsQueryRequest>) -> Vec<Result<DnsQueryResponse>> {
+        let futures = requests.into_iter().map(|mut request| {
+            request.priority.get_or_insert(QueryPriority::Bulk);
+            self.query(request)
+        });
+        futures::future::join_all(futures).await
+    }
+    
+    /// 预热缓存：按正常的查询路径解析每个条目，写入缓存并计入性能指标
+    /// 
+    /// 查询按 [`QueryPriority::Bulk`] 发送，不占用交互式查询的名额。
+    /// 同时进行的查询不超过 `options.max_concurrency` 个。`continue_on_error` 为false时第一个失败后
+    /// 不再发起新的查询；到达 `options.deadline` 时取消尚未完成的查询并立即返回，不留下后台任务
+    pub async fn warm_cache(&self, entries: Vec<(String, DnsRecordType)>, options: WarmOptions) -> Result<WarmReport> {
+        if options.max_concurrency == 0 {
+            return Err(DnsError::InvalidConfig("Warmup concurrency must be greater than zero".to_string()));
+        }
+        
+        let start = Instant::now();
+        let total = entries.len();
+        let started = AtomicUsize::new(0);
+        let mut report = WarmReport::default();
+        let mut results = futures::stream::iter(entries)
+            .map(|(domain, record_type)| {
+                started.fetch_add(1, Ordering::Relaxed);
+                async move {
+                    let (_, error) = self.query_keeping_error(
+                        DnsQueryRequest::new(domain.clone(), record_type).with_priority(QueryPriority::Bulk)
+                    ).await;
+                    (domain, error)
+                }
+            })
+            .buffer_unordered(options.max_concurrency);
+        
+        let drain = async {
+            while let Some((domain, error)) = results.next().await {
+                match error {
+                    None => report.succeeded += 1,
+                    Some(e) => {
+                        dns_debug!("预热 {} 失败: {}", domain, e);
+                        report.failed.push((domain, e));
+                        if !options.continue_on_error {
+                            break;
+                        }
+                    }
+                }
+            }
+        };
+        let deadline_exceeded = match options.deadline {
+            Some(limit) => runtime::timeout(limit, drain).await.is_err(),
+            None => {
+                drain.await;
+                false
+            }
+        };
+        // 到期时尚未完成的查询随流一起取消
+        drop(results);
+        
+        report.deadline_exceeded = deadline_exceeded;
+        report.attempted = started.load(Ordering::Relaxed);
+        report.skipped = total - report.attempted;
+        report.elapsed = start.elapsed();
+        dns_info!(
+            "缓存预热完成: 成功 {}，失败 {}，取消 {}，跳过 {}，耗时 {:?}",
+            report.succeeded, report.failed.len(), report.cancelled(), report.skipped, report.elapsed
+        );
+        Ok(report)
+    }
+    
+    /// 处理bincode编码的查询请求
+    /// 
+    /// 输入为bincode编码的 [`DnsQueryRequest`]，输出为bincode编码的 [`DnsQueryResponse`]，
+    /// 便于跨语言或跨进程调用时避免逐字段转换。输入按 [`EncodedRequestLimits`](super::EncodedRequestLimits) 检查大小，解码后检查查询名称和字段长度；
+    /// 无效输入不发起查询，返回 `success` 为false、`error_code` 标明原因的响应
+    #[cfg(feature = "serde-api")]
+    pub async fn process_encoded_query(&self, encoded_request: &[u8]) -> Result<Vec<u8>> {
+        let response = match encoded::decode_request(encoded_request, &self.active().config.encoded_request_limits) {
+            Ok(request) => self.query(request).await?,
+            Err((code, error)) => {
+                dns_debug!("拒绝编码查询请求: {:?}: {}", code, error);
+                DnsQueryResponse::rejected(&DnsQueryRequest::new("", DnsRecordType::A), code, &error)
+            }
+        };
+        
+        bincode::encode_to_vec(&response, bincode::config::standard())
+            .map_err(|e| DnsError::Parse(format!("Failed to encode query response: {}", e)))
+    }
+    
+    /// 处理bincode编码的批量查询请求
+    /// 
+    /// 输入为bincode编码的 `Vec<DnsQueryRequest>`，输出为顺序相同的bincode编码的 `Vec<DnsQueryResponse>`，
+    /// 有效的请求按 [`batch_query`](Self::batch_query) 并发查询。查询数超过 [`EncodedRequestLimits::max_batch_len`](super::EncodedRequestLimits::max_batch_len)
+    /// 或整批无法解码时只返回一个带错误代码的失败响应；单个请求无效时只有它对应的响应失败
+    #[cfg(feature = "serde-api")]
+    pub async fn process_encoded_batch(&self, encoded_requests: &[u8]) -> Result<Vec<u8>> {
+        let responses = match encoded::decode_batch(encoded_requests, &self.active().config.encoded_request_limits) {
+            Ok(requests) => {
+                let mut responses: Vec<Option<DnsQueryResponse>> = Vec::with_capacity(requests.len());
+                let (mut positions, mut valid) = (Vec::new(), Vec::new());
+                for request in requests {
+                    match encoded::validate_request(&request) {
+                        Ok(()) => {
+                            positions.push(responses.len());
+                            responses.push(None);
+                            valid.push(request);
+                        }
+                        Err((code, error)) => responses.push(Some(DnsQueryResponse::rejected(&request, code, &error))),
+                    }
+                }
+                let results = self.batch_query(valid.clone()).await;
+                for ((position, request), result) in positions.into_iter().zip(&valid).zip(results) {
+                    responses[position] = Some(result.unwrap_or_else(|error| DnsQueryResponse::failed(request, &error)));
+                }
+                responses.into_iter().flatten().collect()
+            }
+            Err((code, error)) => {
+                dns_debug!("拒绝编码批量请求: {:?}: {}", code, error);
+                vec![DnsQueryResponse::rejected(&DnsQueryRequest::new("", DnsRecordType::A), code, &error)]
+            }
+        };
+        
+        bincode::encode_to_vec(&responses, bincode::config::standard())
+            .map_err(|e| DnsError::Parse(format!("Failed to encode query responses: {}", e)))
     }
     
     /// FIFO查询策略
-    async fn query_fifo(&self, request: &DnsQueryRequest) -> Result<(crate::Response, String)> {
+    async fn query_fifo(&self, active: &ActiveResolver, request: &DnsQueryRequest) -> Result<(SharedResponse, Option<TransportInfo>)> {
         let record_type = self.convert_record_type(request.record_type);
 
         // 解析 client_address
@@ -252,21 +2001,11 @@ impl SmartDnsResolver {
             // 使用决策引擎按FIFO顺序选择服务器
             if let Some(spec) = engine.select_fifo_upstream().await {
                 let start_time = Instant::now();
-
-                match self.resolver.query_with_client_ip(&request.domain, record_type, crate::types::QClass::IN, client_ip).await {
-                    Ok(response) => {
-                        let duration = start_time.elapsed();
-                        engine.update_metrics(&spec.name, duration, true, true).await;
-                        Ok((response, spec.name))
-                    },
-                    Err(e) => {
-                        let duration = start_time.elapsed();
-                        engine.update_metrics(&spec.name, duration, false, false).await;
-                        Err(e)
-                    }
-                }
+                let result = self.query_core(active, request, record_type, client_ip).await;
+                score_query(engine, &spec.name, start_time.elapsed(), &result).await;
+                result
             } else {
-                Err(DnsError::NoUpstreamAvailable)
+                Err(DnsError::NoUpstreamAvailable { filter: None })
             }
         } else {
             // 没有决策引擎，无法执行FIFO策略
@@ -275,7 +2014,7 @@ impl SmartDnsResolver {
     }
     
     /// 智能查询策略
-    async fn query_smart(&self, request: &DnsQueryRequest) -> Result<(crate::Response, String)> {
+    async fn query_smart(&self, active: &ActiveResolver, request: &DnsQueryRequest) -> Result<(SharedResponse, Option<TransportInfo>)> {
         let record_type = self.convert_record_type(request.record_type);
 
         // 解析 client_address
@@ -286,50 +2025,91 @@ impl SmartDnsResolver {
             // 使用决策引擎选择最优服务器
             if let Some(spec) = engine.select_smart_upstream().await {
                 let start_time = Instant::now();
-
-                match self.resolver.query_with_client_ip(&request.domain, record_type, crate::types::QClass::IN, client_ip).await {
-                    Ok(response) => {
-                        let duration = start_time.elapsed();
-                        engine.update_metrics(&spec.name, duration, true, true).await;
-                        Ok((response, spec.name))
-                    },
-                    Err(e) => {
-                        let duration = start_time.elapsed();
-                        engine.update_metrics(&spec.name, duration, false, false).await;
-                        Err(e)
-                    }
-                }
+                let result = self.query_core(active, request, record_type, client_ip).await;
+                score_query(engine, &spec.name, start_time.elapsed(), &result).await;
+                result
             } else {
-                Err(DnsError::NoUpstreamAvailable)
+                Err(DnsError::NoUpstreamAvailable { filter: None })
             }
         } else {
             Err(DnsError::InvalidConfig("Smart strategy requires decision engine".to_string()))
         }
     }
     
+    /// 顺序和法定人数策略：上游的选择完全由核心解析器进行，这里只记录应答上游的指标
+    async fn query_selected_by_core(&self, active: &ActiveResolver, request: &DnsQueryRequest) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        let record_type = self.convert_record_type(request.record_type);
+        let client_ip = request.client_address.as_ref()
+            .and_then(|ip| ip.parse().ok());
+        
+        let start_time = Instant::now();
+        let result = self.query_core(active, request, record_type, client_ip).await;
+        if let Some(engine) = &self.decision_engine {
+            match &result {
+                Ok((_, Some(info))) => engine.update_metrics(&info.name, start_time.elapsed(), true, None).await,
+                Ok((_, None)) => engine.record_cache_hit(),
+                Err(_) => {}
+            }
+        }
+        result
+    }
+    
+    /// 向核心解析器查询；请求禁用缓存或要求保留原始报文时直接查询上游
+    async fn query_core(
+        &self,
+        active: &ActiveResolver,
+        request: &DnsQueryRequest,
+        record_type: crate::types::RecordType,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        if request.capture_wire {
+            active.resolver
+                .query_with_wire_capture(&request.domain, record_type, request.qclass, client_ip)
+                .await
+                .map(|(response, info)| (response.into(), Some(info)))
+        } else if request.disable_cache {
+            active.resolver
+                .query_uncached(&request.domain, record_type, request.qclass, client_ip)
+                .await
+                .map(|(response, info)| (response.into(), Some(info)))
+        } else {
+            // 命中缓存时与缓存共用响应，转换为记录时才复制所需的数据
+            active.resolver
+                .query_shared(&request.domain, record_type, request.qclass, client_ip)
+                .await
+                .map(|(response, origin)| (response, origin.into_transport_info()))
+        }
+    }
+    
+    /// 应急查询：向所有上游并发查询，并按应急参数抬高响应TTL
+    async fn query_emergency(&self, active: &ActiveResolver, request: &DnsQueryRequest) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        let engine = self.decision_engine.as_ref()
+            .ok_or_else(|| DnsError::InvalidConfig("Emergency mode requires decision engine".to_string()))?;
+        let ttl_floor = engine.emergency_policy()
+            .map(|policy| policy.ttl_floor)
+            .ok_or_else(|| DnsError::InvalidConfig("Emergency mode requires an emergency policy".to_string()))?;
+        let record_type = self.convert_record_type(request.record_type);
+        let client_ip = request.client_address.as_ref()
+            .and_then(|ip| ip.parse().ok());
+        
+        let start_time = Instant::now();
+        let (response, info) = active.resolver
+            .query_fan_out_with_info(&request.domain, record_type, request.qclass, client_ip, ttl_floor)
+            .await?;
+        match &info {
+            Some(info) => engine.update_metrics(&info.name, start_time.elapsed(), true, None).await,
+            None => engine.record_cache_hit(),
+        }
+        Ok((response.into(), info))
+    }
+    
     /// 转换记录类型
     fn convert_record_type(&self, record_type: DnsRecordType) -> crate::types::RecordType {
-        match record_type {
-            DnsRecordType::A => crate::types::RecordType::A,
-            DnsRecordType::AAAA => crate::types::RecordType::AAAA,
-            DnsRecordType::CNAME => crate::types::RecordType::CNAME,
-            DnsRecordType::MX => crate::types::RecordType::MX,
-            DnsRecordType::TXT => crate::types::RecordType::TXT,
-            DnsRecordType::NS => crate::types::RecordType::NS,
-            DnsRecordType::PTR => crate::types::RecordType::PTR,
-            DnsRecordType::SRV => crate::types::RecordType::SRV,
-            DnsRecordType::SOA => crate::types::RecordType::SOA,
-            // DNSSEC记录类型映射到Unknown类型，使用标准的DNSSEC记录类型代码
-            DnsRecordType::RRSIG => crate::types::RecordType::Unknown(46),  // RRSIG
-            DnsRecordType::DNSKEY => crate::types::RecordType::Unknown(48), // DNSKEY
-            DnsRecordType::DS => crate::types::RecordType::Unknown(43),     // DS
-            DnsRecordType::NSEC => crate::types::RecordType::Unknown(47),   // NSEC
-            DnsRecordType::NSEC3 => crate::types::RecordType::Unknown(50),  // NSEC3
-        }
-    }
-    
-    /// 轮询查询策略（优化版本）
-    async fn query_round_robin(&self, request: &DnsQueryRequest) -> Result<(crate::Response, String)> {
+        record_type.into()
+    }
+    
+    /// 轮询查询策略：决策引擎按轮询选出本次计入指标的上游，整轮失败后的重试由核心解析器按 `retry_count` 进行
+    async fn query_round_robin(&self, active: &ActiveResolver, request: &DnsQueryRequest) -> Result<(SharedResponse, Option<TransportInfo>)> {
         let record_type = self.convert_record_type(request.record_type);
 
         // 解析 client_address
@@ -337,46 +2117,13 @@ impl SmartDnsResolver {
             .and_then(|ip| ip.parse().ok());
 
         if let Some(engine) = &self.decision_engine {
-            let mut last_error = None;
-            let mut attempted_servers = Vec::new();
-
-            // 最多尝试3次不同的服务器
-            for attempt in 0..3 {
-                if let Some(spec) = engine.select_round_robin_upstream().await {
-                    attempted_servers.push(spec.name.clone());
-                    let start_time = Instant::now();
-
-                    match self.resolver.query_with_client_ip(&request.domain, record_type, crate::types::QClass::IN, client_ip).await {
-                        Ok(response) => {
-                            let duration = start_time.elapsed();
-                            engine.update_metrics(&spec.name, duration, true, true).await;
-                            return Ok((response, spec.name));
-                        },
-                        Err(e) => {
-                            let duration = start_time.elapsed();
-                            engine.update_metrics(&spec.name, duration, false, false).await;
-                            last_error = Some(e);
-                            
-                            // 短暂延迟后重试下一个服务器
-                            if attempt < 2 {
-                                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
-                            }
-                        }
-                    }
-                } else {
-                    break;
-                }
-            }
-            
-            // 如果所有尝试都失败了，返回详细的错误信息
-            if let Some(error) = last_error {
-                Err(DnsError::Server(format!(
-                    "Round-robin查询失败，已尝试服务器: [{}]，最后错误: {}",
-                    attempted_servers.join(", "),
-                    error
-                )))
+            if let Some(spec) = engine.select_round_robin_upstream().await {
+                let start_time = Instant::now();
+                let result = self.query_core(active, request, record_type, client_ip).await;
+                score_query(engine, &spec.name, start_time.elapsed(), &result).await;
+                result
             } else {
-                Err(DnsError::NoUpstreamAvailable)
+                Err(DnsError::NoUpstreamAvailable { filter: None })
             }
         } else {
             // 没有决策引擎，无法执行Round-robin策略
@@ -385,66 +2132,27 @@ impl SmartDnsResolver {
     }
     
 
-    /// 转换响应为记录
-    fn convert_response_to_records(&self, response: crate::Response) -> Vec<DnsRecord> {
-        use crate::builder::types::{DnsRecord, DnsRecordValue};
-        
-        let mut records = Vec::new();
-        
-        for record in response.answers {
-            let record_type = match record.rtype {
-                crate::types::RecordType::A => DnsRecordType::A,
-                crate::types::RecordType::AAAA => DnsRecordType::AAAA,
-                crate::types::RecordType::CNAME => DnsRecordType::CNAME,
-                crate::types::RecordType::MX => DnsRecordType::MX,
-                crate::types::RecordType::TXT => DnsRecordType::TXT,
-                crate::types::RecordType::NS => DnsRecordType::NS,
-                crate::types::RecordType::PTR => DnsRecordType::PTR,
-                crate::types::RecordType::SRV => DnsRecordType::SRV,
-                crate::types::RecordType::SOA => DnsRecordType::SOA,
-                _ => continue,
-            };
-            
-            let value = match record.data {
-                crate::types::RecordData::A(addr) => DnsRecordValue::IpAddr(addr.into()),
-                crate::types::RecordData::AAAA(addr) => DnsRecordValue::IpAddr(addr.into()),
-                crate::types::RecordData::CNAME(name) => DnsRecordValue::Domain(name),
-                crate::types::RecordData::NS(name) => DnsRecordValue::Domain(name),
-                crate::types::RecordData::PTR(name) => DnsRecordValue::Domain(name),
-                crate::types::RecordData::TXT(texts) => DnsRecordValue::Text(texts.join(" ")),
-                crate::types::RecordData::MX { priority, exchange } => {
-                    DnsRecordValue::Mx { priority, exchange }
-                },
-                crate::types::RecordData::SRV { priority, weight, port, target } => {
-                    DnsRecordValue::Srv { priority, weight, port, target }
-                },
-                _ => continue,
-            };
-            
-            records.push(DnsRecord {
-                name: record.name,
-                record_type,
-                value,
-                ttl: record.ttl,
-            });
-        }
-        
-        records
-    }
-    
     /// 获取解析器统计信息
     pub async fn get_stats(&self) -> CoreResolverStats {
-        let mut stats = CoreResolverStats::new(self.query_strategy, self.enable_edns);
+        let active = self.active();
+        let mut stats = CoreResolverStats::new(active.query_strategy, self.enable_edns);
+        stats.stats_enabled = active.config.enable_stats;
         
-        if let Some(engine) = &self.decision_engine {
+        if let (Some(engine), false) = (&self.decision_engine, active.config.enable_stats) {
+            stats.total_upstreams = engine.get_upstreams().await.len();
+            stats.available_upstreams = engine.available_upstream_count().await;
+        } else if let Some(engine) = &self.decision_engine {
             let metrics = engine.get_all_metrics().await;
             stats.total_upstreams = metrics.len();
             stats.available_upstreams = engine.available_upstream_count().await;
             
+            let overall = LatencyHistogram::new();
             for (name, metric) in metrics {
                 stats.total_queries += metric.total_queries;
                 stats.successful_queries += metric.successful_queries;
                 stats.failed_queries += metric.failed_queries;
+                overall.merge(&metric.latency_histogram);
+                stats.upstream_latency.insert(name.clone(), metric.latency_histogram.summary());
                 
                 if metric.avg_latency < stats.min_latency || stats.min_latency.is_zero() {
                     stats.min_latency = metric.avg_latency;
@@ -456,14 +2164,84 @@ impl SmartDnsResolver {
                     stats.slowest_upstream = Some(name);
                 }
             }
+            
+            let summary = overall.summary();
+            stats.mean_latency = summary.mean;
+            stats.p50_latency = summary.p50;
+            stats.p95_latency = summary.p95;
+            stats.p99_latency = summary.p99;
+        }
+        if let Some(engine) = &self.decision_engine {
+            stats.metrics_skipped_cache_hits = engine.skipped_cache_hit_updates();
+        }
+        if let Some(state) = self.decision_engine.as_ref().and_then(|engine| engine.emergency_state()) {
+            stats.emergency_mode = state.mode == ResolverMode::Emergency;
+            stats.recent_success_ratio = state.success_ratio;
         }
         
-        stats.strategy = self.query_strategy;
+        stats.strategy = active.query_strategy;
         stats.edns_enabled = self.enable_edns;
+        stats.current_active_tier = active.resolver.current_active_tier();
+        stats.in_flight_sends = active.resolver.in_flight_sends();
+        stats.priorities = active.resolver.priority_stats();
+        stats.retries_performed = active.resolver.retries_performed();
+        stats.dropped_mismatched_records = self.dropped_mismatched_records.load(Ordering::Relaxed);
+        let offline = active.resolver.offline_stats();
+        stats.offline = offline.offline;
+        stats.offline_transitions = offline.transitions;
+        stats.answer_rewrites = active.resolver.answer_rewrite_stats();
+        if let Some(fallback) = active.resolver.encrypted_fallback_stats() {
+            stats.encrypted_fallback_active = fallback.active;
+            stats.encrypted_fallback_transitions = fallback.transitions;
+        }
+        stats.memory = active.resolver.memory_stats();
+        stats.upstream_features = active.resolver.transport_names().into_iter()
+            .filter_map(|name| active.resolver.transport_features(&name).map(|features| (name, features)))
+            .collect();
         
         stats
     }
     
+    /// 以OpenMetrics文本格式导出查询、上游、缓存和运行模式指标，可直接作为抓取响应返回
+    /// 
+    /// 响应头使用 [`OPENMETRICS_CONTENT_TYPE`](super::OPENMETRICS_CONTENT_TYPE)，指标名和标签见 [`super::openmetrics`]
+    pub async fn render_openmetrics(&self) -> String {
+        let mut snapshot = MetricsSnapshot {
+            queries: self.query_counters.snapshot(),
+            cache: self.cache_stats(),
+            emergency_mode: self.resolver_mode() == ResolverMode::Emergency,
+            offline: self.is_offline(),
+            in_flight_sends: self.active().resolver.in_flight_sends(),
+            ..Default::default()
+        };
+        if let Some(engine) = &self.decision_engine {
+            let mut metrics = engine.get_all_metrics().await;
+            for status in self.get_upstream_status().await {
+                let metric = metrics.remove(&status.name).unwrap_or_default();
+                let state = if status.quarantine.is_some() {
+                    UpstreamState::Quarantined
+                } else if status.is_available {
+                    UpstreamState::Available
+                } else {
+                    UpstreamState::Unavailable
+                };
+                // 只输出白名单中的标签，上游没有的标签取空字符串，同一指标的各序列标签名一致
+                let labels = self.active().config.metrics_upstream_labels.iter()
+                    .map(|key| (key.clone(), status.labels.get(key).cloned().unwrap_or_default()))
+                    .collect();
+                snapshot.upstreams.push(UpstreamSample {
+                    name: status.name,
+                    labels,
+                    successes: metric.successful_queries,
+                    failures: metric.failed_queries,
+                    latency: metric.latency_histogram,
+                    state,
+                });
+            }
+        }
+        openmetrics::render(&snapshot)
+    }
+    
     /// 重置所有统计信息
     pub async fn reset_stats(&self) {
         if let Some(engine) = &self.decision_engine {
@@ -471,27 +2249,129 @@ impl SmartDnsResolver {
         }
     }
     
+    /// 按域名覆盖TTL的规则，按添加顺序（见 [`DnsResolverBuilder::with_ttl_override`](super::DnsResolverBuilder::with_ttl_override)）
+    pub fn list_ttl_overrides(&self) -> Vec<TtlOverrideRule> {
+        self.active().resolver.ttl_overrides()
+    }
+    
+    /// 获取缓存统计，未启用缓存时为 `None`
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.active().resolver.cache_stats()
+    }
+    
+    /// 获取后台缓存清理最近一轮扫描得到的缓存概况（条目类型、剩余TTL分布、内存估算、热门名称）
+    /// 
+    /// 不会阻塞查询，见 [`CoreResolver::cache_insights`](crate::resolver::CoreResolver::cache_insights)
+    pub fn cache_insights(&self) -> CacheInsights {
+        self.active().resolver.cache_insights()
+    }
+    
+    /// 清空缓存（使用共享后端时会影响所有共享该后端的实例）
+    pub async fn clear_cache(&self) -> Result<()> {
+        self.active().resolver.clear_cache().await
+    }
+    
+    /// 渐进清空缓存，避免清空后所有名称同时回源：按每秒 `rate_per_second` 个的速率让当前条目依次过期，
+    /// 命中次数少的先过期，到期后的第一次查询重新向上游查询
+    /// 
+    /// 返回受影响的条目数和全部过期的预计时长；速率为0时返回 [`DnsError::InvalidConfig`]，
+    /// 不支持的缓存后端返回 [`DnsError::NotImplemented`]
+    pub async fn clear_cache_gradual(&self, rate_per_second: usize) -> Result<CacheClearReport> {
+        self.active().resolver.clear_cache_gradual(rate_per_second).await
+    }
+    
+    /// 把失效范围内的缓存条目立即标记为过期而不移除，返回标记数
+    /// 
+    /// 与 `invalidate_*` 不同，启用过期答案时这些名称仍先用旧答案应答并在后台刷新
+    pub async fn expire_now(&self, scope: &CacheInvalidation) -> Result<usize> {
+        self.active().resolver.expire_now(scope).await
+    }
+    
+    /// 保留命中次数最多的 `top_n` 个缓存条目，立即移除其余条目
+    /// 
+    /// 命中次数从条目写入时开始计算，从未命中的条目不会被保留
+    pub async fn clear_cache_except_hot(&self, top_n: usize) -> Result<CacheClearReport> {
+        self.active().resolver.clear_cache_except_hot(top_n).await
+    }
+    
+    /// 移除指定名称在所有记录类型下的缓存（含否定应答和各客户端子网的条目），返回移除数
+    /// 
+    /// 进程内缓存每次调用扫描全部条目，耗时与缓存大小成正比；名称为空时返回 [`DnsError::InvalidConfig`]
+    pub async fn invalidate_domain(&self, name: &str) -> Result<usize> {
+        Self::require_cache_name(name)?;
+        self.active().resolver.invalidate_cache(&CacheInvalidation::domain(name)).await
+    }
+    
+    /// 移除指定后缀及其所有子域名的缓存，按标签边界匹配（`corp` 不匹配 `notcorp`），返回移除数
+    /// 
+    /// 与 [`invalidate_domain`](Self::invalidate_domain) 一样扫描全部条目；后缀为空时返回 [`DnsError::InvalidConfig`]
+    pub async fn invalidate_suffix(&self, suffix: &str) -> Result<usize> {
+        Self::require_cache_name(suffix.trim_start_matches('.'))?;
+        self.active().resolver.invalidate_cache(&CacheInvalidation::suffix(suffix)).await
+    }
+    
+    /// 移除指定记录类型的全部缓存，返回移除数
+    pub async fn invalidate_type(&self, record_type: DnsRecordType) -> Result<usize> {
+        self.active().resolver.invalidate_cache(&CacheInvalidation::Type(self.convert_record_type(record_type))).await
+    }
+    
+    fn require_cache_name(name: &str) -> Result<()> {
+        if name.trim().trim_end_matches('.').is_empty() {
+            return Err(DnsError::InvalidConfig("Cache invalidation requires a non-empty name".to_string()));
+        }
+        Ok(())
+    }
+    
     /// 获取上游状态
     pub async fn get_upstream_status(&self) -> Vec<UpstreamStatus> {
+        let active = self.active();
         let mut status_list = Vec::new();
         
         if let Some(engine) = &self.decision_engine {
             let upstreams = engine.get_upstreams().await;
             let metrics = engine.get_all_metrics().await;
+            let mut family_stats = active.resolver.address_family_stats();
             
             for upstream in upstreams {
                 let metric = metrics.get(&upstream.name).cloned().unwrap_or_default();
+                let features = active.resolver.transport_features(&upstream.name)
+                    .unwrap_or_else(|| upstream.features.effective(self.enable_edns));
+                let health = active.resolver.upstream_health(&upstream.name);
+                let saturated = active.resolver.transport_saturations(&upstream.name);
+                let suspicious_negatives = active.resolver.transport_suspicious_negatives(&upstream.name);
+                let rate_limited = active.resolver.transport_rate_limited(&upstream.name);
+                let quarantine = active.resolver.transport_quarantine(&upstream.name);
+                let address_families = family_stats.remove(&upstream.name);
+                let slo = active.resolver.upstream_slo(&upstream.name);
+                let effective_timeout = engine.effective_timeout_of(&upstream.name).await
+                    .unwrap_or_else(|| upstream.timeout.unwrap_or(active.config.default_timeout));
                 
                 status_list.push(UpstreamStatus {
+                    labels: upstream.labels,
                     name: upstream.name,
                     server: upstream.server,
                     transport_type: upstream.transport_type,
-                    is_available: metric.is_available(),
+                    // 上游监控判定为不可用（例如地址解析失败）或因握手失败被隔离时同样视为不可用
+                    is_available: metric.is_available()
+                        && health.as_ref().is_none_or(|health| health.upstream_status != HealthStatus::Unavailable)
+                        && quarantine.is_none(),
                     success_rate: metric.success_rate(),
                     avg_latency: metric.avg_latency,
                     consecutive_failures: metric.consecutive_failures,
                     total_queries: metric.total_queries,
                     last_success: metric.last_success_time,
+                    features,
+                    recent_failures: health.as_ref().map(DetailedStats::recent_failure_summary).unwrap_or_default(),
+                    last_peer: health.as_ref().and_then(|health| health.last_peer),
+                    network_errors: health.map(|health| health.network_errors).unwrap_or_default(),
+                    max_inflight: upstream.max_inflight,
+                    saturated,
+                    suspicious_negatives,
+                    rate_limited,
+                    quarantine,
+                    address_families,
+                    slo,
+                    effective_timeout,
                 });
             }
         }
@@ -499,9 +2379,16 @@ impl SmartDnsResolver {
         status_list
     }
     
+    /// 指定上游最近的状态变化（何时变为不可用、何时恢复及原因），从旧到新
+    /// 
+    /// `since` 不为 `None` 时只返回该时间及之后的事件；未启用上游监控或上游不存在时为空
+    pub fn get_upstream_events(&self, name: &str, since: Option<SystemTime>) -> Vec<UpstreamEvent> {
+        self.active().resolver.upstream_events(name, since)
+    }
+    
     /// 获取查询策略
     pub fn query_strategy(&self) -> QueryStrategy {
-        self.query_strategy
+        self.active().query_strategy
     }
     
     /// 是否启用EDNS
@@ -509,6 +2396,55 @@ impl SmartDnsResolver {
         self.enable_edns
     }
     
+    /// 当前运行模式，未配置应急阈值时始终为正常模式
+    pub fn resolver_mode(&self) -> ResolverMode {
+        self.decision_engine.as_ref().map_or(ResolverMode::Normal, |engine| engine.resolver_mode())
+    }
+    
+    /// 手动进入或退出离线模式，见 [`CoreResolver::set_offline`]
+    /// 
+    /// 离线期间查询只由缓存应答，没有缓存的查询以 [`DnsError::Offline`] 失败
+    pub fn set_offline(&self, offline: bool) {
+        self.active().resolver.set_offline(offline);
+    }
+    
+    /// 是否处于离线模式（手动切换或自动离线）
+    pub fn is_offline(&self) -> bool {
+        self.active().resolver.is_offline()
+    }
+    
+    /// 离线模式的状态与计数
+    pub fn offline_stats(&self) -> OfflineStats {
+        self.active().resolver.offline_stats()
+    }
+    
+    /// 加密上游证书校验失败降级的状态与计数，未开启降级时为 `None`
+    pub fn encrypted_fallback_stats(&self) -> Option<EncryptedFallbackStats> {
+        self.active().resolver.encrypted_fallback_stats()
+    }
+    
+    /// 各DoT上游的握手方式计数（按上游名称）：完整握手、会话恢复和0-RTT早期数据
+    #[cfg(feature = "dot")]
+    pub fn tls_session_stats(&self) -> HashMap<String, TlsSessionStats> {
+        self.active().resolver.tls_session_stats()
+    }
+    
+    /// 开启TCP Fast Open的TCP/DoT上游的计数（按上游名称）：是否支持、尝试、SYN数据被接受和回退的次数
+    #[cfg(feature = "tcp")]
+    pub fn fast_open_stats(&self) -> HashMap<String, FastOpenStats> {
+        self.active().resolver.fast_open_stats()
+    }
+    
+    /// 各UDP上游的源端口池使用情况（按上游名称）
+    pub fn udp_pool_stats(&self) -> HashMap<String, UdpPoolStats> {
+        self.active().resolver.udp_pool_stats()
+    }
+    
+    /// 各UDP/TCP/DoT上游按地址族分开的连接统计（按上游名称）
+    pub fn address_family_stats(&self) -> HashMap<String, AddressFamilyStats> {
+        self.active().resolver.address_family_stats()
+    }
+    
     /// 获取决策引擎引用
     pub fn get_decision_engine(&self) -> Option<&Arc<SmartDecisionEngine>> {
         self.decision_engine.as_ref()
@@ -524,7 +2460,7 @@ impl SmartDnsResolver {
                 return Some(format!(
                     "🚨 应急模式激活: {} (策略: {:?})",
                     emergency_info.emergency_message,
-                    self.query_strategy
+                    self.active().query_strategy
                 ));
             }
         }
@@ -534,14 +2470,21 @@ impl SmartDnsResolver {
     /// 增强错误信息，添加应急响应详情
     /// 
     /// 在查询失败后，检查应急状态并增强错误信息
-    async fn enhance_error_with_emergency_info(&self, original_error: DnsError) -> String {
+    async fn enhance_error_with_emergency_info(&self, active: &ActiveResolver, original_error: DnsError) -> String {
         if let Some(engine) = &self.decision_engine {
             let emergency_info = engine.get_emergency_response_info().await;
             
-            if emergency_info.all_servers_failed {
+            if emergency_info.emergency_mode {
+                format!(
+                    "查询失败 (应急模式, 策略: {:?}): {}\n🚨 应急信息: {}",
+                    active.query_strategy,
+                    original_error,
+                    emergency_info.emergency_message
+                )
+            } else if emergency_info.all_servers_failed {
                 format!(
                     "查询失败 (策略: {:?}): {}\n🚨 应急信息: {}\n📊 失败统计: {}次\n📋 失败服务器: [{}]",
-                    self.query_strategy,
+                    active.query_strategy,
                     original_error,
                     emergency_info.emergency_message,
                     emergency_info.total_failures,
@@ -553,57 +2496,458 @@ impl SmartDnsResolver {
             } else if emergency_info.total_failures > 0 {
                 format!(
                     "查询失败 (策略: {:?}): {}\n⚠️  部分服务器不可用: {}次失败",
-                    self.query_strategy,
+                    active.query_strategy,
                     original_error,
                     emergency_info.total_failures
                 )
             } else {
-                format!("查询失败 (策略: {:?}): {}", self.query_strategy, original_error)
+                format!("查询失败 (策略: {:?}): {}", active.query_strategy, original_error)
             }
         } else {
-            format!("查询失败 (策略: {:?}, 无决策引擎): {}", self.query_strategy, original_error)
+            format!("查询失败 (策略: {:?}, 无决策引擎): {}", active.query_strategy, original_error)
+        }
+    }
+    
+    /// 当前上游配置的副本（包含运行时增删的上游）
+    pub fn upstream_manager(&self) -> UpstreamManager {
+        self.manager_read().clone()
+    }
+    
+    fn manager_read(&self) -> RwLockReadGuard<'_, UpstreamManager> {
+        self.upstream_manager.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
+    fn manager_write(&self) -> RwLockWriteGuard<'_, UpstreamManager> {
+        self.upstream_manager.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
+    /// 运行时添加上游服务器
+    /// 
+    /// 规格按构建时相同的规则校验（名称格式、名称和服务器不可重复），
+    /// 添加后立即参与查询。自定义上游请使用 [`add_custom_upstream`](Self::add_custom_upstream)。
+    pub async fn add_upstream(&self, spec: UpstreamSpec) -> Result<()> {
+        if spec.transport_type == UpstreamType::Custom {
+            return Err(DnsError::InvalidConfig(format!(
+                "Custom upstream '{}' needs a transport instance, use add_custom_upstream", spec.name
+            )));
+        }
+        let transport = create_transport(&spec, &self.active().config, &[], self.active().resolver.cookie_jar())?;
+        self.attach_upstream(spec, transport).await
+    }
+    
+    /// 运行时添加调用方提供传输实例的上游
+    pub async fn add_custom_upstream(&self, name: impl Into<String>, transport: Arc<dyn Transport>) -> Result<()> {
+        let spec = UpstreamSpec::custom(name.into(), transport.endpoint());
+        self.attach_upstream(spec, transport).await
+    }
+    
+    async fn attach_upstream(&self, spec: UpstreamSpec, transport: Arc<dyn Transport>) -> Result<()> {
+        let _reconfiguring = self.reconfigure.lock().await;
+        let active = self.active();
+        // 先在副本上校验，失败时不影响当前配置
+        let mut candidate = self.upstream_manager();
+        candidate.add_upstream(spec.clone())?;
+        candidate.validate_upstreams(false)?;
+        
+        // 先登记到上游管理器，之后任何一步失败都撤销已经完成的步骤
+        self.manager_write().add_upstream(spec.clone())?;
+        let (mut registered, mut in_engine) = (false, false);
+        let attached: Result<()> = async {
+            active.resolver.register_transport(spec.name.clone(), transport.clone())?;
+            registered = true;
+            configure_transport(&active.resolver, &spec)?;
+            if let Some(engine) = &self.decision_engine {
+                engine.add_upstream(spec.clone()).await?;
+                in_engine = true;
+                // 新上游还没有样本，下一次重新计算之前使用配置的超时
+                if engine.adaptive_timeout().is_some() {
+                    let attempt_timeout = engine.effective_timeout_of(&spec.name).await;
+                    active.resolver.set_transport_attempt_timeout(&spec.name, attempt_timeout)?;
+                }
+            }
+            Ok(())
+        }.await;
+        if let Err(e) = attached {
+            if in_engine
+                && let Some(engine) = &self.decision_engine
+                && let Err(undo) = engine.remove_upstream(&spec.name).await
+            {
+                dns_warn!("撤销添加上游 {} 时决策引擎移除失败: {}", spec.name, undo);
+            }
+            if registered
+                && let Err(undo) = active.resolver.remove_transport(&spec.name, Duration::ZERO).await
+            {
+                dns_warn!("撤销添加上游 {} 时移除传输失败: {}", spec.name, undo);
+            }
+            self.manager_write().remove_upstream(&spec.name);
+            return Err(e);
+        }
+        
+        if spec.transport_type == UpstreamType::Custom {
+            self.custom_transports.write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .push((spec.name.clone(), transport));
+        }
+        dns_info!("运行时添加上游: {} ({:?}) -> {}", spec.name, spec.transport_type, spec.server);
+        Ok(())
+    }
+    
+    /// 运行时移除上游服务器
+    /// 
+    /// 新的查询立即不再使用该上游；已经发出的查询最多等待一个默认超时时间，
+    /// 不会被中断。
+    pub async fn remove_upstream(&self, name: &str) -> Result<()> {
+        let _reconfiguring = self.reconfigure.lock().await;
+        let removed = self.manager_write().remove_upstream(name)
+            .ok_or_else(|| DnsError::InvalidConfig(format!("Upstream '{}' not found", name)))?;
+        if removed.transport_type == UpstreamType::Custom {
+            self.custom_transports.write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .retain(|(custom_name, _)| custom_name != name);
         }
+        if let Some(engine) = &self.decision_engine
+            && let Err(e) = engine.remove_upstream(name).await
+        {
+            dns_warn!("决策引擎中移除上游 {} 失败: {}", name, e);
+        }
+        self.active().resolver.remove_transport(name, self.active().config.default_timeout).await?;
+        Ok(())
+    }
+    
+    /// 按新的严格配置重新加载，相当于守护进程收到SIGHUP后重读配置文件
+    /// 
+    /// 先按构建时的规则完整校验新配置；校验失败，或者改变了不能在运行时修改的设置（见 [`RELOADABLE_SETTINGS`]）时
+    /// 返回错误，解析器不做任何修改。之后与当前生效的配置比较：
+    /// - 两边都有且连接参数相同的上游沿用原传输，连接、性能指标和统计保留，权重、层级、标签等属性原地修改；
+    ///   地址、协议或超时改变的上游换用新传输，按名称的性能指标保留
+    /// - 只在旧配置中的上游立即不再参与新的查询，已经发出的查询最多等待一个默认超时，不会被中断
+    /// - 新增的上游新建传输
+    /// 
+    /// 解析器级设置随新的底层解析器一起替换：已经开始的查询按原设置完成，之后的查询使用新设置。
+    /// 缓存保留，只有TTL上限收紧时清空。自定义上游和按域名转发的规则不属于严格配置，原样保留
+    pub async fn reload(&self, config: StrictDnsConfig) -> Result<ReloadReport> {
+        let _reconfiguring = self.reconfigure.lock().await;
+        let current = self.active();
+        let before = self.effective_config();
+        
+        // 校验阶段：失败时不做任何修改
+        let region = self.decision_engine.as_ref().map_or("global", |engine| engine.current_region()).to_string();
+        let mut target = current.config.clone();
+        apply_strict_settings(&mut target, &config);
+        let (specs, emergency_policy) = DnsResolverBuilder::from_strict_config(&config, self.enable_edns, region)?
+            .validate_for_reload(&target)?;
+        let (custom_specs, old_specs): (Vec<UpstreamSpec>, Vec<UpstreamSpec>) = self.manager_read().get_specs().iter()
+            .cloned()
+            .partition(|spec| spec.transport_type == UpstreamType::Custom);
+        let mut manager = UpstreamManager::new();
+        for spec in specs.iter().chain(&custom_specs) {
+            manager.add_upstream(spec.clone())?;
+        }
+        manager.validate_upstreams(false)?;
+        
+        let changed_settings = reload::changed_settings(
+            &ResolverSettings::from_config(&current.config, 0),
+            &ResolverSettings::from_config(&target, 0),
+        );
+        let mut fixed: Vec<String> = changed_settings.iter()
+            .filter(|key| !RELOADABLE_SETTINGS.contains(&key.as_str()))
+            .cloned()
+            .collect();
+        // 开启自适应超时时，决策引擎以构建时的默认超时作为样本不足的上游的超时
+        if target.adaptive_timeout.is_some() && target.default_timeout != current.config.default_timeout {
+            fixed.push("timeout_ms".to_string());
+        }
+        if self.decision_engine.as_ref().is_some_and(|engine| engine.emergency_policy() != emergency_policy.as_ref()) {
+            fixed.push("emergency_threshold".to_string());
+        }
+        if !fixed.is_empty() {
+            return Err(DnsError::InvalidConfig(format!(
+                "Reload cannot change {}; rebuild the resolver to apply them", fixed.join(", ")
+            )));
+        }
+        
+        let mut report = ReloadReport { changed_settings, ..ReloadReport::default() };
+        let describe = |spec: &UpstreamSpec, config: &CoreResolverConfig| {
+            EffectiveUpstream::from_spec(spec, config, spec.features.effective(self.enable_edns), true, false)
+        };
+        let mut created = Vec::new();
+        for spec in &specs {
+            let list = match old_specs.iter().find(|old| old.name == spec.name) {
+                None => &mut report.added,
+                Some(old) if !reload::same_connection(old, spec, current.config.default_timeout, target.default_timeout) => {
+                    &mut report.reconnected
+                }
+                Some(old) if describe(old, &current.config) != describe(spec, &target) => &mut report.updated,
+                Some(_) => {
+                    report.unchanged.push(spec.name.clone());
+                    continue;
+                }
+            };
+            list.push(spec.name.clone());
+            if !report.updated.contains(&spec.name) {
+                created.push((spec, create_transport(spec, &target, &[], current.resolver.cookie_jar())?));
+            }
+        }
+        report.removed = old_specs.iter()
+            .filter(|old| specs.iter().all(|spec| spec.name != old.name))
+            .map(|old| old.name.clone())
+            .collect();
+        
+        // 在新的底层解析器上修改传输列表，替换之前不影响正在进行的查询
+        let resolver = current.resolver.reconfigured(&target);
+        for (spec, transport) in created {
+            if report.added.contains(&spec.name) {
+                resolver.register_transport(spec.name.clone(), transport)?;
+            } else {
+                resolver.replace_transport(&spec.name, transport)?;
+            }
+        }
+        for spec in specs.iter().filter(|spec| !report.unchanged.contains(&spec.name)) {
+            configure_transport(&resolver, spec)?;
+        }
+        let mut draining = Vec::with_capacity(report.removed.len());
+        for name in &report.removed {
+            draining.push((name.clone(), resolver.detach_transport(name)?));
+        }
+        if let Some(engine) = &self.decision_engine {
+            for name in &report.removed {
+                if let Err(e) = engine.remove_upstream(name).await {
+                    dns_warn!("决策引擎中移除上游 {} 失败: {}", name, e);
+                }
+            }
+        }
+        
+        let cache_cleared = target.enable_cache && reload::tightens_ttl_ceiling(current.config.max_ttl, target.max_ttl);
+        let grace = target.default_timeout;
+        *self.active.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(ActiveResolver {
+            resolver,
+            config: target,
+            query_strategy: config.strategy,
+        });
+        *self.manager_write() = manager;
+        let active = self.active();
+        if cache_cleared {
+            active.resolver.clear_cache().await?;
+        }
+        report.cache_cleared = cache_cleared;
+        
+        if let Some(engine) = &self.decision_engine {
+            for spec in &specs {
+                let result = if report.added.contains(&spec.name) {
+                    engine.add_upstream(spec.clone()).await
+                } else {
+                    engine.update_upstream(spec.clone()).await
+                };
+                if let Err(e) = result {
+                    dns_warn!("决策引擎中更新上游 {} 失败: {}", spec.name, e);
+                }
+            }
+            if engine.adaptive_timeout().is_some() {
+                apply_adaptive_timeouts(&active.resolver, engine).await;
+            }
+        }
+        
+        // 被移除的上游上已经发出的查询继续完成
+        let drained = futures::future::join_all(draining.iter()
+            .map(|(name, in_flight)| active.resolver.wait_drained(name, in_flight, grace))).await;
+        report.undrained = draining.into_iter()
+            .zip(drained)
+            .filter(|(_, drained)| !drained)
+            .map(|((name, _), _)| name)
+            .collect();
+        report.changes = before.diff(&self.effective_config());
+        dns_info!(
+            "配置已重新加载: 新增 {} 个上游，移除 {} 个，换用新连接 {} 个，修改 {} 个，解析器设置变化 {} 项{}",
+            report.added.len(), report.removed.len(), report.reconnected.len(), report.updated.len(),
+            report.changed_settings.len(), if report.cache_cleared { "，缓存已清空" } else { "" }
+        );
+        Ok(report)
+    }
+    
+    /// 运行时启用或停用上游服务器，停用的上游保留配置和统计，但不参与查询
+    pub async fn set_upstream_enabled(&self, name: &str, enabled: bool) -> Result<()> {
+        // 与新增、移除和重新加载互斥，避免修改到正在被替换的底层解析器
+        let _reconfiguring = self.reconfigure.lock().await;
+        self.active().resolver.set_transport_enabled(name, enabled)?;
+        if let Some(engine) = &self.decision_engine {
+            engine.set_upstream_enabled(name, enabled).await?;
+        }
+        dns_info!("上游 {} 已{}", name, if enabled { "启用" } else { "停用" });
+        Ok(())
+    }
+    
+    /// 立即解除上游的握手失败隔离（例如刚更换了过期的证书），返回此前是否处于隔离；上游不存在时返回错误
+    /// 
+    /// 见 [`DnsResolverBuilder::with_tls_quarantine`](crate::DnsResolverBuilder::with_tls_quarantine)
+    pub fn clear_quarantine(&self, name: &str) -> Result<bool> {
+        self.active().resolver.clear_transport_quarantine(name)
+    }
+    
+    /// 通过DDR（RFC 9462）发现明文上游声明的加密服务，并注册为新上游
+    /// 
+    /// 对地址为IP的UDP上游和自定义上游查询 `_dns.resolver.arpa` 的SVCB记录，每个明文上游
+    /// 每种协议只采用优先级最高且通过校验的一项，命名为 `<原名称>-ddr-doh` / `<原名称>-ddr-dot`。
+    /// 发现失败只记录在返回的报告中，不影响解析器继续使用原有上游。
+    pub async fn discover_encrypted_upstreams(&self, options: &DdrOptions) -> DdrReport {
+        let candidates: Vec<(String, IpAddr)> = self.manager_read().get_specs().iter()
+            .filter(|spec| matches!(spec.transport_type, UpstreamType::Udp | UpstreamType::Custom))
+            .filter_map(|spec| {
+                let ip = spec.server.parse::<IpAddr>().ok()
+                    .or_else(|| spec.server.parse::<SocketAddr>().ok().map(|addr| addr.ip()))?;
+                Some((spec.name.clone(), ip))
+            })
+            .collect();
+        
+        let mut report = DdrReport::default();
+        for (name, original) in candidates {
+            let registered = match self.discover_from(&name, original, options).await {
+                Ok(registered) if registered.is_empty() => {
+                    report.failures.push((name, "No verified designated resolver".to_string()));
+                    continue;
+                },
+                Ok(registered) => registered,
+                Err(e) => {
+                    dns_warn!("上游 {} 的加密服务发现失败: {}", name, e);
+                    report.failures.push((name, e.to_string()));
+                    continue;
+                },
+            };
+            report.registered.extend(registered);
+            if options.disable_plaintext {
+                match self.set_upstream_enabled(&name, false).await {
+                    Ok(()) => report.disabled.push(name),
+                    Err(e) => report.failures.push((name, e.to_string())),
+                }
+            }
+        }
+        report
+    }
+    
+    async fn discover_from(&self, name: &str, original: IpAddr, options: &DdrOptions) -> Result<Vec<String>> {
+        let response = self.active().resolver
+            .query_transport(name, ddr::DDR_QUERY_NAME, ddr::SVCB_RECORD_TYPE, crate::types::QClass::IN)
+            .await?;
+        let mut designated: Vec<DesignatedResolver> = response.answers.iter()
+            .filter_map(|record| match &record.data {
+                crate::types::RecordData::Unknown(rdata) if record.rtype == ddr::SVCB_RECORD_TYPE => {
+                    SvcbRecord::parse(rdata)
+                        .inspect_err(|e| dns_debug!("忽略无法处理的SVCB记录: {}", e))
+                        .ok()
+                },
+                _ => None,
+            })
+            .flat_map(|record| DesignatedResolver::from_svcb(&record))
+            .collect();
+        designated.sort_by_key(|candidate| candidate.priority);
+        
+        let mut registered = Vec::new();
+        for (protocol, suffix) in [(DesignatedProtocol::DoH, "doh"), (DesignatedProtocol::DoT, "dot")] {
+            for candidate in designated.iter().filter(|candidate| candidate.protocol == protocol) {
+                if let Err(e) = options.verifier.verify(original, candidate).await {
+                    dns_warn!("上游 {} 声明的加密服务 {} 未通过校验: {}", name, candidate.target, e);
+                    continue;
+                }
+                let spec = candidate.to_upstream_spec(format!("{}-ddr-{}", name, suffix), options.weight);
+                let spec_name = spec.name.clone();
+                match self.add_upstream(spec).await {
+                    Ok(()) => registered.push(spec_name),
+                    Err(e) => dns_warn!("注册上游 {} 声明的加密服务失败: {}", name, e),
+                }
+                break;
+            }
+        }
+        Ok(registered)
     }
     
-    /// 获取上游管理器引用
-    pub fn upstream_manager(&self) -> &UpstreamManager {
-        &self.upstream_manager
+    /// 获取生效的解析器配置（构建时的配置，重新加载后为合并了新配置的结果）
+    pub fn config(&self) -> CoreResolverConfig {
+        self.active().config.clone()
+    }
+    
+    /// 响应中返回给调用方的记录：去掉类型与查询不符的记录（除非开启了宽松模式），再按配置排序
+    fn answer_records(&self, active: &ActiveResolver, response: &SharedResponse, request: &DnsQueryRequest) -> Vec<DnsRecord> {
+        let records = response_records(response);
+        if active.config.lenient_answer_types {
+            return self.sorted(active, records);
+        }
+        let (records, dropped) = filter_answer_types(records, &request.domain, request.record_type, request.enable_dnssec);
+        if dropped > 0 {
+            self.dropped_mismatched_records.fetch_add(dropped as u64, Ordering::Relaxed);
+            dns_debug!("{} 的{}应答中有 {} 条类型不符的记录，已去掉", request.domain, request.record_type.as_str(), dropped);
+        }
+        self.sorted(active, records)
+    }
+    
+    /// 按配置的规范排序排列记录，未配置时原样返回
+    fn sorted(&self, active: &ActiveResolver, mut records: Vec<DnsRecord>) -> Vec<DnsRecord> {
+        if let Some(sort) = active.config.sort_records {
+            sort.sort(&mut records);
+        }
+        records
+    }
+    
+    /// 当前实际生效的配置快照：解析器设置、所有上游（含运行时增删和只用于转发的上游）及编译特性
+    /// 
+    /// DoH认证类请求头的值已隐去，可以直接附在问题反馈中
+    pub fn effective_config(&self) -> EffectiveConfig {
+        let active = self.active();
+        let describe = |spec: &UpstreamSpec, route_only: bool| {
+            let features = active.resolver.transport_features(&spec.name)
+                .unwrap_or_else(|| spec.features.effective(self.enable_edns));
+            let enabled = active.resolver.is_transport_enabled(&spec.name) == Some(true);
+            EffectiveUpstream::from_spec(spec, &active.config, features, enabled, route_only)
+        };
+        let mut upstreams: Vec<EffectiveUpstream> = self.manager_read().get_specs().iter()
+            .map(|spec| describe(spec, false))
+            .collect();
+        if let Some(router) = &self.domain_router {
+            upstreams.extend(router.upstreams().iter().map(|spec| describe(spec, true)));
+        }
+        let domain_rules = self.domain_router.as_ref().map_or(0, |router| router.rule_count());
+        EffectiveConfig::new(ResolverSettings::from_config(&active.config, domain_rules), upstreams)
     }
     
 }
 
 impl Clone for SmartDnsResolver {
     fn clone(&self) -> Self {
-        // 由于CoreResolver包含trait对象，我们需要重新创建一个新的实例
-        // 这里我们使用相同的配置来创建新的解析器
-        let config = crate::resolver::CoreResolverConfig {
-            strategy: crate::builder::strategy::QueryStrategy::Smart,
-            default_timeout: std::time::Duration::from_secs(5),
-            retry_count: 2,
-            enable_cache: true,
-            max_cache_ttl: std::time::Duration::from_secs(3600),
-            enable_upstream_monitoring: true,
-            upstream_monitoring_interval: std::time::Duration::from_secs(30),
-            default_client_address: None,
-            port: 53,
-            concurrent_queries: 10,
-            recursion_desired: true,
-            buffer_size: 4096,
-            enable_stats: true,
-            log_level: rat_logger::LevelFilter::Info,
-            enable_dns_log_format: true,
-        };
-        
-        Self::new(
-            config,
-            self.upstream_manager.clone(),
+        // 由于CoreResolver包含trait对象，我们需要用构建时的配置重新创建传输
+        // 克隆体共享决策引擎，但不重复启动性能指标快照任务
+        let active = self.active();
+        let mut resolver = Self::new(
+            active.config.clone(),
+            self.upstream_manager(),
             self.decision_engine.clone(),
-            self.query_strategy,
+            active.query_strategy,
             self.enable_edns,
-        ).expect("Failed to clone SmartDnsResolver")
+            self.custom_transports.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone(),
+        ).expect("Failed to clone SmartDnsResolver");
+        // 运行时停用的上游在克隆体中保持停用
+        let cloned = resolver.active();
+        for name in active.resolver.transport_names() {
+            if active.resolver.is_transport_enabled(&name) == Some(false) {
+                let _ = cloned.resolver.set_transport_enabled(&name, false);
+            }
+        }
+        // 克隆体与原解析器写入同一份查询历史，使用同一组中间件
+        resolver.query_history = self.query_history.clone();
+        resolver.middlewares = self.middlewares.clone();
+        // 探测结果写入共享的决策引擎，克隆体可以手动探测，但不重复启动探测任务
+        resolver.cdn_probes = self.cdn_probes.clone();
+        resolver.query_id_format = self.query_id_format;
+        // 克隆体与原解析器共用粘性记录，同一客户端不因换用克隆体而换上游
+        resolver.sticky_pins = self.sticky_pins.clone();
+        resolver
     }
 }
 
+/// 只有收集统计时才有意义的 [`CoreResolverStats::to_json_value`] 字段
+const STATS_ONLY_FIELDS: [&str; 13] = [
+    "total_queries", "successful_queries", "failed_queries", "success_rate",
+    "avg_latency_ms", "min_latency_ms", "max_latency_ms", "p50_latency_ms", "p95_latency_ms", "p99_latency_ms",
+    "upstream_latency", "fastest_upstream", "slowest_upstream",
+];
+
 /// 解析器统计信息
 #[derive(Debug, Clone)]
 pub struct CoreResolverStats {
@@ -613,6 +2957,9 @@ pub struct CoreResolverStats {
     /// 是否启用EDNS
     pub edns_enabled: bool,
     
+    /// 是否收集了统计；为false时查询次数、成功率和延迟不可用（保持为0，JSON中为null），不代表没有查询
+    pub stats_enabled: bool,
+    
     /// 总上游服务器数量
     pub total_upstreams: usize,
     
@@ -628,17 +2975,77 @@ pub struct CoreResolverStats {
     /// 失败查询次数
     pub failed_queries: u64,
     
-    /// 最小延迟
+    /// 最快上游的平滑延迟
     pub min_latency: std::time::Duration,
     
-    /// 最大延迟
+    /// 最慢上游的平滑延迟
     pub max_latency: std::time::Duration,
     
+    /// 所有上游成功查询的平均延迟
+    pub mean_latency: std::time::Duration,
+    
+    /// 所有上游成功查询延迟的中位数
+    pub p50_latency: std::time::Duration,
+    
+    /// 所有上游成功查询延迟的95分位
+    pub p95_latency: std::time::Duration,
+    
+    /// 所有上游成功查询延迟的99分位
+    pub p99_latency: std::time::Duration,
+    
+    /// 按上游名称索引的延迟分布
+    pub upstream_latency: std::collections::HashMap<String, LatencyPercentiles>,
+    
+    /// 按上游名称索引的实际生效的请求特性（EDNS、DO位、大小写随机化）
+    pub upstream_features: std::collections::HashMap<String, EffectiveFeatures>,
+    
     /// 最快的上游服务器
     pub fastest_upstream: Option<String>,
     
     /// 最慢的上游服务器
     pub slowest_upstream: Option<String>,
+    
+    /// 是否处于应急模式
+    pub emergency_mode: bool,
+    
+    /// 应急判定窗口内的查询成功率（未配置应急阈值或样本不足时为None）
+    pub recent_success_ratio: Option<f64>,
+    
+    /// 当前使用的上游层级（没有可用上游时为None）
+    pub current_active_tier: Option<u8>,
+    
+    /// 当前在途的上游发送数（不超过 `concurrent_queries`）
+    pub in_flight_sends: usize,
+    
+    /// 是否处于离线模式
+    pub offline: bool,
+    
+    /// 进入或退出离线模式的次数
+    pub offline_transitions: u64,
+    
+    /// 命中缓存、因而没有更新决策引擎性能指标的查询数
+    pub metrics_skipped_cache_hits: u64,
+    
+    /// 已进行的重试次数（含义随查询策略不同，见 [`CoreResolver::retries_performed`]）
+    pub retries_performed: u64,
+    
+    /// 因类型与查询不符而从应答中去掉的记录数（每次返回都计数，命中缓存的也计入）
+    pub dropped_mismatched_records: u64,
+    
+    /// 各应答地址改写规则的命中次数，按配置顺序
+    pub answer_rewrites: Vec<RewriteRuleStats>,
+    
+    /// 是否因加密上游证书校验失败而处于降级
+    pub encrypted_fallback_active: bool,
+    
+    /// 进入或退出证书校验降级的次数
+    pub encrypted_fallback_transitions: u64,
+    
+    /// 各查询优先级的准入统计，从高到低
+    pub priorities: Vec<PriorityStats>,
+    
+    /// 全局内存预算的使用情况，未配置预算时为None
+    pub memory: Option<MemoryStats>,
 }
 
 impl CoreResolverStats {
@@ -647,6 +3054,7 @@ impl CoreResolverStats {
         Self {
             strategy,
             edns_enabled,
+            stats_enabled: true,
             total_upstreams: 0,
             available_upstreams: 0,
             total_queries: 0,
@@ -654,8 +3062,28 @@ impl CoreResolverStats {
             failed_queries: 0,
             min_latency: std::time::Duration::from_millis(0),
             max_latency: std::time::Duration::from_millis(0),
+            mean_latency: std::time::Duration::ZERO,
+            p50_latency: std::time::Duration::ZERO,
+            p95_latency: std::time::Duration::ZERO,
+            p99_latency: std::time::Duration::ZERO,
+            upstream_latency: std::collections::HashMap::new(),
+            upstream_features: std::collections::HashMap::new(),
             fastest_upstream: None,
             slowest_upstream: None,
+            emergency_mode: false,
+            recent_success_ratio: None,
+            current_active_tier: None,
+            in_flight_sends: 0,
+            offline: false,
+            offline_transitions: 0,
+            metrics_skipped_cache_hits: 0,
+            retries_performed: 0,
+            dropped_mismatched_records: 0,
+            answer_rewrites: Vec::new(),
+            encrypted_fallback_active: false,
+            encrypted_fallback_transitions: 0,
+            priorities: Vec::new(),
+            memory: None,
         }
     }
     
@@ -668,16 +3096,73 @@ impl CoreResolverStats {
         }
     }
     
-    /// 计算平均延迟
+    /// 平均延迟（所有成功查询延迟的算术平均）
     pub fn avg_latency(&self) -> std::time::Duration {
-        if self.min_latency.is_zero() && self.max_latency.is_zero() {
-            std::time::Duration::from_millis(0)
-        } else {
-            (self.min_latency + self.max_latency) / 2
+        self.mean_latency
+    }
+
+    /// 转为JSON对象，字段名与Python `get_stats()` 返回的字典相同，延迟均为毫秒；
+    /// 未收集统计时查询次数、成功率、延迟和最快/最慢上游为null
+    pub fn to_json_value(&self) -> serde_json::Value {
+        let upstream_latency: serde_json::Map<String, serde_json::Value> = self.upstream_latency.iter()
+            .map(|(name, latency)| (name.clone(), serde_json::json!({
+                "count": latency.count,
+                "avg_ms": duration_ms(latency.mean),
+                "p50_ms": duration_ms(latency.p50),
+                "p95_ms": duration_ms(latency.p95),
+                "p99_ms": duration_ms(latency.p99),
+            })))
+            .collect();
+        let mut value = serde_json::json!({
+            "stats_enabled": self.stats_enabled,
+            "total_queries": self.total_queries,
+            "successful_queries": self.successful_queries,
+            "failed_queries": self.failed_queries,
+            "total_upstreams": self.total_upstreams,
+            "available_upstreams": self.available_upstreams,
+            "current_active_tier": self.current_active_tier,
+            "in_flight_sends": self.in_flight_sends,
+            "offline": self.offline,
+            "offline_transitions": self.offline_transitions,
+            "metrics_skipped_cache_hits": self.metrics_skipped_cache_hits,
+            "retries_performed": self.retries_performed,
+            "dropped_mismatched_records": self.dropped_mismatched_records,
+            "strategy": format!("{:?}", self.strategy),
+            "edns_enabled": self.edns_enabled,
+            "success_rate": self.success_rate(),
+            "avg_latency_ms": duration_ms(self.avg_latency()),
+            "min_latency_ms": duration_ms(self.min_latency),
+            "max_latency_ms": duration_ms(self.max_latency),
+            "p50_latency_ms": duration_ms(self.p50_latency),
+            "p95_latency_ms": duration_ms(self.p95_latency),
+            "p99_latency_ms": duration_ms(self.p99_latency),
+            "upstream_latency": upstream_latency,
+            "upstream_features": self.upstream_features,
+            "emergency_mode": self.emergency_mode,
+            "recent_success_ratio": self.recent_success_ratio,
+            "fastest_upstream": self.fastest_upstream,
+            "slowest_upstream": self.slowest_upstream,
+            "memory": self.memory,
+        });
+        if !self.stats_enabled {
+            for key in STATS_ONLY_FIELDS {
+                value[key] = serde_json::Value::Null;
+            }
         }
+        value
+    }
+
+    /// 序列化为单行JSON，见 [`to_json_value`](Self::to_json_value)
+    pub fn to_json(&self) -> String {
+        self.to_json_value().to_string()
     }
 }
 
+/// 以浮点毫秒表示时长
+fn duration_ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
 /// 上游服务器状态
 #[derive(Debug, Clone)]
 pub struct UpstreamStatus {
@@ -706,5 +3191,79 @@ pub struct UpstreamStatus {
     pub total_queries: u64,
     
     /// 最后成功时间
-    pub last_success: Option<std::time::Instant>,
+    pub last_success: Option<crate::time::Instant>,
+    
+    /// 实际生效的请求特性（EDNS、DO位、大小写随机化）
+    pub features: EffectiveFeatures,
+    
+    /// 各类套接字错误的累计次数（需要启用上游监控）
+    pub network_errors: HashMap<NetworkErrorKind, u64>,
+    
+    /// 最近几次套接字错误的汇总，如 `5x ConnectionRefused`（需要启用上游监控）
+    pub recent_failures: String,
+    
+    /// 最近一次应答实际来自的对端地址（需要启用上游监控）
+    pub last_peer: Option<std::net::SocketAddr>,
+    
+    /// 同时发往该上游的查询上限（None表示不限制）
+    pub max_inflight: Option<usize>,
+    
+    /// 因在途查询已满而被跳过的次数，持续增长说明该上游正被限流保护
+    pub saturated: u64,
+    
+    /// 该上游的否定应答被其他上游的肯定答案推翻的次数（开启否定应答保护时）
+    pub suspicious_negatives: u64,
+    
+    /// 上游返回HTTP 429（限流）的次数
+    pub rate_limited: u64,
+    
+    /// 握手失败隔离状态：隔离时长和距下一次重新接纳探测的时长（开启隔离且正在隔离时），
+    /// 与上游监控判定的不可用相互独立
+    pub quarantine: Option<QuarantineStatus>,
+    
+    /// 按地址族分开的连接统计：当前使用的地址族和各地址族的成功率（DoH和自定义上游为None）
+    pub address_families: Option<AddressFamilyStats>,
+    
+    /// p95响应时间SLO的当前判定（没有设置SLO时为None）
+    pub slo: Option<SloStatus>,
+    
+    /// 单次发送实际使用的超时：开启自适应超时且样本足够时为自适应超时，否则为配置的超时
+    pub effective_timeout: std::time::Duration,
+    
+    /// 上游标签（配置中附加的元数据）
+    pub labels: UpstreamLabels,
+}
+
+impl UpstreamStatus {
+    /// 转为JSON对象：延迟为毫秒，`last_success` 换算为距今秒数 `last_success_secs_ago`
+    pub fn to_json_value(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "server": self.server,
+            "transport_type": format!("{:?}", self.transport_type),
+            "is_available": self.is_available,
+            "success_rate": self.success_rate,
+            "avg_latency_ms": duration_ms(self.avg_latency),
+            "consecutive_failures": self.consecutive_failures,
+            "total_queries": self.total_queries,
+            "last_success_secs_ago": self.last_success.map(|at| at.elapsed().as_secs_f64()),
+            "features": self.features,
+            "network_errors": self.network_errors,
+            "recent_failures": self.recent_failures,
+            "last_peer": self.last_peer.map(|peer| peer.to_string()),
+            "max_inflight": self.max_inflight,
+            "saturated": self.saturated,
+            "suspicious_negatives": self.suspicious_negatives,
+            "rate_limited": self.rate_limited,
+            "quarantine": self.quarantine.as_ref().map(QuarantineStatus::to_json_value),
+            "active_family": self.address_families.and_then(|stats| stats.active),
+            "family_success_rates": self.address_families.map(|stats| serde_json::json!({
+                "v4": stats.v4.success_rate(),
+                "v6": stats.v6.success_rate(),
+            })),
+            "slo": self.slo.as_ref().map(SloStatus::to_json_value),
+            "effective_timeout_ms": duration_ms(self.effective_timeout),
+            "labels": self.labels,
+        })
+    }
 }
\ No newline at end of file
@@ -2,24 +2,62 @@
 //! 
 //! 本模块提供了构建DNS解析器的Builder模式实现
 
+use std::net::IpAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
+use serde::{Deserialize, Serialize};
 
 
+use crate::config::{SearchDomains, StrictDnsConfig, SystemResolvConf};
 use crate::resolver::CoreResolverConfig;
-use crate::upstream_handler::{UpstreamManager, UpstreamSpec};
+use crate::builder::encoded::EncodedRequestLimits;
+use crate::builder::adaptive_timeout::AdaptiveTimeoutConfig;
+use crate::resolver::cache::{CacheJanitorConfig, EcsCacheMode};
+use crate::resolver::rotation::RotationMode;
+use crate::resolver::answer_rewrite::{AnswerRewriteRule, RewriteStage};
+use crate::resolver::ttl_override::{OverrideTtl, TtlOverrideRule};
+use crate::resolver::encrypted_fallback::EncryptedFallbackPolicy;
+use crate::resolver::quarantine::TlsQuarantineConfig;
+use crate::resolver::priority::check_reserve;
+use crate::resolver::health::ProbeConfig;
+use crate::resolver::slo::{QueryObserver, SloConfig};
+use crate::resolver::cache_backend::DnsCacheBackend;
+use crate::resolver::clock::Clock;
+use crate::resolver::random::RandomSource;
+use crate::resolver::rate_limit::RateLimitedBehavior;
+use crate::transport::{AfPreference, BootstrapResolver, HttpVersionPref, RecordLimits, Transport, UdpPoolConfig};
+use crate::types::{ClientAddress, UpstreamFeatures};
+use crate::upstream_handler::{check_label_key, QueueBehavior, UpstreamLabels, UpstreamManager, UpstreamSpec, UpstreamType};
+use crate::utils::parse_simple_server_address;
 use crate::error::{DnsError, Result};
-use crate::dns_error;
+use crate::{dns_error, dns_info, dns_warn};
 use super::{
     strategy::QueryStrategy,
+    emergency::EmergencyPolicy,
     engine::SmartDecisionEngine,
-    resolver::SmartDnsResolver,
+    ddr::DdrOptions,
+    cdn_probe::{CdnProbe, DEFAULT_CDN_PROBE_INTERVAL},
+    types::{QueryIdFormat, RecordSort},
+    stickiness::{StickinessConfig, StickyPins},
+    resolver::{check_upstream, SmartDnsResolver},
+    effective_config::{DryRunReport, EffectiveConfig, EffectiveUpstream, ResolverSettings},
+    history::QueryHistory,
+    tenant::TenantStatsTable,
+    routing::DomainRouter,
+    middleware::QueryMiddleware,
+    warmup::{self, WarmOptions},
+    preset::Preset,
+    upstream::Upstream,
 };
 
 /// 日志初始化策略
-#[derive(Debug, Clone, PartialEq)]
+/// 
+/// rat_logger 的全局处理器在进程内只安装一次：`Silent`/`Debug`/`Auto` 首次构建时安装
+/// 本库的终端处理器，之后再构建解析器只调整日志级别，不会重复安装。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum LoggerInitStrategy {
-    /// 不初始化日志（让上层应用控制）
+    /// 不初始化日志（让上层应用控制），构建过程不产生任何日志相关的副作用
     None,
     /// 使用静默模式初始化
     Silent,
@@ -27,6 +65,8 @@ pub enum LoggerInitStrategy {
     Debug,
     /// 根据配置的日志级别初始化
     Auto,
+    /// DNS日志写入调用方已安装的rat_logger处理器，只按配置的日志级别调整，不安装处理器
+    External,
 }
 
 /// DNS解析器构建器
@@ -50,14 +90,99 @@ pub struct DnsResolverBuilder {
     
     /// 日志初始化策略
     logger_init_strategy: LoggerInitStrategy,
+    
+    /// 性能指标快照文件路径
+    metrics_snapshot_path: Option<PathBuf>,
+    
+    /// 性能指标快照保存间隔
+    metrics_snapshot_interval: Duration,
+    
+    /// 查询历史容量（None表示不记录）
+    query_history_capacity: Option<usize>,
+    
+    /// 按租户统计时单独统计的租户数上限（None表示不统计）
+    tenant_stats_limit: Option<usize>,
+    
+    /// CDN准确性探测（为空表示不探测）
+    cdn_probes: Vec<CdnProbe>,
+    
+    /// CDN准确性探测间隔
+    cdn_probe_interval: Duration,
+    
+    /// 为未指定ID的查询分配查询ID的格式
+    query_id_format: QueryIdFormat,
+    
+    /// 调用方直接提供的自定义传输（按上游名称）
+    custom_transports: Vec<(String, Arc<dyn Transport>)>,
+    
+    /// 应急模式判定参数（None表示不启用应急模式）
+    emergency_policy: Option<EmergencyPolicy>,
+    
+    /// 同一服务器重复注册时是否去重（否则构建报错）
+    dedup_upstreams: bool,
+    
+    /// 构建时执行加密上游发现（None表示不执行）
+    ddr: Option<DdrOptions>,
+    
+    /// 按域名转发的规则（None表示不启用）
+    domain_router: Option<DomainRouter>,
+    
+    /// 查询中间件，按添加顺序执行
+    middlewares: Vec<Arc<dyn QueryMiddleware>>,
+    
+    /// 构建后预热缓存所用的列表文件（None表示不预热）
+    warmup_list: Option<PathBuf>,
+    
+    /// 构建后预热缓存的选项
+    warmup_options: WarmOptions,
+    
+    /// 搜索域（None表示未配置）
+    search_domains: Option<SearchDomains>,
+    
+    /// 查询前是否按搜索域展开名称
+    apply_search_domains: bool,
 }
 
+/// 性能指标快照的默认保存间隔
+pub const DEFAULT_METRICS_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
 // 注意：移除了 Default 实现，因为它包含兜底行为
 // 硬编码的默认值（如 Smart策略、EDNS启用、QuickMem配置等）是兜底代码
 // 用户必须明确指定所有配置项
 
     // 手动实现Clone
 
+/// 把严格配置中的解析器级设置写入解析器配置，不属于严格配置的设置保持不变
+/// 
+/// 构建（[`DnsResolverBuilder::from_strict_config`]）和重新加载（`SmartDnsResolver::reload`）共用
+pub(super) fn apply_strict_settings(target: &mut CoreResolverConfig, config: &StrictDnsConfig) {
+    target.strategy = config.strategy;
+    target.default_timeout = config.default_timeout;
+    target.retry_count = config.retry_count;
+    target.enable_cache = config.enable_cache;
+    target.max_cache_ttl = config.max_cache_ttl;
+    target.enable_upstream_monitoring = config.enable_upstream_monitoring;
+    target.port = config.port;
+    target.concurrent_queries = config.concurrent_queries;
+    target.buffer_size = config.buffer_size;
+    target.upstream_monitoring_interval = config.upstream_monitoring_interval;
+    target.enable_stats = config.enable_stats;
+    target.min_ttl = config.min_ttl;
+    target.max_ttl = config.max_ttl;
+    target.cache_zero_ttl = config.cache_zero_ttl;
+    target.accept_questionless_responses = config.accept_questionless_responses;
+    target.record_limits = config.record_limits;
+    target.cache_truncated_responses = config.cache_truncated_responses;
+    target.record_rotation = config.record_rotation;
+    target.revalidate_window = config.revalidate_window;
+    target.nxdomain_protection_window = config.nxdomain_protection_window;
+    target.health_probe = config.health_probe.clone();
+    target.max_upstream_silence = config.max_upstream_silence;
+    target.tls_quarantine = config.tls_quarantine;
+    target.adaptive_timeout = config.adaptive_timeout;
+    target.metrics_upstream_labels = config.metrics_upstream_labels.clone();
+}
+
 impl DnsResolverBuilder {
     /// 创建新的构造器（需要明确指定所有配置）
     pub fn new(
@@ -75,11 +200,11 @@ impl DnsResolverBuilder {
             false, // 临时默认值，用户应该明确设置
             std::time::Duration::from_secs(30), // 临时默认值
             53, // 临时默认值，用户应该明确设置
-            1, // 临时默认值，用户应该明确设置
+            100, // 在途上游发送数上限，临时默认值，用户应该明确设置
             true, // 临时默认值
-            4096, // 临时默认值
-            false, // 临时默认值
-            rat_logger::LevelFilter::Info, // 临时默认值
+            65535, // DNS报文的最大长度，不截断任何合法的DoH响应
+            true, // 智能策略依赖统计，默认收集
+            crate::logger::LevelFilter::Info, // 临时默认值
             false, // 临时默认值
         );
 
@@ -90,7 +215,71 @@ impl DnsResolverBuilder {
             enable_edns,
             current_region,
             logger_init_strategy: LoggerInitStrategy::Auto, // 默认自动模式，保持向后兼容
+            metrics_snapshot_path: None,
+            metrics_snapshot_interval: DEFAULT_METRICS_SNAPSHOT_INTERVAL,
+            query_history_capacity: None,
+            tenant_stats_limit: None,
+            cdn_probes: Vec::new(),
+            cdn_probe_interval: DEFAULT_CDN_PROBE_INTERVAL,
+            query_id_format: QueryIdFormat::default(),
+            custom_transports: Vec::new(),
+            emergency_policy: None,
+            dedup_upstreams: false,
+            ddr: None,
+            domain_router: None,
+            middlewares: Vec::new(),
+            warmup_list: None,
+            warmup_options: WarmOptions::default(),
+            search_domains: None,
+            apply_search_domains: false,
+        }
+    }
+    
+    /// 从严格配置创建构造器
+    /// 
+    /// 严格配置中的每一项都会原样映射到构造器上；EDNS和区域不属于严格配置，
+    /// 需要调用方明确传入。禁用的上游会被跳过，协议名支持
+    /// `udp`、`tcp`、`doh`（或`https`）、`dot`（或`tls`），其余协议名直接报错。
+    pub fn from_strict_config(
+        config: &StrictDnsConfig,
+        enable_edns: bool,
+        current_region: impl Into<String>,
+    ) -> Result<Self> {
+        config.validate()
+            .map_err(|e| DnsError::InvalidConfig(e.to_string()))?;
+        
+        let mut builder = Self::new(config.strategy, enable_edns, current_region.into());
+        apply_strict_settings(&mut builder.config, config);
+        if let Some(strategy) = &config.logger_init_strategy {
+            builder.logger_init_strategy = strategy.clone();
+        }
+        builder = builder.with_emergency_threshold(config.emergency_threshold)?;
+        builder.dedup_upstreams = config.dedup_upstreams;
+        
+        for upstream in config.enabled_upstreams() {
+            let name = upstream.upstream_name();
+            let server = upstream.address.clone();
+            let spec = match upstream.protocol.to_ascii_lowercase().as_str() {
+                "udp" => UpstreamSpec::udp(name, server),
+                "tcp" => UpstreamSpec::tcp(name, server),
+                "doh" | "https" => UpstreamSpec::doh(name, server).with_query_params(upstream.query_params.clone()),
+                "dot" | "tls" => UpstreamSpec::dot(name, server),
+                other => {
+                    return Err(DnsError::InvalidConfig(
+                        format!("Unsupported upstream protocol '{}' for '{}'", other, upstream.address)
+                    ));
+                }
+            };
+            builder.upstream_manager.add_upstream(
+                spec.with_weight(upstream.weight)
+                    .with_ecs_policy(upstream.ecs_policy.clone())
+                    .with_tier(upstream.tier)
+                    .with_features(upstream.features())
+                    .with_labels(upstream.labels.clone())
+            )?;
         }
+        
+        Ok(builder)
     }
     
     /// 设置查询策略
@@ -111,17 +300,44 @@ impl DnsResolverBuilder {
         self
     }
     
+    /// 添加构造时已校验地址的上游服务器
+    /// 
+    /// ```rust,no_run
+    /// use rat_quickdns::builder::{DnsResolverBuilder, QueryStrategy, Upstream};
+    /// use rat_quickdns::transport::HttpMethod;
+    /// use rat_quickdns::RecordType;
+    /// use std::time::Duration;
+    /// 
+    /// # async fn example() -> rat_quickdns::Result<()> {
+    /// let resolver = DnsResolverBuilder::new(QueryStrategy::Smart, true, "global".to_string())
+    ///     .add(Upstream::udp("google", "8.8.8.8:53".parse().unwrap()).with_weight(2))?
+    ///     .add(Upstream::dot("quad9", "9.9.9.9", 853)?.with_sni("dns.quad9.net")?.with_tier(1))?
+    ///     .add(Upstream::doh("cloudflare", "https://cloudflare-dns.com/dns-query")?
+    ///         .with_method(HttpMethod::GET)
+    ///         .with_timeout(Duration::from_secs(2))
+    ///         .with_record_types([RecordType::A, RecordType::AAAA]))?
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[allow(clippy::should_implement_trait)] // 构建器方法，不是加法
+    pub fn add<P>(mut self, upstream: Upstream<P>) -> Result<Self> {
+        self.upstream_manager.add_upstream(upstream.into_spec())?;
+        Ok(self)
+    }
+    
     /// 添加UDP上游服务器
     pub fn add_udp_upstream(mut self, name: impl Into<String>, server: impl Into<String>) -> Self {
-        let spec = UpstreamSpec::udp(name.into(), server.into());
-        let _ = self.upstream_manager.add_upstream(spec); // 忽略错误，在build时处理
+        let upstream = Upstream::parse_plain(UpstreamType::Udp, name.into(), server.into());
+        let _ = upstream.and_then(|upstream| self.upstream_manager.add_upstream(upstream.into_spec())); // 忽略错误，在build时处理
         self
     }
     
     /// 添加TCP上游服务器
     pub fn add_tcp_upstream(mut self, name: impl Into<String>, server: impl Into<String>) -> Self {
-        let spec = UpstreamSpec::tcp(name.into(), server.into());
-        if let Err(e) = self.upstream_manager.add_upstream(spec) {
+        let upstream = Upstream::parse_plain(UpstreamType::Tcp, name.into(), server.into());
+        if let Err(e) = upstream.and_then(|upstream| self.upstream_manager.add_upstream(upstream.into_spec())) {
             dns_error!("Failed to add TCP upstream: {}", e);
         }
         self
@@ -129,17 +345,62 @@ impl DnsResolverBuilder {
     
     /// 添加DoH上游服务器
     pub fn add_doh_upstream(mut self, name: impl Into<String>, url: impl Into<String>) -> Self {
-        let spec = UpstreamSpec::doh(name.into(), url.into());
-        if let Err(e) = self.upstream_manager.add_upstream(spec) {
+        let upstream = Upstream::doh(name, url.into());
+        if let Err(e) = upstream.and_then(|upstream| self.upstream_manager.add_upstream(upstream.into_spec())) {
             dns_error!("Failed to add DoH upstream: {}", e);
         }
         self
     }
     
+    /// 添加携带附加HTTP请求头的DoH上游服务器
+    /// 
+    /// 请求头会附加到该上游的每个DoH请求上（例如 `Authorization: Bearer ...`），
+    /// 名称或值不合法（包括含有CR/LF）时返回错误
+    pub fn add_doh_upstream_with_headers(
+        mut self,
+        name: impl Into<String>,
+        url: impl Into<String>,
+        headers: Vec<(String, String)>,
+    ) -> Result<Self> {
+        let spec = Upstream::doh(name, url.into())?.into_spec().with_headers(headers);
+        self.upstream_manager.add_upstream(spec)?;
+        Ok(self)
+    }
+    
+    /// 添加携带附加URL查询参数的DoH上游服务器
+    /// 
+    /// 用于端点在非标准路径、需要 `?key=...` 之类参数的DoH服务：参数追加在URL原有的查询串之后，
+    /// GET请求与 `dns` 参数一起发送，POST请求附在URL上。URL不是https、没有主机名、带片段，
+    /// 或参数名为空、与 `dns` 冲突时返回错误；`key`、`token` 等认证类参数的值不会出现在日志和配置导出中
+    pub fn add_doh_upstream_with_query_params(
+        mut self,
+        name: impl Into<String>,
+        url: impl Into<String>,
+        query_params: Vec<(String, String)>,
+    ) -> Result<Self> {
+        let spec = Upstream::doh(name, url.into())?.into_spec().with_query_params(query_params);
+        self.upstream_manager.add_upstream(spec)?;
+        Ok(self)
+    }
+    
+    /// 添加指定HTTP版本的DoH上游服务器
+    /// 
+    /// 选择HTTP/3（`H3Only` / `H3WithFallback`）而未启用 `doh3` 特性时返回错误
+    pub fn add_doh_upstream_with_http_version(
+        mut self,
+        name: impl Into<String>,
+        url: impl Into<String>,
+        http_version: HttpVersionPref,
+    ) -> Result<Self> {
+        let spec = Upstream::doh(name, url.into())?.into_spec().with_http_version(http_version);
+        self.upstream_manager.add_upstream(spec)?;
+        Ok(self)
+    }
+    
     /// 添加DoT上游服务器
     pub fn add_dot_upstream(mut self, name: impl Into<String>, server: impl Into<String>) -> Self {
-        let spec = UpstreamSpec::dot(name.into(), server.into());
-        if let Err(e) = self.upstream_manager.add_upstream(spec) {
+        let upstream = Upstream::parse_dot(name.into(), server.into());
+        if let Err(e) = upstream.and_then(|upstream| self.upstream_manager.add_upstream(upstream.into_spec())) {
             dns_error!("Failed to add DoT upstream: {}", e);
         }
         self
@@ -151,6 +412,28 @@ impl DnsResolverBuilder {
         Ok(self)
     }
     
+    /// 添加自定义传输上游
+    /// 
+    /// 传输实例由调用方提供，与内置协议的上游一样参与决策引擎选择和指标统计
+    pub fn add_custom_upstream(mut self, name: impl Into<String>, transport: Arc<dyn Transport>) -> Result<Self> {
+        let name = name.into();
+        self.upstream_manager.add_upstream(UpstreamSpec::custom(name.clone(), transport.endpoint()))?;
+        self.custom_transports.push((name, transport));
+        Ok(self)
+    }
+    
+    /// 添加内存模拟上游（需要启用 `test-util` 特性）
+    /// 
+    /// 先克隆一份 `mock` 再传入，即可在构建后断言调用记录或切换健康状态
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn add_mock_upstream(
+        self,
+        name: impl Into<String>,
+        mock: crate::transport::mock::MockTransport,
+    ) -> Result<Self> {
+        self.add_custom_upstream(name, Arc::new(mock))
+    }
+    
     /// 批量添加上游服务器
     pub fn add_upstreams(mut self, specs: Vec<UpstreamSpec>) -> Result<Self> {
         for spec in specs {
@@ -159,6 +442,77 @@ impl DnsResolverBuilder {
         Ok(self)
     }
     
+    /// 设置已添加上游的优先层级（0最优先，默认0）
+    /// 
+    /// 查询只使用有可用上游的最优先层级，该层级的上游全部被上游监控判定为不可用后才改用下一层级；
+    /// 层级切换依赖上游监控，需要同时启用 [`with_upstream_monitoring`](Self::with_upstream_monitoring)，
+    /// 并用 [`with_health_probe`](Self::with_health_probe) 配置判断更优先层级是否恢复的探测
+    pub fn with_upstream_tier(mut self, name: &str, tier: u8) -> Result<Self> {
+        self.upstream_manager.set_tier(name, tier)?;
+        Ok(self)
+    }
+    
+    /// 替换已添加上游的标签（如 `provider`、`datacenter`）
+    /// 
+    /// 标签只是元数据，不影响查询，随上游状态、状态变化事件、选择解释和有效配置输出；
+    /// 要作为指标标签输出还需列入 [`with_metrics_upstream_labels`](Self::with_metrics_upstream_labels)
+    pub fn with_upstream_labels(mut self, name: &str, labels: UpstreamLabels) -> Result<Self> {
+        self.upstream_manager.set_labels(name, labels)?;
+        Ok(self)
+    }
+    
+    /// 设置已添加上游的地址族偏好，覆盖 [`with_address_family_preference`](Self::with_address_family_preference)
+    pub fn with_upstream_address_family(mut self, name: &str, preference: AfPreference) -> Result<Self> {
+        self.upstream_manager.set_address_family(name, Some(preference))?;
+        Ok(self)
+    }
+    
+    /// 设置同时发往已添加上游的查询上限
+    /// 
+    /// 缓慢的上游占满名额后，查询策略在本次查询中跳过它、改用其他上游，而不是让查询堆积在它上面；
+    /// [`QueueBehavior::Wait`] 改为限时等待名额。与 [`with_concurrent_queries`](Self::with_concurrent_queries)
+    /// 的解析器总上限相互独立，跳过次数见 [`UpstreamStatus::saturated`](crate::UpstreamStatus::saturated)
+    pub fn with_upstream_max_inflight(mut self, name: &str, max_inflight: usize, behavior: QueueBehavior) -> Result<Self> {
+        self.upstream_manager.set_max_inflight(name, Some(max_inflight), behavior)?;
+        Ok(self)
+    }
+    
+    /// 设置已添加上游的p95响应时间目标
+    /// 
+    /// 需要启用上游监控：监控任务每个监控间隔评估一次，持续违反或恢复时通知
+    /// [`with_query_observer`](Self::with_query_observer) 设置的观察者，当前判定见 [`UpstreamStatus::slo`](crate::UpstreamStatus::slo)
+    pub fn with_upstream_slo(mut self, name: &str, slo: SloConfig) -> Result<Self> {
+        self.upstream_manager.set_slo(name, Some(slo))?;
+        Ok(self)
+    }
+    
+    /// 设置已添加上游的EDNS、DO位和大小写随机化开关，未配置的开关沿用解析器的设置
+    /// 
+    /// EDNS默认取 [`enable_edns`](Self::enable_edns)；关闭EDNS的上游连客户端子网也不发送
+    pub fn with_upstream_features(mut self, name: &str, features: UpstreamFeatures) -> Result<Self> {
+        self.upstream_manager.set_features(name, features)?;
+        Ok(self)
+    }
+    
+    /// 设置主动探测上游时发送的查询
+    /// 
+    /// 没有默认值：启用上游监控且配置了备用层级时必须设置，否则构建失败
+    pub fn with_health_probe(mut self, probe: ProbeConfig) -> Self {
+        self.config.health_probe = Some(probe);
+        self
+    }
+    
+    /// 设置上游静默时长上限：超过该时长没有任何查询或探测的上游，由上游监控任务不论其状态主动探测一次
+    /// 
+    /// 智能策略下曾经表现差的上游、备用层级的上游可能长期分不到查询，到故障转移时才发现已经不能用。
+    /// 探测前先衰减该上游的统计窗口，让很久以前的结果不再主导成功率；探测失败与查询失败一样计入连续失败，
+    /// 达到阈值时上游在被真正用到之前就被判为不可用。每次探测通知 [`QueryObserver::on_upstream_probe`]。
+    /// 需要启用上游监控并设置 [`with_health_probe`](Self::with_health_probe)，且必须大于监控间隔
+    pub fn with_max_upstream_silence(mut self, silence: Duration) -> Self {
+        self.config.max_upstream_silence = Some(silence);
+        self
+    }
+    
     /// 添加常用的公共DNS服务器
     pub fn with_public_dns(mut self) -> Result<Self> {
         // 国内DNS服务器
@@ -182,6 +536,18 @@ impl DnsResolverBuilder {
         Ok(self)
     }
     
+    /// 添加预设的上游服务器
+    /// 
+    /// 只会添加预设中列出的上游服务器（见 [`Preset`] 各变体的文档），
+    /// 不会改变查询策略、超时等其他配置；可以与手动添加的上游服务器组合使用
+    pub fn with_preset(mut self, preset: Preset) -> Result<Self> {
+        dns_info!("添加上游预设: {}", preset.name());
+        for spec in preset.upstreams() {
+            self.upstream_manager.add_upstream(spec)?;
+        }
+        Ok(self)
+    }
+    
     /// 设置查询超时时间
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.config.default_timeout = timeout;
@@ -210,12 +576,22 @@ impl DnsResolverBuilder {
         self
     }
     
-    /// 设置重试次数
+    /// 设置重试次数（默认2），各查询策略中的含义见 [`QueryStrategy`]
     pub fn with_retry_count(mut self, count: usize) -> Self {
         self.config.retry_count = count;
         self
     }
     
+    /// 启用/禁用统计收集（默认启用）
+    /// 
+    /// 禁用时不更新决策引擎的性能指标和上游监控的详细统计，`get_stats` 返回标记为
+    /// `stats_enabled: false` 的快照；上游状态判定、应急判定、查询历史和中间件不受影响。
+    /// 智能策略和性能指标快照依赖统计，禁用时构建报错
+    pub fn with_stats(mut self, enable: bool) -> Self {
+        self.config.enable_stats = enable;
+        self
+    }
+    
     /// 启用/禁用缓存
     pub fn with_cache(mut self, enable: bool) -> Self {
         self.config.enable_cache = enable;
@@ -228,196 +604,1456 @@ impl DnsResolverBuilder {
         self
     }
     
-    /// 启用/禁用上游监控
-    pub fn with_upstream_monitoring(mut self, enable: bool) -> Self {
-        self.config.enable_upstream_monitoring = enable;
+    /// 设置响应TTL下限
+    /// 
+    /// 低于下限的TTL会在写入缓存和返回前被抬高，设置为0表示不抬高
+    pub fn with_min_ttl(mut self, ttl: Duration) -> Self {
+        self.config.min_ttl = Some(ttl);
         self
     }
     
-    // 不再需要设置内存配置，使用全局内存池
+    /// 设置响应TTL上限
+    /// 
+    /// 高于上限的TTL会在写入缓存和返回前被压低
+    pub fn with_max_ttl(mut self, ttl: Duration) -> Self {
+        self.config.max_ttl = Some(ttl);
+        self
+    }
     
-    /// 设置DNS服务器端口
-    pub fn with_port(mut self, port: u16) -> Self {
-        self.config.port = port;
+    /// 设置是否缓存含TTL为0记录的响应
+    /// 
+    /// 默认不缓存，每次查询都问上游；开启后这些记录按1秒缓存。设置了大于0的TTL下限时TTL先被抬高，
+    /// 不受此项影响
+    pub fn with_cache_zero_ttl(mut self, enabled: bool) -> Self {
+        self.config.cache_zero_ttl = enabled;
         self
     }
     
-    /// 设置并发查询数量
-    pub fn with_concurrent_queries(mut self, count: usize) -> Self {
-        self.config.concurrent_queries = count;
+    /// 设置携带ECS客户端子网的查询如何使用缓存
+    /// 
+    /// 默认 [`EcsCacheMode::Scoped`]：按子网分别缓存；`Bypass` 则让ECS查询完全不经过缓存
+    pub fn with_ecs_cache_mode(mut self, mode: EcsCacheMode) -> Self {
+        self.config.ecs_cache_mode = mode;
         self
     }
     
-    /// 启用/禁用递归查询
-    pub fn with_recursion(mut self, enable: bool) -> Self {
-        self.config.recursion_desired = enable;
+    /// 使用自定义缓存后端并启用缓存
+    /// 
+    /// 多个解析器实例共享同一后端（例如Redis）即可共享缓存。后端读取失败或超时按未命中处理，
+    /// 写入不会阻塞查询；之后调用 `with_cache(false)` 仍会停用缓存
+    pub fn with_cache_backend(mut self, backend: Arc<dyn DnsCacheBackend>) -> Self {
+        self.config.enable_cache = true;
+        self.config.cache_backend = Some(backend);
         self
     }
     
-    /// 设置查询缓冲区大小
-    pub fn with_buffer_size(mut self, size: usize) -> Self {
-        self.config.buffer_size = size;
+    /// 设置后台清理过期缓存条目的周期和每次的工作量，`None` 表示不清理（需要启用缓存）
+    /// 
+    /// 过期条目默认只在再次查询时被覆盖，从不再查询的名称会一直留在缓存中。清理任务每个周期
+    /// 最多检查 `max_entries` 个条目或花费 `time_budget`，从上次停下的位置继续，移除过期超过
+    /// `retain_expired` 的条目（至少保留过期答案窗口内的）。默认见 [`CacheJanitorConfig`]
+    pub fn with_cache_janitor(mut self, janitor: Option<CacheJanitorConfig>) -> Self {
+        self.config.cache_janitor = janitor;
         self
     }
     
-    /// 设置日志级别
-    pub fn with_log_level(mut self, level: rat_logger::LevelFilter) -> Self {
-        self.config.log_level = level;
+    /// 设置返回A/AAAA记录时的排列方式
+    /// 
+    /// 缓存原样保存上游给出的顺序，轮换只作用于返回给调用方的副本：`Shuffle` 每次随机打乱，
+    /// `RoundRobinPerHit` 让同一查询的连续调用方拿到不同的首选地址。CNAME等记录位置不变
+    pub fn with_record_rotation(mut self, mode: RotationMode) -> Self {
+        self.config.record_rotation = mode;
         self
     }
     
-    /// 启用/禁用DNS专用日志格式
-    pub fn with_dns_log_format(mut self, enable: bool) -> Self {
-        self.config.enable_dns_log_format = enable;
+    /// 使用自定义随机数来源（大小写随机化、记录随机轮换、SRV加权排序和DNS Cookie密钥）
+    /// 
+    /// 默认使用由操作系统熵源播种的CSPRNG；报文ID不受影响，始终取自操作系统熵源
+    pub fn with_random_source(mut self, random: Arc<dyn RandomSource>) -> Self {
+        self.config.random_source = Some(random);
         self
     }
     
-    /// 设置详细日志（Debug级别）
-    pub fn with_verbose_logging(mut self) -> Self {
-        self.config.log_level = rat_logger::LevelFilter::Debug;
-        self.config.enable_dns_log_format = true;
+    /// 使用按 `seed` 确定的随机数来源，同样的上游应答和查询顺序得到同样的结果（需要启用 `test-util` 特性）
+    /// 
+    /// 用于复现测试和排查问题，不要在生产环境使用：大小写随机化变得可预测，不再能抵御伪造响应
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn with_random_seed(self, seed: u64) -> Self {
+        self.with_random_source(Arc::new(crate::resolver::random::SeededRandom::new(seed)))
+    }
+    
+    /// 使用自定义时间源（缓存、上游监控、重试退避、传输超时、各阶段耗时和决策引擎的性能指标）
+    /// 
+    /// 默认使用真实时钟；测试中可传入 [`TestClock`](crate::resolver::clock::TestClock)，推进时间而不必真正等待
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.config.clock = Some(clock);
         self
     }
     
-    /// 设置静默日志（Error级别）
-    pub fn with_quiet_logging(mut self) -> Self {
-        self.config.log_level = rat_logger::LevelFilter::Error;
+    /// 按规范顺序排列查询响应中的记录，便于比较不同次查询的结果
+    /// 
+    /// 默认记录保持上游应答段的顺序（缓存中同样如此）；排序只作用于 [`DnsQueryResponse::records`](crate::DnsQueryResponse::records)，
+    /// 在记录轮换之后进行，因此开启排序后轮换对 `records` 不再有效果
+    pub fn with_record_sort(mut self, sort: RecordSort) -> Self {
+        self.config.sort_records = Some(sort);
         self
     }
     
-    /// 设置日志初始化策略
+    /// 添加应答地址改写规则（NAT回流）：A/AAAA记录中匹配的公网地址改写为内网地址
     /// 
-    /// # 参数
-    /// * `strategy` - 日志初始化策略
-    ///   - `LoggerInitStrategy::None`: 不初始化日志，让上层应用完全控制
-    ///   - `LoggerInitStrategy::Silent`: 使用静默模式初始化
-    ///   - `LoggerInitStrategy::Debug`: 启用调试级别日志，显示所有调试信息
-    ///   - `LoggerInitStrategy::Auto`: 根据配置的日志级别自动初始化（默认）
-    pub fn with_logger_init_strategy(mut self, strategy: LoggerInitStrategy) -> Self {
-        self.logger_init_strategy = strategy;
+    /// 按添加顺序取第一条匹配的规则，TTL和不匹配的记录不变；改写写入调试日志，
+    /// 各规则的命中次数见 [`CoreResolverStats::answer_rewrites`](crate::CoreResolverStats::answer_rewrites)
+    pub fn with_answer_rewrite(mut self, rule: AnswerRewriteRule) -> Self {
+        self.config.answer_rewrite_rules.push(rule);
         self
     }
     
-    /// 禁用日志初始化（让上层应用控制）
-    /// 这是 `with_logger_init_strategy(LoggerInitStrategy::None)` 的便捷方法
-    pub fn disable_logger_init(mut self) -> Self {
-        self.logger_init_strategy = LoggerInitStrategy::None;
+    /// 为匹配 `pattern` 的名称固定TTL的调整方式，取代全局的 `min_ttl`/`max_ttl` 钳制
+    /// 
+    /// `pattern` 为完整域名或 `*.` 开头的后缀通配（只匹配子域名）；完整域名的规则优先，其次是后缀最长的通配规则。
+    /// 写入缓存和返回给调用方的TTL相同，各规则的命中次数见 [`CacheStats::ttl_overrides`](crate::CacheStats::ttl_overrides)
+    pub fn with_ttl_override(mut self, pattern: &str, ttl: OverrideTtl) -> Result<Self> {
+        self.config.ttl_overrides.push(TtlOverrideRule::new(pattern, ttl)?);
+        Ok(self)
+    }
+    
+    /// 设置地址改写在写入缓存之前还是返回给调用方时进行，默认返回时改写
+    /// 
+    /// 返回时改写让缓存保存上游原样的答案，通过 [`with_cache_backend`](Self::with_cache_backend)
+    /// 共享缓存的其他实例不会看到改写后的地址
+    pub fn with_answer_rewrite_stage(mut self, stage: RewriteStage) -> Self {
+        self.config.answer_rewrite_stage = stage;
         self
     }
     
-    /// 使用静默日志初始化
-    /// 这是 `with_logger_init_strategy(LoggerInitStrategy::Silent)` 的便捷方法
-    pub fn with_silent_logger_init(mut self) -> Self {
-        self.logger_init_strategy = LoggerInitStrategy::Silent;
+    /// 设置缓存过期后先行返回旧答案的时长（需要启用缓存）
+    /// 
+    /// 热门条目过期时，大量并发查询会在同一时刻全部打到上游。过期不超过 `window` 的条目
+    /// 会立即以TTL 0返回，同时只由一个后台任务向上游刷新；超出窗口的未命中也会合并，
+    /// 同一查询同时只有一次上游请求。窗口为零时只合并并发查询，不返回过期答案
+    pub fn with_revalidate_window(mut self, window: Duration) -> Self {
+        self.config.revalidate_window = Some(window);
         self
     }
     
-    /// 启用调试级别日志初始化
-    /// 这是 `with_logger_init_strategy(LoggerInitStrategy::Debug)` 的便捷方法
-    /// 将显示所有调试信息，包括传输创建、地址解析等详细日志
-    pub fn with_debug_logger_init(mut self) -> Self {
-        self.logger_init_strategy = LoggerInitStrategy::Debug;
+    /// 设置否定应答保护窗口（需要启用缓存）
+    /// 
+    /// 上游偶尔会对存在的名称误答NXDOMAIN或NODATA（区域传送中途、负载均衡后的某个实例故障），
+    /// 否定应答一旦缓存，在否定TTL内所有查询都得不到答案。开启后，按查询策略得到的否定应答
+    /// 推翻了过期不超过 `window` 的肯定答案时，先向另一个上游核实：另一个上游给出肯定答案时返回该答案，
+    /// 第一个上游记一次可疑否定应答（计入其健康状态，次数见 `UpstreamStatus::suspicious_negatives`）；
+    /// 两个上游都给出否定应答或没有其他可用上游时照常缓存否定应答，核实失败时返回否定应答但不缓存
+    pub fn with_nxdomain_protection_window(mut self, window: Duration) -> Self {
+        self.config.nxdomain_protection_window = Some(window);
         self
     }
     
-    /// 启用自动日志初始化
-    /// 这是 `with_logger_init_strategy(LoggerInitStrategy::Auto)` 的便捷方法
-    pub fn with_auto_logger_init(mut self) -> Self {
-        self.logger_init_strategy = LoggerInitStrategy::Auto;
+    /// 设置上游限流（HTTP 429）时的处理方式，默认 [`RateLimitedBehavior::FailoverImmediately`]
+    /// 
+    /// 默认限流的上游进入退避期，查询立即改用其他上游；只配置了一个DoH上游时查询随即失败。
+    /// [`RateLimitedBehavior::WaitAndRetry`] 在查询失败且本次查询期间有上游限流时，按该上游的 `Retry-After`
+    /// （没有时为1秒）等待，不超过 `max_wait`，也不越过请求的超时，然后向它重试一次；限流不计入上游监控的失败，
+    /// 次数见 `UpstreamStatus::rate_limited`
+    pub fn with_rate_limited_behavior(mut self, behavior: RateLimitedBehavior) -> Self {
+        self.config.on_rate_limited = behavior;
         self
     }
     
-    /// 构建解析器
-    pub async fn build(self) -> Result<SmartDnsResolver> {
-        if self.upstream_manager.get_specs().is_empty() {
-            return Err(DnsError::InvalidConfig("No upstream servers configured".to_string()));
-        }
-        
-        // 根据策略初始化日志系统
-        match self.logger_init_strategy {
-            LoggerInitStrategy::None => {
-                // 不初始化日志，让上层应用完全控制
-                // 这种情况下，上层应用负责日志初始化
-            },
-            LoggerInitStrategy::Silent => {
-                // 使用静默模式初始化
-                let _ = crate::logger::init_dns_logger_silent();
-            },
-            LoggerInitStrategy::Debug => {
-                // 启用调试级别日志，显示所有调试信息
-                let _ = crate::logger::init_dns_logger(rat_logger::LevelFilter::Debug);
-            },
-            LoggerInitStrategy::Auto => {
-                // 根据配置自动初始化（原有逻辑）
-                if self.config.log_level == rat_logger::LevelFilter::Off {
-                    // 日志级别为Off时使用静默模式
-                    let _ = crate::logger::init_dns_logger_silent();
-                } else if self.config.enable_dns_log_format {
-                    // 用户显式启用了DNS格式日志
-                    let _ = crate::logger::init_dns_logger(self.config.log_level);
-                } else {
-                    // 用户设置了日志级别但不使用DNS格式
-                    let _ = crate::logger::init_dns_logger(self.config.log_level);
-                }
-            },
-        }
-        
-        // 验证上游服务器配置
-        for spec in self.upstream_manager.get_specs() {
-            if spec.name.is_empty() {
-                return Err(DnsError::InvalidConfig("Upstream name cannot be empty".to_string()));
-            }
-            if spec.server.is_empty() {
-                return Err(DnsError::InvalidConfig(
-                    format!("Server address cannot be empty for upstream '{}'", spec.name)
-                ));
-            }
-        }
-        
-        let decision_engine = match self.query_strategy {
-            QueryStrategy::Smart | QueryStrategy::Fifo | QueryStrategy::RoundRobin => {
-                let mut engine = SmartDecisionEngine::new(self.current_region.clone());
-                
-                // 添加所有上游服务器到决策引擎
-                for spec in self.upstream_manager.get_specs() {
-                    engine.add_upstream(spec.clone()).await?;
-                }
-                
-                Some(Arc::new(engine))
-            },
-        };
-        
-        SmartDnsResolver::new(
-            self.config,
-            self.upstream_manager,
-            decision_engine,
-            self.query_strategy,
-            self.enable_edns,
-        )
+    /// 设置缓存、查询历史、上游状态变化事件和热门名称合计的内存上限（字节），默认不限制
+    /// 
+    /// 各组件在变更时报告估算的占用（条目数乘以估算的条目大小），合计超出上限时依次丢弃最旧的查询历史、
+    /// 最早的上游事件、热门名称统计，最后淘汰最久未用的缓存条目，直到回落到上限的7/8以下。
+    /// 估算不含传输连接和运行时本身，应为它们另外留出余量；使用情况见 `CoreResolverStats::memory`
+    pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+        self.config.memory_budget = Some(bytes);
+        self
     }
     
-    /// 获取当前配置的上游服务器数量
-    pub fn upstream_count(&self) -> usize {
-        self.upstream_manager.get_specs().len()
+    /// 开启自适应超时：每个上游单次发送的超时按其成功查询的p99延迟计算，
+    /// 为 `clamp(p99 × multiplier, floor, ceiling)`，由后台任务每隔 `interval` 重新计算一次
+    /// 
+    /// 样本数不足 `min_samples` 时沿用上游单独设置的超时或 [`with_timeout`](Self::with_timeout) 的默认超时；
+    /// 单次发送同时受请求 `timeout_ms` 的限制。快速上游的故障因此能在几十毫秒内发现并故障转移，
+    /// 慢而可用的上游则可以超过配置的超时（不超过 `ceiling`）。当前生效的超时见
+    /// [`UpstreamStatus::effective_timeout`](crate::UpstreamStatus::effective_timeout) 和选择解释。
+    /// 需要启用统计；自定义上游的传输自身的超时不会放宽，自适应超时只能让它提前放弃
+    pub fn with_adaptive_timeout(mut self, config: AdaptiveTimeoutConfig) -> Self {
+        self.config.adaptive_timeout = Some(config);
+        self
     }
     
-    /// 获取当前查询策略
-    pub fn current_strategy(&self) -> QueryStrategy {
-        self.query_strategy
+    /// 设置作为OpenMetrics上游指标标签输出的上游标签键（默认不输出任何标签）
+    /// 
+    /// 列出的键出现在 `rat_quickdns_upstream_*` 各指标上，没有该标签的上游取空字符串；
+    /// 只列出取值有限的键（如 `provider`、`datacenter`），工单链接之类的键会让序列数失控
+    pub fn with_metrics_upstream_labels<I, S>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.metrics_upstream_labels = keys.into_iter().map(Into::into).collect();
+        self
     }
     
-    /// 检查是否启用了EDNS
-    pub fn is_edns_enabled(&self) -> bool {
-        self.enable_edns
+    /// 开启查询摘要日志：每次查询结束时以info级别输出恰好一行 `key=value` 摘要
+    /// （查询ID、域名、类型、策略、缓存、上游、结果、答案数和耗时），查询过程中各步骤的info日志降为debug
+    /// 
+    /// 行格式固定，供日志解析使用，见 [`query_summary`](crate::builder::query_summary)
+    pub fn with_query_summary_log(mut self, enable: bool) -> Self {
+        self.config.log_query_summary = enable;
+        self
     }
     
-    /// 获取当前区域
-    pub fn current_region(&self) -> &str {
-        &self.current_region
+    /// 设置解析以主机名配置的UDP/TCP/DoT上游所用的引导解析器
+    /// 
+    /// 未设置时使用系统解析器。引导解析器不能依赖这些上游本身，例如只以IP地址配置上游的
+    /// [`CoreResolver`](crate::CoreResolver)。DoT上游的SNI仍使用主机名，只有连接地址换成解析出的IP
+    pub fn with_bootstrap_resolver(mut self, resolver: Arc<dyn BootstrapResolver>) -> Self {
+        self.config.host_resolution.resolver = Some(resolver);
+        self
     }
     
-    /// 获取上游管理器的引用
-    pub fn upstream_manager(&self) -> &UpstreamManager {
-        &self.upstream_manager
+    /// 设置引导解析器失败时是否改用系统解析器，默认不改用
+    pub fn with_system_resolver_fallback(mut self, enabled: bool) -> Self {
+        self.config.host_resolution.system_fallback = enabled;
+        self
+    }
+    
+    /// 设置上游主机名解析结果的有效期（默认5分钟）
+    /// 
+    /// 到期后下一次发送前重新解析；连续3次网络失败时不等到期就重新解析，上游可能已经换了地址
+    pub fn with_upstream_address_refresh(mut self, interval: Duration) -> Self {
+        self.config.host_resolution.refresh_interval = interval;
+        self
+    }
+    
+    /// 设置以主机名配置的UDP/TCP/DoT上游连接哪个地址族
+    /// 
+    /// 未设置时按引导解析器给出的顺序连接。`PreferV4`/`PreferV6` 在偏好的地址族连接失败（UDP为网络错误或超时）时
+    /// 改用另一个，失败的地址族30秒内排在后面；两个地址族的连接结果分别统计，见
+    /// [`UpstreamStatus::address_families`](crate::UpstreamStatus::address_families)。
+    /// 单个上游可以用 [`with_upstream_address_family`](Self::with_upstream_address_family) 另行设置
+    pub fn with_address_family_preference(mut self, preference: AfPreference) -> Self {
+        self.config.host_resolution.family_preference = Some(preference);
+        self
+    }
+    
+    /// 设置构建时上游主机名解析失败是否让构建失败
+    /// 
+    /// 默认只把解析失败的上游标记为不可用（需要启用上游监控），构建照常完成，之后发送时重新解析
+    pub fn with_strict_upstream_resolution(mut self, strict: bool) -> Self {
+        self.config.strict_upstream_resolution = strict;
+        self
     }
-}
\ No newline at end of file
+    
+    /// 添加查询中间件，先添加的在外层，最先看到请求、最后看到响应
+    /// 
+    /// 中间件可以改写请求、处理响应，或不调用 [`Next::run`](crate::builder::middleware::Next::run) 直接应答，
+    /// 见 [`QueryMiddleware`]。`query`、`lookup_ip` 等单条查询都经过中间件
+    pub fn with_middleware(mut self, middleware: Arc<dyn QueryMiddleware>) -> Self {
+        self.middlewares.push(middleware);
+        self
+    }
+    
+    /// 所有启用的上游被上游监控判定为不可用超过 `after` 后自动进入离线模式（需要启用上游监控）
+    /// 
+    /// 离线期间只由缓存应答，过期的条目照常返回，不向上游发送查询；配置了
+    /// [`with_health_probe`](Self::with_health_probe) 时每个监控间隔探测一次不可用的上游，
+    /// 有上游恢复可用即自动退出。也可以用 [`SmartDnsResolver::set_offline`] 手动切换
+    pub fn with_auto_offline(mut self, after: Duration) -> Self {
+        self.config.auto_offline_after = Some(after);
+        self
+    }
+    
+    /// 设置所有加密上游（DoT/DoH）证书校验失败时的处理方式，默认 [`EncryptedFallbackPolicy::Strict`] 不降级
+    /// 
+    /// 强制门户或TLS拦截代理会让证书校验全部失败。开启降级后，每个加密上游都连续
+    /// [`with_encrypted_fallback_threshold`](Self::with_encrypted_fallback_threshold) 次证书无效时，
+    /// 查询暂时改走明文DNS或不校验证书的加密连接，同时在后台探测加密上游，恢复即切回。
+    /// 降级期间的应答标记 [`DnsQueryResponse::degraded_security`](crate::DnsQueryResponse::degraded_security)，不写入缓存
+    pub fn with_encrypted_fallback(mut self, policy: EncryptedFallbackPolicy) -> Self {
+        self.config.encrypted_fallback = policy;
+        self
+    }
+    
+    /// 设置每个加密上游连续多少次证书校验失败后才算失败（默认3），0返回错误
+    pub fn with_encrypted_fallback_threshold(mut self, failures: u32) -> Result<Self> {
+        if failures == 0 {
+            return Err(DnsError::InvalidConfig("Encrypted fallback threshold must be greater than zero".to_string()));
+        }
+        self.config.encrypted_fallback_threshold = failures;
+        Ok(self)
+    }
+    
+    /// 设置降级期间探测加密上游的间隔（默认30秒）
+    pub fn with_encrypted_fallback_probe_interval(mut self, interval: Duration) -> Self {
+        self.config.encrypted_fallback_probe_interval = interval;
+        self
+    }
+    
+    /// 开启加密上游的握手失败隔离，配置无效时返回错误
+    /// 
+    /// 证书过期的DoT/DoH上游会让每个选中它的查询先付出一次完整握手再故障转移。开启后，加密上游第一次出现
+    /// 证书无效、证书固定不符等握手失败（握手超时除外）即隔离 `config.initial`，隔离期内查询策略跳过它、不建立连接；
+    /// 期满后的第一个查询作为探测，仍然失败时隔离时长加倍（不超过 `config.max`），成功则立即解除。
+    /// 隔离状态见 [`UpstreamStatus::quarantine`](crate::UpstreamStatus::quarantine)，
+    /// 更换证书后可以用 [`SmartDnsResolver::clear_quarantine`] 立即解除。
+    /// 开启了 [`with_encrypted_fallback`](Self::with_encrypted_fallback) 时加密上游的证书失败由降级处理，不隔离
+    pub fn with_tls_quarantine(mut self, config: TlsQuarantineConfig) -> Result<Self> {
+        config.validate()?;
+        self.config.tls_quarantine = Some(config);
+        Ok(self)
+    }
+    
+    /// 指定加密上游 `upstream` 明文降级时使用的备用上游（`地址[:端口]`，默认端口53）
+    /// 
+    /// 未指定的加密上游降级时连接同一主机的53端口（UDP）
+    pub fn with_plaintext_fallback(mut self, upstream: impl Into<String>, server: &str) -> Self {
+        let address = parse_simple_server_address(server, 53);
+        self.config.plaintext_fallbacks.insert(upstream.into(), address);
+        self
+    }
+    
+    /// 设置退出离线模式后每秒最多刷新的过期条目数（默认10），0返回错误
+    pub fn with_offline_revalidation_rate(mut self, per_second: u32) -> Result<Self> {
+        if per_second == 0 {
+            return Err(DnsError::InvalidConfig("Offline revalidation rate must be greater than zero".to_string()));
+        }
+        self.config.offline_revalidation_rate = per_second;
+        Ok(self)
+    }
+    
+    /// 设置是否拒绝可疑响应
+    /// 
+    /// QR位未置位或回显问题与查询不一致的响应总是不会写入缓存；开启后这类响应
+    /// 还会以 [`DnsError::Protocol`] 返回给调用方，而不是当作正常答案
+    pub fn with_strict_response_check(mut self, enabled: bool) -> Self {
+        self.config.strict_response_check = enabled;
+        self
+    }
+    
+    /// 设置是否接受没有问题段的响应
+    /// 
+    /// 默认拒绝。开启后，ID与查询一致、且每条回答记录的所有者都是查询名或其CNAME链上名称的
+    /// 无问题段响应被接受，并补上查询作为问题段；问题段多于一条的响应不论此项都按畸形报文拒绝
+    pub fn with_accept_questionless_responses(mut self, enabled: bool) -> Self {
+        self.config.accept_questionless_responses = enabled;
+        self
+    }
+    
+    /// 设置是否保留类型与查询不符的应答记录
+    /// 
+    /// 默认只保留查询类型的记录、通往它的CNAME链和请求DNSSEC时的RRSIG，其余记录去掉并计入
+    /// `CoreResolverStats::dropped_mismatched_records`；排查上游的应答时可以开启，原样返回
+    pub fn with_lenient_answer_types(mut self, enabled: bool) -> Self {
+        self.config.lenient_answer_types = enabled;
+        self
+    }
+    
+    /// 设置单个响应的回答记录数、记录总数和CNAME链跳数上限，以及超出时截断还是拒绝
+    /// 
+    /// 拒绝模式下内置传输按报文头部的计数提前失败，不为超出的记录分配空间；截断模式下截去超出的记录，
+    /// 应答标记 `records_truncated`。默认回答记录512条、总数1024条、CNAME链16跳，超出时截断。
+    /// 上限为0或回答记录数上限超过总数上限时返回错误
+    pub fn with_record_limits(mut self, limits: RecordLimits) -> Result<Self> {
+        limits.validate()?;
+        self.config.record_limits = limits;
+        Ok(self)
+    }
+    
+    /// 设置bincode编码查询接口的输入上限
+    /// 
+    /// `process_encoded_query` 和 `process_encoded_batch` 在解码前检查载荷大小和批量查询数，
+    /// 超出时返回带错误代码的失败响应。默认单个载荷64 KiB、每批1000个查询；上限为0或载荷上限超过1 MiB时返回错误
+    pub fn with_encoded_request_limits(mut self, limits: EncodedRequestLimits) -> Result<Self> {
+        limits.validate()?;
+        self.config.encoded_request_limits = limits;
+        Ok(self)
+    }
+    
+    /// 设置因超出记录数上限被截断的响应是否写入缓存
+    /// 
+    /// 默认不缓存，下一次查询重新向上游请求；开启后命中缓存的截断应答不再标记 `records_truncated`
+    pub fn with_cache_truncated_responses(mut self, enabled: bool) -> Self {
+        self.config.cache_truncated_responses = enabled;
+        self
+    }
+    
+    /// 设置是否记录上游查询的分阶段耗时
+    /// 
+    /// 开启后 [`DnsQueryResponse::timing`](crate::builder::types::DnsQueryResponse::timing) 给出连接、TLS握手、请求写出、首字节等阶段的耗时，
+    /// 用于定位慢上游；关闭时传输不额外读取时钟
+    pub fn with_timing_breakdown(mut self, enabled: bool) -> Self {
+        self.config.capture_timing_breakdown = enabled;
+        self
+    }
+    
+    /// 设置保留原始报文时每个报文最多保留的字节数
+    /// 
+    /// 只作用于开启 [`DnsQueryRequest::capture_wire`](crate::builder::types::DnsQueryRequest::capture_wire) 的查询，
+    /// 超出部分被丢弃并在响应中标记 `wire_truncated`。默认完整保留
+    pub fn with_wire_capture_limit(mut self, max_bytes: usize) -> Result<Self> {
+        if max_bytes == 0 {
+            return Err(DnsError::InvalidConfig("Wire capture limit must be greater than zero".to_string()));
+        }
+        self.config.wire_capture_max_bytes = max_bytes;
+        Ok(self)
+    }
+    
+    /// 设置一次查询最多尝试的上游数
+    /// 
+    /// 上游返回SERVFAIL或REFUSED时改向尚未尝试过的上游查询，直到得到其他响应码、
+    /// 上游用尽或达到该上限；NXDOMAIN和NODATA不会触发。1表示关闭故障转移，0返回错误
+    pub fn with_max_failover_attempts(mut self, attempts: usize) -> Result<Self> {
+        if attempts == 0 {
+            return Err(DnsError::InvalidConfig("Max failover attempts must be at least 1".to_string()));
+        }
+        self.config.max_failover_attempts = attempts;
+        Ok(self)
+    }
+    
+    /// 启用或禁用DNS Cookie（RFC 7873）
+    /// 
+    /// 启用后UDP上游的查询携带客户端Cookie，并回显服务器给出的服务器Cookie；
+    /// 服务器返回BADCOOKIE时带上新的服务器Cookie重试一次。TCP、DoT和DoH不受影响
+    pub fn with_dns_cookies(mut self, enabled: bool) -> Self {
+        self.config.enable_dns_cookies = enabled;
+        self
+    }
+    
+    /// 设置客户端Cookie的更换周期，更换后此前取得的服务器Cookie不再使用
+    pub fn with_dns_cookie_lifetime(mut self, lifetime: Duration) -> Result<Self> {
+        if lifetime.is_zero() {
+            return Err(DnsError::InvalidConfig("DNS cookie lifetime must be greater than zero".to_string()));
+        }
+        self.config.dns_cookie_lifetime = lifetime;
+        Ok(self)
+    }
+    
+    /// 设置所有UDP上游的源端口池，替换按连接池大小预先绑定、默认轮换和耗尽策略的池
+    /// 
+    /// 每个UDP上游各有一个这样的池：查询从池中借出套接字、结束后归还，同时占用的源端口数不超过 `size`
+    /// （额外套接字除外），高QPS下不会耗尽临时端口。使用情况见 [`SmartDnsResolver::udp_pool_stats`]
+    pub fn with_udp_socket_pool(mut self, pool: UdpPoolConfig) -> Result<Self> {
+        pool.validate()?;
+        self.config.udp_socket_pool = Some(pool);
+        Ok(self)
+    }
+    
+    /// 开启上游粘性：同一粘性键（客户端子网或租户）的查询优先使用上次成功应答的上游
+    /// 
+    /// 记住的上游被上游监控判定为不可用、停用或进入退避时按查询策略选择，并改为记住实际应答的上游。
+    /// 按域名转发的查询和应急模式不受影响。记录表的使用情况见 [`SmartDnsResolver::stickiness_stats`]
+    pub fn with_stickiness(mut self, stickiness: StickinessConfig) -> Result<Self> {
+        stickiness.validate()?;
+        self.config.stickiness = Some(stickiness);
+        Ok(self)
+    }
+    
+    /// 设置默认的EDNS客户端子网（ECS），未单独指定客户端地址的查询都会携带该子网
+    /// 
+    /// 前缀长度不能超过地址位数（IPv4为32，IPv6为128）
+    pub fn with_default_client_ip(mut self, ip: IpAddr, prefix_length: u8) -> Result<Self> {
+        let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+        if prefix_length > max_prefix {
+            return Err(DnsError::InvalidConfig(
+                format!("Client subnet prefix /{} is too long for {}", prefix_length, ip)
+            ));
+        }
+        self.config.default_client_address = Some(ClientAddress::new(ip, prefix_length));
+        Ok(self)
+    }
+    
+    /// 设置上游监控的检查间隔
+    pub fn with_upstream_monitoring_interval(mut self, interval: Duration) -> Self {
+        self.config.upstream_monitoring_interval = interval;
+        self
+    }
+    
+    /// 启用/禁用上游监控
+    pub fn with_upstream_monitoring(mut self, enable: bool) -> Self {
+        self.config.enable_upstream_monitoring = enable;
+        self
+    }
+    
+    /// 设置上游监控为每个上游保留的状态变化事件数（默认64条，0表示不记录）
+    /// 
+    /// 事件通过 `SmartDnsResolver::get_upstream_events` 读取，需要启用上游监控
+    pub fn with_upstream_event_history(mut self, capacity: usize) -> Self {
+        self.config.upstream_event_history = capacity;
+        self
+    }
+    
+    /// 设置接收上游SLO违反、恢复和主动探测通知的观察者，见 [`with_upstream_slo`](Self::with_upstream_slo)
+    /// 和 [`with_max_upstream_silence`](Self::with_max_upstream_silence)
+    pub fn with_query_observer(mut self, observer: Arc<dyn QueryObserver>) -> Self {
+        self.config.query_observer = Some(observer);
+        self
+    }
+    
+    // 不再需要设置内存配置，使用全局内存池
+    
+    /// 设置DNS服务器端口
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+    
+    /// 设置同时进行的上游发送数上限
+    /// 
+    /// 所有查询合计，同时查询多个上游的策略每个上游占一个名额；名额用完时新的发送排队等待，
+    /// 等待时限见 [`with_concurrency_wait_timeout`](Self::with_concurrency_wait_timeout)
+    pub fn with_concurrent_queries(mut self, count: usize) -> Self {
+        self.config.concurrent_queries = count;
+        self
+    }
+    
+    /// 设置等待并发名额的时限，超过时该次发送返回 [`DnsError::Busy`]（不设置则一直等待）
+    pub fn with_concurrency_wait_timeout(mut self, wait: Duration) -> Self {
+        self.config.concurrency_wait_timeout = Some(wait);
+        self
+    }
+    
+    /// 设置为交互式查询（[`QueryPriority::Interactive`](crate::QueryPriority::Interactive)）保留的并发名额比例，须在 `[0, 1)` 内，默认0
+    /// 
+    /// 普通和批量查询只能使用其余的名额，批量查询占满它们时交互式查询仍有名额可用；
+    /// 设置了 `max_inflight` 的上游也按此比例保留。名额按比例向下取整，至少给其他优先级留一个
+    pub fn with_interactive_reserve(mut self, fraction: f64) -> Result<Self> {
+        check_reserve(fraction)?;
+        self.config.interactive_reserve = fraction;
+        Ok(self)
+    }
+    
+    /// 启用/禁用递归查询
+    pub fn with_recursion(mut self, enable: bool) -> Self {
+        self.config.recursion_desired = enable;
+        self
+    }
+    
+    /// 设置响应缓冲区大小（字节）
+    /// 
+    /// UDP按它分配接收缓冲区并在OPT记录中声明为载荷大小，更长的UDP/TCP/DoT响应返回 [`DnsError::Protocol`]；
+    /// DoH响应正文超过该长度时返回 [`DnsError::ResponseTooLarge`]
+    pub fn with_buffer_size(mut self, size: usize) -> Self {
+        self.config.buffer_size = size;
+        self
+    }
+    
+    /// 设置DoH上游是否跟随3xx重定向（默认跟随）
+    /// 
+    /// 只跟随同源（协议、主机、端口相同）的重定向，最多2跳；不跟随时返回3xx的 [`DnsError::HttpStatus`]
+    pub fn with_doh_follow_redirects(mut self, follow: bool) -> Self {
+        self.config.doh_follow_redirects = follow;
+        self
+    }
+    
+    /// 设置DoT上游恢复会话时是否把查询作为0-RTT早期数据发送（默认不发送）
+    /// 
+    /// 会话恢复总是开启的；早期数据省去一次往返，但可能被重放。服务器拒绝早期数据时在握手完成后自动重发，
+    /// 各上游的接受情况见 [`SmartDnsResolver::tls_session_stats`]
+    pub fn with_tls_early_data(mut self, enable: bool) -> Self {
+        self.config.tls_early_data = enable;
+        self
+    }
+    
+    /// 设置TCP和DoT上游是否以TCP Fast Open方式连接（默认不开启）
+    /// 
+    /// 开启后查询（DoT为ClientHello）随SYN一起发出，省去一次往返。Linux和macOS支持，
+    /// 其他平台或内核拒绝时照常连接；各上游的使用情况见 [`SmartDnsResolver::fast_open_stats`]
+    pub fn with_tcp_fast_open(mut self, enable: bool) -> Self {
+        self.config.tcp_fast_open = enable;
+        self
+    }
+    
+    /// 设置日志级别
+    pub fn with_log_level(mut self, level: crate::logger::LevelFilter) -> Self {
+        self.config.log_level = level;
+        self
+    }
+    
+    /// 启用/禁用DNS专用日志格式
+    pub fn with_dns_log_format(mut self, enable: bool) -> Self {
+        self.config.enable_dns_log_format = enable;
+        self
+    }
+    
+    /// 设置详细日志（Debug级别）
+    pub fn with_verbose_logging(mut self) -> Self {
+        self.config.log_level = crate::logger::LevelFilter::Debug;
+        self.config.enable_dns_log_format = true;
+        self
+    }
+    
+    /// 设置静默日志（Error级别）
+    pub fn with_quiet_logging(mut self) -> Self {
+        self.config.log_level = crate::logger::LevelFilter::Error;
+        self
+    }
+    
+    /// 设置日志初始化策略
+    /// 
+    /// # 参数
+    /// * `strategy` - 日志初始化策略
+    ///   - `LoggerInitStrategy::None`: 不初始化日志，让上层应用完全控制
+    ///   - `LoggerInitStrategy::Silent`: 使用静默模式初始化
+    ///   - `LoggerInitStrategy::Debug`: 启用调试级别日志，显示所有调试信息
+    ///   - `LoggerInitStrategy::Auto`: 根据配置的日志级别自动初始化（默认）
+    ///   - `LoggerInitStrategy::External`: 写入调用方已安装的处理器
+    pub fn with_logger_init_strategy(mut self, strategy: LoggerInitStrategy) -> Self {
+        self.logger_init_strategy = strategy;
+        self
+    }
+    
+    /// 设置日志初始化策略，与 `with_logger_init_strategy` 相同
+    pub fn with_logger_strategy(self, strategy: LoggerInitStrategy) -> Self {
+        self.with_logger_init_strategy(strategy)
+    }
+    
+    /// 将DNS日志写入调用方已安装的rat_logger处理器
+    /// 这是 `with_logger_init_strategy(LoggerInitStrategy::External)` 的便捷方法
+    pub fn with_caller_logger(mut self) -> Self {
+        self.logger_init_strategy = LoggerInitStrategy::External;
+        self
+    }
+    
+    /// 禁用日志初始化（让上层应用控制）
+    /// 这是 `with_logger_init_strategy(LoggerInitStrategy::None)` 的便捷方法
+    pub fn disable_logger_init(mut self) -> Self {
+        self.logger_init_strategy = LoggerInitStrategy::None;
+        self
+    }
+    
+    /// 使用静默日志初始化
+    /// 这是 `with_logger_init_strategy(LoggerInitStrategy::Silent)` 的便捷方法
+    pub fn with_silent_logger_init(mut self) -> Self {
+        self.logger_init_strategy = LoggerInitStrategy::Silent;
+        self
+    }
+    
+    /// 启用调试级别日志初始化
+    /// 这是 `with_logger_init_strategy(LoggerInitStrategy::Debug)` 的便捷方法
+    /// 将显示所有调试信息，包括传输创建、地址解析等详细日志
+    pub fn with_debug_logger_init(mut self) -> Self {
+        self.logger_init_strategy = LoggerInitStrategy::Debug;
+        self
+    }
+    
+    /// 启用自动日志初始化
+    /// 这是 `with_logger_init_strategy(LoggerInitStrategy::Auto)` 的便捷方法
+    pub fn with_auto_logger_init(mut self) -> Self {
+        self.logger_init_strategy = LoggerInitStrategy::Auto;
+        self
+    }
+    
+    /// 设置性能指标快照文件
+    /// 
+    /// 构建时若文件存在则导入其中的指标（损坏或无法读取时仅记录警告，按冷启动处理），
+    /// 之后按 `with_metrics_snapshot_interval` 设置的间隔定期保存，解析器释放时再保存一次
+    pub fn with_metrics_snapshot_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.metrics_snapshot_path = Some(path.into());
+        self
+    }
+    
+    /// 设置性能指标快照的保存间隔
+    pub fn with_metrics_snapshot_interval(mut self, interval: Duration) -> Self {
+        self.metrics_snapshot_interval = interval;
+        self
+    }
+    
+    /// 启用查询历史记录，保留最近 `capacity` 条查询
+    /// 
+    /// 历史可通过 `SmartDnsResolver::recent_queries` / `queries_for_domain` 读取，容量必须大于0
+    pub fn with_query_history(mut self, capacity: usize) -> Self {
+        self.query_history_capacity = Some(capacity);
+        self
+    }
+    
+    /// 按请求上下文中的租户统计查询数、失败数和缓存命中数，最多单独统计 `max_tenants` 个租户
+    /// 
+    /// 之后出现的新租户合并计入 [`OTHER_TENANTS`](super::tenant::OTHER_TENANTS)，上限必须大于0。
+    /// 计数通过 `SmartDnsResolver::tenant_stats` 读取，没有租户的查询不计入
+    pub fn with_tenant_stats(mut self, max_tenants: usize) -> Self {
+        self.tenant_stats_limit = Some(max_tenants);
+        self
+    }
+    
+    /// 配置CDN准确性探测，替代按查询成败估计的CDN准确性
+    /// 
+    /// 构建后立即并每隔 [`with_cdn_probe_interval`](Self::with_cdn_probe_interval) 通过每个上游查询一次
+    /// 探测域名，应答的A记录全部落在预期地址段内才算准确，结果计入该上游的CDN准确性评分。
+    /// 只执行区域与解析器区域相同的探测；没有可执行的探测时CDN准确性不参与上游评分。需要统计
+    pub fn with_cdn_probes(mut self, probes: Vec<CdnProbe>) -> Self {
+        self.cdn_probes = probes;
+        self
+    }
+    
+    /// 设置CDN准确性探测间隔（默认 [`DEFAULT_CDN_PROBE_INTERVAL`]）
+    pub fn with_cdn_probe_interval(mut self, interval: Duration) -> Self {
+        self.cdn_probe_interval = interval;
+        self
+    }
+    
+    /// 设置为未指定ID的查询分配查询ID的格式（默认 [`QueryIdFormat::Sequence`]）
+    /// 
+    /// 需要与外部系统按RFC 4122 UUID关联查询时使用 [`QueryIdFormat::Uuid`]；调用方指定的ID总是原样带回
+    pub fn with_query_id_format(mut self, format: QueryIdFormat) -> Self {
+        self.query_id_format = format;
+        self
+    }
+    
+    /// 启用应急模式：最近查询的成功率低于 `threshold`（0.0-1.0）时向所有上游并发查询
+    /// 
+    /// 窗口、恢复幅度和TTL下限使用 [`EmergencyPolicy::new`] 的取值，需要调整时使用
+    /// `with_emergency_policy`
+    pub fn with_emergency_threshold(self, threshold: f64) -> Result<Self> {
+        self.with_emergency_policy(EmergencyPolicy::new(threshold))
+    }
+    
+    /// 按完整的判定参数启用应急模式
+    pub fn with_emergency_policy(mut self, policy: EmergencyPolicy) -> Result<Self> {
+        policy.validate()?;
+        self.emergency_policy = Some(policy);
+        Ok(self)
+    }
+    
+    /// 设置同一服务器（相同协议和地址）被重复注册时的处理方式
+    /// 
+    /// 为 `true` 时保留权重较高的定义并记录警告，为 `false`（默认）时构建报错。
+    /// 名称重复始终报错。
+    pub fn with_dedup_upstreams(mut self, dedup: bool) -> Self {
+        self.dedup_upstreams = dedup;
+        self
+    }
+    
+    /// 构建时通过DDR发现明文上游声明的加密服务（DoH/DoT），按证书校验后注册为新上游
+    /// 
+    /// 发现失败不影响构建。需要调整权重、停用明文上游或替换校验方式时使用
+    /// [`with_ddr_options`](Self::with_ddr_options)。
+    pub fn with_ddr(mut self, enable: bool) -> Self {
+        self.ddr = enable.then(DdrOptions::new);
+        self
+    }
+    
+    /// 以指定参数在构建时执行加密上游发现
+    pub fn with_ddr_options(mut self, options: DdrOptions) -> Self {
+        self.ddr = Some(options);
+        self
+    }
+    
+    /// 构建后按列表文件预热缓存，文件每行一个 `domain[,type]` 条目，见 [`warmup`](crate::builder::warmup)
+    /// 
+    /// 预热在 `build` 返回前完成；文件无法读取、格式错误或查询失败都不影响构建，只记录警告
+    pub fn with_warmup_list(mut self, path: impl Into<PathBuf>) -> Self {
+        self.warmup_list = Some(path.into());
+        self
+    }
+    
+    /// 设置构建后预热缓存的并发数、出错处理和时限，默认并发8、出错继续、不限时
+    pub fn with_warmup_options(mut self, options: WarmOptions) -> Self {
+        self.warmup_options = options;
+        self
+    }
+    
+    /// 按域名转发：规则匹配的域名只交给规则指定的上游或在本地应答，优先于查询策略
+    /// 
+    /// 规则集中的默认上游加入普通上游列表，与构建器添加的上游一起参与查询策略；
+    /// 只用于转发规则的上游不参与查询策略。规则引用了不存在的上游时返回错误
+    pub fn with_domain_router(mut self, router: DomainRouter) -> Result<Self> {
+        router.validate()?;
+        for spec in router.default_upstreams() {
+            self.upstream_manager.add_upstream(spec.clone())?;
+        }
+        self.domain_router = Some(router);
+        Ok(self)
+    }
+    
+    /// 从dnsmasq配置文件加载按域名转发的规则，见 [`DomainRouter::from_dnsmasq_conf`]
+    pub fn with_dnsmasq_conf(self, path: impl AsRef<std::path::Path>) -> Result<Self> {
+        self.with_domain_router(DomainRouter::from_dnsmasq_conf(path)?)
+    }
+    
+    /// 使用系统配置的DNS服务器：Unix读取 `/etc/resolv.conf`，Windows读取注册表，
+    /// 见 [`with_resolv_conf`](Self::with_resolv_conf)
+    /// 
+    /// 读取或解析失败时返回错误，不会得到零个上游
+    pub fn with_system_resolvers(self) -> Result<Self> {
+        let conf = SystemResolvConf::load().map_err(|e| DnsError::InvalidConfig(e.to_string()))?;
+        self.with_resolv_conf(&conf)
+    }
+    
+    /// 使用解析好的系统解析器配置：每个服务器添加为UDP上游 `system-1`、`system-2`……
+    /// 
+    /// 配置了 `options timeout` 时作为默认超时，`options attempts` 换算为重试次数；
+    /// 搜索域和ndots被保存下来，需要 [`apply_search_domains`](Self::apply_search_domains) 才会展开查询名称
+    pub fn with_resolv_conf(mut self, conf: &SystemResolvConf) -> Result<Self> {
+        for spec in conf.upstream_specs() {
+            self.upstream_manager.add_upstream(spec)?;
+        }
+        if let Some(timeout) = conf.timeout {
+            self.config.default_timeout = timeout;
+        }
+        if let Some(attempts) = conf.attempts {
+            self.config.retry_count = attempts.saturating_sub(1) as usize;
+        }
+        self.search_domains = Some(conf.search.clone());
+        Ok(self)
+    }
+    
+    /// 设置搜索域和ndots，覆盖系统配置中读取的搜索域
+    pub fn with_search_domains(mut self, search: SearchDomains) -> Self {
+        self.search_domains = Some(search);
+        self
+    }
+    
+    /// 查询前按搜索域展开名称（默认关闭），规则见 [`SearchDomains::candidates`]
+    /// 
+    /// 依次查询展开后的名称，直到得到NXDOMAIN以外且有记录的应答；全部没有记录时返回最后一个应答。
+    /// 启用时必须配置搜索域
+    pub fn apply_search_domains(mut self, enable: bool) -> Self {
+        self.apply_search_domains = enable;
+        self
+    }
+    
+    /// 构建前的全部配置校验，重复的上游按 `dedup_upstreams` 合并；构建和 [`validate_only`](Self::validate_only) 共用
+    fn validate(&mut self) -> Result<()> {
+        if self.upstream_manager.get_specs().is_empty() {
+            return Err(DnsError::InvalidConfig("No upstream servers configured".to_string()));
+        }
+        
+        // 名称重复、名称含非法字符或同一服务器重复注册时在触碰日志系统之前报错
+        self.upstream_manager.validate_upstreams(self.dedup_upstreams)?;
+        if let Some(router) = &self.domain_router {
+            for spec in router.upstreams() {
+                if self.upstream_manager.get_specs().iter().any(|existing| existing.name == spec.name) {
+                    return Err(DnsError::InvalidConfig(format!("Duplicate upstream name '{}' in domain routes", spec.name)));
+                }
+            }
+        }
+        if !self.config.enable_stats {
+            if self.query_strategy == QueryStrategy::Smart {
+                return Err(DnsError::InvalidConfig(
+                    "Smart strategy selects upstreams by collected metrics and requires statistics; \
+                     enable them (with_stats(true)) or use Fifo, RoundRobin or Sequential".to_string()
+                ));
+            }
+            if self.metrics_snapshot_path.is_some() {
+                return Err(DnsError::InvalidConfig(
+                    "Metrics snapshots require statistics (with_stats(true))".to_string()
+                ));
+            }
+            if !self.cdn_probes.is_empty() {
+                return Err(DnsError::InvalidConfig(
+                    "CDN probes feed collected metrics and require statistics (with_stats(true))".to_string()
+                ));
+            }
+        }
+        for probe in &self.cdn_probes {
+            probe.validate()?;
+        }
+        if let QueryStrategy::Quorum { required } = self.query_strategy {
+            let upstreams = self.upstream_manager.get_specs().len();
+            if required == 0 || required > upstreams {
+                return Err(DnsError::InvalidConfig(format!(
+                    "Quorum strategy requires between 1 and {} agreeing upstreams (the number configured), got {}",
+                    upstreams, required
+                )));
+            }
+        }
+        if !self.cdn_probes.is_empty() && self.cdn_probe_interval.is_zero() {
+            return Err(DnsError::InvalidConfig("CDN probe interval must be greater than zero".to_string()));
+        }
+        if self.apply_search_domains && self.search_domains.is_none() {
+            return Err(DnsError::InvalidConfig(
+                "apply_search_domains requires search domains (with_system_resolvers or with_search_domains)".to_string()
+            ));
+        }
+        let has_backup_tier = self.upstream_manager.get_specs().iter().any(|spec| spec.tier > 0);
+        match &self.config.health_probe {
+            Some(probe) => probe.validate()?,
+            None if self.config.enable_upstream_monitoring && has_backup_tier => {
+                return Err(DnsError::InvalidConfig(
+                    "Backup tiers with upstream monitoring require a health probe (with_health_probe)".to_string()
+                ));
+            }
+            None => {}
+        }
+        if let Some(adaptive) = &self.config.adaptive_timeout {
+            adaptive.validate()?;
+            if !self.config.enable_stats {
+                return Err(DnsError::InvalidConfig(
+                    "Adaptive timeouts need latency statistics, statistics must be enabled".to_string()
+                ));
+            }
+        }
+        for key in &self.config.metrics_upstream_labels {
+            check_label_key(key)
+                .map_err(|e| DnsError::InvalidConfig(format!("Invalid metrics upstream label: {}", e)))?;
+        }
+        if self.config.auto_offline_after.is_some() && !self.config.enable_upstream_monitoring {
+            return Err(DnsError::InvalidConfig(
+                "Auto offline mode requires upstream monitoring (with_upstream_monitoring)".to_string()
+            ));
+        }
+        if let Some(silence) = self.config.max_upstream_silence {
+            if !self.config.enable_upstream_monitoring || self.config.health_probe.is_none() {
+                return Err(DnsError::InvalidConfig(
+                    "Upstream silence detection requires upstream monitoring and a health probe (with_health_probe)".to_string()
+                ));
+            }
+            if silence <= self.config.upstream_monitoring_interval {
+                return Err(DnsError::InvalidConfig(format!(
+                    "max_upstream_silence ({:?}) must be longer than the upstream monitoring interval ({:?})",
+                    silence, self.config.upstream_monitoring_interval
+                )));
+            }
+        }
+        
+        if let (Some(min), Some(max)) = (self.config.min_ttl, self.config.max_ttl)
+            && min > max
+        {
+            return Err(DnsError::InvalidConfig(
+                format!("min_ttl ({:?}) cannot exceed max_ttl ({:?})", min, max)
+            ));
+        }
+        
+        // 验证上游服务器地址（名称已在前面校验）
+        for spec in self.upstream_manager.get_specs() {
+            if spec.server.is_empty() {
+                return Err(DnsError::InvalidConfig(
+                    format!("Server address cannot be empty for upstream '{}'", spec.name)
+                ));
+            }
+        }
+        
+        if self.metrics_snapshot_path.is_some() && self.metrics_snapshot_interval.is_zero() {
+            return Err(DnsError::InvalidConfig("Metrics snapshot interval must be greater than zero".to_string()));
+        }
+        if let Some(capacity) = self.query_history_capacity {
+            QueryHistory::new(capacity)?;
+        }
+        if let Some(max_tenants) = self.tenant_stats_limit {
+            TenantStatsTable::new(max_tenants)?;
+        }
+        if let Some(stickiness) = &self.config.stickiness {
+            StickyPins::new(*stickiness)?;
+        }
+        if let Some(router) = &self.domain_router {
+            router.validate()?;
+        }
+        Ok(())
+    }
+    
+    /// 构建解析器
+    pub async fn build(mut self) -> Result<SmartDnsResolver> {
+        self.validate()?;
+        
+        // 根据策略初始化日志系统
+        match self.logger_init_strategy {
+            LoggerInitStrategy::None => {
+                // 不初始化日志，让上层应用完全控制
+                // 这种情况下，上层应用负责日志初始化
+            },
+            LoggerInitStrategy::Debug => {
+                // 调用方明确要求本库的调试输出，全局处理器被占用时报错
+                crate::logger::install_dns_logger(crate::logger::LevelFilter::Debug)?;
+            },
+            LoggerInitStrategy::Silent | LoggerInitStrategy::Auto => {
+                let level = if self.logger_init_strategy == LoggerInitStrategy::Silent {
+                    crate::logger::LevelFilter::Off
+                } else {
+                    self.config.log_level
+                };
+                if crate::logger::install_dns_logger(level).is_err() {
+                    // 调用方已自行安装处理器（调用者初始化模式），改为写入该处理器
+                    crate::logger::attach_caller_logger(level)?;
+                }
+            },
+            LoggerInitStrategy::External => {
+                crate::logger::attach_caller_logger(self.config.log_level)?;
+            },
+        }
+        
+        // 只执行本区域的CDN探测
+        let (cdn_probes, skipped_probes): (Vec<CdnProbe>, Vec<CdnProbe>) = self.cdn_probes
+            .into_iter()
+            .partition(|probe| probe.region == self.current_region);
+        if !skipped_probes.is_empty() {
+            dns_info!("跳过 {} 个不属于区域 {} 的CDN探测", skipped_probes.len(), self.current_region);
+        }
+        
+        let decision_engine = match self.query_strategy {
+            QueryStrategy::Smart | QueryStrategy::Fifo | QueryStrategy::RoundRobin | QueryStrategy::Sequential | QueryStrategy::Quorum { .. } => {
+                let mut engine = SmartDecisionEngine::new(self.current_region.clone())
+                    .with_metrics_collection(self.config.enable_stats)
+                    .with_cdn_verification(!cdn_probes.is_empty())
+                    .with_timeouts(self.config.default_timeout, self.config.adaptive_timeout);
+                if let Some(clock) = &self.config.clock {
+                    engine = engine.with_clock(clock.clone());
+                }
+                
+                // 添加所有上游服务器到决策引擎
+                for spec in self.upstream_manager.get_specs() {
+                    engine.add_upstream(spec.clone()).await?;
+                }
+                if let Some(policy) = self.emergency_policy.clone() {
+                    engine = engine.with_emergency_policy(policy);
+                }
+                
+                Some(Arc::new(engine))
+            },
+        };
+        
+        // 导入上次运行保存的性能指标，跳过冷启动阶段
+        if let (Some(path), Some(engine)) = (&self.metrics_snapshot_path, &decision_engine)
+            && path.exists()
+        {
+            match engine.load_metrics_snapshot(path).await {
+                Ok(count) => dns_info!("从 {} 恢复了 {} 个上游的性能指标", path.display(), count),
+                Err(e) => dns_warn!("性能指标快照不可用，按冷启动处理: {}", e),
+            }
+        }
+        
+        let strict_upstream_resolution = self.config.strict_upstream_resolution;
+        let mut resolver = SmartDnsResolver::new(
+            self.config,
+            self.upstream_manager,
+            decision_engine,
+            self.query_strategy,
+            self.enable_edns,
+            self.custom_transports,
+        )?;
+        
+        if let Some(path) = self.metrics_snapshot_path {
+            resolver.enable_metrics_snapshot(path, self.metrics_snapshot_interval);
+        }
+        
+        if let Some(capacity) = self.query_history_capacity {
+            resolver.enable_query_history(capacity)?;
+        }
+        
+        if let Some(max_tenants) = self.tenant_stats_limit {
+            resolver.enable_tenant_stats(max_tenants)?;
+        }
+        resolver.set_query_id_format(self.query_id_format);
+        
+        if let Some(router) = self.domain_router {
+            resolver.set_domain_router(router)?;
+        }
+        resolver.set_middlewares(self.middlewares);
+        if let (true, Some(search)) = (self.apply_search_domains, self.search_domains) {
+            resolver.set_search_domains(search);
+        }
+        resolver.prepare_upstreams(strict_upstream_resolution).await?;
+        resolver.enable_adaptive_timeouts().await;
+        
+        if let Some(options) = self.ddr {
+            let report = resolver.discover_encrypted_upstreams(&options).await;
+            dns_info!(
+                "加密上游发现完成: 注册 {} 个，停用明文上游 {} 个，失败 {} 个",
+                report.registered.len(), report.disabled.len(), report.failures.len()
+            );
+        }
+        
+        // 在上游就绪（包括发现的加密上游）之后开始CDN探测
+        if !cdn_probes.is_empty() {
+            resolver.enable_cdn_probes(cdn_probes, self.cdn_probe_interval);
+        }
+        
+        if let Some(path) = self.warmup_list {
+            let report = match warmup::load_warmup_list(&path) {
+                Ok(entries) => resolver.warm_cache(entries, self.warmup_options).await,
+                Err(e) => Err(e),
+            };
+            match report {
+                Ok(report) if !report.failed.is_empty() => {
+                    dns_warn!("缓存预热有 {} 个条目失败", report.failed.len());
+                }
+                Ok(_) => {}
+                Err(e) => dns_warn!("缓存预热未执行: {}", e),
+            }
+        }
+        
+        Ok(resolver)
+    }
+    
+    /// 执行构建时的全部配置检查，但不构建解析器，用于在没有网络的环境（如CI）中校验生产配置
+    /// 
+    /// 检查上游名称和地址、DoH地址和请求头、根证书加载、转发规则、查询策略及各项选项，构建时因配置而失败的情况
+    /// 在这里以相同的错误失败；不初始化日志、不绑定套接字、不解析上游主机名、不发起加密上游发现，也不启动后台任务。
+    /// 需要联网才能确认或构建时会跳过的内容作为提醒返回
+    pub fn validate_only(&self) -> Result<DryRunReport> {
+        let mut builder = self.clone();
+        let configured: Vec<String> = builder.upstream_manager.get_specs().iter().map(|spec| spec.name.clone()).collect();
+        builder.validate()?;
+        
+        let mut config = builder.config.clone();
+        config.enable_edns = builder.enable_edns;
+        let specs = builder.upstream_manager.get_specs();
+        let routed = builder.domain_router.as_ref().map_or(&[][..], |router| router.upstreams());
+        for spec in specs {
+            check_upstream(spec, &config, &builder.custom_transports)?;
+        }
+        for spec in routed {
+            check_upstream(spec, &config, &[])?;
+        }
+        
+        let mut warnings = Vec::new();
+        for name in configured.iter().filter(|name| specs.iter().all(|spec| spec.name != **name)) {
+            warnings.push(format!("Upstream '{}' points at the same server as another upstream and is merged into it", name));
+        }
+        for spec in specs.iter().chain(routed) {
+            let needs_resolution = match spec.transport_type {
+                UpstreamType::Udp | UpstreamType::Tcp | UpstreamType::DoT => spec.resolved_ip.is_none(),
+                UpstreamType::DoH | UpstreamType::Custom => false,
+            };
+            let (host, _) = parse_simple_server_address(&spec.server, 53);
+            if needs_resolution && host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_err() {
+                warnings.push(format!("Address of upstream '{}' ({}) is resolved when the resolver is built and was not checked", spec.name, host));
+            }
+        }
+        let skipped_probes = builder.cdn_probes.iter().filter(|probe| probe.region != builder.current_region).count();
+        if skipped_probes > 0 {
+            warnings.push(format!("{} CDN probe(s) belong to other regions than '{}' and are skipped", skipped_probes, builder.current_region));
+        }
+        if builder.ddr.is_some() {
+            warnings.push("Encrypted upstream discovery (DDR) queries the network and was not performed".to_string());
+        }
+        if let Some(Err(e)) = builder.warmup_list.as_ref().map(|path| warmup::load_warmup_list(path)) {
+            warnings.push(format!("Cache warm-up is skipped at build time: {}", e));
+        }
+        
+        let describe = |spec: &UpstreamSpec, route_only: bool| {
+            EffectiveUpstream::from_spec(spec, &config, spec.features.effective(builder.enable_edns), true, route_only)
+        };
+        let mut upstreams: Vec<EffectiveUpstream> = specs.iter().map(|spec| describe(spec, false)).collect();
+        upstreams.extend(routed.iter().map(|spec| describe(spec, true)));
+        let domain_rules = builder.domain_router.as_ref().map_or(0, |router| router.rule_count());
+        Ok(DryRunReport {
+            effective_config: EffectiveConfig::new(ResolverSettings::from_config(&config, domain_rules), upstreams),
+            warnings,
+        })
+    }
+    
+    /// 重新加载配置前的检查：与构建时相同的配置校验，各上游按 `config`（合并了新设置的解析器配置）检查能否创建传输
+    /// 
+    /// 返回合并重复项后的上游和应急模式判定参数，见 `SmartDnsResolver::reload`
+    pub(super) fn validate_for_reload(mut self, config: &CoreResolverConfig) -> Result<(Vec<UpstreamSpec>, Option<EmergencyPolicy>)> {
+        self.validate()?;
+        let specs = self.upstream_manager.get_specs().to_vec();
+        for spec in &specs {
+            check_upstream(spec, config, &[])?;
+        }
+        Ok((specs, self.emergency_policy))
+    }
+    
+    /// 获取当前配置的上游服务器数量
+    pub fn upstream_count(&self) -> usize {
+        self.upstream_manager.get_specs().len()
+    }
+    
+    /// 获取当前查询策略
+    pub fn current_strategy(&self) -> QueryStrategy {
+        self.query_strategy
+    }
+    
+    /// 获取应急模式判定参数，未启用时为 `None`
+    pub fn emergency_policy(&self) -> Option<&EmergencyPolicy> {
+        self.emergency_policy.as_ref()
+    }
+    
+    /// 获取当前日志初始化策略
+    pub fn logger_strategy(&self) -> &LoggerInitStrategy {
+        &self.logger_init_strategy
+    }
+    
+    /// 检查是否启用了EDNS
+    pub fn is_edns_enabled(&self) -> bool {
+        self.enable_edns
+    }
+    
+    /// 获取当前区域
+    pub fn current_region(&self) -> &str {
+        &self.current_region
+    }
+    
+    /// 获取上游管理器的引用
+    pub fn upstream_manager(&self) -> &UpstreamManager {
+        &self.upstream_manager
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::strict::UpstreamSpec as StrictUpstreamSpec;
+    use crate::transport::PoolExhaustion;
+
+    fn strict_config(upstreams: Vec<StrictUpstreamSpec>) -> StrictDnsConfig {
+        let mut builder = StrictDnsConfig::builder()
+            .strategy(QueryStrategy::RoundRobin)
+            .timeout(Duration::from_secs(3))
+            .retry_count(1)
+            .enable_cache(true)
+            .cache_ttl(Duration::from_secs(600))
+            .enable_upstream_monitoring(false)
+            .upstream_monitoring_interval(Duration::from_secs(30))
+            .port(53)
+            .concurrent_queries(4)
+            .buffer_size(4096)
+            .enable_stats(true)
+            .emergency_threshold(0.3);
+        for upstream in upstreams {
+            builder = builder.add_upstream(upstream);
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_from_strict_config_maps_upstreams_and_strategy() {
+        let config = strict_config(vec![
+            StrictUpstreamSpec::new("223.5.5.5:53".to_string(), "udp".to_string(), 3),
+            StrictUpstreamSpec::new("https://dns.alidns.com/dns-query".to_string(), "doh".to_string(), 1),
+            StrictUpstreamSpec::disabled("8.8.8.8:53".to_string(), "udp".to_string(), 1),
+        ]);
+
+        let builder = DnsResolverBuilder::from_strict_config(&config, true, "CN").unwrap();
+        assert_eq!(builder.current_strategy(), QueryStrategy::RoundRobin);
+        assert_eq!(builder.upstream_count(), 2);
+        assert_eq!(builder.upstream_manager().get_specs()[0].weight, 3);
+    }
+
+    #[test]
+    fn test_preset_combines_with_manual_upstreams() {
+        let builder = DnsResolverBuilder::new(QueryStrategy::Smart, true, "CN".to_string())
+            .add_udp_upstream("内网DNS", "10.0.0.53")
+            .with_preset(Preset::ChinaMainland)
+            .unwrap();
+
+        let names: Vec<_> = builder.upstream_manager().get_specs().iter().map(|s| s.name.clone()).collect();
+        assert_eq!(names.len(), 1 + Preset::ChinaMainland.upstreams().len());
+        assert_eq!(names[0], "内网DNS");
+        assert!(names.contains(&"阿里DoH".to_string()));
+        assert_eq!(builder.current_strategy(), QueryStrategy::Smart);
+    }
+
+    #[test]
+    fn test_from_strict_config_carries_logger_strategy() {
+        let upstream = || StrictUpstreamSpec::new("223.5.5.5:53".to_string(), "udp".to_string(), 1);
+        let builder = DnsResolverBuilder::from_strict_config(&strict_config(vec![upstream()]), true, "CN").unwrap();
+        assert_eq!(builder.logger_strategy(), &LoggerInitStrategy::Auto);
+
+        let mut config = strict_config(vec![upstream()]);
+        config.logger_init_strategy = Some(LoggerInitStrategy::None);
+        let builder = DnsResolverBuilder::from_strict_config(&config, true, "CN").unwrap();
+        assert_eq!(builder.logger_strategy(), &LoggerInitStrategy::None);
+    }
+
+    #[tokio::test]
+    async fn test_build_rejects_conflicting_upstreams() {
+        let builder = || DnsResolverBuilder::new(QueryStrategy::RoundRobin, false, "CN".to_string()).disable_logger_init();
+        let error = |builder: DnsResolverBuilder| async move {
+            match builder.build().await {
+                Err(DnsError::InvalidConfig(msg)) => msg,
+                other => panic!("expected InvalidConfig, got {:?}", other.map(|_| ())),
+            }
+        };
+
+        // 名称重复：错误信息引用两处定义
+        let msg = error(builder().add_udp_upstream("ali", "223.5.5.5").add_tcp_upstream("ali", "223.6.6.6")).await;
+        assert!(msg.contains("'ali'") && msg.contains("223.5.5.5") && msg.contains("223.6.6.6"), "{}", msg);
+
+        // 同一服务器以不同名称注册（省略默认端口也算同一服务器）
+        let msg = error(builder().add_udp_upstream("ali", "223.5.5.5").add_udp_upstream("alidns", "223.5.5.5:53")).await;
+        assert!(msg.contains("'ali'") && msg.contains("'alidns'"), "{}", msg);
+
+        // 空名称和非法字符
+        error(builder().add_udp_upstream("", "223.5.5.5")).await;
+        error(builder().add_udp_upstream("ali/dns", "223.5.5.5")).await;
+        error(builder().add_udp_upstream("ali:53", "223.5.5.5")).await;
+        error(builder().add_udp_upstream("ali\ndns", "223.5.5.5")).await;
+    }
+
+    #[test]
+    fn test_dedup_keeps_higher_weight_definition() {
+        let builder = DnsResolverBuilder::new(QueryStrategy::RoundRobin, false, "CN".to_string())
+            .with_dedup_upstreams(true)
+            .add_upstreams(vec![
+                UpstreamSpec::udp("ali".to_string(), "223.5.5.5".to_string()).with_weight(1),
+                UpstreamSpec::tcp("ali-tcp".to_string(), "223.5.5.5".to_string()),
+                UpstreamSpec::udp("tencent".to_string(), "119.29.29.29".to_string()),
+                UpstreamSpec::udp("ali-heavy".to_string(), "223.5.5.5:53".to_string()).with_weight(5),
+                UpstreamSpec::udp("tencent-copy".to_string(), "119.29.29.29".to_string()),
+            ])
+            .unwrap();
+
+        let mut manager = builder.upstream_manager().clone();
+        manager.validate_upstreams(true).unwrap();
+        let kept: Vec<_> = manager.get_specs().iter().map(|s| (s.name.as_str(), s.weight)).collect();
+        // 权重较高的定义取代先注册的一项，权重相同保留先注册的
+        assert_eq!(kept, vec![("ali-heavy", 5), ("ali-tcp", 1), ("tencent", 1)]);
+
+        let mut manager = builder.upstream_manager().clone();
+        assert!(manager.validate_upstreams(false).is_err());
+        assert_eq!(manager.get_specs().len(), 5);
+    }
+
+    #[test]
+    fn test_from_strict_config_rejects_unknown_protocol() {
+        let config = strict_config(vec![
+            StrictUpstreamSpec::new("223.5.5.5:53".to_string(), "quic".to_string(), 1),
+        ]);
+
+        assert!(DnsResolverBuilder::from_strict_config(&config, true, "CN").is_err());
+    }
+
+    #[test]
+    fn test_doh_headers_validated_at_config_time() {
+        let builder = DnsResolverBuilder::new(QueryStrategy::Smart, true, "CN".to_string());
+        let result = builder.clone().add_doh_upstream_with_headers(
+            "企业DoH",
+            "https://doh.example.com/dns-query",
+            vec![("Bad Header".to_string(), "x".to_string())],
+        );
+        assert!(matches!(result, Err(DnsError::InvalidConfig(_))));
+
+        let builder = builder
+            .add_doh_upstream_with_headers(
+                "企业DoH",
+                "https://doh.example.com/dns-query",
+                vec![("Authorization".to_string(), "Bearer token".to_string())],
+            )
+            .unwrap();
+        assert_eq!(builder.upstream_count(), 1);
+    }
+
+    #[test]
+    fn test_doh_http3_requires_feature() {
+        let builder = DnsResolverBuilder::new(QueryStrategy::Smart, true, "CN".to_string());
+        let result = builder.clone().add_doh_upstream_with_http_version(
+            "cloudflare-h3",
+            "https://cloudflare-dns.com/dns-query",
+            HttpVersionPref::H3WithFallback,
+        );
+        assert_eq!(result.is_ok(), cfg!(feature = "doh3"));
+
+        let builder = builder
+            .add_doh_upstream_with_http_version("cloudflare-h2", "https://cloudflare-dns.com/dns-query", HttpVersionPref::H2Only)
+            .unwrap();
+        assert_eq!(builder.upstream_count(), 1);
+    }
+
+    #[test]
+    fn test_compiled_out_protocols_are_config_errors() {
+        let builder = DnsResolverBuilder::new(QueryStrategy::Smart, true, "CN".to_string());
+        for (spec, feature) in [
+            (UpstreamSpec::tcp("tcp".to_string(), "223.5.5.5:53".to_string()), "tcp"),
+            (UpstreamSpec::dot("dot".to_string(), "dns.alidns.com:853".to_string()), "dot"),
+            (UpstreamSpec::doh("doh".to_string(), "https://dns.alidns.com/dns-query".to_string()), "doh"),
+        ] {
+            let enabled = match feature {
+                "tcp" => cfg!(feature = "tcp"),
+                "dot" => cfg!(feature = "dot"),
+                _ => cfg!(feature = "doh"),
+            };
+            match builder.clone().add_upstream(spec) {
+                Ok(builder) => assert!(enabled && builder.upstream_count() == 1),
+                Err(e) => {
+                    assert!(!enabled, "{}", e);
+                    assert!(e.to_string().contains(&format!("requires the {} feature", feature)), "{}", e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_typed_upstream_validates_before_build() {
+        assert!(Upstream::doh("坏DoH", "https//dns.example/dns-query").is_err());
+        assert!(Upstream::dot("坏DoT", "dns example", 853).is_err());
+        
+        // 字符串路径经由同样的校验，写错的上游不会加入构建器
+        let builder = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .add_doh_upstream("坏DoH", "https//dns.example/dns-query")
+            .add_dot_upstream("坏DoT", "dns example");
+        assert_eq!(builder.upstream_count(), 0);
+        
+        let builder = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .add(Upstream::udp("plain", "192.0.2.53:53".parse().unwrap()).with_timeout(Duration::ZERO));
+        assert!(builder.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_typed_and_string_upstreams_build_identical_resolvers() {
+        let typed = DnsResolverBuilder::new(QueryStrategy::Smart, true, "global".to_string())
+            .disable_logger_init()
+            .add(Upstream::udp("udp", "192.0.2.53:53".parse().unwrap())).unwrap()
+            .add(Upstream::tcp("tcp", "192.0.2.54:5353".parse().unwrap())).unwrap()
+            .add(Upstream::dot("dot", "dns.example", 853).unwrap()).unwrap()
+            .add(Upstream::doh("doh", "https://dns.example/dns-query").unwrap()).unwrap()
+            .build()
+            .await
+            .unwrap();
+        let strings = DnsResolverBuilder::new(QueryStrategy::Smart, true, "global".to_string())
+            .disable_logger_init()
+            .add_udp_upstream("udp", "192.0.2.53")
+            .add_tcp_upstream("tcp", "192.0.2.54:5353")
+            .add_dot_upstream("dot", "dns.example")
+            .add_doh_upstream("doh", "https://dns.example/dns-query")
+            .build()
+            .await
+            .unwrap();
+        
+        assert_eq!(typed.effective_config().diff(&strings.effective_config()), Vec::<String>::new());
+        let endpoints = |resolver: &SmartDnsResolver| resolver.upstream_manager().get_specs().iter()
+            .map(|spec| format!("{:?}", spec))
+            .collect::<Vec<_>>();
+        assert_eq!(endpoints(&typed), endpoints(&strings));
+    }
+
+    #[tokio::test]
+    async fn test_typed_upstream_options_reach_effective_config() {
+        use crate::types::RecordType;
+        
+        let resolver = DnsResolverBuilder::new(QueryStrategy::Smart, true, "global".to_string())
+            .disable_logger_init()
+            .add(Upstream::dot("dot", "192.0.2.85", 853).unwrap()
+                .with_sni("dns.example").unwrap()
+                .with_timeout(Duration::from_millis(750))
+                .with_record_types([RecordType::A, RecordType::AAAA])).unwrap()
+            .build()
+            .await
+            .unwrap();
+        
+        let upstream = &resolver.effective_config().upstreams[0];
+        assert_eq!(upstream.timeout_ms, 750);
+        assert_eq!(upstream.record_types, Some(vec!["A".to_string(), "AAAA".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_timeout_requires_stats() {
+        let result = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .with_stats(false)
+            .with_adaptive_timeout(AdaptiveTimeoutConfig::default())
+            .add_udp_upstream("test", "127.0.0.1:53")
+            .build()
+            .await;
+        assert!(matches!(result, Err(DnsError::InvalidConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn test_udp_socket_pool_is_validated_and_applied_to_udp_upstreams() {
+        let wait = PoolExhaustion::Wait { timeout: Duration::from_secs(1) };
+        let builder = || DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string()).disable_logger_init();
+        assert!(matches!(builder().with_udp_socket_pool(UdpPoolConfig::new(0).with_exhaustion(wait)), Err(DnsError::InvalidConfig(_))));
+        
+        let resolver = builder()
+            .add_udp_upstream("local", "127.0.0.1:53")
+            .with_udp_socket_pool(UdpPoolConfig::new(3).with_exhaustion(wait))
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let stats = resolver.udp_pool_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats["local"].capacity, stats["local"].idle, stats["local"].binds), (3, 3, 3));
+    }
+}
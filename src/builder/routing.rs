@@ -0,0 +1,580 @@
+//! 按域名转发
+//!
+//! 为指定域名（及其子域名）单独指定上游，或在本地直接应答，其余域名按查询策略查询。
+//! 规则按最长后缀匹配：`corp.example.com` 的规则同样适用于 `a.corp.example.com`，
+//! 同时存在 `example.com` 的规则时以更长的为准。
+//!
+//! 可以从dnsmasq配置文件加载，支持以下指令：
+//! - `server=/域名/[域名/...]IP[#端口]`：这些域名转发到指定服务器，同一域名写多行时依次尝试
+//! - `server=/域名/#`：这些域名按查询策略查询（覆盖更短后缀的规则）
+//! - `server=/域名/` 和 `local=/域名/[域名/...]`：这些域名只在本地应答（NXDOMAIN）
+//! - `server=IP[#端口]`：默认上游，与构建器添加的上游一起参与查询策略
+//!
+//! 其他指令记录警告后忽略。
+//!
+//! 转发到内部权威服务器的域名可以开启QNAME最小化（RFC 9156），见
+//! [`DomainRouter::with_qname_minimisation`]。
+
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use crate::error::{DnsError, Result};
+use crate::transport::host_port;
+use crate::upstream_handler::UpstreamSpec;
+use crate::utils::normalize_name;
+use crate::{dns_debug, dns_warn};
+
+/// 从dnsmasq配置创建的上游的名称前缀，之后是按出现顺序的编号
+pub const DNSMASQ_UPSTREAM_PREFIX: &str = "dnsmasq-";
+
+/// 一个域名的转发方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomainRoute {
+    /// 依次向这些上游（按名称）查询，某个上游出错或返回SERVFAIL/REFUSED时换下一个
+    Upstreams(Vec<String>),
+    /// 不转发，在本地应答NXDOMAIN
+    Local,
+    /// 按查询策略查询
+    Default,
+}
+
+/// 按域名转发的规则及其使用的上游
+#[derive(Debug, Clone, Default)]
+pub struct DomainRouter {
+    /// 只用于转发规则的上游，不参与查询策略
+    upstreams: Vec<UpstreamSpec>,
+    /// 参与查询策略的默认上游
+    default_upstreams: Vec<UpstreamSpec>,
+    /// 域名（小写、不含末尾的点）到转发方式
+    rules: HashMap<String, DomainRoute>,
+    /// 开启QNAME最小化的规则域名
+    minimised: HashSet<String>,
+}
+
+impl DomainRouter {
+    /// 创建空的规则集
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加只用于转发规则的上游
+    pub fn add_upstream(mut self, spec: UpstreamSpec) -> Self {
+        self.upstreams.push(spec);
+        self
+    }
+
+    /// 添加参与查询策略的默认上游
+    pub fn add_default_upstream(mut self, spec: UpstreamSpec) -> Self {
+        self.default_upstreams.push(spec);
+        self
+    }
+
+    /// 为域名设置转发方式，已有的规则被替换
+    pub fn add_rule(mut self, domain: &str, route: DomainRoute) -> Self {
+        self.rules.insert(normalize_domain(domain), route);
+        self
+    }
+
+    /// 对转发到 `domain` 的查询开启QNAME最小化（RFC 9156）
+    ///
+    /// `domain` 必须有指定上游的规则，并被视为区域顶点：查询更深的名称时先从顶点下一级起
+    /// 逐级查询NS，最后才发送完整名称。只适用于内部权威服务器，公共递归上游不受影响
+    pub fn with_qname_minimisation(mut self, domain: &str) -> Self {
+        self.minimised.insert(normalize_domain(domain));
+        self
+    }
+
+    /// 查询名称需要QNAME最小化时返回所匹配规则的域名（区域顶点）
+    pub fn qname_minimisation_apex(&self, name: &str) -> Option<&str> {
+        match self.matching_rule(name)? {
+            (apex, DomainRoute::Upstreams(_)) if self.minimised.contains(apex) => Some(apex),
+            _ => None,
+        }
+    }
+
+    /// 只用于转发规则的上游
+    pub fn upstreams(&self) -> &[UpstreamSpec] {
+        &self.upstreams
+    }
+
+    /// 参与查询策略的默认上游
+    pub fn default_upstreams(&self) -> &[UpstreamSpec] {
+        &self.default_upstreams
+    }
+
+    /// 规则数量
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// 查询名称适用的转发方式（最长后缀匹配），没有规则时为 `None`
+    pub fn route_for(&self, name: &str) -> Option<&DomainRoute> {
+        self.matching_rule(name).map(|(_, route)| route)
+    }
+
+    /// 最长后缀匹配的规则域名及其转发方式
+    fn matching_rule(&self, name: &str) -> Option<(&str, &DomainRoute)> {
+        let name = normalize_domain(name);
+        let mut suffix = name.as_str();
+        loop {
+            if let Some((domain, route)) = self.rules.get_key_value(suffix) {
+                return Some((domain.as_str(), route));
+            }
+            suffix = suffix.split_once('.')?.1;
+        }
+    }
+
+    /// 检查规则引用的上游都存在（转发上游或默认上游）
+    pub fn validate(&self) -> Result<()> {
+        for (domain, route) in &self.rules {
+            if let DomainRoute::Upstreams(names) = route {
+                if names.is_empty() {
+                    return Err(DnsError::InvalidConfig(format!("Route for '{}' has no upstreams", domain)));
+                }
+                if let Some(missing) = names.iter().find(|name| !self.is_known_upstream(name)) {
+                    return Err(DnsError::InvalidConfig(format!("Route for '{}' refers to unknown upstream '{}'", domain, missing)));
+                }
+            }
+        }
+        for domain in &self.minimised {
+            if !matches!(self.rules.get(domain), Some(DomainRoute::Upstreams(_))) {
+                return Err(DnsError::InvalidConfig(format!(
+                    "QNAME minimisation for '{}' requires a route to specific upstreams", domain
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_known_upstream(&self, name: &str) -> bool {
+        self.upstreams.iter().chain(&self.default_upstreams).any(|spec| spec.name == name)
+    }
+
+    /// 从dnsmasq配置文件加载规则
+    ///
+    /// 语法错误（地址或端口不合法等）返回 [`DnsError::Parse`]，错误信息含文件名和行号；
+    /// 不支持的指令记录警告后忽略
+    pub fn from_dnsmasq_conf(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| DnsError::Io(format!("Failed to read dnsmasq config {}: {}", path.display(), e)))?;
+        Self::parse_dnsmasq_conf(&content, &path.display().to_string())
+    }
+
+    /// 解析dnsmasq配置内容，`source` 用于错误信息和警告
+    pub fn parse_dnsmasq_conf(content: &str, source: &str) -> Result<Self> {
+        let mut parser = DnsmasqParser { source, router: Self::new() };
+        for (index, line) in content.lines().enumerate() {
+            parser.parse_line(index + 1, line)?;
+        }
+        dns_debug!(
+            "从 {} 加载了 {} 条转发规则，{} 个转发上游，{} 个默认上游",
+            source, parser.router.rules.len(), parser.router.upstreams.len(), parser.router.default_upstreams.len()
+        );
+        Ok(parser.router)
+    }
+}
+
+/// 统一域名的写法：小写、去掉首尾的点
+fn normalize_domain(domain: &str) -> String {
+    normalize_name(domain.trim().trim_start_matches('.'))
+}
+
+struct DnsmasqParser<'a> {
+    source: &'a str,
+    router: DomainRouter,
+}
+
+impl DnsmasqParser<'_> {
+    fn error(&self, line: usize, message: String) -> DnsError {
+        DnsError::Parse(format!("{}:{}: {}", self.source, line, message))
+    }
+
+    fn parse_line(&mut self, line: usize, text: &str) -> Result<()> {
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            return Ok(());
+        }
+        let (key, value) = match text.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (text, ""),
+        };
+        match key {
+            "server" => self.parse_server(line, value),
+            "local" => self.parse_local(line, value),
+            _ => {
+                dns_warn!("{}:{}: 不支持的dnsmasq指令 '{}'，已忽略", self.source, line, key);
+                Ok(())
+            }
+        }
+    }
+
+    /// `server=/域名/.../地址` 或 `server=地址`
+    fn parse_server(&mut self, line: usize, value: &str) -> Result<()> {
+        let Some(rest) = value.strip_prefix('/') else {
+            let spec = self.upstream(line, value)?;
+            // 同时用于转发规则的地址也作为默认上游参与查询策略
+            self.router.upstreams.retain(|existing| existing.name != spec.name);
+            if !self.router.default_upstreams.iter().any(|existing| existing.name == spec.name) {
+                self.router.default_upstreams.push(spec);
+            }
+            return Ok(());
+        };
+        let Some((domains, target)) = rest.rsplit_once('/') else {
+            return Err(self.error(line, format!("missing '/' after domains in server={}", value)));
+        };
+        let domains = self.domains(line, domains)?;
+        let route = match target.trim() {
+            "" => DomainRoute::Local,
+            "#" => DomainRoute::Default,
+            address => {
+                let spec = self.upstream(line, address)?;
+                let name = spec.name.clone();
+                if !self.router.is_known_upstream(&name) {
+                    self.router.upstreams.push(spec);
+                }
+                DomainRoute::Upstreams(vec![name])
+            }
+        };
+        for domain in domains {
+            // 同一域名写多行 server= 时依次尝试
+            match (self.router.rules.get_mut(&domain), &route) {
+                (Some(DomainRoute::Upstreams(names)), DomainRoute::Upstreams(added)) => {
+                    for name in added {
+                        if !names.contains(name) {
+                            names.push(name.clone());
+                        }
+                    }
+                }
+                _ => {
+                    self.router.rules.insert(domain, route.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `local=/域名/.../`
+    fn parse_local(&mut self, line: usize, value: &str) -> Result<()> {
+        let domains = value.strip_prefix('/')
+            .and_then(|rest| rest.strip_suffix('/'))
+            .ok_or_else(|| self.error(line, format!("expected local=/domain/, got local={}", value)))?;
+        for domain in self.domains(line, domains)? {
+            self.router.rules.insert(domain, DomainRoute::Local);
+        }
+        Ok(())
+    }
+
+    /// `/` 分隔的域名列表
+    fn domains(&self, line: usize, domains: &str) -> Result<Vec<String>> {
+        let domains: Vec<String> = domains.split('/').map(normalize_domain).collect();
+        if domains.iter().any(String::is_empty) {
+            // dnsmasq中空域名表示不含点的名称，这里没有对应的规则
+            return Err(self.error(line, "empty domain (unqualified names are not supported)".to_string()));
+        }
+        Ok(domains)
+    }
+
+    /// 解析 `IP[#端口][@来源]`，地址已出现过时返回已有的上游（调用方决定放入哪个列表）
+    fn upstream(&self, line: usize, address: &str) -> Result<UpstreamSpec> {
+        let address = match address.split_once('@') {
+            Some((address, source)) => {
+                dns_warn!("{}:{}: 不支持指定发送来源 '@{}'，已忽略", self.source, line, source);
+                address
+            }
+            None => address,
+        };
+        let (ip, port) = match address.split_once('#') {
+            Some((ip, port)) => {
+                let port = port.parse::<u16>()
+                    .map_err(|_| self.error(line, format!("invalid port '{}'", port)))?;
+                (ip, port)
+            }
+            None => (address, 53),
+        };
+        let ip = ip.parse::<IpAddr>()
+            .map_err(|_| self.error(line, format!("invalid server address '{}'", ip)))?;
+        let server = host_port(&ip.to_string(), port);
+
+        let existing = self.router.upstreams.iter()
+            .chain(&self.router.default_upstreams)
+            .find(|spec| spec.server.parse::<SocketAddr>().ok() == Some(SocketAddr::new(ip, port)));
+        if let Some(spec) = existing {
+            return Ok(spec.clone());
+        }
+        let count = self.router.upstreams.len() + self.router.default_upstreams.len();
+        Ok(UpstreamSpec::udp(format!("{}{}", DNSMASQ_UPSTREAM_PREFIX, count + 1), server))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{DnsQueryRequest, DnsRecordType, DnsResolverBuilder, QueryStrategy};
+    use crate::dns_response::DnsResponseWrapper;
+    use crate::transport::mock::MockTransport;
+    use crate::transport::UdpTransport;
+    use crate::types::{QClass, RecordType};
+    use crate::DnsResponseBuilder;
+    use std::net::Ipv4Addr;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/dnsmasq.conf");
+
+    fn upstreams(names: &[&str]) -> Option<DomainRoute> {
+        Some(DomainRoute::Upstreams(names.iter().map(|name| name.to_string()).collect()))
+    }
+
+    #[test]
+    fn test_dnsmasq_fixture() {
+        let router = DomainRouter::from_dnsmasq_conf(FIXTURE).unwrap();
+        let servers = |specs: &[UpstreamSpec]| specs.iter()
+            .map(|spec| (spec.name.clone(), spec.server.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(servers(router.default_upstreams()), vec![
+            ("dnsmasq-1".to_string(), "223.5.5.5:53".to_string()),
+            ("dnsmasq-2".to_string(), "[2400:3200::1]:53".to_string()),
+        ]);
+        assert_eq!(servers(router.upstreams()), vec![
+            ("dnsmasq-3".to_string(), "10.1.1.53:53".to_string()),
+            ("dnsmasq-4".to_string(), "10.1.2.53:5353".to_string()),
+            ("dnsmasq-5".to_string(), "[2001:db8::53]:5353".to_string()),
+        ]);
+
+        assert_eq!(router.route_for("host.corp.example.com").cloned(), upstreams(&["dnsmasq-3", "dnsmasq-4"]));
+        assert_eq!(router.route_for("DC01.AD.example.com.").cloned(), upstreams(&["dnsmasq-3"]));
+        assert_eq!(router.route_for("ci.lab.example.com").cloned(), upstreams(&["dnsmasq-5"]));
+        assert_eq!(router.route_for("7.1.1.10.in-addr.arpa").cloned(), upstreams(&["dnsmasq-3"]));
+        // 更长的后缀优先
+        assert_eq!(router.route_for("www.corp.example.com"), Some(&DomainRoute::Default));
+        assert_eq!(router.route_for("printer.localdomain"), Some(&DomainRoute::Local));
+        assert_eq!(router.route_for("internal"), Some(&DomainRoute::Local));
+        assert_eq!(router.route_for("ads.blocked.example.com"), Some(&DomainRoute::Local));
+        assert_eq!(router.route_for("example.com"), None);
+        assert_eq!(router.route_for("notcorp.example.com"), None);
+    }
+
+    #[test]
+    fn test_dnsmasq_parse_errors_report_line() {
+        let content = "server=/corp.example.com/10.1.1.53\n\nserver=/lab.example.com/10.1.1.300\n";
+        let error = DomainRouter::parse_dnsmasq_conf(content, "corp.conf").unwrap_err().to_string();
+        assert!(error.contains("corp.conf:3"), "{}", error);
+
+        assert!(DomainRouter::parse_dnsmasq_conf("server=10.1.1.53#dns", "x").is_err());
+        assert!(DomainRouter::parse_dnsmasq_conf("server=//10.1.1.53", "x").is_err());
+        assert!(DomainRouter::parse_dnsmasq_conf("local=corp.example.com", "x").is_err());
+        // 不支持的指令只记录警告
+        assert!(DomainRouter::parse_dnsmasq_conf("domain-needed\nbogus-priv\naddress=/x/1.2.3.4", "x").is_ok());
+    }
+
+    /// 本地UDP上游，对任何A查询应答 `address`
+    async fn udp_upstream(address: Ipv4Addr) -> u16 {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (len, peer) = upstream.recv_from(&mut buf).await.unwrap();
+                let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+                let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[address], 300);
+                let bytes = UdpTransport::serialize_response(&response).unwrap();
+                upstream.send_to(&bytes, peer).await.unwrap();
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_routed_domains_bypass_strategy_upstreams() {
+        let port = udp_upstream(Ipv4Addr::new(10, 1, 1, 7)).await;
+        let conf = format!("server=/corp.example.com/127.0.0.1#{}\nlocal=/blocked.example.com/\n", port);
+        let router = DomainRouter::parse_dnsmasq_conf(&conf, "inline").unwrap();
+
+        let public = MockTransport::new()
+            .with_a("host.corp.example.com", &[Ipv4Addr::new(203, 0, 113, 1)], 300)
+            .with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+        let handle = public.clone();
+        let resolver = DnsResolverBuilder::new(QueryStrategy::Smart, false, "global".to_string())
+            .disable_logger_init()
+            .with_timeout(Duration::from_secs(2))
+            .add_mock_upstream("public", public)
+            .unwrap()
+            .with_domain_router(router)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let corp = resolver.query(DnsQueryRequest::new("host.corp.example.com", DnsRecordType::A)).await.unwrap();
+        assert!(corp.success);
+        assert_eq!(corp.server_used.as_deref(), Some("dnsmasq-1"));
+        assert_eq!(corp.ip_addresses(), vec![IpAddr::V4(Ipv4Addr::new(10, 1, 1, 7))]);
+
+        let blocked = resolver.query(DnsQueryRequest::new("ads.blocked.example.com", DnsRecordType::A)).await.unwrap();
+        assert_eq!(blocked.rcode, Some(3));
+        assert_eq!(blocked.server_used.as_deref(), Some(crate::builder::resolver::LOCAL_SOURCE));
+        assert_eq!(handle.call_count(), 0);
+
+        // 其余域名照常按查询策略，转发上游不参与
+        let other = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+        assert_eq!(other.server_used.as_deref(), Some("public"));
+        assert_eq!(handle.call_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rules_match_names_case_insensitively() {
+        let router = DomainRouter::new().add_rule("Blocked.Example.COM.", DomainRoute::Local);
+        for spelling in ["ADS.BLOCKED.EXAMPLE.COM.", "ads.blocked.example.com", "aDs.BlOcKeD.eXaMpLe.CoM"] {
+            assert_eq!(router.route_for(spelling), Some(&DomainRoute::Local), "{}", spelling);
+        }
+
+        let public = MockTransport::new();
+        let handle = public.clone();
+        let resolver = DnsResolverBuilder::new(QueryStrategy::Smart, false, "global".to_string())
+            .disable_logger_init()
+            .add_mock_upstream("public", public)
+            .unwrap()
+            .with_domain_router(router)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        for spelling in ["ADS.BLOCKED.EXAMPLE.COM.", "ads.blocked.example.com", "aDs.BlOcKeD.eXaMpLe.CoM"] {
+            let response = resolver.query(DnsQueryRequest::new(spelling, DnsRecordType::A)).await.unwrap();
+            assert_eq!(response.rcode, Some(3));
+            assert_eq!(response.server_used.as_deref(), Some(crate::builder::resolver::LOCAL_SOURCE));
+            assert_eq!(response.domain, spelling);
+        }
+        assert_eq!(handle.call_count(), 0);
+    }
+
+    type SeenQueries = Arc<Mutex<Vec<(String, RecordType)>>>;
+
+    /// 本地UDP权威上游，记录收到的每个问题：`nxdomain` 中的名称的NS查询应答NXDOMAIN，
+    /// 其余NS查询应答一条NS记录，其他查询应答A记录 `address`
+    async fn scripted_authority(address: Ipv4Addr, nxdomain: &'static [&'static str]) -> (u16, SeenQueries) {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        let seen = SeenQueries::default();
+        let log = seen.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (len, peer) = upstream.recv_from(&mut buf).await.unwrap();
+                let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+                let (name, qtype) = (request.query.name.clone(), request.query.qtype);
+                log.lock().unwrap().push((name.clone(), qtype));
+                let response = match qtype {
+                    RecordType::NS if nxdomain.contains(&name.as_str()) => {
+                        DnsResponseWrapper::create_nxdomain_response(request.id, &name, qtype)
+                    }
+                    RecordType::NS => DnsResponseBuilder::new()
+                        .with_id(request.id)
+                        .with_authoritative(true)
+                        .add_query(name.clone(), qtype, QClass::IN)
+                        .add_ns_answer(name.clone(), 300, format!("ns1.{}", name))
+                        .build(),
+                    _ => DnsResponseWrapper::create_a_response(request.id, &name, &[address], 300),
+                };
+                let bytes = UdpTransport::serialize_response(&response).unwrap();
+                upstream.send_to(&bytes, peer).await.unwrap();
+            }
+        });
+        (port, seen)
+    }
+
+    async fn minimising_resolver(port: u16, minimise: bool) -> crate::builder::SmartDnsResolver {
+        let mut router = DomainRouter::new()
+            .add_upstream(UpstreamSpec::udp("auth".to_string(), format!("127.0.0.1:{}", port)))
+            .add_rule("corp.example.com", DomainRoute::Upstreams(vec!["auth".to_string()]));
+        if minimise {
+            router = router.with_qname_minimisation("corp.example.com");
+        }
+        DnsResolverBuilder::new(QueryStrategy::Smart, false, "global".to_string())
+            .disable_logger_init()
+            .with_timeout(Duration::from_secs(2))
+            .with_cache(true)
+            .add_mock_upstream("public", MockTransport::new())
+            .unwrap()
+            .with_domain_router(router)
+            .unwrap()
+            .build()
+            .await
+            .unwrap()
+    }
+
+    fn take(seen: &SeenQueries) -> Vec<(String, RecordType)> {
+        std::mem::take(&mut *seen.lock().unwrap())
+    }
+
+    fn ns(name: &str) -> (String, RecordType) {
+        (name.to_string(), RecordType::NS)
+    }
+
+    fn a(name: &str) -> (String, RecordType) {
+        (name.to_string(), RecordType::A)
+    }
+
+    #[tokio::test]
+    async fn test_qname_minimisation_walks_down_from_apex() {
+        let (port, seen) = scripted_authority(Ipv4Addr::new(10, 1, 1, 9), &[]).await;
+        let resolver = minimising_resolver(port, true).await;
+
+        let response = resolver.query(DnsQueryRequest::new("a.b.c.corp.example.com", DnsRecordType::A)).await.unwrap();
+        assert_eq!(response.ip_addresses(), vec![IpAddr::V4(Ipv4Addr::new(10, 1, 1, 9))]);
+        assert_eq!(take(&seen), vec![ns("c.corp.example.com"), ns("b.c.corp.example.com"), a("a.b.c.corp.example.com")]);
+
+        // 中间层级的NS应答已在缓存中，只发送完整查询
+        resolver.query(DnsQueryRequest::new("x.b.c.corp.example.com", DnsRecordType::A)).await.unwrap();
+        assert_eq!(take(&seen), vec![a("x.b.c.corp.example.com")]);
+
+        // 顶点的下一级没有中间查询
+        resolver.query(DnsQueryRequest::new("host.corp.example.com", DnsRecordType::A)).await.unwrap();
+        assert_eq!(take(&seen), vec![a("host.corp.example.com")]);
+    }
+
+    #[tokio::test]
+    async fn test_qname_minimisation_falls_back_to_full_query_on_nxdomain() {
+        let (port, seen) = scripted_authority(Ipv4Addr::new(10, 1, 1, 9), &["b.c.corp.example.com"]).await;
+        let resolver = minimising_resolver(port, true).await;
+
+        let response = resolver.query(DnsQueryRequest::new("z.a.b.c.corp.example.com", DnsRecordType::A)).await.unwrap();
+        assert_eq!(response.ip_addresses(), vec![IpAddr::V4(Ipv4Addr::new(10, 1, 1, 9))]);
+        assert_eq!(take(&seen), vec![
+            ns("c.corp.example.com"),
+            ns("b.c.corp.example.com"),
+            a("z.a.b.c.corp.example.com"),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_qname_minimisation_is_off_unless_enabled_for_the_route() {
+        let (port, seen) = scripted_authority(Ipv4Addr::new(10, 1, 1, 9), &[]).await;
+        let resolver = minimising_resolver(port, false).await;
+        resolver.query(DnsQueryRequest::new("a.b.c.corp.example.com", DnsRecordType::A)).await.unwrap();
+        assert_eq!(take(&seen), vec![a("a.b.c.corp.example.com")]);
+
+        let router = DomainRouter::new()
+            .add_rule("corp.example.com", DomainRoute::Default)
+            .with_qname_minimisation("corp.example.com");
+        assert!(router.validate().is_err());
+        assert_eq!(router.qname_minimisation_apex("a.corp.example.com"), None);
+    }
+
+    #[test]
+    fn test_minimisation_steps_are_capped() {
+        use crate::resolver::{minimisation_steps, MAX_MINIMISE_STEPS};
+
+        assert_eq!(minimisation_steps("corp.example.com", "a.b.CORP.example.com."), vec!["b.CORP.example.com"]);
+        assert!(minimisation_steps("corp.example.com", "notcorp.example.com").is_empty());
+        assert!(minimisation_steps("corp.example.com", "corp.example.com").is_empty());
+
+        let deep = format!("{}.corp.example.com", vec!["x"; 20].join("."));
+        let steps = minimisation_steps("corp.example.com", &deep);
+        assert_eq!(steps.len(), MAX_MINIMISE_STEPS);
+        assert_eq!(steps[0], "x.corp.example.com");
+    }
+}
@@ -0,0 +1,235 @@
+//! 作用域解析器
+//!
+//! 同一个解析器交给不同的模块使用、又要各自的策略时（例如某个模块只能用内网上游且要求DNSSEC），
+//! 用 [`SmartDnsResolver::scoped`] 创建 [`ScopedResolver`]：传输、缓存、上游监控和决策引擎都与父解析器共用，
+//! 只有 [`ScopeOverrides`] 中的策略不同。创建作用域只复制两个 `Arc`，可以按请求创建。
+//!
+//! 作用域的限制不是在接口处过滤出来的：作用域内的查询带着这份策略执行（tokio任务局部变量，
+//! 同 [`priority::scope`](crate::resolver::priority::scope)），核心解析器选择传输、决定查询策略和客户端子网时读取它，
+//! 故障转移、域名转发、应急模式和上游粘性同样只会用到作用域允许的上游。
+//! 作用域内的查询不与其他查询合并，也不返回待后台刷新的过期答案，避免借用其他作用域的上游得到答案；
+//! 共享缓存中已有的答案照常命中（[`ScopeOverrides::bypass_cache`] 关闭读取）。
+
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use super::middleware::QueryMiddleware;
+use super::resolver::SmartDnsResolver;
+use super::strategy::QueryStrategy;
+use super::types::{DnsQueryRequest, DnsQueryResponse, DnsRecordType, ResolvedAddrs, UpstreamFilter};
+use crate::error::Result;
+use crate::resolver::cache::TtlClamp;
+use crate::resolver::scope;
+use crate::types::EcsPolicy;
+
+/// 作用域相对父解析器改变的策略，未设置的项沿用父解析器
+#[derive(Debug, Clone, Default)]
+pub struct ScopeOverrides {
+    /// 只使用筛选保留的上游（按名称），与 `tiers` 同时设置时须同时满足
+    pub upstreams: Option<UpstreamFilter>,
+    /// 只使用这些层级的上游
+    pub tiers: Option<Vec<u8>>,
+    /// 查询策略
+    pub strategy: Option<QueryStrategy>,
+    /// 作用域内的查询默认不读缓存（同 [`DnsQueryRequest::disable_cache`]），答案照常写入共享缓存
+    pub bypass_cache: bool,
+    /// 作用域内查询携带的客户端子网的处理方式，先于各上游自身的ECS策略生效
+    pub client_subnet: Option<EcsPolicy>,
+    /// 要求应答经过上游验证：查询设置DO位，应答的AD位未置位时以 [`DnsError::DnssecRequired`](crate::DnsError::DnssecRequired) 失败
+    pub require_dnssec: bool,
+    /// 返回给作用域调用方的TTL下限，不改变共享缓存中的TTL
+    pub min_ttl: Option<Duration>,
+    /// 返回给作用域调用方的TTL上限，不改变共享缓存中的TTL
+    pub max_ttl: Option<Duration>,
+    /// 在父解析器的中间件之前执行的中间件
+    pub middlewares: Vec<Arc<dyn QueryMiddleware>>,
+}
+
+impl ScopeOverrides {
+    /// 只使用这些上游
+    pub fn with_upstreams<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.upstreams = Some(UpstreamFilter::Only(names.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    /// 只使用这些层级的上游
+    pub fn with_tiers(mut self, tiers: impl IntoIterator<Item = u8>) -> Self {
+        self.tiers = Some(tiers.into_iter().collect());
+        self
+    }
+
+    /// 改用另一种查询策略
+    pub fn with_strategy(mut self, strategy: QueryStrategy) -> Self {
+        self.strategy = Some(strategy);
+        self
+    }
+
+    /// 默认不读缓存
+    pub fn with_cache_bypass(mut self) -> Self {
+        self.bypass_cache = true;
+        self
+    }
+
+    /// 设置客户端子网的处理方式
+    pub fn with_client_subnet(mut self, policy: EcsPolicy) -> Self {
+        self.client_subnet = Some(policy);
+        self
+    }
+
+    /// 要求应答经过DNSSEC验证
+    pub fn with_dnssec_required(mut self) -> Self {
+        self.require_dnssec = true;
+        self
+    }
+
+    /// 钳制返回给调用方的TTL
+    pub fn with_ttl_clamp(mut self, min_ttl: Option<Duration>, max_ttl: Option<Duration>) -> Self {
+        self.min_ttl = min_ttl;
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    /// 追加中间件
+    pub fn with_middleware(mut self, middleware: Arc<dyn QueryMiddleware>) -> Self {
+        self.middlewares.push(middleware);
+        self
+    }
+
+    /// 名为 `name`、层级为 `tier` 的上游是否可以在作用域内使用
+    pub fn allows(&self, name: &str, tier: u8) -> bool {
+        self.upstreams.as_ref().is_none_or(|filter| filter.allows(name))
+            && self.tiers.as_ref().is_none_or(|tiers| tiers.contains(&tier))
+    }
+
+    /// 作用域对请求默认值的改变
+    pub(super) fn apply_defaults(&self, mut request: DnsQueryRequest) -> DnsQueryRequest {
+        request.disable_cache |= self.bypass_cache;
+        request.enable_dnssec |= self.require_dnssec;
+        request
+    }
+
+    /// 返回给调用方的TTL钳制，没有设置时为 `None`
+    pub(super) fn ttl_clamp(&self) -> Option<TtlClamp> {
+        Some(TtlClamp::new(self.min_ttl, self.max_ttl)).filter(TtlClamp::is_active)
+    }
+}
+
+/// 共用父解析器全部资源、只改变策略的解析器，见 [`SmartDnsResolver::scoped`]
+#[derive(Debug, Clone)]
+pub struct ScopedResolver {
+    parent: Arc<SmartDnsResolver>,
+    overrides: Arc<ScopeOverrides>,
+}
+
+impl ScopedResolver {
+    pub(super) fn new(parent: Arc<SmartDnsResolver>, overrides: ScopeOverrides) -> Self {
+        Self { parent, overrides: Arc::new(overrides) }
+    }
+
+    /// 父解析器
+    pub fn parent(&self) -> &Arc<SmartDnsResolver> {
+        &self.parent
+    }
+
+    /// 作用域的策略
+    pub fn overrides(&self) -> &ScopeOverrides {
+        &self.overrides
+    }
+
+    /// 在作用域中执行父解析器的查询；查询的future较大，放在堆上
+    async fn within<F: Future>(&self, future: F) -> F::Output {
+        scope::scope(self.overrides.clone(), Box::pin(future)).await
+    }
+
+    /// 执行DNS查询，同 [`SmartDnsResolver::query`]
+    pub async fn query(&self, request: DnsQueryRequest) -> Result<DnsQueryResponse> {
+        self.within(self.parent.query(request)).await
+    }
+
+    /// 批量执行DNS查询，同 [`SmartDnsResolver::batch_query`]
+    pub async fn batch_query(&self, requests: Vec<DnsQueryRequest>) -> Vec<Result<DnsQueryResponse>> {
+        self.within(self.parent.batch_query(requests)).await
+    }
+
+    /// 解析域名的A或AAAA地址，同 [`SmartDnsResolver::lookup_ip`]
+    pub async fn lookup_ip(&self, domain: &str, record_type: DnsRecordType) -> Result<Vec<IpAddr>> {
+        self.within(self.parent.lookup_ip(domain, record_type)).await
+    }
+
+    /// 带完整请求选项解析地址，同 [`SmartDnsResolver::lookup_ip_with`]
+    pub async fn lookup_ip_with(&self, request: DnsQueryRequest) -> Result<Vec<IpAddr>> {
+        self.within(self.parent.lookup_ip_with(request)).await
+    }
+
+    /// 并发解析A和AAAA地址并给出有效期，同 [`SmartDnsResolver::resolve_with_ttl`]
+    pub async fn resolve_with_ttl(&self, domain: &str) -> Result<ResolvedAddrs> {
+        self.within(self.parent.resolve_with_ttl(domain)).await
+    }
+}
+
+/// [`SmartDnsResolver`] 和 [`ScopedResolver`] 共有的查询接口，供同时接受两者的泛型代码使用
+#[async_trait]
+pub trait DnsLookup: Send + Sync {
+    /// 执行DNS查询
+    async fn query(&self, request: DnsQueryRequest) -> Result<DnsQueryResponse>;
+
+    /// 批量执行DNS查询，结果顺序与请求顺序一致
+    async fn batch_query(&self, requests: Vec<DnsQueryRequest>) -> Vec<Result<DnsQueryResponse>>;
+
+    /// 带完整请求选项解析A或AAAA地址
+    async fn lookup_ip_with(&self, request: DnsQueryRequest) -> Result<Vec<IpAddr>>;
+
+    /// 解析域名的A或AAAA地址
+    async fn lookup_ip(&self, domain: &str, record_type: DnsRecordType) -> Result<Vec<IpAddr>> {
+        self.lookup_ip_with(DnsQueryRequest::new(domain, record_type)).await
+    }
+
+    /// 并发解析A和AAAA地址并给出有效期
+    async fn resolve_with_ttl(&self, domain: &str) -> Result<ResolvedAddrs>;
+}
+
+#[async_trait]
+impl DnsLookup for SmartDnsResolver {
+    async fn query(&self, request: DnsQueryRequest) -> Result<DnsQueryResponse> {
+        SmartDnsResolver::query(self, request).await
+    }
+
+    async fn batch_query(&self, requests: Vec<DnsQueryRequest>) -> Vec<Result<DnsQueryResponse>> {
+        SmartDnsResolver::batch_query(self, requests).await
+    }
+
+    async fn lookup_ip_with(&self, request: DnsQueryRequest) -> Result<Vec<IpAddr>> {
+        SmartDnsResolver::lookup_ip_with(self, request).await
+    }
+
+    async fn resolve_with_ttl(&self, domain: &str) -> Result<ResolvedAddrs> {
+        SmartDnsResolver::resolve_with_ttl(self, domain).await
+    }
+}
+
+#[async_trait]
+impl DnsLookup for ScopedResolver {
+    async fn query(&self, request: DnsQueryRequest) -> Result<DnsQueryResponse> {
+        ScopedResolver::query(self, request).await
+    }
+
+    async fn batch_query(&self, requests: Vec<DnsQueryRequest>) -> Vec<Result<DnsQueryResponse>> {
+        ScopedResolver::batch_query(self, requests).await
+    }
+
+    async fn lookup_ip_with(&self, request: DnsQueryRequest) -> Result<Vec<IpAddr>> {
+        ScopedResolver::lookup_ip_with(self, request).await
+    }
+
+    async fn resolve_with_ttl(&self, domain: &str) -> Result<ResolvedAddrs> {
+        ScopedResolver::resolve_with_ttl(self, domain).await
+    }
+}
@@ -0,0 +1,274 @@
+//! 上游粘性：同一客户端（或租户）的查询固定使用同一个上游
+//!
+//! Smart策略的探索和RoundRobin轮询会让连续的查询落到不同的上游，同一客户端先后解析的
+//! 几个CDN域名可能得到不同区域的节点，依赖会话亲和的CDN因此出错。开启粘性后，
+//! 每个粘性键第一次查询成功所用的上游会被记住，之后同一键的查询优先使用它；
+//! 该上游不可用时按查询策略选择，并改为记住实际应答的上游。
+//!
+//! 记录表按最近使用淘汰，条目数有上限；条目在最后一次成功使用 `ttl` 之后过期。
+
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::time::Instant;
+
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+
+use crate::builder::types::DnsQueryRequest;
+use crate::error::{DnsError, Result};
+
+/// 粘性记录表的默认条目上限
+pub const DEFAULT_STICKY_CAPACITY: usize = 4096;
+
+/// 按什么区分查询来自哪个客户端
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StickyKey {
+    /// 客户端地址所在的子网（IPv4按/24，IPv6按/56，与发送ECS时一致），没有客户端地址的查询不粘性
+    ClientSubnet,
+    /// 请求上下文中的租户，没有租户的查询不粘性
+    QueryContextTenant,
+}
+
+/// 上游粘性配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StickinessConfig {
+    /// 粘性键
+    pub key: StickyKey,
+    /// 记录在最后一次成功使用之后保留的时间
+    pub ttl: Duration,
+    /// 记录表最多保存的粘性键数，超出时淘汰最久未使用的
+    pub capacity: usize,
+}
+
+impl StickinessConfig {
+    /// 按 `key` 粘性，记录保留 `ttl`，记录表上限为 [`DEFAULT_STICKY_CAPACITY`]
+    pub fn new(key: StickyKey, ttl: Duration) -> Self {
+        Self { key, ttl, capacity: DEFAULT_STICKY_CAPACITY }
+    }
+
+    /// 设置记录表上限
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// 检查配置：保留时间和记录表上限都必须大于0
+    pub fn validate(&self) -> Result<()> {
+        if self.ttl.is_zero() {
+            return Err(DnsError::InvalidConfig("Stickiness ttl must be greater than zero".to_string()));
+        }
+        if self.capacity == 0 {
+            return Err(DnsError::InvalidConfig("Stickiness capacity must be at least 1".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// 粘性记录表的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct StickinessStats {
+    /// 当前的记录数（含尚未清理的过期记录）
+    pub entries: usize,
+    /// 记录表上限
+    pub capacity: usize,
+    /// 带粘性键、并且优先使用了记住的上游的查询数
+    pub hits: u64,
+    /// 带粘性键、但没有记录、记录已过期或记住的上游不可用的查询数
+    pub misses: u64,
+    /// 记录改为另一个上游的次数
+    pub repins: u64,
+}
+
+impl StickinessStats {
+    /// 命中率（hits / (hits + misses)），还没有带粘性键的查询时为0
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pin {
+    upstream: String,
+    last_used: Instant,
+}
+
+#[derive(Debug)]
+struct PinTable {
+    pins: LruCache<String, Pin>,
+    hits: u64,
+    misses: u64,
+    repins: u64,
+}
+
+/// 粘性键到上游的记录表
+#[derive(Debug)]
+pub(crate) struct StickyPins {
+    config: StickinessConfig,
+    table: Mutex<PinTable>,
+}
+
+impl StickyPins {
+    /// 按配置创建记录表，配置无效时返回错误
+    pub(crate) fn new(config: StickinessConfig) -> Result<Self> {
+        config.validate()?;
+        let capacity = NonZeroUsize::new(config.capacity)
+            .ok_or_else(|| DnsError::InvalidConfig("Stickiness capacity must be at least 1".to_string()))?;
+        Ok(Self {
+            config,
+            table: Mutex::new(PinTable { pins: LruCache::new(capacity), hits: 0, misses: 0, repins: 0 }),
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PinTable> {
+        self.table.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 请求的粘性键，请求不带相应信息时为 `None`
+    pub(crate) fn key_for(&self, request: &DnsQueryRequest) -> Option<String> {
+        match self.config.key {
+            StickyKey::ClientSubnet => {
+                let ip: IpAddr = request.client_address.as_ref()?.parse().ok()?;
+                Some(subnet_key(ip))
+            }
+            StickyKey::QueryContextTenant => request.context.as_ref()?.tenant.clone(),
+        }
+    }
+
+    /// 记住的上游，`available` 判断它当前能否使用；结果计入命中或未命中
+    pub(crate) fn preferred(&self, key: &str, available: impl FnOnce(&str) -> bool) -> Option<String> {
+        let mut table = self.lock();
+        let expired = table.pins.peek(key).is_some_and(|pin| pin.last_used.elapsed() >= self.config.ttl);
+        if expired {
+            table.pins.pop(key);
+        }
+        let upstream = table.pins.get(key)
+            .map(|pin| pin.upstream.clone())
+            .filter(|upstream| available(upstream));
+        if upstream.is_some() {
+            table.hits += 1;
+        } else {
+            table.misses += 1;
+        }
+        upstream
+    }
+
+    /// 记住 `upstream` 为 `key` 的上游（查询成功后调用），并刷新保留时间
+    pub(crate) fn pin(&self, key: String, upstream: &str) {
+        let table = &mut *self.lock();
+        let now = Instant::now();
+        if let Some(pin) = table.pins.get_mut(&key) {
+            if pin.upstream != upstream {
+                pin.upstream = upstream.to_string();
+                table.repins += 1;
+            }
+            pin.last_used = now;
+            return;
+        }
+        table.pins.put(key, Pin { upstream: upstream.to_string(), last_used: now });
+    }
+
+    /// 粘性键当前记住的上游（不计入命中统计，过期的记录视为不存在）
+    pub(crate) fn pinned(&self, key: &str) -> Option<String> {
+        self.lock().pins.peek(key)
+            .filter(|pin| pin.last_used.elapsed() < self.config.ttl)
+            .map(|pin| pin.upstream.clone())
+    }
+
+    /// 清空所有记录（统计计数保留）
+    pub(crate) fn clear(&self) {
+        self.lock().pins.clear();
+    }
+
+    /// 当前统计
+    pub(crate) fn stats(&self) -> StickinessStats {
+        let table = self.lock();
+        StickinessStats {
+            entries: table.pins.len(),
+            capacity: self.config.capacity,
+            hits: table.hits,
+            misses: table.misses,
+            repins: table.repins,
+        }
+    }
+}
+
+/// 客户端地址所在子网的文本形式：IPv4取/24，IPv6取/56
+fn subnet_key(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(addr) => {
+            let [a, b, c, _] = addr.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        IpAddr::V6(addr) => {
+            let masked = u128::from(addr) & !((1u128 << 72) - 1);
+            format!("{}/56", std::net::Ipv6Addr::from(masked))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::types::{DnsRecordType, QueryContext};
+
+    fn request(client: Option<&str>, tenant: Option<&str>) -> DnsQueryRequest {
+        let mut request = DnsQueryRequest::new("cdn.example.com", DnsRecordType::A);
+        request.client_address = client.map(str::to_string);
+        request.context = tenant.map(QueryContext::for_tenant);
+        request
+    }
+
+    #[test]
+    fn test_sticky_keys_group_clients_by_subnet_or_tenant() {
+        let by_subnet = StickyPins::new(StickinessConfig::new(StickyKey::ClientSubnet, Duration::from_secs(60))).unwrap();
+        assert_eq!(by_subnet.key_for(&request(Some("203.0.113.7"), None)).as_deref(), Some("203.0.113.0/24"));
+        assert_eq!(by_subnet.key_for(&request(Some("203.0.113.200"), Some("t"))).as_deref(), Some("203.0.113.0/24"));
+        assert_eq!(by_subnet.key_for(&request(Some("2001:db8:aa:bbcc::1"), None)).as_deref(), Some("2001:db8:aa:bb00::/56"));
+        assert_eq!(by_subnet.key_for(&request(None, Some("t"))), None);
+        assert_eq!(by_subnet.key_for(&request(Some("not an ip"), None)), None);
+
+        let by_tenant = StickyPins::new(StickinessConfig::new(StickyKey::QueryContextTenant, Duration::from_secs(60))).unwrap();
+        assert_eq!(by_tenant.key_for(&request(Some("203.0.113.7"), Some("acme"))).as_deref(), Some("acme"));
+        assert_eq!(by_tenant.key_for(&request(Some("203.0.113.7"), None)), None);
+    }
+
+    #[test]
+    fn test_pins_expire_are_bounded_and_counted() {
+        assert!(StickyPins::new(StickinessConfig::new(StickyKey::ClientSubnet, Duration::ZERO)).is_err());
+        assert!(StickyPins::new(StickinessConfig::new(StickyKey::ClientSubnet, Duration::from_secs(1)).with_capacity(0)).is_err());
+
+        let pins = StickyPins::new(
+            StickinessConfig::new(StickyKey::QueryContextTenant, Duration::from_millis(50)).with_capacity(2),
+        ).unwrap();
+        assert_eq!(pins.preferred("a", |_| true), None);
+        pins.pin("a".to_string(), "up1");
+        pins.pin("b".to_string(), "up2");
+        assert_eq!(pins.preferred("a", |_| true).as_deref(), Some("up1"));
+        assert_eq!(pins.preferred("a", |name| name != "up1"), None);
+        pins.pin("a".to_string(), "up3");
+
+        // 容量为2，最久未使用的b被淘汰
+        pins.pin("c".to_string(), "up1");
+        assert_eq!(pins.pinned("b"), None);
+        assert_eq!(pins.pinned("a").as_deref(), Some("up3"));
+
+        let stats = pins.stats();
+        assert_eq!((stats.entries, stats.capacity, stats.hits, stats.misses, stats.repins), (2, 2, 1, 2, 1));
+        assert!((stats.hit_ratio() - 1.0 / 3.0).abs() < 1e-9);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(pins.preferred("a", |_| true), None);
+        assert_eq!(pins.pinned("c"), None);
+
+        pins.clear();
+        assert_eq!(pins.stats().entries, 0);
+        assert_eq!(StickinessStats::default().hit_ratio(), 0.0);
+    }
+}
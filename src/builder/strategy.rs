@@ -5,16 +5,42 @@
 use serde::{Deserialize, Serialize};
 
 /// DNS查询策略
+/// 
+/// 重试次数（`retry_count`）在各策略中的含义：Fifo、Smart和RoundRobin向上游查询一整轮都失败后，
+/// 再查询至多 `retry_count` 轮（只使用仍可用的上游），Quorum未达成一致时同样整轮重试；Sequential让每个上游各自重试 `retry_count` 次后再换下一个。
+/// 重试次数为0时不重试，为1时整轮失败后恰好再查询一轮
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum QueryStrategy {
-    /// FIFO策略：按照配置顺序依次查询上游服务器
+    /// 最快优先策略：同时向所有可用上游查询，采用最先到达的应答并取消其余查询
+    /// 
+    /// 名称沿用早期版本，实际并不按配置顺序逐个尝试，每次查询都会发给所有上游；
+    /// 需要按顺序尝试时使用 [`Sequential`](Self::Sequential)。所有上游都失败时整轮重试
     Fifo,
     
     /// 智能策略：基于性能指标和网络状况智能选择最优上游服务器
+    /// 
+    /// 所有上游都失败时整轮重试，重试次数见上
     Smart,
     
     /// 轮询策略：轮流使用不同的上游服务器
+    /// 
+    /// 重试次数取代了早期版本固定的3次尝试：首次查询加 `retry_count` 次重试
     RoundRobin,
+    
+    /// 顺序策略：严格按配置顺序逐个尝试上游，与resolv.conf中多个nameserver的语义相同
+    /// 
+    /// 每个上游按重试次数重试，出错或超时后才换下一个；前面的上游有应答时不会联系后面的上游
+    Sequential,
+    
+    /// 法定人数策略：同时向所有可用上游查询，`required` 个上游给出一致的答案时才返回
+    /// 
+    /// 答案一致指所查询类型的记录集合相同（不计顺序和TTL）。达成一致后立即返回并取消其余查询；
+    /// 所有查询结束仍未达成一致时返回 [`DnsError::QuorumNotReached`](crate::DnsError::QuorumNotReached)，
+    /// 带有各上游的答案。只有达成一致的答案才会写入缓存，用于发现上游被篡改的场景
+    Quorum {
+        /// 需要给出相同答案的上游数，不能为0，也不能超过启用的上游数
+        required: usize,
+    },
 }
 
 // 注意：移除了 Default 实现，因为它包含兜底行为
@@ -24,9 +50,11 @@ impl QueryStrategy {
     /// 获取策略描述
     pub fn description(&self) -> &'static str {
         match self {
-            Self::Fifo => "按配置顺序依次查询，适合有明确优先级的场景",
+            Self::Fifo => "同时查询所有服务器并采用最快的应答，适合追求低延迟的场景",
             Self::Smart => "基于性能指标智能选择，适合追求最优性能的场景",
             Self::RoundRobin => "轮流使用不同服务器，适合负载均衡场景",
+            Self::Sequential => "按配置顺序逐个尝试，适合有明确主备关系的场景",
+            Self::Quorum { .. } => "多个服务器答案一致才采用，适合需要发现DNS篡改的场景",
         }
     }
     
@@ -37,6 +65,6 @@ impl QueryStrategy {
     
     /// 是否支持并发查询
     pub fn supports_concurrent(&self) -> bool {
-        matches!(self, Self::Fifo | Self::Smart)
+        matches!(self, Self::Fifo | Self::Smart | Self::Quorum { .. })
     }
 }
\ No newline at end of file
@@ -0,0 +1,85 @@
+//! 按租户统计查询
+//!
+//! 解析器作为多个租户共用的服务时，按请求上下文中的租户分别统计查询数、失败数和缓存命中数。
+//! 租户名称来自调用方，统计表最多保存固定数量的租户，之后出现的新租户合并计入 [`OTHER_TENANTS`]，
+//! 内存占用不会随租户名称无限增长。没有租户的查询不计入。
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{DnsError, Result};
+
+/// 超出租户数上限后新出现的租户合并计入的名称
+pub const OTHER_TENANTS: &str = "(other)";
+
+/// 单个租户的查询计数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TenantStats {
+    /// 查询次数
+    pub queries: u64,
+    /// 失败的查询次数（响应的 `success` 为假）
+    pub errors: u64,
+    /// 命中缓存的查询次数
+    pub cache_hits: u64,
+}
+
+/// 按租户的计数表，租户数有上限
+#[derive(Debug)]
+pub(crate) struct TenantStatsTable {
+    max_tenants: usize,
+    tenants: Mutex<HashMap<String, TenantStats>>,
+}
+
+impl TenantStatsTable {
+    /// 创建计数表，最多单独统计 `max_tenants` 个租户（必须大于0）
+    pub(crate) fn new(max_tenants: usize) -> Result<Self> {
+        if max_tenants == 0 {
+            return Err(DnsError::InvalidConfig("Tenant stats require at least one tenant slot".to_string()));
+        }
+        Ok(Self {
+            max_tenants,
+            tenants: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// 记录一次查询
+    pub(crate) fn record(&self, tenant: &str, success: bool, cache_hit: bool) {
+        let mut tenants = self.tenants.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let known = tenants.contains_key(tenant);
+        let key = if known || tenants.len() < self.max_tenants { tenant } else { OTHER_TENANTS };
+        let stats = tenants.entry(key.to_string()).or_default();
+        stats.queries += 1;
+        stats.errors += u64::from(!success);
+        stats.cache_hits += u64::from(cache_hit);
+    }
+
+    /// 当前的计数（按租户名称）
+    pub(crate) fn snapshot(&self) -> HashMap<String, TenantStats> {
+        self.tenants.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_tenants_beyond_limit_are_merged() {
+        assert!(TenantStatsTable::new(0).is_err());
+
+        let table = TenantStatsTable::new(2).unwrap();
+        table.record("alpha", true, false);
+        table.record("beta", false, false);
+        table.record("gamma", true, true);
+        table.record("delta", true, false);
+        table.record("alpha", true, true);
+
+        let stats = table.snapshot();
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats["alpha"], TenantStats { queries: 2, errors: 0, cache_hits: 1 });
+        assert_eq!(stats["beta"], TenantStats { queries: 1, errors: 1, cache_hits: 0 });
+        assert_eq!(stats[OTHER_TENANTS], TenantStats { queries: 2, errors: 0, cache_hits: 1 });
+    }
+}
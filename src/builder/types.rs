@@ -2,11 +2,25 @@
 //! 
 //! 本模块定义了DNS查询过程中使用的核心数据结构
 
+#[cfg(feature = "serde-api")]
+use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::cmp::Ordering;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use crate::time::{SystemTime, UNIX_EPOCH};
+use crate::error::{DnsError, Result};
+use crate::resolver::diagnosis::QueryFailureReport;
+use super::explain::SelectionExplanation;
+use crate::resolver::priority::QueryPriority;
+use crate::transport::{HttpVersion, TransportTiming};
+use crate::types::{QClass, ResponseCode};
 
 /// DNS查询请求
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub struct DnsQueryRequest {
     /// 查询ID（可选，用于追踪）
     pub query_id: Option<String>,
@@ -17,20 +31,50 @@ pub struct DnsQueryRequest {
     /// 记录类型
     pub record_type: DnsRecordType,
     
-    /// 是否启用EDNS
+    /// 是否启用EDNS，默认沿用解析器的设置；为false时本次查询不携带OPT记录，客户端子网和DO位也不发送。
+    /// 上游明确配置的 [`UpstreamFeatures`](crate::types::UpstreamFeatures) 优先
     pub enable_edns: bool,
     
     /// 客户端地址信息（用于CDN优化）
     pub client_address: Option<String>,
     
-    /// 查询超时时间（毫秒）
+    /// 整个查询（含重试和故障转移）的超时时间（毫秒），未设置时只受各上游自身的超时限制
     pub timeout_ms: Option<u64>,
     
-    /// 是否禁用缓存
+    /// 是否禁用缓存（不读缓存，总是向上游查询）
     pub disable_cache: bool,
     
-    /// 是否启用DNSSEC验证
+    /// 是否启用DNSSEC：本次查询设置DO位（总会携带OPT记录），须同时启用EDNS。
+    /// 尚未实现签名验证，响应的 `dnssec_status` 总是不确定
     pub enable_dnssec: bool,
+    
+    /// 是否保留与上游收发的原始DNS报文（见 [`DnsQueryResponse::wire_request`]）
+    #[serde(default)]
+    pub capture_wire: bool,
+    
+    /// 查询类别，默认IN
+    #[serde(default)]
+    pub qclass: QClass,
+    
+    /// 调用方的上下文（租户、追踪ID、标签），记入日志追踪并原样带回响应
+    #[serde(default)]
+    pub context: Option<QueryContext>,
+    
+    /// 只向筛选后的上游查询（调试某个上游时使用），见 [`with_upstream_filter`](Self::with_upstream_filter)
+    #[serde(default)]
+    pub upstream_filter: Option<UpstreamFilter>,
+    
+    /// 按上游筛选的查询是否读缓存，默认不读
+    #[serde(default)]
+    pub upstream_filter_reads_cache: bool,
+    
+    /// 查询优先级，未设置时为普通查询（批量接口中为批量查询），见 [`with_priority`](Self::with_priority)
+    #[serde(default)]
+    pub priority: Option<QueryPriority>,
+    
+    /// 是否在响应中附带智能策略选择上游的解释，见 [`with_explain`](Self::with_explain)
+    #[serde(default)]
+    pub explain: bool,
 }
 
 impl DnsQueryRequest {
@@ -45,6 +89,13 @@ impl DnsQueryRequest {
             timeout_ms: None,
             disable_cache: false,
             enable_dnssec: false,
+            capture_wire: false,
+            qclass: QClass::IN,
+            context: None,
+            upstream_filter: None,
+            upstream_filter_reads_cache: false,
+            priority: None,
+            explain: false,
         }
     }
     
@@ -60,30 +111,357 @@ impl DnsQueryRequest {
         self
     }
     
-    /// 设置超时时间
+    /// 设置整个查询的超时时间（毫秒），须大于零
     pub fn with_timeout(mut self, timeout_ms: u64) -> Self {
         self.timeout_ms = Some(timeout_ms);
         self
     }
     
+    /// 整个查询的超时时间
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+    
+    /// 检查请求选项，不合法时返回 [`DnsError::InvalidConfig`]
+    pub fn validate(&self) -> Result<()> {
+        if self.domain.is_empty() {
+            return Err(DnsError::InvalidConfig("Query domain must not be empty".to_string()));
+        }
+        if self.timeout_ms == Some(0) {
+            return Err(DnsError::InvalidConfig("Query timeout must be greater than zero".to_string()));
+        }
+        if self.enable_dnssec && !self.enable_edns {
+            return Err(DnsError::InvalidConfig("enable_dnssec requires EDNS, but enable_edns = false".to_string()));
+        }
+        Ok(())
+    }
+    
     /// 禁用缓存
     pub fn disable_cache(mut self) -> Self {
         self.disable_cache = true;
         self
     }
     
+    /// 设置本次查询是否启用EDNS，为false时不携带OPT记录
+    pub fn with_edns(mut self, enable: bool) -> Self {
+        self.enable_edns = enable;
+        self
+    }
+    
     /// 启用DNSSEC验证
     pub fn with_dnssec(mut self, enable: bool) -> Self {
         self.enable_dnssec = enable;
         self
     }
+    
+    /// 保留与上游收发的原始DNS报文
+    /// 
+    /// 开启后查询不读缓存、总是发往上游，报文附在响应的 `wire_request` / `wire_response` 中。
+    /// 应急模式的并发查询和 `query_multi_types` 不保留报文
+    pub fn with_capture_wire(mut self, capture: bool) -> Self {
+        self.capture_wire = capture;
+        self
+    }
+    
+    /// 设置查询类别（例如CH类的 `version.bind`），缓存按类别分别保存
+    pub fn with_class(mut self, qclass: QClass) -> Self {
+        self.qclass = qclass;
+        self
+    }
+    
+    /// 设置调用方的上下文，见 [`QueryContext`]
+    pub fn with_context(mut self, context: QueryContext) -> Self {
+        self.context = Some(context);
+        self
+    }
+    
+    /// 请求上下文中的租户
+    pub fn tenant(&self) -> Option<&str> {
+        self.context.as_ref()?.tenant.as_deref()
+    }
+    
+    /// 只向筛选后的上游查询，用于调试某个上游而不必另建解析器
+    /// 
+    /// 查询策略在筛选后的上游中照常选择；上游粘性、层级、域名转发规则和应急模式都不生效，
+    /// SERVFAIL/REFUSED也不会转移到筛选外的上游。筛选后没有已启用的上游时返回
+    /// [`DnsError::NoUpstreamAvailable`]。默认不读缓存，保证查询确实发往所选上游，
+    /// 见 [`with_upstream_filter_reads_cache`](Self::with_upstream_filter_reads_cache)
+    pub fn with_upstream_filter(mut self, filter: UpstreamFilter) -> Self {
+        self.upstream_filter = Some(filter);
+        self
+    }
+    
+    /// 设置按上游筛选的查询是否读缓存（默认不读；答案总是照常写入缓存）
+    pub fn with_upstream_filter_reads_cache(mut self, read: bool) -> Self {
+        self.upstream_filter_reads_cache = read;
+        self
+    }
+    
+    /// 设置查询优先级，决定上游发送的并发名额按什么顺序分配
+    /// 
+    /// 交互式查询可以使用保留的名额（见 [`DnsResolverBuilder::with_interactive_reserve`](super::DnsResolverBuilder::with_interactive_reserve)），
+    /// 名额用完时空出的名额先给交互式查询、最后给批量查询；上游的 `max_inflight` 已满时批量查询不等待
+    pub fn with_priority(mut self, priority: QueryPriority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+    
+    /// 在响应的 [`selection`](DnsQueryResponse::selection) 中附带智能策略选择上游的解释
+    /// 
+    /// 只在智能策略按评分选择上游时提供（应急模式、上游筛选、域名转发和粘性查询不按评分选择）；
+    /// 解释在查询开始前按当时的性能指标计算，未开启时不做任何额外计算
+    pub fn with_explain(mut self, explain: bool) -> Self {
+        self.explain = explain;
+        self
+    }
+    
+    /// 实际使用的查询优先级，未设置时为 [`QueryPriority::Normal`]
+    pub fn priority(&self) -> QueryPriority {
+        self.priority.unwrap_or_default()
+    }
+}
+
+/// 单次查询的上游筛选，按上游名称
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub enum UpstreamFilter {
+    /// 只使用这些上游（可以包括只用于域名转发的上游）
+    Only(Vec<String>),
+    /// 使用除这些以外的上游
+    Exclude(Vec<String>),
+}
+
+impl UpstreamFilter {
+    /// 只使用一个上游
+    pub fn only(name: impl Into<String>) -> Self {
+        Self::Only(vec![name.into()])
+    }
+    
+    /// 名为 `name` 的上游是否保留
+    pub fn allows(&self, name: &str) -> bool {
+        match self {
+            Self::Only(names) => names.iter().any(|allowed| allowed == name),
+            Self::Exclude(names) => !names.iter().any(|excluded| excluded == name),
+        }
+    }
+}
+
+impl fmt::Display for UpstreamFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (mode, names) = match self {
+            Self::Only(names) => ("only", names),
+            Self::Exclude(names) => ("excluding", names),
+        };
+        write!(f, "{} [{}]", mode, names.join(", "))
+    }
+}
+
+/// 调用方随查询传入的上下文，用于把查询归属到租户和请求
+/// 
+/// 解析器不解释其内容：租户和追踪ID作为查询的tracing span字段，响应原样带回整个上下文，
+/// 中间件可以读取它做按租户的策略；开启按租户统计时按 `tenant` 计数
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub struct QueryContext {
+    /// 租户名称
+    pub tenant: Option<String>,
+    /// 调用方的追踪ID
+    pub trace_id: Option<String>,
+    /// 其他键值标签，按添加顺序
+    #[serde(default)]
+    pub tags: Vec<(String, String)>,
+}
+
+impl QueryContext {
+    /// 指定租户的上下文
+    pub fn for_tenant(tenant: impl Into<String>) -> Self {
+        Self { tenant: Some(tenant.into()), ..Self::default() }
+    }
+    
+    /// 设置追踪ID
+    pub fn with_trace_id(mut self, trace_id: impl Into<String>) -> Self {
+        self.trace_id = Some(trace_id.into());
+        self
+    }
+    
+    /// 添加一个标签
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+    
+    /// 标签写作 `键=值`、以逗号分隔，用作tracing字段（只在输出时格式化）
+    pub(crate) fn tag_list(&self) -> TagList<'_> {
+        TagList(&self.tags)
+    }
+}
+
+/// [`QueryContext::tag_list`] 的显示形式
+pub(crate) struct TagList<'a>(&'a [(String, String)]);
+
+impl fmt::Display for TagList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (key, value)) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// 解析器为未指定ID的查询分配查询ID的格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueryIdFormat {
+    /// 进程内序号，写作 `随机前缀-十六进制序号`（如 `3f9a12c4-1a`），进程内唯一，分配时不分配内存
+    #[default]
+    Sequence,
+    /// RFC 4122 随机UUID，用于与外部系统关联
+    Uuid,
+}
+
+/// 查询ID
+/// 
+/// 调用方提供的ID原样带回；解析器分配的ID只保存序号或UUID，
+/// 在显示或序列化时才格式化为字符串。未分配时为空
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct QueryId(QueryIdRepr);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+enum QueryIdRepr {
+    #[default]
+    Empty,
+    Caller(String),
+    Sequence(u64),
+    Uuid(uuid::Uuid),
+}
+
+/// 序号ID的进程前缀：进程内首次分配时随机生成，区分不同进程的序号
+fn sequence_prefix() -> u32 {
+    static PREFIX: std::sync::OnceLock<u32> = std::sync::OnceLock::new();
+    *PREFIX.get_or_init(rand::random)
+}
+
+impl QueryId {
+    /// 按指定格式分配一个新的查询ID
+    pub fn generate(format: QueryIdFormat) -> Self {
+        static NEXT_SEQUENCE: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+        match format {
+            QueryIdFormat::Sequence => Self(QueryIdRepr::Sequence(
+                NEXT_SEQUENCE.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+            )),
+            QueryIdFormat::Uuid => Self(QueryIdRepr::Uuid(uuid::Uuid::new_v4())),
+        }
+    }
+    
+    /// 是否尚未分配
+    pub fn is_empty(&self) -> bool {
+        match &self.0 {
+            QueryIdRepr::Empty => true,
+            QueryIdRepr::Caller(id) => id.is_empty(),
+            QueryIdRepr::Sequence(_) | QueryIdRepr::Uuid(_) => false,
+        }
+    }
+    
+    /// 调用方提供的ID（解析器分配的ID为 `None`）
+    pub fn caller_id(&self) -> Option<&str> {
+        match &self.0 {
+            QueryIdRepr::Caller(id) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for QueryId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            QueryIdRepr::Empty => Ok(()),
+            QueryIdRepr::Caller(id) => f.write_str(id),
+            QueryIdRepr::Sequence(sequence) => write!(f, "{:08x}-{:x}", sequence_prefix(), sequence),
+            QueryIdRepr::Uuid(uuid) => fmt::Display::fmt(&uuid.hyphenated(), f),
+        }
+    }
+}
+
+impl From<String> for QueryId {
+    fn from(id: String) -> Self {
+        if id.is_empty() {
+            Self::default()
+        } else {
+            Self(QueryIdRepr::Caller(id))
+        }
+    }
+}
+
+impl From<&str> for QueryId {
+    fn from(id: &str) -> Self {
+        Self::from(id.to_string())
+    }
+}
+
+impl PartialEq<str> for QueryId {
+    /// 与显示形式比较，不为比较格式化出新的字符串
+    fn eq(&self, other: &str) -> bool {
+        /// 逐段比较写入的内容与剩余的期望文本
+        struct Matcher<'a>(&'a str);
+        
+        impl fmt::Write for Matcher<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0 = self.0.strip_prefix(s).ok_or(fmt::Error)?;
+                Ok(())
+            }
+        }
+        
+        let mut matcher = Matcher(other);
+        fmt::write(&mut matcher, format_args!("{}", self)).is_ok() && matcher.0.is_empty()
+    }
+}
+
+impl PartialEq<&str> for QueryId {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+impl Serialize for QueryId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for QueryId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+#[cfg(feature = "serde-api")]
+impl Encode for QueryId {
+    fn encode<E: bincode::enc::Encoder>(&self, encoder: &mut E) -> std::result::Result<(), bincode::error::EncodeError> {
+        match &self.0 {
+            QueryIdRepr::Caller(id) => id.encode(encoder),
+            _ => self.to_string().encode(encoder),
+        }
+    }
+}
+
+#[cfg(feature = "serde-api")]
+impl<Context> Decode<Context> for QueryId {
+    fn decode<D: bincode::de::Decoder<Context = Context>>(decoder: &mut D) -> std::result::Result<Self, bincode::error::DecodeError> {
+        String::decode(decoder).map(Self::from)
+    }
 }
 
+#[cfg(feature = "serde-api")]
+bincode::impl_borrow_decode!(QueryId);
+
 /// DNS查询响应
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub struct DnsQueryResponse {
-    /// 查询ID
-    pub query_id: String,
+    /// 查询ID：调用方指定的ID，否则为解析器分配的ID
+    pub query_id: QueryId,
     
     /// 查询的域名
     pub domain: String,
@@ -97,33 +475,376 @@ pub struct DnsQueryResponse {
     /// 错误信息（如果失败）
     pub error: Option<String>,
     
-    /// DNS记录列表
+    /// DNS记录列表，保持上游应答段的线路顺序（命中缓存时为写入缓存的应答的顺序）
+    /// 
+    /// 未开启记录轮换和 [`RecordSort`] 时，两次相同的上游应答给出顺序完全相同的记录
     pub records: Vec<DnsRecord>,
     
     /// 查询耗时（毫秒）
     pub duration_ms: u64,
     
-    /// 使用的上游服务器
+    /// 实际给出响应的上游服务器（命中缓存时为 `"cache"`）
     pub server_used: Option<String>,
     
+    /// 实际交换报文的上游对端地址（以主机名配置的上游为当时解析出的IP），
+    /// 用于排查CDN调度；命中缓存或传输取不到时为 `None`，serde中为 `"IP:端口"` 字符串
+    #[serde(default)]
+    pub upstream_peer: Option<SocketAddr>,
+    
+    /// 实际给出响应的传输协议（UDP/TCP/TLS/HTTPS，命中缓存时为 `"cache"`）
+    pub protocol_used: Option<String>,
+    
     /// DNSSEC验证状态
     pub dnssec_status: Option<DnssecStatus>,
     
     /// DNSSEC相关记录（RRSIG、DNSKEY等）
     pub dnssec_records: Vec<DnsRecord>,
+    
+    /// 查询时解析器是否处于应急模式（响应来自全部上游并发查询，TTL已按应急下限抬高）
+    #[serde(default)]
+    pub emergency_mode: bool,
+    
+    /// DNS响应码（RCODE，0为NOERROR、2为SERVFAIL、3为NXDOMAIN），查询失败时为 `None`
+    /// 
+    /// 包含OPT记录中的扩展位，BADVERS（16）、BADCOOKIE（23）等也能表示，见 [`ResponseCode`]
+    #[serde(default)]
+    pub rcode: Option<u16>,
+    
+    /// 上游是否在头部设置了AD位，声明应答中的数据都经过了DNSSEC验证；命中缓存时为写入缓存的应答的值
+    /// 
+    /// 只有到上游的链路可信（如DoT/DoH或本机验证解析器）时才能依赖它，查询失败时为 `false`
+    #[serde(default)]
+    pub authenticated_data: bool,
+    
+    /// 响应失效时间（Unix时间戳，秒）：生成响应时的时间加上记录中最小的TTL，
+    /// 没有记录时为 `None`
+    #[serde(default)]
+    pub valid_until: Option<u64>,
+    
+    /// 上游查询的分阶段耗时，只在解析器开启耗时分解且响应来自上游时提供
+    #[serde(default)]
+    pub timing: Option<TimingBreakdown>,
+    
+    /// 发往上游的原始DNS请求报文（请求开启 `capture_wire` 时），序列化为base64字符串
+    #[serde(default, with = "wire_base64")]
+    pub wire_request: Option<Vec<u8>>,
+    
+    /// 上游返回的原始DNS响应报文（解析前），序列化为base64字符串
+    #[serde(default, with = "wire_base64")]
+    pub wire_response: Option<Vec<u8>>,
+    
+    /// 原始报文是否超过解析器的保留上限而被截断
+    #[serde(default)]
+    pub wire_truncated: bool,
+    
+    /// 应答是否来自加密上游证书校验失败后的降级路径（明文DNS或不校验证书的加密连接），
+    /// 为真时调用方应提示用户当前解析不受加密保护
+    #[serde(default)]
+    pub degraded_security: bool,
+    
+    /// 上游的应答是否超出记录数上限而被截去了记录（见 [`RecordLimits`](crate::transport::RecordLimits)）；
+    /// 命中缓存的截断应答不再标记
+    #[serde(default)]
+    pub records_truncated: bool,
+    
+    /// 否定应答（NXDOMAIN或没有查询类型的记录）的否定缓存时间（秒）：权威段SOA的MINIMUM字段
+    /// 与SOA记录TTL中较小者（RFC 2308），命中缓存时不超过剩余时间；不是否定应答或没有SOA时为 `None`
+    #[serde(default)]
+    pub negative_ttl: Option<u32>,
+    
+    /// 否定应答权威段SOA记录的所有者名称，即查询名称所属区域的顶点，与 `negative_ttl` 同时提供
+    #[serde(default)]
+    pub zone_apex: Option<String>,
+    
+    /// 请求携带的上下文，原样带回
+    #[serde(default)]
+    pub context: Option<QueryContext>,
+    
+    /// 请求本身无效时的错误类别（此时 `error` 为说明），用于区分无效输入和解析失败；
+    /// 查询成功或解析失败时为 `None`
+    #[serde(default)]
+    pub error_code: Option<RequestErrorCode>,
+    
+    /// 查询发往的上游全部失败时各上游的结果（响应码或错误类别、协议、对端地址、耗时）和可能原因的提示；
+    /// 最终应答为SERVFAIL/REFUSED时也提供，NXDOMAIN只在符合某条提示规则时提供。成功或没有发往上游时为 `None`
+    #[serde(default)]
+    pub failure_report: Option<QueryFailureReport>,
+    
+    /// 请求开启 `explain` 且智能策略按评分选择上游时，选择的解释；其他情况为 `None`
+    #[serde(default)]
+    pub selection: Option<SelectionExplanation>,
+}
+
+/// 请求本身无效时的错误类别，见 [`DnsQueryResponse::error_code`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub enum RequestErrorCode {
+    /// 编码后的请求超过大小上限
+    PayloadTooLarge,
+    /// 无法解码为请求
+    Malformed,
+    /// 查询名称无效
+    InvalidDomain,
+    /// 记录类型不是已知的类型
+    InvalidRecordType,
+    /// 某个字段超过长度或数量上限
+    FieldTooLarge,
+    /// 批量请求的查询数超过上限
+    BatchTooLarge,
+}
+
+/// 把一批查询响应写成JSON数组
+/// 
+/// 逐条写入 `writer`，不在内存中拼出整个数组，适合一次导出几十万条结果；
+/// 数组元素与 [`DnsQueryResponse::to_json`] 的输出相同
+pub fn serialize_batch_json<'a, W, I>(mut writer: W, responses: I) -> Result<()>
+where
+    W: std::io::Write,
+    I: IntoIterator<Item = &'a DnsQueryResponse>,
+{
+    let io_error = |e: std::io::Error| DnsError::Io(format!("Failed to write query responses: {}", e));
+    writer.write_all(b"[").map_err(io_error)?;
+    for (index, response) in responses.into_iter().enumerate() {
+        if index > 0 {
+            writer.write_all(b",").map_err(io_error)?;
+        }
+        serde_json::to_writer(&mut writer, response).map_err(|e| if e.is_io() {
+            DnsError::Io(format!("Failed to write query responses: {}", e))
+        } else {
+            DnsError::Parse(format!("Failed to serialize query response: {}", e))
+        })?;
+    }
+    writer.write_all(b"]").map_err(io_error)?;
+    writer.flush().map_err(io_error)
 }
 
+/// 读取 [`serialize_batch_json`] 写出的JSON数组
+pub fn deserialize_batch_json<R: std::io::Read>(reader: R) -> Result<Vec<DnsQueryResponse>> {
+    serde_json::from_reader(std::io::BufReader::new(reader))
+        .map_err(|e| DnsError::Parse(format!("Invalid query response batch JSON: {}", e)))
+}
+
+/// 原始报文在serde中以base64字符串表示，便于写入JSON等文本格式
+mod wire_base64 {
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_some(&STANDARD.encode(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|text| STANDARD.decode(text).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// 本地给出的应答（不经过缓存和上游）使用的来源标记
+pub const LOCAL_SOURCE: &str = "local";
+
+/// 查询名本身是IP地址、直接以该地址应答时使用的来源标记
+pub const LITERAL_SOURCE: &str = "literal";
+
+/// IP地址字面量应答的TTL：地址不会变化，取TTL允许的最大值（RFC 2181）
+pub const LITERAL_TTL: u32 = i32::MAX as u32;
+
 impl DnsQueryResponse {
-    /// 提取IP地址列表
+    /// 在本地直接给出的成功应答（响应码NOERROR），`server_used` / `protocol_used` 为 [`LOCAL_SOURCE`]
+    /// 
+    /// 供查询中间件不经过上游应答使用；`records` 为空时表示NODATA
+    pub fn local_answer(request: &DnsQueryRequest, records: Vec<DnsRecord>) -> Self {
+        let mut response = Self::for_request(request);
+        response.success = true;
+        response.records = records;
+        response.server_used = Some(LOCAL_SOURCE.to_string());
+        response.protocol_used = Some(LOCAL_SOURCE.to_string());
+        response.rcode = Some(0);
+        response.stamp_valid_until(SystemTime::now());
+        response
+    }
+    
+    /// 查询名本身是IP地址时的本地应答，查询名不是IP地址或类型不是A/AAAA时返回None
+    /// 
+    /// IPv4地址的A查询、IPv6地址的AAAA查询以该地址应答，地址族不符时为NODATA（没有记录的NOERROR）；
+    /// `server_used` / `protocol_used` 为 [`LITERAL_SOURCE`]，耗时为0
+    pub fn literal_answer(request: &DnsQueryRequest) -> Option<Self> {
+        let ip = crate::utils::parse_ip_literal(&request.domain)?;
+        let record = match (request.record_type, ip) {
+            (DnsRecordType::A, IpAddr::V4(v4)) => Some(DnsRecord::a(request.domain.clone(), v4, LITERAL_TTL)),
+            (DnsRecordType::AAAA, IpAddr::V6(v6)) => Some(DnsRecord::aaaa(request.domain.clone(), v6, LITERAL_TTL)),
+            (DnsRecordType::A | DnsRecordType::AAAA, _) => None,
+            _ => return None,
+        };
+        let mut response = Self::local_answer(request, record.into_iter().collect());
+        response.server_used = Some(LITERAL_SOURCE.to_string());
+        response.protocol_used = Some(LITERAL_SOURCE.to_string());
+        Some(response)
+    }
+    
+    /// 查询失败的响应，错误信息写入 `error`
+    pub fn failed(request: &DnsQueryRequest, error: &DnsError) -> Self {
+        let mut response = Self::for_request(request);
+        response.error = Some(error.to_string());
+        response
+    }
+
+    /// 请求本身无效、没有发起查询的响应，错误信息写入 `error`，类别写入 `error_code`
+    pub fn rejected(request: &DnsQueryRequest, code: RequestErrorCode, error: &DnsError) -> Self {
+        let mut response = Self::failed(request, error);
+        response.error_code = Some(code);
+        response
+    }
+
+    /// 与请求对应、尚未填入结果的响应
+    pub(crate) fn for_request(request: &DnsQueryRequest) -> Self {
+        Self {
+            query_id: request.query_id.clone().map(QueryId::from).unwrap_or_default(),
+            domain: request.domain.clone(),
+            record_type: request.record_type,
+            success: false,
+            error: None,
+            records: Vec::new(),
+            duration_ms: 0,
+            server_used: None,
+            upstream_peer: None,
+            protocol_used: None,
+            dnssec_status: None,
+            dnssec_records: Vec::new(),
+            emergency_mode: false,
+            rcode: None,
+            authenticated_data: false,
+            valid_until: None,
+            timing: None,
+            wire_request: None,
+            wire_response: None,
+            wire_truncated: false,
+            degraded_security: false,
+            records_truncated: false,
+            negative_ttl: None,
+            zone_apex: None,
+            context: request.context.clone(),
+            error_code: None,
+            failure_report: None,
+            selection: None,
+        }
+    }
+    
+    /// 序列化为单行JSON，原始报文为base64字符串，记录值的形状见 [`DnsRecordValue`]
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self)
+            .map_err(|e| DnsError::Parse(format!("Failed to serialize query response: {}", e)))
+    }
+
+    /// 从 [`to_json`](Self::to_json) 输出的JSON文本解析
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| DnsError::Parse(format!("Invalid query response JSON: {}", e)))
+    }
+
+    /// 记录中最小的TTL（秒），没有记录时返回 `None`
+    pub fn min_ttl(&self) -> Option<u32> {
+        self.records.iter().map(|record| record.ttl).min()
+    }
+    
+    /// 以 `now` 为生成时间计算 `valid_until`
+    pub fn stamp_valid_until(&mut self, now: SystemTime) {
+        self.valid_until = self.min_ttl().map(|ttl| {
+            let created = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            created.saturating_add(u64::from(ttl))
+        });
+    }
+    
+    /// 响应是否已过期；没有记录（无有效期）的响应视为已过期
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(SystemTime::now())
+    }
+    
+    /// 在给定时间点响应是否已过期
+    pub fn is_expired_at(&self, now: SystemTime) -> bool {
+        self.expires_in_at(now).is_zero()
+    }
+    
+    /// 距离过期的剩余时间，已过期或没有有效期时为0
+    pub fn expires_in(&self) -> Duration {
+        self.expires_in_at(SystemTime::now())
+    }
+    
+    /// 在给定时间点距离过期的剩余时间
+    pub fn expires_in_at(&self, now: SystemTime) -> Duration {
+        self.valid_until
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+            .and_then(|deadline| deadline.duration_since(now).ok())
+            .unwrap_or(Duration::ZERO)
+    }
+    
+    /// 提取IP地址，区分“有地址”“域名存在但没有地址记录”和失败
+    /// 
+    /// 至少有一条A/AAAA记录时返回地址；响应码为NOERROR但没有地址记录（NODATA，例如应答只有CNAME）时
+    /// 返回 [`DnsError::NoRecords`]；NXDOMAIN、SERVFAIL、REFUSED、FORMERR返回对应的错误；
+    /// 查询本身失败（超时、传输错误）时返回带原错误信息的 [`DnsError::Server`]，
+    /// 需要原始错误类型时使用 [`SmartDnsResolver::lookup_ip`](crate::builder::SmartDnsResolver::lookup_ip)
+    pub fn addresses(&self) -> Result<Vec<IpAddr>> {
+        self.ensure_answered()?;
+        let addresses = self.ip_addresses();
+        if addresses.is_empty() {
+            return Err(self.no_records());
+        }
+        Ok(addresses)
+    }
+    
+    /// 提取SOA记录的序列号，错误的区分与 [`addresses`](Self::addresses) 相同
+    pub fn soa_serial(&self) -> Result<u32> {
+        self.ensure_answered()?;
+        self.records.iter()
+            .find_map(|record| match &record.value {
+                DnsRecordValue::Soa { serial, .. } => Some(*serial),
+                _ => None,
+            })
+            .ok_or_else(|| self.no_records())
+    }
+    
+    /// 查询成功且响应码为NOERROR
+    fn ensure_answered(&self) -> Result<()> {
+        if !self.success {
+            return Err(DnsError::Server(self.error.clone().unwrap_or_else(|| "query failed".to_string())));
+        }
+        match self.rcode.map(ResponseCode::from) {
+            Some(ResponseCode::NoError) | None => Ok(()),
+            Some(ResponseCode::FormatError) => Err(DnsError::FormatError),
+            Some(ResponseCode::ServerFailure) => Err(DnsError::ServerFailure),
+            Some(ResponseCode::NxDomain) => Err(DnsError::NxDomain),
+            Some(ResponseCode::Refused) => Err(DnsError::Refused),
+            Some(rcode) => Err(DnsError::Server(format!("Response code {} for {}", rcode.name(), self.domain))),
+        }
+    }
+    
+    /// 应答中没有所需类型记录时的错误，附带实际出现的记录类型
+    fn no_records(&self) -> DnsError {
+        let mut answer_types: Vec<String> = Vec::new();
+        for record in &self.records {
+            let rtype = record.record_type.as_str().to_string();
+            if !answer_types.contains(&rtype) {
+                answer_types.push(rtype);
+            }
+        }
+        DnsError::NoRecords { domain: self.domain.clone(), answer_types }
+    }
+    
+    /// 提取IP地址列表（不区分空结果的原因，见 [`addresses`](Self::addresses)）
+    /// 
+    /// A查询只返回IPv4地址，AAAA查询只返回IPv6地址，其他类型的查询返回所有地址
     pub fn ip_addresses(&self) -> Vec<IpAddr> {
         self.records
             .iter()
-            .filter_map(|record| {
-                if let DnsRecordValue::IpAddr(ip) = &record.value {
-                    Some(*ip)
-                } else {
-                    None
-                }
+            .filter_map(|record| match (&record.value, self.record_type) {
+                (DnsRecordValue::IpAddr(ip), DnsRecordType::A) if !ip.is_ipv4() => None,
+                (DnsRecordValue::IpAddr(ip), DnsRecordType::AAAA) if !ip.is_ipv6() => None,
+                (DnsRecordValue::IpAddr(ip), _) => Some(*ip),
+                _ => None,
             })
             .collect()
     }
@@ -142,16 +863,62 @@ impl DnsQueryResponse {
             .collect()
     }
     
-    /// 提取文本列表（用于TXT记录）
+    /// 提取文本列表（用于TXT记录），每条记录的各个字符串按顺序直接拼接，不加分隔符
+    /// 
+    /// 超过255字节的SPF、DKIM记录会被拆成多个字符串，拼接后才是完整的值；需要区分各个字符串时用
+    /// [`txt_chunks`](Self::txt_chunks)
     pub fn texts(&self) -> Vec<String> {
+        self.txt_chunks().into_iter().map(|chunks| chunks.concat()).collect()
+    }
+    
+    /// 提取TXT记录的原始字符串，每条记录一组，保持上游给出的顺序
+    pub fn txt_chunks(&self) -> Vec<Vec<String>> {
         self.records
             .iter()
-            .filter_map(|record| {
-                if let DnsRecordValue::Text(text) = &record.value {
-                    Some(text.clone())
-                } else {
-                    None
-                }
+            .filter_map(|record| match &record.value {
+                DnsRecordValue::Txt(chunks) => Some(chunks.clone()),
+                DnsRecordValue::Text(text) => Some(vec![text.clone()]),
+                _ => None,
+            })
+            .collect()
+    }
+    
+    /// 应答中指定类型的记录
+    pub fn records_of_type(&self, record_type: DnsRecordType) -> Vec<&DnsRecord> {
+        self.records.iter().filter(|record| record.record_type == record_type).collect()
+    }
+    
+    /// 提取SOA记录
+    pub fn soa_records(&self) -> Vec<SoaData> {
+        self.records
+            .iter()
+            .filter_map(|record| match &record.value {
+                DnsRecordValue::Soa { mname, rname, serial, refresh, retry, expire, minimum } => Some(SoaData {
+                    mname: mname.clone(),
+                    rname: rname.clone(),
+                    serial: *serial,
+                    refresh: *refresh,
+                    retry: *retry,
+                    expire: *expire,
+                    minimum: *minimum,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+    
+    /// 提取SRV记录（保持应答中的顺序，按RFC 2782排序后的目标见 [`SmartDnsResolver::resolve_srv`](crate::builder::SmartDnsResolver::resolve_srv)）
+    pub fn srv_records(&self) -> Vec<SrvData> {
+        self.records
+            .iter()
+            .filter_map(|record| match &record.value {
+                DnsRecordValue::Srv { priority, weight, port, target } => Some(SrvData {
+                    priority: *priority,
+                    weight: *weight,
+                    port: *port,
+                    target: target.clone(),
+                }),
+                _ => None,
             })
             .collect()
     }
@@ -212,8 +979,51 @@ impl DnsQueryResponse {
     }
 }
 
+/// 上游查询的分阶段耗时（毫秒），各字段为该阶段结束时相对发送开始的偏移
+/// 
+/// 传输不经历或无法观测的阶段为 `None`，见 [`TransportTiming`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub struct TimingBreakdown {
+    /// 上游主机名解析完成
+    pub upstream_resolve_ms: Option<u64>,
+    /// TCP连接建立
+    pub tcp_connect_ms: Option<u64>,
+    /// TLS握手完成
+    pub tls_handshake_ms: Option<u64>,
+    /// 请求写出
+    pub request_sent_ms: Option<u64>,
+    /// 收到响应首字节
+    pub first_byte_ms: Option<u64>,
+    /// 响应读取完成
+    pub complete_ms: u64,
+    /// 承载查询的HTTP版本（仅DoH）
+    #[serde(default)]
+    pub http_version: Option<HttpVersion>,
+    /// TCP Fast Open时SYN中的数据是否被接受（仅TCP/DoT开启 `tcp_fast_open` 且在Linux上）
+    #[serde(default)]
+    pub tcp_fast_open: Option<bool>,
+}
+
+impl From<TransportTiming> for TimingBreakdown {
+    fn from(timing: TransportTiming) -> Self {
+        let ms = |duration: Duration| duration.as_millis() as u64;
+        Self {
+            upstream_resolve_ms: timing.upstream_resolve.map(ms),
+            tcp_connect_ms: timing.tcp_connect.map(ms),
+            tls_handshake_ms: timing.tls_handshake.map(ms),
+            request_sent_ms: timing.request_sent.map(ms),
+            first_byte_ms: timing.first_byte.map(ms),
+            complete_ms: ms(timing.complete),
+            http_version: timing.http_version,
+            tcp_fast_open: timing.tcp_fast_open,
+        }
+    }
+}
+
 /// DNSSEC验证状态
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub enum DnssecStatus {
     /// 安全 - DNSSEC验证通过
     Secure,
@@ -230,6 +1040,7 @@ pub enum DnssecStatus {
 
 /// DNS记录类型
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub enum DnsRecordType {
     /// A记录 - IPv4地址
     A,
@@ -275,6 +1086,83 @@ pub enum DnsRecordType {
     NSEC3,
 }
 
+impl From<DnsRecordType> for crate::types::RecordType {
+    fn from(record_type: DnsRecordType) -> Self {
+        use crate::types::RecordType;
+        match record_type {
+            DnsRecordType::A => RecordType::A,
+            DnsRecordType::AAAA => RecordType::AAAA,
+            DnsRecordType::CNAME => RecordType::CNAME,
+            DnsRecordType::MX => RecordType::MX,
+            DnsRecordType::TXT => RecordType::TXT,
+            DnsRecordType::NS => RecordType::NS,
+            DnsRecordType::PTR => RecordType::PTR,
+            DnsRecordType::SRV => RecordType::SRV,
+            DnsRecordType::SOA => RecordType::SOA,
+            DnsRecordType::RRSIG => RecordType::RRSIG,
+            DnsRecordType::DNSKEY => RecordType::DNSKEY,
+            DnsRecordType::DS => RecordType::DS,
+            DnsRecordType::NSEC => RecordType::NSEC,
+            DnsRecordType::NSEC3 => RecordType::NSEC3,
+        }
+    }
+}
+
+/// 只有 [`DnsRecordType`] 支持的类型可以转换，其余类型返回 [`DnsError::Parse`]
+impl TryFrom<crate::types::RecordType> for DnsRecordType {
+    type Error = DnsError;
+    
+    fn try_from(record_type: crate::types::RecordType) -> Result<Self> {
+        use crate::types::RecordType;
+        match record_type {
+            RecordType::A => Ok(DnsRecordType::A),
+            RecordType::AAAA => Ok(DnsRecordType::AAAA),
+            RecordType::CNAME => Ok(DnsRecordType::CNAME),
+            RecordType::MX => Ok(DnsRecordType::MX),
+            RecordType::TXT => Ok(DnsRecordType::TXT),
+            RecordType::NS => Ok(DnsRecordType::NS),
+            RecordType::PTR => Ok(DnsRecordType::PTR),
+            RecordType::SRV => Ok(DnsRecordType::SRV),
+            RecordType::SOA => Ok(DnsRecordType::SOA),
+            RecordType::RRSIG => Ok(DnsRecordType::RRSIG),
+            RecordType::DNSKEY => Ok(DnsRecordType::DNSKEY),
+            RecordType::DS => Ok(DnsRecordType::DS),
+            RecordType::NSEC => Ok(DnsRecordType::NSEC),
+            RecordType::NSEC3 => Ok(DnsRecordType::NSEC3),
+            RecordType::Unknown(_) => Err(DnsError::Parse(format!("Unsupported record type: {}", record_type))),
+        }
+    }
+}
+
+impl From<DnsRecordType> for u16 {
+    fn from(record_type: DnsRecordType) -> Self {
+        crate::types::RecordType::from(record_type).into()
+    }
+}
+
+impl TryFrom<u16> for DnsRecordType {
+    type Error = DnsError;
+    
+    fn try_from(code: u16) -> Result<Self> {
+        crate::types::RecordType::from(code).try_into()
+    }
+}
+
+impl fmt::Display for DnsRecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 与 [`RecordType`](crate::types::RecordType) 接受相同的写法：助记符（不区分大小写）或 `TYPE<类型值>`
+impl FromStr for DnsRecordType {
+    type Err = DnsError;
+    
+    fn from_str(value: &str) -> Result<Self> {
+        value.parse::<crate::types::RecordType>()?.try_into()
+    }
+}
+
 impl DnsRecordType {
     /// 获取记录类型的字符串表示
     pub fn as_str(&self) -> &'static str {
@@ -296,27 +1184,17 @@ impl DnsRecordType {
         }
     }
     
-    /// 从字符串解析记录类型
+    /// 从字符串解析记录类型，不支持的写法为 `None`；需要错误原因时使用 [`str::parse`]
     pub fn from_str(s: &str) -> Option<Self> {
-        match s.to_uppercase().as_str() {
-            "A" => Some(Self::A),
-            "AAAA" => Some(Self::AAAA),
-            "CNAME" => Some(Self::CNAME),
-            "MX" => Some(Self::MX),
-            "TXT" => Some(Self::TXT),
-            "NS" => Some(Self::NS),
-            "PTR" => Some(Self::PTR),
-            "SRV" => Some(Self::SRV),
-            "SOA" => Some(Self::SOA),
-            "RRSIG" => Some(Self::RRSIG),
-            "DNSKEY" => Some(Self::DNSKEY),
-            "DS" => Some(Self::DS),
-            "NSEC" => Some(Self::NSEC),
-            "NSEC3" => Some(Self::NSEC3),
-            _ => None,
-        }
+        s.parse().ok()
     }
     
+    /// 所有支持的记录类型
+    pub const ALL: [DnsRecordType; 14] = [
+        Self::A, Self::AAAA, Self::CNAME, Self::MX, Self::TXT, Self::NS, Self::PTR,
+        Self::SRV, Self::SOA, Self::RRSIG, Self::DNSKEY, Self::DS, Self::NSEC, Self::NSEC3,
+    ];
+    
     /// 检查是否为DNSSEC相关记录类型
     pub fn is_dnssec_record(&self) -> bool {
         matches!(self, Self::RRSIG | Self::DNSKEY | Self::DS | Self::NSEC | Self::NSEC3)
@@ -324,7 +1202,8 @@ impl DnsRecordType {
 }
 
 /// DNS记录
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub struct DnsRecord {
     /// 记录名称
     pub name: String,
@@ -340,7 +1219,12 @@ pub struct DnsRecord {
 }
 
 /// DNS记录值
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// 
+/// JSON中以变体名为键（外部标记）：`{"IpAddr": "192.0.2.1"}`、`{"Domain": "example.com"}`、
+/// `{"Txt": ["v=spf1 -all"]}`、`{"Mx": {"priority": 10, "exchange": "mail.example.com"}}`，
+/// `Srv` / `Soa` 同样是以字段名为键的对象。这一形状是对外约定，新增变体不会改变已有变体的形状
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub enum DnsRecordValue {
     /// IP地址（A/AAAA记录）
     IpAddr(IpAddr),
@@ -348,7 +1232,8 @@ pub enum DnsRecordValue {
     /// 域名（CNAME/NS/PTR记录）
     Domain(String),
     
-    /// 文本（TXT记录）
+    /// 已弃用：早期版本的TXT记录值，多个字符串以空格连接，无法还原。解析器不再产生该变体，
+    /// 保留它只为读取旧版本导出的JSON和缓存数据，新代码使用 [`DnsRecordValue::Txt`]
     Text(String),
     
     /// MX记录
@@ -388,6 +1273,142 @@ pub enum DnsRecordValue {
         /// 最小TTL（秒）
         minimum: u32,
     },
+    
+    /// TXT记录的各个字符串（每个不超过255字节），保持上游给出的顺序
+    Txt(Vec<String>),
+}
+
+impl DnsRecordValue {
+    /// 变体的先后，只在比较不同变体时使用
+    fn variant_rank(&self) -> u8 {
+        match self {
+            Self::IpAddr(_) => 0,
+            Self::Domain(_) => 1,
+            Self::Text(_) => 2,
+            Self::Mx { .. } => 3,
+            Self::Srv { .. } => 4,
+            Self::Soa { .. } => 5,
+            Self::Txt(_) => 6,
+        }
+    }
+
+    /// [`RecordSort::ByTypeThenValue`] 中记录值的顺序：IP地址按数值（IPv4在前），域名不区分大小写，
+    /// MX、SRV、SOA按字段依次比较，TXT按字符串依次比较
+    pub(crate) fn canonical_cmp(&self, other: &Self) -> Ordering {
+        let lower = |name: &str| name.to_ascii_lowercase();
+        match (self, other) {
+            (Self::IpAddr(a), Self::IpAddr(b)) => a.cmp(b),
+            (Self::Domain(a), Self::Domain(b)) => lower(a).cmp(&lower(b)),
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+            (Self::Txt(a), Self::Txt(b)) => a.cmp(b),
+            (
+                Self::Mx { priority, exchange },
+                Self::Mx { priority: other_priority, exchange: other_exchange },
+            ) => priority.cmp(other_priority).then_with(|| lower(exchange).cmp(&lower(other_exchange))),
+            (
+                Self::Srv { priority, weight, port, target },
+                Self::Srv { priority: other_priority, weight: other_weight, port: other_port, target: other_target },
+            ) => (priority, weight, port).cmp(&(other_priority, other_weight, other_port))
+                .then_with(|| lower(target).cmp(&lower(other_target))),
+            (
+                Self::Soa { mname, rname, serial, refresh, retry, expire, minimum },
+                Self::Soa { mname: m, rname: r, serial: s, refresh: f, retry: t, expire: e, minimum: n },
+            ) => (lower(mname), lower(rname), serial, refresh, retry, expire, minimum)
+                .cmp(&(lower(m), lower(r), s, f, t, e, n)),
+            _ => self.variant_rank().cmp(&other.variant_rank()),
+        }
+    }
+}
+
+/// 查询响应中记录的规范排序，用于比较不同次查询的结果
+/// 
+/// 不设置时记录保持上游给出的顺序；规范排序不保留别名链顺序（A排在CNAME之前）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RecordSort {
+    /// 先按记录类型编号（A=1、NS=2、CNAME=5、SOA=6、PTR=12、MX=15、TXT=16、AAAA=28、SRV=33），
+    /// 同类型按记录值，再按名称（不区分大小写）和TTL；完全相同的记录保持原有的相对顺序
+    ByTypeThenValue,
+}
+
+impl RecordSort {
+    /// 按该排序比较两条记录
+    pub fn compare(&self, a: &DnsRecord, b: &DnsRecord) -> Ordering {
+        match self {
+            Self::ByTypeThenValue => {
+                let type_code = |record: &DnsRecord| u16::from(crate::types::RecordType::from(record.record_type));
+                type_code(a).cmp(&type_code(b))
+                    .then_with(|| a.value.canonical_cmp(&b.value))
+                    .then_with(|| a.name.to_ascii_lowercase().cmp(&b.name.to_ascii_lowercase()))
+                    .then_with(|| a.ttl.cmp(&b.ttl))
+            }
+        }
+    }
+
+    /// 按该排序稳定地排列记录
+    pub fn sort(&self, records: &mut [DnsRecord]) {
+        records.sort_by(|a, b| self.compare(a, b));
+    }
+}
+
+/// 一组解析出的地址及其有效期，见 [`SmartDnsResolver::resolve_with_ttl`](crate::builder::SmartDnsResolver::resolve_with_ttl)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedAddrs {
+    /// A和AAAA地址，IPv4在前
+    pub addresses: Vec<IpAddr>,
+    /// 这组地址还能使用的时长：地址记录中最小的TTL，命中缓存时已扣除在缓存中经过的时间
+    pub valid_for: Duration,
+    /// 给出地址的应答是否全部来自缓存
+    pub from_cache: bool,
+}
+
+/// SOA记录的字段
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SoaData {
+    /// 主名称服务器
+    pub mname: String,
+    /// 管理员邮箱
+    pub rname: String,
+    /// 序列号
+    pub serial: u32,
+    /// 刷新间隔（秒）
+    pub refresh: u32,
+    /// 重试间隔（秒）
+    pub retry: u32,
+    /// 过期时间（秒）
+    pub expire: u32,
+    /// 最小TTL（秒）
+    pub minimum: u32,
+}
+
+/// SRV记录的字段
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SrvData {
+    /// 优先级，数值越小优先级越高
+    pub priority: u16,
+    /// 权重，用于负载均衡
+    pub weight: u16,
+    /// 服务端口号
+    pub port: u16,
+    /// 目标主机名
+    pub target: String,
+}
+
+/// 记录值的文本形式：TXT为各字符串直接拼接，MX为 `优先级 交换主机`，SRV为 `优先级 权重 端口 目标`，SOA按RFC 1035字段顺序
+impl fmt::Display for DnsRecordValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsRecordValue::IpAddr(ip) => write!(f, "{}", ip),
+            DnsRecordValue::Domain(name) | DnsRecordValue::Text(name) => write!(f, "{}", name),
+            DnsRecordValue::Txt(chunks) => write!(f, "{}", chunks.concat()),
+            DnsRecordValue::Mx { priority, exchange } => write!(f, "{} {}", priority, exchange),
+            DnsRecordValue::Srv { priority, weight, port, target } => {
+                write!(f, "{} {} {} {}", priority, weight, port, target)
+            },
+            DnsRecordValue::Soa { mname, rname, serial, refresh, retry, expire, minimum } => {
+                write!(f, "{} {} {} {} {} {} {}", mname, rname, serial, refresh, retry, expire, minimum)
+            },
+        }
+    }
 }
 
 impl DnsRecord {
@@ -421,13 +1442,236 @@ impl DnsRecord {
         }
     }
     
-    /// 创建TXT记录
+    /// 创建TXT记录，超过255字节的文本按线路格式拆成多个字符串
     pub fn txt(name: impl Into<String>, text: impl Into<String>, ttl: u32) -> Self {
         Self {
             name: name.into(),
             record_type: DnsRecordType::TXT,
-            value: DnsRecordValue::Text(text.into()),
+            value: DnsRecordValue::Txt(crate::dns_response::split_txt(&text.into())),
+            ttl,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_with_ttls(ttls: &[u32]) -> DnsQueryResponse {
+        DnsQueryResponse {
+            query_id: "test".into(),
+            domain: "example.com".to_string(),
+            record_type: DnsRecordType::A,
+            success: true,
+            error: None,
+            records: ttls.iter().enumerate().map(|(i, ttl)| DnsRecord {
+                name: "example.com".to_string(),
+                record_type: DnsRecordType::A,
+                value: DnsRecordValue::IpAddr(IpAddr::from([192, 0, 2, i as u8])),
+                ttl: *ttl,
+            }).collect(),
+            duration_ms: 1,
+            server_used: None,
+            upstream_peer: None,
+            protocol_used: None,
+            dnssec_status: None,
+            dnssec_records: Vec::new(),
+            emergency_mode: false,
+            rcode: Some(0),
+            authenticated_data: false,
+            valid_until: None,
+            timing: None,
+            wire_request: None,
+            wire_response: None,
+            wire_truncated: false,
+            degraded_security: false,
+            records_truncated: false,
+            negative_ttl: None,
+            zone_apex: None,
+            context: None,
+            error_code: None,
+            failure_report: None,
+            selection: None,
+        }
+    }
+
+    #[test]
+    fn test_valid_until_follows_smallest_ttl() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut response = response_with_ttls(&[300, 60, 3600]);
+        assert_eq!(response.min_ttl(), Some(60));
+
+        response.stamp_valid_until(now);
+        assert_eq!(response.valid_until, Some(1_700_000_060));
+        assert_eq!(response.expires_in_at(now), Duration::from_secs(60));
+        assert_eq!(response.expires_in_at(now + Duration::from_secs(45)), Duration::from_secs(15));
+        assert!(!response.is_expired_at(now + Duration::from_secs(59)));
+        assert!(response.is_expired_at(now + Duration::from_secs(60)));
+        assert_eq!(response.expires_in_at(now + Duration::from_secs(600)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_zero_ttl_and_empty_records_are_expired() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut zero = response_with_ttls(&[0, 300]);
+        zero.stamp_valid_until(now);
+        assert_eq!(zero.valid_until, Some(1_700_000_000));
+        assert!(zero.is_expired_at(now));
+
+        let mut empty = response_with_ttls(&[]);
+        empty.stamp_valid_until(now);
+        assert_eq!(empty.min_ttl(), None);
+        assert_eq!(empty.valid_until, None);
+        assert!(empty.is_expired_at(now));
+        assert_eq!(empty.expires_in_at(now), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_wire_bytes_are_base64_in_json() {
+        let mut response = response_with_ttls(&[300]);
+        response.wire_request = Some(vec![0x12, 0x34, 0x01, 0x00]);
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["wire_request"], "EjQBAA==");
+        assert!(json["wire_response"].is_null());
+        
+        let decoded: DnsQueryResponse = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded.wire_request, response.wire_request);
+        assert_eq!(decoded.wire_response, None);
+    }
+
+    #[test]
+    #[cfg(feature = "serde-api")]
+    fn test_request_options_round_trip() {
+        let request = DnsQueryRequest::new("example.com", DnsRecordType::TXT)
+            .with_query_id("q-1")
+            .with_client_address("192.0.2.1")
+            .with_timeout(500)
+            .disable_cache()
+            .with_capture_wire(true)
+            .with_class(QClass::CH)
+            .with_context(QueryContext::for_tenant("team-a").with_tag("env", "prod"));
+        assert!(request.validate().is_ok());
+        assert_eq!(request.timeout(), Some(Duration::from_millis(500)));
+
+        let json: DnsQueryRequest = serde_json::from_str(&serde_json::to_string(&request).unwrap()).unwrap();
+        let config = bincode::config::standard();
+        let encoded = bincode::encode_to_vec(&request, config).unwrap();
+        let (binary, _): (DnsQueryRequest, usize) = bincode::decode_from_slice(&encoded, config).unwrap();
+        for decoded in [json, binary] {
+            assert_eq!(decoded.query_id.as_deref(), Some("q-1"));
+            assert_eq!(decoded.client_address.as_deref(), Some("192.0.2.1"));
+            assert_eq!(decoded.timeout_ms, Some(500));
+            assert!(decoded.disable_cache && decoded.capture_wire);
+            assert_eq!(decoded.qclass, QClass::CH);
+            assert_eq!(decoded.tenant(), Some("team-a"));
+            assert_eq!(decoded.context.unwrap().tags, vec![("env".to_string(), "prod".to_string())]);
+        }
+
+        assert!(matches!(request.clone().with_timeout(0).validate(), Err(DnsError::InvalidConfig(_))));
+        assert!(matches!(DnsQueryRequest::new("", DnsRecordType::A).validate(), Err(DnsError::InvalidConfig(_))));
+        // 不携带OPT记录就无法设置DO位
+        assert!(request.clone().with_dnssec(true).validate().is_ok());
+        assert!(matches!(request.clone().with_edns(false).with_dnssec(true).validate(), Err(DnsError::InvalidConfig(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "serde-api")]
+    fn test_query_ids_format_lazily_and_round_trip_as_strings() {
+        let first = QueryId::generate(QueryIdFormat::Sequence);
+        let second = QueryId::generate(QueryIdFormat::Sequence);
+        assert_ne!(first, second);
+        assert!(!first.is_empty() && first.caller_id().is_none());
+        let (prefix, sequence) = first.to_string().split_once('-').map(|(p, s)| (p.to_string(), s.to_string())).unwrap();
+        assert_eq!(prefix.len(), 8);
+        assert!(u64::from_str_radix(&sequence, 16).is_ok());
+        assert!(second.to_string().starts_with(&prefix));
+
+        let uuid = QueryId::generate(QueryIdFormat::Uuid);
+        assert_eq!(uuid::Uuid::parse_str(&uuid.to_string()).unwrap().get_version_num(), 4);
+
+        assert!(QueryId::default().is_empty());
+        assert_eq!(QueryId::from(""), QueryId::default());
+        let caller = QueryId::from("q-1");
+        assert_eq!(caller.caller_id(), Some("q-1"));
+        assert_eq!(caller, "q-1");
+
+        let config = bincode::config::standard();
+        for id in [caller, first, uuid] {
+            let text = id.to_string();
+            let json: QueryId = serde_json::from_str(&serde_json::to_string(&id).unwrap()).unwrap();
+            let (binary, _): (QueryId, usize) = bincode::decode_from_slice(&bincode::encode_to_vec(&id, config).unwrap(), config).unwrap();
+            assert_eq!(json, text.as_str());
+            assert_eq!(binary, text.as_str());
+        }
+    }
+
+    #[test]
+    fn test_canonical_sort_order_is_documented_and_stable() {
+        let record = |name: &str, record_type: DnsRecordType, value: DnsRecordValue, ttl: u32| DnsRecord {
+            name: name.to_string(),
+            record_type,
+            value,
             ttl,
+        };
+        let ip = |text: &str| DnsRecordValue::IpAddr(text.parse().unwrap());
+        let domain = |text: &str| DnsRecordValue::Domain(text.to_string());
+        let mut records = vec![
+            record("edge.example.net", DnsRecordType::AAAA, ip("2001:db8::1"), 60),
+            record("www.example.com", DnsRecordType::CNAME, domain("edge.example.net"), 300),
+            record("edge.example.net", DnsRecordType::A, ip("192.0.2.20"), 60),
+            record("example.com", DnsRecordType::MX, DnsRecordValue::Mx { priority: 20, exchange: "b.example.com".to_string() }, 300),
+            record("edge.example.net", DnsRecordType::A, ip("192.0.2.3"), 60),
+            record("example.com", DnsRecordType::MX, DnsRecordValue::Mx { priority: 10, exchange: "Z.example.com".to_string() }, 300),
+            record("Edge.example.net", DnsRecordType::A, ip("192.0.2.3"), 30),
+            record("edge.example.net", DnsRecordType::A, ip("192.0.2.3"), 60),
+        ];
+        RecordSort::ByTypeThenValue.sort(&mut records);
+
+        // A(1) < CNAME(5) < MX(15) < AAAA(28)；同类型按值（IP按数值而非字符串），再按名称和TTL
+        let order: Vec<(&str, String, u32)> = records.iter()
+            .map(|record| (record.record_type.as_str(), format!("{:?}", record.value), record.ttl))
+            .collect();
+        assert_eq!(order, vec![
+            ("A", format!("{:?}", ip("192.0.2.3")), 30),
+            ("A", format!("{:?}", ip("192.0.2.3")), 60),
+            ("A", format!("{:?}", ip("192.0.2.3")), 60),
+            ("A", format!("{:?}", ip("192.0.2.20")), 60),
+            ("CNAME", format!("{:?}", domain("edge.example.net")), 300),
+            ("MX", format!("{:?}", DnsRecordValue::Mx { priority: 10, exchange: "Z.example.com".to_string() }), 300),
+            ("MX", format!("{:?}", DnsRecordValue::Mx { priority: 20, exchange: "b.example.com".to_string() }), 300),
+            ("AAAA", format!("{:?}", ip("2001:db8::1")), 60),
+        ]);
+        assert_eq!(records[0].name, "Edge.example.net");
+
+        // 已排好的记录再排一次不变
+        let sorted = serde_json::to_string(&records).unwrap();
+        RecordSort::ByTypeThenValue.sort(&mut records);
+        assert_eq!(serde_json::to_string(&records).unwrap(), sorted);
+    }
+
+    #[test]
+    fn test_record_type_conversions_are_lossless() {
+        use crate::types::RecordType;
+        for record_type in DnsRecordType::ALL {
+            let wire = RecordType::from(record_type);
+            assert!(!matches!(wire, RecordType::Unknown(_)), "{}", record_type);
+            assert_eq!(DnsRecordType::try_from(wire).unwrap(), record_type);
+            assert_eq!(DnsRecordType::try_from(u16::from(record_type)).unwrap(), record_type);
+            assert_eq!(record_type.to_string(), wire.to_string());
+            assert_eq!(record_type.to_string().parse::<DnsRecordType>().unwrap(), record_type);
+            assert_eq!(record_type.as_str().to_lowercase().parse::<DnsRecordType>().unwrap(), record_type);
+            assert_eq!(format!("TYPE{}", u16::from(record_type)).parse::<DnsRecordType>().unwrap(), record_type);
         }
+        // 其余登记的类型和未登记的类型值都不能转换
+        let supported = RecordType::assigned().filter(|rtype| DnsRecordType::try_from(*rtype).is_ok()).count();
+        assert_eq!(supported, DnsRecordType::ALL.len());
+        for code in [0, 41, 64, 257, 65280] {
+            assert!(DnsRecordType::try_from(code).is_err(), "{}", code);
+            assert!(format!("TYPE{}", code).parse::<DnsRecordType>().is_err());
+        }
+        assert!(matches!("CAA".parse::<DnsRecordType>(), Err(DnsError::Parse(_))));
+        assert!("bogus".parse::<DnsRecordType>().is_err());
+        assert_eq!(DnsRecordType::from_str("nsec3"), Some(DnsRecordType::NSEC3));
+        assert_eq!(DnsRecordType::from_str("TYPE999"), None);
     }
-}
\ No newline at end of file
+}
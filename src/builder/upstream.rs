@@ -0,0 +1,243 @@
+//! 类型化的上游构造
+//!
+//! [`Upstream`] 在构造时就检查地址：UDP/TCP只接受 [`SocketAddr`]，DoT的主机名和DoH的URL在
+//! [`Upstream::dot`]、[`Upstream::doh`] 中校验，写错的配置在写错的那一行报错，而不是等到 `build()`。
+//! SNI只能设置在DoT上游上、HTTP方法只能设置在DoH上游上，由类型参数保证。
+//! 字符串形式的 `add_*_upstream` 方法同样经由它加入构建器，两条路径得到的上游规格相同
+
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::transport::doh_url::validate_doh_url;
+use crate::transport::HttpMethod;
+use crate::types::{EcsPolicy, RecordType};
+use crate::upstream_handler::{UpstreamSpec, UpstreamType};
+use crate::utils::{parse_simple_server_address, validate_domain};
+use crate::{DnsError, Result};
+
+/// 上游协议的类型标记，见 [`Upstream`]
+pub mod protocol {
+    /// UDP或TCP
+    #[derive(Debug, Clone, Copy)]
+    pub struct Plain;
+
+    /// DNS over TLS
+    #[derive(Debug, Clone, Copy)]
+    pub struct Tls;
+
+    /// DNS over HTTPS
+    #[derive(Debug, Clone, Copy)]
+    pub struct Https;
+}
+
+use protocol::{Https, Plain, Tls};
+
+/// 构造时已校验地址的上游配置，通过 [`DnsResolverBuilder::add`](crate::builder::DnsResolverBuilder::add) 加入构建器
+///
+/// ```rust,no_run
+/// use rat_quickdns::builder::Upstream;
+/// use rat_quickdns::transport::HttpMethod;
+/// use std::time::Duration;
+///
+/// # fn main() -> rat_quickdns::Result<()> {
+/// let cloudflare = Upstream::doh("cloudflare", "https://cloudflare-dns.com/dns-query")?
+///     .with_method(HttpMethod::GET)
+///     .with_timeout(Duration::from_secs(2));
+/// let quad9 = Upstream::dot("quad9", "9.9.9.9", 853)?.with_sni("dns.quad9.net")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Upstream<P> {
+    spec: UpstreamSpec,
+    _protocol: PhantomData<P>,
+}
+
+impl<P> Upstream<P> {
+    fn new(spec: UpstreamSpec) -> Self {
+        Self { spec, _protocol: PhantomData }
+    }
+
+    /// 设置权重
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.spec.weight = weight;
+        self
+    }
+
+    /// 设置优先层级（0最优先）
+    pub fn with_tier(mut self, tier: u8) -> Self {
+        self.spec.tier = tier;
+        self
+    }
+
+    /// 设置该上游的查询超时，覆盖解析器的默认超时
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.spec.timeout = Some(timeout);
+        self
+    }
+
+    /// 设置向该上游发送客户端子网（ECS）的方式
+    pub fn with_ecs_policy(mut self, ecs_policy: EcsPolicy) -> Self {
+        self.spec.ecs_policy = ecs_policy;
+        self
+    }
+
+    /// 只把这些记录类型的查询发往该上游，其他类型的查询选择上游时跳过它
+    pub fn with_record_types(mut self, record_types: impl IntoIterator<Item = RecordType>) -> Self {
+        self.spec.record_types = Some(record_types.into_iter().collect());
+        self
+    }
+
+    /// 添加一个标签（如 `provider`、`datacenter`），只作为元数据随上游状态和指标输出，不影响查询
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.spec.labels.insert(key.into(), value.into());
+        self
+    }
+
+    /// 构造出的上游规格
+    pub fn spec(&self) -> &UpstreamSpec {
+        &self.spec
+    }
+
+    /// 取出上游规格，用于设置本类型没有提供的选项后交给 `add_upstream`
+    pub fn into_spec(self) -> UpstreamSpec {
+        self.spec
+    }
+}
+
+impl<P> From<Upstream<P>> for UpstreamSpec {
+    fn from(upstream: Upstream<P>) -> Self {
+        upstream.spec
+    }
+}
+
+impl Upstream<Plain> {
+    /// UDP上游
+    pub fn udp(name: impl Into<String>, address: SocketAddr) -> Self {
+        Self::new(UpstreamSpec::udp(name.into(), plain_server(address)))
+    }
+
+    /// TCP上游
+    pub fn tcp(name: impl Into<String>, address: SocketAddr) -> Self {
+        Self::new(UpstreamSpec::tcp(name.into(), plain_server(address)))
+    }
+
+    /// 字符串形式的UDP/TCP地址（IP、`IP:端口` 或主机名），原样保留在规格中
+    pub(crate) fn parse_plain(transport_type: UpstreamType, name: String, server: String) -> Result<Self> {
+        let (host, _) = parse_simple_server_address(&server, 53);
+        validate_domain(&host).map_err(|e| invalid_address(&name, e))?;
+        let spec = match transport_type {
+            UpstreamType::Tcp => UpstreamSpec::tcp(name, server),
+            _ => UpstreamSpec::udp(name, server),
+        };
+        Ok(Self::new(spec))
+    }
+}
+
+impl Upstream<Tls> {
+    /// DoT上游，`host` 为主机名或IP地址，主机名同时用作SNI
+    pub fn dot(name: impl Into<String>, host: &str, port: u16) -> Result<Self> {
+        let name = name.into();
+        validate_domain(host).map_err(|e| invalid_address(&name, e))?;
+        if port == 0 {
+            return Err(DnsError::InvalidConfig(format!("Port for upstream '{}' cannot be 0", name)));
+        }
+        let server = match (port, host.contains(':')) {
+            (853, _) => host.to_string(),
+            (_, true) => format!("[{}]:{}", host, port),
+            (_, false) => format!("{}:{}", host, port),
+        };
+        Ok(Self::new(UpstreamSpec::dot(name, server)))
+    }
+
+    /// 设置握手使用的SNI和证书校验名称，用于以IP地址连接、证书签给另一个名称的服务器
+    pub fn with_sni(mut self, server_name: impl Into<String>) -> Result<Self> {
+        let server_name = server_name.into();
+        validate_domain(&server_name).map_err(|e| DnsError::InvalidConfig(format!(
+            "Invalid TLS server name for upstream '{}': {}", self.spec.name, e
+        )))?;
+        self.spec.tls_server_name = Some(server_name);
+        Ok(self)
+    }
+
+    /// 字符串形式的DoT地址（主机名、`主机:端口`），原样保留在规格中
+    pub(crate) fn parse_dot(name: String, server: String) -> Result<Self> {
+        let (host, port) = parse_simple_server_address(&server, 853);
+        Self::dot(name.clone(), &host, port)?;
+        Ok(Self::new(UpstreamSpec::dot(name, server)))
+    }
+}
+
+impl Upstream<Https> {
+    /// DoH上游，`url` 必须是带主机名的 `https://` URL，接受字符串或 [`url::Url`]
+    pub fn doh(name: impl Into<String>, url: impl AsRef<str>) -> Result<Self> {
+        let name = name.into();
+        let url = url.as_ref();
+        validate_doh_url(url, &[])?;
+        Ok(Self::new(UpstreamSpec::doh(name, url.to_string())))
+    }
+
+    /// 设置DoH请求使用的HTTP方法（默认POST）
+    pub fn with_method(mut self, method: HttpMethod) -> Self {
+        self.spec.doh_method = method;
+        self
+    }
+}
+
+/// UDP/TCP规格中的地址：默认端口时只写IP，与字符串写法 `"8.8.8.8"` 得到的规格相同
+fn plain_server(address: SocketAddr) -> String {
+    if address.port() == 53 {
+        address.ip().to_string()
+    } else {
+        address.to_string()
+    }
+}
+
+fn invalid_address(name: &str, error: DnsError) -> DnsError {
+    DnsError::InvalidConfig(format!("Invalid address for upstream '{}': {}", name, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bad_addresses_fail_at_construction() {
+        assert!(Upstream::doh("doh", "not a url").is_err());
+        assert!(Upstream::doh("doh", "http://dns.example/dns-query").is_err());
+        assert!(Upstream::dot("dot", "", 853).is_err());
+        assert!(Upstream::dot("dot", "dns.example", 0).is_err());
+        assert!(Upstream::dot("dot", "9.9.9.9", 853).unwrap().with_sni("bad name").is_err());
+    }
+
+    #[test]
+    fn test_typed_servers_match_string_forms() {
+        let udp = Upstream::udp("udp", "8.8.8.8:53".parse().unwrap());
+        assert_eq!(udp.spec().server, "8.8.8.8");
+        let tcp = Upstream::tcp("tcp", "[2001:db8::1]:5353".parse().unwrap());
+        assert_eq!(tcp.spec().server, "[2001:db8::1]:5353");
+        assert_eq!(tcp.spec().transport_type, UpstreamType::Tcp);
+        assert_eq!(Upstream::dot("dot", "dns.example", 853).unwrap().spec().server, "dns.example");
+        assert_eq!(Upstream::dot("dot", "2001:db8::1", 8853).unwrap().spec().server, "[2001:db8::1]:8853");
+    }
+
+    #[test]
+    fn test_chained_options_land_in_spec() {
+        let url = url::Url::parse("https://dns.example/dns-query").unwrap();
+        let spec = Upstream::doh("doh", url)
+            .unwrap()
+            .with_method(HttpMethod::GET)
+            .with_weight(3)
+            .with_tier(1)
+            .with_timeout(Duration::from_millis(800))
+            .with_record_types([RecordType::A, RecordType::AAAA])
+            .with_label("provider", "internal")
+            .into_spec();
+        assert_eq!(spec.doh_method, HttpMethod::GET);
+        assert_eq!((spec.weight, spec.tier), (3, 1));
+        assert_eq!(spec.timeout, Some(Duration::from_millis(800)));
+        assert_eq!(spec.record_types, Some(vec![RecordType::A, RecordType::AAAA]));
+        assert_eq!(spec.labels.get("provider").map(String::as_str), Some("internal"));
+    }
+}
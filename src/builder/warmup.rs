@@ -0,0 +1,180 @@
+//! 缓存预热
+//!
+//! 启动时按已知的热门域名列表预先查询，让缓存和智能决策的性能指标在第一个用户请求到来前就绪。
+//! 预热列表每行一个条目，格式为 `domain[,type]`，类型缺省为A；空行和以 `#` 开头的行被忽略。
+
+use std::path::Path;
+use std::time::Duration;
+
+use super::types::DnsRecordType;
+use crate::error::{DnsError, Result};
+
+/// 预热选项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WarmOptions {
+    /// 同时进行的查询数上限，必须大于0
+    pub max_concurrency: usize,
+    /// 某个条目查询失败后是否继续预热其余条目
+    pub continue_on_error: bool,
+    /// 整个预热的时限，到期后尚未完成的查询被取消，`None` 表示不限
+    pub deadline: Option<Duration>,
+}
+
+impl Default for WarmOptions {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 8,
+            continue_on_error: true,
+            deadline: None,
+        }
+    }
+}
+
+/// 预热结果汇总
+#[derive(Debug, Clone, Default)]
+pub struct WarmReport {
+    /// 已发起查询的条目数（包括到期时被取消的）
+    pub attempted: usize,
+    /// 查询成功的条目数
+    pub succeeded: usize,
+    /// 查询失败的条目及其错误
+    pub failed: Vec<(String, DnsError)>,
+    /// 因到期或出错停止而没有发起查询的条目数
+    pub skipped: usize,
+    /// 是否因到期而提前结束
+    pub deadline_exceeded: bool,
+    /// 预热耗时
+    pub elapsed: Duration,
+}
+
+impl WarmReport {
+    /// 已发起但在到期时被取消的查询数
+    pub fn cancelled(&self) -> usize {
+        self.attempted - self.succeeded - self.failed.len()
+    }
+}
+
+/// 解析预热列表，记录类型未知时返回带行号的错误
+pub fn parse_warmup_list(text: &str) -> Result<Vec<(String, DnsRecordType)>> {
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (domain, record_type) = match line.split_once(',') {
+            Some((domain, record_type)) => {
+                let record_type = DnsRecordType::from_str(record_type.trim()).ok_or_else(|| DnsError::InvalidConfig(
+                    format!("Warmup list line {}: unknown record type '{}'", index + 1, record_type.trim())
+                ))?;
+                (domain.trim(), record_type)
+            }
+            None => (line, DnsRecordType::A),
+        };
+        if domain.is_empty() {
+            return Err(DnsError::InvalidConfig(format!("Warmup list line {}: missing domain", index + 1)));
+        }
+        entries.push((domain.to_string(), record_type));
+    }
+    Ok(entries)
+}
+
+/// 读取并解析预热列表文件
+pub fn load_warmup_list(path: &Path) -> Result<Vec<(String, DnsRecordType)>> {
+    let text = std::fs::read_to_string(path).map_err(|e| DnsError::Config(
+        format!("Failed to read warmup list {}: {}", path.display(), e)
+    ))?;
+    parse_warmup_list(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{DnsResolverBuilder, QueryStrategy};
+    use crate::builder::resolver::CACHE_SOURCE;
+    use crate::builder::types::DnsQueryRequest;
+    use crate::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    fn names(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("host{}.warm.test", i)).collect()
+    }
+
+    fn scripted(count: usize, latency: Duration) -> MockTransport {
+        names(count).iter().enumerate().fold(MockTransport::new().with_latency(latency), |mock, (i, name)| {
+            mock.with_a(name, &[Ipv4Addr::new(192, 0, 2, i as u8)], 300)
+        })
+    }
+
+    async fn resolver(mock: MockTransport) -> crate::builder::SmartDnsResolver {
+        DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .with_cache(true)
+            .add_mock_upstream("mock", mock)
+            .unwrap()
+            .build()
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_warmup_list() {
+        let entries = parse_warmup_list("# 热门域名\nexample.com\n\n  mail.example.com , mx \nexample.org,AAAA\n").unwrap();
+        assert_eq!(entries, vec![
+            ("example.com".to_string(), DnsRecordType::A),
+            ("mail.example.com".to_string(), DnsRecordType::MX),
+            ("example.org".to_string(), DnsRecordType::AAAA),
+        ]);
+
+        let error = parse_warmup_list("example.com\nexample.org,BOGUS").unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+        assert!(parse_warmup_list(",A").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_warm_cache_bounds_concurrency_and_fills_cache() {
+        let mock = scripted(10, Duration::from_millis(20));
+        let resolver = resolver(mock.clone()).await;
+        let mut entries: Vec<_> = names(10).into_iter().map(|name| (name, DnsRecordType::A)).collect();
+        entries.push(("missing.warm.test".to_string(), DnsRecordType::A));
+
+        let options = WarmOptions { max_concurrency: 3, ..WarmOptions::default() };
+        let report = resolver.warm_cache(entries, options).await.unwrap();
+        assert_eq!((report.attempted, report.succeeded, report.skipped), (11, 10, 0));
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "missing.warm.test");
+        assert!(!report.deadline_exceeded);
+        assert!(mock.max_in_flight() <= 3 && mock.max_in_flight() > 1, "{}", mock.max_in_flight());
+
+        mock.clear_calls();
+        for name in names(10) {
+            let response = resolver.query(DnsQueryRequest::new(name, DnsRecordType::A)).await.unwrap();
+            assert_eq!(response.server_used.as_deref(), Some(CACHE_SOURCE));
+        }
+        assert_eq!(mock.call_count(), 0);
+        assert!(resolver.warm_cache(Vec::new(), WarmOptions { max_concurrency: 0, ..WarmOptions::default() }).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_warm_cache_stops_at_deadline_and_on_error() {
+        let mock = scripted(6, Duration::from_millis(200));
+        let resolver = resolver(mock.clone()).await;
+        let entries: Vec<_> = names(6).into_iter().map(|name| (name, DnsRecordType::A)).collect();
+
+        let options = WarmOptions { max_concurrency: 2, continue_on_error: true, deadline: Some(Duration::from_millis(50)) };
+        let report = resolver.warm_cache(entries, options).await.unwrap();
+        assert!(report.deadline_exceeded);
+        assert_eq!((report.attempted, report.succeeded, report.cancelled(), report.skipped), (2, 0, 2, 4));
+        assert!(report.elapsed < Duration::from_millis(200));
+        // 到期后不再发起新的查询
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(mock.call_count(), 2);
+
+        let resolver = self::resolver(scripted(6, Duration::ZERO)).await;
+        let mut entries: Vec<_> = names(6).into_iter().map(|name| (name, DnsRecordType::A)).collect();
+        entries.insert(0, ("missing.warm.test".to_string(), DnsRecordType::A));
+        let options = WarmOptions { max_concurrency: 1, continue_on_error: false, deadline: None };
+        let report = resolver.warm_cache(entries, options).await.unwrap();
+        assert_eq!((report.attempted, report.succeeded, report.failed.len(), report.skipped), (1, 0, 1, 6));
+    }
+}
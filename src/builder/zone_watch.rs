@@ -0,0 +1,245 @@
+//! 区域变更监视
+//!
+//! 定期不经缓存查询区域的SOA记录，序列号增大（按RFC 1982序列号算术，允许回绕）时产生一个变更事件，
+//! 供下游据此清理自己的缓存。序列号变小通常是落后的从服务器给出的旧答案，记录警告后忽略。
+//! 监视没有后台任务：轮询只在消费方等待下一个事件时进行，丢弃流即停止。
+
+use std::sync::Arc;
+use std::time::Duration;
+use crate::runtime;
+use crate::time::SystemTime;
+
+use futures::Stream;
+
+use crate::error::{DnsError, Result};
+use crate::{dns_debug, dns_info, dns_warn};
+use super::resolver::SmartDnsResolver;
+
+/// 连续查询失败时退避间隔的上限（轮询间隔更长时以轮询间隔为准）
+const MAX_FAILURE_BACKOFF: Duration = Duration::from_secs(600);
+
+/// 区域SOA序列号的一次变化
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoaChange {
+    /// 区域名称
+    pub zone: String,
+    /// 变化前的序列号
+    pub old_serial: u32,
+    /// 变化后的序列号
+    pub new_serial: u32,
+    /// 观察到变化的时间
+    pub observed_at: SystemTime,
+}
+
+/// 按RFC 1982序列号算术判断 `new` 是否比 `old` 新
+///
+/// 差值恰为2^31时比较无定义，按“不更新”处理
+pub fn serial_is_newer(new: u32, old: u32) -> bool {
+    let distance = new.wrapping_sub(old);
+    distance != 0 && distance < 1 << 31
+}
+
+/// 基于 [`SmartDnsResolver`] 的区域变更监视器
+#[derive(Clone)]
+pub struct ZoneWatcher {
+    resolver: Arc<SmartDnsResolver>,
+}
+
+impl ZoneWatcher {
+    /// 创建监视器
+    pub fn new(resolver: Arc<SmartDnsResolver>) -> Self {
+        Self { resolver }
+    }
+
+    /// 不经缓存查询区域当前的SOA序列号，见 [`SmartDnsResolver::get_zone_serial`]
+    pub async fn get_zone_serial(&self, zone: &str) -> Result<u32> {
+        self.resolver.get_zone_serial(zone).await
+    }
+
+    /// 每隔 `interval` 轮询一次区域的SOA序列号，序列号增大时产生 [`SoaChange`]
+    ///
+    /// 第一次成功的查询只记录基准序列号。查询连续失败时等待时间从 `interval` 起逐次加倍，
+    /// 最长10分钟（`interval` 更长时为 `interval`），成功后恢复原间隔。`interval` 为零时返回
+    /// [`DnsError::InvalidConfig`]
+    pub fn watch_zone(&self, zone: &str, interval: Duration) -> Result<impl Stream<Item = SoaChange> + Send + 'static> {
+        if interval.is_zero() {
+            return Err(DnsError::InvalidConfig("Zone watch interval must be greater than zero".to_string()));
+        }
+        let state = WatchState {
+            resolver: self.resolver.clone(),
+            zone: zone.to_string(),
+            interval,
+            serial: None,
+            failures: 0,
+            first_poll: true,
+        };
+        dns_info!("开始监视区域 {} 的SOA序列号，间隔 {:?}", zone, interval);
+        Ok(futures::stream::unfold(state, |mut state| async move {
+            let change = state.next_change().await;
+            Some((change, state))
+        }))
+    }
+}
+
+/// 监视流在两次事件之间保留的状态
+struct WatchState {
+    resolver: Arc<SmartDnsResolver>,
+    zone: String,
+    interval: Duration,
+    /// 最近一次观察到的序列号，尚未成功查询时为 `None`
+    serial: Option<u32>,
+    /// 连续失败次数
+    failures: u32,
+    first_poll: bool,
+}
+
+impl WatchState {
+    /// 轮询直到序列号增大
+    async fn next_change(&mut self) -> SoaChange {
+        loop {
+            if self.first_poll {
+                self.first_poll = false;
+            } else {
+                runtime::sleep(self.delay()).await;
+            }
+
+            let serial = match self.resolver.get_zone_serial(&self.zone).await {
+                Ok(serial) => serial,
+                Err(e) => {
+                    self.failures = self.failures.saturating_add(1);
+                    dns_warn!("查询区域 {} 的SOA失败（连续 {} 次），{:?} 后重试: {}", self.zone, self.failures, self.delay(), e);
+                    continue;
+                }
+            };
+            self.failures = 0;
+
+            match self.serial {
+                None => {
+                    dns_debug!("区域 {} 的基准SOA序列号: {}", self.zone, serial);
+                    self.serial = Some(serial);
+                }
+                Some(old_serial) if serial_is_newer(serial, old_serial) => {
+                    self.serial = Some(serial);
+                    dns_info!("区域 {} 的SOA序列号变化: {} -> {}", self.zone, old_serial, serial);
+                    return SoaChange {
+                        zone: self.zone.clone(),
+                        old_serial,
+                        new_serial: serial,
+                        observed_at: SystemTime::now(),
+                    };
+                }
+                Some(old_serial) if serial != old_serial => {
+                    dns_warn!("区域 {} 的SOA序列号 {} 不比 {} 新，忽略", self.zone, serial, old_serial);
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// 下一次查询前的等待时间
+    fn delay(&self) -> Duration {
+        failure_backoff(self.interval, self.failures)
+    }
+}
+
+/// 连续失败 `failures` 次后下一次轮询前的等待时间：从 `interval` 起逐次加倍，最长10分钟（`interval` 更长时为 `interval`）
+pub(super) fn failure_backoff(interval: Duration, failures: u32) -> Duration {
+    if failures == 0 {
+        return interval;
+    }
+    let backoff = interval.saturating_mul(1 << failures.min(16));
+    backoff.min(MAX_FAILURE_BACKOFF.max(interval))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{DnsResolverBuilder, QueryStrategy};
+    use crate::dns_response::DnsResponseBuilder;
+    use crate::transport::mock::MockTransport;
+    use crate::types::{QClass, RecordType, Response};
+    use futures::StreamExt;
+
+    const ZONE: &str = "example.com";
+
+    fn soa(serial: u32) -> Response {
+        DnsResponseBuilder::new()
+            .add_query(ZONE.to_string(), RecordType::SOA, QClass::IN)
+            .add_soa_answer(
+                ZONE.to_string(),
+                300,
+                "ns1.example.com".to_string(),
+                "hostmaster.example.com".to_string(),
+                serial,
+                7200,
+                900,
+                1209600,
+                300,
+            )
+            .build()
+    }
+
+    async fn resolver(mock: MockTransport) -> Arc<SmartDnsResolver> {
+        let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .with_retry_count(0)
+            .with_query_history(256)
+            .add_mock_upstream("模拟DNS", mock)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        Arc::new(resolver)
+    }
+
+    #[test]
+    fn test_serial_arithmetic_wraps() {
+        assert!(serial_is_newer(2, 1));
+        assert!(!serial_is_newer(1, 2));
+        assert!(!serial_is_newer(7, 7));
+        assert!(serial_is_newer(5, u32::MAX - 10));
+        assert!(!serial_is_newer(u32::MAX - 10, 5));
+        assert!(!serial_is_newer(1 << 31, 0));
+    }
+
+    #[tokio::test]
+    async fn test_serial_changes_are_streamed() {
+        let mock = MockTransport::new().with_response(ZONE, RecordType::SOA, soa(u32::MAX - 1));
+        let handle = mock.clone();
+        let watcher = ZoneWatcher::new(resolver(mock).await);
+        assert_eq!(watcher.get_zone_serial(ZONE).await.unwrap(), u32::MAX - 1);
+
+        let changes = watcher.watch_zone(ZONE, Duration::from_millis(20)).unwrap();
+        futures::pin_mut!(changes);
+
+        // 序列号不变时没有事件；等待超时不会丢失流的进度
+        assert!(tokio::time::timeout(Duration::from_millis(100), changes.next()).await.is_err());
+
+        // 回绕：2^32-2 -> 3 是更新
+        handle.set_response(ZONE, RecordType::SOA, soa(3));
+        let change = changes.next().await.unwrap();
+        assert_eq!((change.old_serial, change.new_serial), (u32::MAX - 1, 3));
+
+        // 变小的序列号被忽略，之后的增大照常报告
+        handle.set_response(ZONE, RecordType::SOA, soa(2));
+        assert!(tokio::time::timeout(Duration::from_millis(100), changes.next()).await.is_err());
+        handle.set_response(ZONE, RecordType::SOA, soa(4));
+        let change = changes.next().await.unwrap();
+        assert_eq!((change.old_serial, change.new_serial), (3, 4));
+    }
+
+    #[tokio::test]
+    async fn test_failures_back_off() {
+        let mock = MockTransport::new().with_response(ZONE, RecordType::SOA, soa(1));
+        mock.set_healthy(false);
+        let resolver = resolver(mock).await;
+        let watcher = ZoneWatcher::new(resolver.clone());
+
+        let changes = watcher.watch_zone(ZONE, Duration::from_millis(10)).unwrap();
+        futures::pin_mut!(changes);
+        assert!(tokio::time::timeout(Duration::from_millis(400), changes.next()).await.is_err());
+        // 固定10毫秒间隔会查询约40次；退避后等待依次为20、40、80、160毫秒
+        let polls = resolver.queries_for_domain(ZONE).len();
+        assert!((2..=8).contains(&polls), "{} polls", polls);
+    }
+}
@@ -4,9 +4,15 @@
 //! 不提供任何"贴心"的默认值或自动修复功能。
 
 pub mod strict;
+pub mod system_config;
 
 pub use strict::{
     StrictDnsConfig,
     StrictConfigBuilder,
     ConfigError,
-};
\ No newline at end of file
+    ConfigIssue,
+    ConfigIssueKind,
+    ConfigSeverity,
+};
+
+pub use system_config::{SearchDomains, SystemResolvConf};
@@ -6,6 +6,15 @@
 use std::time::Duration;
 use serde::{Deserialize, Serialize};
 use crate::builder::strategy::QueryStrategy;
+use crate::builder::LoggerInitStrategy;
+use crate::builder::adaptive_timeout::AdaptiveTimeoutConfig;
+use crate::resolver::health::{ProbeConfig, MAX_UNAVAILABLE_DURATION};
+use crate::resolver::quarantine::TlsQuarantineConfig;
+use crate::resolver::rotation::RotationMode;
+use crate::transport::RecordLimits;
+use crate::transport::doh_url::validate_doh_url;
+use crate::types::{EcsPolicy, UpstreamFeatures};
+use crate::upstream_handler::{check_label_key, check_upstream_labels, check_upstream_name, UpstreamLabels, UpstreamType, ENCRYPTED_POOL_SIZE, PLAIN_POOL_SIZE};
 
 /// 严格DNS配置错误类型
 #[derive(Debug, thiserror::Error)]
@@ -31,6 +40,83 @@ pub enum ConfigError {
     /// 无效的端口号
     #[error("Invalid port: {0}")]
     InvalidPort(String),
+    /// 构建或验证严格配置时发现的全部问题
+    #[error("{} configuration issue(s): {}", .0.len(), .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    Multiple(Vec<ConfigIssue>),
+}
+
+impl ConfigError {
+    /// [`ConfigError::Multiple`] 中的问题，其他错误为空
+    pub fn issues(&self) -> &[ConfigIssue] {
+        match self {
+            Self::Multiple(issues) => issues,
+            _ => &[],
+        }
+    }
+}
+
+/// 配置问题的类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ConfigIssueKind {
+    /// 缺少必需的配置项
+    MissingRequired,
+    /// 配置项的值无效
+    InvalidValue,
+    /// 多个配置项之间互相矛盾或组合起来没有意义
+    Inconsistent,
+    /// 配置项需要的cargo特性在本次构建中没有启用
+    FeatureDisabled,
+}
+
+/// 配置问题的严重程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ConfigSeverity {
+    /// 配置可以使用，但很可能不是本意
+    Warning,
+    /// 配置无法使用
+    Error,
+}
+
+/// 严格配置中的一个问题
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// 问题所在的配置项（跨字段的问题取主要的一项，上游写作 `upstreams[序号]`）
+    pub field: String,
+    /// 问题类别
+    pub kind: ConfigIssueKind,
+    /// 问题说明
+    pub message: String,
+    severity: ConfigSeverity,
+}
+
+impl ConfigIssue {
+    fn error(field: impl Into<String>, kind: ConfigIssueKind, message: impl Into<String>) -> Self {
+        Self { field: field.into(), kind, message: message.into(), severity: ConfigSeverity::Error }
+    }
+    
+    fn warning(field: impl Into<String>, kind: ConfigIssueKind, message: impl Into<String>) -> Self {
+        Self { field: field.into(), kind, message: message.into(), severity: ConfigSeverity::Warning }
+    }
+    
+    /// 严重程度：[`StrictConfigBuilder::build_lenient`] 只在有 `Error` 时失败
+    pub fn severity(&self) -> ConfigSeverity {
+        self.severity
+    }
+    
+    /// 是否是错误级别的问题
+    pub fn is_error(&self) -> bool {
+        self.severity == ConfigSeverity::Error
+    }
+}
+
+impl std::fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            ConfigSeverity::Warning => "warning",
+            ConfigSeverity::Error => "error",
+        };
+        write!(f, "[{}] {}: {}", severity, self.field, self.message)
+    }
 }
 
 /// 上游服务器规格
@@ -44,6 +130,30 @@ pub struct UpstreamSpec {
     pub weight: u32,
     /// 是否启用
     pub enabled: bool,
+    /// 名称（可选，未设置时由协议和地址生成）
+    #[serde(default)]
+    pub name: Option<String>,
+    /// 向该上游发送客户端子网的方式（未配置时原样发送）
+    #[serde(default)]
+    pub ecs_policy: EcsPolicy,
+    /// 优先层级，数值越小越优先（未配置时为0）；只有更优先的层级没有可用上游时才使用
+    #[serde(default)]
+    pub tier: u8,
+    /// 是否携带EDNS OPT记录（未配置时沿用解析器的设置）
+    #[serde(default)]
+    pub edns: Option<bool>,
+    /// 是否设置DNSSEC OK位（未配置时不设置），不能与 `edns = false` 同时配置
+    #[serde(default)]
+    pub dnssec_do: Option<bool>,
+    /// 是否随机化查询名的大小写（未配置时不随机化）
+    #[serde(default)]
+    pub case_randomization: Option<bool>,
+    /// 附加的URL查询参数（仅DoH），追加在地址原有的查询串之后
+    #[serde(default)]
+    pub query_params: Vec<(String, String)>,
+    /// 上游标签（如 `provider = "internal"`），只作为元数据随状态、事件和指标输出，不影响查询
+    #[serde(default)]
+    pub labels: UpstreamLabels,
 }
 
 /// 严格DNS配置 - 强制用户明确每个配置项
@@ -67,7 +177,7 @@ pub struct StrictDnsConfig {
     pub port: u16,
     /// 并发查询数（必须明确指定）
     pub concurrent_queries: usize,
-    /// 缓冲区大小（必须明确指定）
+    /// 缓冲区大小（必须明确指定）：UDP接收缓冲区和声明的EDNS载荷大小，也是各传输接受的响应长度上限
     pub buffer_size: usize,
     /// 上游服务器列表（必须明确配置）
     pub upstreams: Vec<UpstreamSpec>,
@@ -75,6 +185,54 @@ pub struct StrictDnsConfig {
     pub enable_stats: bool,
     /// 应急模式阈值（必须明确指定）
     pub emergency_threshold: f64,
+    /// 响应TTL下限（可选，未设置时不抬高TTL）
+    #[serde(default)]
+    pub min_ttl: Option<Duration>,
+    /// 响应TTL上限（可选，未设置时不压低TTL）
+    #[serde(default)]
+    pub max_ttl: Option<Duration>,
+    /// 含TTL为0记录的响应是否按1秒缓存（默认为false，不缓存）
+    #[serde(default)]
+    pub cache_zero_ttl: bool,
+    /// 是否接受没有问题段、但回答记录明确属于本查询的响应（默认为false，拒绝）
+    #[serde(default)]
+    pub accept_questionless_responses: bool,
+    /// 单个响应的回答记录数、记录总数和CNAME链跳数上限及超出时的处理方式（默认512/1024/16，截断）
+    #[serde(default)]
+    pub record_limits: RecordLimits,
+    /// 因超出记录数上限被截断的响应是否写入缓存（默认为false，不缓存）
+    #[serde(default)]
+    pub cache_truncated_responses: bool,
+    /// 日志初始化策略（可选，未设置时沿用构造器的策略）
+    #[serde(default)]
+    pub logger_init_strategy: Option<LoggerInitStrategy>,
+    /// 同一服务器重复配置时保留权重较高的一项（默认为false，重复配置视为错误）
+    #[serde(default)]
+    pub dedup_upstreams: bool,
+    /// 返回A/AAAA记录时的排列方式（默认为 `None`，保持上游顺序）
+    #[serde(default)]
+    pub record_rotation: RotationMode,
+    /// 缓存过期后仍可先行返回旧答案的时长（可选，未设置时过期即向上游查询）
+    #[serde(default)]
+    pub revalidate_window: Option<Duration>,
+    /// 否定应答推翻近期肯定答案时向另一个上游核实的时长窗口（可选，未设置时不核实）
+    #[serde(default)]
+    pub nxdomain_protection_window: Option<Duration>,
+    /// 主动探测上游时发送的查询（启用上游监控且有备用层级时必须配置，没有默认域名）
+    #[serde(default)]
+    pub health_probe: Option<ProbeConfig>,
+    /// 上游超过该时长没有查询或探测时主动探测（可选，需要启用上游监控和健康探测，且大于监控间隔）
+    #[serde(default)]
+    pub max_upstream_silence: Option<Duration>,
+    /// 加密上游握手失败时的隔离时长（可选，未设置时不隔离）
+    #[serde(default)]
+    pub tls_quarantine: Option<TlsQuarantineConfig>,
+    /// 按观测延迟计算各上游单次发送超时（可选，未设置时使用配置的超时，需要启用统计）
+    #[serde(default)]
+    pub adaptive_timeout: Option<AdaptiveTimeoutConfig>,
+    /// 作为OpenMetrics上游指标标签输出的上游标签键（可选，未设置时标签不进入指标）
+    #[serde(default)]
+    pub metrics_upstream_labels: Vec<String>,
 }
 
 /// 严格配置构建器 - 强制用户明确每个配置项
@@ -92,6 +250,22 @@ pub struct StrictConfigBuilder {
     upstreams: Vec<UpstreamSpec>,
     enable_stats: Option<bool>,
     emergency_threshold: Option<f64>,
+    min_ttl: Option<Duration>,
+    max_ttl: Option<Duration>,
+    cache_zero_ttl: bool,
+    accept_questionless_responses: bool,
+    record_limits: RecordLimits,
+    cache_truncated_responses: bool,
+    logger_init_strategy: Option<LoggerInitStrategy>,
+    dedup_upstreams: bool,
+    record_rotation: RotationMode,
+    revalidate_window: Option<Duration>,
+    nxdomain_protection_window: Option<Duration>,
+    health_probe: Option<ProbeConfig>,
+    max_upstream_silence: Option<Duration>,
+    tls_quarantine: Option<TlsQuarantineConfig>,
+    adaptive_timeout: Option<AdaptiveTimeoutConfig>,
+    metrics_upstream_labels: Vec<String>,
 }
 
 impl StrictConfigBuilder {
@@ -113,6 +287,22 @@ impl StrictConfigBuilder {
             upstreams: Vec::new(),
             enable_stats: None,
             emergency_threshold: None,
+            min_ttl: None,
+            max_ttl: None,
+            cache_zero_ttl: false,
+            accept_questionless_responses: false,
+            record_limits: RecordLimits::default(),
+            cache_truncated_responses: false,
+            logger_init_strategy: None,
+            dedup_upstreams: false,
+            record_rotation: RotationMode::None,
+            revalidate_window: None,
+            nxdomain_protection_window: None,
+            health_probe: None,
+            max_upstream_silence: None,
+            tls_quarantine: None,
+            adaptive_timeout: None,
+            metrics_upstream_labels: Vec::new(),
         }
     }
     
@@ -196,10 +386,454 @@ impl StrictConfigBuilder {
         self
     }
     
+    /// 设置响应TTL下限（可选功能，不设置则不抬高TTL）
+    pub fn min_ttl(mut self, ttl: Duration) -> Self {
+        self.min_ttl = Some(ttl);
+        self
+    }
+    
+    /// 设置响应TTL上限（可选功能，不设置则不压低TTL）
+    pub fn max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = Some(ttl);
+        self
+    }
+    
+    /// 设置是否缓存含TTL为0记录的响应（按1秒缓存），不设置则不缓存
+    pub fn cache_zero_ttl(mut self, enabled: bool) -> Self {
+        self.cache_zero_ttl = enabled;
+        self
+    }
+    
+    /// 设置是否接受没有问题段的响应，不设置则拒绝
+    pub fn accept_questionless_responses(mut self, enabled: bool) -> Self {
+        self.accept_questionless_responses = enabled;
+        self
+    }
+    
+    /// 设置单个响应的记录数上限及超出时截断还是拒绝，不设置则使用 [`RecordLimits::default`]
+    pub fn record_limits(mut self, limits: RecordLimits) -> Self {
+        self.record_limits = limits;
+        self
+    }
+    
+    /// 设置截断过的响应是否写入缓存，不设置则不缓存
+    pub fn cache_truncated_responses(mut self, enabled: bool) -> Self {
+        self.cache_truncated_responses = enabled;
+        self
+    }
+    
+    /// 设置日志初始化策略（可选，`LoggerInitStrategy::None` 保证构建时不触碰日志系统）
+    pub fn logger_init_strategy(mut self, strategy: LoggerInitStrategy) -> Self {
+        self.logger_init_strategy = Some(strategy);
+        self
+    }
+    
+    /// 设置同一服务器（相同协议和地址）重复配置时是否去重，不设置则重复配置视为错误
+    pub fn dedup_upstreams(mut self, dedup: bool) -> Self {
+        self.dedup_upstreams = dedup;
+        self
+    }
+    
+    /// 设置返回A/AAAA记录时的排列方式，不设置则保持上游给出的顺序
+    pub fn record_rotation(mut self, mode: RotationMode) -> Self {
+        self.record_rotation = mode;
+        self
+    }
+    
+    /// 设置缓存过期后先行返回旧答案的时长（可选功能，不设置则过期条目不再返回）
+    /// 
+    /// 过期不超过该时长的条目立即返回（TTL为0），同时只由一个后台任务向上游刷新
+    pub fn revalidate_window(mut self, window: Duration) -> Self {
+        self.revalidate_window = Some(window);
+        self
+    }
+    
+    /// 设置否定应答保护窗口（可选功能，不设置则不核实否定应答）
+    /// 
+    /// NXDOMAIN/NODATA推翻了过期不超过该时长的肯定答案时，先向另一个上游核实再决定是否缓存
+    pub fn nxdomain_protection_window(mut self, window: Duration) -> Self {
+        self.nxdomain_protection_window = Some(window);
+        self
+    }
+    
+    /// 设置主动探测上游时发送的查询，启用上游监控且有备用层级时必须设置
+    pub fn health_probe(mut self, probe: ProbeConfig) -> Self {
+        self.health_probe = Some(probe);
+        self
+    }
+    
+    /// 设置上游静默时长上限（可选功能，不设置则不检测）
+    /// 
+    /// 超过该时长没有查询或探测的上游由上游监控任务主动探测一次，需要同时启用上游监控并设置健康探测
+    pub fn max_upstream_silence(mut self, silence: Duration) -> Self {
+        self.max_upstream_silence = Some(silence);
+        self
+    }
+    
+    /// 设置加密上游握手失败时的隔离时长（可选功能，不设置则不隔离）
+    /// 
+    /// 第一次证书或其他握手失败即隔离 `initial`，重新接纳的探测失败时加倍，不超过 `max`
+    pub fn tls_quarantine(mut self, config: TlsQuarantineConfig) -> Self {
+        self.tls_quarantine = Some(config);
+        self
+    }
+    
+    /// 设置自适应超时（可选功能，不设置则使用配置的超时）
+    /// 
+    /// 各上游单次发送的超时为 `clamp(p99 × multiplier, floor, ceiling)`，样本不足时沿用配置的超时；需要启用统计
+    pub fn adaptive_timeout(mut self, config: AdaptiveTimeoutConfig) -> Self {
+        self.adaptive_timeout = Some(config);
+        self
+    }
+    
+    /// 设置作为OpenMetrics上游指标标签输出的上游标签键（可选功能，不设置则标签不进入指标）
+    /// 
+    /// 只列出取值有限的键，否则指标的序列数会随标签值增长
+    pub fn metrics_upstream_labels(mut self, keys: Vec<String>) -> Self {
+        self.metrics_upstream_labels = keys;
+        self
+    }
+    
     /// 构建严格配置
     /// 
-    /// 如果任何必需的配置项缺失，将返回错误
+    /// 检查全部配置项，有任何问题（包括警告）时在 [`ConfigError::Multiple`] 中一次返回所有问题
     pub fn build(self) -> Result<StrictDnsConfig, ConfigError> {
+        let issues = self.issues();
+        if !issues.is_empty() {
+            return Err(ConfigError::Multiple(issues));
+        }
+        self.into_config()
+    }
+    
+    /// 构建严格配置，只在有错误级别的问题时失败，成功时一并返回警告
+    pub fn build_lenient(self) -> Result<(StrictDnsConfig, Vec<ConfigIssue>), ConfigError> {
+        let issues = self.issues();
+        if issues.iter().any(ConfigIssue::is_error) {
+            return Err(ConfigError::Multiple(issues));
+        }
+        Ok((self.into_config()?, issues))
+    }
+    
+    /// 检查配置，返回发现的全部问题（每个问题只出现一次），没有问题时为空
+    /// 
+    /// 缺失的必需项记为 `MissingRequired`，依赖缺失项的其他检查跳过
+    pub fn issues(&self) -> Vec<ConfigIssue> {
+        use ConfigIssueKind::{FeatureDisabled, Inconsistent, InvalidValue, MissingRequired};
+        let mut issues = Vec::new();
+        
+        let required = [
+            ("strategy", self.strategy.is_some()),
+            ("default_timeout", self.default_timeout.is_some()),
+            ("retry_count", self.retry_count.is_some()),
+            ("enable_cache", self.enable_cache.is_some()),
+            ("max_cache_ttl", self.max_cache_ttl.is_some()),
+            ("enable_upstream_monitoring", self.enable_upstream_monitoring.is_some()),
+            ("upstream_monitoring_interval", self.upstream_monitoring_interval.is_some()),
+            ("port", self.port.is_some()),
+            ("concurrent_queries", self.concurrent_queries.is_some()),
+            ("buffer_size", self.buffer_size.is_some()),
+            ("enable_stats", self.enable_stats.is_some()),
+            ("emergency_threshold", self.emergency_threshold.is_some()),
+        ];
+        for (field, present) in required {
+            if !present {
+                issues.push(ConfigIssue::error(field, MissingRequired, format!("Missing required configuration: {}", field)));
+            }
+        }
+        if self.upstreams.is_empty() {
+            issues.push(ConfigIssue::error("upstreams", MissingRequired, ConfigError::NoUpstreams.to_string()));
+        }
+        
+        // 验证超时时间
+        if let Some(timeout) = self.default_timeout {
+            if timeout.as_millis() == 0 {
+                issues.push(ConfigIssue::error("default_timeout", InvalidValue, "Timeout cannot be zero"));
+            } else if timeout.as_secs() > 300 {
+                issues.push(ConfigIssue::error("default_timeout", InvalidValue, "Timeout cannot exceed 300 seconds"));
+            }
+        }
+        
+        // 验证重试次数
+        match self.retry_count {
+            Some(0) => issues.push(ConfigIssue::error("retry_count", InvalidValue, "Retry count cannot be zero")),
+            Some(count) if count > 10 => issues.push(ConfigIssue::error("retry_count", InvalidValue, "Retry count cannot exceed 10")),
+            _ => {}
+        }
+        
+        // 验证端口
+        if self.port == Some(0) {
+            issues.push(ConfigIssue::error("port", InvalidValue, "Port cannot be zero"));
+        }
+        
+        // 智能策略按性能指标选择上游，不能关闭统计
+        if self.enable_stats == Some(false) && self.strategy == Some(QueryStrategy::Smart) {
+            issues.push(ConfigIssue::error("enable_stats", Inconsistent,
+                "enable_stats=false is not allowed with the Smart strategy, which selects upstreams by collected metrics; \
+                 enable stats or use Fifo, RoundRobin or Sequential"));
+        }
+        
+        // 验证并发查询数
+        match self.concurrent_queries {
+            Some(0) => issues.push(ConfigIssue::error("concurrent_queries", InvalidValue, "Concurrent queries cannot be zero")),
+            Some(count) if count > 1000 => {
+                issues.push(ConfigIssue::error("concurrent_queries", InvalidValue, "Concurrent queries cannot exceed 1000"));
+            }
+            _ => {}
+        }
+        
+        // 验证缓冲区大小
+        match self.buffer_size {
+            Some(size) if size < 512 => {
+                issues.push(ConfigIssue::error("buffer_size", InvalidValue, "Buffer size must be at least 512 bytes"));
+            }
+            Some(size) if size > 65536 => {
+                issues.push(ConfigIssue::error("buffer_size", InvalidValue, "Buffer size cannot exceed 65536 bytes"));
+            }
+            _ => {}
+        }
+        
+        // 验证每个上游服务器规格
+        for (i, upstream) in self.upstreams.iter().enumerate() {
+            let field = format!("upstreams[{}]", i);
+            if upstream.address.is_empty() {
+                issues.push(ConfigIssue::error(&field, InvalidValue, format!("Upstream {} address cannot be empty", i)));
+            } else if !upstream.address.contains(':') {
+                // 严格要求地址包含端口
+                issues.push(ConfigIssue::error(&field, InvalidValue,
+                    format!("Upstream {} address must include port (e.g., '8.8.8.8:53')", i)));
+            }
+            
+            if upstream.protocol.is_empty() {
+                issues.push(ConfigIssue::error(&field, InvalidValue, format!("Upstream {} protocol cannot be empty", i)));
+            } else if let Some(feature) = upstream.missing_feature() {
+                issues.push(ConfigIssue::error(&field, FeatureDisabled, format!(
+                    "Upstream {}: protocol '{}' requires the {} feature, which this build does not enable",
+                    i, upstream.protocol, feature
+                )));
+            }
+            
+            if upstream.weight == 0 {
+                issues.push(ConfigIssue::error(&field, InvalidValue, format!("Upstream {} weight cannot be zero", i)));
+            }
+            
+            let checks = [
+                check_upstream_name(&upstream.upstream_name()).err().map(|e| e.to_string()),
+                upstream.ecs_policy.validate().err().map(|e| e.to_string()),
+                upstream.features().validate().err().map(|e| e.to_string()),
+                check_upstream_labels(&upstream.labels).err(),
+            ];
+            for e in checks.into_iter().flatten() {
+                issues.push(ConfigIssue::error(&field, InvalidValue, format!("Upstream {}: {}", i, e)));
+            }
+            
+            // DoH地址按URL解析，附加查询参数只对DoH有意义
+            if upstream.canonical_protocol() == "doh" {
+                if let Err(e) = validate_doh_url(&upstream.address, &upstream.query_params) {
+                    issues.push(ConfigIssue::error(&field, InvalidValue, format!("Upstream {}: {}", i, e)));
+                }
+            } else if !upstream.query_params.is_empty() {
+                issues.push(ConfigIssue::error(&field, Inconsistent, format!(
+                    "Upstream {}: query_params are only supported for DoH upstreams, not '{}'", i, upstream.protocol
+                )));
+            }
+        }
+        
+        // 启用的上游之间名称不能重复；同一服务器重复配置只在允许去重时放行
+        let enabled: Vec<(usize, &UpstreamSpec)> = self.upstreams.iter()
+            .enumerate()
+            .filter(|(_, upstream)| upstream.enabled)
+            .collect();
+        for (pos, &(i, upstream)) in enabled.iter().enumerate() {
+            for &(j, other) in &enabled[pos + 1..] {
+                if upstream.upstream_name() == other.upstream_name() {
+                    issues.push(ConfigIssue::error(format!("upstreams[{}]", j), Inconsistent, format!(
+                        "Upstreams {} ({} {}) and {} ({} {}) share the name '{}'",
+                        i, upstream.protocol, upstream.address,
+                        j, other.protocol, other.address,
+                        upstream.upstream_name()
+                    )));
+                } else if !self.dedup_upstreams && upstream.same_server(other) {
+                    issues.push(ConfigIssue::error(format!("upstreams[{}]", j), Inconsistent, format!(
+                        "Upstreams {} and {} both point at {} {}; remove one or enable dedup_upstreams",
+                        i, j, upstream.protocol, upstream.address
+                    )));
+                }
+            }
+        }
+        
+        // 备用层级只在更优先的层级不可用时使用，必须有启用的0层上游承担日常查询
+        if !enabled.is_empty() && enabled.iter().all(|(_, upstream)| upstream.tier > 0) {
+            issues.push(ConfigIssue::error("upstreams", Inconsistent, "At least one enabled upstream must be in tier 0"));
+        }
+        
+        // 只有一个启用的上游时智能策略无从选择
+        if self.strategy == Some(QueryStrategy::Smart) && enabled.len() == 1 {
+            issues.push(ConfigIssue::warning("strategy", Inconsistent,
+                "The Smart strategy has only one enabled upstream to choose from; add upstreams or use Fifo"));
+        }
+        
+        // 法定人数超过启用的上游数时永远无法达成一致
+        if let Some(QueryStrategy::Quorum { required }) = self.strategy {
+            if required == 0 {
+                issues.push(ConfigIssue::error("strategy", InvalidValue, "Quorum size cannot be zero"));
+            } else if required > enabled.len() {
+                issues.push(ConfigIssue::error("strategy", Inconsistent, format!(
+                    "Quorum of {} agreeing upstreams exceeds the {} enabled upstreams", required, enabled.len()
+                )));
+            }
+        }
+        
+        // 并发数超过所有启用上游的连接池总和时，多出的名额用不上
+        if let Some(concurrent) = self.concurrent_queries {
+            let capacity: usize = enabled.iter().map(|(_, upstream)| upstream.pool_size()).sum();
+            if !enabled.is_empty() && concurrent > capacity {
+                issues.push(ConfigIssue::warning("concurrent_queries", Inconsistent, format!(
+                    "concurrent_queries ({}) exceeds the combined connection pools of the enabled upstreams ({})",
+                    concurrent, capacity
+                )));
+            }
+        }
+        
+        // 验证应急阈值：0.0永远不会进入应急模式，1.0只要有一次失败就进入
+        if let Some(threshold) = self.emergency_threshold {
+            if !(0.0..=1.0).contains(&threshold) {
+                issues.push(ConfigIssue::error("emergency_threshold", InvalidValue,
+                    "Emergency threshold must be between 0.0 and 1.0"));
+            } else if threshold == 0.0 || threshold == 1.0 {
+                issues.push(ConfigIssue::warning("emergency_threshold", InvalidValue, format!(
+                    "Emergency threshold {:.1} is degenerate: 0.0 never enters emergency mode, 1.0 enters it on any failure",
+                    threshold
+                )));
+            }
+        }
+        
+        // 验证缓存TTL：关闭缓存时设置的TTL不起作用，设为0表示明知如此
+        match (self.enable_cache, self.max_cache_ttl) {
+            (Some(true), Some(ttl)) if ttl.as_secs() == 0 => {
+                issues.push(ConfigIssue::error("max_cache_ttl", InvalidValue, "Cache TTL cannot be zero when cache is enabled"));
+            }
+            (Some(false), Some(ttl)) if !ttl.is_zero() => {
+                issues.push(ConfigIssue::warning("max_cache_ttl", Inconsistent, format!(
+                    "max_cache_ttl ({:?}) has no effect while enable_cache is false; set it to zero", ttl
+                )));
+            }
+            _ => {}
+        }
+        
+        // 验证TTL钳制范围
+        if let (Some(min), Some(max)) = (self.min_ttl, self.max_ttl)
+            && min > max
+        {
+            issues.push(ConfigIssue::error("min_ttl", Inconsistent,
+                format!("min_ttl ({:?}) cannot exceed max_ttl ({:?})", min, max)));
+        }
+        
+        // 验证记录数上限
+        if let Err(e) = self.record_limits.validate() {
+            issues.push(ConfigIssue::error("record_limits", InvalidValue, e.to_string()));
+        }
+        
+        // 验证上游监控间隔：间隔超过最大不可用时间时，不可用的上游等不到下一次检查就会被强制恢复
+        if let (Some(true), Some(interval)) = (self.enable_upstream_monitoring, self.upstream_monitoring_interval) {
+            if interval.as_secs() == 0 {
+                issues.push(ConfigIssue::error("upstream_monitoring_interval", InvalidValue,
+                    "Upstream monitoring interval cannot be zero when upstream monitoring is enabled"));
+            } else if interval > MAX_UNAVAILABLE_DURATION {
+                issues.push(ConfigIssue::error("upstream_monitoring_interval", Inconsistent, format!(
+                    "Upstream monitoring interval ({:?}) exceeds the maximum unavailable duration ({:?})",
+                    interval, MAX_UNAVAILABLE_DURATION
+                )));
+            }
+        }
+        
+        // 验证健康探测：备用层级靠主动探测判断更优先的上游是否恢复，探测域名必须明确配置
+        match &self.health_probe {
+            Some(probe) => {
+                if let Err(e) = probe.validate() {
+                    issues.push(ConfigIssue::error("health_probe", InvalidValue, e.to_string()));
+                }
+            }
+            None if self.enable_upstream_monitoring == Some(true) && enabled.iter().any(|(_, upstream)| upstream.tier > 0) => {
+                issues.push(ConfigIssue::error("health_probe", MissingRequired,
+                    "health_probe is required when upstream monitoring is enabled and backup tiers are configured"));
+            }
+            None => {}
+        }
+        
+        // 验证静默检测：由监控任务每个间隔检查一次，上限不超过间隔时每次检查都会探测
+        if let Some(silence) = self.max_upstream_silence {
+            if self.enable_upstream_monitoring != Some(true) {
+                issues.push(ConfigIssue::error("max_upstream_silence", Inconsistent,
+                    "max_upstream_silence requires upstream monitoring to be enabled"));
+            } else if let Some(interval) = self.upstream_monitoring_interval.filter(|&interval| silence <= interval) {
+                issues.push(ConfigIssue::error("max_upstream_silence", Inconsistent, format!(
+                    "max_upstream_silence ({:?}) must be longer than the upstream monitoring interval ({:?})",
+                    silence, interval
+                )));
+            }
+            if self.health_probe.is_none() {
+                issues.push(ConfigIssue::error("health_probe", MissingRequired,
+                    "health_probe is required when max_upstream_silence is set"));
+            }
+        }
+        
+        if let Some(Err(e)) = self.tls_quarantine.map(|config| config.validate()) {
+            issues.push(ConfigIssue::error("tls_quarantine", InvalidValue, e.to_string()));
+        }
+        
+        if let Some(adaptive) = &self.adaptive_timeout {
+            if let Err(e) = adaptive.validate() {
+                issues.push(ConfigIssue::error("adaptive_timeout", InvalidValue, e.to_string()));
+            }
+            if self.enable_stats == Some(false) {
+                issues.push(ConfigIssue::error("adaptive_timeout", Inconsistent,
+                    "adaptive_timeout needs latency statistics, enable_stats must be true"));
+            }
+        }
+        
+        for e in self.metrics_upstream_labels.iter().filter_map(|key| check_label_key(key).err()) {
+            issues.push(ConfigIssue::error("metrics_upstream_labels", InvalidValue, e));
+        }
+        
+        issues
+    }
+    
+    /// 从已有配置创建构建器，用于检查配置
+    fn from_config(config: &StrictDnsConfig) -> Self {
+        Self {
+            strategy: Some(config.strategy),
+            default_timeout: Some(config.default_timeout),
+            retry_count: Some(config.retry_count),
+            enable_cache: Some(config.enable_cache),
+            max_cache_ttl: Some(config.max_cache_ttl),
+            enable_upstream_monitoring: Some(config.enable_upstream_monitoring),
+            upstream_monitoring_interval: Some(config.upstream_monitoring_interval),
+            port: Some(config.port),
+            concurrent_queries: Some(config.concurrent_queries),
+            buffer_size: Some(config.buffer_size),
+            upstreams: config.upstreams.clone(),
+            enable_stats: Some(config.enable_stats),
+            emergency_threshold: Some(config.emergency_threshold),
+            min_ttl: config.min_ttl,
+            max_ttl: config.max_ttl,
+            cache_zero_ttl: config.cache_zero_ttl,
+            accept_questionless_responses: config.accept_questionless_responses,
+            record_limits: config.record_limits,
+            cache_truncated_responses: config.cache_truncated_responses,
+            logger_init_strategy: config.logger_init_strategy.clone(),
+            dedup_upstreams: config.dedup_upstreams,
+            record_rotation: config.record_rotation,
+            revalidate_window: config.revalidate_window,
+            nxdomain_protection_window: config.nxdomain_protection_window,
+            health_probe: config.health_probe.clone(),
+            max_upstream_silence: config.max_upstream_silence,
+            tls_quarantine: config.tls_quarantine,
+            adaptive_timeout: config.adaptive_timeout,
+            metrics_upstream_labels: config.metrics_upstream_labels.clone(),
+        }
+    }
+    
+    fn into_config(self) -> Result<StrictDnsConfig, ConfigError> {
         let config = StrictDnsConfig {
             strategy: self.strategy.ok_or_else(|| 
                 ConfigError::MissingRequired("strategy".to_string()))?,
@@ -225,15 +859,28 @@ impl StrictConfigBuilder {
                 ConfigError::MissingRequired("enable_stats".to_string()))?,
             emergency_threshold: self.emergency_threshold.ok_or_else(|| 
                 ConfigError::MissingRequired("emergency_threshold".to_string()))?,
+            min_ttl: self.min_ttl,
+            max_ttl: self.max_ttl,
+            cache_zero_ttl: self.cache_zero_ttl,
+            accept_questionless_responses: self.accept_questionless_responses,
+            record_limits: self.record_limits,
+            cache_truncated_responses: self.cache_truncated_responses,
+            logger_init_strategy: self.logger_init_strategy,
+            dedup_upstreams: self.dedup_upstreams,
+            record_rotation: self.record_rotation,
+            revalidate_window: self.revalidate_window,
+            nxdomain_protection_window: self.nxdomain_protection_window,
+            health_probe: self.health_probe,
+            max_upstream_silence: self.max_upstream_silence,
+            tls_quarantine: self.tls_quarantine,
+            adaptive_timeout: self.adaptive_timeout,
+            metrics_upstream_labels: self.metrics_upstream_labels,
             upstreams: if self.upstreams.is_empty() {
                 return Err(ConfigError::NoUpstreams);
             } else {
                 self.upstreams
             },
         };
-        
-        // 构建后立即验证
-        config.validate()?;
         Ok(config)
     }
 }
@@ -246,106 +893,20 @@ impl StrictDnsConfig {
     
     /// 严格验证配置，不容忍任何无效值
     /// 
-    /// 这个方法不会尝试修复任何配置问题，而是直接报错
+    /// 这个方法不会尝试修复任何配置问题，而是在 [`ConfigError::Multiple`] 中返回全部错误级别的问题；
+    /// 警告不影响验证结果，需要时用 [`issues`](Self::issues) 查看
     pub fn validate(&self) -> Result<(), ConfigError> {
-        // 验证超时时间
-        if self.default_timeout.as_millis() == 0 {
-            return Err(ConfigError::InvalidTimeout(
-                "Timeout cannot be zero".to_string()));
-        }
-        
-        if self.default_timeout.as_secs() > 300 {
-            return Err(ConfigError::InvalidTimeout(
-                "Timeout cannot exceed 300 seconds".to_string()));
-        }
-        
-        // 验证重试次数
-        if self.retry_count == 0 {
-            return Err(ConfigError::InvalidRetryCount(
-                "Retry count cannot be zero".to_string()));
-        }
-        
-        if self.retry_count > 10 {
-            return Err(ConfigError::InvalidRetryCount(
-                "Retry count cannot exceed 10".to_string()));
-        }
-        
-        // 验证端口
-        if self.port == 0 {
-            return Err(ConfigError::InvalidPort(
-                "Port cannot be zero".to_string()));
-        }
-        
-        // 验证并发查询数
-        if self.concurrent_queries == 0 {
-            return Err(ConfigError::InvalidValue(
-                "Concurrent queries cannot be zero".to_string()));
-        }
-        
-        if self.concurrent_queries > 1000 {
-            return Err(ConfigError::InvalidValue(
-                "Concurrent queries cannot exceed 1000".to_string()));
-        }
-        
-        // 验证缓冲区大小
-        if self.buffer_size < 512 {
-            return Err(ConfigError::InvalidBufferSize(
-                "Buffer size must be at least 512 bytes".to_string()));
-        }
-        
-        if self.buffer_size > 65536 {
-            return Err(ConfigError::InvalidBufferSize(
-                "Buffer size cannot exceed 65536 bytes".to_string()));
-        }
-        
-        // 验证上游服务器
-        if self.upstreams.is_empty() {
-            return Err(ConfigError::NoUpstreams);
-        }
-        
-        // 验证每个上游服务器规格
-        for (i, upstream) in self.upstreams.iter().enumerate() {
-            if upstream.address.is_empty() {
-                return Err(ConfigError::InvalidValue(
-                    format!("Upstream {} address cannot be empty", i)));
-            }
-            
-            // 严格要求地址包含端口
-            if !upstream.address.contains(':') {
-                return Err(ConfigError::InvalidValue(
-                    format!("Upstream {} address must include port (e.g., '8.8.8.8:53')", i)));
-            }
-            
-            if upstream.protocol.is_empty() {
-                return Err(ConfigError::InvalidValue(
-                    format!("Upstream {} protocol cannot be empty", i)));
-            }
-            
-            if upstream.weight == 0 {
-                return Err(ConfigError::InvalidValue(
-                    format!("Upstream {} weight cannot be zero", i)));
-            }
-        }
-        
-        // 验证应急阈值
-        if self.emergency_threshold < 0.0 || self.emergency_threshold > 1.0 {
-            return Err(ConfigError::InvalidValue(
-                "Emergency threshold must be between 0.0 and 1.0".to_string()));
-        }
-        
-        // 验证缓存TTL
-        if self.enable_cache && self.max_cache_ttl.as_secs() == 0 {
-            return Err(ConfigError::InvalidValue(
-                "Cache TTL cannot be zero when cache is enabled".to_string()));
-        }
-        
-        // 验证上游监控间隔
-        if self.enable_upstream_monitoring && self.upstream_monitoring_interval.as_secs() == 0 {
-            return Err(ConfigError::InvalidValue(
-                "Upstream monitoring interval cannot be zero when upstream monitoring is enabled".to_string()));
+        let errors: Vec<ConfigIssue> = self.issues().into_iter().filter(ConfigIssue::is_error).collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Multiple(errors))
         }
-        
-        Ok(())
+    }
+    
+    /// 检查配置，返回全部问题（包括警告）
+    pub fn issues(&self) -> Vec<ConfigIssue> {
+        StrictConfigBuilder::from_config(self).issues()
     }
     
     /// 获取启用的上游服务器列表
@@ -369,6 +930,14 @@ impl UpstreamSpec {
             protocol,
             weight,
             enabled: true,
+            name: None,
+            ecs_policy: EcsPolicy::Forward,
+            tier: 0,
+            edns: None,
+            dnssec_do: None,
+            case_randomization: None,
+            query_params: Vec::new(),
+            labels: UpstreamLabels::new(),
         }
     }
     
@@ -379,9 +948,122 @@ impl UpstreamSpec {
             protocol,
             weight,
             enabled: false,
+            name: None,
+            ecs_policy: EcsPolicy::Forward,
+            tier: 0,
+            edns: None,
+            dnssec_do: None,
+            case_randomization: None,
+            query_params: Vec::new(),
+            labels: UpstreamLabels::new(),
         }
     }
     
+    /// 设置名称（不能为空，不能包含控制字符、'/' 或 ':'）
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+    
+    /// 设置向该上游发送客户端子网（ECS）的方式
+    pub fn with_ecs_policy(mut self, ecs_policy: EcsPolicy) -> Self {
+        self.ecs_policy = ecs_policy;
+        self
+    }
+    
+    /// 设置优先层级（0最优先）
+    pub fn with_tier(mut self, tier: u8) -> Self {
+        self.tier = tier;
+        self
+    }
+    
+    /// 设置是否携带EDNS OPT记录
+    pub fn with_edns(mut self, edns: bool) -> Self {
+        self.edns = Some(edns);
+        self
+    }
+    
+    /// 设置是否在请求中设置DNSSEC OK位
+    pub fn with_dnssec_do(mut self, dnssec_do: bool) -> Self {
+        self.dnssec_do = Some(dnssec_do);
+        self
+    }
+    
+    /// 设置是否随机化查询名的大小写
+    pub fn with_case_randomization(mut self, case_randomization: bool) -> Self {
+        self.case_randomization = Some(case_randomization);
+        self
+    }
+    
+    /// 设置附加的URL查询参数（仅DoH）
+    pub fn with_query_params(mut self, query_params: Vec<(String, String)>) -> Self {
+        self.query_params = query_params;
+        self
+    }
+    
+    /// 添加一个标签，同名的键覆盖原来的值
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.insert(key.into(), value.into());
+        self
+    }
+    
+    /// 该上游的请求特性开关
+    pub fn features(&self) -> UpstreamFeatures {
+        UpstreamFeatures {
+            edns: self.edns,
+            dnssec_do: self.dnssec_do,
+            case_randomization: self.case_randomization,
+        }
+    }
+    
+    /// 上游名称：优先使用明确设置的名称，否则由协议和地址生成（如 `udp-8.8.8.8_53`）
+    pub fn upstream_name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        let address = self.address
+            .strip_prefix("https://")
+            .or_else(|| self.address.strip_prefix("tls://"))
+            .unwrap_or(&self.address)
+            .trim_end_matches('/')
+            .replace([':', '/'], "_");
+        format!("{}-{}", self.protocol.to_ascii_lowercase(), address)
+    }
+    
+    /// 规范化的协议名（`https` 视为 `doh`，`tls` 视为 `dot`）
+    fn canonical_protocol(&self) -> String {
+        match self.protocol.to_ascii_lowercase().as_str() {
+            "https" => "doh".to_string(),
+            "tls" => "dot".to_string(),
+            other => other.to_string(),
+        }
+    }
+    
+    /// 该上游的协议需要、但本次构建没有启用的cargo特性
+    fn missing_feature(&self) -> Option<&'static str> {
+        let transport_type = match self.canonical_protocol().as_str() {
+            "tcp" => UpstreamType::Tcp,
+            "dot" => UpstreamType::DoT,
+            "doh" => UpstreamType::DoH,
+            _ => return None,
+        };
+        transport_type.missing_feature()
+    }
+    
+    /// 该上游的连接池大小（DoT、DoH较小）
+    fn pool_size(&self) -> usize {
+        match self.canonical_protocol().as_str() {
+            "dot" | "doh" => ENCRYPTED_POOL_SIZE,
+            _ => PLAIN_POOL_SIZE,
+        }
+    }
+    
+    /// 是否与另一项指向同一服务器（协议相同且地址相同，忽略大小写）
+    pub fn same_server(&self, other: &UpstreamSpec) -> bool {
+        self.canonical_protocol() == other.canonical_protocol()
+            && self.address.trim_end_matches('/').eq_ignore_ascii_case(other.address.trim_end_matches('/'))
+    }
+    
     /// 解析地址和端口
     /// 
     /// 如果地址格式无效，返回错误而不是尝试修复
@@ -408,14 +1090,12 @@ mod tests {
     
     #[test]
     fn test_strict_config_builder_missing_required() {
-        let result = StrictDnsConfig::builder().build();
-        assert!(result.is_err());
-        
-        if let Err(ConfigError::MissingRequired(field)) = result {
-            assert_eq!(field, "strategy");
-        } else {
-            panic!("Expected MissingRequired error");
-        }
+        let error = StrictDnsConfig::builder().build().unwrap_err();
+        let issues = error.issues();
+        assert_eq!(issues.len(), 13);
+        assert!(issues.iter().all(|issue| issue.kind == ConfigIssueKind::MissingRequired && issue.is_error()));
+        assert_eq!(issues[0].field, "strategy");
+        assert_eq!(issues[12].field, "upstreams");
     }
     
     #[test]
@@ -440,11 +1120,67 @@ mod tests {
             .enable_stats(true)
             .emergency_threshold(0.3)
             .add_upstream(upstream)
+            .add_upstream(UpstreamSpec::new("8.8.4.4:53".to_string(), "udp".to_string(), 1))
             .build();
         
         assert!(config.is_ok());
     }
     
+    #[test]
+    fn test_smart_strategy_requires_stats() {
+        let config = |strategy: QueryStrategy, enable_stats: bool| StrictDnsConfig::builder()
+            .strategy(strategy)
+            .timeout(Duration::from_secs(5))
+            .retry_count(3)
+            .enable_cache(true)
+            .cache_ttl(Duration::from_secs(3600))
+            .enable_upstream_monitoring(false)
+            .upstream_monitoring_interval(Duration::from_secs(30))
+            .port(53)
+            .concurrent_queries(10)
+            .buffer_size(4096)
+            .enable_stats(enable_stats)
+            .emergency_threshold(0.3)
+            .add_upstream(UpstreamSpec::new("8.8.8.8:53".to_string(), "udp".to_string(), 1))
+            .add_upstream(UpstreamSpec::new("8.8.4.4:53".to_string(), "udp".to_string(), 1))
+            .build();
+        
+        let result = config(QueryStrategy::Smart, false);
+        assert!(matches!(result, Err(e) if e.to_string().contains("enable_stats") && e.to_string().contains("Fifo")));
+        assert!(config(QueryStrategy::Smart, true).is_ok());
+        assert!(config(QueryStrategy::Fifo, false).is_ok());
+    }
+    
+    #[test]
+    fn test_quorum_cannot_exceed_enabled_upstreams() {
+        let config = |required: usize| {
+            let mut disabled = UpstreamSpec::new("9.9.9.9:53".to_string(), "udp".to_string(), 1);
+            disabled.enabled = false;
+            StrictDnsConfig::builder()
+                .strategy(QueryStrategy::Quorum { required })
+                .timeout(Duration::from_secs(5))
+                .retry_count(3)
+                .enable_cache(true)
+                .cache_ttl(Duration::from_secs(3600))
+                .enable_upstream_monitoring(false)
+                .upstream_monitoring_interval(Duration::from_secs(30))
+                .port(53)
+                .concurrent_queries(10)
+                .buffer_size(4096)
+                .enable_stats(true)
+                .emergency_threshold(0.3)
+                .add_upstream(UpstreamSpec::new("8.8.8.8:53".to_string(), "udp".to_string(), 1))
+                .add_upstream(UpstreamSpec::new("1.1.1.1:53".to_string(), "udp".to_string(), 1))
+                .add_upstream(disabled)
+                .build()
+        };
+        
+        assert!(config(2).is_ok());
+        let result = config(3);
+        assert!(matches!(result, Err(e) if e.to_string().contains("exceeds the 2 enabled upstreams")));
+        assert!(matches!(config(0), Err(e) if e.to_string().contains("Quorum size cannot be zero")));
+    }
+    
     #[test]
     fn test_upstream_spec_parse_address() {
         let upstream = UpstreamSpec::new(
@@ -469,4 +1205,298 @@ mod tests {
         let result = upstream.parse_address();
         assert!(result.is_err());
     }
-}
\ No newline at end of file
+    
+    fn config_with(upstreams: Vec<UpstreamSpec>, dedup: bool) -> Result<StrictDnsConfig, ConfigError> {
+        let mut builder = StrictDnsConfig::builder()
+            .strategy(QueryStrategy::Fifo)
+            .timeout(Duration::from_secs(5))
+            .retry_count(3)
+            .enable_cache(true)
+            .cache_ttl(Duration::from_secs(3600))
+            .enable_upstream_monitoring(false)
+            .upstream_monitoring_interval(Duration::from_secs(30))
+            .port(53)
+            .concurrent_queries(10)
+            .buffer_size(4096)
+            .enable_stats(true)
+            .emergency_threshold(0.3)
+            .dedup_upstreams(dedup);
+        for upstream in upstreams {
+            builder = builder.add_upstream(upstream);
+        }
+        builder.build()
+    }
+    
+    #[test]
+    fn test_duplicate_upstreams_rejected() {
+        let udp = |address: &str| UpstreamSpec::new(address.to_string(), "udp".to_string(), 1);
+        
+        // 同名
+        let result = config_with(vec![udp("8.8.8.8:53").with_name("google"), udp("8.8.4.4:53").with_name("google")], false);
+        assert!(matches!(result, Err(e) if e.to_string().contains("8.8.4.4") && e.to_string().contains("'google'")));
+        
+        // 同一服务器：默认报错，允许去重时放行（由构造器保留权重较高的一项）
+        assert!(config_with(vec![udp("8.8.8.8:53").with_name("a"), udp("8.8.8.8:53").with_name("b")], false).is_err());
+        assert!(config_with(vec![udp("8.8.8.8:53").with_name("a"), udp("8.8.8.8:53").with_name("b")], true).is_ok());
+        
+        // 同一地址不同协议不算重复，生成的名称也不冲突
+        let tcp = UpstreamSpec::new("8.8.8.8:53".to_string(), "tcp".to_string(), 1);
+        assert!(config_with(vec![udp("8.8.8.8:53"), tcp], false).is_ok());
+        
+        // 禁用的上游不参与比较
+        let disabled = UpstreamSpec::disabled("8.8.8.8:53".to_string(), "udp".to_string(), 1);
+        assert!(config_with(vec![udp("8.8.8.8:53"), disabled], false).is_ok());
+    }
+    
+    #[test]
+    fn test_upstream_name_charset() {
+        assert_eq!(UpstreamSpec::new("8.8.8.8:53".to_string(), "UDP".to_string(), 1).upstream_name(), "udp-8.8.8.8_53");
+        assert_eq!(
+            UpstreamSpec::new("https://dns.alidns.com/dns-query".to_string(), "doh".to_string(), 1).upstream_name(),
+            "doh-dns.alidns.com_dns-query"
+        );
+        
+        let upstream = || UpstreamSpec::new("8.8.8.8:53".to_string(), "udp".to_string(), 1);
+        for bad in ["", "  ", "a/b", "a:b", "tab\tname"] {
+            assert!(config_with(vec![upstream().with_name(bad)], false).is_err(), "{:?} should be rejected", bad);
+        }
+        assert!(config_with(vec![upstream().with_name("Google DNS")], false).is_ok());
+    }
+    
+    #[test]
+    fn test_tier_zero_upstream_required() {
+        let udp = |address: &str| UpstreamSpec::new(address.to_string(), "udp".to_string(), 1);
+        
+        let result = config_with(vec![udp("10.0.0.53:53").with_tier(1), udp("10.0.0.54:53").with_tier(2)], false);
+        assert!(matches!(result, Err(e) if e.to_string().contains("tier 0")));
+        
+        // 禁用的0层上游不算
+        let disabled = UpstreamSpec::disabled("10.0.0.53:53".to_string(), "udp".to_string(), 1);
+        assert!(config_with(vec![disabled, udp("10.0.0.54:53").with_tier(1)], false).is_err());
+        
+        let config = config_with(vec![udp("10.0.0.53:53"), udp("10.0.0.54:53").with_tier(1)], false).unwrap();
+        assert_eq!(config.upstreams.iter().map(|u| u.tier).collect::<Vec<_>>(), vec![0, 1]);
+    }
+    
+    #[test]
+    fn test_health_probe_required_for_backup_tiers() {
+        use crate::builder::types::DnsRecordType;
+        
+        let udp = |address: &str| UpstreamSpec::new(address.to_string(), "udp".to_string(), 1);
+        let mut config = config_with(vec![udp("10.0.0.53:53"), udp("10.0.0.54:53").with_tier(1)], false).unwrap();
+        config.enable_upstream_monitoring = true;
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.issues().len(), 1);
+        assert_eq!((error.issues()[0].field.as_str(), error.issues()[0].kind), ("health_probe", ConfigIssueKind::MissingRequired));
+        
+        config.health_probe = Some(ProbeConfig::new(vec![String::new()], DnsRecordType::A, Duration::from_secs(2)));
+        assert_eq!(config.validate().unwrap_err().issues()[0].kind, ConfigIssueKind::InvalidValue);
+        
+        config.health_probe = Some(ProbeConfig::new(vec!["www.qq.com".to_string()], DnsRecordType::A, Duration::from_secs(2)));
+        assert!(config.validate().is_ok());
+        
+        let json = serde_json::to_string(&config).unwrap();
+        let decoded: StrictDnsConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.health_probe, config.health_probe);
+        
+        // 静默上限必须大于监控间隔
+        config.max_upstream_silence = Some(config.upstream_monitoring_interval);
+        let error = config.validate().unwrap_err();
+        assert_eq!((error.issues()[0].field.as_str(), error.issues()[0].kind), ("max_upstream_silence", ConfigIssueKind::Inconsistent));
+        config.max_upstream_silence = Some(config.upstream_monitoring_interval * 4);
+        assert!(config.validate().is_ok());
+    }
+    
+    #[test]
+    fn test_record_limits_are_validated() {
+        use crate::transport::RecordLimitPolicy;
+        
+        let udp = || UpstreamSpec::new("10.0.0.53:53".to_string(), "udp".to_string(), 1);
+        let mut config = config_with(vec![udp()], false).unwrap();
+        assert_eq!(config.record_limits, RecordLimits::default());
+        
+        config.record_limits = RecordLimits { max_answer_records: 100, max_total_records: 50, ..RecordLimits::default() };
+        let error = config.validate().unwrap_err();
+        assert_eq!((error.issues()[0].field.as_str(), error.issues()[0].kind), ("record_limits", ConfigIssueKind::InvalidValue));
+        
+        config.record_limits = RecordLimits { max_cname_chain: 4, policy: RecordLimitPolicy::Reject, ..RecordLimits::default() };
+        config.cache_truncated_responses = true;
+        assert!(config.validate().is_ok());
+        
+        // 旧配置文件没有这两项时取默认值
+        let mut json = serde_json::to_value(&config).unwrap();
+        json.as_object_mut().unwrap().retain(|key, _| key != "record_limits" && key != "cache_truncated_responses");
+        let decoded: StrictDnsConfig = serde_json::from_value(json).unwrap();
+        assert_eq!((decoded.record_limits, decoded.cache_truncated_responses), (RecordLimits::default(), false));
+    }
+    
+    #[test]
+    fn test_doh_url_and_query_params_are_validated() {
+        let doh = |address: &str| UpstreamSpec::new(address.to_string(), "doh".to_string(), 1)
+            .with_query_params(vec![("key".to_string(), "s3cr3t".to_string()), ("filter".to_string(), "family".to_string())]);
+        
+        let udp = || UpstreamSpec::new("10.0.0.53:53".to_string(), "udp".to_string(), 1);
+        assert!(config_with(vec![doh("https://dns.example/v1/abc123?profile=home"), udp()], false).is_ok());
+        
+        let error = config_with(vec![doh("http://dns.example/dns-query")], false).unwrap_err();
+        assert_eq!((error.issues()[0].field.as_str(), error.issues()[0].kind), ("upstreams[0]", ConfigIssueKind::InvalidValue));
+        assert!(error.issues()[0].message.contains("must use https"), "{}", error);
+        
+        let error = config_with(vec![doh("https://dns.example/dns-query").with_query_params(vec![("dns".to_string(), "x".to_string())])], false).unwrap_err();
+        assert!(error.issues()[0].message.contains("reserved"), "{}", error);
+        
+        let error = config_with(vec![udp().with_query_params(vec![("key".to_string(), "s3cr3t".to_string())])], false).unwrap_err();
+        assert_eq!(error.issues()[0].kind, ConfigIssueKind::Inconsistent);
+        assert!(!error.to_string().contains("s3cr3t"));
+    }
+    
+    #[test]
+    fn test_protocols_outside_the_build_are_reported() {
+        let spec = |address: &str, protocol: &str| UpstreamSpec::new(address.to_string(), protocol.to_string(), 1);
+        let udp = spec("10.0.0.53:53", "udp");
+        for (upstream, enabled) in [
+            (spec("10.0.0.53:53", "tcp"), cfg!(feature = "tcp")),
+            (spec("1.1.1.1:853", "tls"), cfg!(feature = "dot")),
+            (spec("https://dns.example/dns-query", "https"), cfg!(feature = "doh")),
+        ] {
+            match config_with(vec![udp.clone(), upstream], false) {
+                Ok(_) => assert!(enabled),
+                Err(error) => {
+                    assert!(!enabled, "{}", error);
+                    assert_eq!((error.issues()[0].field.as_str(), error.issues()[0].kind), ("upstreams[1]", ConfigIssueKind::FeatureDisabled));
+                    assert!(error.issues()[0].message.contains("feature"), "{}", error);
+                }
+            }
+        }
+    }
+    
+    #[test]
+    fn test_dnssec_do_requires_edns() {
+        let udp = || UpstreamSpec::new("10.0.0.53:53".to_string(), "udp".to_string(), 1);
+        
+        let result = config_with(vec![udp().with_edns(false).with_dnssec_do(true)], false);
+        assert!(matches!(result, Err(e) if e.to_string().contains("dnssec_do")));
+        
+        assert!(config_with(vec![udp().with_dnssec_do(true)], false).is_ok());
+        let config = config_with(vec![udp().with_edns(false).with_case_randomization(true)], false).unwrap();
+        let features = config.upstreams[0].features();
+        assert_eq!((features.edns, features.dnssec_do, features.case_randomization), (Some(false), None, Some(true)));
+    }
+    
+    #[test]
+    fn test_upstream_labels_are_validated() {
+        let udp = || UpstreamSpec::new("10.0.0.53:53".to_string(), "udp".to_string(), 1);
+        
+        let long_value = "x".repeat(257);
+        for (key, value) in [("", "a"), ("1st", "a"), ("has-dash", "a"), ("__internal", "a"), ("upstream", "a"),
+                             ("provider", "line\nbreak"), ("provider", long_value.as_str())] {
+            let result = config_with(vec![udp().with_label(key, value)], false);
+            assert!(matches!(&result, Err(e) if e.issues()[0].field == "upstreams[0]"), "{:?}={:?} should be rejected", key, value);
+        }
+        let too_many = (0..17).fold(udp(), |upstream, i| upstream.with_label(format!("k{}", i), "v"));
+        assert!(config_with(vec![too_many], false).is_err());
+        
+        let labelled = udp().with_label("provider", "internal").with_label("ticket", "https://tickets.example/DNS-42");
+        assert!(config_with(vec![labelled], false).is_ok());
+    }
+    
+    #[test]
+    fn test_labels_survive_toml_round_trip() {
+        let upstream = UpstreamSpec::new("10.0.0.53:53".to_string(), "udp".to_string(), 1)
+            .with_name("office")
+            .with_label("provider", "internal")
+            .with_label("datacenter", "sha-1");
+        let mut config = config_with(vec![upstream], false).unwrap();
+        config.metrics_upstream_labels = vec!["provider".to_string()];
+        
+        let text = toml::to_string(&config).unwrap();
+        let parsed: StrictDnsConfig = toml::from_str(&text).unwrap();
+        assert_eq!(parsed.upstreams[0].labels, config.upstreams[0].labels);
+        assert_eq!(parsed.metrics_upstream_labels, vec!["provider".to_string()]);
+        
+        // 没有写标签的旧配置照常解析
+        let minimal: UpstreamSpec = toml::from_str("address = \"10.0.0.53:53\"\nprotocol = \"udp\"\nweight = 1\nenabled = true").unwrap();
+        assert!(minimal.labels.is_empty());
+        
+        config.metrics_upstream_labels = vec!["bad key".to_string()];
+        assert_eq!(config.issues()[0].field, "metrics_upstream_labels");
+        assert!(config.validate().is_err());
+    }
+    
+    #[test]
+    fn test_build_reports_every_issue_once() {
+        use ConfigIssueKind::{Inconsistent, InvalidValue, MissingRequired};
+        use ConfigSeverity::{Error, Warning};
+        
+        let error = StrictDnsConfig::builder()
+            .strategy(QueryStrategy::Smart)
+            .timeout(Duration::ZERO)
+            .enable_cache(false)
+            .cache_ttl(Duration::from_secs(600))
+            .enable_upstream_monitoring(true)
+            .upstream_monitoring_interval(Duration::from_secs(600))
+            .port(53)
+            .concurrent_queries(50)
+            .buffer_size(4096)
+            .enable_stats(true)
+            .emergency_threshold(1.0)
+            .add_upstream(UpstreamSpec::new("10.0.0.53".to_string(), "dot".to_string(), 0))
+            .build()
+            .unwrap_err();
+        
+        let mut found: Vec<(&str, ConfigIssueKind, ConfigSeverity)> = error.issues().iter()
+            .map(|issue| (issue.field.as_str(), issue.kind, issue.severity()))
+            .collect();
+        found.sort();
+        let mut expected = vec![
+            ("retry_count", MissingRequired, Error),
+            ("default_timeout", InvalidValue, Error),
+            ("upstreams[0]", InvalidValue, Error),
+            ("upstreams[0]", InvalidValue, Error),
+            ("strategy", Inconsistent, Warning),
+            ("concurrent_queries", Inconsistent, Warning),
+            ("emergency_threshold", InvalidValue, Warning),
+            ("max_cache_ttl", Inconsistent, Warning),
+            ("upstream_monitoring_interval", Inconsistent, Error),
+        ];
+        expected.sort();
+        assert_eq!(found, expected, "{}", error);
+        // 同一上游的两个问题分别是缺少端口和权重为0
+        let upstream: Vec<&str> = error.issues().iter()
+            .filter(|issue| issue.field == "upstreams[0]")
+            .map(|issue| issue.message.as_str())
+            .collect();
+        assert!(upstream[0].contains("port") && upstream[1].contains("weight"));
+        assert!(error.to_string().starts_with("9 configuration issue(s): "));
+    }
+    
+    #[test]
+    fn test_build_lenient_proceeds_on_warnings() {
+        let builder = |emergency_threshold: f64| StrictDnsConfig::builder()
+            .strategy(QueryStrategy::Smart)
+            .timeout(Duration::from_secs(5))
+            .retry_count(3)
+            .enable_cache(false)
+            .cache_ttl(Duration::from_secs(600))
+            .enable_upstream_monitoring(false)
+            .upstream_monitoring_interval(Duration::from_secs(30))
+            .port(53)
+            .concurrent_queries(10)
+            .buffer_size(4096)
+            .enable_stats(true)
+            .emergency_threshold(emergency_threshold)
+            .add_upstream(UpstreamSpec::new("8.8.8.8:53".to_string(), "udp".to_string(), 1));
+        
+        assert_eq!(builder(0.3).build().unwrap_err().issues().len(), 2);
+        let (config, warnings) = builder(0.3).build_lenient().unwrap();
+        assert_eq!(warnings.iter().map(|issue| issue.field.as_str()).collect::<Vec<_>>(), vec!["strategy", "max_cache_ttl"]);
+        assert!(warnings.iter().all(|issue| issue.severity() == ConfigSeverity::Warning));
+        // 警告不影响运行时验证，但仍可通过 issues 查看
+        assert!(config.validate().is_ok());
+        assert_eq!(config.issues(), warnings);
+        
+        let error = builder(1.5).build_lenient().unwrap_err();
+        assert_eq!(error.issues().iter().filter(|issue| issue.is_error()).count(), 1);
+        assert_eq!(error.issues().len(), 3);
+    }
+}
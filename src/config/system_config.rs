@@ -0,0 +1,353 @@
+//! 系统解析器配置
+//!
+//! 读取操作系统（或容器运行时）配置的DNS服务器和搜索域：Unix读取 `/etc/resolv.conf`，
+//! Windows读取注册表中 Tcpip 参数下各网卡的DNS服务器。解析失败返回 [`ConfigError`]，
+//! 不会静默得到零个上游。
+
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::ConfigError;
+use crate::upstream_handler::UpstreamSpec;
+use crate::dns_warn;
+
+/// resolv.conf 默认的 ndots
+pub const DEFAULT_NDOTS: u8 = 1;
+
+/// resolv.conf 中 ndots 的上限（与glibc相同）
+pub const MAX_NDOTS: u8 = 15;
+
+/// resolv.conf 中 timeout 的上限（秒，与glibc相同）
+pub const MAX_TIMEOUT_SECS: u64 = 30;
+
+/// resolv.conf 中 attempts 的上限（与glibc相同）
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// 默认的 resolv.conf 路径
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// 系统上游的名称前缀，第N个服务器命名为 `system-N`
+pub const SYSTEM_UPSTREAM_PREFIX: &str = "system";
+
+/// 搜索域及其展开规则
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchDomains {
+    /// 搜索域，按配置顺序尝试（不含结尾的点）
+    pub domains: Vec<String>,
+    /// 名称中的点少于该值时先尝试搜索域，否则先按原样查询
+    pub ndots: u8,
+}
+
+impl Default for SearchDomains {
+    fn default() -> Self {
+        Self { domains: Vec::new(), ndots: DEFAULT_NDOTS }
+    }
+}
+
+impl SearchDomains {
+    /// 创建搜索域，`ndots` 超过 [`MAX_NDOTS`] 时按上限处理
+    pub fn new<I, S>(domains: I, ndots: u8) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let domains = domains.into_iter()
+            .map(|domain| domain.into().trim_end_matches('.').to_string())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+        Self { domains, ndots: ndots.min(MAX_NDOTS) }
+    }
+
+    /// 是否没有搜索域
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// 按ndots规则给出依次尝试的完整域名
+    ///
+    /// 以点结尾的名称是完整域名，只按原样查询；点数少于 `ndots` 时先依次拼接搜索域，
+    /// 最后按原样查询，否则先按原样查询再拼接搜索域
+    pub fn candidates(&self, name: &str) -> Vec<String> {
+        if name.ends_with('.') || self.domains.is_empty() {
+            return vec![name.to_string()];
+        }
+
+        let dots = name.matches('.').count();
+        let expanded = self.domains.iter().map(|domain| format!("{}.{}", name, domain));
+        let mut candidates = Vec::with_capacity(self.domains.len() + 1);
+        if dots < self.ndots as usize {
+            candidates.extend(expanded);
+            candidates.push(name.to_string());
+        } else {
+            candidates.push(name.to_string());
+            candidates.extend(expanded);
+        }
+        candidates
+    }
+}
+
+/// 系统解析器配置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemResolvConf {
+    /// DNS服务器，按配置顺序
+    pub nameservers: Vec<IpAddr>,
+    /// 搜索域和ndots
+    pub search: SearchDomains,
+    /// 单次查询的超时（`options timeout:N`），未配置时为 `None`
+    pub timeout: Option<Duration>,
+    /// 每个服务器的尝试次数（`options attempts:N`），未配置时为 `None`
+    pub attempts: Option<u32>,
+}
+
+impl SystemResolvConf {
+    /// 读取当前系统的解析器配置
+    #[cfg(not(windows))]
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_from(RESOLV_CONF_PATH)
+    }
+
+    /// 读取当前系统的解析器配置
+    #[cfg(windows)]
+    pub fn load() -> Result<Self, ConfigError> {
+        let output = std::process::Command::new("reg")
+            .args(["query", r"HKLM\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters", "/s"])
+            .output()
+            .map_err(|e| ConfigError::InvalidValue(format!("Cannot query Tcpip registry parameters: {}", e)))?;
+        if !output.status.success() {
+            return Err(ConfigError::InvalidValue(format!(
+                "Querying Tcpip registry parameters failed: {}", output.status
+            )));
+        }
+        Self::from_windows_registry(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// 读取指定路径的 resolv.conf
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::InvalidValue(format!("Cannot read {}: {}", path.display(), e)))?;
+        Self::parse(&text)
+    }
+
+    /// 解析 resolv.conf 文本
+    ///
+    /// 支持 `nameserver`、`search`、`domain` 和 `options` 中的 `ndots`/`timeout`/`attempts`，
+    /// 其余指令和选项忽略；`search` 与 `domain` 以最后出现的为准。
+    /// 带作用域的IPv6地址（`fe80::1%eth0`）无法作为上游使用，记录警告后跳过
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut conf = Self {
+            nameservers: Vec::new(),
+            search: SearchDomains::default(),
+            timeout: None,
+            attempts: None,
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let line_no = index + 1;
+            let line = line.split(['#', ';']).next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(keyword) = fields.next() else {
+                continue;
+            };
+            match keyword {
+                "nameserver" => {
+                    let address = fields.next().ok_or_else(|| ConfigError::InvalidValue(
+                        format!("resolv.conf line {}: nameserver without an address", line_no)
+                    ))?;
+                    if address.contains('%') {
+                        dns_warn!("resolv.conf 第 {} 行的服务器 {} 带有作用域，已跳过", line_no, address);
+                        continue;
+                    }
+                    let ip = address.parse::<IpAddr>().map_err(|_| ConfigError::InvalidValue(
+                        format!("resolv.conf line {}: invalid nameserver address '{}'", line_no, address)
+                    ))?;
+                    conf.nameservers.push(ip);
+                }
+                "search" | "domain" => {
+                    conf.search.domains = SearchDomains::new(fields, DEFAULT_NDOTS).domains;
+                }
+                "options" => {
+                    for option in fields {
+                        conf.apply_option(option, line_no)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        conf.ensure_nameservers()?;
+        Ok(conf)
+    }
+
+    /// 解析 `reg query HKLM\SYSTEM\CurrentControlSet\Services\Tcpip\Parameters /s` 的输出
+    ///
+    /// 每个网卡优先使用静态配置的 `NameServer`，未配置时使用 `DhcpNameServer`；
+    /// 搜索域取 `SearchList`，未配置时取主DNS后缀（`Domain`/`DhcpDomain`）
+    pub fn from_windows_registry(text: &str) -> Result<Self, ConfigError> {
+        #[derive(Default)]
+        struct KeyValues {
+            name_server: Option<String>,
+            dhcp_name_server: Option<String>,
+        }
+
+        let mut keys: Vec<KeyValues> = Vec::new();
+        let mut search_list = None;
+        let mut domain = None;
+        let mut dhcp_domain = None;
+        for line in text.lines() {
+            if line.starts_with("HKEY_") {
+                keys.push(KeyValues::default());
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(name), Some(kind)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            if !kind.starts_with("REG_") {
+                continue;
+            }
+            let value = fields.collect::<Vec<_>>().join(" ");
+            if value.is_empty() {
+                continue;
+            }
+            match (name, keys.len()) {
+                ("SearchList", 1) => search_list = Some(value),
+                ("Domain", 1) => domain = Some(value),
+                ("DhcpDomain", 1) => dhcp_domain = Some(value),
+                ("NameServer" | "DhcpNameServer", _) => {
+                    let Some(key) = keys.last_mut() else {
+                        continue;
+                    };
+                    if name == "NameServer" {
+                        key.name_server = Some(value);
+                    } else {
+                        key.dhcp_name_server = Some(value);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut conf = Self {
+            nameservers: Vec::new(),
+            search: SearchDomains::default(),
+            timeout: None,
+            attempts: None,
+        };
+        for key in keys {
+            let Some(servers) = key.name_server.or(key.dhcp_name_server) else {
+                continue;
+            };
+            for address in servers.split([',', ' ']).filter(|address| !address.is_empty()) {
+                let ip = address.parse::<IpAddr>().map_err(|_| ConfigError::InvalidValue(
+                    format!("invalid registry nameserver address '{}'", address)
+                ))?;
+                if !conf.nameservers.contains(&ip) {
+                    conf.nameservers.push(ip);
+                }
+            }
+        }
+        if let Some(list) = search_list.or(domain).or(dhcp_domain) {
+            conf.search.domains = SearchDomains::new(list.split([',', ' ']), DEFAULT_NDOTS).domains;
+        }
+
+        conf.ensure_nameservers()?;
+        Ok(conf)
+    }
+
+    /// 系统服务器对应的UDP上游，依次命名为 `system-1`、`system-2`……
+    pub fn upstream_specs(&self) -> Vec<UpstreamSpec> {
+        self.nameservers.iter()
+            .enumerate()
+            .map(|(index, ip)| UpstreamSpec::udp(
+                format!("{}-{}", SYSTEM_UPSTREAM_PREFIX, index + 1),
+                SocketAddr::new(*ip, 53).to_string(),
+            ))
+            .collect()
+    }
+
+    /// 应用 `options` 行中的一项，无法映射到本库配置的选项忽略
+    fn apply_option(&mut self, option: &str, line_no: usize) -> Result<(), ConfigError> {
+        let Some((name, value)) = option.split_once(':') else {
+            return Ok(());
+        };
+        if !matches!(name, "ndots" | "timeout" | "attempts") {
+            return Ok(());
+        }
+        let value: u64 = value.parse().map_err(|_| ConfigError::InvalidValue(
+            format!("resolv.conf line {}: invalid value for option {}: '{}'", line_no, name, value)
+        ))?;
+        match name {
+            "ndots" => self.search.ndots = value.min(MAX_NDOTS as u64) as u8,
+            "timeout" => {
+                if value == 0 {
+                    return Err(ConfigError::InvalidTimeout(format!("resolv.conf line {}: timeout must be at least 1 second", line_no)));
+                }
+                self.timeout = Some(Duration::from_secs(value.min(MAX_TIMEOUT_SECS)));
+            }
+            _ => {
+                if value == 0 {
+                    return Err(ConfigError::InvalidRetryCount(format!("resolv.conf line {}: attempts must be at least 1", line_no)));
+                }
+                self.attempts = Some(value.min(MAX_ATTEMPTS as u64) as u32);
+            }
+        }
+        Ok(())
+    }
+
+    fn ensure_nameservers(&self) -> Result<(), ConfigError> {
+        if self.nameservers.is_empty() {
+            return Err(ConfigError::NoUpstreams);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_domain_candidates_follow_ndots() {
+        let search = SearchDomains::new(["svc.cluster.local", "cluster.local."], 2);
+
+        assert_eq!(search.candidates("redis"), vec![
+            "redis.svc.cluster.local", "redis.cluster.local", "redis",
+        ]);
+        assert_eq!(search.candidates("redis.cache"), vec![
+            "redis.cache.svc.cluster.local", "redis.cache.cluster.local", "redis.cache",
+        ]);
+        assert_eq!(search.candidates("www.example.com"), vec![
+            "www.example.com", "www.example.com.svc.cluster.local", "www.example.com.cluster.local",
+        ]);
+        assert_eq!(search.candidates("redis."), vec!["redis."]);
+    }
+
+    #[test]
+    fn test_windows_registry_prefers_static_nameservers() {
+        let output = "\
+HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters
+    Domain    REG_SZ    corp.example
+    SearchList    REG_SZ    corp.example,lab.example
+
+HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters\\Interfaces\\{A}
+    NameServer    REG_SZ    10.0.0.53,10.0.0.54
+    DhcpNameServer    REG_SZ    192.168.1.1
+
+HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters\\Interfaces\\{B}
+    NameServer    REG_SZ
+    DhcpNameServer    REG_SZ    192.168.1.1 fd00::53
+";
+        let conf = SystemResolvConf::from_windows_registry(output).unwrap();
+
+        let expected: Vec<IpAddr> = ["10.0.0.53", "10.0.0.54", "192.168.1.1", "fd00::53"]
+            .iter().map(|ip| ip.parse().unwrap()).collect();
+        assert_eq!(conf.nameservers, expected);
+        assert_eq!(conf.search.domains, vec!["corp.example", "lab.example"]);
+        assert!(matches!(
+            SystemResolvConf::from_windows_registry("HKEY_LOCAL_MACHINE\\X\n    Domain    REG_SZ    a\n"),
+            Err(ConfigError::NoUpstreams)
+        ));
+    }
+}
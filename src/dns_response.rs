@@ -2,9 +2,201 @@
 //!
 //! 提供将数据包装成传统DNS响应的功能，支持所有DNS记录类型
 
+use crate::error::{DnsError, Result};
 use crate::types::*;
+use std::fmt;
 use std::net::{Ipv4Addr, Ipv6Addr};
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::time::{SystemTime, UNIX_EPOCH};
+
+/// 单个标签的最大长度（字节）
+pub const MAX_LABEL_LEN: usize = 63;
+
+/// 域名编码后的最大长度（字节，含长度前缀和结尾的0）
+pub const MAX_NAME_LEN: usize = 255;
+
+/// TXT记录中单个字符串的最大长度（字节）
+pub const MAX_TXT_CHUNK_LEN: usize = 255;
+
+/// 把超过255字节的TXT文本按字符边界拆成多个字符串，每段不超过255字节
+pub fn split_txt(text: &str) -> Vec<String> {
+    if text.len() <= MAX_TXT_CHUNK_LEN {
+        return vec![text.to_string()];
+    }
+    let mut chunks = Vec::with_capacity(text.len() / MAX_TXT_CHUNK_LEN + 1);
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(MAX_TXT_CHUNK_LEN);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk.to_string());
+        rest = tail;
+    }
+    chunks
+}
+
+/// 响应中无法按线路格式编码的字段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseViolation {
+    /// 字段路径，如 `answers[0].data.exchange`
+    pub path: String,
+    /// 违规说明
+    pub message: String,
+}
+
+impl fmt::Display for ResponseViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// 检查域名的标签长度、空标签和总长度
+fn check_name(path: String, name: &str, violations: &mut Vec<ResponseViolation>) {
+    let trimmed = name.trim_end_matches('.');
+    if trimmed.is_empty() {
+        return;
+    }
+    let mut encoded_len = 1;
+    for (index, label) in trimmed.split('.').enumerate() {
+        if label.is_empty() {
+            violations.push(ResponseViolation {
+                path: path.clone(),
+                message: format!("empty label at position {} in '{}'", index, name),
+            });
+        } else if label.len() > MAX_LABEL_LEN {
+            violations.push(ResponseViolation {
+                path: path.clone(),
+                message: format!(
+                    "label {} is {} bytes, exceeds {} bytes: '{}'",
+                    index, label.len(), MAX_LABEL_LEN, label
+                ),
+            });
+        }
+        encoded_len += label.len() + 1;
+    }
+    if encoded_len > MAX_NAME_LEN {
+        violations.push(ResponseViolation {
+            path,
+            message: format!("name encodes to {} bytes, exceeds {} bytes", encoded_len, MAX_NAME_LEN),
+        });
+    }
+}
+
+/// 检查记录数据中的域名和TXT字符串
+fn check_record(path: &str, record: &Record, violations: &mut Vec<ResponseViolation>) {
+    check_name(format!("{}.name", path), &record.name, violations);
+    match &record.data {
+        RecordData::CNAME(name) | RecordData::NS(name) | RecordData::PTR(name) => {
+            check_name(format!("{}.data", path), name, violations);
+        },
+        RecordData::MX { exchange, .. } => {
+            check_name(format!("{}.data.exchange", path), exchange, violations);
+        },
+        RecordData::SRV { target, .. } => {
+            check_name(format!("{}.data.target", path), target, violations);
+        },
+        RecordData::SOA { mname, rname, .. } => {
+            check_name(format!("{}.data.mname", path), mname, violations);
+            check_name(format!("{}.data.rname", path), rname, violations);
+        },
+        RecordData::TXT(texts) => {
+            for (index, text) in texts.iter().enumerate() {
+                if text.len() > MAX_TXT_CHUNK_LEN {
+                    violations.push(ResponseViolation {
+                        path: format!("{}.data[{}]", path, index),
+                        message: format!("TXT string is {} bytes, exceeds {} bytes", text.len(), MAX_TXT_CHUNK_LEN),
+                    });
+                }
+            }
+        },
+        RecordData::Unknown(data) if data.len() > u16::MAX as usize => {
+            violations.push(ResponseViolation {
+                path: format!("{}.data", path),
+                message: format!("RDATA is {} bytes, exceeds {} bytes", data.len(), u16::MAX),
+            });
+        },
+        _ => {},
+    }
+}
+
+impl Response {
+    /// 列出所有无法按线路格式编码的字段（标签超过63字节、空标签、域名超过255字节、
+    /// TXT字符串超过255字节等），没有问题时返回空列表
+    pub fn violations(&self) -> Vec<ResponseViolation> {
+        let mut violations = Vec::new();
+        for (index, query) in self.queries.iter().enumerate() {
+            check_name(format!("queries[{}].name", index), &query.name, &mut violations);
+        }
+        let sections = [
+            ("answers", &self.answers),
+            ("authorities", &self.authorities),
+            ("additionals", &self.additionals),
+        ];
+        for (section, records) in sections {
+            for (index, record) in records.iter().enumerate() {
+                check_record(&format!("{}[{}]", section, index), record, &mut violations);
+            }
+        }
+        violations
+    }
+
+    /// 完整的响应码：头部的4位响应码加上OPT记录中的扩展RCODE高8位（RFC 6891 第6.1.3节）
+    /// 
+    /// 没有OPT记录时即头部响应码；BADCOOKIE（23）等扩展响应码只能这样取得
+    pub fn extended_rcode(&self) -> u16 {
+        let upper = self.additionals.iter()
+            .find(|record| u16::from(record.rtype) == crate::transport::OPT_RECORD_TYPE)
+            .map_or(0, |record| (record.ttl >> 24) as u16);
+        (upper << 4) | u16::from(self.flags.rcode & 0x0F)
+    }
+
+    /// 上游是否声明响应中的数据都经过了DNSSEC验证（头部AD位）
+    /// 
+    /// AD位只在到上游的链路可信时才有意义，解析器本身不做验证
+    pub fn authenticated_data(&self) -> bool {
+        self.flags.ad
+    }
+
+    /// 完整响应码对应的 [`ResponseCode`]
+    pub fn rcode(&self) -> ResponseCode {
+        ResponseCode::from(self.extended_rcode())
+    }
+
+    /// 设置完整响应码：低4位写入头部，高8位写入OPT记录的扩展RCODE字段
+    /// 
+    /// 已有OPT记录时只改写其扩展RCODE；响应码超过15又没有OPT记录时追加一条不带选项的OPT记录
+    pub fn set_rcode(&mut self, rcode: ResponseCode) {
+        use crate::transport::{OPT_RECORD_TYPE, UDP_EDNS_PAYLOAD_SIZE};
+
+        let code = u16::from(rcode);
+        self.flags.rcode = (code & 0x0F) as u8;
+        let upper = u32::from((code >> 4) & 0xFF) << 24;
+        match self.additionals.iter_mut().find(|record| u16::from(record.rtype) == OPT_RECORD_TYPE) {
+            Some(opt) => opt.ttl = (opt.ttl & 0x00FF_FFFF) | upper,
+            None if upper != 0 => self.additionals.push(Record {
+                name: String::new(),
+                rtype: RecordType::from(OPT_RECORD_TYPE),
+                class: QClass::from(UDP_EDNS_PAYLOAD_SIZE),
+                ttl: upper,
+                data: RecordData::Unknown(Vec::new()),
+            }),
+            None => {},
+        }
+    }
+    
+    /// 校验响应能否编码，失败时错误信息列出全部违规字段
+    pub fn validate(&self) -> Result<()> {
+        let violations = self.violations();
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(DnsError::Protocol(format!(
+            "response cannot be encoded: {}",
+            violations.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+        )))
+    }
+}
 
 /// DNS响应构建器
 #[derive(Debug, Clone)]
@@ -21,6 +213,8 @@ pub struct DnsResponseBuilder {
     authorities: Vec<Record>,
     /// 附加记录
     additionals: Vec<Record>,
+    /// 构建时写入的完整响应码（可能需要OPT记录）
+    rcode: Option<ResponseCode>,
 }
 
 impl Default for DnsResponseBuilder {
@@ -36,18 +230,21 @@ impl DnsResponseBuilder {
             id: 0,
             flags: Flags {
                 qr: true,  // 响应
-                opcode: 0, // 标准查询
+                opcode: Opcode::Query, // 标准查询
                 aa: false, // 非权威回答
                 tc: false, // 未截断
                 rd: true,  // 期望递归
                 ra: true,  // 递归可用
-                z: 0,      // 保留位
+                reserved: false,
+                ad: false, // 未经DNSSEC验证
+                cd: false, // 未禁用检查
                 rcode: 0,  // 无错误
             },
             queries: Vec::new(),
             answers: Vec::new(),
             authorities: Vec::new(),
             additionals: Vec::new(),
+            rcode: None,
         }
     }
 
@@ -60,6 +257,19 @@ impl DnsResponseBuilder {
     /// 设置响应码
     pub fn with_response_code(mut self, rcode: u8) -> Self {
         self.flags.rcode = rcode;
+        self.rcode = None;
+        self
+    }
+
+    /// 设置完整响应码，BADVERS等超过15的响应码在构建时写入OPT记录
+    pub fn with_rcode(mut self, rcode: ResponseCode) -> Self {
+        self.rcode = Some(rcode);
+        self
+    }
+
+    /// 设置操作码，通常回显请求中的值
+    pub fn with_opcode(mut self, opcode: Opcode) -> Self {
+        self.flags.opcode = opcode;
         self
     }
 
@@ -75,6 +285,18 @@ impl DnsResponseBuilder {
         self
     }
 
+    /// 设置已验证数据标志（AD）
+    pub fn with_authenticated_data(mut self, ad: bool) -> Self {
+        self.flags.ad = ad;
+        self
+    }
+
+    /// 设置禁用检查标志（CD），通常回显请求中的值
+    pub fn with_checking_disabled(mut self, cd: bool) -> Self {
+        self.flags.cd = cd;
+        self
+    }
+
     /// 添加查询问题
     pub fn add_query(mut self, name: String, qtype: RecordType, qclass: QClass) -> Self {
         self.queries.push(Query {
@@ -158,6 +380,7 @@ impl DnsResponseBuilder {
     }
 
     /// 添加SOA记录到回答部分
+    #[allow(clippy::too_many_arguments)]
     pub fn add_soa_answer(
         mut self,
         name: String,
@@ -189,13 +412,15 @@ impl DnsResponseBuilder {
     }
 
     /// 添加TXT记录到回答部分
+    /// 
+    /// 超过255字节的文本自动拆成多个字符串（见 [`split_txt`]），客户端按顺序拼接即可还原
     pub fn add_txt_answer(mut self, name: String, ttl: u32, texts: Vec<String>) -> Self {
         self.answers.push(Record {
             name,
             rtype: RecordType::TXT,
             class: QClass::IN,
             ttl,
-            data: RecordData::TXT(texts),
+            data: RecordData::TXT(texts.iter().flat_map(|text| split_txt(text)).collect()),
         });
         self
     }
@@ -238,15 +463,28 @@ impl DnsResponseBuilder {
     }
 
     /// 构建DNS响应
+    /// 
+    /// 不做校验；需要保证响应可以编码时使用 [`try_build`](Self::try_build)
     pub fn build(self) -> Response {
-        Response {
+        let mut response = Response {
             id: self.id,
             flags: self.flags,
             queries: self.queries,
             answers: self.answers,
             authorities: self.authorities,
             additionals: self.additionals,
+        };
+        if let Some(rcode) = self.rcode {
+            response.set_rcode(rcode);
         }
+        response
+    }
+
+    /// 构建DNS响应并校验，存在无法编码的字段时返回列出全部违规的错误
+    pub fn try_build(self) -> Result<Response> {
+        let response = self.build();
+        response.validate()?;
+        Ok(response)
     }
 }
 
@@ -453,4 +691,108 @@ mod tests {
         assert_eq!(response.answers.len(), 0);
         assert_eq!(response.queries[0].name, "notfound.com");
     }
+
+    #[test]
+    fn test_long_txt_chunked_and_round_trips() {
+        use crate::transport::UdpTransport;
+
+        let text: String = "v=spf1 include:_spf.example.com ".repeat(32);
+        assert_eq!(text.len(), 1024);
+        let response = DnsResponseBuilder::new()
+            .add_query("example.com".to_string(), RecordType::TXT, QClass::IN)
+            .add_txt_answer("example.com".to_string(), 300, vec![text.clone()])
+            .try_build()
+            .unwrap();
+
+        let RecordData::TXT(chunks) = &response.answers[0].data else {
+            panic!("Expected TXT record data");
+        };
+        assert_eq!(chunks.iter().map(String::len).collect::<Vec<_>>(), vec![255, 255, 255, 255, 4]);
+
+        let bytes = UdpTransport::serialize_response(&response).unwrap();
+        let parsed = UdpTransport::deserialize_response(&bytes).unwrap();
+        let RecordData::TXT(parsed_chunks) = &parsed.answers[0].data else {
+            panic!("Expected TXT record data");
+        };
+        assert_eq!(parsed_chunks.concat(), text);
+
+        // 多字节字符不会被从中间拆开
+        assert!(split_txt(&"域".repeat(100)).iter().all(|chunk| chunk.len() <= 255 && chunk.len() % 3 == 0));
+    }
+
+    #[test]
+    fn test_oversized_label_reported_with_path() {
+        let long_label = "a".repeat(64);
+        let result = DnsResponseBuilder::new()
+            .add_query("example.com".to_string(), RecordType::MX, QClass::IN)
+            .add_mx_answer("example.com".to_string(), 300, 10, format!("{}.example.com", long_label))
+            .add_cname_answer("bad..example.com".to_string(), 300, "example.com".to_string())
+            .try_build();
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("answers[0].data.exchange: label 0 is 64 bytes"), "{}", message);
+
+        let response = DnsResponseBuilder::new()
+            .add_mx_answer("example.com".to_string(), 300, 10, format!("{}.example.com", long_label))
+            .add_cname_answer("bad..example.com".to_string(), 300, "example.com".to_string())
+            .build();
+        let paths: Vec<_> = response.violations().into_iter().map(|v| v.path).collect();
+        assert_eq!(paths, vec!["answers[0].data.exchange", "answers[1].name"]);
+
+        let too_long = vec!["a".repeat(60); 5].join(".");
+        assert_eq!(DnsResponseWrapper::create_a_response(1, &too_long, &[], 300).violations().len(), 1);
+    }
+
+    #[test]
+    fn test_truncation_sets_tc_within_limit() {
+        use crate::transport::UdpTransport;
+
+        let ips: Vec<_> = (0..60).map(|i| Ipv4Addr::new(192, 0, 2, i)).collect();
+        let response = DnsResponseWrapper::create_a_response(7, "www.example.com", &ips, 300);
+        assert!(UdpTransport::serialize_response(&response).unwrap().len() > 512);
+
+        let bytes = UdpTransport::serialize_response_limited(&response, 512).unwrap();
+        assert!(bytes.len() <= 512);
+        let parsed = UdpTransport::deserialize_response(&bytes).unwrap();
+        assert!(parsed.flags.tc);
+        assert!(!parsed.answers.is_empty() && parsed.answers.len() < ips.len());
+
+        // 放得下时不截断
+        let bytes = UdpTransport::serialize_response_limited(&response, 4096).unwrap();
+        let parsed = UdpTransport::deserialize_response(&bytes).unwrap();
+        assert!(!parsed.flags.tc);
+        assert_eq!(parsed.answers.len(), ips.len());
+    }
+
+    #[test]
+    fn test_extended_rcode_round_trips_through_opt_record() {
+        use crate::transport::UdpTransport;
+
+        for code in 0..4096u16 {
+            assert_eq!(u16::from(ResponseCode::from(code)), code);
+        }
+
+        // 没有问题、只有一条OPT记录的响应：头部RCODE在第4字节低4位，扩展RCODE在OPT的TTL首字节（偏移17）
+        for (header_rcode, upper, expected) in [(0u8, 1u8, ResponseCode::BadVers), (7, 1, ResponseCode::BadCookie)] {
+            let mut packet = vec![0x12, 0x34, 0x81, 0x80 | header_rcode, 0, 0, 0, 0, 0, 0, 0, 1];
+            packet.extend_from_slice(&[0, 0, 41, 0x10, 0x00, upper, 0, 0, 0, 0, 0]);
+            let response = UdpTransport::deserialize_response(&packet).unwrap();
+            assert_eq!(response.flags.rcode, header_rcode);
+            assert_eq!(response.rcode(), expected);
+
+            let bytes = UdpTransport::serialize_response(&response).unwrap();
+            assert_eq!((bytes[3] & 0x0F, bytes[17]), (header_rcode, upper));
+        }
+
+        let mut response = DnsResponseBuilder::new().with_id(1).with_rcode(ResponseCode::BadCookie).build();
+        assert_eq!(response.additionals.len(), 1);
+        let bytes = UdpTransport::serialize_response(&response).unwrap();
+        assert_eq!((bytes[3] & 0x0F, bytes[17]), (7, 1));
+        assert_eq!(UdpTransport::deserialize_response(&bytes).unwrap().rcode(), ResponseCode::BadCookie);
+
+        // 改回头部能容纳的响应码时清掉扩展位，不重复追加OPT记录
+        response.set_rcode(ResponseCode::YxDomain);
+        assert_eq!((response.flags.rcode, response.extended_rcode()), (6, 6));
+        assert_eq!(response.additionals.len(), 1);
+        assert!(DnsResponseBuilder::new().with_rcode(ResponseCode::Refused).build().additionals.is_empty());
+    }
 }
\ No newline at end of file
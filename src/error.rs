@@ -2,6 +2,14 @@
 
 use std::fmt;
 use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::builder::consensus::QueryAllReport;
+use crate::builder::types::UpstreamFilter;
+use crate::resolver::diagnosis::QueryFailureReport;
+use crate::transport::TransportPhase;
 
 /// DNS查询结果类型
 pub type Result<T> = std::result::Result<T, DnsError>;
@@ -14,11 +22,21 @@ pub enum DnsError {
     /// 协议错误
     Protocol(String),
     /// 超时错误
-    Timeout,
+    Timeout {
+        /// 传输发送超时时正在进行的阶段；不是传输发送的超时（例如区域传送、DDR探测）为 `None`
+        phase: Option<TransportPhase>,
+    },
     /// 解析错误
     Parse(String),
     /// 网络错误
-    Network(String),
+    Network {
+        /// 套接字错误类型
+        kind: NetworkErrorKind,
+        /// 上游地址，与具体上游无关时为空
+        upstream: String,
+        /// 底层错误信息
+        message: String,
+    },
     /// TLS错误
     Tls(String),
     /// HTTP错误
@@ -31,6 +49,13 @@ pub enum DnsError {
     Server(String),
     /// 域名不存在
     NxDomain,
+    /// 域名存在但没有所查询类型的记录（NODATA）
+    NoRecords {
+        /// 查询的域名
+        domain: String,
+        /// 应答中实际出现的记录类型（例如只有CNAME），为空表示应答段为空
+        answer_types: Vec<String>,
+    },
     /// 查询被拒绝
     Refused,
     /// 服务器失败
@@ -40,7 +65,281 @@ pub enum DnsError {
     /// 未实现
     NotImplemented(String),
     /// 无可用上游服务器
-    NoUpstreamAvailable,
+    NoUpstreamAvailable {
+        /// 查询带有上游筛选时为该筛选，筛选后没有上游
+        filter: Option<UpstreamFilter>,
+    },
+    /// 服务明确不可用（如SRV目标为"."）
+    ServiceUnavailable(String),
+    /// 上游返回了非成功的HTTP状态码（DoH）
+    HttpStatus {
+        /// HTTP状态码
+        status: u16,
+        /// 上游地址
+        upstream: String,
+        /// 响应正文开头（最多200个字符）
+        body_snippet: Option<String>,
+        /// 响应头 `Retry-After` 要求的等待时长（秒数和HTTP日期两种形式都换算为时长），没有该响应头时为 `None`
+        retry_after: Option<Duration>,
+    },
+    /// DoH上游返回的Content-Type不是 `application/dns-message`（例如CDN返回的HTML错误页）
+    UnexpectedContentType {
+        /// 实际的Content-Type，没有该响应头时为空
+        content_type: String,
+        /// 上游地址
+        upstream: String,
+    },
+    /// DoH响应正文超过上限，已中止读取
+    ResponseTooLarge {
+        /// 正文长度上限（字节）
+        limit: usize,
+        /// 上游地址
+        upstream: String,
+    },
+    /// 与上游的TLS握手失败（DoT/DoH）
+    TlsFailure {
+        /// 失败类型
+        kind: TlsErrorKind,
+        /// 上游地址
+        upstream: String,
+    },
+    /// 同时进行的上游发送已达 `concurrent_queries` 上限，且在等待时限内没有空出名额
+    Busy {
+        /// 并发发送上限
+        limit: usize,
+    },
+    /// 上游的在途查询已达 `max_inflight` 上限，本次查询跳过该上游
+    UpstreamSaturated {
+        /// 上游名称
+        upstream: String,
+        /// 在途查询上限
+        limit: usize,
+    },
+    /// 加密上游因握手失败处于隔离期，本次查询跳过该上游，见 [`TlsQuarantineConfig`](crate::resolver::quarantine::TlsQuarantineConfig)
+    Quarantined {
+        /// 上游名称
+        upstream: String,
+        /// 距下一次重新接纳探测的时长
+        retry_in: Duration,
+    },
+    /// 解析器处于离线模式，缓存中没有该查询的答案
+    Offline {
+        /// 查询的域名
+        name: String,
+    },
+    /// 作用域要求DNSSEC，应答未经上游验证（AD位未置位），见 [`ScopeOverrides::require_dnssec`](crate::builder::ScopeOverrides::require_dnssec)
+    DnssecRequired {
+        /// 查询的域名
+        name: String,
+    },
+    /// 按主机名建立TCP连接时，解析出的地址都没有连上
+    ConnectFailed {
+        /// 目标主机名
+        host: String,
+        /// 目标端口
+        port: u16,
+        /// 按尝试顺序排列的各地址的失败，为空表示没有地址族偏好允许的地址
+        attempts: Vec<ConnectAttemptError>,
+    },
+    /// 法定人数策略下，给出相同答案的上游数没有达到要求
+    QuorumNotReached {
+        /// 查询的域名
+        name: String,
+        /// 要求给出相同答案的上游数
+        required: usize,
+        /// 各上游的答案和一致性汇总，`consensus.dissenting` 列出与多数答案不同的上游
+        report: Box<QueryAllReport>,
+    },
+    /// 查询发往的多个上游全部失败（出错或应答SERVFAIL/REFUSED）
+    AllUpstreamsFailed {
+        /// 各上游的结果和可能原因的提示
+        report: Box<QueryFailureReport>,
+    },
+}
+
+/// 按主机名建立连接时单个地址的失败
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectAttemptError {
+    /// 尝试连接的地址
+    pub addr: SocketAddr,
+    /// 套接字错误类型，超过单次连接时限为 `TimedOut`
+    pub kind: NetworkErrorKind,
+    /// 底层错误信息
+    pub message: String,
+}
+
+/// TLS失败类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsErrorKind {
+    /// 证书无效（不受信任、已过期、名称不匹配等）
+    CertificateInvalid,
+    /// 握手超时
+    HandshakeTimeout,
+    /// 证书与固定的公钥不符
+    PinMismatch,
+    /// 其他TLS错误
+    Other,
+}
+
+impl TlsErrorKind {
+    /// 按rustls错误分类
+    #[cfg(feature = "dot")]
+    pub fn from_rustls(err: &tokio_rustls::rustls::Error) -> Self {
+        use tokio_rustls::rustls::Error;
+        match err {
+            Error::InvalidCertificate(_) | Error::NoCertificatesPresented => Self::CertificateInvalid,
+            Error::General(msg) if msg.to_ascii_lowercase().contains("pin") => Self::PinMismatch,
+            _ => Self::Other,
+        }
+    }
+
+    /// 按错误描述分类（底层TLS库的错误类型不可用时）
+    pub fn from_message(message: &str) -> Self {
+        let message = message.to_ascii_lowercase();
+        if message.contains("pin") && message.contains("mismatch") {
+            Self::PinMismatch
+        } else if message.contains("certificate") || message.contains("unknownissuer") {
+            Self::CertificateInvalid
+        } else if message.contains("timed out") || message.contains("timeout") {
+            Self::HandshakeTimeout
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// 套接字错误类型
+///
+/// 由 `io::ErrorKind` 得出，标准库没有对应类型的少数错误码按 `raw_os_error` 补充；
+/// 拒绝连接多半是防火墙或服务未监听，不可达是路由问题，超时则可能只是上游慢，三者需要不同的告警
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NetworkErrorKind {
+    /// 对端拒绝连接（ECONNREFUSED）
+    ConnectionRefused,
+    /// 主机不可达（EHOSTUNREACH、EHOSTDOWN）
+    HostUnreachable,
+    /// 网络不可达（ENETUNREACH、ENETDOWN）
+    NetworkUnreachable,
+    /// 本机禁止该操作（EACCES、EPERM，例如本地防火墙）
+    PermissionDenied,
+    /// 本机没有可用的地址（EADDRNOTAVAIL，例如只有IPv4的主机访问IPv6上游）
+    AddrNotAvailable,
+    /// 连接已被对端关闭（EPIPE）
+    BrokenPipe,
+    /// 套接字操作超时（ETIMEDOUT）
+    TimedOut,
+    /// 其他错误
+    Other,
+}
+
+impl NetworkErrorKind {
+    /// 按IO错误分类
+    pub fn from_io(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::ConnectionRefused => Self::ConnectionRefused,
+            io::ErrorKind::HostUnreachable => Self::HostUnreachable,
+            io::ErrorKind::NetworkUnreachable | io::ErrorKind::NetworkDown => Self::NetworkUnreachable,
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+            io::ErrorKind::AddrNotAvailable => Self::AddrNotAvailable,
+            io::ErrorKind::BrokenPipe => Self::BrokenPipe,
+            io::ErrorKind::TimedOut => Self::TimedOut,
+            _ => Self::from_raw_os_error(err.raw_os_error()),
+        }
+    }
+    
+    /// 标准库归入 `Uncategorized` 的错误码
+    fn from_raw_os_error(code: Option<i32>) -> Self {
+        // EHOSTDOWN
+        #[cfg(target_os = "linux")]
+        const HOST_DOWN: i32 = 112;
+        #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+        const HOST_DOWN: i32 = 64;
+        #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "ios", target_os = "freebsd")))]
+        const HOST_DOWN: i32 = -1;
+        
+        match code {
+            Some(code) if code == HOST_DOWN => Self::HostUnreachable,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// 查询失败后的重试建议
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryAdvice {
+    /// 临时故障，可以在同一上游重试
+    Retry,
+    /// 上游过载或暂停服务（HTTP 429/503），应暂时避开该上游、改用其他上游
+    Backoff,
+    /// 上游本身不可用（证书无效等），重试同一上游没有意义
+    Unavailable,
+    /// 请求本身被拒绝（如HTTP 400），不应重试
+    Fatal,
+    /// 上游的在途查询已满或处于隔离期，本次查询改用其他上游；请求没有发出，不影响对该上游的健康判定
+    Skip,
+}
+
+impl DnsError {
+    /// 与具体套接字错误无关的网络错误
+    pub fn network(upstream: impl Into<String>, message: impl Into<String>) -> Self {
+        DnsError::Network { kind: NetworkErrorKind::Other, upstream: upstream.into(), message: message.into() }
+    }
+    
+    /// 由套接字IO错误得出的网络错误，`context` 说明失败的操作
+    pub fn network_io(upstream: impl Into<String>, context: &str, err: &io::Error) -> Self {
+        DnsError::Network {
+            kind: NetworkErrorKind::from_io(err),
+            upstream: upstream.into(),
+            message: format!("{}: {}", context, err),
+        }
+    }
+    
+    /// 套接字层面的错误类型：网络错误取其类型，查询超时视为 `TimedOut`，其他错误为 `None`
+    pub fn network_kind(&self) -> Option<NetworkErrorKind> {
+        match self {
+            DnsError::Network { kind, .. } => Some(*kind),
+            DnsError::Timeout { .. } => Some(NetworkErrorKind::TimedOut),
+            _ => None,
+        }
+    }
+    
+    /// 是否为上游限流（HTTP 429）
+    pub fn is_rate_limited(&self) -> bool {
+        matches!(self, DnsError::HttpStatus { status: 429, .. })
+    }
+    
+    /// 上游通过 `Retry-After` 要求的等待时长
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            DnsError::HttpStatus { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+    
+    /// 该错误的重试建议
+    pub fn retry_advice(&self) -> RetryAdvice {
+        match self {
+            DnsError::HttpStatus { status: 429 | 503, .. } => RetryAdvice::Backoff,
+            DnsError::HttpStatus { status: 400..=499, .. } => RetryAdvice::Fatal,
+            // 未跟随的重定向、错误的Content-Type和超大正文都是上游部署问题，重试同一上游没有意义
+            DnsError::HttpStatus { status: 300..=399, .. }
+            | DnsError::UnexpectedContentType { .. }
+            | DnsError::ResponseTooLarge { .. } => RetryAdvice::Unavailable,
+            DnsError::TlsFailure { kind: TlsErrorKind::HandshakeTimeout, .. } => RetryAdvice::Retry,
+            DnsError::TlsFailure { .. } => RetryAdvice::Unavailable,
+            DnsError::InvalidConfig(_)
+            | DnsError::Config(_)
+            | DnsError::NotImplemented(_)
+            | DnsError::NxDomain
+            | DnsError::NoRecords { .. }
+            | DnsError::ServiceUnavailable(_)
+            | DnsError::Busy { .. }
+            | DnsError::Offline { .. }
+            | DnsError::DnssecRequired { .. } => RetryAdvice::Fatal,
+            DnsError::UpstreamSaturated { .. } | DnsError::Quarantined { .. } => RetryAdvice::Skip,
+            _ => RetryAdvice::Retry,
+        }
+    }
 }
 
 impl fmt::Display for DnsError {
@@ -48,20 +347,90 @@ impl fmt::Display for DnsError {
         match self {
             DnsError::Io(msg) => write!(f, "IO error: {}", msg),
             DnsError::Protocol(msg) => write!(f, "Protocol error: {}", msg),
-            DnsError::Timeout => write!(f, "Request timeout"),
+            DnsError::Timeout { phase: None } => write!(f, "Request timeout"),
+            DnsError::Timeout { phase: Some(phase) } => write!(f, "Request timeout during {}", phase),
             DnsError::Parse(msg) => write!(f, "Parse error: {}", msg),
-            DnsError::Network(msg) => write!(f, "Network error: {}", msg),
+            DnsError::Network { kind, upstream, message } => {
+                write!(f, "Network error")?;
+                if *kind != NetworkErrorKind::Other {
+                    write!(f, " ({:?})", kind)?;
+                }
+                if !upstream.is_empty() {
+                    write!(f, " with {}", upstream)?;
+                }
+                write!(f, ": {}", message)
+            },
             DnsError::Tls(msg) => write!(f, "TLS error: {}", msg),
             DnsError::Http(msg) => write!(f, "HTTP error: {}", msg),
             DnsError::Config(msg) => write!(f, "Config error: {}", msg),
             DnsError::InvalidConfig(msg) => write!(f, "Invalid config: {}", msg),
             DnsError::Server(msg) => write!(f, "Server error: {}", msg),
             DnsError::NxDomain => write!(f, "Domain not found"),
+            DnsError::NoRecords { domain, answer_types } => {
+                write!(f, "No records of the requested type for {}", domain)?;
+                if !answer_types.is_empty() {
+                    write!(f, " (answer contained {})", answer_types.join(", "))?;
+                }
+                Ok(())
+            },
             DnsError::Refused => write!(f, "Query refused"),
             DnsError::ServerFailure => write!(f, "Server failure"),
             DnsError::FormatError => write!(f, "Format error"),
             DnsError::NotImplemented(msg) => write!(f, "Not implemented: {}", msg),
-            DnsError::NoUpstreamAvailable => write!(f, "No upstream server available"),
+            DnsError::NoUpstreamAvailable { filter: None } => write!(f, "No upstream server available"),
+            DnsError::NoUpstreamAvailable { filter: Some(filter) } => {
+                write!(f, "No upstream server available with upstream filter {}", filter)
+            }
+            DnsError::ServiceUnavailable(msg) => write!(f, "Service not available: {}", msg),
+            DnsError::HttpStatus { status, upstream, body_snippet, retry_after } => {
+                write!(f, "HTTP {} from {}", status, upstream)?;
+                if let Some(retry_after) = retry_after {
+                    write!(f, " (retry after {:?})", retry_after)?;
+                }
+                match body_snippet {
+                    Some(body) => write!(f, ": {}", body),
+                    None => Ok(()),
+                }
+            },
+            DnsError::UnexpectedContentType { content_type, upstream } => {
+                write!(f, "Unexpected content type '{}' from {}, expected 'application/dns-message'", content_type, upstream)
+            },
+            DnsError::ResponseTooLarge { limit, upstream } => {
+                write!(f, "Response from {} exceeds {} bytes", upstream, limit)
+            },
+            DnsError::TlsFailure { kind, upstream } => write!(f, "TLS error with {}: {:?}", upstream, kind),
+            DnsError::Busy { limit } => write!(f, "Resolver busy: {} concurrent upstream queries in flight", limit),
+            DnsError::UpstreamSaturated { upstream, limit } => {
+                write!(f, "Upstream {} saturated: {} queries in flight", upstream, limit)
+            },
+            DnsError::Quarantined { upstream, retry_in } => {
+                write!(f, "Upstream {} quarantined after TLS handshake failure, next probe in {:?}", upstream, retry_in)
+            },
+            DnsError::Offline { name } => write!(f, "Resolver offline: no cached answer for {}", name),
+            DnsError::DnssecRequired { name } => write!(f, "DNSSEC required: answer for {} is not authenticated", name),
+            DnsError::ConnectFailed { host, port, attempts } => {
+                write!(f, "Failed to connect to {}:{}", host, port)?;
+                if attempts.is_empty() {
+                    return write!(f, ": no usable address");
+                }
+                for (i, attempt) in attempts.iter().enumerate() {
+                    let separator = if i == 0 { ": " } else { "; " };
+                    write!(f, "{}{} ({:?}): {}", separator, attempt.addr, attempt.kind, attempt.message)?;
+                }
+                Ok(())
+            },
+            DnsError::QuorumNotReached { name, required, report } => {
+                write!(f, "Quorum of {} upstreams not reached for {}", required, name)?;
+                for (i, answer) in report.answers.iter().enumerate() {
+                    let separator = if i == 0 { ": " } else { "; " };
+                    match &answer.result {
+                        Ok(set) => write!(f, "{}{} answered [{}]", separator, answer.upstream, set.join(", "))?,
+                        Err(e) => write!(f, "{}{} failed ({})", separator, answer.upstream, e)?,
+                    }
+                }
+                Ok(())
+            },
+            DnsError::AllUpstreamsFailed { report } => write!(f, "{}", report),
         }
     }
 }
@@ -74,16 +443,88 @@ impl From<io::Error> for DnsError {
     }
 }
 
-#[cfg(feature = "tokio-rustls")]
+#[cfg(feature = "dot")]
 impl From<tokio_rustls::rustls::Error> for DnsError {
     fn from(err: tokio_rustls::rustls::Error) -> Self {
         DnsError::Tls(err.to_string())
     }
 }
 
-#[cfg(feature = "reqwest")]
+#[cfg(feature = "doh")]
 impl From<reqwest::Error> for DnsError {
     fn from(err: reqwest::Error) -> Self {
         DnsError::Http(err.to_string())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http(status: u16) -> DnsError {
+        DnsError::HttpStatus { status, upstream: "https://dns.example/dns-query".to_string(), body_snippet: None, retry_after: None }
+    }
+
+    #[test]
+    fn test_retry_advice() {
+        assert_eq!(http(429).retry_advice(), RetryAdvice::Backoff);
+        assert_eq!(http(503).retry_advice(), RetryAdvice::Backoff);
+        assert_eq!(http(400).retry_advice(), RetryAdvice::Fatal);
+        assert_eq!(http(502).retry_advice(), RetryAdvice::Retry);
+        assert_eq!(http(302).retry_advice(), RetryAdvice::Unavailable);
+        assert_eq!(DnsError::Timeout { phase: None }.retry_advice(), RetryAdvice::Retry);
+
+        let tls = |kind| DnsError::TlsFailure { kind, upstream: "9.9.9.9:853".to_string() };
+        assert_eq!(tls(TlsErrorKind::CertificateInvalid).retry_advice(), RetryAdvice::Unavailable);
+        assert_eq!(tls(TlsErrorKind::HandshakeTimeout).retry_advice(), RetryAdvice::Retry);
+    }
+
+    #[test]
+    fn test_network_error_classification() {
+        use io::ErrorKind;
+        let cases = [
+            (ErrorKind::ConnectionRefused, NetworkErrorKind::ConnectionRefused),
+            (ErrorKind::HostUnreachable, NetworkErrorKind::HostUnreachable),
+            (ErrorKind::NetworkUnreachable, NetworkErrorKind::NetworkUnreachable),
+            (ErrorKind::PermissionDenied, NetworkErrorKind::PermissionDenied),
+            (ErrorKind::AddrNotAvailable, NetworkErrorKind::AddrNotAvailable),
+            (ErrorKind::BrokenPipe, NetworkErrorKind::BrokenPipe),
+            (ErrorKind::TimedOut, NetworkErrorKind::TimedOut),
+            (ErrorKind::ConnectionReset, NetworkErrorKind::Other),
+        ];
+        for (io_kind, expected) in cases {
+            assert_eq!(NetworkErrorKind::from_io(&io::Error::from(io_kind)), expected, "{:?}", io_kind);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            // ECONNREFUSED、EHOSTUNREACH、EHOSTDOWN
+            assert_eq!(NetworkErrorKind::from_io(&io::Error::from_raw_os_error(111)), NetworkErrorKind::ConnectionRefused);
+            assert_eq!(NetworkErrorKind::from_io(&io::Error::from_raw_os_error(113)), NetworkErrorKind::HostUnreachable);
+            assert_eq!(NetworkErrorKind::from_io(&io::Error::from_raw_os_error(112)), NetworkErrorKind::HostUnreachable);
+        }
+
+        let refused = DnsError::network_io("127.0.0.1:53", "Connection failed", &io::Error::from(ErrorKind::ConnectionRefused));
+        assert_eq!(refused.network_kind(), Some(NetworkErrorKind::ConnectionRefused));
+        assert!(refused.to_string().starts_with("Network error (ConnectionRefused) with 127.0.0.1:53: Connection failed"));
+        assert_eq!(DnsError::Timeout { phase: None }.network_kind(), Some(NetworkErrorKind::TimedOut));
+        assert_eq!(DnsError::network("", "mock failure").to_string(), "Network error: mock failure");
+    }
+
+    #[test]
+    fn test_tls_error_classification() {
+        #[cfg(feature = "dot")]
+        {
+            use tokio_rustls::rustls::{CertificateError, Error};
+            assert_eq!(
+                TlsErrorKind::from_rustls(&Error::InvalidCertificate(CertificateError::UnknownIssuer)),
+                TlsErrorKind::CertificateInvalid
+            );
+            assert_eq!(TlsErrorKind::from_rustls(&Error::HandshakeNotComplete), TlsErrorKind::Other);
+        }
+        assert_eq!(
+            TlsErrorKind::from_message("error trying to connect: invalid peer certificate: UnknownIssuer"),
+            TlsErrorKind::CertificateInvalid
+        );
+        assert_eq!(TlsErrorKind::from_message("connection reset"), TlsErrorKind::Other);
+    }
+}
@@ -6,6 +6,11 @@
 #![warn(clippy::all)]
 #![deny(unsafe_code)]
 
+#[cfg(all(target_arch = "wasm32", not(feature = "wasm-doh")))]
+compile_error!("wasm32 builds require the wasm-doh feature: --no-default-features --features wasm-doh");
+#[cfg(all(target_arch = "wasm32", any(feature = "tcp", feature = "doh3", feature = "python-bindings", feature = "http-resolver")))]
+compile_error!("wasm32 builds only support the DoH transport: build with --no-default-features --features wasm-doh");
+
 pub mod types;
 pub mod transport;
 pub mod resolver;
@@ -16,28 +21,57 @@ pub mod dns_response;
 pub mod logger;
 pub mod config;
 pub mod utils;
+pub mod time;
+pub(crate) mod runtime;
+pub mod oneshot;
+pub mod registry;
 
 #[cfg(feature = "python-bindings")]
 pub mod python_api;
 
 pub use types::*;
-pub use transport::Transport;
-pub use resolver::CoreResolver;
+pub use transport::{AddressFamily, AddressFamilyStats, AfPreference, BootstrapResolver, FamilyPathStats, HostResolution, PoolExhaustion, RecordLimitPolicy, RecordLimits, Transport, UdpPoolConfig, UdpPoolStats};
+#[cfg(feature = "dot")]
+pub use transport::TlsSessionStats;
+#[cfg(feature = "tcp")]
+pub use transport::FastOpenStats;
+pub use resolver::{CoreResolver, ResponseOrigin, TransportInfo, UpstreamFailover};
+pub use resolver::answer_rewrite::{AnswerRewriteRule, RewriteRuleStats, RewriteStage};
+pub use resolver::ttl_override::{OverrideTtl, TtlOverrideRule, TtlOverrideStats};
+pub use resolver::cache::{CacheClearReport, CacheInvalidation, CacheJanitorConfig, CacheStats, EcsCacheMode};
+pub use resolver::cache_backend::{DnsCacheBackend, ShardedMemoryCache};
+pub use resolver::cache_insights::{CacheInsights, HotName, TtlBucket};
+pub use resolver::health::{ProbeConfig, ProbeOutcome, ProbeReason, StatusTrigger, UpstreamEvent};
+pub use resolver::slo::{QueryObserver, SloConfig, SloStatus};
+pub use resolver::encrypted_fallback::{EncryptedFallbackPolicy, EncryptedFallbackStats};
+pub use resolver::priority::{PriorityStats, QueryPriority};
+pub use resolver::quarantine::{QuarantineStatus, TlsQuarantineConfig};
+pub use resolver::rate_limit::RateLimitedBehavior;
+pub use resolver::memory::{ComponentMemory, MemoryComponent, MemoryStats};
+pub use resolver::offline::{OfflineReason, OfflineStats};
 pub use builder::resolver::CoreResolverStats;
-pub use error::{DnsError, Result};
+pub use error::{ConnectAttemptError, DnsError, NetworkErrorKind, Result, RetryAdvice, TlsErrorKind};
 pub use builder::{
-    DnsResolverBuilder, SmartDnsResolver, DnsQueryRequest, DnsQueryResponse, DnsRecord,
-    QueryStrategy, PerformanceMetrics, SmartDecisionEngine, LoggerInitStrategy
+    DnsResolverBuilder, SmartDnsResolver, DnsQueryRequest, DnsQueryResponse, DnsRecord, RecordSort, QueryContext, UpstreamFilter, QueryId, QueryIdFormat, TenantStats, CdnProbe,
+    StickinessConfig, StickinessStats, StickyKey, DnsDiff, DnsDiffReport, EncodedRequestLimits, RequestErrorCode, AdaptiveTimeoutConfig,
+    QueryStrategy, PerformanceMetrics, SmartDecisionEngine, LoggerInitStrategy, Preset,
+    serialize_batch_json, deserialize_batch_json, EffectiveConfig, ReloadReport
 };
 pub use builder::resolver::UpstreamStatus;
-pub use dns_response::{DnsResponseBuilder, DnsResponseWrapper};
-pub use logger::{init_dns_logger, init_dns_logger_silent, dns_format};
+pub use dns_response::{DnsResponseBuilder, DnsResponseWrapper, ResponseViolation};
+pub use oneshot::{OneshotOptions, OneshotUpstream};
+pub use logger::{init_dns_logger, init_dns_logger_silent};
+#[cfg(not(target_arch = "wasm32"))]
+pub use logger::dns_format;
 
 // DNS日志宏已经通过#[macro_export]自动导出到crate根部，无需重新导出
-pub use config::{StrictDnsConfig, StrictConfigBuilder, ConfigError};
+pub use config::{StrictDnsConfig, StrictConfigBuilder, ConfigError, ConfigIssue, ConfigIssueKind, ConfigSeverity, SearchDomains, SystemResolvConf};
 
-// 重新导出rat_logger基础日志宏到crate根部，供DNS宏使用
+// 重新导出rat_logger基础日志宏到crate根部，供DNS宏使用；wasm32上没有rat_logger，改用tracing的同名宏
+#[cfg(not(target_arch = "wasm32"))]
 pub use rat_logger::{error, warn, info, debug, trace};
+#[cfg(target_arch = "wasm32")]
+pub use tracing::{error, warn, info, debug, trace};
 
 
 // 注意：移除了 quick_dns 宏，因为它包含兜底行为
@@ -60,5 +94,6 @@ pub use rat_logger::{error, warn, info, debug, trace};
 //       .enable_stats(true)
 //       .emergency_threshold(0.3)
 //       .add_upstream(UpstreamSpec::new("8.8.8.8:53".to_string(), "udp".to_string(), 1))
+//       .add_upstream(UpstreamSpec::new("1.1.1.1:53".to_string(), "udp".to_string(), 1))
 //       .build()?;
-//   SmartDnsResolver::from_config(config)?
\ No newline at end of file
+//   DnsResolverBuilder::from_strict_config(&config, true, "global")?.build().await?
\ No newline at end of file
@@ -10,11 +10,39 @@
 //! ## 推荐的初始化流程
 //!
 //! 调用者需要先初始化rat_logger系统，然后才能使用DNS日志功能。
+//!
+//! ## 由构造器安装日志处理器
+//!
+//! 没有自己的日志系统时，构造器可以按 `LoggerInitStrategy` 调用
+//! [`install_dns_logger`] 安装终端处理器。rat_logger 的全局处理器只能安装一次，
+//! 因此本模块记录全局处理器的归属：首次安装之后再构建解析器只会调整级别；
+//! 调用方通过 [`attach_caller_logger`] 声明处理器由自己安装后，本库不会再尝试安装。
+//!
+//! ## 查询摘要
+//!
+//! 开启 `log_query_summary` 后，每次查询结束时以info级别输出一行摘要，查询过程中各步骤的info日志降为debug，
+//! 行格式见 [`crate::builder::query_summary`]。
+//!
+//! ## wasm32
+//!
+//! wasm32-unknown-unknown 上没有 rat_logger，`Level`/`LevelFilter` 由本模块定义，
+//! DNS日志以 `tracing` 事件输出（调用方可以用 tracing-wasm 之类的订阅者转到浏览器控制台），
+//! [`install_dns_logger`] 不安装处理器，只设置级别。
 
-use rat_logger::{Level, LevelFilter};
+#[cfg(not(target_arch = "wasm32"))]
+pub use rat_logger::{Level, LevelFilter};
+#[cfg(not(target_arch = "wasm32"))]
+use rat_logger::{LoggerBuilder, handler::term::TermConfig};
+#[cfg(target_arch = "wasm32")]
+pub use levels::{Level, LevelFilter};
+use std::future::Future;
+#[cfg(not(target_arch = "wasm32"))]
 use std::io::Write;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
 use chrono::Local;
+use crate::error::{DnsError, Result};
 
 /// 确保日志器只初始化一次
 static INIT: std::sync::Once = std::sync::Once::new();
@@ -22,7 +50,24 @@ static INIT: std::sync::Once = std::sync::Once::new();
 /// 日志初始化状态标志（线程安全）
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
 
+/// 全局日志处理器的归属
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoggerBackend {
+    /// 由本库安装的终端处理器
+    Dns,
+    /// 由调用方安装的处理器，本库只调整级别
+    Caller,
+}
+
+/// 全局处理器归属和当前生效的DNS日志级别，同一把锁保证最多安装一次
+static BACKEND: Mutex<Option<(LoggerBackend, LevelFilter)>> = Mutex::new(None);
+
+fn lock_backend() -> std::sync::MutexGuard<'static, Option<(LoggerBackend, LevelFilter)>> {
+    BACKEND.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
 /// DNS 查询专用日志格式化器
+#[cfg(not(target_arch = "wasm32"))]
 pub fn dns_format(
     buf: &mut dyn std::io::Write,
     record: &rat_logger::config::Record
@@ -68,23 +113,20 @@ pub fn dns_format(
 /// 使用方法：
 /// 1. 首先初始化rat_logger系统（调用者责任）
 /// 2. 然后调用此函数初始化DNS日志格式
-pub fn init_dns_logger(level: LevelFilter) -> Result<(), Box<dyn std::error::Error>> {
-    // 检查rat_logger是否已经初始化
-    // 注意：这里我们只是设置一个标志，实际的日志系统初始化由调用者负责
-    INIT.call_once(|| {
-        INITIALIZED.store(true, Ordering::SeqCst);
-    });
-
-    // 设置rat_logger的全局日志级别
-    rat_logger::core::set_max_level(level);
-
+pub fn init_dns_logger(level: LevelFilter) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    // 实际的日志系统初始化由调用者负责，这里只声明归属并设置全局日志级别；
+    // 本库已自行安装终端处理器时仅调整级别
+    let mut backend = lock_backend();
+    let current = *backend;
+    let owner = current.map_or(LoggerBackend::Caller, |(owner, _)| owner);
+    apply_level(&mut backend, owner, level);
     Ok(())
 }
 
 /// 安全的日志初始化函数，默认禁用日志输出
 ///
 /// 这个函数专门用于构造器，确保默认情况下不输出日志
-pub fn init_dns_logger_silent() -> Result<(), Box<dyn std::error::Error>> {
+pub fn init_dns_logger_silent() -> std::result::Result<(), Box<dyn std::error::Error>> {
     init_dns_logger(LevelFilter::Off)
 }
 
@@ -93,6 +135,246 @@ pub fn is_dns_logger_initialized() -> bool {
     INITIALIZED.load(Ordering::SeqCst)
 }
 
+/// 安装本库的终端日志处理器并设置级别（进程内最多安装一次）
+///
+/// 已安装过时只调整级别；处理器已声明归调用方时同样只调整级别。
+/// 全局处理器被未声明的第三方占用导致安装失败时返回错误，而不是panic，
+/// 这种情况应改用 [`attach_caller_logger`]。
+pub fn install_dns_logger(level: LevelFilter) -> Result<LoggerBackend> {
+    let mut backend = lock_backend();
+    let current = *backend;
+    let owner = match current {
+        Some((owner, _)) => owner,
+        #[cfg(target_arch = "wasm32")]
+        None => LoggerBackend::Dns,
+        #[cfg(not(target_arch = "wasm32"))]
+        None => {
+            LoggerBuilder::new()
+                .with_level(level)
+                .add_terminal_with_config(TermConfig::default())
+                .init_global_logger()
+                .map_err(|e| DnsError::InvalidConfig(format!(
+                    "Failed to install the DNS logger: {}. If the application already installed a rat_logger handler, \
+                     use LoggerInitStrategy::External to route DNS logs into it", e
+                )))?;
+            LoggerBackend::Dns
+        }
+    };
+    apply_level(&mut backend, owner, level);
+    Ok(owner)
+}
+
+/// 声明全局处理器由调用方安装，DNS日志写入该处理器，本库只设置级别
+///
+/// 本库已经安装了自己的终端处理器时返回错误：全局处理器只有一个，无法再切换到调用方。
+pub fn attach_caller_logger(level: LevelFilter) -> Result<()> {
+    let mut backend = lock_backend();
+    let current = *backend;
+    if let Some((LoggerBackend::Dns, _)) = current {
+        return Err(DnsError::InvalidConfig(
+            "The DNS terminal logger is already installed in this process; \
+             the caller's rat_logger handler can no longer be attached".to_string()
+        ));
+    }
+    apply_level(&mut backend, LoggerBackend::Caller, level);
+    Ok(())
+}
+
+/// 当前全局处理器的归属，尚未安装或声明时为 `None`
+pub fn dns_logger_backend() -> Option<LoggerBackend> {
+    let current = *lock_backend();
+    current.map(|(owner, _)| owner)
+}
+
+/// 最近一次由本库设置的日志级别，从未设置时为 `None`
+pub fn effective_dns_log_level() -> Option<LevelFilter> {
+    let current = *lock_backend();
+    current.map(|(_, level)| level)
+}
+
+fn apply_level(
+    backend: &mut Option<(LoggerBackend, LevelFilter)>,
+    owner: LoggerBackend,
+    level: LevelFilter,
+) {
+    #[cfg(not(target_arch = "wasm32"))]
+    rat_logger::core::set_max_level(level);
+    #[cfg(target_arch = "wasm32")]
+    levels::set_max_level(level);
+    INIT.call_once(|| {
+        INITIALIZED.store(true, Ordering::SeqCst);
+    });
+    *backend = Some((owner, level));
+}
+
+/// 开启查询摘要的一次查询中的状态：步骤日志降为debug，并记下摘要需要、响应中却没有的事实
+#[derive(Debug, Default)]
+pub(crate) struct SummaryNotes {
+    served_stale: AtomicBool,
+}
+
+impl SummaryNotes {
+    /// 查询是否由过期的缓存条目应答
+    pub(crate) fn served_stale(&self) -> bool {
+        self.served_stale.load(Ordering::Relaxed)
+    }
+}
+
+tokio::task_local! {
+    static SUMMARY: Arc<SummaryNotes>;
+}
+
+/// 在开启查询摘要的查询中执行 `future`：其中的info日志降为debug，状态记入 `notes`
+pub(crate) async fn summary_scope<F: Future>(notes: Arc<SummaryNotes>, future: F) -> F::Output {
+    SUMMARY.scope(notes, future).await
+}
+
+/// 把当前任务的查询摘要状态带进 `future`，用于在新任务中发送的查询
+pub(crate) fn propagate_summary<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let notes = SUMMARY.try_with(Arc::clone).ok();
+    async move {
+        match notes {
+            Some(notes) => SUMMARY.scope(notes, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// 记下本次查询由过期的缓存条目应答，不在开启查询摘要的查询中时忽略
+pub(crate) fn note_stale_answer() {
+    let _ = SUMMARY.try_with(|notes| notes.served_stale.store(true, Ordering::Relaxed));
+}
+
+/// DNS日志宏的共同出口：开启查询摘要的查询中info降为debug，测试中同时写入 `LogCapture`
+#[doc(hidden)]
+pub fn __log(level: Level, args: std::fmt::Arguments<'_>, module_path: &'static str, file: &'static str, line: u32) {
+    let level = match level {
+        Level::Info if SUMMARY.try_with(|_| ()).is_ok() => Level::Debug,
+        level => level,
+    };
+    #[cfg(any(test, feature = "test-util"))]
+    capture::record(level, &args);
+    #[cfg(not(target_arch = "wasm32"))]
+    rat_logger::__private_log_impl(level, args, module_path, file, line);
+    #[cfg(target_arch = "wasm32")]
+    levels::log(level, args, module_path, file, line);
+}
+
+#[cfg(target_arch = "wasm32")]
+mod levels {
+    use std::sync::atomic::{AtomicU8, Ordering};
+
+    /// 日志级别（与rat_logger的同名类型对应）
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub enum Level {
+        /// 错误
+        Error = 1,
+        /// 警告
+        Warn,
+        /// 信息
+        Info,
+        /// 调试
+        Debug,
+        /// 追踪
+        Trace,
+    }
+
+    /// 日志级别过滤器，`Off` 关闭全部日志
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub enum LevelFilter {
+        /// 关闭
+        Off,
+        /// 只输出错误
+        Error,
+        /// 警告及以上
+        Warn,
+        /// 信息及以上
+        Info,
+        /// 调试及以上
+        Debug,
+        /// 全部
+        Trace,
+    }
+
+    /// 未设置级别时不输出，与rat_logger未初始化时一致
+    static MAX_LEVEL: AtomicU8 = AtomicU8::new(LevelFilter::Off as u8);
+
+    pub(super) fn set_max_level(level: LevelFilter) {
+        MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+    }
+
+    pub(super) fn log(level: Level, args: std::fmt::Arguments<'_>, module_path: &str, file: &str, line: u32) {
+        if level as u8 > MAX_LEVEL.load(Ordering::Relaxed) {
+            return;
+        }
+        match level {
+            Level::Error => tracing::error!(target: "rat_quickdns", module_path, file, line, "{}", args),
+            Level::Warn => tracing::warn!(target: "rat_quickdns", module_path, file, line, "{}", args),
+            Level::Info => tracing::info!(target: "rat_quickdns", module_path, file, line, "{}", args),
+            Level::Debug => tracing::debug!(target: "rat_quickdns", module_path, file, line, "{}", args),
+            Level::Trace => tracing::trace!(target: "rat_quickdns", module_path, file, line, "{}", args),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+pub use capture::{CapturedLog, LogCapture};
+
+#[cfg(any(test, feature = "test-util"))]
+mod capture {
+    use super::Level;
+    use std::cell::RefCell;
+
+    thread_local! {
+        static CAPTURED: RefCell<Option<Vec<CapturedLog>>> = const { RefCell::new(None) };
+    }
+
+    /// 捕获到的一条DNS日志
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct CapturedLog {
+        /// 实际输出的级别（查询摘要降级之后）
+        pub level: Level,
+        /// 日志内容
+        pub message: String,
+    }
+
+    /// 捕获当前线程输出的DNS日志，供测试断言（需要启用 `test-util` 特性）
+    ///
+    /// 只捕获经过本库 `dns_*` 日志宏、且在当前线程输出的日志，不受全局日志级别影响；
+    /// 在单线程运行时（`#[tokio::test]` 的默认）中查询派生的任务也在当前线程执行。丢弃时停止捕获
+    #[derive(Debug)]
+    pub struct LogCapture {
+        _private: (),
+    }
+
+    impl LogCapture {
+        /// 开始捕获，之前未取走的记录被丢弃
+        pub fn start() -> Self {
+            CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+            Self { _private: () }
+        }
+
+        /// 取走目前捕获到的日志
+        pub fn take(&self) -> Vec<CapturedLog> {
+            CAPTURED.with(|captured| captured.borrow_mut().as_mut().map(std::mem::take).unwrap_or_default())
+        }
+    }
+
+    impl Drop for LogCapture {
+        fn drop(&mut self) {
+            CAPTURED.with(|captured| *captured.borrow_mut() = None);
+        }
+    }
+
+    pub(super) fn record(level: Level, args: &std::fmt::Arguments<'_>) {
+        CAPTURED.with(|captured| {
+            if let Some(logs) = captured.borrow_mut().as_mut() {
+                logs.push(CapturedLog { level, message: args.to_string() });
+            }
+        });
+    }
+}
+
 /// DNS 查询相关的便捷日志宏
 #[macro_export]
 macro_rules! dns_query {
@@ -117,7 +399,7 @@ macro_rules! dns_response {
 #[macro_export]
 macro_rules! dns_error {
     ($($arg:tt)*) => {
-        $crate::error!($($arg)*)
+        $crate::logger::__log($crate::logger::Level::Error, format_args!($($arg)*), module_path!(), file!(), line!())
     };
 }
 
@@ -127,7 +409,7 @@ macro_rules! dns_error {
 #[macro_export]
 macro_rules! dns_debug {
     ($($arg:tt)*) => {
-        $crate::debug!($($arg)*)
+        $crate::logger::__log($crate::logger::Level::Debug, format_args!($($arg)*), module_path!(), file!(), line!())
     };
 }
 
@@ -137,7 +419,7 @@ macro_rules! dns_debug {
 #[macro_export]
 macro_rules! dns_info {
     ($($arg:tt)*) => {
-        $crate::info!($($arg)*)
+        $crate::logger::__log($crate::logger::Level::Info, format_args!($($arg)*), module_path!(), file!(), line!())
     };
 }
 
@@ -147,7 +429,7 @@ macro_rules! dns_info {
 #[macro_export]
 macro_rules! dns_warn {
     ($($arg:tt)*) => {
-        $crate::warn!($($arg)*)
+        $crate::logger::__log($crate::logger::Level::Warn, format_args!($($arg)*), module_path!(), file!(), line!())
     };
 }
 
@@ -157,7 +439,7 @@ macro_rules! dns_warn {
 #[macro_export]
 macro_rules! dns_transport {
     ($($arg:tt)*) => {
-        $crate::info!($($arg)*)
+        $crate::logger::__log($crate::logger::Level::Info, format_args!($($arg)*), module_path!(), file!(), line!())
     };
 }
 
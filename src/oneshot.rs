@@ -0,0 +1,529 @@
+//! 不构建解析器的单次查询
+//!
+//! 诊断“这台服务器能否回答这个域名”时不必配置完整的解析器：[`query`] 按上游字符串直接创建对应的传输，
+//! 只发送一次查询，返回带耗时分解和应答地址的 [`DnsQueryResponse`]。不经过缓存、上游监控和智能决策，
+//! 也不计入任何解析器的指标。ratdig 命令行工具的上游写法与这里相同。
+//!
+//! 上游写作 `udp://`、`tcp://`、`dot://`（或 `tls://`）加 `主机[:端口]`，`doh://主机/路径` 或完整的
+//! `https://` URL；不带协议前缀时按 [`OneshotOptions::protocol`]（默认UDP）处理。
+//!
+//! ```no_run
+//! # async fn example() -> rat_quickdns::Result<()> {
+//! use rat_quickdns::builder::types::DnsRecordType;
+//! use rat_quickdns::oneshot::{self, OneshotOptions};
+//!
+//! let response = oneshot::query("dot://1.1.1.1", "example.com", DnsRecordType::A, &OneshotOptions::default()).await?;
+//! println!("{:?} 来自 {:?}，耗时 {} ms", response.ip_addresses(), response.upstream_peer, response.duration_ms);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::runtime;
+use crate::builder::resolver::{create_transport, response_negative_ttl, response_records};
+use crate::builder::types::{DnsQueryRequest, DnsQueryResponse, DnsRecordType, DnssecStatus, TimingBreakdown};
+use crate::builder::QueryStrategy;
+use crate::error::{DnsError, Result};
+use crate::resolver::CoreResolverConfig;
+use crate::types::{ClientAddress, Flags, Query, QClass, Request, SharedResponse};
+use crate::resolver::priority::QueryPriority;
+use crate::upstream_handler::{UpstreamSpec, UpstreamType};
+use crate::utils::validate_https_url;
+use crate::dns_debug;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use crate::time::{Instant, SystemTime};
+
+/// 从字符串解析出的单个上游
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OneshotUpstream {
+    protocol: UpstreamType,
+    /// UDP/TCP/DoT为 `主机[:端口]`，DoH为完整的 `https://` URL
+    server: String,
+}
+
+impl OneshotUpstream {
+    /// 解析上游字符串，不带协议前缀时按UDP处理
+    pub fn parse(spec: &str) -> Result<Self> {
+        Self::parse_with_default(spec, UpstreamType::Udp)
+    }
+
+    /// 解析上游字符串，不带协议前缀时按 `default_protocol` 处理
+    pub fn parse_with_default(spec: &str, default_protocol: UpstreamType) -> Result<Self> {
+        let spec = spec.trim();
+        let (protocol, server) = match spec.split_once("://") {
+            Some((scheme, rest)) => match scheme.to_ascii_lowercase().as_str() {
+                "udp" => (UpstreamType::Udp, rest.to_string()),
+                "tcp" => (UpstreamType::Tcp, rest.to_string()),
+                "dot" | "tls" => (UpstreamType::DoT, rest.to_string()),
+                "doh" => (UpstreamType::DoH, format!("https://{}", rest)),
+                "https" => (UpstreamType::DoH, spec.to_string()),
+                other => return Err(DnsError::InvalidConfig(format!("Unsupported upstream scheme '{}' in '{}'", other, spec))),
+            },
+            None => match default_protocol {
+                UpstreamType::DoH => (UpstreamType::DoH, format!("https://{}", spec)),
+                UpstreamType::Custom => {
+                    return Err(DnsError::InvalidConfig("Custom upstreams cannot be parsed from a string".to_string()));
+                }
+                protocol => (protocol, spec.to_string()),
+            },
+        };
+        if server.is_empty() || server == "https://" {
+            return Err(DnsError::InvalidConfig(format!("Upstream address is empty: '{}'", spec)));
+        }
+        match protocol {
+            UpstreamType::DoH => validate_https_url(&server)?,
+            _ => validate_host_port(&server)?,
+        }
+        Ok(Self { protocol, server })
+    }
+
+    /// 上游协议
+    pub fn protocol(&self) -> &UpstreamType {
+        &self.protocol
+    }
+
+    /// 服务器地址：UDP/TCP/DoT为 `主机[:端口]`，DoH为 `https://` URL
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// 协议前缀（`udp`、`tcp`、`dot`、`doh`）
+    pub fn scheme(&self) -> &'static str {
+        match self.protocol {
+            UpstreamType::Udp => "udp",
+            UpstreamType::Tcp => "tcp",
+            UpstreamType::DoT => "dot",
+            UpstreamType::DoH => "doh",
+            UpstreamType::Custom => "custom",
+        }
+    }
+
+    /// 以 `name` 为名称的上游规格，可交给 [`DnsResolverBuilder::add_upstream`](crate::DnsResolverBuilder::add_upstream)
+    pub fn to_spec(&self, name: impl Into<String>) -> UpstreamSpec {
+        let name = name.into();
+        match self.protocol {
+            UpstreamType::Tcp => UpstreamSpec::tcp(name, self.server.clone()),
+            UpstreamType::DoT => UpstreamSpec::dot(name, self.server.clone()),
+            UpstreamType::DoH => UpstreamSpec::doh(name, self.server.clone()),
+            UpstreamType::Udp | UpstreamType::Custom => UpstreamSpec::udp(name, self.server.clone()),
+        }
+    }
+}
+
+impl fmt::Display for OneshotUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.protocol {
+            UpstreamType::DoH => f.write_str(&self.server),
+            _ => write!(f, "{}://{}", self.scheme(), self.server),
+        }
+    }
+}
+
+/// `主机[:端口]` 是否合法：IPv6地址带端口时须写成 `[地址]:端口`
+fn validate_host_port(server: &str) -> Result<()> {
+    let invalid = |reason: &str| DnsError::InvalidConfig(format!("Invalid upstream address '{}': {}", server, reason));
+    if server.parse::<Ipv6Addr>().is_ok() {
+        return Ok(());
+    }
+    if let Ok(address) = server.parse::<SocketAddr>() {
+        return if address.port() == 0 { Err(invalid("port must be 1-65535")) } else { Ok(()) };
+    }
+    let (host, port) = match server.split_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (server, None),
+    };
+    if host.is_empty() {
+        return Err(invalid("missing host"));
+    }
+    if host.contains(['/', '[', ']', '@']) || host.chars().any(char::is_whitespace) {
+        return Err(invalid("not a host name or IP address"));
+    }
+    if let Some(port) = port {
+        match port.parse::<u16>() {
+            Ok(port) if port > 0 => {}
+            _ => return Err(invalid("port must be 1-65535")),
+        }
+    }
+    Ok(())
+}
+
+/// 单次查询的选项
+#[derive(Debug, Clone)]
+pub struct OneshotOptions {
+    /// 查询超时（含上游主机名解析和连接建立），默认5秒
+    pub timeout: Duration,
+    /// 是否携带EDNS OPT记录，默认是
+    pub edns: bool,
+    /// 携带的客户端子网（ECS）
+    pub client_subnet: Option<ClientAddress>,
+    /// 上游不带协议前缀时使用的协议，默认UDP
+    pub protocol: UpstreamType,
+    /// 是否设置DNSSEC OK位
+    pub dnssec: bool,
+    /// DoT/DoH是否校验证书，默认是
+    pub verify_cert: bool,
+}
+
+impl Default for OneshotOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            edns: true,
+            client_subnet: None,
+            protocol: UpstreamType::Udp,
+            dnssec: false,
+            verify_cert: true,
+        }
+    }
+}
+
+impl OneshotOptions {
+    /// 设置客户端子网，前缀长度超出地址长度时返回错误
+    pub fn with_client_subnet(mut self, ip: IpAddr, prefix_length: u8) -> Result<Self> {
+        let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+        if prefix_length > max_prefix {
+            return Err(DnsError::InvalidConfig(
+                format!("Client subnet prefix /{} is too long for {}", prefix_length, ip)
+            ));
+        }
+        self.client_subnet = Some(ClientAddress::new(ip, prefix_length));
+        Ok(self)
+    }
+}
+
+/// 向 `upstream` 发送一次查询
+///
+/// 上游字符串或选项无效时返回 [`DnsError::InvalidConfig`]；上游超时、传输出错等查询失败与
+/// [`SmartDnsResolver::query`](crate::SmartDnsResolver::query) 一样写入响应的 `success`/`error`。
+/// 成功时 `server_used` 为上游字符串，`timing` 总是带有耗时分解
+pub async fn query(upstream: &str, domain: &str, record_type: DnsRecordType, options: &OneshotOptions) -> Result<DnsQueryResponse> {
+    let upstream = OneshotUpstream::parse_with_default(upstream, options.protocol.clone())?;
+    query_upstream(&upstream, domain, record_type, options).await
+}
+
+/// 向已解析的上游发送一次查询，见 [`query`]
+pub async fn query_upstream(
+    upstream: &OneshotUpstream,
+    domain: &str,
+    record_type: DnsRecordType,
+    options: &OneshotOptions,
+) -> Result<DnsQueryResponse> {
+    if options.timeout.is_zero() {
+        return Err(DnsError::InvalidConfig("Oneshot timeout must be greater than zero".to_string()));
+    }
+    let request = DnsQueryRequest::new(domain, record_type).with_dnssec(options.dnssec);
+    if let Err(e) = request.validate() {
+        return Ok(DnsQueryResponse::failed(&request, &e));
+    }
+
+    let config = CoreResolverConfig {
+        enable_edns: options.edns,
+        ..CoreResolverConfig::new(
+            QueryStrategy::Fifo,
+            options.timeout,
+            0,
+            false,
+            Duration::ZERO,
+            false,
+            Duration::from_secs(30),
+            53,
+            1,
+            true,
+            65535, // 与解析器默认一致，不截断任何合法的响应
+            false,
+            crate::logger::LevelFilter::Off,
+            false,
+        )
+    };
+    let transport = create_transport(&upstream.to_spec(upstream.to_string()), &config, &[], None)?;
+    let transport = match options.verify_cert {
+        true => transport,
+        false => transport.insecure_variant()?.unwrap_or(transport),
+    };
+
+    let wire_request = Request {
+        id: rand::random(),
+        flags: Flags::default(),
+        query: Query {
+            name: request.domain.clone(),
+            qtype: record_type.into(),
+            qclass: QClass::IN,
+        },
+        client_address: options.client_subnet.clone(),
+        enable_edns: options.edns,
+        dnssec_ok: options.dnssec,
+        wire_capture_limit: None,
+        priority: QueryPriority::Normal,
+    };
+    dns_debug!("单次查询 {} {} -> {}", request.domain, record_type.as_str(), upstream);
+
+    let start = Instant::now();
+    let result = runtime::timeout(options.timeout, transport.send_timed(&wire_request))
+        .await
+        .unwrap_or(Err(DnsError::Timeout { phase: None }));
+    let mut response = match result {
+        Ok((answer, timing)) => {
+            let answer = SharedResponse::from(answer);
+            let mut response = DnsQueryResponse::for_request(&request);
+            response.success = true;
+            response.records = response_records(&answer);
+            response.server_used = Some(upstream.to_string());
+            response.protocol_used = Some(transport.transport_type().to_string());
+            response.upstream_peer = timing.peer;
+            response.rcode = Some(answer.extended_rcode());
+            if let Some(negative) = response_negative_ttl(&answer, record_type) {
+                response.negative_ttl = Some(negative.ttl);
+                response.zone_apex = Some(negative.zone_apex);
+            }
+            response.timing = Some(TimingBreakdown::from(timing));
+            response.stamp_valid_until(SystemTime::now());
+            response
+        }
+        Err(e) => {
+            dns_debug!("单次查询 {} 失败: {}", upstream, e);
+            DnsQueryResponse::failed(&request, &e)
+        }
+    };
+    response.dnssec_status = Some(DnssecStatus::Indeterminate);
+    response.duration_ms = start.elapsed().as_millis() as u64;
+    Ok(response)
+}
+
+#[cfg(all(test, feature = "dot", feature = "doh"))]
+mod tests {
+    use super::*;
+    use crate::dns_response::DnsResponseWrapper;
+    use crate::transport::UdpTransport;
+    use std::net::Ipv4Addr;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::{TcpListener, UdpSocket};
+    use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+    const ANSWER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+    /// 对任何问题返回一条A记录的报文
+    fn answer(message: &[u8]) -> Vec<u8> {
+        let request = UdpTransport::deserialize_request(message).unwrap();
+        let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[ANSWER], 300);
+        UdpTransport::serialize_response(&response).unwrap()
+    }
+
+    /// 自签名证书（CN=localhost）的TLS接受器
+    fn acceptor() -> tokio_rustls::TlsAcceptor {
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(include_bytes!("transport/testdata/localhost.crt.der").to_vec())],
+                PrivateKey(include_bytes!("transport/testdata/localhost.key.der").to_vec()),
+            )
+            .unwrap();
+        tokio_rustls::TlsAcceptor::from(Arc::new(config))
+    }
+
+    /// 收到的请求，供断言EDNS和客户端子网
+    async fn udp_server() -> (SocketAddr, Arc<Mutex<Vec<Request>>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let seen = received.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                seen.lock().unwrap().push(UdpTransport::deserialize_request(&buf[..len]).unwrap());
+                socket.send_to(&answer(&buf[..len]), peer).await.unwrap();
+            }
+        });
+        (address, received)
+    }
+
+    /// 按两字节长度前缀收发一个报文（TCP/DoT）
+    async fn serve_framed(mut stream: impl AsyncRead + AsyncWrite + Unpin) {
+        let mut length = [0u8; 2];
+        if stream.read_exact(&mut length).await.is_err() {
+            return;
+        }
+        let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut message).await.unwrap();
+        let reply = answer(&message);
+        stream.write_all(&(reply.len() as u16).to_be_bytes()).await.unwrap();
+        stream.write_all(&reply).await.unwrap();
+        stream.flush().await.unwrap();
+    }
+
+    /// 只支持POST、每个连接一个请求的最小DoH服务器
+    async fn serve_doh(mut stream: impl AsyncRead + AsyncWrite + Unpin) {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            if stream.read_exact(&mut byte).await.is_err() {
+                return;
+            }
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head).to_ascii_lowercase();
+        let length: usize = head.lines()
+            .find_map(|line| line.strip_prefix("content-length:"))
+            .map(|value| value.trim().parse().unwrap())
+            .unwrap_or(0);
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).await.unwrap();
+        let reply = answer(&body);
+        let header = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            reply.len()
+        );
+        stream.write_all(header.as_bytes()).await.unwrap();
+        stream.write_all(&reply).await.unwrap();
+        stream.flush().await.unwrap();
+    }
+
+    #[derive(Clone, Copy)]
+    enum Framing {
+        Tcp,
+        Dot,
+        Doh,
+    }
+
+    async fn stream_server(framing: Framing) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let acceptor = acceptor();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    match framing {
+                        Framing::Tcp => serve_framed(stream).await,
+                        Framing::Dot => if let Ok(stream) = acceptor.accept(stream).await {
+                            serve_framed(stream).await
+                        },
+                        Framing::Doh => if let Ok(stream) = acceptor.accept(stream).await {
+                            serve_doh(stream).await
+                        },
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    fn assert_answered(response: &DnsQueryResponse, server: &str, protocol: &str, peer: Option<SocketAddr>) {
+        assert!(response.success, "{:?}", response.error);
+        assert_eq!(response.rcode, Some(0));
+        assert_eq!(response.ip_addresses(), vec![IpAddr::from(ANSWER)]);
+        assert_eq!(response.server_used.as_deref(), Some(server));
+        assert_eq!(response.protocol_used.as_deref(), Some(protocol));
+        assert!(response.timing.is_some());
+        if peer.is_some() {
+            assert_eq!(response.upstream_peer, peer);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_udp_query_carries_options() {
+        let (address, received) = udp_server().await;
+        let options = OneshotOptions { dnssec: true, ..OneshotOptions::default() }
+            .with_client_subnet("203.0.113.0".parse().unwrap(), 24)
+            .unwrap();
+        let response = query(&format!("udp://{}", address), "example.com", DnsRecordType::A, &options).await.unwrap();
+        assert_answered(&response, &format!("udp://{}", address), "UDP", Some(address));
+
+        // 不带协议前缀时按UDP处理
+        let bare = query(&address.to_string(), "example.com", DnsRecordType::A, &OneshotOptions::default()).await.unwrap();
+        assert!(bare.success, "{:?}", bare.error);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert!(received[0].dnssec_ok);
+        assert_eq!(received[0].client_address, Some(ClientAddress::new("203.0.113.0".parse().unwrap(), 24)));
+        assert_eq!(received[1].client_address, None);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_query() {
+        let address = stream_server(Framing::Tcp).await;
+        let upstream = format!("tcp://{}", address);
+        let response = query(&upstream, "example.com", DnsRecordType::A, &OneshotOptions::default()).await.unwrap();
+        assert_answered(&response, &upstream, "TCP", Some(address));
+    }
+
+    #[tokio::test]
+    async fn test_dot_query_verifies_certificate_unless_disabled() {
+        let address = stream_server(Framing::Dot).await;
+        let upstream = format!("dot://{}", address);
+        let strict = query(&upstream, "example.com", DnsRecordType::A, &OneshotOptions::default()).await.unwrap();
+        assert!(!strict.success);
+
+        let insecure = OneshotOptions { verify_cert: false, ..OneshotOptions::default() };
+        let response = query(&format!("tls://{}", address), "example.com", DnsRecordType::A, &insecure).await.unwrap();
+        assert_answered(&response, &upstream, "TLS", Some(address));
+    }
+
+    #[tokio::test]
+    async fn test_doh_query() {
+        let address = stream_server(Framing::Doh).await;
+        let insecure = OneshotOptions { verify_cert: false, ..OneshotOptions::default() };
+        let url = format!("https://{}/dns-query", address);
+        let response = query(&format!("doh://{}/dns-query", address), "example.com", DnsRecordType::A, &insecure).await.unwrap();
+        assert_answered(&response, &url, "HTTPS", None);
+
+        let response = query(&url, "example.com", DnsRecordType::A, &insecure).await.unwrap();
+        assert_answered(&response, &url, "HTTPS", None);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_reported_in_the_response() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let options = OneshotOptions { timeout: Duration::from_millis(200), ..OneshotOptions::default() };
+        let response = query(&silent.local_addr().unwrap().to_string(), "example.com", DnsRecordType::A, &options).await.unwrap();
+        assert!(!response.success);
+        assert!(response.error.is_some());
+        assert!(response.duration_ms < 2000);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_upstreams_are_config_errors() {
+        for upstream in [
+            "",
+            "quic://192.0.2.1",
+            "udp://",
+            "doh://",
+            "https://",
+            "tcp://192.0.2.1:dns",
+            "udp://192.0.2.1:0",
+            "udp://192.0.2.1:70000",
+            "dot://:853",
+            "udp://host name",
+            "udp://192.0.2.1/path",
+            "https://exa mple.com/dns-query",
+        ] {
+            let result = query(upstream, "example.com", DnsRecordType::A, &OneshotOptions::default()).await;
+            assert!(matches!(result, Err(DnsError::InvalidConfig(_))), "{:?}: {:?}", upstream, result);
+        }
+        assert!(OneshotOptions::default().with_client_subnet("192.0.2.0".parse().unwrap(), 33).is_err());
+        let zero = OneshotOptions { timeout: Duration::ZERO, ..OneshotOptions::default() };
+        assert!(query("192.0.2.1", "example.com", DnsRecordType::A, &zero).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_round_trips_through_display() {
+        for (input, protocol, display) in [
+            ("192.0.2.1", UpstreamType::Udp, "udp://192.0.2.1"),
+            ("TCP://192.0.2.1:5353", UpstreamType::Tcp, "tcp://192.0.2.1:5353"),
+            ("tls://dns.example", UpstreamType::DoT, "dot://dns.example"),
+            ("udp://[2001:db8::1]:53", UpstreamType::Udp, "udp://[2001:db8::1]:53"),
+            ("doh://dns.example/dns-query", UpstreamType::DoH, "https://dns.example/dns-query"),
+        ] {
+            let upstream = OneshotUpstream::parse(input).unwrap();
+            assert_eq!(upstream.protocol(), &protocol);
+            assert_eq!(upstream.to_string(), display);
+            assert_eq!(OneshotUpstream::parse(display).unwrap(), upstream);
+        }
+        let dot = OneshotUpstream::parse_with_default("dns.example", UpstreamType::DoT).unwrap();
+        assert_eq!(dot.to_string(), "dot://dns.example");
+    }
+}
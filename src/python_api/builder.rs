@@ -3,12 +3,17 @@
 //! 提供了DnsResolverBuilder的Python绑定，用于配置和构建DNS解析器。
 
 use pyo3::prelude::*;
-use tokio::runtime::Runtime;
+use std::net::IpAddr;
 
 use crate::builder::DnsResolverBuilder as RustDnsResolverBuilder;
 use crate::builder::strategy::QueryStrategy as RustQueryStrategy;
-use crate::upstream_handler::{UpstreamSpec, UpstreamManager};
+use crate::builder::preset::Preset;
+use crate::config::StrictDnsConfig;
+use crate::resolver::ttl_override::OverrideTtl;
+use crate::transport::{RecordLimitPolicy, RecordLimits};
+use crate::upstream_handler::{UpstreamLabels, UpstreamSpec, UpstreamManager};
 use super::resolver::PyDnsResolver;
+use super::runtime::{acquire_runtime, release_runtime};
 use super::types::PyQueryStrategy;
 
 /// Python版本的DNS解析器构建器
@@ -50,6 +55,31 @@ impl PyDnsResolverBuilder {
         }
     }
     
+    /// 从JSON格式的严格配置创建构建器，字段与 `StrictDnsConfig` 的序列化格式相同
+    /// 
+    /// 以后可以用同一格式的配置调用 `DnsResolver.reload` 重新加载
+    /// 
+    /// Args:
+    ///     config_json (str): 严格配置
+    ///     region (str): 当前区域，默认为"CN"
+    /// 
+    /// Returns:
+    ///     DnsResolverBuilder: 按配置设置好的构建器
+    /// 
+    /// Raises:
+    ///     ValueError: JSON无法解析或配置无效
+    #[staticmethod]
+    #[pyo3(signature = (config_json, region = "CN"))]
+    pub fn from_config_json(config_json: &str, region: &str) -> PyResult<Self> {
+        let config: StrictDnsConfig = serde_json::from_str(config_json).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid config: {}", e))
+        })?;
+        let inner = RustDnsResolverBuilder::from_strict_config(&config, true, region).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid config: {}", e))
+        })?;
+        Ok(Self { inner })
+    }
+    
     /// 设置查询策略
     /// 
     /// Args:
@@ -65,21 +95,38 @@ impl PyDnsResolverBuilder {
         Ok(())
     }
     
+    /// 使用法定人数策略：`required` 个上游给出相同答案时才返回
+    /// 
+    /// Args:
+    ///     required (int): 需要给出相同答案的上游数，构建时不能超过上游数
+    /// 
+    /// Example:
+    ///     >>> builder.quorum_strategy(2)
+    pub fn quorum_strategy(&mut self, required: usize) -> PyResult<()> {
+        self.inner = self.inner.clone().query_strategy(RustQueryStrategy::Quorum { required });
+        Ok(())
+    }
+    
     /// 添加UDP上游DNS服务器
     /// 
     /// Args:
     ///     name (str): 服务器名称（用于标识）
     ///     server (str): 服务器地址，支持"IP"、"IP:端口"格式
+    ///     weight (int): 负载均衡权重，默认1
     /// 
     /// Returns:
     ///     DnsResolverBuilder: 返回自身以支持链式调用
     /// 
+    /// Raises:
+    ///     ValueError: 当权重不在1到4294967295之间时
+    /// 
     /// Example:
     ///     >>> builder.add_udp_upstream("Google", "8.8.8.8")
-    ///     >>> builder.add_udp_upstream("Cloudflare", "1.1.1.1")
-    pub fn add_udp_upstream(&mut self, name: String, server: String) -> PyResult<()> {
-        self.inner = self.inner.clone().add_udp_upstream(name, server);
-        Ok(())
+    ///     >>> builder.add_udp_upstream("Cloudflare", "1.1.1.1", weight=3)
+    #[pyo3(signature = (name, server, weight = 1))]
+    pub fn add_udp_upstream(&mut self, name: String, server: String, weight: i64) -> PyResult<()> {
+        let weight = validate_weight(weight)?;
+        self.add_upstream_spec(UpstreamSpec::udp(name, server).with_weight(weight))
     }
     
     /// 添加TCP上游DNS服务器
@@ -87,16 +134,21 @@ impl PyDnsResolverBuilder {
     /// Args:
     ///     name (str): 服务器名称（用于标识）
     ///     server (str): 服务器地址，支持"IP"、"IP:端口"格式
+    ///     weight (int): 负载均衡权重，默认1
     /// 
     /// Returns:
     ///     DnsResolverBuilder: 返回自身以支持链式调用
     /// 
+    /// Raises:
+    ///     ValueError: 当权重不在1到4294967295之间时
+    /// 
     /// Example:
     ///     >>> builder.add_tcp_upstream("Cloudflare", "1.1.1.1")
-    ///     >>> builder.add_tcp_upstream("Quad9", "9.9.9.9")
-    pub fn add_tcp_upstream(&mut self, name: String, server: String) -> PyResult<()> {
-        self.inner = self.inner.clone().add_tcp_upstream(name, server);
-        Ok(())
+    ///     >>> builder.add_tcp_upstream("Quad9", "9.9.9.9", weight=2)
+    #[pyo3(signature = (name, server, weight = 1))]
+    pub fn add_tcp_upstream(&mut self, name: String, server: String, weight: i64) -> PyResult<()> {
+        let weight = validate_weight(weight)?;
+        self.add_upstream_spec(UpstreamSpec::tcp(name, server).with_weight(weight))
     }
     
     /// 添加DoH上游服务器
@@ -104,17 +156,19 @@ impl PyDnsResolverBuilder {
     /// Args:
     ///     name (str): 服务器名称
     ///     url (str): DoH服务器URL (例如: "https://dns.google/dns-query")
+    ///     weight (int): 负载均衡权重，默认1
     /// 
     /// Returns:
     ///     Self: 返回自身以支持链式调用
     /// 
     /// Raises:
-    ///     ValueError: 当URL格式无效时
+    ///     ValueError: 当URL格式无效或权重越界时
     /// 
     /// Example:
     ///     >>> builder.add_doh_upstream("Google DoH", "https://dns.google/dns-query")
-    ///     >>> builder.add_doh_upstream("Cloudflare DoH", "https://cloudflare-dns.com/dns-query")
-    pub fn add_doh_upstream(&mut self, name: String, url: String) -> PyResult<()> {
+    ///     >>> builder.add_doh_upstream("Cloudflare DoH", "https://cloudflare-dns.com/dns-query", weight=2)
+    #[pyo3(signature = (name, url, weight = 1))]
+    pub fn add_doh_upstream(&mut self, name: String, url: String, weight: i64) -> PyResult<()> {
         // 验证URL格式
         if !url.starts_with("https://") {
             return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
@@ -122,8 +176,8 @@ impl PyDnsResolverBuilder {
             ));
         }
         
-        self.inner = self.inner.clone().add_doh_upstream(name, url);
-        Ok(())
+        let weight = validate_weight(weight)?;
+        self.add_upstream_spec(UpstreamSpec::doh(name, url).with_weight(weight))
     }
     
     /// 添加DoT (DNS over TLS) 上游服务器
@@ -131,16 +185,21 @@ impl PyDnsResolverBuilder {
     /// Args:
     ///     name (str): 服务器名称（用于标识）
     ///     server (str): 服务器地址，支持"IP"、"IP:端口"、"域名"格式
+    ///     weight (int): 负载均衡权重，默认1
     /// 
     /// Returns:
     ///     DnsResolverBuilder: 返回自身以支持链式调用
     /// 
+    /// Raises:
+    ///     ValueError: 当权重不在1到4294967295之间时
+    /// 
     /// Example:
     ///     >>> builder.add_dot_upstream("Quad9", "9.9.9.9")
-    ///     >>> builder.add_dot_upstream("Cloudflare DoT", "1.1.1.1")
-    pub fn add_dot_upstream(&mut self, name: String, server: String) -> PyResult<()> {
-        self.inner = self.inner.clone().add_dot_upstream(name, server);
-        Ok(())
+    ///     >>> builder.add_dot_upstream("Cloudflare DoT", "1.1.1.1", weight=2)
+    #[pyo3(signature = (name, server, weight = 1))]
+    pub fn add_dot_upstream(&mut self, name: String, server: String, weight: i64) -> PyResult<()> {
+        let weight = validate_weight(weight)?;
+        self.add_upstream_spec(UpstreamSpec::dot(name, server).with_weight(weight))
     }
     
     /// 设置查询超时时间
@@ -188,6 +247,67 @@ impl PyDnsResolverBuilder {
         Ok(())
     }
     
+    /// 添加预设的上游服务器
+    /// 
+    /// 只添加上游服务器，不会修改查询策略、超时等配置
+    /// 
+    /// Args:
+    ///     preset (str): 预设名称 ("china_mainland", "global", "privacy_focused")
+    /// 
+    /// Raises:
+    ///     ValueError: 当预设名称未知时
+    /// 
+    /// Example:
+    ///     >>> builder.with_preset("china_mainland")
+    pub fn with_preset(&mut self, preset: &str) -> PyResult<()> {
+        let preset = Preset::from_name(preset).ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                format!("Unknown preset '{}'. Available presets: china_mainland, global, privacy_focused", preset)
+            )
+        })?;
+        self.inner = self.inner.clone().with_preset(preset).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Failed to add preset: {}", e))
+        })?;
+        Ok(())
+    }
+    
+    /// 启用查询历史记录
+    /// 
+    /// Args:
+    ///     capacity (int): 保留的最近查询条数，必须大于0
+    /// 
+    /// Example:
+    ///     >>> builder.with_query_history(1000)
+    pub fn with_query_history(&mut self, capacity: usize) -> PyResult<()> {
+        self.inner = self.inner.clone().with_query_history(capacity);
+        Ok(())
+    }
+    
+    /// 为匹配的名称固定TTL，取代全局的TTL上下限
+    /// 
+    /// Args:
+    ///     pattern (str): 完整域名，或 `*.` 开头的后缀通配（只匹配子域名）
+    ///     mode (str): "max"、"min" 或 "exact"
+    ///     ttl_secs (int): TTL秒数
+    /// 
+    /// Example:
+    ///     >>> builder.with_ttl_override("*.failover.corp", "exact", 30)
+    pub fn with_ttl_override(&mut self, pattern: &str, mode: &str, ttl_secs: u64) -> PyResult<()> {
+        let ttl = std::time::Duration::from_secs(ttl_secs);
+        let ttl = match mode.to_ascii_lowercase().as_str() {
+            "max" => OverrideTtl::Max(ttl),
+            "min" => OverrideTtl::Min(ttl),
+            "exact" => OverrideTtl::Exact(ttl),
+            _ => return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                format!("Unknown TTL override mode '{}', expected max, min or exact", mode)
+            )),
+        };
+        self.inner = self.inner.clone().with_ttl_override(pattern, ttl).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string())
+        })?;
+        Ok(())
+    }
+    
     /// 启用EDNS功能
     /// 
     /// Args:
@@ -203,6 +323,79 @@ impl PyDnsResolverBuilder {
         Ok(())
     }
     
+    /// 保留类型与查询不符的应答记录（调试用）
+    /// 
+    /// 默认去掉这类记录（例如AAAA应答里混入的A记录），去掉的条数见 `get_stats()` 的
+    /// `dropped_mismatched_records`；开启后原样返回，`resolve_a` / `resolve_aaaa` 仍只返回对应地址族的地址
+    /// 
+    /// Args:
+    ///     enable (bool): 是否保留
+    /// 
+    /// Example:
+    ///     >>> builder.with_lenient_answer_types(True)
+    pub fn with_lenient_answer_types(&mut self, enable: bool) -> PyResult<()> {
+        self.inner = self.inner.clone().with_lenient_answer_types(enable);
+        Ok(())
+    }
+    
+    /// 设置单个响应的记录数上限
+    /// 
+    /// 默认超出时截去多余的记录，查询结果的 `records_truncated` 为True；`reject=True` 时整个响应作为错误丢弃
+    /// 
+    /// Args:
+    ///     max_answer_records (int): 回答记录数上限，默认512
+    ///     max_total_records (int): 回答、权威、附加三段的记录总数上限，默认1024
+    ///     max_cname_chain (int): CNAME链最多的跳数，默认16
+    ///     reject (bool): 超出时拒绝整个响应，默认False（截断）
+    /// 
+    /// Raises:
+    ///     ValueError: 上限为0，或回答记录数上限超过总数上限
+    /// 
+    /// Example:
+    ///     >>> builder.with_record_limits(max_answer_records=100, reject=True)
+    #[pyo3(signature = (
+        max_answer_records = RecordLimits::DEFAULT_MAX_ANSWER_RECORDS,
+        max_total_records = RecordLimits::DEFAULT_MAX_TOTAL_RECORDS,
+        max_cname_chain = RecordLimits::DEFAULT_MAX_CNAME_CHAIN,
+        reject = false
+    ))]
+    pub fn with_record_limits(
+        &mut self,
+        max_answer_records: usize,
+        max_total_records: usize,
+        max_cname_chain: usize,
+        reject: bool,
+    ) -> PyResult<()> {
+        let limits = RecordLimits {
+            max_answer_records,
+            max_total_records,
+            max_cname_chain,
+            policy: if reject { RecordLimitPolicy::Reject } else { RecordLimitPolicy::Truncate },
+        };
+        self.inner = self.inner.clone().with_record_limits(limits).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string())
+        })?;
+        Ok(())
+    }
+    
+    /// 启用/禁用缓存，默认不缓存
+    /// 
+    /// Args:
+    ///     enable (bool): 是否缓存
+    pub fn cache(&mut self, enable: bool) -> PyResult<()> {
+        self.inner = self.inner.clone().with_cache(enable);
+        Ok(())
+    }
+    
+    /// 截断过的响应是否写入缓存，默认不缓存
+    /// 
+    /// Args:
+    ///     enable (bool): 是否缓存
+    pub fn with_cache_truncated_responses(&mut self, enable: bool) -> PyResult<()> {
+        self.inner = self.inner.clone().with_cache_truncated_responses(enable);
+        Ok(())
+    }
+    
     /// 启用上游监控
     /// 
     /// Args:
@@ -309,12 +502,135 @@ impl PyDnsResolverBuilder {
         Ok(())
     }
 
+    /// 设置默认的EDNS客户端子网（ECS）
+    /// 
+    /// 未单独指定客户端地址的查询都会在OPT记录中携带该子网，
+    /// 让支持ECS的上游返回就近的解析结果
+    /// 
+    /// Args:
+    ///     ip (str): 客户端IP地址
+    ///     prefix (int, optional): 源前缀长度，IPv4默认24，IPv6默认56
+    /// 
+    /// Raises:
+    ///     ValueError: 当IP地址无效或前缀超过地址位数时
+    /// 
+    /// Example:
+    ///     >>> builder.with_default_client_ip("202.96.128.86")
+    ///     >>> builder.with_default_client_ip("240e:1f:1::1", 48)
+    #[pyo3(signature = (ip, prefix = None))]
+    pub fn with_default_client_ip(&mut self, ip: &str, prefix: Option<i64>) -> PyResult<()> {
+        let address: IpAddr = ip.parse().map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid client IP address: '{}'", ip))
+        })?;
+        let prefix = prefix.unwrap_or(if address.is_ipv4() { 24 } else { 56 });
+        let prefix = u8::try_from(prefix).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid client subnet prefix: {}", prefix))
+        })?;
+        self.inner = self.inner.clone().with_default_client_ip(address, prefix).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string())
+        })?;
+        Ok(())
+    }
     
-    // 注意：上游监控间隔在当前版本中不支持配置
-    // 上游监控功能通过 enable_upstream_monitoring 启用后会使用默认间隔
+    /// 设置失败后的重试次数
+    /// 
+    /// Args:
+    ///     count (int): 重试次数，0表示不重试
+    /// 
+    /// Raises:
+    ///     ValueError: 当次数为负数时
+    /// 
+    /// Example:
+    ///     >>> builder.with_retry_count(2)
+    pub fn with_retry_count(&mut self, count: i64) -> PyResult<()> {
+        let count = usize::try_from(count).map_err(|_| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Retry count must not be negative, got {}", count))
+        })?;
+        self.retries(count)
+    }
     
-    /// 构建DNS解析器实例
+    /// 启用上游监控并设置检查间隔
+    /// 
+    /// Args:
+    ///     interval_secs (float): 检查间隔（秒），必须为正数
+    /// 
+    /// Raises:
+    ///     ValueError: 当间隔不是有限正数时
+    /// 
+    /// Example:
+    ///     >>> builder.with_upstream_monitoring(30.0)
+    pub fn with_upstream_monitoring(&mut self, interval_secs: f64) -> PyResult<()> {
+        if !interval_secs.is_finite() || interval_secs <= 0.0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                format!("Monitoring interval must be a positive number of seconds, got {}", interval_secs)
+            ));
+        }
+        self.inner = self.inner.clone()
+            .with_upstream_monitoring(true)
+            .with_upstream_monitoring_interval(std::time::Duration::from_secs_f64(interval_secs));
+        Ok(())
+    }
+
+    /// 设置已添加上游的标签
+    /// 
+    /// 标签只是元数据（如供应商、机房），不影响查询，随 `get_upstream_status`、`get_upstream_events`、
+    /// `explain_selection` 和 `get_effective_config` 输出
+    /// 
+    /// Args:
+    ///     name (str): 已添加的上游名称
+    ///     labels (Dict[str, str]): 标签，键只能含字母、数字和下划线，最多16个
+    /// 
+    /// Raises:
+    ///     ValueError: 上游不存在或标签无效
+    /// 
+    /// Example:
+    ///     >>> builder.add_udp_upstream("office", "10.0.0.53")
+    ///     >>> builder.with_upstream_labels("office", {"provider": "internal", "datacenter": "sha-1"})
+    pub fn with_upstream_labels(&mut self, name: &str, labels: UpstreamLabels) -> PyResult<()> {
+        self.inner = self.inner.clone().with_upstream_labels(name, labels)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+        Ok(())
+    }
+
+    /// 设置作为OpenMetrics上游指标标签输出的标签键（默认不输出）
+    /// 
+    /// 只列出取值有限的键，否则指标的序列数会随标签值增长
+    /// 
+    /// Args:
+    ///     keys (List[str]): 标签键
     /// 
+    /// Example:
+    ///     >>> builder.with_metrics_upstream_labels(["provider", "datacenter"])
+    pub fn with_metrics_upstream_labels(&mut self, keys: Vec<String>) -> PyResult<()> {
+        self.inner = self.inner.clone().with_metrics_upstream_labels(keys);
+        Ok(())
+    }
+
+    /// 检查配置而不构建解析器，不访问网络
+    ///
+    /// Returns:
+    ///     dict: `effective_config` 为构建后将生效的配置（字段同 `get_effective_config`），
+    ///     `warnings` 为需要连网才能确认的项目
+    ///
+    /// Raises:
+    ///     RuntimeError: 如果配置有误
+    ///
+    /// Example:
+    ///     >>> report = builder.validate_only()
+    ///     >>> print(report["warnings"])
+    pub fn validate_only(&self, py: Python) -> pyo3::PyResult<PyObject> {
+        let report = self.inner.validate_only().map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Invalid configuration: {}", e))
+        })?;
+        let json = serde_json::to_string(&report).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string())
+        })?;
+        let report = py.import("json")?.call_method1("loads", (json,))?;
+        Ok(report.into())
+    }
+
+    /// 构建DNS解析器实例
+    ///
     /// Returns:
     ///     DnsResolver: 配置好的DNS解析器实例
     /// 
@@ -325,21 +641,17 @@ impl PyDnsResolverBuilder {
     ///     >>> resolver = builder.build()
     pub fn build(&self, py: Python) -> pyo3::PyResult<PyDnsResolver> {
         py.allow_threads(|| {
-            let rt = Runtime::new().map_err(|e| {
-                PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-                    format!("Failed to create runtime: {}", e)
-                )
-            })?;
-            
-            rt.block_on(async {
-                let resolver = self.inner.clone().build().await.map_err(|e| {
-                    PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+            // 在共享运行时上构建，健康检查等后台任务随解析器一直运行到关闭
+            let runtime = acquire_runtime()?;
+            match runtime.block_on(self.inner.clone().build()) {
+                Ok(resolver) => Ok(PyDnsResolver::new(resolver, runtime)),
+                Err(e) => {
+                    release_runtime(runtime);
+                    Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
                         format!("Failed to build resolver: {}", e)
-                    )
-                })?;
-                
-                Ok(PyDnsResolver::new(resolver))
-            })
+                    ))
+                }
+            }
         })
     }
     
@@ -361,12 +673,6 @@ impl PyDnsResolverBuilder {
         Ok(())
     }
     
-    /// 启用/禁用缓存
-    pub fn cache(&mut self, enable: bool) -> PyResult<()> {
-        self.inner = self.inner.clone().with_cache(enable);
-        Ok(())
-    }
-    
     /// 设置DNS服务器端口
     pub fn port(&mut self, port: u16) -> PyResult<()> {
         self.inner = self.inner.clone().with_port(port);
@@ -391,8 +697,25 @@ impl PyDnsResolverBuilder {
         Ok(())
     }
     
+    /// 添加上游服务器规格（供预设等Rust侧工具使用）
+    pub fn add_upstream_spec(&mut self, spec: UpstreamSpec) -> PyResult<()> {
+        self.inner = self.inner.clone().add_upstream(spec).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to add upstream: {}", e))
+        })?;
+        Ok(())
+    }
+    
     /// 获取内部构建器的引用
     pub fn inner(&self) -> &RustDnsResolverBuilder {
         &self.inner
     }
-}
\ No newline at end of file
+}
+
+/// 校验Python传入的上游权重
+pub(super) fn validate_weight(weight: i64) -> PyResult<u32> {
+    u32::try_from(weight).ok().filter(|w| *w > 0).ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Upstream weight must be between 1 and {}, got {}", u32::MAX, weight)
+        )
+    })
+}
@@ -0,0 +1,115 @@
+//! Python异常类型
+//!
+//! 解析失败的异常都继承自 `DnsError`（模块中另有同一个类的旧名 `DnsResolutionError`）。
+//! 基类目前仍是 `RuntimeError` 的子类，原来捕获 `RuntimeError` 的代码不受影响；这一继承关系保留到下一个版本，
+//! 之后基类直接继承 `Exception`。子类按Rust侧的 `DnsError` 变体区分：
+//!
+//! - `NxDomainError`：域名不存在；`NoRecordsError`：域名存在但没有该类型的记录
+//! - `DnsTimeoutError`：查询超时（不与内置的 `TimeoutError` 重名）
+//! - `ServFailError` / `RefusedError`：上游应答SERVFAIL / REFUSED
+//! - `NetworkError`：连接、TLS、HTTP等传输失败
+//! - `ConfigError`：配置无效
+//! - `AllUpstreamsFailedError`：多个上游全部失败
+//!
+//! 每个异常都带有 `domain`、`record_type`、`upstream`、`rcode`、`phase` 和 `failure_report` 属性，
+//! 取不到的为None。属性保存在实例的 `__dict__` 中，异常可以用pickle往返（例如跨进程池传递）。
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+
+use crate::error::{DnsError, NetworkErrorKind};
+
+create_exception!(rat_quickdns_py, DnsResolutionError, PyRuntimeError, "DNS解析失败");
+create_exception!(rat_quickdns_py, NxDomainError, DnsResolutionError, "域名不存在（NXDOMAIN）");
+create_exception!(rat_quickdns_py, NoRecordsError, DnsResolutionError, "域名存在但没有所查询类型的记录（NODATA）");
+create_exception!(rat_quickdns_py, DnsTimeoutError, DnsResolutionError, "查询超时");
+create_exception!(rat_quickdns_py, ServFailError, DnsResolutionError, "上游应答SERVFAIL");
+create_exception!(rat_quickdns_py, RefusedError, DnsResolutionError, "上游拒绝查询（REFUSED）");
+create_exception!(rat_quickdns_py, NetworkError, DnsResolutionError, "连接或传输失败（网络、TLS、HTTP）");
+create_exception!(rat_quickdns_py, ConfigError, DnsResolutionError, "配置无效");
+create_exception!(rat_quickdns_py, AllUpstreamsFailedError, DnsResolutionError, "所有上游都失败");
+
+/// 把解析错误转换为对应的Python异常
+///
+/// `record_type` 为查询的记录类型名称（如 "A"），一次查询多种类型时为None。
+/// 多个上游全部失败时，异常的 `failure_report` 属性为汇总诊断的字典（与 `DnsResponse.failure_report` 相同），
+/// 其他情况下为None
+pub(crate) fn resolution_error(what: &str, domain: &str, record_type: Option<&str>, error: DnsError) -> PyErr {
+    let message = format!("{} failed for '{}': {}", what, domain, error);
+    let err = match &error {
+        DnsError::NxDomain => NxDomainError::new_err(message),
+        DnsError::NoRecords { .. } => NoRecordsError::new_err(message),
+        DnsError::Timeout { .. }
+        | DnsError::Network { kind: NetworkErrorKind::TimedOut, .. } => DnsTimeoutError::new_err(message),
+        DnsError::ServerFailure => ServFailError::new_err(message),
+        DnsError::Refused => RefusedError::new_err(message),
+        DnsError::Io(_)
+        | DnsError::Network { .. }
+        | DnsError::ConnectFailed { .. }
+        | DnsError::Tls(_)
+        | DnsError::TlsFailure { .. }
+        | DnsError::Http(_)
+        | DnsError::HttpStatus { .. }
+        | DnsError::UnexpectedContentType { .. }
+        | DnsError::ResponseTooLarge { .. } => NetworkError::new_err(message),
+        DnsError::Config(_) | DnsError::InvalidConfig(_) => ConfigError::new_err(message),
+        DnsError::AllUpstreamsFailed { .. } => AllUpstreamsFailedError::new_err(message),
+        _ => DnsResolutionError::new_err(message),
+    };
+
+    let upstream = match &error {
+        DnsError::Network { upstream, .. }
+        | DnsError::TlsFailure { upstream, .. }
+        | DnsError::HttpStatus { upstream, .. }
+        | DnsError::UnexpectedContentType { upstream, .. }
+        | DnsError::ResponseTooLarge { upstream, .. }
+        | DnsError::UpstreamSaturated { upstream, .. }
+        | DnsError::Quarantined { upstream, .. } if !upstream.is_empty() => Some(upstream.clone()),
+        DnsError::ConnectFailed { host, port, .. } => Some(format!("{}:{}", host, port)),
+        _ => None,
+    };
+    let rcode = match &error {
+        DnsError::NxDomain => Some("NXDOMAIN"),
+        DnsError::ServerFailure => Some("SERVFAIL"),
+        DnsError::Refused => Some("REFUSED"),
+        DnsError::FormatError => Some("FORMERR"),
+        _ => None,
+    };
+    let phase = match &error {
+        DnsError::Timeout { phase } => phase.map(|phase| phase.as_str()),
+        _ => None,
+    };
+
+    Python::with_gil(|py| {
+        let report = match &error {
+            DnsError::AllUpstreamsFailed { report } => report.to_json().ok()
+                .and_then(|json| py.import("json").and_then(|json_module| json_module.call_method1("loads", (json,))).ok())
+                .map(|report| report.into_py(py)),
+            _ => None,
+        };
+        let value = err.value(py);
+        let _ = value.setattr("domain", domain);
+        let _ = value.setattr("record_type", record_type);
+        let _ = value.setattr("upstream", upstream);
+        let _ = value.setattr("rcode", rcode);
+        let _ = value.setattr("phase", phase);
+        let _ = value.setattr("failure_report", report.unwrap_or_else(|| py.None()));
+    });
+    err
+}
+
+/// 在模块中注册异常类型
+pub(crate) fn register(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("DnsError", py.get_type::<DnsResolutionError>())?;
+    m.add("DnsResolutionError", py.get_type::<DnsResolutionError>())?;
+    m.add("NxDomainError", py.get_type::<NxDomainError>())?;
+    m.add("NoRecordsError", py.get_type::<NoRecordsError>())?;
+    m.add("DnsTimeoutError", py.get_type::<DnsTimeoutError>())?;
+    m.add("ServFailError", py.get_type::<ServFailError>())?;
+    m.add("RefusedError", py.get_type::<RefusedError>())?;
+    m.add("NetworkError", py.get_type::<NetworkError>())?;
+    m.add("ConfigError", py.get_type::<ConfigError>())?;
+    m.add("AllUpstreamsFailedError", py.get_type::<AllUpstreamsFailedError>())?;
+    Ok(())
+}
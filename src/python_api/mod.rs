@@ -10,20 +10,35 @@ pub mod builder;
 pub mod types;
 pub mod utils;
 pub mod logging;
+pub mod errors;
+mod oneshot;
+mod runtime;
 
-use resolver::PyDnsResolver;
+use resolver::{PyAddressWatch, PyDnsResolver};
 use builder::PyDnsResolverBuilder;
-use types::{PyQueryStrategy, PyDnsResult, PyDnsRecordType, PyTransportType};
+use types::{PyQueryStrategy, PyDnsResult, PyDnsRecord, PyDnsResponse, PyDnsRecordType, PyTransportType};
 
 /// Python模块初始化函数
-pub fn init_python_module(_py: Python, m: &PyModule) -> pyo3::PyResult<()> {
+pub fn init_python_module(py: Python, m: &PyModule) -> pyo3::PyResult<()> {
     // 添加类
     m.add_class::<PyDnsResolver>()?;
+    m.add_class::<PyAddressWatch>()?;
     m.add_class::<PyDnsResolverBuilder>()?;
     m.add_class::<PyQueryStrategy>()?;
     m.add_class::<PyDnsResult>()?;
+    m.add_class::<PyDnsResponse>()?;
+    m.add_class::<PyDnsRecord>()?;
     m.add_class::<PyTransportType>()?;
     m.add_class::<PyDnsRecordType>()?;
+    
+    // 添加异常类型
+    errors::register(py, m)?;
+
+    // 不构建解析器的单次查询
+    oneshot::register(m)?;
+
+    // 共享运行时的构建方式
+    runtime::register(m)?;
 
     // 保持架构纯净性，只暴露核心构建器类
 
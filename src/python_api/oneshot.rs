@@ -0,0 +1,100 @@
+//! 不构建解析器的单次查询（Python绑定）
+
+use pyo3::prelude::*;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use super::runtime::{acquire_runtime, release_runtime};
+use super::types::{PyDnsResult, PyTransportType};
+use crate::builder::types::DnsRecordType;
+use crate::oneshot::{self, OneshotOptions};
+use crate::upstream_handler::UpstreamType;
+
+fn value_error(message: String) -> PyErr {
+    PyErr::new::<pyo3::exceptions::PyValueError, _>(message)
+}
+
+/// 向单个上游发送一次查询，不构建解析器、不经过缓存和上游监控
+///
+/// Args:
+///     upstream (str): 上游，如 "udp://223.5.5.5"、"tcp://[2001:db8::1]:53"、"dot://1.1.1.1"、
+///         "doh://dns.alidns.com/dns-query" 或完整的 https URL
+///     domain (str): 要查询的域名
+///     record_type (str): 记录类型，默认 "A"
+///     timeout_ms (int): 超时（毫秒），默认5000
+///     edns (bool): 是否携带EDNS，默认True
+///     client_subnet (str, optional): 客户端子网，如 "203.0.113.0/24"
+///     protocol (TransportType, optional): 上游不带协议前缀时使用的协议，默认UDP
+///     dnssec (bool): 是否设置DNSSEC OK位
+///     verify_cert (bool): DoT/DoH是否校验证书，默认True
+///
+/// Returns:
+///     Result: 查询结果，`server_used` 为上游字符串；上游超时等失败体现在 `success`/`error` 中
+///
+/// Raises:
+///     ValueError: 上游字符串、记录类型或子网无效
+///
+/// Example:
+///     >>> result = rat_quickdns_py.oneshot_query("dot://1.1.1.1", "example.com")
+///     >>> result.success, result.value, result.protocol_used
+#[pyfunction]
+#[pyo3(signature = (
+    upstream,
+    domain,
+    record_type = "A",
+    timeout_ms = 5000,
+    edns = true,
+    client_subnet = None,
+    protocol = None,
+    dnssec = false,
+    verify_cert = true,
+))]
+#[allow(clippy::too_many_arguments)]
+fn oneshot_query(
+    py: Python,
+    upstream: &str,
+    domain: &str,
+    record_type: &str,
+    timeout_ms: u64,
+    edns: bool,
+    client_subnet: Option<&str>,
+    protocol: Option<PyTransportType>,
+    dnssec: bool,
+    verify_cert: bool,
+) -> PyResult<PyDnsResult> {
+    let record_type = DnsRecordType::from_str(record_type)
+        .ok_or_else(|| value_error(format!("Unknown record type '{}'", record_type)))?;
+    let mut options = OneshotOptions {
+        timeout: Duration::from_millis(timeout_ms),
+        edns,
+        protocol: match protocol {
+            None | Some(PyTransportType::UDP) => UpstreamType::Udp,
+            Some(PyTransportType::TCP) => UpstreamType::Tcp,
+            Some(PyTransportType::DOT) => UpstreamType::DoT,
+            Some(PyTransportType::DOH) => UpstreamType::DoH,
+        },
+        dnssec,
+        verify_cert,
+        ..OneshotOptions::default()
+    };
+    if let Some(subnet) = client_subnet {
+        let (ip, prefix) = subnet.split_once('/')
+            .ok_or_else(|| value_error(format!("Client subnet must be written as IP/prefix: '{}'", subnet)))?;
+        let ip: IpAddr = ip.parse().map_err(|_| value_error(format!("Invalid client subnet address: '{}'", ip)))?;
+        let prefix: u8 = prefix.parse().map_err(|_| value_error(format!("Invalid client subnet prefix: '{}'", prefix)))?;
+        options = options.with_client_subnet(ip, prefix).map_err(|e| value_error(e.to_string()))?;
+    }
+
+    let runtime = acquire_runtime()?;
+    let result = py.allow_threads(|| runtime.block_on(oneshot::query(upstream, domain, record_type, &options)));
+    release_runtime(runtime);
+    result
+        .map(|response| PyDnsResult::from_query_response(&response))
+        .map_err(|e| value_error(e.to_string()))
+}
+
+/// 在模块中注册单次查询函数
+pub(crate) fn register(m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(oneshot_query, m)?)?;
+    Ok(())
+}
@@ -3,28 +3,37 @@
 //! 提供了DnsResolver的Python绑定，用于执行DNS查询操作。
 
 use pyo3::prelude::*;
-use std::sync::Arc;
-use tokio::runtime::Runtime;
+use futures::StreamExt;
+use std::sync::{Arc, Mutex};
 
-use crate::builder::SmartDnsResolver;
-use crate::builder::types::{DnsQueryRequest, DnsRecordType};
+use crate::builder::{parse_warmup_list, AddressEvent, AddressWatch, MxLookupOptions, MxResolution, QueryOutcome, SmartDnsResolver, WarmOptions};
+use crate::builder::types::{DnsQueryRequest, DnsQueryResponse, DnsRecordType, QueryContext, UpstreamFilter};
 use crate::builder::strategy::QueryStrategy;
-use super::types::{PyQueryStrategy, PyDnsResult, PyEmergencyResponseInfo};
+use crate::upstream_handler::{UpstreamLabels, UpstreamSpec};
+use crate::config::StrictDnsConfig;
+use super::builder::validate_weight;
+use super::errors::resolution_error;
+use super::runtime::{release_runtime, RuntimeLease};
+use super::types::{PyQueryStrategy, PyDnsResult, PyDnsResponse, PyEmergencyResponseInfo};
 
 /// Python版本的DNS解析器
 /// 
 /// 提供DNS查询功能，支持单个域名解析、批量解析等操作。
 /// 解析器是线程安全的，可以在多个线程中共享使用。
 /// 
+/// 用完后调用 `close()` 或用 `with` 语句管理生命周期，关闭后再调用任何查询方法都会抛出 RuntimeError。
+/// 
+/// fork之前构建的解析器不能在子进程中使用（调用时抛出 RuntimeError），子进程需要重新构建解析器。
+/// 
 /// Example:
-///     >>> resolver = DnsResolverBuilder().build()
-///     >>> ips = resolver.resolve("example.com")
+///     >>> with DnsResolverBuilder().build() as resolver:
+///     ...     ips = resolver.resolve("example.com")
 ///     >>> print(ips)
 ///     ['93.184.216.34']
 #[pyclass(name = "DnsResolver")]
 pub struct PyDnsResolver {
-    inner: Arc<SmartDnsResolver>,
-    runtime: Arc<Runtime>,
+    /// 解析器及其使用的共享运行时，关闭后为 `None`
+    state: Mutex<Option<(Arc<SmartDnsResolver>, RuntimeLease)>>,
 }
 
 #[pymethods]
@@ -33,30 +42,32 @@ impl PyDnsResolver {
     /// 
     /// Args:
     ///     domain (str): 要解析的域名
+    ///     only_upstream (str, optional): 只经该上游查询（调试用），不读缓存；该上游不存在或已停用时查询失败
     /// 
     /// Returns:
     ///     List[str]: 解析得到的IP地址列表
     /// 
     /// Raises:
-    ///     RuntimeError: 如果解析失败
+    ///     NxDomainError: 域名不存在
+    ///     NoRecordsError: 域名存在但没有该类型的地址记录
+    ///     DnsError: 查询失败，按原因为 DnsTimeoutError、ServFailError、NetworkError 等子类
     /// 
     /// Example:
     ///     >>> ips = resolver.resolve("google.com")
     ///     >>> print(ips)
     ///     ['142.250.191.14']
-    pub fn resolve(&self, py: Python, domain: &str) -> pyo3::PyResult<Vec<String>> {
-        let resolver = self.inner.clone();
+    ///     >>> resolver.resolve("google.com", only_upstream="tencent_doh")
+    #[pyo3(signature = (domain, only_upstream = None))]
+    pub fn resolve(&self, py: Python, domain: &str, only_upstream: Option<String>) -> pyo3::PyResult<Vec<String>> {
+        let (resolver, runtime) = self.live()?;
         let domain = domain.to_string();
+        let request = address_request(&domain, DnsRecordType::A, only_upstream);
         
         py.allow_threads(|| {
-            self.runtime.block_on(async move {
-                let request = DnsQueryRequest::new(domain.clone(), DnsRecordType::A);
-                let result = resolver.query(request).await;
-                match result {
-                    Ok(response) => Ok(response.ip_addresses().into_iter().map(|ip| ip.to_string()).collect()),
-                    Err(e) => Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-                        format!("DNS resolution failed for '{}': {}", domain, e)
-                    )),
+            runtime.block_on(async move {
+                match resolver.lookup_ip_with(request).await {
+                    Ok(addresses) => Ok(addresses.into_iter().map(|ip| ip.to_string()).collect()),
+                    Err(e) => Err(resolution_error("DNS resolution", &domain, Some("A"), e)),
                 }
             })
         })
@@ -79,10 +90,10 @@ impl PyDnsResolver {
     ///     ...     else:
     ///     ...         print(f"{domains[i]}: Error - {result.unwrap_err()}")
     pub fn batch_resolve(&self, py: Python, domains: Vec<String>) -> pyo3::PyResult<Vec<PyDnsResult>> {
-        let resolver = self.inner.clone();
+        let (resolver, runtime) = self.live()?;
         
         py.allow_threads(|| {
-            self.runtime.block_on(async move {
+            runtime.block_on(async move {
                 let mut results = Vec::new();
                 for domain in domains {
                     let request = DnsQueryRequest::new(domain.clone(), DnsRecordType::A);
@@ -91,7 +102,7 @@ impl PyDnsResolver {
                 }
                 Ok(results.into_iter().map(|(domain, result)| {
                     match result {
-                        Ok(response) => PyDnsResult::ok(response.ip_addresses().into_iter().map(|ip| ip.to_string()).collect()),
+                        Ok(response) => PyDnsResult::from_query_response(&response),
                         Err(e) => PyDnsResult::err(e.to_string()),
                     }
                 }).collect())
@@ -99,67 +110,225 @@ impl PyDnsResolver {
         })
     }
     
+    /// 预热缓存：按正常查询路径解析列表中的条目，写入缓存并计入性能指标
+    /// 
+    /// Args:
+    ///     entries (List[str]): 条目，格式为 "domain" 或 "domain,type"（类型缺省为A）
+    ///     max_concurrency (int): 同时进行的查询数上限，默认8
+    ///     continue_on_error (bool): 某个条目失败后是否继续，默认True
+    ///     deadline (float): 整个预热的时限（秒），到期后取消尚未完成的查询，默认不限
+    /// 
+    /// Returns:
+    ///     dict: 包含 attempted、succeeded、failed（(域名, 错误信息) 列表）、skipped、
+    ///     deadline_exceeded、elapsed_ms
+    /// 
+    /// Raises:
+    ///     ValueError: 条目格式错误、并发数为0或时限为负数
+    /// 
+    /// Example:
+    ///     >>> report = resolver.warm_cache(["example.com", "example.com,AAAA", "gmail.com,MX"], deadline=10.0)
+    ///     >>> print(report["succeeded"], report["failed"])
+    #[pyo3(signature = (entries, max_concurrency = 8, continue_on_error = true, deadline = None))]
+    fn warm_cache(
+        &self,
+        py: Python,
+        entries: Vec<String>,
+        max_concurrency: usize,
+        continue_on_error: bool,
+        deadline: Option<f64>,
+    ) -> pyo3::PyResult<PyObject> {
+        let value_error = |e: String| PyErr::new::<pyo3::exceptions::PyValueError, _>(e);
+        let entries = parse_warmup_list(&entries.join("\n")).map_err(|e| value_error(e.to_string()))?;
+        let deadline = deadline
+            .map(std::time::Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|e| value_error(format!("Invalid deadline: {}", e)))?;
+        let options = WarmOptions { max_concurrency, continue_on_error, deadline };
+        let (resolver, runtime) = self.live()?;
+        
+        let report = py.allow_threads(|| runtime.block_on(resolver.warm_cache(entries, options)))
+            .map_err(|e| value_error(e.to_string()))?;
+        
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("attempted", report.attempted)?;
+        dict.set_item("succeeded", report.succeeded)?;
+        let failed: Vec<(String, String)> = report.failed.iter()
+            .map(|(domain, error)| (domain.clone(), error.to_string()))
+            .collect();
+        dict.set_item("failed", failed)?;
+        dict.set_item("skipped", report.skipped)?;
+        dict.set_item("deadline_exceeded", report.deadline_exceeded)?;
+        dict.set_item("elapsed_ms", report.elapsed.as_millis() as u64)?;
+        Ok(dict.into())
+    }
+    
     /// 解析A记录（IPv4地址）
     /// 
     /// Args:
     ///     domain (str): 要解析的域名
+    ///     only_upstream (str, optional): 只经该上游查询（调试用），不读缓存；该上游不存在或已停用时查询失败
     /// 
     /// Returns:
     ///     List[str]: IPv4地址列表
     /// 
     /// Raises:
-    ///     RuntimeError: 如果解析失败
+    ///     NxDomainError: 域名不存在
+    ///     NoRecordsError: 域名存在但没有该类型的地址记录
+    ///     DnsError: 查询失败，按原因为 DnsTimeoutError、ServFailError、NetworkError 等子类
     /// 
     /// Example:
     ///     >>> ipv4_addrs = resolver.resolve_a("example.com")
     ///     >>> print(ipv4_addrs)
     ///     ['93.184.216.34']
-    fn resolve_a(&self, py: Python, domain: &str) -> pyo3::PyResult<Vec<String>> {
-        let resolver = self.inner.clone();
+    #[pyo3(signature = (domain, only_upstream = None))]
+    fn resolve_a(&self, py: Python, domain: &str, only_upstream: Option<String>) -> pyo3::PyResult<Vec<String>> {
+        let (resolver, runtime) = self.live()?;
         let domain = domain.to_string();
+        let request = address_request(&domain, DnsRecordType::A, only_upstream);
         
         py.allow_threads(|| {
-            self.runtime.block_on(async move {
-                let request = DnsQueryRequest::new(domain.clone(), DnsRecordType::A);
-                let result = resolver.query(request).await;
-                match result {
-                    Ok(response) => Ok(response.ip_addresses().into_iter().map(|ip| ip.to_string()).collect()),
-                    Err(e) => Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-                        format!("A record resolution failed for '{}': {}", domain, e)
-                    )),
+            runtime.block_on(async move {
+                match resolver.lookup_ip_with(request).await {
+                    Ok(addresses) => Ok(addresses.into_iter().map(|ip| ip.to_string()).collect()),
+                    Err(e) => Err(resolution_error("A record resolution", &domain, Some("A"), e)),
                 }
             })
         })
     }
     
+    /// 解析地址，立即返回，结果由Rust线程调用 `callback` 交回
+    /// 
+    /// 供不能 `await`、又不希望阻塞调用线程的框架使用。回调的参数为 `(addresses, error)`：
+    /// 成功时 `addresses` 为IP地址列表、`error` 为None，失败时 `addresses` 为None、`error` 为异常对象
+    /// （与 `resolve` 抛出的异常相同）。回调在持有GIL的Rust线程中执行，不在调用方的线程或事件循环中，
+    /// 需要回到事件循环时用 `loop.call_soon_threadsafe`；回调抛出的异常会被打印，不会传播。
+    /// 解析器关闭后尚未完成的查询可能不再回调。
+    /// 
+    /// Args:
+    ///     domain (str): 要解析的域名
+    ///     callback (Callable[[Optional[List[str]], Optional[Exception]], None]): 接收结果的回调
+    ///     record_type (str): "A" 或 "AAAA"，默认 "A"
+    ///     only_upstream (str, optional): 只经该上游查询（调试用），不读缓存
+    /// 
+    /// Raises:
+    ///     ValueError: 记录类型不是A或AAAA
+    ///     TypeError: `callback` 不可调用
+    /// 
+    /// Example:
+    ///     >>> loop = asyncio.get_running_loop()
+    ///     >>> future = loop.create_future()
+    ///     >>> def done(addresses, error):
+    ///     ...     loop.call_soon_threadsafe(future.set_result, addresses or error)
+    ///     >>> resolver.resolve_with_callback("example.com", done)
+    ///     >>> print(await future)
+    #[pyo3(signature = (domain, callback, record_type = "A", only_upstream = None))]
+    fn resolve_with_callback(
+        &self,
+        py: Python,
+        domain: &str,
+        callback: PyObject,
+        record_type: &str,
+        only_upstream: Option<String>,
+    ) -> pyo3::PyResult<()> {
+        let record_type = match DnsRecordType::from_str(record_type) {
+            Some(record_type @ (DnsRecordType::A | DnsRecordType::AAAA)) => record_type,
+            _ => return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                format!("Callback resolution supports A and AAAA records, got '{}'", record_type)
+            )),
+        };
+        if !callback.as_ref(py).is_callable() {
+            return Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>("callback must be callable"));
+        }
+        let (resolver, runtime) = self.live()?;
+        let domain = domain.to_string();
+        let request = address_request(&domain, record_type, only_upstream);
+        let what = format!("{} record resolution", record_type.as_str());
+        
+        runtime.spawn_detached(async move {
+            let result = resolver.lookup_ip_with(request).await;
+            Python::with_gil(|py| {
+                let args = match result {
+                    Ok(addresses) => {
+                        let addresses: Vec<String> = addresses.into_iter().map(|ip| ip.to_string()).collect();
+                        (addresses.into_py(py), py.None())
+                    }
+                    Err(e) => (py.None(), resolution_error(&what, &domain, Some(record_type.as_str()), e).into_py(py)),
+                };
+                if let Err(e) = callback.call1(py, args) {
+                    e.print(py);
+                }
+            });
+        });
+        Ok(())
+    }
+    
+    /// 订阅域名地址集合的变化，返回可迭代的 `AddressWatch`
+    /// 
+    /// 每隔 `interval` 秒不读缓存地重新解析，只在地址集合（不计顺序）变化时产生事件；第一次解析只记录基准集合。
+    /// 事件是dict：变化时包含 added、removed、full_set（IP地址字符串列表）和 observed_at（Unix时间戳）；
+    /// 一次轮询失败时包含 error（错误信息）、consecutive_failures 和 retry_in（秒），订阅继续并逐次加倍等待时间。
+    /// 相同域名、类型和间隔的订阅共用一个后台任务。迭代会阻塞（期间释放GIL），`next_event(timeout)` 可以限时等待；
+    /// 调用 `close()` 或丢弃对象即取消订阅，解析器关闭后迭代结束
+    /// 
+    /// Args:
+    ///     domain (str): 要监视的域名
+    ///     record_type (str): "A" 或 "AAAA"，默认 "A"
+    ///     interval (float): 轮询间隔（秒），默认30
+    /// 
+    /// Raises:
+    ///     ValueError: 记录类型不是A或AAAA、域名无效或间隔不是正数
+    /// 
+    /// Example:
+    ///     >>> for event in resolver.watch("db.example.com", interval=10):
+    ///     ...     if "error" not in event:
+    ///     ...         pool.rebalance(event["full_set"])
+    #[pyo3(signature = (domain, record_type = "A", interval = 30.0))]
+    fn watch(&self, domain: &str, record_type: &str, interval: f64) -> pyo3::PyResult<PyAddressWatch> {
+        let record_type = DnsRecordType::from_str(record_type).ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Unknown record type '{}'", record_type))
+        })?;
+        let interval = std::time::Duration::try_from_secs_f64(interval)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid watch interval: {}", e)))?;
+        let (resolver, runtime) = self.live()?;
+        // 订阅在运行时上启动轮询任务
+        let watch = {
+            let _entered = runtime.enter();
+            resolver.watch(domain, record_type, interval)
+        }.map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?;
+        Ok(PyAddressWatch {
+            state: Mutex::new(Some((watch, runtime))),
+        })
+    }
+    
     /// 解析AAAA记录（IPv6地址）
     /// 
     /// Args:
     ///     domain (str): 要解析的域名
+    ///     only_upstream (str, optional): 只经该上游查询（调试用），不读缓存；该上游不存在或已停用时查询失败
     /// 
     /// Returns:
     ///     List[str]: IPv6地址列表
     /// 
     /// Raises:
-    ///     RuntimeError: 如果解析失败
+    ///     NxDomainError: 域名不存在
+    ///     NoRecordsError: 域名存在但没有该类型的地址记录
+    ///     DnsError: 查询失败，按原因为 DnsTimeoutError、ServFailError、NetworkError 等子类
     /// 
     /// Example:
     ///     >>> ipv6_addrs = resolver.resolve_aaaa("google.com")
     ///     >>> print(ipv6_addrs)
     ///     ['2404:6800:4008:c06::71']
-    fn resolve_aaaa(&self, py: Python, domain: &str) -> pyo3::PyResult<Vec<String>> {
-        let resolver = self.inner.clone();
+    #[pyo3(signature = (domain, only_upstream = None))]
+    fn resolve_aaaa(&self, py: Python, domain: &str, only_upstream: Option<String>) -> pyo3::PyResult<Vec<String>> {
+        let (resolver, runtime) = self.live()?;
         let domain = domain.to_string();
+        let request = address_request(&domain, DnsRecordType::AAAA, only_upstream);
         
         py.allow_threads(|| {
-            self.runtime.block_on(async move {
-                let request = DnsQueryRequest::new(domain.clone(), DnsRecordType::AAAA);
-                let result = resolver.query(request).await;
-                match result {
-                    Ok(response) => Ok(response.ip_addresses().into_iter().map(|ip| ip.to_string()).collect()),
-                    Err(e) => Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-                        format!("AAAA record resolution failed for '{}': {}", domain, e)
-                    )),
+            runtime.block_on(async move {
+                match resolver.lookup_ip_with(request).await {
+                    Ok(addresses) => Ok(addresses.into_iter().map(|ip| ip.to_string()).collect()),
+                    Err(e) => Err(resolution_error("AAAA record resolution", &domain, Some("AAAA"), e)),
                 }
             })
         })
@@ -174,28 +343,15 @@ impl PyDnsResolver {
     ///     List[str]: CNAME记录列表
     /// 
     /// Raises:
-    ///     RuntimeError: 如果解析失败
+    ///     DnsError: 解析失败，按原因为 NxDomainError、DnsTimeoutError 等子类
     /// 
     /// Example:
     ///     >>> cnames = resolver.resolve_cname("www.github.com")
     ///     >>> print(cnames)
     ///     ['github.com']
     fn resolve_cname(&self, py: Python, domain: &str) -> pyo3::PyResult<Vec<String>> {
-        let resolver = self.inner.clone();
-        let domain = domain.to_string();
-        
-        py.allow_threads(|| {
-            self.runtime.block_on(async move {
-                let request = DnsQueryRequest::new(domain.clone(), DnsRecordType::CNAME);
-                let result = resolver.query(request).await;
-                match result {
-                    Ok(response) => Ok(response.domains()),
-                    Err(e) => Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-                        format!("CNAME record resolution failed for '{}': {}", domain, e)
-                    )),
-                }
-            })
-        })
+        let response = self.run_query(py, DnsQueryRequest::new(domain, DnsRecordType::CNAME))?;
+        Ok(response.domains())
     }
     
     /// 解析MX记录
@@ -207,30 +363,73 @@ impl PyDnsResolver {
     ///     List[str]: MX记录列表
     /// 
     /// Raises:
-    ///     RuntimeError: 如果解析失败
+    ///     DnsError: 解析失败，按原因为 NxDomainError、DnsTimeoutError 等子类
     /// 
     /// Example:
     ///     >>> mx_records = resolver.resolve_mx("gmail.com")
     ///     >>> print(mx_records)
     ///     ['5 gmail-smtp-in.l.google.com', '10 alt1.gmail-smtp-in.l.google.com']
     fn resolve_mx(&self, py: Python, domain: &str) -> pyo3::PyResult<Vec<String>> {
-        let resolver = self.inner.clone();
+        let response = self.run_query(py, DnsQueryRequest::new(domain, DnsRecordType::MX))?;
+        Ok(response.mx_records().into_iter().map(|(priority, exchange)| {
+            format!("{} {}", priority, exchange)
+        }).collect())
+    }
+    
+    /// 解析MX记录及各邮件交换主机的地址
+    /// 
+    /// Args:
+    ///     domain (str): 要解析的域名
+    ///     implicit_mx (bool): 没有MX记录时是否回退到域名自身的A/AAAA记录（RFC 5321），默认False
+    ///     max_concurrency (int): 同时进行地址解析的主机数上限，默认4
+    /// 
+    /// Returns:
+    ///     dict: `status` 为 "hosts"、"null_mx" 或 "no_mx"；`hosts` 为按优先级升序排列的主机列表，
+    ///     每项包含 exchange、priority、addresses、implicit
+    /// 
+    /// Raises:
+    ///     DnsError: 解析失败，按原因为 NxDomainError、DnsTimeoutError 等子类
+    /// 
+    /// Example:
+    ///     >>> result = resolver.resolve_mx_full("gmail.com")
+    ///     >>> if result["status"] == "null_mx":
+    ///     ...     print("该域名不接收邮件")
+    ///     >>> for host in result["hosts"]:
+    ///     ...     print(host["priority"], host["exchange"], host["addresses"])
+    #[pyo3(signature = (domain, implicit_mx = false, max_concurrency = 4))]
+    fn resolve_mx_full(&self, py: Python, domain: &str, implicit_mx: bool, max_concurrency: usize) -> pyo3::PyResult<PyObject> {
+        let (resolver, runtime) = self.live()?;
         let domain = domain.to_string();
+        let options = MxLookupOptions::new(implicit_mx, max_concurrency);
         
-        py.allow_threads(|| {
-            self.runtime.block_on(async move {
-                let request = DnsQueryRequest::new(domain.clone(), DnsRecordType::MX);
-                let result = resolver.query(request).await;
-                match result {
-                    Ok(response) => Ok(response.mx_records().into_iter().map(|(priority, exchange)| {
-                        format!("{} {}", priority, exchange)
-                    }).collect()),
-                    Err(e) => Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-                        format!("MX record resolution failed for '{}': {}", domain, e)
-                    )),
-                }
+        let resolution = py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.resolve_mx_with_addresses(&domain, options).await
+                    .map_err(|e| resolution_error("MX resolution", &domain, Some("MX"), e))
             })
-        })
+        })?;
+        
+        let dict = pyo3::types::PyDict::new(py);
+        let hosts = pyo3::types::PyList::empty(py);
+        let status = match resolution {
+            MxResolution::Hosts(mx_hosts) => {
+                for host in mx_hosts {
+                    let item = pyo3::types::PyDict::new(py);
+                    item.set_item("exchange", host.exchange)?;
+                    item.set_item("priority", host.priority)?;
+                    item.set_item("addresses", host.addresses.iter().map(|ip| ip.to_string()).collect::<Vec<_>>())?;
+                    item.set_item("implicit", host.implicit)?;
+                    hosts.append(item)?;
+                }
+                "hosts"
+            },
+            MxResolution::NullMx => "null_mx",
+            MxResolution::NoMx => "no_mx",
+        };
+        dict.set_item("status", status)?;
+        dict.set_item("hosts", hosts)?;
+        
+        Ok(dict.into())
     }
     
     /// 解析TXT记录
@@ -242,28 +441,15 @@ impl PyDnsResolver {
     ///     List[str]: TXT记录列表
     /// 
     /// Raises:
-    ///     RuntimeError: 如果解析失败
+    ///     DnsError: 解析失败，按原因为 NxDomainError、DnsTimeoutError 等子类
     /// 
     /// Example:
     ///     >>> txt_records = resolver.resolve_txt("google.com")
     ///     >>> print(txt_records)
     ///     ['v=spf1 include:_spf.google.com ~all']
     fn resolve_txt(&self, py: Python, domain: &str) -> pyo3::PyResult<Vec<String>> {
-        let resolver = self.inner.clone();
-        let domain = domain.to_string();
-        
-        py.allow_threads(|| {
-            self.runtime.block_on(async move {
-                let request = DnsQueryRequest::new(domain.clone(), DnsRecordType::TXT);
-                let result = resolver.query(request).await;
-                match result {
-                    Ok(response) => Ok(response.texts()),
-                    Err(e) => Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-                        format!("TXT record resolution failed for '{}': {}", domain, e)
-                    )),
-                }
-            })
-        })
+        let response = self.run_query(py, DnsQueryRequest::new(domain, DnsRecordType::TXT))?;
+        Ok(response.texts())
     }
     
     /// 获取解析器统计信息
@@ -275,41 +461,560 @@ impl PyDnsResolver {
     ///     >>> stats = resolver.get_stats()
     ///     >>> print(f"Total queries: {stats['total_queries']}")
     ///     >>> print(f"Cache hit rate: {stats['cache_hit_rate']:.2%}")
+    ///     >>> print(f"p95 latency: {stats['p95_latency_ms']:.1f}ms")
     fn get_stats(&self, py: Python) -> pyo3::PyResult<PyObject> {
-        let resolver = self.inner.clone();
+        let (resolver, runtime) = self.live()?;
         let dict = pyo3::types::PyDict::new(py);
         
         let stats = py.allow_threads(|| {
-            self.runtime.block_on(async move {
+            runtime.block_on(async move {
                 resolver.get_stats().await
             })
         });
         
+        dict.set_item("stats_enabled", stats.stats_enabled)?;
         dict.set_item("total_queries", stats.total_queries)?;
         dict.set_item("successful_queries", stats.successful_queries)?;
         dict.set_item("failed_queries", stats.failed_queries)?;
         dict.set_item("total_upstreams", stats.total_upstreams)?;
         dict.set_item("available_upstreams", stats.available_upstreams)?;
+        dict.set_item("current_active_tier", stats.current_active_tier)?;
+        dict.set_item("in_flight_sends", stats.in_flight_sends)?;
+        dict.set_item("offline", stats.offline)?;
+        dict.set_item("offline_transitions", stats.offline_transitions)?;
+        dict.set_item("metrics_skipped_cache_hits", stats.metrics_skipped_cache_hits)?;
+        dict.set_item("retries_performed", stats.retries_performed)?;
+        dict.set_item("dropped_mismatched_records", stats.dropped_mismatched_records)?;
         dict.set_item("strategy", format!("{:?}", stats.strategy))?;
         dict.set_item("edns_enabled", stats.edns_enabled)?;
         
         let success_rate = stats.success_rate();
         dict.set_item("success_rate", success_rate)?;
         
-        let avg_latency_ms = stats.avg_latency().as_millis() as f64;
+        let avg_latency_ms = stats.avg_latency().as_secs_f64() * 1000.0;
         dict.set_item("avg_latency_ms", avg_latency_ms)?;
+        dict.set_item("min_latency_ms", stats.min_latency.as_secs_f64() * 1000.0)?;
+        dict.set_item("max_latency_ms", stats.max_latency.as_secs_f64() * 1000.0)?;
+        dict.set_item("p50_latency_ms", stats.p50_latency.as_secs_f64() * 1000.0)?;
+        dict.set_item("p95_latency_ms", stats.p95_latency.as_secs_f64() * 1000.0)?;
+        dict.set_item("p99_latency_ms", stats.p99_latency.as_secs_f64() * 1000.0)?;
         
-        if let Some(fastest) = &stats.fastest_upstream {
-            dict.set_item("fastest_upstream", fastest)?;
+        let upstream_latency = pyo3::types::PyDict::new(py);
+        for (name, latency) in &stats.upstream_latency {
+            let entry = pyo3::types::PyDict::new(py);
+            entry.set_item("count", latency.count)?;
+            entry.set_item("avg_ms", latency.mean.as_secs_f64() * 1000.0)?;
+            entry.set_item("p50_ms", latency.p50.as_secs_f64() * 1000.0)?;
+            entry.set_item("p95_ms", latency.p95.as_secs_f64() * 1000.0)?;
+            entry.set_item("p99_ms", latency.p99.as_secs_f64() * 1000.0)?;
+            upstream_latency.set_item(name, entry)?;
         }
+        dict.set_item("upstream_latency", upstream_latency)?;
+        dict.set_item("emergency_mode", stats.emergency_mode)?;
+        dict.set_item("recent_success_ratio", stats.recent_success_ratio)?;
+        // 与 CoreResolverStats::to_json_value 的字段一致，没有时为None
+        dict.set_item("fastest_upstream", &stats.fastest_upstream)?;
+        dict.set_item("slowest_upstream", &stats.slowest_upstream)?;
+        let memory = match &stats.memory {
+            Some(memory) => {
+                let entry = pyo3::types::PyDict::new(py);
+                entry.set_item("limit_bytes", memory.limit_bytes)?;
+                entry.set_item("used_bytes", memory.used_bytes)?;
+                let components = pyo3::types::PyList::empty(py);
+                for usage in &memory.components {
+                    let component = pyo3::types::PyDict::new(py);
+                    component.set_item("component", format!("{:?}", usage.component))?;
+                    component.set_item("approx_bytes", usage.approx_bytes)?;
+                    component.set_item("shrinks", usage.shrinks)?;
+                    components.append(component)?;
+                }
+                entry.set_item("components", components)?;
+                Some(entry)
+            },
+            None => None,
+        };
+        dict.set_item("memory", memory)?;
+        // 按名称、后缀或类型主动失效移除的缓存条目数，未启用缓存时为None
+        let cache_invalidations = self.inner()?.cache_stats().map(|cache| cache.invalidations);
+        dict.set_item("cache_invalidations", cache_invalidations)?;
         
-        if let Some(slowest) = &stats.slowest_upstream {
-            dict.set_item("slowest_upstream", slowest)?;
+        Ok(dict.into())
+    }
+    
+    /// 移除指定名称在所有记录类型下的缓存（含否定应答），返回移除的条目数
+    /// 
+    /// Args:
+    ///     name (str): 域名，不区分大小写
+    /// 
+    /// Raises:
+    ///     ValueError: 名称为空或缓存后端不支持失效
+    /// 
+    /// Example:
+    ///     >>> resolver.invalidate_domain("api.internal.corp")
+    ///     2
+    fn invalidate_domain(&self, py: Python, name: &str) -> pyo3::PyResult<usize> {
+        let (resolver, runtime) = self.live()?;
+        
+        py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.invalidate_domain(name).await.map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to invalidate cache: {}", e))
+                })
+            })
+        })
+    }
+    
+    /// 移除指定后缀及其所有子域名的缓存，按标签边界匹配，返回移除的条目数
+    /// 
+    /// 每次调用扫描全部缓存条目，适合配置变更后调用，不宜频繁调用
+    /// 
+    /// Args:
+    ///     suffix (str): 域名后缀，如 "internal.corp"（不匹配 "notinternal.corp"）
+    /// 
+    /// Raises:
+    ///     ValueError: 后缀为空或缓存后端不支持失效
+    /// 
+    /// Example:
+    ///     >>> resolver.invalidate_suffix("internal.corp")
+    ///     14
+    fn invalidate_suffix(&self, py: Python, suffix: &str) -> pyo3::PyResult<usize> {
+        let (resolver, runtime) = self.live()?;
+        
+        py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.invalidate_suffix(suffix).await.map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to invalidate cache: {}", e))
+                })
+            })
+        })
+    }
+    
+    /// 移除指定记录类型的全部缓存，返回移除的条目数
+    /// 
+    /// Args:
+    ///     record_type (str): 记录类型，如 "AAAA"
+    /// 
+    /// Raises:
+    ///     ValueError: 记录类型未知或缓存后端不支持失效
+    /// 
+    /// Example:
+    ///     >>> resolver.invalidate_type("AAAA")
+    ///     31
+    fn invalidate_type(&self, py: Python, record_type: &str) -> pyo3::PyResult<usize> {
+        let record_type = DnsRecordType::from_str(record_type).ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Unknown record type '{}'", record_type))
+        })?;
+        let (resolver, runtime) = self.live()?;
+        
+        py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.invalidate_type(record_type).await.map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to invalidate cache: {}", e))
+                })
+            })
+        })
+    }
+    
+    /// 渐进清空缓存：按每秒 `rate_per_second` 个的速率让当前条目依次过期，命中次数少的先过期
+    /// 
+    /// 与 `clear_cache()` 不同，清空后上游不会同时收到所有名称的查询
+    /// 
+    /// Args:
+    ///     rate_per_second (int): 每秒过期的条目数，必须大于0
+    /// 
+    /// Returns:
+    ///     dict: `affected` 为安排过期的条目数，`retained` 为不受影响的条目数，
+    ///     `completes_in_secs` 为全部过期的预计秒数
+    /// 
+    /// Raises:
+    ///     ValueError: 速率为0或缓存后端不支持渐进清空
+    /// 
+    /// Example:
+    ///     >>> resolver.clear_cache_gradual(100)
+    ///     {'affected': 1000, 'retained': 0, 'completes_in_secs': 10.0}
+    fn clear_cache_gradual(&self, py: Python, rate_per_second: usize) -> pyo3::PyResult<PyObject> {
+        let (resolver, runtime) = self.live()?;
+        
+        let report = py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.clear_cache_gradual(rate_per_second).await.map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to clear cache gradually: {}", e))
+                })
+            })
+        })?;
+        
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("affected", report.affected)?;
+        dict.set_item("retained", report.retained)?;
+        dict.set_item("completes_in_secs", report.completes_in.as_secs_f64())?;
+        Ok(dict.into())
+    }
+    
+    /// 获取实际生效的配置（认证类请求头的值已隐去），字段与 `EffectiveConfig::to_json` 相同
+    /// 
+    /// Returns:
+    ///     dict: `resolver` 为解析器设置，`upstreams` 为各上游套用默认值后的选项，
+    ///     `features` 为编译时启用的特性，`version` 为库版本
+    /// 
+    /// Example:
+    ///     >>> config = resolver.get_effective_config()
+    ///     >>> print(config["resolver"]["timeout_ms"], [u["name"] for u in config["upstreams"]])
+    fn get_effective_config(&self, py: Python) -> pyo3::PyResult<PyObject> {
+        let json = self.inner()?.effective_config().to_json();
+        let config = py.import("json")?.call_method1("loads", (json,))?;
+        Ok(config.into())
+    }
+
+    /// 解释智能策略按当前性能指标会选择哪个上游，字段与 `SelectionExplanation::to_json` 相同
+    /// 
+    /// Returns:
+    ///     dict | None: `candidates` 为各候选的 `name`、`excluded`（`disabled`/`unavailable`，参与比较时为None）
+    ///     和 `score`（`base_weight`、`success_rate`、`success_component`、`latency_component`、`cdn_component`、
+    ///     `failure_component`、`recency_bonus`、`region_bonus`、`final_score`），`selected` 为选中的上游，
+    ///     `reason` 为选中原因，`runner_up` 为评分第二的上游；没有决策引擎时为None
+    /// 
+    /// Example:
+    ///     >>> explanation = resolver.explain_selection()
+    ///     >>> print(explanation["selected"], explanation["reason"])
+    fn explain_selection(&self, py: Python) -> pyo3::PyResult<Option<PyObject>> {
+        let (resolver, runtime) = self.live()?;
+        let explanation = py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.explain_selection().await
+            })
+        });
+        match explanation {
+            Some(explanation) => {
+                let json = explanation.to_json()
+                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+                Ok(Some(py.import("json")?.call_method1("loads", (json,))?.into()))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// 获取后台缓存清理最近一轮扫描得到的缓存概况，不会阻塞查询
+    ///
+    /// Returns:
+    ///     dict: `computed_at` 为快照时间（还没有快照时为None），`entries_by_type` 为各记录类型的条目数，
+    ///     `ttl_histogram` 为剩余TTL分布，`oldest_entry_age_secs`、`approx_memory_bytes`，
+    ///     以及开启统计时命中最多的 `hot_names`
+    ///
+    /// Example:
+    ///     >>> insights = resolver.get_cache_insights()
+    ///     >>> print(insights["entries"], insights["approx_memory_bytes"])
+    fn get_cache_insights(&self, py: Python) -> pyo3::PyResult<PyObject> {
+        let json = self.inner()?.cache_insights().to_json();
+        let insights = py.import("json")?.call_method1("loads", (json,))?;
+        Ok(insights.into())
+    }
+    
+    /// 向所有上游分别查询并比对答案（诊断模式，绕过缓存）
+    /// 
+    /// Args:
+    ///     domain (str): 要查询的域名
+    ///     record_type (str): 记录类型，默认 "A"
+    /// 
+    /// Returns:
+    ///     dict: `answers` 为各上游结果列表（upstream、protocol、latency_ms、answers 或 error），
+    ///     `majority` 为多数答案，`agreeing`/`failed` 为上游名称列表，
+    ///     `dissenting` 为不一致上游列表（upstream、answers、missing、extra）
+    /// 
+    /// Raises:
+    ///     ValueError: 记录类型未知
+    ///     DnsError: 查询无法执行（例如没有可用的上游），按原因为对应的子类
+    /// 
+    /// Example:
+    ///     >>> report = resolver.query_all("www.google.com")
+    ///     >>> for d in report["dissenting"]:
+    ///     ...     print(d["upstream"], d["extra"])
+    #[pyo3(signature = (domain, record_type = "A"))]
+    fn query_all(&self, py: Python, domain: &str, record_type: &str) -> pyo3::PyResult<PyObject> {
+        let record_type = DnsRecordType::from_str(record_type).ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Unknown record type '{}'", record_type))
+        })?;
+        let (resolver, runtime) = self.live()?;
+        let request = DnsQueryRequest::new(domain, record_type);
+        
+        let report = py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.query_all(&request, false).await.map_err(|e| {
+                    resolution_error("query_all", &request.domain, Some(request.record_type.as_str()), e)
+                })
+            })
+        })?;
+        
+        let answers = pyo3::types::PyList::empty(py);
+        for answer in &report.answers {
+            let item = pyo3::types::PyDict::new(py);
+            item.set_item("upstream", &answer.upstream)?;
+            item.set_item("protocol", &answer.protocol)?;
+            item.set_item("latency_ms", answer.latency.as_secs_f64() * 1000.0)?;
+            match &answer.result {
+                Ok(set) => item.set_item("answers", set)?,
+                Err(e) => item.set_item("error", e)?,
+            }
+            answers.append(item)?;
+        }
+        
+        let dissenting = pyo3::types::PyList::empty(py);
+        for dissent in &report.consensus.dissenting {
+            let item = pyo3::types::PyDict::new(py);
+            item.set_item("upstream", &dissent.upstream)?;
+            item.set_item("answers", &dissent.answers)?;
+            item.set_item("missing", &dissent.missing)?;
+            item.set_item("extra", &dissent.extra)?;
+            dissenting.append(item)?;
         }
         
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("answers", answers)?;
+        dict.set_item("majority", &report.consensus.majority)?;
+        dict.set_item("agreeing", &report.consensus.agreeing)?;
+        dict.set_item("dissenting", dissenting)?;
+        dict.set_item("failed", &report.consensus.failed)?;
         Ok(dict.into())
     }
     
+    /// 查询一种记录类型，返回带TTL和各字段的完整记录
+    /// 
+    /// Args:
+    ///     domain (str): 要查询的域名
+    ///     record_type (str): 记录类型，默认 "A"
+    ///     tenant (str, optional): 查询所属的租户，记入日志追踪和按租户统计，并带回响应的 `tenant`
+    ///     only_upstream (str, optional): 只经该上游查询（调试用），不读缓存；该上游不存在或已停用时查询失败
+    /// 
+    /// Returns:
+    ///     DnsResponse: `records` 为 DnsRecord 列表，每条记录含 name、type、ttl、value，
+    ///     MX记录另有 priority，SRV记录另有 priority、weight、port；查询失败时 `success` 为False
+    /// 
+    /// Raises:
+    ///     ValueError: 记录类型未知
+    /// 
+    /// Example:
+    ///     >>> response = resolver.query("gmail.com", "MX")
+    ///     >>> for record in response.records:
+    ///     ...     print(record.priority, record.value, record.ttl)
+    #[pyo3(signature = (domain, record_type = "A", tenant = None, only_upstream = None))]
+    fn query(
+        &self,
+        py: Python,
+        domain: &str,
+        record_type: &str,
+        tenant: Option<String>,
+        only_upstream: Option<String>,
+    ) -> pyo3::PyResult<PyDnsResponse> {
+        let record_type = DnsRecordType::from_str(record_type).ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Unknown record type '{}'", record_type))
+        })?;
+        let mut request = DnsQueryRequest::new(domain, record_type);
+        if let Some(tenant) = tenant {
+            request = request.with_context(QueryContext::for_tenant(tenant));
+        }
+        if let Some(upstream) = only_upstream {
+            request = request.with_upstream_filter(UpstreamFilter::only(upstream));
+        }
+        let response = self.run_query(py, request)?;
+        Ok(PyDnsResponse::from(&response))
+    }
+    
+    /// 查询并保留与上游收发的原始DNS报文（绕过缓存）
+    /// 
+    /// Args:
+    ///     domain (str): 要查询的域名
+    ///     record_type (str): 记录类型，默认 "A"
+    /// 
+    /// Returns:
+    ///     Result: 查询结果，`wire_request`/`wire_response` 为原始报文（bytes），
+    ///     `wire_truncated` 表示报文是否被截断
+    /// 
+    /// Raises:
+    ///     ValueError: 记录类型未知
+    /// 
+    /// Example:
+    ///     >>> result = resolver.resolve_with_wire("example.com")
+    ///     >>> open("answer.bin", "wb").write(result.wire_response)
+    #[pyo3(signature = (domain, record_type = "A"))]
+    fn resolve_with_wire(&self, py: Python, domain: &str, record_type: &str) -> pyo3::PyResult<PyDnsResult> {
+        let record_type = DnsRecordType::from_str(record_type).ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Unknown record type '{}'", record_type))
+        })?;
+        let (resolver, runtime) = self.live()?;
+        let request = DnsQueryRequest::new(domain, record_type).with_capture_wire(true);
+        
+        py.allow_threads(|| {
+            runtime.block_on(async move {
+                Ok(match resolver.query(request).await {
+                    Ok(response) => PyDnsResult::from_query_response(&response),
+                    Err(e) => PyDnsResult::err(e.to_string()),
+                })
+            })
+        })
+    }
+    
+    /// 一次解析同一域名的多种记录类型
+    /// 
+    /// 各类型并发查询，Smart策略下共用同一次上游选择；某个类型失败不影响其他类型
+    /// 
+    /// Args:
+    ///     domain (str): 要解析的域名
+    ///     types (List[str]): 记录类型，默认 ["A", "AAAA", "MX"]
+    /// 
+    /// Returns:
+    ///     dict: 以记录类型为键，每项包含 success、records、error、server_used、
+    ///     protocol_used、duration_ms、valid_until；records 为记录值的文本形式
+    /// 
+    /// Raises:
+    ///     ValueError: 记录类型未知或为空
+    /// 
+    /// Example:
+    ///     >>> results = resolver.resolve_all("example.com", ["A", "AAAA", "MX", "TXT", "NS"])
+    ///     >>> for record_type, result in results.items():
+    ///     ...     print(record_type, result["records"] if result["success"] else result["error"])
+    #[pyo3(signature = (domain, types = vec!["A".to_string(), "AAAA".to_string(), "MX".to_string()]))]
+    fn resolve_all(&self, py: Python, domain: &str, types: Vec<String>) -> pyo3::PyResult<PyObject> {
+        let types = types.iter().map(|name| {
+            DnsRecordType::from_str(name).ok_or_else(|| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Unknown record type '{}'", name))
+            })
+        }).collect::<pyo3::PyResult<Vec<_>>>()?;
+        let (resolver, runtime) = self.live()?;
+        
+        if types.is_empty() {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>("At least one record type is required"));
+        }
+        
+        let results = py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.query_multi_types(domain, types).await.map_err(|e| {
+                    resolution_error("resolve_all", domain, None, e)
+                })
+            })
+        })?;
+        
+        let dict = pyo3::types::PyDict::new(py);
+        for (record_type, response) in &results {
+            let item = pyo3::types::PyDict::new(py);
+            item.set_item("success", response.success)?;
+            item.set_item("records", response.records.iter().map(|r| r.value.to_string()).collect::<Vec<_>>())?;
+            item.set_item("error", &response.error)?;
+            item.set_item("server_used", &response.server_used)?;
+            item.set_item("protocol_used", &response.protocol_used)?;
+            item.set_item("duration_ms", response.duration_ms)?;
+            item.set_item("valid_until", response.valid_until)?;
+            dict.set_item(record_type.as_str(), item)?;
+        }
+        Ok(dict.into())
+    }
+    
+    /// 获取最近的查询历史
+    /// 
+    /// 需要在构建器上调用 `with_query_history(capacity)` 启用，未启用时返回空列表
+    /// 
+    /// Args:
+    ///     n (int): 返回的最大条数，默认100
+    /// 
+    /// Returns:
+    ///     List[dict]: 按时间倒序排列的查询记录，每项包含 timestamp、domain、record_type、
+    ///     strategy、upstream、outcome、rcode、duration_ms、cache_hit、failovers
+    ///     （因SERVFAIL/REFUSED而放弃的上游，每项为 (上游名称, 响应码)）
+    /// 
+    /// Example:
+    ///     >>> for entry in resolver.get_recent_queries(10):
+    ///     ...     print(entry["timestamp"], entry["domain"], entry["outcome"])
+    #[pyo3(signature = (n = 100))]
+    fn get_recent_queries(&self, py: Python, n: usize) -> pyo3::PyResult<Vec<PyObject>> {
+        self.live()?.0.recent_queries(n).into_iter().map(|entry| {
+            let dict = pyo3::types::PyDict::new(py);
+            let timestamp = entry.timestamp
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0);
+            dict.set_item("timestamp", timestamp)?;
+            dict.set_item("domain", entry.domain.as_ref())?;
+            dict.set_item("record_type", format!("{:?}", entry.record_type))?;
+            dict.set_item("strategy", format!("{:?}", entry.strategy))?;
+            dict.set_item("upstream", entry.upstream.as_deref())?;
+            match entry.outcome {
+                QueryOutcome::Success { rcode } => {
+                    dict.set_item("outcome", "success")?;
+                    dict.set_item("rcode", rcode)?;
+                },
+                QueryOutcome::Failed => {
+                    dict.set_item("outcome", "failed")?;
+                    dict.set_item("rcode", py.None())?;
+                },
+            }
+            dict.set_item("duration_ms", entry.duration.as_secs_f64() * 1000.0)?;
+            dict.set_item("cache_hit", entry.cache_hit)?;
+            let failovers: Vec<(String, u16)> = entry.failovers.iter()
+                .map(|failover| (failover.upstream.clone(), u16::from(failover.rcode)))
+                .collect();
+            dict.set_item("failovers", failovers)?;
+            Ok(dict.into())
+        }).collect()
+    }
+    
+    /// 获取上游最近的状态变化
+    /// 
+    /// 需要启用上游监控，每个上游保留的条数由构建器的 `with_upstream_event_history` 设置（默认64条）
+    /// 
+    /// Args:
+    ///     name (str): 上游名称
+    ///     since (float, optional): Unix时间戳（秒），只返回该时间及之后的事件
+    /// 
+    /// Returns:
+    ///     List[dict]: 按时间顺序排列的事件，每项包含 timestamp、old_status、new_status
+    ///     （Available、Unavailable 或 Unknown）、trigger（如 consecutive_failures、recovery、manual）
+    ///     和上游的 labels
+    /// 
+    /// Example:
+    ///     >>> for event in resolver.get_upstream_events("ali-doh"):
+    ///     ...     print(event["timestamp"], event["old_status"], "->", event["new_status"], event["trigger"])
+    #[pyo3(signature = (name, since = None))]
+    fn get_upstream_events(&self, py: Python, name: &str, since: Option<f64>) -> pyo3::PyResult<Vec<PyObject>> {
+        let since = since
+            .map(|seconds| std::time::Duration::try_from_secs_f64(seconds)
+                .map(|offset| std::time::UNIX_EPOCH + offset)
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("Invalid since timestamp: {}", e))))
+            .transpose()?;
+        self.live()?.0.get_upstream_events(name, since).into_iter().map(|event| {
+            let dict = pyo3::types::PyDict::new(py);
+            let timestamp = event.timestamp
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0);
+            dict.set_item("timestamp", timestamp)?;
+            dict.set_item("old_status", format!("{:?}", event.old_status))?;
+            dict.set_item("new_status", format!("{:?}", event.new_status))?;
+            dict.set_item("trigger", event.trigger.as_str())?;
+            dict.set_item("labels", event.labels)?;
+            Ok(dict.into())
+        }).collect()
+    }
+    
+    /// 获取各上游的当前状态，字段与 `UpstreamStatus::to_json_value` 相同
+    /// 
+    /// Returns:
+    ///     List[dict]: 按配置顺序，每项包含 name、server、is_available、success_rate、avg_latency_ms、
+    ///     effective_timeout_ms、labels 等；没有决策引擎时为空列表
+    /// 
+    /// Example:
+    ///     >>> for status in resolver.get_upstream_status():
+    ///     ...     print(status["name"], status["is_available"], status["labels"].get("provider"))
+    fn get_upstream_status(&self, py: Python) -> pyo3::PyResult<PyObject> {
+        let (resolver, runtime) = self.live()?;
+        let statuses = py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.get_upstream_status().await
+            })
+        });
+        let values: Vec<serde_json::Value> = statuses.iter().map(|status| status.to_json_value()).collect();
+        let json = serde_json::to_string(&values)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+        Ok(py.import("json")?.call_method1("loads", (json,))?.into())
+    }
+    
     /// 使用指定策略解析域名
     /// 
     /// Args:
@@ -325,16 +1030,16 @@ impl PyDnsResolver {
     ///     >>> if result.is_ok():
     ///     ...     print(result.unwrap())
     fn resolve_with_strategy(&self, py: Python, domain: &str, strategy: PyQueryStrategy) -> pyo3::PyResult<PyDnsResult> {
-        let resolver = self.inner.clone();
+        let (resolver, runtime) = self.live()?;
         let domain = domain.to_string();
         let strategy = strategy.to_rust();
         
         py.allow_threads(|| {
-            self.runtime.block_on(async move {
+            runtime.block_on(async move {
                 let request = DnsQueryRequest::new(domain.clone(), DnsRecordType::A);
                 let result = resolver.query(request).await;
                 Ok(match result {
-                    Ok(response) => PyDnsResult::ok(response.ip_addresses().into_iter().map(|ip| ip.to_string()).collect()),
+                    Ok(response) => PyDnsResult::from_query_response(&response),
                     Err(e) => PyDnsResult::err(e.to_string()),
                 })
             })
@@ -353,10 +1058,10 @@ impl PyDnsResolver {
      ///     >>>     for detail in emergency_info.get_failed_server_details():
      ///     >>>         print(f"  - {detail}")
      fn get_emergency_response_info(&self, py: Python) -> pyo3::PyResult<PyEmergencyResponseInfo> {
-         let resolver = self.inner.clone();
+         let (resolver, runtime) = self.live()?;
          
          py.allow_threads(|| {
-             self.runtime.block_on(async move {
+             runtime.block_on(async move {
                  if let Some(engine) = resolver.get_decision_engine() {
                      let info = engine.get_emergency_response_info().await;
                      Ok(PyEmergencyResponseInfo::from(&info))
@@ -368,12 +1073,172 @@ impl PyDnsResolver {
                          last_working_server: None,
                          total_failures: 0,
                          emergency_message: "无决策引擎，使用基础解析器".to_string(),
+                         emergency_mode: false,
+                         success_ratio: None,
                      })
                  }
              })
          })
      }
     
+    /// 运行时添加上游服务器，无需重建解析器
+    /// 
+    /// Args:
+    ///     name (str): 服务器名称，不能与已有上游重复
+    ///     transport (str): 传输协议，"udp"、"tcp"、"dot" 或 "doh"
+    ///     server (str): 服务器地址，DoH为HTTPS URL
+    ///     weight (int): 负载均衡权重，默认1
+    ///     labels (Dict[str, str], optional): 上游标签，只作为元数据输出
+    /// 
+    /// Raises:
+    ///     ValueError: 协议未知、参数或标签无效、名称或服务器已存在
+    /// 
+    /// Example:
+    ///     >>> resolver.add_upstream("Cloudflare", "udp", "1.1.1.1")
+    ///     >>> resolver.add_upstream("阿里DoH", "doh", "https://dns.alidns.com/dns-query", weight=2,
+    ///     ...                       labels={"provider": "alibaba"})
+    #[pyo3(signature = (name, transport, server, weight = 1, labels = None))]
+    fn add_upstream(&self, py: Python, name: String, transport: &str, server: String, weight: i64, labels: Option<UpstreamLabels>) -> pyo3::PyResult<()> {
+        let weight = validate_weight(weight)?;
+        let spec = match transport.to_ascii_lowercase().as_str() {
+            "udp" => UpstreamSpec::udp(name, server),
+            "tcp" => UpstreamSpec::tcp(name, server),
+            "dot" => UpstreamSpec::dot(name, server),
+            "doh" => UpstreamSpec::doh(name, server),
+            other => return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                format!("Unknown transport '{}', expected udp, tcp, dot or doh", other)
+            )),
+        }.with_weight(weight).with_labels(labels.unwrap_or_default());
+        let (resolver, runtime) = self.live()?;
+        
+        py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.add_upstream(spec).await.map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to add upstream: {}", e))
+                })
+            })
+        })
+    }
+    
+    /// 运行时移除上游服务器
+    /// 
+    /// 新的查询立即不再使用该上游，已经发出的查询照常完成
+    /// 
+    /// Args:
+    ///     name (str): 服务器名称
+    /// 
+    /// Raises:
+    ///     ValueError: 上游不存在
+    fn remove_upstream(&self, py: Python, name: &str) -> pyo3::PyResult<()> {
+        let (resolver, runtime) = self.live()?;
+        
+        py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.remove_upstream(name).await.map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to remove upstream: {}", e))
+                })
+            })
+        })
+    }
+    
+    /// 运行时启用或停用上游服务器，停用的上游保留配置但不参与查询
+    /// 
+    /// Args:
+    ///     name (str): 服务器名称
+    ///     enabled (bool): 是否启用
+    /// 
+    /// Raises:
+    ///     ValueError: 上游不存在
+    fn set_upstream_enabled(&self, py: Python, name: &str, enabled: bool) -> pyo3::PyResult<()> {
+        let (resolver, runtime) = self.live()?;
+        
+        py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.set_upstream_enabled(name, enabled).await.map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to update upstream: {}", e))
+                })
+            })
+        })
+    }
+    
+    /// 按新的严格配置重新加载，字段与 `DnsResolverBuilder.from_config_json` 相同
+    /// 
+    /// 先完整校验新配置，失败时解析器不做任何修改。连接参数不变的上游沿用原连接和性能指标，
+    /// 移除的上游等已经发出的查询完成后关闭；已经开始的查询按原设置完成，缓存只在TTL上限收紧时清空
+    /// 
+    /// Args:
+    ///     config_json (str): 新的严格配置
+    /// 
+    /// Returns:
+    ///     dict: 重新加载的结果，字段与 `ReloadReport` 相同（added、removed、reconnected、updated、
+    ///     unchanged、changed_settings、changes、cache_cleared、undrained）
+    /// 
+    /// Raises:
+    ///     ValueError: JSON无法解析、配置无效或改变了不能在运行时修改的设置
+    /// 
+    /// Example:
+    ///     >>> with open("dns.json") as f:
+    ///     ...     report = resolver.reload(f.read())
+    ///     >>> report["added"]
+    fn reload(&self, py: Python, config_json: &str) -> pyo3::PyResult<PyObject> {
+        let config: StrictDnsConfig = serde_json::from_str(config_json).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid config: {}", e))
+        })?;
+        let (resolver, runtime) = self.live()?;
+        let report = py.allow_threads(|| {
+            runtime.block_on(async move {
+                resolver.reload(config).await.map_err(|e| {
+                    PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Failed to reload: {}", e))
+                })
+            })
+        })?;
+        let json = serde_json::to_string(&report)
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
+        Ok(py.import("json")?.call_method1("loads", (json,))?.into())
+    }
+    
+    /// 关闭解析器
+    /// 
+    /// 停止后台任务（配置了性能指标快照路径时会先保存一次快照）并归还共享运行时，
+    /// 最后一个解析器关闭时运行时的工作线程随之退出。重复调用无副作用。
+    /// 
+    /// Example:
+    ///     >>> resolver.close()
+    ///     >>> resolver.resolve("example.com")
+    ///     RuntimeError: DnsResolver is closed
+    fn close(&self, py: Python) {
+        let Some((resolver, runtime)) = self.lock_state().take() else {
+            return;
+        };
+        if runtime.is_inherited() {
+            forget_inherited(resolver, runtime);
+            return;
+        }
+        py.allow_threads(|| {
+            runtime.block_on(resolver.shutdown());
+            // 解析器持有的传输可能依赖运行时，先于运行时释放
+            drop(resolver);
+            release_runtime(runtime);
+        });
+    }
+    
+    /// 进入 `with` 语句，返回解析器本身
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+    
+    /// 退出 `with` 语句时关闭解析器，不吞掉异常
+    fn __exit__(
+        &self,
+        py: Python,
+        _exc_type: Option<&PyAny>,
+        _exc_value: Option<&PyAny>,
+        _traceback: Option<&PyAny>,
+    ) -> bool {
+        self.close(py);
+        false
+    }
+    
     /// 字符串表示
     fn __str__(&self) -> String {
         "DnsResolver".to_string()
@@ -381,36 +1246,175 @@ impl PyDnsResolver {
     
     /// 调试表示
     fn __repr__(&self) -> String {
-        "DnsResolver()".to_string()
+        if self.lock_state().is_some() {
+            "DnsResolver()".to_string()
+        } else {
+            "DnsResolver(closed)".to_string()
+        }
     }
 }
 
 impl Drop for PyDnsResolver {
     fn drop(&mut self) {
-        // 确保Runtime被正确关闭，避免死锁
-        // 由于Runtime是Arc包装的，我们需要检查是否是最后一个引用
-        if Arc::strong_count(&self.runtime) == 1 {
-            // 这是最后一个引用，可以安全关闭Runtime
-            if let Ok(runtime) = Arc::try_unwrap(std::mem::replace(&mut self.runtime, Arc::new(Runtime::new().unwrap()))) {
-                runtime.shutdown_background();
+        // 未调用close()就被回收时同样归还共享运行时，避免运行时永远不被关闭
+        let state = self.state.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        match state {
+            Some((resolver, runtime)) if runtime.is_inherited() => forget_inherited(resolver, runtime),
+            Some((resolver, runtime)) => {
+                drop(resolver);
+                release_runtime(runtime);
             }
+            None => {}
         }
     }
 }
 
 impl PyDnsResolver {
-    /// 创建新的Python DNS解析器实例
-    pub fn new(resolver: SmartDnsResolver) -> Self {
-        let runtime = Runtime::new().expect("Failed to create Tokio runtime");
-        
+    /// 用已构建的解析器和从共享运行时取得的运行时创建Python解析器，运行时在关闭时归还
+    pub(crate) fn new(resolver: SmartDnsResolver, runtime: RuntimeLease) -> Self {
         Self {
-            inner: Arc::new(resolver),
-            runtime: Arc::new(runtime),
+            state: Mutex::new(Some((Arc::new(resolver), runtime))),
+        }
+    }
+    
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, Option<(Arc<SmartDnsResolver>, RuntimeLease)>> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
+    /// 取得解析器和运行时，已关闭或是fork之前构建的时抛出 RuntimeError
+    fn live(&self) -> pyo3::PyResult<(Arc<SmartDnsResolver>, RuntimeLease)> {
+        let (resolver, runtime) = self.lock_state().clone().ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>("DnsResolver is closed")
+        })?;
+        if runtime.is_inherited() {
+            return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+                "DnsResolver was built before fork() and cannot be used in this process; build a new resolver after forking"
+            ));
         }
+        Ok((resolver, runtime))
     }
     
-    /// 获取内部解析器的引用
-    pub fn inner(&self) -> &SmartDnsResolver {
-        &self.inner
+    /// 执行一次查询，等待期间释放GIL；解析器出错时按错误类型抛出 `DnsError` 的子类
+    fn run_query(&self, py: Python, request: DnsQueryRequest) -> pyo3::PyResult<DnsQueryResponse> {
+        let (resolver, runtime) = self.live()?;
+        let record_type = request.record_type.as_str();
+        let what = format!("{} record resolution", record_type);
+        let domain = request.domain.clone();
+        py.allow_threads(|| runtime.block_on(resolver.query(request))).map_err(|e| {
+            resolution_error(&what, &domain, Some(record_type), e)
+        })
+    }
+    
+    /// 获取内部解析器，已关闭时返回错误
+    pub fn inner(&self) -> pyo3::PyResult<Arc<SmartDnsResolver>> {
+        self.live().map(|(resolver, _)| resolver)
     }
-}
\ No newline at end of file
+}
+
+/// `DnsResolver.watch()` 返回的地址变化订阅，可迭代
+#[pyclass(name = "AddressWatch")]
+pub struct PyAddressWatch {
+    /// 事件流及驱动它的运行时，关闭后为 `None`
+    state: Mutex<Option<(AddressWatch, RuntimeLease)>>,
+}
+
+#[pymethods]
+impl PyAddressWatch {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+    
+    fn __next__(&self, py: Python) -> pyo3::PyResult<Option<PyObject>> {
+        self.next_event(py, None)
+    }
+    
+    /// 等待下一个事件
+    /// 
+    /// Args:
+    ///     timeout (float, optional): 最长等待秒数，不指定时一直等待
+    /// 
+    /// Returns:
+    ///     Optional[dict]: 事件，超时返回None
+    /// 
+    /// Raises:
+    ///     StopIteration: 订阅已关闭或解析器已关闭
+    #[pyo3(signature = (timeout = None))]
+    fn next_event(&self, py: Python, timeout: Option<f64>) -> pyo3::PyResult<Option<PyObject>> {
+        let timeout = timeout
+            .map(std::time::Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid timeout: {}", e)))?;
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some((watch, runtime)) = state.as_mut() else {
+            return Err(PyErr::new::<pyo3::exceptions::PyStopIteration, _>(()));
+        };
+        let event = py.allow_threads(|| runtime.block_on(async {
+            match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, watch.next()).await.ok(),
+                None => Some(watch.next().await),
+            }
+        }));
+        match event {
+            None => Ok(None),
+            Some(None) => {
+                *state = None;
+                Err(PyErr::new::<pyo3::exceptions::PyStopIteration, _>(()))
+            }
+            Some(Some(event)) => address_event_dict(py, event).map(Some),
+        }
+    }
+    
+    /// 取消订阅；该键的最后一个订阅关闭时后台轮询随之停止
+    fn close(&self) {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+    }
+    
+    fn __repr__(&self) -> String {
+        let open = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).is_some();
+        format!("AddressWatch(open={})", open)
+    }
+}
+
+/// 地址订阅事件转换为Python dict
+fn address_event_dict(py: Python, event: AddressEvent) -> pyo3::PyResult<PyObject> {
+    let dict = pyo3::types::PyDict::new(py);
+    let addresses = |ips: Vec<std::net::IpAddr>| ips.into_iter().map(|ip| ip.to_string()).collect::<Vec<_>>();
+    match event {
+        AddressEvent::Changed(change) => {
+            let observed_at = change.observed_at
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0);
+            dict.set_item("domain", change.domain)?;
+            dict.set_item("record_type", change.record_type.as_str())?;
+            dict.set_item("added", addresses(change.added))?;
+            dict.set_item("removed", addresses(change.removed))?;
+            dict.set_item("full_set", addresses(change.full_set))?;
+            dict.set_item("observed_at", observed_at)?;
+        }
+        AddressEvent::Error { error, consecutive_failures, retry_in } => {
+            dict.set_item("error", error.to_string())?;
+            dict.set_item("consecutive_failures", consecutive_failures)?;
+            dict.set_item("retry_in", retry_in.as_secs_f64())?;
+        }
+    }
+    Ok(dict.into())
+}
+
+/// 丢弃fork之前构建的解析器
+/// 
+/// 它的套接字注册在父进程的运行时里，释放时会改动与父进程共用的事件注册，运行时也无法在子进程中关闭，
+/// 所以两者都不释放，由子进程退出时一并回收
+fn forget_inherited(resolver: Arc<SmartDnsResolver>, runtime: RuntimeLease) {
+    std::mem::forget(resolver);
+    release_runtime(runtime);
+}
+
+/// 地址查询的请求，指定 `only_upstream` 时只经该上游查询
+fn address_request(domain: &str, record_type: DnsRecordType, only_upstream: Option<String>) -> DnsQueryRequest {
+    let request = DnsQueryRequest::new(domain, record_type);
+    match only_upstream {
+        Some(upstream) => request.with_upstream_filter(UpstreamFilter::only(upstream)),
+        None => request,
+    }
+}
@@ -0,0 +1,182 @@
+//! Python绑定共用的Tokio运行时
+//!
+//! 所有 `DnsResolver` 共用同一个运行时，按存活的解析器计数：
+//! 第一个解析器构建时懒创建，最后一个解析器关闭（或被回收）时停掉，
+//! 反复创建、关闭解析器不会累积工作线程。
+//!
+//! 运行时的构建方式由 `configure_runtime()` 决定，默认为按CPU核数的多线程运行时。
+//!
+//! fork之后子进程里只剩调用fork的线程，继承来的运行时没有工作线程可用，在它上面等待会一直挂起。
+//! 因此取得运行时时检查进程号：进程号变了就丢弃继承来的运行时（不关闭，关闭要等待并不存在的线程），
+//! 之后构建的解析器使用新建的运行时；fork之前构建的解析器在子进程中不再可用，调用时抛出 RuntimeError。
+
+use pyo3::prelude::*;
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::{Builder, Runtime, RuntimeFlavor};
+
+/// 共享运行时的构建方式
+#[derive(Debug, Clone, Copy, Default)]
+struct RuntimeConfig {
+    /// 多线程运行时的工作线程数（None为按CPU核数）
+    worker_threads: Option<usize>,
+    /// 使用当前线程运行时：不创建工作线程，只在调用方等待查询时驱动
+    use_current_thread: bool,
+}
+
+/// 共享运行时、持有它的解析器数量及创建它的进程号
+struct SharedRuntime {
+    runtime: Arc<Runtime>,
+    holders: usize,
+    pid: u32,
+}
+
+static SHARED_RUNTIME: Mutex<Option<SharedRuntime>> = Mutex::new(None);
+
+static RUNTIME_CONFIG: Mutex<RuntimeConfig> = Mutex::new(RuntimeConfig { worker_threads: None, use_current_thread: false });
+
+/// 从共享运行时取得的使用权，记录取得时的进程号
+#[derive(Clone)]
+pub(crate) struct RuntimeLease {
+    runtime: Arc<Runtime>,
+    pid: u32,
+}
+
+impl Deref for RuntimeLease {
+    type Target = Runtime;
+
+    fn deref(&self) -> &Runtime {
+        &self.runtime
+    }
+}
+
+impl RuntimeLease {
+    /// 是否是fork之前在父进程中取得的，这样的运行时在当前进程中不能使用
+    pub(crate) fn is_inherited(&self) -> bool {
+        self.pid != std::process::id()
+    }
+
+    /// 在运行时上执行任务，不等待它完成
+    ///
+    /// 多线程运行时直接派生任务；当前线程运行时没有工作线程，另起一个线程驱动该任务
+    pub(crate) fn spawn_detached<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match self.runtime.handle().runtime_flavor() {
+            RuntimeFlavor::CurrentThread => {
+                let runtime = self.runtime.clone();
+                std::thread::spawn(move || runtime.block_on(future));
+            }
+            _ => {
+                self.runtime.spawn(future);
+            }
+        }
+    }
+}
+
+/// 创建共享运行时的Tokio运行时
+fn build_runtime(config: RuntimeConfig) -> std::io::Result<Runtime> {
+    let mut builder = if config.use_current_thread {
+        Builder::new_current_thread()
+    } else {
+        Builder::new_multi_thread()
+    };
+    if let Some(worker_threads) = config.worker_threads {
+        builder.worker_threads(worker_threads);
+    }
+    builder.enable_all().build()
+}
+
+/// 取得共享运行时，必要时创建，每次成功调用都要对应一次 [`release_runtime`]
+pub(crate) fn acquire_runtime() -> PyResult<RuntimeLease> {
+    let pid = std::process::id();
+    let mut shared = SHARED_RUNTIME.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(inherited) = shared.take_if(|shared| shared.pid != pid) {
+        // 从父进程继承的运行时：它的工作线程不在本进程中，释放它会一直等待，直接丢弃
+        std::mem::forget(inherited);
+    }
+    if let Some(shared) = shared.as_mut() {
+        shared.holders += 1;
+        return Ok(RuntimeLease { runtime: shared.runtime.clone(), pid });
+    }
+    let config = *RUNTIME_CONFIG.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let runtime = Arc::new(build_runtime(config).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Failed to create runtime: {}", e))
+    })?);
+    *shared = Some(SharedRuntime { runtime: runtime.clone(), holders: 1, pid });
+    Ok(RuntimeLease { runtime, pid })
+}
+
+/// 归还共享运行时，最后一个持有者归还时关闭运行时并回收其线程
+///
+/// fork之前取得的运行时不计入当前进程的持有者，直接丢弃
+pub(crate) fn release_runtime(lease: RuntimeLease) {
+    if lease.is_inherited() {
+        std::mem::forget(lease);
+        return;
+    }
+    let last = {
+        let mut shared = SHARED_RUNTIME.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match shared.as_mut() {
+            Some(shared) if shared.holders > 1 => {
+                shared.holders -= 1;
+                None
+            }
+            Some(_) => shared.take(),
+            None => None,
+        }
+    };
+    drop(lease);
+    if let Some(SharedRuntime { runtime, .. }) = last {
+        // 不在锁内关闭，关闭期间新构建的解析器会创建新的运行时
+        if let Ok(runtime) = Arc::try_unwrap(runtime) {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// 设置共享运行时的构建方式
+///
+/// 只影响之后创建的运行时：需要在构建第一个解析器之前调用，或者在所有解析器都关闭之后调用。
+///
+/// Args:
+///     worker_threads (int, optional): 多线程运行时的工作线程数，默认按CPU核数
+///     use_current_thread (bool): 使用当前线程运行时，不创建工作线程，默认False。
+///         查询只在调用方等待结果时进行，健康检查等后台任务只在有查询进行时运行；
+///         适合已经有自己的事件循环、不希望再多出一组线程的服务
+///
+/// Raises:
+///     ValueError: 工作线程数为0，或与 `use_current_thread` 同时指定
+///     RuntimeError: 当前进程中还有未关闭的解析器
+///
+/// Example:
+///     >>> rat_quickdns_py.configure_runtime(worker_threads=2)
+///     >>> resolver = DnsResolverBuilder().build()
+#[pyfunction]
+#[pyo3(signature = (worker_threads = None, use_current_thread = false))]
+fn configure_runtime(worker_threads: Option<usize>, use_current_thread: bool) -> PyResult<()> {
+    if worker_threads == Some(0) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>("worker_threads must be at least 1"));
+    }
+    if use_current_thread && worker_threads.is_some() {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "worker_threads cannot be combined with use_current_thread",
+        ));
+    }
+    let shared = SHARED_RUNTIME.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if shared.as_ref().is_some_and(|shared| shared.pid == std::process::id()) {
+        return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+            "configure_runtime() must be called before any resolver is built or after all resolvers are closed",
+        ));
+    }
+    *RUNTIME_CONFIG.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = RuntimeConfig { worker_threads, use_current_thread };
+    Ok(())
+}
+
+/// 在模块中注册运行时配置函数
+pub(crate) fn register(m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(configure_runtime, m)?)?;
+    Ok(())
+}
@@ -5,23 +5,27 @@
 use pyo3::prelude::*;
 use crate::builder::strategy::QueryStrategy as RustQueryStrategy;
 use crate::builder::engine::{FailedServerInfo, EmergencyResponseInfo};
+use crate::builder::types::{DnsQueryResponse, DnsRecord, DnsRecordValue, SoaData, SrvData};
 use std::time::Instant;
 
 /// Python绑定的查询策略枚举
 /// 
 /// 支持的查询策略：
-/// - FIFO: 先进先出，按添加顺序查询上游服务器
+/// - FIFO: 最快优先，同时查询所有上游服务器并采用最先到达的应答（并非按添加顺序逐个查询）
 /// - SMART: 智能决策，基于历史性能选择最优服务器
 /// - ROUND_ROBIN: 轮询策略，依次使用不同的上游服务器
+/// - SEQUENTIAL: 顺序策略，按添加顺序逐个查询，前一个出错或超时才查询下一个
 #[pyclass(name = "QueryStrategy")]
 #[derive(Debug, Clone, Copy)]
 pub enum PyQueryStrategy {
-    /// 先进先出策略
+    /// 最快优先策略
     FIFO,
     /// 智能决策策略
     SMART,
     /// 轮询策略
     ROUND_ROBIN,
+    /// 顺序策略
+    SEQUENTIAL,
 }
 
 impl PyQueryStrategy {
@@ -31,15 +35,18 @@ impl PyQueryStrategy {
             PyQueryStrategy::FIFO => RustQueryStrategy::Fifo,
             PyQueryStrategy::SMART => RustQueryStrategy::Smart,
             PyQueryStrategy::ROUND_ROBIN => RustQueryStrategy::RoundRobin,
+            PyQueryStrategy::SEQUENTIAL => RustQueryStrategy::Sequential,
         }
     }
     
-    /// 从Rust内部的QueryStrategy转换
-    pub fn from_rust(strategy: RustQueryStrategy) -> Self {
+    /// 从Rust内部的QueryStrategy转换，法定人数策略带参数，没有对应的枚举值
+    pub fn from_rust(strategy: RustQueryStrategy) -> Option<Self> {
         match strategy {
-            RustQueryStrategy::Fifo => PyQueryStrategy::FIFO,
-            RustQueryStrategy::Smart => PyQueryStrategy::SMART,
-            RustQueryStrategy::RoundRobin => PyQueryStrategy::ROUND_ROBIN,
+            RustQueryStrategy::Fifo => Some(PyQueryStrategy::FIFO),
+            RustQueryStrategy::Smart => Some(PyQueryStrategy::SMART),
+            RustQueryStrategy::RoundRobin => Some(PyQueryStrategy::ROUND_ROBIN),
+            RustQueryStrategy::Sequential => Some(PyQueryStrategy::SEQUENTIAL),
+            RustQueryStrategy::Quorum { .. } => None,
         }
     }
 }
@@ -51,6 +58,18 @@ impl PyQueryStrategy {
 #[derive(Clone, Debug)]
 pub struct PyDnsResult {
     inner: Result<Vec<String>, String>,
+    server_used: Option<String>,
+    protocol_used: Option<String>,
+    valid_until: Option<u64>,
+    wire_request: Option<Vec<u8>>,
+    wire_response: Option<Vec<u8>>,
+    wire_truncated: bool,
+    degraded_security: bool,
+    records_truncated: bool,
+    negative_ttl: Option<u32>,
+    zone_apex: Option<String>,
+    soa_records: Vec<SoaData>,
+    srv_records: Vec<SrvData>,
 }
 
 #[pymethods]
@@ -103,6 +122,109 @@ impl PyDnsResult {
         }
     }
     
+    /// 实际给出响应的上游服务器（命中缓存时为 "cache"）
+    #[getter]
+    fn server_used(&self) -> Option<String> {
+        self.server_used.clone()
+    }
+    
+    /// 实际给出响应的传输协议（命中缓存时为 "cache"）
+    #[getter]
+    fn protocol_used(&self) -> Option<String> {
+        self.protocol_used.clone()
+    }
+    
+    /// 距离结果过期的剩余秒数（按记录中最小的TTL计算），没有记录时为None
+    #[getter]
+    fn expires_in_seconds(&self) -> Option<f64> {
+        let valid_until = self.valid_until?;
+        let deadline = std::time::UNIX_EPOCH + std::time::Duration::from_secs(valid_until);
+        Some(deadline.duration_since(std::time::SystemTime::now()).unwrap_or_default().as_secs_f64())
+    }
+    
+    /// 发往上游的原始DNS请求报文（bytes），查询未要求保留报文时为None
+    #[getter]
+    fn wire_request(&self, py: Python) -> Option<PyObject> {
+        self.wire_request.as_deref().map(|bytes| pyo3::types::PyBytes::new(py, bytes).into_py(py))
+    }
+    
+    /// 上游返回的原始DNS响应报文（bytes，解析前），查询未要求保留报文时为None
+    #[getter]
+    fn wire_response(&self, py: Python) -> Option<PyObject> {
+        self.wire_response.as_deref().map(|bytes| pyo3::types::PyBytes::new(py, bytes).into_py(py))
+    }
+    
+    /// 原始报文是否因超过保留上限而被截断
+    #[getter]
+    fn wire_truncated(&self) -> bool {
+        self.wire_truncated
+    }
+    
+    /// 应答是否来自加密上游证书校验失败后的降级路径（明文DNS或不校验证书）
+    #[getter]
+    fn degraded_security(&self) -> bool {
+        self.degraded_security
+    }
+    
+    /// 上游的应答是否超出记录数上限而被截去了记录
+    #[getter]
+    fn records_truncated(&self) -> bool {
+        self.records_truncated
+    }
+    
+    /// 否定应答（NXDOMAIN或没有查询类型的记录）的否定缓存时间（秒），取权威段SOA的MINIMUM与其TTL中较小者
+    /// 
+    /// Returns:
+    ///     Optional[int]: 不是否定应答或没有SOA时为None
+    #[getter]
+    fn negative_ttl(&self) -> Option<u32> {
+        self.negative_ttl
+    }
+    
+    /// 否定应答所属区域的顶点（权威段SOA记录的名称）
+    /// 
+    /// Returns:
+    ///     Optional[str]: 与negative_ttl同时提供
+    #[getter]
+    fn zone_apex(&self) -> Option<String> {
+        self.zone_apex.clone()
+    }
+    
+    /// 应答中的SOA记录
+    /// 
+    /// Returns:
+    ///     List[dict]: 每条记录含 mname、rname、serial、refresh、retry、expire、minimum
+    #[getter]
+    fn soa_records(&self, py: Python) -> pyo3::PyResult<Vec<PyObject>> {
+        self.soa_records.iter().map(|soa| {
+            let item = pyo3::types::PyDict::new(py);
+            item.set_item("mname", &soa.mname)?;
+            item.set_item("rname", &soa.rname)?;
+            item.set_item("serial", soa.serial)?;
+            item.set_item("refresh", soa.refresh)?;
+            item.set_item("retry", soa.retry)?;
+            item.set_item("expire", soa.expire)?;
+            item.set_item("minimum", soa.minimum)?;
+            Ok(item.into())
+        }).collect()
+    }
+    
+    /// 应答中的SRV记录
+    /// 
+    /// Returns:
+    ///     List[dict]: 每条记录含 priority、weight、port、target
+    #[getter]
+    fn srv_records(&self, py: Python) -> pyo3::PyResult<Vec<PyObject>> {
+        self.srv_records.iter().map(|srv| {
+            let item = pyo3::types::PyDict::new(py);
+            item.set_item("priority", srv.priority)?;
+            item.set_item("weight", srv.weight)?;
+            item.set_item("port", srv.port)?;
+            item.set_item("target", &srv.target)?;
+            Ok(item.into())
+        }).collect()
+    }
+    
     /// 获取成功结果的值，如果失败则返回默认值
     /// 
     /// Args:
@@ -136,6 +258,18 @@ impl PyDnsResult {
     pub fn ok(value: Vec<String>) -> Self {
         Self {
             inner: Ok(value),
+            server_used: None,
+            protocol_used: None,
+            valid_until: None,
+            wire_request: None,
+            wire_response: None,
+            wire_truncated: false,
+            degraded_security: false,
+            records_truncated: false,
+            negative_ttl: None,
+            zone_apex: None,
+            soa_records: Vec::new(),
+            srv_records: Vec::new(),
         }
     }
     
@@ -143,9 +277,43 @@ impl PyDnsResult {
     pub fn err(error: String) -> Self {
         Self {
             inner: Err(error),
+            server_used: None,
+            protocol_used: None,
+            valid_until: None,
+            wire_request: None,
+            wire_response: None,
+            wire_truncated: false,
+            degraded_security: false,
+            records_truncated: false,
+            negative_ttl: None,
+            zone_apex: None,
+            soa_records: Vec::new(),
+            srv_records: Vec::new(),
         }
     }
     
+    /// 从查询响应转换，保留上游与协议归属
+    pub fn from_query_response(response: &crate::builder::types::DnsQueryResponse) -> Self {
+        let mut result = if response.success {
+            Self::ok(response.ip_addresses().into_iter().map(|ip| ip.to_string()).collect())
+        } else {
+            Self::err(response.error.clone().unwrap_or_default())
+        };
+        result.server_used = response.server_used.clone();
+        result.protocol_used = response.protocol_used.clone();
+        result.valid_until = response.valid_until;
+        result.wire_request = response.wire_request.clone();
+        result.wire_response = response.wire_response.clone();
+        result.wire_truncated = response.wire_truncated;
+        result.degraded_security = response.degraded_security;
+        result.records_truncated = response.records_truncated;
+        result.negative_ttl = response.negative_ttl;
+        result.zone_apex = response.zone_apex.clone();
+        result.soa_records = response.soa_records();
+        result.srv_records = response.srv_records();
+        result
+    }
+    
     /// 从Rust Result转换
     pub fn from_rust_result(result: Result<Vec<std::net::IpAddr>, crate::error::DnsError>) -> Self {
         match result {
@@ -155,6 +323,224 @@ impl PyDnsResult {
     }
 }
 
+/// Python版本的DNS记录
+/// 
+/// `value` 为记录值的文本形式：A/AAAA为地址，CNAME/NS/PTR为域名，MX为交换主机，SRV为目标主机，
+/// TXT为各字符串直接拼接后的值，SOA按RFC 1035字段顺序以空格分隔。
+/// MX记录另有 `priority`，SRV记录另有 `priority`、`weight`、`port`，其他记录这些字段为None。
+#[pyclass(name = "DnsRecord")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PyDnsRecord {
+    /// 记录名称
+    #[pyo3(get)]
+    pub name: String,
+    /// 记录类型，如 "A"、"MX"，通过 `type` 属性取得
+    pub record_type: String,
+    /// TTL（秒）
+    #[pyo3(get)]
+    pub ttl: u32,
+    /// 记录值的文本形式
+    #[pyo3(get)]
+    pub value: String,
+    /// MX/SRV记录的优先级
+    #[pyo3(get)]
+    pub priority: Option<u16>,
+    /// SRV记录的权重
+    #[pyo3(get)]
+    pub weight: Option<u16>,
+    /// SRV记录的端口
+    #[pyo3(get)]
+    pub port: Option<u16>,
+}
+
+#[pymethods]
+impl PyDnsRecord {
+    /// 记录类型，如 "A"、"MX"
+    #[getter(r#type)]
+    fn record_type(&self) -> &str {
+        &self.record_type
+    }
+    
+    /// 转换为字典，只包含该类型记录具有的字段
+    /// 
+    /// Returns:
+    ///     dict: 含 name、type、ttl、value，MX记录另含 priority，SRV记录另含 priority、weight、port
+    fn to_dict(&self, py: Python) -> pyo3::PyResult<PyObject> {
+        let item = pyo3::types::PyDict::new(py);
+        item.set_item("name", &self.name)?;
+        item.set_item("type", &self.record_type)?;
+        item.set_item("ttl", self.ttl)?;
+        item.set_item("value", &self.value)?;
+        for (key, field) in [("priority", self.priority), ("weight", self.weight), ("port", self.port)] {
+            if let Some(field) = field {
+                item.set_item(key, field)?;
+            }
+        }
+        Ok(item.into())
+    }
+    
+    fn __eq__(&self, other: PyRef<'_, Self>) -> bool {
+        *self == *other
+    }
+    
+    fn __repr__(&self) -> String {
+        let mut repr = format!(
+            "DnsRecord(name='{}', type='{}', ttl={}, value={:?}",
+            self.name, self.record_type, self.ttl, self.value
+        );
+        for (key, field) in [("priority", self.priority), ("weight", self.weight), ("port", self.port)] {
+            if let Some(field) = field {
+                repr.push_str(&format!(", {}={}", key, field));
+            }
+        }
+        repr.push(')');
+        repr
+    }
+}
+
+impl From<&DnsRecord> for PyDnsRecord {
+    fn from(record: &DnsRecord) -> Self {
+        let (value, priority, weight, port) = match &record.value {
+            DnsRecordValue::Mx { priority, exchange } => (exchange.clone(), Some(*priority), None, None),
+            DnsRecordValue::Srv { priority, weight, port, target } => {
+                (target.clone(), Some(*priority), Some(*weight), Some(*port))
+            },
+            value => (value.to_string(), None, None, None),
+        };
+        Self {
+            name: record.name.clone(),
+            record_type: record.record_type.as_str().to_string(),
+            ttl: record.ttl,
+            value,
+            priority,
+            weight,
+            port,
+        }
+    }
+}
+
+/// Python版本的查询响应
+/// 
+/// 查询失败时 `success` 为False、`error` 为错误信息，`records` 为空列表。
+#[pyclass(name = "DnsResponse")]
+#[derive(Clone, Debug)]
+pub struct PyDnsResponse {
+    /// 查询ID
+    #[pyo3(get)]
+    pub query_id: String,
+    /// 查询的域名
+    #[pyo3(get)]
+    pub domain: String,
+    /// 查询的记录类型，通过 `type` 属性取得
+    pub record_type: String,
+    /// 查询是否成功
+    #[pyo3(get)]
+    pub success: bool,
+    /// 错误信息，成功时为None
+    #[pyo3(get)]
+    pub error: Option<String>,
+    /// 应答中的记录
+    #[pyo3(get)]
+    pub records: Vec<PyDnsRecord>,
+    /// 查询耗时（毫秒）
+    #[pyo3(get)]
+    pub duration_ms: u64,
+    /// 实际给出响应的上游服务器（命中缓存时为 "cache"）
+    #[pyo3(get)]
+    pub server_used: Option<String>,
+    /// 实际给出响应的传输协议（命中缓存时为 "cache"）
+    #[pyo3(get)]
+    pub protocol_used: Option<String>,
+    /// DNS响应码（0为NOERROR、3为NXDOMAIN），查询失败时为None
+    #[pyo3(get)]
+    pub rcode: Option<u16>,
+    /// 上游是否设置了AD位（应答经过DNSSEC验证）
+    #[pyo3(get)]
+    pub authenticated_data: bool,
+    /// 上游的应答是否超出记录数上限而被截去了记录
+    #[pyo3(get)]
+    pub records_truncated: bool,
+    /// 查询时指定的租户
+    #[pyo3(get)]
+    pub tenant: Option<String>,
+    /// 上游全部失败时的汇总诊断（JSON），通过 `failure_report` 属性以字典取得
+    pub failure_report: Option<String>,
+}
+
+#[pymethods]
+impl PyDnsResponse {
+    /// 查询的记录类型
+    #[getter(r#type)]
+    fn record_type(&self) -> &str {
+        &self.record_type
+    }
+    
+    /// 转换为字典，`records` 为各记录 `to_dict()` 的列表
+    fn to_dict(&self, py: Python) -> pyo3::PyResult<PyObject> {
+        let item = pyo3::types::PyDict::new(py);
+        item.set_item("query_id", &self.query_id)?;
+        item.set_item("domain", &self.domain)?;
+        item.set_item("type", &self.record_type)?;
+        item.set_item("success", self.success)?;
+        item.set_item("error", &self.error)?;
+        let records = self.records.iter().map(|record| record.to_dict(py)).collect::<pyo3::PyResult<Vec<_>>>()?;
+        item.set_item("records", records)?;
+        item.set_item("duration_ms", self.duration_ms)?;
+        item.set_item("server_used", &self.server_used)?;
+        item.set_item("protocol_used", &self.protocol_used)?;
+        item.set_item("rcode", self.rcode)?;
+        item.set_item("authenticated_data", self.authenticated_data)?;
+        item.set_item("records_truncated", self.records_truncated)?;
+        item.set_item("tenant", &self.tenant)?;
+        item.set_item("failure_report", self.failure_report(py)?)?;
+        Ok(item.into())
+    }
+    
+    /// 上游全部失败（或应答为SERVFAIL/REFUSED）时各上游的结果和可能原因的提示，
+    /// 字典含 `name`、`record_type`、`attempts`（每项含 `upstream`、`protocol`、`peer`、`latency_ms`、
+    /// `outcome`、`rcode`、`error`）和 `hints`（每项含 `code`、`message`）；没有诊断时为None
+    #[getter]
+    fn failure_report(&self, py: Python) -> pyo3::PyResult<Option<PyObject>> {
+        match &self.failure_report {
+            Some(json) => Ok(Some(py.import("json")?.call_method1("loads", (json,))?.into())),
+            None => Ok(None),
+        }
+    }
+    
+    fn __repr__(&self) -> String {
+        match &self.error {
+            Some(error) if !self.success => format!(
+                "DnsResponse(domain='{}', type='{}', error={:?})", self.domain, self.record_type, error
+            ),
+            _ => format!(
+                "DnsResponse(domain='{}', type='{}', records={}, server_used={:?})",
+                self.domain, self.record_type, self.records.len(), self.server_used.as_deref().unwrap_or("-")
+            ),
+        }
+    }
+}
+
+impl From<&DnsQueryResponse> for PyDnsResponse {
+    fn from(response: &DnsQueryResponse) -> Self {
+        Self {
+            query_id: response.query_id.to_string(),
+            domain: response.domain.clone(),
+            record_type: response.record_type.as_str().to_string(),
+            success: response.success,
+            error: response.error.clone(),
+            records: response.records.iter().map(PyDnsRecord::from).collect(),
+            duration_ms: response.duration_ms,
+            server_used: response.server_used.clone(),
+            protocol_used: response.protocol_used.clone(),
+            rcode: response.rcode,
+            authenticated_data: response.authenticated_data,
+            records_truncated: response.records_truncated,
+            tenant: response.context.as_ref().and_then(|context| context.tenant.clone()),
+            failure_report: response.failure_report.as_ref().and_then(|report| report.to_json().ok()),
+        }
+    }
+}
+
 /// Python绑定的传输协议类型
 /// 
 /// 支持的传输协议：
@@ -268,6 +654,10 @@ pub struct PyEmergencyResponseInfo {
     pub total_failures: u32,
     #[pyo3(get)]
     pub emergency_message: String,
+    #[pyo3(get)]
+    pub emergency_mode: bool,
+    #[pyo3(get)]
+    pub success_ratio: Option<f64>,
 }
 
 #[pymethods]
@@ -309,6 +699,8 @@ impl From<&EmergencyResponseInfo> for PyEmergencyResponseInfo {
             last_working_server: info.last_working_server.clone(),
             total_failures: info.total_failures,
             emergency_message: info.emergency_message.clone(),
+            emergency_mode: info.emergency_mode,
+            success_ratio: info.success_ratio,
         }
     }
 }
@@ -317,46 +709,15 @@ impl From<&EmergencyResponseInfo> for PyEmergencyResponseInfo {
 impl PyDnsRecordType {
     /// 转换为字符串
     pub fn to_string(&self) -> String {
-        match self {
-            PyDnsRecordType::A => "A".to_string(),
-            PyDnsRecordType::AAAA => "AAAA".to_string(),
-            PyDnsRecordType::CNAME => "CNAME".to_string(),
-            PyDnsRecordType::MX => "MX".to_string(),
-            PyDnsRecordType::TXT => "TXT".to_string(),
-            PyDnsRecordType::NS => "NS".to_string(),
-            PyDnsRecordType::PTR => "PTR".to_string(),
-            PyDnsRecordType::SOA => "SOA".to_string(),
-            PyDnsRecordType::SRV => "SRV".to_string(),
-            PyDnsRecordType::RRSIG => "RRSIG".to_string(),
-            PyDnsRecordType::DNSKEY => "DNSKEY".to_string(),
-            PyDnsRecordType::DS => "DS".to_string(),
-            PyDnsRecordType::NSEC => "NSEC".to_string(),
-            PyDnsRecordType::NSEC3 => "NSEC3".to_string(),
-        }
+        self.to_rust().to_string()
     }
     
-    /// 从字符串创建
+    /// 从字符串创建：助记符（不区分大小写）或 `TYPE<类型值>`
     #[staticmethod]
     pub fn from_string(s: &str) -> pyo3::PyResult<PyDnsRecordType> {
-        match s.to_uppercase().as_str() {
-            "A" => Ok(PyDnsRecordType::A),
-            "AAAA" => Ok(PyDnsRecordType::AAAA),
-            "CNAME" => Ok(PyDnsRecordType::CNAME),
-            "MX" => Ok(PyDnsRecordType::MX),
-            "TXT" => Ok(PyDnsRecordType::TXT),
-            "NS" => Ok(PyDnsRecordType::NS),
-            "PTR" => Ok(PyDnsRecordType::PTR),
-            "SOA" => Ok(PyDnsRecordType::SOA),
-            "SRV" => Ok(PyDnsRecordType::SRV),
-            "RRSIG" => Ok(PyDnsRecordType::RRSIG),
-            "DNSKEY" => Ok(PyDnsRecordType::DNSKEY),
-            "DS" => Ok(PyDnsRecordType::DS),
-            "NSEC" => Ok(PyDnsRecordType::NSEC),
-            "NSEC3" => Ok(PyDnsRecordType::NSEC3),
-            _ => Err(pyo3::exceptions::PyValueError::new_err(
-                format!("Unsupported DNS record type: {}", s)
-            )),
-        }
+        s.parse::<crate::builder::types::DnsRecordType>()
+            .map(PyDnsRecordType::from_rust)
+            .map_err(|_| pyo3::exceptions::PyValueError::new_err(format!("Unsupported DNS record type: {}", s)))
     }
     
 
@@ -249,8 +249,14 @@ pub fn get_default_dot_servers() -> Vec<(String, String, String)> {
 
 /// 创建快速配置的解析器构建器
 /// 
+/// 上游服务器列表统一来自 Rust 侧的 `Preset` 定义，保证两端一致：
+/// - "china_mainland" / "global" / "privacy_focused"：只添加对应预设的上游服务器
+/// - "fast"：中国大陆预设中的UDP服务器，FIFO策略，3秒超时
+/// - "secure"：中国大陆预设中的DoH/DoT服务器，智能策略，5秒超时
+/// - "balanced"：中国大陆预设的全部服务器，智能策略，4秒超时
+/// 
 /// Args:
-///     preset (str): 预设配置名称 ("fast", "secure", "balanced")
+///     preset (str): 预设配置名称
 /// 
 /// Returns:
 ///     DnsResolverBuilder: 预配置的构建器实例
@@ -261,48 +267,58 @@ pub fn get_default_dot_servers() -> Vec<(String, String, String)> {
 ///     >>> resolver = builder.build()
 #[pyfunction]
 pub fn create_preset_builder(preset: &str) -> pyo3::PyResult<crate::python_api::builder::PyDnsResolverBuilder> {
+    use crate::builder::preset::Preset;
     use crate::python_api::builder::PyDnsResolverBuilder;
     use crate::python_api::types::PyQueryStrategy;
+    use crate::upstream_handler::UpstreamType;
     
     let mut builder = PyDnsResolverBuilder::new();
     
+    if Preset::from_name(preset).is_some() {
+        builder.with_preset(preset)?;
+        return Ok(builder);
+    }
+    
+    let china = Preset::ChinaMainland.upstreams();
     match preset {
         "fast" => {
-            // 快速配置：使用最快优先策略，添加国内高性能DNS服务器
-            builder.query_strategy(&PyQueryStrategy::FIFO);
-            builder.add_udp_upstream("阿里DNS".to_string(), "223.5.5.5:53".to_string());
-            builder.add_udp_upstream("腾讯DNS".to_string(), "119.29.29.29:53".to_string());
-            builder.timeout(3.0);
-            builder.enable_edns(true);
+            // 快速配置：使用最快优先策略，只用国内UDP服务器
+            builder.query_strategy(&PyQueryStrategy::FIFO)?;
+            for spec in china.into_iter().filter(|s| s.transport_type == UpstreamType::Udp) {
+                builder.add_upstream_spec(spec)?;
+            }
+            builder.timeout(3.0)?;
+            builder.enable_edns(true)?;
         },
         "secure" => {
-            // 安全配置：使用国内DoH/DoT协议
-            builder.query_strategy(&PyQueryStrategy::SMART);
-            builder.add_doh_upstream("阿里DNS".to_string(), "https://dns.alidns.com/dns-query".to_string())?;
-            builder.add_doh_upstream("腾讯DNS".to_string(), "https://doh.pub/dns-query".to_string())?;
-            builder.add_dot_upstream("阿里DoT".to_string(), "223.5.5.5:853".to_string());
-            builder.add_dot_upstream("腾讯DoT".to_string(), "1.12.12.12:853".to_string());
-            builder.timeout(5.0);
-            builder.enable_edns(true);
-            builder.enable_upstream_monitoring(true);
+            // 安全配置：只用国内DoH/DoT服务器
+            builder.query_strategy(&PyQueryStrategy::SMART)?;
+            for spec in china.into_iter().filter(|s| matches!(s.transport_type, UpstreamType::DoH | UpstreamType::DoT)) {
+                builder.add_upstream_spec(spec)?;
+            }
+            builder.timeout(5.0)?;
+            builder.enable_edns(true)?;
+            builder.enable_upstream_monitoring(true)?;
         },
         "balanced" => {
-            // 平衡配置：混合使用国内多种协议和策略
-            builder.query_strategy(&PyQueryStrategy::SMART);
-            builder.add_udp_upstream("阿里DNS".to_string(), "223.5.5.5:53".to_string());
-            builder.add_udp_upstream("腾讯DNS".to_string(), "119.29.29.29:53".to_string());
-            builder.add_doh_upstream("阿里DoH".to_string(), "https://dns.alidns.com/dns-query".to_string())?;
-            builder.add_dot_upstream("腾讯DoT".to_string(), "1.12.12.12:853".to_string());
-            builder.timeout(4.0);
-            builder.enable_edns(true);
-            builder.enable_upstream_monitoring(true);
+            // 平衡配置：混合使用国内多种协议
+            builder.query_strategy(&PyQueryStrategy::SMART)?;
+            for spec in china {
+                builder.add_upstream_spec(spec)?;
+            }
+            builder.timeout(4.0)?;
+            builder.enable_edns(true)?;
+            builder.enable_upstream_monitoring(true)?;
         },
         _ => {
             return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
-                format!("Unknown preset '{}'. Available presets: fast, secure, balanced", preset)
+                format!(
+                    "Unknown preset '{}'. Available presets: china_mainland, global, privacy_focused, fast, secure, balanced",
+                    preset
+                )
             ));
         }
     }
     
     Ok(builder)
-}
\ No newline at end of file
+}
@@ -0,0 +1,195 @@
+//! 进程内共享解析器的注册表
+//!
+//! 一个程序里的多个库各自构建解析器时，会有多份缓存和多组上游监控任务。注册表让它们共用同一个
+//! [`SmartDnsResolver`]：由应用在启动时构建解析器并 [`install`]（或 [`install_default`]），
+//! 各库用 [`get`]（或 [`default`]）取得。注册表只保存调用方放进来的解析器，不会自动构建或安装任何解析器，
+//! 也没有后台任务；解析器 [`shutdown`](SmartDnsResolver::shutdown) 时自动从注册表中移除。
+//!
+//! 注册表是本crate的静态变量：同一进程中链接同一份rat_quickdns的各个crate（包括以 `dylib` 共享的）看到的是同一个注册表；
+//! 各自静态链接了一份rat_quickdns的 `cdylib`（例如两个Python扩展模块）各有各的注册表，无法互相取得。
+//!
+//! ```no_run
+//! # async fn example() -> rat_quickdns::Result<()> {
+//! use rat_quickdns::{registry, DnsResolverBuilder, QueryStrategy};
+//! use rat_quickdns::builder::types::DnsRecordType;
+//! use std::sync::Arc;
+//!
+//! let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, true, "global".to_string())
+//!     .add_udp_upstream("阿里DNS", "223.5.5.5")
+//!     .build()
+//!     .await?;
+//! registry::install_default(Arc::new(resolver))?;
+//!
+//! // 其他模块中
+//! if let Some(resolver) = registry::default() {
+//!     println!("{:?}", resolver.lookup_ip("example.com", DnsRecordType::A).await?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::builder::SmartDnsResolver;
+use crate::error::{DnsError, Result};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// 已安装的解析器
+#[derive(Default)]
+struct Registry {
+    named: HashMap<String, Arc<SmartDnsResolver>>,
+    default: Option<Arc<SmartDnsResolver>>,
+}
+
+static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+
+fn read() -> RwLockReadGuard<'static, Registry> {
+    REGISTRY.get_or_init(RwLock::default).read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn write() -> RwLockWriteGuard<'static, Registry> {
+    REGISTRY.get_or_init(RwLock::default).write().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// 以 `name` 安装解析器；名称为空或已被占用时返回 [`DnsError::InvalidConfig`]，已安装的解析器不受影响
+pub fn install(resolver: Arc<SmartDnsResolver>, name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(DnsError::InvalidConfig("Registry name cannot be empty".to_string()));
+    }
+    let mut registry = write();
+    if registry.named.contains_key(name) {
+        return Err(DnsError::InvalidConfig(format!("A resolver named '{}' is already installed", name)));
+    }
+    registry.named.insert(name.to_string(), resolver);
+    Ok(())
+}
+
+/// 取得以 `name` 安装的解析器
+pub fn get(name: &str) -> Option<Arc<SmartDnsResolver>> {
+    read().named.get(name).cloned()
+}
+
+/// 移除以 `name` 安装的解析器并返回它；不会关闭解析器
+pub fn remove(name: &str) -> Option<Arc<SmartDnsResolver>> {
+    write().named.remove(name)
+}
+
+/// 安装默认解析器；已有默认解析器时返回 [`DnsError::InvalidConfig`]
+pub fn install_default(resolver: Arc<SmartDnsResolver>) -> Result<()> {
+    let mut registry = write();
+    if registry.default.is_some() {
+        return Err(DnsError::InvalidConfig("A default resolver is already installed".to_string()));
+    }
+    registry.default = Some(resolver);
+    Ok(())
+}
+
+/// 取得默认解析器，没有安装时为 `None`
+pub fn default() -> Option<Arc<SmartDnsResolver>> {
+    read().default.clone()
+}
+
+/// 移除默认解析器并返回它；不会关闭解析器
+pub fn remove_default() -> Option<Arc<SmartDnsResolver>> {
+    write().default.take()
+}
+
+/// 移除注册表中指向 `resolver` 的全部条目（按地址比较，克隆出来的解析器是另一个实例）
+pub(crate) fn forget(resolver: &SmartDnsResolver) {
+    let installed = |entry: &Arc<SmartDnsResolver>| std::ptr::eq(Arc::as_ptr(entry), resolver);
+    // 多数解析器从未安装，先用读锁检查，避免每次关闭都争用写锁
+    {
+        let registry = read();
+        if !registry.default.as_ref().is_some_and(installed) && !registry.named.values().any(installed) {
+            return;
+        }
+    }
+    let mut registry = write();
+    if registry.default.as_ref().is_some_and(installed) {
+        registry.default = None;
+    }
+    registry.named.retain(|_, entry| !installed(entry));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{DnsResolverBuilder, QueryStrategy};
+    use crate::transport::mock::MockTransport;
+
+    async fn resolver() -> Arc<SmartDnsResolver> {
+        let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .add_mock_upstream("mock", MockTransport::new())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        Arc::new(resolver)
+    }
+
+    #[tokio::test]
+    async fn test_double_install_is_rejected() {
+        let first = resolver().await;
+        install(first.clone(), "registry-test-double").unwrap();
+        let err = install(resolver().await, "registry-test-double").unwrap_err();
+        assert!(matches!(err, DnsError::InvalidConfig(message) if message.contains("registry-test-double")));
+        assert!(Arc::ptr_eq(&get("registry-test-double").unwrap(), &first));
+        assert!(install(first.clone(), "").is_err());
+
+        assert!(Arc::ptr_eq(&remove("registry-test-double").unwrap(), &first));
+        assert!(get("registry-test-double").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_get() {
+        let installed = resolver().await;
+        install(installed.clone(), "registry-test-concurrent").unwrap();
+        let readers: Vec<_> = (0..8)
+            .map(|_| {
+                let installed = installed.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        assert!(Arc::ptr_eq(&get("registry-test-concurrent").unwrap(), &installed));
+                        assert!(get("registry-test-missing").is_none());
+                    }
+                })
+            })
+            .collect();
+        // 读取的同时安装、移除其他名称
+        for i in 0..100 {
+            let name = format!("registry-test-churn-{}", i);
+            install(installed.clone(), &name).unwrap();
+            remove(&name).unwrap();
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        remove("registry-test-concurrent");
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_removes_installed_resolver() {
+        // 默认解析器全局只有一个，有关的断言都在这个测试里
+        let installed = resolver().await;
+        let other = resolver().await;
+        install(installed.clone(), "registry-test-shutdown").unwrap();
+        install(installed.clone(), "registry-test-shutdown-alias").unwrap();
+        install(other.clone(), "registry-test-shutdown-other").unwrap();
+        install_default(installed.clone()).unwrap();
+        assert!(install_default(other.clone()).is_err());
+
+        // 关闭克隆出来的实例不影响注册表
+        installed.as_ref().clone().shutdown().await;
+        assert!(default().is_some());
+
+        installed.shutdown().await;
+        assert!(get("registry-test-shutdown").is_none());
+        assert!(get("registry-test-shutdown-alias").is_none());
+        assert!(default().is_none());
+        assert!(Arc::ptr_eq(&get("registry-test-shutdown-other").unwrap(), &other));
+
+        install_default(other.clone()).unwrap();
+        assert!(Arc::ptr_eq(&remove_default().unwrap(), &other));
+        remove("registry-test-shutdown-other");
+    }
+}
@@ -0,0 +1,248 @@
+//! 应答地址改写（NAT回流）
+//!
+//! 内网客户端通过公网域名访问内部发布的服务时，公网DNS给出的是WAN地址，网关不支持回流时无法连通。
+//! 改写规则把A/AAAA记录中属于 `from` 网段的地址换成 `to` 网段中主机位相同的地址，TTL和其余记录不变。
+//! 规则可以限定在若干域名后缀之下，按查询的域名匹配。
+
+use crate::types::{Query, RecordData, SharedResponse};
+use crate::error::{DnsError, Result};
+use crate::Response;
+use crate::dns_debug;
+use crate::utils::normalize_name;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 改写在缓存之前还是之后进行
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RewriteStage {
+    /// 缓存保存上游原样的答案，每次返回给调用方时改写；共享缓存的其他实例看到的仍是公网地址
+    #[default]
+    AfterCache,
+    /// 写入缓存前改写，缓存中保存的就是改写后的答案
+    BeforeCache,
+}
+
+/// 一条地址改写规则
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnswerRewriteRule {
+    from: IpNet,
+    to: IpNet,
+    /// 限定的域名后缀（小写、不含末尾的点），为空时对所有域名生效
+    domains: Vec<String>,
+}
+
+impl AnswerRewriteRule {
+    /// 把 `from` 网段中的地址改写为 `to` 网段中主机位相同的地址
+    ///
+    /// 两个网段必须是同一地址族且前缀长度相同
+    pub fn new(from: IpNet, to: IpNet) -> Result<Self> {
+        if from.addr().is_ipv4() != to.addr().is_ipv4() || from.prefix_len() != to.prefix_len() {
+            return Err(DnsError::InvalidConfig(
+                format!("Answer rewrite {} -> {} must map between networks of the same family and prefix length", from, to)
+            ));
+        }
+        Ok(Self { from: from.trunc(), to: to.trunc(), domains: Vec::new() })
+    }
+
+    /// 把单个地址改写为另一个地址（公网地址 -> 内网地址）
+    pub fn address(public: IpAddr, internal: IpAddr) -> Result<Self> {
+        Self::new(IpNet::from(public), IpNet::from(internal))
+    }
+
+    /// 只对 `domain` 及其子域名的查询生效，可多次调用
+    pub fn for_domain(mut self, domain: &str) -> Self {
+        self.domains.push(normalize_name(domain));
+        self
+    }
+
+    /// 规则是否适用于该查询域名
+    fn applies_to(&self, name: &str) -> bool {
+        if self.domains.is_empty() {
+            return true;
+        }
+        let name = normalize_name(name);
+        self.domains.iter().any(|domain| {
+            name == *domain || name.strip_suffix(domain.as_str()).is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    }
+
+    /// 改写后的地址，不在 `from` 网段内时为 `None`
+    fn translate(&self, ip: IpAddr) -> Option<IpAddr> {
+        if !self.from.contains(&ip) {
+            return None;
+        }
+        match (ip, self.to) {
+            (IpAddr::V4(ip), IpNet::V4(to)) => {
+                let host = u32::from(ip) & u32::from(to.hostmask());
+                Some(IpAddr::V4(Ipv4Addr::from(u32::from(to.network()) | host)))
+            }
+            (IpAddr::V6(ip), IpNet::V6(to)) => {
+                let host = u128::from(ip) & u128::from(to.hostmask());
+                Some(IpAddr::V6(Ipv6Addr::from(u128::from(to.network()) | host)))
+            }
+            _ => None,
+        }
+    }
+
+    fn describe(&self) -> String {
+        if self.domains.is_empty() {
+            format!("{} -> {}", self.from, self.to)
+        } else {
+            format!("{} -> {} ({})", self.from, self.to, self.domains.join(", "))
+        }
+    }
+}
+
+/// 一条改写规则的命中统计
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RewriteRuleStats {
+    /// 规则描述，如 `203.0.113.5/32 -> 10.0.0.5/32 (example.com)`
+    pub rule: String,
+    /// 改写过的记录数
+    pub hits: u64,
+}
+
+/// 按规则改写应答中的A/AAAA记录
+#[derive(Debug)]
+pub(crate) struct AnswerRewriter {
+    rules: Vec<AnswerRewriteRule>,
+    stage: RewriteStage,
+    /// 与 `rules` 一一对应的命中次数
+    hits: Vec<AtomicU64>,
+}
+
+impl AnswerRewriter {
+    /// 创建改写器
+    pub(crate) fn new(rules: Vec<AnswerRewriteRule>, stage: RewriteStage) -> Self {
+        let hits = rules.iter().map(|_| AtomicU64::new(0)).collect();
+        Self { rules, stage, hits }
+    }
+
+    /// 写入缓存前改写（`BeforeCache`）
+    pub(crate) fn before_cache(&self, query: &Query, response: &mut Response) {
+        if self.stage == RewriteStage::BeforeCache {
+            self.apply(query, response);
+        }
+    }
+
+    /// 返回给调用方前改写（`AfterCache`）
+    pub(crate) fn after_cache(&self, query: &Query, response: &mut Response) {
+        if self.stage == RewriteStage::AfterCache {
+            self.apply(query, response);
+        }
+    }
+
+    /// 返回共享的缓存响应前改写：没有需要改写的记录时原样返回，否则复制一份再改写
+    pub(crate) fn after_cache_shared(&self, query: &Query, response: SharedResponse) -> SharedResponse {
+        if self.stage != RewriteStage::AfterCache || !self.matches(query, &response) {
+            return response;
+        }
+        let mut response = response.into_response();
+        self.apply(query, &mut response);
+        response.into()
+    }
+
+    /// 各规则的命中统计，按配置顺序
+    pub(crate) fn stats(&self) -> Vec<RewriteRuleStats> {
+        self.rules.iter()
+            .zip(&self.hits)
+            .map(|(rule, hits)| RewriteRuleStats { rule: rule.describe(), hits: hits.load(Ordering::Relaxed) })
+            .collect()
+    }
+
+    /// 响应中是否有会被改写的记录
+    fn matches(&self, query: &Query, response: &Response) -> bool {
+        response.answers.iter().any(|record| {
+            address(&record.data).is_some_and(|ip| self.rule_for(query, ip).is_some())
+        })
+    }
+
+    /// 对该查询适用且包含该地址的第一条规则
+    fn rule_for(&self, query: &Query, ip: IpAddr) -> Option<(usize, IpAddr)> {
+        self.rules.iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.applies_to(&query.name))
+            .find_map(|(index, rule)| rule.translate(ip).map(|translated| (index, translated)))
+    }
+
+    /// 改写应答中的地址记录
+    fn apply(&self, query: &Query, response: &mut Response) {
+        if self.rules.is_empty() {
+            return;
+        }
+        for record in &mut response.answers {
+            let Some(ip) = address(&record.data) else { continue };
+            let Some((index, translated)) = self.rule_for(query, ip) else { continue };
+            record.data = match translated {
+                IpAddr::V4(ip) => RecordData::A(ip),
+                IpAddr::V6(ip) => RecordData::AAAA(ip),
+            };
+            self.hits[index].fetch_add(1, Ordering::Relaxed);
+            dns_debug!("应答改写: {} {} {} -> {}（规则 {}）", query.name, record.name, ip, translated, self.rules[index].describe());
+        }
+    }
+}
+
+/// A/AAAA记录中的地址
+fn address(data: &RecordData) -> Option<IpAddr> {
+    match data {
+        RecordData::A(ip) => Some(IpAddr::V4(*ip)),
+        RecordData::AAAA(ip) => Some(IpAddr::V6(*ip)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_response::DnsResponseWrapper;
+    use crate::types::{QClass, RecordType};
+
+    fn query(name: &str) -> Query {
+        Query { name: name.to_string(), qtype: RecordType::A, qclass: QClass::IN }
+    }
+
+    #[test]
+    fn test_network_rules_keep_host_bits_and_validate_shape() {
+        let rule = AnswerRewriteRule::new("203.0.113.0/24".parse().unwrap(), "10.1.2.0/24".parse().unwrap()).unwrap();
+        assert_eq!(rule.translate(IpAddr::from([203, 0, 113, 77])), Some(IpAddr::from([10, 1, 2, 77])));
+        assert_eq!(rule.translate(IpAddr::from([198, 51, 100, 1])), None);
+
+        assert!(AnswerRewriteRule::new("203.0.113.0/24".parse().unwrap(), "10.0.0.0/16".parse().unwrap()).is_err());
+        assert!(AnswerRewriteRule::address(IpAddr::from([203, 0, 113, 5]), "fd00::5".parse().unwrap()).is_err());
+
+        let rule = AnswerRewriteRule::address("2001:db8::5".parse().unwrap(), "fd00::5".parse().unwrap()).unwrap();
+        assert_eq!(rule.translate("2001:db8::5".parse().unwrap()), Some("fd00::5".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_rewrites_only_matching_domains_and_counts_hits() {
+        let public = Ipv4Addr::new(203, 0, 113, 5);
+        let rewriter = AnswerRewriter::new(vec![
+            AnswerRewriteRule::address(public.into(), IpAddr::from([10, 0, 0, 5])).unwrap().for_domain("corp.example."),
+        ], RewriteStage::AfterCache);
+
+        let mut response = DnsResponseWrapper::create_a_response(1, "app.corp.example", &[public, Ipv4Addr::new(192, 0, 2, 1)], 120);
+        rewriter.after_cache(&query("App.Corp.Example"), &mut response);
+        assert_eq!(response.answers[0].data, RecordData::A(Ipv4Addr::new(10, 0, 0, 5)));
+        assert_eq!(response.answers[0].ttl, 120);
+        assert_eq!(response.answers[1].data, RecordData::A(Ipv4Addr::new(192, 0, 2, 1)));
+
+        // 其他域名和写入缓存前的阶段不改写，也不计数
+        let mut other = DnsResponseWrapper::create_a_response(1, "notcorp.example", &[public], 120);
+        rewriter.after_cache(&query("notcorp.example"), &mut other);
+        rewriter.before_cache(&query("app.corp.example"), &mut other);
+        assert_eq!(other.answers[0].data, RecordData::A(public));
+
+        let shared: SharedResponse = DnsResponseWrapper::create_a_response(1, "x.example", &[public], 60).into();
+        let untouched = rewriter.after_cache_shared(&query("x.example"), shared.clone());
+        assert!(untouched.shares_data_with(&shared));
+
+        assert_eq!(rewriter.stats(), vec![RewriteRuleStats {
+            rule: "203.0.113.5/32 -> 10.0.0.5/32 (corp.example)".to_string(),
+            hits: 1,
+        }]);
+    }
+}
@@ -1,24 +1,185 @@
 //! DNS缓存实现
 
 use crate::{Query, Response, Record};
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-use std::time::{Duration, Instant};
+use crate::types::{ClientAddress, RecordData, RecordType, ResponseCode, SharedResponse};
+use crate::dns_debug;
+use crate::utils::{names_equal, normalize_name};
+use super::cache_insights::{approx_response_bytes, CacheInsights, InsightScan, ObservedEntry};
+use super::clock::{Clock, real_clock};
+use super::memory::{MemoryBudget, MemoryComponent, MemoryMeter};
+use super::question::QuestionPolicy;
+use super::ttl_override::TtlOverrideStats;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::mem::size_of;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use crate::time::Instant;
+
+/// 后台清理每次持有缓存锁时最多检查的条目数
+const JANITOR_CHUNK_SIZE: usize = 256;
+
+/// 计入内存预算时估算的单个条目大小，后台清理扫完一轮后按实际条目校准
+pub(super) const DEFAULT_ENTRY_BYTES: usize = 512;
+
+/// 后台清理过期条目的任务配置
+///
+/// 缓存只在读到过期条目时才会覆盖它，之后不再被查询的名称会一直占用内存；
+/// 启用缓存时解析器按 `interval` 周期扫描一段缓存，移除其中的过期条目，下一次从上次停下的位置继续
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheJanitorConfig {
+    /// 两次清理之间的间隔
+    pub interval: Duration,
+    /// 每次清理最多检查的条目数
+    pub max_entries: usize,
+    /// 每次清理最多花费的时间，到期后留到下一次继续
+    pub time_budget: Duration,
+    /// 过期不超过该时长的条目暂不移除，供离线模式继续应答；解析器另外至少保留过期答案窗口内的条目
+    pub retain_expired: Duration,
+    /// 缓存概况中列出命中次数最多的多少个条目，None（默认）不统计
+    ///
+    /// 每轮扫描只保留这么多个候选，内存占用与缓存大小无关
+    pub track_hot_names: Option<usize>,
+}
+
+impl Default for CacheJanitorConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            max_entries: 4096,
+            time_budget: Duration::from_millis(5),
+            retain_expired: Duration::ZERO,
+            track_hot_names: None,
+        }
+    }
+}
+
+/// 一次分段清理的进度
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct SweepProgress {
+    /// 检查过的条目数
+    pub scanned: usize,
+    /// 移除的过期条目数
+    pub removed: usize,
+    /// 是否已扫到表尾，下一次从头开始
+    pub wrapped: bool,
+}
+
+/// 响应TTL钳制规则
+///
+/// 在写入缓存和返回给调用方之前统一应用：低于 `min_ttl` 的TTL被抬高，
+/// 高于 `max_ttl` 的TTL被压低。`min_ttl` 为0时等同于不抬高。
+/// 权威段SOA给出的否定缓存时间为0的NXDOMAIN表示权威服务器明确要求不缓存，这类应答只压低不抬高
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TtlClamp {
+    /// TTL下限（秒）
+    pub min_ttl: Option<u32>,
+    /// TTL上限（秒）
+    pub max_ttl: Option<u32>,
+}
+
+impl TtlClamp {
+    /// 创建TTL钳制规则
+    pub fn new(min_ttl: Option<Duration>, max_ttl: Option<Duration>) -> Self {
+        let to_secs = |d: Duration| d.as_secs().min(u32::MAX as u64) as u32;
+        Self {
+            min_ttl: min_ttl.map(to_secs),
+            max_ttl: max_ttl.map(to_secs),
+        }
+    }
+
+    /// 是否配置了任何钳制
+    pub fn is_active(&self) -> bool {
+        self.min_ttl.is_some_and(|min| min > 0) || self.max_ttl.is_some()
+    }
+
+    /// 钳制单个TTL值
+    pub fn clamp(&self, ttl: u32) -> u32 {
+        let mut ttl = ttl;
+        if let Some(min) = self.min_ttl {
+            ttl = ttl.max(min);
+        }
+        if let Some(max) = self.max_ttl {
+            ttl = ttl.min(max);
+        }
+        ttl
+    }
+
+    /// 钳制响应中所有记录的TTL，返回被修改的记录数
+    /// 
+    /// OPT伪记录的TTL字段是扩展响应码和标志位，不做钳制
+    pub fn apply(&self, response: &mut Response) -> usize {
+        if !self.is_active() {
+            return 0;
+        }
+
+        let clamp = if is_uncacheable_nxdomain(response) {
+            TtlClamp { min_ttl: None, ..*self }
+        } else {
+            *self
+        };
+        let mut changed = 0;
+        for record in response.answers.iter_mut()
+            .chain(response.authorities.iter_mut())
+            .chain(response.additionals.iter_mut())
+            .filter(|record| !is_opt(record))
+        {
+            let clamped = clamp.clamp(record.ttl);
+            if clamped != record.ttl {
+                dns_debug!("TTL钳制: {} {:?} {} -> {}", record.name, record.rtype, record.ttl, clamped);
+                record.ttl = clamped;
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+/// 是否为权威段第一条SOA给出的否定缓存时间为0的NXDOMAIN
+fn is_uncacheable_nxdomain(response: &Response) -> bool {
+    response.rcode() == ResponseCode::NxDomain
+        && response.authorities.iter()
+            .find_map(|record| match record.data {
+                RecordData::SOA { minimum, .. } => Some(minimum.min(record.ttl)),
+                _ => None,
+            })
+            == Some(0)
+}
+
+/// 携带EDNS客户端子网（ECS）的查询如何使用缓存
+///
+/// ECS查询的答案随客户端所在地区变化，按查询本身缓存会把一个地区的答案返回给另一个地区
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcsCacheMode {
+    /// 按发送的客户端子网（地址族、源前缀长度、截断后的地址）分别缓存，同一子网的客户端共享缓存
+    Scoped,
+    /// 携带ECS的查询不读也不写缓存
+    Bypass,
+}
 
 /// DNS缓存条目
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 struct CacheEntry {
-    /// 缓存的响应
-    response: Response,
+    /// 缓存的响应，命中时与调用方共用
+    response: Arc<Response>,
     /// 插入时间
     inserted_at: Instant,
     /// 过期时间
     expires_at: Instant,
     /// 原始TTL
     original_ttl: Duration,
+    /// 写入以来的命中次数
+    hits: AtomicU64,
+    /// 最近一次写入或命中时的访问序号，超出内存预算时先淘汰最久未用的条目
+    last_used: AtomicU64,
 }
 
 /// DNS缓存
+/// 
+/// 每个条目整份保存写入时的响应，读取时各段记录的顺序与写入时完全相同
 #[derive(Debug)]
 pub struct DnsCache {
     /// 缓存存储
@@ -27,17 +188,67 @@ pub struct DnsCache {
     max_ttl: Duration,
     /// 缓存统计
     stats: Arc<RwLock<CacheStats>>,
+    /// 时间源
+    clock: Arc<dyn Clock>,
+    /// TTL为0的记录是否按1秒缓存
+    cache_zero_ttl: bool,
+    /// 后台清理下一次开始检查的位置（按迭代顺序）
+    janitor_cursor: AtomicUsize,
+    /// 本轮后台清理扫描中累积的缓存概况
+    insight_scan: Mutex<InsightScan>,
+    /// 上一轮完整扫描得到的缓存概况
+    insights: RwLock<Option<CacheInsights>>,
+    /// 访问序号，每次写入或命中加一
+    access_tick: AtomicU64,
+    /// 计入内存预算时估算的单个条目大小
+    entry_bytes: AtomicUsize,
+    /// 配置了内存预算时向预算报告条目和热门名称的占用
+    memory: OnceLock<CacheMeters>,
+}
+
+/// 缓存向内存预算报告占用的句柄
+#[derive(Debug)]
+struct CacheMeters {
+    entries: MemoryMeter,
+    hot_names: MemoryMeter,
 }
 
 /// 缓存键
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct CacheKey {
+pub(super) struct CacheKey {
     /// 查询名称
     name: String,
     /// 查询类型
     qtype: u16,
     /// 查询类别
     qclass: u16,
+    /// 客户端子网（未携带ECS时为None）
+    subnet: Option<SubnetKey>,
+}
+
+/// 缓存键中的客户端子网：只保留前缀内的地址位
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SubnetKey {
+    family: u16,
+    prefix: u8,
+    address: Vec<u8>,
+}
+
+impl SubnetKey {
+    fn from_client(client: &ClientAddress) -> Self {
+        let octets = match client.address {
+            IpAddr::V4(addr) => addr.octets().to_vec(),
+            IpAddr::V6(addr) => addr.octets().to_vec(),
+        };
+        let prefix = client.source_prefix_length.min((octets.len() * 8) as u8);
+        let mut address: Vec<u8> = octets[..prefix.div_ceil(8) as usize].to_vec();
+        if !prefix.is_multiple_of(8)
+            && let Some(last) = address.last_mut()
+        {
+            *last &= 0xFFu8 << (8 - prefix % 8);
+        }
+        Self { family: client.family(), prefix, address }
+    }
 }
 
 /// 缓存统计
@@ -51,17 +262,265 @@ pub struct CacheStats {
     pub inserts: u64,
     /// 过期清理次数
     pub evictions: u64,
+    /// 因响应不可信或非NOERROR而拒绝写入的次数
+    pub rejected: u64,
+    /// 响应正常但按缓存策略不写入（TC位置位、NODATA、含TTL为0的记录）的次数
+    pub uncacheable: u64,
+    /// 读写缓存后端失败（含读取超时）的次数，由解析器统计
+    pub backend_errors: u64,
+    /// 在后台刷新期间返回过期答案的次数，由解析器统计
+    pub stale_served: u64,
+    /// 按名称、后缀或类型主动失效移除的条目数
+    pub invalidations: u64,
     /// 当前缓存大小
     pub current_size: usize,
+    /// 各TTL覆盖规则的命中次数，按添加顺序，由解析器统计
+    pub ttl_overrides: Vec<TtlOverrideStats>,
+}
+
+/// 主动失效的范围，覆盖匹配名称下的全部条目（含否定应答和各客户端子网的条目）
+///
+/// 名称不区分大小写，末尾的点可有可无
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheInvalidation {
+    /// 指定名称的全部条目（所有记录类型和类别）
+    Domain(String),
+    /// 指定后缀及其下所有子域名的条目，按标签边界匹配：`corp` 匹配 `a.b.corp`，不匹配 `notcorp`
+    Suffix(String),
+    /// 指定记录类型的全部条目
+    Type(RecordType),
+}
+
+impl CacheInvalidation {
+    /// 按名称失效
+    pub fn domain(name: &str) -> Self {
+        Self::Domain(normalize_name(name.trim()))
+    }
+
+    /// 按后缀失效，开头的点会被忽略
+    pub fn suffix(suffix: &str) -> Self {
+        Self::Suffix(normalize_name(suffix.trim().trim_start_matches('.')))
+    }
+
+    /// 缓存的名称和类型是否在失效范围内，`name` 应为缓存键中已规范化的名称
+    fn matches(&self, name: &str, qtype: u16) -> bool {
+        let name = name.trim_end_matches('.');
+        match self {
+            Self::Domain(domain) => names_equal(name, domain),
+            Self::Suffix(suffix) => {
+                let suffix = suffix.trim_end_matches('.');
+                if suffix.is_empty() {
+                    return true;
+                }
+                if name.len() == suffix.len() {
+                    return name.eq_ignore_ascii_case(suffix);
+                }
+                name.len() > suffix.len()
+                    && name.as_bytes()[name.len() - suffix.len() - 1] == b'.'
+                    && name[name.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+            }
+            Self::Type(rtype) => u16::from(*rtype) == qtype,
+        }
+    }
+}
+
+impl fmt::Display for CacheInvalidation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Domain(name) => write!(f, "domain {}", name),
+            Self::Suffix(suffix) => write!(f, "suffix {}", suffix),
+            Self::Type(rtype) => write!(f, "type {:?}", rtype),
+        }
+    }
+}
+
+/// 渐进清空、保留热门条目清空等批量操作的结果
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheClearReport {
+    /// 受影响的条目数：被移除或被提前安排过期的条目
+    pub affected: usize,
+    /// 原样保留的条目数
+    pub retained: usize,
+    /// 受影响的条目全部过期的预计时长，立即生效的操作为0
+    pub completes_in: Duration,
+}
+
+impl CacheClearReport {
+    /// 合并各分片的结果，预计时长取最大值
+    pub(super) fn merge(self, other: Self) -> Self {
+        Self {
+            affected: self.affected + other.affected,
+            retained: self.retained + other.retained,
+            completes_in: self.completes_in.max(other.completes_in),
+        }
+    }
+}
+
+/// 按每秒 `rate` 个的速率给条目安排过期时间，命中次数少的先过期，返回各条目的过期时间和全部过期的时长
+///
+/// 第i个（从1开始）条目在 `now + i / rate` 过期，最后一个在 `now + n / rate`
+pub(super) fn stagger_expiry<T>(mut entries: Vec<(T, u64)>, rate: NonZeroUsize, now: Instant) -> (Vec<(T, Instant)>, Duration) {
+    entries.sort_by_key(|(_, hits)| *hits);
+    let offset = |position: usize| Duration::from_nanos((position as u128 * 1_000_000_000 / rate.get() as u128) as u64);
+    let completes_in = offset(entries.len());
+    let deadlines = entries.into_iter()
+        .enumerate()
+        .map(|(index, (key, _))| (key, now + offset(index + 1)))
+        .collect();
+    (deadlines, completes_in)
+}
+
+/// 命中次数最多的至多 `top_n` 个条目，从未命中的条目不算热门
+pub(super) fn hottest<T>(mut entries: Vec<(T, u64)>, top_n: usize) -> Vec<T> {
+    entries.retain(|(_, hits)| *hits > 0);
+    entries.sort_by_key(|(_, hits)| std::cmp::Reverse(*hits));
+    entries.into_iter().take(top_n).map(|(key, _)| key).collect()
+}
+
+/// 响应不能写入缓存的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheRejection {
+    /// QR位未置位，报文不是响应
+    NotResponse,
+    /// 响应码不是NOERROR或NXDOMAIN，含OPT记录中的扩展位
+    ErrorRcode(u16),
+    /// 问题段缺失、多于一条或与查询的名称、类型、类别不一致
+    QuestionMismatch,
+    /// TC位置位，答案不完整
+    Truncated,
+    /// 否定应答（NXDOMAIN或没有回答记录）的权威段没有SOA，无法确定否定缓存时间
+    NoData,
+    /// 含TTL为0的记录，上游要求每次都重新查询
+    ZeroTtl,
+}
+
+impl CacheRejection {
+    /// 是否像是伪造或串号的响应（非NOERROR响应本身是正常答复）
+    pub fn is_suspicious(&self) -> bool {
+        matches!(self, CacheRejection::NotResponse | CacheRejection::QuestionMismatch)
+    }
+    
+    /// 响应本身正常，只是按缓存策略不保存，计入 [`CacheStats::uncacheable`] 而不是 `rejected`
+    pub fn is_uncacheable(&self) -> bool {
+        matches!(self, CacheRejection::Truncated | CacheRejection::NoData | CacheRejection::ZeroTtl)
+    }
+}
+
+impl fmt::Display for CacheRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheRejection::NotResponse => write!(f, "QR flag not set"),
+            CacheRejection::ErrorRcode(rcode) => write!(f, "rcode {}", rcode),
+            CacheRejection::QuestionMismatch => write!(f, "echoed question does not match the query"),
+            CacheRejection::Truncated => write!(f, "TC flag set"),
+            CacheRejection::NoData => write!(f, "negative answer without SOA"),
+            CacheRejection::ZeroTtl => write!(f, "record with TTL 0"),
+        }
+    }
+}
+
+/// 是否为OPT伪记录（TTL字段另有含义，不是缓存时间）
+pub(super) fn is_opt(record: &Record) -> bool {
+    u16::from(record.rtype) == crate::transport::OPT_RECORD_TYPE
+}
+
+/// 估算一个缓存条目占用的内存：键、条目本身和共用的响应
+fn approx_entry_bytes(key: &CacheKey, entry: &CacheEntry) -> usize {
+    size_of::<CacheKey>() + key.name.len() + size_of::<CacheEntry>() + approx_response_bytes(&entry.response)
+}
+
+/// 响应中带有缓存时间的记录（OPT伪记录除外）
+fn ttl_records(response: &Response) -> impl Iterator<Item = &Record> {
+    response.answers.iter()
+        .chain(response.authorities.iter())
+        .chain(response.additionals.iter())
+        .filter(|record| !is_opt(record))
+}
+
+/// ANY查询的类型值，任何回答记录都算作匹配
+const ANY_RECORD_TYPE: u16 = 255;
+
+/// 否定应答权威段SOA给出的区域顶点和否定缓存时间（RFC 2308 第5节）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegativeTtl {
+    /// SOA记录的所有者名称，即否定应答所属区域的顶点
+    pub zone_apex: String,
+    /// 否定缓存时间（秒）：SOA的MINIMUM字段与SOA记录自身TTL中较小者
+    pub ttl: u32,
+}
+
+/// 提取否定应答的区域顶点和否定缓存时间
+/// 
+/// 响应码为NXDOMAIN，或回答段没有查询类型的记录（NODATA，含只有CNAME链的情况）时，
+/// 取权威段第一条SOA记录；不是否定应答或权威段没有SOA时返回 `None`
+pub fn negative_ttl(response: &Response, qtype: RecordType) -> Option<NegativeTtl> {
+    let answered = response.answers.iter()
+        .any(|record| record.rtype == qtype || u16::from(qtype) == ANY_RECORD_TYPE);
+    if answered && response.rcode() != ResponseCode::NxDomain {
+        return None;
+    }
+    response.authorities.iter().find_map(|record| match record.data {
+        RecordData::SOA { minimum, .. } => Some(NegativeTtl {
+            zone_apex: record.name.clone(),
+            ttl: minimum.min(record.ttl),
+        }),
+        _ => None,
+    })
+}
+
+/// 检查响应并丢弃类别与查询不同的记录，得到可以写入缓存的响应
+/// 
+/// 除 [`DnsCache::check_response`] 的检查外，TC位置位的响应不缓存；NXDOMAIN和没有回答记录（NODATA）
+/// 的否定应答只在权威段带有SOA时缓存，缓存时间不超过 [`negative_ttl`]；
+/// 只要有一条记录的TTL为0，整个响应都不缓存，不逐条剔除，以免缓存残缺的CNAME链或地址集合。
+/// `cache_zero_ttl` 为true时TTL为0的记录改为按1秒缓存
+pub fn cacheable_response(query: &Query, mut response: Response, cache_zero_ttl: bool) -> std::result::Result<Response, CacheRejection> {
+    DnsCache::check_response(query, &response)?;
+    if response.flags.tc {
+        return Err(CacheRejection::Truncated);
+    }
+    
+    let before = response.answers.len() + response.authorities.len() + response.additionals.len();
+    response.answers.retain(|record| record.class == query.qclass);
+    response.authorities.retain(|record| record.class == query.qclass);
+    response.additionals.retain(|record| record.class == query.qclass);
+    let dropped = before - response.answers.len() - response.authorities.len() - response.additionals.len();
+    if dropped > 0 {
+        dns_debug!("{} 的响应中有 {} 条记录类别与查询不符，未写入缓存", query.name, dropped);
+    }
+    
+    let negative = response.answers.is_empty() || response.rcode() == ResponseCode::NxDomain;
+    if negative && negative_ttl(&response, query.qtype).is_none() {
+        return Err(CacheRejection::NoData);
+    }
+    if ttl_records(&response).any(|record| record.ttl == 0) {
+        if !cache_zero_ttl {
+            return Err(CacheRejection::ZeroTtl);
+        }
+        for record in response.answers.iter_mut()
+            .chain(response.authorities.iter_mut())
+            .chain(response.additionals.iter_mut())
+            .filter(|record| record.ttl == 0 && !is_opt(record))
+        {
+            record.ttl = 1;
+        }
+    }
+    Ok(response)
 }
 
 impl CacheKey {
     /// 从查询创建缓存键
     fn from_query(query: &Query) -> Self {
+        Self::for_client(query, None)
+    }
+    
+    /// 从查询和发送的客户端子网创建缓存键
+    pub(super) fn for_client(query: &Query, client: Option<&ClientAddress>) -> Self {
         Self {
-            name: query.name.to_lowercase(),
+            name: normalize_name(&query.name),
             qtype: query.qtype.into(),
             qclass: query.qclass.into(),
+            subnet: client.map(SubnetKey::from_client),
         }
     }
 }
@@ -69,17 +528,50 @@ impl CacheKey {
 impl DnsCache {
     /// 创建新的DNS缓存
     pub fn new(max_ttl: Duration) -> Self {
+        Self::with_clock(max_ttl, real_clock())
+    }
+    
+    /// 使用指定时间源创建DNS缓存
+    pub fn with_clock(max_ttl: Duration, clock: Arc<dyn Clock>) -> Self {
         Self {
             cache: Arc::new(RwLock::new(HashMap::new())),
             max_ttl,
             stats: Arc::new(RwLock::new(CacheStats::default())),
+            clock,
+            cache_zero_ttl: false,
+            janitor_cursor: AtomicUsize::new(0),
+            insight_scan: Mutex::new(InsightScan::default()),
+            insights: RwLock::new(None),
+            access_tick: AtomicU64::new(0),
+            entry_bytes: AtomicUsize::new(DEFAULT_ENTRY_BYTES),
+            memory: OnceLock::new(),
         }
     }
     
+    /// 设置是否缓存含TTL为0记录的响应（按1秒缓存），默认不缓存
+    pub fn with_zero_ttl_caching(mut self, enabled: bool) -> Self {
+        self.cache_zero_ttl = enabled;
+        self
+    }
+    
     /// 获取缓存记录
     pub fn get(&self, query: &Query) -> Option<Response> {
-        let key = CacheKey::from_query(query);
-        let now = Instant::now();
+        self.get_for_client(query, None)
+    }
+    
+    /// 获取指定客户端子网的缓存记录
+    /// 
+    /// 不同子网的条目互相独立；`client` 为None时只匹配未携带ECS的条目
+    pub fn get_for_client(&self, query: &Query, client: Option<&ClientAddress>) -> Option<Response> {
+        self.get_shared_for_client(query, client).map(SharedResponse::into_response)
+    }
+    
+    /// 获取指定客户端子网的缓存记录，不复制响应
+    /// 
+    /// 返回的响应与缓存条目共用数据，剩余TTL见 [`SharedResponse::ttl_override`]
+    pub fn get_shared_for_client(&self, query: &Query, client: Option<&ClientAddress>) -> Option<SharedResponse> {
+        let key = CacheKey::for_client(query, client);
+        let now = self.clock.now_instant();
         
         let cache = self.cache.read().ok()?;
         
@@ -89,23 +581,12 @@ impl DnsCache {
                 if let Ok(mut stats) = self.stats.write() {
                     stats.hits += 1;
                 }
+                entry.hits.fetch_add(1, Ordering::Relaxed);
+                entry.last_used.store(self.access_tick.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
                 
-                // 调整TTL
-                let mut response = entry.response.clone();
+                // 所有记录的TTL按剩余时间给出
                 let remaining_ttl = entry.expires_at.duration_since(now);
-                
-                // 更新所有记录的TTL
-                for record in &mut response.answers {
-                    record.ttl = remaining_ttl.as_secs() as u32;
-                }
-                for record in &mut response.authorities {
-                    record.ttl = remaining_ttl.as_secs() as u32;
-                }
-                for record in &mut response.additionals {
-                    record.ttl = remaining_ttl.as_secs() as u32;
-                }
-                
-                return Some(response);
+                return Some(SharedResponse::new(entry.response.clone(), Some(remaining_ttl.as_secs() as u32)));
             }
         }
         
@@ -117,22 +598,82 @@ impl DnsCache {
         None
     }
     
+    /// 获取过期不超过 `max_stale` 的缓存记录，记录TTL为0
+    /// 
+    /// 仍然有效的条目不在此返回（用 [`DnsCache::get_for_client`] 读取）；
+    /// 过期条目在 [`DnsCache::cleanup_expired`] 或后台清理移除之前一直保留
+    pub fn get_stale_for_client(&self, query: &Query, client: Option<&ClientAddress>, max_stale: Duration) -> Option<Response> {
+        let key = CacheKey::for_client(query, client);
+        let now = self.clock.now_instant();
+        
+        let cache = self.cache.read().ok()?;
+        let entry = cache.get(&key)?;
+        if now < entry.expires_at || now.duration_since(entry.expires_at) >= max_stale {
+            return None;
+        }
+        
+        Some(SharedResponse::new(entry.response.clone(), Some(0)).into_response())
+    }
+    
     /// 插入缓存记录
     pub fn insert(&self, query: Query, response: Response) {
-        let key = CacheKey::from_query(&query);
-        let now = Instant::now();
+        self.insert_for_client(query, None, response);
+    }
+    
+    /// 检查响应能否作为查询的答案写入缓存
+    /// 
+    /// 要求QR位置位、响应码为NOERROR或NXDOMAIN，且问题段恰好一条并与查询一致（名称不区分大小写），
+    /// 问题段按 [`QuestionPolicy`] 判定
+    pub fn check_response(query: &Query, response: &Response) -> std::result::Result<(), CacheRejection> {
+        if !response.flags.qr {
+            return Err(CacheRejection::NotResponse);
+        }
+        let rcode = response.extended_rcode();
+        if !matches!(ResponseCode::from(rcode), ResponseCode::NoError | ResponseCode::NxDomain) {
+            return Err(CacheRejection::ErrorRcode(rcode));
+        }
+        // 接受的无问题段响应已在逐上游清洗时补上问题段，写入缓存的响应必须带有问题段
+        QuestionPolicy::default()
+            .check(query, response)
+            .map_err(|_| CacheRejection::QuestionMismatch)
+    }
+    
+    /// 按客户端子网插入缓存记录
+    /// 
+    /// 未通过 [`DnsCache::check_response`] 的响应不会写入，只计入 `rejected`；按 [`cacheable_response`]
+    /// 的策略不缓存的响应计入 `uncacheable`。类别与查询不同的记录在写入前被丢弃
+    pub fn insert_for_client(&self, query: Query, client: Option<&ClientAddress>, response: Response) {
+        let response = match cacheable_response(&query, response, self.cache_zero_ttl) {
+            Ok(response) => response,
+            Err(reason) => {
+                dns_debug!("拒绝缓存 {} {:?} 的响应: {}", query.name, query.qtype, reason);
+                if let Ok(mut stats) = self.stats.write() {
+                    if reason.is_uncacheable() {
+                        stats.uncacheable += 1;
+                    } else {
+                        stats.rejected += 1;
+                    }
+                }
+                return;
+            },
+        };
+        
+        let key = CacheKey::for_client(&query, client);
+        let now = self.clock.now_instant();
         
         // 计算TTL
-        let ttl = self.calculate_ttl(&response);
+        let ttl = self.calculate_ttl(&query, &response);
         if ttl.is_zero() {
             return; // 不缓存TTL为0的记录
         }
         
         let entry = CacheEntry {
-            response,
+            response: Arc::new(response),
             inserted_at: now,
             expires_at: now + ttl,
             original_ttl: ttl,
+            hits: AtomicU64::new(0),
+            last_used: AtomicU64::new(self.access_tick.fetch_add(1, Ordering::Relaxed)),
         };
         
         if let Ok(mut cache) = self.cache.write() {
@@ -147,41 +688,22 @@ impl DnsCache {
                 }
             }
         }
+        self.report_entry_memory();
     }
     
-    /// 计算缓存TTL
-    fn calculate_ttl(&self, response: &Response) -> Duration {
-        let mut min_ttl = self.max_ttl;
-        
-        // 找到最小的TTL
-        for record in &response.answers {
-            let record_ttl = Duration::from_secs(record.ttl as u64);
-            if record_ttl < min_ttl {
-                min_ttl = record_ttl;
-            }
-        }
-        
-        for record in &response.authorities {
-            let record_ttl = Duration::from_secs(record.ttl as u64);
-            if record_ttl < min_ttl {
-                min_ttl = record_ttl;
-            }
-        }
-        
-        for record in &response.additionals {
-            let record_ttl = Duration::from_secs(record.ttl as u64);
-            if record_ttl < min_ttl {
-                min_ttl = record_ttl;
-            }
-        }
-        
-        // 确保不超过最大TTL
-        min_ttl.min(self.max_ttl)
+    /// 计算缓存TTL：所有记录（OPT除外）中最小的TTL，否定应答另外不超过SOA给出的否定缓存时间，不超过最大TTL
+    fn calculate_ttl(&self, query: &Query, response: &Response) -> Duration {
+        let negative = negative_ttl(response, query.qtype)
+            .map(|negative| Duration::from_secs(negative.ttl as u64));
+        ttl_records(response)
+            .map(|record| Duration::from_secs(record.ttl as u64))
+            .chain(negative)
+            .fold(self.max_ttl, Duration::min)
     }
     
     /// 清理过期条目
     pub fn cleanup_expired(&self) {
-        let now = Instant::now();
+        let now = self.clock.now_instant();
         let mut evicted_count = 0;
         
         if let Ok(mut cache) = self.cache.write() {
@@ -200,6 +722,122 @@ impl DnsCache {
                 stats.current_size = cache.len();
             }
         }
+        self.report_entry_memory();
+    }
+    
+    /// 分段清理过期超过 `retain` 的条目，返回移除的条目数
+    /// 
+    /// 最多检查 `max_entries` 个条目或花费 `time_budget`，下一次调用从本次停下的位置继续，
+    /// 扫到表尾后从头开始。每段只短暂持有锁，清理期间查询不会被长时间阻塞
+    pub fn cleanup_expired_batch(&self, max_entries: usize, time_budget: Duration, retain: Duration) -> usize {
+        self.sweep_expired(max_entries, Instant::now() + time_budget, retain, None).removed
+    }
+    
+    /// 按后台清理配置分段清理一次，返回移除的条目数
+    /// 
+    /// 与 [`DnsCache::cleanup_expired_batch`] 相同，另外按 `track_hot_names` 统计命中最多的条目
+    pub fn janitor_batch(&self, config: &CacheJanitorConfig) -> usize {
+        let deadline = Instant::now() + config.time_budget;
+        self.sweep_expired(config.max_entries, deadline, config.retain_expired, config.track_hot_names).removed
+    }
+    
+    /// 后台清理上一轮完整扫描得到的缓存概况，还没有扫完过一轮时返回None
+    pub fn insights(&self) -> Option<CacheInsights> {
+        self.insights.read().ok()?.clone()
+    }
+    
+    /// 从游标处检查至多 `max_entries` 个条目，到表尾或超过 `deadline`（真实时间）时停止
+    /// 
+    /// 先在读锁下找出一段中的过期键，再在写锁下移除仍然过期的条目。移除不会改变其余条目的
+    /// 迭代顺序，游标据此前移；期间的插入可能触发扩容打乱顺序，个别条目会留到下一轮。
+    /// 保留下来的条目同时计入缓存概况，扫到表尾时发布本轮的概况；`hot_limit` 见
+    /// [`CacheJanitorConfig::track_hot_names`]
+    pub(super) fn sweep_expired(&self, max_entries: usize, deadline: Instant, retain: Duration, hot_limit: Option<usize>) -> SweepProgress {
+        let now = self.clock.now_instant();
+        let removable = |entry: &CacheEntry| now >= entry.expires_at && now.duration_since(entry.expires_at) >= retain;
+        let Ok(mut scan) = self.insight_scan.lock() else {
+            return SweepProgress::default();
+        };
+        let mut cursor = self.janitor_cursor.load(Ordering::Relaxed);
+        let mut progress = SweepProgress::default();
+        
+        while progress.scanned < max_entries {
+            let chunk = JANITOR_CHUNK_SIZE.min(max_entries - progress.scanned);
+            let (expired, seen, len) = {
+                let Ok(cache) = self.cache.read() else {
+                    break;
+                };
+                if cursor >= cache.len() {
+                    cursor = 0;
+                }
+                let mut seen = 0;
+                let expired: Vec<CacheKey> = cache.iter()
+                    .skip(cursor)
+                    .take(chunk)
+                    .inspect(|_| seen += 1)
+                    .filter(|(key, entry)| {
+                        if removable(entry) {
+                            return true;
+                        }
+                        scan.observe(ObservedEntry {
+                            name: &key.name,
+                            qtype: key.qtype,
+                            remaining: entry.expires_at.checked_duration_since(now).filter(|remaining| !remaining.is_zero()),
+                            age: now.saturating_duration_since(entry.inserted_at),
+                            approx_bytes: approx_entry_bytes(key, entry),
+                            hits: entry.hits.load(Ordering::Relaxed),
+                        }, hot_limit);
+                        false
+                    })
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                (expired, seen, cache.len())
+            };
+            
+            let mut removed = 0;
+            if !expired.is_empty() {
+                let Ok(mut cache) = self.cache.write() else {
+                    break;
+                };
+                for key in &expired {
+                    if cache.get(key).is_some_and(removable) {
+                        cache.remove(key);
+                        removed += 1;
+                    }
+                }
+                if let Ok(mut stats) = self.stats.write() {
+                    stats.evictions += removed as u64;
+                    stats.current_size = cache.len();
+                }
+            }
+            
+            progress.scanned += seen;
+            progress.removed += removed;
+            cursor += seen - removed;
+            if cursor + removed >= len {
+                cursor = 0;
+                progress.wrapped = true;
+                let insights = scan.finish(self.clock.now_system());
+                self.calibrate_entry_bytes(&insights);
+                if let Ok(mut published) = self.insights.write() {
+                    *published = Some(insights);
+                }
+                break;
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+        
+        self.janitor_cursor.store(cursor, Ordering::Relaxed);
+        drop(scan);
+        if progress.removed > 0 || progress.wrapped {
+            self.report_entry_memory();
+        }
+        if progress.wrapped {
+            self.report_hot_name_memory();
+        }
+        progress
     }
     
     /// 清空缓存
@@ -212,6 +850,235 @@ impl DnsCache {
                 stats.current_size = 0;
             }
         }
+        self.report_entry_memory();
+    }
+    
+    /// 移除失效范围内的全部条目，返回移除数
+    ///
+    /// 缓存没有按名称建立索引，每次调用在写锁下扫描全部条目，耗时与缓存大小成正比；
+    /// 适合配置变更等低频操作，不宜在查询路径上调用
+    pub fn invalidate(&self, scope: &CacheInvalidation) -> usize {
+        let Ok(mut cache) = self.cache.write() else {
+            return 0;
+        };
+        let before = cache.len();
+        cache.retain(|key, _| !scope.matches(&key.name, key.qtype));
+        let removed = before - cache.len();
+
+        if let Ok(mut stats) = self.stats.write() {
+            stats.invalidations += removed as u64;
+            stats.current_size = cache.len();
+        }
+        drop(cache);
+        if removed > 0 {
+            dns_debug!("缓存失效 {} 移除了 {} 个条目", scope, removed);
+            self.report_entry_memory();
+        }
+        removed
+    }
+
+    /// 移除指定名称在所有记录类型下的条目，返回移除数
+    pub fn invalidate_domain(&self, name: &str) -> usize {
+        self.invalidate(&CacheInvalidation::domain(name))
+    }
+
+    /// 移除指定后缀及其所有子域名的条目，返回移除数
+    pub fn invalidate_suffix(&self, suffix: &str) -> usize {
+        self.invalidate(&CacheInvalidation::suffix(suffix))
+    }
+
+    /// 移除指定记录类型的全部条目，返回移除数
+    pub fn invalidate_type(&self, rtype: RecordType) -> usize {
+        self.invalidate(&CacheInvalidation::Type(rtype))
+    }
+
+    /// 把失效范围内仍然有效的条目立即标记为过期，返回标记的条目数
+    ///
+    /// 与 [`DnsCache::invalidate`] 不同，条目不会被移除：在过期答案窗口内仍可先行应答并在后台刷新，
+    /// 之后由后台清理移除。同样在写锁下扫描全部条目
+    pub fn expire(&self, scope: &CacheInvalidation) -> usize {
+        let now = self.clock.now_instant();
+        let Ok(mut cache) = self.cache.write() else {
+            return 0;
+        };
+        let mut expired = 0;
+        for (key, entry) in cache.iter_mut() {
+            if now < entry.expires_at && scope.matches(&key.name, key.qtype) {
+                entry.expires_at = now;
+                expired += 1;
+            }
+        }
+        if expired > 0 {
+            dns_debug!("缓存失效 {} 标记了 {} 个条目过期", scope, expired);
+        }
+        expired
+    }
+
+    /// 渐进清空：按每秒 `rate` 个的速率给当前有效的条目安排过期时间，命中次数少的先过期
+    ///
+    /// 条目不会立即移除，到期后的第一次查询重新向上游查询，上游因此看到的是平缓的查询量而不是一次性的突发；
+    /// 到期的条目与自然过期的条目一样由后台清理移除。之后写入的条目不受影响
+    pub fn expire_gradually(&self, rate: NonZeroUsize) -> CacheClearReport {
+        let now = self.clock.now_instant();
+        let (deadlines, completes_in) = stagger_expiry(self.live_hits(), rate, now);
+        let affected = self.expire_keys_at(deadlines, now);
+        CacheClearReport { affected, retained: self.size().saturating_sub(affected), completes_in }
+    }
+
+    /// 保留命中次数最多的 `top_n` 个有效条目，移除其余条目（含已过期的条目）
+    ///
+    /// 命中次数从条目写入时开始计算，从未命中的条目不会被保留。调用期间新写入的条目不会被移除
+    pub fn clear_except_hot(&self, top_n: usize) -> CacheClearReport {
+        let cutoff = self.clock.now_instant();
+        let keep: HashSet<CacheKey> = hottest(self.live_hits(), top_n).into_iter().collect();
+        let affected = self.remove_except(&keep, cutoff);
+        CacheClearReport { affected, retained: self.size(), completes_in: Duration::ZERO }
+    }
+
+    /// 计入内存预算，重复调用时只有第一次生效（多个解析器共享同一缓存时计入第一个解析器的预算）
+    ///
+    /// 收缩由 [`DnsCache::shrink_memory`] 完成，回调由持有缓存的一方注册
+    pub(super) fn attach_memory_budget(&self, budget: &Arc<MemoryBudget>) {
+        let meters = CacheMeters {
+            entries: MemoryMeter::new(budget.clone(), MemoryComponent::Cache),
+            hot_names: MemoryMeter::new(budget.clone(), MemoryComponent::HotNames),
+        };
+        if self.memory.set(meters).is_ok() {
+            self.report_entry_memory();
+            self.report_hot_name_memory();
+        }
+    }
+
+    /// 超出内存预算时释放至少 `wanted` 字节（条目不足时尽量释放），返回估算释放的字节数
+    ///
+    /// 热门名称整体清空，下一轮后台清理扫完后重新统计；缓存条目先淘汰已过期的，再淘汰最久未用的
+    pub(super) fn shrink_memory(&self, component: MemoryComponent, wanted: usize) -> usize {
+        match component {
+            MemoryComponent::HotNames => self.clear_hot_names(),
+            MemoryComponent::Cache => self.evict_least_recently_used(wanted),
+            _ => 0,
+        }
+    }
+
+    fn clear_hot_names(&self) -> usize {
+        let freed = match self.insights.write() {
+            Ok(mut published) => published.as_mut().map_or(0, |insights| {
+                let freed = insights.hot_names_bytes();
+                insights.hot_names = Vec::new();
+                freed
+            }),
+            Err(_) => 0,
+        };
+        self.report_hot_name_memory();
+        freed
+    }
+
+    fn evict_least_recently_used(&self, wanted: usize) -> usize {
+        let entry_bytes = self.entry_bytes.load(Ordering::Relaxed).max(1);
+        let count = wanted.div_ceil(entry_bytes);
+        let now = self.clock.now_instant();
+        let Ok(mut cache) = self.cache.write() else {
+            return 0;
+        };
+        let mut victims: Vec<(bool, u64, CacheKey)> = cache.iter()
+            .map(|(key, entry)| (now < entry.expires_at, entry.last_used.load(Ordering::Relaxed), key.clone()))
+            .collect();
+        if count < victims.len() {
+            victims.select_nth_unstable_by_key(count, |(live, last_used, _)| (*live, *last_used));
+            victims.truncate(count);
+        }
+        for (_, _, key) in &victims {
+            cache.remove(key);
+        }
+        if let Ok(mut stats) = self.stats.write() {
+            stats.evictions += victims.len() as u64;
+            stats.current_size = cache.len();
+        }
+        drop(cache);
+        if !victims.is_empty() {
+            dns_debug!("超出内存预算，淘汰了 {} 个缓存条目", victims.len());
+        }
+        self.report_entry_memory();
+        victims.len() * entry_bytes
+    }
+
+    /// 按一轮完整扫描的实际占用校准单个条目的估算大小
+    fn calibrate_entry_bytes(&self, insights: &CacheInsights) {
+        if let Some(bytes) = insights.approx_memory_bytes.checked_div(insights.entries + insights.expired_entries) {
+            self.entry_bytes.store(bytes.max(1), Ordering::Relaxed);
+        }
+    }
+
+    /// 向内存预算报告条目占用：条目数乘以估算的单个条目大小。调用时不能持有缓存的锁
+    fn report_entry_memory(&self) {
+        if let Some(meters) = self.memory.get() {
+            meters.entries.set(self.size() * self.entry_bytes.load(Ordering::Relaxed));
+        }
+    }
+
+    /// 向内存预算报告已发布的热门名称的占用。调用时不能持有缓存概况的锁
+    fn report_hot_name_memory(&self) {
+        if let Some(meters) = self.memory.get() {
+            let bytes = self.insights.read().ok()
+                .and_then(|published| published.as_ref().map(CacheInsights::hot_names_bytes))
+                .unwrap_or(0);
+            meters.hot_names.set(bytes);
+        }
+    }
+
+    /// 当前时间
+    pub(super) fn now(&self) -> Instant {
+        self.clock.now_instant()
+    }
+
+    /// 仍然有效的条目及其命中次数
+    pub(super) fn live_hits(&self) -> Vec<(CacheKey, u64)> {
+        let now = self.clock.now_instant();
+        let Ok(cache) = self.cache.read() else {
+            return Vec::new();
+        };
+        cache.iter()
+            .filter(|(_, entry)| now < entry.expires_at)
+            .map(|(key, entry)| (key.clone(), entry.hits.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// 把条目的过期时间提前到给定时刻，返回修改的条目数
+    ///
+    /// 已经更早过期的条目不变，`cutoff` 之后重新写入的条目也不变
+    pub(super) fn expire_keys_at(&self, deadlines: impl IntoIterator<Item = (CacheKey, Instant)>, cutoff: Instant) -> usize {
+        let Ok(mut cache) = self.cache.write() else {
+            return 0;
+        };
+        let mut changed = 0;
+        for (key, deadline) in deadlines {
+            if let Some(entry) = cache.get_mut(&key)
+                && deadline < entry.expires_at
+                && entry.inserted_at <= cutoff
+            {
+                entry.expires_at = deadline;
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    /// 移除不在 `keep` 中且写入时间不晚于 `cutoff` 的条目，返回移除数
+    pub(super) fn remove_except(&self, keep: &HashSet<CacheKey>, cutoff: Instant) -> usize {
+        let Ok(mut cache) = self.cache.write() else {
+            return 0;
+        };
+        let before = cache.len();
+        cache.retain(|key, entry| keep.contains(key) || entry.inserted_at > cutoff);
+        let removed = before - cache.len();
+
+        if let Ok(mut stats) = self.stats.write() {
+            stats.invalidations += removed as u64;
+            stats.current_size = cache.len();
+        }
+        drop(cache);
+        self.report_entry_memory();
+        removed
     }
     
     /// 获取缓存大小
@@ -238,7 +1105,7 @@ impl DnsCache {
     /// 检查是否包含指定查询
     pub fn contains(&self, query: &Query) -> bool {
         let key = CacheKey::from_query(query);
-        let now = Instant::now();
+        let now = self.clock.now_instant();
         
         if let Ok(cache) = self.cache.read() {
             if let Some(entry) = cache.get(&key) {
@@ -251,7 +1118,12 @@ impl DnsCache {
     
     /// 移除指定查询的缓存
     pub fn remove(&self, query: &Query) -> bool {
-        let key = CacheKey::from_query(query);
+        self.remove_for_client(query, None)
+    }
+    
+    /// 移除指定查询在某个客户端子网下的缓存
+    pub fn remove_for_client(&self, query: &Query, client: Option<&ClientAddress>) -> bool {
+        let key = CacheKey::for_client(query, client);
         
         if let Ok(mut cache) = self.cache.write() {
             let removed = cache.remove(&key).is_some();
@@ -261,6 +1133,8 @@ impl DnsCache {
                 if let Ok(mut stats) = self.stats.write() {
                     stats.current_size = cache.len();
                 }
+                drop(cache);
+                self.report_entry_memory();
             }
             
             return removed;
@@ -307,7 +1181,7 @@ impl CacheCleanupTask {
     
     /// 启动清理任务
     pub async fn start(self) {
-        let mut interval_timer = tokio::time::interval(self.interval);
+        let mut interval_timer = crate::runtime::interval(self.interval);
         
         loop {
             interval_timer.tick().await;
@@ -319,6 +1193,7 @@ impl CacheCleanupTask {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::resolver::clock::TestClock;
     use crate::types::*;
     use std::net::Ipv4Addr;
     
@@ -333,7 +1208,7 @@ mod tests {
     fn create_test_response() -> Response {
         Response {
             id: 12345,
-            flags: Flags::default(),
+            flags: Flags { qr: true, ..Flags::default() },
             queries: vec![create_test_query()],
             answers: vec![Record {
                 name: "example.com".to_string(),
@@ -378,4 +1253,516 @@ mod tests {
         let cached_response = cache.get(&query);
         assert!(cached_response.is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_cache_expires_with_clock() {
+        let clock = Arc::new(TestClock::new());
+        let cache = DnsCache::with_clock(Duration::from_secs(3600), clock.clone());
+        let query = create_test_query();
+        cache.insert(query.clone(), create_test_response());
+        
+        // 剩余TTL随时钟递减
+        clock.advance(Duration::from_secs(100));
+        assert_eq!(cache.get(&query).unwrap().answers[0].ttl, 200);
+        
+        clock.advance(Duration::from_secs(199));
+        assert!(cache.contains(&query));
+        
+        clock.advance(Duration::from_secs(1));
+        assert!(!cache.contains(&query));
+        assert!(cache.get(&query).is_none());
+        
+        cache.cleanup_expired();
+        assert_eq!(cache.size(), 0);
+        assert_eq!(cache.stats().evictions, 1);
+    }
+    
+    #[test]
+    fn test_janitor_batches_remove_expired_entries_without_lookups() {
+        let clock = Arc::new(TestClock::new());
+        let cache = DnsCache::with_clock(Duration::from_secs(3600), clock.clone());
+        let insert = |name: String, ttl: u32| {
+            let query = Query { name: name.clone(), qtype: RecordType::A, qclass: QClass::IN };
+            let mut response = create_test_response();
+            response.queries = vec![query.clone()];
+            response.answers[0].name = name;
+            response.answers[0].ttl = ttl;
+            cache.insert(query, response);
+        };
+        for i in 0..10_000 {
+            insert(format!("short{}.example.com", i), 5);
+        }
+        for i in 0..100 {
+            insert(format!("long{}.example.com", i), 3600);
+        }
+        assert_eq!(cache.size(), 10_100);
+        
+        // 未过期时清理不移除任何条目
+        assert_eq!(cache.cleanup_expired_batch(20_000, Duration::from_secs(10), Duration::ZERO), 0);
+        
+        // 保留期内的过期条目不移除
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(cache.cleanup_expired_batch(20_000, Duration::from_secs(10), Duration::from_secs(2)), 0);
+        let removed = cache.cleanup_expired_batch(1000, Duration::from_secs(10), Duration::ZERO);
+        assert!(removed <= 1000 && removed > 0, "{}", removed);
+        assert_eq!(cache.size(), 10_100 - removed);
+        
+        // 每次从上次停下的位置继续，一轮扫完全部条目
+        let mut ticks = 1;
+        while cache.size() > 100 {
+            cache.cleanup_expired_batch(1000, Duration::from_secs(10), Duration::ZERO);
+            ticks += 1;
+            assert!(ticks <= 11, "janitor did not finish the sweep in {} ticks", ticks);
+        }
+        
+        let stats = cache.stats();
+        assert_eq!(cache.size(), 100);
+        assert_eq!(stats.current_size, 100);
+        assert_eq!(stats.evictions, 10_000);
+        assert_eq!((stats.hits, stats.misses), (0, 0));
+    }
+
+    #[test]
+    fn test_stale_entry_within_window() {
+        let clock = Arc::new(TestClock::new());
+        let cache = DnsCache::with_clock(Duration::from_secs(3600), clock.clone());
+        let query = create_test_query();
+        let window = Duration::from_secs(10);
+        cache.insert(query.clone(), create_test_response());
+        
+        // 未过期的条目不算过期答案
+        assert!(cache.get_stale_for_client(&query, None, window).is_none());
+        
+        clock.advance(Duration::from_secs(305));
+        assert!(cache.get(&query).is_none());
+        let stale = cache.get_stale_for_client(&query, None, window).unwrap();
+        assert_eq!(stale.answers[0].ttl, 0);
+        
+        clock.advance(Duration::from_secs(5));
+        assert!(cache.get_stale_for_client(&query, None, window).is_none());
+    }
+
+    #[test]
+    fn test_subnet_key_truncates_to_prefix() {
+        let beijing = ClientAddress::from_ipv4(Ipv4Addr::new(202, 96, 128, 86), 20);
+        let same_subnet = ClientAddress::from_ipv4(Ipv4Addr::new(202, 96, 143, 1), 20);
+        let other_subnet = ClientAddress::from_ipv4(Ipv4Addr::new(202, 96, 144, 1), 20);
+        
+        let cache = DnsCache::new(Duration::from_secs(3600));
+        let query = create_test_query();
+        cache.insert_for_client(query.clone(), Some(&beijing), create_test_response());
+        
+        assert!(cache.get_for_client(&query, Some(&same_subnet)).is_some());
+        assert!(cache.get_for_client(&query, Some(&other_subnet)).is_none());
+        assert!(cache.get(&query).is_none());
+    }
+
+    #[test]
+    fn test_untrustworthy_responses_are_not_cached() {
+        let cache = DnsCache::new(Duration::from_secs(3600));
+        let query = create_test_query();
+        
+        let mut not_response = create_test_response();
+        not_response.flags.qr = false;
+        let mut servfail = create_test_response();
+        servfail.flags.rcode = 2;
+        let mut other_name = create_test_response();
+        other_name.queries[0].name = "attacker.example".to_string();
+        let mut other_type = create_test_response();
+        other_type.queries[0].qtype = RecordType::AAAA;
+        let mut no_question = create_test_response();
+        no_question.queries.clear();
+        
+        let cases = [
+            (not_response, CacheRejection::NotResponse),
+            (servfail, CacheRejection::ErrorRcode(2)),
+            (other_name, CacheRejection::QuestionMismatch),
+            (other_type, CacheRejection::QuestionMismatch),
+            (no_question, CacheRejection::QuestionMismatch),
+        ];
+        for (response, expected) in cases {
+            assert_eq!(DnsCache::check_response(&query, &response), Err(expected));
+            cache.insert(query.clone(), response);
+        }
+        
+        assert_eq!(cache.size(), 0);
+        assert_eq!(cache.stats().rejected, 5);
+        assert_eq!(cache.stats().inserts, 0);
+    }
+    
+    #[test]
+    fn test_question_match_ignores_case_and_trailing_dot() {
+        let cache = DnsCache::new(Duration::from_secs(3600));
+        let mut response = create_test_response();
+        response.queries[0].name = "EXAMPLE.com.".to_string();
+        
+        cache.insert(create_test_query(), response);
+        assert!(cache.contains(&create_test_query()));
+        assert_eq!(cache.stats().rejected, 0);
+    }
+    
+    #[test]
+    fn test_records_of_other_class_are_dropped() {
+        let cache = DnsCache::new(Duration::from_secs(3600));
+        let mut response = create_test_response();
+        let mut chaos = response.answers[0].clone();
+        chaos.class = QClass::CH;
+        chaos.data = RecordData::A(Ipv4Addr::new(198, 51, 100, 1));
+        response.answers.push(chaos);
+        
+        cache.insert(create_test_query(), response);
+        let cached = cache.get(&create_test_query()).unwrap();
+        assert_eq!(cached.answers.len(), 1);
+        assert_eq!(cached.answers[0].class, QClass::IN);
+    }
+
+    #[test]
+    fn test_ttl_clamp_raises_and_lowers() {
+        let clamp = TtlClamp::new(Some(Duration::from_secs(30)), Some(Duration::from_secs(86400)));
+        let mut response = create_test_response();
+        response.answers[0].ttl = 0;
+        let mut long = response.answers[0].clone();
+        long.ttl = 1_000_000;
+        response.answers.push(long);
+
+        assert_eq!(clamp.apply(&mut response), 2);
+        assert_eq!(response.answers[0].ttl, 30);
+        assert_eq!(response.answers[1].ttl, 86400);
+
+        // 钳制后TTL为0的记录也能进入缓存
+        let cache = DnsCache::new(Duration::from_secs(3600));
+        cache.insert(create_test_query(), response);
+        assert!(cache.contains(&create_test_query()));
+    }
+
+    #[test]
+    fn test_ttl_clamp_zero_min_disables_raising() {
+        let clamp = TtlClamp::new(Some(Duration::ZERO), None);
+        assert!(!clamp.is_active());
+
+        let mut response = create_test_response();
+        response.answers[0].ttl = 0;
+        assert_eq!(clamp.apply(&mut response), 0);
+        assert_eq!(response.answers[0].ttl, 0);
+    }
+
+    #[test]
+    fn test_zero_ttl_truncated_and_nodata_responses_are_not_cached() {
+        let cache = DnsCache::new(Duration::from_secs(3600));
+        let query = create_test_query();
+        
+        let mut zero_ttl = create_test_response();
+        zero_ttl.answers[0].ttl = 0;
+        // 只要有一条记录的TTL为0，整个响应都不缓存
+        let mut mixed = create_test_response();
+        mixed.answers.push(zero_ttl.answers[0].clone());
+        let mut truncated = create_test_response();
+        truncated.flags.tc = true;
+        let mut nodata = create_test_response();
+        nodata.answers.clear();
+        
+        let cases = [
+            (zero_ttl, CacheRejection::ZeroTtl),
+            (mixed, CacheRejection::ZeroTtl),
+            (truncated, CacheRejection::Truncated),
+            (nodata, CacheRejection::NoData),
+        ];
+        for (response, expected) in cases {
+            assert_eq!(cacheable_response(&query, response.clone(), false).unwrap_err(), expected);
+            assert!(!expected.is_suspicious());
+            cache.insert(query.clone(), response);
+        }
+        
+        assert_eq!(cache.size(), 0);
+        assert_eq!(cache.stats().uncacheable, 4);
+        assert_eq!(cache.stats().rejected, 0);
+    }
+    
+    /// 权威段带 example.com SOA（MINIMUM 60，TTL 300）的否定应答
+    fn negative_response(answers: Vec<Record>, rcode: u8) -> Response {
+        let mut response = create_test_response();
+        response.flags.rcode = rcode;
+        response.answers = answers;
+        response.authorities.push(Record {
+            name: "example.com".to_string(),
+            rtype: RecordType::SOA,
+            class: QClass::IN,
+            ttl: 300,
+            data: RecordData::SOA {
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 60,
+            },
+        });
+        response
+    }
+    
+    #[test]
+    fn test_negative_answers_with_soa_are_cached_for_soa_minimum() {
+        let query = create_test_query();
+        let cname = Record {
+            name: "example.com".to_string(),
+            rtype: RecordType::CNAME,
+            class: QClass::IN,
+            ttl: 600,
+            data: RecordData::CNAME("alias.example.com".to_string()),
+        };
+        let expected = Some(NegativeTtl { zone_apex: "example.com".to_string(), ttl: 60 });
+        assert_eq!(negative_ttl(&negative_response(vec![], 3), query.qtype), expected);
+        assert_eq!(negative_ttl(&negative_response(vec![], 0), query.qtype), expected);
+        // 只有CNAME链、没有查询类型的记录也是NODATA
+        assert_eq!(negative_ttl(&negative_response(vec![cname], 0), query.qtype), expected);
+        assert_eq!(negative_ttl(&create_test_response(), query.qtype), None);
+        
+        for rcode in [3, 0] {
+            let clock = Arc::new(TestClock::new());
+            let cache = DnsCache::with_clock(Duration::from_secs(3600), clock.clone());
+            cache.insert(query.clone(), negative_response(vec![], rcode));
+            
+            clock.advance(Duration::from_secs(59));
+            let cached = cache.get(&query).unwrap();
+            assert_eq!(cached.flags.rcode, rcode);
+            assert_eq!(cached.authorities[0].ttl, 1);
+            clock.advance(Duration::from_secs(1));
+            assert!(cache.get(&query).is_none());
+        }
+        
+        // 没有SOA的NXDOMAIN无从确定否定缓存时间，不缓存
+        let mut nxdomain = create_test_response();
+        nxdomain.flags.rcode = 3;
+        nxdomain.answers.clear();
+        assert_eq!(cacheable_response(&query, nxdomain, false).unwrap_err(), CacheRejection::NoData);
+    }
+    
+    #[test]
+    fn test_zero_ttl_caching_keeps_records_for_one_second() {
+        let clock = Arc::new(TestClock::new());
+        let cache = DnsCache::with_clock(Duration::from_secs(3600), clock.clone()).with_zero_ttl_caching(true);
+        let query = create_test_query();
+        let mut response = create_test_response();
+        let mut zero_ttl = response.answers[0].clone();
+        zero_ttl.ttl = 0;
+        response.answers.push(zero_ttl);
+        
+        cache.insert(query.clone(), response);
+        assert_eq!(cache.get(&query).unwrap().answers.len(), 2);
+        clock.advance(Duration::from_secs(1));
+        assert!(!cache.contains(&query));
+    }
+    
+    #[test]
+    fn test_ttl_clamp_does_not_raise_uncacheable_nxdomain() {
+        let clamp = TtlClamp::new(Some(Duration::from_secs(30)), Some(Duration::from_secs(60)));
+        let nxdomain = |minimum: u32, ttl: u32| {
+            let mut response = negative_response(vec![], 3);
+            response.authorities[0].ttl = ttl;
+            if let RecordData::SOA { minimum: soa_minimum, .. } = &mut response.authorities[0].data {
+                *soa_minimum = minimum;
+            }
+            response
+        };
+        
+        // SOA的MINIMUM或自身TTL为0：不抬高，仍然压低
+        let mut uncacheable = nxdomain(0, 300);
+        assert_eq!(clamp.apply(&mut uncacheable), 1);
+        assert_eq!(uncacheable.authorities[0].ttl, 60);
+        let mut uncacheable = nxdomain(300, 0);
+        assert_eq!(clamp.apply(&mut uncacheable), 0);
+        assert_eq!(uncacheable.authorities[0].ttl, 0);
+        let cache = DnsCache::new(Duration::from_secs(3600));
+        cache.insert(create_test_query(), uncacheable);
+        assert!(!cache.contains(&create_test_query()));
+        
+        // 其他否定应答照常抬高
+        let mut cacheable = nxdomain(300, 5);
+        assert_eq!(clamp.apply(&mut cacheable), 1);
+        assert_eq!(cacheable.authorities[0].ttl, 30);
+    }
+    
+    #[test]
+    fn test_ttl_clamp_leaves_opt_record_alone() {
+        let clamp = TtlClamp::new(Some(Duration::from_secs(30)), Some(Duration::from_secs(86400)));
+        let mut response = create_test_response();
+        response.set_rcode(ResponseCode::BadVers);
+        let opt_ttl = response.additionals[0].ttl;
+        
+        assert_eq!(clamp.apply(&mut response), 0);
+        assert_eq!(response.additionals[0].ttl, opt_ttl);
+        assert_eq!(response.rcode(), ResponseCode::BadVers);
+    }
+    
+    /// 写入一条肯定应答（A或AAAA），或在 `nxdomain` 为真时写入一条带SOA的NXDOMAIN
+    fn insert_entry(cache: &DnsCache, name: &str, qtype: RecordType, client: Option<&ClientAddress>, nxdomain: bool) {
+        let query = Query { name: name.to_string(), qtype, qclass: QClass::IN };
+        let mut response = if nxdomain { negative_response(vec![], 3) } else { create_test_response() };
+        response.queries = vec![query.clone()];
+        for record in &mut response.answers {
+            record.name = name.to_string();
+            record.rtype = qtype;
+            if qtype == RecordType::AAAA {
+                record.data = RecordData::AAAA("2001:db8::1".parse().unwrap());
+            }
+        }
+        cache.insert_for_client(query, client, response);
+    }
+    
+    fn cached_names(cache: &DnsCache) -> Vec<String> {
+        let mut names: Vec<String> = cache.get_cached_queries().into_iter()
+            .map(|query| format!("{} {:?}", query.name, query.qtype))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+    
+    #[test]
+    fn test_invalidation_removes_exactly_matching_entries() {
+        let client = ClientAddress::from_ipv4(Ipv4Addr::new(202, 96, 128, 86), 24);
+        let cache = DnsCache::new(Duration::from_secs(3600));
+        insert_entry(&cache, "internal.corp", RecordType::A, None, false);
+        insert_entry(&cache, "Internal.Corp.", RecordType::AAAA, None, false);
+        insert_entry(&cache, "a.b.internal.corp", RecordType::A, None, false);
+        insert_entry(&cache, "a.b.internal.corp", RecordType::A, Some(&client), false);
+        insert_entry(&cache, "gone.internal.corp", RecordType::A, None, true);
+        insert_entry(&cache, "notinternal.corp", RecordType::A, None, false);
+        insert_entry(&cache, "other.corp", RecordType::AAAA, None, false);
+        insert_entry(&cache, "internal.corp.example", RecordType::A, None, false);
+        assert_eq!(cache.size(), 8);
+        
+        // 后缀匹配后缀本身及多级子域名，包括否定应答和带客户端子网的条目，不匹配同级名称
+        assert_eq!(cache.invalidate_suffix(".INTERNAL.corp."), 5);
+        assert_eq!(cached_names(&cache), vec![
+            "internal.corp.example A",
+            "notinternal.corp A",
+            "other.corp AAAA",
+        ]);
+        assert_eq!(cache.invalidate_suffix("internal.corp"), 0);
+        
+        assert_eq!(cache.invalidate_domain("corp"), 0);
+        assert_eq!(cache.invalidate_domain("NotInternal.corp."), 1);
+        assert_eq!(cache.invalidate_type(RecordType::AAAA), 1);
+        assert_eq!(cached_names(&cache), vec!["internal.corp.example A"]);
+        
+        let stats = cache.stats();
+        assert_eq!(stats.invalidations, 7);
+        assert_eq!(stats.current_size, 1);
+    }
+    
+    #[test]
+    fn test_expire_keeps_entries_for_stale_answers_and_gradual_expiry_starts_cold() {
+        let clock = Arc::new(TestClock::new());
+        let cache = DnsCache::with_clock(Duration::from_secs(3600), clock.clone());
+        for name in ["hot.corp", "cold.corp", "other.example"] {
+            insert_with_ttl(&cache, name, RecordType::A, 600);
+        }
+        let query = |name: &str| Query { name: name.to_string(), qtype: RecordType::A, qclass: QClass::IN };
+        for _ in 0..3 {
+            assert!(cache.get(&query("hot.corp")).is_some());
+        }
+        
+        assert_eq!(cache.expire(&CacheInvalidation::domain("other.example")), 1);
+        assert_eq!(cache.expire(&CacheInvalidation::domain("other.example")), 0);
+        assert!(cache.get(&query("other.example")).is_none());
+        assert!(cache.get_stale_for_client(&query("other.example"), None, Duration::from_secs(30)).is_some());
+        assert_eq!(cache.size(), 3);
+        
+        // 每秒1个：冷条目1秒后过期，热条目2秒后过期，已过期的条目不受影响
+        let report = cache.expire_gradually(NonZeroUsize::new(1).unwrap());
+        assert_eq!(report, CacheClearReport { affected: 2, retained: 1, completes_in: Duration::from_secs(2) });
+        clock.advance(Duration::from_secs(1));
+        assert!(cache.get(&query("cold.corp")).is_none());
+        assert_eq!(cache.get(&query("hot.corp")).unwrap().answers[0].ttl, 1);
+        clock.advance(Duration::from_secs(1));
+        assert!(cache.get(&query("hot.corp")).is_none());
+    }
+    
+    #[test]
+    fn test_invalidation_scope_matching() {
+        let suffix = CacheInvalidation::suffix("example.com.");
+        assert!(suffix.matches("example.com", 1));
+        assert!(suffix.matches("www.example.com.", 28));
+        assert!(!suffix.matches("badexample.com", 1));
+        assert!(!suffix.matches("com", 1));
+        
+        let domain = CacheInvalidation::domain("WWW.example.com");
+        assert!(domain.matches("www.example.com.", 1));
+        assert!(!domain.matches("a.www.example.com", 1));
+        
+        assert!(CacheInvalidation::Type(RecordType::AAAA).matches("example.com", 28));
+        assert!(!CacheInvalidation::Type(RecordType::AAAA).matches("example.com", 1));
+    }
+    
+    fn insert_with_ttl(cache: &DnsCache, name: &str, qtype: RecordType, ttl: u32) {
+        let query = Query { name: name.to_string(), qtype, qclass: QClass::IN };
+        let data = match qtype {
+            RecordType::AAAA => RecordData::AAAA("2001:db8::1".parse().unwrap()),
+            _ => RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        };
+        let response = Response {
+            id: 1,
+            flags: Flags { qr: true, ..Flags::default() },
+            queries: vec![query.clone()],
+            answers: vec![Record { name: name.to_string(), rtype: qtype, class: QClass::IN, ttl, data }],
+            authorities: vec![],
+            additionals: vec![],
+        };
+        cache.insert(query, response);
+    }
+    
+    #[test]
+    fn test_janitor_publishes_insights_after_full_pass() {
+        let clock = Arc::new(TestClock::new());
+        let cache = DnsCache::with_clock(Duration::from_secs(86400), clock.clone());
+        insert_with_ttl(&cache, "old.example", RecordType::A, 5000);
+        insert_with_ttl(&cache, "stale.example", RecordType::A, 50);
+        clock.advance(Duration::from_secs(100));
+        for name in ["hot.example", "warm.example", "cold.example"] {
+            insert_with_ttl(&cache, name, RecordType::A, 5);
+        }
+        insert_with_ttl(&cache, "mid.example", RecordType::A, 30);
+        insert_with_ttl(&cache, "v6.example", RecordType::AAAA, 600);
+        for _ in 0..3 {
+            assert!(cache.get(&Query { name: "hot.example".to_string(), qtype: RecordType::A, qclass: QClass::IN }).is_some());
+        }
+        assert!(cache.get(&Query { name: "warm.example".to_string(), qtype: RecordType::A, qclass: QClass::IN }).is_some());
+        
+        let janitor = CacheJanitorConfig {
+            max_entries: 4,
+            time_budget: Duration::from_secs(10),
+            retain_expired: Duration::from_secs(3600),
+            track_hot_names: Some(1),
+            ..CacheJanitorConfig::default()
+        };
+        // 没有扫完一轮之前不发布
+        assert_eq!(cache.janitor_batch(&janitor), 0);
+        assert!(cache.insights().is_none());
+        assert_eq!(cache.janitor_batch(&janitor), 0);
+        
+        let insights = cache.insights().unwrap();
+        assert_eq!(insights.computed_at, Some(clock.now_system()));
+        assert_eq!(insights.entries, 6);
+        assert_eq!(insights.expired_entries, 1);
+        assert_eq!(insights.entries_by_type, [("A".to_string(), 5), ("AAAA".to_string(), 1)].into_iter().collect());
+        let counts: Vec<usize> = insights.ttl_histogram.iter().map(|bucket| bucket.count).collect();
+        assert_eq!(counts, vec![3, 1, 0, 1, 1, 0]);
+        assert_eq!(insights.ttl_histogram.last().unwrap().le_secs, None);
+        assert_eq!(insights.oldest_entry_age_secs, Some(100));
+        assert!(insights.approx_memory_bytes >= 7 * (size_of::<CacheEntry>() + size_of::<Response>()));
+        assert_eq!(insights.hot_names, vec![super::super::cache_insights::HotName {
+            name: "hot.example".to_string(),
+            record_type: "A".to_string(),
+            hits: 3,
+        }]);
+        
+        // 过期超过保留期的条目被移除，不计入下一轮的概况；v6.example 过期不足一小时，仍按过期条目计
+        clock.advance(Duration::from_secs(4000));
+        assert_eq!(cache.janitor_batch(&CacheJanitorConfig { max_entries: 100, ..janitor }), 5);
+        let insights = cache.insights().unwrap();
+        assert_eq!((insights.entries, insights.expired_entries), (1, 1));
+        assert!(insights.hot_names.is_empty());
+    }
+}
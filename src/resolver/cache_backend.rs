@@ -0,0 +1,823 @@
+//! 可替换的缓存后端
+//!
+//! 解析器通过 [`DnsCacheBackend`] 读写缓存，默认使用进程内的 [`DnsCache`]。
+//! 多个解析器实例需要共享缓存时（例如负载均衡后的多实例部署），可以实现该trait接入Redis等外部存储。
+
+use crate::runtime;
+use crate::{Query, Response, Result, DnsError};
+use crate::types::{ClientAddress, SharedResponse};
+use crate::{dns_debug, dns_warn};
+use super::cache::{cacheable_response, hottest, stagger_expiry, CacheClearReport, CacheInvalidation, CacheJanitorConfig, CacheKey, CacheStats, DnsCache};
+use super::cache_insights::CacheInsights;
+use super::clock::{Clock, real_clock};
+use super::memory::{MemoryBudget, MemoryComponent};
+use async_trait::async_trait;
+use futures::FutureExt;
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use crate::time::Instant;
+use tokio::task::JoinHandle;
+
+/// 读取缓存后端的超时时间，超时按未命中处理
+pub const CACHE_BACKEND_GET_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// DNS缓存后端
+///
+/// 写入的响应已经过可缓存检查（见 [`super::cache::cacheable_response`]）和TTL钳制；
+/// 后端负责按记录TTL过期（否定应答另外不超过 [`super::cache::negative_ttl`]），并在读取时把TTL改写为剩余时间。
+/// 缓存键需要区分名称（不区分大小写）、类型、类别以及客户端子网。
+///
+/// 解析器对后端的要求：
+/// - `get` 返回错误或超过 [`CACHE_BACKEND_GET_TIMEOUT`] 时按未命中处理
+/// - `insert` 的Future在查询路径上只轮询一次，未完成的部分交给后台任务，
+///   因此实现不应在首次轮询中做阻塞操作
+#[async_trait]
+pub trait DnsCacheBackend: Send + Sync + Debug {
+    /// 读取缓存，未命中返回 `Ok(None)`
+    async fn get(&self, query: &Query, client: Option<&ClientAddress>) -> Result<Option<Response>>;
+
+    /// 读取缓存，返回可与缓存共用数据的响应，解析器的查询路径使用此方法
+    ///
+    /// 默认包装 [`get`](Self::get) 的结果；进程内后端可以直接返回共享的条目，避免每次命中都复制记录
+    async fn get_shared(&self, query: &Query, client: Option<&ClientAddress>) -> Result<Option<SharedResponse>> {
+        Ok(self.get(query, client).await?.map(SharedResponse::from))
+    }
+
+    /// 读取过期不超过 `max_stale` 的条目（TTL改写为0），供后台刷新期间先行应答
+    ///
+    /// 默认不保留过期条目，总是返回 `Ok(None)`，解析器随即改为向上游查询
+    async fn get_stale(&self, _query: &Query, _client: Option<&ClientAddress>, _max_stale: Duration) -> Result<Option<Response>> {
+        Ok(None)
+    }
+
+    /// 写入缓存
+    async fn insert(&self, query: Query, client: Option<ClientAddress>, response: Response) -> Result<()>;
+
+    /// 移除缓存，返回是否存在该条目
+    async fn remove(&self, query: &Query, client: Option<&ClientAddress>) -> Result<bool>;
+
+    /// 清空缓存
+    async fn clear(&self) -> Result<()>;
+
+    /// 移除失效范围内的全部条目（含否定应答和各客户端子网的条目），返回移除数
+    ///
+    /// 默认返回 [`DnsError::NotImplemented`]，不支持按名称或类型枚举条目的外部存储只能整体 [`clear`](Self::clear)
+    async fn invalidate(&self, scope: &CacheInvalidation) -> Result<usize> {
+        Err(DnsError::NotImplemented(format!("Cache backend does not support invalidating by {}", scope)))
+    }
+
+    /// 把失效范围内仍然有效的条目立即标记为过期（不移除），返回标记数
+    ///
+    /// 过期答案窗口内的条目仍可先行应答并在后台刷新。默认返回 [`DnsError::NotImplemented`]
+    async fn expire(&self, scope: &CacheInvalidation) -> Result<usize> {
+        Err(DnsError::NotImplemented(format!("Cache backend does not support expiring by {}", scope)))
+    }
+
+    /// 渐进清空：按每秒 `rate` 个的速率给当前有效的条目安排过期时间，见 [`DnsCache::expire_gradually`]
+    ///
+    /// 默认返回 [`DnsError::NotImplemented`]
+    async fn expire_gradually(&self, _rate: NonZeroUsize) -> Result<CacheClearReport> {
+        Err(DnsError::NotImplemented("Cache backend does not support gradual clearing".to_string()))
+    }
+
+    /// 保留命中次数最多的 `top_n` 个条目，移除其余条目，见 [`DnsCache::clear_except_hot`]
+    ///
+    /// 需要按条目统计命中次数，默认返回 [`DnsError::NotImplemented`]
+    async fn clear_except_hot(&self, _top_n: usize) -> Result<CacheClearReport> {
+        Err(DnsError::NotImplemented("Cache backend does not track per-entry hits".to_string()))
+    }
+
+    /// 后台清理：最多检查 `max_entries` 个条目或花费 `time_budget`，移除其中过期超过 `retain` 的条目，返回移除数
+    ///
+    /// 下一次调用应从本次停下的位置继续。默认什么都不做，适用于自行按TTL过期的外部存储
+    fn cleanup_expired_batch(&self, _max_entries: usize, _time_budget: Duration, _retain: Duration) -> usize {
+        0
+    }
+
+    /// 后台清理任务每次调用的入口，返回移除数
+    ///
+    /// 默认按配置调用 [`cleanup_expired_batch`](Self::cleanup_expired_batch)；
+    /// 支持 [`insights`](Self::insights) 的后端在这里顺带统计缓存概况
+    fn janitor_batch(&self, config: &CacheJanitorConfig) -> usize {
+        self.cleanup_expired_batch(config.max_entries, config.time_budget, config.retain_expired)
+    }
+
+    /// 后台清理最近一轮完整扫描得到的缓存概况，必须直接返回已有的快照而不是现场扫描
+    ///
+    /// 默认返回None，外部存储通常无法廉价地统计这些信息
+    fn insights(&self) -> Option<CacheInsights> {
+        None
+    }
+
+    /// 配置了全局内存预算时由解析器调用一次，后端在之后的变更中向预算报告进程内占用的内存
+    ///
+    /// 默认不报告，适用于数据不在进程内的外部存储
+    fn attach_memory_budget(&self, _budget: &Arc<MemoryBudget>) {}
+
+    /// 超出内存预算时释放 `component`（[`MemoryComponent::Cache`] 或 [`MemoryComponent::HotNames`]）
+    /// 中至少 `wanted` 字节，返回估算释放的字节数
+    ///
+    /// 默认什么都不做，返回0
+    fn shrink_memory(&self, _component: MemoryComponent, _wanted: usize) -> usize {
+        0
+    }
+
+    /// 缓存统计
+    fn stats(&self) -> CacheStats;
+}
+
+#[async_trait]
+impl DnsCacheBackend for DnsCache {
+    async fn get(&self, query: &Query, client: Option<&ClientAddress>) -> Result<Option<Response>> {
+        Ok(self.get_for_client(query, client))
+    }
+
+    async fn get_shared(&self, query: &Query, client: Option<&ClientAddress>) -> Result<Option<SharedResponse>> {
+        Ok(self.get_shared_for_client(query, client))
+    }
+
+    async fn get_stale(&self, query: &Query, client: Option<&ClientAddress>, max_stale: Duration) -> Result<Option<Response>> {
+        Ok(self.get_stale_for_client(query, client, max_stale))
+    }
+
+    async fn insert(&self, query: Query, client: Option<ClientAddress>, response: Response) -> Result<()> {
+        self.insert_for_client(query, client.as_ref(), response);
+        Ok(())
+    }
+
+    async fn remove(&self, query: &Query, client: Option<&ClientAddress>) -> Result<bool> {
+        Ok(self.remove_for_client(query, client))
+    }
+
+    async fn clear(&self) -> Result<()> {
+        DnsCache::clear(self);
+        Ok(())
+    }
+
+    async fn invalidate(&self, scope: &CacheInvalidation) -> Result<usize> {
+        Ok(DnsCache::invalidate(self, scope))
+    }
+
+    async fn expire(&self, scope: &CacheInvalidation) -> Result<usize> {
+        Ok(DnsCache::expire(self, scope))
+    }
+
+    async fn expire_gradually(&self, rate: NonZeroUsize) -> Result<CacheClearReport> {
+        Ok(DnsCache::expire_gradually(self, rate))
+    }
+
+    async fn clear_except_hot(&self, top_n: usize) -> Result<CacheClearReport> {
+        Ok(DnsCache::clear_except_hot(self, top_n))
+    }
+
+    fn cleanup_expired_batch(&self, max_entries: usize, time_budget: Duration, retain: Duration) -> usize {
+        DnsCache::cleanup_expired_batch(self, max_entries, time_budget, retain)
+    }
+
+    fn janitor_batch(&self, config: &CacheJanitorConfig) -> usize {
+        DnsCache::janitor_batch(self, config)
+    }
+
+    fn insights(&self) -> Option<CacheInsights> {
+        DnsCache::insights(self)
+    }
+
+    fn attach_memory_budget(&self, budget: &Arc<MemoryBudget>) {
+        DnsCache::attach_memory_budget(self, budget)
+    }
+
+    fn shrink_memory(&self, component: MemoryComponent, wanted: usize) -> usize {
+        DnsCache::shrink_memory(self, component, wanted)
+    }
+
+    fn stats(&self) -> CacheStats {
+        DnsCache::stats(self)
+    }
+}
+
+/// 分片的进程内缓存
+///
+/// 按缓存键的哈希分到N个互相独立的 [`DnsCache`]，每个分片各自加锁，
+/// 多线程高并发读写时减少锁争用
+#[derive(Debug)]
+pub struct ShardedMemoryCache {
+    shards: Vec<DnsCache>,
+    /// 后台清理当前所在的分片
+    janitor_shard: AtomicUsize,
+}
+
+impl ShardedMemoryCache {
+    /// 创建分片缓存，`shard_count` 必须大于0
+    pub fn new(shard_count: usize, max_ttl: Duration) -> Result<Self> {
+        Self::with_clock(shard_count, max_ttl, real_clock())
+    }
+
+    /// 使用指定时间源创建分片缓存
+    pub fn with_clock(shard_count: usize, max_ttl: Duration, clock: Arc<dyn Clock>) -> Result<Self> {
+        if shard_count == 0 {
+            return Err(DnsError::InvalidConfig("Cache shard count must be greater than zero".to_string()));
+        }
+        Ok(Self {
+            shards: (0..shard_count).map(|_| DnsCache::with_clock(max_ttl, clock.clone())).collect(),
+            janitor_shard: AtomicUsize::new(0),
+        })
+    }
+
+    /// 分片数量
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// 清理所有分片中的过期条目
+    pub fn cleanup_expired(&self) {
+        for shard in &self.shards {
+            shard.cleanup_expired();
+        }
+    }
+
+    /// 分段清理过期超过 `retain` 的条目，返回移除的条目数
+    ///
+    /// 逐个分片扫描，一个分片扫完后转到下一个，预算用完时留到下一次从同一位置继续
+    pub fn cleanup_expired_batch(&self, max_entries: usize, time_budget: Duration, retain: Duration) -> usize {
+        self.sweep_shards(max_entries, Instant::now() + time_budget, retain, None)
+    }
+
+    /// 按后台清理配置分段清理一次，返回移除的条目数
+    pub fn janitor_batch(&self, config: &CacheJanitorConfig) -> usize {
+        let deadline = Instant::now() + config.time_budget;
+        self.sweep_shards(config.max_entries, deadline, config.retain_expired, config.track_hot_names)
+    }
+
+    /// 各分片上一轮完整扫描的缓存概况之和，还有分片没有扫完过一轮时返回None
+    pub fn insights(&self) -> Option<CacheInsights> {
+        let mut shards = self.shards.iter().map(DnsCache::insights);
+        let first = shards.next()??;
+        shards.try_fold(first, |total, shard| Some(total.merge(shard?)))
+    }
+
+    /// 渐进清空，所有分片的条目按命中次数统一排队，整体速率为每秒 `rate` 个
+    pub fn expire_gradually(&self, rate: NonZeroUsize) -> CacheClearReport {
+        let now = self.shards[0].now();
+        let (deadlines, completes_in) = stagger_expiry(self.live_hits(), rate, now);
+        let mut by_shard = vec![Vec::new(); self.shards.len()];
+        for ((index, key), deadline) in deadlines {
+            by_shard[index].push((key, deadline));
+        }
+        let affected = self.shards.iter()
+            .zip(by_shard)
+            .map(|(shard, deadlines)| shard.expire_keys_at(deadlines, now))
+            .sum();
+        let size: usize = self.shards.iter().map(DnsCache::size).sum();
+        CacheClearReport { affected, retained: size.saturating_sub(affected), completes_in }
+    }
+
+    /// 保留所有分片中命中次数最多的 `top_n` 个条目，移除其余条目
+    pub fn clear_except_hot(&self, top_n: usize) -> CacheClearReport {
+        let cutoff = self.shards[0].now();
+        let mut keep = vec![HashSet::new(); self.shards.len()];
+        for (index, key) in hottest(self.live_hits(), top_n) {
+            keep[index].insert(key);
+        }
+        self.shards.iter()
+            .zip(&keep)
+            .map(|(shard, keep)| CacheClearReport {
+                affected: shard.remove_except(keep, cutoff),
+                retained: shard.size(),
+                completes_in: Duration::ZERO,
+            })
+            .fold(CacheClearReport::default(), CacheClearReport::merge)
+    }
+
+    /// 各分片中仍然有效的条目，带上分片序号
+    fn live_hits(&self) -> Vec<((usize, CacheKey), u64)> {
+        self.shards.iter()
+            .enumerate()
+            .flat_map(|(index, shard)| shard.live_hits().into_iter().map(move |(key, hits)| ((index, key), hits)))
+            .collect()
+    }
+
+    fn sweep_shards(&self, max_entries: usize, deadline: Instant, retain: Duration, hot_limit: Option<usize>) -> usize {
+        let mut remaining = max_entries;
+        let mut removed = 0;
+        for _ in 0..self.shards.len() {
+            let index = self.janitor_shard.load(Ordering::Relaxed) % self.shards.len();
+            let progress = self.shards[index].sweep_expired(remaining, deadline, retain, hot_limit);
+            removed += progress.removed;
+            remaining -= progress.scanned;
+            if progress.wrapped {
+                self.janitor_shard.store((index + 1) % self.shards.len(), Ordering::Relaxed);
+            }
+            if remaining == 0 || Instant::now() >= deadline {
+                break;
+            }
+        }
+        removed
+    }
+
+    fn shard(&self, query: &Query, client: Option<&ClientAddress>) -> &DnsCache {
+        let mut hasher = DefaultHasher::new();
+        CacheKey::for_client(query, client).hash(&mut hasher);
+        &self.shards[(hasher.finish() % self.shards.len() as u64) as usize]
+    }
+}
+
+#[async_trait]
+impl DnsCacheBackend for ShardedMemoryCache {
+    async fn get(&self, query: &Query, client: Option<&ClientAddress>) -> Result<Option<Response>> {
+        Ok(self.shard(query, client).get_for_client(query, client))
+    }
+
+    async fn get_shared(&self, query: &Query, client: Option<&ClientAddress>) -> Result<Option<SharedResponse>> {
+        Ok(self.shard(query, client).get_shared_for_client(query, client))
+    }
+
+    async fn get_stale(&self, query: &Query, client: Option<&ClientAddress>, max_stale: Duration) -> Result<Option<Response>> {
+        Ok(self.shard(query, client).get_stale_for_client(query, client, max_stale))
+    }
+
+    async fn insert(&self, query: Query, client: Option<ClientAddress>, response: Response) -> Result<()> {
+        self.shard(&query, client.as_ref()).insert_for_client(query, client.as_ref(), response);
+        Ok(())
+    }
+
+    async fn remove(&self, query: &Query, client: Option<&ClientAddress>) -> Result<bool> {
+        Ok(self.shard(query, client).remove_for_client(query, client))
+    }
+
+    async fn clear(&self) -> Result<()> {
+        for shard in &self.shards {
+            shard.clear();
+        }
+        Ok(())
+    }
+
+    async fn invalidate(&self, scope: &CacheInvalidation) -> Result<usize> {
+        Ok(self.shards.iter().map(|shard| shard.invalidate(scope)).sum())
+    }
+
+    async fn expire(&self, scope: &CacheInvalidation) -> Result<usize> {
+        Ok(self.shards.iter().map(|shard| shard.expire(scope)).sum())
+    }
+
+    async fn expire_gradually(&self, rate: NonZeroUsize) -> Result<CacheClearReport> {
+        Ok(ShardedMemoryCache::expire_gradually(self, rate))
+    }
+
+    async fn clear_except_hot(&self, top_n: usize) -> Result<CacheClearReport> {
+        Ok(ShardedMemoryCache::clear_except_hot(self, top_n))
+    }
+
+    fn cleanup_expired_batch(&self, max_entries: usize, time_budget: Duration, retain: Duration) -> usize {
+        ShardedMemoryCache::cleanup_expired_batch(self, max_entries, time_budget, retain)
+    }
+
+    fn janitor_batch(&self, config: &CacheJanitorConfig) -> usize {
+        ShardedMemoryCache::janitor_batch(self, config)
+    }
+
+    fn insights(&self) -> Option<CacheInsights> {
+        ShardedMemoryCache::insights(self)
+    }
+
+    fn attach_memory_budget(&self, budget: &Arc<MemoryBudget>) {
+        for shard in &self.shards {
+            shard.attach_memory_budget(budget);
+        }
+    }
+
+    /// 各分片平均分担，每个分片内先淘汰过期的、再淘汰最久未用的条目
+    fn shrink_memory(&self, component: MemoryComponent, wanted: usize) -> usize {
+        let share = wanted.div_ceil(self.shards.len());
+        self.shards.iter().map(|shard| shard.shrink_memory(component, share)).sum()
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.shards.iter().map(DnsCache::stats).fold(CacheStats::default(), |mut total, stats| {
+            total.hits += stats.hits;
+            total.misses += stats.misses;
+            total.inserts += stats.inserts;
+            total.evictions += stats.evictions;
+            total.rejected += stats.rejected;
+            total.uncacheable += stats.uncacheable;
+            total.backend_errors += stats.backend_errors;
+            total.stale_served += stats.stale_served;
+            total.invalidations += stats.invalidations;
+            total.current_size += stats.current_size;
+            total
+        })
+    }
+}
+
+/// 缓存清理后台任务的句柄，释放或 [`stop`](Self::stop) 时中止任务
+#[derive(Debug)]
+pub(crate) struct CacheJanitor {
+    task: JoinHandle<()>,
+}
+
+impl CacheJanitor {
+    /// 中止清理任务，可以重复调用
+    pub(crate) fn stop(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for CacheJanitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// 解析器持有的缓存：在后端之上做可缓存检查，并吸收后端的错误和延迟
+#[derive(Debug, Clone)]
+pub(crate) struct CacheLayer {
+    backend: Arc<dyn DnsCacheBackend>,
+    /// 被可缓存检查拒绝的次数
+    rejected: Arc<AtomicU64>,
+    /// 按缓存策略未写入的次数
+    uncacheable: Arc<AtomicU64>,
+    /// TTL为0的记录是否按1秒缓存
+    cache_zero_ttl: bool,
+    /// 后端读写失败（含读取超时）的次数
+    backend_errors: Arc<AtomicU64>,
+    /// 返回过期答案的次数
+    stale_served: Arc<AtomicU64>,
+}
+
+impl CacheLayer {
+    pub(crate) fn new(backend: Arc<dyn DnsCacheBackend>, cache_zero_ttl: bool) -> Self {
+        Self {
+            backend,
+            rejected: Arc::new(AtomicU64::new(0)),
+            uncacheable: Arc::new(AtomicU64::new(0)),
+            cache_zero_ttl,
+            backend_errors: Arc::new(AtomicU64::new(0)),
+            stale_served: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// 改变TTL为0的记录是否按1秒缓存，后端及其中的条目不变
+    pub(crate) fn with_zero_ttl_caching(mut self, enabled: bool) -> Self {
+        self.cache_zero_ttl = enabled;
+        self
+    }
+
+    /// 把后端计入内存预算，并注册缓存条目和热门名称的收缩回调
+    pub(crate) fn attach_memory_budget(&self, budget: &Arc<MemoryBudget>) {
+        self.backend.attach_memory_budget(budget);
+        for component in [MemoryComponent::HotNames, MemoryComponent::Cache] {
+            let backend = Arc::downgrade(&self.backend);
+            budget.register(component, move |wanted| Some(backend.upgrade()?.shrink_memory(component, wanted)));
+        }
+    }
+
+    /// 读取缓存，后端出错或超时时记录并按未命中处理
+    pub(crate) async fn get(&self, query: &Query, client: Option<&ClientAddress>) -> Option<SharedResponse> {
+        let error = match runtime::timeout(CACHE_BACKEND_GET_TIMEOUT, self.backend.get_shared(query, client)).await {
+            Ok(Ok(response)) => return response,
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("timed out after {:?}", CACHE_BACKEND_GET_TIMEOUT),
+        };
+        self.backend_errors.fetch_add(1, Ordering::Relaxed);
+        dns_warn!("读取缓存后端失败，按未命中处理: {} {:?}: {}", query.name, query.qtype, error);
+        None
+    }
+
+    /// 读取过期不超过 `max_stale` 的条目并计入返回过期答案的次数，后端出错或超时时按没有过期条目处理
+    pub(crate) async fn get_stale(&self, query: &Query, client: Option<&ClientAddress>, max_stale: Duration) -> Option<Response> {
+        let response = self.peek_stale(query, client, max_stale).await;
+        if response.is_some() {
+            self.stale_served.fetch_add(1, Ordering::Relaxed);
+        }
+        response
+    }
+
+    /// 同 [`get_stale`](Self::get_stale)，但读到的条目只用于比对，不计入返回过期答案的次数
+    pub(crate) async fn peek_stale(&self, query: &Query, client: Option<&ClientAddress>, max_stale: Duration) -> Option<Response> {
+        let lookup = self.backend.get_stale(query, client, max_stale);
+        let error = match runtime::timeout(CACHE_BACKEND_GET_TIMEOUT, lookup).await {
+            Ok(Ok(response)) => return response,
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("timed out after {:?}", CACHE_BACKEND_GET_TIMEOUT),
+        };
+        self.backend_errors.fetch_add(1, Ordering::Relaxed);
+        dns_warn!("读取缓存后端的过期条目失败: {} {:?}: {}", query.name, query.qtype, error);
+        None
+    }
+
+    /// 写入缓存，不等待后端完成
+    ///
+    /// 写入先在当前任务中轮询一次，进程内后端因此同步完成；未完成的写入交给后台任务
+    pub(crate) fn insert(&self, query: Query, client: Option<ClientAddress>, response: Response) {
+        let response = match cacheable_response(&query, response, self.cache_zero_ttl) {
+            Ok(response) => response,
+            Err(reason) => {
+                dns_debug!("拒绝缓存 {} {:?} 的响应: {}", query.name, query.qtype, reason);
+                let counter = if reason.is_uncacheable() { &self.uncacheable } else { &self.rejected };
+                counter.fetch_add(1, Ordering::Relaxed);
+                return;
+            },
+        };
+
+        let backend = self.backend.clone();
+        let backend_errors = self.backend_errors.clone();
+        let name = query.name.clone();
+        let mut write = Box::pin(async move {
+            if let Err(e) = backend.insert(query, client, response).await {
+                backend_errors.fetch_add(1, Ordering::Relaxed);
+                dns_warn!("写入缓存后端失败: {}: {}", name, e);
+            }
+        });
+        if (&mut write).now_or_never().is_none() {
+            runtime::spawn(write);
+        }
+    }
+
+    /// 清空缓存
+    pub(crate) async fn clear(&self) -> Result<()> {
+        self.backend.clear().await
+    }
+
+    /// 移除失效范围内的条目，返回移除数
+    pub(crate) async fn invalidate(&self, scope: &CacheInvalidation) -> Result<usize> {
+        self.backend.invalidate(scope).await
+    }
+
+    /// 把失效范围内的条目标记为过期，返回标记数
+    pub(crate) async fn expire(&self, scope: &CacheInvalidation) -> Result<usize> {
+        self.backend.expire(scope).await
+    }
+
+    /// 按速率渐进清空
+    pub(crate) async fn expire_gradually(&self, rate: NonZeroUsize) -> Result<CacheClearReport> {
+        self.backend.expire_gradually(rate).await
+    }
+
+    /// 保留最热门的条目，移除其余条目
+    pub(crate) async fn clear_except_hot(&self, top_n: usize) -> Result<CacheClearReport> {
+        self.backend.clear_except_hot(top_n).await
+    }
+
+    /// 后台清理一次过期条目，返回移除数
+    pub(crate) fn cleanup_expired_batch(&self, config: &CacheJanitorConfig) -> usize {
+        self.backend.janitor_batch(config)
+    }
+
+    /// 后端上一轮完整扫描得到的缓存概况
+    pub(crate) fn insights(&self) -> Option<CacheInsights> {
+        self.backend.insights()
+    }
+
+    /// 启动定期清理过期条目的后台任务；不在异步运行时中时不启动
+    pub(crate) fn spawn_janitor(&self, config: CacheJanitorConfig) -> Option<CacheJanitor> {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            dns_debug!("不在异步运行时中，缓存清理任务未启动");
+            return None;
+        };
+        let cache = self.clone();
+        let task = runtime.spawn(async move {
+            let mut ticker = tokio::time::interval(config.interval);
+            ticker.tick().await; // 第一次tick立即返回，跳过
+            loop {
+                ticker.tick().await;
+                let removed = cache.cleanup_expired_batch(&config);
+                if removed > 0 {
+                    dns_debug!("缓存清理移除了 {} 个过期条目", removed);
+                }
+            }
+        });
+        Some(CacheJanitor { task })
+    }
+
+    /// 后端统计加上本层记录的拒绝、不缓存、后端错误和过期应答次数
+    pub(crate) fn stats(&self) -> CacheStats {
+        let mut stats = self.backend.stats();
+        stats.rejected += self.rejected.load(Ordering::Relaxed);
+        stats.uncacheable += self.uncacheable.load(Ordering::Relaxed);
+        stats.backend_errors += self.backend_errors.load(Ordering::Relaxed);
+        stats.stale_served += self.stale_served.load(Ordering::Relaxed);
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::strategy::QueryStrategy;
+    use crate::dns_response::DnsResponseWrapper;
+    use crate::resolver::{CoreResolver, CoreResolverConfig};
+    use crate::resolver::clock::TestClock;
+    use crate::transport::mock::MockTransport;
+    use crate::types::{QClass, RecordType};
+    use std::net::Ipv4Addr;
+    use crate::time::Instant;
+
+    fn resolver_with(backend: Arc<dyn DnsCacheBackend>, upstream: MockTransport) -> CoreResolver {
+        let mut config = CoreResolverConfig::new(
+            QueryStrategy::Fifo,
+            Duration::from_secs(2),
+            0,
+            true,
+            Duration::from_secs(3600),
+            false,
+            Duration::from_secs(30),
+            53,
+            10,
+            true,
+            4096,
+            false,
+            crate::logger::LevelFilter::Off,
+            false,
+        );
+        config.cache_backend = Some(backend);
+        let mut resolver = CoreResolver::new(config);
+        resolver.add_transport(Arc::new(upstream));
+        resolver
+    }
+
+    /// 每种后端都要通过的解析器行为
+    async fn check_resolver_behavior(backend: Arc<dyn DnsCacheBackend>) {
+        let forged = DnsResponseWrapper::create_a_response(0, "attacker.example", &[Ipv4Addr::new(198, 51, 100, 1)], 300);
+        let upstream = MockTransport::new()
+            .with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+            .with_response("poisoned.example", RecordType::A, forged);
+        let resolver = resolver_with(backend.clone(), upstream.clone());
+
+        // 第二次查询命中缓存
+        let (_, info) = resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert!(info.is_some());
+        let (cached, info) = resolver.query_with_info("EXAMPLE.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert!(info.is_none());
+        assert_eq!(cached.answers.len(), 1);
+        assert_eq!(upstream.call_count(), 1);
+
+        // 问题不一致的响应照常返回但不写入后端
+        resolver.query("poisoned.example", RecordType::A, QClass::IN).await.unwrap();
+        resolver.query("poisoned.example", RecordType::A, QClass::IN).await.unwrap();
+        assert_eq!(upstream.call_count(), 3);
+
+        let stats = resolver.cache_stats().unwrap();
+        assert_eq!(stats.rejected, 2);
+        assert_eq!(stats.current_size, 1);
+        assert_eq!(stats.backend_errors, 0);
+
+        // 清空后重新向上游查询
+        resolver.clear_cache().await.unwrap();
+        assert_eq!(backend.stats().current_size, 0);
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        assert_eq!(upstream.call_count(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend_behavior() {
+        check_resolver_behavior(Arc::new(DnsCache::new(Duration::from_secs(3600)))).await;
+    }
+
+    #[tokio::test]
+    async fn test_sharded_backend_behavior() {
+        check_resolver_behavior(Arc::new(ShardedMemoryCache::new(8, Duration::from_secs(3600)).unwrap())).await;
+    }
+
+    #[test]
+    fn test_sharded_cache_requires_shards() {
+        assert!(ShardedMemoryCache::new(0, Duration::from_secs(60)).is_err());
+    }
+
+    /// 直接向后端写入 `count` 个TTL为 `ttl` 的条目
+    async fn fill(backend: &dyn DnsCacheBackend, count: usize, ttl: u32) {
+        for i in 0..count {
+            let name = format!("host{}.janitor.test", i);
+            let response = DnsResponseWrapper::create_a_response(0, &name, &[Ipv4Addr::new(192, 0, 2, 1)], ttl);
+            let query = Query { name, qtype: RecordType::A, qclass: QClass::IN };
+            backend.insert(query, None, response).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sharded_cache_janitor_walks_every_shard() {
+        let clock = Arc::new(TestClock::new());
+        let cache = ShardedMemoryCache::with_clock(8, Duration::from_secs(3600), clock.clone()).unwrap();
+        fill(&cache, 2000, 5).await;
+        clock.advance(Duration::from_secs(6));
+
+        let mut ticks = 0;
+        while cache.stats().current_size > 0 {
+            cache.cleanup_expired_batch(300, Duration::from_secs(10), Duration::ZERO);
+            ticks += 1;
+            // 每个分片扫到表尾时最多浪费一次预算
+            assert!(ticks <= 2000 / 300 + 8 + 1, "janitor did not finish in {} ticks", ticks);
+        }
+        assert_eq!(cache.stats().evictions, 2000);
+    }
+
+    #[tokio::test]
+    async fn test_resolver_janitor_cleans_cache_in_background() {
+        let clock = Arc::new(TestClock::new());
+        let backend = Arc::new(DnsCache::with_clock(Duration::from_secs(3600), clock.clone()));
+        let janitor = CacheJanitorConfig {
+            interval: Duration::from_millis(10),
+            max_entries: 1000,
+            time_budget: Duration::from_secs(1),
+            retain_expired: Duration::ZERO,
+            track_hot_names: None,
+        };
+        let mut config = CoreResolverConfig::new(
+            QueryStrategy::Fifo,
+            Duration::from_secs(2),
+            0,
+            true,
+            Duration::from_secs(3600),
+            false,
+            Duration::from_secs(30),
+            53,
+            10,
+            true,
+            4096,
+            false,
+            crate::logger::LevelFilter::Off,
+            false,
+        );
+        config.cache_backend = Some(backend.clone());
+        config.cache_janitor = Some(janitor);
+        let resolver = CoreResolver::with_clock(config.clone(), clock.clone());
+        fill(backend.as_ref(), 3000, 5).await;
+        clock.advance(Duration::from_secs(6));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while backend.size() > 0 {
+            assert!(Instant::now() < deadline, "janitor left {} entries", backend.size());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(resolver.cache_stats().unwrap().evictions, 3000);
+        // 清空后的下一轮扫描发布空的概况
+        while resolver.cache_insights().entries > 0 || resolver.cache_insights().computed_at.is_none() {
+            assert!(Instant::now() < deadline, "janitor did not publish insights");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // 停止后不再清理
+        resolver.stop_cache_janitor();
+        fill(backend.as_ref(), 10, 5).await;
+        clock.advance(Duration::from_secs(6));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(backend.size(), 10);
+
+        // 未启用缓存时不启动清理任务
+        config.enable_cache = false;
+        let resolver = CoreResolver::with_clock(config, clock);
+        assert!(resolver.cache_janitor.is_none());
+        assert_eq!(resolver.cache_insights(), CacheInsights::default());
+    }
+
+    /// 读取总是失败、写入永不完成的后端
+    #[derive(Debug, Default)]
+    struct BrokenBackend {
+        inserts: AtomicU64,
+    }
+
+    #[async_trait]
+    impl DnsCacheBackend for BrokenBackend {
+        async fn get(&self, _query: &Query, _client: Option<&ClientAddress>) -> Result<Option<Response>> {
+            Err(DnsError::network("redis", "connection refused"))
+        }
+
+        async fn insert(&self, _query: Query, _client: Option<ClientAddress>, _response: Response) -> Result<()> {
+            self.inserts.fetch_add(1, Ordering::Relaxed);
+            futures::future::pending().await
+        }
+
+        async fn remove(&self, _query: &Query, _client: Option<&ClientAddress>) -> Result<bool> {
+            Ok(false)
+        }
+
+        async fn clear(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn stats(&self) -> CacheStats {
+            CacheStats::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broken_backend_does_not_block_queries() {
+        let backend = Arc::new(BrokenBackend::default());
+        let upstream = MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+        let resolver = resolver_with(backend.clone(), upstream.clone());
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            let response = resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+            assert_eq!(response.answers.len(), 1);
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(upstream.call_count(), 3);
+        assert_eq!(backend.inserts.load(Ordering::Relaxed), 3);
+        assert_eq!(resolver.cache_stats().unwrap().backend_errors, 3);
+    }
+}
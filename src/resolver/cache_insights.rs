@@ -0,0 +1,254 @@
+//! 缓存内容概况
+//!
+//! 容量规划需要知道缓存里实际放着什么。后台清理任务分段扫描缓存时顺带统计每个条目，
+//! 扫完一整轮后把结果发布为 [`CacheInsights`] 快照；查询路径只多一次原子计数（条目命中次数），
+//! 读取概况时直接返回上一轮的快照，不会为此加锁扫描缓存。
+
+use crate::types::{Record, RecordData, RecordType, Response};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::mem::size_of;
+use std::time::Duration;
+use crate::time::SystemTime;
+
+/// 剩余TTL分布各桶的上界（秒，含），最后另有一个不设上界的桶
+pub const TTL_BUCKET_BOUNDS_SECS: [u64; 5] = [10, 60, 300, 3600, 86400];
+
+/// 剩余TTL分布中的一个桶
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TtlBucket {
+    /// 剩余TTL上界（秒，含），None为不设上界
+    pub le_secs: Option<u64>,
+    /// 剩余TTL落在上一个桶上界与本桶上界之间的条目数
+    pub count: usize,
+}
+
+/// 命中次数靠前的缓存条目
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HotName {
+    /// 查询名称（规范化后）
+    pub name: String,
+    /// 记录类型
+    pub record_type: String,
+    /// 条目写入以来的命中次数，条目被刷新后重新计数
+    pub hits: u64,
+}
+
+/// 后台清理任务最近一轮完整扫描得到的缓存概况
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheInsights {
+    /// 快照完成的时间，None表示还没有完成过一轮扫描（未启用缓存、清理任务或后端不支持时也是None）
+    pub computed_at: Option<SystemTime>,
+    /// 未过期的条目数
+    pub entries: usize,
+    /// 已过期、但在保留期内尚未移除的条目数
+    pub expired_entries: usize,
+    /// 各记录类型（按查询类型）的未过期条目数
+    pub entries_by_type: BTreeMap<String, usize>,
+    /// 未过期条目的剩余TTL分布，桶的上界见 [`TTL_BUCKET_BOUNDS_SECS`]
+    pub ttl_histogram: Vec<TtlBucket>,
+    /// 最早写入的未过期条目已存在的时长（秒），没有未过期条目时为None
+    pub oldest_entry_age_secs: Option<u64>,
+    /// 全部条目（含过期未移除的）估算占用的内存（字节），按结构大小和字符串长度估算
+    pub approx_memory_bytes: usize,
+    /// 命中次数最多的条目，按命中次数从多到少；未开启 [`track_hot_names`](super::cache::CacheJanitorConfig::track_hot_names) 时为空
+    pub hot_names: Vec<HotName>,
+}
+
+impl CacheInsights {
+    /// 转为JSON对象
+    pub fn to_json_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    /// 序列化为单行JSON
+    pub fn to_json(&self) -> String {
+        self.to_json_value().to_string()
+    }
+
+    /// 热门名称列表估算占用的内存
+    pub(super) fn hot_names_bytes(&self) -> usize {
+        self.hot_names.iter()
+            .map(|hot| size_of::<HotName>() + hot.name.len() + hot.record_type.len())
+            .sum()
+    }
+
+    /// 合并另一部分缓存（如另一个分片）的快照，完成时间取较早者
+    pub(super) fn merge(mut self, other: CacheInsights) -> CacheInsights {
+        self.computed_at = match (self.computed_at, other.computed_at) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.entries += other.entries;
+        self.expired_entries += other.expired_entries;
+        for (record_type, count) in other.entries_by_type {
+            *self.entries_by_type.entry(record_type).or_default() += count;
+        }
+        for (bucket, other) in self.ttl_histogram.iter_mut().zip(other.ttl_histogram) {
+            bucket.count += other.count;
+        }
+        self.oldest_entry_age_secs = self.oldest_entry_age_secs.max(other.oldest_entry_age_secs);
+        self.approx_memory_bytes += other.approx_memory_bytes;
+        // 各部分的列表都不超过上限，合并后保留同样多条
+        let limit = self.hot_names.len().max(other.hot_names.len());
+        self.hot_names.extend(other.hot_names);
+        self.hot_names.sort_by(|a, b| b.hits.cmp(&a.hits).then_with(|| a.name.cmp(&b.name)));
+        self.hot_names.truncate(limit);
+        self
+    }
+}
+
+/// 扫描到的一个缓存条目
+pub(super) struct ObservedEntry<'a> {
+    pub name: &'a str,
+    pub qtype: u16,
+    /// 剩余TTL，已过期时为None
+    pub remaining: Option<Duration>,
+    /// 写入以来的时长
+    pub age: Duration,
+    pub approx_bytes: usize,
+    pub hits: u64,
+}
+
+/// 一轮扫描中逐步累积的统计
+#[derive(Debug, Default)]
+pub(super) struct InsightScan {
+    entries: usize,
+    expired_entries: usize,
+    entries_by_type: BTreeMap<String, usize>,
+    ttl_counts: [usize; TTL_BUCKET_BOUNDS_SECS.len() + 1],
+    oldest_age: Option<Duration>,
+    approx_memory_bytes: usize,
+    /// 命中次数最多的条目，小顶堆，不超过上限
+    hot: BinaryHeap<Reverse<(u64, String, String)>>,
+}
+
+impl InsightScan {
+    /// 统计一个条目；`hot_limit` 为None时不记录热门条目
+    pub(super) fn observe(&mut self, entry: ObservedEntry<'_>, hot_limit: Option<usize>) {
+        self.approx_memory_bytes += entry.approx_bytes;
+        let Some(remaining) = entry.remaining else {
+            self.expired_entries += 1;
+            return;
+        };
+        self.entries += 1;
+        let record_type = format!("{:?}", RecordType::from(entry.qtype));
+        let bucket = TTL_BUCKET_BOUNDS_SECS.iter()
+            .position(|&bound| remaining.as_secs() <= bound)
+            .unwrap_or(TTL_BUCKET_BOUNDS_SECS.len());
+        self.ttl_counts[bucket] += 1;
+        self.oldest_age = self.oldest_age.max(Some(entry.age));
+
+        if let Some(limit) = hot_limit.filter(|&limit| limit > 0 && entry.hits > 0) {
+            let beats_coldest = self.hot.len() < limit
+                || self.hot.peek().is_some_and(|Reverse((hits, _, _))| entry.hits > *hits);
+            if beats_coldest {
+                self.hot.push(Reverse((entry.hits, entry.name.to_string(), record_type.clone())));
+                if self.hot.len() > limit {
+                    self.hot.pop();
+                }
+            }
+        }
+        *self.entries_by_type.entry(record_type).or_default() += 1;
+    }
+
+    /// 结束本轮扫描，返回快照并清空统计供下一轮使用
+    pub(super) fn finish(&mut self, computed_at: SystemTime) -> CacheInsights {
+        let scan = std::mem::take(self);
+        let ttl_histogram = scan.ttl_counts.iter()
+            .enumerate()
+            .map(|(index, &count)| TtlBucket { le_secs: TTL_BUCKET_BOUNDS_SECS.get(index).copied(), count })
+            .collect();
+        let mut hot_names: Vec<HotName> = scan.hot.into_iter()
+            .map(|Reverse((hits, name, record_type))| HotName { name, record_type, hits })
+            .collect();
+        hot_names.sort_by(|a, b| b.hits.cmp(&a.hits).then_with(|| a.name.cmp(&b.name)));
+        CacheInsights {
+            computed_at: Some(computed_at),
+            entries: scan.entries,
+            expired_entries: scan.expired_entries,
+            entries_by_type: scan.entries_by_type,
+            ttl_histogram,
+            oldest_entry_age_secs: scan.oldest_age.map(|age| age.as_secs()),
+            approx_memory_bytes: scan.approx_memory_bytes,
+            hot_names,
+        }
+    }
+}
+
+/// 估算响应占用的内存：各段记录的结构大小加上名称和记录数据中字符串、字节串的长度
+pub(super) fn approx_response_bytes(response: &Response) -> usize {
+    let record_bytes = |record: &Record| {
+        let data = match &record.data {
+            RecordData::A(_) | RecordData::AAAA(_) => 0,
+            RecordData::CNAME(name) | RecordData::NS(name) | RecordData::PTR(name) => name.len(),
+            RecordData::MX { exchange, .. } => exchange.len(),
+            RecordData::SOA { mname, rname, .. } => mname.len() + rname.len(),
+            RecordData::TXT(texts) => texts.iter().map(|text| size_of::<String>() + text.len()).sum(),
+            RecordData::SRV { target, .. } => target.len(),
+            RecordData::Unknown(bytes) => bytes.len(),
+        };
+        size_of::<Record>() + record.name.len() + data
+    };
+    size_of::<Response>()
+        + response.queries.iter().map(|query| size_of_val(query) + query.name.len()).sum::<usize>()
+        + response.answers.iter()
+            .chain(&response.authorities)
+            .chain(&response.additionals)
+            .map(record_bytes)
+            .sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observed(name: &str, remaining: Option<u64>, hits: u64) -> ObservedEntry<'_> {
+        ObservedEntry {
+            name,
+            qtype: 1,
+            remaining: remaining.map(Duration::from_secs),
+            age: Duration::from_secs(1),
+            approx_bytes: 100,
+            hits,
+        }
+    }
+
+    #[test]
+    fn test_hot_names_stay_within_limit() {
+        let mut scan = InsightScan::default();
+        for i in 0..1000u64 {
+            scan.observe(observed(&format!("host{}.example", i), Some(60), i), Some(3));
+            assert!(scan.hot.len() <= 3);
+        }
+        let insights = scan.finish(SystemTime::UNIX_EPOCH);
+        let hot: Vec<(&str, u64)> = insights.hot_names.iter().map(|hot| (hot.name.as_str(), hot.hits)).collect();
+        assert_eq!(hot, vec![("host999.example", 999), ("host998.example", 998), ("host997.example", 997)]);
+        assert_eq!(insights.entries, 1000);
+
+        // 统计完成后重新开始
+        assert_eq!(scan.finish(SystemTime::UNIX_EPOCH).entries, 0);
+    }
+
+    #[test]
+    fn test_merge_adds_counts_and_keeps_hottest() {
+        let shard = |names: &[(&str, u64)]| {
+            let mut scan = InsightScan::default();
+            for (name, hits) in names {
+                scan.observe(observed(name, Some(30), *hits), Some(2));
+            }
+            scan.observe(observed("stale.example", None, 0), Some(2));
+            scan.finish(SystemTime::UNIX_EPOCH)
+        };
+        let merged = shard(&[("a.example", 5), ("b.example", 1)]).merge(shard(&[("c.example", 9), ("d.example", 3)]));
+
+        assert_eq!(merged.entries, 4);
+        assert_eq!(merged.expired_entries, 2);
+        assert_eq!(merged.entries_by_type.get("A"), Some(&4));
+        assert_eq!(merged.ttl_histogram[1], TtlBucket { le_secs: Some(60), count: 4 });
+        assert_eq!(merged.approx_memory_bytes, 600);
+        let hot: Vec<&str> = merged.hot_names.iter().map(|hot| hot.name.as_str()).collect();
+        assert_eq!(hot, vec!["c.example", "a.example"]);
+    }
+}
@@ -0,0 +1,175 @@
+//! 时间源抽象
+//!
+//! 缓存过期、上游状态恢复计时、重试退避、传输超时和耗时统计都通过 [`Clock`] 取时间和等待，
+//! 默认使用真实时钟 [`RealClock`]；测试中注入 [`TestClock`] 手动推进时间，
+//! 不需要真实的 sleep 也能覆盖过期、超时和恢复逻辑。
+
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::time::{Instant, SystemTime};
+use tokio::sync::watch;
+
+/// 时间源
+#[async_trait]
+pub trait Clock: Send + Sync + Debug {
+    /// 当前单调时间
+    fn now_instant(&self) -> Instant;
+
+    /// 当前系统时间
+    fn now_system(&self) -> SystemTime;
+
+    /// 等待指定时长
+    async fn sleep(&self, duration: Duration);
+
+    /// 等待到 `deadline`，已经过了 `deadline` 时立即返回
+    async fn sleep_until(&self, deadline: Instant);
+
+    /// 从 `earlier` 到现在经过的系统时间，时钟回拨时为0
+    fn system_elapsed(&self, earlier: SystemTime) -> Duration {
+        self.now_system().duration_since(earlier).unwrap_or(Duration::ZERO)
+    }
+}
+
+/// 真实时钟
+///
+/// 等待使用tokio的定时器；wasm32上没有tokio运行时，改用JS的 `setTimeout`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealClock;
+
+#[async_trait]
+impl Clock for RealClock {
+    fn now_instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_system(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        crate::runtime::sleep(duration).await;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn sleep_until(&self, deadline: Instant) {
+        tokio::time::sleep_until(deadline.into()).await;
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    async fn sleep_until(&self, deadline: Instant) {
+        crate::runtime::sleep(deadline.saturating_duration_since(Instant::now())).await;
+    }
+}
+
+/// 默认时间源
+pub fn real_clock() -> Arc<dyn Clock> {
+    Arc::new(RealClock)
+}
+
+/// 在 `clock` 的 `deadline` 之前完成 `future`，超过时放弃并返回 `None`
+pub async fn timeout_at<F: Future>(clock: &dyn Clock, deadline: Instant, future: F) -> Option<F::Output> {
+    tokio::select! {
+        biased;
+        output = future => Some(output),
+        _ = clock.sleep_until(deadline) => None,
+    }
+}
+
+/// 手动推进的测试时钟
+///
+/// 创建时记录一个起点，之后只有调用 [`TestClock::advance`] 或 `sleep` 时间才会前进；
+/// `sleep` 立即返回并把时钟推进相应时长，重试退避因此不会拖慢测试。
+/// `sleep_until`（传输超时）则一直等到其他调用把时钟推进到截止时间，测试可以决定超时何时发生
+#[derive(Debug)]
+pub struct TestClock {
+    base_instant: Instant,
+    base_system: SystemTime,
+    offset: watch::Sender<Duration>,
+}
+
+impl TestClock {
+    /// 以当前真实时间为起点创建测试时钟
+    pub fn new() -> Self {
+        Self {
+            base_instant: Instant::now(),
+            base_system: SystemTime::now(),
+            offset: watch::Sender::new(Duration::ZERO),
+        }
+    }
+
+    /// 推进时钟，唤醒截止时间已到的 `sleep_until`
+    pub fn advance(&self, duration: Duration) {
+        self.offset.send_modify(|offset| *offset += duration);
+    }
+
+    /// 自创建以来推进的总时长
+    pub fn elapsed(&self) -> Duration {
+        *self.offset.borrow()
+    }
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Clock for TestClock {
+    fn now_instant(&self) -> Instant {
+        self.base_instant + self.elapsed()
+    }
+
+    fn now_system(&self) -> SystemTime {
+        self.base_system + self.elapsed()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        let mut offset = self.offset.subscribe();
+        // 发送端就是时钟本身，存活期间不会关闭
+        let _ = offset.wait_for(|offset| self.base_instant + *offset >= deadline).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_clock_only_moves_when_advanced() {
+        let clock = TestClock::new();
+        let start = clock.now_instant();
+        let start_system = clock.now_system();
+
+        clock.advance(Duration::from_secs(30));
+        clock.sleep(Duration::from_secs(5)).await;
+
+        assert_eq!(clock.now_instant() - start, Duration::from_secs(35));
+        assert_eq!(clock.system_elapsed(start_system), Duration::from_secs(35));
+    }
+
+    #[tokio::test]
+    async fn test_timeout_fires_only_when_clock_passes_deadline() {
+        let clock = Arc::new(TestClock::new());
+        let deadline = clock.now_instant() + Duration::from_secs(5);
+        let pending = tokio::spawn({
+            let clock = clock.clone();
+            async move { timeout_at(&*clock, deadline, std::future::pending::<()>()).await }
+        });
+
+        clock.advance(Duration::from_secs(4));
+        tokio::task::yield_now().await;
+        assert!(!pending.is_finished());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(pending.await.unwrap(), None);
+        assert_eq!(timeout_at(&*clock, deadline, async { 7 }).await, Some(7));
+    }
+}
@@ -0,0 +1,483 @@
+//! 查询失败的汇总诊断
+//!
+//! 一次查询可能先后或同时发往多个上游，全部失败时调用方只看到最后一个错误（或笼统的 "All transports failed"），
+//! 无从判断是上游的问题还是本地网络的问题。查询期间每个上游发送的结果（响应码或错误类别、协议、对端地址、耗时）
+//! 记入当前查询的尝试记录，查询失败时整理为 [`QueryFailureReport`]，并按 [`HintRule`] 表给出可能原因的提示，
+//! 例如所有明文上游都返回REFUSED时提示53端口可能被拦截。
+//!
+//! 尝试记录按tokio任务保存：查询策略在新任务中并发发送时，须用 [`propagate`] 把记录带进新任务。
+
+use super::TransportInfo;
+use crate::error::{DnsError, NetworkErrorKind, Result};
+use crate::types::{Response, ResponseCode};
+#[cfg(feature = "serde-api")]
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 单个上游发送的结果类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+#[serde(rename_all = "snake_case")]
+pub enum AttemptOutcome {
+    /// 上游给出了应答，响应码见 [`UpstreamAttempt::rcode`]
+    Answered,
+    /// 超时
+    Timeout,
+    /// 连接被拒绝或网络、主机不可达
+    Unreachable,
+    /// TLS握手或证书错误
+    Tls,
+    /// DoH的HTTP层错误（状态码、Content-Type、正文过大）
+    Http,
+    /// 响应无法解析或不符合协议
+    Protocol,
+    /// 没有发送：上游处于隔离期、在途查询已满或并发许可等待超时
+    Skipped,
+    /// 其他错误
+    Other,
+}
+
+impl AttemptOutcome {
+    /// 按发送返回的错误分类
+    pub fn from_error(error: &DnsError) -> Self {
+        match error {
+            DnsError::Timeout { .. } => AttemptOutcome::Timeout,
+            DnsError::Network { kind: NetworkErrorKind::TimedOut, .. } => AttemptOutcome::Timeout,
+            DnsError::Network { kind: NetworkErrorKind::Other | NetworkErrorKind::BrokenPipe, .. } => AttemptOutcome::Other,
+            DnsError::Network { .. } | DnsError::ConnectFailed { .. } => AttemptOutcome::Unreachable,
+            DnsError::Tls(_) | DnsError::TlsFailure { .. } => AttemptOutcome::Tls,
+            DnsError::Http(_)
+            | DnsError::HttpStatus { .. }
+            | DnsError::UnexpectedContentType { .. }
+            | DnsError::ResponseTooLarge { .. } => AttemptOutcome::Http,
+            DnsError::Protocol(_) | DnsError::Parse(_) | DnsError::FormatError => AttemptOutcome::Protocol,
+            DnsError::Quarantined { .. } | DnsError::UpstreamSaturated { .. } | DnsError::Busy { .. } => AttemptOutcome::Skipped,
+            _ => AttemptOutcome::Other,
+        }
+    }
+
+    /// 小写名称，与序列化的值相同
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AttemptOutcome::Answered => "answered",
+            AttemptOutcome::Timeout => "timeout",
+            AttemptOutcome::Unreachable => "unreachable",
+            AttemptOutcome::Tls => "tls",
+            AttemptOutcome::Http => "http",
+            AttemptOutcome::Protocol => "protocol",
+            AttemptOutcome::Skipped => "skipped",
+            AttemptOutcome::Other => "other",
+        }
+    }
+}
+
+/// 一次查询中单个上游发送的结果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub struct UpstreamAttempt {
+    /// 上游名称
+    pub upstream: String,
+    /// 传输协议（UDP/TCP/TLS/HTTPS）
+    pub protocol: String,
+    /// 实际交换报文的对端地址，没有应答或传输取不到时为 `None`
+    #[serde(default)]
+    pub peer: Option<SocketAddr>,
+    /// 发送耗时（毫秒）
+    pub latency_ms: u64,
+    /// 结果类别
+    pub outcome: AttemptOutcome,
+    /// 应答的响应码，没有应答时为 `None`
+    #[serde(default)]
+    pub rcode: Option<u16>,
+    /// 错误信息，有应答时为 `None`
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl UpstreamAttempt {
+    /// 应答的响应码
+    pub fn response_code(&self) -> Option<ResponseCode> {
+        self.rcode.map(ResponseCode::from)
+    }
+
+    /// 是否是明文DNS（UDP/TCP，53端口）上游
+    pub fn is_plaintext(&self) -> bool {
+        matches!(self.protocol.as_str(), "UDP" | "TCP")
+    }
+
+    /// 是否给出了可用的应答（不是SERVFAIL/REFUSED）
+    pub fn answered_usably(&self) -> bool {
+        self.outcome == AttemptOutcome::Answered
+            && !matches!(self.response_code(), Some(ResponseCode::ServerFailure | ResponseCode::Refused))
+    }
+
+    /// 上游所属的服务商：名称中第一个 `-`、`_` 或 `.` 之前的部分（小写），
+    /// 例如 `cloudflare-udp` 和 `cloudflare_doh` 同属 `cloudflare`
+    pub fn provider(&self) -> String {
+        let name = self.upstream.split(['-', '_', '.']).next().unwrap_or_default();
+        name.to_ascii_lowercase()
+    }
+}
+
+impl fmt::Display for UpstreamAttempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.upstream, self.protocol)?;
+        if let Some(peer) = self.peer {
+            write!(f, " {}", peer)?;
+        }
+        match (self.response_code(), &self.error) {
+            (Some(rcode), _) => write!(f, ", {}", rcode.name())?,
+            (None, Some(error)) => write!(f, ", {}: {}", self.outcome.as_str(), error)?,
+            (None, None) => write!(f, ", {}", self.outcome.as_str())?,
+        }
+        write!(f, ", {}ms)", self.latency_ms)
+    }
+}
+
+/// 按尝试记录推断出的可能原因
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub struct FailureHint {
+    /// 规则代码，见 [`HintRule::code`]
+    pub code: String,
+    /// 面向用户的说明
+    pub message: String,
+}
+
+/// 提示规则：`detect` 对全部尝试记录返回说明时给出提示
+#[derive(Debug, Clone, Copy)]
+pub struct HintRule {
+    /// 稳定的规则代码，供程序按代码处理
+    pub code: &'static str,
+    /// 检查尝试记录，符合该模式时返回面向用户的说明
+    pub detect: fn(&[UpstreamAttempt]) -> Option<String>,
+}
+
+/// 内置的提示规则
+///
+/// - `port53_interception`：所有明文上游都返回REFUSED，加密上游没有返回REFUSED
+/// - `no_connectivity`：所有上游都超时或不可达
+/// - `nxdomain_filtering`：只有同一服务商的上游返回NXDOMAIN，其他服务商的上游没有
+pub const DEFAULT_HINT_RULES: &[HintRule] = &[
+    HintRule { code: "port53_interception", detect: detect_port53_interception },
+    HintRule { code: "no_connectivity", detect: detect_no_connectivity },
+    HintRule { code: "nxdomain_filtering", detect: detect_nxdomain_filtering },
+];
+
+fn detect_port53_interception(attempts: &[UpstreamAttempt]) -> Option<String> {
+    let refused = |attempt: &&UpstreamAttempt| attempt.response_code() == Some(ResponseCode::Refused);
+    let plaintext: Vec<_> = attempts.iter().filter(|attempt| attempt.is_plaintext()).collect();
+    let encrypted_refused = attempts.iter().filter(|attempt| !attempt.is_plaintext()).any(|attempt| refused(&attempt));
+    if plaintext.is_empty() || !plaintext.iter().all(refused) || encrypted_refused {
+        return None;
+    }
+    Some(format!(
+        "All {} plain DNS upstreams refused the query; a firewall or the network may be intercepting port 53, \
+         try DNS-over-TLS or DNS-over-HTTPS upstreams",
+        plaintext.len()
+    ))
+}
+
+fn detect_no_connectivity(attempts: &[UpstreamAttempt]) -> Option<String> {
+    let offline = attempts.iter().all(|attempt| matches!(attempt.outcome, AttemptOutcome::Timeout | AttemptOutcome::Unreachable));
+    if attempts.is_empty() || !offline {
+        return None;
+    }
+    Some(format!(
+        "None of the {} upstreams could be reached (timeouts or unreachable); check the local network connection",
+        attempts.len()
+    ))
+}
+
+fn detect_nxdomain_filtering(attempts: &[UpstreamAttempt]) -> Option<String> {
+    let nxdomain = |attempt: &UpstreamAttempt| attempt.response_code() == Some(ResponseCode::NxDomain);
+    let mut filtering = attempts.iter().filter(|attempt| nxdomain(attempt)).map(UpstreamAttempt::provider);
+    let provider = filtering.next()?;
+    if filtering.any(|other| other != provider) {
+        return None;
+    }
+    let others: Vec<_> = attempts.iter().filter(|attempt| attempt.provider() != provider).collect();
+    if others.is_empty() || others.iter().any(|attempt| nxdomain(attempt)) {
+        return None;
+    }
+    Some(format!(
+        "Only upstreams of '{}' answered NXDOMAIN while other providers did not; '{}' may be filtering this domain",
+        provider, provider
+    ))
+}
+
+/// 按规则表检查尝试记录，依次给出符合的提示
+pub fn diagnose_with(rules: &[HintRule], attempts: &[UpstreamAttempt]) -> Vec<FailureHint> {
+    rules.iter()
+        .filter_map(|rule| (rule.detect)(attempts).map(|message| FailureHint { code: rule.code.to_string(), message }))
+        .collect()
+}
+
+/// 查询失败的汇总诊断：各上游的结果和可能原因的提示
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub struct QueryFailureReport {
+    /// 查询的域名
+    pub name: String,
+    /// 查询的记录类型
+    pub record_type: String,
+    /// 按完成顺序排列的各上游发送结果（故障转移的发送也在其中）
+    pub attempts: Vec<UpstreamAttempt>,
+    /// 按 [`DEFAULT_HINT_RULES`] 得出的提示，没有符合的规则时为空
+    pub hints: Vec<FailureHint>,
+}
+
+impl QueryFailureReport {
+    /// 按内置规则表整理尝试记录
+    pub fn new(name: impl Into<String>, record_type: impl Into<String>, attempts: Vec<UpstreamAttempt>) -> Self {
+        let hints = diagnose_with(DEFAULT_HINT_RULES, &attempts);
+        Self { name: name.into(), record_type: record_type.into(), attempts, hints }
+    }
+
+    /// 按另一张规则表重新给出提示，例如在内置规则之外加入自定义规则
+    pub fn with_rules(mut self, rules: &[HintRule]) -> Self {
+        self.hints = diagnose_with(rules, &self.attempts);
+        self
+    }
+
+    /// 尝试过的不同上游数
+    pub fn upstream_count(&self) -> usize {
+        let mut upstreams: Vec<_> = self.attempts.iter().map(|attempt| attempt.upstream.as_str()).collect();
+        upstreams.sort_unstable();
+        upstreams.dedup();
+        upstreams.len()
+    }
+
+    /// 转为JSON字符串
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self)
+            .map_err(|e| DnsError::Parse(format!("Failed to serialize failure report: {}", e)))
+    }
+}
+
+impl fmt::Display for QueryFailureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "All {} upstream attempts failed for {} {}", self.attempts.len(), self.name, self.record_type)?;
+        for (i, attempt) in self.attempts.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { ": " } else { "; " }, attempt)?;
+        }
+        for hint in &self.hints {
+            write!(f, ". Hint: {}", hint.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// 整理一次查询的尝试记录：查询出错或最终应答为SERVFAIL/REFUSED（`final_rcode`）时总是给出报告，
+/// 最终应答为NXDOMAIN时只在有提示时给出；没有发往上游（命中缓存等）时为 `None`
+pub(crate) fn failure_report(
+    name: &str,
+    record_type: &str,
+    final_rcode: Option<ResponseCode>,
+    failed: bool,
+    attempts: Vec<UpstreamAttempt>,
+) -> Option<QueryFailureReport> {
+    if attempts.is_empty() {
+        return None;
+    }
+    let report = QueryFailureReport::new(name, record_type, attempts);
+    match final_rcode {
+        _ if failed => Some(report),
+        Some(ResponseCode::ServerFailure | ResponseCode::Refused) => Some(report),
+        Some(ResponseCode::NxDomain) if !report.hints.is_empty() => Some(report),
+        _ => None,
+    }
+}
+
+/// 一次查询的尝试记录，查询的各个任务共用
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AttemptLog(Arc<Mutex<Vec<UpstreamAttempt>>>);
+
+impl AttemptLog {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<UpstreamAttempt>> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 取出全部记录
+    pub(crate) fn take(&self) -> Vec<UpstreamAttempt> {
+        std::mem::take(&mut *self.lock())
+    }
+}
+
+tokio::task_local! {
+    static CURRENT: AttemptLog;
+}
+
+/// 在 `log` 下执行 `future`：其中的上游发送都记入 `log`
+pub(crate) async fn scope<F: Future>(log: AttemptLog, future: F) -> F::Output {
+    CURRENT.scope(log, future).await
+}
+
+/// 把当前任务的尝试记录（以及查询摘要的状态）带进 `future`，用于在新任务中发送的查询策略
+pub(crate) fn propagate<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let log = CURRENT.try_with(AttemptLog::clone).ok();
+    let future = crate::logger::propagate_summary(future);
+    async move {
+        match log {
+            Some(log) => CURRENT.scope(log, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// 把一次上游发送的结果记入当前任务的尝试记录，不在 [`scope`] 中时忽略
+pub(super) fn record(upstream: &str, protocol: &str, latency: Duration, result: &Result<(Response, TransportInfo)>) {
+    let _ = CURRENT.try_with(|log| {
+        let (outcome, peer, rcode, error) = match result {
+            Ok((response, info)) => (AttemptOutcome::Answered, info.peer, Some(u16::from(response.rcode())), None),
+            Err(e) => (AttemptOutcome::from_error(e), None, None, Some(e.to_string())),
+        };
+        log.lock().push(UpstreamAttempt {
+            upstream: upstream.to_string(),
+            protocol: protocol.to_string(),
+            peer,
+            latency_ms: latency.as_millis() as u64,
+            outcome,
+            rcode,
+            error,
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answered(upstream: &str, protocol: &str, rcode: ResponseCode) -> UpstreamAttempt {
+        UpstreamAttempt {
+            upstream: upstream.to_string(),
+            protocol: protocol.to_string(),
+            peer: None,
+            latency_ms: 12,
+            outcome: AttemptOutcome::Answered,
+            rcode: Some(rcode.into()),
+            error: None,
+        }
+    }
+
+    fn failed(upstream: &str, protocol: &str, error: DnsError) -> UpstreamAttempt {
+        UpstreamAttempt {
+            upstream: upstream.to_string(),
+            protocol: protocol.to_string(),
+            peer: None,
+            latency_ms: 5000,
+            outcome: AttemptOutcome::from_error(&error),
+            rcode: None,
+            error: Some(error.to_string()),
+        }
+    }
+
+    fn codes(attempts: &[UpstreamAttempt]) -> Vec<String> {
+        diagnose_with(DEFAULT_HINT_RULES, attempts).into_iter().map(|hint| hint.code).collect()
+    }
+
+    #[test]
+    fn test_plaintext_refusals_suggest_port53_interception() {
+        let attempts = [
+            answered("google-udp", "UDP", ResponseCode::Refused),
+            answered("cloudflare-tcp", "TCP", ResponseCode::Refused),
+        ];
+        assert_eq!(codes(&attempts), ["port53_interception"]);
+
+        // 加密上游同样拒绝时是上游的策略，不是拦截
+        let mut with_encrypted = attempts.to_vec();
+        with_encrypted.push(answered("google-dot", "TLS", ResponseCode::Refused));
+        assert!(codes(&with_encrypted).is_empty());
+
+        // 有一个明文上游没有拒绝
+        let mixed = [
+            answered("google-udp", "UDP", ResponseCode::Refused),
+            answered("cloudflare-udp", "UDP", ResponseCode::ServerFailure),
+        ];
+        assert!(codes(&mixed).is_empty());
+    }
+
+    #[test]
+    fn test_timeouts_and_unreachable_suggest_no_connectivity() {
+        let unreachable = DnsError::Network {
+            kind: NetworkErrorKind::NetworkUnreachable,
+            upstream: "1.1.1.1:53".to_string(),
+            message: "Network is unreachable".to_string(),
+        };
+        let attempts = [failed("google-udp", "UDP", DnsError::Timeout { phase: None }), failed("cloudflare-udp", "UDP", unreachable)];
+        assert_eq!(codes(&attempts), ["no_connectivity"]);
+
+        let with_tls = [failed("google-udp", "UDP", DnsError::Timeout { phase: None }), failed("quad9-dot", "TLS", DnsError::Tls("bad cert".to_string()))];
+        assert!(codes(&with_tls).is_empty());
+        assert!(codes(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_nxdomain_from_single_provider_suggests_filtering() {
+        let attempts = [
+            answered("adguard-udp", "UDP", ResponseCode::NxDomain),
+            answered("AdGuard_doh", "HTTPS", ResponseCode::NxDomain),
+            answered("google-udp", "UDP", ResponseCode::Refused),
+        ];
+        let hints = diagnose_with(DEFAULT_HINT_RULES, &attempts);
+        assert_eq!(hints.len(), 1);
+        assert_eq!(hints[0].code, "nxdomain_filtering");
+        assert!(hints[0].message.contains("'adguard'"), "{}", hints[0].message);
+
+        // 不同服务商都返回NXDOMAIN时域名确实不存在
+        let agreed = [answered("adguard-udp", "UDP", ResponseCode::NxDomain), answered("google-udp", "UDP", ResponseCode::NxDomain)];
+        assert!(codes(&agreed).is_empty());
+
+        // 只问过一个服务商时无从比较
+        let alone = [answered("adguard-udp", "UDP", ResponseCode::NxDomain)];
+        assert!(codes(&alone).is_empty());
+    }
+
+    #[test]
+    fn test_custom_rules_extend_the_table() {
+        fn all_skipped(attempts: &[UpstreamAttempt]) -> Option<String> {
+            attempts.iter().all(|attempt| attempt.outcome == AttemptOutcome::Skipped).then(|| "every upstream was skipped".to_string())
+        }
+        let attempts = vec![failed("google-dot", "TLS", DnsError::Quarantined { upstream: "google-dot".to_string(), retry_in: Duration::from_secs(30) })];
+        let report = QueryFailureReport::new("example.com", "A", attempts);
+        assert!(report.hints.is_empty());
+
+        let mut rules = DEFAULT_HINT_RULES.to_vec();
+        rules.push(HintRule { code: "all_skipped", detect: all_skipped });
+        let report = report.with_rules(&rules);
+        assert_eq!(report.hints, [FailureHint { code: "all_skipped".to_string(), message: "every upstream was skipped".to_string() }]);
+    }
+
+    #[test]
+    fn test_report_only_for_failures_or_explained_nxdomain() {
+        let refused = vec![answered("google-udp", "UDP", ResponseCode::Refused)];
+        assert!(failure_report("example.com", "A", Some(ResponseCode::Refused), false, refused.clone()).is_some());
+        assert!(failure_report("example.com", "A", None, true, refused).is_some());
+        assert!(failure_report("example.com", "A", None, true, Vec::new()).is_none());
+
+        let plain_nxdomain = vec![answered("google-udp", "UDP", ResponseCode::NxDomain)];
+        assert!(failure_report("example.com", "A", Some(ResponseCode::NxDomain), false, plain_nxdomain).is_none());
+        let filtered = vec![answered("adguard-udp", "UDP", ResponseCode::NxDomain), answered("google-udp", "UDP", ResponseCode::Refused)];
+        assert!(failure_report("example.com", "A", Some(ResponseCode::NxDomain), false, filtered).is_some());
+    }
+
+    #[test]
+    fn test_display_lists_attempts_and_hints() {
+        let report = QueryFailureReport::new("example.com", "A", vec![
+            answered("google-udp", "UDP", ResponseCode::Refused),
+            failed("cloudflare-udp", "UDP", DnsError::Timeout { phase: None }),
+        ]);
+        let text = report.to_string();
+        assert!(text.starts_with("All 2 upstream attempts failed for example.com A: google-udp (UDP, REFUSED, 12ms); cloudflare-udp (UDP, timeout: Request timeout, 5000ms)"), "{}", text);
+        assert_eq!(report.upstream_count(), 2);
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["attempts"][0]["outcome"], "answered");
+        assert_eq!(json["attempts"][0]["rcode"], 5);
+        assert_eq!(json["attempts"][1]["outcome"], "timeout");
+    }
+}
@@ -0,0 +1,264 @@
+//! 加密上游证书校验失败时的降级
+//!
+//! 强制门户和企业的TLS拦截代理会让DoT/DoH的证书校验全部失败，严格模式下所有查询都会失败。
+//! 开启降级后，所有加密上游连续多次证书校验失败时暂时改走降级路径：同一主机的明文DNS，
+//! 或不校验证书的同一加密连接。降级期间按探测间隔在后台以正常校验重试加密上游，
+//! 任何一个恢复即退出降级。降级路径给出的应答标记为 `degraded_security`，不写入缓存。
+
+use crate::error::{DnsError, TlsErrorKind};
+use crate::{dns_info, dns_warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::time::Instant;
+
+/// 加密上游证书校验失败时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum EncryptedFallbackPolicy {
+    /// 不降级，证书校验失败的查询直接失败
+    #[default]
+    Strict,
+    /// 改用明文DNS：配置了明文备用上游时用它，否则用同一主机的53端口（UDP）
+    OpportunisticPlaintext,
+    /// 仍使用加密连接，但不校验证书
+    OpportunisticSkipVerify,
+}
+
+/// 降级状态与计数
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedFallbackStats {
+    /// 配置的降级方式
+    pub policy: EncryptedFallbackPolicy,
+    /// 当前是否处于降级
+    pub active: bool,
+    /// 进入或退出降级的次数
+    pub transitions: u64,
+    /// 经降级路径发出的查询数
+    pub degraded_queries: u64,
+    /// 降级期间对加密上游的探测次数
+    pub probes: u64,
+}
+
+/// 降级状态，解析器的克隆体共用
+#[derive(Debug)]
+pub(crate) struct EncryptedFallbackState {
+    policy: EncryptedFallbackPolicy,
+    /// 每个加密上游连续多少次证书校验失败后计入“全部失败”
+    threshold: u32,
+    /// 降级期间探测加密上游的间隔
+    probe_interval: Duration,
+    active: AtomicBool,
+    /// 参与降级判定的加密上游及其连续证书校验失败次数
+    failures: Mutex<HashMap<String, u32>>,
+    /// 最近一次探测的时间
+    probed_at: Mutex<Option<Instant>>,
+    transitions: AtomicU64,
+    degraded_queries: AtomicU64,
+    probes: AtomicU64,
+}
+
+impl EncryptedFallbackState {
+    /// 创建降级状态，`threshold` 至少为1
+    pub(crate) fn new(policy: EncryptedFallbackPolicy, threshold: u32, probe_interval: Duration) -> Self {
+        Self {
+            policy,
+            threshold: threshold.max(1),
+            probe_interval,
+            active: AtomicBool::new(false),
+            failures: Mutex::new(HashMap::new()),
+            probed_at: Mutex::new(None),
+            transitions: AtomicU64::new(0),
+            degraded_queries: AtomicU64::new(0),
+            probes: AtomicU64::new(0),
+        }
+    }
+
+    /// 配置的降级方式
+    pub(crate) fn policy(&self) -> EncryptedFallbackPolicy {
+        self.policy
+    }
+
+    /// 是否处于降级
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// 登记参与降级判定的加密上游
+    pub(crate) fn register(&self, name: &str) {
+        self.lock_failures().insert(name.to_string(), 0);
+    }
+
+    /// 注销移除的上游；剩下的上游都已失败时进入降级
+    pub(crate) fn unregister(&self, name: &str) {
+        let mut failures = self.lock_failures();
+        if failures.remove(name).is_some() {
+            self.check_all_failed(&failures);
+        }
+    }
+
+    /// 记录一次正常校验的加密发送结果：证书无效时累计失败次数，成功时清零；其他错误不影响计数
+    pub(crate) fn observe<T>(&self, name: &str, result: &Result<T, DnsError>) {
+        let mut failures = self.lock_failures();
+        let Some(count) = failures.get_mut(name) else {
+            return;
+        };
+        match result {
+            Ok(_) => *count = 0,
+            Err(e) if is_validation_failure(e) => {
+                *count = count.saturating_add(1);
+                self.check_all_failed(&failures);
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// 该上游的证书校验失败是否由降级处理：处于降级，或正在累计连续失败次数
+    ///
+    /// 上游监控会因证书错误直接把上游判为不可用，由降级处理的上游仍须参与查询
+    pub(crate) fn handles(&self, name: &str) -> bool {
+        self.is_active() || self.lock_failures().get(name).is_some_and(|count| *count > 0)
+    }
+
+    /// 降级期间是否到了探测加密上游的时间，是则记下本次探测
+    pub(crate) fn claim_probe(&self, now: Instant) -> bool {
+        if !self.is_active() {
+            return false;
+        }
+        let mut probed_at = self.probed_at.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if probed_at.is_some_and(|at| now.saturating_duration_since(at) < self.probe_interval) {
+            return false;
+        }
+        *probed_at = Some(now);
+        self.probes.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// 探测结果：加密上游恢复时退出降级
+    pub(crate) fn probe_finished<T>(&self, name: &str, result: &Result<T, DnsError>) {
+        match result {
+            Ok(_) => {
+                if let Some(count) = self.lock_failures().get_mut(name) {
+                    *count = 0;
+                }
+                if self.active.swap(false, Ordering::AcqRel) {
+                    self.transitions.fetch_add(1, Ordering::Relaxed);
+                    dns_info!("🔐 加密上游 {} 证书校验恢复正常，退出降级（{:?}）", name, self.policy);
+                }
+            }
+            Err(e) => dns_warn!("🔓 降级期间探测加密上游 {} 仍然失败: {}", name, e),
+        }
+    }
+
+    /// 记录一次经降级路径发出的查询
+    pub(crate) fn record_degraded(&self) {
+        self.degraded_queries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 当前状态与计数
+    pub(crate) fn stats(&self) -> EncryptedFallbackStats {
+        EncryptedFallbackStats {
+            policy: self.policy,
+            active: self.is_active(),
+            transitions: self.transitions.load(Ordering::Relaxed),
+            degraded_queries: self.degraded_queries.load(Ordering::Relaxed),
+            probes: self.probes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 所有登记的上游都达到失败次数时进入降级
+    fn check_all_failed(&self, failures: &HashMap<String, u32>) {
+        let all_failed = !failures.is_empty() && failures.values().all(|count| *count >= self.threshold);
+        if all_failed && !self.active.swap(true, Ordering::AcqRel) {
+            self.transitions.fetch_add(1, Ordering::Relaxed);
+            // 刚进入降级时先等一个探测间隔再探测
+            *self.probed_at.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Instant::now());
+            dns_warn!(
+                "🔓 所有加密上游（{}个）连续 {} 次证书校验失败，进入降级（{:?}），应答将标记为 degraded_security",
+                failures.len(), self.threshold, self.policy
+            );
+        }
+    }
+
+    fn lock_failures(&self) -> std::sync::MutexGuard<'_, HashMap<String, u32>> {
+        self.failures.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// 是否为证书校验失败（证书不受信任、过期、名称不符等）
+pub(crate) fn is_validation_failure(error: &DnsError) -> bool {
+    matches!(error, DnsError::TlsFailure { kind: TlsErrorKind::CertificateInvalid, .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cert_error() -> Result<(), DnsError> {
+        Err(DnsError::TlsFailure { kind: TlsErrorKind::CertificateInvalid, upstream: "dot".to_string() })
+    }
+
+    #[test]
+    fn test_enters_only_when_every_encrypted_upstream_keeps_failing() {
+        let state = EncryptedFallbackState::new(EncryptedFallbackPolicy::OpportunisticPlaintext, 2, Duration::ZERO);
+        state.register("a");
+        state.register("b");
+
+        state.observe("a", &cert_error());
+        state.observe("a", &cert_error());
+        state.observe("b", &cert_error());
+        // 超时不算证书失败，也不清零
+        state.observe("b", &Err::<(), _>(DnsError::Timeout { phase: None }));
+        assert!(!state.is_active());
+        assert!(state.handles("a") && state.handles("b") && !state.handles("c"));
+
+        // 成功让连续计数从头开始
+        state.observe("a", &Ok(()));
+        state.observe("b", &cert_error());
+        assert!(!state.is_active());
+
+        state.observe("a", &cert_error());
+        state.observe("a", &cert_error());
+        assert!(state.is_active());
+        assert_eq!(state.stats().transitions, 1);
+    }
+
+    #[test]
+    fn test_probe_recovers_and_respects_interval() {
+        let state = EncryptedFallbackState::new(EncryptedFallbackPolicy::OpportunisticSkipVerify, 1, Duration::from_secs(60));
+        state.register("a");
+        assert!(!state.claim_probe(Instant::now()));
+        state.observe("a", &cert_error());
+        assert!(state.is_active());
+
+        // 进入降级后先等一个探测间隔
+        let now = Instant::now();
+        assert!(!state.claim_probe(now));
+        assert!(state.claim_probe(now + Duration::from_secs(61)));
+        assert!(!state.claim_probe(now + Duration::from_secs(62)));
+
+        state.probe_finished("a", &cert_error());
+        assert!(state.is_active());
+        state.probe_finished("a", &Ok(()));
+        assert!(!state.is_active());
+        assert_eq!(state.stats(), EncryptedFallbackStats {
+            policy: EncryptedFallbackPolicy::OpportunisticSkipVerify,
+            active: false,
+            transitions: 2,
+            degraded_queries: 0,
+            probes: 1,
+        });
+    }
+
+    #[test]
+    fn test_removing_the_last_healthy_upstream_enters_fallback() {
+        let state = EncryptedFallbackState::new(EncryptedFallbackPolicy::OpportunisticPlaintext, 1, Duration::ZERO);
+        state.register("a");
+        state.register("b");
+        state.observe("a", &cert_error());
+        assert!(!state.is_active());
+        state.unregister("b");
+        assert!(state.is_active());
+    }
+}
@@ -1,8 +1,22 @@
 //! 传输健康检查器
 
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-use std::time::{SystemTime, Instant, Duration};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+use crate::time::{Instant, SystemTime};
+use serde::{Deserialize, Serialize};
+use super::clock::{Clock, real_clock};
+use super::memory::{MemoryBudget, MemoryComponent, MemoryMeter};
+use super::slo::{QueryObserver, SloConfig, SloStatus, SloTracker, SloTransition};
+use crate::builder::types::DnsRecordType;
+use crate::error::{DnsError, NetworkErrorKind, Result, RetryAdvice};
+use crate::types::Response;
+use crate::upstream_handler::UpstreamLabels;
+use crate::{dns_info, dns_warn};
+use tokio::task::JoinHandle;
 
 /// 基础传输统计
 #[derive(Debug, Clone, Default)]
@@ -21,17 +35,48 @@ pub struct BasicStats {
 
 type TransportStats = BasicStats;
 
+/// [`DetailedStats::recent_failures`] 保留的失败次数
+pub const RECENT_FAILURE_KINDS: usize = 5;
+
+/// 连续这么多次被拒绝连接时直接把上游标记为不可用：对端没有在监听，重试不会好转
+pub const PERSISTENT_REFUSALS: u32 = 2;
+
+/// 每个上游默认保留的状态变化事件数
+pub const DEFAULT_EVENT_HISTORY: usize = 64;
+
 /// 上游监控器
+/// 
+/// 每个上游的统计独立加锁：记录查询结果只锁对应的上游，外层表只在首次出现某个上游时加写锁。
+/// 成功率和平均响应时间按最近 `stats_window_size` 次结果计算，很久以前的故障不会一直拖累状态
 #[derive(Debug)]
 pub struct UpstreamMonitor {
-    /// 传输统计信息
-    stats: Arc<RwLock<HashMap<String, DetailedStats>>>,
+    /// 各上游的统计
+    upstreams: RwLock<HashMap<String, Mutex<UpstreamState>>>,
     /// 检查间隔
     check_interval: Duration,
     /// 健康阈值配置
     config: UpstreamConfig,
+    /// 时间源
+    clock: Arc<dyn Clock>,
+    /// 是否记录累计计数、套接字错误分类和对端地址（关闭时只维护判定状态所需的窗口和连续计数）
+    detailed_stats: bool,
+    /// 每个上游保留的状态变化事件数，0表示不记录
+    event_capacity: usize,
+    /// 各上游的响应时间SLO及其判定状态，重置统计时保留
+    slos: Mutex<HashMap<String, SloTracker>>,
+    /// SLO违反、恢复和主动探测的通知对象
+    observer: Option<Arc<dyn QueryObserver>>,
+    /// 上游限流（HTTP 429）是否不计为失败
+    ignore_rate_limited: bool,
+    /// 各上游保留的状态变化事件总数
+    event_count: AtomicUsize,
+    /// 配置了内存预算时向预算报告状态变化事件的占用
+    memory: OnceLock<MemoryMeter>,
 }
 
+/// 解析器为上游监控设置的最大不可用持续时间
+pub const MAX_UNAVAILABLE_DURATION: Duration = Duration::from_secs(300);
+
 /// 上游监控配置
 #[derive(Debug, Clone)]
 pub struct UpstreamConfig {
@@ -54,6 +99,110 @@ pub struct UpstreamConfig {
 // 这些"宽松"的默认值可能掩盖真实的性能问题
 // 用户现在必须根据实际需求明确配置监控参数
 
+/// 主动探测上游时发送的查询
+///
+/// 没有默认的探测域名：不同部署能访问的域名不同（例如境内部署查不到被屏蔽的域名），
+/// 探测一个上游本就答不出的名字只会把健康的上游判为不可用。配置多个域名时依次轮换使用
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProbeConfig {
+    /// 探测域名，依次轮换
+    pub domains: Vec<String>,
+    /// 探测的记录类型；探测较大的应答（如TXT）可以发现大包路径的问题
+    pub record_type: DnsRecordType,
+    /// 单次探测的超时时间
+    pub timeout: Duration,
+    /// 应答段至少要有的记录数，不足时探测视为失败（0表示有应答即可）
+    pub expect_min_answers: usize,
+}
+
+impl ProbeConfig {
+    /// 创建探测配置，应答记录数不做要求
+    pub fn new(domains: Vec<String>, record_type: DnsRecordType, timeout: Duration) -> Self {
+        Self { domains, record_type, timeout, expect_min_answers: 0 }
+    }
+    
+    /// 设置应答段至少要有的记录数
+    pub fn with_min_answers(mut self, count: usize) -> Self {
+        self.expect_min_answers = count;
+        self
+    }
+    
+    /// 检查配置，域名列表为空、含空域名或超时为零时返回 [`DnsError::InvalidConfig`]
+    pub fn validate(&self) -> Result<()> {
+        if self.domains.is_empty() || self.domains.iter().any(|domain| domain.trim().is_empty()) {
+            return Err(DnsError::InvalidConfig("Health probe domain cannot be empty".to_string()));
+        }
+        if self.timeout.is_zero() {
+            return Err(DnsError::InvalidConfig("Health probe timeout cannot be zero".to_string()));
+        }
+        Ok(())
+    }
+    
+    /// 第 `round` 次探测使用的域名
+    pub fn domain(&self, round: usize) -> &str {
+        &self.domains[round % self.domains.len()]
+    }
+    
+    /// 探测应答是否算作成功
+    pub fn accepts(&self, response: &Response) -> bool {
+        response.answers.len() >= self.expect_min_answers
+    }
+}
+
+/// 主动探测上游的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeReason {
+    /// 被判定为不可用的上游是否已恢复
+    Recovery,
+    /// 上游超过 `max_upstream_silence` 没有任何查询或探测
+    Staleness,
+}
+
+impl ProbeReason {
+    /// 原因的名称，如 `staleness`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProbeReason::Recovery => "recovery",
+            ProbeReason::Staleness => "staleness",
+        }
+    }
+}
+
+/// 一次主动探测的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeOutcome {
+    /// 有应答且满足探测配置
+    Success,
+    /// 有应答，但应答记录数少于 [`ProbeConfig::expect_min_answers`]
+    TooFewAnswers,
+    /// 查询出错
+    Failed,
+    /// 超过 [`ProbeConfig::timeout`] 没有应答
+    TimedOut,
+}
+
+impl ProbeOutcome {
+    /// 结果的名称，如 `timed_out`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProbeOutcome::Success => "success",
+            ProbeOutcome::TooFewAnswers => "too_few_answers",
+            ProbeOutcome::Failed => "failed",
+            ProbeOutcome::TimedOut => "timed_out",
+        }
+    }
+}
+
+/// 监控任务向上游发送探测的方式，由解析器提供
+#[async_trait::async_trait]
+pub(crate) trait UpstreamProber: Send + Sync {
+    /// 参与查询的上游名称
+    fn upstreams(&self) -> Vec<String>;
+    
+    /// 向上游发送一次探测，返回结果和耗时
+    async fn probe(&self, name: &str) -> (ProbeOutcome, Duration);
+}
+
 /// 上游状态
 #[derive(Debug, Clone, PartialEq)]
 pub enum UpstreamStatus {
@@ -65,18 +214,74 @@ pub enum UpstreamStatus {
     Unknown,
 }
 
+/// 上游状态变化的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusTrigger {
+    /// 连续失败次数达到阈值
+    ConsecutiveFailures,
+    /// 统计窗口内的成功率低于阈值
+    SuccessRate,
+    /// 统计窗口内的平均响应时间超过阈值
+    ResponseTime,
+    /// 连续被拒绝连接，或出现重试也不会好转的错误（如证书无效）
+    PersistentError,
+    /// 样本足够且没有触发阈值，或不可用后连续成功达到恢复次数
+    Recovery,
+    /// 统计窗口内的样本不足以判定
+    InsufficientSamples,
+    /// 不可用超过最长持续时间，重新给予机会
+    UnavailableTimeout,
+    /// 通过 [`UpstreamMonitor::set_upstream_status`] 手动设置
+    Manual,
+}
+
+impl StatusTrigger {
+    /// 原因的名称，如 `consecutive_failures`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatusTrigger::ConsecutiveFailures => "consecutive_failures",
+            StatusTrigger::SuccessRate => "success_rate",
+            StatusTrigger::ResponseTime => "response_time",
+            StatusTrigger::PersistentError => "persistent_error",
+            StatusTrigger::Recovery => "recovery",
+            StatusTrigger::InsufficientSamples => "insufficient_samples",
+            StatusTrigger::UnavailableTimeout => "unavailable_timeout",
+            StatusTrigger::Manual => "manual",
+        }
+    }
+}
+
+/// 一次上游状态变化
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamEvent {
+    /// 变化时间
+    pub timestamp: SystemTime,
+    /// 变化前的状态
+    pub old_status: UpstreamStatus,
+    /// 变化后的状态
+    pub new_status: UpstreamStatus,
+    /// 变化的原因
+    pub trigger: StatusTrigger,
+    /// 上游的标签（读取事件时由解析器附上，监控本身不记录）
+    pub labels: UpstreamLabels,
+}
+
 /// 详细的传输统计
 #[derive(Debug, Clone)]
 pub struct DetailedStats {
-    /// 成功次数
+    /// 成功次数（累计）
     pub success_count: u64,
-    /// 失败次数
+    /// 失败次数（累计）
     pub failure_count: u64,
+    /// 统计窗口内的成功次数
+    pub window_success_count: u64,
+    /// 统计窗口内的失败次数
+    pub window_failure_count: u64,
     /// 最后成功时间
     pub last_success: Option<SystemTime>,
     /// 最后失败时间
     pub last_failure: Option<SystemTime>,
-    /// 平均响应时间(毫秒)
+    /// 统计窗口内成功查询的平均响应时间(毫秒)，窗口内没有成功时为0
     pub avg_response_time: u64,
     /// 连续失败次数
     pub consecutive_failures: u32,
@@ -86,26 +291,158 @@ pub struct DetailedStats {
     pub upstream_status: UpstreamStatus,
     /// 状态变更时间
     pub status_changed_at: SystemTime,
+    /// 各类套接字错误的累计次数（查询超时计为 `TimedOut`）
+    pub network_errors: HashMap<NetworkErrorKind, u64>,
+    /// 最近几次套接字错误的类型，从旧到新，最多 [`RECENT_FAILURE_KINDS`] 个
+    pub recent_failures: VecDeque<NetworkErrorKind>,
+    /// 最近一次成功查询实际交换报文的对端地址
+    pub last_peer: Option<SocketAddr>,
 }
 
 // 注意：保留 DetailedStats 的 Default 实现，因为这是功能性需求
 // 统计数据的初始化不是兜底行为，而是正常的数据结构初始化
 impl Default for DetailedStats {
     fn default() -> Self {
+        Self::starting_at(SystemTime::now())
+    }
+}
+
+impl DetailedStats {
+    /// 创建以指定时间为状态起点的空统计
+    pub fn starting_at(now: SystemTime) -> Self {
         Self {
             success_count: 0,
             failure_count: 0,
+            window_success_count: 0,
+            window_failure_count: 0,
             last_success: None,
             last_failure: None,
             avg_response_time: 0,
             consecutive_failures: 0,
             consecutive_successes: 0,
             upstream_status: UpstreamStatus::Unknown,
-            status_changed_at: SystemTime::now(),
+            status_changed_at: now,
+            network_errors: HashMap::new(),
+            recent_failures: VecDeque::with_capacity(RECENT_FAILURE_KINDS),
+            last_peer: None,
+        }
+    }
+    
+    /// 最近几次套接字错误的汇总，如 `3x ConnectionRefused, 2x TimedOut`（按首次出现的顺序），没有时为空
+    pub fn recent_failure_summary(&self) -> String {
+        let mut counts: Vec<(NetworkErrorKind, usize)> = Vec::new();
+        for kind in &self.recent_failures {
+            match counts.iter_mut().find(|(counted, _)| counted == kind) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((*kind, 1)),
+            }
+        }
+        counts.iter()
+            .map(|(kind, count)| format!("{}x {:?}", count, kind))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    
+    fn record_network_error(&mut self, kind: NetworkErrorKind) {
+        *self.network_errors.entry(kind).or_insert(0) += 1;
+        if self.recent_failures.len() == RECENT_FAILURE_KINDS {
+            self.recent_failures.pop_front();
         }
+        self.recent_failures.push_back(kind);
+    }
+    
+    /// 统计窗口内的成功率，窗口为空时为0
+    pub fn window_success_rate(&self) -> f64 {
+        let total = self.window_success_count + self.window_failure_count;
+        if total == 0 {
+            0.0
+        } else {
+            self.window_success_count as f64 / total as f64
+        }
+    }
+}
+
+/// 最近若干次查询结果的环形缓冲
+#[derive(Debug)]
+struct OutcomeWindow {
+    /// 成功时为响应时间（毫秒），失败时为 `None`
+    outcomes: VecDeque<Option<u64>>,
+    capacity: usize,
+    successes: u64,
+    latency_sum: u64,
+}
+
+impl OutcomeWindow {
+    fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            outcomes: VecDeque::with_capacity(capacity),
+            capacity,
+            successes: 0,
+            latency_sum: 0,
+        }
+    }
+    
+    /// 加入一次结果，窗口已满时移出最早的一次
+    fn push(&mut self, outcome: Option<u64>) {
+        if self.outcomes.len() == self.capacity {
+            self.pop_front();
+        }
+        if let Some(latency) = outcome {
+            self.successes += 1;
+            self.latency_sum += latency;
+        }
+        self.outcomes.push_back(outcome);
+    }
+    
+    fn pop_front(&mut self) {
+        if let Some(Some(latency)) = self.outcomes.pop_front() {
+            self.successes -= 1;
+            self.latency_sum -= latency;
+        }
+    }
+    
+    /// 移出较早的一半结果，至少保留最近 `keep` 次
+    fn decay(&mut self, keep: usize) {
+        let len = self.outcomes.len();
+        for _ in 0..(len / 2).min(len.saturating_sub(keep)) {
+            self.pop_front();
+        }
+    }
+}
+
+/// 一个上游的统计和最近结果窗口
+#[derive(Debug)]
+struct UpstreamState {
+    stats: DetailedStats,
+    window: OutcomeWindow,
+    /// 连续被拒绝连接的次数
+    consecutive_refusals: u32,
+    /// 最近的状态变化，从旧到新
+    events: VecDeque<UpstreamEvent>,
+    /// 最近一次记录查询或探测结果的时间（还没有结果时为开始统计的时间）
+    last_activity: Instant,
+}
+
+impl UpstreamState {
+    /// 加入一次结果并刷新按窗口计算的字段
+    fn push(&mut self, outcome: Option<u64>, now: Instant) {
+        self.window.push(outcome);
+        self.last_activity = now;
+        self.refresh_window_stats();
+    }
+    
+    fn refresh_window_stats(&mut self) {
+        self.stats.window_success_count = self.window.successes;
+        self.stats.window_failure_count = self.window.outcomes.len() as u64 - self.window.successes;
+        self.stats.avg_response_time = self.window.latency_sum.checked_div(self.window.successes).unwrap_or(0);
     }
 }
 
+fn lock_state(state: &Mutex<UpstreamState>) -> MutexGuard<'_, UpstreamState> {
+    state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
 impl UpstreamMonitor {
     // 注意：移除了使用默认配置的构造函数
     // 用户现在必须明确提供 UpstreamConfig，不能依赖兜底配置
@@ -116,93 +453,285 @@ impl UpstreamMonitor {
     
     /// 使用自定义配置创建上游监控器
     pub fn with_config(check_interval: Duration, config: UpstreamConfig) -> Self {
+        Self::with_clock(check_interval, config, real_clock())
+    }
+    
+    /// 使用自定义配置和时间源创建上游监控器
+    pub fn with_clock(check_interval: Duration, config: UpstreamConfig, clock: Arc<dyn Clock>) -> Self {
         Self {
-            stats: Arc::new(RwLock::new(HashMap::new())),
+            upstreams: RwLock::new(HashMap::new()),
             check_interval,
             config,
+            clock,
+            detailed_stats: true,
+            event_capacity: DEFAULT_EVENT_HISTORY,
+            slos: Mutex::new(HashMap::new()),
+            observer: None,
+            ignore_rate_limited: false,
+            event_count: AtomicUsize::new(0),
+            memory: OnceLock::new(),
         }
     }
     
+    /// 设置是否记录详细统计（默认记录）
+    /// 
+    /// 关闭时 `success_count`、`failure_count`、最后成功/失败时间、`network_errors`、
+    /// `recent_failures` 和 `last_peer` 保持初始值，上游状态照常按窗口判定
+    pub fn with_detailed_stats(mut self, enable: bool) -> Self {
+        self.detailed_stats = enable;
+        self
+    }
+    
+    /// 设置每个上游保留的状态变化事件数（默认 [`DEFAULT_EVENT_HISTORY`]），超出时丢弃最早的事件，0表示不记录
+    pub fn with_event_history(mut self, capacity: usize) -> Self {
+        self.event_capacity = capacity;
+        self
+    }
+    
+    /// 设置接收SLO违反、恢复和主动探测通知的观察者
+    pub fn with_observer(mut self, observer: Option<Arc<dyn QueryObserver>>) -> Self {
+        self.observer = observer;
+        self
+    }
+    
+    /// 设置上游限流（HTTP 429）是否不计为失败（默认计为失败）
+    /// 
+    /// 解析器按 [`RateLimitedBehavior::WaitAndRetry`](super::rate_limit::RateLimitedBehavior::WaitAndRetry)
+    /// 等待限流的上游时开启：限流是配额而不是故障，不应拉低该上游的成功率
+    pub fn with_rate_limited_ignored(mut self, ignore: bool) -> Self {
+        self.ignore_rate_limited = ignore;
+        self
+    }
+    
+    /// 计入内存预算；超出预算时丢弃各上游最早的状态变化事件。重复调用时只有第一次生效
+    pub(crate) fn attach_memory_budget(self: &Arc<Self>, budget: &Arc<MemoryBudget>) {
+        if self.memory.set(MemoryMeter::new(budget.clone(), MemoryComponent::UpstreamEvents)).is_err() {
+            return;
+        }
+        let monitor = Arc::downgrade(self);
+        budget.register(MemoryComponent::UpstreamEvents, move |wanted| Some(monitor.upgrade()?.shrink_events(wanted)));
+        self.report_event_memory();
+    }
+    
+    /// 从各上游最早的事件开始丢弃，直到释放至少 `wanted` 字节或没有事件，返回释放的字节数
+    fn shrink_events(&self, wanted: usize) -> usize {
+        let mut dropped = 0;
+        let count = wanted.div_ceil(size_of::<UpstreamEvent>());
+        {
+            let upstreams = self.read_upstreams();
+            let share = count.div_ceil(upstreams.len().max(1));
+            for state in upstreams.values() {
+                let mut state = lock_state(state);
+                let n = share.min(state.events.len()).min(count - dropped);
+                state.events.drain(..n);
+                self.event_count.fetch_sub(n, Ordering::Relaxed);
+                dropped += n;
+            }
+        }
+        self.report_event_memory();
+        dropped * size_of::<UpstreamEvent>()
+    }
+    
+    /// 向内存预算报告事件占用：事件数乘以事件大小。调用时不能持有上游统计的锁
+    fn report_event_memory(&self) {
+        if let Some(meter) = self.memory.get() {
+            meter.set(self.event_count.load(Ordering::Relaxed) * size_of::<UpstreamEvent>());
+        }
+    }
+    
+    /// 接收通知的观察者
+    pub(crate) fn observer(&self) -> Option<&Arc<dyn QueryObserver>> {
+        self.observer.as_ref()
+    }
+    
+    fn new_state(&self) -> UpstreamState {
+        UpstreamState {
+            stats: DetailedStats::starting_at(self.clock.now_system()),
+            window: OutcomeWindow::new(self.config.stats_window_size),
+            consecutive_refusals: 0,
+            events: VecDeque::with_capacity(self.event_capacity.min(DEFAULT_EVENT_HISTORY)),
+            last_activity: self.clock.now_instant(),
+        }
+    }
+    
+    fn read_upstreams(&self) -> RwLockReadGuard<'_, HashMap<String, Mutex<UpstreamState>>> {
+        self.upstreams.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
+    fn write_upstreams(&self) -> RwLockWriteGuard<'_, HashMap<String, Mutex<UpstreamState>>> {
+        self.upstreams.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
+    /// 锁住上游的统计后执行 `f`，上游不存在时先创建；外层表只在创建时加写锁
+    fn with_upstream<T>(&self, transport_type: &str, f: impl FnOnce(&mut UpstreamState) -> T) -> T {
+        let result = self.with_upstream_locked(transport_type, f);
+        // `f` 可能记录了状态变化事件，释放锁之后再报告
+        self.report_event_memory();
+        result
+    }
+    
+    fn with_upstream_locked<T>(&self, transport_type: &str, f: impl FnOnce(&mut UpstreamState) -> T) -> T {
+        if let Some(state) = self.read_upstreams().get(transport_type) {
+            return f(&mut lock_state(state));
+        }
+        let mut upstreams = self.write_upstreams();
+        let state = upstreams.entry(transport_type.to_string())
+            .or_insert_with(|| Mutex::new(self.new_state()));
+        f(&mut lock_state(state))
+    }
+    
+    /// 已有统计的上游状态，没有统计时为 `None`
+    fn status_of(&self, transport_type: &str) -> Option<UpstreamStatus> {
+        self.read_upstreams().get(transport_type)
+            .map(|state| lock_state(state).stats.upstream_status.clone())
+    }
+    
+    /// 各上游统计的快照
+    fn snapshot(&self) -> Vec<(String, DetailedStats)> {
+        self.read_upstreams().iter()
+            .map(|(transport_type, state)| (transport_type.clone(), lock_state(state).stats.clone()))
+            .collect()
+    }
+    
     /// 记录成功
     pub fn record_success(&self, transport_type: &str, duration: Duration) {
-        if let Ok(mut stats) = self.stats.write() {
-            let detailed_stats = stats.entry(transport_type.to_string())
-                .or_insert_with(DetailedStats::default);
-            
-            // 更新基础统计
-            detailed_stats.success_count += 1;
-            detailed_stats.last_success = Some(SystemTime::now());
-            
-            // 更新平均响应时间
-            if detailed_stats.avg_response_time == 0 {
-                detailed_stats.avg_response_time = duration.as_millis() as u64;
-            } else {
-                detailed_stats.avg_response_time = 
-                    (detailed_stats.avg_response_time * 9 + duration.as_millis() as u64) / 10;
+        self.with_upstream(transport_type, |state| {
+            state.push(Some(duration.as_millis() as u64), self.clock.now_instant());
+            state.consecutive_refusals = 0;
+            let stats = &mut state.stats;
+            if self.detailed_stats {
+                stats.success_count += 1;
+                stats.last_success = Some(self.clock.now_system());
             }
             
             // 重置连续失败计数
-            detailed_stats.consecutive_failures = 0;
-            detailed_stats.consecutive_successes += 1;
+            stats.consecutive_failures = 0;
+            stats.consecutive_successes += 1;
             
-            // 检查上游状态
-            self.update_upstream_status(detailed_stats);
+            self.update_upstream_status(state);
+        });
+    }
+    
+    /// 记录成功查询实际交换报文的对端地址
+    pub fn record_peer(&self, transport_type: &str, peer: SocketAddr) {
+        if !self.detailed_stats {
+            return;
         }
+        self.with_upstream(transport_type, |state| state.stats.last_peer = Some(peer));
     }
     
     /// 记录失败
     pub fn record_failure(&self, transport_type: &str) {
-        if let Ok(mut stats) = self.stats.write() {
-            let detailed_stats = stats.entry(transport_type.to_string())
-                .or_insert_with(DetailedStats::default);
-            
-            // 更新基础统计
-            detailed_stats.failure_count += 1;
-            detailed_stats.last_failure = Some(SystemTime::now());
-            
-            // 重置连续成功计数
-            detailed_stats.consecutive_successes = 0;
-            detailed_stats.consecutive_failures += 1;
-            
-            // 检查上游状态
-            self.update_upstream_status(detailed_stats);
+        self.with_upstream(transport_type, |state| {
+            state.consecutive_refusals = 0;
+            self.push_failure(state);
+        });
+    }
+    
+    fn push_failure(&self, state: &mut UpstreamState) {
+        state.push(None, self.clock.now_instant());
+        let stats = &mut state.stats;
+        if self.detailed_stats {
+            stats.failure_count += 1;
+            stats.last_failure = Some(self.clock.now_system());
+        }
+        
+        // 重置连续成功计数
+        stats.consecutive_successes = 0;
+        stats.consecutive_failures += 1;
+        
+        self.update_upstream_status(state);
+    }
+
+    /// 记录一次主动探测的结果并通知观察者：成功计为一次成功查询，其余计为失败
+    pub fn record_probe(&self, transport_type: &str, reason: ProbeReason, outcome: ProbeOutcome, duration: Duration) {
+        match outcome {
+            ProbeOutcome::Success => self.record_success(transport_type, duration),
+            _ => self.record_failure(transport_type),
+        }
+        if let Some(observer) = &self.observer {
+            observer.on_upstream_probe(transport_type, reason, outcome);
         }
     }
     
-    /// 更新上游状态
-    fn update_upstream_status(&self, stats: &mut DetailedStats) {
-        let old_status = stats.upstream_status.clone();
+    /// 上游距最近一次查询或探测结果的时长；还没有统计的上游从现在开始计时
+    pub fn silent_for(&self, transport_type: &str) -> Duration {
+        let now = self.clock.now_instant();
+        self.with_upstream(transport_type, |state| now.saturating_duration_since(state.last_activity))
+    }
+    
+    /// 丢弃统计窗口中较早的一半结果，长期闲置的上游不再由很久以前的结果决定成功率和平均响应时间
+    /// 
+    /// 至少保留最近 `max_consecutive_failures` 次结果，连续探测失败仍能凑足判定所需的样本；
+    /// 连续成功/失败计数和当前状态不变
+    pub fn decay_window(&self, transport_type: &str) {
+        let keep = self.config.max_consecutive_failures as usize;
+        self.with_upstream(transport_type, |state| {
+            state.window.decay(keep);
+            state.refresh_window_stats();
+        });
+    }
+    
+    /// 按错误类型记录失败
+    ///
+    /// 套接字错误按类型计数。证书无效、证书固定不匹配等重试也不会好转的错误，
+    /// 以及连续 [`PERSISTENT_REFUSALS`] 次被拒绝连接，直接把上游标记为不可用，不必等连续失败次数累积到阈值
+    pub fn record_error(&self, transport_type: &str, error: &DnsError) {
+        // 本地并发或该上游的在途上限已满、上游处于隔离期时请求没有发出，与上游无关
+        if matches!(error, DnsError::Busy { .. } | DnsError::UpstreamSaturated { .. } | DnsError::Quarantined { .. }) {
+            return;
+        }
+        if self.ignore_rate_limited && error.is_rate_limited() {
+            return;
+        }
+        let kind = error.network_kind();
+        let refused_persistently = self.with_upstream(transport_type, |state| {
+            if kind == Some(NetworkErrorKind::ConnectionRefused) {
+                state.consecutive_refusals += 1;
+            } else {
+                state.consecutive_refusals = 0;
+            }
+            if let (Some(kind), true) = (kind, self.detailed_stats) {
+                state.stats.record_network_error(kind);
+            }
+            self.push_failure(state);
+            state.consecutive_refusals >= PERSISTENT_REFUSALS
+        });
+        if refused_persistently || error.retry_advice() == RetryAdvice::Unavailable {
+            self.set_status(transport_type, UpstreamStatus::Unavailable, StatusTrigger::PersistentError);
+        }
+    }
+
+    /// 按统计窗口更新上游状态
+    fn update_upstream_status(&self, state: &mut UpstreamState) {
+        let stats = &state.stats;
         let mut new_status = UpstreamStatus::Unknown;  // 默认为未知状态
+        let mut trigger = StatusTrigger::InsufficientSamples;
         
-        let total = stats.success_count + stats.failure_count;
+        let total = stats.window_success_count + stats.window_failure_count;
         
         // 如果样本数不足，保持未知状态
-        if total < 3 {
-            new_status = UpstreamStatus::Unknown;
-        } else {
-            // 有足够样本时才进行状态判断
-            new_status = UpstreamStatus::Available;
-            
-            // 检查连续失败
-            if stats.consecutive_failures >= self.config.max_consecutive_failures {
-                new_status = UpstreamStatus::Unavailable;
-            }
-            
-            // 检查成功率（只有足够样本时才检查）
-            if total >= 5 && (stats.success_count as f64 / total as f64) < self.config.min_success_rate {
-                new_status = UpstreamStatus::Unavailable;
-            }
-            
-            // 检查平均响应时间
-            if stats.avg_response_time > 0 && Duration::from_millis(stats.avg_response_time) > self.config.max_avg_response_time {
-                new_status = UpstreamStatus::Unavailable;
-            }
+        if total >= 3 {
+            // 有足够样本时才进行状态判断，按连续失败、成功率（只有足够样本时才检查）、平均响应时间的顺序取第一个触发的阈值
+            let breach = if stats.consecutive_failures >= self.config.max_consecutive_failures {
+                Some(StatusTrigger::ConsecutiveFailures)
+            } else if total >= 5 && stats.window_success_rate() < self.config.min_success_rate {
+                Some(StatusTrigger::SuccessRate)
+            } else if stats.avg_response_time > 0 && Duration::from_millis(stats.avg_response_time) > self.config.max_avg_response_time {
+                Some(StatusTrigger::ResponseTime)
+            } else {
+                None
+            };
+            (new_status, trigger) = match breach {
+                Some(breach) => (UpstreamStatus::Unavailable, breach),
+                None => (UpstreamStatus::Available, StatusTrigger::Recovery),
+            };
         }
         
         // 检查恢复条件
         if stats.upstream_status == UpstreamStatus::Unavailable {
             if stats.consecutive_successes >= self.config.recovery_success_count {
-                new_status = UpstreamStatus::Available;
+                (new_status, trigger) = (UpstreamStatus::Available, StatusTrigger::Recovery);
             } else {
                 new_status = UpstreamStatus::Unavailable;
             }
@@ -210,198 +739,219 @@ impl UpstreamMonitor {
         
         // 检查长期不可用状态
         if stats.upstream_status == UpstreamStatus::Unavailable {
-            if let Ok(elapsed) = stats.status_changed_at.elapsed() {
-                if elapsed > self.config.max_unavailable_duration {
-                    // 长期不可用，给一次恢复机会
-                    if stats.consecutive_successes > 0 {
-                        new_status = UpstreamStatus::Available;
-                    }
+            let elapsed = self.clock.system_elapsed(stats.status_changed_at);
+            if elapsed > self.config.max_unavailable_duration {
+                // 长期不可用，给一次恢复机会
+                if stats.consecutive_successes > 0 {
+                    (new_status, trigger) = (UpstreamStatus::Available, StatusTrigger::Recovery);
                 }
             }
         }
         
-        // 更新状态
-        if new_status != old_status {
-            stats.upstream_status = new_status;
-            stats.status_changed_at = SystemTime::now();
+        self.change_status(state, new_status, trigger);
+    }
+    
+    /// 状态与当前不同时更新状态和变更时间，并记录一条状态变化事件
+    fn change_status(&self, state: &mut UpstreamState, status: UpstreamStatus, trigger: StatusTrigger) {
+        if state.stats.upstream_status == status {
+            return;
+        }
+        let now = self.clock.now_system();
+        let old_status = std::mem::replace(&mut state.stats.upstream_status, status.clone());
+        state.stats.status_changed_at = now;
+        if self.event_capacity == 0 {
+            return;
+        }
+        if state.events.len() == self.event_capacity {
+            state.events.pop_front();
+        } else {
+            self.event_count.fetch_add(1, Ordering::Relaxed);
         }
+        state.events.push_back(UpstreamEvent { timestamp: now, old_status, new_status: status, trigger, labels: UpstreamLabels::new() });
+    }
+    
+    fn set_status(&self, transport_type: &str, status: UpstreamStatus, trigger: StatusTrigger) {
+        self.with_upstream(transport_type, |state| self.change_status(state, status, trigger));
     }
     
     /// 检查传输是否可用
     pub fn is_available(&self, transport_type: &str) -> bool {
-        if let Ok(stats) = self.stats.read() {
-            if let Some(stats) = stats.get(transport_type) {
-                return stats.upstream_status == UpstreamStatus::Available ||
-                       stats.upstream_status == UpstreamStatus::Unknown;
-            }
-        }
-        
         // 默认认为是可用的（新传输）
-        true
+        self.status_of(transport_type)
+            .is_none_or(|status| status != UpstreamStatus::Unavailable)
     }
     
     /// 获取传输上游状态
     pub fn get_upstream_status(&self, transport_type: &str) -> UpstreamStatus {
-        if let Ok(stats) = self.stats.read() {
-            if let Some(detailed_stats) = stats.get(transport_type) {
-                return detailed_stats.upstream_status.clone();
-            }
-        }
-        
-        UpstreamStatus::Unknown
+        self.status_of(transport_type).unwrap_or(UpstreamStatus::Unknown)
     }
     
-    /// 获取所有传输的统计信息
+    /// 获取所有传输的统计信息：（累计成功次数，累计失败次数，窗口内平均响应时间）
     pub fn get_stats(&self) -> HashMap<String, (u64, u64, Duration)> {
-        let mut result = HashMap::new();
-        
-        if let Ok(stats) = self.stats.read() {
-            for (transport_type, detailed_stats) in stats.iter() {
-                result.insert(
-                    transport_type.clone(),
-                    (
-                        detailed_stats.success_count,
-                        detailed_stats.failure_count,
-                        Duration::from_millis(detailed_stats.avg_response_time),
-                    ),
-                );
-            }
-        }
-        
-        result
+        self.snapshot().into_iter()
+            .map(|(transport_type, stats)| (
+                transport_type,
+                (stats.success_count, stats.failure_count, Duration::from_millis(stats.avg_response_time)),
+            ))
+            .collect()
     }
     
     /// 获取详细统计信息
     pub fn get_detailed_stats(&self) -> HashMap<String, DetailedStats> {
-        if let Ok(stats) = self.stats.read() {
-            stats.clone()
-        } else {
-            HashMap::new()
-        }
+        self.snapshot().into_iter().collect()
+    }
+    
+    /// 单个上游的详细统计，没有统计时为 `None`
+    pub fn detailed_stats(&self, transport_type: &str) -> Option<DetailedStats> {
+        self.read_upstreams().get(transport_type).map(|state| lock_state(state).stats.clone())
+    }
+    
+    /// 上游最近的状态变化，从旧到新；`since` 不为 `None` 时只返回该时间及之后的事件
+    /// 
+    /// 每个上游最多保留 [`with_event_history`](Self::with_event_history) 设置的条数，重置统计时一并清空
+    pub fn get_upstream_events(&self, transport_type: &str, since: Option<SystemTime>) -> Vec<UpstreamEvent> {
+        let Some(events) = self.read_upstreams().get(transport_type).map(|state| lock_state(state).events.clone()) else {
+            return Vec::new();
+        };
+        events.into_iter()
+            .filter(|event| since.is_none_or(|since| event.timestamp >= since))
+            .collect()
     }
     
     /// 获取可用的传输列表
     pub fn get_available_transports(&self) -> Vec<String> {
-        let mut available = Vec::new();
-        
-        if let Ok(stats) = self.stats.read() {
-            for (transport_type, detailed_stats) in stats.iter() {
-                if detailed_stats.upstream_status == UpstreamStatus::Available ||
-               detailed_stats.upstream_status == UpstreamStatus::Unknown {
-                    available.push(transport_type.clone());
-                }
-            }
-        }
-        
-        available
+        self.snapshot().into_iter()
+            .filter(|(_, stats)| stats.upstream_status != UpstreamStatus::Unavailable)
+            .map(|(transport_type, _)| transport_type)
+            .collect()
     }
     
     /// 检查传输是否可用（包括新传输）
     pub fn is_transport_available(&self, transport_type: &str) -> bool {
-        if let Ok(stats) = self.stats.read() {
-            if let Some(detailed_stats) = stats.get(transport_type) {
-                // 有统计记录的传输，检查其健康状态
-                detailed_stats.upstream_status == UpstreamStatus::Available ||
-                detailed_stats.upstream_status == UpstreamStatus::Unknown
-            } else {
-                // 新传输默认认为是健康的
-                true
-            }
-        } else {
-            // 无法读取统计信息时，默认认为健康
-            true
-        }
+        self.is_available(transport_type)
     }
     
     /// 获取不可用的传输列表
     pub fn get_unavailable_transports(&self) -> Vec<String> {
-        let mut unavailable = Vec::new();
-        
-        if let Ok(stats) = self.stats.read() {
-            for (transport_type, detailed_stats) in stats.iter() {
-                if detailed_stats.upstream_status == UpstreamStatus::Unavailable {
-                    unavailable.push(transport_type.clone());
-                }
-            }
-        }
-        
-        unavailable
+        self.snapshot().into_iter()
+            .filter(|(_, stats)| stats.upstream_status == UpstreamStatus::Unavailable)
+            .map(|(transport_type, _)| transport_type)
+            .collect()
     }
     
     /// 重置传输统计
     pub fn reset_stats(&self, transport_type: &str) {
-        if let Ok(mut stats) = self.stats.write() {
-            if let Some(detailed_stats) = stats.get_mut(transport_type) {
-                *detailed_stats = DetailedStats::default();
-            }
+        if let Some(state) = self.read_upstreams().get(transport_type) {
+            let old = std::mem::replace(&mut *lock_state(state), self.new_state());
+            self.event_count.fetch_sub(old.events.len(), Ordering::Relaxed);
         }
+        self.report_event_memory();
     }
     
     /// 重置所有统计
     pub fn reset_all_stats(&self) {
-        if let Ok(mut stats) = self.stats.write() {
-            stats.clear();
+        {
+            let mut upstreams = self.write_upstreams();
+            let events: usize = upstreams.values().map(|state| lock_state(state).events.len()).sum();
+            self.event_count.fetch_sub(events, Ordering::Relaxed);
+            upstreams.clear();
         }
+        self.report_event_memory();
     }
     
     /// 设置传输上游状态（用于测试或手动干预）
     pub fn set_upstream_status(&self, transport_type: &str, status: UpstreamStatus) {
-        if let Ok(mut stats) = self.stats.write() {
-            let detailed_stats = stats.entry(transport_type.to_string())
-                .or_insert_with(DetailedStats::default);
-            
-            if detailed_stats.upstream_status != status {
-                detailed_stats.upstream_status = status;
-                detailed_stats.status_changed_at = SystemTime::now();
-            }
-        }
+        self.set_status(transport_type, status, StatusTrigger::Manual);
     }
     
-    /// 获取传输排名（按健康程度和性能）
+    /// 获取传输排名（按健康程度和性能，成功率按统计窗口计算）
     pub fn get_transport_ranking(&self) -> Vec<(String, f64)> {
-        let mut rankings = Vec::new();
-        
-        if let Ok(stats) = self.stats.read() {
-            for (transport_type, detailed_stats) in stats.iter() {
+        let mut rankings: Vec<(String, f64)> = self.snapshot().into_iter()
+            .map(|(transport_type, stats)| {
                 let mut score = 0.0;
                 
                 // 上游状态分数
-                match detailed_stats.upstream_status {
+                match stats.upstream_status {
                     UpstreamStatus::Available => score += 100.0,
-            UpstreamStatus::Unknown => score += 80.0,
-            UpstreamStatus::Unavailable => score += 0.0,
+                    UpstreamStatus::Unknown => score += 80.0,
+                    UpstreamStatus::Unavailable => score += 0.0,
                 }
                 
                 // 成功率分数
-                let total = detailed_stats.success_count + detailed_stats.failure_count;
-                let success_rate = if total > 0 {
-                    detailed_stats.success_count as f64 / total as f64
-                } else {
-                    0.0
-                };
-                score += success_rate * 50.0;
+                score += stats.window_success_rate() * 50.0;
                 
                 // 响应时间分数（越快越好）
-                let avg_ms = detailed_stats.avg_response_time as f64;
+                let avg_ms = stats.avg_response_time as f64;
                 if avg_ms > 0.0 {
-                    score += (1000.0 / avg_ms.max(1.0)).min(50.0).max(0.0);
+                    score += (1000.0 / avg_ms.max(1.0)).clamp(0.0, 50.0);
                 }
                 
                 // 连续成功分数
-                score += (detailed_stats.consecutive_successes as f64).min(20.0).max(0.0);
+                score += (stats.consecutive_successes as f64).min(20.0);
                 
                 // 连续失败惩罚
-                score -= (detailed_stats.consecutive_failures as f64) * 5.0;
+                score -= (stats.consecutive_failures as f64) * 5.0;
                 
-                rankings.push((transport_type.clone(), score.max(0.0)));
-            }
-        }
+                (transport_type, score.max(0.0))
+            })
+            .collect();
         
         // 按分数降序排序
         rankings.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
         rankings
     }
     
+    /// 设置上游的响应时间SLO，`None` 表示取消；重新设置后判定状态从头开始
+    pub fn set_slo(&self, transport_type: &str, slo: Option<SloConfig>) {
+        let mut slos = self.slos.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match slo {
+            Some(config) => { slos.insert(transport_type.to_string(), SloTracker::new(config)); },
+            None => { slos.remove(transport_type); },
+        }
+    }
+    
+    /// 上游SLO的当前判定，没有设置SLO时为 `None`
+    pub fn slo_status(&self, transport_type: &str) -> Option<SloStatus> {
+        let slos = self.slos.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        slos.get(transport_type).map(|tracker| tracker.status().clone())
+    }
+    
+    /// 按统计窗口内成功查询的响应时间评估各上游的SLO，状态变化时通知观察者
+    /// 
+    /// 由 [`UpstreamMonitorTask`] 每个检查间隔调用一次；通知在释放锁之后发出
+    pub fn evaluate_slos(&self) {
+        let now = self.clock.now_system();
+        let transitions: Vec<(String, SloTransition)> = {
+            let upstreams = self.read_upstreams();
+            let mut slos = self.slos.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            slos.iter_mut()
+                .filter_map(|(transport_type, tracker)| {
+                    let mut latencies: Vec<u64> = upstreams.get(transport_type)
+                        .map(|state| lock_state(state).window.outcomes.iter().flatten().copied().collect())
+                        .unwrap_or_default();
+                    tracker.evaluate(&mut latencies, now).map(|transition| (transport_type.clone(), transition))
+                })
+                .collect()
+        };
+        
+        for (transport_type, transition) in transitions {
+            match transition {
+                SloTransition::Breach { observed_p95, threshold } => {
+                    dns_warn!("上游 {} 的p95响应时间 {:?} 持续超过SLO阈值 {:?}", transport_type, observed_p95, threshold);
+                    if let Some(observer) = &self.observer {
+                        observer.on_slo_breach(&transport_type, observed_p95, threshold);
+                    }
+                },
+                SloTransition::Recovery { observed_p95, threshold } => {
+                    dns_info!("上游 {} 的p95响应时间 {:?} 已回到SLO阈值 {:?} 以内", transport_type, observed_p95, threshold);
+                    if let Some(observer) = &self.observer {
+                        observer.on_slo_recovery(&transport_type, observed_p95, threshold);
+                    }
+                },
+            }
+        }
+    }
+    
     /// 获取检查间隔
     pub fn check_interval(&self) -> Duration {
         self.check_interval
@@ -412,6 +962,11 @@ impl UpstreamMonitor {
         self.check_interval = interval;
     }
     
+    /// 获取时间源
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+    
     /// 获取配置引用
     pub fn config(&self) -> &UpstreamConfig {
         &self.config
@@ -426,17 +981,31 @@ impl UpstreamMonitor {
 /// 上游监控任务
 pub struct UpstreamMonitorTask {
     monitor: Arc<UpstreamMonitor>,
+    /// 探测静默上游的方式和静默时长上限（未开启静默检测时为None）
+    silence_probe: Option<(Arc<dyn UpstreamProber>, Duration)>,
 }
 
 impl UpstreamMonitorTask {
     /// 创建新的上游监控任务
     pub fn new(monitor: Arc<UpstreamMonitor>) -> Self {
-        Self { monitor }
+        Self { monitor, silence_probe: None }
+    }
+    
+    /// 开启静默检测：每个检查间隔探测超过 `max_silence` 没有查询或探测的上游
+    pub(crate) fn with_silence_probe(mut self, prober: Arc<dyn UpstreamProber>, max_silence: Duration) -> Self {
+        self.silence_probe = Some((prober, max_silence));
+        self
+    }
+    
+    /// 在当前异步运行时中启动上游监控任务，不在异步运行时中时不启动
+    pub(crate) fn spawn(self) -> Option<UpstreamMonitorHandle> {
+        let runtime = tokio::runtime::Handle::try_current().ok()?;
+        Some(UpstreamMonitorHandle { task: runtime.spawn(self.start()) })
     }
     
     /// 启动上游监控任务
     pub async fn start(self) {
-        let mut interval = tokio::time::interval(self.monitor.check_interval());
+        let mut interval = crate::runtime::interval(self.monitor.check_interval());
         
         loop {
             interval.tick().await;
@@ -448,30 +1017,470 @@ impl UpstreamMonitorTask {
     }
     
     /// 执行上游监控
-    async fn perform_upstream_monitoring(&self) {
+    pub(crate) async fn perform_upstream_monitoring(&self) {
         // 获取所有传输的统计信息
         let stats = self.monitor.get_detailed_stats();
         
         for (transport_type, detailed_stats) in stats {
             // 检查长时间无活动的传输
             if let Some(last_success) = detailed_stats.last_success {
-                if let Ok(elapsed) = last_success.elapsed() {
-                    if elapsed > Duration::from_secs(300) {
-                        // 5分钟无成功请求，可能需要主动检查
-                        // 这里可以添加主动ping逻辑
-                    }
+                if self.monitor.clock().system_elapsed(last_success) > Duration::from_secs(300) {
+                    // 5分钟无成功请求，可能需要主动检查
+                    // 这里可以添加主动ping逻辑
                 }
             }
             
             // 检查长时间不健康的传输
             if detailed_stats.upstream_status == UpstreamStatus::Unavailable {
-                if let Ok(elapsed) = detailed_stats.status_changed_at.elapsed() {
-                    if elapsed > self.monitor.config().max_unavailable_duration {
-                        // 给予恢复机会
-                        self.monitor.set_upstream_status(&transport_type, UpstreamStatus::Unknown);
-                    }
+                let elapsed = self.monitor.clock().system_elapsed(detailed_stats.status_changed_at);
+                if elapsed > self.monitor.config().max_unavailable_duration {
+                    // 给予恢复机会
+                    self.monitor.set_status(&transport_type, UpstreamStatus::Unknown, StatusTrigger::UnavailableTimeout);
                 }
             }
         }
+        
+        if let Some((prober, max_silence)) = &self.silence_probe {
+            self.probe_silent_upstreams(prober.as_ref(), *max_silence).await;
+        }
+        
+        self.monitor.evaluate_slos();
+    }
+    
+    /// 探测超过 `max_silence` 没有查询或探测的上游，不论其当前状态
+    /// 
+    /// 查询策略长期不选用的上游（例如智能策略下曾经表现差的上游、备用层级的上游）到故障转移时才发现不能用就晚了。
+    /// 探测前先衰减统计窗口；探测失败与查询失败一样计入连续失败，达到阈值时上游在被真正用到之前就被判为不可用
+    async fn probe_silent_upstreams(&self, prober: &dyn UpstreamProber, max_silence: Duration) {
+        let silent: Vec<String> = prober.upstreams()
+            .into_iter()
+            .filter(|name| self.monitor.silent_for(name) >= max_silence)
+            .collect();
+        let probes = silent.iter().map(|name| async move {
+            self.monitor.decay_window(name);
+            let (outcome, duration) = prober.probe(name).await;
+            if outcome == ProbeOutcome::Success {
+                dns_info!("上游 {} 已静默超过 {:?}，探测有应答", name, max_silence);
+            } else {
+                dns_warn!("上游 {} 已静默超过 {:?}，探测失败: {}", name, max_silence, outcome.as_str());
+            }
+            self.monitor.record_probe(name, ProbeReason::Staleness, outcome, duration);
+        });
+        futures::future::join_all(probes).await;
+    }
+}
+
+/// 上游监控后台任务的句柄，释放或 [`stop`](Self::stop) 时中止任务
+#[derive(Debug)]
+pub(crate) struct UpstreamMonitorHandle {
+    task: JoinHandle<()>,
+}
+
+impl UpstreamMonitorHandle {
+    /// 中止监控任务，可以重复调用
+    pub(crate) fn stop(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for UpstreamMonitorHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::clock::TestClock;
+
+    fn monitor_with(clock: Arc<TestClock>) -> Arc<UpstreamMonitor> {
+        monitor_with_window(clock, 100)
+    }
+
+    fn monitor_with_window(clock: Arc<TestClock>, stats_window_size: usize) -> Arc<UpstreamMonitor> {
+        Arc::new(UpstreamMonitor::with_clock(
+            Duration::from_secs(30),
+            UpstreamConfig {
+                min_success_rate: 0.7,
+                max_avg_response_time: Duration::from_secs(5),
+                max_consecutive_failures: 3,
+                recovery_success_count: 2,
+                stats_window_size,
+                max_unavailable_duration: Duration::from_secs(300),
+            },
+            clock,
+        ))
+    }
+
+    #[test]
+    fn test_status_follows_window_not_lifetime_totals() {
+        let monitor = monitor_with_window(Arc::new(TestClock::new()), 10);
+        for _ in 0..10 {
+            monitor.record_success("udp", Duration::from_secs(4));
+        }
+        for _ in 0..40 {
+            monitor.record_failure("udp");
+        }
+        assert_eq!(monitor.get_upstream_status("udp"), UpstreamStatus::Unavailable);
+
+        // 累计成功率只有约三成，但窗口内已全是快速的成功
+        for _ in 0..10 {
+            monitor.record_success("udp", Duration::from_millis(20));
+        }
+        for _ in 0..5 {
+            monitor.record_success("udp", Duration::from_millis(20));
+            assert_eq!(monitor.get_upstream_status("udp"), UpstreamStatus::Available);
+        }
+
+        let stats = &monitor.get_detailed_stats()["udp"];
+        assert_eq!((stats.success_count, stats.failure_count), (25, 40));
+        assert_eq!((stats.window_success_count, stats.window_failure_count), (10, 0));
+        // 早先4秒的响应时间已移出窗口
+        assert_eq!(stats.avg_response_time, 20);
+        assert_eq!(monitor.get_stats()["udp"], (25, 40, Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn test_silence_is_measured_and_window_decays() {
+        let clock = Arc::new(TestClock::new());
+        let monitor = monitor_with(clock.clone());
+        assert_eq!(monitor.silent_for("udp"), Duration::ZERO);
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(monitor.silent_for("udp"), Duration::from_secs(90));
+
+        for _ in 0..20 {
+            monitor.record_success("udp", Duration::from_millis(10));
+        }
+        assert_eq!(monitor.silent_for("udp"), Duration::ZERO);
+        monitor.decay_window("udp");
+        assert_eq!(monitor.get_detailed_stats()["udp"].window_success_count, 10);
+        // 至少保留连续失败阈值（3）次结果
+        for _ in 0..5 {
+            monitor.decay_window("udp");
+        }
+        let stats = &monitor.get_detailed_stats()["udp"];
+        assert_eq!((stats.window_success_count, stats.success_count), (3, 20));
+        assert_eq!(stats.upstream_status, UpstreamStatus::Available);
+    }
+
+    #[test]
+    fn test_concurrent_records_are_all_counted() {
+        let monitor = monitor_with_window(Arc::new(TestClock::new()), 64);
+        std::thread::scope(|scope| {
+            for worker in 0..16 {
+                let monitor = &monitor;
+                scope.spawn(move || {
+                    for i in 0..1000 {
+                        let name = if worker % 2 == 0 { "shared" } else { "other" };
+                        if i % 10 == 0 {
+                            monitor.record_failure(name);
+                        } else {
+                            monitor.record_success(name, Duration::from_millis(5));
+                        }
+                    }
+                });
+            }
+        });
+        let stats = monitor.get_stats();
+        assert_eq!(stats["shared"].0 + stats["shared"].1, 8000);
+        assert_eq!(stats["other"], (7200, 800, Duration::from_millis(5)));
+        let detailed = &monitor.get_detailed_stats()["shared"];
+        assert_eq!(detailed.window_success_count + detailed.window_failure_count, 64);
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_recovers_to_unknown_after_timer() {
+        let clock = Arc::new(TestClock::new());
+        let monitor = monitor_with(clock.clone());
+        for _ in 0..3 {
+            monitor.record_failure("udp");
+        }
+        assert_eq!(monitor.get_upstream_status("udp"), UpstreamStatus::Unavailable);
+
+        let task = UpstreamMonitorTask::new(monitor.clone());
+        clock.advance(Duration::from_secs(300));
+        task.perform_upstream_monitoring().await;
+        assert_eq!(monitor.get_upstream_status("udp"), UpstreamStatus::Unavailable);
+
+        clock.advance(Duration::from_secs(1));
+        task.perform_upstream_monitoring().await;
+        assert_eq!(monitor.get_upstream_status("udp"), UpstreamStatus::Unknown);
+    }
+
+    #[test]
+    fn test_long_unavailable_gets_recovery_chance_on_success() {
+        let clock = Arc::new(TestClock::new());
+        let monitor = monitor_with(clock.clone());
+        for _ in 0..3 {
+            monitor.record_failure("tcp");
+        }
+
+        // 未超过最长不可用时间：一次成功不足以恢复
+        monitor.record_success("tcp", Duration::from_millis(20));
+        assert_eq!(monitor.get_upstream_status("tcp"), UpstreamStatus::Unavailable);
+
+        monitor.record_failure("tcp");
+        clock.advance(Duration::from_secs(301));
+        monitor.record_success("tcp", Duration::from_millis(20));
+        assert_eq!(monitor.get_upstream_status("tcp"), UpstreamStatus::Available);
+    }
+
+    #[test]
+    fn test_certificate_error_marks_unavailable_immediately() {
+        use crate::error::TlsErrorKind;
+
+        let monitor = monitor_with(Arc::new(TestClock::new()));
+        monitor.record_error("HTTPS", &DnsError::HttpStatus {
+            status: 503,
+            upstream: "doh".to_string(),
+            body_snippet: None,
+            retry_after: None,
+        });
+        assert_eq!(monitor.get_upstream_status("HTTPS"), UpstreamStatus::Unknown);
+
+        monitor.record_error("TLS", &DnsError::TlsFailure {
+            kind: TlsErrorKind::CertificateInvalid,
+            upstream: "127.0.0.1:853".to_string(),
+        });
+        assert_eq!(monitor.get_upstream_status("TLS"), UpstreamStatus::Unavailable);
+    }
+
+    #[test]
+    fn test_network_errors_counted_by_kind() {
+        let network = |kind| DnsError::Network { kind, upstream: "192.0.2.53:53".to_string(), message: String::new() };
+        let monitor = monitor_with(Arc::new(TestClock::new()));
+        monitor.record_error("udp", &DnsError::Timeout { phase: None });
+        monitor.record_error("udp", &network(NetworkErrorKind::HostUnreachable));
+        monitor.record_error("udp", &DnsError::Timeout { phase: None });
+        monitor.record_error("udp", &DnsError::Protocol("bad".to_string()));
+        let stats = monitor.detailed_stats("udp").unwrap();
+        assert_eq!(stats.network_errors[&NetworkErrorKind::TimedOut], 2);
+        assert_eq!(stats.network_errors[&NetworkErrorKind::HostUnreachable], 1);
+        assert_eq!(stats.recent_failure_summary(), "2x TimedOut, 1x HostUnreachable");
+        assert!(monitor.detailed_stats("tcp").is_none());
+
+        // 只保留最近几次
+        for _ in 0..RECENT_FAILURE_KINDS {
+            monitor.record_error("udp", &DnsError::Timeout { phase: None });
+        }
+        assert_eq!(monitor.detailed_stats("udp").unwrap().recent_failure_summary(), "5x TimedOut");
+    }
+
+    #[test]
+    fn test_disabled_detailed_stats_still_track_status() {
+        let clock = Arc::new(TestClock::new());
+        let monitor = UpstreamMonitor::with_config(Duration::from_secs(30), monitor_with(clock).config().clone())
+            .with_detailed_stats(false);
+        monitor.record_success("udp", Duration::from_millis(20));
+        monitor.record_peer("udp", "192.0.2.53:53".parse().unwrap());
+        for _ in 0..3 {
+            monitor.record_error("udp", &DnsError::Timeout { phase: None });
+        }
+
+        let stats = monitor.detailed_stats("udp").unwrap();
+        assert_eq!((stats.success_count, stats.failure_count), (0, 0));
+        assert_eq!((stats.last_success, stats.last_failure, stats.last_peer), (None, None, None));
+        assert!(stats.network_errors.is_empty() && stats.recent_failures.is_empty());
+        assert_eq!((stats.window_success_count, stats.window_failure_count, stats.consecutive_failures), (1, 3, 3));
+        assert_eq!(stats.upstream_status, UpstreamStatus::Unavailable);
+    }
+
+    #[test]
+    fn test_persistent_refusal_marks_unavailable() {
+        let refused = DnsError::Network {
+            kind: NetworkErrorKind::ConnectionRefused,
+            upstream: "127.0.0.1:53".to_string(),
+            message: String::new(),
+        };
+        let monitor = monitor_with(Arc::new(TestClock::new()));
+        monitor.record_error("tcp", &refused);
+        assert_eq!(monitor.get_upstream_status("tcp"), UpstreamStatus::Unknown);
+        monitor.record_error("tcp", &refused);
+        assert_eq!(monitor.get_upstream_status("tcp"), UpstreamStatus::Unavailable);
+
+        // 中间有成功时重新计数
+        monitor.record_error("udp", &refused);
+        monitor.record_success("udp", Duration::from_millis(20));
+        monitor.record_error("udp", &refused);
+        assert_eq!(monitor.get_upstream_status("udp"), UpstreamStatus::Available);
     }
-}
\ No newline at end of file
+
+    fn transitions(monitor: &UpstreamMonitor, name: &str) -> Vec<(UpstreamStatus, UpstreamStatus, StatusTrigger)> {
+        monitor.get_upstream_events(name, None).into_iter()
+            .map(|event| (event.old_status, event.new_status, event.trigger))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_status_changes_are_recorded_with_triggers() {
+        use UpstreamStatus::{Available, Unavailable, Unknown};
+
+        let clock = Arc::new(TestClock::new());
+        let monitor = monitor_with(clock.clone());
+        // 足够多的成功让窗口成功率在连续失败达到阈值时仍高于下限
+        for _ in 0..10 {
+            monitor.record_success("doh", Duration::from_millis(20));
+        }
+        let failed_at = {
+            clock.advance(Duration::from_secs(10));
+            clock.now_system()
+        };
+        for _ in 0..3 {
+            monitor.record_failure("doh");
+        }
+        clock.advance(Duration::from_secs(10));
+        for _ in 0..2 {
+            monitor.record_success("doh", Duration::from_millis(20));
+        }
+        monitor.set_upstream_status("doh", Unavailable);
+        clock.advance(Duration::from_secs(301));
+        UpstreamMonitorTask::new(monitor.clone()).perform_upstream_monitoring().await;
+
+        assert_eq!(transitions(&monitor, "doh"), vec![
+            (Unknown, Available, StatusTrigger::Recovery),
+            (Available, Unavailable, StatusTrigger::ConsecutiveFailures),
+            (Unavailable, Available, StatusTrigger::Recovery),
+            (Available, Unavailable, StatusTrigger::Manual),
+            (Unavailable, Unknown, StatusTrigger::UnavailableTimeout),
+        ]);
+        let events = monitor.get_upstream_events("doh", Some(failed_at));
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].timestamp, failed_at);
+        assert_eq!(events[1].timestamp, failed_at + Duration::from_secs(10));
+        assert_eq!(events.last().unwrap().timestamp, monitor.detailed_stats("doh").unwrap().status_changed_at);
+        assert!(monitor.get_upstream_events("doh", Some(clock.now_system() + Duration::from_secs(1))).is_empty());
+        assert!(monitor.get_upstream_events("dot", None).is_empty());
+    }
+
+    #[test]
+    fn test_threshold_breaches_name_their_trigger() {
+        use crate::error::TlsErrorKind;
+        use UpstreamStatus::{Available, Unavailable, Unknown};
+
+        let monitor = monitor_with(Arc::new(TestClock::new()));
+        for _ in 0..3 {
+            monitor.record_success("slow", Duration::from_secs(6));
+        }
+        assert_eq!(transitions(&monitor, "slow"), vec![(Unknown, Unavailable, StatusTrigger::ResponseTime)]);
+
+        // 成功穿插在失败之间，连续失败次数始终不到阈值
+        monitor.record_success("flaky", Duration::from_millis(20));
+        monitor.record_failure("flaky");
+        monitor.record_success("flaky", Duration::from_millis(20));
+        monitor.record_failure("flaky");
+        monitor.record_failure("flaky");
+        assert_eq!(transitions(&monitor, "flaky"), vec![
+            (Unknown, Available, StatusTrigger::Recovery),
+            (Available, Unavailable, StatusTrigger::SuccessRate),
+        ]);
+
+        monitor.record_error("dot", &DnsError::TlsFailure {
+            kind: TlsErrorKind::CertificateInvalid,
+            upstream: "127.0.0.1:853".to_string(),
+        });
+        assert_eq!(transitions(&monitor, "dot"), vec![(Unknown, Unavailable, StatusTrigger::PersistentError)]);
+        // 状态不变时不记录
+        monitor.set_upstream_status("dot", Unavailable);
+        assert_eq!(transitions(&monitor, "dot").len(), 1);
+    }
+
+    #[test]
+    fn test_event_history_keeps_the_most_recent_events() {
+        let clock = Arc::new(TestClock::new());
+        let monitor = UpstreamMonitor::with_config(Duration::from_secs(30), monitor_with(clock.clone()).config().clone())
+            .with_event_history(3);
+        for round in 0..5 {
+            clock.advance(Duration::from_secs(1));
+            let status = if round % 2 == 0 { UpstreamStatus::Unavailable } else { UpstreamStatus::Available };
+            monitor.set_upstream_status("udp", status);
+        }
+        let events = monitor.get_upstream_events("udp", None);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].old_status, UpstreamStatus::Available);
+        assert_eq!(events[0].new_status, UpstreamStatus::Unavailable);
+        assert!(events.windows(2).all(|pair| pair[0].timestamp < pair[1].timestamp));
+
+        let disabled = UpstreamMonitor::with_config(Duration::from_secs(30), monitor.config().clone())
+            .with_event_history(0);
+        disabled.set_upstream_status("udp", UpstreamStatus::Unavailable);
+        assert_eq!(disabled.get_upstream_status("udp"), UpstreamStatus::Unavailable);
+        assert!(disabled.get_upstream_events("udp", None).is_empty());
+
+        monitor.reset_stats("udp");
+        assert!(monitor.get_upstream_events("udp", None).is_empty());
+    }
+    
+    #[derive(Debug, Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<(&'static str, String, Duration, Duration)>>,
+    }
+    
+    impl QueryObserver for RecordingObserver {
+        fn on_slo_breach(&self, upstream: &str, observed_p95: Duration, threshold: Duration) {
+            self.events.lock().unwrap().push(("breach", upstream.to_string(), observed_p95, threshold));
+        }
+        
+        fn on_slo_recovery(&self, upstream: &str, observed_p95: Duration, threshold: Duration) {
+            self.events.lock().unwrap().push(("recovery", upstream.to_string(), observed_p95, threshold));
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_slo_breach_and_recovery_are_sustained_and_reported_once() {
+        let clock = Arc::new(TestClock::new());
+        let observer = Arc::new(RecordingObserver::default());
+        let monitor = Arc::new(
+            Arc::try_unwrap(monitor_with_window(clock.clone(), 10)).unwrap()
+                .with_observer(Some(observer.clone()))
+        );
+        let threshold = Duration::from_millis(100);
+        monitor.set_slo("udp", Some(SloConfig::new(threshold, 5, Duration::from_secs(60))));
+        let task = UpstreamMonitorTask::new(monitor.clone());
+        let fill = |latency_ms: u64| {
+            for _ in 0..10 {
+                monitor.record_success("udp", Duration::from_millis(latency_ms));
+            }
+        };
+        
+        fill(500);
+        task.perform_upstream_monitoring().await;
+        clock.advance(Duration::from_secs(40));
+        // 未持续到60秒就回到阈值以内，不通知
+        fill(20);
+        task.perform_upstream_monitoring().await;
+        fill(500);
+        clock.advance(Duration::from_secs(30));
+        task.perform_upstream_monitoring().await;
+        clock.advance(Duration::from_secs(59));
+        task.perform_upstream_monitoring().await;
+        assert!(observer.events.lock().unwrap().is_empty());
+        assert!(!monitor.slo_status("udp").unwrap().breached);
+        
+        clock.advance(Duration::from_secs(1));
+        task.perform_upstream_monitoring().await;
+        clock.advance(Duration::from_secs(60));
+        task.perform_upstream_monitoring().await;
+        let status = monitor.slo_status("udp").unwrap();
+        assert!(status.breached);
+        assert_eq!(status.observed_p95, Some(Duration::from_millis(500)));
+        
+        fill(20);
+        task.perform_upstream_monitoring().await;
+        clock.advance(Duration::from_secs(60));
+        task.perform_upstream_monitoring().await;
+        task.perform_upstream_monitoring().await;
+        assert!(!monitor.slo_status("udp").unwrap().breached);
+        
+        assert_eq!(*observer.events.lock().unwrap(), vec![
+            ("breach", "udp".to_string(), Duration::from_millis(500), threshold),
+            ("recovery", "udp".to_string(), Duration::from_millis(20), threshold),
+        ]);
+        
+        // 重置统计不影响SLO配置，取消后不再有判定
+        monitor.reset_stats("udp");
+        assert!(monitor.slo_status("udp").is_some());
+        monitor.set_slo("udp", None);
+        assert!(monitor.slo_status("udp").is_none());
+    }
+}
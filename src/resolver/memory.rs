@@ -0,0 +1,458 @@
+//! 全局内存预算
+//!
+//! 缓存、查询历史、上游事件和热门名称各自有上限，但合起来仍可能超出嵌入式部署给定的内存。
+//! 配置了 [`MemoryBudget`] 时，这些组件在变更后报告估算的占用（条目数乘以估算的条目大小，
+//! 只更新原子计数）；总占用超出预算时按 [`MemoryComponent::SHRINK_ORDER`] 依次收缩，
+//! 直到回落到预算的7/8以下，留出余量避免每次写入都触发收缩。未配置预算时各组件不做任何统计。
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+/// 计入内存预算的组件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MemoryComponent {
+    /// 查询历史
+    QueryHistory,
+    /// 各上游的状态变化事件
+    UpstreamEvents,
+    /// 缓存概况中的热门名称
+    HotNames,
+    /// DNS缓存条目
+    Cache,
+}
+
+impl MemoryComponent {
+    /// 超出预算时的收缩顺序：先丢弃诊断用的历史，最后才淘汰缓存条目
+    pub const SHRINK_ORDER: [MemoryComponent; 4] = [
+        MemoryComponent::QueryHistory,
+        MemoryComponent::UpstreamEvents,
+        MemoryComponent::HotNames,
+        MemoryComponent::Cache,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for MemoryComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MemoryComponent::QueryHistory => "query_history",
+            MemoryComponent::UpstreamEvents => "upstream_events",
+            MemoryComponent::HotNames => "hot_names",
+            MemoryComponent::Cache => "cache",
+        };
+        f.write_str(name)
+    }
+}
+
+/// 一个组件的内存占用
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComponentMemory {
+    /// 组件
+    pub component: MemoryComponent,
+    /// 估算占用的字节数
+    pub approx_bytes: usize,
+    /// 因超出预算被收缩的次数
+    pub shrinks: u64,
+}
+
+/// 内存预算的使用情况
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryStats {
+    /// 预算（字节）
+    pub limit_bytes: usize,
+    /// 各组件估算占用之和
+    pub used_bytes: usize,
+    /// 各组件的占用，按收缩顺序
+    pub components: Vec<ComponentMemory>,
+}
+
+impl MemoryStats {
+    /// 指定组件的占用
+    pub fn component(&self, component: MemoryComponent) -> Option<&ComponentMemory> {
+        self.components.iter().find(|usage| usage.component == component)
+    }
+}
+
+/// 收缩回调：参数为希望释放的字节数，返回实际释放的字节数；组件已被释放时返回None
+type Shrinker = Box<dyn Fn(usize) -> Option<usize> + Send + Sync>;
+
+/// 各组件共享的内存预算
+pub struct MemoryBudget {
+    limit: usize,
+    usage: [AtomicIsize; 4],
+    shrinks: [AtomicU64; 4],
+    shrinkers: Mutex<Vec<(MemoryComponent, Shrinker)>>,
+    /// 正在收缩：收缩中组件报告占用时不再重入
+    shrinking: AtomicBool,
+}
+
+impl fmt::Debug for MemoryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryBudget")
+            .field("limit", &self.limit)
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+impl MemoryBudget {
+    /// 创建总量为 `limit` 字节的预算
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            usage: Default::default(),
+            shrinks: Default::default(),
+            shrinkers: Mutex::new(Vec::new()),
+            shrinking: AtomicBool::new(false),
+        }
+    }
+
+    /// 预算（字节）
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// 各组件估算占用之和
+    pub fn used(&self) -> usize {
+        MemoryComponent::SHRINK_ORDER.iter().map(|&component| self.usage(component)).sum()
+    }
+
+    /// 指定组件的估算占用
+    pub fn usage(&self, component: MemoryComponent) -> usize {
+        self.usage[component.index()].load(Ordering::Relaxed).max(0) as usize
+    }
+
+    /// 使用情况快照
+    pub fn stats(&self) -> MemoryStats {
+        MemoryStats {
+            limit_bytes: self.limit,
+            used_bytes: self.used(),
+            components: MemoryComponent::SHRINK_ORDER.iter()
+                .map(|&component| ComponentMemory {
+                    component,
+                    approx_bytes: self.usage(component),
+                    shrinks: self.shrinks[component.index()].load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+
+    /// 注册组件的收缩回调，回调返回None（组件已被释放）时自动移除
+    ///
+    /// 回调在收缩时调用，调用前不持有任何组件的锁；回调内报告占用不会重入收缩
+    pub(crate) fn register(&self, component: MemoryComponent, shrinker: impl Fn(usize) -> Option<usize> + Send + Sync + 'static) {
+        self.lock_shrinkers().push((component, Box::new(shrinker)));
+    }
+
+    /// 总占用超出预算时按收缩顺序收缩各组件，直到回落到预算的7/8以下或没有可收缩的内容
+    ///
+    /// 已有其他线程在收缩时直接返回
+    pub fn enforce(&self) {
+        if self.used() <= self.limit || self.shrinking.swap(true, Ordering::Acquire) {
+            return;
+        }
+        let _guard = ShrinkingGuard(&self.shrinking);
+        let target = self.limit - self.limit / 8;
+        let mut shrinkers = self.lock_shrinkers();
+        for component in MemoryComponent::SHRINK_ORDER {
+            let used = self.used();
+            if used <= target {
+                break;
+            }
+            let wanted = (used - target).min(self.usage(component));
+            if wanted == 0 {
+                continue;
+            }
+            let mut freed = 0;
+            shrinkers.retain(|(registered, shrink)| {
+                if *registered != component || freed >= wanted {
+                    return true;
+                }
+                match shrink(wanted - freed) {
+                    Some(bytes) => {
+                        freed += bytes;
+                        true
+                    },
+                    None => false,
+                }
+            });
+            if freed > 0 {
+                self.shrinks[component.index()].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn lock_shrinkers(&self) -> std::sync::MutexGuard<'_, Vec<(MemoryComponent, Shrinker)>> {
+        self.shrinkers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// 收缩结束（含回调panic）时清除收缩标记
+struct ShrinkingGuard<'a>(&'a AtomicBool);
+
+impl Drop for ShrinkingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// 一个组件实例向预算报告占用的句柄，释放时撤回报告的占用
+///
+/// 同一组件可以有多个实例（如分片缓存的各个分片），各自报告自己的部分
+#[derive(Debug)]
+pub(crate) struct MemoryMeter {
+    budget: Arc<MemoryBudget>,
+    component: MemoryComponent,
+    reported: AtomicIsize,
+}
+
+impl MemoryMeter {
+    pub(crate) fn new(budget: Arc<MemoryBudget>, component: MemoryComponent) -> Self {
+        Self { budget, component, reported: AtomicIsize::new(0) }
+    }
+
+    /// 报告当前占用；占用增加时检查预算
+    ///
+    /// 调用时不能持有组件自己的锁：超出预算时会在当前线程调用各组件的收缩回调
+    pub(crate) fn set(&self, bytes: usize) {
+        if self.apply(bytes) {
+            self.budget.enforce();
+        }
+    }
+
+    /// 更新占用，返回是否增加
+    fn apply(&self, bytes: usize) -> bool {
+        let bytes = bytes.min(isize::MAX as usize) as isize;
+        let delta = bytes - self.reported.swap(bytes, Ordering::Relaxed);
+        self.budget.usage[self.component.index()].fetch_add(delta, Ordering::Relaxed);
+        delta > 0
+    }
+}
+
+impl Drop for MemoryMeter {
+    fn drop(&mut self) {
+        self.apply(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::cache::{CacheJanitorConfig, DnsCache, DEFAULT_ENTRY_BYTES};
+    use super::super::cache_backend::CacheLayer;
+    use super::super::health::{UpstreamConfig, UpstreamEvent, UpstreamMonitor, UpstreamStatus};
+    use crate::builder::history::{QueryHistory, QueryHistoryEntry, QueryOutcome};
+    use crate::builder::strategy::QueryStrategy;
+    use crate::builder::types::DnsRecordType;
+    use crate::types::{Flags, QClass, Query, Record, RecordData, RecordType, Response};
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+    use crate::time::SystemTime;
+
+    /// 按请求释放、并把释放后的占用报告给预算的假组件
+    fn component(budget: &Arc<MemoryBudget>, component: MemoryComponent, bytes: usize) -> Arc<(MemoryMeter, AtomicUsize)> {
+        let state = Arc::new((MemoryMeter::new(budget.clone(), component), AtomicUsize::new(bytes)));
+        let weak = Arc::downgrade(&state);
+        budget.register(component, move |wanted| {
+            let state = weak.upgrade()?;
+            let freed = wanted.min(state.1.load(Ordering::Relaxed));
+            let left = state.1.fetch_sub(freed, Ordering::Relaxed) - freed;
+            state.0.set(left);
+            Some(freed)
+        });
+        state
+    }
+
+    #[test]
+    fn test_shrinks_in_order_until_under_budget() {
+        let budget = Arc::new(MemoryBudget::new(1000));
+        let history = component(&budget, MemoryComponent::QueryHistory, 300);
+        let cache = component(&budget, MemoryComponent::Cache, 600);
+        history.0.set(300);
+        cache.0.set(600);
+        assert_eq!(budget.used(), 900);
+        assert!(budget.stats().components.iter().all(|usage| usage.shrinks == 0));
+
+        // 超出后收缩到875字节以下：历史先释放300，缓存再释放余下的部分
+        cache.1.store(1000, Ordering::Relaxed);
+        cache.0.set(1000);
+        let stats = budget.stats();
+        assert!(stats.used_bytes <= 875, "{:?}", stats);
+        assert_eq!(stats.component(MemoryComponent::QueryHistory).unwrap().approx_bytes, 0);
+        assert_eq!(stats.component(MemoryComponent::Cache).unwrap().approx_bytes, 875);
+        assert_eq!(stats.component(MemoryComponent::QueryHistory).unwrap().shrinks, 1);
+        assert_eq!(stats.component(MemoryComponent::HotNames).unwrap().shrinks, 0);
+        assert_eq!(stats.component(MemoryComponent::Cache).unwrap().shrinks, 1);
+    }
+
+    #[test]
+    fn test_dropped_component_releases_usage() {
+        let budget = Arc::new(MemoryBudget::new(100));
+        let history = component(&budget, MemoryComponent::QueryHistory, 80);
+        history.0.set(80);
+        drop(history);
+        assert_eq!(budget.used(), 0);
+
+        let cache = component(&budget, MemoryComponent::Cache, 200);
+        cache.0.set(200);
+        assert!(budget.used() <= 88);
+    }
+
+    fn query(name: &str) -> Query {
+        Query { name: name.to_string(), qtype: RecordType::A, qclass: QClass::IN }
+    }
+
+    fn response(name: &str) -> Response {
+        Response {
+            id: 1,
+            flags: Flags { qr: true, ..Flags::default() },
+            queries: vec![query(name)],
+            answers: vec![Record {
+                name: name.to_string(),
+                rtype: RecordType::A,
+                class: QClass::IN,
+                ttl: 300,
+                data: RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            }],
+            authorities: vec![],
+            additionals: vec![],
+        }
+    }
+
+    fn history_entry(domain: &str) -> QueryHistoryEntry {
+        QueryHistoryEntry {
+            sequence: 0,
+            timestamp: SystemTime::now(),
+            domain: Arc::from(domain),
+            record_type: DnsRecordType::A,
+            strategy: QueryStrategy::Smart,
+            upstream: Some(Arc::from("udp")),
+            outcome: QueryOutcome::Success { rcode: 0 },
+            duration: Duration::from_millis(5),
+            cache_hit: false,
+            failovers: Vec::new(),
+        }
+    }
+
+    /// 查询历史、上游事件和缓存，`budget` 为None时不计入预算
+    struct Components {
+        history: Arc<QueryHistory>,
+        monitor: Arc<UpstreamMonitor>,
+        cache: Arc<DnsCache>,
+        _layer: CacheLayer,
+    }
+
+    impl Components {
+        fn new(budget: Option<&Arc<MemoryBudget>>) -> Self {
+            let history = Arc::new(QueryHistory::new(64).unwrap());
+            let monitor = Arc::new(UpstreamMonitor::with_config(Duration::from_secs(30), UpstreamConfig {
+                min_success_rate: 0.7,
+                max_avg_response_time: Duration::from_secs(5),
+                max_consecutive_failures: 3,
+                recovery_success_count: 2,
+                stats_window_size: 100,
+                max_unavailable_duration: Duration::from_secs(300),
+            }));
+            let cache = Arc::new(DnsCache::new(Duration::from_secs(3600)));
+            let layer = CacheLayer::new(cache.clone(), false);
+            if let Some(budget) = budget {
+                history.attach_memory_budget(budget);
+                monitor.attach_memory_budget(budget);
+                layer.attach_memory_budget(budget);
+            }
+            Self { history, monitor, cache, _layer: layer }
+        }
+
+        /// 40条历史、每个上游10个事件，以及一个命中过的缓存条目和统计出的热门名称
+        fn fill_diagnostics(&self) {
+            self.cache.insert(query("keep.example.com"), response("keep.example.com"));
+            self.cache.get(&query("keep.example.com"));
+            self.cache.janitor_batch(&CacheJanitorConfig { track_hot_names: Some(4), ..CacheJanitorConfig::default() });
+            assert_eq!(self.cache.insights().unwrap().hot_names.len(), 1);
+            for i in 0..40 {
+                self.history.record(history_entry(&format!("h{}.example.com", i)));
+            }
+            for upstream in ["udp", "doh"] {
+                for round in 0..10 {
+                    let status = if round % 2 == 0 { UpstreamStatus::Unavailable } else { UpstreamStatus::Available };
+                    self.monitor.set_upstream_status(upstream, status);
+                }
+            }
+        }
+
+        fn events(&self) -> usize {
+            ["udp", "doh"].iter().map(|upstream| self.monitor.get_upstream_events(upstream, None).len()).sum()
+        }
+    }
+
+    #[test]
+    fn test_components_shrink_in_order_within_budget() {
+        let probe = Components::new(None);
+        probe.fill_diagnostics();
+        let diagnostics = probe.history.approx_memory_bytes() + probe.events() * size_of::<UpstreamEvent>();
+        let limit = diagnostics + 16 * DEFAULT_ENTRY_BYTES;
+        assert_eq!(probe.cache.insights().unwrap().hot_names.len(), 1);
+
+        let budget = Arc::new(MemoryBudget::new(limit));
+        let components = Components::new(Some(&budget));
+        components.fill_diagnostics();
+        let events = budget.usage(MemoryComponent::UpstreamEvents);
+        assert!(budget.usage(MemoryComponent::HotNames) > 0);
+        assert!(budget.used() < limit);
+
+        for i in 0..200 {
+            let name = format!("n{}.example.com", i);
+            components.cache.insert(query(&name), response(&name));
+            assert!(components.cache.get(&query("keep.example.com")).is_some(), "最近使用的条目被淘汰");
+
+            let stats = budget.stats();
+            let usage = |component| stats.component(component).unwrap().clone();
+            assert!(stats.used_bytes <= limit, "{:?}", stats);
+            // 上游事件开始收缩前查询历史已经清空，缓存开始淘汰前两者都已清空
+            if usage(MemoryComponent::UpstreamEvents).approx_bytes < events {
+                assert_eq!(usage(MemoryComponent::QueryHistory).approx_bytes, 0);
+            }
+            if usage(MemoryComponent::HotNames).approx_bytes == 0 {
+                assert_eq!(usage(MemoryComponent::UpstreamEvents).approx_bytes, 0);
+            }
+            if usage(MemoryComponent::Cache).shrinks > 0 {
+                assert_eq!(usage(MemoryComponent::QueryHistory).approx_bytes, 0);
+                assert_eq!(usage(MemoryComponent::UpstreamEvents).approx_bytes, 0);
+                assert_eq!(usage(MemoryComponent::HotNames).approx_bytes, 0);
+            }
+        }
+
+        let stats = budget.stats();
+        assert!(components.history.recent(usize::MAX).is_empty());
+        assert_eq!(components.events(), 0);
+        assert!(components.cache.size() < 201);
+        assert!(components.cache.get(&query("n199.example.com")).is_some());
+        assert!(components.cache.insights().unwrap().hot_names.is_empty());
+        for component in MemoryComponent::SHRINK_ORDER {
+            assert!(stats.component(component).unwrap().shrinks > 0, "{} 没有收缩", component);
+        }
+        assert_eq!(components.cache.stats().evictions as usize, 201 - components.cache.size());
+    }
+
+    #[test]
+    fn test_no_budget_keeps_everything() {
+        let components = Components::new(None);
+        components.fill_diagnostics();
+        for i in 0..200 {
+            let name = format!("n{}.example.com", i);
+            components.cache.insert(query(&name), response(&name));
+        }
+        assert_eq!(components.history.recent(usize::MAX).len(), 40);
+        assert_eq!(components.events(), 20);
+        assert_eq!(components.cache.size(), 201);
+        assert_eq!(components.cache.insights().unwrap().hot_names.len(), 1);
+        assert_eq!(components.cache.stats().evictions, 0);
+    }
+}
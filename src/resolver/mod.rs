@@ -1,23 +1,197 @@
 //! 智能DNS解析器
 
+use crate::runtime;
 use crate::{Request, Response, Result, DnsError};
-use crate::types::{Query, RecordType, QClass, Flags, ClientAddress};
-use crate::transport::{Transport, UdpTransport, TcpTransport, TlsTransport, HttpsTransport};
-use crate::transport::{TransportConfig, TlsConfig, HttpsConfig};
+use crate::error::RetryAdvice;
+use crate::types::{
+    Query, RecordType, QClass, Flags, ClientAddress, EcsPolicy, ResponseCode, SharedResponse, UpstreamFeatures,
+    EffectiveFeatures, RecordData,
+};
+use crate::transport::{Transport, UdpTransport};
+#[cfg(feature = "tcp")]
+use crate::transport::{TcpTransport, FastOpenStats};
+#[cfg(feature = "dot")]
+use crate::transport::{TlsTransport, TlsSessionStats};
+#[cfg(feature = "doh")]
+use crate::transport::HttpsTransport;
+use crate::transport::{TransportConfig, TransportTiming, WireCapture, DnsCookieJar};
+#[cfg(feature = "dot")]
+use crate::transport::TlsConfig;
+#[cfg(feature = "doh")]
+use crate::transport::HttpsConfig;
+use crate::transport::{AddressFamilyStats, UdpPoolConfig, UdpPoolStats};
+use crate::transport::{BootstrapResolver, HostResolution, RecordLimits};
+use crate::transport::query_id::{randomize_case, restore_case, QueryIds};
+use crate::upstream_handler::{QueueBehavior, UpstreamLabels};
+use crate::utils::normalize_name;
 use std::fmt::Debug;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-use std::net::IpAddr;
-use tokio::time::timeout;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use crate::time::{Instant, SystemTime};
+use std::net::{IpAddr, SocketAddr};
+use tokio::sync::watch;
+use crate::runtime::timeout;
 use std::collections::HashMap;
 use crate::{dns_debug, dns_info, dns_error, dns_transport, dns_warn};
 
+pub mod answer_rewrite;
 pub mod cache;
+pub mod cache_backend;
+pub mod cache_insights;
+pub mod clock;
+pub mod diagnosis;
+pub mod encrypted_fallback;
 pub mod health;
+pub mod offline;
+pub mod priority;
+pub mod quarantine;
+pub mod question;
+pub(crate) mod query_edns;
+pub(crate) mod query_type;
+pub mod random;
+pub mod memory;
+pub mod rate_limit;
+pub mod rotation;
+pub(crate) mod scope;
+pub mod slo;
+pub mod ttl_override;
+#[cfg(feature = "tcp")]
+pub mod zone_transfer;
 
 use crate::builder::strategy::QueryStrategy;
-use cache::DnsCache;
-use health::UpstreamMonitor;
+use crate::builder::consensus::{self, PerUpstreamAnswer, QueryAllReport};
+use crate::builder::types::{RecordSort, UpstreamFilter};
+use crate::builder::stickiness::StickinessConfig;
+use crate::builder::encoded::EncodedRequestLimits;
+use crate::builder::adaptive_timeout::AdaptiveTimeoutConfig;
+use cache::{CacheClearReport, CacheInvalidation, CacheJanitorConfig, CacheKey, CacheRejection, CacheStats, DnsCache, EcsCacheMode, TtlClamp};
+use cache_backend::{CacheJanitor, CacheLayer, DnsCacheBackend};
+use cache_insights::CacheInsights;
+use clock::Clock;
+use memory::{MemoryBudget, MemoryStats};
+use random::RandomSource;
+use rate_limit::{RateLimitState, RateLimitedBehavior};
+use health::{DetailedStats, ProbeConfig, ProbeOutcome, ProbeReason, UpstreamEvent, UpstreamMonitor, UpstreamMonitorHandle, UpstreamMonitorTask, UpstreamProber};
+use slo::{QueryObserver, SloConfig, SloStatus};
+use offline::{OfflineReason, OfflineState, OfflineStats};
+use answer_rewrite::{AnswerRewriteRule, AnswerRewriter, RewriteRuleStats, RewriteStage};
+use question::QuestionPolicy;
+use ttl_override::{TtlOverrideRule, TtlOverrides};
+use rotation::{RecordRotator, RotationMode};
+use encrypted_fallback::{EncryptedFallbackPolicy, EncryptedFallbackState, EncryptedFallbackStats};
+use priority::{GatePermit, PriorityCounters, PriorityGate, PriorityStats, QueryPriority};
+use quarantine::{HandshakeQuarantine, QuarantineStatus, TlsQuarantineConfig};
+
+/// 把一次上游查询的结果计入上游监控
+/// 
+/// 开启了握手失败隔离的上游，握手失败由隔离处理，不计入上游监控
+fn record_outcome(monitor: Option<&UpstreamMonitor>, entry: &NamedTransport, result: &Result<(Response, TransportInfo)>) {
+    let Some(monitor) = monitor else {
+        return;
+    };
+    let name = &entry.name;
+    match result {
+        Ok((_, info)) => {
+            monitor.record_success(name, info.duration);
+            if let Some(peer) = info.peer {
+                monitor.record_peer(name, peer);
+            }
+        },
+        Err(e) if entry.quarantine.is_some() && quarantine::handshake_failure(e).is_some() => {},
+        Err(e) => monitor.record_error(name, e),
+    }
+}
+
+/// 第 `round` 轮探测发送的查询
+fn probe_request(probe: &ProbeConfig, round: usize, enable_edns: bool) -> Request {
+    Request {
+        id: rand::random(),
+        flags: Flags::default(),
+        query: Query {
+            name: probe.domain(round).to_string(),
+            qtype: probe.record_type.into(),
+            qclass: QClass::IN,
+        },
+        client_address: None,
+        enable_edns,
+        dnssec_ok: false,
+        wire_capture_limit: None,
+        priority: QueryPriority::Normal,
+    }
+}
+
+/// 向传输发送一次探测，返回结果和耗时
+async fn send_probe(entry: &NamedTransport, probe: &ProbeConfig, request: &Request) -> (ProbeOutcome, Duration) {
+    let start = entry.clock.now_instant();
+    let outcome = match clock::timeout_at(&*entry.clock, start + probe.timeout, entry.send(request)).await {
+        Some(Ok((response, _))) if probe.accepts(&response) => ProbeOutcome::Success,
+        Some(Ok(_)) => ProbeOutcome::TooFewAnswers,
+        Some(Err(_)) => ProbeOutcome::Failed,
+        None => ProbeOutcome::TimedOut,
+    };
+    (outcome, entry.elapsed_since(start))
+}
+
+/// 上游监控任务探测静默上游所用的传输列表
+/// 
+/// 克隆体共用同一份列表，以最近一次增删传输的实例为准
+#[derive(Debug)]
+struct TransportProber {
+    targets: RwLock<Arc<Vec<NamedTransport>>>,
+    probe: Arc<ProbeConfig>,
+    rounds: Arc<AtomicUsize>,
+    enable_edns: bool,
+}
+
+impl TransportProber {
+    fn set_targets(&self, transports: Arc<Vec<NamedTransport>>) {
+        *self.targets.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = transports;
+    }
+    
+    fn target(&self, name: &str) -> Option<NamedTransport> {
+        let targets = self.targets.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        targets.iter().find(|entry| entry.name == name).cloned()
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamProber for TransportProber {
+    fn upstreams(&self) -> Vec<String> {
+        let targets = self.targets.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        targets.iter()
+            .filter(|entry| entry.enabled && !entry.routed_only && !entry.quarantined())
+            .map(|entry| entry.name.clone())
+            .collect()
+    }
+    
+    async fn probe(&self, name: &str) -> (ProbeOutcome, Duration) {
+        let Some(entry) = self.target(name) else {
+            return (ProbeOutcome::Failed, Duration::ZERO);
+        };
+        let request = probe_request(&self.probe, self.rounds.fetch_add(1, Ordering::Relaxed), self.enable_edns);
+        send_probe(&entry, &self.probe, &request).await
+    }
+}
+
+/// 响应是否为否定应答：NXDOMAIN，或NOERROR但没有回答记录（NODATA）
+fn is_negative_answer(response: &Response) -> bool {
+    match response.rcode() {
+        ResponseCode::NxDomain => true,
+        ResponseCode::NoError => response.answers.is_empty(),
+        _ => false,
+    }
+}
+
+/// 向另一个上游核实否定应答的结果
+enum NegativeCheck {
+    /// 另一个上游给出了肯定答案
+    Overturned(Box<(Response, TransportInfo)>),
+    /// 另一个上游也给出否定应答，或没有其他可用上游
+    Confirmed,
+    /// 核实失败（出错或其他响应码），否定应答照常返回但不缓存
+    Inconclusive,
+}
 
 /// 查询结果
 #[derive(Debug, Clone)]
@@ -28,25 +202,675 @@ pub struct QueryResult {
     pub duration: Duration,
     /// 传输类型
     pub transport_type: String,
+    /// 传输名称
+    pub transport_name: String,
+    /// 各阶段耗时（开启耗时分解且查询成功时）
+    pub timing: Option<TransportTiming>,
+    /// 收发的原始DNS报文（查询要求保留报文且查询成功时）
+    pub wire: Option<WireCapture>,
+    /// 按该传输的ECS策略实际发出的客户端子网
+    pub client_subnet: Option<ClientAddress>,
+    /// 实际交换报文的对端地址（查询成功且传输能取到时）
+    pub peer: Option<SocketAddr>,
+    /// 应答是否来自证书校验失败后的降级路径
+    pub degraded_security: bool,
+    /// 应答是否因超出记录数上限被截去了记录
+    pub records_truncated: bool,
 }
 
-/// 智能DNS解析器
+/// 实际返回响应的传输信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportInfo {
+    /// 传输名称（上游名称，未命名时为端点地址）
+    pub name: String,
+    /// 传输协议（UDP/TCP/TLS/HTTPS）
+    pub protocol: String,
+    /// 该传输的查询耗时
+    pub duration: Duration,
+    /// 各阶段耗时（开启耗时分解时）
+    pub timing: Option<TransportTiming>,
+    /// 收发的原始DNS报文（查询要求保留报文时）
+    pub wire: Option<WireCapture>,
+    /// 实际发出的客户端子网（按上游的ECS策略改写后），决定答案写入哪个缓存键
+    pub client_subnet: Option<ClientAddress>,
+    /// 在该传输应答之前，因SERVFAIL/REFUSED而被放弃的上游（按放弃顺序）
+    pub failovers: Vec<UpstreamFailover>,
+    /// 实际交换报文的对端地址（以主机名配置的上游为解析出的IP），传输取不到时为 `None`
+    pub peer: Option<SocketAddr>,
+    /// 应答是否来自证书校验失败后的降级路径（明文DNS或不校验证书的加密连接）
+    pub degraded_security: bool,
+    /// 应答是否因超出记录数上限被截去了记录，见 [`RecordLimits`]
+    pub records_truncated: bool,
+}
+
+/// 一次跨上游故障转移：该上游的应答被放弃，改用其他上游
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamFailover {
+    /// 被放弃的上游名称
+    pub upstream: String,
+    /// 触发转移的响应码
+    pub rcode: ResponseCode,
+}
+
+/// 需要换一个上游重试的响应码
+/// 
+/// SERVFAIL和REFUSED通常是单个上游自身的问题（递归失败、访问控制），换上游可能得到正常应答；
+/// NXDOMAIN和NODATA是对域名本身的回答，换上游没有意义
+pub(crate) fn failover_rcode(response: &Response) -> Option<ResponseCode> {
+    match response.rcode() {
+        rcode @ (ResponseCode::ServerFailure | ResponseCode::Refused) => Some(rcode),
+        _ => None,
+    }
+}
+
+/// 在同一次查询得到的多个应答中选出第一个不需要故障转移的，都需要时选 `first`；
+/// 被放弃的SERVFAIL/REFUSED应答记入选中应答的 `failovers`
+fn prefer_usable_answer(first: (Response, TransportInfo), rest: Vec<(Response, TransportInfo)>) -> (Response, TransportInfo) {
+    let mut answers = rest;
+    answers.insert(0, first);
+    let chosen = answers.iter()
+        .position(|(response, _)| failover_rcode(response).is_none())
+        .unwrap_or(0);
+    let (response, mut info) = answers.remove(chosen);
+    info.failovers.extend(answers.iter().filter_map(|(response, other)| {
+        failover_rcode(response).map(|rcode| UpstreamFailover { upstream: other.name.clone(), rcode })
+    }));
+    (response, info)
+}
+
+/// 响应的来源
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::large_enum_variant)]
+pub enum ResponseOrigin {
+    /// 上游应答（并发的相同查询共用同一次上游查询时，信息来自那次查询）
+    Upstream(TransportInfo),
+    /// 缓存中仍然有效的答案
+    Cache,
+    /// 刚过期的缓存答案（TTL为0），后台正在向上游刷新
+    StaleCache,
+}
+
+impl ResponseOrigin {
+    /// 上游传输信息，来自缓存时为 `None`
+    pub fn into_transport_info(self) -> Option<TransportInfo> {
+        match self {
+            ResponseOrigin::Upstream(info) => Some(info),
+            ResponseOrigin::Cache | ResponseOrigin::StaleCache => None,
+        }
+    }
+}
+
+/// 一次上游查询的结果，由等待同一查询的调用方共享
+type FetchResult = Result<(Response, TransportInfo)>;
+
+/// 进行中的上游查询，按缓存键登记；等待方订阅结果
+type PendingFetches = Arc<Mutex<HashMap<CacheKey, watch::Receiver<Option<FetchResult>>>>>;
+
+/// 进行中上游查询的登记守卫，查询结束或被取消时注销
+struct PendingFetchGuard {
+    pending: PendingFetches,
+    key: CacheKey,
+}
+
+impl Drop for PendingFetchGuard {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&self.key);
+    }
+}
+
+/// 带名称的传输实例
+#[derive(Debug, Clone)]
+struct NamedTransport {
+    name: String,
+    transport: Arc<dyn Transport + Send + Sync + 'static>,
+    /// 是否参与查询（运行时可停用）
+    enabled: bool,
+    /// 正在进行的查询数，移除传输时据此等待
+    in_flight: Arc<AtomicUsize>,
+    /// 上游要求退避（HTTP 429/503）时，在此时间之前不再选用
+    backoff_until: Arc<Mutex<Option<Instant>>>,
+    /// 发往该上游、尚未完成的报文ID
+    query_ids: Arc<QueryIds>,
+    /// 是否记录各阶段耗时
+    capture_timing: bool,
+    /// 发往该上游时客户端子网的处理方式
+    ecs_policy: EcsPolicy,
+    /// 发往该上游时EDNS、DO位和大小写随机化的设置
+    features: UpstreamFeatures,
+    /// 只用于按域名转发，不参与查询策略
+    routed_only: bool,
+    /// 优先层级，数值越小越优先
+    tier: u8,
+    /// 解析器内所有传输共用的发送许可
+    permits: Arc<SendPermits>,
+    /// 证书校验失败降级时改用的传输（加密上游且开启了降级时）
+    degraded: Option<DegradedRoute>,
+    /// 同时发往该上游的查询上限（未配置时为None）
+    inflight_limit: Option<Arc<InflightLimit>>,
+    /// 响应问题段的检查策略
+    question_policy: QuestionPolicy,
+    /// 响应的记录数上限
+    record_limits: RecordLimits,
+    /// 否定应答被其他上游的肯定应答推翻的次数，见 [`CoreResolverConfig::nxdomain_protection_window`]
+    suspicious_negatives: Arc<AtomicU64>,
+    /// 该上游的限流（HTTP 429）记录
+    rate_limit: Arc<RateLimitState>,
+    /// 握手失败隔离状态（开启了隔离的加密上游，且没有由证书校验降级接管时）
+    quarantine: Option<Arc<HandshakeQuarantine>>,
+    /// 大小写随机化使用的随机数来源
+    random: Arc<dyn RandomSource>,
+    /// 只把这些记录类型的查询发往该上游（None表示不限制）
+    record_types: Option<Arc<[RecordType]>>,
+    /// 单次发送的超时（自适应超时），None表示只受传输自身的超时限制
+    attempt_timeout: Option<Duration>,
+    /// 上游标签，附在该上游的状态变化事件上
+    labels: Arc<UpstreamLabels>,
+    /// 计算耗时、退避期和单次发送超时使用的时间源
+    clock: Arc<dyn Clock>,
+}
+
+/// 加密上游在降级期间改用的传输
 #[derive(Debug, Clone)]
+struct DegradedRoute {
+    transport: Arc<dyn Transport + Send + Sync + 'static>,
+    state: Arc<EncryptedFallbackState>,
+}
+
+/// 解析器内同时进行的上游发送数上限（`concurrent_queries`）
+///
+/// 许可只在单次发送期间持有，而不是整个查询：同时查询多个上游的策略照常工作，
+/// 只是所有查询合计的在途发送数受限。许可按请求的 [`QueryPriority`] 准入，见 [`priority`]
+#[derive(Debug)]
+struct SendPermits {
+    gate: PriorityGate,
+    /// 为交互式查询保留的名额比例，各上游的 `max_inflight` 也按它保留
+    reserve: f64,
+    /// 等待许可的时限，超过时返回 [`DnsError::Busy`]；None表示一直等待
+    wait_timeout: Option<Duration>,
+    counters: PriorityCounters,
+}
+
+impl SendPermits {
+    fn new(limit: usize, reserve: f64, wait_timeout: Option<Duration>) -> Self {
+        Self { gate: PriorityGate::new(limit, reserve), reserve, wait_timeout, counters: PriorityCounters::default() }
+    }
+    
+    async fn acquire(&self, priority: QueryPriority) -> Result<GatePermit<'_>> {
+        let start = Instant::now();
+        let permit = match self.wait_timeout {
+            Some(wait) => timeout(wait, self.gate.acquire(priority)).await.ok(),
+            None => Some(self.gate.acquire(priority).await),
+        };
+        self.counters.record_wait(priority, permit.is_some(), start.elapsed());
+        permit.ok_or(DnsError::Busy { limit: self.gate.limit() })
+    }
+    
+    /// 当前在途的发送数
+    fn in_use(&self) -> usize {
+        self.gate.in_use()
+    }
+}
+
+/// 单个上游的在途查询上限（`max_inflight`）
+///
+/// 与解析器的 `concurrent_queries` 许可相互独立：一个缓慢的上游占满自己的名额后，
+/// 新查询按 [`QueueBehavior`] 跳过它或限时等待，不会继续堆积在该上游上。
+/// 名额同样按优先级准入，批量查询从不等待，已满时最先被跳过
+#[derive(Debug)]
+struct InflightLimit {
+    gate: PriorityGate,
+    behavior: QueueBehavior,
+    /// 因名额已满而跳过该上游的次数
+    saturated: AtomicU64,
+}
+
+impl InflightLimit {
+    fn new(limit: usize, reserve: f64, behavior: QueueBehavior) -> Self {
+        Self { gate: PriorityGate::new(limit, reserve), behavior, saturated: AtomicU64::new(0) }
+    }
+    
+    async fn acquire(&self, upstream: &str, priority: QueryPriority) -> Result<GatePermit<'_>> {
+        let permit = match (self.behavior, priority) {
+            (QueueBehavior::Reject, _) | (QueueBehavior::Wait { .. }, QueryPriority::Bulk) => self.gate.try_acquire(priority),
+            (QueueBehavior::Wait { timeout: wait }, _) => timeout(wait, self.gate.acquire(priority)).await.ok(),
+        };
+        permit.ok_or_else(|| {
+            self.saturated.fetch_add(1, Ordering::Relaxed);
+            dns_debug!("上游 {} 的在途查询已达上限 {}，本次{}查询跳过", upstream, self.gate.limit(), priority);
+            DnsError::UpstreamSaturated { upstream: upstream.to_string(), limit: self.gate.limit() }
+        })
+    }
+}
+
+/// 上游返回 429/503 后暂停选用它的时长
+const UPSTREAM_BACKOFF: Duration = Duration::from_secs(5);
+
+/// QNAME最小化时一次查询最多发送的中间查询数（RFC 9156 的 MAX_MINIMISE_COUNT）
+pub const MAX_MINIMISE_STEPS: usize = 10;
+
+/// QNAME最小化依次查询的中间名称：从 `apex` 下一级开始每次多一个标签，不含 `name` 本身
+/// 
+/// `name` 不在 `apex` 之下时为空；超过 [`MAX_MINIMISE_STEPS`] 个时只取前面的
+pub(crate) fn minimisation_steps(apex: &str, name: &str) -> Vec<String> {
+    let name = name.trim_end_matches('.');
+    let apex = apex.trim_matches('.');
+    let (name_bytes, apex_bytes) = (name.as_bytes(), apex.as_bytes());
+    let under_apex = !apex.is_empty()
+        && name_bytes.len() > apex_bytes.len() + 1
+        && name_bytes[name_bytes.len() - apex_bytes.len()..].eq_ignore_ascii_case(apex_bytes)
+        && name_bytes[name_bytes.len() - apex_bytes.len() - 1] == b'.';
+    if !under_apex {
+        return Vec::new();
+    }
+    let labels: Vec<&str> = name.split('.').collect();
+    let apex_labels = apex.split('.').count();
+    (apex_labels + 1..labels.len())
+        .take(MAX_MINIMISE_STEPS)
+        .map(|count| labels[labels.len() - count..].join("."))
+        .collect()
+}
+
+/// 进行中查询的计数守卫，查询完成或future被丢弃时扣减
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl<'a> InFlightGuard<'a> {
+    fn enter(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::AcqRel);
+        Self(counter)
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// 查询路径
+#[derive(Clone, Copy)]
+enum QueryRoute<'a> {
+    /// 按配置的查询策略
+    Strategy,
+    /// 应急：向所有传输并发查询，并把TTL抬高到给定下限
+    FanOut(Duration),
+    /// 优先使用指定名称的传输，失败后按查询策略
+    Preferred(&'a str),
+    /// 只使用指定名称的传输（跨上游故障转移、按域名转发）
+    Only(&'a str),
+    /// 在筛选后的传输中按查询策略查询，不分层级
+    Filtered(&'a UpstreamFilter),
+}
+
+/// 查询能否由缓存应答
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CacheUse {
+    /// 先查缓存
+    Read,
+    /// 不读缓存，总是向上游查询（答案照常写入缓存）
+    Bypass,
+    /// 不读缓存，并保留与上游收发的原始报文
+    BypassCapturingWire,
+}
+
+impl NamedTransport {
+    fn new(
+        name: String,
+        transport: Arc<dyn Transport + Send + Sync + 'static>,
+        capture_timing: bool,
+        permits: Arc<SendPermits>,
+    ) -> Self {
+        Self {
+            name,
+            transport,
+            enabled: true,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            backoff_until: Arc::new(Mutex::new(None)),
+            query_ids: Arc::new(QueryIds::new()),
+            capture_timing,
+            ecs_policy: EcsPolicy::Forward,
+            features: UpstreamFeatures::default(),
+            routed_only: false,
+            tier: 0,
+            permits,
+            degraded: None,
+            inflight_limit: None,
+            question_policy: QuestionPolicy::default(),
+            record_limits: RecordLimits::default(),
+            suspicious_negatives: Arc::new(AtomicU64::new(0)),
+            rate_limit: Arc::new(RateLimitState::default()),
+            quarantine: None,
+            random: random::thread_random(),
+            record_types: None,
+            attempt_timeout: None,
+            labels: Arc::default(),
+            clock: clock::real_clock(),
+        }
+    }
+    
+    fn info(&self, duration: Duration) -> TransportInfo {
+        TransportInfo {
+            name: self.name.clone(),
+            protocol: self.transport.transport_type().to_string(),
+            duration,
+            timing: None,
+            wire: None,
+            client_subnet: None,
+            failovers: Vec::new(),
+            peer: None,
+            degraded_security: false,
+            records_truncated: false,
+        }
+    }
+    
+    /// 发送查询并计入进行中的查询数，上游要求退避时记录退避截止时间
+    /// 
+    /// 每次发送（包括重试）都分配新的随机报文ID，响应ID改回调用方请求的ID；
+    /// 客户端子网在此按该上游的ECS策略改写，EDNS和DO位按该上游的设置改写；
+    /// 启用大小写随机化时查询名改为随机大小写，响应须原样回显后再改回；问题段按 [`QuestionPolicy`] 清洗，
+    /// 不合格时以协议错误失败；记录数按 [`RecordLimits`] 截断或拒绝。发送前先取得该上游的在途名额
+    /// （已满时返回 [`DnsError::UpstreamSaturated`]），再取得解析器的并发许可，两者都按请求的优先级准入。
+    /// 证书校验失败降级期间改用降级传输，到探测间隔时在后台以正常校验重试该上游。
+    /// 因握手失败处于隔离期时不建立连接，直接返回 [`DnsError::Quarantined`]。
+    /// 结果记入当前查询的尝试记录（见 [`diagnosis`]）
+    async fn send(&self, request: &Request) -> Result<(Response, TransportInfo)> {
+        let start = self.clock.now_instant();
+        let result = self.send_attempt(request).await;
+        diagnosis::record(&self.name, self.transport.transport_type(), self.elapsed_since(start), &result);
+        result
+    }
+    
+    async fn send_attempt(&self, request: &Request) -> Result<(Response, TransportInfo)> {
+        let probe_claim = match self.quarantine.as_deref().map(HandshakeQuarantine::admit) {
+            Some(Err(retry_in)) => return Err(DnsError::Quarantined { upstream: self.name.clone(), retry_in }),
+            Some(Ok(claim)) => claim,
+            None => None,
+        };
+        let _guard = InFlightGuard::enter(&self.in_flight);
+        let _upstream_permit = match &self.inflight_limit {
+            Some(limit) => Some(limit.acquire(&self.name, request.priority).await.inspect_err(|_| {
+                self.permits.counters.record_shed(request.priority);
+            })?),
+            None => None,
+        };
+        let _permit = self.permits.acquire(request.priority).await.inspect_err(|_| {
+            dns_warn!("在途上游查询已达上限 {}，放弃发往 {} 的{}查询", self.permits.gate.limit(), self.name, request.priority);
+        })?;
+        let query_id = self.query_ids.reserve()?;
+        let mut wire_request = query_id.wire_request(request);
+        wire_request.client_address = self.ecs_policy.apply(request.client_address.as_ref());
+        self.features.apply(&mut wire_request);
+        let case_randomized = self.features.case_randomization == Some(true);
+        if case_randomized {
+            wire_request.query.name = randomize_case(&request.query.name, self.random.as_ref());
+        }
+        let degraded = self.degraded.as_ref().filter(|route| route.state.is_active());
+        let transport = match degraded {
+            Some(route) => {
+                route.state.record_degraded();
+                if route.state.claim_probe(self.clock.now_instant()) {
+                    self.spawn_probe(route, &wire_request);
+                }
+                &route.transport
+            }
+            None => &self.transport,
+        };
+        let start = self.clock.now_instant();
+        let exchange = async {
+            match request.wire_capture_limit {
+                Some(max_bytes) => transport.send_captured(&wire_request, max_bytes).await
+                    .map(|(response, timing, wire)| (response, timing.peer, self.capture_timing.then_some(timing), Some(wire))),
+                None if self.capture_timing => transport.send_timed(&wire_request).await
+                    .map(|(response, timing)| (response, timing.peer, Some(timing), None)),
+                None => transport.send_with_peer(&wire_request).await.map(|(response, peer)| (response, peer, None, None)),
+            }
+        };
+        let result = match self.attempt_timeout {
+            Some(limit) => clock::timeout_at(&*self.clock, start + limit, exchange).await.unwrap_or_else(|| {
+                dns_debug!("发往 {} 的查询在自适应超时 {:?} 内没有完成", self.name, limit);
+                Err(DnsError::Timeout { phase: None })
+            }),
+            None => exchange.await,
+        };
+        if let (Some(route), None) = (&self.degraded, degraded) {
+            route.state.observe(&self.name, &result);
+        }
+        if let Some(quarantine) = &self.quarantine {
+            quarantine.observe(&self.name, &result, probe_claim);
+        }
+        let result = result.and_then(|(response, peer, timing, wire)| {
+            let response = self.question_policy.sanitize(&wire_request, response).inspect_err(|e| {
+                dns_warn!("丢弃上游 {} 的响应: {}", self.name, e);
+            })?;
+            let mut response = if case_randomized {
+                restore_case(&wire_request.query.name, request, response).inspect_err(|e| {
+                    dns_warn!("上游 {} 的响应未回显查询名的大小写: {}", self.name, e);
+                })?
+            } else {
+                response
+            };
+            let records_truncated = self.record_limits.enforce(&request.query, &mut response).inspect_err(|e| {
+                dns_warn!("丢弃上游 {} 的响应: {}", self.name, e);
+            })?;
+            if records_truncated {
+                dns_warn!("上游 {} 对 {} 的响应超出记录数上限，已截断", self.name, request.query.name);
+            }
+            Ok((response, peer, timing, wire, records_truncated))
+        });
+        let result = result.map(|(response, peer, timing, wire, records_truncated)| {
+            let info = TransportInfo {
+                timing,
+                wire,
+                peer,
+                records_truncated,
+                client_subnet: wire_request.client_address.clone(),
+                degraded_security: degraded.is_some(),
+                ..self.info(self.elapsed_since(start))
+            };
+            (query_id.restore(response, request), info)
+        });
+        if let Some(e) = result.as_ref().err().filter(|e| e.is_rate_limited()) {
+            self.rate_limit.record(self.clock.now_instant(), e.retry_after());
+        }
+        if let Some(e) = result.as_ref().err().filter(|e| e.retry_advice() == RetryAdvice::Backoff) {
+            dns_warn!("上游 {} 要求退避: {}，{:?} 内不再选用", self.name, e, UPSTREAM_BACKOFF);
+            *self.lock_backoff() = Some(self.clock.now_instant() + UPSTREAM_BACKOFF);
+        }
+        result
+    }
+
+    /// 在后台以正常的证书校验向该上游发送一次请求，成功则退出降级
+    fn spawn_probe(&self, route: &DegradedRoute, request: &Request) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let (name, transport, state) = (self.name.clone(), self.transport.clone(), route.state.clone());
+        let probe = Request { id: rand::random(), wire_capture_limit: None, ..request.clone() };
+        dns_debug!("🔓 降级期间探测加密上游 {}", name);
+        runtime.spawn(async move {
+            let result = transport.send(&probe).await;
+            state.probe_finished(&name, &result);
+        });
+    }
+
+    fn lock_backoff(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+        self.backoff_until.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 是否处于退避期
+    fn in_backoff(&self) -> bool {
+        matches!(*self.lock_backoff(), Some(until) if self.clock.now_instant() < until)
+    }
+
+    /// 按该传输的时间源计算自 `start` 以来的耗时
+    fn elapsed_since(&self, start: Instant) -> Duration {
+        self.clock.now_instant().saturating_duration_since(start)
+    }
+
+    /// 是否因握手失败处于隔离期（或重新接纳的探测正在进行）
+    fn quarantined(&self) -> bool {
+        self.quarantine.as_ref().is_some_and(|quarantine| quarantine.blocks())
+    }
+
+    /// 当前查询所在的作用域是否允许使用（不在作用域中时总是允许），且该上游服务当前查询的记录类型
+    fn in_scope(&self) -> bool {
+        scope::allows(&self.name, self.tier) && query_type::allows(self.record_types.as_deref())
+    }
+}
+
+/// 智能DNS解析器
+/// 
+/// 传输列表可在运行时增删和停用：每次查询开始时取一份列表快照，
+/// 修改以写时复制的方式替换整份列表，不影响已经开始的查询。
+#[derive(Debug)]
 pub struct CoreResolver {
     /// 传输层实例
-    transports: Vec<Arc<dyn Transport + Send + Sync + 'static>>,
+    transports: RwLock<Arc<Vec<NamedTransport>>>,
     /// 查询策略
     strategy: QueryStrategy,
     /// DNS缓存
-    cache: Option<Arc<DnsCache>>,
-    /// 上游监控器
+    cache: Option<CacheLayer>,
+    /// 定期清理过期缓存条目的后台任务，克隆体共用，最后一个克隆体释放时中止
+    cache_janitor: Option<Arc<CacheJanitor>>,
+    /// 上游监控器（按传输名称统计）
     upstream_monitor: Option<Arc<UpstreamMonitor>>,
+    /// 全局内存预算，克隆体共用
+    memory_budget: Option<Arc<MemoryBudget>>,
+    /// 定期评估上游状态和SLO的后台任务，第一次设置SLO时启动，克隆体共用
+    upstream_monitor_task: Arc<Mutex<Option<UpstreamMonitorHandle>>>,
+    /// 最近一次选用的层级，克隆体共用，用于记录层级切换
+    active_tier: Arc<Mutex<Option<u8>>>,
+    /// 最近一次探测更优先层级的时间，克隆体共用
+    tier_probed_at: Arc<Mutex<Option<Instant>>>,
+    /// 主动探测上游时发送的查询（None表示不主动探测）
+    health_probe: Option<Arc<ProbeConfig>>,
+    /// 上游发送的并发许可，克隆体共用
+    send_permits: Arc<SendPermits>,
+    /// 已进行的探测轮数，用于轮换探测域名，克隆体共用
+    probe_rounds: Arc<AtomicUsize>,
+    /// 上游监控任务探测静默上游所用的传输列表和静默时长上限，克隆体共用（未开启静默检测时为None）
+    silence_prober: Option<(Arc<TransportProber>, Duration)>,
     /// 默认超时时间
     default_timeout: Duration,
     /// 重试次数
     retry_count: usize,
+    /// 已进行的重试次数，克隆体共用
+    retries_performed: Arc<AtomicU64>,
     /// 默认客户端地址信息
     default_client_address: Option<ClientAddress>,
+    /// 响应TTL钳制规则
+    ttl_clamp: TtlClamp,
+    /// 按域名覆盖TTL的规则，命中时取代全局钳制，克隆体共用命中计数
+    ttl_overrides: Arc<TtlOverrides>,
+    /// ECS查询的缓存方式
+    ecs_cache_mode: EcsCacheMode,
+    /// 是否把QR未置位或问题不一致的响应当作错误
+    strict_response_check: bool,
+    /// 响应问题段的检查策略，各上游共用
+    question_policy: QuestionPolicy,
+    /// 响应的记录数上限，各上游共用
+    record_limits: RecordLimits,
+    /// 截断过的响应是否写入缓存
+    cache_truncated_responses: bool,
+    /// 发出的请求是否携带EDNS OPT记录
+    enable_edns: bool,
+    /// 是否记录上游查询的分阶段耗时
+    capture_timing_breakdown: bool,
+    /// 返回给调用方的地址记录的排列方式，克隆体共用轮换计数
+    record_rotation: Arc<RecordRotator>,
+    /// 应答地址改写规则，克隆体共用命中计数
+    answer_rewrite: Arc<AnswerRewriter>,
+    /// 缓存条目过期后仍可先行返回的时长（None表示不返回过期答案，也不合并并发查询）
+    revalidate_window: Option<Duration>,
+    /// 否定应答推翻近期肯定答案时向另一个上游核实的时长窗口（None表示不核实）
+    nxdomain_protection_window: Option<Duration>,
+    /// 上游限流且没有其他可用上游时的处理方式
+    on_rate_limited: RateLimitedBehavior,
+    /// 进行中的上游查询，克隆体共用（后台刷新任务持有克隆体）
+    pending_fetches: PendingFetches,
+    /// 保留原始报文时每个报文最多保留的字节数
+    wire_capture_max_bytes: usize,
+    /// DNS Cookie状态（开启时由所有UDP传输共用）
+    cookie_jar: Option<Arc<DnsCookieJar>>,
+    /// 离线模式状态，克隆体共用
+    offline: Arc<OfflineState>,
+    /// 所有上游不可用超过该时长后自动进入离线模式（None表示只能手动切换）
+    auto_offline_after: Option<Duration>,
+    /// 恢复在线后刷新相邻两个条目的间隔
+    offline_revalidation_interval: Duration,
+    /// 以主机名配置的上游如何解析地址（添加UDP/TCP/DoT传输时使用）
+    host_resolution: HostResolution,
+    /// UDP传输的源端口池配置（添加UDP传输时使用，None为按各传输的 `pool_size` 使用默认策略）
+    udp_socket_pool: Option<UdpPoolConfig>,
+    /// 加密上游证书校验失败时的降级状态，克隆体共用（严格模式下为None）
+    encrypted_fallback: Option<Arc<EncryptedFallbackState>>,
+    /// 按加密上游名称指定的明文备用上游（主机, 端口）
+    plaintext_fallbacks: Arc<HashMap<String, (String, u16)>>,
+    /// 加密上游握手失败的隔离时长（None表示不隔离）
+    tls_quarantine: Option<TlsQuarantineConfig>,
+    /// 时间源
+    clock: Arc<dyn Clock>,
+    /// 随机数来源
+    random: Arc<dyn RandomSource>,
+}
+
+impl Clone for CoreResolver {
+    /// 克隆体从当前传输列表开始，之后各自独立增删传输
+    fn clone(&self) -> Self {
+        Self {
+            transports: RwLock::new(self.transports()),
+            strategy: self.strategy,
+            cache: self.cache.clone(),
+            cache_janitor: self.cache_janitor.clone(),
+            upstream_monitor: self.upstream_monitor.clone(),
+            memory_budget: self.memory_budget.clone(),
+            upstream_monitor_task: self.upstream_monitor_task.clone(),
+            active_tier: self.active_tier.clone(),
+            tier_probed_at: self.tier_probed_at.clone(),
+            health_probe: self.health_probe.clone(),
+            send_permits: self.send_permits.clone(),
+            probe_rounds: self.probe_rounds.clone(),
+            silence_prober: self.silence_prober.clone(),
+            default_timeout: self.default_timeout,
+            retry_count: self.retry_count,
+            retries_performed: self.retries_performed.clone(),
+            default_client_address: self.default_client_address.clone(),
+            ttl_clamp: self.ttl_clamp,
+            ttl_overrides: self.ttl_overrides.clone(),
+            ecs_cache_mode: self.ecs_cache_mode,
+            strict_response_check: self.strict_response_check,
+            question_policy: self.question_policy,
+            record_limits: self.record_limits,
+            cache_truncated_responses: self.cache_truncated_responses,
+            enable_edns: self.enable_edns,
+            capture_timing_breakdown: self.capture_timing_breakdown,
+            record_rotation: self.record_rotation.clone(),
+            answer_rewrite: self.answer_rewrite.clone(),
+            revalidate_window: self.revalidate_window,
+            nxdomain_protection_window: self.nxdomain_protection_window,
+            on_rate_limited: self.on_rate_limited,
+            pending_fetches: self.pending_fetches.clone(),
+            wire_capture_max_bytes: self.wire_capture_max_bytes,
+            cookie_jar: self.cookie_jar.clone(),
+            offline: self.offline.clone(),
+            auto_offline_after: self.auto_offline_after,
+            offline_revalidation_interval: self.offline_revalidation_interval,
+            host_resolution: self.host_resolution.clone(),
+            udp_socket_pool: self.udp_socket_pool.clone(),
+            encrypted_fallback: self.encrypted_fallback.clone(),
+            plaintext_fallbacks: self.plaintext_fallbacks.clone(),
+            tls_quarantine: self.tls_quarantine,
+            clock: self.clock.clone(),
+            random: self.random.clone(),
+        }
+    }
+}
+
+/// 检查传输的在途查询上限，上限为0时返回错误
+pub(crate) fn check_max_inflight(name: &str, max_inflight: Option<usize>) -> Result<()> {
+    if max_inflight == Some(0) {
+        return Err(DnsError::InvalidConfig(format!("max_inflight for transport '{}' must be at least 1", name)));
+    }
+    Ok(())
 }
 
 /// 解析器配置
@@ -56,7 +880,7 @@ pub struct CoreResolverConfig {
     pub strategy: QueryStrategy,
     /// 默认超时时间
     pub default_timeout: Duration,
-    /// 重试次数
+    /// 重试次数，各查询策略中的含义见 [`QueryStrategy`]
     pub retry_count: usize,
     /// 是否启用缓存
     pub enable_cache: bool,
@@ -66,22 +890,145 @@ pub struct CoreResolverConfig {
     pub enable_upstream_monitoring: bool,
     /// 上游监控间隔
     pub upstream_monitoring_interval: Duration,
+    /// 上游监控为每个上游保留的状态变化事件数，0表示不记录，见 [`CoreResolver::upstream_events`]
+    pub upstream_event_history: usize,
+    /// 接收上游SLO违反、恢复和主动探测通知的观察者，见 [`CoreResolver::set_transport_slo`]
+    pub query_observer: Option<Arc<dyn QueryObserver>>,
+    /// 主动探测上游时发送的查询，用于判断更优先层级中不可用的上游是否已恢复；
+    /// None表示不主动探测，不可用的上游只能等超过最长不可用时间后再获得机会
+    pub health_probe: Option<ProbeConfig>,
     /// 默认客户端地址信息
     pub default_client_address: Option<ClientAddress>,
     /// DNS服务器端口
     pub port: u16,
-    /// 并发查询数量
+    /// 同时进行的上游发送数上限（所有查询合计，每次发送占用一个名额）
     pub concurrent_queries: usize,
+    /// 等待并发名额的时限，超过时该次发送返回 [`DnsError::Busy`]；None表示一直等待
+    pub concurrency_wait_timeout: Option<Duration>,
+    /// 为交互式查询（[`QueryPriority::Interactive`]）保留的 `concurrent_queries` 名额比例，`[0, 1)`，向下取整；
+    /// 设置了 `max_inflight` 的上游也按此比例保留
+    pub interactive_reserve: f64,
     /// 是否启用递归查询
     pub recursion_desired: bool,
-    /// 查询缓冲区大小
+    /// 响应缓冲区大小（字节）：UDP按它分配接收缓冲区并在OPT记录中声明，
+    /// 更长的UDP/TCP/DoT响应报文以协议错误失败，DoH响应正文超过该长度时中止读取
     pub buffer_size: usize,
-    /// 是否启用统计
+    /// DoH上游是否跟随3xx重定向（只跟随同源的，最多2跳）
+    pub doh_follow_redirects: bool,
+    /// DoT上游恢复会话时是否把查询作为0-RTT早期数据发送（服务器拒绝时自动重发）
+    pub tls_early_data: bool,
+    /// TCP和DoT上游是否以TCP Fast Open方式连接，平台不支持时照常连接
+    pub tcp_fast_open: bool,
+    /// 是否收集统计：关闭时不更新性能指标和上游监控的详细统计（上游状态照常判定），
+    /// 智能策略依赖性能指标，不能关闭
     pub enable_stats: bool,
     /// 日志级别
-    pub log_level: rat_logger::LevelFilter,
+    pub log_level: crate::logger::LevelFilter,
     /// 是否启用DNS专用日志格式
     pub enable_dns_log_format: bool,
+    /// 响应TTL下限（None表示不抬高）
+    pub min_ttl: Option<Duration>,
+    /// 响应TTL上限（None表示不压低）
+    pub max_ttl: Option<Duration>,
+    /// 含TTL为0记录的响应是否按1秒缓存（默认不缓存；设置了 `min_ttl` 时TTL先被抬高，照常缓存）
+    pub cache_zero_ttl: bool,
+    /// 携带ECS客户端子网的查询如何使用缓存
+    pub ecs_cache_mode: EcsCacheMode,
+    /// QR未置位或回显问题与查询不一致的响应是否作为错误返回（否则只是不写入缓存）
+    pub strict_response_check: bool,
+    /// 是否接受没有问题段的响应：ID一致且每条回答记录的所有者都是查询名或其CNAME链上的名称时接受，
+    /// 并补上查询作为问题段；问题段多于一条的响应总是按畸形报文拒绝，见 [`QuestionPolicy`]
+    pub accept_questionless_responses: bool,
+    /// 是否保留类型与查询不符的应答记录（调试用）；默认去掉，去掉的条数见 `CoreResolverStats::dropped_mismatched_records`
+    pub lenient_answer_types: bool,
+    /// 单个响应的回答记录数、记录总数和CNAME链跳数上限，以及超出时截断还是拒绝
+    pub record_limits: RecordLimits,
+    /// 因超出记录数上限被截断的响应是否写入缓存
+    pub cache_truncated_responses: bool,
+    /// bincode编码查询接口的载荷大小和批量查询数上限，见 [`EncodedRequestLimits`]
+    pub encoded_request_limits: EncodedRequestLimits,
+    /// 自定义缓存后端（None表示使用进程内的 [`DnsCache`]），仅在启用缓存时使用
+    pub cache_backend: Option<Arc<dyn DnsCacheBackend>>,
+    /// 后台分段清理过期缓存条目的配置（None表示只在读到过期条目时覆盖），仅在启用缓存时生效；
+    /// 离线模式只能用尚未被清理的过期条目应答，需要时调大 [`CacheJanitorConfig::retain_expired`]
+    pub cache_janitor: Option<CacheJanitorConfig>,
+    /// 请求是否携带EDNS OPT记录；未启用时只有携带客户端地址的请求才会带OPT
+    pub enable_edns: bool,
+    /// 是否记录每次上游查询的分阶段耗时（连接、TLS握手、首字节等），结果见 [`TransportInfo::timing`]
+    pub capture_timing_breakdown: bool,
+    /// 返回A/AAAA记录时的排列方式（缓存中保存的响应不受影响）
+    pub record_rotation: RotationMode,
+    /// 查询响应（[`DnsQueryResponse`](crate::DnsQueryResponse)）中记录的规范排序，None为保持上游给出的顺序
+    pub sort_records: Option<RecordSort>,
+    /// 上游粘性：同一客户端子网或租户的查询优先使用上次成功应答的上游，None为按查询策略逐次选择
+    pub stickiness: Option<StickinessConfig>,
+    /// 按域名覆盖TTL的规则，命中的名称不再做 `min_ttl`/`max_ttl` 钳制
+    pub ttl_overrides: Vec<TtlOverrideRule>,
+    /// A/AAAA应答的地址改写规则（NAT回流），按配置顺序取第一条匹配的规则
+    pub answer_rewrite_rules: Vec<AnswerRewriteRule>,
+    /// 地址改写在写入缓存之前还是返回给调用方时进行
+    pub answer_rewrite_stage: RewriteStage,
+    /// 缓存条目过期不超过该时长时先返回旧答案，同时只由一个后台任务向上游刷新；
+    /// 设置后并发的相同查询在未命中时共用一次上游查询。None表示关闭，仅在启用缓存时生效
+    pub revalidate_window: Option<Duration>,
+    /// 按查询策略得到NXDOMAIN或NODATA、而缓存中同一查询的肯定答案过期不超过该时长时，先向另一个上游核实：
+    /// 另一个上游给出肯定答案时返回该答案，并记第一个上游一次可疑否定应答（计入其健康状态）；
+    /// 两个上游都给出否定应答，或没有其他可用上游时才缓存否定应答。None表示关闭，仅在启用缓存时生效
+    pub nxdomain_protection_window: Option<Duration>,
+    /// 上游限流（HTTP 429）后查询失败、而本次查询期间被限流的上游没有其他上游可以替代时的处理方式。
+    /// 设为 [`RateLimitedBehavior::WaitAndRetry`] 时，按上游的 `Retry-After` 等待后向该上游重试一次，
+    /// 限流不计入上游监控的失败
+    pub on_rate_limited: RateLimitedBehavior,
+    /// 查询要求保留原始报文时，每个报文最多保留的字节数，超出部分截断
+    pub wire_capture_max_bytes: usize,
+    /// UDP查询是否携带DNS Cookie（RFC 7873）
+    pub enable_dns_cookies: bool,
+    /// 客户端Cookie的更换周期，仅在开启DNS Cookie时使用
+    pub dns_cookie_lifetime: Duration,
+    /// 一次查询最多尝试的上游数：上游返回SERVFAIL/REFUSED时换未尝试过的上游重试，
+    /// 实际上限还不超过上游总数；1表示不做跨上游故障转移
+    pub max_failover_attempts: usize,
+    /// 所有启用的上游被上游监控判定为不可用超过该时长后自动进入离线模式，需要启用上游监控；
+    /// None表示只能通过 [`CoreResolver::set_offline`] 手动切换
+    pub auto_offline_after: Option<Duration>,
+    /// 上游超过该时长没有任何查询或探测时，上游监控任务不论其状态主动探测一次，需要启用上游监控并设置
+    /// [`health_probe`](Self::health_probe)，且应大于监控间隔；None表示不检测
+    pub max_upstream_silence: Option<Duration>,
+    /// 恢复在线后每秒最多刷新的离线期间返回过的过期条目数（0按1处理）
+    pub offline_revalidation_rate: u32,
+    /// 以主机名配置的UDP/TCP/DoT上游如何解析地址
+    pub host_resolution: HostResolution,
+    /// UDP上游的源端口池配置，None为每个UDP上游按其 `pool_size` 预先绑定套接字、使用默认的轮换和耗尽策略
+    pub udp_socket_pool: Option<UdpPoolConfig>,
+    /// 构建时上游主机名解析失败是否让构建失败（否则只把该上游标记为不可用，之后发送时重新解析）
+    pub strict_upstream_resolution: bool,
+    /// 所有加密上游（DoT/DoH）证书校验失败时是否降级，见 [`EncryptedFallbackPolicy`]
+    pub encrypted_fallback: EncryptedFallbackPolicy,
+    /// 每个加密上游连续多少次证书校验失败后才算失败（所有加密上游都失败时降级）
+    pub encrypted_fallback_threshold: u32,
+    /// 降级期间在后台以正常校验探测加密上游的间隔
+    pub encrypted_fallback_probe_interval: Duration,
+    /// 明文降级时按加密上游名称使用的备用上游（主机, 端口），未指定的上游使用同一主机的53端口
+    pub plaintext_fallbacks: HashMap<String, (String, u16)>,
+    /// 加密上游第一次出现证书或其他握手失败（握手超时除外）时立即隔离，隔离期内查询策略跳过它、不建立连接，
+    /// 见 [`TlsQuarantineConfig`]；None表示不隔离。开启了证书校验降级的上游由降级处理，不隔离
+    pub tls_quarantine: Option<TlsQuarantineConfig>,
+    /// 大小写随机化、记录随机轮换、SRV加权排序和DNS Cookie密钥使用的随机数来源，None表示使用
+    /// [`random::ThreadRandom`]；报文ID始终取自操作系统熵源，见 [`random`]
+    pub random_source: Option<Arc<dyn RandomSource>>,
+    /// 缓存、上游监控、重试退避、传输超时和耗时统计使用的时间源，None表示使用 [`clock::RealClock`]，见 [`clock`]
+    pub clock: Option<Arc<dyn Clock>>,
+    /// 缓存、查询历史、上游状态变化事件和热门名称合计估算占用的内存上限（字节），超出时按
+    /// [`memory::MemoryComponent::SHRINK_ORDER`] 收缩，见 [`memory`]。None表示不统计也不限制
+    pub memory_budget: Option<usize>,
+    /// 每次查询结束时以info级别输出一行 `key=value` 摘要，查询过程中的info日志降为debug，
+    /// 行格式见 [`crate::builder::query_summary`]
+    pub log_query_summary: bool,
+    /// 按观测延迟为每个上游计算单次发送的超时，见 [`AdaptiveTimeoutConfig`]；None表示使用配置的超时
+    pub adaptive_timeout: Option<AdaptiveTimeoutConfig>,
+    /// 作为OpenMetrics上游指标标签输出的上游标签键（白名单），未列出的标签只出现在状态和配置中，
+    /// 避免随意的标签值放大指标的序列数
+    pub metrics_upstream_labels: Vec<String>,
 }
 
 // 注意：移除了 Default 实现，因为它包含兜底行为
@@ -107,7 +1054,7 @@ impl CoreResolverConfig {
         recursion_desired: bool,
         buffer_size: usize,
         enable_stats: bool,
-        log_level: rat_logger::LevelFilter,
+        log_level: crate::logger::LevelFilter,
         enable_dns_log_format: bool,
     ) -> Self {
         Self {
@@ -118,14 +1065,66 @@ impl CoreResolverConfig {
             max_cache_ttl,
             enable_upstream_monitoring,
             upstream_monitoring_interval,
+            upstream_event_history: health::DEFAULT_EVENT_HISTORY,
+            query_observer: None,
+            health_probe: None, // 探测域名因部署而异，没有默认值，需要单独设置
             default_client_address: None, // 客户端地址需要单独设置
             port,
             concurrent_queries,
+            concurrency_wait_timeout: None, // 与此前一致：排队等待而不是直接失败
+            interactive_reserve: 0.0, // 不保留：所有优先级共用全部名额，只按优先级排队
             recursion_desired,
             buffer_size,
+            doh_follow_redirects: true, // 与此前一致跟随重定向，但不再跟随跨域的
+            tls_early_data: false, // 早期数据可能被重放，需要单独开启
+            tcp_fast_open: false, // 部分中间设备丢弃带数据的SYN，需要单独开启
             enable_stats,
             log_level,
             enable_dns_log_format,
+            min_ttl: None, // TTL钳制为可选功能，需要单独设置
+            max_ttl: None,
+            cache_zero_ttl: false, // TTL为0表示上游要求每次重新查询
+            ecs_cache_mode: EcsCacheMode::Scoped, // 按子网缓存是唯一不会串答案的做法
+            strict_response_check: false, // 可疑响应总是不进缓存，是否拒绝返回需要单独开启
+            accept_questionless_responses: false, // 省略问题段少了一道防伪造校验，需要单独开启
+            lenient_answer_types: false, // 类型不符的记录会让地址查询混入另一地址族，只在排查上游时保留
+            record_limits: RecordLimits::default(), // 正常应答远达不到上限，超出时截断而不是让查询失败
+            cache_truncated_responses: false, // 截断的答案不完整，不应在TTL内一直返回
+            encoded_request_limits: EncodedRequestLimits::default(), // 正常请求不过几百字节，上限只拦截异常输入
+            cache_backend: None, // 缓存后端需要单独设置
+            cache_janitor: Some(CacheJanitorConfig::default()), // 不清理时不再被查询的过期条目会一直占用内存
+            enable_edns: false, // 与此前行为一致：只在携带客户端地址时附加OPT
+            capture_timing_breakdown: false, // 诊断功能，需要单独开启
+            record_rotation: RotationMode::None, // 保持上游给出的顺序，轮换需要单独开启
+            sort_records: None, // 排序会打乱别名链顺序，只在需要比较结果时开启
+            stickiness: None, // 固定上游会绕开查询策略的负载分摊，需要单独开启
+            ttl_overrides: Vec::new(),
+            answer_rewrite_rules: Vec::new(),
+            answer_rewrite_stage: RewriteStage::AfterCache, // 缓存保存上游原样的答案，可以在实例间共享
+            revalidate_window: None, // 返回过期答案会改变语义，需要单独开启
+            nxdomain_protection_window: None, // 核实会让部分否定应答多查一次上游，需要单独开启
+            on_rate_limited: RateLimitedBehavior::FailoverImmediately, // 等待会拉长查询耗时，需要单独开启
+            wire_capture_max_bytes: u16::MAX as usize, // DNS报文不超过65535字节，即完整保留
+            enable_dns_cookies: false, // 不是所有上游都正确处理COOKIE选项，需要单独开启
+            dns_cookie_lifetime: Duration::from_secs(3600),
+            max_failover_attempts: 3, // 与轮询策略最多尝试的服务器数一致
+            auto_offline_after: None, // 离线时不再向上游发送查询，自动切换需要单独开启
+            max_upstream_silence: None, // 探测会产生额外的上游查询，需要单独开启
+            offline_revalidation_rate: 10,
+            host_resolution: HostResolution::default(), // 与此前一致：主机名交给系统解析器
+            udp_socket_pool: None,
+            strict_upstream_resolution: false, // 一个上游的名称解析不了不应让整个解析器不可用
+            encrypted_fallback: EncryptedFallbackPolicy::Strict, // 降级会削弱安全性，需要单独开启
+            encrypted_fallback_threshold: 3,
+            encrypted_fallback_probe_interval: Duration::from_secs(30),
+            plaintext_fallbacks: HashMap::new(),
+            tls_quarantine: None, // 隔离期内即使证书已更换也不会使用该上游，需要单独开启
+            random_source: None, // 由操作系统熵源播种的CSPRNG，不可预测
+            clock: None, // 真实时钟
+            memory_budget: None, // 各组件已各自限量，统一预算面向内存受限的部署，需要单独开启
+            log_query_summary: false, // 改变已有的info日志输出，需要单独开启
+            adaptive_timeout: None, // 超时随观测延迟变化，需要单独开启
+            metrics_upstream_labels: Vec::new(), // 标签默认不进入指标
         }
     }
 }
@@ -133,14 +1132,35 @@ impl CoreResolverConfig {
 impl CoreResolver {
     /// 创建新的解析器
     pub fn new(config: CoreResolverConfig) -> Self {
+        let clock = config.clock.clone().unwrap_or_else(clock::real_clock);
+        Self::with_clock(config, clock)
+    }
+    
+    /// 使用指定时间源创建解析器（忽略 [`CoreResolverConfig::clock`]），缓存、上游监控、重试退避
+    /// 和各传输条目的耗时、退避期、单次发送超时共用该时间源
+    pub fn with_clock(config: CoreResolverConfig, clock: Arc<dyn Clock>) -> Self {
+        let random = config.random_source.clone().unwrap_or_else(random::thread_random);
         let cache = if config.enable_cache {
-            Some(Arc::new(DnsCache::new(config.max_cache_ttl)))
+            let backend = config.cache_backend.clone().unwrap_or_else(|| {
+                Arc::new(DnsCache::with_clock(config.max_cache_ttl, clock.clone()))
+            });
+            Some(CacheLayer::new(backend, config.cache_zero_ttl))
         } else {
             None
         };
+        // 过期答案窗口内的条目还要先行返回，否定应答保护窗口内的条目还要用来比对，清理时保留
+        let cache_janitor = cache.as_ref()
+            .zip(config.cache_janitor)
+            .and_then(|(cache, mut janitor)| {
+                janitor.retain_expired = janitor.retain_expired
+                    .max(config.revalidate_window.unwrap_or_default())
+                    .max(config.nxdomain_protection_window.unwrap_or_default());
+                cache.spawn_janitor(janitor)
+            })
+            .map(Arc::new);
         
         let upstream_monitor = if config.enable_upstream_monitoring {
-            Some(Arc::new(UpstreamMonitor::with_config(
+            Some(Arc::new(UpstreamMonitor::with_clock(
                 config.upstream_monitoring_interval,
                 health::UpstreamConfig {
                     min_success_rate: 0.7,
@@ -148,519 +1168,3700 @@ impl CoreResolver {
                     max_consecutive_failures: 3,
                     recovery_success_count: 2,
                     stats_window_size: 100,
-                    max_unavailable_duration: std::time::Duration::from_secs(300),
-                }
-            )))
+                    max_unavailable_duration: health::MAX_UNAVAILABLE_DURATION,
+                },
+                clock.clone(),
+            )
+                .with_detailed_stats(config.enable_stats)
+                .with_event_history(config.upstream_event_history)
+                .with_observer(config.query_observer.clone())
+                .with_rate_limited_ignored(config.on_rate_limited != RateLimitedBehavior::FailoverImmediately)))
         } else {
             None
         };
         
+        let memory_budget = config.memory_budget.map(|limit| Arc::new(MemoryBudget::new(limit)));
+        if let Some(budget) = &memory_budget {
+            if let Some(cache) = &cache {
+                cache.attach_memory_budget(budget);
+            }
+            if let Some(monitor) = &upstream_monitor {
+                monitor.attach_memory_budget(budget);
+            }
+        }
+        
+        let health_probe = config.health_probe.map(Arc::new);
+        let probe_rounds = Arc::new(AtomicUsize::new(0));
+        let silence_prober = match (config.max_upstream_silence, &health_probe, &upstream_monitor) {
+            (Some(max_silence), Some(probe), Some(_)) => Some((
+                Arc::new(TransportProber {
+                    targets: RwLock::new(Arc::new(Vec::new())),
+                    probe: probe.clone(),
+                    rounds: probe_rounds.clone(),
+                    enable_edns: config.enable_edns,
+                }),
+                max_silence,
+            )),
+            (Some(_), _, _) => {
+                dns_warn!("静默检测需要启用上游监控并设置健康探测，max_upstream_silence 不生效");
+                None
+            },
+            (None, _, _) => None,
+        };
+        
         Self {
-            transports: Vec::new(),
+            transports: RwLock::new(Arc::new(Vec::new())),
             strategy: config.strategy,
             cache,
+            cache_janitor,
             upstream_monitor,
+            memory_budget,
+            upstream_monitor_task: Arc::new(Mutex::new(None)),
+            active_tier: Arc::new(Mutex::new(None)),
+            tier_probed_at: Arc::new(Mutex::new(None)),
+            health_probe,
+            send_permits: Arc::new(SendPermits::new(config.concurrent_queries, config.interactive_reserve, config.concurrency_wait_timeout)),
+            probe_rounds,
+            silence_prober,
             default_timeout: config.default_timeout,
             retry_count: config.retry_count,
+            retries_performed: Arc::new(AtomicU64::new(0)),
             default_client_address: config.default_client_address,
+            ttl_clamp: TtlClamp::new(config.min_ttl, config.max_ttl),
+            ttl_overrides: Arc::new(TtlOverrides::new(config.ttl_overrides)),
+            ecs_cache_mode: config.ecs_cache_mode,
+            strict_response_check: config.strict_response_check,
+            question_policy: QuestionPolicy::new(config.accept_questionless_responses),
+            record_limits: config.record_limits,
+            cache_truncated_responses: config.cache_truncated_responses,
+            enable_edns: config.enable_edns,
+            capture_timing_breakdown: config.capture_timing_breakdown,
+            record_rotation: Arc::new(RecordRotator::new(config.record_rotation, random.clone())),
+            answer_rewrite: Arc::new(AnswerRewriter::new(config.answer_rewrite_rules, config.answer_rewrite_stage)),
+            revalidate_window: config.revalidate_window,
+            nxdomain_protection_window: config.nxdomain_protection_window,
+            on_rate_limited: config.on_rate_limited,
+            pending_fetches: Arc::new(Mutex::new(HashMap::new())),
+            wire_capture_max_bytes: config.wire_capture_max_bytes,
+            cookie_jar: config.enable_dns_cookies.then(|| Arc::new(DnsCookieJar::with_random_source(config.dns_cookie_lifetime, random.as_ref()))),
+            offline: Arc::new(OfflineState::default()),
+            auto_offline_after: config.auto_offline_after,
+            offline_revalidation_interval: Duration::from_secs(1) / config.offline_revalidation_rate.max(1),
+            host_resolution: config.host_resolution,
+            udp_socket_pool: config.udp_socket_pool,
+            encrypted_fallback: (config.encrypted_fallback != EncryptedFallbackPolicy::Strict).then(|| Arc::new(
+                EncryptedFallbackState::new(config.encrypted_fallback, config.encrypted_fallback_threshold, config.encrypted_fallback_probe_interval)
+            )),
+            plaintext_fallbacks: Arc::new(config.plaintext_fallbacks),
+            tls_quarantine: config.tls_quarantine,
+            clock,
+            random,
         }
     }
     
     // 注意：移除了 default() 方法，因为它依赖兜底配置
     // 用户现在必须明确提供配置，不能依赖隐式默认值
     
+    /// DNS Cookie状态，未开启时为 `None`
+    pub(crate) fn cookie_jar(&self) -> Option<&Arc<DnsCookieJar>> {
+        self.cookie_jar.as_ref()
+    }
+    
+    /// 解析器使用的随机数来源
+    pub(crate) fn random_source(&self) -> &Arc<dyn RandomSource> {
+        &self.random
+    }
+    
     /// 添加UDP传输
     pub fn add_udp_transport(&mut self, config: TransportConfig) {
         dns_info!("🪶 添加UDP传输: {}:{}", config.server, config.port);
-        let transport = Arc::new(UdpTransport::new(config));
-        self.transports.push(transport.clone());
-        dns_info!("🪶 UDP传输已添加，当前传输总数: {}", self.transports.len());
+        let mut transport = UdpTransport::new(config).with_host_resolution(self.host_resolution.clone());
+        if let Some(pool) = &self.udp_socket_pool {
+            transport = transport.with_socket_pool(pool.clone());
+        }
+        if let Some(jar) = &self.cookie_jar {
+            transport = transport.with_cookies(jar.clone());
+        }
+        let transport = Arc::new(transport);
+        self.push_transport(transport.endpoint(), transport.clone());
+        dns_info!("🪶 UDP传输已添加，当前传输总数: {}", self.transport_count());
         dns_debug!("新添加的传输类型: {}", transport.transport_type());
     }
     
     /// 添加TCP传输
+    #[cfg(feature = "tcp")]
     pub fn add_tcp_transport(&mut self, config: TransportConfig) {
         dns_info!("🔗 添加TCP传输: {}:{}", config.server, config.port);
-        let transport = Arc::new(TcpTransport::new(config));
-        self.transports.push(transport.clone());
-        dns_info!("🔗 TCP传输已添加，当前传输总数: {}", self.transports.len());
+        let transport = Arc::new(TcpTransport::new(config).with_host_resolution(self.host_resolution.clone()));
+        self.push_transport(transport.endpoint(), transport.clone());
+        dns_info!("🔗 TCP传输已添加，当前传输总数: {}", self.transport_count());
         dns_debug!("新添加的传输类型: {}", transport.transport_type());
     }
     
     /// 添加TLS传输
+    #[cfg(feature = "dot")]
     pub fn add_tls_transport(&mut self, config: TlsConfig) -> Result<()> {
         dns_info!("🔒 添加DoT传输: {}:{}", config.base.server, config.base.port);
-        let transport = Arc::new(TlsTransport::new(config)?);
-        self.transports.push(transport.clone());
-        dns_info!("🔒 DoT传输已添加，当前传输总数: {}", self.transports.len());
+        let transport = Arc::new(TlsTransport::new(config)?.with_host_resolution(self.host_resolution.clone()));
+        self.push_transport(transport.endpoint(), transport.clone());
+        dns_info!("🔒 DoT传输已添加，当前传输总数: {}", self.transport_count());
         dns_debug!("新添加的传输类型: {}", transport.transport_type());
         Ok(())
     }
     
     /// 添加HTTPS传输
+    #[cfg(feature = "doh")]
     pub fn add_https_transport(&mut self, config: HttpsConfig) -> Result<()> {
         dns_info!("🌐 添加DoH传输: {}", config.url);
         let transport = Arc::new(HttpsTransport::new(config)?);
-        self.transports.push(transport.clone());
-        dns_info!("🌐 DoH传输已添加，当前传输总数: {}", self.transports.len());
+        self.push_transport(transport.endpoint(), transport.clone());
+        dns_info!("🌐 DoH传输已添加，当前传输总数: {}", self.transport_count());
         dns_debug!("新添加的传输类型: {}", transport.transport_type());
         Ok(())
     }
     
-    /// 添加自定义传输
+    /// 添加自定义传输（以端点地址作为名称）
     pub fn add_transport(&mut self, transport: Arc<dyn Transport>) {
-        self.transports.push(transport);
+        self.push_transport(transport.endpoint(), transport);
     }
     
-    /// 查询DNS记录
-    pub async fn query(
-        &self,
-        name: &str,
-        record_type: RecordType,
-        class: QClass,
-    ) -> Result<Response> {
-        self.query_with_client_ip(name, record_type, class, None).await
+    /// 添加带名称的传输
+    /// 
+    /// 名称会出现在 [`TransportInfo::name`] 中，通常使用上游服务器名称
+    pub fn add_named_transport(&mut self, name: impl Into<String>, transport: Arc<dyn Transport>) {
+        let name = name.into();
+        dns_debug!("添加传输 {} ({}: {})", name, transport.transport_type(), transport.endpoint());
+        self.push_transport(name, transport);
     }
     
-    /// 查询DNS记录并指定客户端IP
-    pub async fn query_with_client_ip(
-        &self,
-        name: &str,
-        record_type: RecordType,
-        class: QClass,
-        client_ip: Option<IpAddr>,
-    ) -> Result<Response> {
-        let client_address = client_ip.map(|ip| match ip {
-            IpAddr::V4(addr) => ClientAddress::from_ipv4(addr, 24),
-            IpAddr::V6(addr) => ClientAddress::from_ipv6(addr, 56),
-        });
-        
-        let query = Query {
-            name: name.to_string(),
-            qtype: record_type,
-            qclass: class,
-        };
-        
-        // 检查缓存
-        if let Some(cache) = &self.cache {
-            if let Some(cached_response) = cache.get(&query) {
-                return Ok(cached_response);
-            }
+    fn push_transport(&self, name: String, transport: Arc<dyn Transport + Send + Sync + 'static>) {
+        let entry = self.named_transport(name, transport);
+        self.update_transports(|list| list.push(entry));
+    }
+    
+    /// 创建传输条目，开启了证书校验降级时为加密上游准备降级传输，否则开启了握手失败隔离时准备隔离状态
+    fn named_transport(&self, name: String, transport: Arc<dyn Transport + Send + Sync + 'static>) -> NamedTransport {
+        let degraded = self.degraded_route(&name, &transport);
+        let quarantine = self.tls_quarantine
+            .filter(|_| degraded.is_none() && transport.encrypted_host().is_some())
+            .map(|config| Arc::new(HandshakeQuarantine::new(config, self.clock.clone())));
+        NamedTransport {
+            degraded,
+            quarantine,
+            question_policy: self.question_policy,
+            record_limits: self.record_limits,
+            random: self.random.clone(),
+            clock: self.clock.clone(),
+            ..NamedTransport::new(name, transport, self.capture_timing_breakdown, self.send_permits.clone())
         }
-        
-        // 创建DNS请求
-        let request = Request {
-            id: rand::random(),
-            flags: Flags::default(),
-            query: query.clone(),
-            client_address: client_address.or_else(|| self.default_client_address.clone()),
+    }
+    
+    /// 加密上游的降级传输：明文降级连接备用上游或同一主机的53端口，跳过校验降级连接同一上游但不校验证书
+    fn degraded_route(&self, name: &str, transport: &Arc<dyn Transport + Send + Sync + 'static>) -> Option<DegradedRoute> {
+        let state = self.encrypted_fallback.as_ref()?;
+        let host = transport.encrypted_host()?;
+        let fallback: Arc<dyn Transport> = match state.policy() {
+            EncryptedFallbackPolicy::Strict => return None,
+            EncryptedFallbackPolicy::OpportunisticPlaintext => {
+                let (server, port) = self.plaintext_fallbacks.get(name).cloned().unwrap_or((host, 53));
+                let config = TransportConfig {
+                    server,
+                    port,
+                    timeout: transport.timeout(),
+                    tcp_fast_open: false,
+                    tcp_nodelay: true,
+                    pool_size: 1,
+                    buffer_size: crate::transport::UDP_EDNS_PAYLOAD_SIZE as usize,
+                    record_limits: Some(self.record_limits),
+                };
+                Arc::new(UdpTransport::new(config).with_host_resolution(self.host_resolution.clone()))
+            }
+            EncryptedFallbackPolicy::OpportunisticSkipVerify => match transport.insecure_variant() {
+                Ok(Some(insecure)) => insecure,
+                Ok(None) => return None,
+                Err(e) => {
+                    dns_warn!("无法为加密上游 {} 创建不校验证书的连接，该上游不参与降级: {}", name, e);
+                    return None;
+                }
+            },
         };
-        
-        // 执行查询策略
-        let response = self.execute_query_strategy(&request).await?;
-        
-        // 缓存结果
-        if let Some(cache) = &self.cache {
-            cache.insert(query, response.clone());
+        dns_debug!("加密上游 {} 的降级传输: {} ({})", name, fallback.endpoint(), fallback.transport_type());
+        state.register(name);
+        Some(DegradedRoute { transport: fallback, state: state.clone() })
+    }
+    
+    /// 当前传输列表的快照，查询期间列表被修改不影响本次查询
+    fn transports(&self) -> Arc<Vec<NamedTransport>> {
+        self.transports.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+    
+    /// 启用中、参与查询策略的传输（在作用域中查询时只含作用域允许的传输）
+    fn enabled_transports(&self) -> Vec<NamedTransport> {
+        self.transports().iter()
+            .filter(|entry| entry.enabled && !entry.routed_only && entry.in_scope())
+            .cloned()
+            .collect()
+    }
+    
+    /// 启用中、只用于按域名转发的传输（在作用域中查询时只含作用域允许的传输）
+    fn routed_transports(&self) -> Vec<NamedTransport> {
+        self.transports().iter()
+            .filter(|entry| entry.enabled && entry.routed_only && entry.in_scope())
+            .cloned()
+            .collect()
+    }
+    
+    /// 以写时复制的方式修改传输列表
+    fn update_transports<R>(&self, update: impl FnOnce(&mut Vec<NamedTransport>) -> R) -> R {
+        let mut guard = self.transports.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut list = guard.as_ref().clone();
+        let result = update(&mut list);
+        *guard = Arc::new(list);
+        if let Some((prober, _)) = &self.silence_prober {
+            prober.set_targets(guard.clone());
+            drop(guard);
+            self.ensure_upstream_monitor_task();
         }
-        
-        Ok(response)
+        result
     }
     
-    /// 设置默认客户端地址
-    pub fn set_default_client_address(&mut self, client_address: Option<ClientAddress>) {
-        self.default_client_address = client_address;
+    /// 运行时注册带名称的传输，名称已存在时报错
+    pub fn register_transport(&self, name: impl Into<String>, transport: Arc<dyn Transport>) -> Result<()> {
+        let name = name.into();
+        self.update_transports(|list| {
+            if list.iter().any(|entry| entry.name == name) {
+                return Err(DnsError::InvalidConfig(format!("Transport '{}' already exists", name)));
+            }
+            dns_info!("注册传输 {} ({}: {})", name, transport.transport_type(), transport.endpoint());
+            list.push(self.named_transport(name, transport));
+            Ok(())
+        })
     }
     
-    /// 设置默认客户端IP（便捷方法）
-    pub fn set_default_client_ip(&mut self, client_ip: Option<IpAddr>) {
-        self.default_client_address = client_ip.map(|ip| match ip {
-            IpAddr::V4(addr) => ClientAddress::from_ipv4(addr, 24),
-            IpAddr::V6(addr) => ClientAddress::from_ipv6(addr, 56),
+    /// 运行时注册只用于按域名转发的传输：它只应答指定给它的查询，不参与查询策略
+    pub fn register_routed_transport(&self, name: impl Into<String>, transport: Arc<dyn Transport>) -> Result<()> {
+        let name = name.into();
+        self.register_transport(name.clone(), transport)?;
+        self.update_transports(|list| {
+            if let Some(entry) = list.iter_mut().find(|entry| entry.name == name) {
+                entry.routed_only = true;
+            }
         });
+        Ok(())
     }
     
-    /// 执行查询策略
-    async fn execute_query_strategy(&self, request: &Request) -> Result<Response> {
-        if self.transports.is_empty() {
-            return Err(DnsError::Config("No transports configured".to_string()));
-        }
-        
-        dns_info!("🔍 开始DNS查询: {} (类型: {:?}), 策略: {:?}, 可用传输: {}", 
-                 request.query.name, request.query.qtype, self.strategy, self.transports.len());
-        
-        // 打印所有可用传输的类型
-        for (i, transport) in self.transports.iter().enumerate() {
-            dns_debug!("传输[{}]: {}", i, transport.transport_type());
+    /// 启用或停用指定名称的传输，停用的传输不参与任何查询
+    pub fn set_transport_enabled(&self, name: &str, enabled: bool) -> Result<()> {
+        self.update_transports(|list| {
+            let entry = list.iter_mut()
+                .find(|entry| entry.name == name)
+                .ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", name)))?;
+            entry.enabled = enabled;
+            Ok(())
+        })
+    }
+    
+    /// 设置发往指定名称的传输时客户端子网的处理方式
+    pub fn set_transport_ecs_policy(&self, name: &str, policy: EcsPolicy) -> Result<()> {
+        policy.validate()?;
+        self.update_transports(|list| {
+            let entry = list.iter_mut()
+                .find(|entry| entry.name == name)
+                .ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", name)))?;
+            entry.ecs_policy = policy;
+            Ok(())
+        })
+    }
+    
+    /// 设置发往指定名称的传输时EDNS、DO位和大小写随机化的开关，未配置的开关沿用解析器的设置
+    pub fn set_transport_features(&self, name: &str, features: UpstreamFeatures) -> Result<()> {
+        features.validate()?;
+        self.update_transports(|list| {
+            let entry = list.iter_mut()
+                .find(|entry| entry.name == name)
+                .ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", name)))?;
+            entry.features = features;
+            Ok(())
+        })
+    }
+    
+    /// 指定名称的传输实际生效的请求特性，不存在时返回 `None`
+    pub fn transport_features(&self, name: &str) -> Option<EffectiveFeatures> {
+        self.transports().iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.features.effective(self.enable_edns))
+    }
+    
+    /// 设置同时发往指定名称的传输的查询上限及达到上限时的处理方式，`None` 表示不限制
+    /// 
+    /// 上限已满时查询策略在本次查询中跳过该传输（[`QueueBehavior::Wait`] 时先限时等待），
+    /// 跳过次数见 [`transport_saturations`](Self::transport_saturations)；重新设置后计数从0开始
+    pub fn set_transport_max_inflight(&self, name: &str, max_inflight: Option<usize>, behavior: QueueBehavior) -> Result<()> {
+        check_max_inflight(name, max_inflight)?;
+        self.update_transports(|list| {
+            let entry = list.iter_mut()
+                .find(|entry| entry.name == name)
+                .ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", name)))?;
+            entry.inflight_limit = max_inflight.map(|limit| Arc::new(InflightLimit::new(limit, self.send_permits.reserve, behavior)));
+            Ok(())
+        })
+    }
+    
+    /// 设置指定名称的传输单次发送的超时，`None` 表示只受传输自身的超时限制
+    /// 
+    /// 超时比传输自身的超时短时提前放弃该次发送，以 [`DnsError::Timeout`]（`phase` 为 `None`）失败；
+    /// 比传输自身的超时长时不起作用。由自适应超时定期设置，见 [`CoreResolverConfig::adaptive_timeout`]
+    pub fn set_transport_attempt_timeout(&self, name: &str, attempt_timeout: Option<Duration>) -> Result<()> {
+        if attempt_timeout.is_some_and(|limit| limit.is_zero()) {
+            return Err(DnsError::InvalidConfig(format!("Attempt timeout for transport '{}' cannot be zero", name)));
         }
-        
-        match self.strategy {
-            QueryStrategy::Fifo => self.query_fastest_first(request).await,
-            QueryStrategy::Smart => self.query_smart_decision(request).await,
-            QueryStrategy::RoundRobin => self.query_parallel(request).await,
+        self.update_transports(|list| {
+            let entry = list.iter_mut()
+                .find(|entry| entry.name == name)
+                .ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", name)))?;
+            entry.attempt_timeout = attempt_timeout;
+            Ok(())
+        })
+    }
+    
+    /// 指定名称的传输单次发送的超时，没有设置或传输不存在时为 `None`
+    pub fn transport_attempt_timeout(&self, name: &str) -> Option<Duration> {
+        self.transports().iter()
+            .find(|entry| entry.name == name)
+            .and_then(|entry| entry.attempt_timeout)
+    }
+    
+    /// 指定名称的传输因在途查询已满而被跳过的次数，没有设置上限或传输不存在时为0
+    pub fn transport_saturations(&self, name: &str) -> u64 {
+        self.transports().iter()
+            .find(|entry| entry.name == name)
+            .and_then(|entry| entry.inflight_limit.as_ref())
+            .map_or(0, |limit| limit.saturated.load(Ordering::Relaxed))
+    }
+    
+    /// 指定名称的传输给出的否定应答被其他上游推翻的次数，传输不存在时为0
+    ///
+    /// 见 [`CoreResolverConfig::nxdomain_protection_window`]
+    pub fn transport_suspicious_negatives(&self, name: &str) -> u64 {
+        self.transports().iter()
+            .find(|entry| entry.name == name)
+            .map_or(0, |entry| entry.suspicious_negatives.load(Ordering::Relaxed))
+    }
+    
+    /// 指定名称的传输被限流（HTTP 429）的次数，传输不存在时为0
+    ///
+    /// 见 [`CoreResolverConfig::on_rate_limited`]
+    pub fn transport_rate_limited(&self, name: &str) -> u64 {
+        self.transports().iter()
+            .find(|entry| entry.name == name)
+            .map_or(0, |entry| entry.rate_limit.count())
+    }
+    
+    /// 指定名称的传输的握手失败隔离状态，没有隔离、未开启隔离或传输不存在时为 `None`
+    ///
+    /// 见 [`CoreResolverConfig::tls_quarantine`]
+    pub fn transport_quarantine(&self, name: &str) -> Option<QuarantineStatus> {
+        self.transports().iter()
+            .find(|entry| entry.name == name)
+            .and_then(|entry| entry.quarantine.as_ref())
+            .and_then(|quarantine| quarantine.status())
+    }
+    
+    /// 立即解除指定名称的传输的握手失败隔离（例如刚更换了证书），返回此前是否处于隔离；传输不存在时返回错误
+    pub fn clear_transport_quarantine(&self, name: &str) -> Result<bool> {
+        let transports = self.transports();
+        let entry = transports.iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", name)))?;
+        let cleared = entry.quarantine.as_ref().is_some_and(|quarantine| quarantine.clear());
+        if cleared {
+            dns_info!("🔐 手动解除上游 {} 的握手失败隔离", name);
         }
+        Ok(cleared)
     }
     
-    /// 最快优先策略（优化版：支持早期取消）
-    async fn query_fastest_first(&self, request: &Request) -> Result<Response> {
-        use tokio::sync::{oneshot, broadcast};
-        
-        // 获取健康的传输实例
-        let available_transports = self.get_available_transports();
-        
-        if available_transports.is_empty() {
-            return Err(DnsError::Server("No available transports".to_string()));
+    /// 设置指定名称的传输的p95响应时间SLO，`None` 表示取消
+    /// 
+    /// 需要启用上游监控：监控任务每个监控间隔评估一次，持续违反或恢复时通知
+    /// [`CoreResolverConfig::query_observer`]，当前判定见 [`upstream_slo`](Self::upstream_slo)
+    pub fn set_transport_slo(&self, name: &str, slo: Option<SloConfig>) -> Result<()> {
+        if !self.transports().iter().any(|entry| entry.name == name) {
+            return Err(DnsError::InvalidConfig(format!("Transport '{}' not found", name)));
+        }
+        let Some(monitor) = &self.upstream_monitor else {
+            if slo.is_none() {
+                return Ok(());
+            }
+            return Err(DnsError::InvalidConfig(format!(
+                "SLO for upstream '{}' requires upstream monitoring to be enabled", name
+            )));
+        };
+        if let Some(config) = &slo {
+            config.validate(name)?;
+            self.ensure_upstream_monitor_task();
+        }
+        monitor.set_slo(name, slo);
+        Ok(())
+    }
+    
+    /// 上游监控任务还没有运行时启动它（开启了静默检测时带上探测），不在异步运行时中时只记录警告
+    fn ensure_upstream_monitor_task(&self) {
+        let Some(monitor) = &self.upstream_monitor else {
+            return;
+        };
+        let mut task = self.upstream_monitor_task.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if task.is_none() {
+            *task = self.upstream_monitor_task_for(monitor.clone()).spawn();
+            if task.is_none() {
+                dns_warn!("不在异步运行时中，上游监控任务未启动，SLO和上游静默不会被检查");
+            }
+        }
+    }
+    
+    fn upstream_monitor_task_for(&self, monitor: Arc<UpstreamMonitor>) -> UpstreamMonitorTask {
+        let task = UpstreamMonitorTask::new(monitor);
+        match &self.silence_prober {
+            Some((prober, max_silence)) => task.with_silence_probe(prober.clone(), *max_silence),
+            None => task,
+        }
+    }
+    
+    /// 指定名称的传输的SLO当前判定，未设置SLO时为 `None`
+    pub fn upstream_slo(&self, name: &str) -> Option<SloStatus> {
+        self.upstream_monitor.as_ref()?.slo_status(name)
+    }
+    
+    /// 设置指定名称的传输的优先层级（0最优先）
+    /// 
+    /// 查询策略只使用有可用传输的最优先层级；一个层级的传输全部被上游监控判定为不可用后才改用下一层级
+    pub fn set_transport_tier(&self, name: &str, tier: u8) -> Result<()> {
+        self.update_transports(|list| {
+            let entry = list.iter_mut()
+                .find(|entry| entry.name == name)
+                .ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", name)))?;
+            entry.tier = tier;
+            Ok(())
+        })
+    }
+    
+    /// 设置只发往指定名称传输的记录类型，`None` 表示不限制
+    /// 
+    /// 其他类型的查询在选择传输时跳过它；没有任何上游服务某个记录类型时，该类型的查询没有可用上游
+    pub fn set_transport_record_types(&self, name: &str, record_types: Option<Vec<RecordType>>) -> Result<()> {
+        if record_types.as_ref().is_some_and(Vec::is_empty) {
+            return Err(DnsError::InvalidConfig(format!("Record types for transport '{}' cannot be empty", name)));
         }
+        let record_types: Option<Arc<[RecordType]>> = record_types.map(Into::into);
+        self.update_transports(|list| {
+            let entry = list.iter_mut()
+                .find(|entry| entry.name == name)
+                .ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", name)))?;
+            entry.record_types = record_types;
+            Ok(())
+        })
+    }
+    
+    /// 设置指定名称传输的标签，只作为元数据附在状态变化事件上
+    pub fn set_transport_labels(&self, name: &str, labels: UpstreamLabels) -> Result<()> {
+        let labels = Arc::new(labels);
+        self.update_transports(|list| {
+            let entry = list.iter_mut()
+                .find(|entry| entry.name == name)
+                .ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", name)))?;
+            entry.labels = labels;
+            Ok(())
+        })
+    }
+    
+    /// 移除指定名称的传输
+    /// 
+    /// 移除后新的查询不再使用该传输；随后最多等待 `grace` 让已经发出的查询完成，
+    /// 全部完成返回 `true`，超时返回 `false`（剩余查询照常结束，只是不再等待）。
+    pub async fn remove_transport(&self, name: &str, grace: Duration) -> Result<bool> {
+        let in_flight = self.detach_transport(name)?;
+        Ok(self.wait_drained(name, &in_flight, grace).await)
+    }
+    
+    /// 从传输列表中摘下指定名称的传输，不等待已经发出的查询，返回其在途查询计数
+    /// 
+    /// 用于先在替换前的副本上摘下传输、替换后再等待排空，见 [`wait_drained`](Self::wait_drained)
+    pub(crate) fn detach_transport(&self, name: &str) -> Result<Arc<AtomicUsize>> {
+        let removed = self.update_transports(|list| {
+            let index = list.iter().position(|entry| entry.name == name)?;
+            Some(list.remove(index))
+        }).ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", name)))?;
         
-        dns_info!("⚡ 使用最快优先策略，并发查询 {} 个传输", available_transports.len());
-        for (i, transport) in available_transports.iter().enumerate() {
-            dns_debug!("并发传输[{}]: {}", i, transport.transport_type());
+        // 上游监控按传输名称统计，清掉该传输的统计和SLO，之后以同名注册的传输从头统计
+        if let Some(monitor) = &self.upstream_monitor {
+            monitor.reset_stats(&removed.name);
+            monitor.set_slo(&removed.name, None);
+        }
+        if let Some(route) = &removed.degraded {
+            route.state.unregister(&removed.name);
+        }
+        Ok(removed.in_flight)
+    }
+    
+    /// 最多等待 `grace` 让已摘下的传输上的查询完成，全部完成返回 `true`
+    pub(crate) async fn wait_drained(&self, name: &str, in_flight: &AtomicUsize, grace: Duration) -> bool {
+        let step = Duration::from_millis(10);
+        let mut waited = Duration::ZERO;
+        while in_flight.load(Ordering::Acquire) > 0 {
+            if waited >= grace {
+                dns_warn!(
+                    "传输 {} 已移除，仍有 {} 个查询未完成，不再等待",
+                    name, in_flight.load(Ordering::Acquire)
+                );
+                return false;
+            }
+            self.clock.sleep(step).await;
+            waited += step;
+        }
+        dns_info!("传输 {} 已移除", name);
+        true
+    }
+    
+    /// 把指定名称的传输换成新的传输实例（连接参数变化时），启用状态、层级、ECS等设置和按名称的统计保留
+    /// 
+    /// 已经开始的查询继续使用原传输
+    pub(crate) fn replace_transport(&self, name: &str, transport: Arc<dyn Transport>) -> Result<()> {
+        let replacement = self.named_transport(name.to_string(), transport);
+        self.update_transports(|list| {
+            let entry = list.iter_mut()
+                .find(|entry| entry.name == name)
+                .ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", name)))?;
+            if let Some(route) = &entry.degraded {
+                route.state.unregister(&entry.name);
+            }
+            *entry = NamedTransport {
+                enabled: entry.enabled,
+                ecs_policy: entry.ecs_policy.clone(),
+                features: entry.features,
+                routed_only: entry.routed_only,
+                tier: entry.tier,
+                inflight_limit: entry.inflight_limit.clone(),
+                record_types: entry.record_types.clone(),
+                labels: entry.labels.clone(),
+                ..replacement
+            };
+            Ok(())
+        })
+    }
+    
+    /// 按新配置中可以在运行时替换的设置创建副本，供 `SmartDnsResolver::reload` 整体替换
+    /// 
+    /// 替换查询策略、默认超时、重试次数、TTL上下限、TTL为0的记录和截断响应是否缓存，以及没有问题段的
+    /// 响应是否接受；缓存、上游监控、发送许可和各项计数与原解析器共享，传输列表从当前列表开始
+    pub(crate) fn reconfigured(&self, config: &CoreResolverConfig) -> Self {
+        let question_policy = QuestionPolicy::new(config.accept_questionless_responses);
+        let resolver = Self {
+            strategy: config.strategy,
+            cache: self.cache.clone().map(|cache| cache.with_zero_ttl_caching(config.cache_zero_ttl)),
+            default_timeout: config.default_timeout,
+            retry_count: config.retry_count,
+            ttl_clamp: TtlClamp::new(config.min_ttl, config.max_ttl),
+            question_policy,
+            cache_truncated_responses: config.cache_truncated_responses,
+            record_rotation: Arc::new(RecordRotator::new(config.record_rotation, self.random.clone())),
+            ..self.clone()
+        };
+        resolver.update_transports(|list| {
+            for entry in list.iter_mut() {
+                entry.question_policy = question_policy;
+            }
+        });
+        resolver
+    }
+    
+    /// 指定名称的传输是否启用，不存在时返回 `None`
+    pub fn is_transport_enabled(&self, name: &str) -> Option<bool> {
+        self.transports().iter().find(|entry| entry.name == name).map(|entry| entry.enabled)
+    }
+
+    /// 全部传输的名称（按添加顺序，包括已停用的）
+    pub fn transport_names(&self) -> Vec<String> {
+        self.transports().iter().map(|entry| entry.name.clone()).collect()
+    }
+    
+    /// 查询DNS记录
+    pub async fn query(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+    ) -> Result<Response> {
+        self.query_with_client_ip(name, record_type, class, None).await
+    }
+    
+    /// 查询DNS记录并指定客户端IP
+    pub async fn query_with_client_ip(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Response> {
+        self.query_with_info(name, record_type, class, client_ip)
+            .await
+            .map(|(response, _)| response)
+    }
+    
+    /// 查询DNS记录，同时返回实际给出响应的传输信息
+    /// 
+    /// 命中缓存时传输信息为 `None`
+    pub async fn query_with_info(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(Response, Option<TransportInfo>)> {
+        self.query_with_origin(name, record_type, class, client_ip)
+            .await
+            .map(|(response, origin)| (response, origin.into_transport_info()))
+    }
+    
+    /// 查询DNS记录，同时返回响应的来源
+    /// 
+    /// 与 [`CoreResolver::query_with_info`] 相同，另外区分有效缓存和后台刷新期间返回的过期缓存
+    pub async fn query_with_origin(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(Response, ResponseOrigin)> {
+        self.query_inner(name, record_type, class, client_ip, QueryRoute::Strategy, CacheUse::Read).await
+    }
+    
+    /// 查询DNS记录，命中缓存时返回与缓存共用数据的响应，不复制记录
+    /// 
+    /// 其余与 [`CoreResolver::query_with_origin`] 相同；开启了记录轮换时命中缓存仍需复制一份再重排
+    pub async fn query_shared(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(SharedResponse, ResponseOrigin)> {
+        self.query_inner_shared(name, record_type, class, client_ip, QueryRoute::Strategy, CacheUse::Read).await
+    }
+    
+    /// 不读缓存，直接向上游查询DNS记录（答案照常写入缓存）
+    /// 
+    /// 用于必须看到上游当前数据的场合，例如比对区域的SOA序列号
+    pub async fn query_uncached(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(Response, TransportInfo)> {
+        self.query_upstream(name, record_type, class, client_ip, QueryRoute::Strategy, CacheUse::Bypass).await
+    }
+    
+    /// 查询DNS记录并保留与上游收发的原始DNS报文（见 [`TransportInfo::wire`]）
+    /// 
+    /// 与 [`CoreResolver::query_uncached`] 一样不读缓存；每个报文最多保留
+    /// [`CoreResolverConfig::wire_capture_max_bytes`] 字节
+    pub async fn query_with_wire_capture(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(Response, TransportInfo)> {
+        self.query_upstream(name, record_type, class, client_ip, QueryRoute::Strategy, CacheUse::BypassCapturingWire).await
+    }
+    
+    /// 只向名为 `transport_name` 的传输查询，不读缓存（答案照常写入缓存）
+    /// 
+    /// 用于上层在某个上游返回SERVFAIL/REFUSED后换一个上游重试；该传输已停用、
+    /// 处于退避期或被上游监控判定为不可用时返回错误
+    pub(crate) async fn query_only_transport(
+        &self,
+        transport_name: &str,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+        capture_wire: bool,
+    ) -> Result<(Response, TransportInfo)> {
+        let cache_use = if capture_wire { CacheUse::BypassCapturingWire } else { CacheUse::Bypass };
+        self.query_upstream(name, record_type, class, client_ip, QueryRoute::Only(transport_name), cache_use).await
+    }
+    
+    /// 只在 `filter` 保留的传输中按查询策略查询（单次查询的上游筛选），命中缓存时传输信息为 `None`
+    /// 
+    /// 不考虑层级、退避和上游健康状态，已停用的传输不参与；筛选后没有传输时返回
+    /// [`DnsError::NoUpstreamAvailable`]。`read_cache` 为假时不读缓存，答案照常写入缓存
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn query_filtered(
+        &self,
+        filter: &UpstreamFilter,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+        read_cache: bool,
+        capture_wire: bool,
+    ) -> Result<(SharedResponse, Option<TransportInfo>)> {
+        let cache_use = match (capture_wire, read_cache) {
+            (true, _) => CacheUse::BypassCapturingWire,
+            (false, true) => CacheUse::Read,
+            (false, false) => CacheUse::Bypass,
+        };
+        self.query_inner_shared(name, record_type, class, client_ip, QueryRoute::Filtered(filter), cache_use)
+            .await
+            .map(|(response, origin)| (response, origin.into_transport_info()))
+    }
+    
+    /// 只向名为 `transport_name` 的传输查询（按域名转发），读缓存；命中缓存时传输信息为 `None`
+    pub(crate) async fn query_via_transport(
+        &self,
+        transport_name: &str,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(Response, Option<TransportInfo>)> {
+        self.query_inner(name, record_type, class, client_ip, QueryRoute::Only(transport_name), CacheUse::Read)
+            .await
+            .map(|(response, origin)| (response, origin.into_transport_info()))
+    }
+    
+    /// QNAME最小化（RFC 9156）：在发送完整查询前，向 `transport_name` 从 `apex` 下一级起逐级查询NS
+    /// 
+    /// 中间查询读写缓存、不携带客户端子网。某一级的应答为NXDOMAIN、REFUSED等非NOERROR
+    /// 或查询出错时停止，由调用方直接发送完整查询（RFC 9156 第2.3节）。返回发出的中间查询数
+    pub(crate) async fn minimise_towards(&self, transport_name: &str, apex: &str, name: &str, class: QClass) -> usize {
+        let mut sent = 0;
+        for step in minimisation_steps(apex, name) {
+            sent += 1;
+            match self.query_via_transport(transport_name, &step, RecordType::NS, class, None).await {
+                Ok((response, _)) if response.rcode() == ResponseCode::NoError => {}
+                Ok((response, _)) => {
+                    dns_debug!("QNAME最小化: {} NS 应答 {}，改发完整查询 {}", step, response.rcode().name(), name);
+                    break;
+                }
+                Err(e) => {
+                    dns_debug!("QNAME最小化: {} NS 查询失败（{}），改发完整查询 {}", step, e, name);
+                    break;
+                }
+            }
+        }
+        sent
+    }
+    
+    /// 绕过缓存的查询，返回给出响应的传输信息
+    async fn query_upstream(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+        route: QueryRoute<'_>,
+        cache_use: CacheUse,
+    ) -> Result<(Response, TransportInfo)> {
+        let (response, origin) = self.query_inner(name, record_type, class, client_ip, route, cache_use).await?;
+        match origin {
+            ResponseOrigin::Upstream(info) => Ok((response, info)),
+            ResponseOrigin::Cache | ResponseOrigin::StaleCache => {
+                Err(DnsError::Server(format!("Uncached query for {} was answered from cache", name)))
+            }
         }
+    }
+    
+    /// 应急查询：忽略查询策略和上游健康状态，向所有传输并发查询并采用第一个成功的响应
+    /// 
+    /// 写入缓存和返回前把低于 `ttl_floor` 的TTL抬高，上游恢复前尽量由缓存应答。
+    /// 命中缓存时传输信息为 `None`
+    pub async fn query_fan_out_with_info(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+        ttl_floor: Duration,
+    ) -> Result<(Response, Option<TransportInfo>)> {
+        self.query_inner(name, record_type, class, client_ip, QueryRoute::FanOut(ttl_floor), CacheUse::Read)
+            .await
+            .map(|(response, origin)| (response, origin.into_transport_info()))
+    }
+    
+    /// 优先向名为 `preferred` 的传输查询，它不可用或查询失败时按查询策略查询
+    /// 
+    /// 用于多个查询共用同一次上游选择的场景；命中缓存时传输信息为 `None`
+    pub async fn query_preferring(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+        preferred: &str,
+    ) -> Result<(Response, Option<TransportInfo>)> {
+        self.query_inner(name, record_type, class, client_ip, QueryRoute::Preferred(preferred), CacheUse::Read)
+            .await
+            .map(|(response, origin)| (response, origin.into_transport_info()))
+    }
+    
+    /// 查询实现，返回独立的响应
+    async fn query_inner(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+        route: QueryRoute<'_>,
+        cache_use: CacheUse,
+    ) -> Result<(Response, ResponseOrigin)> {
+        self.query_inner_shared(name, record_type, class, client_ip, route, cache_use)
+            .await
+            .map(|(response, origin)| (response.into_response(), origin))
+    }
+    
+    /// 查询实现，命中缓存时与缓存共用响应数据
+    async fn query_inner_shared(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+        route: QueryRoute<'_>,
+        cache_use: CacheUse,
+    ) -> Result<(SharedResponse, ResponseOrigin)> {
+        let client_address = client_ip
+            .map(|ip| match ip {
+                IpAddr::V4(addr) => ClientAddress::from_ipv4(addr, 24),
+                IpAddr::V6(addr) => ClientAddress::from_ipv6(addr, 56),
+            })
+            .or_else(|| self.default_client_address.clone());
+        // 作用域的ECS策略先于各上游自身的策略生效
+        let scope = scope::current();
+        let client_address = match scope.as_ref().and_then(|scope| scope.client_subnet.as_ref()) {
+            Some(policy) => policy.apply(client_address.as_ref()),
+            None => client_address,
+        };
+        let client_address = query_edns::client_address(client_address);
         
-        // 创建取消通道，用于在获得第一个成功响应后取消其他任务
-        let (cancel_tx, _) = broadcast::channel::<()>(1);
-        let cancel_tx = Arc::new(cancel_tx);
-        let (success_tx, mut success_rx) = oneshot::channel();
-        let success_tx = Arc::new(tokio::sync::Mutex::new(Some(success_tx)));
+        // 名称不区分大小写：统一为小写、去掉末尾的点后再查缓存和发送，同一名称的不同写法共用缓存条目
+        let query = Query {
+            name: normalize_name(name),
+            qtype: record_type,
+            qclass: class,
+        };
         
-        // 并发查询所有传输
-        let mut tasks = Vec::new();
+        // 携带ECS的答案因地区而异：按实际发给上游的子网缓存，或按配置完全绕过缓存。
+        // 各上游的ECS策略不同时，答案可能缓存在其中任何一个子网下
+        let subnets: Vec<Option<ClientAddress>> = self.effective_client_subnets(client_address.as_ref())
+            .into_iter()
+            .filter(|subnet| self.is_cacheable_subnet(subnet.as_ref()))
+            .collect();
+        let cache = self.cache.as_ref().filter(|_| !subnets.is_empty());
         
-        for transport in available_transports {
-            let transport_clone = Arc::clone(&transport);
-            let request_clone = request.clone();
-            let mut cancel_rx = cancel_tx.subscribe();
-            let success_tx_clone = success_tx.clone();
-            let cancel_tx_clone = cancel_tx.clone();
-            let upstream_monitor = self.upstream_monitor.clone();
-            
-            let task = tokio::spawn(async move {
-                let start = Instant::now();
-                let transport_type = transport_clone.transport_type();
-                dns_debug!("🚀 开始使用 {} 传输查询", transport_type);
-                
-                // 使用select!来同时监听取消信号和DNS查询
-                tokio::select! {
-                    // DNS查询结果
-                    result = transport_clone.send(&request_clone) => {
-                        let duration = start.elapsed();
-                        
-                        match result {
-                            Ok(response) => {
-                                dns_info!("✅ {} 传输查询成功 (耗时: {:?}ms)", transport_type, duration.as_millis());
-                                // 记录成功统计
-                                if let Some(upstream_monitor) = &upstream_monitor {
-                                    upstream_monitor.record_success(transport_type, duration);
-                                }
-                                
-                                // 尝试发送成功结果（只有第一个成功的会被接收）
-                                if let Ok(mut sender) = success_tx_clone.try_lock() {
-                                    if let Some(tx) = sender.take() {
-                                        let _ = tx.send(Ok(response));
-                                        // 通知其他任务取消
-                                        let _ = cancel_tx_clone.send(());
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                dns_debug!("❌ {} 传输查询失败: {} (耗时: {:?}ms)", transport_type, e, duration.as_millis());
-                                // 记录失败统计
-                                if let Some(upstream_monitor) = &upstream_monitor {
-                                    upstream_monitor.record_failure(transport_type);
-                                }
-                                // 失败不取消其他任务，继续等待
-                            }
-                        }
+        // 检查缓存
+        if let Some(cache) = cache.filter(|_| cache_use == CacheUse::Read) {
+            for subnet in &subnets {
+                if let Some(cached_response) = cache.get(&query, subnet.as_ref()).await {
+                    let cached_response = self.answer_rewrite.after_cache_shared(&query, cached_response);
+                    return Ok((self.record_rotation.apply_shared(&query, cached_response), ResponseOrigin::Cache));
+                }
+            }
+        }
+        
+        // 创建DNS请求，当前查询的EDNS选项改写解析器的设置
+        let mut request = Request {
+            id: rand::random(),
+            flags: Flags::default(),
+            query: query.clone(),
+            client_address,
+            enable_edns: self.enable_edns,
+            dnssec_ok: scope.as_ref().is_some_and(|scope| scope.require_dnssec),
+            wire_capture_limit: (cache_use == CacheUse::BypassCapturingWire).then_some(self.wire_capture_max_bytes),
+            priority: priority::current(),
+        };
+        query_edns::apply(&mut request);
+        
+        // 离线模式：过期多久的缓存条目都照常应答，没有缓存的查询直接失败，不向上游发送
+        if self.check_offline() {
+            if let Some(cache) = cache.filter(|_| cache_use == CacheUse::Read) {
+                for subnet in &subnets {
+                    if let Some(mut stale) = cache.get_stale(&query, subnet.as_ref(), Duration::MAX).await {
+                        self.offline.record_stale_answer(&query, &request);
+                        self.answer_rewrite.after_cache(&query, &mut stale);
+                        self.record_rotation.apply(&query, &mut stale);
+                        crate::logger::note_stale_answer();
+                        return Ok((stale.into(), ResponseOrigin::StaleCache));
                     }
-                    // 取消信号
-                    _ = cancel_rx.recv() => {
-                        // 任务被取消，直接退出
-                        dns_debug!("传输 {} 的查询任务被取消", transport_clone.transport_type());
+                }
+            }
+            self.offline.record_refused();
+            dns_debug!("📴 离线模式: {} {:?} 没有缓存的答案", query.name, query.qtype);
+            return Err(DnsError::Offline { name: query.name });
+        }
+        
+        let (mut response, info) = match (cache, self.revalidate_window, route) {
+            // 刚过期的条目先返回旧答案并在后台刷新，其余未命中与并发的相同查询共用一次上游查询。
+            // 作用域内的查询不参与：合并的查询和后台刷新可能经过作用域之外的上游
+            (Some(cache), Some(window), QueryRoute::Strategy) if cache_use == CacheUse::Read && scope.is_none() => {
+                for subnet in &subnets {
+                    if let Some(mut stale) = cache.get_stale(&query, subnet.as_ref(), window).await {
+                        self.spawn_revalidation(request);
+                        self.answer_rewrite.after_cache(&query, &mut stale);
+                        self.record_rotation.apply(&query, &mut stale);
+                        crate::logger::note_stale_answer();
+                        return Ok((stale.into(), ResponseOrigin::StaleCache));
                     }
                 }
-            });
-            
-            tasks.push(task);
+                self.fetch_coalesced(&request, cache).await?
+            }
+            _ => self.fetch(&request, route, cache).await?,
+        };
+        self.answer_rewrite.after_cache(&query, &mut response);
+        self.record_rotation.apply(&query, &mut response);
+        
+        Ok((response.into(), ResponseOrigin::Upstream(info)))
+    }
+    
+    /// 按各启用传输的ECS策略，查询可能实际发出的客户端子网（去重，按传输顺序）
+    fn effective_client_subnets(&self, client: Option<&ClientAddress>) -> Vec<Option<ClientAddress>> {
+        let mut subnets: Vec<Option<ClientAddress>> = Vec::new();
+        for entry in self.transports().iter().filter(|entry| entry.enabled) {
+            let subnet = entry.ecs_policy.apply(client);
+            if !subnets.contains(&subnet) {
+                subnets.push(subnet);
+            }
+        }
+        if subnets.is_empty() {
+            subnets.push(client.cloned());
+        }
+        subnets
+    }
+    
+    /// 以该子网发出的查询的答案能否写入或读取缓存
+    fn is_cacheable_subnet(&self, subnet: Option<&ClientAddress>) -> bool {
+        subnet.is_none() || self.ecs_cache_mode != EcsCacheMode::Bypass
+    }
+    
+    /// 向上游查询，钳制TTL并写入缓存
+    async fn fetch(&self, request: &Request, route: QueryRoute<'_>, cache: Option<&CacheLayer>) -> FetchResult {
+        let query = &request.query;
+        // 进入离线模式前已经开始的合并查询和后台刷新也不再发出
+        if self.offline.is_active() {
+            return Err(DnsError::Offline { name: query.name.clone() });
+        }
+        
+        // 执行查询策略
+        let started = self.clock.now_instant();
+        // 选择传输时按记录类型跳过不服务该类型的上游；各查询策略的future较大，放在堆上
+        let (mut response, mut info) = Box::pin(query_type::scope(query.qtype, async {
+            let result = match route {
+                QueryRoute::Strategy => self.execute_query_strategy(request).await,
+                QueryRoute::FanOut(_) => self.query_fan_out(request).await,
+                QueryRoute::Preferred(preferred) => self.query_preferred(request, preferred).await,
+                QueryRoute::Only(transport_name) => self.query_only(request, transport_name).await,
+                QueryRoute::Filtered(filter) => self.query_filtered_strategy(request, filter).await,
+            };
+            match result {
+                // 很少走到的等待重试放在堆上，不增大每次查询的future
+                Err(e) => match Box::pin(self.retry_rate_limited(request, route, started)).await {
+                    Some(retried) => retried,
+                    None => Err(e),
+                },
+                result => result,
+            }
+        })).await?;
+        
+        // 否定应答推翻了不久前的肯定答案时，先向另一个上游核实
+        let mut cache_negative = true;
+        if let (QueryRoute::Strategy, Some(cache), Some(window)) = (route, cache, self.nxdomain_protection_window)
+            && is_negative_answer(&response)
+        {
+            let recently_positive = cache.peek_stale(query, info.client_subnet.as_ref(), window).await
+                .is_some_and(|stale| stale.rcode() == ResponseCode::NoError && !stale.answers.is_empty());
+            if recently_positive {
+                match self.verify_negative(request, &info.name).await {
+                    NegativeCheck::Overturned(answer) => (response, info) = *answer,
+                    NegativeCheck::Confirmed => {},
+                    NegativeCheck::Inconclusive => cache_negative = false,
+                }
+            }
+        }
+        
+        // 先钳制TTL，缓存和调用方看到的是同一份TTL；命中覆盖规则的名称按规则调整，不再做全局钳制
+        if !self.ttl_overrides.apply(query, &mut response) {
+            let clamped = self.ttl_clamp.apply(&mut response);
+            if clamped > 0 {
+                dns_debug!("{} 的响应中有 {} 条记录的TTL被钳制", query.name, clamped);
+            }
+        }
+        if let QueryRoute::FanOut(floor) = route {
+            let raised = TtlClamp::new(Some(floor), None).apply(&mut response);
+            if raised > 0 {
+                dns_debug!("应急模式: {} 的响应中有 {} 条记录的TTL被抬高到 {:?}", query.name, raised, floor);
+            }
+        }
+        
+        if self.strict_response_check {
+            let suspicious = DnsCache::check_response(query, &response)
+                .err()
+                .filter(CacheRejection::is_suspicious);
+            if let Some(reason) = suspicious {
+                dns_warn!("{} {:?} 的响应可疑，已丢弃: {}", query.name, query.qtype, reason);
+                return Err(DnsError::Protocol(format!("Suspicious response for {}: {}", query.name, reason)));
+            }
+        }
+        
+        self.answer_rewrite.before_cache(query, &mut response);
+        
+        // 按实际发出的子网缓存结果（可疑或非NOERROR的响应由缓存自行拒绝）；
+        // 降级路径的应答不写入缓存，不会在加密上游恢复后继续被使用；截断的应答按配置决定是否缓存
+        let cacheable = self.is_cacheable_subnet(info.client_subnet.as_ref())
+            && !info.degraded_security
+            && cache_negative
+            && (!info.records_truncated || self.cache_truncated_responses);
+        if let Some(cache) = cache.filter(|_| cacheable) {
+            cache.insert(query.clone(), info.client_subnet.clone(), response.clone());
+        }
+        
+        Ok((response, info))
+    }
+    
+    /// 向 `first` 以外的另一个可用上游（层级最优先的一个）核实否定应答，推翻时记 `first` 一次可疑否定应答
+    async fn verify_negative(&self, request: &Request, first: &str) -> NegativeCheck {
+        let Some(entry) = self.healthy_transports()
+            .into_iter()
+            .filter(|entry| entry.name != first)
+            .min_by_key(|entry| entry.tier)
+        else {
+            return NegativeCheck::Confirmed;
+        };
+        let result = entry.send(request).await;
+        record_outcome(self.upstream_monitor.as_deref(), &entry, &result);
+        match result {
+            Ok((answer, _)) if is_negative_answer(&answer) => NegativeCheck::Confirmed,
+            Ok((answer, info)) if answer.rcode() == ResponseCode::NoError => {
+                dns_warn!("{} {:?}: 上游 {} 的否定应答被 {} 的肯定答案推翻", request.query.name, request.query.qtype, first, entry.name);
+                if let Some(suspect) = self.transports().iter().find(|entry| entry.name == first) {
+                    suspect.suspicious_negatives.fetch_add(1, Ordering::Relaxed);
+                }
+                if let Some(monitor) = &self.upstream_monitor {
+                    monitor.record_failure(first);
+                }
+                NegativeCheck::Overturned(Box::new((answer, info)))
+            },
+            _ => NegativeCheck::Inconclusive,
+        }
+    }
+    
+    /// 登记一次上游查询；同一缓存键已有查询在进行时返回其结果的订阅
+    fn begin_fetch(&self, key: &CacheKey) -> std::result::Result<watch::Sender<Option<FetchResult>>, watch::Receiver<Option<FetchResult>>> {
+        let mut pending = self.pending_fetches.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(receiver) = pending.get(key) {
+            return Err(receiver.clone());
+        }
+        let (sender, receiver) = watch::channel(None);
+        pending.insert(key.clone(), receiver);
+        Ok(sender)
+    }
+    
+    /// 执行已登记的上游查询，把结果发给等待方，结束时注销
+    async fn lead_fetch(
+        &self,
+        sender: watch::Sender<Option<FetchResult>>,
+        key: CacheKey,
+        request: &Request,
+        cache: &CacheLayer,
+    ) -> FetchResult {
+        let _registration = PendingFetchGuard { pending: self.pending_fetches.clone(), key };
+        let result = self.fetch(request, QueryRoute::Strategy, Some(cache)).await;
+        sender.send_replace(Some(result.clone()));
+        result
+    }
+    
+    /// 合并并发的相同查询：同一缓存键同时只有一次上游查询，其余调用方等待它的结果
+    async fn fetch_coalesced(&self, request: &Request, cache: &CacheLayer) -> FetchResult {
+        let key = CacheKey::for_client(&request.query, request.client_address.as_ref());
+        let mut receiver = match self.begin_fetch(&key) {
+            Ok(sender) => return self.lead_fetch(sender, key, request, cache).await,
+            Err(receiver) => receiver,
+        };
+        
+        loop {
+            if let Some(result) = receiver.borrow_and_update().as_ref() {
+                return result.clone();
+            }
+            if receiver.changed().await.is_err() {
+                break;
+            }
+        }
+        
+        // 进行中的查询被取消，没有给出结果：自行查询
+        dns_debug!("{} {:?} 的合并查询被取消，单独向上游查询", request.query.name, request.query.qtype);
+        self.fetch(request, QueryRoute::Strategy, Some(cache)).await
+    }
+    
+    /// 在后台刷新刚过期的条目，同一条目已有查询在进行时不再发起
+    fn spawn_revalidation(&self, request: Request) {
+        let Some(cache) = self.cache.clone() else {
+            return;
+        };
+        let key = CacheKey::for_client(&request.query, request.client_address.as_ref());
+        let Ok(sender) = self.begin_fetch(&key) else {
+            return;
+        };
+        
+        dns_debug!("🔄 {} {:?} 已过期，返回旧答案并在后台刷新", request.query.name, request.query.qtype);
+        let resolver = self.clone();
+        runtime::spawn(async move {
+            if let Err(e) = resolver.lead_fetch(sender, key, &request, &cache).await {
+                dns_warn!("后台刷新 {} {:?} 失败: {}", request.query.name, request.query.qtype, e);
+            }
+        });
+    }
+    
+    /// 向每个传输分别发送查询，返回各自的结果（按传输添加顺序）
+    /// 
+    /// 诊断用途：绕过缓存和上游监控，不做TTL钳制，同时进行的查询不超过 `max_concurrency` 个
+    pub async fn query_each(
+        &self,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+        client_ip: Option<IpAddr>,
+        max_concurrency: usize,
+    ) -> Result<Vec<(TransportInfo, Result<Response>)>> {
+        use futures::StreamExt;
+        
+        if max_concurrency == 0 {
+            return Err(DnsError::InvalidConfig("max_concurrency must be greater than zero".to_string()));
+        }
+        
+        let client_address = client_ip.map(|ip| match ip {
+            IpAddr::V4(addr) => ClientAddress::from_ipv4(addr, 24),
+            IpAddr::V6(addr) => ClientAddress::from_ipv6(addr, 56),
+        });
+        let request = Request {
+            id: rand::random(),
+            flags: Flags::default(),
+            query: Query {
+                name: name.to_string(),
+                qtype: record_type,
+                qclass: class,
+            },
+            client_address: client_address.or_else(|| self.default_client_address.clone()),
+            enable_edns: self.enable_edns,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: priority::current(),
+        };
+        
+        let queries = self.enabled_transports().into_iter().map(|entry| {
+            let request = request.clone();
+            async move {
+                let start = entry.clock.now_instant();
+                match entry.send(&request).await {
+                    Ok((response, info)) => (info, Ok(response)),
+                    Err(e) => (entry.info(entry.elapsed_since(start)), Err(e)),
+                }
+            }
+        });
+        
+        Ok(futures::stream::iter(queries)
+            .buffered(max_concurrency)
+            .collect::<Vec<_>>()
+            .await)
+    }
+    
+    /// 只通过指定名称的传输发送查询（包括已停用的传输），绕过缓存和上游监控
+    pub async fn query_transport(
+        &self,
+        transport_name: &str,
+        name: &str,
+        record_type: RecordType,
+        class: QClass,
+    ) -> Result<Response> {
+        let entry = self.transports().iter()
+            .find(|entry| entry.name == transport_name)
+            .cloned()
+            .ok_or_else(|| DnsError::InvalidConfig(format!("Transport '{}' not found", transport_name)))?;
+        let request = Request {
+            id: rand::random(),
+            flags: Flags::default(),
+            query: Query {
+                name: name.to_string(),
+                qtype: record_type,
+                qclass: class,
+            },
+            client_address: None,
+            enable_edns: self.enable_edns,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: priority::current(),
+        };
+        entry.send(&request).await.map(|(response, _)| response)
+    }
+
+    /// 通过独立的TCP连接对 `server` 执行AXFR，返回区域的全部记录
+    /// 
+    /// 首条为SOA，不含结尾重复的SOA；不经过已配置的传输、缓存和上游监控，
+    /// 超时时间作用于每一条消息
+    #[cfg(feature = "tcp")]
+    pub async fn axfr(&self, zone: &str, server: &str) -> Result<Vec<crate::types::Record>> {
+        zone_transfer::axfr(zone, server, self.default_timeout).await
+    }
+    
+    /// 通过独立的TCP连接对 `server` 执行IXFR，`serial` 为本地持有的区域序列号
+    /// 
+    /// 服务器以完整传送答复或不支持IXFR时返回 [`zone_transfer::ZoneTransfer::Full`]
+    #[cfg(feature = "tcp")]
+    pub async fn ixfr(&self, zone: &str, server: &str, serial: u32) -> Result<zone_transfer::ZoneTransfer> {
+        zone_transfer::ixfr(zone, server, serial, self.default_timeout).await
+    }
+    
+    /// 设置默认客户端地址
+    pub fn set_default_client_address(&mut self, client_address: Option<ClientAddress>) {
+        self.default_client_address = client_address;
+    }
+    
+    /// 设置默认客户端IP（便捷方法）
+    pub fn set_default_client_ip(&mut self, client_ip: Option<IpAddr>) {
+        self.default_client_address = client_ip.map(|ip| match ip {
+            IpAddr::V4(addr) => ClientAddress::from_ipv4(addr, 24),
+            IpAddr::V6(addr) => ClientAddress::from_ipv6(addr, 56),
+        });
+    }
+    
+    /// 本次查询使用的策略：在作用域中查询且作用域改用了其他策略时为该策略
+    fn strategy(&self) -> QueryStrategy {
+        scope::strategy().unwrap_or(self.strategy)
+    }
+    
+    /// 执行查询策略
+    async fn execute_query_strategy(&self, request: &Request) -> Result<(Response, TransportInfo)> {
+        let transports = self.transports();
+        if transports.is_empty() {
+            return Err(DnsError::Config("No transports configured".to_string()));
+        }
+        
+        dns_info!("🔍 开始DNS查询: {} (类型: {:?}), 策略: {:?}, 可用传输: {}", 
+                 request.query.name, request.query.qtype, self.strategy(), transports.len());
+        
+        // 打印所有可用传输的类型
+        for (i, entry) in transports.iter().enumerate() {
+            dns_debug!("传输[{}]: {} ({})", i, entry.name, entry.transport.transport_type());
+        }
+        
+        let mut tier = self.current_active_tier();
+        loop {
+            let result = self.run_strategy(request, None).await;
+            // 本层级最后一个可用的传输在这次查询中被判定为不可用时，在同一次查询中改用下一层级
+            match (result, tier, self.current_active_tier()) {
+                (Err(e), Some(current), Some(next)) if next > current => {
+                    dns_warn!("层级 {} 的上游查询 {} 全部失败（{}），改用层级 {}", current, request.query.name, e, next);
+                    tier = Some(next);
+                }
+                (result, _, _) => return result,
+            }
+        }
+    }
+    
+    /// 按查询策略查询一次；`filter` 为单次查询的上游筛选，见 [`candidate_transports`](Self::candidate_transports)
+    async fn run_strategy(&self, request: &Request, filter: Option<&UpstreamFilter>) -> Result<(Response, TransportInfo)> {
+        match self.strategy() {
+            QueryStrategy::Sequential => self.query_sequential(request, filter).await,
+            _ => self.query_rounds(request, filter).await,
+        }
+    }
+    
+    /// 带上游筛选的查询：筛选后没有传输时直接失败，否则按查询策略查询，不在层级之间切换
+    async fn query_filtered_strategy(&self, request: &Request, filter: &UpstreamFilter) -> Result<(Response, TransportInfo)> {
+        if self.candidate_transports(Some(filter)).is_empty() {
+            return Err(DnsError::NoUpstreamAvailable { filter: Some(filter.clone()) });
+        }
+        dns_debug!("上游筛选 {}: 查询 {} (策略: {:?})", filter, request.query.name, self.strategy());
+        self.run_strategy(request, Some(filter)).await
+    }
+    
+    /// 按Fifo、Smart或RoundRobin策略查询一整轮，整轮失败时再查询至多 `retry_count` 轮
+    /// 
+    /// 每一轮只使用当时仍可用的传输，上一轮中被判定为不可用的传输不再参与；请求本身有问题等
+    /// 不应重试的错误直接返回。第n次重试前等待n×100毫秒。请求的 `timeout_ms` 限制包括重试在内的整个查询，
+    /// 到时未完成的重试随之取消
+    async fn query_rounds(&self, request: &Request, filter: Option<&UpstreamFilter>) -> Result<(Response, TransportInfo)> {
+        let strategy = self.strategy();
+        let mut retries = 0;
+        loop {
+            let result = match strategy {
+                QueryStrategy::Smart => self.query_smart_decision(request, filter).await,
+                QueryStrategy::RoundRobin => self.query_parallel(request, filter).await,
+                QueryStrategy::Quorum { required } => self.query_quorum(request, required, filter).await,
+                _ => self.query_fastest_first(request, filter).await,
+            };
+            match result {
+                Err(e) if retries < self.retry_count && e.retry_advice() != RetryAdvice::Fatal => {
+                    retries += 1;
+                    self.retries_performed.fetch_add(1, Ordering::Relaxed);
+                    dns_debug!("{:?}策略: {} 第 {} 轮查询失败（{}），进行第 {} 次重试", strategy, request.query.name, retries, e, retries);
+                    self.clock.sleep(Duration::from_millis(100 * retries as u64)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+    
+    /// 最快优先策略（优化版：支持早期取消）
+    async fn query_fastest_first(&self, request: &Request, filter: Option<&UpstreamFilter>) -> Result<(Response, TransportInfo)> {
+        use tokio::sync::{oneshot, broadcast};
+        
+        // 获取健康的传输实例
+        let available_transports = self.candidate_transports(filter);
+        
+        if available_transports.is_empty() {
+            return Err(DnsError::Server("No available transports".to_string()));
+        }
+        
+        dns_info!("⚡ 使用最快优先策略，并发查询 {} 个传输", available_transports.len());
+        for (i, entry) in available_transports.iter().enumerate() {
+            dns_debug!("并发传输[{}]: {} ({})", i, entry.name, entry.transport.transport_type());
+        }
+        
+        // 创建取消通道，用于在获得第一个成功响应后取消其他任务
+        let (cancel_tx, _) = broadcast::channel::<()>(1);
+        let cancel_tx = Arc::new(cancel_tx);
+        let (success_tx, mut success_rx) = oneshot::channel();
+        let success_tx = Arc::new(tokio::sync::Mutex::new(Some(success_tx)));
+        // SERVFAIL/REFUSED应答不算成功，先留着，没有其他应答时再采用
+        let passed_over: Arc<Mutex<Vec<(Response, TransportInfo)>>> = Arc::new(Mutex::new(Vec::new()));
+        
+        // 并发查询所有传输
+        let mut tasks = Vec::new();
+        
+        for entry in available_transports {
+            let request_clone = request.clone();
+            let mut cancel_rx = cancel_tx.subscribe();
+            let success_tx_clone = success_tx.clone();
+            let passed_over = passed_over.clone();
+            let cancel_tx_clone = cancel_tx.clone();
+            let upstream_monitor = self.upstream_monitor.clone();
+            
+            let task = runtime::spawn(diagnosis::propagate(async move {
+                let start = entry.clock.now_instant();
+                let transport_type = entry.transport.transport_type();
+                dns_debug!("🚀 开始使用 {} ({}) 传输查询", entry.name, transport_type);
+                
+                // 使用select!来同时监听取消信号和DNS查询
+                tokio::select! {
+                    // DNS查询结果
+                    result = entry.send(&request_clone) => {
+                        let duration = entry.elapsed_since(start);
+                        
+                        match result {
+                            Ok((response, info)) => {
+                                dns_info!("✅ {} ({}) 传输查询成功 (耗时: {:?}ms)", entry.name, transport_type, duration.as_millis());
+                                // 记录成功统计
+                                if let Some(upstream_monitor) = &upstream_monitor {
+                                    upstream_monitor.record_success(&entry.name, duration);
+                                    if let Some(peer) = info.peer {
+                                        upstream_monitor.record_peer(&entry.name, peer);
+                                    }
+                                }
+                                if let Some(rcode) = failover_rcode(&response) {
+                                    dns_debug!("{} ({}) 返回 {:?}，等待其他传输的应答", entry.name, transport_type, rcode);
+                                    passed_over.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push((response, info));
+                                    return;
+                                }
+                                
+                                // 尝试发送成功结果（只有第一个成功的会被接收）
+                                if let Ok(mut sender) = success_tx_clone.try_lock() {
+                                    if let Some(tx) = sender.take() {
+                                        let _ = tx.send(Ok((response, info)));
+                                        // 通知其他任务取消
+                                        let _ = cancel_tx_clone.send(());
+                                    }
+                                }
+                            }
+                            Err(e) => {
+                                dns_debug!("❌ {} ({}) 传输查询失败: {} (耗时: {:?}ms)", entry.name, transport_type, e, duration.as_millis());
+                                // 记录失败统计
+                                if let Some(upstream_monitor) = &upstream_monitor {
+                                    upstream_monitor.record_error(&entry.name, &e);
+                                }
+                                // 失败不取消其他任务，继续等待
+                            }
+                        }
+                    }
+                    // 取消信号
+                    _ = cancel_rx.recv() => {
+                        // 任务被取消，直接退出
+                        dns_debug!("传输 {} 的查询任务被取消", entry.name);
+                    }
+                }
+            }));
+            
+            tasks.push(task);
+        }
+        
+        // 使用oneshot通道来处理任务完成情况
+        let (all_done_tx, all_done_rx) = oneshot::channel::<Result<(Response, TransportInfo)>>();
+        let all_done_tx = Arc::new(tokio::sync::Mutex::new(Some(all_done_tx)));
+        
+        // 创建一个单独的任务来等待所有查询完成
+        let all_tasks_handle = runtime::spawn({
+            let all_done_tx = all_done_tx.clone();
+            async move {
+                // 等待所有任务完成
+                let _ = futures::future::join_all(tasks).await;
+                
+                // 如果还没有成功结果，则发送失败信息
+                if let Ok(mut sender) = all_done_tx.try_lock() {
+                    if let Some(tx) = sender.take() {
+                        let _ = tx.send(Err(DnsError::Server("All transports failed".to_string())));
+                    }
+                }
+            }
+        });
+        
+        // 等待第一个成功的结果或所有任务完成；成功的任务结束时两个通道同时就绪，先取成功结果
+        let result = tokio::select! {
+            biased;
+            // 收到成功响应
+            result = &mut success_rx => {
+                // 取消所有剩余任务
+                let _ = cancel_tx.send(());
+                match result {
+                    Ok(response) => response,
+                    Err(_) => Err(DnsError::Server("Internal communication error".to_string()))
+                }
+            }
+            // 所有任务都完成了但没有成功响应
+            result = all_done_rx => {
+                result.unwrap_or(Err(DnsError::Server("Internal communication error".to_string())))
+            }
+        };
+        
+        // 等待清理任务完成（设置短超时避免长时间等待）
+        let _ = runtime::timeout(Duration::from_millis(100), all_tasks_handle).await;
+        
+        let mut passed_over = std::mem::take(&mut *passed_over.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+        match result {
+            Ok(answer) => Ok(prefer_usable_answer(answer, passed_over)),
+            Err(e) if passed_over.is_empty() => Err(e),
+            Err(_) => {
+                let first = passed_over.remove(0);
+                Ok(prefer_usable_answer(first, passed_over))
+            }
+        }
+    }
+    
+    /// 先向指定传输查询，它不可用（已停用、退避中或被判定为不可用）或查询失败时按查询策略查询
+    async fn query_preferred(&self, request: &Request, preferred: &str) -> Result<(Response, TransportInfo)> {
+        let entry = self.get_available_transports()
+            .into_iter()
+            .find(|entry| entry.name == preferred);
+        if let Some(entry) = entry {
+            let result = entry.send(request).await;
+            record_outcome(self.upstream_monitor.as_deref(), &entry, &result);
+            match result {
+                Ok(answer) => return Ok(answer),
+                Err(e) => dns_debug!("首选传输 {} 查询 {} 失败，改按查询策略: {}", preferred, request.query.name, e),
+            }
+        }
+        self.execute_query_strategy(request).await
+    }
+
+    /// 只向指定名称的可用传输查询
+    async fn query_only(&self, request: &Request, transport_name: &str) -> Result<(Response, TransportInfo)> {
+        let entry = self.get_available_transports()
+            .into_iter()
+            .chain(self.routed_transports().into_iter().filter(|entry| !entry.in_backoff()))
+            .find(|entry| entry.name == transport_name)
+            .ok_or_else(|| DnsError::Server(format!("Transport '{}' is not available", transport_name)))?;
+        let result = entry.send(request).await;
+        record_outcome(self.upstream_monitor.as_deref(), &entry, &result);
+        result
+    }
+    
+    /// 可参与查询的传输名称（已启用、不在退避期且未被上游监控判定为不可用），按添加顺序
+    pub(crate) fn available_transport_names(&self) -> Vec<String> {
+        self.get_available_transports().into_iter().map(|entry| entry.name).collect()
+    }
+
+    /// 向所有传输并发查询（不按上游健康状态筛选），返回第一个成功的响应
+    async fn query_fan_out(&self, request: &Request) -> Result<(Response, TransportInfo)> {
+        use futures::StreamExt;
+        
+        let transports = self.enabled_transports();
+        if transports.is_empty() {
+            return Err(DnsError::Config("No transports configured".to_string()));
+        }
+        dns_debug!("应急模式: 向全部 {} 个传输并发查询 {}", transports.len(), request.query.name);
+        
+        let mut pending: futures::stream::FuturesUnordered<_> = transports.iter()
+            .map(|entry| entry.send(request))
+            .collect();
+        
+        let mut last_error = None;
+        while let Some(result) = pending.next().await {
+            match result {
+                Ok(answer) => return Ok(answer),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| DnsError::Server("All transports failed".to_string())))
+    }
+    
+    /// 并行查询策略
+    async fn query_parallel(&self, request: &Request, filter: Option<&UpstreamFilter>) -> Result<(Response, TransportInfo)> {
+        let available_transports = self.candidate_transports(filter);
+        
+        if available_transports.is_empty() {
+            return Err(DnsError::Server("No available transports".to_string()));
+        }
+        
+        let mut tasks = Vec::new();
+        
+        for entry in available_transports {
+            let request_clone = request.clone();
+            let upstream_monitor = self.upstream_monitor.clone();
+            
+            let task = runtime::spawn(diagnosis::propagate(async move {
+                let result = entry.send(&request_clone).await;
+                record_outcome(upstream_monitor.as_deref(), &entry, &result);
+                result
+            }));
+            
+            tasks.push(task);
+        }
+        
+        // 等待所有任务完成
+        let results = futures::future::join_all(tasks).await;
+        
+        // 返回第一个成功的结果（按传输添加顺序），SERVFAIL/REFUSED只在没有其他应答时采用
+        let mut answers: Vec<_> = results.into_iter()
+            .filter_map(|result| result.ok().and_then(|result| result.ok()))
+            .collect();
+        if answers.is_empty() {
+            return Err(DnsError::Server("All parallel queries failed".to_string()));
+        }
+        let first = answers.remove(0);
+        Ok(prefer_usable_answer(first, answers))
+    }
+    
+    /// 法定人数策略：向所有可用传输并发查询，`required` 个传输给出相同的答案集合时立即返回其中最先到达的应答，
+    /// 未完成的查询随之取消
+    /// 
+    /// 答案集合按 [`consensus::answer_set`] 比较（只比较所查询类型的记录，不计顺序和TTL），SERVFAIL/REFUSED应答
+    /// 和查询失败不参与投票。所有查询结束仍未达成一致时返回 [`DnsError::QuorumNotReached`]，
+    /// 报告按传输添加顺序列出各传输的结果
+    async fn query_quorum(&self, request: &Request, required: usize, filter: Option<&UpstreamFilter>) -> Result<(Response, TransportInfo)> {
+        use futures::StreamExt;
+        
+        let available_transports = self.candidate_transports(filter);
+        if available_transports.is_empty() {
+            return Err(DnsError::Server("No available transports".to_string()));
+        }
+        
+        let monitor = self.upstream_monitor.as_deref();
+        let mut pending: futures::stream::FuturesUnordered<_> = available_transports.into_iter()
+            .enumerate()
+            .map(|(index, entry)| async move {
+                let start = entry.clock.now_instant();
+                let result = entry.send(request).await;
+                record_outcome(monitor, &entry, &result);
+                (index, entry.info(entry.elapsed_since(start)), result)
+            })
+            .collect();
+        
+        // (答案集合, 票数, 最先给出该答案的应答)
+        let mut tally: Vec<(Vec<String>, usize, (Response, TransportInfo))> = Vec::new();
+        let mut answers = Vec::new();
+        while let Some((index, info, result)) = pending.next().await {
+            let (info, summary) = match result {
+                Ok((response, info)) => match failover_rcode(&response) {
+                    Some(rcode) => (info, Err(format!("{:?}", rcode))),
+                    None => {
+                        let set = consensus::answer_set(&response, request.query.qtype);
+                        let position = match tally.iter().position(|(candidate, _, _)| *candidate == set) {
+                            Some(position) => position,
+                            None => {
+                                tally.push((set.clone(), 0, (response, info.clone())));
+                                tally.len() - 1
+                            }
+                        };
+                        tally[position].1 += 1;
+                        if tally[position].1 >= required.max(1) {
+                            dns_debug!("法定人数策略: {} 的 {} 个上游答案一致", request.query.name, tally[position].1);
+                            return Ok(tally.swap_remove(position).2);
+                        }
+                        (info, Ok(set))
+                    }
+                },
+                Err(e) => (info, Err(e.to_string())),
+            };
+            answers.push((index, PerUpstreamAnswer {
+                upstream: info.name,
+                protocol: info.protocol,
+                latency: info.duration,
+                result: summary,
+            }));
+        }
+        
+        answers.sort_by_key(|(index, _)| *index);
+        let report = QueryAllReport::new(answers.into_iter().map(|(_, answer)| answer).collect());
+        dns_warn!(
+            "法定人数策略: {} 没有 {} 个上游给出相同答案，不一致的上游: [{}]",
+            request.query.name,
+            required,
+            report.consensus.dissenting.iter().map(|d| d.upstream.as_str()).collect::<Vec<_>>().join(", ")
+        );
+        Err(DnsError::QuorumNotReached { name: request.query.name.clone(), required, report: Box::new(report) })
+    }
+    
+    /// 顺序查询策略：按传输添加顺序（即上游配置顺序）逐个查询，每个传输各自重试 `retry_count` 次，
+    /// 出错或超时才换下一个
+    async fn query_sequential(&self, request: &Request, filter: Option<&UpstreamFilter>) -> Result<(Response, TransportInfo)> {
+        let available_transports = self.candidate_transports(filter);
+        
+        if available_transports.is_empty() {
+            return Err(DnsError::Server("No available transports".to_string()));
+        }
+        
+        let mut last_error = DnsError::Server("No transports tried".to_string());
+        
+        for entry in available_transports {
+            for attempt in 0..=self.retry_count {
+                let result = entry.send(request).await;
+                record_outcome(self.upstream_monitor.as_deref(), &entry, &result);
+                match result {
+                    Ok(answer) => return Ok(answer),
+                    Err(e) => {
+                        dns_debug!("顺序策略: {} 第 {} 次查询 {} 失败: {}", entry.name, attempt + 1, request.query.name, e);
+                        let advice = e.retry_advice();
+                        last_error = e;
+                        match advice {
+                            // 请求本身有问题，换上游也不会成功
+                            RetryAdvice::Fatal => return Err(last_error),
+                            // 上游限流、不可用或在途查询已满，直接换下一个
+                            RetryAdvice::Backoff | RetryAdvice::Unavailable | RetryAdvice::Skip => break,
+                            RetryAdvice::Retry if attempt < self.retry_count => {
+                                self.retries_performed.fetch_add(1, Ordering::Relaxed);
+                                self.clock.sleep(Duration::from_millis(100 * (attempt + 1) as u64)).await;
+                            }
+                            RetryAdvice::Retry => {}
+                        }
+                    }
+                }
+            }
+        }
+        
+        Err(last_error)
+    }
+    
+    /// 智能决策策略
+    async fn query_smart_decision(&self, request: &Request, filter: Option<&UpstreamFilter>) -> Result<(Response, TransportInfo)> {
+        // 智能决策：结合速度、可靠性和结果完整性
+        let available_transports = self.candidate_transports(filter);
+        
+        if available_transports.is_empty() {
+            return Err(DnsError::Server("No available transports".to_string()));
+        }
+        
+        let mut tasks = Vec::new();
+        
+        for entry in available_transports {
+            let request_clone = request.clone();
+            let upstream_monitor = self.upstream_monitor.clone();
+            
+            let task = runtime::spawn(diagnosis::propagate(async move {
+                let start = entry.clock.now_instant();
+                let transport_type = entry.transport.transport_type();
+                
+                let result = entry.send(&request_clone).await;
+                let duration = entry.elapsed_since(start);
+                record_outcome(upstream_monitor.as_deref(), &entry, &result);
+                
+                let (timing, wire, peer, degraded_security, records_truncated) = match &result {
+                    Ok((_, info)) => (info.timing, info.wire.clone(), info.peer, info.degraded_security, info.records_truncated),
+                    Err(_) => (None, None, None, false, false),
+                };
+                QueryResult {
+                    timing,
+                    wire,
+                    peer,
+                    degraded_security,
+                    records_truncated,
+                    client_subnet: entry.ecs_policy.apply(request_clone.client_address.as_ref()),
+                    response: result.map(|(response, _)| response),
+                    duration,
+                    transport_type: transport_type.to_string(),
+                    transport_name: entry.name,
+                }
+            }));
+            
+            tasks.push(task);
+        }
+        
+        // 收集所有结果
+        let mut results = Vec::new();
+        
+        // 等待所有结果或超时
+        let timeout_duration = self.default_timeout;
+        let deadline = self.clock.now_instant() + timeout_duration;
+        
+        while !tasks.is_empty() && self.clock.now_instant() < deadline {
+            match clock::timeout_at(&*self.clock, deadline, futures::future::select_all(tasks)).await {
+                Some((task_result, _index, remaining_tasks)) => {
+                    tasks = remaining_tasks;
+                    
+                    if let Ok(query_result) = task_result {
+                        results.push(query_result);
+                    }
+                }
+                None => {
+                    break; // 超时
+                }
+            }
+        }
+        
+        // 智能选择最佳结果
+        let final_result = self.select_best_result(results);
+        
+        // 记录最终选择的策略结果
+        match &final_result {
+            Ok((response, info)) => {
+                dns_info!("🧠 Smart策略: 最终选择成功 - 传输: {}, 答案数: {}, 查询: {}", 
+                         info.name, response.answers.len(), request.query.name);
+            }
+            Err(e) => {
+                dns_warn!("🧠 Smart策略: 所有传输均失败 - 错误: {}, 查询: {}", 
+                         e, request.query.name);
+            }
+        }
+        
+        final_result
+    }
+    
+    /// 选择最佳查询结果
+    fn select_best_result(&self, results: Vec<QueryResult>) -> Result<(Response, TransportInfo)> {
+        if results.is_empty() {
+            return Err(DnsError::Timeout { phase: None });
+        }
+        
+        // 按优先级选择结果：
+        // 1. 结果最完整的（答案记录最多）
+        // 2. 如果完整性相同，选择最快的
+        // 3. 如果都失败，返回第一个错误
+        
+        let mut best: Option<(Response, TransportInfo)> = None;
+        let mut best_score = -1i32;
+        
+        // 分析每个结果（SERVFAIL/REFUSED不参与比较，只在没有其他应答时采用）
+        for result in results.iter() {
+            if let Some(response) = result.response.as_ref().ok().filter(|response| failover_rcode(response).is_none()) {
+                let score = response.answers.len() as i32;
+                let faster = best.as_ref().is_none_or(|(_, info)| result.duration < info.duration);
+                
+                if score > best_score || (score == best_score && faster) {
+                    best_score = score;
+                    best = Some((response.clone(), TransportInfo {
+                        name: result.transport_name.clone(),
+                        protocol: result.transport_type.clone(),
+                        duration: result.duration,
+                        timing: result.timing,
+                        wire: result.wire.clone(),
+                        client_subnet: result.client_subnet.clone(),
+                        failovers: Vec::new(),
+                        peer: result.peer,
+                        degraded_security: result.degraded_security,
+                        records_truncated: result.records_truncated,
+                    }));
+                }
+            }
+        }
+        
+        let mut passed_over: Vec<(Response, TransportInfo)> = results.iter()
+            .filter_map(|result| {
+                let response = result.response.as_ref().ok().filter(|response| failover_rcode(response).is_some())?;
+                Some((response.clone(), TransportInfo {
+                    name: result.transport_name.clone(),
+                    protocol: result.transport_type.clone(),
+                    duration: result.duration,
+                    timing: result.timing,
+                    wire: result.wire.clone(),
+                    client_subnet: result.client_subnet.clone(),
+                    failovers: Vec::new(),
+                    peer: result.peer,
+                    degraded_security: result.degraded_security,
+                    records_truncated: result.records_truncated,
+                }))
+            })
+            .collect();
+        let best = match best {
+            Some(best) => Some(prefer_usable_answer(best, passed_over)),
+            None if !passed_over.is_empty() => {
+                let first = passed_over.remove(0);
+                Some(prefer_usable_answer(first, passed_over))
+            }
+            None => None,
+        };
+        
+        // 如果有完整结果，返回最佳结果
+        if let Some((response, info)) = best {
+            dns_info!("🎯 Smart策略: 选择最佳结果 - 传输: {} ({}), 答案数: {}, 耗时: {:?}ms", 
+                     info.name, info.protocol, best_score, info.duration.as_millis());
+            return Ok((response, info));
+        }
+        
+        // 返回第一个错误
+        for result in results {
+            if let Err(e) = result.response {
+                return Err(e);
+            }
+        }
+        
+        Err(DnsError::Server("No valid results".to_string()))
+    }
+    
+    /// 查询失败后按 [`CoreResolverConfig::on_rate_limited`] 等待并重试本次查询期间被限流的上游，不重试时为 `None`
+    /// 
+    /// 查询失败说明故障转移已经没有其他上游可用。路由允许的传输中，`started` 之后被限流的取要求等待最短的一个，
+    /// 按 [`rate_limit::wait_before_retry`] 等待后绕过其退避期重试一次，成功时结束退避。应急模式的并发查询不重试
+    async fn retry_rate_limited(&self, request: &Request, route: QueryRoute<'_>, started: Instant) -> Option<Result<(Response, TransportInfo)>> {
+        if self.on_rate_limited == RateLimitedBehavior::FailoverImmediately {
+            return None;
+        }
+        let candidates = match route {
+            QueryRoute::FanOut(_) => return None,
+            QueryRoute::Strategy | QueryRoute::Preferred(_) => self.enabled_transports(),
+            QueryRoute::Only(transport_name) => self.candidate_transports(Some(&UpstreamFilter::only(transport_name))),
+            QueryRoute::Filtered(filter) => self.candidate_transports(Some(filter)),
+        };
+        let (entry, requested) = candidates.into_iter()
+            .filter_map(|entry| entry.rate_limit.requested_wait_since(started).map(|wait| (entry, wait)))
+            .min_by_key(|(_, wait)| *wait)?;
+        let wait = rate_limit::wait_before_retry(self.on_rate_limited, requested)?;
+        dns_info!("上游 {} 限流且没有其他可用上游，等待 {:?} 后重试 {}", entry.name, wait, request.query.name);
+        self.clock.sleep(wait).await;
+        let result = entry.send(request).await;
+        record_outcome(self.upstream_monitor.as_deref(), &entry, &result);
+        if result.is_ok() {
+            *entry.lock_backoff() = None;
+        }
+        Some(result)
+    }
+    
+    /// 参与本次查询的传输：没有上游筛选时为 [`get_available_transports`](Self::get_available_transports)；
+    /// 有筛选时为筛选保留的已启用传输（`Only` 可以点名只用于域名转发的传输），不考虑层级、退避和上游健康状态
+    fn candidate_transports(&self, filter: Option<&UpstreamFilter>) -> Vec<NamedTransport> {
+        let Some(filter) = filter else {
+            return self.get_available_transports();
+        };
+        self.transports().iter()
+            .filter(|entry| entry.enabled && filter.allows(&entry.name) && entry.in_scope())
+            .filter(|entry| !entry.routed_only || matches!(filter, UpstreamFilter::Only(_)))
+            .cloned()
+            .collect()
+    }
+    
+    /// 获取可用的传输实例（已启用、不在退避期且未被上游监控判定为不可用，证书失败由降级处理的除外），
+    /// 只取其中最优先的层级
+    fn get_available_transports(&self) -> Vec<NamedTransport> {
+        let healthy = self.healthy_transports();
+        let Some(tier) = healthy.iter().map(|entry| entry.tier).min() else {
+            return healthy;
+        };
+        // 作用域只看到部分传输，它的最优层级不代表解析器当前使用的层级
+        if scope::current().is_none() {
+            self.note_active_tier(tier);
+            if self.enabled_transports().iter().any(|entry| entry.tier < tier) {
+                self.probe_unavailable(Some(tier));
+            }
+        }
+        healthy.into_iter().filter(|entry| entry.tier == tier).collect()
+    }
+    
+    /// 已启用、不在退避期或隔离期且未被上游监控判定为不可用的传输（不区分层级）
+    ///
+    /// 所有传输都在退避期时忽略退避，避免限流期间完全无上游可用；隔离期不会被忽略
+    fn healthy_transports(&self) -> Vec<NamedTransport> {
+        let enabled: Vec<NamedTransport> = self.enabled_transports()
+            .into_iter()
+            .filter(|entry| !entry.quarantined())
+            .collect();
+        let transports = if enabled.iter().all(NamedTransport::in_backoff) {
+            enabled
+        } else {
+            enabled.into_iter().filter(|entry| !entry.in_backoff()).collect()
+        };
+        if let Some(upstream_monitor) = &self.upstream_monitor {
+            transports
+                .into_iter()
+                .filter(|entry| {
+                    upstream_monitor.is_transport_available(&entry.name)
+                        || entry.degraded.as_ref().is_some_and(|route| route.state.handles(&entry.name))
+                })
+                .collect()
+        } else {
+            transports
+        }
+    }
+    
+    /// 当前使用的层级：有可用传输的最优先层级，没有可用传输时为 `None`
+    pub fn current_active_tier(&self) -> Option<u8> {
+        self.healthy_transports().iter().map(|entry| entry.tier).min()
+    }
+    
+    /// 记下本次选用的层级，与上次不同时记录层级切换
+    fn note_active_tier(&self, tier: u8) {
+        let previous = self.active_tier.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).replace(tier);
+        match previous {
+            Some(previous) if tier > previous => dns_warn!("⬇️ 层级 {} 没有可用上游，改用层级 {}", previous, tier),
+            Some(previous) if tier < previous => dns_info!("⬆️ 层级 {} 恢复可用，不再使用层级 {}", tier, previous),
+            _ => {}
+        }
+    }
+    
+    /// 每个监控间隔向被判定为不可用的传输发送一次探测查询，应答满足探测配置即清空它的监控统计，
+    /// 使它重新参与查询。每轮换用下一个探测域名
+    /// 
+    /// 使用备用层级期间只探测比 `below_tier` 更优先的层级；自动离线期间（`None`）探测所有启用的传输
+    fn probe_unavailable(&self, below_tier: Option<u8>) {
+        let (Some(monitor), Some(probe)) = (&self.upstream_monitor, &self.health_probe) else {
+            return;
+        };
+        let now = self.clock.now_instant();
+        {
+            let mut probed_at = self.tier_probed_at.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if probed_at.is_some_and(|at| now.saturating_duration_since(at) < monitor.check_interval()) {
+                return;
+            }
+            *probed_at = Some(now);
+        }
+        
+        let request = probe_request(probe, self.probe_rounds.fetch_add(1, Ordering::Relaxed), self.enable_edns);
+        let unavailable = self.enabled_transports()
+            .into_iter()
+            .filter(|entry| below_tier.is_none_or(|tier| entry.tier < tier) && !monitor.is_transport_available(&entry.name));
+        for entry in unavailable {
+            let monitor = monitor.clone();
+            let probe = probe.clone();
+            let request = request.clone();
+            runtime::spawn(async move {
+                let (outcome, _) = send_probe(&entry, &probe, &request).await;
+                if outcome == ProbeOutcome::Success {
+                    dns_info!("层级 {} 的上游 {} 探测有应答，恢复参与查询", entry.tier, entry.name);
+                    monitor.reset_stats(&entry.name);
+                } else {
+                    dns_debug!("层级 {} 的上游 {} 探测 {} 失败: {}", entry.tier, entry.name, request.query.name, outcome.as_str());
+                }
+                if let Some(observer) = monitor.observer() {
+                    observer.on_upstream_probe(&entry.name, ProbeReason::Recovery, outcome);
+                }
+            });
+        }
+    }
+    
+    /// 手动进入或退出离线模式
+    /// 
+    /// 离线期间查询只由缓存应答：过期的条目照常返回（TTL为0），没有缓存的查询立即以
+    /// [`DnsError::Offline`] 失败，不向上游发送查询。退出时在后台按
+    /// [`CoreResolverConfig::offline_revalidation_rate`] 限速刷新离线期间返回过的过期条目。
+    /// 手动进入的离线模式不会因上游恢复而自动退出
+    pub fn set_offline(&self, offline: bool) {
+        if !offline {
+            self.go_online();
+        } else if self.offline.enter(OfflineReason::Manual) {
+            dns_warn!("📴 进入离线模式，只由缓存应答");
+        }
+    }
+    
+    /// 是否处于离线模式
+    pub fn is_offline(&self) -> bool {
+        self.offline.is_active()
+    }
+    
+    /// 离线模式的状态与计数
+    pub fn offline_stats(&self) -> OfflineStats {
+        self.offline.stats()
+    }
+    
+    /// 是否处于离线模式；配置了自动离线时先按上游监控的判定更新状态
+    /// 
+    /// 自动离线期间每个监控间隔探测一次不可用的上游，有上游恢复可用即退出离线模式
+    fn check_offline(&self) -> bool {
+        let (Some(after), Some(monitor)) = (self.auto_offline_after, &self.upstream_monitor) else {
+            return self.offline.is_active();
+        };
+        let enabled = self.enabled_transports();
+        let reachable = enabled.is_empty() || enabled.iter().any(|entry| monitor.is_transport_available(&entry.name));
+        match self.offline.reason() {
+            None if self.offline.unreachable_for(reachable, self.clock.now_instant(), after)
+                && self.offline.enter(OfflineReason::Auto) => {
+                dns_warn!("📴 所有上游不可用已超过 {:?}，进入离线模式，只由缓存应答", after);
+            }
+            Some(OfflineReason::Auto) if reachable => self.go_online(),
+            Some(OfflineReason::Auto) => self.probe_unavailable(None),
+            _ => {}
+        }
+        self.offline.is_active()
+    }
+    
+    /// 退出离线模式，在后台刷新离线期间返回过的过期条目
+    fn go_online(&self) {
+        let Some(served) = self.offline.leave() else {
+            return;
+        };
+        dns_info!("📶 退出离线模式，后台刷新离线期间返回过的 {} 个过期条目", served.len());
+        self.spawn_offline_revalidation(served);
+    }
+    
+    /// 按限速逐个刷新条目；刷新中途再次离线时，剩余条目留到下次退出离线模式时刷新
+    fn spawn_offline_revalidation(&self, requests: Vec<Request>) {
+        let Some(cache) = self.cache.clone().filter(|_| !requests.is_empty()) else {
+            self.offline.defer_revalidation(requests);
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            dns_warn!("不在异步运行时中，{} 个过期条目留到下次退出离线模式时刷新", requests.len());
+            self.offline.defer_revalidation(requests);
+            return;
+        };
+        
+        let resolver = self.clone();
+        runtime.spawn(async move {
+            for (index, request) in requests.iter().enumerate() {
+                if index > 0 {
+                    resolver.clock.sleep(resolver.offline_revalidation_interval).await;
+                }
+                if resolver.offline.is_active() {
+                    resolver.offline.defer_revalidation(requests[index..].to_vec());
+                    return;
+                }
+                if let Err(e) = resolver.fetch(request, QueryRoute::Strategy, Some(&cache)).await {
+                    dns_debug!("刷新离线期间返回过的 {} {:?} 失败: {}", request.query.name, request.query.qtype, e);
+                }
+                resolver.offline.record_revalidated();
+            }
+        });
+    }
+    
+    /// 获取传输统计信息（按传输名称）
+    pub fn get_transport_stats(&self) -> HashMap<String, (u64, u64, Duration)> {
+        if let Some(upstream_monitor) = &self.upstream_monitor {
+            upstream_monitor.get_stats()
+        } else {
+            HashMap::new()
+        }
+    }
+    
+    /// 指定名称的传输在上游监控中的详细统计，未启用上游监控或尚无统计时为 `None`
+    pub fn upstream_health(&self, name: &str) -> Option<DetailedStats> {
+        self.upstream_monitor.as_ref()?.detailed_stats(name)
+    }
+    
+    /// 指定名称的传输最近的状态变化，从旧到新，见 [`UpstreamMonitor::get_upstream_events`]；未启用上游监控时为空
+    /// 
+    /// 事件附带该传输当前的标签
+    pub fn upstream_events(&self, name: &str, since: Option<SystemTime>) -> Vec<UpstreamEvent> {
+        let Some(monitor) = &self.upstream_monitor else {
+            return Vec::new();
+        };
+        let mut events = monitor.get_upstream_events(name, since);
+        if let Some(entry) = self.transports().iter().find(|entry| entry.name == name && !entry.labels.is_empty()) {
+            for event in &mut events {
+                event.labels = (*entry.labels).clone();
+            }
+        }
+        events
+    }
+    
+    /// 各UDP传输的源端口池使用情况（按传输名称）
+    pub fn udp_pool_stats(&self) -> HashMap<String, UdpPoolStats> {
+        self.transports().iter()
+            .filter_map(|entry| entry.transport.udp_pool_stats().map(|stats| (entry.name.clone(), stats)))
+            .collect()
+    }
+    
+    /// 各UDP/TCP/DoT传输按地址族分开的连接统计（按传输名称）
+    pub fn address_family_stats(&self) -> HashMap<String, AddressFamilyStats> {
+        self.transports().iter()
+            .filter_map(|entry| entry.transport.address_family_stats().map(|stats| (entry.name.clone(), stats)))
+            .collect()
+    }
+    
+    /// 各DoT传输的握手方式计数（按传输名称）：完整握手、会话恢复和0-RTT早期数据
+    #[cfg(feature = "dot")]
+    pub fn tls_session_stats(&self) -> HashMap<String, TlsSessionStats> {
+        self.transports().iter()
+            .filter_map(|entry| entry.transport.tls_session_stats().map(|stats| (entry.name.clone(), stats)))
+            .collect()
+    }
+    
+    /// 开启TCP Fast Open的TCP/DoT传输的计数（按传输名称）
+    #[cfg(feature = "tcp")]
+    pub fn fast_open_stats(&self) -> HashMap<String, FastOpenStats> {
+        self.transports().iter()
+            .filter_map(|entry| entry.transport.fast_open_stats().map(|stats| (entry.name.clone(), stats)))
+            .collect()
+    }
+    
+    /// 加密上游证书校验失败降级的状态与计数，未开启降级时为 `None`
+    pub fn encrypted_fallback_stats(&self) -> Option<EncryptedFallbackStats> {
+        self.encrypted_fallback.as_ref().map(|state| state.stats())
+    }
+    
+    /// 按域名覆盖TTL的规则，按添加顺序
+    pub fn ttl_overrides(&self) -> Vec<TtlOverrideRule> {
+        self.ttl_overrides.rules().to_vec()
+    }
+    
+    /// 各应答地址改写规则的命中次数（改写过的记录数），按配置顺序
+    pub fn answer_rewrite_stats(&self) -> Vec<RewriteRuleStats> {
+        self.answer_rewrite.stats()
+    }
+    
+    /// 已进行的重试次数：Fifo、Smart和RoundRobin策略为整轮失败后重新查询的轮数，
+    /// Sequential策略为各传输重试的次数之和
+    pub fn retries_performed(&self) -> u64 {
+        self.retries_performed.load(Ordering::Relaxed)
+    }
+    
+    /// 当前在途的上游发送数（不超过 `concurrent_queries`）
+    pub fn in_flight_sends(&self) -> usize {
+        self.send_permits.in_use()
+    }
+    
+    /// 各优先级的准入统计，从高到低
+    pub fn priority_stats(&self) -> Vec<PriorityStats> {
+        self.send_permits.counters.snapshot()
+    }
+    
+    /// 获取传输数量
+    pub fn transport_count(&self) -> usize {
+        self.transports().len()
+    }
+    
+    /// 清空缓存
+    pub async fn clear_cache(&self) -> Result<()> {
+        match &self.cache {
+            Some(cache) => cache.clear().await,
+            None => Ok(()),
+        }
+    }
+    
+    /// 移除失效范围内的缓存条目，返回移除数；未启用缓存时为0
+    /// 
+    /// 进程内缓存每次调用扫描全部条目，见 [`DnsCache::invalidate`]
+    pub async fn invalidate_cache(&self, scope: &CacheInvalidation) -> Result<usize> {
+        match &self.cache {
+            Some(cache) => cache.invalidate(scope).await,
+            None => Ok(0),
+        }
+    }
+    
+    /// 把失效范围内仍然有效的缓存条目立即标记为过期，返回标记数；未启用缓存时为0
+    /// 
+    /// 条目不会被移除，过期答案窗口内仍可先行应答并在后台刷新，见 [`DnsCache::expire`]
+    pub async fn expire_now(&self, scope: &CacheInvalidation) -> Result<usize> {
+        match &self.cache {
+            Some(cache) => cache.expire(scope).await,
+            None => Ok(0),
+        }
+    }
+    
+    /// 渐进清空缓存：按每秒 `rate_per_second` 个的速率让当前条目依次过期，命中次数少的先过期
+    /// 
+    /// 与 [`clear_cache`](Self::clear_cache) 不同，上游不会在清空后立即收到所有名称的查询；
+    /// 返回受影响的条目数和全部过期的预计时长。速率为0时返回 [`DnsError::InvalidConfig`]，未启用缓存时返回空结果
+    pub async fn clear_cache_gradual(&self, rate_per_second: usize) -> Result<CacheClearReport> {
+        let rate = std::num::NonZeroUsize::new(rate_per_second)
+            .ok_or_else(|| DnsError::InvalidConfig("Gradual cache clear rate must be greater than zero".to_string()))?;
+        match &self.cache {
+            Some(cache) => cache.expire_gradually(rate).await,
+            None => Ok(CacheClearReport::default()),
+        }
+    }
+    
+    /// 保留命中次数最多的 `top_n` 个缓存条目，立即移除其余条目，未启用缓存时返回空结果
+    pub async fn clear_cache_except_hot(&self, top_n: usize) -> Result<CacheClearReport> {
+        match &self.cache {
+            Some(cache) => cache.clear_except_hot(top_n).await,
+            None => Ok(CacheClearReport::default()),
+        }
+    }
+    
+    /// 获取缓存统计，未启用缓存时为 `None`
+    /// 
+    /// `rejected` 和 `backend_errors` 包含解析器在缓存后端之外统计的次数，`ttl_overrides` 为各TTL覆盖规则的命中次数
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|cache| CacheStats { ttl_overrides: self.ttl_overrides.stats(), ..cache.stats() })
+    }
+    
+    /// 获取后台清理任务最近一轮完整扫描得到的缓存概况
+    /// 
+    /// 只读取已有的快照，不扫描缓存；未启用缓存、未启用清理任务、后端不支持或还没有扫完一轮时
+    /// 返回空的概况（`computed_at` 为None）。热门名称需要在 [`CacheJanitorConfig::track_hot_names`] 中开启
+    pub fn cache_insights(&self) -> CacheInsights {
+        self.cache.as_ref().and_then(CacheLayer::insights).unwrap_or_default()
+    }
+    
+    /// 全局内存预算的使用情况（各组件的估算占用和收缩次数），未配置 [`CoreResolverConfig::memory_budget`] 时为 `None`
+    pub fn memory_stats(&self) -> Option<MemoryStats> {
+        self.memory_budget.as_ref().map(|budget| budget.stats())
+    }
+    
+    /// 全局内存预算，供解析器之外的组件（如查询历史）计入
+    pub(crate) fn memory_budget(&self) -> Option<&Arc<MemoryBudget>> {
+        self.memory_budget.as_ref()
+    }
+    
+    /// 让所有传输做好发送准备（解析以主机名配置的上游地址），返回失败的传输及其错误
+    /// 
+    /// 失败的传输仍保留在列表中，之后的发送会重新解析；启用上游监控时先把它标记为不可用，
+    /// 查询优先使用其他上游
+    pub async fn prepare_transports(&self) -> Vec<(String, DnsError)> {
+        let transports = self.transports();
+        let results = futures::future::join_all(transports.iter().map(|entry| entry.transport.prepare())).await;
+        let mut failures = Vec::new();
+        for (entry, result) in transports.iter().zip(results) {
+            if let Err(e) = result {
+                dns_warn!("上游 {} ({}) 的地址解析失败，暂时标记为不可用: {}", entry.name, entry.transport.endpoint(), e);
+                if let Some(monitor) = &self.upstream_monitor {
+                    monitor.set_upstream_status(&entry.name, health::UpstreamStatus::Unavailable);
+                }
+                failures.push((entry.name.clone(), e));
+            }
+        }
+        failures
+    }
+    
+    /// 中止清理过期缓存条目的后台任务（所有克隆体共用同一任务），之后过期条目只在读到时覆盖
+    pub fn stop_cache_janitor(&self) {
+        if let Some(janitor) = &self.cache_janitor {
+            janitor.stop();
+        }
+    }
+    
+    /// 中止上游监控后台任务（所有克隆体共用同一任务），之后不再评估SLO
+    pub fn stop_upstream_monitor_task(&self) {
+        if let Some(task) = self.upstream_monitor_task.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+            task.stop();
+        }
+    }
+}
+
+/// 作为引导解析器时，依次查询A和AAAA记录，两者都失败时返回A查询的错误
+#[async_trait::async_trait]
+impl BootstrapResolver for CoreResolver {
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        let (v4, v6) = futures::future::join(
+            self.query(host, RecordType::A, QClass::IN),
+            self.query(host, RecordType::AAAA, QClass::IN),
+        ).await;
+        if v4.is_err() && v6.is_err() {
+            return v4.map(|_| Vec::new());
+        }
+        let addresses = v4.into_iter().chain(v6)
+            .flat_map(|response| response.answers)
+            .filter_map(|record| match record.data {
+                RecordData::A(ip) => Some(IpAddr::V4(ip)),
+                RecordData::AAAA(ip) => Some(IpAddr::V6(ip)),
+                _ => None,
+            })
+            .collect();
+        Ok(addresses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_response::DnsResponseWrapper;
+    use crate::transport::mock::MockTransport;
+    use crate::types::{Record, RecordData};
+    use std::net::Ipv4Addr;
+    
+    /// 固定延迟、对 example.com 返回 `answers` 条相同A记录的模拟传输
+    fn mock(address: Ipv4Addr, answers: usize, delay: Duration) -> Arc<MockTransport> {
+        let response = DnsResponseWrapper::create_a_response(0, "example.com", &vec![address; answers], 300);
+        Arc::new(MockTransport::new()
+            .with_response("example.com", RecordType::A, response)
+            .with_latency(delay))
+    }
+    
+    const ALPHA: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const BETA: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+    
+    fn test_config(strategy: QueryStrategy, enable_cache: bool) -> CoreResolverConfig {
+        CoreResolverConfig::new(
+            strategy,
+            Duration::from_secs(2),
+            0,
+            enable_cache,
+            Duration::from_secs(3600),
+            false,
+            Duration::from_secs(30),
+            53,
+            10,
+            true,
+            4096,
+            false,
+            crate::logger::LevelFilter::Off,
+            false,
+        )
+    }
+    
+    /// alpha：先添加、较慢、答案更完整；beta：后添加、最快、只有一条答案
+    fn resolver_with(strategy: QueryStrategy) -> CoreResolver {
+        let mut resolver = CoreResolver::new(test_config(strategy, false));
+        resolver.add_named_transport("alpha", mock(ALPHA, 2, Duration::from_millis(80)));
+        resolver.add_named_transport("beta", mock(BETA, 1, Duration::from_millis(0)));
+        resolver
+    }
+    
+    async fn answered_by(strategy: QueryStrategy) -> (Ipv4Addr, TransportInfo) {
+        let resolver = resolver_with(strategy);
+        let (response, info) = resolver
+            .query_with_info("example.com", RecordType::A, QClass::IN, None)
+            .await
+            .unwrap();
+        let address = match &response.answers[0].data {
+            RecordData::A(addr) => *addr,
+            other => panic!("unexpected record: {:?}", other),
+        };
+        (address, info.expect("uncached query must carry transport info"))
+    }
+    
+    #[tokio::test]
+    async fn test_fifo_attributes_fastest_transport() {
+        let (address, info) = answered_by(QueryStrategy::Fifo).await;
+        assert_eq!(address, BETA);
+        assert_eq!(info.name, "beta");
+        assert_eq!(info.protocol, "MOCK");
+    }
+    
+    #[tokio::test]
+    async fn test_smart_attributes_most_complete_transport() {
+        let (address, info) = answered_by(QueryStrategy::Smart).await;
+        assert_eq!(address, ALPHA);
+        assert_eq!(info.name, "alpha");
+    }
+    
+    #[tokio::test]
+    async fn test_query_each_reports_poisoned_transport() {
+        use crate::builder::consensus::{answer_set, PerUpstreamAnswer, QueryAllReport};
+        
+        let mut resolver = resolver_with(QueryStrategy::Smart);
+        resolver.add_named_transport("gamma", mock(ALPHA, 1, Duration::from_millis(5)));
+        
+        let results = resolver
+            .query_each("example.com", RecordType::A, QClass::IN, None, 2)
+            .await
+            .unwrap();
+        let report = QueryAllReport::new(results.into_iter().map(|(info, result)| PerUpstreamAnswer {
+            upstream: info.name,
+            protocol: info.protocol,
+            latency: info.duration,
+            result: result.map(|r| answer_set(&r, RecordType::A)).map_err(|e| e.to_string()),
+        }).collect());
+        
+        let names: Vec<_> = report.answers.iter().map(|a| a.upstream.as_str()).collect();
+        assert_eq!(names, vec!["alpha", "beta", "gamma"]);
+        assert_eq!(report.consensus.majority, Some(vec![ALPHA.to_string()]));
+        assert_eq!(report.consensus.agreeing, vec!["alpha".to_string(), "gamma".to_string()]);
+        assert_eq!(report.consensus.dissenting.len(), 1);
+        let dissent = &report.consensus.dissenting[0];
+        assert_eq!(dissent.upstream, "beta");
+        assert_eq!(dissent.missing, vec![ALPHA.to_string()]);
+        assert_eq!(dissent.extra, vec![BETA.to_string()]);
+    }
+    
+    #[tokio::test]
+    async fn test_parallel_attributes_selected_transport() {
+        let (address, info) = answered_by(QueryStrategy::RoundRobin).await;
+        assert_eq!(address, ALPHA);
+        assert_eq!(info.name, "alpha");
+    }
+    
+    #[tokio::test]
+    async fn test_disabled_and_removed_transports_skip_queries() {
+        let resolver = Arc::new(resolver_with(QueryStrategy::Fifo));
+        assert!(resolver.register_transport("alpha", mock(ALPHA, 1, Duration::ZERO)).is_err());
+        
+        resolver.set_transport_enabled("beta", false).unwrap();
+        assert_eq!(answered_by_resolver(&resolver).await.name, "alpha");
+        resolver.set_transport_enabled("beta", true).unwrap();
+        assert_eq!(answered_by_resolver(&resolver).await.name, "beta");
+        
+        // alpha 的查询进行中时移除：等待其完成，之后不再使用
+        let slow = mock(ALPHA, 1, Duration::from_millis(150));
+        resolver.register_transport("slow", slow.clone()).unwrap();
+        resolver.set_transport_enabled("beta", false).unwrap();
+        resolver.set_transport_enabled("alpha", false).unwrap();
+        let in_flight = tokio::spawn({
+            let resolver = resolver.clone();
+            async move { resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await }
+        });
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(resolver.remove_transport("slow", Duration::from_secs(2)).await.unwrap());
+        assert_eq!(in_flight.await.unwrap().unwrap().1.unwrap().name, "slow");
+        assert_eq!(resolver.transport_names(), vec!["alpha", "beta"]);
+        assert!(resolver.remove_transport("slow", Duration::ZERO).await.is_err());
+        
+        // 宽限期不足时放弃等待，查询本身照常完成
+        resolver.register_transport("slow", slow.clone()).unwrap();
+        let in_flight = tokio::spawn({
+            let resolver = resolver.clone();
+            async move { resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await }
+        });
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(!resolver.remove_transport("slow", Duration::from_millis(20)).await.unwrap());
+        assert!(in_flight.await.unwrap().is_ok());
+        assert_eq!(slow.call_count(), 2);
+    }
+    
+    async fn answered_by_resolver(resolver: &CoreResolver) -> TransportInfo {
+        let (_, info) = resolver
+            .query_with_info("example.com", RecordType::A, QClass::IN, None)
+            .await
+            .unwrap();
+        info.expect("uncached query must carry transport info")
+    }
+    
+    #[tokio::test]
+    async fn test_cached_answer_expires_with_clock() {
+        let clock = Arc::new(clock::TestClock::new());
+        let mut resolver = CoreResolver::with_clock(test_config(QueryStrategy::Fifo, true), clock.clone());
+        resolver.add_named_transport("beta", mock(BETA, 1, Duration::ZERO));
+        
+        let (_, info) = resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert!(info.is_some());
+        
+        clock.advance(Duration::from_secs(299));
+        let (cached, info) = resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert!(info.is_none());
+        assert_eq!(cached.answers[0].ttl, 1);
+        
+        clock.advance(Duration::from_secs(1));
+        let (_, info) = resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert_eq!(info.map(|i| i.name), Some("beta".to_string()));
+    }
+    
+    #[tokio::test]
+    async fn test_ttl_override_takes_precedence_over_clamp_in_cache() {
+        use ttl_override::OverrideTtl;
+        
+        let clock = Arc::new(clock::TestClock::new());
+        let mut config = test_config(QueryStrategy::Fifo, true);
+        config.min_ttl = Some(Duration::from_secs(60));
+        config.ttl_overrides = vec![TtlOverrideRule::new("*.failover.corp", OverrideTtl::Exact(Duration::from_secs(30))).unwrap()];
+        let mut resolver = CoreResolver::with_clock(config, clock.clone());
+        let upstream = Arc::new(MockTransport::new()
+            .with_a("web.failover.corp", &[ALPHA], 3600)
+            .with_a("web.corp", &[BETA], 3600));
+        resolver.add_named_transport("beta", upstream.clone());
+        
+        let query = |name: &'static str| {
+            let resolver = &resolver;
+            async move { resolver.query_with_info(name, RecordType::A, QClass::IN, None).await.unwrap() }
+        };
+        let (pinned, _) = query("web.failover.corp").await;
+        assert_eq!(pinned.answers[0].ttl, 30);
+        let (sibling, _) = query("web.corp").await;
+        assert_eq!(sibling.answers[0].ttl, 3600);
+        
+        clock.advance(Duration::from_secs(29));
+        let (cached, info) = query("web.failover.corp").await;
+        assert!(info.is_none());
+        assert_eq!(cached.answers[0].ttl, 1);
+        
+        // 30秒后覆盖规则下的名称重新查询上游，兄弟名称仍在缓存中
+        clock.advance(Duration::from_secs(1));
+        let (_, info) = query("web.failover.corp").await;
+        assert!(info.is_some());
+        let (cached, info) = query("web.corp").await;
+        assert!(info.is_none());
+        assert_eq!(cached.answers[0].ttl, 3570);
+        assert_eq!(upstream.call_count(), 3);
+        
+        let stats = resolver.cache_stats().unwrap().ttl_overrides;
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].rule.as_str(), stats[0].hits), ("*.failover.corp exact 30s", 2));
+    }
+    
+    #[tokio::test]
+    async fn test_expired_hot_entry_is_refreshed_once() {
+        let clock = Arc::new(clock::TestClock::new());
+        let mut config = test_config(QueryStrategy::Fifo, true);
+        config.revalidate_window = Some(Duration::from_secs(30));
+        let mut resolver = CoreResolver::with_clock(config, clock.clone());
+        let upstream = mock(BETA, 1, Duration::from_millis(300));
+        resolver.add_named_transport("beta", upstream.clone());
+        
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        upstream.clear_calls();
+        clock.advance(Duration::from_secs(305));
+        
+        // 200个并发调用方都立即拿到旧答案，不等待上游
+        let start = Instant::now();
+        let results = futures::future::join_all((0..200).map(|_| {
+            resolver.query_with_origin("example.com", RecordType::A, QClass::IN, None)
+        })).await;
+        assert!(start.elapsed() < Duration::from_millis(300));
+        for result in results {
+            let (response, origin) = result.unwrap();
+            assert_eq!(origin, ResponseOrigin::StaleCache);
+            assert_eq!(response.answers[0].ttl, 0);
+        }
+        
+        // 只有一次后台刷新，完成后回到有效缓存
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(upstream.call_count(), 1);
+        let (_, origin) = resolver.query_with_origin("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert_eq!(origin, ResponseOrigin::Cache);
+        assert_eq!(resolver.cache_stats().unwrap().stale_served, 200);
+    }
+
+    #[tokio::test]
+    async fn test_stale_answer_served_only_within_revalidate_window() {
+        let clock = Arc::new(clock::TestClock::new());
+        let mut config = test_config(QueryStrategy::Fifo, true);
+        config.revalidate_window = Some(Duration::from_secs(30));
+        let mut resolver = CoreResolver::with_clock(config, clock.clone());
+        let upstream = mock(BETA, 1, Duration::ZERO);
+        resolver.add_named_transport("beta", upstream.clone());
+        
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        upstream.set_healthy(false);
+        
+        // 过期后窗口内（TTL 300秒，窗口30秒）上游失败也返回旧答案
+        for step in [305, 24] {
+            clock.advance(Duration::from_secs(step));
+            let (response, origin) = resolver.query_with_origin("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+            assert_eq!(origin, ResponseOrigin::StaleCache);
+            assert_eq!(response.answers[0].data, RecordData::A(BETA));
+        }
+        
+        // 越过窗口后不再返回旧答案，查询发往上游并报告其失败
+        clock.advance(Duration::from_secs(2));
+        upstream.clear_calls();
+        assert!(resolver.query_with_origin("example.com", RecordType::A, QClass::IN, None).await.is_err());
+        assert!(upstream.call_count() >= 1);
+        assert_eq!(resolver.cache_stats().unwrap().stale_served, 2);
+    }
+
+    #[tokio::test]
+    async fn test_nxdomain_flip_is_verified_with_another_upstream() {
+        // 权威段带SOA，否定应答可以写入缓存
+        let mut nxdomain = DnsResponseWrapper::create_nxdomain_response(0, "example.com", RecordType::A);
+        nxdomain.authorities.push(Record {
+            name: "example.com".to_string(),
+            rtype: RecordType::SOA,
+            class: QClass::IN,
+            ttl: 300,
+            data: RecordData::SOA {
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 60,
+            },
+        });
+        let clock = Arc::new(clock::TestClock::new());
+        let mut config = test_config(QueryStrategy::Sequential, true);
+        config.nxdomain_protection_window = Some(Duration::from_secs(60));
+        let mut resolver = CoreResolver::with_clock(config, clock.clone());
+        let alpha = mock(ALPHA, 1, Duration::ZERO);
+        let beta = mock(BETA, 1, Duration::ZERO);
+        resolver.add_named_transport("alpha", alpha.clone());
+        resolver.add_named_transport("beta", beta.clone());
+
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        clock.advance(Duration::from_secs(305));
+        alpha.set_response("example.com", RecordType::A, nxdomain.clone());
+
+        // alpha翻转为NXDOMAIN，beta仍然给出答案：返回beta的答案，alpha记一次可疑否定应答
+        let response = resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        assert_eq!(response.rcode(), ResponseCode::NoError);
+        assert_eq!(response.answers[0].data, RecordData::A(BETA));
+        assert_eq!(resolver.transport_suspicious_negatives("alpha"), 1);
+        assert_eq!(resolver.transport_suspicious_negatives("beta"), 0);
+        assert_eq!(beta.call_count(), 1);
+
+        // 缓存的是beta的答案而不是否定应答，比对用的过期条目不计入返回过期答案的次数
+        let (cached, origin) = resolver.query_with_origin("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert_eq!(origin, ResponseOrigin::Cache);
+        assert_eq!(cached.answers[0].data, RecordData::A(BETA));
+        assert_eq!(resolver.cache_stats().unwrap().stale_served, 0);
+
+        // 两个上游都给出NXDOMAIN时才缓存否定应答
+        clock.advance(Duration::from_secs(305));
+        beta.set_response("example.com", RecordType::A, nxdomain);
+        let response = resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        assert_eq!(response.rcode(), ResponseCode::NxDomain);
+        assert_eq!(resolver.transport_suspicious_negatives("alpha"), 1);
+        let (cached, origin) = resolver.query_with_origin("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert_eq!(origin, ResponseOrigin::Cache);
+        assert_eq!(cached.rcode(), ResponseCode::NxDomain);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_share_one_upstream_query() {
+        let mut config = test_config(QueryStrategy::Fifo, true);
+        config.revalidate_window = Some(Duration::ZERO);
+        let mut resolver = CoreResolver::new(config);
+        let upstream = mock(BETA, 1, Duration::from_millis(50));
+        resolver.add_named_transport("beta", upstream.clone());
+        
+        let results = futures::future::join_all((0..50).map(|_| {
+            resolver.query_with_info("example.com", RecordType::A, QClass::IN, None)
+        })).await;
+        assert_eq!(upstream.call_count(), 1);
+        for result in results {
+            let (_, info) = result.unwrap();
+            assert_eq!(info.map(|info| info.name), Some("beta".to_string()));
+        }
+    }
+    
+    /// 按客户端子网返回不同地址的传输：第一个八位组决定答案
+    #[derive(Debug, Default)]
+    struct GeoTransport {
+        calls: std::sync::atomic::AtomicUsize,
+    }
+    
+    #[async_trait::async_trait]
+    impl Transport for GeoTransport {
+        async fn send(&self, request: &Request) -> Result<Response> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let first_octet = match request.client_address.as_ref().map(|c| c.address) {
+                Some(IpAddr::V4(addr)) => addr.octets()[0],
+                _ => 0,
+            };
+            let address = Ipv4Addr::new(first_octet, 0, 2, 1);
+            let mut response = DnsResponseWrapper::create_a_response(0, &request.query.name, &[address], 300);
+            response.id = request.id;
+            Ok(response)
+        }
+        
+        fn transport_type(&self) -> &'static str {
+            "GEO"
+        }
+        
+        fn set_timeout(&mut self, _timeout: Duration) {}
+        
+        fn timeout(&self) -> Duration {
+            Duration::from_secs(1)
+        }
+    }
+    
+    async fn answer_for(resolver: &CoreResolver, client: [u8; 4]) -> (Ipv4Addr, bool) {
+        let client_ip = IpAddr::V4(Ipv4Addr::from(client));
+        let (response, info) = resolver
+            .query_with_info("example.com", RecordType::A, QClass::IN, Some(client_ip))
+            .await
+            .unwrap();
+        match &response.answers[0].data {
+            RecordData::A(addr) => (*addr, info.is_none()),
+            other => panic!("unexpected record: {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_memory_budget_reports_cache_usage_only_when_configured() {
+        let mut resolver = CoreResolver::new(test_config(QueryStrategy::Fifo, true));
+        resolver.add_named_transport("alpha", mock(ALPHA, 1, Duration::ZERO));
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        assert!(resolver.memory_stats().is_none());
+        
+        let mut config = test_config(QueryStrategy::Fifo, true);
+        config.memory_budget = Some(1 << 20);
+        let mut resolver = CoreResolver::new(config);
+        resolver.add_named_transport("alpha", mock(ALPHA, 1, Duration::ZERO));
+        assert_eq!(resolver.memory_stats().unwrap().used_bytes, 0);
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        let stats = resolver.memory_stats().unwrap();
+        assert_eq!(stats.limit_bytes, 1 << 20);
+        assert_eq!(stats.component(memory::MemoryComponent::Cache).unwrap().approx_bytes, stats.used_bytes);
+        assert!(stats.used_bytes > 0);
+        assert!(stats.components.iter().all(|usage| usage.shrinks == 0));
+    }
+    
+    #[tokio::test]
+    async fn test_ecs_answers_do_not_cross_subnets() {
+        let geo = Arc::new(GeoTransport::default());
+        let mut resolver = CoreResolver::new(test_config(QueryStrategy::Fifo, true));
+        resolver.add_transport(geo.clone());
+        
+        assert_eq!(answer_for(&resolver, [10, 1, 1, 1]).await, (Ipv4Addr::new(10, 0, 2, 1), false));
+        assert_eq!(answer_for(&resolver, [20, 1, 1, 1]).await, (Ipv4Addr::new(20, 0, 2, 1), false));
+        // 同一个 /24 的客户端命中缓存
+        assert_eq!(answer_for(&resolver, [10, 1, 1, 200]).await, (Ipv4Addr::new(10, 0, 2, 1), true));
+        assert_eq!(geo.calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+    
+    #[tokio::test]
+    async fn test_ecs_bypass_mode_skips_cache() {
+        let geo = Arc::new(GeoTransport::default());
+        let mut config = test_config(QueryStrategy::Fifo, true);
+        config.ecs_cache_mode = EcsCacheMode::Bypass;
+        let mut resolver = CoreResolver::new(config);
+        resolver.add_transport(geo.clone());
+        
+        answer_for(&resolver, [10, 1, 1, 1]).await;
+        assert!(!answer_for(&resolver, [10, 1, 1, 1]).await.1);
+        assert_eq!(geo.calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+        
+        // 不带ECS的查询仍然使用缓存
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        let (_, info) = resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert!(info.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_ecs_policy_rewrites_subnet_per_upstream() {
+        let policies = [
+            ("strip", EcsPolicy::Strip),
+            ("forward", EcsPolicy::Forward),
+            ("override", EcsPolicy::Override { ip: IpAddr::V4(Ipv4Addr::new(198, 51, 100, 0)), prefix: 24 }),
+            ("zero", EcsPolicy::ZeroScope),
+        ];
+        let mut resolver = CoreResolver::new(test_config(QueryStrategy::Fifo, false));
+        let mut upstreams = Vec::new();
+        for (name, policy) in policies {
+            let upstream = mock(ALPHA, 1, Duration::ZERO);
+            resolver.add_named_transport(name, upstream.clone());
+            resolver.set_transport_ecs_policy(name, policy).unwrap();
+            upstreams.push(upstream);
         }
         
-        // 使用oneshot通道来处理任务完成情况
-        let (all_done_tx, all_done_rx) = oneshot::channel::<Result<Response>>();
-        let all_done_tx = Arc::new(tokio::sync::Mutex::new(Some(all_done_tx)));
+        let client = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 77));
+        let results = resolver.query_each("example.com", RecordType::A, QClass::IN, Some(client), 4).await.unwrap();
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
         
-        // 创建一个单独的任务来等待所有查询完成
-        let all_tasks_handle = tokio::spawn({
-            let all_done_tx = all_done_tx.clone();
-            async move {
-                // 等待所有任务完成
-                let _ = futures::future::join_all(tasks).await;
-                
-                // 如果还没有成功结果，则发送失败信息
-                if let Ok(mut sender) = all_done_tx.try_lock() {
-                    if let Some(tx) = sender.take() {
-                        let _ = tx.send(Err(DnsError::Server("All transports failed".to_string())));
-                    }
-                }
-            }
-        });
+        // 各上游实际收到的报文中OPT记录的ECS选项
+        let sent: Vec<Option<Vec<u8>>> = upstreams.iter().map(|upstream| {
+            let wire = UdpTransport::serialize_request(&upstream.calls()[0].request).unwrap();
+            UdpTransport::parse_edns(&wire).unwrap()
+                .and_then(|edns| edns.options.into_iter().find(|option| option.code == crate::types::edns_option_codes::CLIENT_ADDRESS))
+                .map(|option| option.data)
+        }).collect();
+        assert_eq!(sent, vec![
+            None,
+            Some(vec![0, 1, 24, 0, 203, 0, 113]),
+            Some(vec![0, 1, 24, 0, 198, 51, 100]),
+            Some(vec![0, 1, 0, 0]),
+        ]);
+        assert_eq!(results[3].0.client_subnet, Some(ClientAddress::from_ipv4(Ipv4Addr::UNSPECIFIED, 0)));
+    }
+    
+    #[tokio::test]
+    async fn test_upstream_features_shape_each_request() {
+        let features = [
+            ("default", UpstreamFeatures::default()),
+            ("legacy", UpstreamFeatures { edns: Some(false), ..Default::default() }),
+            ("edns", UpstreamFeatures { edns: Some(true), ..Default::default() }),
+            ("signed", UpstreamFeatures { dnssec_do: Some(true), ..Default::default() }),
+            ("mixed-case", UpstreamFeatures { case_randomization: Some(true), ..Default::default() }),
+        ];
+        let mut resolver = CoreResolver::new(test_config(QueryStrategy::Fifo, false));
+        let mut upstreams = Vec::new();
+        for (name, features) in features {
+            let upstream = mock(ALPHA, 1, Duration::ZERO);
+            resolver.add_named_transport(name, upstream.clone());
+            resolver.set_transport_features(name, features).unwrap();
+            upstreams.push(upstream);
+        }
+        let contradictory = UpstreamFeatures { edns: Some(false), dnssec_do: Some(true), ..Default::default() };
+        assert!(resolver.set_transport_features("legacy", contradictory).is_err());
         
-        // 等待第一个成功的结果或所有任务完成
-        let result = tokio::select! {
-            // 收到成功响应
-            result = &mut success_rx => {
-                // 取消所有剩余任务
-                let _ = cancel_tx.send(());
-                match result {
-                    Ok(response) => response,
-                    Err(_) => Err(DnsError::Server("Internal communication error".to_string()))
-                }
-            }
-            // 所有任务都完成了但没有成功响应
-            result = all_done_rx => {
-                result.unwrap_or(Err(DnsError::Server("Internal communication error".to_string())))
-            }
-        };
+        // 不带客户端地址：OPT记录和DO位完全由各上游的设置决定
+        let results = resolver.query_each("example.com", RecordType::A, QClass::IN, None, 5).await.unwrap();
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        let sent: Vec<Option<bool>> = upstreams.iter().map(|upstream| {
+            let wire = UdpTransport::serialize_request(&upstream.calls()[0].request).unwrap();
+            UdpTransport::parse_edns(&wire).unwrap().map(|edns| edns.dnssec_ok)
+        }).collect();
+        assert_eq!(sent, vec![None, None, Some(false), Some(true), None]);
         
-        // 等待清理任务完成（设置短超时避免长时间等待）
-        let _ = tokio::time::timeout(Duration::from_millis(100), all_tasks_handle).await;
+        // 大小写随机化：发出的查询名与原名只有大小写不同，返回给调用方的名称改回原样
+        let mixed = &upstreams[4].calls()[0].request.query.name;
+        assert!(mixed.eq_ignore_ascii_case("example.com"));
+        let response = results[4].1.as_ref().unwrap();
+        assert_eq!(response.queries[0].name, "example.com");
+        assert_eq!(response.answers[0].name, "example.com");
         
-        result
+        // 带客户端地址时照常发送ECS，只有关闭EDNS的上游连OPT记录也不发送
+        let client = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 77));
+        resolver.query_each("example.com", RecordType::A, QClass::IN, Some(client), 5).await.unwrap();
+        let with_opt: Vec<bool> = upstreams.iter().map(|upstream| {
+            let wire = UdpTransport::serialize_request(&upstream.calls()[1].request).unwrap();
+            UdpTransport::parse_edns(&wire).unwrap().is_some()
+        }).collect();
+        assert_eq!(with_opt, vec![true, false, true, true, true]);
+        
+        assert_eq!(resolver.transport_features("signed"), Some(EffectiveFeatures { edns: true, dnssec_do: true, case_randomization: false }));
+        assert_eq!(resolver.transport_features("legacy"), Some(EffectiveFeatures { edns: false, dnssec_do: false, case_randomization: false }));
     }
     
-    /// 并行查询策略
-    async fn query_parallel(&self, request: &Request) -> Result<Response> {
-        let available_transports = self.get_available_transports();
+    #[tokio::test]
+    async fn test_cache_keyed_by_subnet_actually_sent() {
+        let geo = Arc::new(GeoTransport::default());
+        let resolver = CoreResolver::new(test_config(QueryStrategy::Fifo, true));
+        resolver.register_transport("geo", geo.clone()).unwrap();
+        resolver.set_transport_ecs_policy("geo", EcsPolicy::Override {
+            ip: IpAddr::V4(Ipv4Addr::new(30, 0, 0, 0)),
+            prefix: 8,
+        }).unwrap();
         
-        if available_transports.is_empty() {
-            return Err(DnsError::Server("No available transports".to_string()));
+        // 上游只看到固定子网，不同客户端的答案相同，共用一个缓存条目
+        assert_eq!(answer_for(&resolver, [10, 1, 1, 1]).await, (Ipv4Addr::new(30, 0, 2, 1), false));
+        assert_eq!(answer_for(&resolver, [20, 1, 1, 1]).await, (Ipv4Addr::new(30, 0, 2, 1), true));
+        assert_eq!(geo.calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        
+        // 不发送ECS的上游，答案写入不带子网的缓存键
+        resolver.set_transport_ecs_policy("geo", EcsPolicy::Strip).unwrap();
+        assert_eq!(answer_for(&resolver, [10, 1, 1, 1]).await, (Ipv4Addr::new(0, 0, 2, 1), false));
+        let (_, info) = resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert!(info.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_rate_limited_transport_backs_off() {
+        let limited = Arc::new(MockTransport::new().with_error("example.com", RecordType::A, DnsError::HttpStatus {
+            status: 429,
+            upstream: "limited".to_string(),
+            body_snippet: None,
+            retry_after: None,
+        }));
+        let mut resolver = CoreResolver::new(test_config(QueryStrategy::Fifo, false));
+        resolver.add_named_transport("limited", limited.clone());
+        resolver.add_named_transport("healthy", mock(ALPHA, 1, Duration::from_millis(20)));
+        
+        for _ in 0..2 {
+            let (_, info) = resolver
+                .query_with_info("example.com", RecordType::A, QClass::IN, None)
+                .await
+                .unwrap();
+            assert_eq!(info.unwrap().name, "healthy");
+        }
+        // 第二次查询时 limited 处于退避期，没有再收到请求
+        assert_eq!(limited.call_count(), 1);
+    }
+    
+    /// 先返回 `limits` 次带 `retry_after` 的429、之后正常应答的DoH上游，以及只使用它的解析器
+    #[cfg(feature = "doh")]
+    async fn rate_limited_doh(limits: u64, retry_after: &str, behavior: RateLimitedBehavior) -> (wiremock::MockServer, CoreResolver) {
+        use crate::transport::{HttpMethod, HttpVersionPref};
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+        
+        /// 回显请求报文ID的DNS应答
+        struct EchoId(Vec<u8>);
+        
+        impl wiremock::Respond for EchoId {
+            fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+                let mut body = self.0.clone();
+                body[..2].copy_from_slice(&request.body[..2]);
+                ResponseTemplate::new(200).insert_header("content-type", "application/dns-message").set_body_bytes(body)
+            }
         }
         
-        let mut tasks = Vec::new();
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", retry_after))
+            .up_to_n_times(limits)
+            .mount(&server)
+            .await;
+        let answer = crate::dns_response::DnsResponseWrapper::create_a_response(0, "example.com", &[ALPHA], 300);
+        Mock::given(method("POST"))
+            .respond_with(EchoId(UdpTransport::serialize_response(&answer).unwrap()))
+            .mount(&server)
+            .await;
         
-        for transport in available_transports {
-            let transport_clone = Arc::clone(&transport);
-            let request_clone = request.clone();
-            
-            let task = tokio::spawn(async move {
-                transport_clone.send(&request_clone).await
-            });
+        let transport = HttpsTransport::new(HttpsConfig {
+            base: TransportConfig {
+                server: "127.0.0.1".to_string(),
+                port: 443,
+                timeout: Duration::from_secs(3),
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: 1,
+                buffer_size: 4096,
+                record_limits: None,
+            },
+            url: format!("{}/dns-query", server.uri()),
+            method: HttpMethod::POST,
+            user_agent: "rat_quickdns-test".to_string(),
+            extra_headers: Vec::new(),
+            query_params: Vec::new(),
+            http_version: HttpVersionPref::Auto,
+            max_response_size: 65535,
+            follow_redirects: true,
+            connect_addr: None,
+        }).unwrap();
+        let mut config = test_config(QueryStrategy::Fifo, false);
+        config.enable_upstream_monitoring = true;
+        config.enable_stats = true;
+        config.on_rate_limited = behavior;
+        let mut resolver = CoreResolver::new(config);
+        resolver.add_named_transport("doh", Arc::new(transport));
+        (server, resolver)
+    }
+    
+    #[cfg(feature = "doh")]
+    #[tokio::test]
+    async fn test_rate_limited_sole_upstream_waits_and_retries() {
+        let wait = RateLimitedBehavior::WaitAndRetry { max_wait: Duration::from_secs(5) };
+        let (_server, resolver) = rate_limited_doh(1, "1", wait).await;
+        let start = Instant::now();
+        let (_, info) = resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(1), "{:?}", start.elapsed());
+        assert_eq!(info.unwrap().name, "doh");
+        assert_eq!(resolver.transport_rate_limited("doh"), 1);
+        // 限流不计为失败，重试成功后不再退避
+        let health = resolver.upstream_health("doh").unwrap();
+        assert_eq!((health.failure_count, health.success_count), (0, 1));
+        resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        
+        // 默认立即失败，限流计为失败
+        let (_server, resolver) = rate_limited_doh(1, "1", RateLimitedBehavior::FailoverImmediately).await;
+        let start = Instant::now();
+        let error = resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(matches!(error, DnsError::Server(_)), "{:?}", error);
+        assert_eq!(resolver.transport_rate_limited("doh"), 1);
+        assert_eq!(resolver.upstream_health("doh").unwrap().failure_count, 1);
+    }
+    
+    #[cfg(feature = "doh")]
+    #[tokio::test]
+    async fn test_rate_limited_wait_capped_by_max_wait_and_deadline() {
+        // 上游要求等待2分钟，只等 max_wait
+        let capped = RateLimitedBehavior::WaitAndRetry { max_wait: Duration::from_millis(200) };
+        let (_server, resolver) = rate_limited_doh(1, "120", capped).await;
+        let start = Instant::now();
+        resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(3), "{:?}", start.elapsed());
+        
+        // 等完已超过查询的截止时间时不等待，立即失败
+        let patient = RateLimitedBehavior::WaitAndRetry { max_wait: Duration::from_secs(10) };
+        let (_server, resolver) = rate_limited_doh(1, "3", patient).await;
+        let start = Instant::now();
+        let query = resolver.query_with_info("example.com", RecordType::A, QClass::IN, None);
+        assert!(rate_limit::with_deadline(Instant::now() + Duration::from_secs(1), query).await.is_err());
+        assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+    }
+    
+    #[tokio::test]
+    async fn test_max_inflight_rejects_or_waits_for_a_slot() {
+        async fn run(behavior: QueueBehavior) -> (usize, usize, u64) {
+            let mut config = test_config(QueryStrategy::Smart, false);
+            config.default_timeout = Duration::from_secs(1);
+            let resolver = CoreResolver::new(config);
+            let slow = mock(ALPHA, 1, Duration::from_millis(100));
+            let fast = mock(BETA, 1, Duration::ZERO);
+            resolver.register_transport("slow", slow.clone()).unwrap();
+            resolver.register_transport("fast", fast).unwrap();
+            resolver.set_transport_max_inflight("slow", Some(1), behavior).unwrap();
             
-            tasks.push(task);
+            let (first, second) = tokio::join!(
+                resolver.query("example.com", RecordType::A, QClass::IN),
+                resolver.query("example.com", RecordType::A, QClass::IN),
+            );
+            first.unwrap();
+            second.unwrap();
+            (slow.call_count(), slow.max_in_flight(), resolver.transport_saturations("slow"))
         }
         
-        // 等待所有任务完成
-        let results = futures::future::join_all(tasks).await;
+        // 名额已满时第二个查询跳过 slow，只由 fast 应答
+        assert_eq!(run(QueueBehavior::Reject).await, (1, 1, 1));
+        // 限时等待：第一个查询结束后第二个查询取得名额，两次都发给了 slow 但从不并发
+        assert_eq!(run(QueueBehavior::Wait { timeout: Duration::from_millis(500) }).await, (2, 1, 0));
         
-        // 返回第一个成功的结果
-        for result in results {
-            if let Ok(Ok(response)) = result {
-                return Ok(response);
+        let resolver = CoreResolver::new(test_config(QueryStrategy::Smart, false));
+        resolver.register_transport("slow", mock(ALPHA, 1, Duration::ZERO)).unwrap();
+        assert!(resolver.set_transport_max_inflight("slow", Some(0), QueueBehavior::Reject).is_err());
+        assert_eq!(resolver.transport_saturations("missing"), 0);
+    }
+    
+    #[tokio::test]
+    async fn test_mismatched_question_is_not_cached_and_rejected_when_strict() {
+        // 上游回显了别的问题
+        let forged = DnsResponseWrapper::create_a_response(0, "attacker.example", &[BETA], 300);
+        let upstream = Arc::new(MockTransport::new().with_response("example.com", RecordType::A, forged));
+        
+        let mut resolver = CoreResolver::new(test_config(QueryStrategy::Fifo, true));
+        resolver.add_transport(upstream.clone());
+        for _ in 0..2 {
+            let response = resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+            assert_eq!(response.answers.len(), 1);
+        }
+        assert_eq!(upstream.call_count(), 2);
+        assert_eq!(resolver.cache_stats().unwrap().rejected, 2);
+        
+        let mut config = test_config(QueryStrategy::Fifo, true);
+        config.strict_response_check = true;
+        let mut strict = CoreResolver::new(config);
+        strict.add_transport(upstream);
+        let error = strict.query("example.com", RecordType::A, QClass::IN).await.unwrap_err();
+        assert!(matches!(error, DnsError::Protocol(_)), "{:?}", error);
+    }
+    
+    #[tokio::test]
+    async fn test_zero_ttl_answers_bypass_cache_unless_floored() {
+        async fn upstream_calls(config: CoreResolverConfig) -> (usize, CacheStats) {
+            let upstream = Arc::new(MockTransport::new().with_a("example.com", &[ALPHA], 0));
+            let mut resolver = CoreResolver::new(config);
+            resolver.add_transport(upstream.clone());
+            for _ in 0..2 {
+                resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
             }
+            (upstream.call_count(), resolver.cache_stats().unwrap())
         }
         
-        Err(DnsError::Server("All parallel queries failed".to_string()))
+        let (calls, stats) = upstream_calls(test_config(QueryStrategy::Fifo, true)).await;
+        assert_eq!(calls, 2);
+        assert_eq!((stats.uncacheable, stats.rejected, stats.inserts), (2, 0, 0));
+        
+        // TTL下限先把0抬高，答案照常缓存
+        let mut floored = test_config(QueryStrategy::Fifo, true);
+        floored.min_ttl = Some(Duration::from_secs(30));
+        assert_eq!(upstream_calls(floored).await.0, 1);
+        
+        let mut escape_hatch = test_config(QueryStrategy::Fifo, true);
+        escape_hatch.cache_zero_ttl = true;
+        assert_eq!(upstream_calls(escape_hatch).await.0, 1);
     }
     
-    /// 顺序查询策略
-    async fn query_sequential(&self, request: &Request) -> Result<Response> {
-        let available_transports = self.get_available_transports();
+    /// 连续查询100次，统计每个地址排在第一位的次数（按地址排序）
+    async fn first_addresses(rotation: RotationMode) -> Vec<(Ipv4Addr, usize)> {
+        let addresses: Vec<Ipv4Addr> = (1..=4).map(|i| Ipv4Addr::new(192, 0, 2, i)).collect();
+        let response = DnsResponseWrapper::create_a_response(0, "example.com", &addresses, 300);
+        let upstream = Arc::new(MockTransport::new().with_response("example.com", RecordType::A, response));
+        let mut config = test_config(QueryStrategy::Fifo, true);
+        config.record_rotation = rotation;
+        let mut resolver = CoreResolver::new(config);
+        resolver.add_transport(upstream.clone());
         
-        if available_transports.is_empty() {
-            return Err(DnsError::Server("No available transports".to_string()));
+        let mut firsts: HashMap<Ipv4Addr, usize> = HashMap::new();
+        for _ in 0..100 {
+            let response = resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+            assert_eq!(response.answers.len(), 4);
+            match &response.answers[0].data {
+                RecordData::A(addr) => *firsts.entry(*addr).or_default() += 1,
+                other => panic!("unexpected record: {:?}", other),
+            }
         }
+        assert_eq!(upstream.call_count(), 1);
+        let mut firsts: Vec<_> = firsts.into_iter().collect();
+        firsts.sort();
+        firsts
+    }
+    
+    #[tokio::test]
+    async fn test_round_robin_rotation_spreads_first_address() {
+        let firsts = first_addresses(RotationMode::RoundRobinPerHit).await;
+        assert_eq!(firsts.len(), 4);
+        assert!(firsts.iter().all(|(_, count)| *count == 25), "{:?}", firsts);
         
-        let mut last_error = DnsError::Server("No transports tried".to_string());
-        
-        for transport in available_transports {
-            for attempt in 0..=self.retry_count {
-                match transport.send(request).await {
-                    Ok(response) => return Ok(response),
-                    Err(e) => {
-                        last_error = e;
-                        if attempt < self.retry_count {
-                            tokio::time::sleep(Duration::from_millis(100 * (attempt + 1) as u64)).await;
-                        }
-                    }
+        assert_eq!(first_addresses(RotationMode::None).await, vec![(Ipv4Addr::new(192, 0, 2, 1), 100)]);
+    }
+    
+    /// 记录同时进行中的报文ID，发现重复时计数
+    #[derive(Debug, Default)]
+    struct IdRecordingTransport {
+        in_flight: Mutex<std::collections::HashSet<u16>>,
+        duplicates: AtomicUsize,
+        peak: AtomicUsize,
+    }
+    
+    #[async_trait::async_trait]
+    impl Transport for IdRecordingTransport {
+        async fn send(&self, request: &Request) -> Result<Response> {
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                if !in_flight.insert(request.id) {
+                    self.duplicates.fetch_add(1, Ordering::SeqCst);
                 }
+                self.peak.fetch_max(in_flight.len(), Ordering::SeqCst);
             }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            self.in_flight.lock().unwrap().remove(&request.id);
+            Ok(DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[ALPHA], 300))
         }
         
-        Err(last_error)
+        fn transport_type(&self) -> &'static str {
+            "RECORDING"
+        }
+        
+        fn set_timeout(&mut self, _timeout: Duration) {}
+        
+        fn timeout(&self) -> Duration {
+            Duration::from_secs(5)
+        }
     }
     
-    /// 智能决策策略
-    async fn query_smart_decision(&self, request: &Request) -> Result<Response> {
-        // 智能决策：结合速度、可靠性和结果完整性
-        let available_transports = self.get_available_transports();
+    #[tokio::test]
+    async fn test_concurrent_queries_never_share_an_id_on_one_upstream() {
+        let upstream = Arc::new(IdRecordingTransport::default());
+        let mut config = test_config(QueryStrategy::Fifo, false);
+        config.concurrent_queries = 1000;
+        let mut resolver = CoreResolver::new(config);
+        resolver.add_transport(upstream.clone());
         
-        if available_transports.is_empty() {
-            return Err(DnsError::Server("No available transports".to_string()));
+        let queries = (0..10_000).map(|_| resolver.query("example.com", RecordType::A, QClass::IN));
+        let results = futures::future::join_all(queries).await;
+        
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(upstream.duplicates.load(Ordering::SeqCst), 0);
+        assert!(upstream.peak.load(Ordering::SeqCst) > 100, "queries did not overlap");
+        assert!(upstream.in_flight.lock().unwrap().is_empty());
+    }
+    
+    /// 记录同时在处理的查询数峰值的慢速传输
+    #[derive(Debug, Default)]
+    struct PeakTransport {
+        current: AtomicUsize,
+        peak: AtomicUsize,
+    }
+    
+    #[async_trait::async_trait]
+    impl Transport for PeakTransport {
+        async fn send(&self, request: &Request) -> Result<Response> {
+            let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(current, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.current.fetch_sub(1, Ordering::SeqCst);
+            Ok(DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[ALPHA], 300))
         }
         
-        let mut tasks = Vec::new();
+        fn transport_type(&self) -> &'static str {
+            "PEAK"
+        }
         
-        for (index, transport) in available_transports.iter().enumerate() {
-            let transport_clone = Arc::clone(transport);
-            let request_clone = request.clone();
+        fn set_timeout(&mut self, _timeout: Duration) {}
+        
+        fn timeout(&self) -> Duration {
+            Duration::from_secs(5)
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_concurrent_queries_bounds_in_flight_sends() {
+        for wait_timeout in [None, Some(Duration::from_millis(20))] {
+            let upstream = Arc::new(PeakTransport::default());
+            let mut config = test_config(QueryStrategy::Sequential, false);
+            config.concurrent_queries = 5;
+            config.concurrency_wait_timeout = wait_timeout;
+            let mut resolver = CoreResolver::new(config);
+            resolver.add_transport(upstream.clone());
             
-            let task = tokio::spawn(async move {
-                let start = Instant::now();
-                let transport_type = transport_clone.transport_type();
-                
-                let result = transport_clone.send(&request_clone).await;
-                let duration = start.elapsed();
-                
-                QueryResult {
-                    response: result,
-                    duration,
-                    transport_type: transport_type.to_string(),
-                }
-            });
+            let queries = (0..50).map(|_| resolver.query("example.com", RecordType::A, QClass::IN));
+            let results = futures::future::join_all(queries).await;
             
-            tasks.push(task);
+            assert_eq!(upstream.peak.load(Ordering::SeqCst), 5);
+            assert_eq!(resolver.in_flight_sends(), 0);
+            let busy = results.iter().filter(|result| matches!(result, Err(DnsError::Busy { limit: 5 }))).count();
+            let answered = results.iter().filter(|result| result.is_ok()).count();
+            match wait_timeout {
+                // 一直等待：全部排队完成
+                None => assert_eq!(answered, 50),
+                // 第一批5个发送占用50毫秒，其余等待20毫秒后放弃
+                Some(_) => assert_eq!((answered, busy), (5, 45)),
+            }
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_timing_breakdown_only_when_enabled() {
+        for capture in [false, true] {
+            let mut config = test_config(QueryStrategy::Fifo, false);
+            config.capture_timing_breakdown = capture;
+            let mut resolver = CoreResolver::new(config);
+            resolver.add_transport(mock(ALPHA, 1, Duration::from_millis(20)));
+            
+            let (_, info) = resolver
+                .query_with_info("example.com", RecordType::A, QClass::IN, None)
+                .await
+                .unwrap();
+            let timing = info.unwrap().timing;
+            assert_eq!(timing.is_some(), capture);
+            if let Some(timing) = timing {
+                // 模拟传输只能给出总耗时
+                assert_eq!(timing.phases().len(), 1);
+                assert!(timing.complete >= Duration::from_millis(20));
+            }
         }
+    }
+    
+    #[tokio::test]
+    async fn test_offline_serves_expired_cache_and_revalidates_at_bounded_rate() {
+        let clock = Arc::new(clock::TestClock::new());
+        let mut resolver = CoreResolver::with_clock(test_config(QueryStrategy::Fifo, true), clock.clone());
+        let names = ["a.test", "b.test", "c.test"];
+        let upstream = Arc::new(names.iter().fold(MockTransport::new(), |mock, name| mock.with_a(name, &[ALPHA], 60)));
+        resolver.add_named_transport("alpha", upstream.clone());
+        for name in names {
+            resolver.query(name, RecordType::A, QClass::IN).await.unwrap();
+        }
+        upstream.clear_calls();
         
-        // 收集所有结果
-        let mut results = Vec::new();
-        let mut fastest_response: Option<Response> = None;
-        let mut fastest_time = Duration::from_secs(u64::MAX);
+        resolver.set_offline(true);
+        clock.advance(Duration::from_secs(3600));
+        for name in names {
+            let (response, origin) = resolver.query_with_origin(name, RecordType::A, QClass::IN, None).await.unwrap();
+            assert_eq!(origin, ResponseOrigin::StaleCache);
+            assert_eq!(response.answers[0].ttl, 0);
+        }
+        let missing = resolver.query("missing.test", RecordType::A, QClass::IN).await;
+        assert!(matches!(missing, Err(DnsError::Offline { .. })), "{:?}", missing);
+        assert_eq!(upstream.call_count(), 0);
         
-        // 等待所有结果或超时
-        let timeout_duration = self.default_timeout;
-        let deadline = Instant::now() + timeout_duration;
+        // 默认每秒刷新10个：3个条目之间等待两次100ms
+        let before = clock.elapsed();
+        resolver.set_offline(false);
+        while resolver.offline_stats().pending_revalidation > 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(upstream.call_count(), 3);
+        assert_eq!(clock.elapsed() - before, Duration::from_millis(200));
         
-        while !tasks.is_empty() && Instant::now() < deadline {
-            let remaining_time = deadline.duration_since(Instant::now());
-            
-            match timeout(remaining_time, futures::future::select_all(tasks)).await {
-                Ok((task_result, _index, remaining_tasks)) => {
-                    tasks = remaining_tasks;
-                    
-                    if let Ok(query_result) = task_result {
-                        if let Ok(response) = &query_result.response {
-                            // 记录最快的响应
-                            if query_result.duration < fastest_time {
-                                fastest_time = query_result.duration;
-                                fastest_response = Some(response.clone());
-                            }
-                        }
-                        results.push(query_result);
-                    }
-                }
-                Err(_) => {
-                    break; // 超时
-                }
-            }
+        let stats = resolver.offline_stats();
+        assert!(!stats.offline);
+        assert_eq!((stats.transitions, stats.stale_answers, stats.refused, stats.revalidated), (2, 3, 1, 3));
+        let (_, origin) = resolver.query_with_origin("a.test", RecordType::A, QClass::IN, None).await.unwrap();
+        assert_eq!(origin, ResponseOrigin::Cache);
+    }
+    
+    #[tokio::test]
+    async fn test_gradual_cache_clear_smears_upstream_queries() {
+        let clock = Arc::new(clock::TestClock::new());
+        let mut resolver = CoreResolver::with_clock(test_config(QueryStrategy::Fifo, true), clock.clone());
+        let names: Vec<String> = (0..1000).map(|i| format!("host{}.test", i)).collect();
+        let upstream = Arc::new(names.iter().fold(MockTransport::new(), |mock, name| mock.with_a(name, &[ALPHA], 3600)));
+        resolver.add_named_transport("alpha", upstream.clone());
+        for name in &names {
+            resolver.query(name, RecordType::A, QClass::IN).await.unwrap();
         }
+        upstream.clear_calls();
         
-        // 智能选择最佳结果
-        let final_result = self.select_best_result(results, fastest_response);
+        assert!(matches!(resolver.clear_cache_gradual(0).await, Err(DnsError::InvalidConfig(_))));
+        let report = resolver.clear_cache_gradual(100).await.unwrap();
+        assert_eq!(report, CacheClearReport { affected: 1000, retained: 0, completes_in: Duration::from_secs(10) });
         
-        // 记录最终选择的策略结果
-        match &final_result {
-            Ok(response) => {
-                dns_info!("🧠 Smart策略: 最终选择成功 - 答案数: {}, 查询: {}", 
-                         response.answers.len(), request.query.name);
+        // 客户端每秒把所有名称查询一遍，上游每秒只收到100个查询，而不是一次1000个
+        let mut per_second = Vec::new();
+        for _ in 0..11 {
+            let before = upstream.call_count();
+            for name in &names {
+                resolver.query(name, RecordType::A, QClass::IN).await.unwrap();
             }
-            Err(e) => {
-                dns_warn!("🧠 Smart策略: 所有传输均失败 - 错误: {}, 查询: {}", 
-                         e, request.query.name);
+            per_second.push(upstream.call_count() - before);
+            clock.advance(Duration::from_secs(1));
+        }
+        assert_eq!(per_second, [0, 100, 100, 100, 100, 100, 100, 100, 100, 100, 100]);
+    }
+    
+    #[tokio::test]
+    async fn test_clear_cache_except_hot_keeps_most_hit_entries() {
+        let mut resolver = CoreResolver::new(test_config(QueryStrategy::Fifo, true));
+        let names = ["hot.test", "warm.test", "cold.test", "idle.test"];
+        let upstream = Arc::new(names.iter().fold(MockTransport::new(), |mock, name| mock.with_a(name, &[ALPHA], 3600)));
+        resolver.add_named_transport("alpha", upstream.clone());
+        for (name, hits) in names.iter().zip([5, 3, 1, 0]) {
+            for _ in 0..=hits {
+                resolver.query(name, RecordType::A, QClass::IN).await.unwrap();
             }
         }
+        upstream.clear_calls();
         
-        final_result
+        let report = resolver.clear_cache_except_hot(2).await.unwrap();
+        assert_eq!(report, CacheClearReport { affected: 2, retained: 2, completes_in: Duration::ZERO });
+        for name in ["hot.test", "warm.test"] {
+            let (_, origin) = resolver.query_with_origin(name, RecordType::A, QClass::IN, None).await.unwrap();
+            assert_eq!(origin, ResponseOrigin::Cache);
+        }
+        assert_eq!(upstream.call_count(), 0);
+        resolver.query("cold.test", RecordType::A, QClass::IN).await.unwrap();
+        assert_eq!(upstream.call_count(), 1);
+        assert_eq!(resolver.cache_stats().unwrap().invalidations, 2);
     }
     
-    /// 选择最佳查询结果
-    fn select_best_result(
-        &self,
-        results: Vec<QueryResult>,
-        fastest_response: Option<Response>,
-    ) -> Result<Response> {
-        if results.is_empty() {
-            return Err(DnsError::Timeout);
-        }
+    #[tokio::test]
+    async fn test_auto_offline_after_all_upstreams_unavailable() {
+        let clock = Arc::new(clock::TestClock::new());
+        let mut config = test_config(QueryStrategy::Fifo, true);
+        config.enable_upstream_monitoring = true;
+        config.auto_offline_after = Some(Duration::from_secs(30));
+        let mut resolver = CoreResolver::with_clock(config, clock.clone());
+        resolver.add_named_transport("beta", mock(BETA, 1, Duration::ZERO));
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
         
-        // 按优先级选择结果：
-        // 1. 结果最完整的（答案记录最多）
-        // 2. 如果完整性相同，选择最快的
-        // 3. 如果都失败，返回最快的错误
+        let monitor = resolver.upstream_monitor.clone().unwrap();
+        monitor.set_upstream_status("beta", health::UpstreamStatus::Unavailable);
+        clock.advance(Duration::from_secs(301));
+        let _ = resolver.query("example.com", RecordType::A, QClass::IN).await;
+        assert!(!resolver.is_offline());
         
-        let mut best_response: Option<Response> = None;
-        let mut best_score = -1i32;
-        let mut best_duration = Duration::from_secs(u64::MAX);
-        let mut best_transport_type = String::new();
+        clock.advance(Duration::from_secs(30));
+        let (_, origin) = resolver.query_with_origin("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert_eq!(origin, ResponseOrigin::StaleCache);
+        assert_eq!(resolver.offline_stats().reason, Some(OfflineReason::Auto));
         
-        // 分析每个结果
-        for result in results.iter() {
-            if let Ok(response) = &result.response {
-                let score = response.answers.len() as i32;
-                
-                if score > best_score || 
-                   (score == best_score && result.duration < best_duration) {
-                    best_score = score;
-                    best_duration = result.duration;
-                    best_response = Some(response.clone());
-                    best_transport_type = result.transport_type.clone();
-                }
+        // 上游恢复可用后下一次查询即退出离线模式
+        monitor.set_upstream_status("beta", health::UpstreamStatus::Available);
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        assert!(!resolver.is_offline());
+        assert_eq!(resolver.offline_stats().transitions, 2);
+    }
+
+    #[derive(Debug, Default)]
+    struct ProbeLog(Mutex<Vec<(String, ProbeReason, ProbeOutcome)>>);
+
+    impl QueryObserver for ProbeLog {
+        fn on_upstream_probe(&self, upstream: &str, reason: ProbeReason, outcome: ProbeOutcome) {
+            self.0.lock().unwrap().push((upstream.to_string(), reason, outcome));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_silent_upstreams_are_probed_and_dead_ones_marked_unavailable() {
+        let clock = Arc::new(clock::TestClock::new());
+        let observer = Arc::new(ProbeLog::default());
+        let mut config = test_config(QueryStrategy::Sequential, false);
+        config.enable_upstream_monitoring = true;
+        config.health_probe = Some(ProbeConfig::new(vec!["example.com".to_string()], crate::builder::types::DnsRecordType::A, Duration::from_secs(1)));
+        config.max_upstream_silence = Some(Duration::from_secs(60));
+        config.query_observer = Some(observer.clone());
+        let mut resolver = CoreResolver::with_clock(config, clock.clone());
+        let alpha = mock(ALPHA, 1, Duration::ZERO);
+        let dead = Arc::new(MockTransport::new().with_failure_rate(1.0));
+        resolver.add_named_transport("alpha", alpha.clone());
+        resolver.add_named_transport("dead", dead.clone());
+        // 由测试按测试时钟驱动监控，不用后台任务
+        resolver.stop_upstream_monitor_task();
+        let monitor = resolver.upstream_monitor.clone().unwrap();
+        let task = resolver.upstream_monitor_task_for(monitor.clone());
+
+        // 第一次检查从现在开始计时，没有达到上限前不探测
+        task.perform_upstream_monitoring().await;
+        clock.advance(Duration::from_secs(59));
+        task.perform_upstream_monitoring().await;
+        assert_eq!((alpha.call_count(), dead.call_count()), (0, 0));
+
+        clock.advance(Duration::from_secs(1));
+        task.perform_upstream_monitoring().await;
+        assert_eq!((alpha.call_count(), dead.call_count()), (1, 1));
+        assert_eq!(observer.0.lock().unwrap().clone(), vec![
+            ("alpha".to_string(), ProbeReason::Staleness, ProbeOutcome::Success),
+            ("dead".to_string(), ProbeReason::Staleness, ProbeOutcome::Failed),
+        ]);
+
+        // 有真实查询的上游不再被探测；没有任何查询碰过的dead连续探测失败后被判为不可用
+        for _ in 0..2 {
+            clock.advance(Duration::from_secs(60));
+            resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+            task.perform_upstream_monitoring().await;
+        }
+        assert_eq!((alpha.call_count(), dead.call_count()), (3, 3));
+        assert!(monitor.is_transport_available("alpha"));
+        assert!(!monitor.is_transport_available("dead"));
+        assert_eq!(resolver.upstream_events("dead", None).last().unwrap().trigger, health::StatusTrigger::ConsecutiveFailures);
+    }
+
+    /// 模拟被TLS拦截的加密上游：拦截期间证书校验失败，否则正常应答
+    #[derive(Debug, Default)]
+    struct InterceptedTransport {
+        intercepted: std::sync::atomic::AtomicBool,
+        sends: AtomicUsize,
+    }
+    
+    #[async_trait::async_trait]
+    impl Transport for InterceptedTransport {
+        async fn send(&self, request: &Request) -> Result<Response> {
+            self.sends.fetch_add(1, Ordering::SeqCst);
+            if self.intercepted.load(Ordering::SeqCst) {
+                return Err(DnsError::TlsFailure { kind: crate::error::TlsErrorKind::CertificateInvalid, upstream: "dot".to_string() });
             }
+            Ok(DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[ALPHA], 300))
         }
         
-        // 如果有完整结果，返回最佳结果
-        if let Some(response) = best_response {
-            dns_info!("🎯 Smart策略: 选择最佳结果 - 传输: {}, 答案数: {}, 耗时: {:?}ms", 
-                     best_transport_type, best_score, best_duration.as_millis());
-            return Ok(response);
+        fn transport_type(&self) -> &'static str {
+            "TLS"
+        }
+        
+        fn set_timeout(&mut self, _timeout: Duration) {}
+        
+        fn timeout(&self) -> Duration {
+            Duration::from_secs(5)
         }
         
-        // 如果没有成功结果，但有最快的响应，返回它
-        if let Some(response) = fastest_response {
-            return Ok(response);
+        fn encrypted_host(&self) -> Option<String> {
+            Some("127.0.0.1".to_string())
         }
         
-        // 返回第一个错误
-        for result in results {
-            if let Err(e) = result.response {
-                return Err(e);
+        fn insecure_variant(&self) -> Result<Option<Arc<dyn Transport>>> {
+            Ok(Some(mock(BETA, 1, Duration::ZERO)))
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_encrypted_fallback_flags_degraded_answers_and_recovers() {
+        let upstream = Arc::new(InterceptedTransport::default());
+        upstream.intercepted.store(true, Ordering::SeqCst);
+        let mut config = test_config(QueryStrategy::Fifo, true);
+        config.encrypted_fallback = EncryptedFallbackPolicy::OpportunisticSkipVerify;
+        config.encrypted_fallback_threshold = 2;
+        config.encrypted_fallback_probe_interval = Duration::ZERO;
+        let mut resolver = CoreResolver::new(config);
+        resolver.add_named_transport("dot", upstream.clone());
+        
+        for _ in 0..2 {
+            assert!(resolver.query("example.com", RecordType::A, QClass::IN).await.is_err());
+        }
+        assert!(resolver.encrypted_fallback_stats().unwrap().active);
+        
+        // 降级应答带标记且不写入缓存，每次都经过降级传输
+        for _ in 0..2 {
+            let (response, info) = resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+            assert_eq!(response.answers[0].data, RecordData::A(BETA));
+            assert!(info.expect("degraded answers are never cached").degraded_security);
+        }
+        
+        // 拦截解除后，后台探测成功即退出降级
+        upstream.intercepted.store(false, Ordering::SeqCst);
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        for _ in 0..100 {
+            if !resolver.encrypted_fallback_stats().unwrap().active {
+                break;
             }
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
+        let (response, info) = resolver.query_with_info("example.com", RecordType::A, QClass::IN, None).await.unwrap();
+        assert_eq!(response.answers[0].data, RecordData::A(ALPHA));
+        assert!(!info.unwrap().degraded_security);
         
-        Err(DnsError::Server("No valid results".to_string()))
+        let stats = resolver.encrypted_fallback_stats().unwrap();
+        assert!(!stats.active);
+        assert_eq!(stats.transitions, 2);
+        assert!(stats.degraded_queries >= 3);
     }
     
-    /// 获取可用的传输实例
-    fn get_available_transports(&self) -> Vec<Arc<dyn Transport + Send + Sync + 'static>> {
-        if let Some(upstream_monitor) = &self.upstream_monitor {
-            self.transports
-                .iter()
-                .filter(|t| {
-                    let transport_type = t.transport_type();
-                    upstream_monitor.is_transport_available(transport_type)
-                })
-                .cloned()
-                .collect()
-        } else {
-            self.transports.clone()
+    async fn answer_of(resolver: &CoreResolver) -> RecordData {
+        let response = resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        response.answers[0].data.clone()
+    }
+    
+    #[tokio::test]
+    async fn test_handshake_failure_quarantines_upstream_with_exponential_readmission() {
+        let dot = Arc::new(InterceptedTransport::default());
+        dot.intercepted.store(true, Ordering::SeqCst);
+        let clock = Arc::new(clock::TestClock::new());
+        let mut config = test_config(QueryStrategy::Sequential, false);
+        config.enable_upstream_monitoring = true;
+        config.tls_quarantine = Some(TlsQuarantineConfig { initial: Duration::from_secs(30), max: Duration::from_secs(120) });
+        let mut resolver = CoreResolver::with_clock(config, clock.clone());
+        resolver.add_named_transport("dot", dot.clone());
+        resolver.add_named_transport("backup", mock(BETA, 1, Duration::ZERO));
+        let sends = || dot.sends.load(Ordering::SeqCst);
+        
+        // 第一次证书失败即隔离，查询转到备用上游；握手失败不计入上游监控
+        assert_eq!(answer_of(&resolver).await, RecordData::A(BETA));
+        assert_eq!(sends(), 1);
+        let status = resolver.transport_quarantine("dot").unwrap();
+        assert_eq!((status.kind, status.period, status.failures), (crate::error::TlsErrorKind::CertificateInvalid, Duration::from_secs(30), 1));
+        assert!(resolver.upstream_monitor.as_ref().unwrap().detailed_stats("dot").is_none());
+        
+        // 隔离期内不建立任何连接
+        for _ in 0..5 {
+            clock.advance(Duration::from_secs(5));
+            assert_eq!(answer_of(&resolver).await, RecordData::A(BETA));
+        }
+        assert_eq!(sends(), 1);
+        assert_eq!(resolver.transport_quarantine("dot").unwrap().next_probe_in, Duration::from_secs(5));
+        
+        // 每次重新接纳的探测失败，隔离时长加倍，不超过上限
+        for expected in [60, 120, 120] {
+            clock.advance(resolver.transport_quarantine("dot").unwrap().next_probe_in);
+            let before = sends();
+            assert_eq!(answer_of(&resolver).await, RecordData::A(BETA));
+            assert_eq!(answer_of(&resolver).await, RecordData::A(BETA));
+            assert_eq!(sends(), before + 1);
+            assert_eq!(resolver.transport_quarantine("dot").unwrap().period, Duration::from_secs(expected));
         }
+        
+        // 证书修复后，期满的第一个查询探测成功，立即解除隔离
+        dot.intercepted.store(false, Ordering::SeqCst);
+        clock.advance(Duration::from_secs(120));
+        assert_eq!(answer_of(&resolver).await, RecordData::A(ALPHA));
+        assert_eq!(resolver.transport_quarantine("dot"), None);
+        assert!(resolver.upstream_monitor.as_ref().unwrap().is_transport_available("dot"));
+        
+        // 手动解除不必等隔离期满
+        dot.intercepted.store(true, Ordering::SeqCst);
+        answer_of(&resolver).await;
+        dot.intercepted.store(false, Ordering::SeqCst);
+        assert!(resolver.transport_quarantine("dot").is_some());
+        assert!(resolver.clear_transport_quarantine("dot").unwrap());
+        assert!(!resolver.clear_transport_quarantine("dot").unwrap());
+        assert!(resolver.clear_transport_quarantine("missing").is_err());
+        assert_eq!(answer_of(&resolver).await, RecordData::A(ALPHA));
     }
     
-    /// 获取传输统计信息
-    pub fn get_transport_stats(&self) -> HashMap<String, (u64, u64, Duration)> {
-        if let Some(upstream_monitor) = &self.upstream_monitor {
-            upstream_monitor.get_stats()
-        } else {
-            HashMap::new()
+    #[tokio::test]
+    #[cfg(feature = "dot")]
+    async fn test_untrusted_certificate_quarantines_real_dot_upstream() {
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+        
+        // 出示自签名证书的DoT服务器，只统计连接数
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(include_bytes!("../transport/testdata/localhost.crt.der").to_vec())],
+                PrivateKey(include_bytes!("../transport/testdata/localhost.key.der").to_vec()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let _ = acceptor.accept(stream).await;
+                });
+            }
+        });
+        
+        let mut config = test_config(QueryStrategy::Sequential, false);
+        config.tls_quarantine = Some(TlsQuarantineConfig::default());
+        let mut resolver = CoreResolver::new(config);
+        resolver.add_tls_transport(TlsConfig {
+            base: TransportConfig {
+                server: "127.0.0.1".to_string(),
+                port,
+                timeout: Duration::from_secs(2),
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: 1,
+                buffer_size: 4096,
+                record_limits: None,
+            },
+            server_name: "localhost".to_string(),
+            verify_cert: true,
+            enable_tls_early_data: false,
+        }).unwrap();
+        resolver.add_named_transport("backup", mock(BETA, 1, Duration::ZERO));
+        let name = resolver.transports()[0].name.clone();
+        
+        assert_eq!(answer_of(&resolver).await, RecordData::A(BETA));
+        let status = resolver.transport_quarantine(&name).expect("untrusted certificate quarantines the upstream");
+        assert_eq!(status.kind, crate::error::TlsErrorKind::CertificateInvalid);
+        let handshakes = connections.load(Ordering::SeqCst);
+        assert!(handshakes >= 1);
+        
+        for _ in 0..5 {
+            assert_eq!(answer_of(&resolver).await, RecordData::A(BETA));
         }
+        assert_eq!(connections.load(Ordering::SeqCst), handshakes);
     }
     
-    /// 获取传输数量
-    pub fn transport_count(&self) -> usize {
-        self.transports.len()
+    #[tokio::test]
+    async fn test_interactive_queries_bypass_saturating_bulk_traffic() {
+        let mut config = test_config(QueryStrategy::Sequential, false);
+        config.concurrent_queries = 4;
+        config.interactive_reserve = 0.5;
+        let mut resolver = CoreResolver::new(config);
+        let upstream = mock(ALPHA, 1, Duration::from_millis(200));
+        resolver.add_transport(upstream.clone());
+        let resolver = &resolver;
+        let stats = |priority: QueryPriority| {
+            resolver.priority_stats().into_iter().find(|stats| stats.priority == priority).unwrap()
+        };
+        
+        let bulk = (0..8).map(|_| priority::scope(QueryPriority::Bulk, resolver.query("example.com", RecordType::A, QClass::IN)));
+        let (bulk, interactive_elapsed) = tokio::join!(futures::future::join_all(bulk), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            // 批量查询只占用未保留的2个名额，其余排队
+            assert_eq!(resolver.in_flight_sends(), 2);
+            let start = Instant::now();
+            let interactive = (0..2).map(|_| priority::scope(QueryPriority::Interactive, resolver.query("example.com", RecordType::A, QClass::IN)));
+            for result in futures::future::join_all(interactive).await {
+                result.unwrap();
+            }
+            // 交互式查询到达时批量查询仍只有第一批取得了名额
+            assert_eq!(stats(QueryPriority::Bulk).admitted, 2);
+            start.elapsed()
+        });
+        
+        // 不必等排在前面的6个批量查询（3轮），一次上游往返即完成
+        assert!(interactive_elapsed < Duration::from_millis(350), "{:?}", interactive_elapsed);
+        assert!(bulk.iter().all(Result::is_ok));
+        let (interactive, bulk) = (stats(QueryPriority::Interactive), stats(QueryPriority::Bulk));
+        assert_eq!((interactive.admitted, interactive.rejected), (2, 0));
+        assert!(interactive.max_queued < Duration::from_millis(50));
+        assert_eq!((bulk.admitted, bulk.rejected), (8, 0));
+        assert!(bulk.max_queued >= Duration::from_millis(500), "{:?}", bulk.max_queued);
+        assert_eq!(stats(QueryPriority::Normal).admitted, 0);
+        assert_eq!(upstream.max_in_flight(), 4);
     }
     
-    /// 清空缓存
-    pub fn clear_cache(&self) {
-        if let Some(cache) = &self.cache {
-            cache.clear();
-        }
+    #[tokio::test]
+    async fn test_saturated_max_inflight_sheds_bulk_first() {
+        let resolver = CoreResolver::new(test_config(QueryStrategy::Sequential, false));
+        resolver.register_transport("slow", mock(ALPHA, 1, Duration::from_millis(100))).unwrap();
+        resolver.set_transport_max_inflight("slow", Some(1), QueueBehavior::Wait { timeout: Duration::from_millis(500) }).unwrap();
+        let query = |priority| priority::scope(priority, resolver.query("example.com", RecordType::A, QClass::IN));
+        
+        let (first, bulk, interactive) = tokio::join!(query(QueryPriority::Normal), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let start = Instant::now();
+            (query(QueryPriority::Bulk).await, start.elapsed())
+        }, async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            query(QueryPriority::Interactive).await
+        });
+        
+        first.unwrap();
+        interactive.unwrap();
+        // 上游已满时批量查询不等待，立即跳过该上游
+        let (bulk, elapsed) = bulk;
+        assert!(bulk.is_err());
+        assert!(elapsed < Duration::from_millis(50), "{:?}", elapsed);
+        assert_eq!(resolver.transport_saturations("slow"), 1);
+        let shed: Vec<u64> = resolver.priority_stats().iter().map(|stats| stats.shed).collect();
+        assert_eq!(shed, [0, 0, 1]);
+    }
+    
+    #[tokio::test]
+    async fn test_record_types_restrict_transport_selection() {
+        let mut resolver = CoreResolver::new(test_config(QueryStrategy::Sequential, false));
+        let alpha = mock(ALPHA, 1, Duration::ZERO);
+        let beta = mock(BETA, 1, Duration::ZERO);
+        resolver.add_named_transport("alpha", alpha.clone());
+        resolver.add_named_transport("beta", beta.clone());
+        
+        // 只服务AAAA的alpha不参与A查询
+        resolver.set_transport_record_types("alpha", Some(vec![RecordType::AAAA])).unwrap();
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        assert_eq!((alpha.call_count(), beta.call_count()), (0, 1));
+        
+        resolver.set_transport_record_types("alpha", None).unwrap();
+        resolver.query("example.com", RecordType::A, QClass::IN).await.unwrap();
+        assert_eq!((alpha.call_count(), beta.call_count()), (1, 1));
+        
+        // 没有上游服务的记录类型没有可用上游
+        resolver.set_transport_record_types("alpha", Some(vec![RecordType::AAAA])).unwrap();
+        resolver.set_transport_record_types("beta", Some(vec![RecordType::AAAA])).unwrap();
+        assert!(resolver.query("example.com", RecordType::A, QClass::IN).await.is_err());
+        assert_eq!((alpha.call_count(), beta.call_count()), (1, 1));
+        assert!(resolver.set_transport_record_types("beta", Some(Vec::new())).is_err());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,212 @@
+//! 离线模式
+//!
+//! 离线期间查询只由缓存应答：过期的条目照常返回，未缓存的名称直接以 [`DnsError::Offline`](crate::DnsError::Offline)
+//! 失败，不向上游发送任何查询。离线期间按过期答案返回过的条目会被记下，恢复在线后由后台按限速逐个刷新。
+
+use crate::types::Query;
+use crate::Request;
+use super::cache::CacheKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::time::Instant;
+
+/// 待刷新条目数的上限，超过后不再记录新的条目
+const MAX_PENDING_REVALIDATIONS: usize = 4096;
+
+/// 进入离线模式的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OfflineReason {
+    /// 调用方通过 `set_offline(true)` 切换
+    Manual,
+    /// 所有上游被判定为不可用的时长超过了自动离线阈值
+    Auto,
+}
+
+/// 离线模式的状态与计数
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OfflineStats {
+    /// 当前是否离线
+    pub offline: bool,
+    /// 当前离线的原因，在线时为 `None`
+    pub reason: Option<OfflineReason>,
+    /// 进入或离开离线模式的次数
+    pub transitions: u64,
+    /// 离线期间以过期答案应答的次数
+    pub stale_answers: u64,
+    /// 离线期间因没有缓存而失败的查询数
+    pub refused: u64,
+    /// 恢复在线后尚未刷新的条目数
+    pub pending_revalidation: usize,
+    /// 恢复在线后已刷新的条目数（包括刷新失败的）
+    pub revalidated: u64,
+}
+
+/// 离线状态，解析器的克隆体共用
+#[derive(Debug, Default)]
+pub(crate) struct OfflineState {
+    active: AtomicBool,
+    reason: Mutex<Option<OfflineReason>>,
+    /// 开始观察到所有上游都不可用的时间
+    unreachable_since: Mutex<Option<Instant>>,
+    /// 离线期间按过期答案返回过的条目，恢复在线后刷新
+    served: Mutex<HashMap<CacheKey, Request>>,
+    pending: AtomicU64,
+    transitions: AtomicU64,
+    stale_answers: AtomicU64,
+    refused: AtomicU64,
+    revalidated: AtomicU64,
+}
+
+impl OfflineState {
+    /// 是否处于离线模式
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// 当前离线的原因
+    pub(crate) fn reason(&self) -> Option<OfflineReason> {
+        *self.reason.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 进入离线模式，已经离线时返回 `false`（原因不变）
+    pub(crate) fn enter(&self, reason: OfflineReason) -> bool {
+        let mut current = self.reason.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if current.is_some() {
+            return false;
+        }
+        *current = Some(reason);
+        self.active.store(true, Ordering::Release);
+        self.transitions.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// 离开离线模式，返回离线期间按过期答案返回过的条目；本来就在线时返回 `None`
+    pub(crate) fn leave(&self) -> Option<Vec<Request>> {
+        let mut current = self.reason.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        current.take()?;
+        self.active.store(false, Ordering::Release);
+        self.transitions.fetch_add(1, Ordering::Relaxed);
+        *self.unreachable_since.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+
+        let served: Vec<Request> = self.served.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .drain()
+            .map(|(_, request)| request)
+            .collect();
+        self.pending.fetch_add(served.len() as u64, Ordering::Relaxed);
+        Some(served)
+    }
+
+    /// 记录一次上游可达性观察，所有上游不可用持续超过 `after` 时返回 `true`
+    pub(crate) fn unreachable_for(&self, reachable: bool, now: Instant, after: Duration) -> bool {
+        let mut since = self.unreachable_since.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if reachable {
+            *since = None;
+            return false;
+        }
+        let since = *since.get_or_insert(now);
+        now.saturating_duration_since(since) >= after
+    }
+
+    /// 记下离线期间以过期答案应答的条目
+    pub(crate) fn record_stale_answer(&self, query: &Query, request: &Request) {
+        self.stale_answers.fetch_add(1, Ordering::Relaxed);
+        let key = CacheKey::for_client(query, request.client_address.as_ref());
+        let mut served = self.served.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if served.len() < MAX_PENDING_REVALIDATIONS || served.contains_key(&key) {
+            served.insert(key, request.clone());
+        }
+    }
+
+    /// 记下一次因没有缓存而失败的查询
+    pub(crate) fn record_refused(&self) {
+        self.refused.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记下一个条目已刷新
+    pub(crate) fn record_revalidated(&self) {
+        self.revalidated.fetch_add(1, Ordering::Relaxed);
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// 刷新中途再次离线，剩余的条目留到下次恢复在线时刷新
+    pub(crate) fn defer_revalidation(&self, requests: Vec<Request>) {
+        self.pending.fetch_sub(requests.len() as u64, Ordering::Relaxed);
+        let mut served = self.served.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for request in requests {
+            let key = CacheKey::for_client(&request.query, request.client_address.as_ref());
+            served.entry(key).or_insert(request);
+        }
+    }
+
+    /// 状态与计数的快照
+    pub(crate) fn stats(&self) -> OfflineStats {
+        let deferred = self.served.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).len();
+        OfflineStats {
+            offline: self.is_active(),
+            reason: self.reason(),
+            transitions: self.transitions.load(Ordering::Relaxed),
+            stale_answers: self.stale_answers.load(Ordering::Relaxed),
+            refused: self.refused.load(Ordering::Relaxed),
+            pending_revalidation: deferred + self.pending.load(Ordering::Relaxed) as usize,
+            revalidated: self.revalidated.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::priority::QueryPriority;
+    use crate::types::{Flags, QClass, RecordType};
+
+    fn request(name: &str) -> Request {
+        Request {
+            id: 0,
+            flags: Flags::default(),
+            query: Query { name: name.to_string(), qtype: RecordType::A, qclass: QClass::IN },
+            client_address: None,
+            enable_edns: false,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: QueryPriority::Normal,
+        }
+    }
+
+    #[test]
+    fn test_transitions_hand_back_served_entries_once() {
+        let state = OfflineState::default();
+        assert!(state.leave().is_none());
+        assert!(state.enter(OfflineReason::Manual));
+        assert!(!state.enter(OfflineReason::Auto));
+        assert_eq!(state.reason(), Some(OfflineReason::Manual));
+
+        let served = request("a.test");
+        state.record_stale_answer(&served.query, &served);
+        state.record_stale_answer(&served.query, &served);
+
+        let pending = state.leave().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(state.stats().pending_revalidation, 1);
+        state.record_revalidated();
+
+        let stats = state.stats();
+        assert!(!stats.offline);
+        assert_eq!((stats.transitions, stats.stale_answers, stats.revalidated, stats.pending_revalidation), (2, 2, 1, 0));
+    }
+
+    #[test]
+    fn test_unreachable_timer_resets_when_an_upstream_recovers() {
+        let state = OfflineState::default();
+        let start = Instant::now();
+        let after = Duration::from_secs(30);
+        assert!(!state.unreachable_for(false, start, after));
+        assert!(!state.unreachable_for(false, start + Duration::from_secs(29), after));
+        assert!(!state.unreachable_for(true, start + Duration::from_secs(29), after));
+        assert!(!state.unreachable_for(false, start + Duration::from_secs(31), after));
+        assert!(state.unreachable_for(false, start + Duration::from_secs(61), after));
+    }
+}
@@ -0,0 +1,322 @@
+//! 查询优先级与分级准入
+//!
+//! 同一个解析器既服务交互式的客户端查询，也服务大批量的后台查询时，后者很容易占满
+//! `concurrent_queries` 名额，交互式查询只能排在它们后面。每个查询带一个 [`QueryPriority`]，
+//! 上游发送按优先级准入：交互式查询独占一部分名额（`interactive_reserve`），其余优先级只能使用剩下的名额；
+//! 名额用完时空出的名额先给等待中的交互式查询，再给普通查询，批量查询最后。
+//! 上游的 `max_inflight` 也按优先级准入，已满时最先放弃的是批量查询。
+
+use crate::error::{DnsError, Result};
+#[cfg(feature = "serde-api")]
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// 查询的优先级
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub enum QueryPriority {
+    /// 交互式查询：可以使用为它保留的名额，名额用完时最先得到空出的名额
+    Interactive,
+    /// 普通查询（默认）
+    #[default]
+    Normal,
+    /// 批量查询：只使用其他查询剩下的名额，名额用完时最后得到空出的名额，
+    /// 上游的 `max_inflight` 已满时不等待、直接跳过该上游
+    Bulk,
+}
+
+impl QueryPriority {
+    /// 全部优先级，从高到低
+    pub const ALL: [QueryPriority; 3] = [QueryPriority::Interactive, QueryPriority::Normal, QueryPriority::Bulk];
+
+    /// 小写名称
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueryPriority::Interactive => "interactive",
+            QueryPriority::Normal => "normal",
+            QueryPriority::Bulk => "bulk",
+        }
+    }
+
+    fn rank(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for QueryPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+tokio::task_local! {
+    static CURRENT: QueryPriority;
+}
+
+/// 在 `priority` 下执行 `future`：其中构造的上游请求都带这个优先级
+pub async fn scope<F: Future>(priority: QueryPriority, future: F) -> F::Output {
+    CURRENT.scope(priority, future).await
+}
+
+/// 当前任务的查询优先级，不在 [`scope`] 中时为 [`QueryPriority::Normal`]
+pub fn current() -> QueryPriority {
+    CURRENT.try_with(|priority| *priority).unwrap_or_default()
+}
+
+/// 检查交互式查询保留的名额比例，须在 `[0, 1)` 内
+pub(crate) fn check_reserve(fraction: f64) -> Result<()> {
+    if !(0.0..1.0).contains(&fraction) {
+        return Err(DnsError::InvalidConfig(format!(
+            "Interactive reserve must be at least 0 and less than 1, got {}", fraction
+        )));
+    }
+    Ok(())
+}
+
+/// 一个优先级的准入统计
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriorityStats {
+    /// 优先级
+    pub priority: QueryPriority,
+    /// 取得并发名额的上游发送数
+    pub admitted: u64,
+    /// 等待并发名额超时、返回 [`DnsError::Busy`] 的发送数
+    pub rejected: u64,
+    /// 因上游的 `max_inflight` 已满而跳过该上游的次数
+    pub shed: u64,
+    /// 取得并发名额前的累计等待时间
+    pub queued_time: Duration,
+    /// 单次发送等待并发名额的最长时间
+    pub max_queued: Duration,
+}
+
+impl PriorityStats {
+    /// 每次取得名额前的平均等待时间
+    pub fn avg_queued(&self) -> Duration {
+        match self.admitted {
+            0 => Duration::ZERO,
+            admitted => Duration::from_micros(self.queued_time.as_micros() as u64 / admitted),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ClassCounters {
+    admitted: AtomicU64,
+    rejected: AtomicU64,
+    shed: AtomicU64,
+    queued_micros: AtomicU64,
+    max_queued_micros: AtomicU64,
+}
+
+/// 按优先级分开的准入计数
+#[derive(Debug, Default)]
+pub(crate) struct PriorityCounters {
+    classes: [ClassCounters; 3],
+}
+
+impl PriorityCounters {
+    /// 记录一次等待并发名额的结果
+    pub(crate) fn record_wait(&self, priority: QueryPriority, admitted: bool, waited: Duration) {
+        let class = &self.classes[priority.rank()];
+        if admitted {
+            let micros = waited.as_micros() as u64;
+            class.admitted.fetch_add(1, Ordering::Relaxed);
+            class.queued_micros.fetch_add(micros, Ordering::Relaxed);
+            class.max_queued_micros.fetch_max(micros, Ordering::Relaxed);
+        } else {
+            class.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 记录一次因上游的 `max_inflight` 已满而跳过该上游
+    pub(crate) fn record_shed(&self, priority: QueryPriority) {
+        self.classes[priority.rank()].shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 各优先级的统计，从高到低
+    pub(crate) fn snapshot(&self) -> Vec<PriorityStats> {
+        QueryPriority::ALL.iter().map(|&priority| {
+            let class = &self.classes[priority.rank()];
+            PriorityStats {
+                priority,
+                admitted: class.admitted.load(Ordering::Relaxed),
+                rejected: class.rejected.load(Ordering::Relaxed),
+                shed: class.shed.load(Ordering::Relaxed),
+                queued_time: Duration::from_micros(class.queued_micros.load(Ordering::Relaxed)),
+                max_queued: Duration::from_micros(class.max_queued_micros.load(Ordering::Relaxed)),
+            }
+        }).collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct GateState {
+    in_use: usize,
+    /// 各优先级正在等待的数量
+    waiting: [usize; 3],
+}
+
+/// 按优先级准入的名额
+///
+/// 交互式查询可以使用全部 `limit` 个名额，其余优先级只能使用 `limit - reserved` 个；
+/// 有更高优先级的查询在等待时，低优先级的查询不会取得名额。同一优先级的等待者之间不保证先后
+#[derive(Debug)]
+pub(crate) struct PriorityGate {
+    limit: usize,
+    reserved: usize,
+    state: Mutex<GateState>,
+    released: Notify,
+}
+
+impl PriorityGate {
+    /// `reserve` 为交互式查询保留的名额比例，向下取整，至少给其他优先级留一个名额
+    pub(crate) fn new(limit: usize, reserve: f64) -> Self {
+        let limit = limit.max(1);
+        let reserved = ((limit as f64 * reserve).floor() as usize).min(limit - 1);
+        Self { limit, reserved, state: Mutex::new(GateState::default()), released: Notify::new() }
+    }
+
+    /// 名额总数
+    pub(crate) fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// 当前占用的名额数
+    pub(crate) fn in_use(&self) -> usize {
+        self.lock().in_use
+    }
+
+    /// 不等待地取得名额，已满或有更高优先级的查询在等待时返回 `None`
+    pub(crate) fn try_acquire(&self, priority: QueryPriority) -> Option<GatePermit<'_>> {
+        let mut state = self.lock();
+        let cap = match priority {
+            QueryPriority::Interactive => self.limit,
+            _ => self.limit - self.reserved,
+        };
+        let preempted = state.waiting[..priority.rank()].iter().any(|&waiting| waiting > 0);
+        if state.in_use >= cap || preempted {
+            return None;
+        }
+        state.in_use += 1;
+        Some(GatePermit { gate: self })
+    }
+
+    /// 取得名额，没有时等待；取消时放弃等待
+    pub(crate) async fn acquire(&self, priority: QueryPriority) -> GatePermit<'_> {
+        if let Some(permit) = self.try_acquire(priority) {
+            return permit;
+        }
+        let _waiting = Waiting::register(self, priority);
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            // 先登记通知再检查，检查与等待之间释放的名额不会漏掉
+            released.as_mut().enable();
+            if let Some(permit) = self.try_acquire(priority) {
+                return permit;
+            }
+            released.await;
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, GateState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// 占用的名额，释放时唤醒等待者
+#[derive(Debug)]
+pub(crate) struct GatePermit<'a> {
+    gate: &'a PriorityGate,
+}
+
+impl Drop for GatePermit<'_> {
+    fn drop(&mut self) {
+        self.gate.lock().in_use -= 1;
+        self.gate.released.notify_waiters();
+    }
+}
+
+/// 等待中的登记，结束等待（包括被取消）时撤销，让低优先级的等待者重新检查
+struct Waiting<'a> {
+    gate: &'a PriorityGate,
+    priority: QueryPriority,
+}
+
+impl<'a> Waiting<'a> {
+    fn register(gate: &'a PriorityGate, priority: QueryPriority) -> Self {
+        gate.lock().waiting[priority.rank()] += 1;
+        Self { gate, priority }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.gate.lock().waiting[self.priority.rank()] -= 1;
+        self.gate.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_reserved_share_is_only_for_interactive() {
+        let gate = PriorityGate::new(10, 0.3);
+        let bulk: Vec<_> = (0..7).map(|_| gate.try_acquire(QueryPriority::Bulk).unwrap()).collect();
+        assert!(gate.try_acquire(QueryPriority::Bulk).is_none());
+        assert!(gate.try_acquire(QueryPriority::Normal).is_none());
+        let interactive: Vec<_> = (0..3).map(|_| gate.try_acquire(QueryPriority::Interactive).unwrap()).collect();
+        assert!(gate.try_acquire(QueryPriority::Interactive).is_none());
+        assert_eq!(gate.in_use(), 10);
+        drop((bulk, interactive));
+        assert_eq!(gate.in_use(), 0);
+
+        // 保留比例再大也给其他优先级留一个名额
+        let gate = PriorityGate::new(2, 0.9);
+        assert!(gate.try_acquire(QueryPriority::Bulk).is_some());
+        assert!(check_reserve(0.0).is_ok());
+        assert!(check_reserve(1.0).is_err());
+        assert!(check_reserve(f64::NAN).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_released_slot_goes_to_the_highest_waiting_priority() {
+        let gate = Arc::new(PriorityGate::new(1, 0.0));
+        let held = gate.try_acquire(QueryPriority::Normal).unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let mut waiters = Vec::new();
+        for priority in [QueryPriority::Bulk, QueryPriority::Normal, QueryPriority::Interactive] {
+            let (gate, order) = (gate.clone(), order.clone());
+            waiters.push(tokio::spawn(async move {
+                let _permit = gate.acquire(priority).await;
+                order.lock().unwrap().push(priority);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }));
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // 有更高优先级在等待时，低优先级不能插队
+        assert!(gate.try_acquire(QueryPriority::Normal).is_none());
+        drop(held);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), QueryPriority::ALL);
+
+        // 被取消的高优先级等待者不会挡住低优先级
+        let held = gate.try_acquire(QueryPriority::Bulk).unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(5), gate.acquire(QueryPriority::Interactive)).await.is_err());
+        drop(held);
+        assert!(gate.try_acquire(QueryPriority::Bulk).is_some());
+    }
+}
@@ -0,0 +1,266 @@
+//! 加密上游握手失败隔离
+//!
+//! 证书过期、证书与固定的公钥不符这类握手失败不会自行好转，每个选中该上游的查询都要先付出一次完整的握手
+//! 再故障转移，而上游监控要等失败累积才会反应。开启隔离后，加密上游（DoT/DoH）第一次出现这类失败即被隔离：
+//! 隔离期内查询策略跳过它，不建立任何连接。隔离期满后的第一个查询作为重新接纳的探测，
+//! 仍然失败时隔离期加倍（不超过上限），成功则立即解除。握手超时可能只是网络抖动，不触发隔离。
+
+use super::clock::Clock;
+use crate::error::{DnsError, Result, TlsErrorKind};
+use crate::{dns_info, dns_warn};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use crate::time::Instant;
+
+/// 握手失败隔离的时长配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsQuarantineConfig {
+    /// 第一次握手失败后的隔离时长
+    pub initial: Duration,
+    /// 重新接纳的探测反复失败时，隔离时长加倍的上限
+    pub max: Duration,
+}
+
+impl Default for TlsQuarantineConfig {
+    fn default() -> Self {
+        Self { initial: Duration::from_secs(30), max: Duration::from_secs(600) }
+    }
+}
+
+impl TlsQuarantineConfig {
+    /// 检查配置，初始时长为零或上限小于初始时长时返回 [`DnsError::InvalidConfig`]
+    pub fn validate(&self) -> Result<()> {
+        if self.initial.is_zero() {
+            return Err(DnsError::InvalidConfig("TLS quarantine period cannot be zero".to_string()));
+        }
+        if self.max < self.initial {
+            return Err(DnsError::InvalidConfig(format!(
+                "TLS quarantine cap ({:?}) must not be shorter than the initial period ({:?})",
+                self.max, self.initial
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 上游的隔离状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantineStatus {
+    /// 触发隔离的失败类型（最近一次）
+    pub kind: TlsErrorKind,
+    /// 当前的隔离时长
+    pub period: Duration,
+    /// 距下一次重新接纳探测的时长，为零表示下一个选中该上游的查询即为探测
+    pub next_probe_in: Duration,
+    /// 隔离以来的握手失败次数（包括失败的探测）
+    pub failures: u32,
+    /// 探测查询是否正在进行
+    pub probing: bool,
+}
+
+impl QuarantineStatus {
+    /// 转为JSON对象，时长为毫秒
+    pub fn to_json_value(&self) -> serde_json::Value {
+        serde_json::json!({
+            "kind": format!("{:?}", self.kind),
+            "period_ms": self.period.as_millis() as u64,
+            "next_probe_in_ms": self.next_probe_in.as_millis() as u64,
+            "failures": self.failures,
+            "probing": self.probing,
+        })
+    }
+}
+
+/// 重新接纳探测的登记，探测的发送被取消（例如同时查询的其他上游先给出了答案）时释放，
+/// 下一个选中该上游的查询重新作为探测
+#[derive(Debug)]
+pub(crate) struct ProbeClaim<'a> {
+    quarantine: &'a HandshakeQuarantine,
+    finished: bool,
+}
+
+impl Drop for ProbeClaim<'_> {
+    fn drop(&mut self) {
+        if !self.finished
+            && let Some(quarantine) = self.quarantine.lock().as_mut()
+        {
+            quarantine.probing = false;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Quarantine {
+    kind: TlsErrorKind,
+    period: Duration,
+    until: Instant,
+    failures: u32,
+    probing: bool,
+}
+
+/// 单个加密上游的隔离状态，传输条目的克隆共用
+#[derive(Debug)]
+pub(crate) struct HandshakeQuarantine {
+    config: TlsQuarantineConfig,
+    clock: Arc<dyn Clock>,
+    state: Mutex<Option<Quarantine>>,
+}
+
+impl HandshakeQuarantine {
+    pub(crate) fn new(config: TlsQuarantineConfig, clock: Arc<dyn Clock>) -> Self {
+        Self { config, clock, state: Mutex::new(None) }
+    }
+
+    /// 发送前调用：没有隔离时为 `Ok(None)`；隔离期满且没有探测在进行时把本次发送登记为探测，返回登记；
+    /// 否则返回距下一次探测的时长（探测进行中时为零）
+    pub(crate) fn admit(&self) -> std::result::Result<Option<ProbeClaim<'_>>, Duration> {
+        let now = self.clock.now_instant();
+        let mut state = self.lock();
+        match state.as_mut() {
+            None => Ok(None),
+            Some(quarantine) if quarantine.probing => Err(Duration::ZERO),
+            Some(quarantine) if now < quarantine.until => Err(quarantine.until - now),
+            Some(quarantine) => {
+                quarantine.probing = true;
+                Ok(Some(ProbeClaim { quarantine: self, finished: false }))
+            }
+        }
+    }
+
+    /// 查询策略是否应跳过该上游：隔离期未满，或探测正在进行
+    pub(crate) fn blocks(&self) -> bool {
+        let now = self.clock.now_instant();
+        self.lock().as_ref().is_some_and(|quarantine| quarantine.probing || now < quarantine.until)
+    }
+
+    /// 记录一次发送结果，`claim` 为本次发送的探测登记
+    ///
+    /// 成功时立即解除隔离；第一次握手失败时开始隔离；探测失败（任何错误）时隔离时长加倍，不超过上限。
+    /// 隔离开始前已经发出的查询随后失败不延长隔离
+    pub(crate) fn observe<T>(&self, name: &str, result: &Result<T>, claim: Option<ProbeClaim<'_>>) {
+        let probe = claim.is_some_and(|mut claim| {
+            claim.finished = true;
+            true
+        });
+        let now = self.clock.now_instant();
+        let mut state = self.lock();
+        match (result, state.as_mut()) {
+            (Ok(_), Some(quarantine)) => {
+                dns_info!("🔐 上游 {} 握手恢复正常，解除隔离（此前失败 {} 次）", name, quarantine.failures);
+                *state = None;
+            }
+            (Err(e), None) => {
+                if let Some(kind) = handshake_failure(e) {
+                    dns_warn!("⛔ 上游 {} 握手失败（{:?}），隔离 {:?}", name, kind, self.config.initial);
+                    *state = Some(Quarantine {
+                        kind,
+                        period: self.config.initial,
+                        until: now + self.config.initial,
+                        failures: 1,
+                        probing: false,
+                    });
+                }
+            }
+            (Err(e), Some(quarantine)) if probe => {
+                quarantine.period = quarantine.period.saturating_mul(2).min(self.config.max);
+                quarantine.until = now + quarantine.period;
+                quarantine.failures += 1;
+                quarantine.probing = false;
+                if let Some(kind) = handshake_failure(e) {
+                    quarantine.kind = kind;
+                }
+                dns_warn!("⛔ 上游 {} 重新接纳探测失败: {}，隔离延长至 {:?}", name, e, quarantine.period);
+            }
+            _ => {}
+        }
+    }
+
+    /// 手动解除隔离（例如运维刚更换了证书），返回此前是否处于隔离
+    pub(crate) fn clear(&self) -> bool {
+        self.lock().take().is_some()
+    }
+
+    /// 当前的隔离状态，没有隔离时为 `None`
+    pub(crate) fn status(&self) -> Option<QuarantineStatus> {
+        let now = self.clock.now_instant();
+        self.lock().as_ref().map(|quarantine| QuarantineStatus {
+            kind: quarantine.kind,
+            period: quarantine.period,
+            next_probe_in: quarantine.until.saturating_duration_since(now),
+            failures: quarantine.failures,
+            probing: quarantine.probing,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Quarantine>> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// 触发隔离的握手失败类型：证书无效、证书固定不符和其他TLS错误，握手超时除外
+pub(crate) fn handshake_failure(error: &DnsError) -> Option<TlsErrorKind> {
+    match error {
+        DnsError::TlsFailure { kind: TlsErrorKind::HandshakeTimeout, .. } => None,
+        DnsError::TlsFailure { kind, .. } => Some(*kind),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::clock::TestClock;
+
+    fn cert_error() -> Result<()> {
+        Err(DnsError::TlsFailure { kind: TlsErrorKind::CertificateInvalid, upstream: "dot".to_string() })
+    }
+
+    #[test]
+    fn test_probe_failures_double_the_period_up_to_the_cap() {
+        let clock = Arc::new(TestClock::new());
+        let quarantine = HandshakeQuarantine::new(
+            TlsQuarantineConfig { initial: Duration::from_secs(30), max: Duration::from_secs(100) },
+            clock.clone(),
+        );
+
+        // 握手超时和普通网络错误不触发隔离
+        quarantine.observe("dot", &Err::<(), _>(DnsError::TlsFailure { kind: TlsErrorKind::HandshakeTimeout, upstream: "dot".to_string() }), None);
+        quarantine.observe("dot", &Err::<(), _>(DnsError::Timeout { phase: None }), None);
+        assert!(matches!(quarantine.admit(), Ok(None)));
+
+        quarantine.observe("dot", &cert_error(), None);
+        assert_eq!(quarantine.admit().unwrap_err(), Duration::from_secs(30));
+
+        for expected in [60, 100, 100] {
+            clock.advance(quarantine.status().unwrap().next_probe_in);
+            let claim = quarantine.admit().unwrap().expect("period elapsed");
+            // 探测进行中，其他查询仍然跳过
+            assert!(quarantine.blocks());
+            assert_eq!(quarantine.admit().unwrap_err(), Duration::ZERO);
+            quarantine.observe("dot", &cert_error(), Some(claim));
+            assert_eq!(quarantine.status().unwrap().period, Duration::from_secs(expected));
+        }
+        assert_eq!(quarantine.status().unwrap().failures, 4);
+
+        // 隔离开始前发出的查询随后失败不延长隔离
+        quarantine.observe("dot", &cert_error(), None);
+        assert_eq!(quarantine.status().unwrap().next_probe_in, Duration::from_secs(100));
+
+        // 被取消的探测不延长隔离，下一个查询重新作为探测
+        clock.advance(Duration::from_secs(100));
+        drop(quarantine.admit().unwrap().expect("period elapsed"));
+        assert!(!quarantine.blocks());
+        let claim = quarantine.admit().unwrap().expect("cancelled probe released");
+        quarantine.observe("dot", &Ok(()), Some(claim));
+        assert_eq!(quarantine.status(), None);
+        assert!(matches!(quarantine.admit(), Ok(None)));
+    }
+
+    #[test]
+    fn test_config_is_validated() {
+        assert!(TlsQuarantineConfig::default().validate().is_ok());
+        assert!(TlsQuarantineConfig { initial: Duration::ZERO, max: Duration::from_secs(1) }.validate().is_err());
+        assert!(TlsQuarantineConfig { initial: Duration::from_secs(2), max: Duration::from_secs(1) }.validate().is_err());
+    }
+}
@@ -0,0 +1,47 @@
+//! 当前查询自己的EDNS选项
+//!
+//! [`DnsQueryRequest`](crate::builder::types::DnsQueryRequest) 的 `enable_edns` 和 `enable_dnssec`
+//! 在 [`scope`] 中生效：核心解析器构造上游请求时读取，再由各上游的 [`UpstreamFeatures`](crate::types::UpstreamFeatures)
+//! 改写（上游明确配置的开关优先）。不在查询中时沿用解析器的设置。与作用域一样，须在派生任务之前（构造请求时）生效
+
+use std::future::Future;
+
+use crate::types::{ClientAddress, Request};
+
+/// 单个查询的EDNS选项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct QueryEdns {
+    /// 为false时不携带OPT记录，客户端子网和DO位也不发送；为true时沿用解析器的EDNS设置
+    pub edns: bool,
+    /// 是否设置DO位，设置时总会携带OPT记录
+    pub dnssec_ok: bool,
+}
+
+tokio::task_local! {
+    static CURRENT: QueryEdns;
+}
+
+/// 以 `options` 为当前查询的EDNS选项执行 `future`
+pub(crate) async fn scope<F: Future>(options: QueryEdns, future: F) -> F::Output {
+    CURRENT.scope(options, future).await
+}
+
+/// 当前查询的客户端子网：关闭EDNS的查询不发送
+pub(crate) fn client_address(client_address: Option<ClientAddress>) -> Option<ClientAddress> {
+    client_address.filter(|_| CURRENT.try_with(|options| options.edns).unwrap_or(true))
+}
+
+/// 按当前查询的选项改写以解析器设置构造的请求
+pub(crate) fn apply(request: &mut Request) {
+    let _ = CURRENT.try_with(|options| {
+        if !options.edns {
+            request.enable_edns = false;
+            request.dnssec_ok = false;
+            request.client_address = None;
+        }
+        if options.dnssec_ok {
+            request.enable_edns = true;
+            request.dnssec_ok = true;
+        }
+    });
+}
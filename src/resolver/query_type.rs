@@ -0,0 +1,25 @@
+//! 当前查询的记录类型
+//!
+//! 只服务部分记录类型的上游（[`UpstreamSpec::record_types`](crate::upstream_handler::UpstreamSpec::record_types)）
+//! 在选择传输时读取它；不在上游查询中时不做过滤。与作用域一样，须在派生任务之前（选择传输时）生效
+
+use std::future::Future;
+
+use crate::types::RecordType;
+
+tokio::task_local! {
+    static CURRENT: RecordType;
+}
+
+/// 以 `qtype` 为当前记录类型执行 `future`
+pub(crate) async fn scope<F: Future>(qtype: RecordType, future: F) -> F::Output {
+    CURRENT.scope(qtype, future).await
+}
+
+/// 只服务 `record_types` 的上游能否用于当前查询，不在上游查询中时总是可以
+pub(crate) fn allows(record_types: Option<&[RecordType]>) -> bool {
+    match record_types {
+        Some(types) => CURRENT.try_with(|qtype| types.contains(qtype)).unwrap_or(true),
+        None => true,
+    }
+}
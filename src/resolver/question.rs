@@ -0,0 +1,217 @@
+//! 响应问题段的检查策略
+//!
+//! 响应的问题段本应原样回显查询。有些上游（部分转发器、负载均衡器）在应答里省略问题段，
+//! 把它们一律当作错配会让这些上游完全不可用；但不加检查地接受又等于放弃了一道防伪造的校验。
+//! 这里是唯一的判定实现：逐上游的响应清洗、写入缓存前的检查和严格响应校验都调用它，不会各自得出不同结论。
+//! 清洗只拒绝问题段多于一条和不被接受的无问题段响应；回显了别的问题的响应照旧不写入缓存，开启严格校验时作为错误返回。
+//!
+//! - 问题段多于一条的响应按畸形报文拒绝，不论配置如何
+//! - 问题段恰好一条时必须与查询的名称（不区分大小写）、类型、类别一致
+//! - 没有问题段的响应默认拒绝；启用 `accept_questionless_responses` 后，ID与发出的查询一致、
+//!   至少有一条回答记录、且每条回答记录的所有者都是查询名或从查询名出发的CNAME链上的名称时接受，
+//!   清洗时补上查询作为问题段
+
+use crate::error::{DnsError, Result};
+use crate::types::{Query, RecordData, Request, Response};
+use crate::utils::names_equal;
+use std::fmt;
+
+/// 问题段不合格的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuestionError {
+    /// 问题段多于一条
+    Multiple(usize),
+    /// 没有问题段，且策略不接受这样的响应
+    Missing,
+    /// 回显的问题与查询的名称、类型或类别不一致
+    Mismatch,
+    /// 没有问题段的响应ID与发出的查询不一致
+    IdMismatch {
+        /// 发出的查询ID
+        expected: u16,
+        /// 响应中的ID
+        actual: u16,
+    },
+    /// 没有问题段的响应没有回答记录，无法确认是对本查询的答复
+    NoAnswers,
+    /// 没有问题段的响应中有回答记录的所有者不在查询名的CNAME链上
+    UnrelatedAnswer(String),
+}
+
+impl fmt::Display for QuestionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuestionError::Multiple(count) => write!(f, "malformed response with {} questions", count),
+            QuestionError::Missing => write!(f, "question section is missing"),
+            QuestionError::Mismatch => write!(f, "echoed question does not match the query"),
+            QuestionError::IdMismatch { expected, actual } => {
+                write!(f, "questionless response ID {} does not match query ID {}", actual, expected)
+            },
+            QuestionError::NoAnswers => write!(f, "questionless response carries no answers"),
+            QuestionError::UnrelatedAnswer(owner) => {
+                write!(f, "questionless response answers for unrelated name {}", owner)
+            },
+        }
+    }
+}
+
+/// 响应问题段的检查策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QuestionPolicy {
+    /// 是否接受没有问题段、但回答记录明确属于本查询的响应
+    pub accept_questionless: bool,
+}
+
+impl QuestionPolicy {
+    /// 按是否接受无问题段的响应创建策略
+    pub fn new(accept_questionless: bool) -> Self {
+        Self { accept_questionless }
+    }
+
+    /// 检查响应的问题段，不看报文ID
+    ///
+    /// 只接受问题段恰好一条且与查询一致的响应；没有问题段的响应在启用时按回答记录判定
+    pub fn check(&self, query: &Query, response: &Response) -> std::result::Result<(), QuestionError> {
+        match response.queries.as_slice() {
+            [] if self.accept_questionless => answers_belong_to(query, response),
+            [] => Err(QuestionError::Missing),
+            [echoed] => {
+                let matches = echoed.qtype == query.qtype
+                    && echoed.qclass == query.qclass
+                    && names_equal(&echoed.name, &query.name);
+                if matches { Ok(()) } else { Err(QuestionError::Mismatch) }
+            },
+            questions => Err(QuestionError::Multiple(questions.len())),
+        }
+    }
+
+    /// 清洗上游对 `sent` 的响应：问题段多于一条或不被接受的无问题段响应返回协议错误，
+    /// 接受的无问题段响应补上发出的查询作为问题段
+    ///
+    /// 无问题段的响应还要求ID与 `sent` 一致，清洗后的响应在后续检查中和正常回显的响应没有区别。
+    /// 回显了别的问题的响应原样放行，由写入缓存前的检查和严格响应校验处理
+    pub fn sanitize(&self, sent: &Request, mut response: Response) -> Result<Response> {
+        let checked = match self.check(&sent.query, &response) {
+            Err(QuestionError::Mismatch) => Ok(()),
+            Ok(()) if response.queries.is_empty() && response.id != sent.id => {
+                Err(QuestionError::IdMismatch { expected: sent.id, actual: response.id })
+            },
+            checked => checked,
+        };
+        if let Err(e) = checked {
+            return Err(DnsError::Protocol(format!("Response for {} rejected: {}", sent.query.name, e)));
+        }
+        if response.queries.is_empty() {
+            response.queries.push(sent.query.clone());
+        }
+        Ok(response)
+    }
+}
+
+/// 没有问题段时，回答记录的所有者必须都是查询名或其CNAME链上的名称
+fn answers_belong_to(query: &Query, response: &Response) -> std::result::Result<(), QuestionError> {
+    if response.answers.is_empty() {
+        return Err(QuestionError::NoAnswers);
+    }
+    // CNAME记录不一定按链的顺序排列，反复扩展直到没有新名称
+    let mut chain = vec![query.name.as_str()];
+    loop {
+        let before = chain.len();
+        for record in &response.answers {
+            if let RecordData::CNAME(target) = &record.data {
+                let on_chain = chain.iter().any(|name| names_equal(name, &record.name));
+                if on_chain && !chain.iter().any(|name| names_equal(name, target)) {
+                    chain.push(target);
+                }
+            }
+        }
+        if chain.len() == before {
+            break;
+        }
+    }
+    match response.answers.iter().find(|record| !chain.iter().any(|name| names_equal(name, &record.name))) {
+        Some(record) => Err(QuestionError::UnrelatedAnswer(record.name.clone())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::priority::QueryPriority;
+    use crate::dns_response::DnsResponseWrapper;
+    use crate::types::{Flags, QClass, Record, RecordType};
+    use std::net::Ipv4Addr;
+
+    fn request(id: u16, name: &str) -> Request {
+        Request {
+            id,
+            flags: Flags::default(),
+            query: Query { name: name.to_string(), qtype: RecordType::A, qclass: QClass::IN },
+            client_address: None,
+            enable_edns: false,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: QueryPriority::Normal,
+        }
+    }
+
+    fn questionless(id: u16, owner: &str) -> Response {
+        let mut response = DnsResponseWrapper::create_a_response(id, owner, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+        response.queries.clear();
+        response
+    }
+
+    fn cname(owner: &str, target: &str) -> Record {
+        Record {
+            name: owner.to_string(),
+            rtype: RecordType::CNAME,
+            class: QClass::IN,
+            ttl: 300,
+            data: RecordData::CNAME(target.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_questionless_responses_follow_the_flag() {
+        let sent = request(7, "www.example.com");
+        let strict = QuestionPolicy::default();
+        let lenient = QuestionPolicy::new(true);
+
+        assert_eq!(strict.check(&sent.query, &questionless(7, "WWW.example.com.")), Err(QuestionError::Missing));
+        let repaired = lenient.sanitize(&sent, questionless(7, "WWW.example.com.")).unwrap();
+        assert_eq!(repaired.queries, vec![sent.query.clone()]);
+        assert_eq!(strict.check(&sent.query, &repaired), Ok(()));
+
+        assert!(lenient.sanitize(&sent, questionless(8, "www.example.com")).is_err());
+        assert_eq!(
+            lenient.check(&sent.query, &questionless(7, "attacker.example")),
+            Err(QuestionError::UnrelatedAnswer("attacker.example".to_string()))
+        );
+        let mut empty = questionless(7, "www.example.com");
+        empty.answers.clear();
+        assert_eq!(lenient.check(&sent.query, &empty), Err(QuestionError::NoAnswers));
+    }
+
+    #[test]
+    fn test_questionless_answers_may_follow_an_unordered_cname_chain() {
+        let sent = request(7, "www.example.com");
+        let mut response = questionless(7, "edge.cdn.example");
+        response.answers.insert(0, cname("alias.example.net", "edge.cdn.example"));
+        response.answers.push(cname("www.example.com", "alias.example.net"));
+
+        assert_eq!(QuestionPolicy::new(true).check(&sent.query, &response), Ok(()));
+    }
+
+    #[test]
+    fn test_multiple_questions_are_always_malformed() {
+        let sent = request(7, "www.example.com");
+        let mut response = DnsResponseWrapper::create_a_response(7, "www.example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+        response.queries.push(sent.query.clone());
+
+        for policy in [QuestionPolicy::default(), QuestionPolicy::new(true)] {
+            assert_eq!(policy.check(&sent.query, &response), Err(QuestionError::Multiple(2)));
+            assert!(policy.sanitize(&sent, response.clone()).is_err());
+        }
+    }
+}
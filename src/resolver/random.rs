@@ -0,0 +1,129 @@
+//! 随机数来源
+//!
+//! 查询名的大小写随机化（0x20编码）、应答记录的随机轮换、SRV目标的加权随机排序和DNS Cookie的密钥
+//! 都从 [`RandomSource`] 取随机数。默认使用 [`ThreadRandom`]，即由操作系统熵源播种的线程本地CSPRNG；
+//! 测试和排查问题时可以注入 [`SeededRandom`]（需要启用 `test-util` 特性），同一种子、同样的输入和同样的
+//! 查询顺序得到同样的结果。
+//!
+//! 发往上游的报文ID不经过这里，始终取自操作系统熵源（见 [`crate::transport::query_id`]）：
+//! ID是抵御伪造响应的主要随机性，能复现的ID意味着攻击者也能预先算出下一个ID。
+
+use rand::RngCore;
+use std::fmt::Debug;
+use std::sync::Arc;
+#[cfg(any(test, feature = "test-util"))]
+use std::sync::Mutex;
+
+/// 随机数来源
+///
+/// 解析器的所有克隆体和传输共用同一个来源，实现需要自行处理并发调用
+pub trait RandomSource: Send + Sync + Debug {
+    /// 下一个均匀分布的64位随机数
+    fn next_u64(&self) -> u64;
+
+    /// `[0, 1)` 内均匀分布的随机数
+    fn next_f64(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// 用随机字节填满 `dest`
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+/// 线程本地的CSPRNG（`rand::thread_rng()`，由操作系统熵源定期重新播种的ChaCha12）
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadRandom;
+
+impl RandomSource for ThreadRandom {
+    fn next_u64(&self) -> u64 {
+        rand::thread_rng().next_u64()
+    }
+
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        rand::thread_rng().fill_bytes(dest);
+    }
+}
+
+/// 默认随机数来源
+pub fn thread_random() -> Arc<dyn RandomSource> {
+    Arc::new(ThreadRandom)
+}
+
+/// 按种子确定的随机数来源（需要启用 `test-util` 特性）
+///
+/// 所有调用共用一个生成器，并发查询交错取数时结果取决于调度顺序；要复现同一序列，查询需要按相同顺序依次进行
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug)]
+pub struct SeededRandom {
+    rng: Mutex<rand::rngs::StdRng>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl SeededRandom {
+    /// 以 `seed` 创建
+    pub fn new(seed: u64) -> Self {
+        use rand::SeedableRng;
+        Self { rng: Mutex::new(rand::rngs::StdRng::seed_from_u64(seed)) }
+    }
+
+    fn rng(&self) -> std::sync::MutexGuard<'_, rand::rngs::StdRng> {
+        self.rng.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl RandomSource for SeededRandom {
+    fn next_u64(&self) -> u64 {
+        self.rng().next_u64()
+    }
+
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        self.rng().fill_bytes(dest);
+    }
+}
+
+/// 把 [`RandomSource`] 当作 `rand` 的生成器使用，供 `shuffle`、`gen_range` 等调用
+pub struct RandomRng<'a>(pub &'a dyn RandomSource);
+
+impl RngCore for RandomRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        (self.0.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_source_repeats_and_stays_in_range() {
+        let draw = |seed| {
+            let random = SeededRandom::new(seed);
+            let mut bytes = [0u8; 13];
+            random.fill_bytes(&mut bytes);
+            (random.next_u64(), random.next_f64(), bytes)
+        };
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+
+        let random = SeededRandom::new(1);
+        assert!((0..1000).map(|_| random.next_f64()).all(|value| (0.0..1.0).contains(&value)));
+    }
+}
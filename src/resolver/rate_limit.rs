@@ -0,0 +1,116 @@
+//! 上游限流（HTTP 429）的处理
+//!
+//! DoH上游超出配额时返回429，通常带 `Retry-After` 响应头。默认立即故障转移到其他上游，该上游进入退避期；
+//! 只配置了这一个上游（或其他上游也都在退避）时，故障转移无处可去，查询会立即失败。
+//! [`RateLimitedBehavior::WaitAndRetry`] 在这种情况下按上游要求的时长等待（不超过 `max_wait` 和查询的剩余时间），
+//! 再向同一上游重试一次。这样处理的429是配额而不是故障，不计入上游监控的失败，只计入该上游的限流次数。
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use crate::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+/// 上游没有给出 `Retry-After` 时的等待时长（同样不超过 `max_wait`）
+pub const DEFAULT_RETRY_WAIT: Duration = Duration::from_secs(1);
+
+/// 上游限流时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RateLimitedBehavior {
+    /// 立即改用其他上游，没有其他上游时查询失败
+    #[default]
+    FailoverImmediately,
+    /// 没有其他可用上游（或都在退避）时，等待上游要求的时长后向同一上游重试一次
+    WaitAndRetry {
+        /// 等待时长的上限
+        max_wait: Duration,
+    },
+}
+
+/// 一个上游的限流记录
+#[derive(Debug, Default)]
+pub(crate) struct RateLimitState {
+    count: AtomicU64,
+    /// 最近一次限流的时间和上游要求的等待时长
+    last: Mutex<Option<(Instant, Option<Duration>)>>,
+}
+
+impl RateLimitState {
+    /// 记录一次发生在 `at` 的限流
+    pub(crate) fn record(&self, at: Instant, retry_after: Option<Duration>) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        *self.last.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some((at, retry_after));
+    }
+
+    /// 累计的限流次数
+    pub(crate) fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// `since` 之后最近一次限流要求的等待时长，没有要求时为 [`DEFAULT_RETRY_WAIT`]；这段时间内没有被限流时为 `None`
+    pub(crate) fn requested_wait_since(&self, since: Instant) -> Option<Duration> {
+        match *self.last.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+            Some((at, retry_after)) if at >= since => Some(retry_after.unwrap_or(DEFAULT_RETRY_WAIT)),
+            _ => None,
+        }
+    }
+}
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+/// 在截止时间 `deadline` 下执行 `future`：等待限流时不会越过它
+pub(crate) async fn with_deadline<F: Future>(deadline: Instant, future: F) -> F::Output {
+    DEADLINE.scope(deadline, future).await
+}
+
+/// 按 `behavior` 在重试前应等待的时长：不等待重试时为 `None`
+///
+/// 等待时长取上游要求的时长与 `max_wait` 中较小者；等完已经到了当前查询的截止时间时不再等待
+pub(crate) fn wait_before_retry(behavior: RateLimitedBehavior, requested: Duration) -> Option<Duration> {
+    let RateLimitedBehavior::WaitAndRetry { max_wait } = behavior else {
+        return None;
+    };
+    let wait = requested.min(max_wait);
+    match DEADLINE.try_with(|deadline| deadline.saturating_duration_since(Instant::now())) {
+        Ok(remaining) if remaining <= wait => None,
+        _ => Some(wait),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wait_capped_by_max_wait_and_deadline() {
+        let behavior = RateLimitedBehavior::WaitAndRetry { max_wait: Duration::from_secs(2) };
+        assert_eq!(wait_before_retry(RateLimitedBehavior::FailoverImmediately, Duration::from_secs(1)), None);
+        assert_eq!(wait_before_retry(behavior, Duration::from_secs(1)), Some(Duration::from_secs(1)));
+        assert_eq!(wait_before_retry(behavior, Duration::from_secs(30)), Some(Duration::from_secs(2)));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        with_deadline(deadline, async {
+            assert_eq!(wait_before_retry(behavior, Duration::from_secs(1)), Some(Duration::from_secs(1)));
+            // 等待后已没有时间重试
+            let short = RateLimitedBehavior::WaitAndRetry { max_wait: Duration::from_secs(10) };
+            assert_eq!(wait_before_retry(short, Duration::from_secs(8)), None);
+        }).await;
+    }
+
+    #[test]
+    fn test_requested_wait_only_counts_recent_limits() {
+        let state = RateLimitState::default();
+        let before = Instant::now();
+        assert_eq!(state.requested_wait_since(before), None);
+        state.record(Instant::now(), Some(Duration::from_secs(3)));
+        assert_eq!(state.requested_wait_since(before), Some(Duration::from_secs(3)));
+        state.record(Instant::now(), None);
+        assert_eq!(state.requested_wait_since(before), Some(DEFAULT_RETRY_WAIT));
+        assert_eq!(state.requested_wait_since(Instant::now() + Duration::from_secs(1)), None);
+        assert_eq!(state.count(), 2);
+    }
+}
@@ -0,0 +1,142 @@
+//! 应答记录轮换
+//!
+//! 上游往往按固定顺序返回A/AAAA记录，而缓存原样保存响应，所有调用方都会拿到同一个首选地址。
+//! 轮换只作用于返回给调用方的副本：同类型的地址记录在它们原有的位置之间重新排列，
+//! CNAME等其他记录的位置不变，别名链始终排在地址记录之前。
+
+use crate::types::{Query, Record, RecordType, SharedResponse};
+use crate::Response;
+use super::random::{RandomRng, RandomSource};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// 参与轮换的记录类型
+const ROTATED_TYPES: [RecordType; 2] = [RecordType::A, RecordType::AAAA];
+
+/// 轮换计数表的容量，超过后清空重新计数
+const MAX_TRACKED_QUERIES: usize = 4096;
+
+/// 返回A/AAAA记录时的排列方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RotationMode {
+    /// 保持上游给出的顺序
+    #[default]
+    None,
+    /// 每次返回时随机打乱
+    Shuffle,
+    /// 同一查询每返回一次，地址记录轮转一位
+    RoundRobinPerHit,
+}
+
+/// 按 [`RotationMode`] 重排返回给调用方的响应
+#[derive(Debug)]
+pub(crate) struct RecordRotator {
+    mode: RotationMode,
+    /// 每个查询已返回的次数（仅 `RoundRobinPerHit` 使用）
+    hits: Mutex<HashMap<Query, usize>>,
+    /// 随机打乱使用的随机数来源（仅 `Shuffle` 使用）
+    random: Arc<dyn RandomSource>,
+}
+
+impl RecordRotator {
+    /// 创建轮换器
+    pub(crate) fn new(mode: RotationMode, random: Arc<dyn RandomSource>) -> Self {
+        Self { mode, hits: Mutex::new(HashMap::new()), random }
+    }
+
+    /// 重排一份即将返回给调用方的响应
+    pub(crate) fn apply(&self, query: &Query, response: &mut Response) {
+        match self.mode {
+            RotationMode::None => {}
+            RotationMode::Shuffle => {
+                let mut rng = RandomRng(self.random.as_ref());
+                for rtype in ROTATED_TYPES {
+                    reorder(&mut response.answers, rtype, |records| records.shuffle(&mut rng));
+                }
+            }
+            RotationMode::RoundRobinPerHit => {
+                let hit = self.next_hit(query);
+                for rtype in ROTATED_TYPES {
+                    reorder(&mut response.answers, rtype, |records| {
+                        let shift = hit % records.len();
+                        records.rotate_left(shift);
+                    });
+                }
+            }
+        }
+    }
+
+    /// 重排共享的响应：不轮换时原样返回，否则复制一份再重排
+    pub(crate) fn apply_shared(&self, query: &Query, response: SharedResponse) -> SharedResponse {
+        if self.mode == RotationMode::None {
+            return response;
+        }
+        let mut response = response.into_response();
+        self.apply(query, &mut response);
+        response.into()
+    }
+
+    /// 返回该查询此前的返回次数并加一
+    fn next_hit(&self, query: &Query) -> usize {
+        let mut hits = self.hits.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if hits.len() >= MAX_TRACKED_QUERIES && !hits.contains_key(query) {
+            hits.clear();
+        }
+        let count = hits.entry(query.clone()).or_insert(0);
+        let hit = *count;
+        *count = count.wrapping_add(1);
+        hit
+    }
+}
+
+/// 在 `rtype` 类型记录占据的位置之间重排这些记录，其他记录不动
+fn reorder(answers: &mut [Record], rtype: RecordType, arrange: impl FnOnce(&mut Vec<Record>)) {
+    let slots: Vec<usize> = answers.iter()
+        .enumerate()
+        .filter(|(_, record)| record.rtype == rtype)
+        .map(|(index, _)| index)
+        .collect();
+    if slots.len() < 2 {
+        return;
+    }
+    let mut records: Vec<Record> = slots.iter().map(|&index| answers[index].clone()).collect();
+    arrange(&mut records);
+    for (slot, record) in slots.into_iter().zip(records) {
+        answers[slot] = record;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{QClass, RecordData};
+    use std::net::Ipv4Addr;
+
+    fn record(rtype: RecordType, data: RecordData) -> Record {
+        Record { name: "www.example.com".to_string(), rtype, class: QClass::IN, ttl: 300, data }
+    }
+
+    #[test]
+    fn test_cname_keeps_its_position() {
+        let query = Query { name: "www.example.com".to_string(), qtype: RecordType::A, qclass: QClass::IN };
+        let mut answers = vec![record(RecordType::CNAME, RecordData::CNAME("edge.example.net".to_string()))];
+        answers.extend((1..=3).map(|i| record(RecordType::A, RecordData::A(Ipv4Addr::new(192, 0, 2, i)))));
+        let mut response = Response {
+            id: 1,
+            flags: Default::default(),
+            queries: vec![query.clone()],
+            answers,
+            authorities: vec![],
+            additionals: vec![],
+        };
+
+        let rotator = RecordRotator::new(RotationMode::Shuffle, super::super::random::thread_random());
+        for _ in 0..20 {
+            rotator.apply(&query, &mut response);
+            assert_eq!(response.answers[0].rtype, RecordType::CNAME);
+            assert!(response.answers[1..].iter().all(|r| r.rtype == RecordType::A));
+        }
+    }
+}
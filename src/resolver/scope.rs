@@ -0,0 +1,35 @@
+//! 当前查询所在的作用域
+//!
+//! [`ScopedResolver`](crate::builder::ScopedResolver) 的查询在 [`scope`] 中进行。核心解析器选择传输、
+//! 决定查询策略和客户端子网时读取当前作用域，不在作用域中时一切照旧。
+//! 任务局部变量不会带入 `tokio::spawn` 出的任务：作用域的限制须在派生任务之前（选择传输时）生效
+
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::builder::scope::ScopeOverrides;
+use crate::builder::strategy::QueryStrategy;
+
+tokio::task_local! {
+    static CURRENT: Arc<ScopeOverrides>;
+}
+
+/// 在作用域 `overrides` 中执行 `future`
+pub(crate) async fn scope<F: Future>(overrides: Arc<ScopeOverrides>, future: F) -> F::Output {
+    CURRENT.scope(overrides, future).await
+}
+
+/// 当前任务所在的作用域
+pub(crate) fn current() -> Option<Arc<ScopeOverrides>> {
+    CURRENT.try_with(Arc::clone).ok()
+}
+
+/// 当前作用域是否允许使用名为 `name`、层级为 `tier` 的传输，不在作用域中时总是允许
+pub(crate) fn allows(name: &str, tier: u8) -> bool {
+    CURRENT.try_with(|overrides| overrides.allows(name, tier)).unwrap_or(true)
+}
+
+/// 当前作用域改用的查询策略
+pub(crate) fn strategy() -> Option<QueryStrategy> {
+    CURRENT.try_with(|overrides| overrides.strategy).ok().flatten()
+}
@@ -0,0 +1,201 @@
+//! 上游响应时间SLO
+//!
+//! 为上游设置p95响应时间目标后，上游监控任务每个监控间隔按统计窗口内的成功查询计算一次p95。
+//! p95持续 `sustain` 超过阈值才判为违反，回到阈值以内同样要持续 `sustain` 才判为恢复；
+//! 期间只要有一次评估结果相反就重新计时，在阈值附近来回波动不会反复通知
+
+use super::health::{ProbeOutcome, ProbeReason};
+use crate::error::{DnsError, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::time::Duration;
+use crate::time::SystemTime;
+
+/// 上游的p95响应时间目标
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SloConfig {
+    /// p95响应时间上限
+    pub p95_threshold: Duration,
+    /// 统计窗口内至少要有的成功查询数，不足时不做判定
+    pub min_samples: u64,
+    /// 越过阈值（或回到阈值以内）持续多久才通知
+    pub sustain: Duration,
+}
+
+impl SloConfig {
+    /// 创建SLO配置
+    pub fn new(p95_threshold: Duration, min_samples: u64, sustain: Duration) -> Self {
+        Self { p95_threshold, min_samples, sustain }
+    }
+
+    /// 检查配置，阈值为零或样本数为0时返回 [`DnsError::InvalidConfig`]
+    pub fn validate(&self, name: &str) -> Result<()> {
+        if self.p95_threshold.is_zero() {
+            return Err(DnsError::InvalidConfig(format!("SLO p95 threshold for upstream '{}' cannot be zero", name)));
+        }
+        if self.min_samples == 0 {
+            return Err(DnsError::InvalidConfig(format!("SLO min_samples for upstream '{}' must be at least 1", name)));
+        }
+        Ok(())
+    }
+}
+
+/// 上游SLO的当前判定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SloStatus {
+    /// 生效的SLO配置
+    pub config: SloConfig,
+    /// 是否处于违反状态
+    pub breached: bool,
+    /// 最近一次评估得到的p95，样本不足时为None
+    pub observed_p95: Option<Duration>,
+    /// 最近一次评估时统计窗口内的成功查询数
+    pub samples: u64,
+    /// 进入当前状态（违反或达标）的时间，还没有发生过状态变化时为None
+    pub since: Option<SystemTime>,
+    /// 与当前状态相反的评估结果已持续到现在的起点，没有待定的变化时为None
+    pub pending_since: Option<SystemTime>,
+}
+
+impl SloStatus {
+    /// 转为JSON对象：时长为毫秒，时间为Unix秒
+    pub fn to_json_value(&self) -> serde_json::Value {
+        let unix_secs = |at: SystemTime| at.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        serde_json::json!({
+            "breached": self.breached,
+            "p95_threshold_ms": self.config.p95_threshold.as_secs_f64() * 1000.0,
+            "observed_p95_ms": self.observed_p95.map(|p95| p95.as_secs_f64() * 1000.0),
+            "samples": self.samples,
+            "min_samples": self.config.min_samples,
+            "sustain_ms": self.config.sustain.as_secs_f64() * 1000.0,
+            "since": self.since.map(unix_secs),
+            "pending_since": self.pending_since.map(unix_secs),
+        })
+    }
+}
+
+/// 接收解析器通知的观察者
+///
+/// 回调在上游监控任务中同步调用，实现应尽快返回（例如只把通知投递到队列），不要在回调里做阻塞操作
+pub trait QueryObserver: Send + Sync + Debug {
+    /// 上游的p95响应时间持续超过SLO阈值
+    fn on_slo_breach(&self, _upstream: &str, _observed_p95: Duration, _threshold: Duration) {}
+
+    /// 违反SLO的上游的p95响应时间持续回到阈值以内
+    fn on_slo_recovery(&self, _upstream: &str, _observed_p95: Duration, _threshold: Duration) {}
+
+    /// 主动探测了上游，结果已计入其健康统计
+    fn on_upstream_probe(&self, _upstream: &str, _reason: ProbeReason, _outcome: ProbeOutcome) {}
+}
+
+/// 一次评估引起的状态变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SloTransition {
+    /// 进入违反状态
+    Breach { observed_p95: Duration, threshold: Duration },
+    /// 恢复达标
+    Recovery { observed_p95: Duration, threshold: Duration },
+}
+
+/// 一个上游的SLO判定状态
+#[derive(Debug, Clone)]
+pub(crate) struct SloTracker {
+    status: SloStatus,
+}
+
+impl SloTracker {
+    pub(crate) fn new(config: SloConfig) -> Self {
+        Self {
+            status: SloStatus {
+                config,
+                breached: false,
+                observed_p95: None,
+                samples: 0,
+                since: None,
+                pending_since: None,
+            },
+        }
+    }
+
+    pub(crate) fn status(&self) -> &SloStatus {
+        &self.status
+    }
+
+    /// 按统计窗口内成功查询的响应时间（毫秒）评估一次，状态发生变化时返回该变化
+    ///
+    /// 样本不足时不做判定，待定的变化重新计时
+    pub(crate) fn evaluate(&mut self, latencies_ms: &mut [u64], now: SystemTime) -> Option<SloTransition> {
+        let status = &mut self.status;
+        let threshold = status.config.p95_threshold;
+        status.samples = latencies_ms.len() as u64;
+        if status.samples < status.config.min_samples {
+            status.observed_p95 = None;
+            status.pending_since = None;
+            return None;
+        }
+        let observed_p95 = percentile_ms(latencies_ms, 0.95);
+        status.observed_p95 = Some(observed_p95);
+        if (observed_p95 > threshold) == status.breached {
+            status.pending_since = None;
+            return None;
+        }
+        let pending_since = *status.pending_since.get_or_insert(now);
+        if now.duration_since(pending_since).unwrap_or_default() < status.config.sustain {
+            return None;
+        }
+        status.breached = !status.breached;
+        status.since = Some(now);
+        status.pending_since = None;
+        Some(if status.breached {
+            SloTransition::Breach { observed_p95, threshold }
+        } else {
+            SloTransition::Recovery { observed_p95, threshold }
+        })
+    }
+}
+
+/// 样本的分位数（最近秩法），样本不能为空
+fn percentile_ms(samples: &mut [u64], quantile: f64) -> Duration {
+    samples.sort_unstable();
+    let rank = ((samples.len() as f64 * quantile).ceil() as usize).clamp(1, samples.len());
+    Duration::from_millis(samples[rank - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_p95_uses_nearest_rank() {
+        let mut samples: Vec<u64> = (1..=100).rev().collect();
+        assert_eq!(percentile_ms(&mut samples, 0.95), Duration::from_millis(95));
+        assert_eq!(percentile_ms(&mut [7], 0.95), Duration::from_millis(7));
+    }
+
+    #[test]
+    fn test_flapping_restarts_the_sustain_timer() {
+        let config = SloConfig::new(Duration::from_millis(100), 3, Duration::from_secs(60));
+        let mut tracker = SloTracker::new(config);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        // 样本不足时不判定
+        assert_eq!(tracker.evaluate(&mut [500, 500], at(0)), None);
+        assert_eq!(tracker.status().observed_p95, None);
+
+        assert_eq!(tracker.evaluate(&mut [500, 500, 500], at(0)), None);
+        assert_eq!(tracker.status().pending_since, Some(at(0)));
+        // 中途回到阈值以内，重新计时
+        assert_eq!(tracker.evaluate(&mut [50, 50, 50], at(50)), None);
+        assert_eq!(tracker.status().pending_since, None);
+        assert_eq!(tracker.evaluate(&mut [500, 500, 500], at(70)), None);
+        assert_eq!(tracker.evaluate(&mut [500, 500, 500], at(100)), None);
+        assert_eq!(
+            tracker.evaluate(&mut [500, 500, 500], at(130)),
+            Some(SloTransition::Breach { observed_p95: Duration::from_millis(500), threshold: config.p95_threshold })
+        );
+        assert!(tracker.status().breached);
+        assert_eq!(tracker.status().since, Some(at(130)));
+        assert_eq!(tracker.evaluate(&mut [500, 500, 500], at(1_000)), None);
+    }
+}
@@ -0,0 +1,214 @@
+//! 按域名覆盖TTL
+//!
+//! 运维需要固定某些名称的缓存时长：故障切换用的CNAME权威TTL是3600秒，但事故时会被改掉，必须频繁重查；
+//! 另一些名称则可以缓存得比声明的更久。规则按查询的域名匹配，命中时取代全局的 `min_ttl`/`max_ttl` 钳制，
+//! 作用于响应中的全部记录，写入缓存和返回给调用方的TTL相同。
+//!
+//! 模式为完整域名（`failover.corp`），或以 `*.` 开头的后缀通配（`*.failover.corp` 匹配其下的所有子域名，
+//! 不含 `failover.corp` 本身）。完整域名的规则优先，其次是后缀最长的通配规则，同样长时按添加顺序。
+
+use crate::error::{DnsError, Result};
+use crate::types::{Query, Response};
+use crate::utils::{is_subdomain, names_equal, normalize_name};
+use crate::dns_debug;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use super::cache::is_opt;
+
+/// 命中规则的名称如何调整TTL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverrideTtl {
+    /// TTL不超过该值
+    Max(Duration),
+    /// TTL不低于该值
+    Min(Duration),
+    /// TTL固定为该值
+    Exact(Duration),
+}
+
+impl OverrideTtl {
+    fn apply(self, ttl: u32) -> u32 {
+        match self {
+            OverrideTtl::Max(max) => ttl.min(secs(max)),
+            OverrideTtl::Min(min) => ttl.max(secs(min)),
+            OverrideTtl::Exact(exact) => secs(exact),
+        }
+    }
+}
+
+impl fmt::Display for OverrideTtl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverrideTtl::Max(ttl) => write!(f, "max {}s", ttl.as_secs()),
+            OverrideTtl::Min(ttl) => write!(f, "min {}s", ttl.as_secs()),
+            OverrideTtl::Exact(ttl) => write!(f, "exact {}s", ttl.as_secs()),
+        }
+    }
+}
+
+fn secs(ttl: Duration) -> u32 {
+    ttl.as_secs().min(u32::MAX as u64) as u32
+}
+
+/// 一条TTL覆盖规则
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TtlOverrideRule {
+    /// 规范化后的模式：完整域名，或 `*.` 加后缀
+    pub pattern: String,
+    /// TTL调整方式
+    pub ttl: OverrideTtl,
+}
+
+impl TtlOverrideRule {
+    /// 创建规则，`*` 只能作为第一个标签出现
+    pub fn new(pattern: &str, ttl: OverrideTtl) -> Result<Self> {
+        let pattern = normalize_name(pattern.trim());
+        let name = pattern.strip_prefix("*.").unwrap_or(&pattern);
+        if name.is_empty() || name.contains('*') || name.split('.').any(str::is_empty) {
+            return Err(DnsError::InvalidConfig(format!(
+                "Invalid TTL override pattern '{}': expected a domain name or '*.' followed by a domain name", pattern
+            )));
+        }
+        Ok(Self { pattern, ttl })
+    }
+
+    /// 通配规则的后缀，完整域名的规则为 `None`
+    fn suffix(&self) -> Option<&str> {
+        self.pattern.strip_prefix("*.")
+    }
+
+    /// 规则是否适用于该查询域名
+    fn matches(&self, name: &str) -> bool {
+        match self.suffix() {
+            Some(suffix) => is_subdomain(name, suffix),
+            None => names_equal(name, &self.pattern),
+        }
+    }
+}
+
+impl fmt::Display for TtlOverrideRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.pattern, self.ttl)
+    }
+}
+
+/// 一条TTL覆盖规则的命中统计
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TtlOverrideStats {
+    /// 规则描述，如 `*.failover.corp exact 30s`
+    pub rule: String,
+    /// 按该规则调整过TTL的上游响应数
+    pub hits: u64,
+}
+
+/// 按规则覆盖响应的TTL
+#[derive(Debug, Default)]
+pub(crate) struct TtlOverrides {
+    rules: Vec<TtlOverrideRule>,
+    /// 与 `rules` 一一对应的命中次数
+    hits: Vec<AtomicU64>,
+}
+
+impl TtlOverrides {
+    /// 创建规则表
+    pub(crate) fn new(rules: Vec<TtlOverrideRule>) -> Self {
+        let hits = rules.iter().map(|_| AtomicU64::new(0)).collect();
+        Self { rules, hits }
+    }
+
+    /// 适用于该查询域名的规则序号：完整域名优先，其次是后缀最长的通配规则
+    fn rule_for(&self, name: &str) -> Option<usize> {
+        self.rules.iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.matches(name))
+            .min_by_key(|(index, rule)| (rule.suffix().map_or(0, |suffix| usize::MAX - suffix.len()), *index))
+            .map(|(index, _)| index)
+    }
+
+    /// 查询域名命中规则时按规则调整响应中全部记录的TTL并返回 `true`，调用方不再做全局钳制
+    ///
+    /// OPT伪记录的TTL字段是扩展响应码和标志位，不做调整
+    pub(crate) fn apply(&self, query: &Query, response: &mut Response) -> bool {
+        let Some(index) = self.rule_for(&query.name) else {
+            return false;
+        };
+        let rule = &self.rules[index];
+        for record in response.answers.iter_mut()
+            .chain(response.authorities.iter_mut())
+            .chain(response.additionals.iter_mut())
+            .filter(|record| !is_opt(record))
+        {
+            let ttl = rule.ttl.apply(record.ttl);
+            if ttl != record.ttl {
+                dns_debug!("TTL覆盖: {} {:?} {} -> {}（规则 {}）", record.name, record.rtype, record.ttl, ttl, rule);
+                record.ttl = ttl;
+            }
+        }
+        self.hits[index].fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// 全部规则，按添加顺序
+    pub(crate) fn rules(&self) -> &[TtlOverrideRule] {
+        &self.rules
+    }
+
+    /// 各规则的命中统计，按添加顺序
+    pub(crate) fn stats(&self) -> Vec<TtlOverrideStats> {
+        self.rules.iter()
+            .zip(&self.hits)
+            .map(|(rule, hits)| TtlOverrideStats { rule: rule.to_string(), hits: hits.load(Ordering::Relaxed) })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_response::DnsResponseWrapper;
+    use crate::types::{QClass, RecordType};
+    use std::net::Ipv4Addr;
+
+    fn query(name: &str) -> Query {
+        Query { name: name.to_string(), qtype: RecordType::A, qclass: QClass::IN }
+    }
+
+    fn ttl_after(overrides: &TtlOverrides, name: &str, ttl: u32) -> Option<u32> {
+        let mut response = DnsResponseWrapper::create_a_response(1, name, &[Ipv4Addr::new(192, 0, 2, 1)], ttl);
+        overrides.apply(&query(name), &mut response).then(|| response.answers[0].ttl)
+    }
+
+    #[test]
+    fn test_patterns_are_normalized_and_validated() {
+        let rule = TtlOverrideRule::new("*.Failover.Corp.", OverrideTtl::Exact(Duration::from_secs(30))).unwrap();
+        assert_eq!(rule.pattern, "*.failover.corp");
+        assert_eq!(rule.to_string(), "*.failover.corp exact 30s");
+
+        for pattern in ["", "*", "*.", "a.*.corp", "**.corp", "a..corp"] {
+            assert!(TtlOverrideRule::new(pattern, OverrideTtl::Max(Duration::from_secs(1))).is_err(), "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_most_specific_rule_wins_and_counts_hits() {
+        let overrides = TtlOverrides::new(vec![
+            TtlOverrideRule::new("*.corp", OverrideTtl::Min(Duration::from_secs(86400))).unwrap(),
+            TtlOverrideRule::new("*.failover.corp", OverrideTtl::Exact(Duration::from_secs(30))).unwrap(),
+            TtlOverrideRule::new("db.failover.corp", OverrideTtl::Max(Duration::from_secs(5))).unwrap(),
+        ]);
+
+        assert_eq!(ttl_after(&overrides, "WWW.Failover.Corp", 3600), Some(30));
+        assert_eq!(ttl_after(&overrides, "db.failover.corp.", 3600), Some(5));
+        assert_eq!(ttl_after(&overrides, "db.failover.corp", 3), Some(3));
+        // 通配规则不含后缀本身，按标签边界匹配
+        assert_eq!(ttl_after(&overrides, "failover.corp", 3600), Some(86400));
+        assert_eq!(ttl_after(&overrides, "notcorp", 3600), None);
+        assert_eq!(ttl_after(&overrides, "corp", 3600), None);
+
+        let hits: Vec<u64> = overrides.stats().iter().map(|stats| stats.hits).collect();
+        assert_eq!(hits, vec![1, 1, 2]);
+    }
+}
@@ -0,0 +1,563 @@
+//! 区域传送（AXFR/IXFR）客户端
+//!
+//! 面向DNS审计等服务器管理场景：通过独立的TCP连接向允许传送的权威服务器拉取整个区域。
+//! 响应是同一连接上的多条带长度前缀的DNS消息，这里逐条读取、逐条解析，
+//! 不会把整个区域缓冲成一条大消息。
+//!
+//! 传送必须以区域的SOA记录开始，并以序列号相同的SOA记录结束（RFC 5936 / RFC 1995）。
+
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::time::timeout;
+
+use crate::transport::{TcpTransport, Transport, TransportConfig, UdpTransport};
+use crate::types::{Flags, QClass, Query, Record, RecordData, RecordType, Request, ResponseCode};
+use crate::resolver::priority::QueryPriority;
+use crate::builder::lookup::normalize_host;
+use crate::utils::parse_server_address;
+use crate::{DnsError, Result};
+use crate::{dns_debug, dns_info, dns_warn};
+
+/// AXFR查询类型
+pub const QTYPE_AXFR: u16 = 252;
+/// IXFR查询类型
+pub const QTYPE_IXFR: u16 = 251;
+
+/// IXFR中的一次版本变更
+#[derive(Debug, Clone, PartialEq)]
+pub struct IxfrDiff {
+    /// 变更前的序列号
+    pub from_serial: u32,
+    /// 变更后的序列号
+    pub to_serial: u32,
+    /// 删除的记录
+    pub deleted: Vec<Record>,
+    /// 新增的记录
+    pub added: Vec<Record>,
+}
+
+/// 区域传送结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZoneTransfer {
+    /// 完整区域：首条为SOA，不含结尾重复的SOA
+    Full(Vec<Record>),
+    /// 增量变更，按版本先后排列
+    Incremental {
+        /// 服务器当前的序列号
+        serial: u32,
+        /// 各版本的变更
+        diffs: Vec<IxfrDiff>,
+    },
+    /// 客户端已是最新版本
+    UpToDate {
+        /// 服务器当前的序列号
+        serial: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransferKind {
+    Axfr,
+    Ixfr(u32),
+}
+
+impl TransferKind {
+    fn name(self) -> &'static str {
+        match self {
+            TransferKind::Axfr => "AXFR",
+            TransferKind::Ixfr(_) => "IXFR",
+        }
+    }
+
+    fn qtype(self) -> RecordType {
+        match self {
+            TransferKind::Axfr => RecordType::Unknown(QTYPE_AXFR),
+            TransferKind::Ixfr(_) => RecordType::Unknown(QTYPE_IXFR),
+        }
+    }
+}
+
+/// 服务器的答复：完成传送，或以非零响应码拒绝
+enum TransferReply {
+    Completed(ZoneTransfer),
+    Rejected(ResponseCode),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// 等待开头的SOA
+    Start,
+    /// IXFR：已收到开头的SOA，尚不确定是增量还是完整传送
+    AfterFirst,
+    /// 完整传送的区域内容
+    Full,
+    /// IXFR：当前版本删除的记录
+    Deleted,
+    /// IXFR：当前版本新增的记录
+    Added,
+    /// 已收到结尾的SOA
+    Done,
+}
+
+/// 按到达顺序逐条消费记录的传送状态机
+struct TransferCollector {
+    zone: String,
+    kind: TransferKind,
+    phase: Phase,
+    serial: u32,
+    records: Vec<Record>,
+    diffs: Vec<IxfrDiff>,
+    current: Option<IxfrDiff>,
+    /// IXFR答复只有一条SOA（客户端已是最新）
+    up_to_date: bool,
+}
+
+fn soa_serial(record: &Record) -> Option<u32> {
+    match (&record.rtype, &record.data) {
+        (RecordType::SOA, RecordData::SOA { serial, .. }) => Some(*serial),
+        _ => None,
+    }
+}
+
+impl TransferCollector {
+    fn new(zone: &str, kind: TransferKind) -> Self {
+        Self {
+            zone: normalize_host(zone),
+            kind,
+            phase: Phase::Start,
+            serial: 0,
+            records: Vec::new(),
+            diffs: Vec::new(),
+            current: None,
+            up_to_date: false,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.phase == Phase::Done
+    }
+
+    fn push(&mut self, record: Record) -> Result<()> {
+        let record_serial = soa_serial(&record);
+        match self.phase {
+            Phase::Start => {
+                let serial = record_serial.ok_or_else(|| {
+                    DnsError::Protocol(format!("{} of {} must begin with an SOA record", self.kind.name(), self.zone))
+                })?;
+                if normalize_host(&record.name) != self.zone {
+                    return Err(DnsError::Protocol(format!(
+                        "{} of {} begins with the SOA of {}", self.kind.name(), self.zone, record.name
+                    )));
+                }
+                self.serial = serial;
+                self.records.push(record);
+                self.phase = match self.kind {
+                    TransferKind::Axfr => Phase::Full,
+                    TransferKind::Ixfr(_) => Phase::AfterFirst,
+                };
+            },
+            Phase::AfterFirst => match record_serial {
+                // 只有SOA的区域做完整传送时，紧跟着的就是结尾SOA
+                Some(serial) if serial == self.serial => self.phase = Phase::Done,
+                Some(serial) => {
+                    self.start_diff(serial);
+                },
+                None => {
+                    // 服务器以完整传送答复IXFR
+                    self.records.push(record);
+                    self.phase = Phase::Full;
+                },
+            },
+            Phase::Full => match record_serial {
+                Some(serial) => {
+                    self.check_closing_serial(serial)?;
+                    self.phase = Phase::Done;
+                },
+                None => self.records.push(record),
+            },
+            Phase::Deleted => {
+                let diff = self.current.as_mut().expect("Deleted阶段必有当前变更");
+                match record_serial {
+                    Some(serial) => {
+                        diff.to_serial = serial;
+                        self.phase = Phase::Added;
+                    },
+                    None => diff.deleted.push(record),
+                }
+            },
+            Phase::Added => match record_serial {
+                Some(serial) => {
+                    let diff = self.current.take().expect("Added阶段必有当前变更");
+                    let reached_latest = diff.to_serial == self.serial;
+                    self.diffs.push(diff);
+                    if reached_latest {
+                        self.check_closing_serial(serial)?;
+                        self.phase = Phase::Done;
+                    } else {
+                        self.start_diff(serial);
+                    }
+                },
+                None => self.current.as_mut().expect("Added阶段必有当前变更").added.push(record),
+            },
+            Phase::Done => {
+                return Err(DnsError::Protocol(format!(
+                    "{} of {} continued after the closing SOA", self.kind.name(), self.zone
+                )));
+            },
+        }
+        Ok(())
+    }
+
+    fn start_diff(&mut self, from_serial: u32) {
+        self.current = Some(IxfrDiff {
+            from_serial,
+            to_serial: from_serial,
+            deleted: Vec::new(),
+            added: Vec::new(),
+        });
+        self.phase = Phase::Deleted;
+    }
+
+    fn check_closing_serial(&self, serial: u32) -> Result<()> {
+        if serial != self.serial {
+            return Err(DnsError::Protocol(format!(
+                "{} of {}: closing SOA serial {} does not match opening serial {}",
+                self.kind.name(), self.zone, serial, self.serial
+            )));
+        }
+        Ok(())
+    }
+
+    /// 一条消息处理完毕
+    fn end_of_message(&mut self) {
+        // 只含一条SOA的IXFR答复表示客户端已是最新版本（RFC 1995 第4节）
+        if self.phase == Phase::AfterFirst {
+            self.up_to_date = true;
+            self.phase = Phase::Done;
+        }
+    }
+
+    fn finish(self) -> Result<ZoneTransfer> {
+        if self.phase != Phase::Done {
+            return Err(DnsError::Protocol(format!(
+                "{} of {} ended before the closing SOA", self.kind.name(), self.zone
+            )));
+        }
+        if self.up_to_date {
+            Ok(ZoneTransfer::UpToDate { serial: self.serial })
+        } else if !self.diffs.is_empty() {
+            Ok(ZoneTransfer::Incremental { serial: self.serial, diffs: self.diffs })
+        } else {
+            Ok(ZoneTransfer::Full(self.records))
+        }
+    }
+}
+
+/// 构造传送请求的线路格式（不含TCP长度前缀）
+fn encode_request(id: u16, zone: &str, kind: TransferKind) -> Result<Vec<u8>> {
+    let request = Request {
+        id,
+        flags: Flags { rd: false, ..Flags::default() },
+        query: Query {
+            name: zone.to_string(),
+            qtype: kind.qtype(),
+            qclass: QClass::IN,
+        },
+        client_address: None,
+        enable_edns: false,
+        dnssec_ok: false,
+        wire_capture_limit: None,
+        priority: QueryPriority::Normal,
+    };
+    let mut buffer = UdpTransport::serialize_request(&request)?;
+
+    if let TransferKind::Ixfr(serial) = kind {
+        // IXFR在权威部分携带客户端当前的SOA，服务器只关心其中的序列号
+        let soa = Record {
+            name: zone.to_string(),
+            rtype: RecordType::SOA,
+            class: QClass::IN,
+            ttl: 0,
+            data: RecordData::SOA {
+                mname: ".".to_string(),
+                rname: ".".to_string(),
+                serial,
+                refresh: 0,
+                retry: 0,
+                expire: 0,
+                minimum: 0,
+            },
+        };
+        buffer[8..10].copy_from_slice(&1u16.to_be_bytes());
+        UdpTransport::encode_record(&soa, &mut buffer)?;
+    }
+
+    Ok(buffer)
+}
+
+/// 在已建立的流上执行一次传送
+async fn transfer_over<S>(stream: &mut S, zone: &str, kind: TransferKind, io_timeout: Duration) -> Result<TransferReply>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let id: u16 = rand::random();
+    let request = encode_request(id, zone, kind)?;
+    let mut framed = Vec::with_capacity(request.len() + 2);
+    framed.extend_from_slice(&(request.len() as u16).to_be_bytes());
+    framed.extend_from_slice(&request);
+
+    match timeout(io_timeout, stream.write_all(&framed)).await {
+        Ok(Ok(())) => {},
+        Ok(Err(e)) => return Err(DnsError::network_io("", "Send failed", &e)),
+        Err(_) => return Err(DnsError::Timeout { phase: None }),
+    }
+
+    let mut collector = TransferCollector::new(zone, kind);
+    let mut messages = 0usize;
+    while !collector.is_done() {
+        let bytes = match timeout(io_timeout, TcpTransport::read_tcp_response(stream)).await {
+            Ok(result) => result?,
+            Err(_) => return Err(DnsError::Timeout { phase: None }),
+        };
+        let response = UdpTransport::deserialize_response(&bytes)?;
+        messages += 1;
+
+        if response.id != id {
+            return Err(DnsError::Protocol(format!(
+                "{} of {}: response id {} does not match request id {}", kind.name(), zone, response.id, id
+            )));
+        }
+        let rcode = response.rcode();
+        if rcode != ResponseCode::NoError {
+            return Ok(TransferReply::Rejected(rcode));
+        }
+
+        for record in response.answers {
+            collector.push(record)?;
+        }
+        collector.end_of_message();
+    }
+
+    dns_debug!("{} of {} completed in {} messages", kind.name(), zone, messages);
+    collector.finish().map(TransferReply::Completed)
+}
+
+/// 建立到服务器的独立TCP连接并执行一次传送
+async fn run_transfer(zone: &str, server: &str, kind: TransferKind, io_timeout: Duration) -> Result<TransferReply> {
+    let (host, port) = parse_server_address(server, 53)?;
+    let transport = TcpTransport::new(TransportConfig {
+        server: host,
+        port,
+        timeout: io_timeout,
+        tcp_fast_open: false,
+        tcp_nodelay: true,
+        pool_size: 1,
+        buffer_size: usize::from(u16::MAX),
+        record_limits: None,
+    });
+    dns_info!("🔁 {} {} <- {}", kind.name(), zone, transport.endpoint());
+    let mut stream = transport.connect().await?;
+    transfer_over(&mut stream, zone, kind, transport.timeout()).await
+}
+
+fn rejected(kind: TransferKind, zone: &str, rcode: ResponseCode) -> DnsError {
+    DnsError::Server(format!("{} of {} rejected with {}", kind.name(), zone, rcode.name()))
+}
+
+/// 执行AXFR，返回区域的全部记录（首条为SOA，不含结尾重复的SOA）
+///
+/// `io_timeout` 作用于连接建立和每一条消息的读写，而不是整个传送过程
+pub async fn axfr(zone: &str, server: &str, io_timeout: Duration) -> Result<Vec<Record>> {
+    match run_transfer(zone, server, TransferKind::Axfr, io_timeout).await? {
+        TransferReply::Completed(ZoneTransfer::Full(records)) => Ok(records),
+        TransferReply::Completed(_) => unreachable!("AXFR只会产生完整传送"),
+        TransferReply::Rejected(rcode) => Err(rejected(TransferKind::Axfr, zone, rcode)),
+    }
+}
+
+/// 执行IXFR，`serial` 为客户端当前持有的区域序列号
+///
+/// 服务器以完整传送答复时返回 [`ZoneTransfer::Full`]；服务器不支持IXFR
+/// （NOTIMP/FORMERR）时改用AXFR重新传送
+pub async fn ixfr(zone: &str, server: &str, serial: u32, io_timeout: Duration) -> Result<ZoneTransfer> {
+    let kind = TransferKind::Ixfr(serial);
+    match run_transfer(zone, server, kind, io_timeout).await? {
+        TransferReply::Completed(transfer) => Ok(transfer),
+        TransferReply::Rejected(rcode @ (ResponseCode::FormatError | ResponseCode::NotImplemented)) => {
+            dns_warn!("{} 不支持IXFR ({})，改用AXFR", server, rcode.name());
+            axfr(zone, server, io_timeout).await.map(ZoneTransfer::Full)
+        },
+        TransferReply::Rejected(rcode) => Err(rejected(kind, zone, rcode)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Response;
+    use std::net::Ipv4Addr;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    const ZONE: &str = "example.com";
+
+    fn soa(serial: u32) -> Record {
+        Record {
+            name: ZONE.to_string(),
+            rtype: RecordType::SOA,
+            class: QClass::IN,
+            ttl: 3600,
+            data: RecordData::SOA {
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            },
+        }
+    }
+
+    fn a(name: &str, last: u8) -> Record {
+        Record {
+            name: name.to_string(),
+            rtype: RecordType::A,
+            class: QClass::IN,
+            ttl: 300,
+            data: RecordData::A(Ipv4Addr::new(192, 0, 2, last)),
+        }
+    }
+
+    fn ns() -> Record {
+        Record {
+            name: ZONE.to_string(),
+            rtype: RecordType::NS,
+            class: QClass::IN,
+            ttl: 3600,
+            data: RecordData::NS("ns1.example.com".to_string()),
+        }
+    }
+
+    /// 接受一个连接，读取请求后按脚本逐条回放消息；返回监听地址和收到的请求
+    async fn scripted_server(
+        script: Vec<(u8, Vec<Record>)>,
+    ) -> (String, tokio::task::JoinHandle<Response>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let bytes = TcpTransport::read_tcp_response(&mut stream).await.unwrap();
+            let request = UdpTransport::deserialize_response(&bytes).unwrap();
+            for (rcode, answers) in script {
+                let response = Response {
+                    id: request.id,
+                    flags: Flags { qr: true, aa: true, rd: false, rcode, ..Flags::default() },
+                    queries: request.queries.clone(),
+                    answers,
+                    authorities: vec![],
+                    additionals: vec![],
+                };
+                let data = UdpTransport::serialize_response(&response).unwrap();
+                stream.write_all(&(data.len() as u16).to_be_bytes()).await.unwrap();
+                stream.write_all(&data).await.unwrap();
+            }
+            // 等待客户端关闭连接
+            let _ = stream.read(&mut [0u8; 1]).await;
+            request
+        });
+        (address, handle)
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[tokio::test]
+    async fn test_axfr_multi_message() {
+        let (server, handle) = scripted_server(vec![
+            (0, vec![soa(2024010101), ns(), a("www.example.com", 1)]),
+            (0, vec![a("mail.example.com", 2)]),
+            (0, vec![a("ftp.example.com", 3), soa(2024010101)]),
+        ]).await;
+
+        let records = axfr(ZONE, &server, TIMEOUT).await.unwrap();
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[0], soa(2024010101));
+        assert_eq!(records[4], a("ftp.example.com", 3));
+
+        let request = handle.await.unwrap();
+        assert_eq!(request.queries[0].qtype, RecordType::Unknown(QTYPE_AXFR));
+    }
+
+    #[tokio::test]
+    async fn test_axfr_rejects_mismatched_closing_soa() {
+        let (server, _handle) = scripted_server(vec![
+            (0, vec![soa(5), a("www.example.com", 1), soa(6)]),
+        ]).await;
+        assert!(matches!(axfr(ZONE, &server, TIMEOUT).await, Err(DnsError::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn test_axfr_refused() {
+        let (server, _handle) = scripted_server(vec![(5, vec![])]).await;
+        assert!(matches!(axfr(ZONE, &server, TIMEOUT).await, Err(DnsError::Server(_))));
+    }
+
+    #[tokio::test]
+    async fn test_ixfr_incremental() {
+        let (server, handle) = scripted_server(vec![
+            (0, vec![soa(3), soa(1), a("old.example.com", 1), soa(2), a("new.example.com", 2)]),
+            (0, vec![soa(2), soa(3), a("newer.example.com", 3), soa(3)]),
+        ]).await;
+
+        let transfer = ixfr(ZONE, &server, 1, TIMEOUT).await.unwrap();
+        let ZoneTransfer::Incremental { serial, diffs } = transfer else {
+            panic!("expected incremental transfer, got {:?}", transfer);
+        };
+        assert_eq!(serial, 3);
+        assert_eq!(diffs.len(), 2);
+        assert_eq!((diffs[0].from_serial, diffs[0].to_serial), (1, 2));
+        assert_eq!(diffs[0].deleted, vec![a("old.example.com", 1)]);
+        assert_eq!(diffs[0].added, vec![a("new.example.com", 2)]);
+        assert!(diffs[1].deleted.is_empty());
+        assert_eq!(diffs[1].added, vec![a("newer.example.com", 3)]);
+
+        // 请求的权威部分携带客户端序列号
+        let request = handle.await.unwrap();
+        assert_eq!(request.queries[0].qtype, RecordType::Unknown(QTYPE_IXFR));
+        assert_eq!(soa_serial(&request.authorities[0]), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_ixfr_answered_with_full_transfer() {
+        let (server, _handle) = scripted_server(vec![
+            (0, vec![soa(9), ns(), a("www.example.com", 1), soa(9)]),
+        ]).await;
+        let transfer = ixfr(ZONE, &server, 1, TIMEOUT).await.unwrap();
+        assert_eq!(transfer, ZoneTransfer::Full(vec![soa(9), ns(), a("www.example.com", 1)]));
+    }
+
+    #[tokio::test]
+    async fn test_ixfr_up_to_date() {
+        let (server, _handle) = scripted_server(vec![(0, vec![soa(7)])]).await;
+        assert_eq!(ixfr(ZONE, &server, 7, TIMEOUT).await.unwrap(), ZoneTransfer::UpToDate { serial: 7 });
+    }
+
+    #[test]
+    fn test_transfer_must_start_with_zone_soa() {
+        let mut collector = TransferCollector::new(ZONE, TransferKind::Axfr);
+        assert!(collector.push(a("www.example.com", 1)).is_err());
+
+        let mut collector = TransferCollector::new("other.org", TransferKind::Axfr);
+        assert!(collector.push(soa(1)).is_err());
+    }
+
+    #[test]
+    fn test_truncated_transfer_is_error() {
+        let mut collector = TransferCollector::new(ZONE, TransferKind::Axfr);
+        collector.push(soa(1)).unwrap();
+        collector.push(ns()).unwrap();
+        collector.end_of_message();
+        assert!(collector.finish().is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! 任务派生和定时器
+//!
+//! 其他平台上就是tokio的同名函数。wasm32-unknown-unknown 上没有tokio运行时（`tokio::spawn` 和tokio的定时器会panic），
+//! 任务经 `wasm_bindgen_futures::spawn_local` 在JS事件循环中执行，定时器使用JS的 `setTimeout`。
+//! 查询路径和可选的后台任务都经过这里；上游监控和缓存清理任务不在异步运行时中时本来就不启动，wasm32上同样不启动
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use tokio::spawn;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use tokio::task::JoinHandle;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use tokio::time::{interval, sleep, timeout};
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) use wasm::{interval, sleep, spawn, timeout, JoinHandle};
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use crate::time::Instant;
+    use futures::channel::oneshot;
+    use futures::future::{AbortHandle, Abortable};
+    use send_wrapper::SendWrapper;
+    use std::fmt;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen_futures::JsFuture;
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(js_name = setTimeout)]
+        fn set_timeout(handler: &js_sys::Function, millis: i32) -> JsValue;
+
+        #[wasm_bindgen(js_name = clearTimeout)]
+        fn clear_timeout(handle: &JsValue);
+    }
+
+    /// 丢弃时取消定时器，被放弃的等待（超时前完成的查询）不会让JS环境一直等着定时器到期
+    struct Timer(JsValue);
+
+    impl Drop for Timer {
+        fn drop(&mut self) {
+            clear_timeout(&self.0);
+        }
+    }
+
+    /// JS对象不是Send，wasm32只有一个线程，包装后可以在要求Send的future中持有
+    fn start_timer(duration: Duration) -> (SendWrapper<JsFuture>, SendWrapper<Timer>) {
+        let millis = duration.as_millis().min(i32::MAX as u128) as i32;
+        let mut handle = JsValue::UNDEFINED;
+        let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+            handle = set_timeout(&resolve, millis);
+        });
+        (SendWrapper::new(JsFuture::from(promise)), SendWrapper::new(Timer(handle)))
+    }
+
+    /// 等待指定时长
+    pub(crate) async fn sleep(duration: Duration) {
+        let (fired, _timer) = start_timer(duration);
+        let _ = fired.await;
+    }
+
+    /// 超时的错误
+    #[derive(Debug)]
+    pub(crate) struct Elapsed;
+
+    impl fmt::Display for Elapsed {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("deadline has elapsed")
+        }
+    }
+
+    /// 在 `duration` 内完成 `future`，超过时放弃
+    pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+        tokio::select! {
+            biased;
+            output = future => Ok(output),
+            _ = sleep(duration) => Err(Elapsed),
+        }
+    }
+
+    /// 固定间隔的定时器，第一次 `tick` 立即返回
+    #[derive(Debug)]
+    pub(crate) struct Interval {
+        period: Duration,
+        next: Instant,
+    }
+
+    pub(crate) fn interval(period: Duration) -> Interval {
+        Interval { period, next: Instant::now() }
+    }
+
+    impl Interval {
+        /// 等到下一个时刻；错过的时刻不补，从现在起重新计算
+        pub(crate) async fn tick(&mut self) {
+            let now = Instant::now();
+            if self.next > now {
+                sleep(self.next - now).await;
+            }
+            self.next = self.next.max(now) + self.period;
+        }
+    }
+
+    /// 派生任务的句柄，丢弃时任务继续执行
+    #[derive(Debug)]
+    pub(crate) struct JoinHandle<T> {
+        output: oneshot::Receiver<T>,
+        abort: AbortHandle,
+    }
+
+    impl<T> JoinHandle<T> {
+        /// 取消任务
+        pub(crate) fn abort(&self) {
+            self.abort.abort();
+        }
+    }
+
+    /// 任务被取消
+    #[derive(Debug)]
+    pub(crate) struct JoinError;
+
+    impl fmt::Display for JoinError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("task was cancelled")
+        }
+    }
+
+    impl<T> Future for JoinHandle<T> {
+        type Output = Result<T, JoinError>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            Pin::new(&mut self.output).poll(cx).map(|output| output.map_err(|_| JoinError))
+        }
+    }
+
+    /// 在JS事件循环中执行 `future`
+    pub(crate) fn spawn<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (sender, output) = oneshot::channel();
+        let (abort, registration) = AbortHandle::new_pair();
+        let task = Abortable::new(future, registration);
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Ok(value) = task.await {
+                let _ = sender.send(value);
+            }
+        });
+        JoinHandle { output, abort }
+    }
+}
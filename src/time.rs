@@ -0,0 +1,10 @@
+//! 时间类型
+//!
+//! wasm32-unknown-unknown 上标准库的 `Instant::now` 和 `SystemTime::now` 会panic，改用取自
+//! `performance.now()` 和 `Date.now()` 的 [web-time](https://docs.rs/web-time)；其他平台上就是标准库的类型。
+//! 本库中表示时刻的公开字段和参数都使用这里的类型
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(target_arch = "wasm32")]
+pub use web_time::{Instant, SystemTime, UNIX_EPOCH};
@@ -0,0 +1,136 @@
+//! DNS Cookie（RFC 7873）
+//!
+//! 客户端Cookie为8字节，由解析器私有的随机密钥对服务器地址和当前周期序号做SipHash得到
+//! （密钥取自解析器的随机数来源，见 [`crate::resolver::random`]）：
+//! 同一周期内发给同一服务器的客户端Cookie不变，周期结束后更换，旧的服务器Cookie随之作废。
+//! 服务器Cookie从响应的COOKIE选项中取得，按服务器地址缓存，之后发往该服务器的查询原样回显。
+
+use crate::resolver::random::{RandomSource, ThreadRandom};
+use crate::types::{edns_option_codes, EdnsOption};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use crate::time::Instant;
+
+/// 客户端Cookie长度
+pub const CLIENT_COOKIE_LEN: usize = 8;
+
+/// 服务器Cookie的最短和最长长度（RFC 7873 第4节）
+const SERVER_COOKIE_LEN: std::ops::RangeInclusive<usize> = 8..=32;
+
+/// 扩展响应码BADCOOKIE：服务器要求先回显它给出的服务器Cookie
+pub const BADCOOKIE: u16 = 23;
+
+/// 某个服务器的Cookie
+#[derive(Debug, Clone)]
+struct ServerCookie {
+    /// 取得服务器Cookie时使用的客户端Cookie
+    client: [u8; CLIENT_COOKIE_LEN],
+    server: Vec<u8>,
+}
+
+/// 一个解析器的Cookie状态，由它的所有UDP传输共用
+#[derive(Debug)]
+pub struct DnsCookieJar {
+    secret: [u8; 16],
+    lifetime: Duration,
+    started: Instant,
+    servers: Mutex<HashMap<String, ServerCookie>>,
+}
+
+impl DnsCookieJar {
+    /// 创建Cookie状态，客户端Cookie每 `lifetime` 更换一次
+    pub fn new(lifetime: Duration) -> Self {
+        Self::with_random_source(lifetime, &ThreadRandom)
+    }
+
+    /// 创建Cookie状态，密钥取自 `random`
+    pub fn with_random_source(lifetime: Duration, random: &dyn RandomSource) -> Self {
+        let mut secret = [0u8; 16];
+        random.fill_bytes(&mut secret);
+        Self {
+            secret,
+            lifetime,
+            started: Instant::now(),
+            servers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn servers(&self) -> MutexGuard<'_, HashMap<String, ServerCookie>> {
+        self.servers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 当前发给 `server` 的客户端Cookie
+    pub fn client_cookie(&self, server: &str) -> [u8; CLIENT_COOKIE_LEN] {
+        let epoch = self.started.elapsed().as_nanos() / self.lifetime.as_nanos().max(1);
+        let mut hasher = DefaultHasher::new();
+        self.secret.hash(&mut hasher);
+        server.hash(&mut hasher);
+        epoch.hash(&mut hasher);
+        hasher.finish().to_be_bytes()
+    }
+
+    /// 发往 `server` 的COOKIE选项：客户端Cookie，之后是用同一客户端Cookie取得的服务器Cookie（如有）
+    pub fn request_option(&self, server: &str) -> EdnsOption {
+        let client = self.client_cookie(server);
+        let mut data = client.to_vec();
+        if let Some(cookie) = self.servers().get(server).filter(|cookie| cookie.client == client) {
+            data.extend_from_slice(&cookie.server);
+        }
+        EdnsOption { code: edns_option_codes::COOKIE, data }
+    }
+
+    /// 记下响应COOKIE选项中的服务器Cookie，返回是否记下
+    ///
+    /// 客户端Cookie与当前的不符（迟到或伪造的响应）或服务器Cookie长度不合法时忽略
+    pub fn observe(&self, server: &str, option_data: &[u8]) -> bool {
+        if option_data.len() < CLIENT_COOKIE_LEN || !SERVER_COOKIE_LEN.contains(&(option_data.len() - CLIENT_COOKIE_LEN)) {
+            return false;
+        }
+        let client = self.client_cookie(server);
+        if option_data[..CLIENT_COOKIE_LEN] != client {
+            return false;
+        }
+        self.servers().insert(server.to_string(), ServerCookie {
+            client,
+            server: option_data[CLIENT_COOKIE_LEN..].to_vec(),
+        });
+        true
+    }
+
+    /// 已缓存的 `server` 的服务器Cookie（客户端Cookie已更换的不算）
+    pub fn server_cookie(&self, server: &str) -> Option<Vec<u8>> {
+        let client = self.client_cookie(server);
+        self.servers().get(server)
+            .filter(|cookie| cookie.client == client)
+            .map(|cookie| cookie.server.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_cookie_is_dropped_when_client_cookie_rotates() {
+        let jar = DnsCookieJar::new(Duration::from_millis(200));
+        let server = "192.0.2.53:53";
+        let client = jar.client_cookie(server);
+        assert_eq!(jar.client_cookie(server), client);
+        assert_ne!(jar.client_cookie("192.0.2.54:53"), client);
+
+        // 客户端Cookie不符或服务器Cookie过短都不记下
+        assert!(!jar.observe(server, &[0u8; 16]));
+        assert!(!jar.observe(server, &[client.as_slice(), &[1, 2, 3]].concat()));
+
+        assert!(jar.observe(server, &[client.as_slice(), &[7u8; 8]].concat()));
+        assert_eq!(jar.request_option(server).data, [client.as_slice(), &[7u8; 8]].concat());
+
+        std::thread::sleep(Duration::from_millis(250));
+        assert_ne!(jar.client_cookie(server), client);
+        assert_eq!(jar.server_cookie(server), None);
+        assert_eq!(jar.request_option(server).data.len(), CLIENT_COOKIE_LEN);
+    }
+}
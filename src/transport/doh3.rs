@@ -0,0 +1,264 @@
+//! DNS over HTTP/3（`doh3` 特性）
+//!
+//! 在quinn建立的QUIC连接上用h3发送DoH请求。连接建立后由后续查询复用，
+//! 连接关闭（空闲超时、服务器关闭等）后的下一次查询重新握手。
+
+use crate::{DnsError, NetworkErrorKind, Request, Response, Result};
+use super::{HttpMethod, HttpsConfig, RecordLimits};
+use super::https::{doh_request_url, ensure_dns_message, parse_retry_after, redact_url, response_body_snippet, HttpsTransport};
+use super::query_id::ensure_matching_id;
+use super::timing::{TimingPhase, TimingRecorder, TransportPhase};
+use super::wire::WireRecorder;
+use super::udp::UdpTransport;
+use bytes::{Buf, Bytes};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use crate::{dns_debug, dns_info};
+
+/// QUIC握手失败后暂停尝试HTTP/3的时长，避免UDP被拦截时每次查询都等待握手超时
+const HTTP3_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// 发送HTTP/3请求的句柄
+type SendRequest = h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>;
+
+/// 已建立的HTTP/3连接
+struct Session {
+    /// 连接使用的本地端点，与连接同生命周期
+    _endpoint: quinn::Endpoint,
+    connection: quinn::Connection,
+    send_request: SendRequest,
+}
+
+/// DoH的HTTP/3客户端
+pub(crate) struct Http3Client {
+    url: url::Url,
+    /// 日志和错误中使用的URL，认证类查询参数的值已隐去
+    display_url: String,
+    server: String,
+    port: u16,
+    /// 配置了连接地址时直接连接它，SNI仍为 `server_name`
+    connect_addr: Option<SocketAddr>,
+    server_name: String,
+    user_agent: String,
+    extra_headers: Vec<(String, String)>,
+    max_response_size: usize,
+    record_limits: Option<RecordLimits>,
+    handshake_timeout: Duration,
+    client_config: quinn::ClientConfig,
+    session: Mutex<Option<Session>>,
+    /// 回退模式下握手失败后，在此时间之前直接走TCP
+    suspended_until: std::sync::Mutex<Option<Instant>>,
+}
+
+impl std::fmt::Debug for Http3Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Http3Client")
+            .field("url", &self.display_url)
+            .field("server", &self.server)
+            .field("port", &self.port)
+            .field("connect_addr", &self.connect_addr)
+            .finish()
+    }
+}
+
+impl Http3Client {
+    /// 创建客户端，不立即建立连接；`roots` 为校验服务器证书使用的根证书
+    pub(crate) fn new(config: &HttpsConfig, roots: RootCertStore, handshake_timeout: Duration) -> Result<Self> {
+        let url = doh_request_url(&config.url, &config.query_params)?;
+        let display_url = redact_url(url.as_str(), "***");
+        let server_name = url.host_str()
+            .ok_or_else(|| DnsError::InvalidConfig(format!("DoH URL '{}' has no host", display_url)))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+
+        let mut crypto = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![b"h3".to_vec()];
+
+        Ok(Self {
+            url,
+            display_url,
+            server: config.base.server.clone(),
+            port: config.base.port,
+            connect_addr: config.connect_addr,
+            server_name,
+            user_agent: config.user_agent.clone(),
+            extra_headers: config.extra_headers.clone(),
+            max_response_size: config.max_response_size,
+            record_limits: config.base.record_limits,
+            handshake_timeout,
+            client_config: quinn::ClientConfig::new(Arc::new(crypto)),
+            session: Mutex::new(None),
+            suspended_until: std::sync::Mutex::new(None),
+        })
+    }
+
+    /// 是否处于握手失败后的暂停期
+    pub(crate) fn is_suspended(&self) -> bool {
+        let suspended_until = self.suspended_until.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        suspended_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// 握手失败后暂停使用HTTP/3
+    pub(crate) fn suspend(&self) {
+        *self.suspended_until.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Instant::now() + HTTP3_RETRY_AFTER);
+    }
+
+    /// 取得可用连接，没有连接或连接已关闭时重新握手
+    ///
+    /// 返回的错误都发生在请求发出之前，回退模式据此改走TCP
+    pub(crate) async fn connect(&self, timing: &mut TimingRecorder) -> Result<SendRequest> {
+        let mut session = self.session.lock().await;
+        if let Some(existing) = session.as_ref() {
+            if existing.connection.close_reason().is_none() {
+                timing.set_peer(existing.connection.remote_address());
+                return Ok(existing.send_request.clone());
+            }
+        }
+        *session = None;
+
+        let fresh = timeout(self.handshake_timeout, self.handshake(timing)).await
+            .map_err(|_| DnsError::Network {
+                kind: NetworkErrorKind::TimedOut,
+                upstream: self.display_url.clone(),
+                message: "QUIC handshake timed out".to_string(),
+            })??;
+        timing.set_peer(fresh.connection.remote_address());
+        let send_request = fresh.send_request.clone();
+        *session = Some(fresh);
+        Ok(send_request)
+    }
+
+    /// 解析上游地址（配置为主机名时），完成QUIC握手并建立HTTP/3会话
+    async fn handshake(&self, timing: &mut TimingRecorder) -> Result<Session> {
+        let address = match (self.connect_addr, self.server.parse::<IpAddr>()) {
+            (Some(address), _) => address,
+            (None, Ok(ip)) => SocketAddr::new(ip, self.port),
+            (None, Err(_)) => {
+                timing.enter(TransportPhase::UpstreamResolve);
+                let address = tokio::net::lookup_host((self.server.as_str(), self.port)).await
+                    .map_err(|e| DnsError::network_io(self.server.clone(), "Failed to resolve", &e))?
+                    .next()
+                    .ok_or_else(|| DnsError::network(self.server.clone(), "No address found"))?;
+                timing.mark(TimingPhase::UpstreamResolve);
+                address
+            }
+        };
+
+        let bind_address: SocketAddr = if address.is_ipv6() {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        };
+        let mut endpoint = quinn::Endpoint::client(bind_address)
+            .map_err(|e| DnsError::network_io(self.display_url.clone(), "Failed to open QUIC endpoint", &e))?;
+        endpoint.set_default_client_config(self.client_config.clone());
+        timing.enter(TransportPhase::TlsHandshake);
+
+        let connection = endpoint.connect(address, &self.server_name)
+            .map_err(|e| DnsError::network(address.to_string(), format!("QUIC connect failed: {}", e)))?
+            .await
+            .map_err(|e| DnsError::network(address.to_string(), format!("QUIC handshake failed: {}", e)))?;
+        timing.mark(TimingPhase::TlsHandshake);
+
+        let (mut driver, send_request) = h3::client::new(h3_quinn::Connection::new(connection.clone())).await
+            .map_err(|e| DnsError::Http(format!("HTTP/3 setup with {} failed: {}", address, e)))?;
+        // 驱动连接级的控制流，连接关闭时结束
+        tokio::spawn(async move {
+            let _ = futures::future::poll_fn(|cx| driver.poll_close(cx)).await;
+        });
+        dns_info!("🌐 HTTP/3连接已建立: {} ({})", self.display_url, address);
+
+        Ok(Session { _endpoint: endpoint, connection, send_request })
+    }
+
+    /// 在已建立的连接上发送一次DoH请求
+    pub(crate) async fn exchange(
+        &self,
+        mut send_request: SendRequest,
+        request: &Request,
+        method: HttpMethod,
+        timing: &mut TimingRecorder,
+        wire: &mut WireRecorder,
+    ) -> Result<Response> {
+        dns_debug!("🌐 DoH HTTP/3请求开始: {} -> {}", request.query.name, self.display_url);
+        let mut builder = http::Request::builder()
+            .header("accept", "application/dns-message")
+            .header("user-agent", self.user_agent.as_str());
+        for (name, value) in &self.extra_headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        let dns_data = HttpsTransport::encode_request(request)?;
+        wire.record_request(&dns_data);
+        let (builder, body) = match method {
+            HttpMethod::GET => {
+                let mut url = self.url.clone();
+                url.query_pairs_mut().append_pair("dns", &HttpsTransport::encode_dns_query_base64url(&dns_data));
+                (builder.method(http::Method::GET).uri(url.as_str()), None)
+            }
+            HttpMethod::POST => {
+                let builder = builder
+                    .method(http::Method::POST)
+                    .uri(self.url.as_str())
+                    .header("content-type", "application/dns-message");
+                (builder, Some(Bytes::from(dns_data)))
+            }
+        };
+        let http_request = builder.body(())
+            .map_err(|e| DnsError::Http(format!("Failed to build HTTP/3 request: {}", e)))?;
+
+        timing.enter(TransportPhase::RequestSent);
+        let mut stream = send_request.send_request(http_request).await.map_err(Self::request_error)?;
+        if let Some(body) = body {
+            stream.send_data(body).await.map_err(Self::request_error)?;
+        }
+        stream.finish().await.map_err(Self::request_error)?;
+        timing.mark(TimingPhase::RequestSent);
+
+        timing.enter(TransportPhase::FirstByte);
+        let head = stream.recv_response().await.map_err(Self::request_error)?;
+        timing.mark(TimingPhase::FirstByte);
+        timing.enter(TransportPhase::Complete);
+
+        let mut payload = Vec::new();
+        while let Some(mut chunk) = stream.recv_data().await.map_err(Self::request_error)? {
+            if payload.len() + chunk.remaining() > self.max_response_size {
+                return Err(DnsError::ResponseTooLarge { limit: self.max_response_size, upstream: self.display_url.clone() });
+            }
+            payload.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+        }
+
+        wire.record_response(&payload);
+        
+        if !head.status().is_success() {
+            return Err(DnsError::HttpStatus {
+                status: head.status().as_u16(),
+                upstream: self.display_url.clone(),
+                body_snippet: response_body_snippet(&payload),
+                retry_after: head.headers()
+                    .get("retry-after")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| parse_retry_after(value, chrono::Utc::now())),
+            });
+        }
+        let content_type = head.headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        ensure_dns_message(content_type, &self.display_url)?;
+
+        UdpTransport::deserialize_response_with_limits(&payload, self.record_limits.as_ref())
+            .and_then(|response| ensure_matching_id(request, response))
+    }
+
+    fn request_error(err: h3::Error) -> DnsError {
+        DnsError::Http(format!("HTTP/3 request failed: {}", err))
+    }
+}
@@ -0,0 +1,102 @@
+//! DoH端点URL的检查与日志脱敏
+//!
+//! 不依赖HTTP客户端：未启用 `doh` 特性时，配置检查和配置导出同样需要校验端点、隐藏认证信息。
+
+use crate::{DnsError, Result};
+
+/// 日志中需要隐藏值的请求头名称（小写）
+const SENSITIVE_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie", "x-api-key"];
+
+/// 请求头是否携带认证信息，其值不应出现在日志或配置导出中
+pub fn is_sensitive_header(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    SENSITIVE_HEADERS.contains(&lower.as_str())
+        || ["token", "secret", "password"].iter().any(|word| lower.contains(word))
+}
+
+/// 日志用的请求头值：认证类请求头只保留前4个字符
+pub fn redact_header_value<'a>(name: &str, value: &'a str) -> std::borrow::Cow<'a, str> {
+    if is_sensitive_header(name) {
+        let visible: String = value.chars().take(4).collect();
+        std::borrow::Cow::Owned(format!("{}***", visible))
+    } else {
+        std::borrow::Cow::Borrowed(value)
+    }
+}
+
+/// 查询参数是否携带认证信息（如 `key`、`api_key`、`token`），其值不应出现在日志或配置导出中
+pub fn is_sensitive_param(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    ["key", "token", "secret", "password", "auth", "sig"].iter().any(|word| lower.contains(word))
+}
+
+/// 把URL查询串中认证类参数的值替换为 `replacement`，其余部分原样保留
+pub fn redact_url(url: &str, replacement: &str) -> String {
+    let Some((base, rest)) = url.split_once('?') else {
+        return url.to_string();
+    };
+    let (query, fragment) = match rest.split_once('#') {
+        Some((query, fragment)) => (query, Some(fragment)),
+        None => (rest, None),
+    };
+    let query: Vec<String> = query.split('&')
+        .map(|pair| {
+            let raw_name = pair.split('=').next().unwrap_or("");
+            let sensitive = url::form_urlencoded::parse(raw_name.as_bytes())
+                .next()
+                .is_some_and(|(name, _)| is_sensitive_param(&name));
+            if sensitive { format!("{}={}", raw_name, replacement) } else { pair.to_string() }
+        })
+        .collect();
+    match fragment {
+        Some(fragment) => format!("{}?{}#{}", base, query.join("&"), fragment),
+        None => format!("{}?{}", base, query.join("&")),
+    }
+}
+
+/// 解析DoH端点URL并追加附加查询参数，得到不含 `dns` 参数的请求URL
+///
+/// URL必须是带主机名的绝对URL且不含片段；参数名不能为空，也不能是GET请求使用的 `dns`
+/// （URL自带的查询串同样检查）。参数值按 `application/x-www-form-urlencoded` 编码，
+/// 追加在URL原有的查询串之后。协议是否为https由调用方检查（测试用的本地服务器是http）
+pub fn doh_request_url(url: &str, query_params: &[(String, String)]) -> Result<url::Url> {
+    let shown = redact_url(url, "***");
+    let mut parsed = url::Url::parse(url)
+        .map_err(|e| DnsError::InvalidConfig(format!("Invalid DoH URL '{}': {}", shown, e)))?;
+    if parsed.host_str().is_none_or(str::is_empty) {
+        return Err(DnsError::InvalidConfig(format!("DoH URL '{}' has no host", shown)));
+    }
+    if parsed.fragment().is_some() {
+        return Err(DnsError::InvalidConfig(format!("DoH URL '{}' must not contain a fragment", shown)));
+    }
+    if parsed.query_pairs().any(|(name, _)| name == "dns") {
+        return Err(DnsError::InvalidConfig(format!(
+            "DoH URL '{}' must not contain the 'dns' query parameter, which carries the GET request", shown
+        )));
+    }
+    for (name, _) in query_params {
+        if name.is_empty() {
+            return Err(DnsError::InvalidConfig(format!("DoH query parameter name cannot be empty ({})", shown)));
+        }
+        if name == "dns" {
+            return Err(DnsError::InvalidConfig(format!(
+                "DoH query parameter 'dns' is reserved for the GET request ({})", shown
+            )));
+        }
+    }
+    if !query_params.is_empty() {
+        parsed.query_pairs_mut().extend_pairs(query_params);
+    }
+    Ok(parsed)
+}
+
+/// 检查DoH端点：协议必须是https，其余同 [`doh_request_url`]
+pub fn validate_doh_url(url: &str, query_params: &[(String, String)]) -> Result<()> {
+    let parsed = doh_request_url(url, query_params)?;
+    if parsed.scheme() != "https" {
+        return Err(DnsError::InvalidConfig(format!(
+            "DoH URL '{}' must use https, got '{}'", redact_url(url, "***"), parsed.scheme()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,306 @@
+//! TCP Fast Open（RFC 7413）
+//!
+//! 开启 [`TransportConfig::tcp_fast_open`](super::TransportConfig::tcp_fast_open) 后，TCP和DoT连接第一次写出的数据
+//! （DNS查询或TLS ClientHello）随SYN一起发送，省去一次往返：
+//!
+//! - Linux：连接前设置 `TCP_FASTOPEN_CONNECT`，`connect` 立即返回，数据随第一次写出发送。内核还没有该服务器的
+//!   Cookie时照常握手并取得Cookie，之后的连接才真正携带数据；
+//! - macOS：用 `connectx` 并带 `CONNECT_DATA_IDEMPOTENT | CONNECT_RESUME_ON_READ_WRITE` 发起连接，效果相同；
+//! - 其他平台，或内核拒绝该选项时照常连接，不报错，计入 [`FastOpenStats::fallbacks`]。
+//!
+//! 开启后连接被拒绝等错误要到写出请求时才出现。Linux上读完响应后用 `TCP_INFO` 判断SYN中的数据是否被服务器接受。
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use tokio::net::TcpStream;
+
+use crate::dns_debug;
+
+/// 一个TCP/DoT传输的TCP Fast Open计数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FastOpenStats {
+    /// 当前平台是否支持TCP Fast Open
+    pub supported: bool,
+    /// 以TCP Fast Open方式发起的连接数
+    pub attempts: u64,
+    /// SYN中的数据被服务器接受的连接数（只有Linux可以观测，包含在 `attempts` 中）
+    pub syn_data_accepted: u64,
+    /// 无法开启、改为普通连接的连接数
+    pub fallbacks: u64,
+}
+
+/// TCP Fast Open计数器
+#[derive(Debug, Default)]
+pub(crate) struct FastOpenCounters {
+    attempts: AtomicU64,
+    syn_data_accepted: AtomicU64,
+    fallbacks: AtomicU64,
+}
+
+impl FastOpenCounters {
+    /// 读完响应后记录SYN中的数据是否被接受，无法观测时不计数并返回 `None`
+    pub(crate) fn observe(&self, stream: &TcpStream) -> Option<bool> {
+        let accepted = sys::syn_data_accepted(stream)?;
+        if accepted {
+            self.syn_data_accepted.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(accepted)
+    }
+
+    pub(crate) fn snapshot(&self) -> FastOpenStats {
+        FastOpenStats {
+            supported: is_supported(),
+            attempts: self.attempts.load(Ordering::Relaxed),
+            syn_data_accepted: self.syn_data_accepted.load(Ordering::Relaxed),
+            fallbacks: self.fallbacks.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 当前平台是否支持TCP Fast Open（Linux和macOS）
+pub fn is_supported() -> bool {
+    cfg!(any(target_os = "linux", target_os = "macos"))
+}
+
+/// 以TCP Fast Open方式发起连接的平台实现，不支持时返回 `ErrorKind::Unsupported`
+type FastConnect = fn(&Socket, &SockAddr) -> io::Result<()>;
+
+/// 以TCP Fast Open方式连接 `addr`，无法开启时照常连接
+pub(crate) async fn connect(addr: SocketAddr, counters: &FastOpenCounters) -> io::Result<TcpStream> {
+    connect_with(addr, counters, sys::fast_connect).await
+}
+
+async fn connect_with(addr: SocketAddr, counters: &FastOpenCounters, fast_connect: FastConnect) -> io::Result<TcpStream> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_nonblocking(true)?;
+    let target = SockAddr::from(addr);
+    let started = match fast_connect(&socket, &target) {
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+            dns_debug!("连接 {} 无法使用TCP Fast Open（{}），改为普通连接", addr, e);
+            counters.fallbacks.fetch_add(1, Ordering::Relaxed);
+            socket.connect(&target)
+        }
+        started => {
+            counters.attempts.fetch_add(1, Ordering::Relaxed);
+            started
+        }
+    };
+    match started {
+        Ok(()) => {}
+        Err(e) if is_in_progress(&e) => {}
+        Err(e) => return Err(e),
+    }
+
+    // 推迟到第一次写出的连接立即可写；正在握手的连接等握手结束后取出连接错误
+    let stream = TcpStream::from_std(std::net::TcpStream::from(socket))?;
+    stream.writable().await?;
+    match stream.take_error()? {
+        Some(e) => Err(e),
+        None => Ok(stream),
+    }
+}
+
+/// 非阻塞连接已发起、尚未完成
+fn is_in_progress(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    if error.raw_os_error() == Some(libc::EINPROGRESS) {
+        return true;
+    }
+    error.kind() == io::ErrorKind::WouldBlock
+}
+
+#[cfg(target_os = "linux")]
+#[allow(unsafe_code)]
+mod sys {
+    use std::io;
+    use std::mem;
+    use std::os::fd::AsRawFd;
+
+    use socket2::{SockAddr, Socket};
+    use tokio::net::TcpStream;
+
+    /// `tcp_info.tcpi_options` 中表示SYN携带的数据被确认的位
+    const TCPI_OPT_SYN_DATA: u8 = 32;
+
+    pub(super) fn fast_connect(socket: &Socket, addr: &SockAddr) -> io::Result<()> {
+        let enable: libc::c_int = 1;
+        // SAFETY: 套接字在调用期间有效，选项值指向栈上的c_int，长度与其一致
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_FASTOPEN_CONNECT,
+                (&enable as *const libc::c_int).cast(),
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, io::Error::last_os_error()));
+        }
+        socket.connect(addr)
+    }
+
+    #[cfg(any(target_env = "gnu", target_env = "musl"))]
+    pub(super) fn syn_data_accepted(stream: &TcpStream) -> Option<bool> {
+        // SAFETY: tcp_info只含整数字段，全零是合法的值
+        let mut info: libc::tcp_info = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+        // SAFETY: 套接字在调用期间有效，缓冲区和长度描述同一个tcp_info
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_INFO,
+                (&mut info as *mut libc::tcp_info).cast(),
+                &mut len,
+            )
+        };
+        (result == 0).then_some(info.tcpi_options & TCPI_OPT_SYN_DATA != 0)
+    }
+
+    #[cfg(not(any(target_env = "gnu", target_env = "musl")))]
+    pub(super) fn syn_data_accepted(_stream: &TcpStream) -> Option<bool> {
+        None
+    }
+}
+
+#[cfg(target_os = "macos")]
+#[allow(unsafe_code)]
+mod sys {
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::ptr;
+
+    use socket2::{SockAddr, Socket};
+    use tokio::net::TcpStream;
+
+    pub(super) fn fast_connect(socket: &Socket, addr: &SockAddr) -> io::Result<()> {
+        let endpoints = libc::sa_endpoints_t {
+            sae_srcif: 0,
+            sae_srcaddr: ptr::null(),
+            sae_srcaddrlen: 0,
+            sae_dstaddr: addr.as_ptr(),
+            sae_dstaddrlen: addr.len(),
+        };
+        // SAFETY: 套接字在调用期间有效，endpoints中的目标地址指向 `addr`，其余指针为空
+        let result = unsafe {
+            libc::connectx(
+                socket.as_raw_fd(),
+                &endpoints,
+                libc::SAE_ASSOCID_ANY,
+                libc::CONNECT_DATA_IDEMPOTENT | libc::CONNECT_RESUME_ON_READ_WRITE,
+                ptr::null(),
+                0,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    pub(super) fn syn_data_accepted(_stream: &TcpStream) -> Option<bool> {
+        None
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod sys {
+    use std::io;
+
+    use socket2::{SockAddr, Socket};
+    use tokio::net::TcpStream;
+
+    pub(super) fn fast_connect(_socket: &Socket, _addr: &SockAddr) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "TCP Fast Open is not supported on this platform"))
+    }
+
+    pub(super) fn syn_data_accepted(_stream: &TcpStream) -> Option<bool> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn unsupported(_socket: &Socket, _addr: &SockAddr) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "ENOPROTOOPT"))
+    }
+
+    async fn echo_listener() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 4];
+                if stream.read_exact(&mut buf).await.is_ok() {
+                    let _ = stream.write_all(&buf).await;
+                }
+            }
+        });
+        addr
+    }
+
+    async fn round_trip(mut stream: TcpStream) {
+        stream.write_all(b"ping").await.unwrap();
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_option_falls_back_to_plain_connect() {
+        let addr = echo_listener().await;
+        let counters = FastOpenCounters::default();
+        let stream = connect_with(addr, &counters, unsupported).await.unwrap();
+        round_trip(stream).await;
+
+        let stats = counters.snapshot();
+        assert_eq!((stats.attempts, stats.fallbacks, stats.syn_data_accepted), (0, 1, 0));
+        assert_eq!(stats.supported, is_supported());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_still_reports_connection_errors() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let counters = FastOpenCounters::default();
+        let error = connect_with(addr, &counters, unsupported).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_loopback_fast_open_round_trip() {
+        let addr = echo_listener().await;
+        let counters = FastOpenCounters::default();
+        for _ in 0..2 {
+            let stream = connect(addr, &counters).await.unwrap();
+            round_trip_observed(stream, &counters).await;
+        }
+        let stats = counters.snapshot();
+        assert!(stats.supported);
+        // 内核拒绝该选项（如旧内核）时走普通连接，两种情况下连接都可用
+        assert_eq!(stats.attempts + stats.fallbacks, 2);
+        assert!(stats.syn_data_accepted <= stats.attempts);
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn round_trip_observed(mut stream: TcpStream, counters: &FastOpenCounters) {
+        stream.write_all(b"ping").await.unwrap();
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"ping");
+        assert!(counters.observe(&stream).is_some());
+    }
+}
@@ -0,0 +1,283 @@
+//! TCP与DoT的消息分帧
+//!
+//! 流式传输的每条DNS消息前有2字节长度前缀（RFC 1035 4.2.2）。请求直接编码到开头预留了前缀位置的缓冲区，
+//! 编码完成后回填长度，整帧一次写出，不再为拼接前缀另建一份副本；`write_all` 在系统只接受部分字节时继续写剩余部分。
+//! 响应的消息体读入同一个缓冲区。缓冲区取自传输的 [`FrameBufferPool`]，查询结束后清空归还，
+//! 之后的查询复用已分配的容量
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::timing::{TimingPhase, TimingRecorder, TransportPhase};
+use super::udp::UdpTransport;
+use super::STREAM_EDNS_PAYLOAD_SIZE;
+use crate::{DnsError, Request, Result};
+
+/// 长度前缀的字节数
+pub(crate) const LENGTH_PREFIX: usize = 2;
+
+/// 把请求编码为带长度前缀的一帧，写入清空后的 `buffer`；OPT记录声明流式传输的载荷大小
+pub(crate) fn encode_request(request: &Request, buffer: &mut Vec<u8>) -> Result<()> {
+    buffer.clear();
+    buffer.extend_from_slice(&[0; LENGTH_PREFIX]);
+    let edns = UdpTransport::request_edns(request, STREAM_EDNS_PAYLOAD_SIZE);
+    UdpTransport::serialize_request_into(request, edns.as_ref(), buffer)?;
+    let length = u16::try_from(buffer.len() - LENGTH_PREFIX)
+        .map_err(|_| DnsError::Protocol(format!("Request of {} bytes exceeds the TCP message limit", buffer.len() - LENGTH_PREFIX)))?;
+    buffer[..LENGTH_PREFIX].copy_from_slice(&length.to_be_bytes());
+    Ok(())
+}
+
+/// 读取一条带长度前缀的DNS消息，`buffer` 清空后只含消息体；读到长度前缀时记为首字节
+///
+/// 长度前缀超过 `max_size` 时在调整缓冲区之前返回 [`DnsError::Protocol`]。
+/// 同一连接上可以连续调用，逐条读取多消息响应
+pub(crate) async fn read_message<S>(stream: &mut S, max_size: usize, buffer: &mut Vec<u8>, timing: &mut TimingRecorder) -> Result<()>
+where
+    S: AsyncRead + Unpin,
+{
+    timing.enter(TransportPhase::FirstByte);
+    let mut length_buf = [0u8; LENGTH_PREFIX];
+    stream.read_exact(&mut length_buf).await
+        .map_err(|e| DnsError::network_io("", "Failed to read length", &e))?;
+    timing.mark(TimingPhase::FirstByte);
+    timing.enter(TransportPhase::Complete);
+
+    let length = u16::from_be_bytes(length_buf) as usize;
+    if length == 0 {
+        return Err(DnsError::Protocol("Invalid response length".to_string()));
+    }
+    if length > max_size {
+        return Err(DnsError::Protocol(format!(
+            "Response length {} exceeds the buffer_size limit of {} bytes", length, max_size
+        )));
+    }
+
+    buffer.clear();
+    buffer.resize(length, 0);
+    stream.read_exact(buffer).await
+        .map_err(|e| DnsError::network_io("", "Failed to read response", &e))?;
+    Ok(())
+}
+
+/// 分帧缓冲区池，每个TCP/DoT传输一个
+///
+/// 缓冲区首次取出时按 `buffer_size` 加长度前缀分配，足以容纳请求和不超过限制的响应；
+/// 空闲的缓冲区最多保留 `max_idle` 个，多出的随查询结束释放
+#[derive(Debug)]
+pub(crate) struct FrameBufferPool {
+    idle: Mutex<Vec<Vec<u8>>>,
+    capacity: usize,
+    max_idle: usize,
+}
+
+impl FrameBufferPool {
+    pub(crate) fn new(buffer_size: usize, max_idle: usize) -> Self {
+        Self {
+            idle: Mutex::new(Vec::new()),
+            capacity: buffer_size.min(usize::from(u16::MAX)) + LENGTH_PREFIX,
+            max_idle: max_idle.max(1),
+        }
+    }
+
+    /// 取出一个空缓冲区，离开作用域时归还
+    pub(crate) fn get(&self) -> PooledBuffer<'_> {
+        let buffer = self.idle.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).pop()
+            .unwrap_or_else(|| Vec::with_capacity(self.capacity));
+        PooledBuffer { pool: self, buffer }
+    }
+
+    /// 当前空闲的缓冲区数
+    #[cfg(test)]
+    fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+}
+
+/// 从 [`FrameBufferPool`] 取出的缓冲区
+pub(crate) struct PooledBuffer<'a> {
+    pool: &'a FrameBufferPool,
+    buffer: Vec<u8>,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        // 超出分配容量的缓冲区不留在池中，避免个别大消息长期占用内存
+        if buffer.capacity() > self.pool.capacity {
+            return;
+        }
+        buffer.clear();
+        let mut idle = self.pool.idle.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if idle.len() < self.pool.max_idle {
+            idle.push(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Flags, QClass, Query, QueryPriority, RecordType};
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncWrite, AsyncWriteExt, ReadBuf};
+
+    /// 每次只接受或给出1个字节、且每隔一次先返回 `Pending` 的流，模拟系统只完成部分写入和读取
+    #[derive(Default)]
+    struct Trickle {
+        written: Vec<u8>,
+        input: Vec<u8>,
+        read_pos: usize,
+        yield_next: bool,
+    }
+
+    impl Trickle {
+        fn poll_turn(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+            self.yield_next = !self.yield_next;
+            if self.yield_next {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(())
+        }
+    }
+
+    impl AsyncWrite for Trickle {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            if self.poll_turn(cx).is_pending() {
+                return Poll::Pending;
+            }
+            let accepted = buf.len().min(1);
+            self.written.extend_from_slice(&buf[..accepted]);
+            Poll::Ready(Ok(accepted))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            if self.poll_turn(cx).is_pending() {
+                return Poll::Pending;
+            }
+            if let Some(&byte) = self.input.get(self.read_pos) {
+                buf.put_slice(&[byte]);
+                self.read_pos += 1;
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn request(name: &str) -> Request {
+        Request {
+            id: 0x4242,
+            flags: Flags::default(),
+            query: Query { name: name.to_string(), qtype: RecordType::A, qclass: QClass::IN },
+            client_address: None,
+            enable_edns: true,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: QueryPriority::Normal,
+        }
+    }
+
+    fn framed(message: &[u8]) -> Vec<u8> {
+        let mut bytes = (message.len() as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(message);
+        bytes
+    }
+
+    #[tokio::test]
+    async fn test_frame_survives_one_byte_writes() {
+        let pool = FrameBufferPool::new(4096, 1);
+        let mut stream = Trickle::default();
+        let mut expected = Vec::new();
+        // 同一缓冲区先后编码两条请求，后一条不残留前一条的字节
+        for name in ["a-much-longer-name.subdomain.example.org", "example.com"] {
+            let request = request(name);
+            let mut buffer = pool.get();
+            encode_request(&request, &mut buffer).unwrap();
+            stream.write_all(&buffer).await.unwrap();
+            expected.extend(framed(&UdpTransport::serialize_request_with_payload_size(&request, STREAM_EDNS_PAYLOAD_SIZE).unwrap()));
+        }
+        assert_eq!(stream.written, expected);
+    }
+
+    #[tokio::test]
+    async fn test_messages_read_one_byte_at_a_time() {
+        let first = vec![0xAB; 300];
+        let second = vec![0xCD; 17];
+        let mut stream = Trickle { input: [framed(&first), framed(&second)].concat(), ..Trickle::default() };
+        let pool = FrameBufferPool::new(512, 1);
+        let mut buffer = pool.get();
+        let allocation = buffer.as_ptr();
+
+        read_message(&mut stream, 512, &mut buffer, &mut TimingRecorder::disabled()).await.unwrap();
+        assert_eq!(*buffer, first);
+        read_message(&mut stream, 512, &mut buffer, &mut TimingRecorder::disabled()).await.unwrap();
+        assert_eq!(*buffer, second);
+        // 两条消息都读入同一块分配
+        assert_eq!(buffer.as_ptr(), allocation);
+
+        // 读到末尾时返回网络错误
+        assert!(read_message(&mut stream, 512, &mut buffer, &mut TimingRecorder::disabled()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_length_rejected_before_reading_body() {
+        let mut stream = Trickle { input: framed(&[0; 600]), ..Trickle::default() };
+        let mut buffer = Vec::new();
+        let error = read_message(&mut stream, 512, &mut buffer, &mut TimingRecorder::disabled()).await.unwrap_err();
+        assert!(matches!(error, DnsError::Protocol(_)), "{:?}", error);
+        assert_eq!(stream.read_pos, LENGTH_PREFIX);
+        assert_eq!(buffer.capacity(), 0);
+    }
+
+    #[test]
+    fn test_pool_reuses_buffers() {
+        let pool = FrameBufferPool::new(512, 2);
+        let allocation = {
+            let mut buffer = pool.get();
+            encode_request(&request("example.com"), &mut buffer).unwrap();
+            assert_eq!(buffer.capacity(), 512 + LENGTH_PREFIX);
+            buffer.as_ptr()
+        };
+        assert_eq!(pool.idle(), 1);
+        let buffer = pool.get();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.as_ptr(), allocation);
+
+        // 空闲缓冲区不超过上限，超出分配容量的缓冲区不归还
+        let (a, b, c) = (pool.get(), pool.get(), pool.get());
+        drop((buffer, a, b, c));
+        assert_eq!(pool.idle(), 2);
+        let mut grown = pool.get();
+        grown.reserve(4096);
+        drop(grown);
+        assert_eq!(pool.idle(), 1);
+    }
+}
@@ -1,24 +1,203 @@
 //! HTTPS传输实现 (DNS over HTTPS)
 
 use crate::{Request, Response, Result, DnsError};
-use super::{Transport, HttpsConfig, HttpMethod};
+use crate::error::TlsErrorKind;
+use crate::resolver::clock::{self, Clock};
+use super::{Transport, HttpsConfig, HttpMethod, STREAM_EDNS_PAYLOAD_SIZE};
+#[cfg(not(target_arch = "wasm32"))]
+use super::HttpVersionPref;
 use super::udp::UdpTransport;
+use super::query_id::ensure_matching_id;
+use super::timing::{TimingPhase, TimingRecorder, TransportPhase, TransportTiming};
+#[cfg(not(target_arch = "wasm32"))]
+use super::timing::HttpVersion;
+use super::wire::{WireCapture, WireRecorder};
+pub use super::doh_url::{doh_request_url, is_sensitive_header, is_sensitive_param, redact_header_value, redact_url, validate_doh_url};
+#[cfg(feature = "doh3")]
+use super::doh3::Http3Client;
 use async_trait::async_trait;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::time::timeout;
+use chrono::{DateTime, NaiveDateTime, Utc};
 
 use reqwest::{Client, Method};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+/// 错误中保留的响应正文长度（字符）
+const BODY_SNIPPET_CHARS: usize = 200;
+
+/// 跟随重定向时最多跟随的跳数
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_REDIRECTS: usize = 2;
+
+/// 校验附加请求头并转换为 `HeaderMap`
+/// 
+/// 名称和值必须是合法的HTTP头（值中不允许出现CR/LF等控制字符），否则返回配置错误；
+/// `Host` 总是取自URL的主机名，不能通过附加请求头改写
+pub fn build_header_map(headers: &[(String, String)]) -> Result<HeaderMap> {
+    let mut map = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| DnsError::InvalidConfig(format!("Invalid DoH header name: {:?}", name)))?;
+        if header_name == reqwest::header::HOST {
+            return Err(DnsError::InvalidConfig("DoH Host header is always the URL host and cannot be overridden".to_string()));
+        }
+        let header_value = HeaderValue::from_str(value)
+            .map_err(|_| DnsError::InvalidConfig(format!("Invalid value for DoH header '{}'", name)))?;
+        map.append(header_name, header_value);
+    }
+    Ok(map)
+}
+
+/// 错误信息中保留的响应正文开头，正文为空白时为 `None`
+pub(crate) fn response_body_snippet(body: &[u8]) -> Option<String> {
+    let snippet: String = String::from_utf8_lossy(body).chars().take(BODY_SNIPPET_CHARS).collect();
+    (!snippet.trim().is_empty()).then_some(snippet)
+}
+
+/// 解析 `Retry-After` 响应头（RFC 9110 10.2.3）：秒数，或HTTP日期（含两种旧格式）换算为距 `now` 的时长
+/// 
+/// 日期已过时为零，无法解析时为 `None`
+pub(crate) fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+        return Some(Duration::from_secs(value.parse().unwrap_or(u64::MAX)));
+    }
+    let at = DateTime::parse_from_rfc2822(value).map(|at| at.with_timezone(&Utc)).ok()
+        .or_else(|| {
+            ["%A, %d-%b-%y %H:%M:%S GMT", "%a %b %e %H:%M:%S %Y"].iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+                .map(|at| at.and_utc())
+        })?;
+    Some((at - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+/// 检查响应的Content-Type是 `application/dns-message`（忽略大小写和参数）
+pub(crate) fn ensure_dns_message(content_type: &str, upstream: &str) -> Result<()> {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    if !media_type.eq_ignore_ascii_case("application/dns-message") {
+        return Err(DnsError::UnexpectedContentType {
+            content_type: content_type.to_string(),
+            upstream: upstream.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// 检查连接地址与URL一致，返回需要替换解析结果的主机名和地址
+/// 
+/// 端口必须与URL的端口相同；URL的主机本身是IP时连接地址只能是它。URL的主机是名称时返回 `(主机名, 地址)`
+#[cfg(not(target_arch = "wasm32"))]
+fn connect_target<'a>(url: &'a url::Url, connect_addr: Option<SocketAddr>, display_url: &str) -> Result<Option<(&'a str, SocketAddr)>> {
+    let Some(address) = connect_addr else {
+        return Ok(None);
+    };
+    let port = url.port_or_known_default().unwrap_or(443);
+    if address.port() != port {
+        return Err(DnsError::InvalidConfig(format!(
+            "Connect address {} for DoH upstream {} must use the URL port {}", address, display_url, port
+        )));
+    }
+    match url.host() {
+        Some(url::Host::Domain(host)) => Ok(Some((host, address))),
+        Some(url::Host::Ipv4(ip)) if IpAddr::from(ip) == address.ip() => Ok(None),
+        Some(url::Host::Ipv6(ip)) if IpAddr::from(ip) == address.ip() => Ok(None),
+        _ => Err(DnsError::InvalidConfig(format!(
+            "Connect address {} does not match the IP in DoH URL {}", address, display_url
+        ))),
+    }
+}
+
+/// 重定向策略：只跟随同源的重定向，最多 [`MAX_REDIRECTS`] 跳；停止跟随时原样返回3xx响应
+#[cfg(not(target_arch = "wasm32"))]
+fn redirect_policy(follow_redirects: bool) -> reqwest::redirect::Policy {
+    if !follow_redirects {
+        return reqwest::redirect::Policy::none();
+    }
+    reqwest::redirect::Policy::custom(|attempt| {
+        let same_origin = attempt.previous().first()
+            .is_some_and(|original| original.origin() == attempt.url().origin());
+        if same_origin && attempt.previous().len() <= MAX_REDIRECTS {
+            attempt.follow()
+        } else {
+            attempt.stop()
+        }
+    })
+}
 
 /// HTTPS传输实现
 #[derive(Debug)]
 pub struct HttpsTransport {
     config: HttpsConfig,
+    /// 追加了附加查询参数的请求URL（GET请求再追加 `dns` 参数）
+    request_url: url::Url,
+    /// 日志和错误中使用的URL，认证类查询参数的值已隐去
+    display_url: String,
     client: Client,
+    /// 选择HTTP/3时使用的QUIC客户端
+    #[cfg(feature = "doh3")]
+    http3: Option<Http3Client>,
+    /// 超时和耗时统计使用的时间源
+    clock: Arc<dyn Clock>,
 }
 
 impl HttpsTransport {
     /// 创建新的HTTPS传输
     pub fn new(config: HttpsConfig) -> Result<Self> {
+        Self::build(config, true)
+    }
+    
+    /// 创建HTTPS传输，`verify_cert` 为假时不校验服务器证书（仅用于证书校验失败的降级）
+    #[cfg(not(target_arch = "wasm32"))]
+    fn build(config: HttpsConfig, verify_cert: bool) -> Result<Self> {
+        Self::build_with_roots(config, verify_cert, &[])
+    }
+    
+    /// 创建经fetch发送请求的HTTPS传输
+    /// 
+    /// 连接、TLS和重定向都由JS环境处理：连接超时、TCP选项和HTTP版本偏好不起作用，
+    /// 不能关闭证书校验，也不能指定连接地址
+    #[cfg(target_arch = "wasm32")]
+    fn build(config: HttpsConfig, verify_cert: bool) -> Result<Self> {
+        let request_url = doh_request_url(&config.url, &config.query_params)?;
+        let display_url = redact_url(request_url.as_str(), "***");
+        if !verify_cert {
+            return Err(DnsError::InvalidConfig(format!(
+                "Certificate verification cannot be disabled for DoH upstream {} on wasm32", display_url
+            )));
+        }
+        if config.connect_addr.is_some() {
+            return Err(DnsError::InvalidConfig(format!(
+                "A connect address cannot be set for DoH upstream {} on wasm32, fetch resolves the URL host itself", display_url
+            )));
+        }
+        if config.http_version.uses_http3() {
+            Self::check_http3(&display_url)?;
+        }
+        let extra_headers = build_header_map(&config.extra_headers)?;
+        for (name, value) in &config.extra_headers {
+            crate::dns_debug!("DoH附加请求头 {}: {} ({})", name, redact_header_value(name, value), display_url);
+        }
+        
+        let client = Client::builder()
+            .default_headers(extra_headers)
+            .user_agent(&config.user_agent)
+            .build()
+            .map_err(|e| DnsError::Http(format!("Failed to create HTTP client: {}", e)))?;
+        
+        Ok(Self {
+            config,
+            request_url,
+            display_url,
+            client,
+            clock: clock::real_clock(),
+        })
+    }
+    
+    /// 创建HTTPS传输，除系统根证书外再信任 `roots`（测试中的自签名证书）
+    #[cfg(not(target_arch = "wasm32"))]
+    fn build_with_roots(config: HttpsConfig, verify_cert: bool, roots: &[reqwest::Certificate]) -> Result<Self> {
         // 设置连接超时为总超时的1/3，最小2秒，最大5秒
         let connect_timeout = std::cmp::min(
             std::cmp::max(
@@ -28,21 +207,77 @@ impl HttpsTransport {
             Duration::from_secs(5)
         );
         
-        let client = Client::builder()
-            .timeout(config.base.timeout)  // 总体超时
+        let request_url = doh_request_url(&config.url, &config.query_params)?;
+        let display_url = redact_url(request_url.as_str(), "***");
+        let extra_headers = build_header_map(&config.extra_headers)?;
+        for (name, value) in &config.extra_headers {
+            crate::dns_debug!("DoH附加请求头 {}: {} ({})", name, redact_header_value(name, value), display_url);
+        }
+        
+        let mut client_builder = Client::builder()
+            .default_headers(extra_headers)  // 附加请求头，GET/POST都会携带
             .connect_timeout(connect_timeout)  // 连接超时，实现快速失败
             .tcp_keepalive(Duration::from_secs(30))  // TCP保活
             .tcp_nodelay(config.base.tcp_nodelay)  // TCP无延迟
-            .user_agent(&config.user_agent)
+            .redirect(redirect_policy(config.follow_redirects))
+            .user_agent(&config.user_agent);
+        if config.http_version == HttpVersionPref::H2Only {
+            client_builder = client_builder.http2_prior_knowledge();
+        }
+        if !verify_cert {
+            client_builder = client_builder.danger_accept_invalid_certs(true);
+        }
+        for root in roots {
+            client_builder = client_builder.add_root_certificate(root.clone());
+        }
+        if let Some(address) = connect_target(&request_url, config.connect_addr, &display_url)? {
+            // 只替换主机名的解析结果，SNI、证书校验和Host仍然来自URL
+            client_builder = client_builder.resolve(address.0, address.1);
+        }
+        let client = client_builder
             .build()
             .map_err(|e| DnsError::Http(format!("Failed to create HTTP client: {}", e)))?;
         
+        // QUIC握手与TCP连接共用连接超时，回退模式下握手失败后仍有时间走TCP
+        #[cfg(feature = "doh3")]
+        let http3 = if config.http_version.uses_http3() {
+            Some(Http3Client::new(&config, super::TlsTransport::load_root_certs()?, connect_timeout)?)
+        } else {
+            None
+        };
+        #[cfg(not(feature = "doh3"))]
+        if config.http_version.uses_http3() {
+            Self::check_http3(&display_url)?;
+        }
+        
         Ok(Self {
             config,
+            request_url,
+            display_url,
             client,
+            #[cfg(feature = "doh3")]
+            http3,
+            clock: clock::real_clock(),
         })
     }
     
+    /// 使用指定的时间源计算超时和各阶段耗时，默认为真实时钟
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+    
+    /// 检查选择HTTP/3的上游能否创建QUIC客户端（需要 `doh3` 特性和根证书），但不绑定QUIC端点
+    pub(crate) fn check_http3(url: &str) -> Result<()> {
+        #[cfg(feature = "doh3")]
+        {
+            let _ = url;
+            super::TlsTransport::load_root_certs().map(drop)
+        }
+        #[cfg(not(feature = "doh3"))]
+        Err(DnsError::InvalidConfig(format!("HTTP/3 for DoH upstream {} requires the doh3 feature", url)))
+    }
+    
     // 注意：移除了 default() 方法，因为它依赖兜底配置
     // 用户现在必须明确提供 HttpsConfig，不能依赖隐式默认值
     // 
@@ -56,46 +291,57 @@ impl HttpsTransport {
     //         tcp_fast_open: false,
     //         tcp_nodelay: true,
     //         pool_size: 10,
+    //         buffer_size: 4096,
+    //         record_limits: None,
     //     },
     //     url: "https://cloudflare-dns.com/dns-query".to_string(),
     //     method: HttpMethod::POST,
     //     user_agent: "RatQuickDNS/0.1.0".to_string(),
+    //     extra_headers: Vec::new(),
+    //     query_params: Vec::new(),
+    //     http_version: HttpVersionPref::Auto,
+    //     max_response_size: 65535,
+    //     follow_redirects: true,
     // })
     
-    /// 将DNS请求编码为base64url格式(用于GET方法)
-    fn encode_dns_query_base64url(request: &Request) -> Result<String> {
-        use base64::{Engine as _, engine::general_purpose};
-        let dns_data = UdpTransport::serialize_request(request)?;
-        Ok(general_purpose::URL_SAFE_NO_PAD.encode(&dns_data))
+    /// 非成功状态码转换为 [`DnsError::HttpStatus`]，保留状态码和正文开头
+    async fn status_error(&self, response: reqwest::Response) -> DnsError {
+        let status = response.status().as_u16();
+        let retry_after = response.headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, Utc::now()));
+        let body = response.bytes().await.ok()
+            .and_then(|bytes| response_body_snippet(&bytes));
+        DnsError::HttpStatus {
+            status,
+            upstream: self.display_url.clone(),
+            body_snippet: body,
+            retry_after,
+        }
     }
     
-    /// 发送GET请求
-    async fn send_get_request(&self, request: &Request) -> Result<Response> {
-        use crate::{dns_debug, dns_info};
-        dns_info!("🌐 DoH GET请求开始: {} -> {}", request.query.name, self.config.url);
-        let dns_query = Self::encode_dns_query_base64url(request)?;
-        
-        let response = timeout(
-            self.config.base.timeout,
-            self.client
-                .get(&self.config.url)
-                .query(&[("dns", dns_query)])
-                .header("Accept", "application/dns-message")
-                .send()
-        ).await;
-        
-        let http_response = match response {
-            Ok(Ok(resp)) => resp,
-            Ok(Err(e)) => return Err(DnsError::Http(format!("HTTP request failed: {}", e))),
-            Err(_) => return Err(DnsError::Timeout),
-        };
+    /// 检查状态码和Content-Type后读取正文并解析DNS报文
+    /// 
+    /// 状态码不是2xx（含未跟随的重定向）或Content-Type不对时不读取正文，更不会交给DNS解析器
+    async fn read_dns_response(
+        &self,
+        http_response: reqwest::Response,
+        request: &Request,
+        timing: &mut TimingRecorder,
+        wire: &mut WireRecorder,
+    ) -> Result<Response> {
+        timing.mark(TimingPhase::FirstByte);
+        timing.enter(TransportPhase::Complete);
+        // fetch不暴露协商出的HTTP版本
+        #[cfg(not(target_arch = "wasm32"))]
+        timing.set_http_version(HttpVersion::from(http_response.version()));
+        if let Some(peer) = self.response_peer(&http_response) {
+            timing.set_peer(peer);
+        }
         
         if !http_response.status().is_success() {
-            return Err(DnsError::Http(format!(
-                "HTTP error: {} {}", 
-                http_response.status().as_u16(),
-                http_response.status().canonical_reason().unwrap_or("Unknown")
-            )));
+            return Err(self.status_error(http_response).await);
         }
         
         let content_type = http_response
@@ -103,103 +349,248 @@ impl HttpsTransport {
             .get("content-type")
             .and_then(|v| v.to_str().ok())
             .unwrap_or("");
+        ensure_dns_message(content_type, &self.display_url)?;
         
-        if !content_type.contains("application/dns-message") {
-            return Err(DnsError::Http(format!(
-                "Invalid content type: expected 'application/dns-message', got '{}'",
-                content_type
-            )));
-        }
-        
-        let body_result = timeout(
-            self.config.base.timeout,
-            http_response.bytes()
-        ).await;
-        
-        let body = match body_result {
-            Ok(Ok(bytes)) => bytes.to_vec(),
-            Ok(Err(e)) => return Err(DnsError::Http(format!("Failed to read response body: {}", e))),
-            Err(_) => return Err(DnsError::Timeout),
-        };
+        let body = self.read_body(http_response).await?;
+        wire.record_response(&body);
         
-        UdpTransport::deserialize_response(&body)
+        UdpTransport::deserialize_response_with_limits(&body, self.config.base.record_limits.as_ref())
+            .and_then(|response| ensure_matching_id(request, response))
     }
     
-    /// 发送POST请求
-    async fn send_post_request(&self, request: &Request) -> Result<Response> {
-        use crate::{dns_debug, dns_info};
-        dns_info!("🌐 DoH POST请求开始: {} -> {}", request.query.name, self.config.url);
-        let dns_data = UdpTransport::serialize_request(request)?;
-        
-        let response = timeout(
-            self.config.base.timeout,
-            self.client
-                .post(&self.config.url)
-                .header("Content-Type", "application/dns-message")
-                .header("Accept", "application/dns-message")
-                .body(dns_data)
-                .send()
-        ).await;
-        
-        let http_response = match response {
-            Ok(Ok(resp)) => resp,
-            Ok(Err(e)) => return Err(DnsError::Http(format!("HTTP request failed: {}", e))),
-            Err(_) => return Err(DnsError::Timeout),
-        };
-        
-        if !http_response.status().is_success() {
-            return Err(DnsError::Http(format!(
-                "HTTP error: {} {}", 
-                http_response.status().as_u16(),
-                http_response.status().canonical_reason().unwrap_or("Unknown")
-            )));
+    /// 承载响应的连接的对端地址；HTTP客户端取不到时用配置的连接地址，以IP配置的上游用配置的IP
+    fn response_peer(&self, http_response: &reqwest::Response) -> Option<SocketAddr> {
+        #[cfg(not(target_arch = "wasm32"))]
+        let remote_addr = http_response.remote_addr();
+        // fetch不暴露对端地址
+        #[cfg(target_arch = "wasm32")]
+        let remote_addr = { let _ = http_response; None };
+        remote_addr.or(self.config.connect_addr).or_else(|| {
+            self.config.base.server.parse::<IpAddr>().ok()
+                .map(|ip| SocketAddr::new(ip, self.config.base.port))
+        })
+    }
+    
+    /// 读取响应正文，Content-Length或已读长度超过 `max_response_size` 时立即中止
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn read_body(&self, mut http_response: reqwest::Response) -> Result<Vec<u8>> {
+        let limit = self.config.max_response_size;
+        let too_large = || DnsError::ResponseTooLarge { limit, upstream: self.display_url.clone() };
+        if http_response.content_length().is_some_and(|length| length > limit as u64) {
+            return Err(too_large());
         }
-        
-        let content_type = http_response
-            .headers()
-            .get("content-type")
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("");
-        
-        if !content_type.contains("application/dns-message") {
-            return Err(DnsError::Http(format!(
-                "Invalid content type: expected 'application/dns-message', got '{}'",
-                content_type
-            )));
+        let mut body = Vec::new();
+        while let Some(chunk) = http_response.chunk().await
+            .map_err(|e| DnsError::Http(format!("Failed to read response body: {}", e)))?
+        {
+            if body.len() + chunk.len() > limit {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+    
+    /// 读取响应正文；fetch的正文不能分块读取，读完后再检查长度
+    #[cfg(target_arch = "wasm32")]
+    async fn read_body(&self, http_response: reqwest::Response) -> Result<Vec<u8>> {
+        let limit = self.config.max_response_size;
+        let too_large = || DnsError::ResponseTooLarge { limit, upstream: self.display_url.clone() };
+        if http_response.content_length().is_some_and(|length| length > limit as u64) {
+            return Err(too_large());
+        }
+        let body = http_response.bytes().await
+            .map_err(|e| DnsError::Http(format!("Failed to read response body: {}", e)))?;
+        if body.len() > limit {
+            return Err(too_large());
+        }
+        Ok(body.to_vec())
+    }
+    
+    /// 请求发送失败：TLS握手失败转换为 [`DnsError::TlsFailure`]，连接超时（`connect_timeout`）转换为 [`DnsError::Timeout`]，
+    /// 其余保持为HTTP错误
+    fn request_error(&self, err: reqwest::Error) -> DnsError {
+        #[cfg(not(target_arch = "wasm32"))]
+        let is_connect = err.is_connect();
+        // fetch失败时只有一个TypeError，分不出连接错误
+        #[cfg(target_arch = "wasm32")]
+        let is_connect = false;
+        if err.is_timeout() {
+            let phase = if is_connect { TransportPhase::TcpConnect } else { TransportPhase::FirstByte };
+            return DnsError::Timeout { phase: Some(phase) };
+        }
+        // reqwest不暴露底层TLS错误类型，只能沿错误链按描述判断
+        let mut chain = err.to_string();
+        let mut source = std::error::Error::source(&err);
+        while let Some(inner) = source {
+            chain.push_str(": ");
+            chain.push_str(&inner.to_string());
+            source = inner.source();
+        }
+        let lower = chain.to_ascii_lowercase();
+        if is_connect && (lower.contains("tls") || lower.contains("ssl") || lower.contains("certificate")) {
+            return DnsError::TlsFailure {
+                kind: TlsErrorKind::from_message(&chain),
+                upstream: self.display_url.clone(),
+            };
         }
+        DnsError::Http(format!("HTTP request failed: {}", chain))
+    }
+    
+    /// 编码请求报文（POST正文，GET时再做base64url编码），OPT记录声明流式传输的载荷大小
+    pub fn encode_request(request: &Request) -> Result<Vec<u8>> {
+        UdpTransport::serialize_request_with_payload_size(request, STREAM_EDNS_PAYLOAD_SIZE)
+    }
+    
+    /// 将编码好的请求报文转为base64url格式(用于GET方法的 `dns` 参数)
+    pub(crate) fn encode_dns_query_base64url(dns_data: &[u8]) -> String {
+        use base64::{Engine as _, engine::general_purpose};
+        general_purpose::URL_SAFE_NO_PAD.encode(dns_data)
+    }
+    
+    /// 发送GET请求，收到响应头时记为首字节
+    async fn send_get_request(&self, request: &Request, timing: &mut TimingRecorder, wire: &mut WireRecorder) -> Result<Response> {
+        use crate::dns_info;
+        dns_info!("🌐 DoH GET请求开始: {} -> {}", request.query.name, self.display_url);
+        let dns_data = Self::encode_request(request)?;
+        wire.record_request(&dns_data);
+        let dns_query = Self::encode_dns_query_base64url(&dns_data);
         
-        let body_result = timeout(
-            self.config.base.timeout,
-            http_response.bytes()
-        ).await;
+        timing.enter(TransportPhase::FirstByte);
+        let http_response = self.client
+            .get(self.request_url.clone())
+            .query(&[("dns", dns_query)])
+            .header("Accept", "application/dns-message")
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+        self.read_dns_response(http_response, request, timing, wire).await
+    }
+    
+    /// 发送POST请求，收到响应头时记为首字节
+    async fn send_post_request(&self, request: &Request, timing: &mut TimingRecorder, wire: &mut WireRecorder) -> Result<Response> {
+        use crate::dns_info;
+        dns_info!("🌐 DoH POST请求开始: {} -> {}", request.query.name, self.display_url);
+        let dns_data = Self::encode_request(request)?;
+        wire.record_request(&dns_data);
         
-        let body = match body_result {
-            Ok(Ok(bytes)) => bytes.to_vec(),
-            Ok(Err(e)) => return Err(DnsError::Http(format!("Failed to read response body: {}", e))),
-            Err(_) => return Err(DnsError::Timeout),
+        timing.enter(TransportPhase::FirstByte);
+        let http_response = self.client
+            .post(self.request_url.clone())
+            .header("Content-Type", "application/dns-message")
+            .header("Accept", "application/dns-message")
+            .body(dns_data)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+        self.read_dns_response(http_response, request, timing, wire).await
+    }
+    
+    /// 通过HTTP/3发送；回退模式下QUIC握手失败时返回 `None`，由调用方改走TCP
+    #[cfg(feature = "doh3")]
+    async fn exchange_http3(
+        &self,
+        http3: &Http3Client,
+        request: &Request,
+        timing: &mut TimingRecorder,
+        wire: &mut WireRecorder,
+    ) -> Result<Option<Response>> {
+        use crate::dns_warn;
+        let fallback = self.config.http_version == HttpVersionPref::H3WithFallback;
+        if fallback && http3.is_suspended() {
+            return Ok(None);
+        }
+        let send_request = match http3.connect(timing).await {
+            Ok(send_request) => send_request,
+            Err(e) if fallback => {
+                dns_warn!("HTTP/3不可用，改用TCP上的DoH: {} ({})", self.display_url, e);
+                http3.suspend();
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
         };
-        
-        UdpTransport::deserialize_response(&body)
+        let response = http3.exchange(send_request, request, self.config.method, timing, wire).await?;
+        timing.set_http_version(HttpVersion::Http3);
+        Ok(Some(response))
+    }
+    
+    /// 发送请求并接收响应；reqwest不暴露连接和握手耗时，走TCP时 `timing` 只记录首字节
+    /// 
+    /// `wire` 记录的是DNS报文本身，不含HTTP头。`timeout` 限制整个过程，超时的错误带有当时所处的阶段：
+    /// 走TCP时收到响应头之前（包括建立连接）都记为 `first_byte`
+    async fn exchange(&self, request: &Request, timing: &mut TimingRecorder, wire: &mut WireRecorder) -> Result<Response> {
+        let deadline = self.clock.now_instant() + self.config.base.timeout;
+        let exchange = self.try_exchange(request, timing, wire);
+        // fetch的future持有JS对象，不是Send；wasm32只有一个线程，包装后满足Transport的Send要求
+        #[cfg(target_arch = "wasm32")]
+        let exchange = send_wrapper::SendWrapper::new(exchange);
+        clock::timeout_at(&*self.clock, deadline, exchange).await
+            .unwrap_or_else(|| Err(timing.timed_out()))
+    }
+    
+    async fn try_exchange(&self, request: &Request, timing: &mut TimingRecorder, wire: &mut WireRecorder) -> Result<Response> {
+        #[cfg(feature = "doh3")]
+        if let Some(http3) = &self.http3 {
+            if let Some(response) = self.exchange_http3(http3, request, timing, wire).await? {
+                return Ok(response);
+            }
+        }
+        match self.config.method {
+            HttpMethod::GET => self.send_get_request(request, timing, wire).await,
+            HttpMethod::POST => self.send_post_request(request, timing, wire).await,
+        }
     }
 }
 
 #[async_trait]
 impl Transport for HttpsTransport {
     async fn send(&self, request: &Request) -> Result<Response> {
-        match self.config.method {
-            HttpMethod::GET => self.send_get_request(request).await,
-            HttpMethod::POST => self.send_post_request(request).await,
-        }
+        self.exchange(request, &mut TimingRecorder::disabled(), &mut WireRecorder::disabled()).await
+    }
+    
+    async fn send_with_peer(&self, request: &Request) -> Result<(Response, Option<SocketAddr>)> {
+        let mut timing = TimingRecorder::disabled();
+        let response = self.exchange(request, &mut timing, &mut WireRecorder::disabled()).await?;
+        Ok((response, timing.finish().peer))
+    }
+    
+    async fn send_timed(&self, request: &Request) -> Result<(Response, TransportTiming)> {
+        let mut timing = TimingRecorder::start(&self.clock);
+        let response = self.exchange(request, &mut timing, &mut WireRecorder::disabled()).await?;
+        Ok((response, timing.finish()))
+    }
+    
+    async fn send_captured(&self, request: &Request, max_bytes: usize) -> Result<(Response, TransportTiming, WireCapture)> {
+        let mut timing = TimingRecorder::start(&self.clock);
+        let mut wire = WireRecorder::with_limit(max_bytes);
+        let response = self.exchange(request, &mut timing, &mut wire).await?;
+        Ok((response, timing.finish(), wire.finish()))
+    }
+    
+    fn encrypted_host(&self) -> Option<String> {
+        let url = url::Url::parse(&self.config.url).ok()?;
+        url.host_str().map(|host| host.trim_start_matches('[').trim_end_matches(']').to_string())
+    }
+    
+    // fetch不能关闭证书校验，wasm32上沿用默认的 `None`
+    #[cfg(not(target_arch = "wasm32"))]
+    fn insecure_variant(&self) -> Result<Option<Arc<dyn Transport>>> {
+        // 不校验证书的QUIC客户端没有实现，降级时只走TCP
+        let http_version = if self.config.http_version.uses_http3() { HttpVersionPref::Auto } else { self.config.http_version };
+        let config = HttpsConfig { http_version, ..self.config.clone() };
+        Ok(Some(Arc::new(Self::build(config, false)?.with_clock(self.clock.clone()))))
     }
     
     fn transport_type(&self) -> &'static str {
         "HTTPS"
     }
     
+    fn endpoint(&self) -> String {
+        redact_url(&self.config.url, "***")
+    }
+    
     fn set_timeout(&mut self, timeout: Duration) {
         self.config.base.timeout = timeout;
-        // 注意: reqwest客户端的超时时间在创建时设置，无法动态修改
-        // 如果需要动态修改超时时间，需要重新创建客户端
     }
     
     fn timeout(&self) -> Duration {
@@ -223,8 +614,717 @@ impl Transport for HttpsTransport {
 //         tcp_fast_open: false,
 //         tcp_nodelay: true,
 //         pool_size: 10,
+//         buffer_size: 4096,
+//         record_limits: None,
 //     },
 //     url: "https://cloudflare-dns.com/dns-query".to_string(),
 //     method: HttpMethod::POST,
 //     user_agent: get_user_agent(),
-// }
\ No newline at end of file
+//     extra_headers: Vec::new(),
+//     http_version: HttpVersionPref::Auto,
+//     max_response_size: 65535,
+//     follow_redirects: true,
+// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::priority::QueryPriority;
+    use crate::transport::TransportConfig;
+    use crate::types::{Flags, Query, QClass, RecordType};
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn config(url: String, method: HttpMethod, extra_headers: Vec<(String, String)>) -> HttpsConfig {
+        HttpsConfig {
+            base: TransportConfig {
+                server: "127.0.0.1".to_string(),
+                port: 443,
+                timeout: Duration::from_secs(3),
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: 1,
+                buffer_size: 4096,
+                record_limits: None,
+            },
+            url,
+            method,
+            user_agent: "rat_quickdns-test".to_string(),
+            extra_headers,
+            query_params: Vec::new(),
+            http_version: HttpVersionPref::Auto,
+            max_response_size: 65535,
+            follow_redirects: true,
+            connect_addr: None,
+        }
+    }
+
+    fn request() -> Request {
+        Request {
+            id: 0x1234,
+            flags: Flags::default(),
+            query: Query {
+                name: "example.com".to_string(),
+                qtype: RecordType::A,
+                qclass: QClass::IN,
+            },
+            client_address: None,
+            enable_edns: false,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: QueryPriority::Normal,
+        }
+    }
+
+    fn auth_headers() -> Vec<(String, String)> {
+        vec![
+            ("Authorization".to_string(), "Bearer s3cr3t-token".to_string()),
+            ("X-Client-Id".to_string(), "probe-7".to_string()),
+        ]
+    }
+
+    async fn mock_doh_server(http_method: &str) -> MockServer {
+        mock_doh_server_expecting(http_method, 1).await
+    }
+
+    /// 模拟服务器返回的DNS报文
+    fn mock_doh_response_body() -> Vec<u8> {
+        UdpTransport::serialize_response(&Response {
+            id: 0x1234,
+            flags: Flags { qr: true, ..Flags::default() },
+            queries: vec![request().query],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        }).unwrap()
+    }
+
+    async fn mock_doh_server_expecting(http_method: &str, requests: u64) -> MockServer {
+        let server = MockServer::start().await;
+        let body = mock_doh_response_body();
+        Mock::given(method(http_method))
+            .and(path("/dns-query"))
+            .and(header("authorization", "Bearer s3cr3t-token"))
+            .and(header("x-client-id", "probe-7"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "application/dns-message")
+                    .set_body_bytes(body),
+            )
+            .expect(requests)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_extra_headers_sent_with_post() {
+        let server = mock_doh_server("POST").await;
+        let transport = HttpsTransport::new(config(
+            format!("{}/dns-query", server.uri()),
+            HttpMethod::POST,
+            auth_headers(),
+        )).unwrap();
+
+        let response = transport.send(&request()).await.unwrap();
+        assert_eq!(response.id, 0x1234);
+    }
+
+    #[tokio::test]
+    async fn test_extra_headers_sent_with_get() {
+        let server = mock_doh_server("GET").await;
+        let transport = HttpsTransport::new(config(
+            format!("{}/dns-query", server.uri()),
+            HttpMethod::GET,
+            auth_headers(),
+        )).unwrap();
+
+        assert!(transport.send(&request()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_http_status_preserved_in_error() {
+        let server = MockServer::start().await;
+        for (status, body) in [(429, "rate limited, slow down"), (503, ""), (400, "bad dns message")] {
+            Mock::given(method("POST"))
+                .and(path(format!("/status-{}", status)))
+                .respond_with(ResponseTemplate::new(status).set_body_string(body))
+                .mount(&server)
+                .await;
+        }
+
+        let send = |status: u16| {
+            let url = format!("{}/status-{}", server.uri(), status);
+            async move {
+                HttpsTransport::new(config(url, HttpMethod::POST, Vec::new()))
+                    .unwrap()
+                    .send(&request())
+                    .await
+                    .unwrap_err()
+            }
+        };
+
+        let err = send(429).await;
+        assert!(matches!(
+            &err,
+            DnsError::HttpStatus { status: 429, upstream, body_snippet: Some(body), .. }
+                if upstream.ends_with("/status-429") && body == "rate limited, slow down"
+        ));
+        assert_eq!(err.retry_advice(), crate::error::RetryAdvice::Backoff);
+
+        let err = send(503).await;
+        assert!(matches!(err, DnsError::HttpStatus { status: 503, body_snippet: None, .. }));
+        assert_eq!(err.retry_advice(), crate::error::RetryAdvice::Backoff);
+
+        let err = send(400).await;
+        assert!(matches!(err, DnsError::HttpStatus { status: 400, .. }));
+        assert_eq!(err.retry_advice(), crate::error::RetryAdvice::Fatal);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc2822("Sun, 06 Nov 1994 08:49:00 GMT").unwrap().with_timezone(&Utc);
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        // IMF-fixdate、RFC 850和asctime三种HTTP日期格式
+        for date in ["Sun, 06 Nov 1994 08:49:37 GMT", "Sunday, 06-Nov-94 08:49:37 GMT", "Sun Nov  6 08:49:37 1994"] {
+            assert_eq!(parse_retry_after(date, now), Some(Duration::from_secs(37)), "{}", date);
+        }
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:48:00 GMT", now), Some(Duration::ZERO));
+        for invalid in ["", "-1", "1.5", "soon"] {
+            assert_eq!(parse_retry_after(invalid, now), None, "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_kept_in_rate_limit_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "7"))
+            .mount(&server)
+            .await;
+        let err = HttpsTransport::new(config(server.uri(), HttpMethod::POST, Vec::new())).unwrap()
+            .send(&request()).await.unwrap_err();
+        assert!(err.is_rate_limited());
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(7)));
+        assert!(err.to_string().contains("retry after"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_status_codes_map_to_retry_advice() {
+        use crate::error::RetryAdvice;
+        let server = MockServer::start().await;
+        for status in [404, 415, 502] {
+            Mock::given(method("POST"))
+                .and(path(format!("/status-{}", status)))
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&server)
+                .await;
+        }
+        for (status, advice) in [(404, RetryAdvice::Fatal), (415, RetryAdvice::Fatal), (502, RetryAdvice::Retry)] {
+            let url = format!("{}/status-{}", server.uri(), status);
+            let err = HttpsTransport::new(config(url, HttpMethod::POST, Vec::new())).unwrap()
+                .send(&request()).await.unwrap_err();
+            assert!(matches!(err, DnsError::HttpStatus { status: s, .. } if s == status), "{:?}", err);
+            assert_eq!(err.retry_advice(), advice);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_html_error_page_is_not_parsed() {
+        // 正文本身是合法的DNS报文：只要交给了解析器，查询就会成功
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/dns-query"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "text/html; charset=utf-8")
+                    .set_body_bytes(mock_doh_response_body()),
+            )
+            .mount(&server)
+            .await;
+
+        let transport = HttpsTransport::new(config(format!("{}/dns-query", server.uri()), HttpMethod::POST, Vec::new())).unwrap();
+        let err = transport.send(&request()).await.unwrap_err();
+        assert!(matches!(
+            &err,
+            DnsError::UnexpectedContentType { content_type, upstream }
+                if content_type == "text/html; charset=utf-8" && upstream.ends_with("/dns-query")
+        ), "{:?}", err);
+        assert_eq!(err.retry_advice(), crate::error::RetryAdvice::Unavailable);
+
+        assert!(ensure_dns_message("Application/DNS-Message; charset=binary", "u").is_ok());
+        assert!(ensure_dns_message("application/dns-message-html", "u").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_is_rejected_before_parsing() {
+        let server = MockServer::start().await;
+        let mut body = mock_doh_response_body();
+        body.resize(600, 0);
+        Mock::given(method("POST"))
+            .and(path("/dns-query"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "application/dns-message")
+                    .set_body_bytes(body),
+            )
+            .mount(&server)
+            .await;
+
+        let mut small = config(format!("{}/dns-query", server.uri()), HttpMethod::POST, Vec::new());
+        small.max_response_size = 512;
+        let err = HttpsTransport::new(small).unwrap().send(&request()).await.unwrap_err();
+        assert!(matches!(err, DnsError::ResponseTooLarge { limit: 512, .. }), "{:?}", err);
+
+        let large = config(format!("{}/dns-query", server.uri()), HttpMethod::POST, Vec::new());
+        assert!(HttpsTransport::new(large).unwrap().send(&request()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_redirects_follow_same_origin_only() {
+        let server = MockServer::start().await;
+        let elsewhere = MockServer::start().await;
+        let redirect = |location: String| ResponseTemplate::new(307).insert_header("location", location.as_str());
+        let routes = [
+            ("/one-hop", format!("{}/dns-query", server.uri())),
+            ("/hop-1", format!("{}/hop-2", server.uri())),
+            ("/hop-2", format!("{}/hop-3", server.uri())),
+            ("/hop-3", format!("{}/dns-query", server.uri())),
+            ("/cross-origin", format!("{}/dns-query", elsewhere.uri())),
+        ];
+        for (from, to) in routes {
+            Mock::given(path(from)).respond_with(redirect(to)).mount(&server).await;
+        }
+        Mock::given(path("/dns-query"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "application/dns-message")
+                    .set_body_bytes(mock_doh_response_body()),
+            )
+            .mount(&server)
+            .await;
+
+        let send = |route: &str, follow_redirects: bool| {
+            let mut https = config(format!("{}{}", server.uri(), route), HttpMethod::POST, Vec::new());
+            https.follow_redirects = follow_redirects;
+            async move { HttpsTransport::new(https).unwrap().send(&request()).await }
+        };
+
+        assert!(send("/one-hop", true).await.is_ok());
+        assert!(matches!(send("/one-hop", false).await, Err(DnsError::HttpStatus { status: 307, .. })));
+        // 第3跳不再跟随
+        assert!(matches!(send("/hop-1", true).await, Err(DnsError::HttpStatus { status: 307, .. })));
+        assert!(matches!(send("/cross-origin", true).await, Err(DnsError::HttpStatus { status: 307, .. })));
+        assert!(elsewhere.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_captured_wire_is_dns_message_without_http_envelope() {
+        use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+        
+        for (http_method, name) in [(HttpMethod::POST, "POST"), (HttpMethod::GET, "GET")] {
+            let server = mock_doh_server(name).await;
+            let transport = HttpsTransport::new(config(
+                format!("{}/dns-query", server.uri()),
+                http_method,
+                auth_headers(),
+            )).unwrap();
+
+            let (_, _, wire) = transport.send_captured(&request(), 4096).await.unwrap();
+            let received = server.received_requests().await.unwrap().remove(0);
+            let sent_message = match http_method {
+                HttpMethod::POST => received.body,
+                HttpMethod::GET => {
+                    let (_, dns) = received.url.query_pairs().find(|(key, _)| key == "dns").unwrap();
+                    URL_SAFE_NO_PAD.decode(dns.as_bytes()).unwrap()
+                }
+            };
+            assert_eq!(wire.request, sent_message);
+            assert_eq!(wire.response, mock_doh_response_body());
+        }
+    }
+
+    #[test]
+    fn test_invalid_headers_rejected() {
+        let bad_name = vec![("X Client".to_string(), "1".to_string())];
+        assert!(matches!(build_header_map(&bad_name), Err(DnsError::InvalidConfig(_))));
+
+        let injected = vec![("X-Client-Id".to_string(), "1\r\nHost: evil".to_string())];
+        assert!(matches!(build_header_map(&injected), Err(DnsError::InvalidConfig(_))));
+
+        let result = HttpsTransport::new(config("https://dns.example/dns-query".to_string(), HttpMethod::POST, bad_name));
+        assert!(result.is_err());
+
+        let host = vec![("host".to_string(), "192.0.2.1".to_string())];
+        assert!(matches!(build_header_map(&host), Err(DnsError::InvalidConfig(_))));
+    }
+
+    /// 本地TLS上的HTTP/1.1 DoH服务器，握手时出示 `cert`，应答一次查询后返回收到的Host请求头；握手失败时返回 `None`
+    #[cfg(feature = "dot")]
+    async fn spawn_tls_doh_server(cert: &'static [u8], key: &'static [u8]) -> (u16, tokio::task::JoinHandle<Option<String>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![Certificate(cert.to_vec())], PrivateKey(key.to_vec()))
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.ok()?;
+            // 读完请求头，再按Content-Length读完正文
+            let mut buffer = Vec::new();
+            let mut chunk = [0u8; 1024];
+            let header_end = loop {
+                let read = stream.read(&mut chunk).await.ok().filter(|&read| read > 0)?;
+                buffer.extend_from_slice(&chunk[..read]);
+                if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+                    break end + 4;
+                }
+            };
+            let head = String::from_utf8_lossy(&buffer[..header_end]).into_owned();
+            let header = |name: &str| head.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+            });
+            let length: usize = header("content-length")?.parse().ok()?;
+            while buffer.len() < header_end + length {
+                let read = stream.read(&mut chunk).await.ok().filter(|&read| read > 0)?;
+                buffer.extend_from_slice(&chunk[..read]);
+            }
+            let query = UdpTransport::deserialize_request(&buffer[header_end..header_end + length]).ok()?;
+            let answer = UdpTransport::serialize_response(&Response {
+                id: query.id,
+                flags: Flags { qr: true, ..Flags::default() },
+                queries: vec![query.query],
+                answers: vec![],
+                authorities: vec![],
+                additionals: vec![],
+            }).unwrap();
+            let response_head = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                answer.len()
+            );
+            stream.write_all(response_head.as_bytes()).await.ok()?;
+            stream.write_all(&answer).await.ok()?;
+            let _ = stream.shutdown().await;
+            header("host")
+        });
+        (port, server)
+    }
+
+    /// 以 `doh.test` 为主机名、只能经连接地址到达本地服务器的传输，信任两张测试证书
+    #[cfg(feature = "dot")]
+    fn transport_dialing(port: u16) -> HttpsTransport {
+        let mut https = config(format!("https://doh.test:{}/dns-query", port), HttpMethod::POST, Vec::new());
+        https.base.server = "doh.test".to_string();
+        https.base.port = port;
+        https.connect_addr = Some(SocketAddr::from(([127, 0, 0, 1], port)));
+        let roots = [
+            reqwest::Certificate::from_der(include_bytes!("testdata/doh.test.crt.der")).unwrap(),
+            reqwest::Certificate::from_der(include_bytes!("testdata/localhost.crt.der")).unwrap(),
+        ];
+        HttpsTransport::build_with_roots(https, true, &roots).unwrap()
+    }
+
+    #[cfg(feature = "dot")]
+    #[tokio::test]
+    async fn test_connect_addr_keeps_hostname_for_tls_and_host_header() {
+        let (port, server) = spawn_tls_doh_server(
+            include_bytes!("testdata/doh.test.crt.der"),
+            include_bytes!("testdata/doh.test.key.der"),
+        ).await;
+
+        let (response, peer) = transport_dialing(port).send_with_peer(&request()).await.unwrap();
+        assert_eq!(response.id, 0x1234);
+        assert_eq!(peer, Some(SocketAddr::from(([127, 0, 0, 1], port))));
+        assert_eq!(server.await.unwrap(), Some(format!("doh.test:{}", port)));
+    }
+
+    #[cfg(feature = "dot")]
+    #[tokio::test]
+    async fn test_connect_addr_rejects_certificate_for_other_name() {
+        // 证书受信任、IP也对得上，但签给的是localhost而不是URL中的doh.test
+        let (port, server) = spawn_tls_doh_server(
+            include_bytes!("testdata/localhost.crt.der"),
+            include_bytes!("testdata/localhost.key.der"),
+        ).await;
+
+        let err = transport_dialing(port).send(&request()).await.unwrap_err();
+        assert!(matches!(err, DnsError::TlsFailure { .. }), "{:?}", err);
+        assert_eq!(server.await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_connect_addr_must_match_url() {
+        let with_addr = |url: &str, address: &str| {
+            let mut https = config(url.to_string(), HttpMethod::POST, Vec::new());
+            https.connect_addr = Some(address.parse().unwrap());
+            HttpsTransport::new(https)
+        };
+        assert!(with_addr("https://dns.example/dns-query", "192.0.2.1:443").is_ok());
+        assert!(with_addr("https://dns.example:8443/dns-query", "192.0.2.1:8443").is_ok());
+        assert!(with_addr("https://192.0.2.1/dns-query", "192.0.2.1:443").is_ok());
+        for (url, address) in [
+            ("https://dns.example/dns-query", "192.0.2.1:8443"),
+            ("https://192.0.2.1/dns-query", "192.0.2.2:443"),
+        ] {
+            assert!(matches!(with_addr(url, address), Err(DnsError::InvalidConfig(_))), "{} {}", url, address);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalls_end_within_timeout_and_report_phase() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        // 从不应答时停在等待响应头，响应头之后正文不来时停在读取响应的其余部分
+        for (head, phase) in [
+            (&b""[..], TransportPhase::FirstByte),
+            (&b"HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\ncontent-length: 64\r\n\r\n"[..], TransportPhase::Complete),
+        ] {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = [0u8; 1024];
+                let _ = stream.read(&mut buffer).await;
+                let _ = stream.write_all(head).await;
+                std::future::pending::<()>().await;
+            });
+            let mut https = config(format!("http://127.0.0.1:{}/dns-query", port), HttpMethod::POST, Vec::new());
+            https.base.timeout = Duration::from_millis(300);
+            let transport = HttpsTransport::new(https).unwrap();
+            
+            let start = crate::time::Instant::now();
+            let err = transport.send(&request()).await.unwrap_err();
+            let elapsed = start.elapsed();
+            assert!(matches!(err, DnsError::Timeout { phase: Some(p) } if p == phase), "{:?}", err);
+            assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_millis(550), "{:?}", elapsed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_response_reports_http_version() {
+        let server = mock_doh_server("POST").await;
+        let transport = HttpsTransport::new(config(
+            format!("{}/dns-query", server.uri()),
+            HttpMethod::POST,
+            auth_headers(),
+        )).unwrap();
+
+        let (_, timing) = transport.send_timed(&request()).await.unwrap();
+        assert_eq!(timing.http_version, Some(HttpVersion::Http1));
+    }
+
+    #[tokio::test]
+    async fn test_peer_is_the_connection_remote_address() {
+        // 配置中的地址是127.0.0.1:443，报告的必须是实际连接的对端
+        for _ in 0..2 {
+            let server = mock_doh_server("POST").await;
+            let transport = HttpsTransport::new(config(
+                format!("{}/dns-query", server.uri()),
+                HttpMethod::POST,
+                auth_headers(),
+            )).unwrap();
+
+            let (_, peer) = transport.send_with_peer(&request()).await.unwrap();
+            assert_eq!(peer, Some(*server.address()));
+        }
+    }
+
+    #[cfg(not(feature = "doh3"))]
+    #[test]
+    fn test_http3_requires_feature() {
+        let mut h3 = config("https://dns.example/dns-query".to_string(), HttpMethod::POST, Vec::new());
+        h3.http_version = HttpVersionPref::H3WithFallback;
+        assert!(matches!(HttpsTransport::new(h3), Err(DnsError::InvalidConfig(_))));
+    }
+
+    /// 本地HTTP/3 DoH服务器（自签名的localhost证书），对任何查询回应空答案
+    #[cfg(feature = "doh3")]
+    fn spawn_http3_server() -> u16 {
+        use bytes::{Buf, Bytes};
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+        let mut crypto = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(include_bytes!("testdata/localhost.crt.der").to_vec())],
+                PrivateKey(include_bytes!("testdata/localhost.key.der").to_vec()),
+            )
+            .unwrap();
+        crypto.alpn_protocols = vec![b"h3".to_vec()];
+        let endpoint = quinn::Endpoint::server(
+            quinn::ServerConfig::with_crypto(std::sync::Arc::new(crypto)),
+            "127.0.0.1:0".parse().unwrap(),
+        ).unwrap();
+        let port = endpoint.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            while let Some(connecting) = endpoint.accept().await {
+                tokio::spawn(async move {
+                    let Ok(connection) = connecting.await else { return };
+                    let Ok(mut h3_connection) = h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(connection)).await else {
+                        return;
+                    };
+                    while let Ok(Some((_, mut stream))) = h3_connection.accept().await {
+                        let mut body = Vec::new();
+                        while let Ok(Some(mut chunk)) = stream.recv_data().await {
+                            body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+                        }
+                        let query = UdpTransport::deserialize_request(&body).unwrap();
+                        let answer = UdpTransport::serialize_response(&Response {
+                            id: query.id,
+                            flags: Flags { qr: true, ..Flags::default() },
+                            queries: vec![query.query],
+                            answers: vec![],
+                            authorities: vec![],
+                            additionals: vec![],
+                        }).unwrap();
+                        let head = http::Response::builder()
+                            .status(200)
+                            .header("content-type", "application/dns-message")
+                            .body(())
+                            .unwrap();
+                        stream.send_response(head).await.unwrap();
+                        stream.send_data(Bytes::from(answer)).await.unwrap();
+                        stream.finish().await.unwrap();
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    #[cfg(feature = "doh3")]
+    #[tokio::test]
+    async fn test_http3_query_against_local_server() {
+        let port = spawn_http3_server();
+        let mut h3 = config(format!("https://localhost:{}/dns-query", port), HttpMethod::POST, Vec::new());
+        h3.base.port = port;
+        h3.http_version = HttpVersionPref::H3Only;
+
+        // 信任测试用的自签名证书
+        let mut roots = tokio_rustls::rustls::RootCertStore::empty();
+        roots.add(&tokio_rustls::rustls::Certificate(include_bytes!("testdata/localhost.crt.der").to_vec())).unwrap();
+        let mut transport = HttpsTransport::new(h3.clone()).unwrap();
+        transport.http3 = Some(Http3Client::new(&h3, roots, Duration::from_secs(2)).unwrap());
+
+        for _ in 0..2 {
+            let (response, timing) = transport.send_timed(&request()).await.unwrap();
+            assert_eq!(response.id, 0x1234);
+            assert_eq!(timing.http_version, Some(HttpVersion::Http3));
+        }
+    }
+
+    #[cfg(feature = "doh3")]
+    #[tokio::test]
+    async fn test_blocked_quic_falls_back_to_tcp() {
+        let server = mock_doh_server_expecting("POST", 2).await;
+        let address = *server.address();
+        // 占住同一端口的UDP并丢弃所有数据报，模拟UDP被拦截
+        let _blackhole = tokio::net::UdpSocket::bind(address).await.unwrap();
+
+        let mut fallback = config(format!("{}/dns-query", server.uri()), HttpMethod::POST, auth_headers());
+        fallback.base.port = address.port();
+        fallback.http_version = HttpVersionPref::H3WithFallback;
+        let transport = HttpsTransport::new(fallback).unwrap();
+
+        let (response, timing) = transport.send_timed(&request()).await.unwrap();
+        assert_eq!(response.id, 0x1234);
+        assert_eq!(timing.http_version, Some(HttpVersion::Http1));
+
+        // 暂停期内不再等待QUIC握手
+        let started = crate::time::Instant::now();
+        let (_, timing) = transport.send_timed(&request()).await.unwrap();
+        assert_eq!(timing.http_version, Some(HttpVersion::Http1));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let mut strict = config(format!("{}/dns-query", server.uri()), HttpMethod::POST, Vec::new());
+        strict.base.port = address.port();
+        strict.http_version = HttpVersionPref::H3Only;
+        assert!(HttpsTransport::new(strict).unwrap().send(&request()).await.is_err());
+    }
+
+    #[test]
+    fn test_sensitive_header_redaction() {
+        assert_eq!(redact_header_value("Authorization", "Bearer s3cr3t-token"), "Bear***");
+        assert_eq!(redact_header_value("X-Auth-Token", "abcdef"), "abcd***");
+        assert_eq!(redact_header_value("X-Client-Id", "probe-7"), "probe-7");
+    }
+
+    #[tokio::test]
+    async fn test_query_params_merged_into_request_url() {
+        let server = MockServer::start().await;
+        Mock::given(path("/v1/abc123"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "application/dns-message")
+                    .set_body_bytes(mock_doh_response_body()),
+            )
+            .mount(&server)
+            .await;
+        let params = vec![
+            ("key".to_string(), "s3cr3t value&x".to_string()),
+            ("filter".to_string(), "family".to_string()),
+        ];
+        let dns = HttpsTransport::encode_dns_query_base64url(&HttpsTransport::encode_request(&request()).unwrap());
+        let cases = [
+            ("/v1/abc123?profile=home", HttpMethod::POST, "profile=home&key=s3cr3t+value%26x&filter=family".to_string()),
+            ("/v1/abc123?profile=home", HttpMethod::GET, format!("profile=home&key=s3cr3t+value%26x&filter=family&dns={}", dns)),
+            ("/v1/abc123", HttpMethod::POST, "key=s3cr3t+value%26x&filter=family".to_string()),
+            ("/v1/abc123", HttpMethod::GET, format!("key=s3cr3t+value%26x&filter=family&dns={}", dns)),
+        ];
+
+        for (endpoint, http_method, expected_query) in cases {
+            let mut https = config(format!("{}{}", server.uri(), endpoint), http_method, Vec::new());
+            https.query_params = params.clone();
+            let transport = HttpsTransport::new(https).unwrap();
+            assert!(transport.send(&request()).await.is_ok());
+
+            let received = server.received_requests().await.unwrap().pop().unwrap();
+            assert_eq!(received.url.path(), "/v1/abc123");
+            assert_eq!(received.url.query(), Some(expected_query.as_str()), "{:?} {}", http_method, endpoint);
+            assert!(!transport.display_url.contains("s3cr3t"), "{}", transport.display_url);
+        }
+    }
+
+    #[test]
+    fn test_doh_url_validation() {
+        let params = |name: &str| vec![(name.to_string(), "1".to_string())];
+        let error = |result: Result<()>| match result {
+            Err(DnsError::InvalidConfig(message)) => message,
+            other => panic!("unexpected result: {:?}", other),
+        };
+
+        assert!(validate_doh_url("https://dns.example:8443/v1/abc?profile=home", &params("key")).is_ok());
+        assert!(error(validate_doh_url("http://dns.example/dns-query?key=s3cr3t", &[])).contains("must use https, got 'http'"));
+        assert!(error(validate_doh_url("dns.example/dns-query", &[])).contains("Invalid DoH URL"));
+        assert!(error(validate_doh_url("https://dns.example/dns-query#frag", &[])).contains("fragment"));
+        assert!(error(validate_doh_url("https://dns.example/dns-query?dns=AAAB", &[])).contains("'dns' query parameter"));
+        assert!(error(validate_doh_url("https://dns.example/dns-query", &params(""))).contains("name cannot be empty"));
+        assert!(error(validate_doh_url("https://dns.example/dns-query", &params("dns"))).contains("reserved"));
+        // 错误信息中不出现认证参数的值
+        assert!(!error(validate_doh_url("http://dns.example/dns-query?key=s3cr3t", &[])).contains("s3cr3t"));
+    }
+
+    #[test]
+    fn test_sensitive_param_redaction() {
+        assert_eq!(
+            redact_url("https://dns.example/q?api_key=s3cr3t&filter=family&Token=abc#x", "***"),
+            "https://dns.example/q?api_key=***&filter=family&Token=***#x"
+        );
+        assert_eq!(redact_url("https://dns.example/dns-query", "***"), "https://dns.example/dns-query");
+
+        let transport = HttpsTransport::new(config(
+            "https://dns.example/dns-query?key=s3cr3t".to_string(),
+            HttpMethod::POST,
+            Vec::new(),
+        )).unwrap();
+        assert_eq!(transport.endpoint(), "https://dns.example/dns-query?key=***");
+    }
+}
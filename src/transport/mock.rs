@@ -0,0 +1,415 @@
+//! 内存模拟传输（需要启用 `test-util` 特性）
+//!
+//! 为下游crate提供不依赖网络的 [`Transport`] 实现：按 (域名, 记录类型) 预置响应，
+//! 可配置人为延迟、失败率和接下来若干次调用的失败，记录每一次调用供断言，并可在运行时切换健康状态。
+//!
+//! ```ignore
+//! use rat_quickdns::transport::mock::MockTransport;
+//! use std::net::Ipv4Addr;
+//!
+//! let mock = MockTransport::new()
+//!     .with_a("example.com", &[Ipv4Addr::new(93, 184, 216, 34)], 300)
+//!     .with_latency(std::time::Duration::from_millis(20));
+//! let handle = mock.clone(); // 克隆体共享脚本、调用记录和健康状态
+//!
+//! let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+//!     .add_mock_upstream("mock", mock)?
+//!     .build()
+//!     .await?;
+//! resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await?;
+//! assert_eq!(handle.call_count(), 1);
+//!
+//! handle.set_healthy(false); // 之后的查询返回网络错误
+//! ```
+//!
+//! [`MockBootstrap`] 按固定映射解析上游主机名，用于测试以主机名配置的上游。
+
+use super::Transport;
+use super::upstream_addr::BootstrapResolver;
+use crate::dns_response::DnsResponseWrapper;
+use crate::types::{QClass, RecordType, Request, Response};
+use crate::utils::normalize_name;
+use crate::{DnsError, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use crate::time::Instant;
+
+/// 一次被记录的调用
+#[derive(Debug, Clone)]
+pub struct MockCall {
+    /// 收到的请求
+    pub request: Request,
+    /// 收到请求的时间
+    pub at: Instant,
+}
+
+#[derive(Debug, Clone)]
+enum MockReply {
+    Response(Response),
+    Error(DnsError),
+}
+
+#[derive(Debug)]
+struct MockState {
+    replies: Mutex<HashMap<(String, RecordType, QClass), MockReply>>,
+    latency: Mutex<Duration>,
+    failure_rate: Mutex<f64>,
+    scripted_failures: AtomicUsize,
+    healthy: AtomicBool,
+    calls: Mutex<Vec<MockCall>>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+/// 一次调用处理期间计入 `in_flight`，调用结束或被取消时减去
+struct InFlightGuard<'a>(&'a MockState);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// 内存模拟传输
+///
+/// 克隆体共享同一份脚本、调用记录和健康状态：把一个克隆交给解析器，
+/// 保留另一个用于断言和运行时调整。超时时间不共享，默认5秒。
+#[derive(Debug, Clone)]
+pub struct MockTransport {
+    endpoint: String,
+    transport_type: &'static str,
+    timeout: Duration,
+    state: Arc<MockState>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn reply_key(name: &str, rtype: RecordType, qclass: QClass) -> (String, RecordType, QClass) {
+    (normalize_name(name), rtype, qclass)
+}
+
+impl MockTransport {
+    /// 创建没有任何预置响应的模拟传输
+    pub fn new() -> Self {
+        Self {
+            endpoint: "mock".to_string(),
+            transport_type: "MOCK",
+            timeout: Duration::from_secs(5),
+            state: Arc::new(MockState {
+                replies: Mutex::new(HashMap::new()),
+                latency: Mutex::new(Duration::ZERO),
+                failure_rate: Mutex::new(0.0),
+                scripted_failures: AtomicUsize::new(0),
+                healthy: AtomicBool::new(true),
+                calls: Mutex::new(Vec::new()),
+                in_flight: AtomicUsize::new(0),
+                max_in_flight: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// 设置端点名称（出现在日志和 `endpoint()` 中）
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// 设置报告的传输协议（默认为 `"MOCK"`），用于模拟明文或加密上游，例如 `"UDP"`、`"TLS"`
+    pub fn with_transport_type(mut self, transport_type: &'static str) -> Self {
+        self.transport_type = transport_type;
+        self
+    }
+
+    /// 预置 (域名, 记录类型) 的响应，响应ID改写为请求ID，问题名按请求的大小写回显
+    pub fn with_response(self, name: &str, rtype: RecordType, response: Response) -> Self {
+        self.set_response(name, rtype, response);
+        self
+    }
+
+    /// 预置A记录响应
+    pub fn with_a(self, name: &str, ips: &[Ipv4Addr], ttl: u32) -> Self {
+        let response = DnsResponseWrapper::create_a_response(0, name, ips, ttl);
+        self.with_response(name, RecordType::A, response)
+    }
+
+    /// 预置AAAA记录响应
+    pub fn with_aaaa(self, name: &str, ips: &[Ipv6Addr], ttl: u32) -> Self {
+        let response = DnsResponseWrapper::create_aaaa_response(0, name, ips, ttl);
+        self.with_response(name, RecordType::AAAA, response)
+    }
+
+    /// 预置NXDOMAIN响应
+    pub fn with_nxdomain(self, name: &str, rtype: RecordType) -> Self {
+        let response = DnsResponseWrapper::create_nxdomain_response(0, name, rtype);
+        self.with_response(name, rtype, response)
+    }
+
+    /// 预置传输错误
+    pub fn with_error(self, name: &str, rtype: RecordType, error: DnsError) -> Self {
+        lock(&self.state.replies).insert(reply_key(name, rtype, QClass::IN), MockReply::Error(error));
+        self
+    }
+
+    /// 设置每次调用的人为延迟
+    pub fn with_latency(self, latency: Duration) -> Self {
+        self.set_latency(latency);
+        self
+    }
+
+    /// 设置随机失败率（0.0-1.0），失败时返回网络错误
+    pub fn with_failure_rate(self, rate: f64) -> Self {
+        self.set_failure_rate(rate);
+        self
+    }
+
+    /// 让接下来的 `count` 次调用返回网络错误，之后恢复正常
+    pub fn with_failures(self, count: usize) -> Self {
+        self.fail_next(count);
+        self
+    }
+
+    /// 运行时设置接下来返回网络错误的调用次数（覆盖尚未用完的次数）
+    pub fn fail_next(&self, count: usize) {
+        self.state.scripted_failures.store(count, Ordering::SeqCst);
+    }
+
+    /// 运行时替换 (域名, 记录类型) 的响应
+    pub fn set_response(&self, name: &str, rtype: RecordType, response: Response) {
+        self.set_class_response(name, rtype, QClass::IN, response);
+    }
+
+    /// 预置非IN类别（例如CH）的 (域名, 记录类型) 响应
+    pub fn with_class_response(self, name: &str, rtype: RecordType, qclass: QClass, response: Response) -> Self {
+        self.set_class_response(name, rtype, qclass, response);
+        self
+    }
+
+    /// 运行时替换指定类别的 (域名, 记录类型) 的响应
+    pub fn set_class_response(&self, name: &str, rtype: RecordType, qclass: QClass, response: Response) {
+        lock(&self.state.replies).insert(reply_key(name, rtype, qclass), MockReply::Response(response));
+    }
+
+    /// 运行时调整延迟
+    pub fn set_latency(&self, latency: Duration) {
+        *lock(&self.state.latency) = latency;
+    }
+
+    /// 运行时调整失败率（超出0.0-1.0的值会被截断）
+    pub fn set_failure_rate(&self, rate: f64) {
+        *lock(&self.state.failure_rate) = rate.clamp(0.0, 1.0);
+    }
+
+    /// 切换健康状态；不健康时所有调用都返回网络错误
+    pub fn set_healthy(&self, healthy: bool) {
+        self.state.healthy.store(healthy, Ordering::Relaxed);
+    }
+
+    /// 当前是否健康
+    pub fn is_healthy(&self) -> bool {
+        self.state.healthy.load(Ordering::Relaxed)
+    }
+
+    /// 全部调用记录，按到达顺序排列
+    pub fn calls(&self) -> Vec<MockCall> {
+        lock(&self.state.calls).clone()
+    }
+
+    /// 调用次数
+    pub fn call_count(&self) -> usize {
+        lock(&self.state.calls).len()
+    }
+
+    /// 同时处理中的调用数曾达到的最大值
+    pub fn max_in_flight(&self) -> usize {
+        self.state.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// 清空调用记录
+    pub fn clear_calls(&self) {
+        lock(&self.state.calls).clear();
+    }
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Transport for MockTransport {
+    async fn send(&self, request: &Request) -> Result<Response> {
+        lock(&self.state.calls).push(MockCall {
+            request: request.clone(),
+            at: Instant::now(),
+        });
+        let in_flight = self.state.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.state.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        let _in_flight = InFlightGuard(&self.state);
+
+        let latency = *lock(&self.state.latency);
+        if latency > self.timeout {
+            tokio::time::sleep(self.timeout).await;
+            return Err(DnsError::Timeout { phase: None });
+        }
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+
+        if !self.is_healthy() {
+            return Err(DnsError::network(self.endpoint.clone(), "mock upstream is marked unhealthy"));
+        }
+        if self.state.scripted_failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+            return Err(DnsError::network(self.endpoint.clone(), "mock upstream scripted failure"));
+        }
+        let failure_rate = *lock(&self.state.failure_rate);
+        if failure_rate > 0.0 && rand::random::<f64>() < failure_rate {
+            return Err(DnsError::network(self.endpoint.clone(), "mock upstream simulated failure"));
+        }
+
+        let reply = lock(&self.state.replies)
+            .get(&reply_key(&request.query.name, request.query.qtype, request.query.qclass))
+            .cloned();
+        match reply {
+            Some(MockReply::Response(mut response)) => {
+                response.id = request.id;
+                for query in response.queries.iter_mut().filter(|query| query.name.eq_ignore_ascii_case(&request.query.name)) {
+                    query.name = request.query.name.clone();
+                }
+                Ok(response)
+            },
+            Some(MockReply::Error(error)) => Err(error),
+            None => Err(DnsError::NotImplemented(format!(
+                "mock upstream {} has no scripted response for {} {:?}",
+                self.endpoint, request.query.name, request.query.qtype
+            ))),
+        }
+    }
+
+    fn transport_type(&self) -> &'static str {
+        self.transport_type
+    }
+
+    fn endpoint(&self) -> String {
+        self.endpoint.clone()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+/// 按固定映射解析主机名的引导解析器，克隆体共享映射和调用计数
+#[derive(Debug, Clone, Default)]
+pub struct MockBootstrap {
+    hosts: Arc<Mutex<HashMap<String, Vec<IpAddr>>>>,
+    calls: Arc<AtomicUsize>,
+}
+
+impl MockBootstrap {
+    /// 创建没有任何映射的引导解析器，所有主机名都解析失败
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 把主机名映射到给定地址
+    pub fn with_host(self, host: &str, ips: &[IpAddr]) -> Self {
+        self.set_host(host, ips);
+        self
+    }
+
+    /// 运行时修改主机名的映射（模拟上游换了地址）
+    pub fn set_host(&self, host: &str, ips: &[IpAddr]) {
+        lock(&self.hosts).insert(host.to_ascii_lowercase(), ips.to_vec());
+    }
+
+    /// 移除主机名的映射，之后解析该名称失败
+    pub fn remove_host(&self, host: &str) {
+        lock(&self.hosts).remove(&host.to_ascii_lowercase());
+    }
+
+    /// 解析次数
+    pub fn call_count(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl BootstrapResolver for MockBootstrap {
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        lock(&self.hosts).get(&host.to_ascii_lowercase()).cloned().ok_or(DnsError::NxDomain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::priority::QueryPriority;
+    use crate::types::{Flags, QClass, Query, RecordData};
+
+    fn request(name: &str, rtype: RecordType) -> Request {
+        Request {
+            id: 4242,
+            flags: Flags::default(),
+            query: Query { name: name.to_string(), qtype: rtype, qclass: QClass::IN },
+            client_address: None,
+            enable_edns: false,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: QueryPriority::Normal,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scripted_response_and_call_log() {
+        let mock = MockTransport::new().with_a("Example.com.", &[Ipv4Addr::new(192, 0, 2, 1)], 60);
+        let handle = mock.clone();
+
+        let response = mock.send(&request("example.com", RecordType::A)).await.unwrap();
+        assert_eq!(response.id, 4242);
+        assert_eq!(response.answers[0].data, RecordData::A(Ipv4Addr::new(192, 0, 2, 1)));
+
+        assert!(matches!(
+            mock.send(&request("example.com", RecordType::AAAA)).await,
+            Err(DnsError::NotImplemented(_))
+        ));
+        let calls = handle.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].request.query.qtype, RecordType::AAAA);
+        assert!(calls[0].at <= calls[1].at);
+    }
+
+    #[tokio::test]
+    async fn test_health_and_failure_rate() {
+        let mock = MockTransport::new().with_a("example.com", &[Ipv4Addr::LOCALHOST], 60);
+        mock.set_healthy(false);
+        assert!(matches!(mock.send(&request("example.com", RecordType::A)).await, Err(DnsError::Network { .. })));
+
+        mock.set_healthy(true);
+        mock.set_failure_rate(1.0);
+        assert!(mock.send(&request("example.com", RecordType::A)).await.is_err());
+
+        mock.set_failure_rate(0.0);
+        assert!(mock.send(&request("example.com", RecordType::A)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_latency_beyond_timeout_times_out() {
+        let mut mock = MockTransport::new()
+            .with_a("example.com", &[Ipv4Addr::LOCALHOST], 60)
+            .with_latency(Duration::from_secs(1));
+        mock.set_timeout(Duration::from_millis(10));
+        assert!(matches!(mock.send(&request("example.com", RecordType::A)).await, Err(DnsError::Timeout { .. })));
+    }
+}
@@ -1,28 +1,151 @@
 //! DNS传输层抽象
 
 use crate::{Request, Response, Result};
+use crate::resolver::clock::{self, Clock};
 use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
 pub mod udp;
+pub mod udp_pool;
+#[cfg(feature = "tcp")]
 pub mod tcp;
+#[cfg(feature = "dot")]
 pub mod tls;
+#[cfg(feature = "doh")]
 pub mod https;
+pub mod doh_url;
+pub mod query_id;
+pub mod timing;
+pub mod wire;
+pub mod cookie;
+pub mod upstream_addr;
+#[cfg(feature = "tcp")]
+pub mod fast_open;
+#[cfg(feature = "tcp")]
+pub(crate) mod framing;
+pub mod record_limits;
+pub mod response_shaping;
+#[cfg(feature = "doh3")]
+mod doh3;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_server;
 
 pub use udp::UdpTransport;
+pub use udp_pool::{PoolExhaustion, UdpPoolConfig, UdpPoolStats};
+#[cfg(feature = "tcp")]
 pub use tcp::TcpTransport;
-pub use tls::TlsTransport;
+#[cfg(feature = "dot")]
+pub use tls::{TlsSessionStats, TlsTransport};
+#[cfg(feature = "tcp")]
+pub use fast_open::FastOpenStats;
+pub use record_limits::{RecordLimitPolicy, RecordLimits};
+pub use response_shaping::{AdditionalsPolicy, ResponseShaping};
+#[cfg(feature = "doh")]
 pub use https::HttpsTransport;
+pub use timing::{HttpVersion, TransportPhase, TransportTiming};
+pub use wire::WireCapture;
+pub use cookie::DnsCookieJar;
+pub use upstream_addr::{
+    AddressFamily, AddressFamilyStats, AfPreference, BootstrapResolver, FamilyPathStats, HostResolution, SystemBootstrap, UpstreamAddress,
+};
+
+/// OPT伪记录的类型值（RFC 6891）
+pub const OPT_RECORD_TYPE: u16 = 41;
+
+/// 不经传输编码UDP请求时在OPT记录中声明的载荷大小；UDP传输声明的是配置的 `buffer_size`
+pub const UDP_EDNS_PAYLOAD_SIZE: u16 = 4096;
+
+/// TCP/DoT/DoH请求在OPT记录中声明的载荷大小：流式传输的报文只受2字节长度前缀限制
+pub const STREAM_EDNS_PAYLOAD_SIZE: u16 = u16::MAX;
 
 /// DNS传输层抽象接口
 #[async_trait]
 pub trait Transport: std::fmt::Debug + Send + Sync {
     /// 发送DNS请求并接收响应
+    /// 
+    /// 内置传输的 `timeout` 限制整个调用，包括解析上游主机名、建立连接、TLS握手、写出请求和读取响应，
+    /// 超过时放弃所有未完成的IO，返回带有当时所处阶段的 [`DnsError::Timeout`](crate::DnsError::Timeout)
     async fn send(&self, request: &Request) -> Result<Response>;
     
+    /// 发送DNS请求，同时返回实际交换报文的对端地址
+    /// 
+    /// 以主机名配置的上游可能解析到不同的地址，对端地址用于排查是哪个IP给出的应答。
+    /// 默认实现取不到对端地址，返回 `None`
+    async fn send_with_peer(&self, request: &Request) -> Result<(Response, Option<SocketAddr>)> {
+        self.send(request).await.map(|response| (response, None))
+    }
+    
+    /// 发送DNS请求，同时记录各阶段耗时（解析器开启耗时分解时使用）
+    /// 
+    /// 默认实现只记录总耗时；内置传输按各自能观测到的阶段打点
+    async fn send_timed(&self, request: &Request) -> Result<(Response, TransportTiming)> {
+        let start = crate::time::Instant::now();
+        let response = self.send(request).await?;
+        Ok((response, TransportTiming::total(start.elapsed())))
+    }
+    
+    /// 发送DNS请求并保留收发的原始DNS报文，每个报文最多保留 `max_bytes` 字节；同时记录各阶段耗时
+    /// 
+    /// 请求设置了 `wire_capture_limit` 时使用。默认实现取不到原始报文，
+    /// 只保留按UDP格式编码的请求，响应为空
+    async fn send_captured(&self, request: &Request, max_bytes: usize) -> Result<(Response, TransportTiming, WireCapture)> {
+        let mut wire = wire::WireRecorder::with_limit(max_bytes);
+        wire.record_request(&UdpTransport::serialize_request(request)?);
+        let (response, timing) = self.send_timed(request).await?;
+        Ok((response, timing, wire.finish()))
+    }
+    
+    /// 发送前的准备，构建解析器时调用：以主机名配置的上游在这里解析地址
+    /// 
+    /// 失败时该上游仍会保留，之后的发送会重新尝试。默认什么都不做
+    async fn prepare(&self) -> Result<()> {
+        Ok(())
+    }
+    
+    /// 加密传输（DoT/DoH）连接的主机，证书校验失败改用明文DNS时连接该主机的53端口；明文传输为 `None`
+    fn encrypted_host(&self) -> Option<String> {
+        None
+    }
+    
+    /// 连接同一上游但不校验证书的传输，只在证书校验失败降级时使用；不支持时为 `None`
+    fn insecure_variant(&self) -> Result<Option<Arc<dyn Transport>>> {
+        Ok(None)
+    }
+    
+    /// TLS会话恢复的计数（完整握手、恢复握手、0-RTT早期数据），非DoT传输为 `None`
+    #[cfg(feature = "dot")]
+    fn tls_session_stats(&self) -> Option<TlsSessionStats> {
+        None
+    }
+    
+    /// TCP Fast Open的计数，未开启 `tcp_fast_open` 或非TCP/DoT传输为 `None`
+    #[cfg(feature = "tcp")]
+    fn fast_open_stats(&self) -> Option<FastOpenStats> {
+        None
+    }
+    
+    /// 源端口池的使用情况，非UDP传输为 `None`
+    fn udp_pool_stats(&self) -> Option<UdpPoolStats> {
+        None
+    }
+    
+    /// 按地址族分开的连接统计，DoH和自定义传输为 `None`
+    fn address_family_stats(&self) -> Option<AddressFamilyStats> {
+        None
+    }
+    
     /// 获取传输类型名称
     fn transport_type(&self) -> &'static str;
     
+    /// 获取传输连接的上游端点（地址:端口或URL），用于日志和结果归属
+    fn endpoint(&self) -> String {
+        self.transport_type().to_string()
+    }
+    
     /// 设置超时时间
     fn set_timeout(&mut self, timeout: Duration);
     
@@ -37,14 +160,42 @@ pub struct TransportConfig {
     pub server: String,
     /// 端口
     pub port: u16,
-    /// 超时时间
+    /// 超时时间，限制一次发送的全过程（见 [`Transport::send`]）
     pub timeout: Duration,
-    /// 是否启用TCP快速打开
+    /// 是否启用TCP快速打开（TCP和DoT），平台不支持时照常连接，见 [`fast_open`]
     pub tcp_fast_open: bool,
     /// 是否启用TCP无延迟
     pub tcp_nodelay: bool,
     /// 连接池大小
     pub pool_size: usize,
+    /// 接受的最大响应报文长度（字节）：UDP按它分配接收缓冲区并在OPT记录中声明为载荷大小，
+    /// TCP/DoT的长度前缀超过它时不再读取报文
+    pub buffer_size: usize,
+    /// 响应的记录数上限：拒绝模式下报文头部的计数超出时直接失败，不解析记录（None为不限制）
+    pub record_limits: Option<RecordLimits>,
+}
+
+/// 依次尝试多个地址时单个地址的时限：后面还有地址时最多用掉到 `deadline` 为止剩余时间的一半，
+/// 给后面的地址留出时间；最后一个地址到 `deadline` 为止。超过时限返回 `None`
+///
+/// `deadline` 与整个发送的超时相同：两者同时到期时先完成的是这里的时限，调用方仍能记下是哪个地址超时
+pub(crate) async fn within_share<F: std::future::Future>(clock: &dyn Clock, deadline: crate::time::Instant, last: bool, attempt: F) -> Option<F::Output> {
+    let limit = if last {
+        deadline
+    } else {
+        let now = clock.now_instant();
+        now + deadline.saturating_duration_since(now) / 2
+    };
+    clock::timeout_at(clock, limit, attempt).await
+}
+
+/// 拼接 `主机:端口`，IPv6地址加方括号
+pub(crate) fn host_port(server: &str, port: u16) -> String {
+    if server.parse::<std::net::Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", server, port)
+    } else {
+        format!("{}:{}", server, port)
+    }
 }
 
 // 注意：移除了 Default 实现，因为它包含兜底行为
@@ -60,6 +211,8 @@ pub struct TransportConfig {
 //     tcp_fast_open: false,
 //     tcp_nodelay: true,
 //     pool_size: 10,
+//     buffer_size: 4096,
+//     record_limits: None,
 // }
 
 /// HTTPS传输配置
@@ -73,6 +226,24 @@ pub struct HttpsConfig {
     pub method: HttpMethod,
     /// 用户代理
     pub user_agent: String,
+    /// 附加的HTTP请求头（GET和POST请求都会携带），例如认证用的 `Authorization`
+    pub extra_headers: Vec<(String, String)>,
+    /// 附加的查询参数（如 `key`、`filter`），追加在URL原有的查询串之后；
+    /// GET请求与 `dns` 参数一起发送，POST请求附在URL上
+    pub query_params: Vec<(String, String)>,
+    /// 使用的HTTP版本，HTTP/3需要启用 `doh3` 特性
+    pub http_version: HttpVersionPref,
+    /// 响应正文长度上限（字节），超过时中止读取并返回 [`DnsError::ResponseTooLarge`](crate::DnsError::ResponseTooLarge)
+    pub max_response_size: usize,
+    /// 是否跟随3xx重定向：只跟随同源（协议、主机、端口相同）的重定向，最多2跳；
+    /// 不跟随时返回 [`DnsError::HttpStatus`](crate::DnsError::HttpStatus)
+    pub follow_redirects: bool,
+    /// 实际连接的地址（如预解析出的IP），为 `None` 时按URL中的主机名解析
+    /// 
+    /// 只决定TCP/QUIC连接发往哪里：TLS的SNI、证书校验和 `Host`（HTTP/3中为 `:authority`）总是使用URL中的主机名，
+    /// 证书不是签给该主机名时握手失败，不会退而校验IP。端口必须与URL的端口相同。
+    /// 经HTTP代理（`HTTPS_PROXY` 等环境变量）时由代理按主机名建立连接，此地址不生效，身份校验同样使用主机名
+    pub connect_addr: Option<SocketAddr>,
 }
 
 /// HTTP方法
@@ -84,6 +255,26 @@ pub enum HttpMethod {
     POST,
 }
 
+/// DoH使用的HTTP版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpVersionPref {
+    /// 在TCP上与服务器协商HTTP/1.1或HTTP/2，不尝试HTTP/3
+    Auto,
+    /// 只用HTTP/2（不经协商直接发送HTTP/2帧，服务器必须支持）
+    H2Only,
+    /// 只用HTTP/3，QUIC握手失败时查询失败
+    H3Only,
+    /// 优先HTTP/3，QUIC握手失败或UDP被拦截时改走TCP，一段时间内不再尝试QUIC
+    H3WithFallback,
+}
+
+impl HttpVersionPref {
+    /// 是否会使用HTTP/3
+    pub fn uses_http3(self) -> bool {
+        matches!(self, Self::H3Only | Self::H3WithFallback)
+    }
+}
+
 // 注意：移除了 Default 实现，因为它包含兜底行为
 // 硬编码的默认值（如 cloudflare URL、POST方法）是兜底代码
 // 用户现在必须明确配置所有HTTPS参数
@@ -97,8 +288,262 @@ pub struct TlsConfig {
     pub server_name: String,
     /// 是否验证证书
     pub verify_cert: bool,
+    /// 恢复会话时是否把查询作为0-RTT早期数据发送；服务器拒绝时握手完成后自动重发。
+    /// 早期数据可能被重放，只适合幂等的查询
+    pub enable_tls_early_data: bool,
 }
 
 // 注意：移除了 Default 实现，因为它包含兜底行为
 // 硬编码的默认值（如 cloudflare 服务器名、端口853）是兜底代码
-// 用户现在必须明确配置所有TLS参数
\ No newline at end of file
+// 用户现在必须明确配置所有TLS参数
+// 比较各传输实际发出的报文，需要全部传输
+#[cfg(all(test, feature = "dot", feature = "doh"))]
+mod tests {
+    use super::*;
+    use crate::resolver::priority::QueryPriority;
+    use crate::types::{edns_option_codes, ClientAddress, Flags, Query, QClass, RecordType};
+    use base64::{Engine as _, engine::general_purpose};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    fn request(client_address: Option<ClientAddress>, enable_edns: bool) -> Request {
+        Request {
+            id: 0x1234,
+            flags: Flags::default(),
+            query: Query {
+                name: "example.com".to_string(),
+                qtype: RecordType::A,
+                qclass: QClass::IN,
+            },
+            client_address,
+            enable_edns,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: QueryPriority::Normal,
+        }
+    }
+
+    /// 各传输实际发出的DNS报文（去掉长度前缀、还原base64url）及其声明的载荷大小
+    fn wire_messages(request: &Request) -> Vec<(&'static str, Vec<u8>, u16)> {
+        // TCP与DoT发送时共用同一个带长度前缀的编码入口
+        let framed = || {
+            let mut framed = Vec::new();
+            TcpTransport::encode_request_framed(request, &mut framed).unwrap();
+            let length = u16::from_be_bytes([framed[0], framed[1]]) as usize;
+            assert_eq!(length, framed.len() - 2);
+            framed[2..].to_vec()
+        };
+        let get_query = HttpsTransport::encode_dns_query_base64url(&HttpsTransport::encode_request(request).unwrap());
+        vec![
+            ("udp", UdpTransport::serialize_request(request).unwrap(), UDP_EDNS_PAYLOAD_SIZE),
+            ("tcp", framed(), STREAM_EDNS_PAYLOAD_SIZE),
+            ("dot", framed(), STREAM_EDNS_PAYLOAD_SIZE),
+            ("doh-get", general_purpose::URL_SAFE_NO_PAD.decode(get_query).unwrap(), STREAM_EDNS_PAYLOAD_SIZE),
+            ("doh-post", HttpsTransport::encode_request(request).unwrap(), STREAM_EDNS_PAYLOAD_SIZE),
+        ]
+    }
+
+    #[test]
+    fn test_client_address_sent_by_every_transport() {
+        let cases = [
+            (ClientAddress::from_ipv4(Ipv4Addr::new(202, 96, 128, 86), 24), 1u16, vec![202, 96, 128]),
+            (ClientAddress::from_ipv6("2001:db8:abcd::1".parse::<Ipv6Addr>().unwrap(), 48), 2, vec![0x20, 0x01, 0x0d, 0xb8, 0xab, 0xcd]),
+        ];
+        for (client_address, family, address) in cases {
+            let request = request(Some(client_address.clone()), false);
+            for (transport, message, payload_size) in wire_messages(&request) {
+                let edns = UdpTransport::parse_edns(&message).unwrap()
+                    .unwrap_or_else(|| panic!("{} 未携带OPT记录", transport));
+                assert_eq!(edns.udp_payload_size, payload_size, "{}", transport);
+                assert_eq!(edns.options.len(), 1, "{}", transport);
+
+                let option = &edns.options[0];
+                assert_eq!(option.code, edns_option_codes::CLIENT_ADDRESS, "{}", transport);
+                assert_eq!(u16::from_be_bytes([option.data[0], option.data[1]]), family, "{}", transport);
+                assert_eq!(option.data[2], client_address.source_prefix_length, "{}", transport);
+                assert_eq!(option.data[3], 0, "{}", transport);
+                assert_eq!(&option.data[4..], &address[..], "{}", transport);
+
+                let decoded = UdpTransport::deserialize_request(&message).unwrap();
+                let decoded_client = decoded.client_address.unwrap();
+                assert_eq!(decoded_client.family(), family, "{}", transport);
+                assert_eq!(decoded_client.source_prefix_length, client_address.source_prefix_length, "{}", transport);
+            }
+        }
+    }
+
+    #[test]
+    fn test_edns_without_client_address() {
+        for (transport, message, payload_size) in wire_messages(&request(None, true)) {
+            let edns = UdpTransport::parse_edns(&message).unwrap()
+                .unwrap_or_else(|| panic!("{} 未携带OPT记录", transport));
+            assert_eq!(edns.udp_payload_size, payload_size, "{}", transport);
+            assert!(edns.options.is_empty(), "{}", transport);
+        }
+
+        for (transport, message, _) in wire_messages(&request(None, false)) {
+            assert!(UdpTransport::parse_edns(&message).unwrap().is_none(), "{}", transport);
+            assert_eq!(u16::from_be_bytes([message[10], message[11]]), 0, "{}", transport);
+        }
+    }
+
+    #[test]
+    fn test_transports_share_encoding() {
+        let request = request(Some(ClientAddress::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 24)), true);
+        let messages = wire_messages(&request);
+        let udp = &messages[0].1;
+        for (transport, message, _) in &messages[1..] {
+            // 只有OPT记录CLASS字段（声明的载荷大小）不同
+            assert_eq!(message.len(), udp.len(), "{}", transport);
+            let differing: Vec<usize> = (0..udp.len()).filter(|&i| udp[i] != message[i]).collect();
+            let class_offset = udp.len() - 11 - 4 - 7 + 3;
+            assert!(differing.iter().all(|&i| i == class_offset || i == class_offset + 1), "{}", transport);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refused_connection_is_classified() {
+        // 端口在监听器关闭后不再有人监听，回环上的连接会被立即拒绝
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let transport = TcpTransport::new(TransportConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            timeout: std::time::Duration::from_secs(2),
+            tcp_fast_open: false,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size: 4096,
+            record_limits: None,
+        });
+        match transport.send(&request(None, false)).await {
+            Err(crate::DnsError::Network { kind, upstream, .. }) => {
+                assert_eq!(kind, crate::NetworkErrorKind::ConnectionRefused);
+                assert_eq!(upstream, format!("127.0.0.1:{}", port));
+            }
+            other => panic!("expected a refused connection, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_peer_is_the_connected_socket() {
+        use crate::dns_response::DnsResponseWrapper;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        for _ in 0..2 {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut length = [0u8; 2];
+                stream.read_exact(&mut length).await.unwrap();
+                let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut message).await.unwrap();
+                let request = UdpTransport::deserialize_request(&message).unwrap();
+                let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+                let bytes = UdpTransport::serialize_response(&response).unwrap();
+                stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+                stream.write_all(&bytes).await.unwrap();
+            });
+
+            let transport = TcpTransport::new(TransportConfig {
+                server: "127.0.0.1".to_string(),
+                port: address.port(),
+                timeout: std::time::Duration::from_secs(2),
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: 1,
+                buffer_size: 4096,
+                record_limits: None,
+            });
+            let (_, peer) = transport.send_with_peer(&request(None, false)).await.unwrap();
+            assert_eq!(peer, Some(address));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tcp_length_prefix_above_buffer_size_is_rejected() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // 声明65000字节的报文后不再发送任何数据：必须凭长度前缀立即失败，而不是等到超时
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 512];
+            let _ = stream.read(&mut request).await.unwrap();
+            stream.write_all(&65000u16.to_be_bytes()).await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+        });
+
+        let transport = TcpTransport::new(TransportConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            timeout: std::time::Duration::from_secs(5),
+            tcp_fast_open: false,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size: 4096,
+            record_limits: None,
+        });
+        let start = crate::time::Instant::now();
+        match transport.send(&request(None, false)).await {
+            Err(crate::DnsError::Protocol(message)) => {
+                assert!(message.contains("65000") && message.contains("4096"), "{}", message);
+            }
+            other => panic!("expected the oversized length prefix to be rejected, got {:?}", other),
+        }
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_fast_open_round_trip() {
+        use crate::dns_response::DnsResponseWrapper;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut length = [0u8; 2];
+                stream.read_exact(&mut length).await.unwrap();
+                let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut message).await.unwrap();
+                let request = UdpTransport::deserialize_request(&message).unwrap();
+                let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+                let bytes = UdpTransport::serialize_response(&response).unwrap();
+                stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+                stream.write_all(&bytes).await.unwrap();
+            }
+        });
+
+        let config = |port| TransportConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            timeout: std::time::Duration::from_secs(2),
+            tcp_fast_open: true,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size: 4096,
+            record_limits: None,
+        };
+        let transport = TcpTransport::new(config(port));
+        for _ in 0..2 {
+            let (response, timing) = transport.send_timed(&request(None, false)).await.unwrap();
+            assert_eq!(response.answers.len(), 1);
+            // Linux上读完响应后能观测SYN数据是否被接受，其他平台为 `None`
+            assert_eq!(timing.tcp_fast_open.is_some(), cfg!(target_os = "linux"));
+        }
+        let stats = transport.fast_open_stats().unwrap();
+        assert_eq!(stats.attempts + stats.fallbacks, 2);
+        assert_eq!(stats.supported, fast_open::is_supported());
+
+        // 连接推迟到写出请求时，被拒绝的连接仍按原因分类
+        let refused = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        match TcpTransport::new(config(refused)).send(&request(None, false)).await {
+            Err(crate::DnsError::Network { kind, .. }) => assert_eq!(kind, crate::NetworkErrorKind::ConnectionRefused),
+            other => panic!("expected a refused connection, got {:?}", other),
+        }
+        assert_eq!(TcpTransport::new(config(port)).fast_open_stats().map(|stats| stats.attempts), Some(0));
+        let plain = TcpTransport::new(TransportConfig { tcp_fast_open: false, ..config(port) });
+        assert!(plain.fast_open_stats().is_none());
+    }
+}
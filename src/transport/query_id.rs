@@ -0,0 +1,198 @@
+//! 查询ID分配
+//!
+//! 每一次发往上游的报文都使用新的16位随机ID，而不是沿用调用方请求的ID：
+//! 同一查询扇出到多个上游、以及失败后的每次重发，线路上的ID都互不相关。
+//! ID取自 `rand::thread_rng()`，即由操作系统熵源定期重新播种的ChaCha12，属于密码学安全的随机数生成器，
+//! 攻击者无法根据已观察到的ID推测下一个。即使解析器注入了确定的随机数来源（见 [`crate::resolver::random`]），
+//! ID也不使用它：ID是抵御伪造响应的主要随机性，不能为了复现而变得可预测。
+//!
+//! [`QueryIds`] 记录一个上游当前未完成的ID，分配时跳过占用中的值，
+//! 保证同一上游（包括它的每一条连接）上同时进行的查询不会共用ID。
+
+use crate::{DnsError, Request, Response, Result};
+use crate::utils::names_equal;
+use crate::resolver::random::RandomSource;
+use rand::Rng;
+use std::collections::HashSet;
+use std::sync::{Mutex, MutexGuard};
+
+/// 一个上游当前未完成的查询ID
+#[derive(Debug, Default)]
+pub struct QueryIds {
+    outstanding: Mutex<HashSet<u16>>,
+}
+
+impl QueryIds {
+    /// 创建空的ID表
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashSet<u16>> {
+        self.outstanding.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 分配一个未被占用的随机ID，在返回的守卫被丢弃之前不会再次分配
+    ///
+    /// 65536个ID全部占用时返回错误
+    pub fn reserve(&self) -> Result<QueryId<'_>> {
+        let mut outstanding = self.lock();
+        if outstanding.len() > u16::MAX as usize {
+            return Err(DnsError::network("", "All 65536 query IDs are in flight"));
+        }
+        let mut rng = rand::thread_rng();
+        loop {
+            let id: u16 = rng.r#gen();
+            if outstanding.insert(id) {
+                return Ok(QueryId { id, owner: self });
+            }
+        }
+    }
+
+    /// 当前未完成的ID数量
+    pub fn outstanding(&self) -> usize {
+        self.lock().len()
+    }
+}
+
+/// 已分配的查询ID，丢弃时归还
+#[derive(Debug)]
+pub struct QueryId<'a> {
+    id: u16,
+    owner: &'a QueryIds,
+}
+
+impl QueryId<'_> {
+    /// 分配到的ID
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// 以分配到的ID复制一份请求，作为实际发往上游的报文
+    pub fn wire_request(&self, request: &Request) -> Request {
+        Request { id: self.id, ..request.clone() }
+    }
+
+    /// 把上游响应的ID改回调用方请求的ID
+    pub fn restore(&self, mut response: Response, caller: &Request) -> Response {
+        response.id = caller.id;
+        response
+    }
+}
+
+impl Drop for QueryId<'_> {
+    fn drop(&mut self) {
+        self.owner.lock().remove(&self.id);
+    }
+}
+
+/// 检查响应ID与请求一致，不一致时视为协议错误（可能是伪造或错配的响应）
+pub fn ensure_matching_id(request: &Request, response: Response) -> Result<Response> {
+    if response.id != request.id {
+        return Err(DnsError::Protocol(format!(
+            "Response ID {} does not match query ID {} for {}",
+            response.id, request.id, request.query.name
+        )));
+    }
+    Ok(response)
+}
+
+/// 随机化域名中每个字母的大小写（0x20编码），增加伪造响应需要猜中的位数
+///
+/// 随机位取自 `random`，与报文ID不同，可以由解析器注入确定的来源，见 [`crate::resolver::random`]
+pub fn randomize_case(name: &str, random: &dyn RandomSource) -> String {
+    let mut bits = 0u64;
+    name.chars()
+        .enumerate()
+        .map(|(index, c)| {
+            if index % 64 == 0 {
+                bits = random.next_u64();
+            }
+            let upper = bits >> (index % 64) & 1 == 1;
+            if upper { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() }
+        })
+        .collect()
+}
+
+/// 检查响应按发出的大小写回显了问题名，再把响应中的该名称改回调用方请求的写法
+///
+/// 大小写不一致时视为协议错误；没有问题段的响应不检查
+pub fn restore_case(sent: &str, caller: &Request, mut response: Response) -> Result<Response> {
+    let sent = sent.trim_end_matches('.');
+    if let Some(question) = response.queries.first()
+        && question.name.trim_end_matches('.') != sent
+    {
+        return Err(DnsError::Protocol(format!(
+            "Response question {} does not echo the case of query {}",
+            question.name, sent
+        )));
+    }
+    let matches_sent = |name: &str| names_equal(name, sent);
+    for query in response.queries.iter_mut().filter(|query| matches_sent(&query.name)) {
+        query.name = caller.query.name.clone();
+    }
+    for record in response.answers.iter_mut()
+        .chain(response.authorities.iter_mut())
+        .chain(response.additionals.iter_mut())
+        .filter(|record| matches_sent(&record.name))
+    {
+        record.name = caller.query.name.clone();
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::priority::QueryPriority;
+
+    #[test]
+    fn test_reserved_ids_are_unique_until_released() {
+        let ids = QueryIds::new();
+        let guards: Vec<_> = (0..1000).map(|_| ids.reserve().unwrap()).collect();
+        let distinct: HashSet<u16> = guards.iter().map(QueryId::id).collect();
+        assert_eq!(distinct.len(), 1000);
+        assert_eq!(ids.outstanding(), 1000);
+
+        drop(guards);
+        assert_eq!(ids.outstanding(), 0);
+    }
+
+    #[test]
+    fn test_exhausted_ids() {
+        let ids = QueryIds::new();
+        let guards: Vec<_> = (0..=u16::MAX as usize).map(|_| ids.reserve().unwrap()).collect();
+        assert!(ids.reserve().is_err());
+        drop(guards);
+        assert!(ids.reserve().is_ok());
+    }
+
+    #[test]
+    fn test_case_randomization_round_trip() {
+        use crate::dns_response::DnsResponseWrapper;
+        use crate::types::{Flags, QClass, Query, RecordType};
+        use std::net::Ipv4Addr;
+
+        let name = "www.example-zone.com";
+        let randomized = (0..16).map(|_| randomize_case(name, &crate::resolver::random::ThreadRandom)).find(|candidate| candidate != name).unwrap();
+        assert!(randomized.eq_ignore_ascii_case(name));
+
+        let caller = Request {
+            id: 1,
+            flags: Flags::default(),
+            query: Query { name: name.to_string(), qtype: RecordType::A, qclass: QClass::IN },
+            client_address: None,
+            enable_edns: false,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: QueryPriority::Normal,
+        };
+        let echoed = DnsResponseWrapper::create_a_response(1, &randomized, &[Ipv4Addr::LOCALHOST], 60);
+        let restored = restore_case(&randomized, &caller, echoed).unwrap();
+        assert_eq!(restored.queries[0].name, name);
+        assert_eq!(restored.answers[0].name, name);
+
+        let lowered = DnsResponseWrapper::create_a_response(1, name, &[Ipv4Addr::LOCALHOST], 60);
+        assert!(matches!(restore_case(&randomized, &caller, lowered), Err(DnsError::Protocol(_))));
+    }
+}
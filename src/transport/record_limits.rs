@@ -0,0 +1,263 @@
+//! 单个响应的记录数上限
+//!
+//! 有问题或恶意的上游可以在一个（TCP/DoH）响应里放进数千条记录或极长的CNAME链，
+//! 不加限制时每条记录都会被解析、转换并写入缓存。这里限制回答记录数、三段记录总数和CNAME链的跳数，
+//! 超出时按 [`RecordLimitPolicy`] 处理：
+//!
+//! - 拒绝：传输解析报文时先按头部声明的计数检查，超出直接以协议错误失败，不为记录分配空间；
+//!   CNAME链要解析出记录后才能判断，由解析器检查
+//! - 截断：解析器在写入缓存和转换结果之前截去超出的记录，应答标记为已截断
+
+use crate::error::{DnsError, Result};
+use crate::types::{Query, RecordData, Response};
+use crate::utils::names_equal;
+use serde::{Deserialize, Serialize};
+
+/// 超出上限时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RecordLimitPolicy {
+    /// 截去超出的记录，应答标记为已截断
+    #[default]
+    Truncate,
+    /// 以协议错误拒绝整个响应
+    Reject,
+}
+
+/// 单个响应的记录数上限
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordLimits {
+    /// 回答段的记录数上限
+    pub max_answer_records: usize,
+    /// 回答、权威、附加三段的记录总数上限
+    pub max_total_records: usize,
+    /// 从查询名出发的CNAME链最多的跳数
+    pub max_cname_chain: usize,
+    /// 超出上限时的处理方式
+    pub policy: RecordLimitPolicy,
+}
+
+impl Default for RecordLimits {
+    fn default() -> Self {
+        Self {
+            max_answer_records: Self::DEFAULT_MAX_ANSWER_RECORDS,
+            max_total_records: Self::DEFAULT_MAX_TOTAL_RECORDS,
+            max_cname_chain: Self::DEFAULT_MAX_CNAME_CHAIN,
+            policy: RecordLimitPolicy::Truncate,
+        }
+    }
+}
+
+impl RecordLimits {
+    /// 默认的回答记录数上限
+    pub const DEFAULT_MAX_ANSWER_RECORDS: usize = 512;
+    /// 默认的记录总数上限
+    pub const DEFAULT_MAX_TOTAL_RECORDS: usize = 1024;
+    /// 默认的CNAME链跳数上限
+    pub const DEFAULT_MAX_CNAME_CHAIN: usize = 16;
+
+    /// 检查上限，为0或回答记录数上限超过总数上限时返回 [`DnsError::InvalidConfig`]
+    pub fn validate(&self) -> Result<()> {
+        if self.max_answer_records == 0 {
+            return Err(DnsError::InvalidConfig("max_answer_records must be at least 1".to_string()));
+        }
+        if self.max_total_records < self.max_answer_records {
+            return Err(DnsError::InvalidConfig(format!(
+                "max_total_records ({}) cannot be smaller than max_answer_records ({})",
+                self.max_total_records, self.max_answer_records
+            )));
+        }
+        if self.max_cname_chain == 0 {
+            return Err(DnsError::InvalidConfig("max_cname_chain must be at least 1".to_string()));
+        }
+        Ok(())
+    }
+
+    /// 按报文头部声明的计数检查：拒绝模式下超出上限时返回协议错误，截断模式总是通过
+    pub fn check_counts(&self, answers: usize, total: usize) -> Result<()> {
+        if self.policy != RecordLimitPolicy::Reject {
+            return Ok(());
+        }
+        if answers > self.max_answer_records {
+            return Err(DnsError::Protocol(format!(
+                "响应有 {} 条回答记录，超过上限 {}", answers, self.max_answer_records
+            )));
+        }
+        if total > self.max_total_records {
+            return Err(DnsError::Protocol(format!(
+                "响应共有 {} 条记录，超过上限 {}", total, self.max_total_records
+            )));
+        }
+        Ok(())
+    }
+
+    /// 对解析出的响应执行上限，返回是否截去了记录
+    ///
+    /// 拒绝模式下超出任一上限时返回协议错误；截断模式下依次截去超出的回答记录、
+    /// 超出总数的权威和附加记录，CNAME链超长时只保留前 `max_cname_chain` 跳及链上名称的记录
+    pub fn enforce(&self, query: &Query, response: &mut Response) -> Result<bool> {
+        let total = response.answers.len() + response.authorities.len() + response.additionals.len();
+        self.check_counts(response.answers.len(), total)?;
+        let mut truncated = total > self.max_total_records || response.answers.len() > self.max_answer_records;
+        response.answers.truncate(self.max_answer_records);
+        let remaining = self.max_total_records - response.answers.len();
+        response.authorities.truncate(remaining);
+        response.additionals.truncate(remaining - response.authorities.len());
+
+        let chain = self.cname_chain(query, response);
+        if chain.len() > self.max_cname_chain + 1 {
+            if self.policy == RecordLimitPolicy::Reject {
+                return Err(DnsError::Protocol(format!(
+                    "{} 的CNAME链超过 {} 跳", query.name, self.max_cname_chain
+                )));
+            }
+            let kept = &chain[..=self.max_cname_chain];
+            let last = &kept[self.max_cname_chain];
+            response.answers.retain(|record| {
+                kept.iter().any(|name| names_equal(name, &record.name))
+                    && !(matches!(record.data, RecordData::CNAME(_)) && names_equal(last, &record.name))
+            });
+            truncated = true;
+        }
+        Ok(truncated)
+    }
+
+    /// 从查询名出发沿回答段的CNAME记录走出的名称（含查询名），最多走到超出上限的一跳
+    fn cname_chain(&self, query: &Query, response: &Response) -> Vec<String> {
+        let mut chain = vec![query.name.clone()];
+        while chain.len() <= self.max_cname_chain + 1 {
+            let owner = &chain[chain.len() - 1];
+            let target = response.answers.iter().find_map(|record| match &record.data {
+                RecordData::CNAME(target) if names_equal(owner, &record.name) => Some(target.clone()),
+                _ => None,
+            });
+            match target {
+                Some(target) => chain.push(target),
+                None => break,
+            }
+        }
+        chain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Flags, QClass, Record, RecordType};
+    use std::net::Ipv4Addr;
+
+    fn a(name: &str, last_octet: u8) -> Record {
+        Record {
+            name: name.to_string(),
+            rtype: RecordType::A,
+            class: QClass::IN,
+            ttl: 300,
+            data: RecordData::A(Ipv4Addr::new(192, 0, 2, last_octet)),
+        }
+    }
+
+    fn cname(name: &str, target: &str) -> Record {
+        Record {
+            name: name.to_string(),
+            rtype: RecordType::CNAME,
+            class: QClass::IN,
+            ttl: 300,
+            data: RecordData::CNAME(target.to_string()),
+        }
+    }
+
+    fn response(answers: Vec<Record>, authorities: Vec<Record>, additionals: Vec<Record>) -> Response {
+        Response { id: 1, flags: Flags::default(), queries: Vec::new(), answers, authorities, additionals }
+    }
+
+    fn limits(policy: RecordLimitPolicy) -> RecordLimits {
+        RecordLimits { max_answer_records: 3, max_total_records: 4, max_cname_chain: 2, policy }
+    }
+
+    #[test]
+    fn test_validate_rejects_inconsistent_limits() {
+        assert!(RecordLimits::default().validate().is_ok());
+        assert!(RecordLimits { max_answer_records: 0, ..RecordLimits::default() }.validate().is_err());
+        assert!(RecordLimits { max_total_records: 10, max_answer_records: 11, ..RecordLimits::default() }.validate().is_err());
+        assert!(RecordLimits { max_cname_chain: 0, ..RecordLimits::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_truncate_keeps_answers_first() {
+        let query = Query { name: "example.com".to_string(), qtype: RecordType::A, qclass: QClass::IN };
+        let answers = (1..=5).map(|i| a("example.com", i)).collect();
+        let mut oversized = response(answers, vec![a("ns.example.com", 10)], vec![a("glue.example.com", 11), a("glue.example.com", 12)]);
+        assert!(limits(RecordLimitPolicy::Truncate).enforce(&query, &mut oversized).unwrap());
+        assert_eq!(oversized.answers, (1..=3).map(|i| a("example.com", i)).collect::<Vec<_>>());
+        assert_eq!(oversized.authorities.len(), 1);
+        assert!(oversized.additionals.is_empty());
+
+        let mut within = response(vec![a("example.com", 1)], Vec::new(), Vec::new());
+        assert!(!limits(RecordLimitPolicy::Reject).enforce(&query, &mut within).unwrap());
+        assert_eq!(within.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_long_cname_chain_is_cut_or_rejected() {
+        let query = Query { name: "a.example".to_string(), qtype: RecordType::A, qclass: QClass::IN };
+        let chain = || response(
+            vec![cname("a.example", "b.example"), cname("b.example", "c.example"), cname("c.example", "d.example"), a("d.example", 1)],
+            Vec::new(),
+            Vec::new(),
+        );
+        let limits = |policy| RecordLimits { max_answer_records: 10, max_total_records: 10, ..limits(policy) };
+
+        let mut truncated = chain();
+        assert!(limits(RecordLimitPolicy::Truncate).enforce(&query, &mut truncated).unwrap());
+        assert_eq!(truncated.answers, vec![cname("a.example", "b.example"), cname("b.example", "c.example")]);
+
+        let err = limits(RecordLimitPolicy::Reject).enforce(&query, &mut chain()).unwrap_err();
+        assert!(matches!(err, DnsError::Protocol(_)));
+
+        // 恰好达到上限的链不受影响
+        let mut exact = chain();
+        exact.answers.remove(2);
+        exact.answers[2] = a("c.example", 1);
+        assert!(!limits(RecordLimitPolicy::Reject).enforce(&query, &mut exact).unwrap());
+        assert_eq!(exact.answers.len(), 3);
+    }
+
+    /// 头部声明 `count` 条回答记录、没有问题段的报文，每条记录都是根名称下的A记录；
+    /// `corrupt_first` 时第一条记录的名称换成向后指的压缩指针，真正开始解析记录就会失败
+    fn message_with_answers(count: u16, corrupt_first: bool) -> Vec<u8> {
+        let mut message = vec![0x12, 0x34, 0x81, 0x80, 0, 0];
+        message.extend_from_slice(&count.to_be_bytes());
+        message.extend_from_slice(&[0, 0, 0, 0]);
+        for i in 0..count {
+            let name: &[u8] = if corrupt_first && i == 0 { &[0xC0, 0xFF] } else { &[0] };
+            message.extend_from_slice(name);
+            message.extend_from_slice(&[0, 1, 0, 1, 0, 0, 1, 0x2C, 0, 4, 192, 0, 2, i as u8]);
+        }
+        message
+    }
+
+    #[test]
+    fn test_reject_mode_fails_on_header_counts_before_parsing() {
+        use crate::transport::UdpTransport;
+
+        let reject = RecordLimits { policy: RecordLimitPolicy::Reject, ..RecordLimits::default() };
+        // 头部计数超限时不解析任何记录：损坏的第一条记录不会被读到
+        let corrupt = message_with_answers(5000, true);
+        let parse_error = UdpTransport::deserialize_response(&corrupt).unwrap_err().to_string();
+        assert!(!parse_error.contains("超过上限"), "{}", parse_error);
+        let limit_error = UdpTransport::deserialize_response_with_limits(&corrupt, Some(&reject)).unwrap_err();
+        assert!(matches!(&limit_error, DnsError::Protocol(message) if message.contains("5000 条回答记录")), "{}", limit_error);
+
+        // 截断模式在解析时放行，由解析器截去超出的记录
+        let message = message_with_answers(5000, false);
+        let truncate = RecordLimits::default();
+        let mut response = UdpTransport::deserialize_response_with_limits(&message, Some(&truncate)).unwrap();
+        assert_eq!(response.answers.len(), 5000);
+        let query = Query { name: ".".to_string(), qtype: RecordType::A, qclass: QClass::IN };
+        assert!(truncate.enforce(&query, &mut response).unwrap());
+        assert_eq!(response.answers.len(), RecordLimits::DEFAULT_MAX_ANSWER_RECORDS);
+
+        // 未超限的报文照常解析
+        let small = message_with_answers(3, false);
+        assert_eq!(UdpTransport::deserialize_response_with_limits(&small, Some(&reject)).unwrap().answers.len(), 3);
+    }
+}
@@ -0,0 +1,225 @@
+//! 发给下游客户端的响应整形
+//!
+//! 以服务端身份把应答重新编码发给客户端时，不同客户端的需求不同：有的存根解析器处理不了很大的附加部分，
+//! SRV的使用者则需要目标主机的地址（胶水记录）。[`ResponseShaping`] 决定保留权威部分、附加部分中的哪些记录，
+//! 去掉OPT记录中的哪些EDNS选项（例如回答客户端前去掉客户端子网），以及是否只给出回答（最小响应）。
+//!
+//! 整形只作用于发给客户端的报文，不修改传入的响应：缓存中保存的仍是上游的完整应答。
+//! UDP报文的大小上限和TC标志按整形后留下的部分计算，见 [`ResponseShaping::encode`]。
+
+use super::udp::UdpTransport;
+use super::OPT_RECORD_TYPE;
+use crate::error::Result;
+use crate::types::{EdnsRecord, RecordData, RecordType, Response, ResponseCode};
+use crate::utils::names_equal;
+use serde::{Deserialize, Serialize};
+
+/// 附加部分的保留方式，附加部分中的OPT记录总会去掉，应答的EDNS由 [`ResponseShaping::encode`] 的参数给出
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AdditionalsPolicy {
+    /// 保留全部附加记录
+    #[default]
+    All,
+    /// 只保留胶水记录：名称为保留下来的NS、MX、SRV记录目标的A/AAAA记录
+    GlueOnly,
+    /// 不保留附加记录
+    None,
+}
+
+/// 发给客户端的响应的整形选项，默认原样转发
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponseShaping {
+    /// 是否保留权威部分
+    pub include_authority: bool,
+    /// 附加部分的保留方式
+    pub include_additionals: AdditionalsPolicy,
+    /// 从OPT记录中去掉的EDNS选项代码，例如 [`edns_option_codes::CLIENT_ADDRESS`](crate::types::edns_option_codes::CLIENT_ADDRESS)
+    pub strip_edns_options: Vec<u16>,
+    /// 最小响应：有回答记录的NOERROR应答只给出回答部分，去掉权威和附加部分；
+    /// 否定应答仍按 `include_authority` 保留权威部分的SOA
+    pub minimal_responses: bool,
+}
+
+impl Default for ResponseShaping {
+    fn default() -> Self {
+        Self {
+            include_authority: true,
+            include_additionals: AdditionalsPolicy::All,
+            strip_edns_options: Vec::new(),
+            minimal_responses: false,
+        }
+    }
+}
+
+impl ResponseShaping {
+    /// 整形后的响应，`response` 本身不变
+    pub fn shape(&self, response: &Response) -> Response {
+        let minimal = self.minimal_responses
+            && response.rcode() == ResponseCode::NoError
+            && !response.answers.is_empty();
+        let authorities = if self.include_authority && !minimal {
+            response.authorities.clone()
+        } else {
+            Vec::new()
+        };
+        let additionals = match self.include_additionals {
+            _ if minimal => Vec::new(),
+            AdditionalsPolicy::All => response.additionals.iter()
+                .filter(|record| u16::from(record.rtype) != OPT_RECORD_TYPE)
+                .cloned()
+                .collect(),
+            AdditionalsPolicy::None => Vec::new(),
+            AdditionalsPolicy::GlueOnly => {
+                let targets: Vec<&str> = response.answers.iter().chain(&authorities)
+                    .filter_map(|record| match &record.data {
+                        RecordData::NS(target) | RecordData::MX { exchange: target, .. } | RecordData::SRV { target, .. } => Some(target.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                response.additionals.iter()
+                    .filter(|record| matches!(record.rtype, RecordType::A | RecordType::AAAA))
+                    .filter(|record| targets.iter().any(|target| names_equal(target, &record.name)))
+                    .cloned()
+                    .collect()
+            }
+        };
+        Response {
+            id: response.id,
+            flags: response.flags,
+            queries: response.queries.clone(),
+            answers: response.answers.clone(),
+            authorities,
+            additionals,
+        }
+    }
+
+    /// 去掉 `strip_edns_options` 中的选项后的OPT记录
+    pub fn shape_edns(&self, edns: &EdnsRecord) -> EdnsRecord {
+        EdnsRecord {
+            options: edns.options.iter()
+                .filter(|option| !self.strip_edns_options.contains(&option.code))
+                .cloned()
+                .collect(),
+            ..edns.clone()
+        }
+    }
+
+    /// 整形并编码发给客户端的报文，`edns` 为应答带的OPT记录（客户端的请求没有EDNS时应为 `None`）
+    ///
+    /// `max_size` 为UDP的报文上限（TCP为 `None`）：放不下时从整形后留下的记录尾部丢弃，
+    /// 只有回答或权威部分的记录被丢弃时设置TC，OPT记录总会保留，见 [`UdpTransport::serialize_response_with_edns`]
+    pub fn encode(&self, response: &Response, edns: Option<&EdnsRecord>, max_size: Option<usize>) -> Result<Vec<u8>> {
+        let shaped = self.shape(response);
+        let edns = edns.map(|edns| self.shape_edns(edns));
+        UdpTransport::serialize_response_with_edns(&shaped, edns.as_ref(), max_size.unwrap_or(u16::MAX as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{edns_option_codes, EdnsOption, Flags, QClass, Query, Record};
+    use std::net::Ipv4Addr;
+
+    fn record(name: &str, rtype: RecordType, data: RecordData) -> Record {
+        Record { name: name.to_string(), rtype, class: QClass::IN, ttl: 300, data }
+    }
+
+    fn srv_response() -> Response {
+        Response {
+            id: 7,
+            flags: Flags { qr: true, ..Flags::default() },
+            queries: vec![Query { name: "_sip._udp.example.test".to_string(), qtype: RecordType::SRV, qclass: QClass::IN }],
+            answers: vec![record("_sip._udp.example.test", RecordType::SRV, RecordData::SRV {
+                priority: 10,
+                weight: 5,
+                port: 5060,
+                target: "sip.example.test".to_string(),
+            })],
+            authorities: vec![record("example.test", RecordType::NS, RecordData::NS("ns1.example.test".to_string()))],
+            additionals: vec![
+                record("SIP.example.test.", RecordType::A, RecordData::A(Ipv4Addr::new(192, 0, 2, 5))),
+                record("ns1.example.test", RecordType::A, RecordData::A(Ipv4Addr::new(192, 0, 2, 53))),
+                record("other.example.test", RecordType::A, RecordData::A(Ipv4Addr::new(192, 0, 2, 9))),
+            ],
+        }
+    }
+
+    fn names(records: &[Record]) -> Vec<&str> {
+        records.iter().map(|record| record.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_default_passes_everything_through() {
+        let response = srv_response();
+        let shaped = ResponseShaping::default().shape(&response);
+        assert_eq!((shaped.answers.len(), shaped.authorities.len(), shaped.additionals.len()), (1, 1, 3));
+    }
+
+    #[test]
+    fn test_glue_only_follows_retained_targets() {
+        let response = srv_response();
+        let glue = ResponseShaping { include_additionals: AdditionalsPolicy::GlueOnly, ..ResponseShaping::default() };
+        assert_eq!(names(&glue.shape(&response).additionals), ["SIP.example.test.", "ns1.example.test"]);
+
+        // 权威部分去掉后，NS目标的地址不再是胶水
+        let no_authority = ResponseShaping { include_authority: false, ..glue };
+        let shaped = no_authority.shape(&response);
+        assert!(shaped.authorities.is_empty());
+        assert_eq!(names(&shaped.additionals), ["SIP.example.test."]);
+    }
+
+    #[test]
+    fn test_minimal_responses_only_trim_positive_answers() {
+        let minimal = ResponseShaping { minimal_responses: true, ..ResponseShaping::default() };
+        let shaped = minimal.shape(&srv_response());
+        assert_eq!((shaped.answers.len(), shaped.authorities.len(), shaped.additionals.len()), (1, 0, 0));
+
+        let mut negative = srv_response();
+        negative.flags.rcode = 3;
+        negative.answers.clear();
+        let shaped = minimal.shape(&negative);
+        assert_eq!((shaped.authorities.len(), shaped.additionals.len()), (1, 3));
+    }
+
+    #[test]
+    fn test_stripped_options_and_truncation_account_for_shaping() {
+        let edns = EdnsRecord {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: vec![
+                EdnsOption { code: edns_option_codes::CLIENT_ADDRESS, data: vec![0, 1, 24, 0, 192, 0, 2] },
+                EdnsOption { code: edns_option_codes::COOKIE, data: vec![1; 8] },
+            ],
+        };
+        let shaping = ResponseShaping {
+            include_additionals: AdditionalsPolicy::None,
+            strip_edns_options: vec![edns_option_codes::CLIENT_ADDRESS],
+            ..ResponseShaping::default()
+        };
+        let bytes = shaping.encode(&srv_response(), Some(&edns), None).unwrap();
+        let parsed = UdpTransport::deserialize_response(&bytes).unwrap();
+        assert!(parsed.additionals.iter().all(|record| u16::from(record.rtype) == OPT_RECORD_TYPE));
+        let options = UdpTransport::parse_edns(&bytes).unwrap().unwrap().options;
+        assert_eq!(options.iter().map(|option| option.code).collect::<Vec<_>>(), [edns_option_codes::COOKIE]);
+
+        // 保留附加部分时同样的上限放不下附加记录，丢弃附加记录不设置TC，OPT记录仍在
+        let keep_additionals = ResponseShaping { include_additionals: AdditionalsPolicy::All, ..shaping.clone() };
+        let full = keep_additionals.encode(&srv_response(), Some(&edns), None).unwrap();
+        let limit = bytes.len();
+        assert!(full.len() > limit);
+        let limited = keep_additionals.encode(&srv_response(), Some(&edns), Some(limit)).unwrap();
+        let parsed = UdpTransport::deserialize_response(&limited).unwrap();
+        assert!(!parsed.flags.tc);
+        assert_eq!(limited, bytes);
+
+        // 回答放不下时设置TC
+        let answer_only = ResponseShaping { minimal_responses: true, ..ResponseShaping::default() };
+        let answer_len = answer_only.encode(&srv_response(), Some(&edns), None).unwrap().len();
+        let truncated = answer_only.encode(&srv_response(), Some(&edns), Some(answer_len - 1)).unwrap();
+        let parsed = UdpTransport::deserialize_response(&truncated).unwrap();
+        assert!(parsed.flags.tc && parsed.answers.is_empty());
+    }
+}
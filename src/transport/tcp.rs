@@ -1,25 +1,56 @@
 //! TCP传输实现
 
 use crate::{Request, Response, Result, DnsError};
-use super::{Transport, TransportConfig};
+use crate::resolver::clock::{self, Clock};
+use super::{Transport, TransportConfig, STREAM_EDNS_PAYLOAD_SIZE};
 use super::udp::UdpTransport;
+use super::query_id::ensure_matching_id;
+use super::timing::{TimingPhase, TimingRecorder, TransportPhase, TransportTiming};
+use super::wire::{WireCapture, WireRecorder};
+use super::upstream_addr::{AddressFamilyStats, HostResolution, UpstreamAddress};
+use super::fast_open::{self, FastOpenCounters, FastOpenStats};
+use super::framing::{self, FrameBufferPool, LENGTH_PREFIX};
 use async_trait::async_trait;
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::time::timeout;
+use tokio::io::{AsyncRead, AsyncWriteExt};
 use crate::{dns_debug, dns_info, dns_error, dns_transport};
 
 /// TCP传输实现
 #[derive(Debug)]
 pub struct TcpTransport {
     config: TransportConfig,
+    /// 连接目标，以主机名配置时解析后缓存
+    address: UpstreamAddress,
+    /// 开启 `tcp_fast_open` 时的TCP Fast Open计数
+    fast_open: Option<FastOpenCounters>,
+    /// 请求和响应共用的分帧缓冲区，查询之间复用
+    buffers: FrameBufferPool,
+    /// 超时和耗时统计使用的时间源
+    clock: Arc<dyn Clock>,
 }
 
 impl TcpTransport {
-    /// 创建新的TCP传输
+    /// 创建新的TCP传输，以主机名配置的服务器由系统解析器解析
     pub fn new(config: TransportConfig) -> Self {
-        Self { config }
+        let address = UpstreamAddress::new(config.server.clone(), config.port, HostResolution::default());
+        let fast_open = config.tcp_fast_open.then(FastOpenCounters::default);
+        let buffers = FrameBufferPool::new(config.buffer_size, config.pool_size);
+        Self { config, address, fast_open, buffers, clock: clock::real_clock() }
+    }
+    
+    /// 设置以主机名配置的服务器的解析方式
+    pub fn with_host_resolution(mut self, resolution: HostResolution) -> Self {
+        self.address = UpstreamAddress::new(self.config.server.clone(), self.config.port, resolution);
+        self
+    }
+    
+    /// 使用指定的时间源计算超时和各阶段耗时，默认为真实时钟
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
     }
     
     // 注意：移除了 default() 方法，因为它依赖兜底配置
@@ -34,121 +65,206 @@ impl TcpTransport {
     //     tcp_fast_open: false,
     //     tcp_nodelay: true,
     //     pool_size: 10,
+    //     buffer_size: 4096,
+    //     record_limits: None,
     // })
     
-    /// 序列化DNS请求为TCP格式(带长度前缀)
-    fn serialize_request_tcp(request: &Request) -> Result<Vec<u8>> {
-        // 复用UDP的序列化逻辑
-        let udp_data = UdpTransport::serialize_request(request)?;
-        
-        // TCP格式需要2字节长度前缀
-        let mut tcp_data = Vec::with_capacity(udp_data.len() + 2);
-        tcp_data.extend_from_slice(&(udp_data.len() as u16).to_be_bytes());
-        tcp_data.extend_from_slice(&udp_data);
-        
-        Ok(tcp_data)
+    /// 编码请求报文（不含长度前缀），OPT记录声明流式传输的载荷大小
+    pub fn encode_request(request: &Request) -> Result<Vec<u8>> {
+        UdpTransport::serialize_request_with_payload_size(request, STREAM_EDNS_PAYLOAD_SIZE)
     }
     
-    /// 从TCP流读取完整的DNS响应
-    async fn read_tcp_response(stream: &mut TcpStream) -> Result<Vec<u8>> {
-        // 读取2字节长度前缀
-        let mut length_buf = [0u8; 2];
-        stream.read_exact(&mut length_buf).await
-            .map_err(|e| DnsError::Network(format!("Failed to read length: {}", e)))?;
-        
-        let length = u16::from_be_bytes(length_buf) as usize;
+    /// 把请求编码为带2字节长度前缀的一帧，写入清空后的 `buffer`（TCP与DoT共用）
+    /// 
+    /// 报文直接编码在前缀之后，不另做拼接；`buffer` 可以在查询之间复用，已分配的容量足够时不再分配内存
+    pub fn encode_request_framed(request: &Request, buffer: &mut Vec<u8>) -> Result<()> {
+        framing::encode_request(request, buffer)
+    }
+    
+    /// 建立到上游的TCP连接，并按配置设置TCP选项；解析和连接合计受 `timeout` 限制
+    pub(crate) async fn connect(&self) -> Result<TcpStream> {
+        let mut timing = TimingRecorder::disabled();
+        let deadline = self.clock.now_instant() + self.config.timeout;
+        clock::timeout_at(&*self.clock, deadline, self.connect_timed(deadline, &mut timing)).await
+            .unwrap_or_else(|| Err(timing.timed_out()))
+    }
+    
+    async fn connect_timed(&self, deadline: Instant, timing: &mut TimingRecorder) -> Result<TcpStream> {
+        let stream = Self::open_stream(&self.address, &*self.clock, deadline, self.fast_open.as_ref(), timing).await?;
         
-        if length == 0 {
-            return Err(DnsError::Protocol("Invalid response length".to_string()));
+        // 设置TCP选项
+        if self.config.tcp_nodelay
+            && let Err(e) = stream.set_nodelay(true)
+        {
+            // 记录警告但不失败
+            dns_error!("Failed to set TCP_NODELAY: {}", e);
         }
         
-        if length > 65535 {
-            return Err(DnsError::Protocol("Response too large".to_string()));
+        Ok(stream)
+    }
+    
+    /// 建立TCP连接（TCP与DoT共用）
+    /// 
+    /// 上游以主机名配置时先取得解析后的地址（缓存到期时重新解析），把解析和连接分别计为两个阶段；
+    /// 解析出多个地址时按地址族偏好依次尝试，后面还有地址时每个地址最多用掉到 `clock` 的 `deadline` 为止剩余时间的一半，
+    /// 连接结果按地址族分别记录。传入 `fast_open` 时以TCP Fast Open方式连接，见 [`fast_open`]
+    pub(crate) async fn open_stream(
+        address: &UpstreamAddress,
+        clock: &dyn Clock,
+        deadline: Instant,
+        fast_open: Option<&FastOpenCounters>,
+        timing: &mut TimingRecorder,
+    ) -> Result<TcpStream> {
+        let server_addr = super::host_port(address.host(), address.port());
+        if address.is_hostname() {
+            timing.enter(TransportPhase::UpstreamResolve);
+        }
+        let addrs = address.dial_addrs(deadline.saturating_duration_since(clock.now_instant())).await?;
+        if address.is_hostname() {
+            timing.mark(TimingPhase::UpstreamResolve);
         }
         
-        // 读取实际的DNS响应数据
-        let mut response_buf = vec![0u8; length];
-        stream.read_exact(&mut response_buf).await
-            .map_err(|e| DnsError::Network(format!("Failed to read response: {}", e)))?;
-        
+        timing.enter(TransportPhase::TcpConnect);
+        let mut last_error = None;
+        let count = addrs.len();
+        for (index, addr) in addrs.into_iter().enumerate() {
+            let connect = async {
+                match fast_open {
+                    Some(counters) => fast_open::connect(addr, counters).await,
+                    None => TcpStream::connect(addr).await,
+                }
+            };
+            let error = match super::within_share(clock, deadline, index + 1 == count, connect).await {
+                Some(Ok(stream)) => {
+                    address.record_family(addr, true);
+                    timing.mark(TimingPhase::TcpConnect);
+                    timing.set_peer(stream.peer_addr().unwrap_or(addr));
+                    return Ok(stream);
+                }
+                Some(Err(e)) => DnsError::network_io(server_addr.clone(), "Connection failed", &e),
+                None => timing.timed_out(),
+            };
+            dns_debug!("连接 {} ({}) 失败: {}", server_addr, addr, error);
+            address.record_family(addr, false);
+            last_error = Some(error);
+        }
+        Err(last_error.unwrap_or_else(|| DnsError::network(server_addr, "No address to connect to")))
+    }
+    
+    /// 从TCP流读取一条完整的DNS消息（2字节长度前缀 + 消息体）
+    /// 
+    /// 同一连接上可以连续调用，逐条读取多消息响应（如区域传送）
+    pub(crate) async fn read_tcp_response<S>(stream: &mut S) -> Result<Vec<u8>>
+    where
+        S: AsyncRead + Unpin,
+    {
+        Self::read_framed_response(stream, usize::from(u16::MAX), &mut TimingRecorder::disabled()).await
+    }
+    
+    /// 读取一条带长度前缀的DNS消息，读到长度前缀时记为首字节
+    /// 
+    /// 长度前缀超过 `max_size` 时在分配缓冲区之前返回 [`DnsError::Protocol`]
+    pub(crate) async fn read_framed_response<S>(stream: &mut S, max_size: usize, timing: &mut TimingRecorder) -> Result<Vec<u8>>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let mut response_buf = Vec::new();
+        framing::read_message(stream, max_size, &mut response_buf, timing).await?;
         Ok(response_buf)
     }
-}
-
-#[async_trait]
-impl Transport for TcpTransport {
-    async fn send(&self, request: &Request) -> Result<Response> {
+    
+    /// 读完响应后记录SYN中的数据是否被接受
+    fn observe_fast_open(&self, stream: &TcpStream, timing: &mut TimingRecorder) {
+        if let Some(counters) = &self.fast_open {
+            timing.set_tcp_fast_open(counters.observe(stream));
+        }
+    }
+    
+    /// 发送请求并接收响应，`timing` 开启时在各阶段结束时打点；网络失败计入地址的连续失败次数
+    /// 
+    /// `timeout` 限制整个过程，超时的错误带有当时所处的阶段
+    async fn exchange(&self, request: &Request, timing: &mut TimingRecorder, wire: &mut WireRecorder) -> Result<Response> {
+        let deadline = self.clock.now_instant() + self.config.timeout;
+        let result = clock::timeout_at(&*self.clock, deadline, self.try_exchange(request, deadline, timing, wire)).await
+            .unwrap_or_else(|| Err(timing.timed_out()));
+        self.address.observe(&result);
+        result
+    }
+    
+    async fn try_exchange(&self, request: &Request, deadline: Instant, timing: &mut TimingRecorder, wire: &mut WireRecorder) -> Result<Response> {
         use crate::dns_debug;
         dns_debug!("TCP请求开始: {} -> {}:{}", request.query.name, self.config.server, self.config.port);
-        let server_addr = format!("{}:{}", self.config.server, self.config.port);
-        
-        // 建立TCP连接
-        let connect_result = timeout(
-            self.config.timeout,
-            TcpStream::connect(&server_addr)
-        ).await;
-        
-        let mut stream = match connect_result {
-            Ok(Ok(stream)) => stream,
-            Ok(Err(e)) => return Err(DnsError::Network(format!("Connection failed: {}", e))),
-            Err(_) => return Err(DnsError::Timeout),
-        };
-        
-        // 设置TCP选项
-        if self.config.tcp_nodelay {
-            if let Err(e) = stream.set_nodelay(true) {
-                // 记录警告但不失败
-                dns_error!("Failed to set TCP_NODELAY: {}", e);
-            }
-        }
-        
-        // 序列化请求
-        let request_data = Self::serialize_request_tcp(request)?;
+        let mut stream = self.connect_timed(deadline, timing).await?;
         
-        // 发送请求
-        let send_result = timeout(
-            self.config.timeout,
-            stream.write_all(&request_data)
-        ).await;
+        // 序列化请求，响应随后读入同一个缓冲区
+        let mut buffer = self.buffers.get();
+        Self::encode_request_framed(request, &mut buffer)?;
+        wire.record_request(&buffer[LENGTH_PREFIX..]);
         
-        match send_result {
-            Ok(Ok(_)) => {},
-            Ok(Err(e)) => return Err(DnsError::Network(format!("Send failed: {}", e))),
-            Err(_) => return Err(DnsError::Timeout),
-        }
-        
-        // 确保数据发送完毕
-        let flush_result = timeout(
-            self.config.timeout,
-            stream.flush()
-        ).await;
-        
-        match flush_result {
-            Ok(Ok(_)) => {},
-            Ok(Err(e)) => return Err(DnsError::Network(format!("Flush failed: {}", e))),
-            Err(_) => return Err(DnsError::Timeout),
-        }
+        // 发送请求并确保数据发送完毕
+        timing.enter(TransportPhase::RequestSent);
+        stream.write_all(&buffer).await
+            .map_err(|e| DnsError::network_io(self.endpoint(), "Send failed", &e))?;
+        stream.flush().await
+            .map_err(|e| DnsError::network_io(self.endpoint(), "Flush failed", &e))?;
+        timing.mark(TimingPhase::RequestSent);
         
         // 读取响应
-        let response_data = timeout(
-            self.config.timeout,
-            Self::read_tcp_response(&mut stream)
-        ).await;
-        
-        let response_bytes = match response_data {
-            Ok(Ok(data)) => data,
-            Ok(Err(e)) => return Err(e),
-            Err(_) => return Err(DnsError::Timeout),
-        };
+        framing::read_message(&mut stream, self.config.buffer_size, &mut buffer, timing).await?;
+        wire.record_response(&buffer);
+        self.observe_fast_open(&stream, timing);
         
         // 复用UDP的反序列化逻辑
-        UdpTransport::deserialize_response(&response_bytes)
+        UdpTransport::deserialize_response_with_limits(&buffer, self.config.record_limits.as_ref())
+            .and_then(|response| ensure_matching_id(request, response))
+    }
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn send(&self, request: &Request) -> Result<Response> {
+        self.exchange(request, &mut TimingRecorder::disabled(), &mut WireRecorder::disabled()).await
+    }
+    
+    async fn prepare(&self) -> Result<()> {
+        self.address.socket_addrs(self.config.timeout).await.map(|_| ())
+    }
+    
+    async fn send_with_peer(&self, request: &Request) -> Result<(Response, Option<SocketAddr>)> {
+        let mut timing = TimingRecorder::disabled();
+        let response = self.exchange(request, &mut timing, &mut WireRecorder::disabled()).await?;
+        Ok((response, timing.finish().peer))
+    }
+    
+    async fn send_timed(&self, request: &Request) -> Result<(Response, TransportTiming)> {
+        let mut timing = TimingRecorder::start(&self.clock);
+        let response = self.exchange(request, &mut timing, &mut WireRecorder::disabled()).await?;
+        Ok((response, timing.finish()))
+    }
+    
+    async fn send_captured(&self, request: &Request, max_bytes: usize) -> Result<(Response, TransportTiming, WireCapture)> {
+        let mut timing = TimingRecorder::start(&self.clock);
+        let mut wire = WireRecorder::with_limit(max_bytes);
+        let response = self.exchange(request, &mut timing, &mut wire).await?;
+        Ok((response, timing.finish(), wire.finish()))
+    }
+    
+    fn address_family_stats(&self) -> Option<AddressFamilyStats> {
+        Some(self.address.family_stats())
+    }
+    
+    fn fast_open_stats(&self) -> Option<FastOpenStats> {
+        self.fast_open.as_ref().map(FastOpenCounters::snapshot)
     }
     
     fn transport_type(&self) -> &'static str {
         "TCP"
     }
     
+    fn endpoint(&self) -> String {
+        super::host_port(&self.config.server, self.config.port)
+    }
+    
     fn set_timeout(&mut self, timeout: Duration) {
         self.config.timeout = timeout;
     }
@@ -156,4 +272,74 @@ impl Transport for TcpTransport {
     fn timeout(&self) -> Duration {
         self.config.timeout
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Flags, Query, QClass, RecordType};
+    use tokio::net::TcpListener;
+
+    /// 接受连接并读走请求的上游，之后只写出 `reply`（可以为空）然后一直不再发送
+    async fn stalling_upstream(reply: &'static [u8]) -> u16 {
+        use tokio::io::AsyncReadExt;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buffer = [0u8; 512];
+                let _ = stream.read(&mut buffer).await;
+                let _ = stream.write_all(reply).await;
+                held.push(stream);
+            }
+        });
+        port
+    }
+
+    fn transport(port: u16) -> TcpTransport {
+        TcpTransport::new(TransportConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            timeout: Duration::from_millis(300),
+            tcp_fast_open: false,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size: 4096,
+            record_limits: None,
+        })
+    }
+
+    fn request() -> Request {
+        Request {
+            id: 0x2468,
+            flags: Flags::default(),
+            query: Query {
+                name: "example.com".to_string(),
+                qtype: RecordType::A,
+                qclass: QClass::IN,
+            },
+            client_address: None,
+            enable_edns: false,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: crate::QueryPriority::Normal,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalls_end_within_timeout_and_report_phase() {
+        // 从不应答时停在等待首字节，只给出长度前缀时停在读取响应的其余部分
+        for (reply, phase) in [
+            (&b""[..], TransportPhase::FirstByte),
+            (&b"\x00\x40"[..], TransportPhase::Complete),
+        ] {
+            let transport = transport(stalling_upstream(reply).await);
+            let start = Instant::now();
+            let error = transport.send(&request()).await.unwrap_err();
+            let elapsed = start.elapsed();
+            assert!(matches!(error, DnsError::Timeout { phase: Some(p) } if p == phase), "{:?}", error);
+            assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_millis(550), "{:?}", elapsed);
+        }
+    }
+}
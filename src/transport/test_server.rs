@@ -0,0 +1,397 @@
+//! 进程内权威DNS服务器（需要启用 `test-util` 特性）
+//!
+//! 在 `127.0.0.1` 的同一端口上同时监听UDP和TCP，按 (域名, 记录类型) 从可编程的区域表应答，
+//! 记录每一个成功解析的请求，并可注入故障（丢弃前N个请求、延迟、UDP截断、错误ID、垃圾数据），
+//! 用于不访问外部网络地走通真实的UDP/TCP传输。
+//!
+//! ```ignore
+//! use rat_quickdns::transport::test_server::TestDnsServer;
+//! use std::net::Ipv4Addr;
+//!
+//! let server = TestDnsServer::start().await?;
+//! server.add_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+//! server.drop_first(1); // 第一个请求不应答，验证重试
+//!
+//! let resolver = DnsResolverBuilder::new(QueryStrategy::Sequential, false, "global".to_string())
+//!     .add_udp_upstream("fixture", server.addr().to_string())
+//!     .build()
+//!     .await?;
+//! resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await?;
+//! assert_eq!(server.request_count(), 2);
+//! ```
+//!
+//! 区域表中没有的域名应答NXDOMAIN，域名存在但没有所需类型时应答没有记录的NOERROR。
+//! 应答按 [`ResponseShaping`] 整形后编码（默认原样发送），用于驱动服务端的响应整形选项。
+//! 操作码不是QUERY的报文（NOTIFY、UPDATE等）一律应答NOTIMP，不查区域表、不计入请求记录，也不受故障注入影响。
+
+use super::response_shaping::ResponseShaping;
+use super::udp::UdpTransport;
+use crate::types::{edns_option_codes, EdnsOption, EdnsRecord, Flags, Opcode, QClass, Query, Record, RecordData, RecordType, Request, Response};
+use crate::utils::normalize_name;
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+
+/// 请求到达的协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerProtocol {
+    /// UDP
+    Udp,
+    /// TCP（带两字节长度前缀）
+    Tcp,
+}
+
+/// 一个被记录的请求
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    /// 解析出的请求
+    pub request: Request,
+    /// 到达的协议
+    pub protocol: ServerProtocol,
+    /// 收到的时间
+    pub at: Instant,
+    /// 发送请求的客户端地址
+    pub peer: SocketAddr,
+}
+
+#[derive(Debug, Clone)]
+enum Answer {
+    Records(Vec<Record>),
+    /// 回答、权威、附加三部分
+    Sections(Vec<Record>, Vec<Record>, Vec<Record>),
+    /// 原样发送的报文（前两个字节换成请求ID）
+    Raw(Vec<u8>),
+}
+
+#[derive(Debug, Default)]
+struct Faults {
+    drop_first: AtomicUsize,
+    wrong_id_first: AtomicUsize,
+    garbage_first: AtomicUsize,
+    delay: Mutex<Duration>,
+    udp_max_size: Mutex<Option<usize>>,
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    zone: Mutex<HashMap<(String, RecordType), Answer>>,
+    requests: Mutex<Vec<ReceivedRequest>>,
+    unsupported_opcodes: AtomicUsize,
+    faults: Faults,
+    shaping: Mutex<ResponseShaping>,
+    /// 对带EDNS的请求应答OPT记录时携带的选项，`None` 表示应答不带OPT记录
+    edns_options: Mutex<Option<Vec<EdnsOption>>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn zone_key(name: &str, rtype: RecordType) -> (String, RecordType) {
+    (normalize_name(name), rtype)
+}
+
+/// 计数大于0时减1并返回true
+fn take_one(counter: &AtomicUsize) -> bool {
+    counter.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok()
+}
+
+/// 对一个请求的处置
+enum Action {
+    Drop,
+    Reply(Vec<u8>),
+}
+
+/// 进程内权威DNS服务器，释放时停止监听
+#[derive(Debug)]
+pub struct TestDnsServer {
+    addr: SocketAddr,
+    state: Arc<ServerState>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for TestDnsServer {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl TestDnsServer {
+    /// 在 `127.0.0.1` 的随机端口上启动，UDP和TCP使用同一端口
+    pub async fn start() -> io::Result<Self> {
+        // 系统为UDP分配的端口在TCP上可能已被占用，换端口重试几次
+        let mut last_error = None;
+        for _ in 0..16 {
+            let udp = UdpSocket::bind("127.0.0.1:0").await?;
+            let addr = udp.local_addr()?;
+            match TcpListener::bind(addr).await {
+                Ok(tcp) => return Ok(Self::spawn(addr, udp, tcp)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::AddrInUse, "no free port")))
+    }
+
+    fn spawn(addr: SocketAddr, udp: UdpSocket, tcp: TcpListener) -> Self {
+        let state = Arc::new(ServerState::default());
+        let udp_task = tokio::spawn(serve_udp(udp, state.clone()));
+        let tcp_task = tokio::spawn(serve_tcp(tcp, state.clone()));
+        Self { addr, state, tasks: vec![udp_task, tcp_task] }
+    }
+
+    /// 监听地址
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 监听端口（UDP和TCP相同）
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// 设置 (域名, 类型) 的应答记录，替换已有的
+    pub fn set_records(&self, name: &str, rtype: RecordType, records: Vec<Record>) {
+        lock(&self.state.zone).insert(zone_key(name, rtype), Answer::Records(records));
+    }
+
+    /// 设置A记录
+    pub fn add_a(&self, name: &str, ips: &[Ipv4Addr], ttl: u32) {
+        let records = ips.iter().map(|ip| record(name, RecordType::A, ttl, RecordData::A(*ip))).collect();
+        self.set_records(name, RecordType::A, records);
+    }
+
+    /// 设置AAAA记录
+    pub fn add_aaaa(&self, name: &str, ips: &[Ipv6Addr], ttl: u32) {
+        let records = ips.iter().map(|ip| record(name, RecordType::AAAA, ttl, RecordData::AAAA(*ip))).collect();
+        self.set_records(name, RecordType::AAAA, records);
+    }
+
+    /// 设置 (域名, 类型) 的回答、权威和附加部分，替换已有的
+    pub fn set_sections(&self, name: &str, rtype: RecordType, answers: Vec<Record>, authorities: Vec<Record>, additionals: Vec<Record>) {
+        lock(&self.state.zone).insert(zone_key(name, rtype), Answer::Sections(answers, authorities, additionals));
+    }
+
+    /// 设置应答发给客户端前的整形选项，UDP截断按整形后的报文计算
+    pub fn set_response_shaping(&self, shaping: ResponseShaping) {
+        *lock(&self.state.shaping) = shaping;
+    }
+
+    /// 对带EDNS的请求应答OPT记录，携带 `options`；请求带客户端子网时按RFC 7871回显该选项。`None` 表示不带OPT记录（默认）
+    pub fn respond_with_edns(&self, options: Option<Vec<EdnsOption>>) {
+        *lock(&self.state.edns_options) = options;
+    }
+
+    /// 对 (域名, 类型) 原样回应 `bytes`，用于畸形响应的测试；不少于2字节时前两个字节换成请求ID
+    pub fn set_raw_reply(&self, name: &str, rtype: RecordType, bytes: Vec<u8>) {
+        lock(&self.state.zone).insert(zone_key(name, rtype), Answer::Raw(bytes));
+    }
+
+    /// 之后的前 `count` 个请求不应答（UDP丢弃，TCP关闭连接）
+    pub fn drop_first(&self, count: usize) {
+        self.state.faults.drop_first.store(count, Ordering::SeqCst);
+    }
+
+    /// 每个应答前等待 `delay`
+    pub fn set_delay(&self, delay: Duration) {
+        *lock(&self.state.faults.delay) = delay;
+    }
+
+    /// UDP应答超过 `max_size` 字节时按 [`UdpTransport::serialize_response_limited`] 截断并设置TC；`None` 表示不截断
+    pub fn truncate_udp(&self, max_size: Option<usize>) {
+        *lock(&self.state.faults.udp_max_size) = max_size;
+    }
+
+    /// 之后的前 `count` 个请求用ID不符的应答回应（只发送错误的应答）
+    pub fn wrong_id_first(&self, count: usize) {
+        self.state.faults.wrong_id_first.store(count, Ordering::SeqCst);
+    }
+
+    /// 之后的前 `count` 个请求用不是DNS报文的垃圾数据回应（保留请求ID）
+    pub fn garbage_first(&self, count: usize) {
+        self.state.faults.garbage_first.store(count, Ordering::SeqCst);
+    }
+
+    /// 收到的所有请求，按到达顺序
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        lock(&self.state.requests).clone()
+    }
+
+    /// 收到的请求数
+    pub fn request_count(&self) -> usize {
+        lock(&self.state.requests).len()
+    }
+
+    /// 清空请求记录
+    pub fn clear_requests(&self) {
+        lock(&self.state.requests).clear();
+    }
+
+    /// 以NOTIMP应答的非QUERY报文数
+    pub fn unsupported_opcode_count(&self) -> usize {
+        self.state.unsupported_opcodes.load(Ordering::SeqCst)
+    }
+}
+
+fn record(name: &str, rtype: RecordType, ttl: u32, data: RecordData) -> Record {
+    Record { name: name.to_string(), rtype, class: QClass::IN, ttl, data }
+}
+
+async fn serve_udp(socket: UdpSocket, state: Arc<ServerState>) {
+    let mut buffer = vec![0u8; 65535];
+    loop {
+        let Ok((len, peer)) = socket.recv_from(&mut buffer).await else {
+            continue;
+        };
+        if let Some(Action::Reply(bytes)) = handle(&state, &buffer[..len], ServerProtocol::Udp, peer).await {
+            let _ = socket.send_to(&bytes, peer).await;
+        }
+    }
+}
+
+async fn serve_tcp(listener: TcpListener, state: Arc<ServerState>) {
+    loop {
+        let Ok((stream, peer)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(serve_tcp_connection(stream, peer, state.clone()));
+    }
+}
+
+async fn serve_tcp_connection(mut stream: TcpStream, peer: SocketAddr, state: Arc<ServerState>) {
+    loop {
+        let mut length = [0u8; 2];
+        if stream.read_exact(&mut length).await.is_err() {
+            return;
+        }
+        let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+        if stream.read_exact(&mut message).await.is_err() {
+            return;
+        }
+        let Some(Action::Reply(bytes)) = handle(&state, &message, ServerProtocol::Tcp, peer).await else {
+            return;
+        };
+        let mut framed = (bytes.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&bytes);
+        if stream.write_all(&framed).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// 解析并记录请求，按区域表和故障设置给出处置；无法解析的请求不应答，非QUERY报文应答NOTIMP
+async fn handle(state: &ServerState, data: &[u8], protocol: ServerProtocol, peer: SocketAddr) -> Option<Action> {
+    let request = UdpTransport::deserialize_request(data).ok()?;
+    if request.flags.opcode != Opcode::Query {
+        state.unsupported_opcodes.fetch_add(1, Ordering::SeqCst);
+        return UdpTransport::serialize_response(&not_implemented(&request)).ok().map(Action::Reply);
+    }
+    lock(&state.requests).push(ReceivedRequest { request: request.clone(), protocol, at: Instant::now(), peer });
+
+    let faults = &state.faults;
+    if take_one(&faults.drop_first) {
+        return Some(Action::Drop);
+    }
+    let delay = *lock(&faults.delay);
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    if take_one(&faults.garbage_first) {
+        let mut garbage = request.id.to_be_bytes().to_vec();
+        garbage.extend_from_slice(b"\xff\xfe not a dns message");
+        return Some(Action::Reply(garbage));
+    }
+
+    let answer = lock(&state.zone).get(&zone_key(&request.query.name, request.query.qtype)).cloned();
+    let mut bytes = match answer {
+        Some(Answer::Raw(mut bytes)) => {
+            if bytes.len() >= 2 {
+                bytes[..2].copy_from_slice(&request.id.to_be_bytes());
+            }
+            bytes
+        }
+        answer => {
+            let response = build_response(state, &request, answer);
+            let max_size = match protocol {
+                ServerProtocol::Udp => *lock(&faults.udp_max_size),
+                ServerProtocol::Tcp => None,
+            };
+            let edns = response_edns(state, &request);
+            lock(&state.shaping).encode(&response, edns.as_ref(), max_size).ok()?
+        }
+    };
+    if take_one(&faults.wrong_id_first) && bytes.len() >= 2 {
+        bytes[..2].copy_from_slice(&request.id.wrapping_add(1).to_be_bytes());
+    }
+    Some(Action::Reply(bytes))
+}
+
+/// 应答的OPT记录：开启了 [`TestDnsServer::respond_with_edns`] 且请求带EDNS时生成
+fn response_edns(state: &ServerState, request: &Request) -> Option<EdnsRecord> {
+    let mut options = lock(&state.edns_options).clone().filter(|_| request.enable_edns)?;
+    if let Some(client_address) = &request.client_address {
+        options.push(EdnsOption { code: edns_option_codes::CLIENT_ADDRESS, data: client_address.encode() });
+    }
+    Some(EdnsRecord { udp_payload_size: 1232, extended_rcode: 0, version: 0, dnssec_ok: request.dnssec_ok, options })
+}
+
+fn build_response(state: &ServerState, request: &Request, answer: Option<Answer>) -> Response {
+    let (records, authorities, additionals) = match answer {
+        Some(Answer::Records(records)) => (Some(records), Vec::new(), Vec::new()),
+        Some(Answer::Sections(answers, authorities, additionals)) => (Some(answers), authorities, additionals),
+        _ => (None, Vec::new(), Vec::new()),
+    };
+    // 同一域名有其他类型的记录时是NOERROR/NODATA，否则是NXDOMAIN
+    let name_exists = records.is_some() || {
+        let name = zone_key(&request.query.name, request.query.qtype).0;
+        lock(&state.zone).keys().any(|(existing, _)| *existing == name)
+    };
+    Response {
+        id: request.id,
+        flags: Flags {
+            qr: true,
+            opcode: Opcode::Query,
+            aa: true,
+            tc: false,
+            rd: request.flags.rd,
+            ra: false,
+            reserved: false,
+            ad: false,
+            cd: request.flags.cd,
+            rcode: if name_exists { 0 } else { 3 },
+        },
+        queries: vec![Query {
+            name: request.query.name.clone(),
+            qtype: request.query.qtype,
+            qclass: request.query.qclass,
+        }],
+        answers: records.unwrap_or_default(),
+        authorities,
+        additionals,
+    }
+}
+
+/// 非QUERY报文的应答：回显ID、操作码和问题（UPDATE为区域），响应码NOTIMP
+fn not_implemented(request: &Request) -> Response {
+    Response {
+        id: request.id,
+        flags: Flags {
+            qr: true,
+            opcode: request.flags.opcode,
+            rd: request.flags.rd,
+            rcode: 4,
+            ..Flags::default()
+        },
+        queries: vec![request.query.clone()],
+        answers: Vec::new(),
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+    }
+}
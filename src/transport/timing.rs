@@ -0,0 +1,300 @@
+//! 传输分阶段耗时
+//!
+//! 开启耗时分解后，传输在每个阶段结束时打点，结果为相对发送开始的偏移。
+//! 未开启时记录器为空，打点不读取时钟。
+//!
+//! 不论是否开启，记录器都记着正在进行的阶段（不读取时钟）：传输的 `timeout` 限制整个发送过程，
+//! 超时时由它得出 [`DnsError::Timeout`] 中的阶段。
+
+#[cfg(feature = "serde-api")]
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::time::Instant;
+
+use crate::DnsError;
+use crate::resolver::clock::Clock;
+
+/// 一次发送的分阶段耗时，各字段为该阶段结束时相对发送开始的偏移
+///
+/// 传输不经历或无法观测的阶段为 `None`：UDP只有发送和接收，
+/// DoH只能观测到响应头到达（`first_byte`）和读完正文
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TransportTiming {
+    /// 上游主机名解析完成（上游以IP配置时为 `None`）
+    pub upstream_resolve: Option<Duration>,
+    /// TCP连接建立
+    pub tcp_connect: Option<Duration>,
+    /// TLS握手完成
+    pub tls_handshake: Option<Duration>,
+    /// 请求写出
+    pub request_sent: Option<Duration>,
+    /// 收到响应的第一个字节
+    pub first_byte: Option<Duration>,
+    /// 响应读取并解析完成
+    pub complete: Duration,
+    /// 实际承载这次DoH查询的HTTP版本（其他传输为 `None`）
+    pub http_version: Option<HttpVersion>,
+    /// 实际交换报文的对端地址，取不到时为 `None`
+    pub peer: Option<SocketAddr>,
+    /// 开启TCP Fast Open时SYN中的数据是否被服务器接受；未开启或无法观测（非Linux）时为 `None`
+    pub tcp_fast_open: Option<bool>,
+}
+
+/// DoH查询实际使用的HTTP版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub enum HttpVersion {
+    /// HTTP/1.x
+    Http1,
+    /// HTTP/2
+    Http2,
+    /// HTTP/3（QUIC）
+    Http3,
+}
+
+#[cfg(feature = "doh")]
+impl From<reqwest::Version> for HttpVersion {
+    fn from(version: reqwest::Version) -> Self {
+        match version {
+            reqwest::Version::HTTP_2 => Self::Http2,
+            reqwest::Version::HTTP_3 => Self::Http3,
+            _ => Self::Http1,
+        }
+    }
+}
+
+impl fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Http1 => "HTTP/1.1",
+            Self::Http2 => "HTTP/2",
+            Self::Http3 => "HTTP/3",
+        })
+    }
+}
+
+/// 传输打点的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimingPhase {
+    /// 上游主机名解析
+    UpstreamResolve,
+    /// TCP连接
+    TcpConnect,
+    /// TLS握手
+    TlsHandshake,
+    /// 写出请求
+    RequestSent,
+    /// 等待首字节
+    FirstByte,
+}
+
+/// 发送过程中正在进行的阶段，超时时随 [`DnsError::Timeout`] 报告
+///
+/// 名称与 [`TransportTiming::phases`] 中的阶段名相同，即以该阶段结束时的打点命名
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportPhase {
+    /// 解析上游主机名
+    UpstreamResolve,
+    /// 建立TCP连接
+    TcpConnect,
+    /// TLS握手
+    TlsHandshake,
+    /// 写出请求
+    RequestSent,
+    /// 等待响应的第一个字节（DoH为等待响应头，包含建立连接）
+    FirstByte,
+    /// 读取响应的其余部分
+    Complete,
+}
+
+impl TransportPhase {
+    /// 阶段名
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UpstreamResolve => "upstream_resolve",
+            Self::TcpConnect => "tcp_connect",
+            Self::TlsHandshake => "tls_handshake",
+            Self::RequestSent => "request_sent",
+            Self::FirstByte => "first_byte",
+            Self::Complete => "complete",
+        }
+    }
+}
+
+impl fmt::Display for TransportPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TransportTiming {
+    /// 只有总耗时的记录（无法分阶段观测的传输使用）
+    pub fn total(complete: Duration) -> Self {
+        Self { complete, ..Self::default() }
+    }
+
+    /// 各阶段自身的耗时（本阶段结束偏移减去上一个已记录阶段的结束偏移），按发生顺序，跳过未记录的阶段
+    ///
+    /// 最后一项 `"complete"` 为首字节（或最后一个已记录阶段）之后读取并解析响应的耗时
+    pub fn phases(&self) -> Vec<(&'static str, Duration)> {
+        let marks = [
+            ("upstream_resolve", self.upstream_resolve),
+            ("tcp_connect", self.tcp_connect),
+            ("tls_handshake", self.tls_handshake),
+            ("request_sent", self.request_sent),
+            ("first_byte", self.first_byte),
+            ("complete", Some(self.complete)),
+        ];
+        let mut previous = Duration::ZERO;
+        marks.into_iter()
+            .filter_map(|(name, at)| at.map(|at| (name, at)))
+            .map(|(name, at)| {
+                let phase = at.saturating_sub(previous);
+                previous = at;
+                (name, phase)
+            })
+            .collect()
+    }
+
+    /// 耗时最长的阶段
+    pub fn slowest_phase(&self) -> (&'static str, Duration) {
+        self.phases()
+            .into_iter()
+            .max_by_key(|(_, duration)| *duration)
+            .unwrap_or(("complete", self.complete))
+    }
+}
+
+/// 传输内部使用的打点器，未开启时不读取时钟
+#[derive(Debug)]
+pub(crate) struct TimingRecorder {
+    /// 开启时为开始时间和读取时间的时钟
+    start: Option<(Instant, Arc<dyn Clock>)>,
+    timing: TransportTiming,
+    phase: TransportPhase,
+}
+
+impl TimingRecorder {
+    /// 不记录耗时
+    pub(crate) fn disabled() -> Self {
+        Self { start: None, timing: TransportTiming::default(), phase: TransportPhase::RequestSent }
+    }
+
+    /// 从 `clock` 的现在开始记录
+    pub(crate) fn start(clock: &Arc<dyn Clock>) -> Self {
+        Self { start: Some((clock.now_instant(), clock.clone())), timing: TransportTiming::default(), phase: TransportPhase::RequestSent }
+    }
+
+    /// 进入一个阶段；不论是否开启耗时分解都记录
+    pub(crate) fn enter(&mut self, phase: TransportPhase) {
+        self.phase = phase;
+    }
+
+    /// 正在进行的阶段
+    #[cfg(feature = "dot")]
+    pub(crate) fn phase(&self) -> TransportPhase {
+        self.phase
+    }
+
+    /// 发送超时的错误，带上超时时正在进行的阶段
+    pub(crate) fn timed_out(&self) -> DnsError {
+        DnsError::Timeout { phase: Some(self.phase) }
+    }
+
+    /// 是否在记录
+    #[cfg(any(feature = "tcp", all(feature = "doh", not(target_arch = "wasm32"))))]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.start.is_some()
+    }
+
+    /// 记录阶段结束
+    pub(crate) fn mark(&mut self, phase: TimingPhase) {
+        let Some((start, clock)) = &self.start else {
+            return;
+        };
+        let at = Some(clock.now_instant().saturating_duration_since(*start));
+        match phase {
+            TimingPhase::UpstreamResolve => self.timing.upstream_resolve = at,
+            TimingPhase::TcpConnect => self.timing.tcp_connect = at,
+            TimingPhase::TlsHandshake => self.timing.tls_handshake = at,
+            TimingPhase::RequestSent => self.timing.request_sent = at,
+            TimingPhase::FirstByte => self.timing.first_byte = at,
+        }
+    }
+
+    /// 记录承载请求的HTTP版本
+    #[cfg(all(feature = "doh", not(target_arch = "wasm32")))]
+    pub(crate) fn set_http_version(&mut self, version: HttpVersion) {
+        if self.is_enabled() {
+            self.timing.http_version = Some(version);
+        }
+    }
+
+    /// 记录SYN中的数据是否被接受
+    #[cfg(feature = "tcp")]
+    pub(crate) fn set_tcp_fast_open(&mut self, accepted: Option<bool>) {
+        if self.is_enabled() {
+            self.timing.tcp_fast_open = accepted;
+        }
+    }
+
+    /// 记录实际交换报文的对端地址；不论是否开启耗时分解都记录
+    pub(crate) fn set_peer(&mut self, peer: SocketAddr) {
+        self.timing.peer = Some(peer);
+    }
+
+    /// 结束记录，未开启时各阶段为空、总耗时为零
+    pub(crate) fn finish(mut self) -> TransportTiming {
+        if let Some((start, clock)) = &self.start {
+            self.timing.complete = clock.now_instant().saturating_duration_since(*start);
+        }
+        self.timing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phases_are_differences_between_marks() {
+        let timing = TransportTiming {
+            upstream_resolve: None,
+            tcp_connect: Some(Duration::from_millis(10)),
+            tls_handshake: Some(Duration::from_millis(250)),
+            request_sent: Some(Duration::from_millis(251)),
+            first_byte: Some(Duration::from_millis(281)),
+            complete: Duration::from_millis(282),
+            http_version: None,
+            peer: None,
+            tcp_fast_open: None,
+        };
+        assert_eq!(timing.phases(), vec![
+            ("tcp_connect", Duration::from_millis(10)),
+            ("tls_handshake", Duration::from_millis(240)),
+            ("request_sent", Duration::from_millis(1)),
+            ("first_byte", Duration::from_millis(30)),
+            ("complete", Duration::from_millis(1)),
+        ]);
+        assert_eq!(timing.slowest_phase(), ("tls_handshake", Duration::from_millis(240)));
+    }
+
+    #[test]
+    fn test_disabled_recorder_records_nothing() {
+        let mut recorder = TimingRecorder::disabled();
+        recorder.mark(TimingPhase::TcpConnect);
+        assert_eq!(recorder.finish(), TransportTiming::default());
+    }
+
+    #[test]
+    fn test_phase_is_tracked_without_timing() {
+        let mut recorder = TimingRecorder::disabled();
+        recorder.enter(TransportPhase::TlsHandshake);
+        assert!(matches!(recorder.timed_out(), DnsError::Timeout { phase: Some(TransportPhase::TlsHandshake) }));
+        assert_eq!(recorder.timed_out().to_string(), "Request timeout during tls_handshake");
+    }
+}
@@ -1,52 +1,171 @@
 //! TLS传输实现 (DNS over TLS)
 
 use crate::{Request, Response, Result, DnsError};
+use crate::error::TlsErrorKind;
+use crate::resolver::clock::{self, Clock};
 use super::{Transport, TlsConfig};
 use super::udp::UdpTransport;
+use super::tcp::TcpTransport;
+use super::query_id::ensure_matching_id;
+use super::timing::{TimingPhase, TimingRecorder, TransportPhase, TransportTiming};
+use super::wire::{WireCapture, WireRecorder};
+use super::upstream_addr::{AddressFamilyStats, HostResolution, UpstreamAddress};
+use super::fast_open::{FastOpenCounters, FastOpenStats};
+use super::framing::{self, FrameBufferPool, LENGTH_PREFIX};
 use async_trait::async_trait;
-use std::time::Duration;
-use tokio::net::TcpStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::time::timeout;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
 use std::sync::{Arc, Mutex};
 use crate::{dns_debug, dns_info, dns_error, dns_transport};
-use tokio_rustls::{TlsConnector, rustls::{ClientConfig, ServerName}};
+use tokio_rustls::{TlsConnector, rustls::{ClientConfig, ClientConnection, ServerName}};
+use tokio_rustls::rustls::client::{Resumption, ServerCertVerifier, WebPkiVerifier};
+
+/// 会话缓存最多保存的服务器数，每个服务器保留最近的几张会话票据
+const SESSION_CACHE_SIZE: usize = 256;
+
+/// DoT握手方式的计数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsSessionStats {
+    /// 完整握手次数（服务器发送并校验了证书）
+    pub full_handshakes: u64,
+    /// 用之前连接的会话票据恢复的握手次数
+    pub resumed_handshakes: u64,
+    /// 恢复时0-RTT早期数据被服务器接受的次数（包含在 `resumed_handshakes` 中）
+    pub early_data_accepted: u64,
+    /// 早期数据被服务器拒绝、握手完成后重发的次数
+    pub early_data_rejected: u64,
+}
+
+/// 握手方式计数器
+#[derive(Debug, Default)]
+struct SessionCounters {
+    full_handshakes: AtomicU64,
+    resumed_handshakes: AtomicU64,
+    early_data_accepted: AtomicU64,
+    early_data_rejected: AtomicU64,
+}
+
+impl SessionCounters {
+    /// 按握手完成后的连接状态计数，`sent_early_data` 为本次连接是否发送过早期数据
+    fn record(&self, connection: &ClientConnection, certificate_verified: bool, sent_early_data: bool) {
+        if certificate_verified {
+            self.full_handshakes.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.resumed_handshakes.fetch_add(1, Ordering::Relaxed);
+        }
+        if sent_early_data {
+            if connection.is_early_data_accepted() {
+                self.early_data_accepted.fetch_add(1, Ordering::Relaxed);
+            } else {
+                self.early_data_rejected.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    
+    fn snapshot(&self) -> TlsSessionStats {
+        TlsSessionStats {
+            full_handshakes: self.full_handshakes.load(Ordering::Relaxed),
+            resumed_handshakes: self.resumed_handshakes.load(Ordering::Relaxed),
+            early_data_accepted: self.early_data_accepted.load(Ordering::Relaxed),
+            early_data_rejected: self.early_data_rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
 /// TLS传输实现
+/// 
+/// 同一传输的连接共用会话缓存：之后的连接用服务器下发的会话票据恢复会话，省去证书传输和校验；
+/// 开启早期数据时查询随ClientHello一起发出（0-RTT）
 pub struct TlsTransport {
-    #[allow(dead_code)]
-    connector: Arc<TlsConnector>,
+    /// 连接共用的客户端配置，包含会话缓存
+    client_config: Arc<ClientConfig>,
+    /// 证书校验器，每次连接包装一层以判断是否为完整握手
+    verifier: Arc<dyn ServerCertVerifier>,
     config: Arc<Mutex<TlsConfig>>,
+    /// 连接目标，以主机名配置时解析后缓存；SNI始终使用 `server_name`
+    address: UpstreamAddress,
+    sessions: Arc<SessionCounters>,
+    /// 开启 `tcp_fast_open` 时的TCP Fast Open计数，ClientHello随SYN发出
+    fast_open: Option<FastOpenCounters>,
+    /// 请求和响应共用的分帧缓冲区，查询之间复用
+    buffers: FrameBufferPool,
+    /// 超时和耗时统计使用的时间源
+    clock: Arc<dyn Clock>,
 }
 
 impl std::fmt::Debug for TlsTransport {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("TlsTransport")
             .field("config", &self.config)
+            .field("address", &self.address)
             .finish()
     }
 }
 
 impl TlsTransport {
-    /// 创建新的TLS传输
+    /// 创建新的TLS传输，以主机名配置的服务器由系统解析器解析
     pub fn new(config: TlsConfig) -> Result<Self> {
+        let verifier: Arc<dyn ServerCertVerifier> = if config.verify_cert {
+            Arc::new(WebPkiVerifier::new(Self::load_root_certs()?, None))
+        } else {
+            Arc::new(NoVerifier)
+        };
         let mut client_config = ClientConfig::builder()
             .with_safe_defaults()
-            .with_root_certificates(Self::load_root_certs()?)
+            .with_custom_certificate_verifier(verifier.clone())
             .with_no_client_auth();
+        client_config.resumption = Resumption::in_memory_sessions(SESSION_CACHE_SIZE);
+        client_config.enable_early_data = config.enable_tls_early_data;
         
-        if !config.verify_cert {
-            client_config.dangerous()
-                .set_certificate_verifier(Arc::new(NoVerifier));
-        }
-        
-        let connector = TlsConnector::from(Arc::new(client_config));
+        let address = UpstreamAddress::new(config.base.server.clone(), config.base.port, HostResolution::default());
+        let fast_open = config.base.tcp_fast_open.then(FastOpenCounters::default);
+        let buffers = FrameBufferPool::new(config.base.buffer_size, config.base.pool_size);
         
         Ok(Self {
+            client_config: Arc::new(client_config),
+            verifier,
             config: Arc::new(Mutex::new(config)),
-            connector: Arc::new(connector),
+            address,
+            sessions: Arc::new(SessionCounters::default()),
+            fast_open,
+            buffers,
+            clock: clock::real_clock(),
         })
     }
     
+    /// 握手方式的计数
+    pub fn session_stats(&self) -> TlsSessionStats {
+        self.sessions.snapshot()
+    }
+    
+    /// 为一次连接创建连接器：共用会话缓存，证书校验器记录本次握手是否校验过证书
+    fn connector(&self) -> (TlsConnector, Arc<ObservedVerifier>) {
+        let verifier = Arc::new(ObservedVerifier::new(self.verifier.clone()));
+        let mut client_config = ClientConfig::clone(&self.client_config);
+        client_config.dangerous().set_certificate_verifier(verifier.clone());
+        let early_data = client_config.enable_early_data;
+        (TlsConnector::from(Arc::new(client_config)).early_data(early_data), verifier)
+    }
+    
+    /// 设置以主机名配置的服务器的解析方式
+    pub fn with_host_resolution(mut self, resolution: HostResolution) -> Self {
+        let (server, port) = {
+            let config = self.config.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            (config.base.server.clone(), config.base.port)
+        };
+        self.address = UpstreamAddress::new(server, port, resolution);
+        self
+    }
+    
+    /// 使用指定的时间源计算超时和各阶段耗时，默认为真实时钟
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+    
     // 注意：移除了 default() 方法，因为它依赖兜底配置
     // 用户现在必须明确提供 TlsConfig，不能依赖隐式默认值
     // 
@@ -60,13 +179,16 @@ impl TlsTransport {
     //         tcp_fast_open: false,
     //         tcp_nodelay: true,
     //         pool_size: 10,
+    //         buffer_size: 4096,
+    //         record_limits: None,
     //     },
     //     server_name: "your-dns-server.com".to_string(),
     //     verify_cert: true,
+    //     enable_tls_early_data: false,
     // })
     
     /// 加载根证书
-    fn load_root_certs() -> Result<tokio_rustls::rustls::RootCertStore> {
+    pub(crate) fn load_root_certs() -> Result<tokio_rustls::rustls::RootCertStore> {
         let mut root_store = tokio_rustls::rustls::RootCertStore::empty();
         
         // 加载系统根证书
@@ -99,134 +221,175 @@ impl TlsTransport {
         Ok(root_store)
     }
     
-    /// 从TCP流读取完整的DNS响应
-    async fn read_tls_response(
-        stream: &mut tokio_rustls::client::TlsStream<TcpStream>
-    ) -> Result<Vec<u8>> {
-        // 读取2字节长度前缀
-        let mut length_buf = [0u8; 2];
-        stream.read_exact(&mut length_buf).await
-            .map_err(|e| DnsError::Network(format!("Failed to read length: {}", e)))?;
-        
-        let length = u16::from_be_bytes(length_buf) as usize;
-        
-        if length == 0 {
-            return Err(DnsError::Protocol("Invalid response length".to_string()));
-        }
-        
-        if length > 65535 {
-            return Err(DnsError::Protocol("Response too large".to_string()));
+    /// 握手失败转换为 [`DnsError::TlsFailure`]，按底层rustls错误分类
+    fn handshake_error(err: std::io::Error, upstream: &str) -> DnsError {
+        let kind = err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<tokio_rustls::rustls::Error>())
+            .map(TlsErrorKind::from_rustls)
+            .unwrap_or(TlsErrorKind::Other);
+        dns_debug!("DoT握手失败 {}: {} ({:?})", upstream, err, kind);
+        DnsError::TlsFailure { kind, upstream: upstream.to_string() }
+    }
+    
+    /// 发送请求时的错误：早期数据的握手在发送时才完成，握手失败仍按握手错误分类
+    fn stream_error(err: std::io::Error, upstream: &str, context: &str) -> DnsError {
+        let handshake_failed = err.get_ref()
+            .is_some_and(|inner| inner.is::<tokio_rustls::rustls::Error>());
+        if handshake_failed {
+            Self::handshake_error(err, upstream)
+        } else {
+            DnsError::network_io(upstream.to_string(), context, &err)
         }
-        
-        // 读取实际的DNS响应数据
-        let mut response_buf = vec![0u8; length];
-        stream.read_exact(&mut response_buf).await
-            .map_err(|e| DnsError::Network(format!("Failed to read response: {}", e)))?;
-        
-        Ok(response_buf)
     }
     
-    /// 序列化DNS请求为TLS格式(带长度前缀)
-    fn serialize_request_tls(request: &Request) -> Result<Vec<u8>> {
-        // TLS使用与TCP相同的格式
-        let udp_data = UdpTransport::serialize_request(request)?;
-        
-        let mut tls_data = Vec::with_capacity(udp_data.len() + 2);
-        tls_data.extend_from_slice(&(udp_data.len() as u16).to_be_bytes());
-        tls_data.extend_from_slice(&udp_data);
-        
-        Ok(tls_data)
+    /// 编码请求报文（不含长度前缀），与TCP相同
+    pub fn encode_request(request: &Request) -> Result<Vec<u8>> {
+        TcpTransport::encode_request(request)
     }
-}
-
-#[async_trait]
-impl Transport for TlsTransport {
-    async fn send(&self, request: &Request) -> Result<Response> {
+    
+    /// 发送请求并接收响应，`timing` 开启时在各阶段结束时打点；网络失败计入地址的连续失败次数
+    /// 
+    /// `timeout` 限制整个过程，超时的错误带有当时所处的阶段；握手阶段超时仍记为 [`TlsErrorKind::HandshakeTimeout`]
+    async fn exchange(&self, request: &Request, timing: &mut TimingRecorder, wire: &mut WireRecorder) -> Result<Response> {
+        let deadline = self.clock.now_instant() + self.config.lock().unwrap().base.timeout;
+        let result = match clock::timeout_at(&*self.clock, deadline, self.try_exchange(request, deadline, timing, wire)).await {
+            Some(result) => result,
+            None if timing.phase() == TransportPhase::TlsHandshake => Err(DnsError::TlsFailure {
+                kind: TlsErrorKind::HandshakeTimeout,
+                upstream: self.endpoint(),
+            }),
+            None => Err(timing.timed_out()),
+        };
+        self.address.observe(&result);
+        result
+    }
+    
+    async fn try_exchange(&self, request: &Request, deadline: Instant, timing: &mut TimingRecorder, wire: &mut WireRecorder) -> Result<Response> {
         use crate::{dns_debug, dns_info};
-        let (server_addr, timeout_duration, server_name) = {
+        let (server, port, server_name, max_size, record_limits) = {
             let config = self.config.lock().unwrap();
             dns_info!("🔒 DoT请求开始: {} -> {}:{}", request.query.name, config.base.server, config.base.port);
             (
-                format!("{}:{}", config.base.server, config.base.port),
-                config.base.timeout,
-                config.server_name.clone()
+                config.base.server.clone(),
+                config.base.port,
+                config.server_name.clone(),
+                config.base.buffer_size,
+                config.base.record_limits,
             )
         };
+        let server_addr = format!("{}:{}", server, port);
         
         // 建立TCP连接
-        let connect_result = timeout(
-            timeout_duration,
-            TcpStream::connect(&server_addr)
-        ).await;
-        
-        let tcp_stream = match connect_result {
-            Ok(Ok(stream)) => stream,
-            Ok(Err(e)) => return Err(DnsError::Network(format!("Connection failed: {}", e))),
-            Err(_) => return Err(DnsError::Timeout),
-        };
+        let tcp_stream = TcpTransport::open_stream(&self.address, &*self.clock, deadline, self.fast_open.as_ref(), timing).await?;
         
         // 建立TLS连接
         let server_name = ServerName::try_from(server_name.as_str())
             .map_err(|e| DnsError::Tls(format!("Invalid server name: {}", e)))?;
         
-        let tls_result = timeout(
-            timeout_duration,
-            self.connector.connect(server_name, tcp_stream)
-        ).await;
-        
-        let mut tls_stream = match tls_result {
-            Ok(Ok(stream)) => stream,
-            Ok(Err(e)) => return Err(DnsError::Tls(format!("TLS handshake failed: {}", e))),
-            Err(_) => return Err(DnsError::Timeout),
-        };
-        
-        // 序列化请求
-        let request_data = Self::serialize_request_tls(request)?;
-        
-        // 发送请求
-        let send_result = timeout(
-            timeout_duration,
-            tls_stream.write_all(&request_data)
-        ).await;
+        // 开启早期数据且有可用的会话票据时，连接在握手完成前就返回，请求作为早期数据写入
+        timing.enter(TransportPhase::TlsHandshake);
+        let (connector, verifier) = self.connector();
+        let mut tls_stream = connector.connect(server_name, tcp_stream).await
+            .map_err(|e| Self::handshake_error(e, &server_addr))?;
+        let sent_early_data = tls_stream.get_ref().1.is_handshaking();
+        timing.mark(TimingPhase::TlsHandshake);
         
-        match send_result {
-            Ok(Ok(_)) => {},
-            Ok(Err(e)) => return Err(DnsError::Network(format!("Send failed: {}", e))),
-            Err(_) => return Err(DnsError::Timeout),
-        }
+        // 序列化请求，响应随后读入同一个缓冲区
+        let mut buffer = self.buffers.get();
+        TcpTransport::encode_request_framed(request, &mut buffer)?;
+        wire.record_request(&buffer[LENGTH_PREFIX..]);
         
-        // 确保数据发送完毕
-        let flush_result = timeout(
-            timeout_duration,
-            tls_stream.flush()
-        ).await;
+        // 整帧一次写入，开启早期数据时整条请求随ClientHello发出
+        timing.enter(TransportPhase::RequestSent);
+        tls_stream.write_all(&buffer).await
+            .map_err(|e| Self::stream_error(e, &server_addr, "Send failed"))?;
         
-        match flush_result {
-            Ok(Ok(_)) => {},
-            Ok(Err(e)) => return Err(DnsError::Network(format!("Flush failed: {}", e))),
-            Err(_) => return Err(DnsError::Timeout),
-        }
+        // 确保数据发送完毕；发送过早期数据时在这里完成握手，早期数据被拒绝则重发请求
+        tls_stream.flush().await
+            .map_err(|e| Self::stream_error(e, &server_addr, "Flush failed"))?;
+        self.sessions.record(tls_stream.get_ref().1, verifier.verified(), sent_early_data);
+        timing.mark(TimingPhase::RequestSent);
         
         // 读取响应
-        let response_data = timeout(
-            timeout_duration,
-            Self::read_tls_response(&mut tls_stream)
-        ).await;
-        
-        let response_bytes = match response_data {
-            Ok(Ok(data)) => data,
-            Ok(Err(e)) => return Err(e),
-            Err(_) => return Err(DnsError::Timeout),
-        };
+        framing::read_message(&mut tls_stream, max_size, &mut buffer, timing).await?;
+        wire.record_response(&buffer);
+        if let Some(counters) = &self.fast_open {
+            timing.set_tcp_fast_open(counters.observe(tls_stream.get_ref().0));
+        }
         
         // 复用UDP的反序列化逻辑
-        UdpTransport::deserialize_response(&response_bytes)
+        UdpTransport::deserialize_response_with_limits(&buffer, record_limits.as_ref())
+            .and_then(|response| ensure_matching_id(request, response))
+    }
+}
+
+#[async_trait]
+impl Transport for TlsTransport {
+    async fn send(&self, request: &Request) -> Result<Response> {
+        self.exchange(request, &mut TimingRecorder::disabled(), &mut WireRecorder::disabled()).await
+    }
+    
+    async fn prepare(&self) -> Result<()> {
+        self.address.socket_addrs(self.timeout()).await.map(|_| ())
+    }
+    
+    async fn send_with_peer(&self, request: &Request) -> Result<(Response, Option<SocketAddr>)> {
+        let mut timing = TimingRecorder::disabled();
+        let response = self.exchange(request, &mut timing, &mut WireRecorder::disabled()).await?;
+        Ok((response, timing.finish().peer))
+    }
+    
+    async fn send_timed(&self, request: &Request) -> Result<(Response, TransportTiming)> {
+        let mut timing = TimingRecorder::start(&self.clock);
+        let response = self.exchange(request, &mut timing, &mut WireRecorder::disabled()).await?;
+        Ok((response, timing.finish()))
+    }
+    
+    async fn send_captured(&self, request: &Request, max_bytes: usize) -> Result<(Response, TransportTiming, WireCapture)> {
+        let mut timing = TimingRecorder::start(&self.clock);
+        let mut wire = WireRecorder::with_limit(max_bytes);
+        let response = self.exchange(request, &mut timing, &mut wire).await?;
+        Ok((response, timing.finish(), wire.finish()))
+    }
+    
+    fn encrypted_host(&self) -> Option<String> {
+        Some(self.address.host().to_string())
+    }
+    
+    fn tls_session_stats(&self) -> Option<TlsSessionStats> {
+        Some(self.session_stats())
+    }
+    
+    fn fast_open_stats(&self) -> Option<FastOpenStats> {
+        self.fast_open.as_ref().map(FastOpenCounters::snapshot)
+    }
+    
+    fn address_family_stats(&self) -> Option<AddressFamilyStats> {
+        Some(self.address.family_stats())
+    }
+    
+    fn insecure_variant(&self) -> Result<Option<Arc<dyn Transport>>> {
+        let config = TlsConfig {
+            verify_cert: false,
+            ..self.config.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+        };
+        let transport = TlsTransport::new(config)?
+            .with_host_resolution(self.address.resolution().clone())
+            .with_clock(self.clock.clone());
+        Ok(Some(Arc::new(transport)))
     }
     
     fn transport_type(&self) -> &'static str {
         "TLS"
     }
     
+    fn endpoint(&self) -> String {
+        match self.config.lock() {
+            Ok(config) => format!("{}:{}", config.base.server, config.base.port),
+            Err(_) => self.transport_type().to_string(),
+        }
+    }
+    
     fn set_timeout(&mut self, timeout: Duration) {
         if let Ok(mut config) = self.config.lock() {
             config.base.timeout = timeout;
@@ -240,6 +403,63 @@ impl Transport for TlsTransport {
 
 
 
+/// 记录本次连接是否校验过服务器证书：恢复会话的握手不发送证书，据此区分完整握手与恢复
+struct ObservedVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    verified: AtomicBool,
+}
+
+impl ObservedVerifier {
+    fn new(inner: Arc<dyn ServerCertVerifier>) -> Self {
+        Self { inner, verified: AtomicBool::new(false) }
+    }
+    
+    fn verified(&self) -> bool {
+        self.verified.load(Ordering::Relaxed)
+    }
+}
+
+impl ServerCertVerifier for ObservedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &tokio_rustls::rustls::Certificate,
+        intermediates: &[tokio_rustls::rustls::Certificate],
+        server_name: &tokio_rustls::rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> std::result::Result<tokio_rustls::rustls::client::ServerCertVerified, tokio_rustls::rustls::Error> {
+        self.verified.store(true, Ordering::Relaxed);
+        self.inner.verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)
+    }
+    
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &tokio_rustls::rustls::Certificate,
+        dss: &tokio_rustls::rustls::DigitallySignedStruct,
+    ) -> std::result::Result<tokio_rustls::rustls::client::HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+    
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &tokio_rustls::rustls::Certificate,
+        dss: &tokio_rustls::rustls::DigitallySignedStruct,
+    ) -> std::result::Result<tokio_rustls::rustls::client::HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+    
+    fn supported_verify_schemes(&self) -> Vec<tokio_rustls::rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+    
+    fn request_scts(&self) -> bool {
+        self.inner.request_scts()
+    }
+}
+
 /// 不验证证书的验证器(仅用于测试)
 struct NoVerifier;
 
@@ -255,4 +475,510 @@ impl tokio_rustls::rustls::client::ServerCertVerifier for NoVerifier {
     ) -> std::result::Result<tokio_rustls::rustls::client::ServerCertVerified, tokio_rustls::rustls::Error> {
         Ok(tokio_rustls::rustls::client::ServerCertVerified::assertion())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::priority::QueryPriority;
+    use crate::transport::TransportConfig;
+    use crate::types::{Flags, Query, QClass, RecordType};
+    use std::time::Instant;
+    use tokio::net::TcpListener;
+
+    fn transport(port: u16) -> TlsTransport {
+        TlsTransport::new(TlsConfig {
+            base: TransportConfig {
+                server: "127.0.0.1".to_string(),
+                port,
+                timeout: Duration::from_millis(300),
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: 1,
+                buffer_size: 4096,
+                record_limits: None,
+            },
+            server_name: "localhost".to_string(),
+            verify_cert: true,
+            enable_tls_early_data: false,
+        }).unwrap()
+    }
+
+    fn request() -> Request {
+        Request {
+            id: 0x4321,
+            flags: Flags::default(),
+            query: Query {
+                name: "example.com".to_string(),
+                qtype: RecordType::A,
+                qclass: QClass::IN,
+            },
+            client_address: None,
+            enable_edns: false,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: QueryPriority::Normal,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handshake_failures_are_classified() {
+        // 接受连接但从不回应：握手超时
+        let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent_port = silent.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = silent.accept().await {
+                held.push(stream);
+            }
+        });
+
+        let err = transport(silent_port).send(&request()).await.unwrap_err();
+        assert!(matches!(
+            err,
+            DnsError::TlsFailure { kind: TlsErrorKind::HandshakeTimeout, ref upstream } if upstream.ends_with(&silent_port.to_string())
+        ), "unexpected error: {:?}", err);
+
+        // 回应明文HTTP：握手失败，不能被当作普通网络错误
+        let plaintext = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let plaintext_port = plaintext.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = plaintext.accept().await {
+                let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await;
+            }
+        });
+
+        let err = transport(plaintext_port).send(&request()).await.unwrap_err();
+        assert!(matches!(err, DnsError::TlsFailure { .. }), "unexpected error: {:?}", err);
+    }
+    
+    #[tokio::test]
+    async fn test_stalls_end_within_timeout() {
+        use tokio::io::AsyncReadExt;
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+        
+        // 接受连接但从不握手：超时仍记为握手超时
+        let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent_port = silent.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = silent.accept().await {
+                held.push(stream);
+            }
+        });
+        let start = Instant::now();
+        let err = transport(silent_port).send(&request()).await.unwrap_err();
+        let elapsed = start.elapsed();
+        assert!(matches!(err, DnsError::TlsFailure { kind: TlsErrorKind::HandshakeTimeout, .. }), "{:?}", err);
+        assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_millis(550), "{:?}", elapsed);
+        
+        // 完成握手、读走请求后从不应答：停在等待首字节
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(include_bytes!("testdata/localhost.crt.der").to_vec())],
+                PrivateKey(include_bytes!("testdata/localhost.key.der").to_vec()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let mut buffer = [0u8; 512];
+            let _ = stream.read(&mut buffer).await;
+            std::future::pending::<()>().await;
+        });
+        let transport = TlsTransport::new(TlsConfig {
+            base: TransportConfig {
+                server: "127.0.0.1".to_string(),
+                port,
+                timeout: Duration::from_millis(300),
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: 1,
+                buffer_size: 4096,
+                record_limits: None,
+            },
+            server_name: "localhost".to_string(),
+            verify_cert: false,
+            enable_tls_early_data: false,
+        }).unwrap();
+        let start = Instant::now();
+        let err = transport.send(&request()).await.unwrap_err();
+        let elapsed = start.elapsed();
+        assert!(matches!(err, DnsError::Timeout { phase: Some(TransportPhase::FirstByte) }), "{:?}", err);
+        assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_millis(550), "{:?}", elapsed);
+    }
+    
+    #[tokio::test]
+    async fn test_slow_handshake_dominates_timing() {
+        use crate::dns_response::DnsResponseWrapper;
+        use tokio::io::AsyncReadExt;
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+        
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(include_bytes!("testdata/localhost.crt.der").to_vec())],
+                PrivateKey(include_bytes!("testdata/localhost.key.der").to_vec()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+        
+        // 接受连接后拖延300毫秒才开始握手，之后立即应答
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).await.unwrap();
+            let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+            stream.read_exact(&mut message).await.unwrap();
+            let request = UdpTransport::deserialize_request(&message).unwrap();
+            let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[std::net::Ipv4Addr::new(192, 0, 2, 1)], 300);
+            let bytes = UdpTransport::serialize_response(&response).unwrap();
+            stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+            stream.write_all(&bytes).await.unwrap();
+            stream.flush().await.unwrap();
+        });
+        
+        // 自签名证书，跳过证书校验
+        let transport = TlsTransport::new(TlsConfig {
+            base: TransportConfig {
+                server: "127.0.0.1".to_string(),
+                port,
+                timeout: Duration::from_secs(2),
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: 1,
+                buffer_size: 4096,
+                record_limits: None,
+            },
+            server_name: "localhost".to_string(),
+            verify_cert: false,
+            enable_tls_early_data: false,
+        }).unwrap();
+        
+        let (response, timing) = transport.send_timed(&request()).await.unwrap();
+        assert_eq!(response.id, 0x4321);
+        assert!(timing.upstream_resolve.is_none());
+        assert!(timing.tcp_connect.is_some() && timing.request_sent.is_some() && timing.first_byte.is_some());
+        
+        let (phase, duration) = timing.slowest_phase();
+        assert_eq!(phase, "tls_handshake", "{:?}", timing.phases());
+        assert!(duration >= Duration::from_millis(300));
+    }
+    
+    #[tokio::test]
+    async fn test_peer_is_the_connected_socket() {
+        use crate::dns_response::DnsResponseWrapper;
+        use tokio::io::AsyncReadExt;
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+        
+        let server_config = Arc::new(ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(include_bytes!("testdata/localhost.crt.der").to_vec())],
+                PrivateKey(include_bytes!("testdata/localhost.key.der").to_vec()),
+            )
+            .unwrap());
+        
+        for _ in 0..2 {
+            let acceptor = tokio_rustls::TlsAcceptor::from(server_config.clone());
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = acceptor.accept(stream).await.unwrap();
+                let mut length = [0u8; 2];
+                stream.read_exact(&mut length).await.unwrap();
+                let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut message).await.unwrap();
+                let request = UdpTransport::deserialize_request(&message).unwrap();
+                let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[std::net::Ipv4Addr::new(192, 0, 2, 1)], 300);
+                let bytes = UdpTransport::serialize_response(&response).unwrap();
+                stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+                stream.write_all(&bytes).await.unwrap();
+                stream.flush().await.unwrap();
+            });
+            
+            let transport = TlsTransport::new(TlsConfig {
+                base: TransportConfig {
+                    server: "127.0.0.1".to_string(),
+                    port: address.port(),
+                    timeout: Duration::from_secs(2),
+                    tcp_fast_open: false,
+                    tcp_nodelay: true,
+                    pool_size: 1,
+                    buffer_size: 4096,
+                    record_limits: None,
+                },
+                server_name: "localhost".to_string(),
+                verify_cert: false,
+                enable_tls_early_data: false,
+            }).unwrap();
+            
+            let (_, peer) = transport.send_with_peer(&request()).await.unwrap();
+            assert_eq!(peer, Some(address));
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_hostname_dials_resolved_ip_with_hostname_sni() {
+        use crate::dns_response::DnsResponseWrapper;
+        use crate::transport::mock::MockBootstrap;
+        use tokio::io::AsyncReadExt;
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+        
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(include_bytes!("testdata/localhost.crt.der").to_vec())],
+                PrivateKey(include_bytes!("testdata/localhost.key.der").to_vec()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+        
+        // 记下客户端在握手中给出的SNI
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let sni = stream.get_ref().1.server_name().map(str::to_string);
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).await.unwrap();
+            let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+            stream.read_exact(&mut message).await.unwrap();
+            let request = UdpTransport::deserialize_request(&message).unwrap();
+            let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[std::net::Ipv4Addr::new(192, 0, 2, 1)], 300);
+            let bytes = UdpTransport::serialize_response(&response).unwrap();
+            stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+            stream.write_all(&bytes).await.unwrap();
+            stream.flush().await.unwrap();
+            sni
+        });
+        
+        let bootstrap = MockBootstrap::new().with_host("dot.corp.internal", &[std::net::IpAddr::from([127, 0, 0, 1])]);
+        let transport = TlsTransport::new(TlsConfig {
+            base: TransportConfig {
+                server: "dot.corp.internal".to_string(),
+                port,
+                timeout: Duration::from_secs(2),
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: 1,
+                buffer_size: 4096,
+                record_limits: None,
+            },
+            server_name: "dot.corp.internal".to_string(),
+            verify_cert: false,
+            enable_tls_early_data: false,
+        }).unwrap().with_host_resolution(HostResolution {
+            resolver: Some(Arc::new(bootstrap.clone())),
+            ..HostResolution::default()
+        });
+        
+        let (response, timing) = transport.send_timed(&request()).await.unwrap();
+        assert_eq!(response.id, 0x4321);
+        assert!(timing.upstream_resolve.is_some());
+        assert_eq!(timing.peer, Some(SocketAddr::from(([127, 0, 0, 1], port))));
+        assert_eq!(server.await.unwrap().as_deref(), Some("dot.corp.internal"));
+        assert_eq!(bootstrap.call_count(), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_captured_wire_excludes_length_prefix() {
+        use crate::dns_response::DnsResponseWrapper;
+        use tokio::io::AsyncReadExt;
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+        
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(include_bytes!("testdata/localhost.crt.der").to_vec())],
+                PrivateKey(include_bytes!("testdata/localhost.key.der").to_vec()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+        
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).await.unwrap();
+            let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+            stream.read_exact(&mut message).await.unwrap();
+            let request = UdpTransport::deserialize_request(&message).unwrap();
+            let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[std::net::Ipv4Addr::new(192, 0, 2, 1)], 300);
+            let bytes = UdpTransport::serialize_response(&response).unwrap();
+            stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+            stream.write_all(&bytes).await.unwrap();
+            stream.flush().await.unwrap();
+            (message, bytes)
+        });
+        
+        let transport = TlsTransport::new(TlsConfig {
+            base: TransportConfig {
+                server: "127.0.0.1".to_string(),
+                port,
+                timeout: Duration::from_secs(2),
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: 1,
+                buffer_size: 4096,
+                record_limits: None,
+            },
+            server_name: "localhost".to_string(),
+            verify_cert: false,
+            enable_tls_early_data: false,
+        }).unwrap();
+        
+        let (_, _, wire) = transport.send_captured(&request(), 4096).await.unwrap();
+        let (received, sent) = server.await.unwrap();
+        assert_eq!(wire.request, received);
+        assert_eq!(wire.response, sent);
+        assert!(!wire.truncated);
+    }
+    
+    /// 使用测试证书、按会话缓存下发会话票据的服务器配置，`max_early_data_size` 为0时不接受早期数据
+    fn ticket_server_config(max_early_data_size: u32) -> tokio_rustls::rustls::ServerConfig {
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+        
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(include_bytes!("testdata/localhost.crt.der").to_vec())],
+                PrivateKey(include_bytes!("testdata/localhost.key.der").to_vec()),
+            )
+            .unwrap();
+        config.max_early_data_size = max_early_data_size;
+        config
+    }
+    
+    /// 依次用 `configs` 接受连接并应答一个查询，返回端口和每个连接的请求是否来自早期数据
+    async fn ticket_server(configs: Vec<Arc<tokio_rustls::rustls::ServerConfig>>) -> (u16, tokio::task::JoinHandle<Vec<bool>>) {
+        use crate::dns_response::DnsResponseWrapper;
+        use std::io::Read;
+        use tokio::io::AsyncReadExt;
+        
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let mut early = Vec::new();
+            for config in configs {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = tokio_rustls::TlsAcceptor::from(config).accept(stream).await.unwrap();
+                let mut framed = Vec::new();
+                if let Some(mut early_data) = stream.get_mut().1.early_data() {
+                    early_data.read_to_end(&mut framed).unwrap();
+                }
+                early.push(!framed.is_empty());
+                if framed.is_empty() {
+                    let mut length = [0u8; 2];
+                    stream.read_exact(&mut length).await.unwrap();
+                    framed.extend_from_slice(&length);
+                    framed.resize(2 + u16::from_be_bytes(length) as usize, 0);
+                    stream.read_exact(&mut framed[2..]).await.unwrap();
+                }
+                let request = UdpTransport::deserialize_request(&framed[2..]).unwrap();
+                let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[std::net::Ipv4Addr::new(192, 0, 2, 1)], 300);
+                let bytes = UdpTransport::serialize_response(&response).unwrap();
+                stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+                stream.write_all(&bytes).await.unwrap();
+                stream.flush().await.unwrap();
+            }
+            early
+        });
+        (port, server)
+    }
+    
+    fn session_transport(port: u16, enable_tls_early_data: bool) -> TlsTransport {
+        TlsTransport::new(TlsConfig {
+            base: TransportConfig {
+                server: "127.0.0.1".to_string(),
+                port,
+                timeout: Duration::from_secs(2),
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: 1,
+                buffer_size: 4096,
+                record_limits: None,
+            },
+            server_name: "localhost".to_string(),
+            verify_cert: false,
+            enable_tls_early_data,
+        }).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn test_later_connections_resume_the_session() {
+        let config = Arc::new(ticket_server_config(0));
+        let (port, server) = ticket_server(vec![config.clone(), config.clone(), config]).await;
+        let transport = session_transport(port, false);
+        
+        for _ in 0..3 {
+            assert_eq!(transport.send(&request()).await.unwrap().id, 0x4321);
+        }
+        assert_eq!(server.await.unwrap(), vec![false, false, false]);
+        assert_eq!(transport.tls_session_stats(), Some(TlsSessionStats {
+            full_handshakes: 1,
+            resumed_handshakes: 2,
+            early_data_accepted: 0,
+            early_data_rejected: 0,
+        }));
+    }
+    
+    #[tokio::test]
+    async fn test_early_data_is_accepted_on_resumption() {
+        let config = Arc::new(ticket_server_config(1024));
+        let (port, server) = ticket_server(vec![config.clone(), config]).await;
+        let transport = session_transport(port, true);
+        
+        // 第一次连接还没有会话票据，只能完整握手
+        transport.send(&request()).await.unwrap();
+        let (response, timing) = transport.send_timed(&request()).await.unwrap();
+        assert_eq!(response.id, 0x4321);
+        assert!(timing.tls_handshake.is_some());
+        
+        assert_eq!(server.await.unwrap(), vec![false, true]);
+        assert_eq!(transport.session_stats(), TlsSessionStats {
+            full_handshakes: 1,
+            resumed_handshakes: 1,
+            early_data_accepted: 1,
+            early_data_rejected: 0,
+        });
+    }
+    
+    #[tokio::test]
+    async fn test_rejected_early_data_is_resent_after_handshake() {
+        // 第二个连接的服务器共用会话缓存但不再接受早期数据：会话照常恢复，请求在握手完成后重发
+        let accepting = ticket_server_config(1024);
+        let mut rejecting = accepting.clone();
+        rejecting.max_early_data_size = 0;
+        let (port, server) = ticket_server(vec![Arc::new(accepting), Arc::new(rejecting)]).await;
+        let transport = session_transport(port, true);
+        
+        transport.send(&request()).await.unwrap();
+        assert_eq!(transport.send(&request()).await.unwrap().id, 0x4321);
+        
+        assert_eq!(server.await.unwrap(), vec![false, false]);
+        assert_eq!(transport.session_stats(), TlsSessionStats {
+            full_handshakes: 1,
+            resumed_handshakes: 1,
+            early_data_accepted: 0,
+            early_data_rejected: 1,
+        });
+    }
+}
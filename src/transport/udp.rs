@@ -1,24 +1,126 @@
 //! UDP传输实现
 
 use crate::{Request, Response, Result, DnsError};
-use crate::types::{EdnsRecord, EdnsOption, edns_option_codes};
-use super::{Transport, TransportConfig};
+use crate::types::{EdnsRecord, EdnsOption, Opcode, edns_option_codes};
+use crate::resolver::clock::{self, Clock};
+use crate::resolver::priority::QueryPriority;
+use super::{RecordLimits, Transport, TransportConfig, OPT_RECORD_TYPE, UDP_EDNS_PAYLOAD_SIZE};
+use super::timing::{TimingPhase, TimingRecorder, TransportPhase, TransportTiming};
+use super::wire::{WireCapture, WireRecorder};
+use super::cookie::{DnsCookieJar, BADCOOKIE};
+use super::upstream_addr::{AddressFamily, AddressFamilyStats, HostResolution, UpstreamAddress};
+use super::udp_pool::{UdpPoolConfig, UdpPoolStats, UdpSocket, UdpSocketPool};
 use async_trait::async_trait;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::UdpSocket;
-use tokio::time::timeout;
-use crate::{dns_debug, dns_info, dns_error, dns_transport};
+use crate::time::Instant;
+use crate::{dns_debug, dns_info, dns_error, dns_transport, dns_warn};
+
+/// 域名的最大线路长度（RFC 1035 第3.1节，含各标签的长度字节和结尾的0）
+pub const MAX_NAME_WIRE_LEN: usize = 255;
+
+/// 解析一个域名时最多跟随的压缩指针数；合法的域名最多127个标签，每个标签至多对应一次跳转
+const MAX_POINTER_JUMPS: usize = 127;
+
+/// 解析一条报文中的全部域名时，每字节报文允许的工作量（读取一个标签或跟随一个压缩指针计一次）
+/// 
+/// 单个域名的工作量受标签数和跳转次数限制，但大量记录的名称都指向同一条很长的指针链时，总工作量随报文长度的平方增长；
+/// 按报文长度给出总预算后解析时间与报文长度成线性关系。真实报文（包括64KB的区域传送）每字节的工作量远小于该值
+const NAME_WORK_PER_BYTE: usize = 16;
+
+/// 查询的最短线路长度：根域名（1字节）+ 类型和类（4字节）
+const MIN_QUERY_LEN: usize = 5;
+
+/// 资源记录的最短线路长度：根域名（1字节）+ 类型、类、TTL和数据长度（10字节）
+const MIN_RECORD_LEN: usize = 11;
+
+/// 调试日志中报文的十六进制预览，只在日志实际输出时格式化
+struct HexPreview<'a>(&'a [u8]);
+
+impl std::fmt::Display for HexPreview<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// 一条报文的域名解析工作量预算，见 [`NAME_WORK_PER_BYTE`]
+struct NameBudget {
+    remaining: usize,
+}
+
+impl NameBudget {
+    fn for_message(data: &[u8]) -> Self {
+        Self { remaining: data.len().saturating_mul(NAME_WORK_PER_BYTE) }
+    }
+    
+    /// 记一次工作量，预算用完时返回错误
+    fn spend(&mut self) -> Result<()> {
+        self.remaining = self.remaining.checked_sub(1)
+            .ok_or_else(|| DnsError::Protocol("报文中域名解析的工作量超出预算".to_string()))?;
+        Ok(())
+    }
+}
 
 /// UDP传输实现
 #[derive(Debug)]
 pub struct UdpTransport {
     config: TransportConfig,
+    /// DNS Cookie状态（开启Cookie时）
+    cookies: Option<Arc<DnsCookieJar>>,
+    /// 发送目标，以主机名配置时解析后缓存
+    address: UpstreamAddress,
+    /// 源端口池，查询时从中借出套接字
+    pool: UdpSocketPool,
+    /// 超时和耗时统计使用的时间源
+    clock: Arc<dyn Clock>,
 }
 
 impl UdpTransport {
-    /// 创建新的UDP传输
+    /// 创建新的UDP传输，以主机名配置的服务器由系统解析器解析
+    /// 
+    /// 预先绑定 `pool_size` 个套接字供查询轮流使用，轮换和耗尽策略为默认值，见 [`with_socket_pool`](Self::with_socket_pool)
     pub fn new(config: TransportConfig) -> Self {
-        Self { config }
+        let address = UpstreamAddress::new(config.server.clone(), config.port, HostResolution::default());
+        let pool = UdpSocketPool::new(UdpPoolConfig::new(config.pool_size), Self::server_ip(&config.server));
+        Self { config, cookies: None, address, pool, clock: clock::real_clock() }
+    }
+    
+    /// 使用指定的源端口池配置，替换 `pool_size` 对应的默认池
+    pub fn with_socket_pool(mut self, pool: UdpPoolConfig) -> Self {
+        self.pool = UdpSocketPool::new(pool, Self::server_ip(&self.config.server));
+        self
+    }
+    
+    /// 以IP配置的服务器地址，用于预先绑定同地址族的套接字
+    fn server_ip(server: &str) -> Option<IpAddr> {
+        server.trim_start_matches('[').trim_end_matches(']').parse().ok()
+    }
+    
+    /// 设置以主机名配置的服务器的解析方式
+    pub fn with_host_resolution(mut self, resolution: HostResolution) -> Self {
+        self.address = UpstreamAddress::new(self.config.server.clone(), self.config.port, resolution);
+        self
+    }
+    
+    /// 在查询中携带DNS Cookie（RFC 7873），服务器Cookie记在 `jar` 中
+    /// 
+    /// 服务器返回BADCOOKIE时用它给出的新服务器Cookie重试一次
+    pub fn with_cookies(mut self, jar: Arc<DnsCookieJar>) -> Self {
+        self.cookies = Some(jar);
+        self
+    }
+    
+    /// 使用指定的时间源计算超时和各阶段耗时，默认为真实时钟
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
     }
     
     // 注意：移除了 default() 方法，因为它依赖兜底配置
@@ -28,103 +130,64 @@ impl UdpTransport {
     // 旧代码: UdpTransport::default()
     // 新代码: UdpTransport::new(your_transport_config)
     
-    /// Windows平台特定的socket创建
-    #[cfg(windows)]
-    async fn create_windows_socket(&self) -> Result<UdpSocket> {
-        use std::net::SocketAddr;
-        
-        dns_debug!("Windows平台：开始创建UDP socket");
-        
-        // Windows平台尝试多种绑定策略
-        let bind_addresses = [
-            "0.0.0.0:0",
-            "127.0.0.1:0",
-            "::1:0",  // IPv6 localhost
-        ];
-        
-        let mut last_error = None;
-        
-        for addr in &bind_addresses {
-            dns_debug!("尝试绑定地址: {}", addr);
-            match UdpSocket::bind(addr).await {
-                Ok(socket) => {
-                    dns_debug!("成功绑定到地址: {}", addr);
-                    // 成功绑定，返回socket
-                    return Ok(socket);
-                }
-                Err(e) => {
-                    dns_debug!("绑定失败 {}: {}", addr, e);
-                    last_error = Some(e);
-                    // 继续尝试下一个地址
-                    continue;
-                }
-            }
-        }
-        
-        // 所有绑定尝试都失败
-        dns_error!("所有绑定尝试都失败");
-        if let Some(e) = last_error {
-            Err(DnsError::Network(format!("Windows UDP socket 绑定失败: {}", e)))
-        } else {
-            Err(DnsError::Network("Windows UDP socket 绑定失败: 未知错误".to_string()))
-        }
+    /// 序列化DNS请求为字节，OPT记录声明 [`UDP_EDNS_PAYLOAD_SIZE`]
+    pub fn serialize_request(request: &Request) -> Result<Vec<u8>> {
+        Self::serialize_request_with_payload_size(request, UDP_EDNS_PAYLOAD_SIZE)
     }
     
-    /// Windows平台特定的socket配置
-    #[cfg(windows)]
-    async fn configure_windows_socket(&self, socket: &UdpSocket) -> Result<()> {
-        // Windows平台的socket配置
-        // 注意：tokio的UdpSocket没有直接的socket选项设置方法
-        // 这里可以添加Windows特定的配置逻辑
-        
-        // 设置接收缓冲区大小（如果需要）
-        // 在实际应用中，可能需要使用socket2 crate来设置更多选项
-        
-        Ok(())
+    /// 请求对应的OPT记录：启用EDNS、设置DO位或携带客户端地址时生成，客户端地址编码为CLIENT_ADDRESS选项
+    pub fn request_edns(request: &Request, udp_payload_size: u16) -> Option<EdnsRecord> {
+        if !request.enable_edns && !request.dnssec_ok && request.client_address.is_none() {
+            return None;
+        }
+        let options = request.client_address.iter()
+            .map(|client_address| EdnsOption {
+                code: edns_option_codes::CLIENT_ADDRESS,
+                data: client_address.encode(),
+            })
+            .collect();
+        Some(EdnsRecord {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: request.dnssec_ok,
+            options,
+        })
     }
     
-    /// 非Windows平台的socket创建（占位方法）
-    #[cfg(not(windows))]
-    async fn create_windows_socket(&self) -> Result<UdpSocket> {
-        // 这个方法在非Windows平台不应该被调用
-        Err(DnsError::Network("在非Windows平台调用create_windows_socket方法".to_string()))
+    /// 序列化DNS请求为字节，OPT记录声明指定的载荷大小
+    /// 
+    /// 所有传输共用这一编码，只有声明的载荷大小不同（流式传输见 [`super::STREAM_EDNS_PAYLOAD_SIZE`]）
+    pub fn serialize_request_with_payload_size(request: &Request, udp_payload_size: u16) -> Result<Vec<u8>> {
+        Self::serialize_request_with_edns(request, Self::request_edns(request, udp_payload_size).as_ref())
     }
     
-    /// 非Windows平台的socket配置（占位方法）
-    #[cfg(not(windows))]
-    async fn configure_windows_socket(&self, _socket: &UdpSocket) -> Result<()> {
-        // 这个方法在非Windows平台不应该被调用
-        Ok(())
+    /// 序列化DNS请求为字节，附加段为给定的OPT记录
+    pub fn serialize_request_with_edns(request: &Request, edns: Option<&EdnsRecord>) -> Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(512);
+        Self::serialize_request_into(request, edns, &mut buffer)?;
+        Ok(buffer)
     }
     
-    /// 序列化DNS请求为字节
-    pub fn serialize_request(request: &Request) -> Result<Vec<u8>> {
+    /// 把DNS请求编码追加到 `buffer` 末尾，附加段为给定的OPT记录
+    /// 
+    /// 流式传输在缓冲区开头预留长度前缀的位置后调用，编码完成再回填长度，省去拼接前缀的复制
+    pub fn serialize_request_into(request: &Request, edns: Option<&EdnsRecord>, buffer: &mut Vec<u8>) -> Result<()> {
         dns_debug!("开始序列化DNS请求");
         dns_debug!("请求ID: {}", request.id);
         dns_debug!("查询域名: '{}'", request.query.name);
         dns_debug!("查询类型: {:?}", request.query.qtype);
         dns_debug!("客户端地址: {:?}", request.client_address);
         
-        let mut buffer = Vec::with_capacity(512);
-        
-        // 检查是否需要EDNS记录
-        let has_edns = request.client_address.is_some();
-        let additional_count = if has_edns { 1u16 } else { 0u16 };
-        dns_debug!("需要EDNS记录: {}, 附加记录数: {}", has_edns, additional_count);
+        let start = buffer.len();
+        let additional_count = if edns.is_some() { 1u16 } else { 0u16 };
+        dns_debug!("需要EDNS记录: {}, 附加记录数: {}", edns.is_some(), additional_count);
         
         // DNS头部 (12字节)
         buffer.extend_from_slice(&request.id.to_be_bytes());
         
         // 标志位
-        let mut flags = 0u16;
-        if request.flags.qr { flags |= 0x8000; }
-        flags |= (request.flags.opcode as u16) << 11;
-        if request.flags.aa { flags |= 0x0400; }
-        if request.flags.tc { flags |= 0x0200; }
-        if request.flags.rd { flags |= 0x0100; }
-        if request.flags.ra { flags |= 0x0080; }
-        flags |= (request.flags.z as u16) << 4;
-        flags |= request.flags.rcode as u16;
+        let flags = request.flags.to_bits();
         buffer.extend_from_slice(&flags.to_be_bytes());
         dns_debug!("DNS头部标志位: 0x{:04X}", flags);
         
@@ -136,36 +199,32 @@ impl UdpTransport {
         buffer.extend_from_slice(&0u16.to_be_bytes());
         // 附加计数
         buffer.extend_from_slice(&additional_count.to_be_bytes());
-        dns_debug!("DNS头部完成，当前缓冲区长度: {} 字节", buffer.len());
+        dns_debug!("DNS头部完成，当前缓冲区长度: {} 字节", buffer.len() - start);
         
         // 查询部分
-        let name_start_pos = buffer.len();
-        Self::encode_name(&request.query.name, &mut buffer)?;
-        let name_end_pos = buffer.len();
+        let name_start_pos = buffer.len() - start;
+        Self::encode_name(&request.query.name, buffer)?;
+        let name_end_pos = buffer.len() - start;
         dns_debug!("域名编码完成，占用 {} 字节 (位置 {}-{})", name_end_pos - name_start_pos, name_start_pos, name_end_pos);
         
         buffer.extend_from_slice(&u16::from(request.query.qtype).to_be_bytes());
         buffer.extend_from_slice(&u16::from(request.query.qclass).to_be_bytes());
-        dns_debug!("查询类型和类别添加完成，当前缓冲区长度: {} 字节", buffer.len());
+        dns_debug!("查询类型和类别添加完成，当前缓冲区长度: {} 字节", buffer.len() - start);
         
         // 添加EDNS记录(如果需要)
-        if let Some(ref client_address) = request.client_address {
-                dns_debug!("添加EDNS记录");
-                Self::encode_edns_record(&mut buffer, client_address)?;
-            dns_debug!("EDNS记录添加完成，最终缓冲区长度: {} 字节", buffer.len());
+        if let Some(edns) = edns {
+            dns_debug!("添加EDNS记录，声明载荷大小 {} 字节", edns.udp_payload_size);
+            Self::encode_opt_record(buffer, edns);
+            dns_debug!("EDNS记录添加完成，最终缓冲区长度: {} 字节", buffer.len() - start);
         }
         
-        dns_debug!("DNS请求序列化完成，总长度: {} 字节", buffer.len());
+        dns_debug!("DNS请求序列化完成，总长度: {} 字节", buffer.len() - start);
         
         // 打印前64字节的十六进制内容用于调试
-        let preview_len = buffer.len().min(64);
-        let hex_preview: String = buffer[..preview_len].iter()
-            .map(|b| format!("{:02X}", b))
-            .collect::<Vec<_>>()
-            .join(" ");
-        dns_debug!("请求数据预览 (前{}字节): {}", preview_len, hex_preview);
+        let preview_len = (buffer.len() - start).min(64);
+        dns_debug!("请求数据预览 (前{}字节): {}", preview_len, HexPreview(&buffer[start..start + preview_len]));
         
-        Ok(buffer)
+        Ok(())
     }
     
     /// 编码域名
@@ -217,15 +276,7 @@ impl UdpTransport {
         buffer.extend_from_slice(&response.id.to_be_bytes());
         
         // 标志位
-        let mut flags = 0u16;
-        if response.flags.qr { flags |= 0x8000; }
-        flags |= (response.flags.opcode as u16) << 11;
-        if response.flags.aa { flags |= 0x0400; }
-        if response.flags.tc { flags |= 0x0200; }
-        if response.flags.rd { flags |= 0x0100; }
-        if response.flags.ra { flags |= 0x0080; }
-        flags |= (response.flags.z as u16) << 4;
-        flags |= response.flags.rcode as u16;
+        let flags = response.flags.to_bits();
         buffer.extend_from_slice(&flags.to_be_bytes());
         dns_debug!("DNS头部标志位: 0x{:04X}", flags);
         
@@ -264,15 +315,76 @@ impl UdpTransport {
         
         // 打印前64字节的十六进制内容用于调试
         let preview_len = buffer.len().min(64);
-        let hex_preview: String = buffer[..preview_len].iter()
-            .map(|b| format!("{:02X}", b))
-            .collect::<Vec<_>>()
-            .join(" ");
-        dns_debug!("响应数据预览 (前{}字节): {}", preview_len, hex_preview);
+        dns_debug!("响应数据预览 (前{}字节): {}", preview_len, HexPreview(&buffer[..preview_len]));
         
         Ok(buffer)
     }
     
+    /// 序列化DNS响应，编码后不超过 `max_size` 字节
+    /// 
+    /// 放不下时从尾部整条丢弃记录（回答、权威、附加依次填充）。按RFC 2181 第9节，
+    /// 只有回答或权威部分的记录被丢弃时才设置TC标志，只丢弃附加记录时不设置。
+    /// 头部和查询部分本身超过 `max_size` 时返回错误。
+    pub fn serialize_response_limited(response: &Response, max_size: usize) -> Result<Vec<u8>> {
+        Self::serialize_response_with_edns(response, None, max_size)
+    }
+    
+    /// 序列化DNS响应并在附加部分末尾加上OPT记录，编码后不超过 `max_size` 字节
+    /// 
+    /// OPT记录总会保留：先从上限中扣除它的长度，其余记录按 [`serialize_response_limited`](Self::serialize_response_limited)
+    /// 的规则填充和截断。头部、查询部分和OPT记录合计超过 `max_size` 时返回错误。
+    pub fn serialize_response_with_edns(response: &Response, edns: Option<&EdnsRecord>, max_size: usize) -> Result<Vec<u8>> {
+        let mut opt = Vec::new();
+        if let Some(edns) = edns {
+            Self::encode_opt_record(&mut opt, edns);
+        }
+        let mut buffer = Vec::with_capacity(max_size.min(4096));
+        buffer.extend_from_slice(&[0u8; 12]);
+        for query in &response.queries {
+            Self::encode_name(&query.name, &mut buffer)?;
+            buffer.extend_from_slice(&u16::from(query.qtype).to_be_bytes());
+            buffer.extend_from_slice(&u16::from(query.qclass).to_be_bytes());
+        }
+        if buffer.len() + opt.len() > max_size {
+            return Err(DnsError::Protocol(format!(
+                "问题部分和OPT记录编码后为 {} 字节，超过上限 {} 字节", buffer.len() + opt.len(), max_size
+            )));
+        }
+        let limit = max_size - opt.len();
+        
+        let sections = [&response.answers, &response.authorities, &response.additionals];
+        let mut counts = [0u16; 3];
+        let mut truncated = false;
+        'sections: for (section, records) in sections.iter().enumerate() {
+            for record in records.iter() {
+                let mut encoded = Vec::new();
+                Self::encode_record(record, &mut encoded)?;
+                if buffer.len() + encoded.len() > limit {
+                    truncated = section < 2;
+                    dns_debug!("响应超过 {} 字节，丢弃第 {} 部分起的剩余记录", max_size, section);
+                    break 'sections;
+                }
+                buffer.extend_from_slice(&encoded);
+                counts[section] += 1;
+            }
+        }
+        if edns.is_some() {
+            buffer.extend_from_slice(&opt);
+            counts[2] += 1;
+        }
+        
+        let mut flags = response.flags;
+        flags.tc |= truncated;
+        buffer[0..2].copy_from_slice(&response.id.to_be_bytes());
+        buffer[2..4].copy_from_slice(&flags.to_bits().to_be_bytes());
+        buffer[4..6].copy_from_slice(&(response.queries.len() as u16).to_be_bytes());
+        for (index, count) in counts.iter().enumerate() {
+            let offset = 6 + index * 2;
+            buffer[offset..offset + 2].copy_from_slice(&count.to_be_bytes());
+        }
+        Ok(buffer)
+    }
+    
     /// 编码DNS记录
     pub fn encode_record(record: &crate::types::Record, buffer: &mut Vec<u8>) -> Result<()> {
         // 编码名称
@@ -344,6 +456,8 @@ impl UdpTransport {
     }
     
     /// 反序列化DNS请求
+    /// 
+    /// 操作码不是QUERY的请求只解析头部和第一个问题（UPDATE为区域），不读取EDNS
     pub fn deserialize_request(data: &[u8]) -> Result<Request> {
         if data.len() < 12 {
             return Err(DnsError::Protocol("请求数据过短".to_string()));
@@ -352,16 +466,7 @@ impl UdpTransport {
         let id = u16::from_be_bytes([data[0], data[1]]);
         let flags_raw = u16::from_be_bytes([data[2], data[3]]);
         
-        let flags = crate::types::Flags {
-            qr: (flags_raw & 0x8000) != 0,
-            opcode: ((flags_raw >> 11) & 0x0F) as u8,
-            aa: (flags_raw & 0x0400) != 0,
-            tc: (flags_raw & 0x0200) != 0,
-            rd: (flags_raw & 0x0100) != 0,
-            ra: (flags_raw & 0x0080) != 0,
-            z: ((flags_raw >> 4) & 0x07) as u8,
-            rcode: (flags_raw & 0x0F) as u8,
-        };
+        let flags = crate::types::Flags::from_bits(flags_raw);
         
         let qdcount = u16::from_be_bytes([data[4], data[5]]);
         
@@ -369,24 +474,50 @@ impl UdpTransport {
             return Err(DnsError::Protocol("请求必须包含且仅包含一个查询".to_string()));
         }
         
-        let mut offset = 12;
-        
-        // 解析查询部分
-        let (query, _) = Self::parse_query(data, offset)?;
+        // 解析查询部分（UPDATE报文中为区域段，格式相同）
+        let (query, _) = Self::parse_query_with_budget(data, 12, &mut NameBudget::for_message(data))?;
+        
+        // NOTIFY、UPDATE等其他操作码的后续各段与查询不同，不再解析，由调用方按操作码处理
+        if flags.opcode != Opcode::Query {
+            return Ok(Request {
+                id,
+                flags,
+                query,
+                client_address: None,
+                enable_edns: false,
+                dnssec_ok: false,
+                wire_capture_limit: None,
+                priority: QueryPriority::Normal,
+            });
+        }
         
-        // 检查是否有EDNS记录
-        let client_address = None; // 简化处理，暂不解析EDNS
+        // 附加段中的OPT记录及其客户端地址选项
+        let edns = Self::parse_edns(data)?;
+        let client_address = edns.as_ref()
+            .and_then(|edns| edns.options.iter().find(|option| option.code == edns_option_codes::CLIENT_ADDRESS))
+            .map(|option| crate::types::ClientAddress::decode(&option.data))
+            .transpose()
+            .map_err(|e| DnsError::Protocol(format!("客户端地址选项无效: {}", e)))?;
         
         Ok(Request {
             id,
             flags,
             query,
             client_address,
+            enable_edns: edns.is_some(),
+            dnssec_ok: edns.as_ref().is_some_and(|edns| edns.dnssec_ok),
+            wire_capture_limit: None,
+            priority: QueryPriority::Normal,
         })
     }
     
     /// 反序列化DNS响应
     pub fn deserialize_response(data: &[u8]) -> Result<Response> {
+        Self::deserialize_response_with_limits(data, None)
+    }
+    
+    /// 反序列化DNS响应，拒绝模式下头部声明的记录数超出 `limits` 时直接返回协议错误，不解析记录
+    pub fn deserialize_response_with_limits(data: &[u8], limits: Option<&RecordLimits>) -> Result<Response> {
         if data.len() < 12 {
             return Err(DnsError::Protocol("响应数据过短".to_string()));
         }
@@ -394,52 +525,56 @@ impl UdpTransport {
         let id = u16::from_be_bytes([data[0], data[1]]);
         let flags_raw = u16::from_be_bytes([data[2], data[3]]);
         
-        let flags = crate::types::Flags {
-            qr: (flags_raw & 0x8000) != 0,
-            opcode: ((flags_raw >> 11) & 0x0F) as u8,
-            aa: (flags_raw & 0x0400) != 0,
-            tc: (flags_raw & 0x0200) != 0,
-            rd: (flags_raw & 0x0100) != 0,
-            ra: (flags_raw & 0x0080) != 0,
-            z: ((flags_raw >> 4) & 0x07) as u8,
-            rcode: (flags_raw & 0x0F) as u8,
-        };
+        let flags = crate::types::Flags::from_bits(flags_raw);
         
         let qdcount = u16::from_be_bytes([data[4], data[5]]);
         let ancount = u16::from_be_bytes([data[6], data[7]]);
         let nscount = u16::from_be_bytes([data[8], data[9]]);
         let arcount = u16::from_be_bytes([data[10], data[11]]);
         
+        // 计数与报文长度明显不符时直接拒绝，不逐条解析到报文末尾
+        let record_count = ancount as usize + nscount as usize + arcount as usize;
+        if qdcount as usize * MIN_QUERY_LEN + record_count * MIN_RECORD_LEN > data.len() - 12 {
+            return Err(DnsError::Protocol(format!(
+                "报文头部声明了 {} 个查询和 {} 条记录，但报文只有 {} 字节",
+                qdcount, record_count, data.len()
+            )));
+        }
+        if let Some(limits) = limits {
+            limits.check_counts(ancount as usize, record_count)?;
+        }
+        
         let mut offset = 12;
         let mut queries = Vec::new();
         let mut answers = Vec::new();
         let mut authorities = Vec::new();
         let mut additionals = Vec::new();
+        let mut budget = NameBudget::for_message(data);
         
         // 解析查询部分
         for _ in 0..qdcount {
-            let (query, new_offset) = Self::parse_query(data, offset)?;
+            let (query, new_offset) = Self::parse_query_with_budget(data, offset, &mut budget)?;
             queries.push(query);
             offset = new_offset;
         }
         
         // 解析回答部分
         for _ in 0..ancount {
-            let (record, new_offset) = Self::parse_record(data, offset)?;
+            let (record, new_offset) = Self::parse_record_with_budget(data, offset, &mut budget)?;
             answers.push(record);
             offset = new_offset;
         }
         
         // 解析权威部分
         for _ in 0..nscount {
-            let (record, new_offset) = Self::parse_record(data, offset)?;
+            let (record, new_offset) = Self::parse_record_with_budget(data, offset, &mut budget)?;
             authorities.push(record);
             offset = new_offset;
         }
         
         // 解析附加部分
         for _ in 0..arcount {
-            let (record, new_offset) = Self::parse_record(data, offset)?;
+            let (record, new_offset) = Self::parse_record_with_budget(data, offset, &mut budget)?;
             additionals.push(record);
             offset = new_offset;
         }
@@ -456,7 +591,11 @@ impl UdpTransport {
     
     /// 解析查询记录
     pub fn parse_query(data: &[u8], offset: usize) -> Result<(crate::types::Query, usize)> {
-        let (name, mut offset) = Self::parse_name(data, offset)?;
+        Self::parse_query_with_budget(data, offset, &mut NameBudget::for_message(data))
+    }
+    
+    fn parse_query_with_budget(data: &[u8], offset: usize, budget: &mut NameBudget) -> Result<(crate::types::Query, usize)> {
+        let (name, mut offset) = Self::parse_name_with_budget(data, offset, budget)?;
         
         if offset + 4 > data.len() {
             return Err(DnsError::Protocol("查询格式无效".to_string()));
@@ -471,7 +610,11 @@ impl UdpTransport {
     
     /// 解析资源记录
     pub fn parse_record(data: &[u8], offset: usize) -> Result<(crate::types::Record, usize)> {
-        let (name, mut offset) = Self::parse_name(data, offset)?;
+        Self::parse_record_with_budget(data, offset, &mut NameBudget::for_message(data))
+    }
+    
+    fn parse_record_with_budget(data: &[u8], offset: usize, budget: &mut NameBudget) -> Result<(crate::types::Record, usize)> {
+        let (name, mut offset) = Self::parse_name_with_budget(data, offset, budget)?;
         
         if offset + 10 > data.len() {
             return Err(DnsError::Protocol("记录格式无效".to_string()));
@@ -488,7 +631,7 @@ impl UdpTransport {
         }
         
         let rdata = &data[offset..offset + rdlength];
-        let record_data = Self::parse_record_data(rtype, rdata, data, offset)?;
+        let record_data = Self::parse_record_data_with_budget(rtype, rdata, data, offset, budget)?;
         offset += rdlength;
         
         Ok((crate::types::Record {
@@ -501,32 +644,34 @@ impl UdpTransport {
     }
     
     /// 解析域名
-    pub fn parse_name(data: &[u8], mut offset: usize) -> Result<(String, usize)> {
+    /// 
+    /// 压缩指针必须指向当前这段标签之前的位置（只能向报文开头跳转），因此不会形成循环；
+    /// 跳转超过 [`MAX_POINTER_JUMPS`] 次或域名超过 [`MAX_NAME_WIRE_LEN`] 字节时返回错误
+    pub fn parse_name(data: &[u8], offset: usize) -> Result<(String, usize)> {
+        Self::parse_name_with_budget(data, offset, &mut NameBudget::for_message(data))
+    }
+    
+    /// 解析域名，读取的每个标签和跟随的每个指针计入报文的工作量预算
+    fn parse_name_with_budget(data: &[u8], mut offset: usize, budget: &mut NameBudget) -> Result<(String, usize)> {
         dns_debug!("开始解析域名，起始偏移: {}, 数据长度: {}", offset, data.len());
         
         let mut name = String::new();
-        let mut jumped = false;
-        let mut jump_offset = 0;
-        let mut loop_count = 0;
-        const MAX_LOOPS: usize = 100; // 防止无限循环
+        let mut jump_offset = None;
+        let mut jumps = 0;
+        // 当前这段标签的起始位置，压缩指针只能指向它之前
+        let mut segment_start = offset;
+        // 已解析部分的线路长度（不含结尾的0）
+        let mut wire_len = 0;
         
         loop {
-            loop_count += 1;
-            if loop_count > MAX_LOOPS {
-                dns_debug!("域名解析循环次数超限，可能存在循环引用");
-                return Err(DnsError::Protocol("域名解析检测到循环引用".to_string()));
-            }
-            
             if offset >= data.len() {
                 dns_debug!("偏移量 {} 超出数据长度 {}", offset, data.len());
                 return Err(DnsError::Protocol("域名解析数据溢出".to_string()));
             }
             
             let len = data[offset];
-            dns_debug!("偏移 {}: 长度字节 = 0x{:02X} ({})", offset, len, len);
             
             if len == 0 {
-                dns_debug!("遇到域名终止符，解析完成");
                 offset += 1;
                 break;
             }
@@ -534,34 +679,39 @@ impl UdpTransport {
             if (len & 0xC0) == 0xC0 {
                 // 压缩指针
                 if offset + 1 >= data.len() {
-                    dns_debug!("压缩指针数据不完整");
                     return Err(DnsError::Protocol("压缩指针数据不完整".to_string()));
                 }
                 
                 let pointer = (((len & 0x3F) as usize) << 8) | (data[offset + 1] as usize);
-                dns_debug!("压缩指针指向偏移: {}", pointer);
-                
-                if pointer >= data.len() {
-                    dns_debug!("压缩指针 {} 超出数据范围 {}", pointer, data.len());
+                if pointer >= segment_start {
+                    dns_debug!("压缩指针 {} 没有指向 {} 之前", pointer, segment_start);
                     return Err(DnsError::Protocol("压缩指针无效".to_string()));
                 }
                 
-                if !jumped {
-                    jump_offset = offset + 2;
-                    jumped = true;
-                    dns_debug!("设置跳转返回点: {}", jump_offset);
+                jumps += 1;
+                if jumps > MAX_POINTER_JUMPS {
+                    return Err(DnsError::Protocol("域名压缩指针跳转次数过多".to_string()));
                 }
+                budget.spend()?;
                 
+                jump_offset.get_or_insert(offset + 2);
                 offset = pointer;
+                segment_start = pointer;
                 continue;
             }
             
-            // 普通标签
+            // 普通标签（0x40和0x80开头的扩展标签类型也在这里拒绝）
             if len > 63 {
                 dns_debug!("标签长度 {} 超过63字节限制", len);
                 return Err(DnsError::Protocol("标签长度过长".to_string()));
             }
             
+            wire_len += 1 + len as usize;
+            if wire_len + 1 > MAX_NAME_WIRE_LEN {
+                return Err(DnsError::Protocol(format!("域名超过{}字节", MAX_NAME_WIRE_LEN)));
+            }
+            budget.spend()?;
+            
             offset += 1;
             if offset + len as usize > data.len() {
                 dns_debug!("标签数据超出范围: 偏移{}+长度{} > 数据长度{}", offset, len, data.len());
@@ -572,17 +722,11 @@ impl UdpTransport {
                 name.push('.');
             }
             
-            let label = String::from_utf8_lossy(&data[offset..offset + len as usize]);
-            dns_debug!("解析标签: '{}'", label);
-            name.push_str(&label);
+            name.push_str(&String::from_utf8_lossy(&data[offset..offset + len as usize]));
             offset += len as usize;
         }
         
-        if jumped {
-            offset = jump_offset;
-            dns_debug!("恢复到跳转返回点: {}", offset);
-        }
-        
+        let offset = jump_offset.unwrap_or(offset);
         dns_debug!("域名解析完成: '{}', 最终偏移: {}", name, offset);
         Ok((name, offset))
     }
@@ -593,10 +737,30 @@ impl UdpTransport {
         rdata: &[u8],
         full_data: &[u8],
         rdata_offset: usize,
+    ) -> Result<crate::types::RecordData> {
+        Self::parse_record_data_with_budget(rtype, rdata, full_data, rdata_offset, &mut NameBudget::for_message(full_data))
+    }
+    
+    fn parse_record_data_with_budget(
+        rtype: crate::types::RecordType,
+        rdata: &[u8],
+        full_data: &[u8],
+        rdata_offset: usize,
+        budget: &mut NameBudget,
     ) -> Result<crate::types::RecordData> {
         use crate::types::{RecordType, RecordData};
         use std::net::{Ipv4Addr, Ipv6Addr};
         
+        // 记录数据中的域名必须在数据长度之内结束（压缩指针指向的部分除外）
+        let rdata_end = rdata_offset + rdata.len();
+        let mut name_at = |start: usize| -> Result<(String, usize)> {
+            let (name, end) = Self::parse_name_with_budget(full_data, start, budget)?;
+            if end > rdata_end {
+                return Err(DnsError::Protocol("记录数据中的域名超出数据长度".to_string()));
+            }
+            Ok((name, end))
+        };
+        
         match rtype {
             RecordType::A => {
                 if rdata.len() != 4 {
@@ -613,8 +777,7 @@ impl UdpTransport {
                 Ok(RecordData::AAAA(Ipv6Addr::from(addr)))
             }
             RecordType::CNAME | RecordType::NS | RecordType::PTR => {
-                // 使用正确的偏移量来解析域名
-                let (name, _) = Self::parse_name(full_data, rdata_offset)?;
+                let (name, _) = name_at(rdata_offset)?;
                 match rtype {
                     RecordType::CNAME => Ok(RecordData::CNAME(name)),
                     RecordType::NS => Ok(RecordData::NS(name)),
@@ -629,7 +792,7 @@ impl UdpTransport {
                 // MX记录格式: 优先级(2字节) + 交换机域名
                 let priority = u16::from_be_bytes([rdata[0], rdata[1]]);
                 // 解析交换机域名，从rdata_offset + 2开始
-                let (exchange, _) = Self::parse_name(full_data, rdata_offset + 2)?;
+                let (exchange, _) = name_at(rdata_offset + 2)?;
                 Ok(RecordData::MX { priority, exchange })
             }
             RecordType::TXT => {
@@ -637,9 +800,6 @@ impl UdpTransport {
                 let mut texts = Vec::new();
                 let mut offset = 0;
                 while offset < rdata.len() {
-                    if offset >= rdata.len() {
-                        break;
-                    }
                     let len = rdata[offset] as usize;
                     offset += 1;
                     if offset + len > rdata.len() {
@@ -651,12 +811,59 @@ impl UdpTransport {
                 }
                 Ok(RecordData::TXT(texts))
             }
+            RecordType::SRV => {
+                if rdata.len() < 7 {
+                    return Err(DnsError::Protocol("SRV记录长度无效".to_string()));
+                }
+                // SRV记录格式: 优先级(2字节) + 权重(2字节) + 端口(2字节) + 目标域名
+                let priority = u16::from_be_bytes([rdata[0], rdata[1]]);
+                let weight = u16::from_be_bytes([rdata[2], rdata[3]]);
+                let port = u16::from_be_bytes([rdata[4], rdata[5]]);
+                let (target, _) = name_at(rdata_offset + 6)?;
+                Ok(RecordData::SRV { priority, weight, port, target })
+            }
+            RecordType::SOA => {
+                // SOA记录格式: 主服务器域名 + 管理员邮箱域名 + 5个u32字段
+                let (mname, offset) = name_at(rdata_offset)?;
+                let (rname, offset) = name_at(offset)?;
+                if offset + 20 > rdata_end {
+                    return Err(DnsError::Protocol("SOA记录长度无效".to_string()));
+                }
+                let field = |index: usize| {
+                    let start = offset + index * 4;
+                    u32::from_be_bytes([full_data[start], full_data[start + 1], full_data[start + 2], full_data[start + 3]])
+                };
+                Ok(RecordData::SOA {
+                    mname,
+                    rname,
+                    serial: field(0),
+                    refresh: field(1),
+                    retry: field(2),
+                    expire: field(3),
+                    minimum: field(4),
+                })
+            }
             _ => Ok(RecordData::Unknown(rdata.to_vec())),
         }
     }
     
-    /// 编码EDNS记录
+    /// 编码携带客户端地址的EDNS记录（载荷大小为 [`UDP_EDNS_PAYLOAD_SIZE`]）
     pub fn encode_edns_record(buffer: &mut Vec<u8>, client_address: &crate::types::ClientAddress) -> Result<()> {
+        Self::encode_opt_record(buffer, &EdnsRecord {
+            udp_payload_size: UDP_EDNS_PAYLOAD_SIZE,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: vec![EdnsOption {
+                code: edns_option_codes::CLIENT_ADDRESS,
+                data: client_address.encode(),
+            }],
+        });
+        Ok(())
+    }
+    
+    /// 编码OPT伪记录
+    pub fn encode_opt_record(buffer: &mut Vec<u8>, edns: &EdnsRecord) {
         // EDNS记录格式:
         // NAME: . (root, 1字节: 0x00)
         // TYPE: OPT (41, 2字节)
@@ -669,132 +876,309 @@ impl UdpTransport {
         buffer.push(0x00);
         
         // TYPE: OPT (41)
-        buffer.extend_from_slice(&41u16.to_be_bytes());
+        buffer.extend_from_slice(&OPT_RECORD_TYPE.to_be_bytes());
         
         // CLASS: UDP payload size
-        buffer.extend_from_slice(&4096u16.to_be_bytes());
+        buffer.extend_from_slice(&edns.udp_payload_size.to_be_bytes());
         
         // TTL: Extended RCODE(1) + Version(1) + DO bit + Z(2)
-        buffer.push(0); // Extended RCODE
-        buffer.push(0); // Version
-        buffer.extend_from_slice(&0u16.to_be_bytes()); // Flags (DO=0, Z=0)
-        
-        // 编码Client Address选项
-        let client_address_data = client_address.encode();
-        let option_length = client_address_data.len() as u16;
-        
-        // RDLENGTH: 选项头部(4字节) + 选项数据长度
-        let rdlength = 4 + option_length;
-        buffer.extend_from_slice(&rdlength.to_be_bytes());
-        
-        // 选项代码: Client Address (8) - 修正命名，原CLIENT_SUBNET容易误导
-        buffer.extend_from_slice(&edns_option_codes::CLIENT_ADDRESS.to_be_bytes());
+        buffer.push(edns.extended_rcode);
+        buffer.push(edns.version);
+        let flags: u16 = if edns.dnssec_ok { 0x8000 } else { 0 };
+        buffer.extend_from_slice(&flags.to_be_bytes());
         
-        // 选项长度
-        buffer.extend_from_slice(&option_length.to_be_bytes());
+        // RDLENGTH: 每个选项为 代码(2) + 长度(2) + 数据
+        let rdlength: usize = edns.options.iter().map(|option| 4 + option.data.len()).sum();
+        buffer.extend_from_slice(&(rdlength as u16).to_be_bytes());
         
-        // 选项数据
-        buffer.extend_from_slice(&client_address_data);
+        for option in &edns.options {
+            buffer.extend_from_slice(&option.code.to_be_bytes());
+            buffer.extend_from_slice(&(option.data.len() as u16).to_be_bytes());
+            buffer.extend_from_slice(&option.data);
+        }
+    }
+    
+    /// 解析报文附加段中的OPT伪记录，没有OPT时返回 `None`
+    pub fn parse_edns(data: &[u8]) -> Result<Option<EdnsRecord>> {
+        if data.len() < 12 {
+            return Err(DnsError::Protocol("报文数据过短".to_string()));
+        }
+        let count = |index: usize| u16::from_be_bytes([data[index], data[index + 1]]) as usize;
+        let (qdcount, record_count) = (count(4), count(6) + count(8) + count(10));
         
-        Ok(())
+        let mut offset = 12;
+        let mut budget = NameBudget::for_message(data);
+        for _ in 0..qdcount {
+            let (_, next) = Self::parse_query_with_budget(data, offset, &mut budget)?;
+            offset = next;
+        }
+        for _ in 0..record_count {
+            let (_, name_end) = Self::parse_name_with_budget(data, offset, &mut budget)?;
+            let header = data.get(name_end..name_end + 10)
+                .ok_or_else(|| DnsError::Protocol("资源记录头部不完整".to_string()))?;
+            let rtype = u16::from_be_bytes([header[0], header[1]]);
+            let rdlength = u16::from_be_bytes([header[8], header[9]]) as usize;
+            let rdata = data.get(name_end + 10..name_end + 10 + rdlength)
+                .ok_or_else(|| DnsError::Protocol("资源记录数据不完整".to_string()))?;
+            offset = name_end + 10 + rdlength;
+            if rtype != OPT_RECORD_TYPE {
+                continue;
+            }
+            
+            let mut options = Vec::new();
+            let mut cursor = 0;
+            while cursor + 4 <= rdata.len() {
+                let code = u16::from_be_bytes([rdata[cursor], rdata[cursor + 1]]);
+                let length = u16::from_be_bytes([rdata[cursor + 2], rdata[cursor + 3]]) as usize;
+                let value = rdata.get(cursor + 4..cursor + 4 + length)
+                    .ok_or_else(|| DnsError::Protocol("EDNS选项数据不完整".to_string()))?;
+                options.push(EdnsOption { code, data: value.to_vec() });
+                cursor += 4 + length;
+            }
+            return Ok(Some(EdnsRecord {
+                udp_payload_size: u16::from_be_bytes([header[2], header[3]]),
+                extended_rcode: header[4],
+                version: header[5],
+                dnssec_ok: header[6] & 0x80 != 0,
+                options,
+            }));
+        }
+        Ok(None)
     }
-}
-
-#[async_trait]
-impl Transport for UdpTransport {
-    async fn send(&self, request: &Request) -> Result<Response> {
+    
+    /// 发送请求并接收响应，`timing` 开启时在各阶段结束时打点；网络失败计入地址的连续失败次数
+    /// 
+    /// `timeout` 限制整个过程，超时的错误带有当时所处的阶段
+    async fn exchange(&self, request: &Request, timing: &mut TimingRecorder, wire: &mut WireRecorder) -> Result<Response> {
+        let deadline = self.clock.now_instant() + self.config.timeout;
+        let result = clock::timeout_at(&*self.clock, deadline, self.try_exchange(request, deadline, timing, wire)).await
+            .unwrap_or_else(|| Err(timing.timed_out()));
+        self.address.observe(&result);
+        result
+    }
+    
+    async fn try_exchange(&self, request: &Request, deadline: Instant, timing: &mut TimingRecorder, wire: &mut WireRecorder) -> Result<Response> {
         dns_debug!("UDP传输开始发送请求");
         dns_debug!("目标域名: {}", request.query.name);
         dns_debug!("查询类型: {:?}", request.query.qtype);
         
-        // 平台特定的socket绑定策略
-        let socket = if cfg!(windows) {
-            dns_debug!("使用Windows平台socket创建策略");
-            // Windows平台：使用特定的绑定策略
-            self.create_windows_socket().await?
-        } else {
-            dns_debug!("使用Unix/Linux平台socket创建策略");
-            // Unix/Linux平台：使用标准绑定
-            UdpSocket::bind("0.0.0.0:0").await
-                .map_err(|e| DnsError::Network(format!("UDP socket 绑定失败: {}", e)))?
-        };
-        
-        let server_addr = format!("{}:{}", self.config.server, self.config.port);
-        dns_debug!("DNS服务器地址: {}", server_addr);
-        
-        // 平台特定的socket配置
-        if cfg!(windows) {
-            dns_debug!("配置Windows socket选项");
-            self.configure_windows_socket(&socket).await?;
+        if self.address.is_hostname() {
+            timing.enter(TransportPhase::UpstreamResolve);
+        }
+        let addrs = self.address.dial_addrs(self.config.timeout).await?;
+        if self.address.is_hostname() {
+            timing.mark(TimingPhase::UpstreamResolve);
         }
         
-        let request_data = Self::serialize_request(request)?;
+        // 每个地址族只取排在最前的地址：偏好的地址族网络失败（含超时）时改用另一个地址族，
+        // 因此偏好的地址族最多等待剩余时间的一半
+        let mut targets: Vec<SocketAddr> = Vec::with_capacity(2);
+        for addr in addrs {
+            if !targets.iter().any(|target| AddressFamily::of(target.ip()) == AddressFamily::of(addr.ip())) {
+                targets.push(addr);
+            }
+        }
+        let mut last_error = None;
+        let count = targets.len();
+        for (index, target) in targets.into_iter().enumerate() {
+            let attempt = super::within_share(&*self.clock, deadline, index + 1 == count, self.exchange_with(target, request, timing, wire)).await;
+            match attempt.unwrap_or_else(|| Err(timing.timed_out())) {
+                Err(e) if e.network_kind().is_some() => {
+                    dns_debug!("UDP上游 {} 经 {} 查询失败: {}", self.config.server, target, e);
+                    self.address.record_family(target, false);
+                    last_error = Some(e);
+                }
+                result => {
+                    self.address.record_family(target, true);
+                    return result;
+                }
+            }
+        }
+        Err(last_error.unwrap_or(DnsError::NoUpstreamAvailable { filter: None }))
+    }
+    
+    /// 向一个地址发送请求并接收响应
+    async fn exchange_with(
+        &self,
+        target: SocketAddr,
+        request: &Request,
+        timing: &mut TimingRecorder,
+        wire: &mut WireRecorder,
+    ) -> Result<Response> {
+        // 从源端口池借出套接字，本次查询（包括BADCOOKIE重试）结束后归还
+        let lease = self.pool.checkout(target).await?;
+        let socket = lease.socket();
+        
+        let server_addr = super::host_port(&self.config.server, self.config.port);
+        dns_debug!("DNS服务器地址: {} ({})", server_addr, target);
+        
+        // 服务器返回BADCOOKIE并给出新的服务器Cookie时，带上它重试一次
+        let mut retried = false;
+        loop {
+            let request_data = self.encode_request(request, &server_addr)?;
+            let message = self.exchange_datagram(socket, target, request, &request_data, timing, wire).await?;
+            let response = Self::deserialize_response_with_limits(&message, self.config.record_limits.as_ref());
+            match &response {
+                Ok(response) => {
+                    dns_debug!("DNS响应解析成功，包含 {} 个回答记录", response.answers.len());
+                },
+                Err(e) => {
+                    dns_error!("DNS响应解析失败: {}", e);
+                }
+            }
+            
+            let Some(jar) = &self.cookies else {
+                return response;
+            };
+            let response = response?;
+            let fresh_cookie = Self::parse_edns(&message).ok().flatten()
+                .and_then(|edns| edns.options.into_iter().find(|option| option.code == edns_option_codes::COOKIE))
+                .is_some_and(|option| jar.observe(&server_addr, &option.data));
+            if response.extended_rcode() != BADCOOKIE {
+                return Ok(response);
+            }
+            if !fresh_cookie || retried {
+                return Err(DnsError::Server(format!("{} rejected the DNS cookie (BADCOOKIE)", server_addr)));
+            }
+            dns_info!("🍪 {} 返回BADCOOKIE，使用新的服务器Cookie重试", server_addr);
+            retried = true;
+        }
+    }
+    
+    /// OPT记录中声明的载荷大小，即配置的 `buffer_size`
+    fn payload_size(&self) -> u16 {
+        u16::try_from(self.config.buffer_size).unwrap_or(u16::MAX)
+    }
+    
+    /// 编码请求，开启Cookie时在OPT记录中附加COOKIE选项（即使请求本身不带OPT）
+    fn encode_request(&self, request: &Request, server_addr: &str) -> Result<Vec<u8>> {
+        let Some(jar) = &self.cookies else {
+            return Self::serialize_request_with_payload_size(request, self.payload_size());
+        };
+        let mut edns = Self::request_edns(request, self.payload_size()).unwrap_or_else(|| EdnsRecord {
+            udp_payload_size: self.payload_size(),
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        });
+        edns.options.push(jar.request_option(server_addr));
+        Self::serialize_request_with_edns(request, Some(&edns))
+    }
+    
+    /// 发出一个请求数据报，返回ID相符的响应数据报
+    async fn exchange_datagram(
+        &self,
+        socket: &UdpSocket,
+        target: SocketAddr,
+        request: &Request,
+        request_data: &[u8],
+        timing: &mut TimingRecorder,
+        wire: &mut WireRecorder,
+    ) -> Result<Vec<u8>> {
+        let server_addr = super::host_port(&self.config.server, self.config.port);
+        wire.record_request(request_data);
         dns_debug!("请求数据长度: {} 字节", request_data.len());
         
-        let send_result = timeout(
-            self.config.timeout,
-            socket.send_to(&request_data, &server_addr)
-        ).await;
-        
-        match send_result {
-            Ok(Ok(_)) => {},
-            Ok(Err(e)) => {
-                let error_msg = if cfg!(windows) {
-                    format!("Windows UDP 发送失败: {} (服务器: {})", e, server_addr)
-                } else {
-                    format!("UDP 发送失败: {} (服务器: {})", e, server_addr)
-                };
-                return Err(DnsError::Network(error_msg));
-            },
-            Err(_) => return Err(DnsError::Timeout),
+        timing.enter(TransportPhase::RequestSent);
+        if let Err(e) = socket.send_to(request_data, target).await {
+            let context = if cfg!(windows) { "Windows UDP 发送失败" } else { "UDP 发送失败" };
+            return Err(DnsError::network_io(server_addr, context, &e));
         }
-        
-        let mut buffer = [0u8; 512];
-        let recv_result = timeout(
-            self.config.timeout,
-            socket.recv(&mut buffer)
-        ).await;
+        timing.mark(TimingPhase::RequestSent);
+        
+        // 接收缓冲区比声明的载荷大小多1字节，用来发现超长的数据报。
+        // 池中的套接字不绑定对端且会被复用，来源不是目标上游的数据报（伪造的响应）和ID不符的数据报（迟到的响应）丢弃后继续等待
+        timing.enter(TransportPhase::FirstByte);
+        let limit = self.config.buffer_size;
+        let mut buffer = vec![0u8; limit + 1];
+        let recv_result = loop {
+            match socket.recv_from(&mut buffer).await {
+                Ok((len, peer)) if peer != target => {
+                    dns_warn!("丢弃来源不是上游 {} 的UDP数据报: {} 字节，来自 {}", target, len, peer);
+                }
+                Ok((len, _)) if len < 2 || u16::from_be_bytes([buffer[0], buffer[1]]) != request.id => {
+                    dns_warn!("丢弃ID不匹配的UDP数据报: {} 字节，期望ID {}", len, request.id);
+                }
+                result => break result,
+            }
+        };
         
         let len = match recv_result {
-            Ok(Ok(len)) => len,
-            Ok(Err(e)) => {
-                let error_msg = if cfg!(windows) {
-                    format!("Windows UDP 接收失败: {}", e)
-                } else {
-                    format!("UDP 接收失败: {}", e)
-                };
-                return Err(DnsError::Network(error_msg));
+            Ok((len, peer)) => {
+                timing.set_peer(peer);
+                len
+            },
+            Err(e) => {
+                let context = if cfg!(windows) { "Windows UDP 接收失败" } else { "UDP 接收失败" };
+                return Err(DnsError::network_io(server_addr, context, &e));
             },
-            Err(_) => return Err(DnsError::Timeout),
         };
+        timing.mark(TimingPhase::FirstByte);
+        if len > limit {
+            return Err(DnsError::Protocol(format!(
+                "UDP response from {} exceeds the buffer_size limit of {} bytes (received at least {} bytes)",
+                server_addr, limit, len
+            )));
+        }
+        wire.record_response(&buffer[..len]);
         
         dns_debug!("收到DNS响应，长度: {} 字节", len);
         
         // 打印响应数据的十六进制内容用于调试
         let preview_len = len.min(64);
-        let hex_preview: String = buffer[..preview_len].iter()
-            .map(|b| format!("{:02X}", b))
-            .collect::<Vec<_>>()
-            .join(" ");
-        dns_debug!("响应数据预览 (前{}字节): {}", preview_len, hex_preview);
-        
-        let result = Self::deserialize_response(&buffer[..len]);
-        match &result {
-            Ok(response) => {
-                dns_debug!("DNS响应解析成功，包含 {} 个回答记录", response.answers.len());
-            },
-            Err(e) => {
-                dns_error!("DNS响应解析失败: {}", e);
-            }
-        }
+        dns_debug!("响应数据预览 (前{}字节): {}", preview_len, HexPreview(&buffer[..preview_len]));
         
-        result
+        buffer.truncate(len);
+        Ok(buffer)
+    }
+}
+
+#[async_trait]
+impl Transport for UdpTransport {
+    async fn send(&self, request: &Request) -> Result<Response> {
+        self.exchange(request, &mut TimingRecorder::disabled(), &mut WireRecorder::disabled()).await
+    }
+    
+    async fn prepare(&self) -> Result<()> {
+        self.address.socket_addrs(self.config.timeout).await.map(|_| ())
+    }
+    
+    async fn send_with_peer(&self, request: &Request) -> Result<(Response, Option<SocketAddr>)> {
+        let mut timing = TimingRecorder::disabled();
+        let response = self.exchange(request, &mut timing, &mut WireRecorder::disabled()).await?;
+        Ok((response, timing.finish().peer))
+    }
+    
+    async fn send_timed(&self, request: &Request) -> Result<(Response, TransportTiming)> {
+        let mut timing = TimingRecorder::start(&self.clock);
+        let response = self.exchange(request, &mut timing, &mut WireRecorder::disabled()).await?;
+        Ok((response, timing.finish()))
+    }
+    
+    async fn send_captured(&self, request: &Request, max_bytes: usize) -> Result<(Response, TransportTiming, WireCapture)> {
+        let mut timing = TimingRecorder::start(&self.clock);
+        let mut wire = WireRecorder::with_limit(max_bytes);
+        let response = self.exchange(request, &mut timing, &mut wire).await?;
+        Ok((response, timing.finish(), wire.finish()))
+    }
+    
+    fn udp_pool_stats(&self) -> Option<UdpPoolStats> {
+        Some(self.pool.stats())
+    }
+    
+    fn address_family_stats(&self) -> Option<AddressFamilyStats> {
+        Some(self.address.family_stats())
     }
     
     fn transport_type(&self) -> &'static str {
         "UDP"
     }
     
+    fn endpoint(&self) -> String {
+        super::host_port(&self.config.server, self.config.port)
+    }
+    
     fn set_timeout(&mut self, timeout: Duration) {
         self.config.timeout = timeout;
     }
@@ -802,4 +1186,418 @@ impl Transport for UdpTransport {
     fn timeout(&self) -> Duration {
         self.config.timeout
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_response::DnsResponseWrapper;
+    use crate::types::{Flags, Query, QClass, RecordData, RecordType};
+    use crate::transport::cookie::CLIENT_COOKIE_LEN;
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// 要求回显服务器Cookie的模拟上游，返回端口和收到的请求计数
+    async fn cookie_enforcing_upstream(server_cookie: [u8; 8]) -> (u16, Arc<AtomicUsize>) {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (len, peer) = upstream.recv_from(&mut buf).await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+                let cookie = UdpTransport::parse_edns(&buf[..len]).unwrap().unwrap()
+                    .options.into_iter()
+                    .find(|option| option.code == edns_option_codes::COOKIE)
+                    .unwrap()
+                    .data;
+                let echoed = cookie.len() > CLIENT_COOKIE_LEN && cookie[CLIENT_COOKIE_LEN..] == server_cookie;
+
+                let mut response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+                if !echoed {
+                    // BADCOOKIE(23)：头部保留低4位，高位放在OPT记录中
+                    response.answers.clear();
+                    response.flags.rcode = (BADCOOKIE & 0x0F) as u8;
+                }
+                let mut bytes = UdpTransport::serialize_response(&response).unwrap();
+                bytes[11] += 1;
+                UdpTransport::encode_opt_record(&mut bytes, &EdnsRecord {
+                    udp_payload_size: UDP_EDNS_PAYLOAD_SIZE,
+                    extended_rcode: if echoed { 0 } else { (BADCOOKIE >> 4) as u8 },
+                    version: 0,
+                    dnssec_ok: false,
+                    options: vec![EdnsOption {
+                        code: edns_option_codes::COOKIE,
+                        data: [&cookie[..CLIENT_COOKIE_LEN], server_cookie.as_slice()].concat(),
+                    }],
+                });
+                upstream.send_to(&bytes, peer).await.unwrap();
+            }
+        });
+        (port, requests)
+    }
+
+    fn cookie_transport(port: u16, jar: &Arc<DnsCookieJar>) -> UdpTransport {
+        UdpTransport::new(TransportConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            timeout: Duration::from_secs(2),
+            tcp_fast_open: false,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size: 4096,
+            record_limits: None,
+        }).with_cookies(jar.clone())
+    }
+
+    fn a_request(id: u16) -> Request {
+        Request {
+            id,
+            flags: Flags::default(),
+            query: Query {
+                name: "example.com".to_string(),
+                qtype: RecordType::A,
+                qclass: QClass::IN,
+            },
+            client_address: None,
+            enable_edns: false,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: QueryPriority::Normal,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_badcookie_is_retried_with_server_cookie() {
+        let jar = Arc::new(DnsCookieJar::new(Duration::from_secs(3600)));
+        let (first_port, first_requests) = cookie_enforcing_upstream([0xA1; 8]).await;
+        let (second_port, second_requests) = cookie_enforcing_upstream([0xB2; 8]).await;
+        let first = cookie_transport(first_port, &jar);
+        let second = cookie_transport(second_port, &jar);
+
+        // 第一次只有客户端Cookie，收到BADCOOKIE后带上服务器Cookie重试
+        let response = first.send(&a_request(1)).await.unwrap();
+        assert_eq!(response.answers[0].data, RecordData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(first_requests.load(Ordering::SeqCst), 2);
+
+        // 之后的查询直接回显缓存的服务器Cookie
+        first.send(&a_request(2)).await.unwrap();
+        assert_eq!(first_requests.load(Ordering::SeqCst), 3);
+
+        // Cookie按服务器分别缓存
+        second.send(&a_request(3)).await.unwrap();
+        assert_eq!(second_requests.load(Ordering::SeqCst), 2);
+        let first_addr = format!("127.0.0.1:{}", first_port);
+        let second_addr = format!("127.0.0.1:{}", second_port);
+        assert_eq!(jar.server_cookie(&first_addr), Some(vec![0xA1; 8]));
+        assert_eq!(jar.server_cookie(&second_addr), Some(vec![0xB2; 8]));
+        assert_ne!(jar.client_cookie(&first_addr), jar.client_cookie(&second_addr));
+    }
+
+    #[tokio::test]
+    async fn test_datagram_with_wrong_id_is_ignored() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, peer) = upstream.recv_from(&mut buf).await.unwrap();
+            let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+            // 先送出一个ID错误的伪造响应，再送出真正的响应
+            for (id, address) in [(request.id.wrapping_add(1), Ipv4Addr::new(203, 0, 113, 66)), (request.id, Ipv4Addr::new(192, 0, 2, 1))] {
+                let response = DnsResponseWrapper::create_a_response(id, &request.query.name, &[address], 300);
+                let bytes = UdpTransport::serialize_response(&response).unwrap();
+                upstream.send_to(&bytes, peer).await.unwrap();
+            }
+        });
+
+        let transport = UdpTransport::new(TransportConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            timeout: Duration::from_secs(2),
+            tcp_fast_open: false,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size: 4096,
+            record_limits: None,
+        });
+        let request = Request {
+            id: 0x2468,
+            flags: Flags::default(),
+            query: Query {
+                name: "example.com".to_string(),
+                qtype: RecordType::A,
+                qclass: QClass::IN,
+            },
+            client_address: None,
+            enable_edns: false,
+            dnssec_ok: false,
+            wire_capture_limit: None,
+            priority: QueryPriority::Normal,
+        };
+        let response = transport.send(&request).await.unwrap();
+        assert_eq!(response.id, 0x2468);
+        assert_eq!(response.answers[0].data, RecordData::A(Ipv4Addr::new(192, 0, 2, 1)));
+    }
+
+    #[tokio::test]
+    async fn test_datagram_from_other_source_is_ignored() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let spoofer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, peer) = upstream.recv_from(&mut buf).await.unwrap();
+            let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+            // 另一个套接字先送出ID相符的伪造响应，上游再送出真正的响应
+            let forged = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(203, 0, 113, 66)], 300);
+            spoofer.send_to(&UdpTransport::serialize_response(&forged).unwrap(), peer).await.unwrap();
+            let genuine = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+            upstream.send_to(&UdpTransport::serialize_response(&genuine).unwrap(), peer).await.unwrap();
+        });
+
+        let transport = UdpTransport::new(TransportConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            timeout: Duration::from_secs(2),
+            tcp_fast_open: false,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size: 4096,
+            record_limits: None,
+        });
+        let (response, peer) = transport.send_with_peer(&a_request(0x1357)).await.unwrap();
+        assert_eq!(response.id, 0x1357);
+        assert_eq!(response.answers[0].data, RecordData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(peer, Some(SocketAddr::from(([127, 0, 0, 1], port))));
+    }
+
+    #[tokio::test]
+    async fn test_captured_wire_matches_upstream_bytes() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, peer) = upstream.recv_from(&mut buf).await.unwrap();
+            let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+            let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+            let bytes = UdpTransport::serialize_response(&response).unwrap();
+            upstream.send_to(&bytes, peer).await.unwrap();
+            (buf[..len].to_vec(), bytes)
+        });
+
+        let transport = UdpTransport::new(TransportConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            timeout: Duration::from_secs(2),
+            tcp_fast_open: false,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size: 4096,
+            record_limits: None,
+        });
+        let request = Request {
+            id: 0x1357,
+            flags: Flags::default(),
+            query: Query {
+                name: "example.com".to_string(),
+                qtype: RecordType::A,
+                qclass: QClass::IN,
+            },
+            client_address: None,
+            enable_edns: true,
+            dnssec_ok: false,
+            wire_capture_limit: Some(usize::from(u16::MAX)),
+            priority: QueryPriority::Normal,
+        };
+        let (_, _, wire) = transport.send_captured(&request, usize::from(u16::MAX)).await.unwrap();
+        let (received, sent) = server.await.unwrap();
+        assert_eq!(wire.request, received);
+        assert_eq!(wire.response, sent);
+        assert!(!wire.truncated);
+    }
+
+    #[tokio::test]
+    async fn test_peer_is_the_answering_socket() {
+        let mut transports = Vec::new();
+        for _ in 0..2 {
+            let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let address = upstream.local_addr().unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 512];
+                let (len, peer) = upstream.recv_from(&mut buf).await.unwrap();
+                let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+                let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+                upstream.send_to(&UdpTransport::serialize_response(&response).unwrap(), peer).await.unwrap();
+            });
+            transports.push((address, UdpTransport::new(TransportConfig {
+                server: "127.0.0.1".to_string(),
+                port: address.port(),
+                timeout: Duration::from_secs(2),
+                tcp_fast_open: false,
+                tcp_nodelay: true,
+                pool_size: 1,
+                buffer_size: 4096,
+                record_limits: None,
+            })));
+        }
+
+        for (address, transport) in transports {
+            let (_, peer) = transport.send_with_peer(&a_request(7)).await.unwrap();
+            assert_eq!(peer, Some(address));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hostname_is_re_resolved_when_upstream_moves() {
+        use crate::transport::mock::MockBootstrap;
+        use crate::transport::HostResolution;
+        use std::net::IpAddr;
+        
+        // 两个地址上同一端口的上游，第一个只应答一次后下线
+        let first = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = first.local_addr().unwrap().port();
+        let second = UdpSocket::bind(("127.0.0.2", port)).await.unwrap();
+        for (upstream, count) in [(first, 1), (second, usize::MAX)] {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 512];
+                for _ in 0..count {
+                    let (len, peer) = upstream.recv_from(&mut buf).await.unwrap();
+                    let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+                    let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+                    upstream.send_to(&UdpTransport::serialize_response(&response).unwrap(), peer).await.unwrap();
+                }
+            });
+        }
+        
+        let bootstrap = MockBootstrap::new().with_host("dns.corp.internal", &[IpAddr::from([127, 0, 0, 1])]);
+        let transport = UdpTransport::new(TransportConfig {
+            server: "dns.corp.internal".to_string(),
+            port,
+            timeout: Duration::from_millis(200),
+            tcp_fast_open: false,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size: 4096,
+            record_limits: None,
+        }).with_host_resolution(HostResolution {
+            resolver: Some(Arc::new(bootstrap.clone())),
+            ..HostResolution::default()
+        });
+        
+        transport.prepare().await.unwrap();
+        let (_, peer) = transport.send_with_peer(&a_request(1)).await.unwrap();
+        assert_eq!(peer, Some(SocketAddr::from(([127, 0, 0, 1], port))));
+        assert_eq!(bootstrap.call_count(), 1);
+        
+        // 上游换了地址：旧地址连续3次失败后重新解析
+        bootstrap.set_host("dns.corp.internal", &[IpAddr::from([127, 0, 0, 2])]);
+        for id in 2..5 {
+            let error = transport.send(&a_request(id)).await.unwrap_err();
+            assert!(error.network_kind().is_some(), "{:?}", error);
+        }
+        let (_, peer) = transport.send_with_peer(&a_request(5)).await.unwrap();
+        assert_eq!(peer, Some(SocketAddr::from(([127, 0, 0, 2], port))));
+        assert_eq!(bootstrap.call_count(), 2);
+    }
+    
+    #[tokio::test]
+    async fn test_silent_upstream_times_out_once() {
+        // 上游收下请求但从不回应：整个发送在 `timeout` 内结束，不是发送和接收各等一次
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = silent.local_addr().unwrap().port();
+        let transport = UdpTransport::new(TransportConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            timeout: Duration::from_millis(300),
+            tcp_fast_open: false,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size: 4096,
+            record_limits: None,
+        });
+        
+        let start = Instant::now();
+        let error = transport.send(&a_request(1)).await.unwrap_err();
+        let elapsed = start.elapsed();
+        assert!(matches!(error, DnsError::Timeout { phase: Some(TransportPhase::FirstByte) }), "{:?}", error);
+        assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_millis(550), "{:?}", elapsed);
+        drop(silent);
+    }
+    
+    #[tokio::test]
+    async fn test_timeout_follows_injected_clock() {
+        use crate::resolver::clock::TestClock;
+        
+        // 超时由注入的时钟决定：真实时间过去多久都不超时，时钟推进到超时时间才结束
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = silent.local_addr().unwrap().port();
+        let clock = Arc::new(TestClock::new());
+        let transport = UdpTransport::new(TransportConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            timeout: Duration::from_secs(30),
+            tcp_fast_open: false,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size: 4096,
+            record_limits: None,
+        }).with_clock(clock.clone());
+        
+        let send = tokio::spawn(async move { transport.send_timed(&a_request(1)).await });
+        let mut buffer = [0u8; 512];
+        silent.recv_from(&mut buffer).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        clock.advance(Duration::from_secs(29));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!send.is_finished());
+        
+        clock.advance(Duration::from_secs(1));
+        let error = send.await.unwrap().unwrap_err();
+        assert!(matches!(error, DnsError::Timeout { phase: Some(TransportPhase::FirstByte) }), "{:?}", error);
+    }
+    
+    #[tokio::test]
+    async fn test_buffer_size_bounds_accepted_response() {
+        // 上游不理会请求声明的载荷大小，总是返回约2000字节的应答，并记下声明的载荷大小
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        let advertised = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = advertised.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (len, peer) = upstream.recv_from(&mut buf).await.unwrap();
+                let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+                let edns = UdpTransport::parse_edns(&buf[..len]).unwrap().unwrap();
+                recorded.lock().unwrap().push(edns.udp_payload_size);
+                let addresses: Vec<Ipv4Addr> = (0..75).map(|i| Ipv4Addr::new(192, 0, 2, i)).collect();
+                let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &addresses, 300);
+                let bytes = UdpTransport::serialize_response(&response).unwrap();
+                assert!((1500..4096).contains(&bytes.len()), "{}", bytes.len());
+                upstream.send_to(&bytes, peer).await.unwrap();
+            }
+        });
+        let transport = |buffer_size| UdpTransport::new(TransportConfig {
+            server: "127.0.0.1".to_string(),
+            port,
+            timeout: Duration::from_secs(2),
+            tcp_fast_open: false,
+            tcp_nodelay: true,
+            pool_size: 1,
+            buffer_size,
+            record_limits: None,
+        });
+        let request = Request { enable_edns: true, ..a_request(9) };
+
+        let response = transport(4096).send(&request).await.unwrap();
+        assert_eq!(response.answers.len(), 75);
+
+        match transport(512).send(&request).await {
+            Err(DnsError::Protocol(message)) => assert!(message.contains("512"), "{}", message),
+            other => panic!("expected the oversized response to be rejected, got {:?}", other),
+        }
+        assert_eq!(*advertised.lock().unwrap(), vec![4096, 512]);
+    }
+}
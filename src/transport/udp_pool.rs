@@ -0,0 +1,481 @@
+//! UDP源端口池
+//!
+//! 每次查询都绑定一个新套接字时，高QPS下关闭的套接字来不及释放端口，临时端口范围会被耗尽（绑定报EADDRINUSE）。
+//! UDP传输因此持有一个套接字池：构造时预先绑定 `size` 个套接字，每次查询独占借出一个，查询结束后归还。
+//! 套接字使用到一定次数或时长后关闭、下次借出时重新绑定，源端口仍然不断变化，保留随机源端口对伪造响应的防护。
+
+use crate::runtime;
+use crate::{DnsError, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use crate::time::Instant;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use tokio::net::UdpSocket;
+#[cfg(target_arch = "wasm32")]
+pub(crate) use unsupported::UdpSocket;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// 一个套接字默认最多承载的查询数，之后换新的源端口
+pub const DEFAULT_MAX_SOCKET_USES: u32 = 256;
+
+/// 一个套接字默认最长使用时间，之后换新的源端口
+pub const DEFAULT_MAX_SOCKET_AGE: Duration = Duration::from_secs(60);
+
+/// 池中的套接字全部借出时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PoolExhaustion {
+    /// 临时绑定一个额外的套接字，查询结束后关闭；并发不受池大小限制
+    #[default]
+    ExtraSocket,
+    /// 最多等待 `timeout` 让其他查询归还套接字，超时后查询以 [`DnsError::ServiceUnavailable`] 失败
+    Wait {
+        /// 最长等待时间
+        timeout: Duration,
+    },
+}
+
+/// UDP套接字池配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdpPoolConfig {
+    /// 池中的套接字数，即同时在用的源端口数上限（额外套接字除外）；0表示每次查询单独绑定
+    pub size: usize,
+    /// 绑定的本地地址，`None` 为与上游同地址族的通配地址
+    pub bind_address: Option<IpAddr>,
+    /// 一个套接字最多承载的查询数
+    pub max_uses: u32,
+    /// 一个套接字的最长使用时间
+    pub max_age: Duration,
+    /// 全部借出时的处理方式
+    pub exhaustion: PoolExhaustion,
+}
+
+impl UdpPoolConfig {
+    /// 创建指定大小的套接字池配置，其余为默认值
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            bind_address: None,
+            max_uses: DEFAULT_MAX_SOCKET_USES,
+            max_age: DEFAULT_MAX_SOCKET_AGE,
+            exhaustion: PoolExhaustion::default(),
+        }
+    }
+
+    /// 绑定到指定的本地地址
+    pub fn with_bind_address(mut self, address: IpAddr) -> Self {
+        self.bind_address = Some(address);
+        self
+    }
+
+    /// 套接字承载 `max_uses` 次查询或使用 `max_age` 后换新的源端口
+    pub fn with_rotation(mut self, max_uses: u32, max_age: Duration) -> Self {
+        self.max_uses = max_uses;
+        self.max_age = max_age;
+        self
+    }
+
+    /// 设置全部借出时的处理方式
+    pub fn with_exhaustion(mut self, exhaustion: PoolExhaustion) -> Self {
+        self.exhaustion = exhaustion;
+        self
+    }
+
+    /// 验证配置
+    pub fn validate(&self) -> Result<()> {
+        if self.max_uses == 0 {
+            return Err(DnsError::InvalidConfig("UDP socket pool max_uses must be greater than zero".to_string()));
+        }
+        if self.max_age.is_zero() {
+            return Err(DnsError::InvalidConfig("UDP socket pool max_age must be greater than zero".to_string()));
+        }
+        if let PoolExhaustion::Wait { timeout } = self.exhaustion {
+            if self.size == 0 {
+                return Err(DnsError::InvalidConfig(
+                    "UDP socket pool with size 0 cannot wait for a free socket; use PoolExhaustion::ExtraSocket".to_string(),
+                ));
+            }
+            if timeout.is_zero() {
+                return Err(DnsError::InvalidConfig("UDP socket pool wait timeout must be greater than zero".to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// UDP套接字池的使用情况
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UdpPoolStats {
+    /// 池的大小
+    pub capacity: usize,
+    /// 已绑定、等待借出的套接字数
+    pub idle: usize,
+    /// 正在使用的套接字数（包括额外套接字）
+    pub in_use: usize,
+    /// 同时使用的套接字数峰值
+    pub peak_in_use: usize,
+    /// 借出次数
+    pub checkouts: u64,
+    /// 因全部借出而等待的次数
+    pub waits: u64,
+    /// 等待超时、查询失败的次数
+    pub exhausted: u64,
+    /// 全部借出时临时绑定的额外套接字数
+    pub extra_sockets: u64,
+    /// 达到使用次数或时长上限而关闭的套接字数
+    pub rotations: u64,
+    /// 绑定套接字的总次数（包括预先绑定和额外套接字）
+    pub binds: u64,
+}
+
+/// 池中的一个套接字
+#[derive(Debug)]
+struct PooledSocket {
+    socket: SocketState,
+    bound_at: Instant,
+    uses: u32,
+    ipv6: bool,
+}
+
+/// 预先绑定的套接字在第一次借出（此时一定在运行时中）时才注册到tokio
+#[derive(Debug)]
+enum SocketState {
+    Bound(std::net::UdpSocket),
+    Registered(UdpSocket),
+}
+
+impl PooledSocket {
+    fn expired(&self, config: &UdpPoolConfig) -> bool {
+        self.uses >= config.max_uses || self.bound_at.elapsed() >= config.max_age
+    }
+}
+
+/// 使用计数
+#[derive(Debug, Default)]
+struct PoolCounters {
+    in_use: AtomicUsize,
+    peak_in_use: AtomicUsize,
+    checkouts: AtomicU64,
+    waits: AtomicU64,
+    exhausted: AtomicU64,
+    extra_sockets: AtomicU64,
+    rotations: AtomicU64,
+    binds: AtomicU64,
+}
+
+/// 一个UDP传输的套接字池
+#[derive(Debug)]
+pub(crate) struct UdpSocketPool {
+    config: UdpPoolConfig,
+    idle: Mutex<Vec<PooledSocket>>,
+    permits: Semaphore,
+    counters: PoolCounters,
+}
+
+impl UdpSocketPool {
+    /// 创建套接字池；上游地址族已知（以IP配置）时立即绑定全部套接字，否则在借出时绑定
+    pub(crate) fn new(config: UdpPoolConfig, upstream: Option<IpAddr>) -> Self {
+        let pool = Self {
+            permits: Semaphore::new(config.size),
+            idle: Mutex::new(Vec::with_capacity(config.size)),
+            counters: PoolCounters::default(),
+            config,
+        };
+        if let Some(upstream) = upstream {
+            let mut idle = pool.lock();
+            for _ in 0..pool.config.size {
+                match pool.bind(upstream.is_ipv6()) {
+                    Ok(socket) => idle.push(socket),
+                    Err(e) => {
+                        crate::dns_warn!("预先绑定UDP套接字失败，改为借出时绑定: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+        pool
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<PooledSocket>> {
+        self.idle.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 绑定一个新套接字
+    fn bind(&self, ipv6: bool) -> Result<PooledSocket> {
+        let address = match self.config.bind_address {
+            Some(address) if address.is_ipv6() != ipv6 => {
+                return Err(DnsError::InvalidConfig(format!(
+                    "UDP bind address {} cannot reach an {} upstream",
+                    address,
+                    if ipv6 { "IPv6" } else { "IPv4" }
+                )));
+            }
+            Some(address) => address,
+            None if ipv6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        };
+        let socket = std::net::UdpSocket::bind(SocketAddr::new(address, 0))
+            .and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+            .map_err(|e| DnsError::network_io(address.to_string(), "UDP socket 绑定失败", &e))?;
+        self.counters.binds.fetch_add(1, Ordering::Relaxed);
+        Ok(PooledSocket { socket: SocketState::Bound(socket), bound_at: Instant::now(), uses: 0, ipv6 })
+    }
+
+    /// 借出一个能发往 `target` 的套接字，在返回的租约被丢弃时归还
+    pub(crate) async fn checkout(&self, target: SocketAddr) -> Result<SocketLease<'_>> {
+        self.counters.checkouts.fetch_add(1, Ordering::Relaxed);
+        let permit = match self.permits.try_acquire() {
+            Ok(permit) => Some(permit),
+            Err(_) => match self.config.exhaustion {
+                PoolExhaustion::ExtraSocket => None,
+                PoolExhaustion::Wait { timeout } => {
+                    self.counters.waits.fetch_add(1, Ordering::Relaxed);
+                    match runtime::timeout(timeout, self.permits.acquire()).await {
+                        Ok(Ok(permit)) => Some(permit),
+                        _ => {
+                            self.counters.exhausted.fetch_add(1, Ordering::Relaxed);
+                            return Err(DnsError::ServiceUnavailable(format!(
+                                "all {} UDP sockets for {} stayed in use for {:?}",
+                                self.config.size, target, timeout
+                            )));
+                        }
+                    }
+                }
+            },
+        };
+
+        let reusable = match permit {
+            Some(_) => self.take_idle(target.is_ipv6()),
+            None => {
+                self.counters.extra_sockets.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        };
+        let mut pooled = match reusable {
+            Some(pooled) => pooled,
+            None => self.bind(target.is_ipv6())?,
+        };
+        if let SocketState::Bound(socket) = pooled.socket {
+            let socket = UdpSocket::from_std(socket)
+                .map_err(|e| DnsError::network_io(target.to_string(), "UDP socket 注册失败", &e))?;
+            pooled.socket = SocketState::Registered(socket);
+        }
+
+        let in_use = self.counters.in_use.fetch_add(1, Ordering::Relaxed) + 1;
+        self.counters.peak_in_use.fetch_max(in_use, Ordering::Relaxed);
+        let lease = SocketLease { pool: self, socket: Some(pooled), _permit: permit };
+        lease.discard_stale();
+        Ok(lease)
+    }
+
+    /// 取一个同地址族、未过期的空闲套接字；过期的关闭，地址族不符的（上游地址变了）直接丢弃
+    fn take_idle(&self, ipv6: bool) -> Option<PooledSocket> {
+        let mut idle = self.lock();
+        while let Some(pooled) = idle.pop() {
+            if pooled.ipv6 != ipv6 {
+                continue;
+            }
+            if pooled.expired(&self.config) {
+                self.counters.rotations.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            return Some(pooled);
+        }
+        None
+    }
+
+    /// 归还套接字：额外套接字和到期的套接字关闭，其余放回池中
+    fn release(&self, mut pooled: PooledSocket, pooled_slot: bool) {
+        self.counters.in_use.fetch_sub(1, Ordering::Relaxed);
+        if !pooled_slot {
+            return;
+        }
+        pooled.uses += 1;
+        if pooled.expired(&self.config) {
+            self.counters.rotations.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.lock().push(pooled);
+    }
+
+    /// 当前使用情况
+    pub(crate) fn stats(&self) -> UdpPoolStats {
+        UdpPoolStats {
+            capacity: self.config.size,
+            idle: self.lock().len(),
+            in_use: self.counters.in_use.load(Ordering::Relaxed),
+            peak_in_use: self.counters.peak_in_use.load(Ordering::Relaxed),
+            checkouts: self.counters.checkouts.load(Ordering::Relaxed),
+            waits: self.counters.waits.load(Ordering::Relaxed),
+            exhausted: self.counters.exhausted.load(Ordering::Relaxed),
+            extra_sockets: self.counters.extra_sockets.load(Ordering::Relaxed),
+            rotations: self.counters.rotations.load(Ordering::Relaxed),
+            binds: self.counters.binds.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 借出的套接字，丢弃时归还到池中
+#[derive(Debug)]
+pub(crate) struct SocketLease<'a> {
+    pool: &'a UdpSocketPool,
+    socket: Option<PooledSocket>,
+    /// 占用的池名额，额外套接字为 `None`
+    _permit: Option<SemaphorePermit<'a>>,
+}
+
+impl SocketLease<'_> {
+    pub(crate) fn socket(&self) -> &UdpSocket {
+        match self.socket.as_ref().map(|pooled| &pooled.socket) {
+            Some(SocketState::Registered(socket)) => socket,
+            _ => unreachable!("leased sockets are registered at checkout"),
+        }
+    }
+
+    /// 丢弃之前的查询留下的迟到响应，免得占用接收缓冲区；残留的数据报即使漏掉也会因ID不符被丢弃
+    fn discard_stale(&self) {
+        let mut buffer = [0u8; 512];
+        while self.socket().try_recv_from(&mut buffer).is_ok() {}
+    }
+}
+
+impl Drop for SocketLease<'_> {
+    fn drop(&mut self) {
+        if let Some(pooled) = self.socket.take() {
+            self.pool.release(pooled, self._permit.is_some());
+        }
+    }
+}
+
+/// wasm32-unknown-unknown 上没有tokio的套接字，UDP传输照常编译，发送时以不支持的错误失败
+#[cfg(target_arch = "wasm32")]
+mod unsupported {
+    use std::io;
+    use std::net::SocketAddr;
+
+    #[derive(Debug)]
+    pub(crate) struct UdpSocket(std::net::UdpSocket);
+
+    impl UdpSocket {
+        pub(crate) fn from_std(socket: std::net::UdpSocket) -> io::Result<Self> {
+            Ok(Self(socket))
+        }
+
+        pub(crate) async fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+            self.0.send_to(buf, target)
+        }
+
+        pub(crate) async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            self.0.recv_from(buf)
+        }
+
+        pub(crate) fn try_recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            self.0.recv_from(buf)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loopback() -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53)
+    }
+
+    fn local_port(lease: &SocketLease<'_>) -> u16 {
+        lease.socket().local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn test_sockets_are_prebound_and_reused() {
+        let pool = UdpSocketPool::new(UdpPoolConfig::new(2), Some(loopback().ip()));
+        assert_eq!(pool.stats().binds, 2);
+        assert_eq!(pool.stats().idle, 2);
+
+        let first = pool.checkout(loopback()).await.unwrap();
+        let port = local_port(&first);
+        assert_eq!(pool.stats().in_use, 1);
+        drop(first);
+
+        let again = pool.checkout(loopback()).await.unwrap();
+        assert_eq!(local_port(&again), port);
+        drop(again);
+
+        let stats = pool.stats();
+        assert_eq!((stats.binds, stats.checkouts, stats.in_use, stats.idle), (2, 2, 0, 2));
+    }
+
+    #[tokio::test]
+    async fn test_socket_rotates_after_max_uses() {
+        let config = UdpPoolConfig::new(1).with_rotation(2, Duration::from_secs(60));
+        let pool = UdpSocketPool::new(config, Some(loopback().ip()));
+
+        let first = local_port(&pool.checkout(loopback()).await.unwrap());
+        assert_eq!(local_port(&pool.checkout(loopback()).await.unwrap()), first);
+        // 第二次归还后达到上限，下一次借出绑定新套接字
+        let lease = pool.checkout(loopback()).await.unwrap();
+        assert_eq!(pool.stats().rotations, 1);
+        assert_eq!(pool.stats().binds, 2);
+        drop(lease);
+    }
+
+    #[tokio::test]
+    async fn test_exhaustion_extra_socket_is_closed_after_use() {
+        let pool = UdpSocketPool::new(UdpPoolConfig::new(1), Some(loopback().ip()));
+        let held = pool.checkout(loopback()).await.unwrap();
+        let extra = pool.checkout(loopback()).await.unwrap();
+        assert_ne!(local_port(&extra), local_port(&held));
+        assert_eq!(pool.stats().peak_in_use, 2);
+        drop(extra);
+        drop(held);
+
+        let stats = pool.stats();
+        assert_eq!((stats.extra_sockets, stats.idle, stats.in_use), (1, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_exhaustion_wait_times_out_then_succeeds_after_release() {
+        let config = UdpPoolConfig::new(1)
+            .with_exhaustion(PoolExhaustion::Wait { timeout: Duration::from_millis(50) });
+        let pool = UdpSocketPool::new(config, Some(loopback().ip()));
+        let held = pool.checkout(loopback()).await.unwrap();
+
+        let err = pool.checkout(loopback()).await.unwrap_err();
+        assert!(matches!(err, DnsError::ServiceUnavailable(_)), "{:?}", err);
+
+        let (waiting, _) = tokio::join!(pool.checkout(loopback()), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(held);
+        });
+        assert!(waiting.is_ok());
+
+        let stats = pool.stats();
+        assert_eq!((stats.waits, stats.exhausted, stats.extra_sockets, stats.binds), (2, 1, 0, 1));
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(UdpPoolConfig::new(4).validate().is_ok());
+        assert!(UdpPoolConfig::new(0).validate().is_ok());
+        assert!(UdpPoolConfig::new(4).with_rotation(0, DEFAULT_MAX_SOCKET_AGE).validate().is_err());
+        assert!(UdpPoolConfig::new(4).with_rotation(1, Duration::ZERO).validate().is_err());
+        let wait = PoolExhaustion::Wait { timeout: Duration::from_secs(1) };
+        assert!(UdpPoolConfig::new(0).with_exhaustion(wait).validate().is_err());
+        assert!(UdpPoolConfig::new(4).with_exhaustion(PoolExhaustion::Wait { timeout: Duration::ZERO }).validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_address_family_must_match_upstream() {
+        let config = UdpPoolConfig::new(1).with_bind_address(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let pool = UdpSocketPool::new(config, None);
+        let lease = pool.checkout(loopback()).await.unwrap();
+        assert_eq!(lease.socket().local_addr().unwrap().ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        drop(lease);
+
+        let v6 = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 53);
+        assert!(matches!(pool.checkout(v6).await, Err(DnsError::InvalidConfig(_))));
+    }
+}
@@ -0,0 +1,562 @@
+//! 以主机名配置的上游地址
+//!
+//! UDP/TCP/DoT上游可以写成 `dns.corp.internal:53`：构建解析器时（或第一次发送前）通过引导解析器解析主机名，
+//! 结果缓存到刷新间隔到期；连续多次网络失败时提前重新解析，上游可能已经换了地址。
+//! 以IP地址配置的上游不经过解析。
+//!
+//! 主机名同时解析出IPv4和IPv6地址时按 [`AfPreference`] 选择连接的地址族，偏好的地址族连接失败时改用另一个，
+//! 两个地址族的连接结果分别统计（[`AddressFamilyStats`]）：IPv6路径不通而IPv4正常时，上游整体仍然可用。
+
+use crate::{DnsError, Result};
+use super::timing::TransportPhase;
+use crate::{dns_debug, dns_warn};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::time::Instant;
+use crate::runtime::timeout;
+
+/// 引导解析器：把上游的主机名解析为IP地址
+///
+/// 解析上游自身的名称不能依赖这些上游，通常是一个只以IP地址配置上游的解析器
+/// （[`CoreResolver`](crate::CoreResolver) 实现了该trait）或企业内网的固定映射
+#[async_trait]
+pub trait BootstrapResolver: Send + Sync + Debug {
+    /// 解析主机名，返回的地址按优先顺序排列
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>>;
+}
+
+/// 使用操作系统解析器（`getaddrinfo`）的引导解析器
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemBootstrap;
+
+#[async_trait]
+impl BootstrapResolver for SystemBootstrap {
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        let addrs = tokio::net::lookup_host((host, 0)).await
+            .map_err(|e| DnsError::network_io(host, "Failed to resolve", &e))?;
+        Ok(addrs.map(|addr| addr.ip()).collect())
+    }
+
+    /// wasm32上没有系统解析器，以IP配置上游或换用其他引导解析器
+    #[cfg(target_arch = "wasm32")]
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        Err(DnsError::InvalidConfig(format!(
+            "Cannot resolve upstream host {}: the system resolver is not available on wasm32", host
+        )))
+    }
+}
+
+/// 连接失败的地址族在这段时间内排到另一个地址族之后，到期后重新按偏好尝试
+const FAMILY_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// IP地址族
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum AddressFamily {
+    /// IPv4
+    V4,
+    /// IPv6
+    V6,
+}
+
+impl AddressFamily {
+    /// 地址所属的地址族
+    pub fn of(ip: IpAddr) -> Self {
+        if ip.is_ipv4() { Self::V4 } else { Self::V6 }
+    }
+}
+
+/// 以主机名配置的上游连接哪个地址族
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AfPreference {
+    /// 只连接IPv4地址
+    V4Only,
+    /// 只连接IPv6地址
+    V6Only,
+    /// 先连接IPv4地址，失败时改用IPv6
+    PreferV4,
+    /// 先连接IPv6地址，失败时改用IPv4
+    PreferV6,
+}
+
+impl AfPreference {
+    /// 是否允许连接该地址族
+    pub fn allows(self, family: AddressFamily) -> bool {
+        match self {
+            Self::V4Only => family == AddressFamily::V4,
+            Self::V6Only => family == AddressFamily::V6,
+            Self::PreferV4 | Self::PreferV6 => true,
+        }
+    }
+
+    /// 优先连接的地址族
+    pub fn preferred(self) -> AddressFamily {
+        match self {
+            Self::V4Only | Self::PreferV4 => AddressFamily::V4,
+            Self::V6Only | Self::PreferV6 => AddressFamily::V6,
+        }
+    }
+}
+
+/// 单个地址族的连接统计
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FamilyPathStats {
+    /// 连接（UDP为收到应答）成功次数
+    pub successes: u64,
+    /// 连接失败（含超时）次数
+    pub failures: u64,
+    /// 连续失败次数
+    pub consecutive_failures: u32,
+    /// 是否可以按偏好使用；最近失败过的地址族暂时排到另一个地址族之后
+    pub healthy: bool,
+}
+
+impl FamilyPathStats {
+    /// 成功率，还没有连接过时为 `None`
+    pub fn success_rate(&self) -> Option<f64> {
+        let total = self.successes + self.failures;
+        (total > 0).then(|| self.successes as f64 / total as f64)
+    }
+}
+
+/// 上游按地址族分开的连接统计
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AddressFamilyStats {
+    /// 配置的地址族偏好，None为按解析结果的顺序
+    pub preference: Option<AfPreference>,
+    /// 最近一次成功连接使用的地址族
+    pub active: Option<AddressFamily>,
+    /// IPv4路径
+    pub v4: FamilyPathStats,
+    /// IPv6路径
+    pub v6: FamilyPathStats,
+}
+
+impl AddressFamilyStats {
+    /// 指定地址族的统计
+    pub fn family(&self, family: AddressFamily) -> &FamilyPathStats {
+        match family {
+            AddressFamily::V4 => &self.v4,
+            AddressFamily::V6 => &self.v6,
+        }
+    }
+}
+
+/// 单个地址族的连接结果
+#[derive(Debug, Default)]
+struct FamilyPath {
+    successes: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    failed_at: Option<Instant>,
+}
+
+impl FamilyPath {
+    fn is_down(&self) -> bool {
+        self.failed_at.is_some_and(|at| at.elapsed() < FAMILY_RETRY_INTERVAL)
+    }
+
+    fn snapshot(&self) -> FamilyPathStats {
+        FamilyPathStats {
+            successes: self.successes,
+            failures: self.failures,
+            consecutive_failures: self.consecutive_failures,
+            healthy: !self.is_down(),
+        }
+    }
+}
+
+/// 两个地址族的连接结果
+#[derive(Debug, Default)]
+struct FamilyPaths {
+    v4: FamilyPath,
+    v6: FamilyPath,
+    active: Option<AddressFamily>,
+}
+
+impl FamilyPaths {
+    fn path(&mut self, family: AddressFamily) -> &mut FamilyPath {
+        match family {
+            AddressFamily::V4 => &mut self.v4,
+            AddressFamily::V6 => &mut self.v6,
+        }
+    }
+}
+
+/// 上游主机名的解析方式
+#[derive(Debug, Clone)]
+pub struct HostResolution {
+    /// 引导解析器，None表示使用系统解析器
+    pub resolver: Option<Arc<dyn BootstrapResolver>>,
+    /// 引导解析器失败时是否改用系统解析器
+    pub system_fallback: bool,
+    /// 解析结果的有效期，到期后下一次发送前重新解析
+    pub refresh_interval: Duration,
+    /// 连续多少次网络失败（含超时）后提前重新解析
+    pub max_failures: u32,
+    /// 解析出两个地址族时连接哪个，None为按解析结果的顺序（以IP地址配置的上游不受影响）
+    pub family_preference: Option<AfPreference>,
+}
+
+impl Default for HostResolution {
+    fn default() -> Self {
+        Self {
+            resolver: None, // 与此前一致：主机名交给系统解析器
+            system_fallback: false, // 指定了引导解析器时不悄悄绕过它
+            refresh_interval: Duration::from_secs(300),
+            max_failures: 3,
+            family_preference: None, // 与此前一致：按引导解析器给出的顺序连接
+        }
+    }
+}
+
+/// 缓存的解析结果
+#[derive(Debug, Default)]
+struct ResolvedState {
+    addrs: Option<Arc<[SocketAddr]>>,
+    resolved_at: Option<Instant>,
+    failures: u32,
+}
+
+/// 上游的连接地址：IP地址直接使用，主机名按 [`HostResolution`] 解析并缓存
+#[derive(Debug)]
+pub struct UpstreamAddress {
+    host: String,
+    port: u16,
+    /// 以IP地址配置时的连接地址
+    literal: Option<Arc<[SocketAddr]>>,
+    resolution: HostResolution,
+    state: Mutex<ResolvedState>,
+    /// 同一时间只进行一次解析
+    resolving: tokio::sync::Mutex<()>,
+    /// 按地址族的连接结果
+    families: Mutex<FamilyPaths>,
+}
+
+impl UpstreamAddress {
+    /// 创建上游地址，`host` 为IP地址或主机名（IPv6地址不带方括号）
+    pub fn new(host: impl Into<String>, port: u16, resolution: HostResolution) -> Self {
+        let host = host.into();
+        let literal = host.parse::<IpAddr>().ok()
+            .map(|ip| Arc::from([SocketAddr::new(ip, port)]));
+        Self {
+            host,
+            port,
+            literal,
+            resolution,
+            state: Mutex::new(ResolvedState::default()),
+            resolving: tokio::sync::Mutex::new(()),
+            families: Mutex::new(FamilyPaths::default()),
+        }
+    }
+
+    /// 配置的主机名或IP地址
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// 端口
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// 主机名的解析方式
+    pub fn resolution(&self) -> &HostResolution {
+        &self.resolution
+    }
+
+    /// 是否以主机名配置（需要解析）
+    pub fn is_hostname(&self) -> bool {
+        self.literal.is_none()
+    }
+
+    /// 最近一次解析得到的地址，以IP地址配置时就是该地址，尚未解析时为 `None`
+    pub fn cached(&self) -> Option<Arc<[SocketAddr]>> {
+        self.literal.clone().or_else(|| self.lock_state().addrs.clone())
+    }
+
+    /// 连接地址：缓存未到期时直接返回，否则在 `limit` 内重新解析
+    ///
+    /// 重新解析失败而此前有解析结果时继续使用旧地址，只记录警告
+    pub async fn socket_addrs(&self, limit: Duration) -> Result<Arc<[SocketAddr]>> {
+        if let Some(literal) = &self.literal {
+            return Ok(literal.clone());
+        }
+        if let Some(addrs) = self.fresh() {
+            return Ok(addrs);
+        }
+
+        let _resolving = self.resolving.lock().await;
+        // 等待期间其他发送可能已经完成解析
+        if let Some(addrs) = self.fresh() {
+            return Ok(addrs);
+        }
+        match self.resolve(limit).await {
+            Ok(addrs) => Ok(addrs),
+            Err(e) => match self.lock_state().addrs.clone() {
+                Some(stale) => {
+                    dns_warn!("重新解析上游 {} 失败，继续使用上次的地址: {}", self.host, e);
+                    Ok(stale)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// 立即解析主机名并更新缓存（以IP地址配置时直接返回该地址）
+    pub async fn resolve(&self, limit: Duration) -> Result<Arc<[SocketAddr]>> {
+        if let Some(literal) = &self.literal {
+            return Ok(literal.clone());
+        }
+        let ips = match timeout(limit, self.lookup()).await {
+            Ok(result) => result?,
+            Err(_) => return Err(DnsError::Timeout { phase: Some(TransportPhase::UpstreamResolve) }),
+        };
+        if ips.is_empty() {
+            return Err(DnsError::network(self.endpoint(), "Upstream host name resolved to no addresses"));
+        }
+
+        let mut ips = ips;
+        if let Some(preference) = self.resolution.family_preference {
+            ips.retain(|ip| preference.allows(AddressFamily::of(*ip)));
+            if ips.is_empty() {
+                let family = match preference.preferred() {
+                    AddressFamily::V4 => "IPv4",
+                    AddressFamily::V6 => "IPv6",
+                };
+                return Err(DnsError::network(self.endpoint(), format!("Upstream host name resolved to no {} addresses", family)));
+            }
+            // 稳定排序，同一地址族内保持解析结果的顺序
+            ips.sort_by_key(|ip| AddressFamily::of(*ip) != preference.preferred());
+        }
+
+        let addrs: Arc<[SocketAddr]> = ips.into_iter().map(|ip| SocketAddr::new(ip, self.port)).collect();
+        dns_debug!("上游 {} 解析为 {:?}", self.host, addrs);
+        let mut state = self.lock_state();
+        state.addrs = Some(addrs.clone());
+        state.resolved_at = Some(Instant::now());
+        state.failures = 0;
+        Ok(addrs)
+    }
+
+    /// 按发送结果更新连续失败计数，网络错误（含超时）达到上限时让缓存的地址失效
+    pub fn observe<T>(&self, result: &Result<T>) {
+        if self.literal.is_some() {
+            return;
+        }
+        let mut state = self.lock_state();
+        match result {
+            Err(e) if e.network_kind().is_some() => {
+                state.failures += 1;
+                if state.failures >= self.resolution.max_failures.max(1) && state.resolved_at.is_some() {
+                    dns_debug!("上游 {} 连续 {} 次网络失败，下一次发送前重新解析", self.host, state.failures);
+                    state.resolved_at = None;
+                    state.failures = 0;
+                }
+            }
+            Err(_) => {}
+            Ok(_) => state.failures = 0,
+        }
+    }
+
+    /// 按连接顺序排列的地址：按地址族偏好排列，最近连接失败的地址族排到另一个地址族之后
+    pub async fn dial_addrs(&self, limit: Duration) -> Result<Vec<SocketAddr>> {
+        let mut addrs = self.socket_addrs(limit).await?.to_vec();
+        let (v4_down, v6_down) = {
+            let families = self.lock_families();
+            (families.v4.is_down(), families.v6.is_down())
+        };
+        if v4_down != v6_down {
+            addrs.sort_by_key(|addr| match AddressFamily::of(addr.ip()) {
+                AddressFamily::V4 => v4_down,
+                AddressFamily::V6 => v6_down,
+            });
+        }
+        Ok(addrs)
+    }
+
+    /// 记录一次向 `addr` 的连接结果（UDP为是否收到应答），计入该地址所属的地址族
+    pub fn record_family(&self, addr: SocketAddr, success: bool) {
+        let family = AddressFamily::of(addr.ip());
+        let mut families = self.lock_families();
+        let path = families.path(family);
+        if success {
+            path.successes += 1;
+            path.consecutive_failures = 0;
+            path.failed_at = None;
+            families.active = Some(family);
+        } else {
+            path.failures += 1;
+            path.consecutive_failures += 1;
+            path.failed_at = Some(Instant::now());
+        }
+    }
+
+    /// 按地址族分开的连接统计
+    pub fn family_stats(&self) -> AddressFamilyStats {
+        let families = self.lock_families();
+        AddressFamilyStats {
+            preference: self.resolution.family_preference,
+            active: families.active,
+            v4: families.v4.snapshot(),
+            v6: families.v6.snapshot(),
+        }
+    }
+
+    /// 按解析方式查询主机名
+    async fn lookup(&self) -> Result<Vec<IpAddr>> {
+        let Some(resolver) = &self.resolution.resolver else {
+            return SystemBootstrap.resolve(&self.host).await;
+        };
+        match resolver.resolve(&self.host).await {
+            Ok(ips) => Ok(ips),
+            Err(e) if self.resolution.system_fallback => {
+                dns_warn!("引导解析器解析 {} 失败，改用系统解析器: {}", self.host, e);
+                SystemBootstrap.resolve(&self.host).await
+            }
+            Err(e) => Err(DnsError::network(self.endpoint(), format!("Failed to resolve upstream host: {}", e))),
+        }
+    }
+
+    /// 未到刷新时间的缓存地址
+    fn fresh(&self) -> Option<Arc<[SocketAddr]>> {
+        let state = self.lock_state();
+        let resolved_at = state.resolved_at?;
+        if resolved_at.elapsed() < self.resolution.refresh_interval {
+            state.addrs.clone()
+        } else {
+            None
+        }
+    }
+
+    fn endpoint(&self) -> String {
+        super::host_port(&self.host, self.port)
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, ResolvedState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_families(&self) -> std::sync::MutexGuard<'_, FamilyPaths> {
+        self.families.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockBootstrap;
+
+    fn resolution(bootstrap: &MockBootstrap) -> HostResolution {
+        HostResolution { resolver: Some(Arc::new(bootstrap.clone())), ..HostResolution::default() }
+    }
+
+    const LIMIT: Duration = Duration::from_secs(1);
+
+    #[tokio::test]
+    async fn test_literal_address_is_not_resolved() {
+        let bootstrap = MockBootstrap::new();
+        let address = UpstreamAddress::new("::1", 5353, resolution(&bootstrap));
+        assert!(!address.is_hostname());
+        assert_eq!(&*address.socket_addrs(LIMIT).await.unwrap(), &["[::1]:5353".parse().unwrap()]);
+        assert_eq!(bootstrap.call_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_hostname_is_cached_and_re_resolved_after_failures() {
+        let bootstrap = MockBootstrap::new().with_host("dns.corp.internal", &[IpAddr::from([127, 0, 0, 1])]);
+        let address = UpstreamAddress::new("dns.corp.internal", 5353, resolution(&bootstrap));
+        assert!(address.is_hostname() && address.cached().is_none());
+
+        for _ in 0..3 {
+            let addrs = address.socket_addrs(LIMIT).await.unwrap();
+            assert_eq!(&*addrs, &["127.0.0.1:5353".parse().unwrap()]);
+        }
+        assert_eq!(bootstrap.call_count(), 1);
+
+        // 上游换了地址：连续失败达到上限后重新解析，非网络错误和成功会清零计数
+        bootstrap.set_host("dns.corp.internal", &[IpAddr::from([127, 0, 0, 2])]);
+        address.observe::<()>(&Err(DnsError::Timeout { phase: None }));
+        address.observe::<()>(&Err(DnsError::Timeout { phase: None }));
+        address.observe(&Ok(()));
+        address.observe::<()>(&Err(DnsError::Timeout { phase: None }));
+        address.observe::<()>(&Err(DnsError::NxDomain));
+        address.observe::<()>(&Err(DnsError::network("dns.corp.internal:5353", "refused")));
+        assert_eq!(address.socket_addrs(LIMIT).await.unwrap()[0].ip(), IpAddr::from([127, 0, 0, 1]));
+        address.observe::<()>(&Err(DnsError::Timeout { phase: None }));
+        assert_eq!(address.socket_addrs(LIMIT).await.unwrap()[0].ip(), IpAddr::from([127, 0, 0, 2]));
+        assert_eq!(bootstrap.call_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_keeps_stale_address_when_resolution_fails() {
+        let bootstrap = MockBootstrap::new().with_host("dns.corp.internal", &[IpAddr::from([127, 0, 0, 1])]);
+        let address = UpstreamAddress::new("dns.corp.internal", 53, HostResolution {
+            refresh_interval: Duration::ZERO,
+            ..resolution(&bootstrap)
+        });
+        address.socket_addrs(LIMIT).await.unwrap();
+
+        bootstrap.remove_host("dns.corp.internal");
+        let addrs = address.socket_addrs(LIMIT).await.unwrap();
+        assert_eq!(addrs[0].ip(), IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(bootstrap.call_count(), 2);
+
+        // 从未解析成功时返回错误
+        let missing = UpstreamAddress::new("missing.corp.internal", 53, resolution(&bootstrap));
+        assert!(matches!(missing.socket_addrs(LIMIT).await, Err(DnsError::Network { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_system_fallback_is_explicit() {
+        let bootstrap = MockBootstrap::new();
+        let strict = UpstreamAddress::new("localhost", 53, resolution(&bootstrap));
+        assert!(strict.socket_addrs(LIMIT).await.is_err());
+
+        let fallback = UpstreamAddress::new("localhost", 53, HostResolution {
+            system_fallback: true,
+            ..resolution(&bootstrap)
+        });
+        let addrs = fallback.socket_addrs(LIMIT).await.unwrap();
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback() && addr.port() == 53));
+    }
+
+    #[tokio::test]
+    async fn test_family_preference_orders_filters_and_demotes_failed_family() {
+        let v4 = IpAddr::from([127, 0, 0, 1]);
+        let v6 = IpAddr::from(std::net::Ipv6Addr::LOCALHOST);
+        let bootstrap = MockBootstrap::new().with_host("dual.corp.internal", &[v4, v6]);
+        let with_preference = |preference| UpstreamAddress::new("dual.corp.internal", 53, HostResolution {
+            family_preference: preference,
+            ..resolution(&bootstrap)
+        });
+        let ips = |addrs: Vec<SocketAddr>| addrs.into_iter().map(|addr| addr.ip()).collect::<Vec<_>>();
+
+        assert_eq!(ips(with_preference(None).dial_addrs(LIMIT).await.unwrap()), vec![v4, v6]);
+        assert_eq!(ips(with_preference(Some(AfPreference::V6Only)).dial_addrs(LIMIT).await.unwrap()), vec![v6]);
+
+        let address = with_preference(Some(AfPreference::PreferV6));
+        assert_eq!(ips(address.dial_addrs(LIMIT).await.unwrap()), vec![v6, v4]);
+        // IPv6连接失败后排到IPv4之后，IPv4的统计不受影响
+        address.record_family(SocketAddr::new(v6, 53), false);
+        address.record_family(SocketAddr::new(v4, 53), true);
+        assert_eq!(ips(address.dial_addrs(LIMIT).await.unwrap()), vec![v4, v6]);
+        let stats = address.family_stats();
+        assert_eq!(stats.active, Some(AddressFamily::V4));
+        assert!(!stats.v6.healthy && stats.v4.healthy);
+        assert_eq!((stats.v6.failures, stats.v6.consecutive_failures, stats.v4.successes), (1, 1, 1));
+        // IPv6恢复后回到偏好的顺序
+        address.record_family(SocketAddr::new(v6, 53), true);
+        assert_eq!(ips(address.dial_addrs(LIMIT).await.unwrap()), vec![v6, v4]);
+        assert_eq!(address.family_stats().active, Some(AddressFamily::V6));
+
+        // 只有另一个地址族的地址时解析失败
+        bootstrap.set_host("dual.corp.internal", &[v4]);
+        let v6_only = with_preference(Some(AfPreference::V6Only));
+        assert!(matches!(v6_only.socket_addrs(LIMIT).await, Err(DnsError::Network { .. })));
+    }
+}
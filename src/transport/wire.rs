@@ -0,0 +1,85 @@
+//! 原始报文保留
+//!
+//! 请求要求保留原始报文时，传输记下实际发出的DNS报文和解析前收到的DNS报文：
+//! TCP/DoT不含2字节长度前缀，DoH只保留DNS报文本身（POST正文或GET参数解码后的内容），
+//! 不含HTTP头。未开启时记录器为空，不复制任何数据。
+
+/// 一次上游交互的原始DNS报文
+///
+/// 报文中的ID是实际发往上游的随机ID，与调用方看到的响应ID可能不同
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct WireCapture {
+    /// 发出的请求报文
+    pub request: Vec<u8>,
+    /// 收到的响应报文（解析前）
+    pub response: Vec<u8>,
+    /// 是否有报文超过保留上限而被截断
+    pub truncated: bool,
+}
+
+/// 传输内部使用的报文记录器
+#[derive(Debug)]
+pub(crate) struct WireRecorder {
+    /// 每个报文保留的最大字节数，`None` 表示不记录
+    limit: Option<usize>,
+    capture: WireCapture,
+}
+
+impl WireRecorder {
+    /// 不记录报文
+    pub(crate) fn disabled() -> Self {
+        Self { limit: None, capture: WireCapture::default() }
+    }
+
+    /// 记录报文，每个报文最多保留 `limit` 字节
+    pub(crate) fn with_limit(limit: usize) -> Self {
+        Self { limit: Some(limit), capture: WireCapture::default() }
+    }
+
+    /// 记录发出的请求报文
+    pub(crate) fn record_request(&mut self, message: &[u8]) {
+        if let Some(limit) = self.limit {
+            self.capture.request = self.keep(message, limit);
+        }
+    }
+
+    /// 记录收到的响应报文
+    pub(crate) fn record_response(&mut self, message: &[u8]) {
+        if let Some(limit) = self.limit {
+            self.capture.response = self.keep(message, limit);
+        }
+    }
+
+    fn keep(&mut self, message: &[u8], limit: usize) -> Vec<u8> {
+        if message.len() > limit {
+            self.capture.truncated = true;
+        }
+        message[..message.len().min(limit)].to_vec()
+    }
+
+    /// 结束记录
+    pub(crate) fn finish(self) -> WireCapture {
+        self.capture
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_over_limit_are_truncated() {
+        let mut recorder = WireRecorder::with_limit(4);
+        recorder.record_request(&[1, 2, 3]);
+        recorder.record_response(&[1, 2, 3, 4, 5, 6]);
+        assert_eq!(recorder.finish(), WireCapture {
+            request: vec![1, 2, 3],
+            response: vec![1, 2, 3, 4],
+            truncated: true,
+        });
+
+        let mut disabled = WireRecorder::disabled();
+        disabled.record_request(&[1, 2, 3]);
+        assert_eq!(disabled.finish(), WireCapture::default());
+    }
+}
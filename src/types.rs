@@ -1,11 +1,18 @@
 //! DNS核心类型定义
 
 use std::fmt;
+use std::str::FromStr;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+#[cfg(feature = "serde-api")]
 use bincode::{Encode, Decode};
+use serde::{Deserialize, Serialize};
+use crate::resolver::priority::QueryPriority;
+use crate::DnsError;
 
 /// DNS查询请求
-#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub struct Request {
     /// 事务ID
     pub id: u16,
@@ -15,10 +22,19 @@ pub struct Request {
     pub query: Query,
     /// 客户端地址信息 (EDNS Client Subnet)
     pub client_address: Option<ClientAddress>,
+    /// 是否携带EDNS OPT记录（设置了客户端地址或DO位时总会携带）
+    pub enable_edns: bool,
+    /// 是否在OPT记录中设置DNSSEC OK（DO）位
+    pub dnssec_ok: bool,
+    /// 保留收发的原始报文时每个报文最多保留的字节数（None表示不保留）
+    pub wire_capture_limit: Option<usize>,
+    /// 查询优先级，决定上游发送的准入顺序
+    pub priority: QueryPriority,
 }
 
 /// DNS响应
-#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub struct Response {
     /// 事务ID
     pub id: u16,
@@ -34,8 +50,71 @@ pub struct Response {
     pub additionals: Vec<Record>,
 }
 
+/// 共享的DNS响应
+///
+/// 缓存命中时与缓存条目共用同一份响应，不复制记录；剩余TTL单独保存，读取记录的TTL
+/// 用 [`record_ttl`](Self::record_ttl)。需要修改响应时用 [`into_response`](Self::into_response)
+/// 取得独立的副本，只有这时才复制记录（独占时不复制）
+#[derive(Debug, Clone)]
+pub struct SharedResponse {
+    response: Arc<Response>,
+    /// 所有记录的TTL，`None` 表示使用记录自身的TTL
+    ttl_override: Option<u32>,
+}
+
+impl SharedResponse {
+    /// 包装共享的响应，`ttl_override` 为读取时所有记录的TTL
+    pub fn new(response: Arc<Response>, ttl_override: Option<u32>) -> Self {
+        Self { response, ttl_override }
+    }
+
+    /// 读取时覆盖所有记录的TTL（缓存中的剩余时间）
+    pub fn ttl_override(&self) -> Option<u32> {
+        self.ttl_override
+    }
+
+    /// 记录对调用方的TTL
+    pub fn record_ttl(&self, record: &Record) -> u32 {
+        self.ttl_override.unwrap_or(record.ttl)
+    }
+
+    /// 是否与另一份共用同一响应数据
+    pub fn shares_data_with(&self, other: &SharedResponse) -> bool {
+        Arc::ptr_eq(&self.response, &other.response)
+    }
+
+    /// 取得可修改的响应，TTL按 [`record_ttl`](Self::record_ttl) 写入记录
+    pub fn into_response(self) -> Response {
+        let mut response = Arc::unwrap_or_clone(self.response);
+        if let Some(ttl) = self.ttl_override {
+            for record in response.answers.iter_mut()
+                .chain(response.authorities.iter_mut())
+                .chain(response.additionals.iter_mut())
+            {
+                record.ttl = ttl;
+            }
+        }
+        response
+    }
+}
+
+impl std::ops::Deref for SharedResponse {
+    type Target = Response;
+
+    fn deref(&self) -> &Response {
+        &self.response
+    }
+}
+
+impl From<Response> for SharedResponse {
+    fn from(response: Response) -> Self {
+        Self::new(Arc::new(response), None)
+    }
+}
+
 /// DNS查询问题
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub struct Query {
     /// 查询名称
     pub name: String,
@@ -46,7 +125,8 @@ pub struct Query {
 }
 
 /// DNS资源记录
-#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub struct Record {
     /// 记录名称
     pub name: String,
@@ -61,7 +141,8 @@ pub struct Record {
 }
 
 /// DNS记录数据
-#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub enum RecordData {
     /// A记录 - IPv4地址
     A(Ipv4Addr),
@@ -134,7 +215,8 @@ pub enum RecordData {
 }
 
 /// DNS记录类型
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 #[repr(u16)]
 pub enum RecordType {
     /// A记录
@@ -155,15 +237,63 @@ pub enum RecordType {
     AAAA = 28,
     /// SRV记录
     SRV = 33,
-    /// 未知类型
+    /// DS记录（委托签名者）
+    DS = 43,
+    /// RRSIG记录（资源记录签名）
+    RRSIG = 46,
+    /// NSEC记录
+    NSEC = 47,
+    /// DNSKEY记录
+    DNSKEY = 48,
+    /// NSEC3记录
+    NSEC3 = 50,
+    /// 没有单独变体的类型，按类型值原样保留
     Unknown(u16),
 }
 
+/// IANA登记的资源记录类型及其助记符（RFC 6895），包括已废弃和仅用于查询的类型
+/// 
+/// 没有单独变体的类型以 [`RecordType::Unknown`] 表示，显示和解析仍使用这里的助记符
+const RECORD_TYPE_NAMES: &[(u16, &str)] = &[
+    (1, "A"), (2, "NS"), (3, "MD"), (4, "MF"), (5, "CNAME"), (6, "SOA"), (7, "MB"), (8, "MG"),
+    (9, "MR"), (10, "NULL"), (11, "WKS"), (12, "PTR"), (13, "HINFO"), (14, "MINFO"), (15, "MX"), (16, "TXT"),
+    (17, "RP"), (18, "AFSDB"), (19, "X25"), (20, "ISDN"), (21, "RT"), (22, "NSAP"), (23, "NSAP-PTR"), (24, "SIG"),
+    (25, "KEY"), (26, "PX"), (27, "GPOS"), (28, "AAAA"), (29, "LOC"), (30, "NXT"), (31, "EID"), (32, "NIMLOC"),
+    (33, "SRV"), (34, "ATMA"), (35, "NAPTR"), (36, "KX"), (37, "CERT"), (38, "A6"), (39, "DNAME"), (40, "SINK"),
+    (41, "OPT"), (42, "APL"), (43, "DS"), (44, "SSHFP"), (45, "IPSECKEY"), (46, "RRSIG"), (47, "NSEC"), (48, "DNSKEY"),
+    (49, "DHCID"), (50, "NSEC3"), (51, "NSEC3PARAM"), (52, "TLSA"), (53, "SMIMEA"), (55, "HIP"), (56, "NINFO"), (57, "RKEY"),
+    (58, "TALINK"), (59, "CDS"), (60, "CDNSKEY"), (61, "OPENPGPKEY"), (62, "CSYNC"), (63, "ZONEMD"), (64, "SVCB"), (65, "HTTPS"),
+    (66, "DSYNC"), (99, "SPF"), (100, "UINFO"), (101, "UID"), (102, "GID"), (103, "UNSPEC"), (104, "NID"), (105, "L32"),
+    (106, "L64"), (107, "LP"), (108, "EUI48"), (109, "EUI64"), (128, "NXNAME"), (249, "TKEY"), (250, "TSIG"), (251, "IXFR"),
+    (252, "AXFR"), (253, "MAILB"), (254, "MAILA"), (255, "ANY"), (256, "URI"), (257, "CAA"), (258, "AVC"), (259, "DOA"),
+    (260, "AMTRELAY"), (261, "RESINFO"), (262, "WALLET"), (263, "CLA"), (264, "IPN"), (32768, "TA"), (32769, "DLV"),
+];
+
+impl RecordType {
+    /// 类型值
+    pub fn code(self) -> u16 {
+        self.into()
+    }
+    
+    /// IANA登记的助记符，未登记的类型值为 `None`
+    pub fn mnemonic(self) -> Option<&'static str> {
+        let code = self.code();
+        RECORD_TYPE_NAMES.iter().find(|(value, _)| *value == code).map(|(_, name)| *name)
+    }
+    
+    /// 所有登记了助记符的类型
+    pub fn assigned() -> impl Iterator<Item = RecordType> {
+        RECORD_TYPE_NAMES.iter().map(|(code, _)| RecordType::from(*code))
+    }
+}
+
 /// DNS查询类别
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 #[repr(u16)]
 pub enum QClass {
     /// Internet类别
+    #[default]
     IN = 1,
     /// Chaos类别
     CH = 3,
@@ -175,13 +305,74 @@ pub enum QClass {
     Unknown(u16),
 }
 
+/// DNS操作码（头部4位，RFC 1035、RFC 1996、RFC 2136）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
+pub enum Opcode {
+    /// 标准查询（0）
+    #[default]
+    Query,
+    /// 反向查询（1，已由RFC 3425废弃）
+    IQuery,
+    /// 服务器状态请求（2）
+    Status,
+    /// 区域变更通知（4，RFC 1996）
+    Notify,
+    /// 动态更新（5，RFC 2136）
+    Update,
+    /// 未知操作码
+    Unknown(u8),
+}
+
+impl Opcode {
+    /// dig风格的名称，未知操作码为 `OPCODE<数值>`
+    pub fn name(self) -> String {
+        let name = match self {
+            Opcode::Query => "QUERY",
+            Opcode::IQuery => "IQUERY",
+            Opcode::Status => "STATUS",
+            Opcode::Notify => "NOTIFY",
+            Opcode::Update => "UPDATE",
+            Opcode::Unknown(value) => return format!("OPCODE{}", value),
+        };
+        name.to_string()
+    }
+}
+
+impl From<u8> for Opcode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Opcode::Query,
+            1 => Opcode::IQuery,
+            2 => Opcode::Status,
+            4 => Opcode::Notify,
+            5 => Opcode::Update,
+            _ => Opcode::Unknown(value),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> Self {
+        match opcode {
+            Opcode::Query => 0,
+            Opcode::IQuery => 1,
+            Opcode::Status => 2,
+            Opcode::Notify => 4,
+            Opcode::Update => 5,
+            Opcode::Unknown(value) => value,
+        }
+    }
+}
+
 /// DNS标志位
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub struct Flags {
     /// 查询/响应标志
     pub qr: bool,
     /// 操作码
-    pub opcode: u8,
+    pub opcode: Opcode,
     /// 权威回答
     pub aa: bool,
     /// 截断标志
@@ -190,34 +381,131 @@ pub struct Flags {
     pub rd: bool,
     /// 递归可用
     pub ra: bool,
-    /// 保留位
-    pub z: u8,
+    /// 保留位Z（RFC 1035要求为0），收到的值原样保留
+    pub reserved: bool,
+    /// 已验证数据（AD，RFC 4035 第3.2.3节）：响应中的数据都经过了上游的DNSSEC验证
+    pub ad: bool,
+    /// 禁用检查（CD，RFC 4035 第3.2.2节）：请求上游不做DNSSEC验证
+    pub cd: bool,
     /// 响应码
     pub rcode: u8,
 }
 
+impl Flags {
+    const QR: u16 = 0x8000;
+    const AA: u16 = 0x0400;
+    const TC: u16 = 0x0200;
+    const RD: u16 = 0x0100;
+    const RA: u16 = 0x0080;
+    const Z: u16 = 0x0040;
+    const AD: u16 = 0x0020;
+    const CD: u16 = 0x0010;
+
+    /// 解码头部第3、4字节的标志位
+    pub fn from_bits(bits: u16) -> Self {
+        Self {
+            qr: bits & Self::QR != 0,
+            opcode: Opcode::from(((bits >> 11) & 0x0F) as u8),
+            aa: bits & Self::AA != 0,
+            tc: bits & Self::TC != 0,
+            rd: bits & Self::RD != 0,
+            ra: bits & Self::RA != 0,
+            reserved: bits & Self::Z != 0,
+            ad: bits & Self::AD != 0,
+            cd: bits & Self::CD != 0,
+            rcode: (bits & 0x0F) as u8,
+        }
+    }
+
+    /// 编码为头部第3、4字节，操作码和响应码超出4位的部分忽略
+    pub fn to_bits(&self) -> u16 {
+        let mut bits = (u16::from(u8::from(self.opcode) & 0x0F) << 11) | u16::from(self.rcode & 0x0F);
+        for (set, mask) in [
+            (self.qr, Self::QR),
+            (self.aa, Self::AA),
+            (self.tc, Self::TC),
+            (self.rd, Self::RD),
+            (self.ra, Self::RA),
+            (self.reserved, Self::Z),
+            (self.ad, Self::AD),
+            (self.cd, Self::CD),
+        ] {
+            if set {
+                bits |= mask;
+            }
+        }
+        bits
+    }
+
+    /// 原先合在一起的3位 `z` 字段中真正的保留位Z
+    #[deprecated(since = "0.2.5", note = "the AD and CD bits are now `ad` and `cd`; the Z bit is `reserved`")]
+    pub fn z(&self) -> u8 {
+        u8::from(self.reserved)
+    }
+}
+
 /// DNS响应码
+/// 
+/// 包括OPT记录扩展出的12位响应码（RFC 6891），头部只能容纳低4位，
+/// 完整的值见 [`Response::extended_rcode`](crate::Response::extended_rcode)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
 pub enum ResponseCode {
-    /// 无错误
-    NoError = 0,
-    /// 格式错误
-    FormatError = 1,
-    /// 服务器失败
-    ServerFailure = 2,
-    /// 域名不存在
-    NxDomain = 3,
-    /// 未实现
-    NotImplemented = 4,
-    /// 查询被拒绝
-    Refused = 5,
+    /// 无错误（0）
+    NoError,
+    /// 格式错误（1）
+    FormatError,
+    /// 服务器失败（2）
+    ServerFailure,
+    /// 域名不存在（3）
+    NxDomain,
+    /// 未实现（4）
+    NotImplemented,
+    /// 查询被拒绝（5）
+    Refused,
+    /// 不应存在的域名存在（6，DNS UPDATE）
+    YxDomain,
+    /// 不应存在的记录集存在（7，DNS UPDATE）
+    YxRrSet,
+    /// 应存在的记录集不存在（8，DNS UPDATE）
+    NxRrSet,
+    /// 服务器对该区域没有权威（9）
+    NotAuth,
+    /// 名称不在区域内（10，DNS UPDATE）
+    NotZone,
+    /// 不支持请求的EDNS版本（16）
+    BadVers,
+    /// 服务器Cookie缺失或错误（23）
+    BadCookie,
     /// 未知响应码
-    Unknown(u8),
+    Unknown(u16),
+}
+
+impl ResponseCode {
+    /// dig风格的名称，未知响应码为 `RCODE<数值>`
+    pub fn name(self) -> String {
+        let name = match self {
+            ResponseCode::NoError => "NOERROR",
+            ResponseCode::FormatError => "FORMERR",
+            ResponseCode::ServerFailure => "SERVFAIL",
+            ResponseCode::NxDomain => "NXDOMAIN",
+            ResponseCode::NotImplemented => "NOTIMP",
+            ResponseCode::Refused => "REFUSED",
+            ResponseCode::YxDomain => "YXDOMAIN",
+            ResponseCode::YxRrSet => "YXRRSET",
+            ResponseCode::NxRrSet => "NXRRSET",
+            ResponseCode::NotAuth => "NOTAUTH",
+            ResponseCode::NotZone => "NOTZONE",
+            ResponseCode::BadVers => "BADVERS",
+            ResponseCode::BadCookie => "BADCOOKIE",
+            ResponseCode::Unknown(value) => return format!("RCODE{}", value),
+        };
+        name.to_string()
+    }
 }
 
 /// EDNS客户端地址信息
-#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-api", derive(Encode, Decode))]
 pub struct ClientAddress {
     /// 客户端IP地址
     pub address: IpAddr,
@@ -366,6 +654,120 @@ impl ClientAddress {
     }
 }
 
+/// 向某个上游发送EDNS客户端子网（ECS）的方式
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum EcsPolicy {
+    /// 不发送ECS选项
+    Strip,
+    /// 原样发送查询的客户端地址
+    #[default]
+    Forward,
+    /// 总是发送固定的子网，替换查询的客户端地址
+    Override {
+        /// 子网地址
+        ip: IpAddr,
+        /// 源前缀长度
+        prefix: u8,
+    },
+    /// 发送源前缀长度为0的ECS选项，要求上游不使用客户端子网（RFC 7871 第7.1.2节）
+    ///
+    /// 地址族与查询的客户端地址相同，没有客户端地址时为IPv4
+    ZeroScope,
+}
+
+impl EcsPolicy {
+    /// 按策略得出实际发给上游的客户端地址
+    pub fn apply(&self, client: Option<&ClientAddress>) -> Option<ClientAddress> {
+        match self {
+            EcsPolicy::Strip => None,
+            EcsPolicy::Forward => client.cloned(),
+            EcsPolicy::Override { ip, prefix } => Some(ClientAddress::new(*ip, *prefix)),
+            EcsPolicy::ZeroScope => {
+                let unspecified = match client.map(|client| client.address) {
+                    Some(IpAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                    _ => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                };
+                Some(ClientAddress::new(unspecified, 0))
+            }
+        }
+    }
+    
+    /// 检查固定子网的前缀长度不超过地址位数
+    pub fn validate(&self) -> crate::Result<()> {
+        if let EcsPolicy::Override { ip, prefix } = self {
+            let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+            if *prefix > max_prefix {
+                return Err(crate::DnsError::InvalidConfig(format!("ECS override prefix /{} exceeds {} bits for {}", prefix, max_prefix, ip)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 单个上游的请求特性，`None` 表示沿用解析器的设置
+///
+/// 解析器的设置：EDNS取解析器的 `enable_edns`，DO位和查询名大小写随机化默认关闭
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct UpstreamFeatures {
+    /// 是否携带OPT记录；为 `Some(false)` 时客户端子网和DO位也不发送
+    #[serde(default)]
+    pub edns: Option<bool>,
+    /// 是否设置DNSSEC OK（DO）位，设置时总会携带OPT记录
+    #[serde(default)]
+    pub dnssec_do: Option<bool>,
+    /// 是否随机化查询名的大小写（0x20编码），启用后要求响应原样回显问题名
+    #[serde(default)]
+    pub case_randomization: Option<bool>,
+}
+
+/// 上游实际生效的请求特性
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EffectiveFeatures {
+    /// 是否携带OPT记录（携带客户端子网时即使为false也会携带）
+    pub edns: bool,
+    /// 是否设置DO位
+    pub dnssec_do: bool,
+    /// 是否随机化查询名的大小写
+    pub case_randomization: bool,
+}
+
+impl UpstreamFeatures {
+    /// 检查开关之间没有矛盾：不携带OPT记录时无法设置DO位
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.dnssec_do == Some(true) && self.edns == Some(false) {
+            return Err(crate::DnsError::InvalidConfig(
+                "dnssec_do = true requires EDNS, but edns = false".to_string()));
+        }
+        Ok(())
+    }
+    
+    /// 以解析器的EDNS设置补全未配置的开关
+    pub fn effective(&self, resolver_edns: bool) -> EffectiveFeatures {
+        let dnssec_do = self.edns != Some(false) && self.dnssec_do.unwrap_or(false);
+        EffectiveFeatures {
+            edns: self.edns.unwrap_or(resolver_edns || dnssec_do),
+            dnssec_do,
+            case_randomization: self.case_randomization.unwrap_or(false),
+        }
+    }
+    
+    /// 按配置改写发往该上游的请求（大小写随机化由发送方处理）
+    pub fn apply(&self, request: &mut Request) {
+        if let Some(dnssec_do) = self.dnssec_do {
+            request.dnssec_ok = dnssec_do;
+        }
+        match self.edns {
+            Some(true) => request.enable_edns = true,
+            Some(false) => {
+                request.enable_edns = false;
+                request.dnssec_ok = false;
+                request.client_address = None;
+            }
+            None => {}
+        }
+    }
+}
+
 // 注意：移除了 Default 实现，因为它包含兜底行为
 // 硬编码的默认值（如 4096 UDP载荷大小）是兜底代码
 // 用户现在必须明确配置所有EDNS参数
@@ -392,6 +794,11 @@ impl From<u16> for RecordType {
             16 => RecordType::TXT,
             28 => RecordType::AAAA,
             33 => RecordType::SRV,
+            43 => RecordType::DS,
+            46 => RecordType::RRSIG,
+            47 => RecordType::NSEC,
+            48 => RecordType::DNSKEY,
+            50 => RecordType::NSEC3,
             _ => RecordType::Unknown(value),
         }
     }
@@ -409,6 +816,11 @@ impl From<RecordType> for u16 {
             RecordType::TXT => 16,
             RecordType::AAAA => 28,
             RecordType::SRV => 33,
+            RecordType::DS => 43,
+            RecordType::RRSIG => 46,
+            RecordType::NSEC => 47,
+            RecordType::DNSKEY => 48,
+            RecordType::NSEC3 => 50,
             RecordType::Unknown(value) => value,
         }
     }
@@ -438,8 +850,8 @@ impl From<QClass> for u16 {
     }
 }
 
-impl From<u8> for ResponseCode {
-    fn from(value: u8) -> Self {
+impl From<u16> for ResponseCode {
+    fn from(value: u16) -> Self {
         match value {
             0 => ResponseCode::NoError,
             1 => ResponseCode::FormatError,
@@ -447,12 +859,26 @@ impl From<u8> for ResponseCode {
             3 => ResponseCode::NxDomain,
             4 => ResponseCode::NotImplemented,
             5 => ResponseCode::Refused,
+            6 => ResponseCode::YxDomain,
+            7 => ResponseCode::YxRrSet,
+            8 => ResponseCode::NxRrSet,
+            9 => ResponseCode::NotAuth,
+            10 => ResponseCode::NotZone,
+            16 => ResponseCode::BadVers,
+            23 => ResponseCode::BadCookie,
             _ => ResponseCode::Unknown(value),
         }
     }
 }
 
-impl From<ResponseCode> for u8 {
+/// 头部的4位响应码
+impl From<u8> for ResponseCode {
+    fn from(value: u8) -> Self {
+        ResponseCode::from(u16::from(value))
+    }
+}
+
+impl From<ResponseCode> for u16 {
     fn from(rcode: ResponseCode) -> Self {
         match rcode {
             ResponseCode::NoError => 0,
@@ -461,6 +887,13 @@ impl From<ResponseCode> for u8 {
             ResponseCode::NxDomain => 3,
             ResponseCode::NotImplemented => 4,
             ResponseCode::Refused => 5,
+            ResponseCode::YxDomain => 6,
+            ResponseCode::YxRrSet => 7,
+            ResponseCode::NxRrSet => 8,
+            ResponseCode::NotAuth => 9,
+            ResponseCode::NotZone => 10,
+            ResponseCode::BadVers => 16,
+            ResponseCode::BadCookie => 23,
             ResponseCode::Unknown(value) => value,
         }
     }
@@ -472,30 +905,81 @@ impl Default for Flags {
     fn default() -> Self {
         Self {
             qr: false,
-            opcode: 0,
+            opcode: Opcode::Query,
             aa: false,
             tc: false,
             rd: true,
             ra: false,
-            z: 0,
+            reserved: false,
+            ad: false,
+            cd: false,
             rcode: 0,
         }
     }
 }
 
+/// 登记了助记符的类型显示助记符，其余按RFC 3597显示为 `TYPE<类型值>`
 impl fmt::Display for RecordType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            RecordType::A => write!(f, "A"),
-            RecordType::NS => write!(f, "NS"),
-            RecordType::CNAME => write!(f, "CNAME"),
-            RecordType::SOA => write!(f, "SOA"),
-            RecordType::PTR => write!(f, "PTR"),
-            RecordType::MX => write!(f, "MX"),
-            RecordType::TXT => write!(f, "TXT"),
-            RecordType::AAAA => write!(f, "AAAA"),
-            RecordType::SRV => write!(f, "SRV"),
-            RecordType::Unknown(value) => write!(f, "TYPE{}", value),
+        match self.mnemonic() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "TYPE{}", self.code()),
         }
     }
-}
\ No newline at end of file
+}
+
+/// 解析助记符（不区分大小写）或RFC 3597的 `TYPE<类型值>` 写法
+impl FromStr for RecordType {
+    type Err = DnsError;
+    
+    fn from_str(value: &str) -> crate::Result<Self> {
+        let value = value.trim();
+        if let Some((code, _)) = RECORD_TYPE_NAMES.iter().find(|(_, name)| name.eq_ignore_ascii_case(value)) {
+            return Ok(RecordType::from(*code));
+        }
+        value.get(..4)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("TYPE"))
+            .map(|_| &value[4..])
+            .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse::<u16>().ok())
+            .map(RecordType::from)
+            .ok_or_else(|| DnsError::Parse(format!("Unknown record type: {}", value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_assigned_type_round_trips() {
+        for (code, name) in RECORD_TYPE_NAMES {
+            let rtype = RecordType::from(*code);
+            assert_eq!(rtype.code(), *code);
+            assert_eq!(rtype.to_string(), *name);
+            assert_eq!(name.parse::<RecordType>().unwrap(), rtype);
+            assert_eq!(name.to_lowercase().parse::<RecordType>().unwrap(), rtype);
+            assert_eq!(format!("TYPE{}", code).parse::<RecordType>().unwrap(), rtype);
+        }
+        assert_eq!(RecordType::assigned().count(), RECORD_TYPE_NAMES.len());
+        // 有单独变体的类型不以 Unknown 表示
+        for rtype in [RecordType::DS, RecordType::RRSIG, RecordType::NSEC, RecordType::DNSKEY, RecordType::NSEC3] {
+            assert_eq!(RecordType::from(rtype.code()), rtype);
+            assert!(!matches!(RecordType::from(rtype.code()), RecordType::Unknown(_)));
+        }
+    }
+
+    #[test]
+    fn test_unassigned_codes_use_rfc3597_syntax() {
+        for code in (0..=u16::MAX).filter(|code| RECORD_TYPE_NAMES.iter().all(|(value, _)| value != code)) {
+            let rtype = RecordType::from(code);
+            assert_eq!(rtype, RecordType::Unknown(code));
+            assert_eq!(rtype.to_string(), format!("TYPE{}", code));
+            assert_eq!(rtype.to_string().parse::<RecordType>().unwrap(), rtype);
+        }
+        assert_eq!("type65280".parse::<RecordType>().unwrap(), RecordType::Unknown(65280));
+        for invalid in ["", "TYPE", "TYPE65536", "TYPE-1", "TYPE+1", "TYPE 1", "AAAAA", "SVC"] {
+            assert!(invalid.parse::<RecordType>().is_err(), "{}", invalid);
+        }
+    }
+}
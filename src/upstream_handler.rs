@@ -3,17 +3,43 @@
 //! 基于handler模式的上游服务器管理，避免强制类型转换，提供最优性能
 
 use crate::{
-    transport::{Transport, TransportConfig, HttpsConfig, TlsConfig},
-    utils::{parse_server_address, parse_url_components, get_user_agent},
+    transport::{AfPreference, HttpMethod, Transport, TransportConfig, HttpVersionPref, UDP_EDNS_PAYLOAD_SIZE},
+    utils::parse_server_address,
+    types::{EcsPolicy, RecordType, UpstreamFeatures},
+    resolver::slo::SloConfig,
     Result, DnsError,
-    dns_info, dns_debug,
+    dns_info, dns_debug, dns_warn,
 };
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     time::Duration,
 };
 use async_trait::async_trait;
 
+/// UDP、TCP上游的连接池大小
+pub(crate) const PLAIN_POOL_SIZE: usize = 10;
+
+/// DoT、DoH上游的连接池大小
+pub(crate) const ENCRYPTED_POOL_SIZE: usize = 5;
+
+/// 上游标签：运维附加的元数据（机房、供应商、费用等级、工单链接等），按键排序
+/// 
+/// 标签不影响查询行为，只随上游状态、状态变化事件、选择解释和有效配置输出，
+/// 列入白名单的键还作为OpenMetrics上游指标的标签
+pub type UpstreamLabels = BTreeMap<String, String>;
+
+/// 每个上游最多的标签数
+pub const MAX_UPSTREAM_LABELS: usize = 16;
+
+/// 标签键的最大长度（字节）
+pub const MAX_LABEL_KEY_LEN: usize = 63;
+
+/// 标签值的最大长度（字节）
+pub const MAX_LABEL_VALUE_LEN: usize = 256;
+
+/// OpenMetrics上游指标已有的标签名，不能用作标签键
+const RESERVED_LABEL_KEYS: [&str; 4] = ["upstream", "result", "le", "state"];
+
 /// 上游服务器类型
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum UpstreamType {
@@ -25,6 +51,40 @@ pub enum UpstreamType {
     DoT,
     /// DNS over HTTPS
     DoH,
+    /// 自定义传输：由调用方直接提供Transport实例（如测试用的MockTransport）
+    Custom,
+}
+
+impl UpstreamType {
+    /// 该传输类型需要、但本次构建没有启用的cargo特性
+    pub fn missing_feature(&self) -> Option<&'static str> {
+        match self {
+            UpstreamType::Tcp if !cfg!(feature = "tcp") => Some("tcp"),
+            UpstreamType::DoT if !cfg!(feature = "dot") => Some("dot"),
+            UpstreamType::DoH if !cfg!(feature = "doh") => Some("doh"),
+            _ => None,
+        }
+    }
+}
+
+/// 上游的传输类型在本次构建中没有编译进来时的配置错误
+pub(crate) fn feature_disabled(spec: &UpstreamSpec, feature: &str) -> DnsError {
+    DnsError::InvalidConfig(format!(
+        "Upstream '{}' uses {:?}, which requires the {} feature", spec.name, spec.transport_type, feature
+    ))
+}
+
+/// 上游的在途查询达到 [`UpstreamSpec::max_inflight`] 时新查询的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueueBehavior {
+    /// 本次查询立即跳过该上游，改用其他上游
+    #[default]
+    Reject,
+    /// 最多等待 `timeout` 空出名额，仍然没有名额时跳过该上游
+    Wait {
+        /// 等待名额的时限
+        timeout: Duration,
+    },
 }
 
 /// 上游服务器配置（字符串存储）
@@ -42,6 +102,36 @@ pub struct UpstreamSpec {
     pub weight: u32,
     /// 期望区域
     pub region: Option<String>,
+    /// 附加的HTTP请求头（仅DoH使用）
+    pub headers: Vec<(String, String)>,
+    /// 附加的URL查询参数（仅DoH使用）
+    pub query_params: Vec<(String, String)>,
+    /// DoH使用的HTTP版本（仅DoH使用）
+    pub http_version: HttpVersionPref,
+    /// 向该上游发送客户端子网的方式
+    pub ecs_policy: EcsPolicy,
+    /// 优先层级，数值越小越优先；只有更优先的层级没有可用上游时才使用该上游
+    pub tier: u8,
+    /// 发往该上游时EDNS、DO位和大小写随机化的开关（未配置的沿用解析器的设置）
+    pub features: UpstreamFeatures,
+    /// 同时发往该上游的查询上限，避免缓慢的上游占满任务和内存（None表示不限制）
+    pub max_inflight: Option<usize>,
+    /// 达到 `max_inflight` 时新查询的处理方式
+    pub queue_behavior: QueueBehavior,
+    /// 以主机名配置时连接哪个地址族（None沿用解析器的设置）
+    pub address_family: Option<AfPreference>,
+    /// p95响应时间目标，持续违反或恢复时通知查询观察者（None表示不设目标）
+    pub slo: Option<SloConfig>,
+    /// 该上游的查询超时（None沿用解析器的 `default_timeout`）
+    pub timeout: Option<Duration>,
+    /// DoT握手使用的SNI和证书校验名称（None使用 `server` 中的主机名）
+    pub tls_server_name: Option<String>,
+    /// DoH请求使用的HTTP方法（仅DoH使用，默认POST）
+    pub doh_method: HttpMethod,
+    /// 只把这些记录类型的查询发往该上游（None表示不限制）
+    pub record_types: Option<Vec<RecordType>>,
+    /// 上游标签，不影响查询行为
+    pub labels: UpstreamLabels,
 }
 
 /// 上游处理器trait
@@ -79,10 +169,12 @@ impl UpstreamHandler for UdpHandler {
         let config = TransportConfig {
             server: actual_server.clone(),
             port,
-            timeout: Duration::from_secs(5),
+            timeout: spec.timeout.unwrap_or(Duration::from_secs(5)),
             tcp_fast_open: false,
             tcp_nodelay: true,
-            pool_size: 10,
+            pool_size: PLAIN_POOL_SIZE,
+            buffer_size: usize::from(UDP_EDNS_PAYLOAD_SIZE),
+            record_limits: None,
         };
         
         Ok(Box::new(crate::transport::UdpTransport::new(config)))
@@ -101,9 +193,11 @@ impl UpstreamHandler for UdpHandler {
 }
 
 /// TCP处理器
+#[cfg(feature = "tcp")]
 #[derive(Debug, Default)]
 pub struct TcpHandler;
 
+#[cfg(feature = "tcp")]
 #[async_trait]
 impl UpstreamHandler for TcpHandler {
     fn handler_type(&self) -> UpstreamType {
@@ -119,10 +213,12 @@ impl UpstreamHandler for TcpHandler {
         let config = TransportConfig {
             server: actual_server.clone(),
             port,
-            timeout: Duration::from_secs(5),
+            timeout: spec.timeout.unwrap_or(Duration::from_secs(5)),
             tcp_fast_open: false,
             tcp_nodelay: true,
-            pool_size: 10,
+            pool_size: PLAIN_POOL_SIZE,
+            buffer_size: usize::from(crate::transport::STREAM_EDNS_PAYLOAD_SIZE),
+            record_limits: None,
         };
         
         Ok(Box::new(crate::transport::TcpTransport::new(config)))
@@ -141,9 +237,11 @@ impl UpstreamHandler for TcpHandler {
 }
 
 /// DoT处理器
+#[cfg(feature = "dot")]
 #[derive(Debug, Default)]
 pub struct DoTHandler;
 
+#[cfg(feature = "dot")]
 #[async_trait]
 impl UpstreamHandler for DoTHandler {
     fn handler_type(&self) -> UpstreamType {
@@ -155,19 +253,23 @@ impl UpstreamHandler for DoTHandler {
         
         // 对于DoT，连接地址优先使用预解析IP，但SNI必须使用原始域名
         let connection_server = spec.resolved_ip.as_ref().unwrap_or(&server);
-        let sni_name = server.clone(); // SNI使用原始域名，确保证书验证正确
+        // SNI默认使用原始域名，确保证书验证正确
+        let sni_name = spec.tls_server_name.clone().unwrap_or_else(|| server.clone());
             
-        let config = TlsConfig {
+        let config = crate::transport::TlsConfig {
             base: TransportConfig {
                 server: connection_server.clone(),
                 port,
-                timeout: Duration::from_secs(10),
+                timeout: spec.timeout.unwrap_or(Duration::from_secs(10)),
                 tcp_fast_open: false,
                 tcp_nodelay: true,
-                pool_size: 5,
+                pool_size: ENCRYPTED_POOL_SIZE,
+                buffer_size: usize::from(crate::transport::STREAM_EDNS_PAYLOAD_SIZE),
+                record_limits: None,
             },
             server_name: sni_name,
             verify_cert: true,
+            enable_tls_early_data: false,
         };
         
         Ok(Box::new(crate::transport::TlsTransport::new(config)?))
@@ -185,10 +287,24 @@ impl UpstreamHandler for DoTHandler {
     }
 }
 
+/// DoH上游的连接地址：配置了预解析IP时为该IP和URL的端口，否则为 `None`（按URL的主机名解析）
+#[cfg(feature = "doh")]
+pub(crate) fn doh_connect_addr(spec: &UpstreamSpec, port: u16) -> Result<Option<std::net::SocketAddr>> {
+    spec.resolved_ip.as_deref()
+        .map(|ip| {
+            ip.parse::<std::net::IpAddr>()
+                .map(|ip| std::net::SocketAddr::new(ip, port))
+                .map_err(|_| DnsError::InvalidConfig(format!("Invalid resolved IP '{}' for upstream '{}'", ip, spec.name)))
+        })
+        .transpose()
+}
+
 /// DoH处理器
+#[cfg(feature = "doh")]
 #[derive(Debug, Default)]
 pub struct DoHHandler;
 
+#[cfg(feature = "doh")]
 #[async_trait]
 impl UpstreamHandler for DoHHandler {
     fn handler_type(&self) -> UpstreamType {
@@ -200,23 +316,31 @@ impl UpstreamHandler for DoHHandler {
         let url = &spec.server;
         
         // 从URL中提取主机名和端口
-        let (hostname, port) = parse_url_components(url)?;
+        let (hostname, port) = crate::utils::parse_url_components(url)?;
         
         // 连接地址优先使用预解析IP，但SNI必须使用原始域名
-        let connection_server = spec.resolved_ip.as_ref().unwrap_or(&hostname);
+        let connect_addr = doh_connect_addr(spec, port)?;
         
-        let config = HttpsConfig {
+        let config = crate::transport::HttpsConfig {
             base: TransportConfig {
-                server: connection_server.clone(),
+                server: hostname,
                 port,
-                timeout: Duration::from_secs(10),
+                timeout: spec.timeout.unwrap_or(Duration::from_secs(10)),
                 tcp_fast_open: false,
                 tcp_nodelay: true,
-                pool_size: 5,
+                pool_size: ENCRYPTED_POOL_SIZE,
+                buffer_size: usize::from(crate::transport::STREAM_EDNS_PAYLOAD_SIZE),
+                record_limits: None,
             },
             url: url.clone(),
-            method: crate::transport::HttpMethod::POST,
-            user_agent: get_user_agent(),
+            method: spec.doh_method,
+            user_agent: crate::utils::get_user_agent(),
+            extra_headers: spec.headers.clone(),
+            query_params: spec.query_params.clone(),
+            http_version: spec.http_version,
+            max_response_size: usize::from(crate::transport::STREAM_EDNS_PAYLOAD_SIZE),
+            follow_redirects: true,
+            connect_addr,
         };
         
         Ok(Box::new(crate::transport::HttpsTransport::new(config)?))
@@ -227,9 +351,14 @@ impl UpstreamHandler for DoHHandler {
             return Err(DnsError::InvalidConfig("DoH server cannot be empty".to_string()));
         }
         
-        // 验证URL格式
-        if !spec.server.starts_with("https://") {
-            return Err(DnsError::InvalidConfig("DoH URL must use HTTPS".to_string()));
+        // 验证URL格式和附加查询参数
+        crate::transport::doh_url::validate_doh_url(&spec.server, &spec.query_params)?;
+        crate::transport::https::build_header_map(&spec.headers)?;
+        
+        if spec.http_version.uses_http3() && !cfg!(feature = "doh3") {
+            return Err(DnsError::InvalidConfig(format!(
+                "DoH upstream '{}' requests HTTP/3, which requires the doh3 feature", spec.name
+            )));
         }
         
         Ok(())
@@ -240,6 +369,36 @@ impl UpstreamHandler for DoHHandler {
     }
 }
 
+/// 自定义传输处理器
+/// 
+/// 自定义上游的传输实例由调用方直接提供，处理器只负责校验规格
+#[derive(Debug, Default)]
+pub struct CustomHandler;
+
+#[async_trait]
+impl UpstreamHandler for CustomHandler {
+    fn handler_type(&self) -> UpstreamType {
+        UpstreamType::Custom
+    }
+    
+    async fn create_transport(&self, spec: &UpstreamSpec) -> Result<Box<dyn Transport>> {
+        Err(DnsError::InvalidConfig(
+            format!("Custom upstream '{}' must be given a transport instance", spec.name)
+        ))
+    }
+    
+    fn validate_spec(&self, spec: &UpstreamSpec) -> Result<()> {
+        if spec.name.is_empty() {
+            return Err(DnsError::InvalidConfig("Custom upstream name cannot be empty".to_string()));
+        }
+        Ok(())
+    }
+    
+    fn default_port(&self) -> u16 {
+        0
+    }
+}
+
 /// 上游管理器
 #[derive(Debug)]
 pub struct UpstreamManager {
@@ -258,10 +417,16 @@ impl Clone for UpstreamManager {
 impl Default for UpstreamManager {
     fn default() -> Self {
         let mut handlers: HashMap<UpstreamType, Box<dyn UpstreamHandler>> = HashMap::new();
+        // wasm32上没有UDP套接字
+        #[cfg(not(target_arch = "wasm32"))]
         handlers.insert(UpstreamType::Udp, Box::new(UdpHandler));
+        #[cfg(feature = "tcp")]
         handlers.insert(UpstreamType::Tcp, Box::new(TcpHandler));
+        #[cfg(feature = "dot")]
         handlers.insert(UpstreamType::DoT, Box::new(DoTHandler));
+        #[cfg(feature = "doh")]
         handlers.insert(UpstreamType::DoH, Box::new(DoHHandler));
+        handlers.insert(UpstreamType::Custom, Box::new(CustomHandler));
         
         Self {
             handlers,
@@ -281,8 +446,17 @@ impl UpstreamManager {
         dns_info!("Adding upstream server: {} ({:?}) -> {}", spec.name, spec.transport_type, spec.server);
         
         // 验证规格
+        spec.ecs_policy.validate()?;
+        spec.features.validate()?;
+        check_max_inflight(&spec.name, spec.max_inflight)?;
+        if let Some(slo) = &spec.slo {
+            slo.validate(&spec.name)?;
+        }
+        spec.validate_options()?;
         if let Some(handler) = self.handlers.get(&spec.transport_type) {
             handler.validate_spec(&spec)?;
+        } else if let Some(feature) = spec.transport_type.missing_feature() {
+            return Err(feature_disabled(&spec, feature));
         } else {
             return Err(DnsError::InvalidConfig(
                 format!("Unsupported transport type: {:?}", spec.transport_type)
@@ -298,6 +472,8 @@ impl UpstreamManager {
     pub async fn create_transport(&self, spec: &UpstreamSpec) -> Result<Box<dyn Transport>> {
         if let Some(handler) = self.handlers.get(&spec.transport_type) {
             handler.create_transport(spec).await
+        } else if let Some(feature) = spec.transport_type.missing_feature() {
+            Err(feature_disabled(spec, feature))
         } else {
             Err(DnsError::InvalidConfig(
                 format!("No handler for transport type: {:?}", spec.transport_type)
@@ -305,6 +481,75 @@ impl UpstreamManager {
         }
     }
     
+    /// 按名称移除上游，返回被移除的规格
+    pub fn remove_upstream(&mut self, name: &str) -> Option<UpstreamSpec> {
+        let index = self.specs.iter().position(|spec| spec.name == name)?;
+        dns_info!("Removing upstream server: {}", name);
+        Some(self.specs.remove(index))
+    }
+    
+    /// 设置已添加上游的优先层级
+    pub fn set_tier(&mut self, name: &str, tier: u8) -> Result<()> {
+        let spec = self.specs.iter_mut()
+            .find(|spec| spec.name == name)
+            .ok_or_else(|| DnsError::InvalidConfig(format!("Upstream '{}' not found", name)))?;
+        spec.tier = tier;
+        Ok(())
+    }
+    
+    /// 设置已添加上游的地址族偏好
+    pub fn set_address_family(&mut self, name: &str, preference: Option<AfPreference>) -> Result<()> {
+        let spec = self.specs.iter_mut()
+            .find(|spec| spec.name == name)
+            .ok_or_else(|| DnsError::InvalidConfig(format!("Upstream '{}' not found", name)))?;
+        spec.address_family = preference;
+        Ok(())
+    }
+    
+    /// 设置已添加上游的请求特性开关
+    pub fn set_features(&mut self, name: &str, features: UpstreamFeatures) -> Result<()> {
+        features.validate()?;
+        let spec = self.specs.iter_mut()
+            .find(|spec| spec.name == name)
+            .ok_or_else(|| DnsError::InvalidConfig(format!("Upstream '{}' not found", name)))?;
+        spec.features = features;
+        Ok(())
+    }
+    
+    /// 设置已添加上游的在途查询上限及达到上限时的处理方式
+    pub fn set_max_inflight(&mut self, name: &str, max_inflight: Option<usize>, behavior: QueueBehavior) -> Result<()> {
+        check_max_inflight(name, max_inflight)?;
+        let spec = self.specs.iter_mut()
+            .find(|spec| spec.name == name)
+            .ok_or_else(|| DnsError::InvalidConfig(format!("Upstream '{}' not found", name)))?;
+        spec.max_inflight = max_inflight;
+        spec.queue_behavior = behavior;
+        Ok(())
+    }
+    
+    /// 替换已添加上游的标签
+    pub fn set_labels(&mut self, name: &str, labels: UpstreamLabels) -> Result<()> {
+        check_upstream_labels(&labels)
+            .map_err(|e| DnsError::InvalidConfig(format!("Invalid labels for upstream '{}': {}", name, e)))?;
+        let spec = self.specs.iter_mut()
+            .find(|spec| spec.name == name)
+            .ok_or_else(|| DnsError::InvalidConfig(format!("Upstream '{}' not found", name)))?;
+        spec.labels = labels;
+        Ok(())
+    }
+    
+    /// 设置已添加上游的p95响应时间目标，`None` 表示取消
+    pub fn set_slo(&mut self, name: &str, slo: Option<SloConfig>) -> Result<()> {
+        if let Some(slo) = &slo {
+            slo.validate(name)?;
+        }
+        let spec = self.specs.iter_mut()
+            .find(|spec| spec.name == name)
+            .ok_or_else(|| DnsError::InvalidConfig(format!("Upstream '{}' not found", name)))?;
+        spec.slo = slo;
+        Ok(())
+    }
+    
     /// 获取所有上游规格
     pub fn get_specs(&self) -> &[UpstreamSpec] {
         &self.specs
@@ -316,6 +561,137 @@ impl UpstreamManager {
             .filter(|spec| spec.transport_type == transport_type)
             .collect()
     }
+    
+    /// 校验上游列表
+    /// 
+    /// 名称必须合法且互不相同；同一协议和地址被重复注册时，`dedup` 为 `true` 则保留权重较高的
+    /// 定义（权重相同保留先注册的）并记录警告，否则报错。自定义传输各自是独立实例，不参与地址比较。
+    pub fn validate_upstreams(&mut self, dedup: bool) -> Result<()> {
+        let mut names: HashMap<&str, &UpstreamSpec> = HashMap::new();
+        for spec in &self.specs {
+            check_upstream_name(&spec.name).map_err(DnsError::InvalidConfig)?;
+            if let Some(previous) = names.insert(&spec.name, spec) {
+                return Err(DnsError::InvalidConfig(format!(
+                    "Duplicate upstream name '{}': defined as {} and as {}",
+                    spec.name, previous.describe(), spec.describe()
+                )));
+            }
+        }
+        
+        let mut kept: Vec<UpstreamSpec> = Vec::with_capacity(self.specs.len());
+        let mut endpoints: HashMap<(UpstreamType, String), usize> = HashMap::new();
+        for spec in &self.specs {
+            let Some(key) = self.endpoint_key(spec) else {
+                kept.push(spec.clone());
+                continue;
+            };
+            let Some(&index) = endpoints.get(&key) else {
+                endpoints.insert(key, kept.len());
+                kept.push(spec.clone());
+                continue;
+            };
+            
+            let previous = &kept[index];
+            if !dedup {
+                return Err(DnsError::InvalidConfig(format!(
+                    "Upstream '{}' and '{}' point at the same server: {} and {}",
+                    previous.name, spec.name, previous.describe(), spec.describe()
+                )));
+            }
+            if spec.weight > previous.weight {
+                dns_warn!("上游 '{}' 与 '{}' 指向同一服务器，保留权重较高的 '{}'", previous.name, spec.name, spec.name);
+                kept[index] = spec.clone();
+            } else {
+                dns_warn!("上游 '{}' 与 '{}' 指向同一服务器，保留权重较高的 '{}'", previous.name, spec.name, previous.name);
+            }
+        }
+        self.specs = kept;
+        Ok(())
+    }
+    
+    /// 上游端点的比较键：协议 + 规范化地址（补全默认端口、忽略大小写），自定义传输返回 `None`
+    fn endpoint_key(&self, spec: &UpstreamSpec) -> Option<(UpstreamType, String)> {
+        let address = spec.server.trim().to_ascii_lowercase();
+        let address = match spec.transport_type {
+            UpstreamType::Custom => return None,
+            UpstreamType::DoH => address.trim_end_matches('/').to_string(),
+            UpstreamType::Udp | UpstreamType::Tcp | UpstreamType::DoT => {
+                let default_port = self.handlers.get(&spec.transport_type)
+                    .map(|handler| handler.default_port())
+                    .unwrap_or(53);
+                match parse_server_address(&address, default_port) {
+                    Ok((host, port)) => format!("{}:{}", host, port),
+                    Err(_) => address,
+                }
+            },
+        };
+        Some((spec.transport_type.clone(), address))
+    }
+}
+
+/// 检查上游名称：不能为空，不能包含控制字符、'/' 或 ':'
+/// 
+/// 名称会作为统计信息的键并出现在日志中，返回值为不合法的原因
+pub fn check_upstream_name(name: &str) -> std::result::Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("Upstream name cannot be empty".to_string());
+    }
+    if let Some(c) = name.chars().find(|c| c.is_control() || *c == '/' || *c == ':') {
+        return Err(format!(
+            "Upstream name '{}' contains invalid character {:?} (control characters, '/' and ':' are not allowed)",
+            name.escape_debug(), c
+        ));
+    }
+    Ok(())
+}
+
+/// 检查标签键能否作为OpenMetrics标签名：字母或下划线开头，只含ASCII字母、数字和下划线，
+/// 不以 `__` 开头（保留给采集端），也不能与上游指标已有的标签同名
+pub fn check_label_key(key: &str) -> std::result::Result<(), String> {
+    let valid = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!(
+            "Label key '{}' must start with a letter or '_' and contain only ASCII letters, digits and '_'",
+            key.escape_debug()
+        ));
+    }
+    if key.len() > MAX_LABEL_KEY_LEN {
+        return Err(format!("Label key '{}' is longer than {} bytes", key, MAX_LABEL_KEY_LEN));
+    }
+    if key.starts_with("__") {
+        return Err(format!("Label key '{}' cannot start with '__'", key));
+    }
+    if RESERVED_LABEL_KEYS.contains(&key) {
+        return Err(format!("Label key '{}' is reserved by the upstream metrics", key));
+    }
+    Ok(())
+}
+
+/// 检查上游标签：数量不超过 [`MAX_UPSTREAM_LABELS`]，键见 [`check_label_key`]，
+/// 值不超过 [`MAX_LABEL_VALUE_LEN`] 字节且不含控制字符
+pub fn check_upstream_labels(labels: &UpstreamLabels) -> std::result::Result<(), String> {
+    if labels.len() > MAX_UPSTREAM_LABELS {
+        return Err(format!("Too many labels ({}), at most {} are allowed", labels.len(), MAX_UPSTREAM_LABELS));
+    }
+    for (key, value) in labels {
+        check_label_key(key)?;
+        if value.len() > MAX_LABEL_VALUE_LEN {
+            return Err(format!("Value of label '{}' is longer than {} bytes", key, MAX_LABEL_VALUE_LEN));
+        }
+        if value.chars().any(char::is_control) {
+            return Err(format!("Value of label '{}' contains control characters", key));
+        }
+    }
+    Ok(())
+}
+
+/// 在途查询上限不能为0
+fn check_max_inflight(name: &str, max_inflight: Option<usize>) -> Result<()> {
+    if max_inflight == Some(0) {
+        return Err(DnsError::InvalidConfig(format!("max_inflight for upstream '{}' must be at least 1", name)));
+    }
+    Ok(())
 }
 
 // 解析函数已移至 crate::utils 模块，避免代码重复
@@ -331,6 +707,21 @@ impl UpstreamSpec {
             resolved_ip: None,
             weight: 1,
             region: None,
+            headers: Vec::new(),
+            query_params: Vec::new(),
+            http_version: HttpVersionPref::Auto,
+            ecs_policy: EcsPolicy::Forward,
+            tier: 0,
+            features: UpstreamFeatures::default(),
+            max_inflight: None,
+            queue_behavior: QueueBehavior::Reject,
+            address_family: None,
+            slo: None,
+            timeout: None,
+            tls_server_name: None,
+            doh_method: HttpMethod::POST,
+            record_types: None,
+            labels: UpstreamLabels::new(),
         }
     }
     
@@ -343,6 +734,21 @@ impl UpstreamSpec {
             resolved_ip: None,
             weight: 1,
             region: None,
+            headers: Vec::new(),
+            query_params: Vec::new(),
+            http_version: HttpVersionPref::Auto,
+            ecs_policy: EcsPolicy::Forward,
+            tier: 0,
+            features: UpstreamFeatures::default(),
+            max_inflight: None,
+            queue_behavior: QueueBehavior::Reject,
+            address_family: None,
+            slo: None,
+            timeout: None,
+            tls_server_name: None,
+            doh_method: HttpMethod::POST,
+            record_types: None,
+            labels: UpstreamLabels::new(),
         }
     }
     
@@ -355,6 +761,21 @@ impl UpstreamSpec {
             resolved_ip: None,
             weight: 1,
             region: None,
+            headers: Vec::new(),
+            query_params: Vec::new(),
+            http_version: HttpVersionPref::Auto,
+            ecs_policy: EcsPolicy::Forward,
+            tier: 0,
+            features: UpstreamFeatures::default(),
+            max_inflight: None,
+            queue_behavior: QueueBehavior::Reject,
+            address_family: None,
+            slo: None,
+            timeout: None,
+            tls_server_name: None,
+            doh_method: HttpMethod::POST,
+            record_types: None,
+            labels: UpstreamLabels::new(),
         }
     }
     
@@ -367,6 +788,48 @@ impl UpstreamSpec {
             resolved_ip: None,
             weight: 1,
             region: None,
+            headers: Vec::new(),
+            query_params: Vec::new(),
+            http_version: HttpVersionPref::Auto,
+            ecs_policy: EcsPolicy::Forward,
+            tier: 0,
+            features: UpstreamFeatures::default(),
+            max_inflight: None,
+            queue_behavior: QueueBehavior::Reject,
+            address_family: None,
+            slo: None,
+            timeout: None,
+            tls_server_name: None,
+            doh_method: HttpMethod::POST,
+            record_types: None,
+            labels: UpstreamLabels::new(),
+        }
+    }
+    
+    /// 创建自定义传输上游配置，`server` 字段仅作展示用
+    pub fn custom(name: String, endpoint: String) -> Self {
+        Self {
+            name,
+            transport_type: UpstreamType::Custom,
+            server: endpoint,
+            resolved_ip: None,
+            weight: 1,
+            region: None,
+            headers: Vec::new(),
+            query_params: Vec::new(),
+            http_version: HttpVersionPref::Auto,
+            ecs_policy: EcsPolicy::Forward,
+            tier: 0,
+            features: UpstreamFeatures::default(),
+            max_inflight: None,
+            queue_behavior: QueueBehavior::Reject,
+            address_family: None,
+            slo: None,
+            timeout: None,
+            tls_server_name: None,
+            doh_method: HttpMethod::POST,
+            record_types: None,
+            labels: UpstreamLabels::new(),
         }
     }
     
@@ -387,4 +850,116 @@ impl UpstreamSpec {
         self.region = Some(region);
         self
     }
+    
+    /// 设置DoH请求附加的HTTP请求头
+    pub fn with_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.headers = headers;
+        self
+    }
+    
+    /// 设置DoH请求附加的URL查询参数
+    pub fn with_query_params(mut self, query_params: Vec<(String, String)>) -> Self {
+        self.query_params = query_params;
+        self
+    }
+    
+    /// 设置DoH使用的HTTP版本
+    pub fn with_http_version(mut self, http_version: HttpVersionPref) -> Self {
+        self.http_version = http_version;
+        self
+    }
+    
+    /// 设置向该上游发送客户端子网（ECS）的方式
+    pub fn with_ecs_policy(mut self, ecs_policy: EcsPolicy) -> Self {
+        self.ecs_policy = ecs_policy;
+        self
+    }
+    
+    /// 设置优先层级（0最优先）
+    pub fn with_tier(mut self, tier: u8) -> Self {
+        self.tier = tier;
+        self
+    }
+    
+    /// 设置发往该上游时EDNS、DO位和大小写随机化的开关
+    pub fn with_features(mut self, features: UpstreamFeatures) -> Self {
+        self.features = features;
+        self
+    }
+    
+    /// 设置同时发往该上游的查询上限及达到上限时的处理方式
+    pub fn with_max_inflight(mut self, max_inflight: usize, behavior: QueueBehavior) -> Self {
+        self.max_inflight = Some(max_inflight);
+        self.queue_behavior = behavior;
+        self
+    }
+    
+    /// 设置该上游的p95响应时间目标，需要启用上游监控
+    pub fn with_slo(mut self, slo: SloConfig) -> Self {
+        self.slo = Some(slo);
+        self
+    }
+    
+    /// 设置该上游的查询超时，覆盖解析器的 `default_timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    
+    /// 设置DoT握手使用的SNI和证书校验名称（连接IP地址形式的DoT上游、证书签给另一个名称时使用）
+    pub fn with_tls_server_name(mut self, server_name: String) -> Self {
+        self.tls_server_name = Some(server_name);
+        self
+    }
+    
+    /// 设置DoH请求使用的HTTP方法
+    pub fn with_doh_method(mut self, method: HttpMethod) -> Self {
+        self.doh_method = method;
+        self
+    }
+    
+    /// 只把这些记录类型的查询发往该上游
+    pub fn with_record_types(mut self, record_types: Vec<RecordType>) -> Self {
+        self.record_types = Some(record_types);
+        self
+    }
+    
+    /// 添加一个标签，同名的键覆盖原来的值
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.insert(key.into(), value.into());
+        self
+    }
+    
+    /// 替换全部标签
+    pub fn with_labels(mut self, labels: UpstreamLabels) -> Self {
+        self.labels = labels;
+        self
+    }
+    
+    /// 检查超时、SNI、记录类型和标签这几项逐上游选项
+    pub(crate) fn validate_options(&self) -> Result<()> {
+        if self.timeout == Some(Duration::ZERO) {
+            return Err(DnsError::InvalidConfig(format!("Timeout for upstream '{}' must be greater than zero", self.name)));
+        }
+        if let Some(server_name) = &self.tls_server_name {
+            if self.transport_type != UpstreamType::DoT {
+                return Err(DnsError::InvalidConfig(format!(
+                    "Upstream '{}' sets a TLS server name, which only applies to DoT", self.name
+                )));
+            }
+            crate::utils::validate_domain(server_name)
+                .map_err(|e| DnsError::InvalidConfig(format!("Invalid TLS server name for upstream '{}': {}", self.name, e)))?;
+        }
+        if self.record_types.as_ref().is_some_and(Vec::is_empty) {
+            return Err(DnsError::InvalidConfig(format!("Record types for upstream '{}' cannot be empty", self.name)));
+        }
+        check_upstream_labels(&self.labels)
+            .map_err(|e| DnsError::InvalidConfig(format!("Invalid labels for upstream '{}': {}", self.name, e)))?;
+        Ok(())
+    }
+    
+    /// 用于错误信息的简短描述
+    fn describe(&self) -> String {
+        format!("{:?} {} (weight {})", self.transport_type, self.server, self.weight)
+    }
 }
\ No newline at end of file
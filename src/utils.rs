@@ -44,6 +44,13 @@ pub fn parse_url_components(url: &str) -> Result<(String, u16)> {
 /// 
 /// 这是一个更简单的实现，用于替换resolver.rs中的内联解析逻辑
 pub fn parse_simple_server_address(server: &str, default_port: u16) -> (String, u16) {
+    // IPv6地址：不带端口的原样使用，带端口的须写成 `[地址]:端口`
+    if server.parse::<std::net::Ipv6Addr>().is_ok() {
+        return (server.to_string(), default_port);
+    }
+    if let Ok(std::net::SocketAddr::V6(address)) = server.parse::<std::net::SocketAddr>() {
+        return (address.ip().to_string(), address.port());
+    }
     if server.contains(':') {
         let parts: Vec<&str> = server.split(':').collect();
         let port = parts.get(1)
@@ -72,6 +79,76 @@ pub fn validate_https_url(url: &str) -> Result<()> {
     Ok(())
 }
 
+/// 规范化DNS名称：ASCII字母转小写，去掉末尾的点
+/// 
+/// DNS名称不区分大小写（RFC 4343），`EXAMPLE.COM.`、`example.com` 和 `eXaMpLe.CoM`
+/// 规范化后相同。缓存键、转发规则等按名称查找的地方都用它统一写法；根域名规范化为空字符串
+pub fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// 比较两个DNS名称是否相同：不区分ASCII大小写，忽略末尾的点，不分配内存
+pub fn names_equal(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+/// `name` 是否是 `parent` 的子域名（不含 `parent` 本身）：按标签边界比较，不区分ASCII大小写，忽略末尾的点
+/// 
+/// `a.b.corp` 是 `corp` 的子域名，`notcorp` 不是；根域名（空字符串）是所有非根名称的父域
+pub fn is_subdomain(name: &str, parent: &str) -> bool {
+    let (name, parent) = (name.trim_end_matches('.'), parent.trim_end_matches('.'));
+    if parent.is_empty() {
+        return !name.is_empty();
+    }
+    name.len() > parent.len()
+        && name.as_bytes()[name.len() - parent.len() - 1] == b'.'
+        && name[name.len() - parent.len()..].eq_ignore_ascii_case(parent)
+}
+
+/// 名称本身是IP地址字面量时返回该地址
+/// 
+/// 接受 `192.0.2.1`、`2001:db8::1` 和带方括号的 `[2001:db8::1]`，两端的空白被忽略；
+/// 带末尾点的写法（`192.0.2.1.`）是合法的DNS名称，不视为地址
+pub fn parse_ip_literal(name: &str) -> Option<std::net::IpAddr> {
+    let name = name.trim();
+    match name.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+        Some(inner) => inner.parse::<std::net::Ipv6Addr>().ok().map(std::net::IpAddr::V6),
+        None => name.parse().ok(),
+    }
+}
+
+/// 检查查询名称：非空，编码后不超过255字节，每个标签1到63字节，不含空白和控制字符
+///
+/// 允许末尾的点、单独的 `.`（根域名）和IP地址字面量；不限制字符集，`_dmarc.example.com` 之类的名称同样通过
+pub fn validate_domain(name: &str) -> Result<()> {
+    let invalid = |reason: &str| Err(DnsError::Parse(format!(
+        "Invalid domain name '{}': {}", name.chars().take(64).collect::<String>(), reason
+    )));
+    if name.is_empty() {
+        return invalid("empty name");
+    }
+    if name == "." || parse_ip_literal(name).is_some() {
+        return Ok(());
+    }
+    if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return invalid("contains whitespace or control characters");
+    }
+    let relative = name.strip_suffix('.').unwrap_or(name);
+    // 每个标签一个长度字节，再加结尾的根标签
+    if relative.len() + 2 > crate::transport::udp::MAX_NAME_WIRE_LEN {
+        return invalid("longer than 255 bytes on the wire");
+    }
+    for label in relative.split('.') {
+        if label.is_empty() {
+            return invalid("empty label");
+        }
+        if label.len() > 63 {
+            return invalid("label longer than 63 bytes");
+        }
+    }
+    Ok(())
+}
+
 /// 获取用户代理字符串
 pub fn get_user_agent() -> String {
     // 检查是否在OrniDNS上下文中运行
@@ -103,6 +180,8 @@ mod tests {
     fn test_parse_simple_server_address() {
         assert_eq!(parse_simple_server_address("example.com", 53), ("example.com".to_string(), 53));
         assert_eq!(parse_simple_server_address("example.com:8080", 53), ("example.com".to_string(), 8080));
+        assert_eq!(parse_simple_server_address("2001:db8::53", 53), ("2001:db8::53".to_string(), 53));
+        assert_eq!(parse_simple_server_address("[2001:db8::53]:5353", 53), ("2001:db8::53".to_string(), 5353));
     }
 
     #[test]
@@ -112,6 +191,49 @@ mod tests {
         assert!(validate_https_url("").is_err());
     }
 
+    #[test]
+    fn test_normalize_name() {
+        for name in ["EXAMPLE.COM.", "example.com", "eXaMpLe.CoM"] {
+            assert_eq!(normalize_name(name), "example.com");
+            assert!(names_equal(name, "Example.Com."));
+        }
+        assert_eq!(normalize_name("."), "");
+        assert!(names_equal(".", ""));
+        assert!(!names_equal("example.com", "www.example.com"));
+    }
+
+    #[test]
+    fn test_is_subdomain() {
+        assert!(is_subdomain("A.B.Corp.", "corp"));
+        assert!(is_subdomain("www.example.com", "EXAMPLE.com."));
+        assert!(!is_subdomain("corp", "corp"));
+        assert!(!is_subdomain("notcorp", "corp"));
+        assert!(is_subdomain("corp", ""));
+        assert!(!is_subdomain(".", ""));
+    }
+
+    #[test]
+    fn test_parse_ip_literal() {
+        assert_eq!(parse_ip_literal("192.0.2.1"), Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(parse_ip_literal(" 2001:db8::1 "), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(parse_ip_literal("[2001:DB8::1]"), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(parse_ip_literal("[192.0.2.1]"), None);
+        assert_eq!(parse_ip_literal("192.0.2.1."), None);
+        assert_eq!(parse_ip_literal("example.com"), None);
+    }
+
+    #[test]
+    fn test_validate_domain() {
+        for name in ["example.com", "example.com.", ".", "_dmarc.example.com", "2001:db8::1", &"a".repeat(63)] {
+            assert!(validate_domain(name).is_ok(), "{}", name);
+        }
+        let longest = ["a".repeat(63), "b".repeat(63), "c".repeat(63), "d".repeat(61)].join(".");
+        assert!(validate_domain(&longest).is_ok());
+        for name in ["", "a..b", ".example.com", "exa mple.com", "a\0b", &"a".repeat(64), &format!("{}e", longest)] {
+            assert!(validate_domain(name).is_err(), "{:?}", name);
+        }
+    }
+
     #[test]
     fn test_get_user_agent() {
         let ua = get_user_agent();
@@ -0,0 +1,113 @@
+//! 双栈上游的地址族偏好：主机名同时解析出IPv4和IPv6地址，IPv6上没有监听时偏好IPv6的上游透明地改用IPv4，
+//! IPv6路径被单独标记为不健康，上游整体保持可用
+
+use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+use rat_quickdns::transport::mock::MockBootstrap;
+use rat_quickdns::transport::test_server::TestDnsServer;
+use rat_quickdns::{AddressFamily, AfPreference, DnsResolverBuilder, QueryStrategy, SmartDnsResolver};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::Duration;
+
+const HOST: &str = "dual.example.test";
+
+/// 测试服务器只监听127.0.0.1，引导解析器把主机名解析为 [127.0.0.1, ::1]
+async fn dual_homed() -> (TestDnsServer, MockBootstrap) {
+    let server = TestDnsServer::start().await.unwrap();
+    server.add_a("www.example.test", &[Ipv4Addr::new(192, 0, 2, 10)], 300);
+    let bootstrap = MockBootstrap::new().with_host(HOST, &[IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)]);
+    (server, bootstrap)
+}
+
+fn builder(bootstrap: &MockBootstrap) -> DnsResolverBuilder {
+    DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_retry_count(0)
+        .with_timeout(Duration::from_millis(300))
+        .with_upstream_monitoring(true)
+        .with_bootstrap_resolver(Arc::new(bootstrap.clone()))
+}
+
+async fn query(resolver: &SmartDnsResolver) {
+    let response = resolver.query(DnsQueryRequest::new("www.example.test", DnsRecordType::A)).await.unwrap();
+    assert!(response.success, "{:?}", response.error);
+}
+
+async fn assert_fell_back_to_v4(resolver: &SmartDnsResolver, name: &str) {
+    let status = resolver.get_upstream_status().await;
+    let status = status.iter().find(|status| status.name == name).unwrap();
+    assert!(status.is_available, "{:?}", status);
+    let families = status.address_families.unwrap();
+    assert_eq!(families.preference, Some(AfPreference::PreferV6));
+    assert_eq!(families.active, Some(AddressFamily::V4));
+    // IPv6只在第一次查询时尝试过，之后直接使用IPv4
+    assert_eq!((families.v6.failures, families.v6.successes), (1, 0), "{:?}", families);
+    assert!(!families.v6.healthy);
+    assert_eq!(families.v6.success_rate(), Some(0.0));
+    assert_eq!((families.v4.successes, families.v4.failures), (3, 0), "{:?}", families);
+    assert!(families.v4.healthy);
+    assert_eq!(families.family(AddressFamily::V4).success_rate(), Some(1.0));
+    assert_eq!(status.consecutive_failures, 0);
+    assert_eq!(resolver.address_family_stats()[name], families);
+
+    let json = status.to_json_value();
+    assert_eq!(json["active_family"], "V4");
+    assert_eq!(json["family_success_rates"]["v6"], 0.0);
+}
+
+#[tokio::test]
+async fn test_prefer_v6_udp_upstream_falls_back_to_v4() {
+    let (server, bootstrap) = dual_homed().await;
+    let resolver = builder(&bootstrap)
+        .with_address_family_preference(AfPreference::PreferV6)
+        .add_udp_upstream("dual", format!("{}:{}", HOST, server.port()))
+        .build()
+        .await
+        .unwrap();
+
+    for _ in 0..3 {
+        query(&resolver).await;
+    }
+    assert_fell_back_to_v4(&resolver, "dual").await;
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn test_per_upstream_preference_applies_to_tcp() {
+    let (server, bootstrap) = dual_homed().await;
+    let resolver = builder(&bootstrap)
+        .with_address_family_preference(AfPreference::V4Only)
+        .add_tcp_upstream("dual-tcp", format!("{}:{}", HOST, server.port()))
+        .with_upstream_address_family("dual-tcp", AfPreference::PreferV6)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let effective = resolver.effective_config();
+    assert_eq!(effective.upstreams[0].address_family, Some(AfPreference::PreferV6));
+
+    for _ in 0..3 {
+        query(&resolver).await;
+    }
+    assert_fell_back_to_v4(&resolver, "dual-tcp").await;
+}
+
+#[tokio::test]
+async fn test_v6_only_never_dials_v4() {
+    let (server, bootstrap) = dual_homed().await;
+    let resolver = builder(&bootstrap)
+        .with_address_family_preference(AfPreference::V6Only)
+        .add_udp_upstream("v6-only", format!("{}:{}", HOST, server.port()))
+        .build()
+        .await
+        .unwrap();
+
+    let response = resolver.query(DnsQueryRequest::new("www.example.test", DnsRecordType::A)).await.unwrap();
+    assert!(!response.success);
+    assert!(server.requests().is_empty());
+    let families = resolver.address_family_stats()["v6-only"];
+    assert_eq!(families.active, None);
+    assert_eq!((families.v4.successes, families.v4.failures), (0, 0));
+    assert_eq!(families.v6.failures, 1);
+}
@@ -0,0 +1,82 @@
+//! 缓存命中时的内存分配次数
+//!
+//! 用计数分配器比较复制式读取（`get_for_client`）与共享式读取（`get_shared_for_client`），
+//! 确认命中缓存不再逐条复制记录
+
+use rat_quickdns::resolver::cache::DnsCache;
+use rat_quickdns::{DnsResponseBuilder, QClass, Query, RecordType};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::time::Duration;
+
+struct CountingAllocator;
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn record_allocation() {
+    if COUNTING.try_with(Cell::get).unwrap_or(false) {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_allocation();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record_allocation();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// 统计 `f` 在当前线程上的分配次数
+fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    ALLOCATIONS.with(|count| count.set(0));
+    COUNTING.with(|counting| counting.set(true));
+    let value = f();
+    COUNTING.with(|counting| counting.set(false));
+    (value, ALLOCATIONS.with(Cell::get))
+}
+
+#[test]
+fn test_shared_cache_read_allocates_far_less_than_clone() {
+    let name = "txt-heavy.example.com";
+    let mut builder = DnsResponseBuilder::new().add_query(name.to_string(), RecordType::TXT, QClass::IN);
+    for i in 0..20 {
+        builder = builder.add_txt_answer(
+            name.to_string(),
+            300,
+            vec![format!("v=spf1 include:_spf{}.example.com ~all", i), "x".repeat(200)],
+        );
+    }
+    let cache = DnsCache::new(Duration::from_secs(3600));
+    let query = Query { name: name.to_string(), qtype: RecordType::TXT, qclass: QClass::IN };
+    cache.insert(query.clone(), builder.build());
+
+    let (cloned, cloned_allocations) = count_allocations(|| cache.get_for_client(&query, None));
+    let (shared, shared_allocations) = count_allocations(|| cache.get_shared_for_client(&query, None));
+    let cloned = cloned.unwrap();
+    let shared = shared.unwrap();
+
+    assert_eq!(shared.answers.len(), 20);
+    let shared = shared.into_response();
+    assert!(shared.answers.iter().map(|r| &r.data).eq(cloned.answers.iter().map(|r| &r.data)));
+    assert!(
+        shared_allocations * 5 < cloned_allocations,
+        "shared read allocated {} times, cloned read {}",
+        shared_allocations,
+        cloned_allocations
+    );
+}
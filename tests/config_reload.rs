@@ -0,0 +1,182 @@
+//! 运行中重新加载严格配置：持续查询期间替换上游集合不产生失败的查询，保留的上游沿用连接和性能指标，
+//! 无效配置不修改解析器，TTL上限收紧时清空缓存
+
+use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+use rat_quickdns::transport::test_server::TestDnsServer;
+use rat_quickdns::{DnsResolverBuilder, SmartDnsResolver, StrictDnsConfig};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const NAME: &str = "www.example.test";
+
+async fn server() -> TestDnsServer {
+    let server = TestDnsServer::start().await.unwrap();
+    server.add_a(NAME, &[Ipv4Addr::new(192, 0, 2, 10)], 300);
+    server
+}
+
+/// 轮询使用全部上游的严格配置，`upstreams` 为 (名称, 测试服务器)
+fn config(upstreams: &[(&str, &TestDnsServer)], enable_cache: bool, max_ttl_secs: u64) -> StrictDnsConfig {
+    let mut toml = format!(
+        r#"
+strategy = "RoundRobin"
+default_timeout = {{ secs = 1, nanos = 0 }}
+retry_count = 1
+enable_cache = {enable_cache}
+max_cache_ttl = {{ secs = 600, nanos = 0 }}
+enable_upstream_monitoring = false
+upstream_monitoring_interval = {{ secs = 30, nanos = 0 }}
+port = 53
+concurrent_queries = 8
+buffer_size = 4096
+enable_stats = true
+emergency_threshold = 0.3
+max_ttl = {{ secs = {max_ttl_secs}, nanos = 0 }}
+logger_init_strategy = "None"
+"#
+    );
+    for (name, server) in upstreams {
+        toml.push_str(&format!(
+            "\n[[upstreams]]\nname = \"{}\"\naddress = \"{}\"\nprotocol = \"udp\"\nweight = 1\nenabled = true\n",
+            name,
+            server.addr()
+        ));
+    }
+    toml::from_str(&toml).unwrap()
+}
+
+async fn build(config: &StrictDnsConfig) -> SmartDnsResolver {
+    DnsResolverBuilder::from_strict_config(config, false, "global")
+        .unwrap()
+        .build()
+        .await
+        .unwrap()
+}
+
+async fn upstream_names(resolver: &SmartDnsResolver) -> Vec<String> {
+    resolver.get_upstream_status().await.into_iter().map(|status| status.name).collect()
+}
+
+async fn total_queries(resolver: &SmartDnsResolver, name: &str) -> u64 {
+    resolver.get_upstream_status().await.into_iter()
+        .find(|status| status.name == name)
+        .map(|status| status.total_queries)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_reload_under_load_fails_no_queries() {
+    let (kept, removed, added) = (server().await, server().await, server().await);
+    let resolver = Arc::new(build(&config(&[("kept", &kept), ("removed", &removed)], false, 3600)).await);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let succeeded = Arc::new(AtomicUsize::new(0));
+    let failed = Arc::new(AtomicUsize::new(0));
+    let workers: Vec<_> = (0..8).map(|_| {
+        let (resolver, stop, succeeded, failed) = (resolver.clone(), stop.clone(), succeeded.clone(), failed.clone());
+        tokio::spawn(async move {
+            while !stop.load(Ordering::Relaxed) {
+                match resolver.query(DnsQueryRequest::new(NAME, DnsRecordType::A)).await {
+                    Ok(response) if response.success => succeeded.fetch_add(1, Ordering::Relaxed),
+                    _ => failed.fetch_add(1, Ordering::Relaxed),
+                };
+            }
+        })
+    }).collect();
+
+    while succeeded.load(Ordering::Relaxed) < 200 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    let kept_before = total_queries(&resolver, "kept").await;
+    assert!(kept_before > 0);
+    assert!(removed.request_count() > 0);
+
+    let report = resolver.reload(config(&[("kept", &kept), ("added", &added)], false, 3600)).await.unwrap();
+    assert_eq!(report.added, vec!["added".to_string()]);
+    assert_eq!(report.removed, vec!["removed".to_string()]);
+    assert_eq!(report.unchanged, vec!["kept".to_string()]);
+    assert!(report.reconnected.is_empty() && report.updated.is_empty());
+    assert!(report.changed_settings.is_empty());
+    assert!(report.undrained.is_empty());
+    assert!(!report.cache_cleared);
+    assert!(report.changes.contains(&"upstream added added".to_string()), "{:?}", report.changes);
+    assert!(report.changes.contains(&"upstream removed removed".to_string()), "{:?}", report.changes);
+
+    // 移除的上游排空后不再收到查询，新增的上游开始接收查询
+    let removed_after_reload = removed.request_count();
+    let at_reload = succeeded.load(Ordering::Relaxed);
+    while succeeded.load(Ordering::Relaxed) < at_reload + 200 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    stop.store(true, Ordering::Relaxed);
+    for worker in workers {
+        worker.await.unwrap();
+    }
+
+    assert_eq!(failed.load(Ordering::Relaxed), 0);
+    assert_eq!(removed.request_count(), removed_after_reload);
+    assert!(added.request_count() > 0);
+    assert_eq!(upstream_names(&resolver).await, vec!["kept".to_string(), "added".to_string()]);
+    assert_eq!(resolver.effective_config().upstreams.len(), 2);
+    // 保留的上游的性能指标没有重置
+    assert!(total_queries(&resolver, "kept").await > kept_before);
+}
+
+#[tokio::test]
+async fn test_rejected_reload_leaves_resolver_unchanged() {
+    let (first, second) = (server().await, server().await);
+    let original = config(&[("first", &first)], false, 3600);
+    let resolver = build(&original).await;
+    let before = resolver.effective_config();
+
+    // 没有上游
+    let mut empty = original.clone();
+    empty.upstreams.clear();
+    assert!(resolver.reload(empty).await.is_err());
+    // 不能在运行时修改的设置
+    let mut fixed = config(&[("first", &first), ("second", &second)], false, 3600);
+    fixed.concurrent_queries = 16;
+    let error = resolver.reload(fixed).await.unwrap_err().to_string();
+    assert!(error.contains("concurrent_queries"), "{}", error);
+    // 重复的上游名称
+    let duplicated = config(&[("first", &first), ("first", &second)], false, 3600);
+    assert!(resolver.reload(duplicated).await.is_err());
+
+    assert!(resolver.effective_config().diff(&before).is_empty());
+    assert_eq!(upstream_names(&resolver).await, vec!["first".to_string()]);
+    let response = resolver.query(DnsQueryRequest::new(NAME, DnsRecordType::A)).await.unwrap();
+    assert!(response.success);
+
+    // 相同的配置不做任何修改
+    let report = resolver.reload(original).await.unwrap();
+    assert!(report.is_unchanged(), "{:?}", report);
+    assert!(report.changes.is_empty());
+}
+
+#[tokio::test]
+async fn test_cache_survives_reload_unless_ttl_ceiling_tightens() {
+    let upstream = server().await;
+    let resolver = build(&config(&[("local", &upstream)], true, 3600)).await;
+    let query = || resolver.query(DnsQueryRequest::new(NAME, DnsRecordType::A));
+
+    assert!(query().await.unwrap().success);
+    assert!(query().await.unwrap().success);
+    assert_eq!(upstream.request_count(), 1);
+
+    // 放宽TTL上限时缓存保留
+    let report = resolver.reload(config(&[("local", &upstream)], true, 7200)).await.unwrap();
+    assert_eq!(report.changed_settings, vec!["max_ttl_secs".to_string()]);
+    assert!(!report.cache_cleared);
+    assert!(query().await.unwrap().success);
+    assert_eq!(upstream.request_count(), 1);
+
+    // 收紧TTL上限时已缓存的条目可能超出上限，清空缓存
+    let report = resolver.reload(config(&[("local", &upstream)], true, 60)).await.unwrap();
+    assert!(report.cache_cleared);
+    let response = query().await.unwrap();
+    assert!(response.success);
+    assert_eq!(upstream.request_count(), 2);
+    assert!(response.records.iter().all(|record| record.ttl <= 60), "{:?}", response.records);
+}
@@ -0,0 +1,118 @@
+//! 按主机名建立TCP连接：模拟上游给出多个地址，只有部分地址上有监听，
+//! 连接按RFC 8305的顺序回退到可用的地址，失败的尝试立即让位给下一个地址
+
+use rat_quickdns::builder::{connect_with_resolver, ConnectOptions};
+use rat_quickdns::transport::mock::MockTransport;
+use rat_quickdns::{AfPreference, DnsError, DnsResolverBuilder, NetworkErrorKind, QueryStrategy, RecordType, SmartDnsResolver};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+
+/// 127.0.0.0/8 中除127.0.0.1以外的地址上没有监听，连接被立即拒绝
+const REFUSED_V4: [Ipv4Addr; 2] = [Ipv4Addr::new(127, 0, 0, 2), Ipv4Addr::new(127, 0, 0, 3)];
+
+async fn resolver(mock: MockTransport) -> SmartDnsResolver {
+    DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("mock", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap()
+}
+
+async fn listener() -> (TcpListener, u16) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    (listener, port)
+}
+
+#[tokio::test]
+async fn test_falls_back_to_the_address_with_a_listener() {
+    let (listener, port) = listener().await;
+    let mock = MockTransport::new()
+        .with_a("svc.test", &[REFUSED_V4[0], REFUSED_V4[1], Ipv4Addr::LOCALHOST], 300)
+        .with_aaaa("svc.test", &[Ipv6Addr::LOCALHOST], 300);
+    let resolver = resolver(mock).await;
+
+    // 失败的尝试立即开始下一个，不等待尝试间隔
+    let opts = ConnectOptions::default().with_happy_eyeballs_delay(Duration::from_secs(5));
+    let started = Instant::now();
+    let (stream, accepted) = tokio::join!(connect_with_resolver(&resolver, "svc.test", port, opts), listener.accept());
+    let stream = stream.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(2), "took {:?}", started.elapsed());
+    assert_eq!(stream.peer_addr().unwrap(), SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+    assert_eq!(accepted.unwrap().1.ip(), Ipv4Addr::LOCALHOST);
+}
+
+#[tokio::test]
+async fn test_stalled_address_does_not_block_the_next_attempt() {
+    let (listener, port) = listener().await;
+    // 文档地址不可路由：连接要么挂起到单次时限，要么立即失败，两种情况下都应很快改连127.0.0.1
+    let mock = MockTransport::new()
+        .with_a("slow.test", &[Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::LOCALHOST], 300)
+        .with_aaaa("slow.test", &[], 300);
+    let resolver = resolver(mock).await;
+
+    let delay = Duration::from_millis(100);
+    let opts = ConnectOptions::default()
+        .with_per_attempt_timeout(Duration::from_secs(10))
+        .with_happy_eyeballs_delay(delay)
+        .with_af_preference(AfPreference::PreferV4);
+    let started = Instant::now();
+    let (stream, _) = tokio::join!(connect_with_resolver(&resolver, "slow.test", port, opts), listener.accept());
+    let stream = stream.unwrap();
+    assert!(started.elapsed() < delay + Duration::from_secs(2), "took {:?}", started.elapsed());
+    assert_eq!(stream.peer_addr().unwrap().ip(), Ipv4Addr::LOCALHOST);
+}
+
+#[tokio::test]
+async fn test_every_failed_address_is_reported_in_attempt_order() {
+    let (listener, port) = listener().await;
+    drop(listener);
+    let mock = MockTransport::new()
+        .with_a("down.test", &REFUSED_V4, 300)
+        .with_aaaa("down.test", &[Ipv6Addr::LOCALHOST], 300);
+    let down = resolver(mock).await;
+
+    let error = connect_with_resolver(&down, "down.test", port, ConnectOptions::default()).await.unwrap_err();
+    let DnsError::ConnectFailed { host, port: failed_port, attempts } = &error else {
+        panic!("unexpected error: {:?}", error);
+    };
+    assert_eq!((host.as_str(), *failed_port), ("down.test", port));
+    let tried: Vec<SocketAddr> = attempts.iter().map(|attempt| attempt.addr).collect();
+    assert_eq!(tried, vec![
+        SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+        SocketAddr::from((REFUSED_V4[0], port)),
+        SocketAddr::from((REFUSED_V4[1], port)),
+    ]);
+    assert_eq!(attempts[1].kind, NetworkErrorKind::ConnectionRefused);
+    assert_eq!(attempts[2].kind, NetworkErrorKind::ConnectionRefused);
+    assert!(error.to_string().contains("127.0.0.3"), "{}", error);
+
+    // 偏好不允许任何解析出的地址
+    let v6_only = ConnectOptions::default().with_af_preference(AfPreference::V6Only);
+    let mock = MockTransport::new()
+        .with_a("v4.test", &[Ipv4Addr::LOCALHOST], 300)
+        .with_aaaa("v4.test", &[], 300);
+    let error = connect_with_resolver(&resolver(mock).await, "v4.test", port, v6_only).await.unwrap_err();
+    assert!(matches!(&error, DnsError::ConnectFailed { attempts, .. } if attempts.is_empty()), "{:?}", error);
+}
+
+#[tokio::test]
+async fn test_resolution_errors_and_ip_literals() {
+    let (listener, port) = listener().await;
+    let mock = MockTransport::new()
+        .with_nxdomain("missing.test", RecordType::A)
+        .with_nxdomain("missing.test", RecordType::AAAA);
+    let resolver = resolver(mock.clone()).await;
+
+    let missing = connect_with_resolver(&resolver, "missing.test", port, ConnectOptions::default()).await;
+    assert!(matches!(missing, Err(DnsError::NxDomain)), "{:?}", missing.map(|_| ()));
+
+    // IP地址不经解析直接连接
+    let calls = mock.call_count();
+    let (stream, _) = tokio::join!(connect_with_resolver(&resolver, "127.0.0.1", port, ConnectOptions::default()), listener.accept());
+    stream.unwrap();
+    assert_eq!(mock.call_count(), calls);
+}
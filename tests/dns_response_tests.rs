@@ -3,7 +3,7 @@
 //! 测试所有DNS记录类型的响应创建和验证功能
 
 use rat_quickdns::{
-    DnsResponseBuilder, DnsResponseWrapper, RecordType, QClass, RecordData, ResponseCode
+    DnsResponseBuilder, DnsResponseWrapper, Opcode, RecordType, QClass, RecordData, ResponseCode
 };
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
@@ -579,10 +579,12 @@ fn test_response_structure_completeness() {
     
     // 验证响应结构的完整性
     assert!(response.flags.qr); // 必须是响应
-    assert_eq!(response.flags.opcode, 0); // 标准查询
+    assert_eq!(response.flags.opcode, Opcode::Query); // 标准查询
     assert!(response.flags.rd); // 期望递归
     assert!(response.flags.ra); // 递归可用
-    assert_eq!(response.flags.z, 0); // 保留位必须为0
+    assert!(!response.flags.reserved); // 保留位必须为0
+    assert!(!response.flags.ad); // 未经DNSSEC验证
+    assert!(!response.flags.cd); // 未禁用检查
     
     // 验证记录结构
     let answer = &response.answers[0];
@@ -0,0 +1,153 @@
+//! 构建前检查（`DnsResolverBuilder::validate_only`）
+//!
+//! 全部离线运行：检查不创建套接字、不解析上游地址，配置样例见 `tests/fixtures/strict_config.toml`
+
+use rat_quickdns::builder::types::DnsRecordType;
+use rat_quickdns::builder::{DomainRoute, DomainRouter};
+#[cfg(not(feature = "doh3"))]
+use rat_quickdns::transport::HttpVersionPref;
+use rat_quickdns::upstream_handler::UpstreamSpec;
+use rat_quickdns::{DnsError, DnsResolverBuilder, ProbeConfig, QueryStrategy, StrictDnsConfig};
+use std::time::Duration;
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn strict_config() -> StrictDnsConfig {
+    toml::from_str(&std::fs::read_to_string(fixture("strict_config.toml")).unwrap()).unwrap()
+}
+
+fn production_builder() -> DnsResolverBuilder {
+    DnsResolverBuilder::from_strict_config(&strict_config(), true, "CN")
+        .unwrap()
+        .disable_logger_init()
+        .add_doh_upstream_with_headers("corp-doh", "https://doh.example.com/dns-query", vec![
+            ("Authorization".to_string(), "Bearer s3cr3t-token".to_string()),
+        ])
+        .unwrap()
+        .with_dnsmasq_conf(fixture("dnsmasq.conf"))
+        .unwrap()
+        .with_query_history(256)
+        .with_tenant_stats(32)
+}
+
+fn ip_only_builder() -> DnsResolverBuilder {
+    DnsResolverBuilder::new(QueryStrategy::RoundRobin, true, "CN".to_string())
+        .disable_logger_init()
+        .with_timeout(Duration::from_secs(3))
+        .with_min_ttl(Duration::from_secs(30))
+        .add_udp_upstream("udp-1", "192.0.2.53")
+        .add_tcp_upstream("tcp-1", "192.0.2.54:53")
+        .add_doh_upstream("doh-1", "https://192.0.2.80/dns-query")
+}
+
+fn invalid_config(result: rat_quickdns::Result<impl std::fmt::Debug>) -> String {
+    match result {
+        Err(DnsError::InvalidConfig(msg)) => msg,
+        other => panic!("expected InvalidConfig, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_dry_run_accepts_the_production_fixture() {
+    let report = production_builder().validate_only().unwrap();
+    let config = &report.effective_config;
+
+    let names: Vec<&str> = config.upstreams.iter().map(|upstream| upstream.name.as_str()).collect();
+    assert!(names.starts_with(&["office", "office-tcp", "resolver-dot", "public-doh", "corp-doh"]), "{:?}", names);
+    assert!(!names.contains(&"upstream-4"), "{:?}", names);
+    assert!(config.upstreams.iter().any(|upstream| upstream.name.starts_with("dnsmasq-")));
+    assert_eq!(config.resolver.upstream_event_history, 64);
+    assert_eq!(config.resolver.metrics_upstream_labels, ["provider", "datacenter"]);
+    assert_eq!(config.upstreams[0].labels.get("provider").map(String::as_str), Some("internal"));
+    assert_eq!(config.upstreams[0].labels.len(), 3);
+
+    // 只有写成主机名的DoT上游需要在构建时解析
+    assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+    assert!(report.warnings[0].contains("'resolver-dot' (dot.example.net)"), "{:?}", report.warnings);
+}
+
+#[test]
+fn test_dry_run_leaves_the_builder_usable() {
+    let builder = ip_only_builder().with_dedup_upstreams(true).add_udp_upstream("udp-copy", "192.0.2.53:53");
+    builder.validate_only().unwrap();
+    builder.validate_only().unwrap();
+    assert_eq!(builder.upstream_count(), 4);
+}
+
+#[tokio::test]
+async fn test_dry_run_matches_the_built_resolver() {
+    let report = ip_only_builder().validate_only().unwrap();
+    assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+    let resolver = ip_only_builder().build().await.unwrap();
+    assert_eq!(report.effective_config, resolver.effective_config());
+}
+
+#[test]
+fn test_dry_run_reports_dedup_merges() {
+    let report = ip_only_builder()
+        .with_dedup_upstreams(true)
+        .add_udp_upstream("udp-copy", "192.0.2.53:53")
+        .validate_only()
+        .unwrap();
+    assert_eq!(report.effective_config.upstreams.len(), 3);
+    assert_eq!(report.warnings, vec![
+        "Upstream 'udp-copy' points at the same server as another upstream and is merged into it".to_string(),
+    ]);
+}
+
+#[test]
+fn test_dry_run_reports_broken_fields() {
+    let msg = invalid_config(production_builder().with_min_ttl(Duration::from_secs(7200)).with_max_ttl(Duration::from_secs(60)).validate_only());
+    assert!(msg.contains("min_ttl"), "{}", msg);
+
+    let msg = invalid_config(production_builder().with_query_history(0).validate_only());
+    assert_eq!(msg, "Query history capacity must be greater than zero");
+
+    let msg = invalid_config(production_builder().with_stats(false).validate_only());
+    assert!(msg.starts_with("Smart strategy selects upstreams by collected metrics"), "{}", msg);
+
+    let msg = invalid_config(production_builder().with_upstream_monitoring(false).with_auto_offline(Duration::from_secs(60)).validate_only());
+    assert_eq!(msg, "Auto offline mode requires upstream monitoring (with_upstream_monitoring)");
+
+    let msg = invalid_config(production_builder().add_udp_upstream("office-copy", "192.0.2.53").validate_only());
+    assert!(msg.contains("office-copy"), "{}", msg);
+
+    let builder = ip_only_builder().with_upstream_monitoring(true).with_upstream_tier("doh-1", 1).unwrap();
+    let msg = invalid_config(builder.validate_only());
+    assert_eq!(msg, "Backup tiers with upstream monitoring require a health probe (with_health_probe)");
+
+    let probe = ProbeConfig::new(Vec::new(), DnsRecordType::A, Duration::from_secs(2));
+    assert!(matches!(production_builder().with_health_probe(probe).validate_only(), Err(DnsError::InvalidConfig(_))));
+
+    let builder = DnsResolverBuilder::new(QueryStrategy::Quorum { required: 4 }, true, "CN".to_string())
+        .disable_logger_init()
+        .add_udp_upstream("udp-1", "192.0.2.53")
+        .add_tcp_upstream("tcp-1", "192.0.2.54:53")
+        .add_doh_upstream("doh-1", "https://192.0.2.80/dns-query");
+    let msg = invalid_config(builder.validate_only());
+    assert!(msg.starts_with("Quorum strategy requires between 1 and 3"), "{}", msg);
+}
+
+#[test]
+fn test_dry_run_reports_broken_transports() {
+    // 转发规则的上游不经过构建器的添加检查，请求头在创建传输时才会被拒绝
+    let router = DomainRouter::new()
+        .add_upstream(UpstreamSpec::doh("corp-doh".to_string(), "https://doh.corp.example.com/dns-query".to_string())
+            .with_headers(vec![("X-Token".to_string(), "line\r\nInjected: 1".to_string())]))
+        .add_rule("corp.example.com", DomainRoute::Upstreams(vec!["corp-doh".to_string()]));
+    let msg = invalid_config(ip_only_builder().with_domain_router(router).unwrap().validate_only());
+    assert_eq!(msg, "Invalid value for DoH header 'X-Token'");
+
+    #[cfg(not(feature = "doh3"))]
+    {
+        let router = DomainRouter::new()
+            .add_upstream(UpstreamSpec::doh("h3".to_string(), "https://doh.corp.example.com/dns-query".to_string())
+                .with_http_version(HttpVersionPref::H3Only))
+            .add_rule("corp.example.com", DomainRoute::Upstreams(vec!["h3".to_string()]));
+        let msg = invalid_config(ip_only_builder().with_domain_router(router).unwrap().validate_only());
+        assert_eq!(msg, "HTTP/3 for DoH upstream https://doh.corp.example.com/dns-query requires the doh3 feature");
+    }
+}
@@ -0,0 +1,63 @@
+//! 实际生效配置的导出与比对
+//!
+//! 黄金文件 `tests/fixtures/json/effective_config.json` 固定了导出的字段名，改名或删字段会让这里失败
+
+use rat_quickdns::builder::effective_config::REDACTED;
+use rat_quickdns::{DnsResolverBuilder, EffectiveConfig, QueryStrategy, SmartDnsResolver};
+use std::time::Duration;
+
+async fn resolver() -> SmartDnsResolver {
+    DnsResolverBuilder::new(QueryStrategy::Smart, true, "CN".to_string())
+        .disable_logger_init()
+        .with_timeout(Duration::from_secs(3))
+        .with_cache(true)
+        .with_min_ttl(Duration::from_secs(30))
+        .add_udp_upstream("udp-1", "192.0.2.53")
+        .add_doh_upstream_with_headers("corp-doh", "https://doh.example.com/dns-query", vec![
+            ("Authorization".to_string(), "Bearer s3cr3t-token".to_string()),
+            ("X-Client-Id".to_string(), "probe-7".to_string()),
+        ])
+        .unwrap()
+        .add_dot_upstream("dot-1", "dns.example.com")
+        .build()
+        .await
+        .unwrap()
+}
+
+fn golden() -> serde_json::Value {
+    let path = format!("{}/tests/fixtures/json/effective_config.json", env!("CARGO_MANIFEST_DIR"));
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn test_effective_config_matches_golden_with_secrets_redacted() {
+    let resolver = resolver().await;
+    let mut config = resolver.effective_config();
+    assert_eq!(config.version, env!("CARGO_PKG_VERSION"));
+
+    let json = config.to_json();
+    assert!(!json.contains("s3cr3t"), "{}", json);
+    assert_eq!(config.upstreams[1].headers[0].1, REDACTED);
+
+    // 版本和编译特性随构建变化，其余字段与黄金文件逐一相同
+    config.version = "0.0.0".to_string();
+    config.features.clear();
+    assert_eq!(config.to_json_value(), golden());
+    assert_eq!(EffectiveConfig::from_json(&config.to_json()).unwrap(), config);
+}
+
+#[tokio::test]
+async fn test_diff_reports_runtime_changes() {
+    let resolver = resolver().await;
+    let before = resolver.effective_config();
+
+    resolver.set_upstream_enabled("udp-1", false).await.unwrap();
+    resolver.remove_upstream("dot-1").await.unwrap();
+    let after = resolver.effective_config();
+
+    assert_eq!(before.diff(&after), vec![
+        "upstream dot-1 removed".to_string(),
+        "upstreams[udp-1].enabled: true -> false".to_string(),
+    ]);
+    assert!(after.diff(&after).is_empty());
+}
@@ -0,0 +1,87 @@
+//! 库级特性：默认特性保持全部功能，可选依赖只由对应的特性引入，各特性组合都能编译
+//!
+//! 逐个组合编译较慢，放在忽略的测试中：`cargo test --test feature_matrix -- --ignored`，
+//! 组合列表见 `tools/check_features.sh`
+
+use std::path::Path;
+use std::process::Command;
+
+fn manifest() -> toml::Table {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    toml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+/// 特性启用的项（其他特性和 `dep:` 依赖）
+fn feature(manifest: &toml::Table, name: &str) -> Vec<String> {
+    manifest["features"][name].as_array()
+        .unwrap_or_else(|| panic!("feature '{}' is missing", name))
+        .iter()
+        .map(|item| item.as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn test_default_features_keep_every_protocol() {
+    let manifest = manifest();
+    assert_eq!(feature(&manifest, "default"), ["udp", "tcp", "dot", "doh", "serde-api"]);
+    assert!(feature(&manifest, "dot").contains(&"tcp".to_string()));
+    for name in ["udp", "tcp", "dot", "doh", "serde-api"] {
+        assert!(feature(&manifest, "python-bindings").contains(&name.to_string()), "{}", name);
+    }
+}
+
+#[test]
+fn test_optional_dependencies_belong_to_their_features() {
+    let manifest = manifest();
+    let pulled_by = |dependency: &str| -> Vec<String> {
+        manifest["features"].as_table().unwrap().iter()
+            .filter(|(_, items)| items.as_array().unwrap().iter().any(|item| {
+                let item = item.as_str().unwrap();
+                item == dependency || item.strip_prefix("dep:") == Some(dependency)
+            }))
+            .map(|(name, _)| name.clone())
+            .collect()
+    };
+    for (dependency, owner) in [
+        ("rustls", "dot"),
+        ("tokio-rustls", "dot"),
+        ("reqwest", "doh"),
+        ("bincode", "serde-api"),
+        ("socket2", "tcp"),
+        ("pyo3", "python-bindings"),
+    ] {
+        assert!(pulled_by(dependency).contains(&owner.to_string()), "{} is not pulled by {}", dependency, owner);
+        assert!(!pulled_by(dependency).contains(&"udp".to_string()), "udp pulls {}", dependency);
+    }
+    assert_eq!(pulled_by("pyo3"), ["python-bindings"]);
+    assert_eq!(pulled_by("hyper-014"), ["http-resolver"]);
+    assert_eq!(pulled_by("hyper-util"), ["http-resolver"]);
+}
+
+#[test]
+fn test_check_script_covers_every_feature() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let script = std::fs::read_to_string(root.join("tools/check_features.sh")).unwrap();
+    let (_, combinations) = script.split_once("set --").unwrap();
+    let (combinations, _) = combinations.split_once("fi").unwrap();
+    let checked: Vec<&str> = combinations
+        .split(|c: char| c.is_whitespace() || matches!(c, '"' | ',' | '\\'))
+        .filter(|name| !name.is_empty())
+        .collect();
+    for name in manifest()["features"].as_table().unwrap().keys().filter(|name| *name != "default") {
+        assert!(checked.contains(&name.as_str()), "tools/check_features.sh does not check '{}'", name);
+    }
+}
+
+#[test]
+#[ignore = "compiles the library once per feature combination"]
+fn test_every_feature_combination_compiles() {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let status = Command::new("sh")
+        .arg(Path::new(root).join("tools/check_features.sh"))
+        // 独立的target目录，不与运行本测试的构建争用锁
+        .env("CARGO_TARGET_DIR", Path::new(root).join("target/feature-matrix"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
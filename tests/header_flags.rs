@@ -0,0 +1,134 @@
+//! 头部标志位的逐位编解码：RA、Z、AD、CD四位的全部16种组合经请求和响应编解码后不变
+
+use rat_quickdns::transport::UdpTransport;
+use rat_quickdns::types::{Flags, Opcode, QClass, Query, RecordType, Request};
+use rat_quickdns::QueryPriority;
+use rat_quickdns::DnsResponseBuilder;
+use std::net::Ipv4Addr;
+
+/// 标志位第二字节的高4位：RA、Z、AD、CD
+fn nibble_flags(nibble: u8, qr: bool) -> Flags {
+    Flags {
+        qr,
+        ra: nibble & 0b1000 != 0,
+        reserved: nibble & 0b0100 != 0,
+        ad: nibble & 0b0010 != 0,
+        cd: nibble & 0b0001 != 0,
+        ..Flags::default()
+    }
+}
+
+fn request(flags: Flags) -> Request {
+    Request {
+        id: 0x1234,
+        flags,
+        query: Query { name: "example.com".to_string(), qtype: RecordType::A, qclass: QClass::IN },
+        client_address: None,
+        enable_edns: false,
+        dnssec_ok: false,
+        wire_capture_limit: None,
+        priority: QueryPriority::Normal,
+    }
+}
+
+#[test]
+fn test_every_header_flag_word_round_trips() {
+    for bits in 0..=u16::MAX {
+        assert_eq!(Flags::from_bits(bits).to_bits(), bits, "flags word {:#06x}", bits);
+    }
+}
+
+#[test]
+fn test_flag_masks_match_rfc_bit_positions() {
+    let single = |flags: Flags| flags.to_bits();
+    let none = Flags { rd: false, ..Flags::default() };
+    assert_eq!(single(none), 0);
+    assert_eq!(single(Flags { qr: true, ..none }), 0x8000);
+    assert_eq!(single(Flags { opcode: Opcode::Unknown(0x0F), ..none }), 0x7800);
+    assert_eq!(single(Flags { aa: true, ..none }), 0x0400);
+    assert_eq!(single(Flags { tc: true, ..none }), 0x0200);
+    assert_eq!(single(Flags { rd: true, ..none }), 0x0100);
+    assert_eq!(single(Flags { ra: true, ..none }), 0x0080);
+    assert_eq!(single(Flags { reserved: true, ..none }), 0x0040);
+    assert_eq!(single(Flags { ad: true, ..none }), 0x0020);
+    assert_eq!(single(Flags { cd: true, ..none }), 0x0010);
+    assert_eq!(single(Flags { rcode: 0x0F, ..none }), 0x000F);
+}
+
+#[test]
+fn test_request_flags_round_trip_over_all_combinations() {
+    for nibble in 0..16u8 {
+        let flags = nibble_flags(nibble, false);
+        let bytes = UdpTransport::serialize_request(&request(flags)).unwrap();
+        assert_eq!(bytes[2], 0x01, "RD only in the first flags byte for {:04b}", nibble);
+        assert_eq!(bytes[3], nibble << 4, "second flags byte for {:04b}", nibble);
+
+        let decoded = UdpTransport::deserialize_request(&bytes).unwrap();
+        assert_eq!(decoded.flags, flags, "request flags {:04b}", nibble);
+    }
+}
+
+#[test]
+fn test_response_flags_round_trip_over_all_combinations() {
+    for nibble in 0..16u8 {
+        let mut response = DnsResponseBuilder::new()
+            .with_id(0x1234)
+            .add_query("example.com".to_string(), RecordType::A, QClass::IN)
+            .add_a_answer("example.com".to_string(), 300, Ipv4Addr::new(192, 0, 2, 1))
+            .build();
+        response.flags = Flags { rcode: 3, ..nibble_flags(nibble, true) };
+
+        let bytes = UdpTransport::serialize_response(&response).unwrap();
+        assert_eq!(bytes[2], 0x81, "QR and RD in the first flags byte for {:04b}", nibble);
+        assert_eq!(bytes[3], (nibble << 4) | 3, "second flags byte for {:04b}", nibble);
+
+        let decoded = UdpTransport::deserialize_response(&bytes).unwrap();
+        assert_eq!(decoded.flags, response.flags, "response flags {:04b}", nibble);
+        assert_eq!(decoded.authenticated_data(), nibble & 0b0010 != 0);
+        // 再编码一次，报文逐字节相同
+        assert_eq!(UdpTransport::serialize_response(&decoded).unwrap(), bytes);
+    }
+}
+
+#[test]
+fn test_builder_sets_ad_and_cd_explicitly() {
+    let plain = DnsResponseBuilder::new().build();
+    assert!(!plain.flags.ad && !plain.flags.cd && !plain.flags.reserved);
+    assert!(!plain.authenticated_data());
+
+    let validated = DnsResponseBuilder::new()
+        .with_authenticated_data(true)
+        .with_checking_disabled(true)
+        .build();
+    assert!(validated.authenticated_data());
+    assert_eq!(validated.flags.to_bits() & 0x0030, 0x0030);
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_deprecated_z_accessor_reads_only_the_z_bit() {
+    assert_eq!(Flags::from_bits(0x0030).z(), 0);
+    assert_eq!(Flags::from_bits(0x0040).z(), 1);
+}
+
+#[test]
+fn test_opcodes_round_trip_through_request_and_response() {
+    for value in 0..16u8 {
+        let opcode = Opcode::from(value);
+        assert_eq!(u8::from(opcode), value);
+        let bytes = UdpTransport::serialize_request(&request(Flags { opcode, ..Flags::default() })).unwrap();
+        assert_eq!(bytes[2] >> 3, value, "opcode {}", value);
+        assert_eq!(UdpTransport::deserialize_request(&bytes).unwrap().flags.opcode, opcode);
+
+        let response = DnsResponseBuilder::new()
+            .with_id(0x1234)
+            .with_opcode(opcode)
+            .add_query("example.com".to_string(), RecordType::A, QClass::IN)
+            .build();
+        let bytes = UdpTransport::serialize_response(&response).unwrap();
+        assert_eq!(UdpTransport::deserialize_response(&bytes).unwrap().flags.opcode, opcode);
+    }
+    assert_eq!(Opcode::from(4), Opcode::Notify);
+    assert_eq!(Opcode::from(5).name(), "UPDATE");
+    assert_eq!(Opcode::from(3).name(), "OPCODE3");
+}
@@ -0,0 +1,77 @@
+//! HTTP客户端解析适配器：经模拟上游解析主机名，并由hyper-util的 `HttpConnector` 建立连接
+
+use hyper_util::client::legacy::connect::dns::Name;
+use hyper_util::client::legacy::connect::HttpConnector;
+use rat_quickdns::builder::HttpResolver;
+use rat_quickdns::transport::mock::MockTransport;
+use rat_quickdns::{DnsResolverBuilder, QueryStrategy, RecordType};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use tower_service::Service;
+
+async fn resolver() -> HttpResolver {
+    let mock = MockTransport::new()
+        .with_a("svc.test", &[Ipv4Addr::LOCALHOST], 300)
+        .with_aaaa("svc.test", &[], 300)
+        .with_nxdomain("missing.test", RecordType::A)
+        .with_nxdomain("missing.test", RecordType::AAAA);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("mock", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    HttpResolver::new(Arc::new(resolver))
+}
+
+#[tokio::test]
+async fn test_name_service_resolves_through_mock_upstream() {
+    let mut resolver = resolver().await;
+    let addrs: Vec<SocketAddr> = resolver.call(Name::from_str("svc.test").unwrap()).await.unwrap().collect();
+    assert_eq!(addrs, vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 0))]);
+
+    assert!(resolver.call(Name::from_str("missing.test").unwrap()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_http_connector_connects_to_resolved_address() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let mut connector = HttpConnector::new_with_resolver(resolver().await);
+    std::future::poll_fn(|cx| connector.poll_ready(cx)).await.unwrap();
+    let uri: hyper::Uri = format!("http://svc.test:{}/", port).parse().unwrap();
+    let (connected, accepted) = tokio::join!(connector.call(uri), listener.accept());
+    connected.unwrap();
+    assert_eq!(accepted.unwrap().1.ip(), Ipv4Addr::LOCALHOST);
+}
+
+#[tokio::test]
+async fn test_reqwest_client_fetches_from_resolved_address() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = vec![0u8; 1024];
+        let n = stream.read(&mut request).await.unwrap();
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok").await.unwrap();
+        String::from_utf8_lossy(&request[..n]).into_owned()
+    });
+
+    let client = reqwest::Client::builder()
+        .dns_resolver(Arc::new(resolver().await))
+        .no_proxy()
+        .build()
+        .unwrap();
+    let body = client.get(format!("http://svc.test:{}/", port)).send().await.unwrap().text().await.unwrap();
+    assert_eq!(body, "ok");
+    // 请求发往解析出的地址，Host头仍是原主机名
+    assert!(server.await.unwrap().contains(&format!("host: svc.test:{}", port)));
+
+    let missing = client.get(format!("http://missing.test:{}/", port)).send().await;
+    assert!(missing.unwrap_err().is_connect());
+}
@@ -0,0 +1,153 @@
+//! 查询响应与统计信息的JSON导出
+//!
+//! 黄金文件位于 `tests/fixtures/json`，字段名或记录值形状的任何变化都会让这里失败
+
+use rat_quickdns::builder::types::{DnsRecordValue, DnssecStatus, TimingBreakdown};
+use rat_quickdns::builder::{DnsRecordType, LatencyPercentiles};
+use rat_quickdns::{
+    deserialize_batch_json, serialize_batch_json, CoreResolverStats, DnsQueryResponse, DnsRecord, QueryContext, QueryStrategy,
+};
+use std::net::IpAddr;
+use std::time::Duration;
+
+fn record(name: &str, record_type: DnsRecordType, value: DnsRecordValue, ttl: u32) -> DnsRecord {
+    DnsRecord { name: name.to_string(), record_type, value, ttl }
+}
+
+/// 覆盖所有记录值变体的响应
+fn sample_response() -> DnsQueryResponse {
+    DnsQueryResponse {
+        query_id: "q-1".into(),
+        domain: "example.com".to_string(),
+        record_type: DnsRecordType::A,
+        success: true,
+        error: None,
+        records: vec![
+            record("example.com", DnsRecordType::A, DnsRecordValue::IpAddr(IpAddr::from([192, 0, 2, 1])), 300),
+            record("example.com", DnsRecordType::AAAA, DnsRecordValue::IpAddr("2001:db8::1".parse().unwrap()), 300),
+            record("www.example.com", DnsRecordType::CNAME, DnsRecordValue::Domain("example.com".to_string()), 60),
+            record("example.com", DnsRecordType::TXT, DnsRecordValue::Txt(vec!["v=spf1 -all".to_string()]), 3600),
+            record("example.com", DnsRecordType::MX, DnsRecordValue::Mx {
+                priority: 10,
+                exchange: "mail.example.com".to_string(),
+            }, 3600),
+            record("_sip._udp.example.com", DnsRecordType::SRV, DnsRecordValue::Srv {
+                priority: 1,
+                weight: 5,
+                port: 5060,
+                target: "sip.example.com".to_string(),
+            }, 3600),
+            record("example.com", DnsRecordType::SOA, DnsRecordValue::Soa {
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            }, 3600),
+        ],
+        duration_ms: 12,
+        server_used: Some("udp-1".to_string()),
+        upstream_peer: Some("192.0.2.53:53".parse().unwrap()),
+        protocol_used: Some("UDP".to_string()),
+        dnssec_status: Some(DnssecStatus::Indeterminate),
+        dnssec_records: Vec::new(),
+        emergency_mode: false,
+        rcode: Some(0),
+        authenticated_data: false,
+        valid_until: Some(1_700_000_060),
+        timing: Some(TimingBreakdown {
+            upstream_resolve_ms: None,
+            tcp_connect_ms: None,
+            tls_handshake_ms: None,
+            request_sent_ms: Some(1),
+            first_byte_ms: Some(11),
+            complete_ms: 12,
+            http_version: None,
+            tcp_fast_open: None,
+        }),
+        wire_request: Some(vec![0x12, 0x34, 0x01, 0x00]),
+        wire_response: None,
+        wire_truncated: false,
+        degraded_security: false,
+        records_truncated: false,
+        negative_ttl: None,
+        zone_apex: None,
+        context: Some(QueryContext::for_tenant("team-a").with_trace_id("trace-1").with_tag("env", "prod")),
+        error_code: None,
+        failure_report: None,
+        selection: None,
+    }
+}
+
+fn golden(name: &str) -> serde_json::Value {
+    let path = format!("{}/tests/fixtures/json/{}", env!("CARGO_MANIFEST_DIR"), name);
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn test_query_response_matches_golden_and_round_trips() {
+    let response = sample_response();
+    let json = response.to_json().unwrap();
+    assert!(!json.contains('\n'));
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&json).unwrap(), golden("query_response.json"));
+
+    let decoded = DnsQueryResponse::from_json(&json).unwrap();
+    assert_eq!(decoded.to_json().unwrap(), json);
+    assert!(DnsQueryResponse::from_json("{\"query_id\": 1}").is_err());
+}
+
+#[test]
+fn test_stats_match_golden() {
+    let mut stats = CoreResolverStats::new(QueryStrategy::Smart, true);
+    stats.total_upstreams = 2;
+    stats.available_upstreams = 1;
+    stats.total_queries = 10;
+    stats.successful_queries = 8;
+    stats.failed_queries = 2;
+    stats.min_latency = Duration::from_millis(5);
+    stats.max_latency = Duration::from_millis(80);
+    stats.mean_latency = Duration::from_millis(20);
+    stats.p50_latency = Duration::from_millis(15);
+    stats.p95_latency = Duration::from_millis(60);
+    stats.p99_latency = Duration::from_millis(75);
+    stats.upstream_latency.insert("udp-1".to_string(), LatencyPercentiles {
+        count: 8,
+        mean: Duration::from_millis(20),
+        p50: Duration::from_millis(15),
+        p95: Duration::from_millis(60),
+        p99: Duration::from_millis(75),
+    });
+    stats.fastest_upstream = Some("udp-1".to_string());
+    stats.current_active_tier = Some(0);
+    stats.in_flight_sends = 3;
+
+    assert_eq!(stats.to_json_value(), golden("resolver_stats.json"));
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&stats.to_json()).unwrap(), stats.to_json_value());
+}
+
+#[test]
+fn test_batch_is_streamed_as_json_array() {
+    let mut failed = sample_response();
+    failed.success = false;
+    failed.error = Some("timeout".to_string());
+    failed.records.clear();
+    failed.rcode = None;
+    let batch = vec![sample_response(), failed];
+
+    let mut buffer = Vec::new();
+    serialize_batch_json(&mut buffer, &batch).unwrap();
+    let array: Vec<serde_json::Value> = serde_json::from_slice(&buffer).unwrap();
+    assert_eq!(array.len(), 2);
+    assert_eq!(array[0], golden("query_response.json"));
+
+    let decoded = deserialize_batch_json(buffer.as_slice()).unwrap();
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(decoded[1].error.as_deref(), Some("timeout"));
+    assert_eq!(decoded[1].rcode, None);
+
+    let mut empty = Vec::new();
+    serialize_batch_json(&mut empty, &[]).unwrap();
+    assert_eq!(empty, b"[]");
+}
@@ -0,0 +1,50 @@
+//! 日志初始化策略的进程级测试
+//!
+//! rat_logger 的全局处理器是进程级状态，放在独立的测试二进制里，
+//! 避免与库内其它测试相互干扰。
+
+use rat_logger::LevelFilter;
+use rat_quickdns::logger::{self, LoggerBackend};
+use rat_quickdns::{DnsResolverBuilder, LoggerInitStrategy, QueryStrategy};
+
+fn builder(strategy: LoggerInitStrategy) -> DnsResolverBuilder {
+    DnsResolverBuilder::new(QueryStrategy::Fifo, false, "CN".to_string())
+        .add_udp_upstream("阿里DNS", "223.5.5.5")
+        .with_logger_strategy(strategy)
+}
+
+#[tokio::test]
+async fn test_resolvers_built_back_to_back_share_one_logger() {
+    // None 不产生任何副作用
+    builder(LoggerInitStrategy::None).build().await.unwrap();
+    assert_eq!(logger::dns_logger_backend(), None);
+    assert_eq!(logger::effective_dns_log_level(), None);
+
+    // 第一次安装终端处理器
+    builder(LoggerInitStrategy::Debug).build().await.unwrap();
+    assert_eq!(logger::dns_logger_backend(), Some(LoggerBackend::Dns));
+    assert_eq!(logger::effective_dns_log_level(), Some(LevelFilter::Debug));
+
+    // 之后只调整级别，不会因为重复安装而失败
+    builder(LoggerInitStrategy::Silent).build().await.unwrap();
+    assert_eq!(logger::effective_dns_log_level(), Some(LevelFilter::Off));
+
+    builder(LoggerInitStrategy::Debug).build().await.unwrap();
+    assert_eq!(logger::effective_dns_log_level(), Some(LevelFilter::Debug));
+
+    builder(LoggerInitStrategy::Auto)
+        .with_log_level(LevelFilter::Warn)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(logger::effective_dns_log_level(), Some(LevelFilter::Warn));
+
+    // None 不改变已生效的级别
+    builder(LoggerInitStrategy::None).build().await.unwrap();
+    assert_eq!(logger::effective_dns_log_level(), Some(LevelFilter::Warn));
+
+    // 本库已占用全局处理器，改写入调用方处理器是真正的冲突，返回错误而不是panic
+    let result = builder(LoggerInitStrategy::External).build().await;
+    assert!(result.is_err());
+    assert_eq!(logger::dns_logger_backend(), Some(LoggerBackend::Dns));
+}
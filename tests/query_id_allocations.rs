@@ -0,0 +1,102 @@
+//! 查询ID的分配开销：未指定ID的查询不为查询ID分配内存，调用方指定的ID原样带回
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::net::Ipv4Addr;
+
+use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+use rat_quickdns::transport::mock::MockTransport;
+use rat_quickdns::{DnsResolverBuilder, QueryStrategy, SmartDnsResolver};
+
+/// 只统计当前线程在计数期间的分配次数，其他线程（运行时、测试框架）的分配不计入
+struct CountingAllocator;
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = COUNTING.try_with(|counting| {
+            if counting.get() {
+                let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            }
+        });
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const QUERIES: u64 = 200;
+
+async fn resolver() -> SmartDnsResolver {
+    DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("mock", MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300))
+        .unwrap()
+        .build()
+        .await
+        .unwrap()
+}
+
+/// 执行 `QUERIES` 次命中缓存的查询，返回当前线程的分配次数
+async fn count_allocations(resolver: &SmartDnsResolver, query_id: Option<&str>) -> u64 {
+    let requests: Vec<DnsQueryRequest> = (0..QUERIES)
+        .map(|_| {
+            let request = DnsQueryRequest::new("example.com", DnsRecordType::A);
+            match query_id {
+                Some(id) => request.with_query_id(id),
+                None => request,
+            }
+        })
+        .collect();
+    ALLOCATIONS.with(|count| count.set(0));
+    COUNTING.with(|counting| counting.set(true));
+    for request in requests {
+        let response = resolver.query(request).await.unwrap();
+        assert!(response.success);
+    }
+    COUNTING.with(|counting| counting.set(false));
+    ALLOCATIONS.with(|count| count.get())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_generated_query_ids_do_not_allocate() {
+    let resolver = resolver().await;
+    // 预热缓存，之后的查询都走缓存命中路径
+    resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+
+    let generated = count_allocations(&resolver, None).await;
+    let explicit = count_allocations(&resolver, Some("caller-request-id")).await;
+    // 调用方的ID每次查询至少复制到响应中一次；分配的ID只是序号，不产生额外分配
+    assert!(
+        generated + QUERIES <= explicit,
+        "generated ids: {} allocations, caller ids: {} allocations for {} queries",
+        generated, explicit, QUERIES
+    );
+
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+    assert!(response.query_id.caller_id().is_none());
+    assert!(!response.query_id.to_string().is_empty());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_caller_query_ids_pass_through_untouched() {
+    let resolver = resolver().await;
+    for id in ["caller-1", "  spaced id  ", "550e8400-e29b-41d4-a716-446655440000"] {
+        let response = resolver
+            .query(DnsQueryRequest::new("example.com", DnsRecordType::A).with_query_id(id))
+            .await
+            .unwrap();
+        assert_eq!(response.query_id.caller_id(), Some(id));
+        let json: serde_json::Value = serde_json::from_str(&response.to_json().unwrap()).unwrap();
+        assert_eq!(json["query_id"], id);
+    }
+}
@@ -0,0 +1,90 @@
+//! ratdig 命令行工具测试（需要 `cli` 特性）
+//!
+//! 对回环地址上的模拟UDP上游运行编译好的 ratdig，检查输出和退出码
+
+#![cfg(feature = "cli")]
+
+use assert_cmd::Command;
+use rat_quickdns::transport::UdpTransport;
+use rat_quickdns::DnsResponseWrapper;
+use std::net::{Ipv4Addr, UdpSocket};
+
+/// 启动模拟上游：example.com 返回A记录，missing.example 返回NXDOMAIN，其余返回SERVFAIL
+fn spawn_upstream() -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+            let Ok(request) = UdpTransport::deserialize_request(&buf[..len]) else {
+                continue;
+            };
+            let query = &request.query;
+            let response = match query.name.trim_end_matches('.') {
+                "example.com" => DnsResponseWrapper::create_a_response(
+                    request.id, &query.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300,
+                ),
+                "missing.example" => DnsResponseWrapper::create_nxdomain_response(request.id, &query.name, query.qtype),
+                _ => DnsResponseWrapper::create_server_failure_response(request.id, &query.name, query.qtype),
+            };
+            if let Ok(bytes) = UdpTransport::serialize_response(&response) {
+                let _ = socket.send_to(&bytes, peer);
+            }
+        }
+    });
+    format!("@udp://{}", addr)
+}
+
+fn ratdig(args: &[&str]) -> assert_cmd::assert::Assert {
+    Command::cargo_bin("ratdig").unwrap().args(args).assert()
+}
+
+#[test]
+fn test_answer_as_json() {
+    let upstream = spawn_upstream();
+    let output = ratdig(&["example.com", "A", &upstream, "--json", "--stats"])
+        .code(0)
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["response"]["rcode"], 0);
+    assert_eq!(json["response"]["records"][0]["value"]["IpAddr"], "192.0.2.1");
+    assert_eq!(json["stats"]["upstreams"][0]["name"], "udp-1");
+}
+
+#[test]
+fn test_single_upstream_queries_directly() {
+    let upstream = spawn_upstream();
+    let output = ratdig(&["example.com", &upstream, "--json"])
+        .code(0)
+        .get_output()
+        .stdout
+        .clone();
+
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["records"][0]["value"]["IpAddr"], "192.0.2.1");
+    assert_eq!(json["server_used"], upstream.trim_start_matches('@'));
+    assert_eq!(json["upstream_peer"], upstream.trim_start_matches("@udp://"));
+}
+
+#[test]
+fn test_exit_codes_distinguish_failures() {
+    let upstream = spawn_upstream();
+    ratdig(&["missing.example", &upstream]).code(3);
+    ratdig(&["broken.example", &upstream]).code(2);
+
+    // 不回应的上游：超时
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let silent_upstream = format!("@udp://{}", silent.local_addr().unwrap());
+    ratdig(&["example.com", &silent_upstream, "--timeout", "300"]).code(4);
+}
+
+#[test]
+fn test_usage_errors() {
+    ratdig(&["example.com"]).code(1);
+    ratdig(&["example.com", "BOGUS", "@udp://127.0.0.1:53"]).code(1);
+    ratdig(&["example.com", "-s", "quic://127.0.0.1"]).code(1);
+    ratdig(&["example.com", "@udp://127.0.0.1:dns"]).code(1);
+}
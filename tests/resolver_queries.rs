@@ -0,0 +1,1704 @@
+//! 经由模拟上游（`transport::mock`）走通完整查询路径的解析器行为测试：缓存、应答处理、查询上下文、诊断和导出
+
+use rat_quickdns::config::SearchDomains;
+use rat_quickdns::{
+    AnswerRewriteRule, DnsError, DnsResolverBuilder, OverrideTtl, QueryStrategy, RecordSort, RewriteStage, SmartDnsResolver,
+};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_mock_upstream_serves_queries() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 7)], 300);
+    let handle = mock.clone();
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("模拟DNS", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let response = resolver
+        .query(DnsQueryRequest::new("example.com", DnsRecordType::A))
+        .await
+        .unwrap();
+    assert!(response.success);
+    assert_eq!(response.server_used.as_deref(), Some("模拟DNS"));
+    assert_eq!(handle.call_count(), 1);
+
+    // 克隆出来的解析器使用同一个模拟传输
+    resolver.clone()
+        .query(DnsQueryRequest::new("example.com", DnsRecordType::A))
+        .await
+        .unwrap();
+    assert_eq!(handle.call_count(), 2);
+}
+
+#[tokio::test]
+async fn test_query_summary_log_is_single_info_line() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::logger::{Level, LogCapture};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 7)], 300);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_query_summary_log(true)
+        .with_cache(true)
+        .add_mock_upstream("模拟DNS", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let failing = DnsResolverBuilder::new(QueryStrategy::Sequential, false, "global".to_string())
+        .disable_logger_init()
+        .with_query_summary_log(true)
+        .with_retry_count(0)
+        .add_mock_upstream("故障DNS", MockTransport::new().with_failure_rate(1.0))
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let capture = LogCapture::start();
+    let info_lines = || capture.take().into_iter()
+        .filter(|log| log.level == Level::Info)
+        .map(|log| log.message)
+        .collect::<Vec<_>>();
+
+    resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+    let lines = info_lines();
+    assert_eq!(lines.len(), 1, "{:?}", lines);
+    assert!(lines[0].starts_with("query_summary query_id="), "{}", lines[0]);
+    assert!(lines[0].contains(" domain=example.com type=A strategy=fifo cache=miss upstream=模拟DNS "), "{}", lines[0]);
+    assert!(lines[0].contains(" outcome=success rcode=NOERROR answers=1 "), "{}", lines[0]);
+
+    resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+    let lines = info_lines();
+    assert_eq!(lines.len(), 1, "{:?}", lines);
+    assert!(lines[0].contains(" cache=hit upstream=- protocol=- peer=- outcome=success "), "{}", lines[0]);
+
+    let _ = failing.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await;
+    let lines = info_lines();
+    assert_eq!(lines.len(), 1, "{:?}", lines);
+    assert!(lines[0].contains(" strategy=sequential cache=bypass upstream=- "), "{}", lines[0]);
+    assert!(!lines[0].contains("outcome=success"), "{}", lines[0]);
+}
+
+#[tokio::test]
+async fn test_lookup_ip_distinguishes_nxdomain_nodata_and_failure() {
+    use rat_quickdns::builder::types::DnsRecordType;
+    use rat_quickdns::dns_response::DnsResponseWrapper;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::RecordType;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new()
+        .with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 7)], 300)
+        .with_response("alias.example", RecordType::A, DnsResponseWrapper::create_cname_response(0, "alias.example", "example.com", 300))
+        .with_nxdomain("missing.example", RecordType::A)
+        .with_error("broken.example", RecordType::A, DnsError::Timeout { phase: None });
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .add_mock_upstream("模拟DNS", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let addresses = resolver.lookup_ip("example.com", DnsRecordType::A).await.unwrap();
+    assert_eq!(addresses, vec![std::net::IpAddr::from(Ipv4Addr::new(192, 0, 2, 7))]);
+
+    let nodata = resolver.lookup_ip("alias.example", DnsRecordType::A).await.unwrap_err();
+    assert!(
+        matches!(&nodata, DnsError::NoRecords { domain, answer_types } if domain == "alias.example" && answer_types == &["CNAME"]),
+        "{:?}", nodata
+    );
+
+    let nxdomain = resolver.lookup_ip("missing.example", DnsRecordType::A).await.unwrap_err();
+    assert!(matches!(nxdomain, DnsError::NxDomain), "{:?}", nxdomain);
+
+    let failure = resolver.lookup_ip("broken.example", DnsRecordType::A).await.unwrap_err();
+    assert!(!matches!(failure, DnsError::NxDomain | DnsError::NoRecords { .. }), "{:?}", failure);
+    assert_ne!(failure.retry_advice(), rat_quickdns::error::RetryAdvice::Fatal);
+
+    assert!(resolver.lookup_ip("example.com", DnsRecordType::MX).await.is_err());
+}
+
+#[tokio::test]
+async fn test_default_client_ip_sent_as_ecs_option() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::{mock::MockTransport, UdpTransport};
+    use std::net::Ipv4Addr;
+
+    let builder = DnsResolverBuilder::new(QueryStrategy::Fifo, true, "CN".to_string());
+    assert!(builder.clone().with_default_client_ip("202.96.128.86".parse().unwrap(), 33).is_err());
+
+    let mock = MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 7)], 300);
+    let handle = mock.clone();
+    let resolver = builder
+        .disable_logger_init()
+        .with_default_client_ip("202.96.128.86".parse().unwrap(), 24)
+        .unwrap()
+        .add_mock_upstream("模拟DNS", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+
+    let request = &handle.calls()[0].request;
+    let client = request.client_address.as_ref().expect("default client subnet must be sent");
+    assert_eq!(client.source_prefix_length, 24);
+
+    // 线路格式：附加记录数为1，且以OPT记录（类型41）携带ECS选项（代码8）
+    let bytes = UdpTransport::serialize_request(request).unwrap();
+    assert_eq!(&bytes[10..12], &1u16.to_be_bytes());
+    let opt = bytes.len() - (11 + 4 + 7);
+    assert_eq!(&bytes[opt..opt + 3], &[0, 0, 41]);
+    assert_eq!(&bytes[opt + 11..opt + 13], &8u16.to_be_bytes());
+    assert_eq!(&bytes[bytes.len() - 3..], &[202, 96, 128]);
+}
+
+#[tokio::test]
+async fn test_request_edns_and_dnssec_apply_per_query() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 7)], 300);
+    let handle = mock.clone();
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, true, "CN".to_string())
+        .disable_logger_init()
+        .with_default_client_ip("202.96.128.86".parse().unwrap(), 24)
+        .unwrap()
+        .add_mock_upstream("模拟DNS", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let sent = |request: DnsQueryRequest| {
+        let resolver = resolver.clone();
+        let handle = handle.clone();
+        async move {
+            handle.clear_calls();
+            resolver.query(request.disable_cache()).await.unwrap();
+            handle.calls()[0].request.clone()
+        }
+    };
+
+    let request = sent(DnsQueryRequest::new("example.com", DnsRecordType::A)).await;
+    assert!(request.enable_edns && !request.dnssec_ok && request.client_address.is_some());
+
+    // 请求DNSSEC时设置DO位
+    let request = sent(DnsQueryRequest::new("example.com", DnsRecordType::A).with_dnssec(true)).await;
+    assert!(request.enable_edns && request.dnssec_ok);
+
+    // 关闭EDNS的查询不携带OPT记录，客户端子网也不发送
+    let request = sent(DnsQueryRequest::new("example.com", DnsRecordType::A).with_edns(false)).await;
+    assert!(!request.enable_edns && !request.dnssec_ok && request.client_address.is_none());
+
+    // 关闭EDNS又请求DNSSEC的查询不合法，不发往上游
+    handle.clear_calls();
+    let response = resolver
+        .query(DnsQueryRequest::new("example.com", DnsRecordType::A).with_edns(false).with_dnssec(true))
+        .await
+        .unwrap();
+    assert!(!response.success);
+    assert_eq!(handle.call_count(), 0);
+
+    // 解析器关闭EDNS时，请求DNSSEC的查询仍然携带OPT记录
+    let mock = MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 7)], 300);
+    let handle = mock.clone();
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("模拟DNS", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A).with_dnssec(true)).await.unwrap();
+    let request = &handle.calls()[0].request;
+    assert!(request.enable_edns && request.dnssec_ok);
+}
+
+#[tokio::test]
+async fn test_query_multi_types_runs_concurrently_and_isolates_failures() {
+    use rat_quickdns::builder::types::DnsRecordType;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::RecordType;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    let upstream = |v4: u8| MockTransport::new()
+        .with_a("example.com", &[Ipv4Addr::new(192, 0, 2, v4)], 300)
+        .with_aaaa("example.com", &[Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, v4.into())], 300)
+        .with_error("example.com", RecordType::MX, DnsError::network("mock", "connection reset"))
+        .with_latency(Duration::from_millis(150));
+    let (alpha, beta) = (upstream(1), upstream(2));
+    let (alpha_handle, beta_handle) = (alpha.clone(), beta.clone());
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Smart, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_retry_count(0)
+        .with_concurrent_queries(4)
+        .add_mock_upstream("alpha", alpha)
+        .unwrap()
+        .add_mock_upstream("beta", beta)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    assert!(resolver.query_multi_types("example.com", Vec::new()).await.is_err());
+
+    let start = std::time::Instant::now();
+    let results = resolver
+        .query_multi_types(
+            "example.com",
+            vec![DnsRecordType::A, DnsRecordType::AAAA, DnsRecordType::MX, DnsRecordType::A],
+        )
+        .await
+        .unwrap();
+    // 依次查询至少需要600ms（MX在选中的上游失败后还要再按策略查询一轮），并发时约300ms
+    assert!(start.elapsed() < Duration::from_millis(500), "queries ran sequentially: {:?}", start.elapsed());
+
+    assert_eq!(results.len(), 3);
+    let (a, aaaa, mx) = (&results[&DnsRecordType::A], &results[&DnsRecordType::AAAA], &results[&DnsRecordType::MX]);
+    assert!(a.success && aaaa.success);
+    assert_eq!(a.ip_addresses().len(), 1);
+    assert_eq!(a.record_type, DnsRecordType::A);
+    assert!(!mx.success);
+    assert!(mx.error.is_some());
+
+    // 共用同一次上游选择：成功的类型都由选中的上游应答
+    assert_eq!(a.server_used, aaaa.server_used);
+    let chosen = a.server_used.clone().unwrap();
+    let (chosen_handle, other_handle) = if chosen == "alpha" {
+        (&alpha_handle, &beta_handle)
+    } else {
+        (&beta_handle, &alpha_handle)
+    };
+    let types_asked = |handle: &MockTransport| {
+        handle.calls().iter().map(|call| call.request.query.qtype).collect::<Vec<_>>()
+    };
+    assert_eq!(types_asked(chosen_handle).iter().filter(|t| **t != RecordType::MX).count(), 2);
+    // 只有在选中上游失败的MX才交给其他上游
+    assert_eq!(types_asked(other_handle), vec![RecordType::MX]);
+}
+
+#[tokio::test]
+async fn test_chaos_queries_fingerprint_upstream_and_cache_per_class() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType, DnsRecordValue};
+    use rat_quickdns::dns_response::DnsResponseBuilder;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{QClass, RecordType, Response};
+
+    let txt = |name: &str, qclass: QClass, text: &str| -> Response {
+        let mut response = DnsResponseBuilder::new()
+            .add_query(name.to_string(), RecordType::TXT, qclass)
+            .add_txt_answer(name.to_string(), 300, vec![text.to_string()])
+            .build();
+        response.answers.iter_mut().for_each(|record| record.class = qclass);
+        response
+    };
+    let refused = DnsResponseBuilder::new()
+        .with_response_code(5)
+        .add_query("version.bind".to_string(), RecordType::TXT, QClass::CH)
+        .build();
+    let mock = MockTransport::new()
+        .with_class_response("id.server", RecordType::TXT, QClass::CH, txt("id.server", QClass::CH, "pop-hkg-7"))
+        .with_class_response("version.bind", RecordType::TXT, QClass::CH, refused)
+        .with_response("id.server", RecordType::TXT, txt("id.server", QClass::IN, "in-class answer"));
+    let handle = mock.clone();
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .add_mock_upstream("anycast", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    assert_eq!(resolver.query_server_id("anycast").await.unwrap(), vec!["pop-hkg-7".to_string()]);
+    assert!(matches!(resolver.query_server_version("anycast").await, Err(DnsError::Refused)));
+    assert!(resolver.query_server_id("missing").await.is_err());
+
+    let text = |response: &rat_quickdns::builder::types::DnsQueryResponse| match &response.records[0].value {
+        DnsRecordValue::Txt(chunks) => chunks.concat(),
+        other => panic!("unexpected record {:?}", other),
+    };
+    let in_request = DnsQueryRequest::new("id.server", DnsRecordType::TXT);
+    let ch_request = DnsQueryRequest::new("id.server", DnsRecordType::TXT).with_class(QClass::CH);
+    assert_eq!(text(&resolver.query(in_request.clone()).await.unwrap()), "in-class answer");
+    // IN类答案已缓存，CH类查询仍要发往上游
+    assert_eq!(text(&resolver.query(ch_request.clone()).await.unwrap()), "pop-hkg-7");
+    assert_eq!(text(&resolver.query(in_request).await.unwrap()), "in-class answer");
+    assert_eq!(text(&resolver.query(ch_request).await.unwrap()), "pop-hkg-7");
+
+    let classes = handle.calls().iter().map(|call| call.request.query.qclass).collect::<Vec<_>>();
+    // 两个辅助方法各一次CH查询，之后IN和CH各一次，重复的查询由各自类别的缓存应答
+    assert_eq!(classes, vec![QClass::CH, QClass::CH, QClass::IN, QClass::CH]);
+}
+
+#[tokio::test]
+async fn test_soa_and_srv_answers_have_typed_getters() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType, SoaData, SrvData};
+    use rat_quickdns::dns_response::DnsResponseBuilder;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{QClass, RecordType};
+
+    let soa = DnsResponseBuilder::new()
+        .add_query("example.com".to_string(), RecordType::SOA, QClass::IN)
+        .add_soa_answer(
+            "example.com".to_string(),
+            3600,
+            "ns1.example.com".to_string(),
+            "hostmaster.example.com".to_string(),
+            2024010101,
+            7200,
+            900,
+            1209600,
+            300,
+        )
+        .build();
+    let srv_name = "_sip._tcp.example.com";
+    let srv = DnsResponseBuilder::new()
+        .add_query(srv_name.to_string(), RecordType::SRV, QClass::IN)
+        .add_cname_answer(srv_name.to_string(), 300, "_sip._tcp.example.net".to_string())
+        .add_srv_answer(srv_name.to_string(), 300, 10, 60, 5060, "sip1.example.com".to_string())
+        .add_srv_answer(srv_name.to_string(), 300, 20, 0, 5061, "sip2.example.com".to_string())
+        .build();
+    let mock = MockTransport::new()
+        .with_response("example.com", RecordType::SOA, soa)
+        .with_response(srv_name, RecordType::SRV, srv);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("模拟DNS", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::SOA)).await.unwrap();
+    assert_eq!(response.soa_records(), vec![SoaData {
+        mname: "ns1.example.com".to_string(),
+        rname: "hostmaster.example.com".to_string(),
+        serial: 2024010101,
+        refresh: 7200,
+        retry: 900,
+        expire: 1209600,
+        minimum: 300,
+    }]);
+    assert!(response.srv_records().is_empty());
+
+    let response = resolver.query(DnsQueryRequest::new(srv_name, DnsRecordType::SRV)).await.unwrap();
+    assert_eq!(response.srv_records(), vec![
+        SrvData { priority: 10, weight: 60, port: 5060, target: "sip1.example.com".to_string() },
+        SrvData { priority: 20, weight: 0, port: 5061, target: "sip2.example.com".to_string() },
+    ]);
+    assert_eq!(response.records_of_type(DnsRecordType::SRV).len(), 2);
+    assert_eq!(response.records_of_type(DnsRecordType::CNAME).len(), 1);
+    assert!(response.records_of_type(DnsRecordType::SOA).is_empty());
+}
+
+#[tokio::test]
+async fn test_negative_answers_report_soa_negative_ttl_and_zone_apex() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseBuilder;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{QClass, Record, RecordData, RecordType, ResponseCode};
+
+    let negative = |name: &str, rcode: ResponseCode| DnsResponseBuilder::new()
+        .with_rcode(rcode)
+        .add_query(name.to_string(), RecordType::A, QClass::IN)
+        .add_authority(Record {
+            name: "example.com".to_string(),
+            rtype: RecordType::SOA,
+            class: QClass::IN,
+            ttl: 300,
+            data: RecordData::SOA {
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 60,
+            },
+        })
+        .build();
+    let mock = MockTransport::new()
+        .with_response("missing.example.com", RecordType::A, negative("missing.example.com", ResponseCode::NxDomain))
+        .with_response("v6only.example.com", RecordType::A, negative("v6only.example.com", ResponseCode::NoError))
+        .with_a("www.example.com", &[std::net::Ipv4Addr::new(192, 0, 2, 1)], 300);
+    let handle = mock.clone();
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .add_mock_upstream("模拟DNS", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    for (name, rcode) in [("missing.example.com", 3), ("v6only.example.com", 0)] {
+        let response = resolver.query(DnsQueryRequest::new(name, DnsRecordType::A)).await.unwrap();
+        assert_eq!(response.rcode, Some(rcode));
+        assert_eq!(response.negative_ttl, Some(60));
+        assert_eq!(response.zone_apex.as_deref(), Some("example.com"));
+
+        // 否定应答按SOA给出的时间缓存，命中时报告剩余时间
+        let cached = resolver.query(DnsQueryRequest::new(name, DnsRecordType::A)).await.unwrap();
+        assert_eq!(cached.server_used.as_deref(), Some("cache"));
+        assert_eq!(cached.rcode, Some(rcode));
+        assert!(cached.negative_ttl.is_some_and(|ttl| ttl <= 60), "{:?}", cached.negative_ttl);
+        assert_eq!(cached.zone_apex.as_deref(), Some("example.com"));
+    }
+    assert_eq!(handle.call_count(), 2);
+
+    let response = resolver.query(DnsQueryRequest::new("www.example.com", DnsRecordType::A)).await.unwrap();
+    assert_eq!((response.negative_ttl, response.zone_apex), (None, None));
+}
+
+#[tokio::test]
+async fn test_search_domains_expand_short_names_in_order() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::RecordType;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new()
+        .with_nxdomain("db.corp.example", RecordType::A)
+        .with_a("db.lab.example", &[Ipv4Addr::new(192, 0, 2, 7)], 300)
+        .with_a("www.example.com", &[Ipv4Addr::new(192, 0, 2, 8)], 300);
+    let handle = mock.clone();
+    let builder = DnsResolverBuilder::new(QueryStrategy::Sequential, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .add_mock_upstream("mock", mock)
+        .unwrap()
+        .apply_search_domains(true);
+    assert!(builder.clone().build().await.is_err());
+    let resolver = builder
+        .with_search_domains(SearchDomains::new(["corp.example", "lab.example"], 1))
+        .build()
+        .await
+        .unwrap();
+
+    let response = resolver.query(DnsQueryRequest::new("db", DnsRecordType::A)).await.unwrap();
+    assert!(response.success, "{:?}", response.error);
+    assert_eq!(response.domain, "db.lab.example");
+    let names: Vec<String> = handle.calls().iter().map(|call| call.request.query.name.clone()).collect();
+    assert_eq!(names, vec!["db.corp.example", "db.lab.example"]);
+
+    handle.clear_calls();
+    let response = resolver.query(DnsQueryRequest::new("www.example.com", DnsRecordType::A)).await.unwrap();
+    assert_eq!(response.domain, "www.example.com");
+    assert_eq!(handle.call_count(), 1);
+}
+
+#[tokio::test]
+async fn test_request_timeout_bounds_whole_query() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new()
+        .with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+        .with_latency(Duration::from_millis(300));
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .add_mock_upstream("slow", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A).with_timeout(50)).await.unwrap();
+    assert!(!response.success);
+    assert!(response.error.unwrap().contains("timeout"));
+    assert!(response.duration_ms < 300, "took {} ms", response.duration_ms);
+
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+    assert!(response.success);
+
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A).with_timeout(0)).await.unwrap();
+    assert!(!response.success);
+}
+
+#[tokio::test]
+async fn test_resolve_with_ttl_counts_down_while_cached() {
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::RecordType;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    let v6: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let mock = MockTransport::new()
+        .with_a("pool.example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 3)
+        .with_aaaa("pool.example.com", &[v6], 60)
+        .with_nxdomain("missing.example.com", RecordType::A)
+        .with_nxdomain("missing.example.com", RecordType::AAAA);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .add_mock_upstream("mock", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let fresh = resolver.resolve_with_ttl("pool.example.com").await.unwrap();
+    assert_eq!(fresh.addresses, vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), IpAddr::V6(v6)]);
+    assert_eq!(fresh.valid_for, Duration::from_secs(3));
+    assert!(!fresh.from_cache);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let cached = resolver.resolve_with_ttl("pool.example.com").await.unwrap();
+    assert_eq!(cached.addresses, fresh.addresses);
+    assert!(cached.from_cache);
+    assert!(cached.valid_for < fresh.valid_for, "{:?}", cached.valid_for);
+
+    assert!(matches!(resolver.resolve_with_ttl("missing.example.com").await, Err(DnsError::NxDomain)));
+}
+
+#[tokio::test]
+async fn test_valid_until_follows_injected_clock() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::resolver::clock::{Clock, TestClock};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+    use std::time::UNIX_EPOCH;
+
+    let clock = Arc::new(TestClock::new());
+    clock.advance(Duration::from_secs(10 * 86400));
+    let mock = MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 7)], 300);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_clock(clock.clone())
+        .add_mock_upstream("mock", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    // 有效期从测试时钟的当前时间起算，而不是真实时间
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+    let now = clock.now_system().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    assert_eq!(response.valid_until, Some(now + 300));
+    assert!(!response.is_expired_at(clock.now_system()));
+    assert!(response.is_expired_at(clock.now_system() + Duration::from_secs(300)));
+}
+
+#[tokio::test]
+async fn test_offline_mode_answers_only_from_cache() {
+    use rat_quickdns::builder::types::DnsRecordType;
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new().with_a("cached.example", &[Ipv4Addr::new(192, 0, 2, 7)], 300);
+    let handle = mock.clone();
+    let builder = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .with_auto_offline(Duration::from_secs(60))
+        .add_mock_upstream("mock", mock)
+        .unwrap();
+    // 自动离线依据上游监控的判定
+    assert!(matches!(builder.clone().build().await, Err(DnsError::InvalidConfig(_))));
+    assert!(builder.clone().with_offline_revalidation_rate(0).is_err());
+    let resolver = builder.with_upstream_monitoring(true).build().await.unwrap();
+
+    resolver.lookup_ip("cached.example", DnsRecordType::A).await.unwrap();
+    resolver.set_offline(true);
+    assert!(resolver.is_offline());
+    assert_eq!(resolver.lookup_ip("cached.example", DnsRecordType::A).await.unwrap().len(), 1);
+    let missing = resolver.lookup_ip("uncached.example", DnsRecordType::A).await;
+    assert!(matches!(missing, Err(DnsError::Offline { .. })), "{:?}", missing);
+    assert_eq!(handle.call_count(), 1);
+
+    let stats = resolver.get_stats().await;
+    assert!(stats.offline);
+    assert_eq!(stats.offline_transitions, 1);
+}
+
+#[tokio::test]
+async fn test_response_and_status_report_upstream_peer() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseWrapper;
+    use rat_quickdns::transport::UdpTransport;
+    use std::net::Ipv4Addr;
+
+    let upstream = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, peer) = upstream.recv_from(&mut buf).await.unwrap();
+            let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+            let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+            upstream.send_to(&UdpTransport::serialize_response(&response).unwrap(), peer).await.unwrap();
+        }
+    });
+
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .with_upstream_monitoring(true)
+        .add_udp_upstream("loopback", address.to_string())
+        .build()
+        .await
+        .unwrap();
+    let response = resolver.query(DnsQueryRequest::new("peer.example", DnsRecordType::A)).await.unwrap();
+    assert!(response.success, "{:?}", response.error);
+    assert_eq!(response.upstream_peer, Some(address));
+    assert!(response.to_json().unwrap().contains(&format!("\"upstream_peer\":\"{}\"", address)));
+
+    let status = resolver.get_upstream_status().await;
+    assert_eq!(status[0].last_peer, Some(address));
+
+    // 缓存应答不归属任何上游地址
+    let cached = resolver.query(DnsQueryRequest::new("peer.example", DnsRecordType::A)).await.unwrap();
+    assert_eq!(cached.upstream_peer, None);
+}
+
+#[tokio::test]
+async fn test_hostname_upstreams_resolve_through_bootstrap_resolver() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseWrapper;
+    use rat_quickdns::transport::UdpTransport;
+    use rat_quickdns::transport::mock::MockBootstrap;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    let upstream = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, peer) = upstream.recv_from(&mut buf).await.unwrap();
+            let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+            let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+            upstream.send_to(&UdpTransport::serialize_response(&response).unwrap(), peer).await.unwrap();
+        }
+    });
+    let bootstrap = Arc::new(MockBootstrap::new().with_host("dns.corp.internal", &[IpAddr::from([127, 0, 0, 1])]));
+    let builder = || DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_upstream_monitoring(true)
+        .with_bootstrap_resolver(bootstrap.clone())
+        .add_udp_upstream("corp", format!("dns.corp.internal:{}", address.port()))
+        .add_udp_upstream("gone", "gone.corp.internal:53");
+
+    // 解析失败的上游只被标记为不可用，其余上游照常工作
+    let resolver = builder().build().await.unwrap();
+    let response = resolver.query(DnsQueryRequest::new("intranet.example", DnsRecordType::A)).await.unwrap();
+    assert!(response.success, "{:?}", response.error);
+    assert_eq!(response.upstream_peer, Some(address));
+    let status = resolver.get_upstream_status().await;
+    let gone = status.iter().find(|status| status.name == "gone").unwrap();
+    assert!(!gone.is_available);
+    assert!(status.iter().find(|status| status.name == "corp").unwrap().is_available);
+
+    // 严格模式下任何上游解析失败都让构建失败
+    let err = builder().with_strict_upstream_resolution(true).build().await.unwrap_err();
+    assert!(matches!(&err, DnsError::InvalidConfig(message) if message.contains("'gone'")), "{err}");
+}
+
+#[tokio::test]
+async fn test_answer_rewrite_translates_matching_domains() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::resolver::answer_rewrite::RewriteRuleStats;
+    use rat_quickdns::resolver::cache::DnsCache;
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let public = Ipv4Addr::new(203, 0, 113, 5);
+    let internal = IpAddr::from([10, 0, 0, 5]);
+    let mock = MockTransport::new()
+        .with_a("app.corp.example", &[public], 300)
+        .with_a("www.other.example", &[public], 300);
+    let rule = AnswerRewriteRule::address(public.into(), internal).unwrap().for_domain("corp.example");
+    let builder = |backend: Arc<DnsCache>, stage| DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("mock", mock.clone())
+        .unwrap()
+        .with_cache(true)
+        .with_cache_backend(backend)
+        .with_answer_rewrite(rule.clone())
+        .with_answer_rewrite_stage(stage);
+
+    let resolver = builder(Arc::new(DnsCache::new(Duration::from_secs(3600))), RewriteStage::AfterCache).build().await.unwrap();
+    let response = resolver.query(DnsQueryRequest::new("app.corp.example", DnsRecordType::A)).await.unwrap();
+    assert_eq!(response.ip_addresses(), vec![internal]);
+    assert_eq!(response.records[0].ttl, 300);
+    let cached = resolver.query(DnsQueryRequest::new("app.corp.example", DnsRecordType::A)).await.unwrap();
+    assert_eq!(cached.ip_addresses(), vec![internal]);
+    let other = resolver.query(DnsQueryRequest::new("www.other.example", DnsRecordType::A)).await.unwrap();
+    assert_eq!(other.ip_addresses(), vec![IpAddr::from(public)]);
+    assert_eq!(resolver.get_stats().await.answer_rewrites, vec![RewriteRuleStats {
+        rule: "203.0.113.5/32 -> 10.0.0.5/32 (corp.example)".to_string(),
+        hits: 2,
+    }]);
+
+    // 共享缓存的实例只在写入前改写时看到内网地址
+    for (stage, cached) in [(RewriteStage::AfterCache, IpAddr::from(public)), (RewriteStage::BeforeCache, internal)] {
+        let backend = Arc::new(DnsCache::new(Duration::from_secs(3600)));
+        let rewriting = builder(backend.clone(), stage).build().await.unwrap();
+        rewriting.query(DnsQueryRequest::new("app.corp.example", DnsRecordType::A)).await.unwrap();
+        let plain = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .add_mock_upstream("mock", MockTransport::new())
+            .unwrap()
+            .with_cache(true)
+            .with_cache_backend(backend)
+            .build()
+            .await
+            .unwrap();
+        let response = plain.query(DnsQueryRequest::new("app.corp.example", DnsRecordType::A)).await.unwrap();
+        assert_eq!(response.ip_addresses(), vec![cached], "{:?}", stage);
+    }
+}
+
+/// 记下 `dns_query` span字段和事件字段的tracing订阅者
+#[derive(Clone, Default)]
+struct CapturedTracing {
+    spans: Arc<std::sync::Mutex<Vec<(String, String)>>>,
+    events: Arc<std::sync::Mutex<Vec<(String, String)>>>,
+}
+
+struct FieldCollector<'a>(&'a mut Vec<(String, String)>);
+
+impl tracing::field::Visit for FieldCollector<'_> {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.push((field.name().to_string(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0.push((field.name().to_string(), format!("{:?}", value)));
+    }
+}
+
+impl tracing::Subscriber for CapturedTracing {
+    fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        if span.metadata().name() == "dns_query" {
+            span.record(&mut FieldCollector(&mut self.spans.lock().unwrap()));
+        }
+        tracing::span::Id::from_u64(1)
+    }
+
+    fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+    fn event(&self, event: &tracing::Event<'_>) {
+        event.record(&mut FieldCollector(&mut self.events.lock().unwrap()));
+    }
+
+    fn enter(&self, _span: &tracing::span::Id) {}
+
+    fn exit(&self, _span: &tracing::span::Id) {}
+}
+
+#[tokio::test]
+async fn test_query_context_is_traced_echoed_and_counted_per_tenant() {
+    use rat_quickdns::builder::resolver::CACHE_SOURCE;
+    use rat_quickdns::builder::tenant::{TenantStats, OTHER_TENANTS};
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType, QueryContext};
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::RecordType;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new()
+        .with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+        .with_error("broken.example", RecordType::A, DnsError::Server("upstream down".to_string()));
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .with_tenant_stats(2)
+        .add_mock_upstream("mock", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    assert!(resolver.tenant_stats().unwrap().is_empty());
+
+    let tracing = CapturedTracing::default();
+    let _guard = tracing::subscriber::set_default(tracing.clone());
+    let alpha = QueryContext::for_tenant("alpha")
+        .with_trace_id("trace-1")
+        .with_tag("env", "prod")
+        .with_tag("team", "dns");
+    let request = DnsQueryRequest::new("example.com", DnsRecordType::A).with_context(alpha.clone());
+    let response = resolver.query(request.clone().with_query_id("q-1")).await.unwrap();
+    assert!(response.success);
+    assert_eq!(response.context.as_ref(), Some(&alpha));
+
+    let spans = tracing.spans.lock().unwrap().clone();
+    for (field, value) in [("query_id", "q-1"), ("tenant", "alpha"), ("trace_id", "trace-1"), ("tags", "env=prod,team=dns"), ("strategy", "Fifo")] {
+        assert!(spans.contains(&(field.to_string(), value.to_string())), "{} missing from {:?}", field, spans);
+    }
+    assert!(tracing.events.lock().unwrap().contains(&("success".to_string(), "true".to_string())));
+
+    // 命中缓存和查询失败同样带回上下文
+    let cached = resolver.query(request).await.unwrap();
+    assert_eq!(cached.server_used.as_deref(), Some(CACHE_SOURCE));
+    assert_eq!(cached.context.as_ref(), Some(&alpha));
+    assert!(!cached.query_id.is_empty());
+
+    let failed = resolver.query(DnsQueryRequest::new("broken.example", DnsRecordType::A)
+        .with_context(QueryContext::for_tenant("beta"))).await.unwrap();
+    assert!(!failed.success);
+    assert_eq!(failed.context, Some(QueryContext::for_tenant("beta")));
+
+    // 超出上限的租户合并计数，没有租户的查询不计入
+    resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)
+        .with_context(QueryContext::for_tenant("gamma"))).await.unwrap();
+    resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+
+    let stats = resolver.tenant_stats().unwrap();
+    assert_eq!(stats.len(), 3);
+    assert_eq!(stats["alpha"], TenantStats { queries: 2, errors: 0, cache_hits: 1 });
+    assert_eq!(stats["beta"], TenantStats { queries: 1, errors: 1, cache_hits: 0 });
+    assert_eq!(stats[OTHER_TENANTS], TenantStats { queries: 1, errors: 0, cache_hits: 1 });
+}
+
+#[tokio::test]
+async fn test_generated_query_ids_and_caller_ids_pass_through() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType, QueryIdFormat};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let build = |format| DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_query_id_format(format)
+        .add_mock_upstream("mock", MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300))
+        .unwrap()
+        .build();
+    let resolver = build(QueryIdFormat::Sequence).await.unwrap();
+    assert_eq!(resolver.query_id_format(), QueryIdFormat::Sequence);
+
+    let explicit = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A).with_query_id("caller-42")).await.unwrap();
+    assert_eq!(explicit.query_id.caller_id(), Some("caller-42"));
+    assert_eq!(serde_json::to_value(&explicit).unwrap()["query_id"], "caller-42");
+
+    let batch = resolver.batch_query(vec![
+        DnsQueryRequest::new("example.com", DnsRecordType::A),
+        DnsQueryRequest::new("example.com", DnsRecordType::A).with_query_id("caller-43"),
+        DnsQueryRequest::new("example.com", DnsRecordType::A),
+    ]).await;
+    let ids: Vec<String> = batch.into_iter().map(|response| response.unwrap().query_id.to_string()).collect();
+    assert_eq!(ids[1], "caller-43");
+    assert!(!ids[0].is_empty() && !ids[2].is_empty() && ids[0] != ids[2]);
+
+    let multi = resolver.query_multi_types("example.com", vec![DnsRecordType::A, DnsRecordType::AAAA]).await.unwrap();
+    assert!(multi.values().all(|response| !response.query_id.is_empty() && response.query_id.caller_id().is_none()));
+
+    let resolver = build(QueryIdFormat::Uuid).await.unwrap();
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+    assert!(uuid::Uuid::parse_str(&response.query_id.to_string()).is_ok());
+}
+
+#[tokio::test]
+async fn test_cache_invalidation_by_domain_suffix_and_type() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    let a = [Ipv4Addr::new(192, 0, 2, 1)];
+    let mock = MockTransport::new()
+        .with_a("api.internal.corp", &a, 300)
+        .with_aaaa("api.internal.corp", &[Ipv6Addr::LOCALHOST], 300)
+        .with_a("db.eu.internal.corp", &a, 300)
+        .with_a("notinternal.corp", &a, 300);
+    let handle = mock.clone();
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .add_mock_upstream("mock", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let queries = [
+        ("api.internal.corp", DnsRecordType::A),
+        ("api.internal.corp", DnsRecordType::AAAA),
+        ("db.eu.internal.corp", DnsRecordType::A),
+        ("notinternal.corp", DnsRecordType::A),
+    ];
+    let fill = || async {
+        for (name, record_type) in queries {
+            resolver.query(DnsQueryRequest::new(name, record_type)).await.unwrap();
+        }
+        handle.calls().len()
+    };
+
+    assert_eq!(fill().await, 4);
+    assert_eq!(fill().await, 4);
+    assert_eq!(resolver.invalidate_domain("API.internal.corp.").await.unwrap(), 2);
+    assert_eq!(fill().await, 6);
+    assert_eq!(resolver.invalidate_suffix("internal.corp").await.unwrap(), 3);
+    assert_eq!(fill().await, 9);
+    assert_eq!(resolver.invalidate_type(DnsRecordType::AAAA).await.unwrap(), 1);
+    assert_eq!(fill().await, 10);
+
+    assert!(matches!(resolver.invalidate_suffix(".").await, Err(DnsError::InvalidConfig(_))));
+    assert!(matches!(resolver.invalidate_domain("").await, Err(DnsError::InvalidConfig(_))));
+    assert_eq!(resolver.cache_stats().unwrap().invalidations, 6);
+}
+
+#[tokio::test]
+async fn test_authenticated_data_flag_reaches_query_response() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseWrapper;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::RecordType;
+    use std::net::Ipv4Addr;
+
+    let mut validated = DnsResponseWrapper::create_a_response(0, "secure.test", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+    validated.flags.ad = true;
+    let mock = MockTransport::new()
+        .with_response("secure.test", RecordType::A, validated)
+        .with_a("plain.test", &[Ipv4Addr::new(192, 0, 2, 2)], 300);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .add_mock_upstream("mock", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    for _ in 0..2 {
+        let secure = resolver.query(DnsQueryRequest::new("secure.test", DnsRecordType::A)).await.unwrap();
+        assert!(secure.authenticated_data, "served by {:?}", secure.server_used);
+        let plain = resolver.query(DnsQueryRequest::new("plain.test", DnsRecordType::A)).await.unwrap();
+        assert!(!plain.authenticated_data);
+    }
+}
+
+#[tokio::test]
+async fn test_record_order_survives_cache_conversion_and_json() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseBuilder;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{QClass, RecordType};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    // CNAME与地址记录交错，地址也不是按数值排列
+    let upstream = DnsResponseBuilder::new()
+        .add_query("www.example.com".to_string(), RecordType::A, QClass::IN)
+        .add_a_answer("edge.example.net".to_string(), 60, Ipv4Addr::new(192, 0, 2, 20))
+        .add_cname_answer("www.example.com".to_string(), 300, "edge.example.net".to_string())
+        .add_a_answer("edge.example.net".to_string(), 60, Ipv4Addr::new(192, 0, 2, 3))
+        .add_aaaa_answer("edge.example.net".to_string(), 60, Ipv6Addr::LOCALHOST)
+        .add_cname_answer("edge.example.net".to_string(), 300, "edge.example.org".to_string())
+        .add_a_answer("edge.example.org".to_string(), 60, Ipv4Addr::new(192, 0, 2, 1))
+        .build();
+    let wire_order: Vec<(String, RecordType)> = upstream.answers.iter().map(|record| (record.name.clone(), record.rtype)).collect();
+    let resolver = |sort: Option<RecordSort>| {
+        let upstream = upstream.clone();
+        async move {
+            // 保留混入的AAAA记录，检查它在排序中的位置
+            let mut builder = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+                .disable_logger_init()
+                .with_cache(true)
+                .with_lenient_answer_types(true)
+                .add_mock_upstream("mock", MockTransport::new().with_response("www.example.com", RecordType::A, upstream))
+                .unwrap();
+            if let Some(sort) = sort {
+                builder = builder.with_record_sort(sort);
+            }
+            builder.build().await.unwrap()
+        }
+    };
+    // 去掉随时间变化的字段后序列化
+    async fn query_json(resolver: &SmartDnsResolver) -> (String, rat_quickdns::DnsQueryResponse) {
+        let mut response = resolver
+            .query(DnsQueryRequest::new("www.example.com", DnsRecordType::A).with_query_id("q"))
+            .await
+            .unwrap();
+        response.duration_ms = 0;
+        response.valid_until = None;
+        for record in &mut response.records {
+            record.ttl = 0;
+        }
+        (response.to_json().unwrap(), response)
+    }
+
+    let first = resolver(None).await;
+    let second = resolver(None).await;
+    let (miss, response) = query_json(&first).await;
+    let (hit, cached) = query_json(&first).await;
+    assert_eq!(response.server_used.as_deref(), Some("mock"));
+    assert_eq!(cached.server_used.as_deref(), Some(rat_quickdns::builder::resolver::CACHE_SOURCE));
+    assert_eq!(query_json(&second).await.0, miss);
+    assert_eq!(query_json(&second).await.0, hit);
+
+    let records = |response: &rat_quickdns::DnsQueryResponse| serde_json::to_string(&response.records).unwrap();
+    assert_eq!(records(&cached), records(&response));
+    let listed: Vec<(String, RecordType)> = response.records.iter().map(|record| (record.name.clone(), record.record_type.into())).collect();
+    assert_eq!(listed, wire_order);
+
+    // 规范排序：A按数值，然后CNAME按目标，最后AAAA；命中缓存时相同
+    let sorting = resolver(Some(RecordSort::ByTypeThenValue)).await;
+    let (_, sorted) = query_json(&sorting).await;
+    let (_, sorted_hit) = query_json(&sorting).await;
+    let listed: Vec<String> = sorted.records.iter().map(|record| format!("{} {:?}", record.record_type.as_str(), record.value)).collect();
+    assert_eq!(listed, vec![
+        "A IpAddr(192.0.2.1)",
+        "A IpAddr(192.0.2.3)",
+        "A IpAddr(192.0.2.20)",
+        "CNAME Domain(\"edge.example.net\")",
+        "CNAME Domain(\"edge.example.org\")",
+        "AAAA IpAddr(::1)",
+    ]);
+    assert_eq!(records(&sorted_hit), records(&sorted));
+}
+
+#[tokio::test]
+async fn test_cache_hits_do_not_update_upstream_metrics() {
+    use rat_quickdns::builder::resolver::CACHE_SOURCE;
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new()
+        .with_a("cached.example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+        .with_latency(Duration::from_millis(20));
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Smart, false, "global".to_string())
+        .with_cache(true)
+        .add_mock_upstream("mock", mock.clone())
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let engine = resolver.get_decision_engine().unwrap().clone();
+    let query = || resolver.query(DnsQueryRequest::new("cached.example.com", DnsRecordType::A));
+
+    let warm = query().await.unwrap();
+    assert!(warm.success, "{:?}", warm.error);
+    assert_eq!(warm.server_used.as_deref(), Some("mock"));
+    let warmed = engine.get_metrics("mock").await.unwrap();
+    assert_eq!(engine.skipped_cache_hit_updates(), 0);
+
+    for _ in 0..100 {
+        let response = query().await.unwrap();
+        assert_eq!(response.server_used.as_deref(), Some(CACHE_SOURCE));
+    }
+    // 缓存应答不计入上游的查询次数和延迟
+    let metrics = engine.get_metrics("mock").await.unwrap();
+    assert_eq!(metrics.total_queries, warmed.total_queries);
+    assert_eq!(metrics.successful_queries, warmed.successful_queries);
+    assert_eq!(metrics.avg_latency, warmed.avg_latency);
+    assert_eq!(mock.call_count(), 1);
+    assert_eq!(engine.skipped_cache_hit_updates(), 100);
+    assert_eq!(resolver.get_stats().await.metrics_skipped_cache_hits, 100);
+}
+
+#[tokio::test]
+async fn test_query_and_compare_bypasses_cache_and_reports_changes() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType, DnsRecordValue};
+    use rat_quickdns::dns_response::DnsResponseWrapper;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::RecordType;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new()
+        .with_a("watched.example.com", &[Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2)], 300);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .with_cache(true)
+        .add_mock_upstream("mock", mock.clone())
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let request = || DnsQueryRequest::new("watched.example.com", DnsRecordType::A);
+    let baseline = resolver.query(request()).await.unwrap();
+
+    let (fresh, diff) = resolver.query_and_compare(request(), &baseline).await.unwrap();
+    assert_eq!(fresh.server_used.as_deref(), Some("mock"));
+    assert!(diff.is_empty(), "{:?}", diff);
+
+    let changed = [Ipv4Addr::new(192, 0, 2, 2), Ipv4Addr::new(198, 51, 100, 7)];
+    mock.set_response("watched.example.com", RecordType::A, DnsResponseWrapper::create_a_response(0, "watched.example.com", &changed, 300));
+    let (fresh, diff) = resolver.query_and_compare(request(), &baseline).await.unwrap();
+    assert_eq!(fresh.records.len(), 2);
+    assert!(diff.is_significant());
+    assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.ttl_changes.is_empty());
+    assert_eq!(diff.changed.len(), 1);
+    assert_eq!(diff.changed[0].old_value, DnsRecordValue::IpAddr(Ipv4Addr::new(192, 0, 2, 1).into()));
+    assert_eq!(diff.changed[0].new_value, DnsRecordValue::IpAddr(Ipv4Addr::new(198, 51, 100, 7).into()));
+    assert_eq!(mock.call_count(), 3);
+}
+
+#[tokio::test]
+async fn test_name_spellings_share_one_cache_entry() {
+    use rat_quickdns::builder::resolver::CACHE_SOURCE;
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    let mock = MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .add_mock_upstream("mock", mock.clone())
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let mut sources = Vec::new();
+    for spelling in ["EXAMPLE.COM.", "example.com", "eXaMpLe.CoM"] {
+        let response = resolver.query(DnsQueryRequest::new(spelling, DnsRecordType::A)).await.unwrap();
+        assert!(response.success, "{:?}", response.error);
+        // 响应中的域名保持调用方的写法
+        assert_eq!(response.domain, spelling);
+        assert_eq!(response.ip_addresses(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+        sources.push(response.server_used);
+    }
+    assert_eq!(mock.call_count(), 1);
+    assert_eq!(sources[1].as_deref(), Some(CACHE_SOURCE));
+    assert_eq!(sources[2].as_deref(), Some(CACHE_SOURCE));
+}
+
+#[tokio::test]
+async fn test_ttl_override_rules_pin_reported_ttl() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new()
+        .with_a("web.failover.corp", &[Ipv4Addr::new(192, 0, 2, 1)], 3600)
+        .with_a("web.corp", &[Ipv4Addr::new(192, 0, 2, 2)], 3600)
+        .with_a("static.cdn.corp", &[Ipv4Addr::new(192, 0, 2, 3)], 60);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("mock", mock)
+        .unwrap()
+        .with_cache(true)
+        .with_max_ttl(Duration::from_secs(600))
+        .with_ttl_override("*.Failover.Corp.", OverrideTtl::Exact(Duration::from_secs(30)))
+        .unwrap()
+        .with_ttl_override("static.cdn.corp", OverrideTtl::Min(Duration::from_secs(86400)))
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    assert!(matches!(
+        DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string()).with_ttl_override("a.*.corp", OverrideTtl::Exact(Duration::ZERO)),
+        Err(DnsError::InvalidConfig(_))
+    ));
+
+    let ttl = |name: &'static str| {
+        let resolver = &resolver;
+        async move { resolver.query(DnsQueryRequest::new(name, DnsRecordType::A)).await.unwrap().records[0].ttl }
+    };
+    assert_eq!(ttl("web.failover.corp").await, 30);
+    // 不匹配的名称仍按全局上限钳制，覆盖规则可以超出上限
+    assert_eq!(ttl("web.corp").await, 600);
+    assert_eq!(ttl("static.cdn.corp").await, 86400);
+
+    let rules: Vec<String> = resolver.list_ttl_overrides().iter().map(ToString::to_string).collect();
+    assert_eq!(rules, vec!["*.failover.corp exact 30s".to_string(), "static.cdn.corp min 86400s".to_string()]);
+    let hits: Vec<u64> = resolver.cache_stats().unwrap().ttl_overrides.iter().map(|stats| stats.hits).collect();
+    assert_eq!(hits, vec![1, 1]);
+}
+
+#[tokio::test]
+async fn test_ttl_clamp_applies_to_cached_lifetime_and_returned_records() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseBuilder;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{QClass, Record, RecordData, RecordType, ResponseCode};
+    use std::net::Ipv4Addr;
+
+    // SOA自身TTL为0的NXDOMAIN：权威服务器明确要求不缓存
+    let uncacheable = DnsResponseBuilder::new()
+        .with_rcode(ResponseCode::NxDomain)
+        .add_query("gone.example.com".to_string(), RecordType::A, QClass::IN)
+        .add_authority(Record {
+            name: "example.com".to_string(),
+            rtype: RecordType::SOA,
+            class: QClass::IN,
+            ttl: 0,
+            data: RecordData::SOA {
+                mname: "ns1.example.com".to_string(),
+                rname: "hostmaster.example.com".to_string(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            },
+        })
+        .build();
+    let mock = || MockTransport::new()
+        .with_a("short.example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 0)
+        .with_a("long.example.com", &[Ipv4Addr::new(192, 0, 2, 2)], 1_000_000)
+        .with_response("gone.example.com", RecordType::A, uncacheable.clone());
+    let build = |mock: MockTransport, min_ttl: Duration| async move {
+        DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .add_mock_upstream("mock", mock)
+            .unwrap()
+            .with_cache(true)
+            .with_cache_ttl(Duration::from_secs(3600))
+            .with_min_ttl(min_ttl)
+            .with_max_ttl(Duration::from_secs(600))
+            .build()
+            .await
+            .unwrap()
+    };
+    async fn query(resolver: &SmartDnsResolver, name: &str) -> rat_quickdns::DnsQueryResponse {
+        resolver.query(DnsQueryRequest::new(name, DnsRecordType::A)).await.unwrap()
+    }
+
+    let handle = mock();
+    let resolver = build(handle.clone(), Duration::from_secs(30)).await;
+    for (name, clamped) in [("short.example.com", 30), ("long.example.com", 600)] {
+        let fresh = query(&resolver, name).await;
+        assert_eq!(fresh.records[0].ttl, clamped, "{}", name);
+        // 缓存按钳制后的TTL保存，命中时返回剩余时间
+        let cached = query(&resolver, name).await;
+        assert_eq!(cached.server_used.as_deref(), Some("cache"));
+        assert!((clamped - 1..=clamped).contains(&cached.records[0].ttl), "{}: {}", name, cached.records[0].ttl);
+    }
+    assert_eq!(handle.call_count(), 2);
+
+    // 明确不可缓存的NXDOMAIN不被抬高，也不写入缓存
+    for _ in 0..2 {
+        let response = query(&resolver, "gone.example.com").await;
+        assert_eq!(response.rcode, Some(3));
+        assert_eq!(response.negative_ttl, Some(0));
+    }
+    assert_eq!(handle.call_count(), 4);
+
+    // min_ttl为0时不抬高，TTL为0的应答照常不缓存
+    let handle = mock();
+    let resolver = build(handle.clone(), Duration::ZERO).await;
+    assert_eq!(query(&resolver, "short.example.com").await.records[0].ttl, 0);
+    assert_eq!(query(&resolver, "short.example.com").await.records[0].ttl, 0);
+    assert_eq!(query(&resolver, "long.example.com").await.records[0].ttl, 600);
+    assert_eq!(handle.call_count(), 3);
+}
+
+#[tokio::test]
+async fn test_questionless_and_multi_question_responses_follow_policy() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseWrapper;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::RecordType;
+    use std::net::Ipv4Addr;
+
+    let mut questionless = DnsResponseWrapper::create_a_response(0, "forwarder.example", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+    questionless.queries.clear();
+    let mut two_questions = DnsResponseWrapper::create_a_response(0, "double.example", &[Ipv4Addr::new(192, 0, 2, 2)], 300);
+    two_questions.queries.push(two_questions.queries[0].clone());
+
+    for accept in [false, true] {
+        let mock = MockTransport::new()
+            .with_response("forwarder.example", RecordType::A, questionless.clone())
+            .with_response("double.example", RecordType::A, two_questions.clone());
+        let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .add_mock_upstream("mock", mock)
+            .unwrap()
+            .with_cache(true)
+            .with_accept_questionless_responses(accept)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(resolver.effective_config().resolver.accept_questionless_responses, accept);
+
+        let answered = |name: &'static str| {
+            let resolver = &resolver;
+            async move {
+                resolver.query(DnsQueryRequest::new(name, DnsRecordType::A)).await
+                    .map(|response| response.success)
+                    .unwrap_or(false)
+            }
+        };
+        assert_eq!(answered("forwarder.example").await, accept);
+        // 多个问题总是畸形报文
+        assert!(!answered("double.example").await);
+        // 接受的无问题段响应补上了问题段，照常写入缓存
+        assert_eq!(resolver.cache_stats().unwrap().inserts, u64::from(accept));
+    }
+}
+
+#[tokio::test]
+async fn test_ip_literal_queries_are_answered_locally() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType, DnsRecordValue, LITERAL_SOURCE};
+    use rat_quickdns::error::DnsError;
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::IpAddr;
+
+    let mock = MockTransport::new();
+    let handle = mock.clone();
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("mock", mock)
+        .unwrap()
+        .with_cache(true)
+        .build()
+        .await
+        .unwrap();
+
+    let cases = [
+        ("192.0.2.1", DnsRecordType::A, Some("192.0.2.1")),
+        ("2001:db8::1", DnsRecordType::AAAA, Some("2001:db8::1")),
+        ("[2001:db8::1]", DnsRecordType::AAAA, Some("2001:db8::1")),
+        ("2001:db8::1", DnsRecordType::A, None),
+        ("192.0.2.1", DnsRecordType::AAAA, None),
+    ];
+    for (domain, record_type, expected) in cases {
+        let response = resolver.query(DnsQueryRequest::new(domain, record_type)).await.unwrap();
+        assert!(response.success, "{} {:?}", domain, record_type);
+        assert_eq!(response.server_used.as_deref(), Some(LITERAL_SOURCE));
+        assert_eq!(response.duration_ms, 0);
+        assert_eq!(response.rcode, Some(0));
+        assert!(!response.query_id.is_empty());
+        let addresses: Vec<IpAddr> = response.records.iter()
+            .filter_map(|record| match record.value {
+                DnsRecordValue::IpAddr(ip) => Some(ip),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(addresses, expected.map(|ip| ip.parse::<IpAddr>().unwrap()).into_iter().collect::<Vec<_>>());
+    }
+
+    assert_eq!(resolver.lookup_ip("192.0.2.1", DnsRecordType::A).await.unwrap(), vec!["192.0.2.1".parse::<IpAddr>().unwrap()]);
+    assert!(matches!(resolver.lookup_ip("192.0.2.1", DnsRecordType::AAAA).await, Err(DnsError::NoRecords { .. })));
+
+    // 没有联系上游，也没有读写缓存
+    assert_eq!(handle.call_count(), 0);
+    let stats = resolver.cache_stats().unwrap();
+    assert_eq!((stats.hits, stats.misses, stats.inserts), (0, 0, 0));
+}
+
+#[tokio::test]
+async fn test_answer_records_of_the_wrong_type_are_dropped() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseBuilder;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{QClass, RecordType};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    // AAAA应答里混入了A记录、与查询无关的CNAME和TXT
+    let polluted = DnsResponseBuilder::new()
+        .add_query("polluted.example".to_string(), RecordType::AAAA, QClass::IN)
+        .add_cname_answer("polluted.example".to_string(), 300, "edge.example".to_string())
+        .add_a_answer("edge.example".to_string(), 300, Ipv4Addr::new(192, 0, 2, 9))
+        .add_aaaa_answer("edge.example".to_string(), 300, Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+        .add_cname_answer("unrelated.example".to_string(), 300, "other.example".to_string())
+        .add_txt_answer("edge.example".to_string(), 300, vec!["v=spf1 -all".to_string()])
+        .build();
+    let build = |lenient: bool| {
+        let mock = MockTransport::new().with_response("polluted.example", RecordType::AAAA, polluted.clone());
+        DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .with_cache(false)
+            .with_lenient_answer_types(lenient)
+            .add_mock_upstream("mock", mock)
+            .unwrap()
+            .build()
+    };
+    let request = || DnsQueryRequest::new("polluted.example", DnsRecordType::AAAA);
+
+    let resolver = build(false).await.unwrap();
+    let response = resolver.query(request()).await.unwrap();
+    let types: Vec<DnsRecordType> = response.records.iter().map(|record| record.record_type).collect();
+    assert_eq!(types, vec![DnsRecordType::CNAME, DnsRecordType::AAAA]);
+    assert_eq!(resolver.lookup_ip_with(request()).await.unwrap(), vec![IpAddr::from(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))]);
+    let stats = resolver.get_stats().await;
+    assert_eq!(stats.dropped_mismatched_records, 6);
+    assert_eq!(stats.to_json_value()["dropped_mismatched_records"], 6);
+
+    // 宽松模式原样返回，地址仍按查询类型筛选地址族
+    let lenient = build(true).await.unwrap();
+    let response = lenient.query(request()).await.unwrap();
+    assert_eq!(response.records.len(), 5);
+    assert_eq!(response.ip_addresses(), vec![IpAddr::from(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))]);
+    assert_eq!(lenient.get_stats().await.dropped_mismatched_records, 0);
+}
+
+#[tokio::test]
+async fn test_oversized_answers_are_truncated_or_rejected() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::transport::{RecordLimitPolicy, RecordLimits};
+    use rat_quickdns::dns_response::DnsResponseWrapper;
+    use rat_quickdns::types::RecordType;
+    use std::net::Ipv4Addr;
+
+    let ips: Vec<Ipv4Addr> = (0..5000u32).map(|i| Ipv4Addr::from(0xC000_0000 + i)).collect();
+    let flood = DnsResponseWrapper::create_a_response(0, "flood.example", &ips, 300);
+    let normal = DnsResponseWrapper::create_a_response(0, "normal.example", &ips[..3], 300);
+    let build = |policy: RecordLimitPolicy, cache_truncated: bool| {
+        let mock = MockTransport::new()
+            .with_response("flood.example", RecordType::A, flood.clone())
+            .with_response("normal.example", RecordType::A, normal.clone());
+        let builder = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .with_cache(true)
+            .with_record_limits(RecordLimits { policy, ..RecordLimits::default() })
+            .unwrap()
+            .with_cache_truncated_responses(cache_truncated)
+            .add_mock_upstream("mock", mock.clone())
+            .unwrap();
+        (builder, mock)
+    };
+    let flood_request = || DnsQueryRequest::new("flood.example", DnsRecordType::A);
+
+    // 截断：只返回上限内的记录并标记，默认不写入缓存
+    let (builder, mock) = build(RecordLimitPolicy::Truncate, false);
+    let resolver = builder.build().await.unwrap();
+    let response = resolver.query(flood_request()).await.unwrap();
+    assert!(response.success && response.records_truncated);
+    assert_eq!(response.records.len(), RecordLimits::DEFAULT_MAX_ANSWER_RECORDS);
+    resolver.query(flood_request()).await.unwrap();
+    assert_eq!(mock.call_count(), 2);
+    // 正常的响应不受影响，照常缓存
+    let response = resolver.query(DnsQueryRequest::new("normal.example", DnsRecordType::A)).await.unwrap();
+    assert!(!response.records_truncated);
+    assert_eq!(response.records.len(), 3);
+    resolver.query(DnsQueryRequest::new("normal.example", DnsRecordType::A)).await.unwrap();
+    assert_eq!(mock.call_count(), 3);
+
+    // 允许缓存截断的响应时，第二次查询命中缓存
+    let (builder, mock) = build(RecordLimitPolicy::Truncate, true);
+    let resolver = builder.build().await.unwrap();
+    assert!(resolver.query(flood_request()).await.unwrap().records_truncated);
+    let cached = resolver.query(flood_request()).await.unwrap();
+    assert_eq!((cached.records.len(), mock.call_count()), (RecordLimits::DEFAULT_MAX_ANSWER_RECORDS, 1));
+
+    // 拒绝：整个响应作为协议错误丢弃，查询失败
+    let (builder, _) = build(RecordLimitPolicy::Reject, false);
+    let resolver = builder.build().await.unwrap();
+    let response = resolver.query(flood_request()).await.unwrap();
+    assert!(!response.success && !response.records_truncated);
+    let response = resolver.query(DnsQueryRequest::new("normal.example", DnsRecordType::A)).await.unwrap();
+    assert_eq!(response.records.len(), 3);
+
+    let invalid = RecordLimits { max_answer_records: 10, max_total_records: 5, ..RecordLimits::default() };
+    assert!(DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string()).with_record_limits(invalid).is_err());
+}
+
+#[cfg(feature = "serde-api")]
+#[tokio::test]
+async fn test_invalid_encoded_requests_get_error_codes_without_querying() {
+    use rat_quickdns::builder::encoded::EncodedRequestLimits;
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsQueryResponse, DnsRecordType, RequestErrorCode};
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::dns_response::DnsResponseWrapper;
+    use rat_quickdns::types::RecordType;
+    use std::net::Ipv4Addr;
+
+    let config = bincode::config::standard();
+    let answer = DnsResponseWrapper::create_a_response(0, "example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+    let mock = MockTransport::new().with_response("example.com", RecordType::A, answer);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_encoded_request_limits(EncodedRequestLimits { max_payload_bytes: 1024, max_batch_len: 3 })
+        .unwrap()
+        .add_mock_upstream("mock", mock.clone())
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let query = |bytes: Vec<u8>| {
+        let resolver = &resolver;
+        async move {
+            let encoded = resolver.process_encoded_query(&bytes).await.unwrap();
+            bincode::decode_from_slice::<DnsQueryResponse, _>(&encoded, config).unwrap().0
+        }
+    };
+
+    let response = query(bincode::encode_to_vec(DnsQueryRequest::new("example.com", DnsRecordType::A), config).unwrap()).await;
+    assert!(response.success && response.error_code.is_none());
+    let response = query(vec![0u8; 1025]).await;
+    assert_eq!((response.success, response.error_code), (false, Some(RequestErrorCode::PayloadTooLarge)));
+    let response = query(bincode::encode_to_vec(DnsQueryRequest::new("bad name", DnsRecordType::A), config).unwrap()).await;
+    assert_eq!((response.success, response.error_code), (false, Some(RequestErrorCode::InvalidDomain)));
+    assert!(response.error.unwrap().contains("bad name"));
+    assert_eq!(mock.call_count(), 1);
+
+    // 批量：无效的请求只让对应的响应失败，顺序不变；超过上限时整批只返回一个失败响应
+    let batch = vec![
+        DnsQueryRequest::new("a..b", DnsRecordType::A),
+        DnsQueryRequest::new("example.com", DnsRecordType::A),
+    ];
+    let encoded = resolver.process_encoded_batch(&bincode::encode_to_vec(&batch, config).unwrap()).await.unwrap();
+    let (responses, _): (Vec<DnsQueryResponse>, usize) = bincode::decode_from_slice(&encoded, config).unwrap();
+    assert_eq!(responses.iter().map(|r| (r.success, r.error_code)).collect::<Vec<_>>(), vec![
+        (false, Some(RequestErrorCode::InvalidDomain)),
+        (true, None),
+    ]);
+    assert_eq!(responses[0].domain, "a..b");
+
+    let batch = vec![DnsQueryRequest::new("example.com", DnsRecordType::A); 4];
+    let encoded = resolver.process_encoded_batch(&bincode::encode_to_vec(&batch, config).unwrap()).await.unwrap();
+    let (responses, _): (Vec<DnsQueryResponse>, usize) = bincode::decode_from_slice(&encoded, config).unwrap();
+    assert_eq!(responses.iter().map(|r| r.error_code).collect::<Vec<_>>(), vec![Some(RequestErrorCode::BatchTooLarge)]);
+    assert_eq!(mock.call_count(), 2);
+
+    let builder = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string());
+    assert!(builder.with_encoded_request_limits(EncodedRequestLimits { max_payload_bytes: 0, max_batch_len: 1 }).is_err());
+}
+
+#[tokio::test]
+async fn test_failed_queries_carry_aggregated_diagnostics() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::{DnsResponseBuilder, DnsResponseWrapper};
+    use rat_quickdns::resolver::diagnosis::AttemptOutcome;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{QClass, RecordType};
+    use std::net::Ipv4Addr;
+
+    let refused = || DnsResponseBuilder::new().with_response_code(5).add_query("example.com".to_string(), RecordType::A, QClass::IN).build();
+    let build = |upstreams: Vec<(&'static str, MockTransport)>| async move {
+        let mut builder = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .with_cache(false)
+            .with_retry_count(0);
+        for (name, mock) in upstreams {
+            builder = builder.add_mock_upstream(name, mock).unwrap();
+        }
+        builder.build().await.unwrap()
+    };
+    let hint_codes = |response: &rat_quickdns::builder::types::DnsQueryResponse| -> Vec<String> {
+        response.failure_report.as_ref().unwrap().hints.iter().map(|hint| hint.code.clone()).collect()
+    };
+
+    // 所有明文上游都返回REFUSED
+    let resolver = build(vec![
+        ("google-udp", MockTransport::new().with_transport_type("UDP").with_response("example.com", RecordType::A, refused())),
+        ("cloudflare-udp", MockTransport::new().with_transport_type("UDP").with_response("example.com", RecordType::A, refused())),
+    ]).await;
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+    assert_eq!(response.rcode, Some(5));
+    assert_eq!(hint_codes(&response), ["port53_interception"]);
+    let report = response.failure_report.unwrap();
+    assert_eq!(report.attempts.iter().map(|a| (a.upstream.as_str(), a.rcode)).collect::<Vec<_>>(), [
+        ("google-udp", Some(5)),
+        ("cloudflare-udp", Some(5)),
+    ]);
+    let error = resolver.lookup_ip("example.com", DnsRecordType::A).await.unwrap_err();
+    let DnsError::AllUpstreamsFailed { report } = &error else {
+        panic!("expected aggregated failure, got {:?}", error);
+    };
+    assert_eq!(report.upstream_count(), 2);
+    assert!(error.to_string().contains("port 53"), "{}", error);
+
+    // 所有上游都超时
+    let resolver = build(vec![
+        ("google-udp", MockTransport::new().with_transport_type("UDP").with_error("example.com", RecordType::A, DnsError::Timeout { phase: None })),
+        ("quad9-dot", MockTransport::new().with_transport_type("TLS").with_error("example.com", RecordType::A, DnsError::Timeout { phase: None })),
+    ]).await;
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+    assert!(!response.success);
+    assert_eq!(hint_codes(&response), ["no_connectivity"]);
+    assert!(response.error.unwrap().contains("All 2 upstream attempts failed"));
+    assert!(response.failure_report.unwrap().attempts.iter().all(|a| a.outcome == AttemptOutcome::Timeout));
+    assert!(matches!(resolver.lookup_ip("example.com", DnsRecordType::A).await, Err(DnsError::AllUpstreamsFailed { .. })));
+
+    // 开放的上游拒绝，只有过滤型服务商的上游返回NXDOMAIN
+    let resolver = build(vec![
+        ("google-udp", MockTransport::new().with_transport_type("UDP").with_response("example.com", RecordType::A, refused())),
+        ("adguard-udp", MockTransport::new().with_transport_type("UDP").with_nxdomain("example.com", RecordType::A)),
+    ]).await;
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+    assert_eq!(response.rcode, Some(3));
+    assert_eq!(hint_codes(&response), ["nxdomain_filtering"]);
+    assert!(matches!(resolver.lookup_ip("example.com", DnsRecordType::A).await, Err(DnsError::NxDomain)));
+
+    // 成功的查询没有诊断
+    let answer = DnsResponseWrapper::create_a_response(0, "example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+    let resolver = build(vec![
+        ("google-udp", MockTransport::new().with_transport_type("UDP").with_response("example.com", RecordType::A, refused())),
+        ("cloudflare-udp", MockTransport::new().with_transport_type("UDP").with_response("example.com", RecordType::A, answer)),
+    ]).await;
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+    assert_eq!(response.server_used.as_deref(), Some("cloudflare-udp"));
+    assert!(response.failure_report.is_none());
+}
+
+#[tokio::test]
+async fn test_batch_queries_default_to_bulk_priority() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::resolver::priority::QueryPriority;
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    assert!(DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string()).with_interactive_reserve(1.0).is_err());
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_interactive_reserve(0.25)
+        .unwrap()
+        .add_mock_upstream("mock", MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300))
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(resolver.effective_config().resolver.interactive_reserve, 0.25);
+
+    let request = || DnsQueryRequest::new("example.com", DnsRecordType::A);
+    let batch = resolver.batch_query(vec![request(), request(), request().with_priority(QueryPriority::Interactive)]).await;
+    assert!(batch.into_iter().all(|response| response.unwrap().success));
+    resolver.query(request()).await.unwrap();
+
+    let admitted: Vec<(QueryPriority, u64)> = resolver.get_stats().await.priorities.iter()
+        .map(|stats| (stats.priority, stats.admitted))
+        .collect();
+    assert_eq!(admitted, [(QueryPriority::Interactive, 1), (QueryPriority::Normal, 1), (QueryPriority::Bulk, 2)]);
+}
+
+#[tokio::test]
+async fn test_openmetrics_export_counts_queries_and_upstreams() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::RecordType;
+    use std::net::Ipv4Addr;
+    
+    let mock = MockTransport::new()
+        .with_a("www.example.test", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+        .with_nxdomain("missing.example.test", RecordType::A);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .with_retry_count(0)
+        .add_mock_upstream("mock", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        resolver.query(DnsQueryRequest::new("www.example.test", DnsRecordType::A)).await.unwrap();
+    }
+    resolver.query(DnsQueryRequest::new("missing.example.test", DnsRecordType::A)).await.unwrap();
+    
+    let text = resolver.render_openmetrics().await;
+    assert!(text.ends_with("# EOF\n"));
+    assert!(text.contains("# TYPE rat_quickdns_queries counter\n"));
+    assert!(text.contains("rat_quickdns_queries_total{record_type=\"A\",outcome=\"noerror\"} 3\n"), "{}", text);
+    assert!(text.contains("rat_quickdns_queries_total{record_type=\"A\",outcome=\"nxdomain\"} 1\n"), "{}", text);
+    assert!(text.contains("rat_quickdns_upstream_latency_seconds_bucket{upstream=\"mock\",le=\"+Inf\"} "));
+    assert!(text.contains("rat_quickdns_upstream_state{upstream=\"mock\",state=\"available\"} 1\n"));
+    assert!(text.contains("rat_quickdns_cache_hits_total 2\n"), "{}", text);
+    assert!(text.contains("rat_quickdns_emergency_mode 0\n"));
+    assert!(text.contains("rat_quickdns_in_flight_sends 0\n"));
+    resolver.shutdown().await;
+}
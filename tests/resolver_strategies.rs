@@ -0,0 +1,927 @@
+//! 上游选择与故障转移：各查询策略、重试、自适应超时、紧急模式、粘滞、仲裁和候选过滤
+
+use rat_quickdns::builder::EmergencyPolicy;
+use rat_quickdns::resolver::rotation::RotationMode;
+use rat_quickdns::types::UpstreamFeatures;
+use rat_quickdns::upstream_handler::QueueBehavior;
+use rat_quickdns::{
+    AdaptiveTimeoutConfig, CdnProbe, DnsError, DnsResolverBuilder, EncryptedFallbackPolicy, ProbeConfig, QueryStrategy,
+    SmartDnsResolver, StickinessConfig,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_explain_attaches_selection_to_smart_queries() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let build = |strategy| async move {
+        DnsResolverBuilder::new(strategy, false, "global".to_string())
+            .disable_logger_init()
+            .add_mock_upstream("a", MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300))
+            .unwrap()
+            .add_mock_upstream("b", MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 2)], 300))
+            .unwrap()
+            .build()
+            .await
+            .unwrap()
+    };
+    let request = DnsQueryRequest::new("example.com", DnsRecordType::A);
+
+    let smart = build(QueryStrategy::Smart).await;
+    assert!(smart.query(request.clone()).await.unwrap().selection.is_none());
+    let response = smart.query(request.clone().with_explain(true)).await.unwrap();
+    let selection = response.selection.expect("smart query explains its selection");
+    assert_eq!(selection.candidates.len(), 2);
+    assert!(selection.selected.is_some());
+    assert!(smart.explain_selection().await.is_some());
+
+    let fifo = build(QueryStrategy::Fifo).await;
+    assert!(fifo.query(request.with_explain(true)).await.unwrap().selection.is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_adaptive_timeout_fails_over_before_default_timeout() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let primary = MockTransport::new()
+        .with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+        .with_latency(Duration::from_millis(20));
+    let adaptive = AdaptiveTimeoutConfig::new(3.0, Duration::from_millis(50), Duration::from_secs(8), 10);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Sequential, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_retry_count(0)
+        .with_adaptive_timeout(adaptive)
+        .add_mock_upstream("primary", primary.clone())
+        .unwrap()
+        .add_mock_upstream("backup", MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 2)], 300))
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let request = DnsQueryRequest::new("example.com", DnsRecordType::A);
+
+    for _ in 0..10 {
+        let response = resolver.query(request.clone()).await.unwrap();
+        assert_eq!(response.server_used.as_deref(), Some("primary"));
+    }
+    let timeouts: std::collections::HashMap<_, _> = resolver.recalculate_adaptive_timeouts().await.into_iter().collect();
+    assert!(timeouts["primary"] < Duration::from_millis(100), "{:?}", timeouts);
+    assert_eq!(timeouts["backup"], Duration::from_secs(5));
+    let status = resolver.get_upstream_status().await;
+    assert_eq!(status[0].effective_timeout, timeouts["primary"]);
+
+    // 主上游卡住：按自适应超时放弃，而不是等满5秒
+    primary.set_latency(Duration::from_secs(3));
+    let started = tokio::time::Instant::now();
+    let response = resolver.query(request).await.unwrap();
+    assert_eq!(response.server_used.as_deref(), Some("backup"));
+    assert!(started.elapsed() < Duration::from_millis(200), "{:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn test_emergency_mode_follows_success_ratio() {
+    use rat_quickdns::builder::emergency::ResolverMode;
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    assert!(DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .with_emergency_threshold(1.5)
+        .is_err());
+
+    let mock = MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 7)], 30);
+    let handle = mock.clone();
+    let policy = EmergencyPolicy::new(0.5)
+        .with_recovery_margin(0.25)
+        .with_window(6, 4)
+        .with_ttl_floor(Duration::from_secs(300));
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_emergency_policy(policy)
+        .unwrap()
+        .add_mock_upstream("模拟DNS", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let query = || resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A));
+
+    let response = query().await.unwrap();
+    assert!(response.success && !response.emergency_mode);
+    assert_eq!(response.records[0].ttl, 30);
+
+    // 1成功3失败，成功率0.25低于阈值
+    handle.set_healthy(false);
+    for _ in 0..3 {
+        assert!(!query().await.unwrap().success);
+    }
+    assert_eq!(resolver.resolver_mode(), ResolverMode::Emergency);
+    let stats = resolver.get_stats().await;
+    assert!(stats.emergency_mode);
+    assert_eq!(stats.recent_success_ratio, Some(0.25));
+
+    // 应急模式下的响应TTL被抬高到下限
+    handle.set_healthy(true);
+    let response = query().await.unwrap();
+    assert!(response.success && response.emergency_mode);
+    assert_eq!(response.records[0].ttl, 300);
+
+    // 成功率回到阈值但未超过恢复线时保持应急模式
+    query().await.unwrap();
+    assert_eq!(resolver.resolver_mode(), ResolverMode::Emergency);
+
+    // 窗口 [F,S,S,S,S,S] 成功率0.83，退出应急模式
+    for _ in 0..3 {
+        query().await.unwrap();
+    }
+    assert_eq!(resolver.resolver_mode(), ResolverMode::Normal);
+    let response = query().await.unwrap();
+    assert!(!response.emergency_mode);
+    assert_eq!(response.records[0].ttl, 30);
+}
+
+#[tokio::test]
+async fn test_servfail_fails_over_to_next_upstream() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseWrapper;
+    use rat_quickdns::resolver::UpstreamFailover;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{RecordType, ResponseCode};
+    use std::net::Ipv4Addr;
+
+    assert!(DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .with_max_failover_attempts(0)
+        .is_err());
+
+    // 出错的上游先应答，健康的上游稍慢
+    let broken = MockTransport::new().with_response(
+        "example.com",
+        RecordType::A,
+        DnsResponseWrapper::create_server_failure_response(0, "example.com", RecordType::A),
+    );
+    let healthy = MockTransport::new()
+        .with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+        .with_latency(Duration::from_millis(50));
+    let (broken_handle, healthy_handle) = (broken.clone(), healthy.clone());
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_retry_count(0)
+        .with_query_history(16)
+        .add_mock_upstream("broken", broken)
+        .unwrap()
+        .add_mock_upstream("healthy", healthy)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+    assert!(response.success);
+    assert_eq!(response.server_used.as_deref(), Some("healthy"));
+    assert_eq!(response.ip_addresses().len(), 1);
+    // 每个上游只问一次
+    assert_eq!(broken_handle.call_count(), 1);
+    assert_eq!(healthy_handle.call_count(), 1);
+
+    let entry = resolver.recent_queries(1).pop().unwrap();
+    assert_eq!(entry.failovers, vec![UpstreamFailover {
+        upstream: "broken".to_string(),
+        rcode: ResponseCode::ServerFailure,
+    }]);
+}
+
+#[tokio::test]
+async fn test_backup_tier_serves_until_primary_recovers() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseBuilder;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{QClass, RecordType};
+    use std::net::Ipv4Addr;
+    use std::time::Instant;
+
+    let probe_ns = |name: &str| DnsResponseBuilder::new()
+        .add_query(name.to_string(), RecordType::NS, QClass::IN)
+        .add_ns_answer(name.to_string(), 86400, "ns1.intranet.example".to_string())
+        .build();
+    let primary = MockTransport::new()
+        .with_a("intranet.example", &[Ipv4Addr::new(10, 0, 0, 1)], 60)
+        .with_response("probe-a.example", RecordType::NS, probe_ns("probe-a.example"))
+        .with_response("probe-b.example", RecordType::NS, probe_ns("probe-b.example"));
+    let backup = MockTransport::new().with_a("intranet.example", &[Ipv4Addr::new(192, 0, 2, 1)], 60);
+    let handle = primary.clone();
+    handle.set_healthy(false);
+    let interval = Duration::from_millis(200);
+    let builder = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_retry_count(0)
+        .with_upstream_monitoring(true)
+        .with_upstream_monitoring_interval(interval)
+        .add_mock_upstream("primary", primary)
+        .unwrap()
+        .add_mock_upstream("backup", backup)
+        .unwrap()
+        .with_upstream_tier("backup", 1)
+        .unwrap();
+    // 备用层级靠探测发现主层级恢复，探测域名没有默认值
+    assert!(matches!(builder.clone().build().await, Err(DnsError::InvalidConfig(_))));
+    let probe = ProbeConfig::new(
+        vec!["probe-a.example".to_string(), "probe-b.example".to_string()],
+        DnsRecordType::NS,
+        Duration::from_secs(1),
+    );
+    let resolver = builder.with_health_probe(probe).build().await.unwrap();
+    let query = || resolver.query(DnsQueryRequest::new("intranet.example", DnsRecordType::A));
+    assert_eq!(resolver.get_stats().await.current_active_tier, Some(0));
+
+    // 主层级失败但尚未被判定为不可用时，查询照常失败，不提前动用备用层级
+    for _ in 0..2 {
+        assert!(!query().await.unwrap().success);
+    }
+    // 第三次失败使主层级的最后一个上游变为不可用，同一次查询改由备用层级应答
+    let response = query().await.unwrap();
+    assert_eq!(response.server_used.as_deref(), Some("backup"));
+    assert_eq!(resolver.get_stats().await.current_active_tier, Some(1));
+    assert_eq!(query().await.unwrap().server_used.as_deref(), Some("backup"));
+
+    // 主层级恢复后，一个监控间隔内的探测使流量回到主层级
+    handle.set_healthy(true);
+    tokio::time::sleep(interval).await;
+    let deadline = Instant::now() + interval;
+    let mut served_by = query().await.unwrap().server_used;
+    while served_by.as_deref() != Some("primary") && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        served_by = query().await.unwrap().server_used;
+    }
+    assert_eq!(served_by.as_deref(), Some("primary"));
+    assert_eq!(resolver.get_stats().await.current_active_tier, Some(0));
+    // 两轮探测依次使用配置的两个域名
+    let probes: Vec<String> = handle.calls().into_iter()
+        .map(|call| call.request.query.name)
+        .filter(|name| name.starts_with("probe-"))
+        .collect();
+    assert_eq!(probes, vec!["probe-a.example", "probe-b.example"]);
+}
+
+#[tokio::test]
+async fn test_disabled_stats_reject_smart_and_report_disabled_snapshot() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let mock = || MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+    let smart = DnsResolverBuilder::new(QueryStrategy::Smart, false, "global".to_string())
+        .disable_logger_init()
+        .with_stats(false)
+        .add_mock_upstream("mock", mock())
+        .unwrap()
+        .build()
+        .await;
+    assert!(matches!(smart, Err(DnsError::InvalidConfig(msg)) if msg.contains("Smart") && msg.contains("with_stats")));
+
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_stats(false)
+        .add_mock_upstream("mock", mock())
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+        assert!(response.success, "{:?}", response.error);
+    }
+
+    assert!(resolver.get_decision_engine().unwrap().get_all_metrics().await.is_empty());
+    let stats = resolver.get_stats().await;
+    assert!(!stats.stats_enabled);
+    assert_eq!((stats.total_upstreams, stats.available_upstreams, stats.total_queries), (1, 1, 0));
+    let json = stats.to_json_value();
+    assert_eq!(json["stats_enabled"], false);
+    assert!(json["total_queries"].is_null() && json["success_rate"].is_null() && json["p95_latency_ms"].is_null());
+    assert_eq!(json["total_upstreams"], 1);
+    assert_eq!(json["strategy"], "Fifo");
+}
+
+#[tokio::test]
+async fn test_saturated_upstream_is_skipped_and_counted() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    // 从不应答的上游：每个查询都会一直占着它的名额
+    let stuck = MockTransport::new()
+        .with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+        .with_latency(Duration::from_secs(3600));
+    let healthy = MockTransport::new().with_a("example.com", &[Ipv4Addr::new(198, 51, 100, 1)], 300);
+    let (stuck_handle, healthy_handle) = (stuck.clone(), healthy.clone());
+    let builder = DnsResolverBuilder::new(QueryStrategy::Smart, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_timeout(Duration::from_millis(300))
+        .add_mock_upstream("stuck", stuck)
+        .unwrap()
+        .add_mock_upstream("healthy", healthy)
+        .unwrap();
+    assert!(builder.clone().with_upstream_max_inflight("stuck", 0, QueueBehavior::Reject).is_err());
+    let resolver = builder
+        .with_upstream_max_inflight("stuck", 3, QueueBehavior::Reject)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let queries = (0..50).map(|_| resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)));
+    let responses = futures::future::join_all(queries).await;
+    for response in responses {
+        let response = response.unwrap();
+        assert!(response.success, "{:?}", response.error);
+        assert_eq!(response.server_used.as_deref(), Some("healthy"));
+    }
+    assert_eq!(stuck_handle.call_count(), 3);
+    assert_eq!(healthy_handle.call_count(), 50);
+
+    let status = resolver.get_upstream_status().await;
+    let stuck_status = status.iter().find(|status| status.name == "stuck").unwrap();
+    assert_eq!((stuck_status.max_inflight, stuck_status.saturated), (Some(3), 47));
+    let healthy_status = status.iter().find(|status| status.name == "healthy").unwrap();
+    assert_eq!((healthy_status.max_inflight, healthy_status.saturated), (None, 0));
+}
+
+#[tokio::test]
+async fn test_probe_with_too_few_answers_counts_as_failure() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseBuilder;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{QClass, RecordType};
+    use std::net::Ipv4Addr;
+
+    // 主层级对探测域名返回没有记录的NOERROR，例如大包路径出问题后只剩空应答
+    let empty = DnsResponseBuilder::new()
+        .add_query("probe.example".to_string(), RecordType::TXT, QClass::IN)
+        .build();
+    let primary = MockTransport::new()
+        .with_a("intranet.example", &[Ipv4Addr::new(10, 0, 0, 1)], 60)
+        .with_response("probe.example", RecordType::TXT, empty);
+    let handle = primary.clone();
+    handle.set_healthy(false);
+    let interval = Duration::from_millis(100);
+    let probe = ProbeConfig::new(vec!["probe.example".to_string()], DnsRecordType::TXT, Duration::from_secs(1))
+        .with_min_answers(1);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_retry_count(0)
+        .with_upstream_monitoring(true)
+        .with_upstream_monitoring_interval(interval)
+        .with_health_probe(probe)
+        .add_mock_upstream("primary", primary)
+        .unwrap()
+        .add_mock_upstream("backup", MockTransport::new().with_a("intranet.example", &[Ipv4Addr::new(192, 0, 2, 1)], 60))
+        .unwrap()
+        .with_upstream_tier("backup", 1)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let query = || resolver.query(DnsQueryRequest::new("intranet.example", DnsRecordType::A));
+    for _ in 0..3 {
+        query().await.unwrap();
+    }
+    assert_eq!(resolver.get_stats().await.current_active_tier, Some(1));
+
+    // 主层级恢复收发，但探测应答不足一条，仍留在备用层级
+    handle.set_healthy(true);
+    for _ in 0..4 {
+        tokio::time::sleep(interval + Duration::from_millis(20)).await;
+        assert_eq!(query().await.unwrap().server_used.as_deref(), Some("backup"));
+    }
+    let probes = handle.calls().iter().filter(|call| call.request.query.name == "probe.example").count();
+    assert!(probes >= 3, "{} probes", probes);
+    assert_eq!(resolver.get_stats().await.current_active_tier, Some(1));
+}
+
+#[tokio::test]
+async fn test_sequential_strategy_tries_upstreams_in_order() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let mock = |last_octet: u8| MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, last_octet)], 300);
+    let (first, second, third) = (mock(1), mock(2), mock(3));
+    let handles = [first.clone(), second.clone(), third.clone()];
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Sequential, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_retry_count(1)
+        .with_upstream_monitoring(false)
+        .add_mock_upstream("first", first)
+        .unwrap()
+        .add_mock_upstream("second", second)
+        .unwrap()
+        .add_mock_upstream("third", third)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let counts = || handles.iter().map(MockTransport::call_count).collect::<Vec<_>>();
+    let query = || resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A));
+
+    // 第一个上游有应答时，后面的上游一次都不联系
+    assert_eq!(query().await.unwrap().server_used.as_deref(), Some("first"));
+    assert_eq!(counts(), vec![1, 0, 0]);
+
+    // 第一个上游出错：先按重试次数重试，再换第二个，第三个仍不联系
+    handles[0].set_healthy(false);
+    assert_eq!(query().await.unwrap().server_used.as_deref(), Some("second"));
+    assert_eq!(counts(), vec![3, 1, 0]);
+
+    handles[1].set_healthy(false);
+    assert_eq!(query().await.unwrap().server_used.as_deref(), Some("third"));
+    assert_eq!(counts(), vec![5, 3, 1]);
+}
+
+#[tokio::test]
+#[cfg(feature = "dot")]
+async fn test_intercepted_dot_falls_back_to_plaintext_when_opted_in() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseWrapper;
+    use rat_quickdns::transport::UdpTransport;
+    use std::net::Ipv4Addr;
+    use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+    // 使用自签名证书的“拦截代理”：握手总是因证书不受信任而失败
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![Certificate(include_bytes!("../src/transport/testdata/localhost.crt.der").to_vec())],
+            PrivateKey(include_bytes!("../src/transport/testdata/localhost.key.der").to_vec()),
+        )
+        .unwrap();
+    let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+    let intercepting = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dot_address = intercepting.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = intercepting.accept().await {
+            let _ = acceptor.accept(stream).await;
+        }
+    });
+
+    let plaintext = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let plaintext_address = plaintext.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, peer) = plaintext.recv_from(&mut buf).await.unwrap();
+            let request = UdpTransport::deserialize_request(&buf[..len]).unwrap();
+            let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+            plaintext.send_to(&UdpTransport::serialize_response(&response).unwrap(), peer).await.unwrap();
+        }
+    });
+
+    let builder = || DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .with_timeout(Duration::from_secs(2))
+        .with_retry_count(0)
+        .with_upstream_monitoring(true)
+        .add_dot_upstream("dot", dot_address.to_string())
+        .with_plaintext_fallback("dot", &plaintext_address.to_string())
+        .with_encrypted_fallback_threshold(2)
+        .unwrap();
+    let request = || DnsQueryRequest::new("portal.example", DnsRecordType::A);
+
+    // 默认严格模式：证书校验失败的查询一直失败
+    let strict = builder().build().await.unwrap();
+    for _ in 0..3 {
+        assert!(!strict.query(request()).await.unwrap().success);
+    }
+    assert_eq!(strict.encrypted_fallback_stats(), None);
+
+    let resolver = builder()
+        .with_encrypted_fallback(EncryptedFallbackPolicy::OpportunisticPlaintext)
+        .build()
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        assert!(!resolver.query(request()).await.unwrap().success);
+    }
+    for _ in 0..2 {
+        let response = resolver.query(request()).await.unwrap();
+        assert!(response.success, "{:?}", response.error);
+        assert!(response.degraded_security);
+        assert_eq!(response.upstream_peer, Some(plaintext_address));
+    }
+    let stats = resolver.encrypted_fallback_stats().unwrap();
+    assert!(stats.active);
+    assert_eq!(stats.degraded_queries, 2);
+    assert!(resolver.get_stats().await.encrypted_fallback_active);
+}
+
+#[tokio::test]
+async fn test_cdn_probes_score_upstreams_by_returned_addresses() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let far = MockTransport::new().with_a("cdn.example", &[Ipv4Addr::new(198, 51, 100, 7)], 60);
+    let near = MockTransport::new().with_a("cdn.example", &[Ipv4Addr::new(203, 0, 113, 7)], 60);
+    let far_handle = far.clone();
+    let probes = vec![
+        CdnProbe::new("cdn.example", "CN", &["203.0.113.0/24"]).unwrap(),
+        CdnProbe::new("us.cdn.example", "US", &["192.0.2.0/24"]).unwrap(),
+    ];
+
+    let no_stats = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "CN".to_string())
+        .disable_logger_init()
+        .with_stats(false)
+        .with_cdn_probes(probes.clone())
+        .add_mock_upstream("far", far.clone())
+        .unwrap()
+        .build()
+        .await;
+    assert!(matches!(no_stats, Err(DnsError::InvalidConfig(msg)) if msg.contains("CDN probes")));
+
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Smart, false, "CN".to_string())
+        .disable_logger_init()
+        .with_cdn_probes(probes)
+        .with_cdn_probe_interval(Duration::from_secs(3600))
+        .add_mock_upstream("far", far)
+        .unwrap()
+        .add_mock_upstream("near", near)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let engine = resolver.get_decision_engine().unwrap().clone();
+    assert!(engine.cdn_verification_enabled());
+    assert_eq!(resolver.cdn_probes().len(), 1);
+
+    // 构建后立即执行第一轮探测
+    for _ in 0..200 {
+        let metrics = engine.get_all_metrics().await;
+        if metrics.values().all(|metric| metric.cdn_checks > 0) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let metrics = engine.get_all_metrics().await;
+    assert_eq!((metrics["far"].cdn_checks, metrics["far"].cdn_accuracy_score), (1, 0.0));
+    assert_eq!((metrics["near"].cdn_checks, metrics["near"].cdn_accuracy_score), (1, 1.0));
+    assert!(far_handle.calls().iter().all(|call| call.request.query.name == "cdn.example"));
+
+    resolver.run_cdn_probes().await.unwrap();
+    let metrics = engine.get_all_metrics().await;
+    assert_eq!((metrics["far"].cdn_checks, metrics["near"].cdn_checks), (2, 2));
+    assert_eq!(metrics["far"].successful_queries, 2);
+
+    // 普通查询不改变CDN准确性评分
+    let response = resolver.query(DnsQueryRequest::new("cdn.example", DnsRecordType::A)).await.unwrap();
+    assert!(response.success, "{:?}", response.error);
+    let metrics = engine.get_all_metrics().await;
+    assert_eq!((metrics["far"].cdn_checks, metrics["near"].cdn_checks), (2, 2));
+}
+
+#[tokio::test]
+async fn test_sticky_keys_stay_on_one_upstream_until_it_is_unavailable() {
+    use rat_quickdns::builder::stickiness::StickyKey;
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let mocks: Vec<MockTransport> = (1..=3u8)
+        .map(|i| MockTransport::new()
+            .with_a("static.example.com", &[Ipv4Addr::new(192, 0, 2, i)], 60)
+            .with_a("img.example.com", &[Ipv4Addr::new(198, 51, 100, i)], 60))
+        .collect();
+    let handles = mocks.clone();
+    let sticky = StickinessConfig::new(StickyKey::ClientSubnet, Duration::from_secs(600));
+    assert!(matches!(
+        DnsResolverBuilder::new(QueryStrategy::RoundRobin, false, "global".to_string())
+            .with_stickiness(sticky.with_capacity(0)),
+        Err(DnsError::InvalidConfig(_))
+    ));
+    let mut builder = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_retry_count(0)
+        .with_upstream_monitoring(true)
+        .with_upstream_monitoring_interval(Duration::from_secs(600))
+        .with_stickiness(sticky)
+        .unwrap();
+    for (name, mock) in ["alpha", "beta", "gamma"].into_iter().zip(mocks) {
+        builder = builder.add_mock_upstream(name, mock).unwrap();
+    }
+    let resolver = builder.build().await.unwrap();
+    let index = |name: &str| ["alpha", "beta", "gamma"].iter().position(|n| *n == name).unwrap();
+
+    async fn served_by(resolver: &SmartDnsResolver, client: Option<&str>, domain: &str) -> String {
+        let mut request = DnsQueryRequest::new(domain, DnsRecordType::A);
+        request.client_address = client.map(str::to_string);
+        let response = resolver.query(request).await.unwrap();
+        assert!(response.success, "{:?}", response.error);
+        response.server_used.unwrap()
+    }
+
+    // B第一次查询时alpha停用，B固定在beta；alpha恢复后FIFO会选alpha，但B仍留在beta
+    let (client_a, client_b) = ("203.0.113.7", "198.51.100.20");
+    let pinned_a = served_by(&resolver, Some(client_a), "static.example.com").await;
+    resolver.set_upstream_enabled("alpha", false).await.unwrap();
+    let pinned_b = served_by(&resolver, Some(client_b), "static.example.com").await;
+    resolver.set_upstream_enabled("alpha", true).await.unwrap();
+    assert_eq!((pinned_a.as_str(), pinned_b.as_str()), ("alpha", "beta"));
+    for domain in ["img.example.com", "static.example.com"].repeat(3) {
+        assert_eq!(served_by(&resolver, Some("203.0.113.99"), domain).await, pinned_a);
+        assert_eq!(served_by(&resolver, Some(client_b), domain).await, pinned_b);
+        // 不带客户端地址的查询照常按查询策略选择
+        assert_eq!(served_by(&resolver, None, domain).await, "alpha");
+    }
+    assert_eq!(resolver.sticky_upstream("203.0.113.0/24"), Some(pinned_a.clone()));
+    let stats = resolver.stickiness_stats().unwrap();
+    assert_eq!((stats.entries, stats.hits, stats.misses, stats.repins), (2, 12, 2, 0));
+
+    // A记住的上游出错，直到被上游监控判定为不可用
+    handles[index(&pinned_a)].set_healthy(false);
+    for _ in 0..10 {
+        let status = resolver.get_upstream_status().await;
+        if status.iter().any(|s| s.name == pinned_a && !s.is_available) {
+            break;
+        }
+        served_by(&resolver, None, "static.example.com").await;
+    }
+    assert!(resolver.get_upstream_status().await.iter().any(|s| s.name == pinned_a && !s.is_available));
+
+    // A改用其他上游并重新固定，B不受影响
+    let repinned_a = served_by(&resolver, Some(client_a), "img.example.com").await;
+    assert_ne!(repinned_a, pinned_a);
+    for domain in ["static.example.com", "img.example.com"] {
+        assert_eq!(served_by(&resolver, Some(client_a), domain).await, repinned_a);
+        assert_eq!(served_by(&resolver, Some(client_b), domain).await, pinned_b);
+    }
+    let stats = resolver.stickiness_stats().unwrap();
+    assert_eq!((stats.entries, stats.misses, stats.repins), (2, 3, 1));
+    assert!(stats.hit_ratio() > 0.8, "{:?}", stats);
+
+    resolver.clear_sticky_pins();
+    assert_eq!(resolver.stickiness_stats().unwrap().entries, 0);
+    assert_eq!(resolver.sticky_upstream("203.0.113.0/24"), None);
+}
+
+#[tokio::test]
+async fn test_retry_count_applies_to_every_strategy() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    /// 上游前 `failures` 次调用失败，返回查询是否成功、上游调用次数和重试次数
+    async fn run(strategy: QueryStrategy, retry_count: usize, failures: usize) -> (bool, usize, u64) {
+        let mock = MockTransport::new()
+            .with_a("retry.example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+            .with_failures(failures);
+        let resolver = DnsResolverBuilder::new(strategy, false, "global".to_string())
+            .disable_logger_init()
+            .with_cache(false)
+            .with_retry_count(retry_count)
+            .add_mock_upstream("mock", mock.clone())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let response = resolver.query(DnsQueryRequest::new("retry.example.com", DnsRecordType::A)).await.unwrap();
+        (response.success, mock.call_count(), resolver.get_stats().await.retries_performed)
+    }
+
+    for strategy in [QueryStrategy::Fifo, QueryStrategy::Smart, QueryStrategy::RoundRobin, QueryStrategy::Sequential] {
+        // 重试1次就是恰好再查询一次
+        assert_eq!(run(strategy, 1, 1).await, (true, 2, 1), "{:?}", strategy);
+        assert_eq!(run(strategy, 1, 2).await, (false, 2, 1), "{:?}", strategy);
+        assert_eq!(run(strategy, 3, 2).await, (true, 3, 2), "{:?}", strategy);
+        assert_eq!(run(strategy, 0, 1).await, (false, 1, 0), "{:?}", strategy);
+        assert_eq!(run(strategy, 2, 0).await, (true, 1, 0), "{:?}", strategy);
+    }
+}
+
+#[tokio::test]
+async fn test_retries_stop_at_the_request_deadline() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let mock = MockTransport::new()
+        .with_a("retry.example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+        .with_failures(100);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_retry_count(10)
+        .add_mock_upstream("mock", mock.clone())
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    // 重试前依次等待100、200、300毫秒，250毫秒的时限内最多查询两轮
+    let mut request = DnsQueryRequest::new("retry.example.com", DnsRecordType::A);
+    request.timeout_ms = Some(250);
+    let started = std::time::Instant::now();
+    let response = resolver.query(request).await.unwrap();
+    assert!(!response.success);
+    assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+    assert_eq!(mock.call_count(), 2);
+    assert_eq!(resolver.get_stats().await.retries_performed, 2);
+}
+
+#[tokio::test]
+async fn test_quorum_strategy_requires_agreeing_upstreams() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    let agreed = Ipv4Addr::new(192, 0, 2, 1);
+    let build = |required: usize| {
+        let honest = || MockTransport::new().with_a("bank.example.com", &[agreed], 300);
+        let poisoned = MockTransport::new().with_a("bank.example.com", &[Ipv4Addr::new(198, 51, 100, 66)], 300);
+        let builder = DnsResolverBuilder::new(QueryStrategy::Quorum { required }, false, "global".to_string())
+            .disable_logger_init()
+            .with_retry_count(0)
+            .with_cache(true)
+            .add_mock_upstream("first", honest())
+            .unwrap()
+            .add_mock_upstream("poisoned", poisoned.clone())
+            .unwrap()
+            .add_mock_upstream("second", honest().with_latency(Duration::from_millis(20)))
+            .unwrap();
+        (builder, poisoned)
+    };
+
+    // 两个上游答案一致即可返回，达成一致的答案写入缓存
+    let (builder, poisoned) = build(2);
+    let resolver = builder.build().await.unwrap();
+    for _ in 0..2 {
+        let response = resolver.query(DnsQueryRequest::new("bank.example.com", DnsRecordType::A)).await.unwrap();
+        assert!(response.success, "{:?}", response.error);
+        assert_eq!(response.ip_addresses(), vec![IpAddr::V4(agreed)]);
+    }
+    assert_eq!(poisoned.call_count(), 1);
+
+    // 要求三个上游一致时被篡改的上游使查询失败，错误中指出不一致的上游，失败的结果不缓存
+    let (builder, poisoned) = build(3);
+    let resolver = builder.build().await.unwrap();
+    for round in 1..=2 {
+        let error = resolver.lookup_ip("bank.example.com", DnsRecordType::A).await.unwrap_err();
+        let DnsError::QuorumNotReached { required, report, .. } = &error else {
+            panic!("unexpected error: {:?}", error);
+        };
+        assert_eq!(*required, 3);
+        let upstreams: Vec<&str> = report.answers.iter().map(|answer| answer.upstream.as_str()).collect();
+        assert_eq!(upstreams, vec!["first", "poisoned", "second"]);
+        assert_eq!(report.consensus.majority, Some(vec![agreed.to_string()]));
+        assert_eq!(report.consensus.agreeing, vec!["first".to_string(), "second".to_string()]);
+        assert_eq!(report.consensus.dissenting.len(), 1);
+        assert_eq!(report.consensus.dissenting[0].upstream, "poisoned");
+        assert!(error.to_string().contains("poisoned answered [198.51.100.66]"), "{}", error);
+        assert_eq!(poisoned.call_count(), round);
+    }
+
+    // 法定人数超过上游数时无法构建
+    let (builder, _) = build(4);
+    assert!(matches!(builder.build().await, Err(DnsError::InvalidConfig(message)) if message.contains("Quorum")));
+    let (builder, _) = build(0);
+    assert!(builder.build().await.is_err());
+}
+
+#[tokio::test]
+async fn test_upstream_filter_limits_the_candidates() {
+    use rat_quickdns::builder::resolver::CACHE_SOURCE;
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType, UpstreamFilter};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let strategies = [QueryStrategy::Fifo, QueryStrategy::Smart, QueryStrategy::RoundRobin, QueryStrategy::Sequential];
+    for strategy in strategies {
+        let mock = |last: u8| MockTransport::new().with_a("svc.example.com", &[Ipv4Addr::new(192, 0, 2, last)], 300);
+        let (public, doh, internal, backup) = (mock(1), mock(2), mock(3), mock(4));
+        let resolver = DnsResolverBuilder::new(strategy, false, "global".to_string())
+            .disable_logger_init()
+            .with_cache(true)
+            .add_mock_upstream("public", public.clone())
+            .unwrap()
+            .add_mock_upstream("tencent_doh", doh.clone())
+            .unwrap()
+            .add_mock_upstream("internal", internal.clone())
+            .unwrap()
+            .add_mock_upstream("backup", backup.clone())
+            .unwrap()
+            .with_upstream_tier("backup", 1)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let request = || DnsQueryRequest::new("svc.example.com", DnsRecordType::A);
+
+        // 只有点名的上游收到查询，默认不读缓存，两次查询都发往该上游
+        for round in 1..=2 {
+            let only = request().with_upstream_filter(UpstreamFilter::only("tencent_doh"));
+            let response = resolver.query(only).await.unwrap();
+            assert!(response.success, "{:?}: {:?}", strategy, response.error);
+            assert_eq!(response.server_used.as_deref(), Some("tencent_doh"), "{:?}", strategy);
+            assert_eq!(doh.call_count(), round, "{:?}", strategy);
+        }
+        assert_eq!((public.call_count(), internal.call_count(), backup.call_count()), (0, 0, 0), "{:?}", strategy);
+
+        // 筛选不受层级限制：备用层级的上游在主层级可用时也能点名
+        let response = resolver.query(request().with_upstream_filter(UpstreamFilter::only("backup"))).await.unwrap();
+        assert_eq!(response.server_used.as_deref(), Some("backup"), "{:?}", strategy);
+
+        // 排除的上游从不收到查询；允许读缓存时由前面写入的答案应答
+        for _ in 0..3 {
+            let exclude = request().with_upstream_filter(UpstreamFilter::Exclude(vec!["internal".to_string()]));
+            let response = resolver.query(exclude).await.unwrap();
+            assert!(response.success, "{:?}: {:?}", strategy, response.error);
+            assert_ne!(response.server_used.as_deref(), Some("internal"), "{:?}", strategy);
+        }
+        assert_eq!(internal.call_count(), 0, "{:?}", strategy);
+        let cached = request()
+            .with_upstream_filter(UpstreamFilter::only("internal"))
+            .with_upstream_filter_reads_cache(true);
+        assert_eq!(resolver.query(cached).await.unwrap().server_used.as_deref(), Some(CACHE_SOURCE), "{:?}", strategy);
+        assert_eq!(internal.call_count(), 0, "{:?}", strategy);
+
+        // 筛选后没有上游时直接失败，错误中带上筛选
+        let missing = request().with_upstream_filter(UpstreamFilter::Only(vec!["nope".to_string()]));
+        let error = resolver.lookup_ip_with(missing).await.unwrap_err();
+        assert!(matches!(&error, DnsError::NoUpstreamAvailable { filter: Some(_) }), "{:?}", error);
+        assert!(error.to_string().contains("only [nope]"), "{}", error);
+    }
+}
+
+#[tokio::test]
+async fn test_seeded_resolvers_repeat_random_choices() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{Flags, QClass, Query, Record, RecordData, RecordType, Response};
+    use std::net::Ipv4Addr;
+
+    let addresses: Vec<Ipv4Addr> = (1..=6).map(|i| Ipv4Addr::new(192, 0, 2, i)).collect();
+    let hosts = ["a.example.test", "b.example.test", "c.example.test", "d.example.test"];
+    let srv_name = "_sip._udp.example.test";
+    let srv = Response {
+        id: 0,
+        flags: Flags { qr: true, ..Flags::default() },
+        queries: vec![Query { name: srv_name.to_string(), qtype: RecordType::SRV, qclass: QClass::IN }],
+        answers: hosts.iter().map(|host| Record {
+            name: srv_name.to_string(),
+            rtype: RecordType::SRV,
+            class: QClass::IN,
+            ttl: 300,
+            data: RecordData::SRV { priority: 10, weight: 25, port: 5060, target: host.to_string() },
+        }).collect(),
+        authorities: vec![],
+        additionals: vec![],
+    };
+    let run = |seed: u64| {
+        let upstream = hosts.iter().fold(
+            MockTransport::new().with_a("www.example.test", &addresses, 300).with_response(srv_name, RecordType::SRV, srv.clone()),
+            |mock, host| mock.with_a(host, &addresses[..1], 300),
+        );
+        async move {
+            let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+                .disable_logger_init()
+                .with_cache(false)
+                .with_retry_count(0)
+                .with_record_rotation(RotationMode::Shuffle)
+                .with_random_seed(seed)
+                .add_mock_upstream("primary", upstream.clone()).unwrap()
+                .add_mock_upstream("secondary", MockTransport::new()).unwrap()
+                .with_upstream_features("primary", UpstreamFeatures { case_randomization: Some(true), ..UpstreamFeatures::default() }).unwrap()
+                .build()
+                .await
+                .unwrap();
+            let mut transcript = Vec::new();
+            for _ in 0..8 {
+                let response = resolver.query(DnsQueryRequest::new("www.example.test", DnsRecordType::A)).await.unwrap();
+                let values: Vec<String> = response.records.iter().map(|record| format!("{:?}", record.value)).collect();
+                transcript.push(format!("{} via {:?}", values.join(","), response.server_used));
+                let targets = resolver.resolve_srv("sip", "udp", "example.test").await.unwrap();
+                transcript.push(targets.iter().map(|target| target.host.as_str()).collect::<Vec<_>>().join(","));
+            }
+            let sent: Vec<String> = upstream.calls().into_iter().map(|call| call.request.query.name).collect();
+            (transcript, sent)
+        }
+    };
+
+    // 同一种子：上游选择、地址顺序、SRV目标顺序和发出的查询名大小写完全相同
+    let (transcript, sent) = run(42).await;
+    assert_eq!((transcript.clone(), sent.clone()), run(42).await);
+    assert!(sent.iter().any(|name| *name != name.to_ascii_lowercase()));
+    assert_ne!((transcript, sent), run(43).await);
+}
@@ -0,0 +1,246 @@
+//! 响应解析器的畸形报文回归测试
+//!
+//! 每个用例对应一类曾导致越界读取、循环或长时间解析的报文，要求解析器返回 `DnsError::Protocol`；
+//! 种子报文同时收录在 `fuzz/corpus` 中
+
+use rat_quickdns::transport::UdpTransport;
+use rat_quickdns::{DnsError, DnsResponseWrapper, RecordData};
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+fn header(qdcount: u16, ancount: u16, nscount: u16, arcount: u16) -> Vec<u8> {
+    let mut bytes = vec![0x12, 0x34, 0x81, 0x80];
+    for count in [qdcount, ancount, nscount, arcount] {
+        bytes.extend_from_slice(&count.to_be_bytes());
+    }
+    bytes
+}
+
+fn name(domain: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for label in domain.split('.') {
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label.as_bytes());
+    }
+    bytes.push(0);
+    bytes
+}
+
+/// 问题为 `domain`、类型为 `qtype` 的报文开头
+fn with_question(ancount: u16, domain: &str, qtype: u16) -> Vec<u8> {
+    let mut bytes = header(1, ancount, 0, 0);
+    bytes.extend_from_slice(&name(domain));
+    bytes.extend_from_slice(&qtype.to_be_bytes());
+    bytes.extend_from_slice(&1u16.to_be_bytes());
+    bytes
+}
+
+/// 名称为指向问题名的压缩指针的资源记录
+fn record(rtype: u16, rdata: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0xC0, 0x0C];
+    bytes.extend_from_slice(&rtype.to_be_bytes());
+    bytes.extend_from_slice(&1u16.to_be_bytes());
+    bytes.extend_from_slice(&300u32.to_be_bytes());
+    bytes.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(rdata);
+    bytes
+}
+
+fn assert_protocol_error(data: &[u8]) {
+    match UdpTransport::deserialize_response(data) {
+        Err(DnsError::Protocol(_)) => {}
+        other => panic!("expected protocol error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_inflated_record_count_is_rejected_up_front() {
+    let data = with_question(u16::MAX, "example.com", 1);
+    let started = Instant::now();
+    assert_protocol_error(&data);
+    assert!(started.elapsed() < Duration::from_millis(50));
+
+    let mut data = header(u16::MAX, 0, 0, 0);
+    data.extend_from_slice(&[0; 64]);
+    assert_protocol_error(&data);
+}
+
+#[test]
+fn test_mx_with_two_byte_rdata() {
+    let mut data = with_question(1, "example.com", 15);
+    data.extend_from_slice(&record(15, &[0, 10]));
+    assert_protocol_error(&data);
+}
+
+#[test]
+fn test_rdata_name_running_past_rdlength() {
+    // MX的交换机名称编码后长14字节，数据长度却只声明了5字节
+    let mut rdata = vec![0, 10];
+    rdata.extend_from_slice(&name("mail.example"));
+    let mut data = with_question(1, "example.com", 15);
+    let mut mx = record(15, &rdata);
+    mx[10..12].copy_from_slice(&5u16.to_be_bytes());
+    data.extend_from_slice(&mx);
+    assert_protocol_error(&data);
+}
+
+#[test]
+fn test_txt_length_byte_overrunning_rdata() {
+    let mut data = with_question(1, "example.com", 16);
+    data.extend_from_slice(&record(16, &[5, b'h', b'e', b'l', b'l', b'o', 9, b'x']));
+    assert_protocol_error(&data);
+}
+
+#[test]
+fn test_compression_pointer_to_itself_or_forward() {
+    // 问题名是指向自身的指针
+    let mut data = header(1, 0, 0, 0);
+    data.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1]);
+    assert_protocol_error(&data);
+
+    // 问题名指向后面的记录名，记录名再指回问题名
+    let mut data = header(1, 1, 0, 0);
+    data.extend_from_slice(&[0xC0, 0x12, 0, 1, 0, 1]);
+    data.extend_from_slice(&record(1, &[192, 0, 2, 1]));
+    assert_protocol_error(&data);
+
+    // 标签之后的指针指回本段开头
+    let mut data = header(1, 0, 0, 0);
+    data.extend_from_slice(&[1, b'a', 0xC0, 0x0C, 0, 1, 0, 1]);
+    assert_protocol_error(&data);
+}
+
+#[test]
+fn test_pointer_chain_and_name_length_limits() {
+    // 第一个指针指向开头的根标签，之后每个指针指向前一个指针：都向报文开头跳转，但次数超过上限
+    let mut packet = vec![0];
+    for index in 0..200u16 {
+        let target = if index == 0 { 0 } else { index * 2 - 1 };
+        packet.extend_from_slice(&(0xC000 | target).to_be_bytes());
+    }
+    let result = UdpTransport::parse_name(&packet, packet.len() - 2);
+    assert!(matches!(result, Err(DnsError::Protocol(_))), "{:?}", result);
+
+    // 4个63字节的标签编码后为257字节，超过255字节上限
+    let long = ["a".repeat(63), "b".repeat(63), "c".repeat(63), "d".repeat(63)].join(".");
+    assert!(matches!(UdpTransport::parse_name(&name(&long), 0), Err(DnsError::Protocol(_))));
+    let longest = ["a".repeat(63), "b".repeat(63), "c".repeat(63), "d".repeat(61)].join(".");
+    assert_eq!(UdpTransport::parse_name(&name(&longest), 0).unwrap().0, longest);
+}
+
+/// 名称为 `owner`、CNAME目标为指向 `target` 的压缩指针的资源记录
+fn cname_record(owner: &[u8], target: u16) -> Vec<u8> {
+    let mut bytes = owner.to_vec();
+    bytes.extend_from_slice(&5u16.to_be_bytes());
+    bytes.extend_from_slice(&1u16.to_be_bytes());
+    bytes.extend_from_slice(&300u32.to_be_bytes());
+    bytes.extend_from_slice(&2u16.to_be_bytes());
+    bytes.extend_from_slice(&(0xC000 | target).to_be_bytes());
+    bytes
+}
+
+#[test]
+fn test_large_tcp_message_with_many_compressed_names() {
+    // 接近64KB的TCP响应：每条记录的名称是一个标签加指向问题名的指针，CNAME目标指向上一条记录的名称；
+    // 指针只能指向前16KB，之后的记录改为指向那里的最后一个名称
+    let mut data = with_question(0, "zone.example.com", 252);
+    let mut previous = 12u16;
+    let mut count = 0u16;
+    while data.len() + 30 < u16::MAX as usize {
+        let owner_offset = data.len() as u16;
+        let mut owner = name(&format!("host{}", count));
+        owner.truncate(owner.len() - 1);
+        owner.extend_from_slice(&[0xC0, 0x0C]);
+        data.extend_from_slice(&cname_record(&owner, previous));
+        if owner_offset < 0x4000 {
+            previous = owner_offset;
+        }
+        count += 1;
+    }
+    data[6..8].copy_from_slice(&count.to_be_bytes());
+    assert!(count > 2000);
+
+    let started = Instant::now();
+    let response = UdpTransport::deserialize_response(&data).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(response.answers.len(), count as usize);
+    assert_eq!(response.answers[1].data, RecordData::CNAME("host0.zone.example.com".to_string()));
+    let last = &response.answers[count as usize - 1];
+    assert_eq!(last.name, format!("host{}.zone.example.com", count - 1));
+    assert!(matches!(&last.data, RecordData::CNAME(target) if target.ends_with(".zone.example.com")));
+}
+
+#[test]
+fn test_repeated_long_pointer_chains_exhaust_the_message_budget() {
+    // 第一条记录的数据中是一条合法的长链：每段一个标签加指向前一段的指针，展开后126个标签、跳转126次
+    let mut chain = vec![0u8];
+    let chain_start = 12 + 1 + 10;
+    let mut last_segment = chain_start as u16;
+    for _ in 0..126 {
+        let segment = (chain_start + chain.len()) as u16;
+        chain.extend_from_slice(&[1, b'a']);
+        chain.extend_from_slice(&(0xC000 | last_segment).to_be_bytes());
+        last_segment = segment;
+    }
+    let message = |records: u16| {
+        let mut data = header(0, records + 1, 0, 0);
+        data.extend_from_slice(&[0, 0xFF, 0x99, 0, 1, 0, 0, 1, 0x2C]);
+        data.extend_from_slice(&(chain.len() as u16).to_be_bytes());
+        data.extend_from_slice(&chain);
+        let owner = (0xC000 | last_segment).to_be_bytes();
+        for _ in 0..records {
+            data.extend_from_slice(&cname_record(&owner, last_segment));
+        }
+        data
+    };
+
+    // 单个名称在上限之内，少量这样的记录可以解析
+    let response = UdpTransport::deserialize_response(&message(4)).unwrap();
+    assert_eq!(response.answers[1].name, vec!["a"; 126].join("."));
+
+    // 每条记录只有14字节却要展开两条长链，总工作量超出按报文长度计算的预算
+    let started = Instant::now();
+    assert_protocol_error(&message(4000));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_reserved_label_types_are_rejected() {
+    for prefix in [0x40, 0x80] {
+        let mut data = header(1, 0, 0, 0);
+        data.extend_from_slice(&[prefix, 0x0C, 0, 1, 0, 1]);
+        assert_protocol_error(&data);
+    }
+}
+
+#[test]
+fn test_zero_and_multiple_questions_are_tolerated() {
+    let response = UdpTransport::deserialize_response(&header(0, 0, 0, 0)).unwrap();
+    assert!(response.queries.is_empty());
+
+    let mut data = header(2, 1, 0, 0);
+    data.extend_from_slice(&name("example.com"));
+    data.extend_from_slice(&[0, 1, 0, 1]);
+    data.extend_from_slice(&name("example.org"));
+    data.extend_from_slice(&[0, 28, 0, 1]);
+    data.extend_from_slice(&record(1, &[192, 0, 2, 1]));
+    let response = UdpTransport::deserialize_response(&data).unwrap();
+    assert_eq!(response.queries.len(), 2);
+    assert_eq!(response.answers[0].name, "example.com");
+    assert_eq!(response.answers[0].data, RecordData::A(Ipv4Addr::new(192, 0, 2, 1)));
+}
+
+#[test]
+fn test_every_truncation_of_a_valid_response_fails_cleanly() {
+    let response = DnsResponseWrapper::create_a_response(
+        0x1234,
+        "www.example.com",
+        &[Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2)],
+        300,
+    );
+    let bytes = UdpTransport::serialize_response(&response).unwrap();
+    for length in 0..bytes.len() {
+        assert!(UdpTransport::deserialize_response(&bytes[..length]).is_err(), "prefix of {} bytes", length);
+    }
+    assert_eq!(UdpTransport::deserialize_response(&bytes).unwrap().answers.len(), 2);
+}
@@ -0,0 +1,478 @@
+//! 运行时的上游变化：增删上游、加密服务发现（DDR）、上游事件与SLO、标签和作用域解析器
+
+use rat_quickdns::builder::ddr::DdrOptions;
+use rat_quickdns::upstream_handler::UpstreamLabels;
+use rat_quickdns::{DnsError, DnsResolverBuilder, QueryStrategy, Result};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_upstreams_added_and_removed_at_runtime() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsQueryResponse, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+
+    let first = MockTransport::new()
+        .with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+        .with_latency(Duration::from_millis(50));
+    let second = MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 2)], 300);
+    let (first_handle, second_handle) = (first.clone(), second.clone());
+    let resolver = Arc::new(
+        DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .with_cache(false)
+            .add_mock_upstream("first", first)
+            .unwrap()
+            .build()
+            .await
+            .unwrap(),
+    );
+    let request = || DnsQueryRequest::new("example.com", DnsRecordType::A);
+    let server_used = |response: DnsQueryResponse| response.server_used.unwrap_or_default();
+
+    assert_eq!(server_used(resolver.query(request()).await.unwrap()), "first");
+
+    // 重名上游被拒绝，配置保持不变
+    assert!(resolver.add_custom_upstream("first", Arc::new(MockTransport::new())).await.is_err());
+    resolver.add_custom_upstream("second", Arc::new(second)).await.unwrap();
+    assert_eq!(resolver.upstream_manager().get_specs().len(), 2);
+
+    resolver.set_upstream_enabled("first", false).await.unwrap();
+    assert_eq!(server_used(resolver.query(request()).await.unwrap()), "second");
+    resolver.set_upstream_enabled("first", true).await.unwrap();
+
+    // 移除上游时已经发出的查询照常完成
+    let in_flight = tokio::spawn({
+        let resolver = resolver.clone();
+        async move { resolver.query(request()).await }
+    });
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    resolver.remove_upstream("first").await.unwrap();
+    assert!(in_flight.await.unwrap().unwrap().success);
+
+    let first_calls = first_handle.call_count();
+    for _ in 0..3 {
+        assert_eq!(server_used(resolver.query(request()).await.unwrap()), "second");
+    }
+    assert_eq!(first_handle.call_count(), first_calls);
+    assert!(second_handle.call_count() >= 4);
+    assert!(resolver.remove_upstream("first").await.is_err());
+}
+
+#[tokio::test]
+async fn test_ddr_upgrades_plaintext_upstream() {
+    use rat_quickdns::builder::ddr::{DesignatedResolver, DesignationVerifier, SvcbRecord, DDR_QUERY_NAME, SVCB_RECORD_TYPE};
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{Flags, QClass, Query, Record, RecordData, Response};
+    use rat_quickdns::upstream_handler::UpstreamType;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Mutex;
+
+    /// 只接受证书覆盖 192.0.2.53 的加密服务，并记录每次校验
+    #[derive(Debug, Default)]
+    struct RecordingVerifier {
+        checked: Mutex<Vec<(IpAddr, String)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl DesignationVerifier for RecordingVerifier {
+        async fn verify(&self, original: IpAddr, designated: &DesignatedResolver) -> Result<()> {
+            self.checked.lock().unwrap().push((original, designated.target.clone()));
+            if designated.target == "doh.example" {
+                Ok(())
+            } else {
+                Err(DnsError::Tls("certificate does not cover the original resolver".to_string()))
+            }
+        }
+    }
+
+    let svcb = |priority: u16, target: &str, alpn: &str, dohpath: Option<&str>| Record {
+        name: DDR_QUERY_NAME.to_string(),
+        rtype: SVCB_RECORD_TYPE,
+        class: QClass::IN,
+        ttl: 300,
+        data: RecordData::Unknown(SvcbRecord {
+            priority,
+            target: target.to_string(),
+            alpn: vec![alpn.to_string()],
+            ipv4_hints: vec![Ipv4Addr::new(192, 0, 2, 80)],
+            dohpath: dohpath.map(str::to_string),
+            ..Default::default()
+        }.encode()),
+    };
+    let response = Response {
+        id: 0,
+        flags: Flags::default(),
+        queries: vec![Query { name: DDR_QUERY_NAME.to_string(), qtype: SVCB_RECORD_TYPE, qclass: QClass::IN }],
+        answers: vec![
+            svcb(2, "doh.example", "h2", Some("/dns-query{?dns}")),
+            svcb(1, "dot.attacker.example", "dot", None),
+        ],
+        authorities: Vec::new(),
+        additionals: Vec::new(),
+    };
+    let plain = MockTransport::new()
+        .with_endpoint("192.0.2.53:53")
+        .with_response(DDR_QUERY_NAME, SVCB_RECORD_TYPE, response);
+    let verifier = Arc::new(RecordingVerifier::default());
+
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("plain", plain)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    let options = DdrOptions::new()
+        .with_weight(5)
+        .with_disable_plaintext(true)
+        .with_verifier(verifier.clone());
+    let report = resolver.discover_encrypted_upstreams(&options).await;
+
+    assert_eq!(report.registered, vec!["plain-ddr-doh".to_string()]);
+    assert_eq!(report.disabled, vec!["plain".to_string()]);
+    let manager = resolver.upstream_manager();
+    let spec = manager.get_specs().iter().find(|spec| spec.name == "plain-ddr-doh").unwrap();
+    assert_eq!(spec.transport_type, UpstreamType::DoH);
+    assert_eq!(spec.server, "https://doh.example/dns-query");
+    assert_eq!(spec.resolved_ip.as_deref(), Some("192.0.2.80"));
+    assert_eq!(spec.weight, 5);
+
+    // 未通过校验的DoT服务不注册，校验时使用原上游的IP
+    let checked = verifier.checked.lock().unwrap().clone();
+    assert!(checked.iter().all(|(ip, _)| *ip == IpAddr::from([192, 0, 2, 53])));
+    assert!(checked.iter().any(|(_, target)| target == "dot.attacker.example"));
+    assert_eq!(resolver.upstream_manager().get_specs().len(), 2);
+
+    // 已经注册过的服务再次发现时不会重复添加，也不会让调用失败
+    let report = resolver.discover_encrypted_upstreams(&DdrOptions::new().with_verifier(verifier)).await;
+    assert!(report.registered.is_empty());
+    assert_eq!(report.failures.len(), 1);
+}
+
+#[tokio::test]
+async fn test_upstream_events_record_when_an_upstream_went_down() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::resolver::health::{StatusTrigger, UpstreamStatus as HealthStatus};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+    use std::time::SystemTime;
+
+    let mock = MockTransport::new()
+        .with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 7)], 300)
+        .with_failures(3);
+    let started = SystemTime::now();
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_retry_count(0)
+        .with_upstream_monitoring(true)
+        .add_mock_upstream("ali-doh", mock)
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        let response = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await.unwrap();
+        assert!(!response.success);
+    }
+
+    let events = resolver.get_upstream_events("ali-doh", None);
+    assert_eq!(events.len(), 1);
+    assert_eq!((&events[0].old_status, &events[0].new_status), (&HealthStatus::Unknown, &HealthStatus::Unavailable));
+    assert_eq!(events[0].trigger, StatusTrigger::ConsecutiveFailures);
+    assert!(events[0].timestamp >= started);
+    assert_eq!(resolver.get_upstream_events("ali-doh", Some(events[0].timestamp)).len(), 1);
+    assert!(resolver.get_upstream_events("missing", None).is_empty());
+
+    let quiet = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_upstream_monitoring(true)
+        .with_upstream_event_history(0)
+        .add_mock_upstream("ali-doh", MockTransport::new().with_failures(3))
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(quiet.config().upstream_event_history, 0);
+    for _ in 0..3 {
+        let _ = quiet.query(DnsQueryRequest::new("example.com", DnsRecordType::A)).await;
+    }
+    assert!(quiet.get_upstream_events("ali-doh", None).is_empty());
+}
+
+#[tokio::test]
+async fn test_upstream_slo_breach_and_recovery_reach_the_observer() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::resolver::slo::{QueryObserver, SloConfig};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct Transitions(Mutex<Vec<(String, bool)>>);
+
+    impl QueryObserver for Transitions {
+        fn on_slo_breach(&self, upstream: &str, _observed_p95: Duration, _threshold: Duration) {
+            self.0.lock().unwrap().push((upstream.to_string(), true));
+        }
+
+        fn on_slo_recovery(&self, upstream: &str, _observed_p95: Duration, _threshold: Duration) {
+            self.0.lock().unwrap().push((upstream.to_string(), false));
+        }
+    }
+
+    let observer = Arc::new(Transitions::default());
+    let mock = MockTransport::new()
+        .with_a("slow.example", &[Ipv4Addr::new(192, 0, 2, 1)], 60)
+        .with_latency(Duration::from_millis(30));
+    let handle = mock.clone();
+    let slo = SloConfig::new(Duration::from_millis(10), 3, Duration::ZERO);
+    let builder = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(false)
+        .with_upstream_monitoring_interval(Duration::from_millis(20))
+        .with_query_observer(observer.clone())
+        .add_mock_upstream("slow", mock)
+        .unwrap();
+    assert!(builder.clone().with_upstream_slo("slow", SloConfig { p95_threshold: Duration::ZERO, ..slo }).is_err());
+    assert!(builder.clone().with_upstream_slo("missing", slo).is_err());
+    let builder = builder.with_upstream_slo("slow", slo).unwrap();
+    // SLO由上游监控评估
+    assert!(matches!(builder.clone().with_upstream_monitoring(false).build().await, Err(DnsError::InvalidConfig(_))));
+    let resolver = builder.with_upstream_monitoring(true).build().await.unwrap();
+    let query = || resolver.query(DnsQueryRequest::new("slow.example", DnsRecordType::A));
+    let observed = &observer.0;
+    let wait_for = |count: usize| async move {
+        for _ in 0..100 {
+            if observed.lock().unwrap().len() >= count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("observer was not notified");
+    };
+
+    for _ in 0..3 {
+        assert!(query().await.unwrap().success);
+    }
+    wait_for(1).await;
+    let status = resolver.get_upstream_status().await.into_iter().find(|status| status.name == "slow").unwrap();
+    let slo_status = status.slo.clone().unwrap();
+    assert!(slo_status.breached);
+    assert!(slo_status.observed_p95.unwrap() >= Duration::from_millis(30));
+    assert_eq!(status.to_json_value()["slo"]["breached"], true);
+
+    // 快的应答占满p95之后恢复
+    handle.set_latency(Duration::ZERO);
+    for _ in 0..60 {
+        query().await.unwrap();
+    }
+    wait_for(2).await;
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(*observer.0.lock().unwrap(), vec![("slow".to_string(), true), ("slow".to_string(), false)]);
+    assert!(!resolver.get_upstream_status().await[0].slo.as_ref().unwrap().breached);
+    resolver.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_upstream_labels_flow_into_status_events_and_metrics() {
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType, UpstreamFilter};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+    
+    let labels = UpstreamLabels::from([
+        ("provider".to_string(), "internal".to_string()),
+        ("ticket".to_string(), "https://tickets.example/DNS-42".to_string()),
+    ]);
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Smart, false, "global".to_string())
+        .disable_logger_init()
+        .with_retry_count(0)
+        .with_upstream_monitoring(true)
+        .with_metrics_upstream_labels(["provider"])
+        .add_mock_upstream("office", MockTransport::new().with_failures(3))
+        .unwrap()
+        .with_upstream_labels("office", labels.clone())
+        .unwrap()
+        .add_mock_upstream("plain", MockTransport::new().with_a("example.com", &[Ipv4Addr::new(192, 0, 2, 7)], 300))
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+    
+    let status = resolver.get_upstream_status().await;
+    assert_eq!(status[0].labels, labels);
+    assert!(status[1].labels.is_empty());
+    assert_eq!(status[0].to_json_value()["labels"]["provider"], "internal");
+    assert_eq!(resolver.effective_config().upstreams[0].labels, labels);
+    assert_eq!(resolver.effective_config().resolver.metrics_upstream_labels, vec!["provider".to_string()]);
+    let explanation = resolver.explain_selection().await.unwrap();
+    assert_eq!(explanation.candidates[0].labels, labels);
+    
+    // 标签不改变查询行为：失败照常记录为状态变化，事件附带标签
+    for _ in 0..3 {
+        let _ = resolver.query(DnsQueryRequest::new("example.com", DnsRecordType::A).with_upstream_filter(UpstreamFilter::only("office"))).await;
+    }
+    let events = resolver.get_upstream_events("office", None);
+    assert!(!events.is_empty());
+    assert!(events.iter().all(|event| event.labels == labels));
+    
+    // 只有白名单中的标签进入指标，没有该标签的上游取空字符串
+    let text = resolver.render_openmetrics().await;
+    assert!(text.contains("rat_quickdns_upstream_state{upstream=\"office\",provider=\"internal\",state=\"available\"} "), "{}", text);
+    assert!(text.contains("rat_quickdns_upstream_state{upstream=\"plain\",provider=\"\",state=\"available\"} "), "{}", text);
+    assert!(!text.contains("ticket"), "{}", text);
+    
+    let result = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_udp_upstream("test", "127.0.0.1:53")
+        .with_upstream_labels("test", UpstreamLabels::from([("bad-key".to_string(), "x".to_string())]));
+    assert!(matches!(result, Err(DnsError::InvalidConfig(_))));
+    let result = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_metrics_upstream_labels(["upstream"])
+        .add_udp_upstream("test", "127.0.0.1:53")
+        .validate_only();
+    assert!(matches!(result, Err(DnsError::InvalidConfig(_))));
+    resolver.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_scoped_resolvers_isolate_upstreams_and_share_cache() {
+    use rat_quickdns::builder::scope::{DnsLookup, ScopeOverrides};
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::transport::mock::MockTransport;
+    use std::net::Ipv4Addr;
+    
+    let names = ["one.scope.test", "two.scope.test", "three.scope.test"];
+    let mock = |last_octet: u8| {
+        names.iter().chain(["shared.scope.test"].iter()).fold(MockTransport::new(), |mock, name| {
+            mock.with_a(name, &[Ipv4Addr::new(192, 0, 2, last_octet)], 300)
+        })
+    };
+    let (a, b) = (mock(1), mock(2));
+    let parent = Arc::new(
+        DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .with_cache(true)
+            .with_retry_count(0)
+            .add_mock_upstream("a", a.clone())
+            .unwrap()
+            .add_mock_upstream("b", b.clone())
+            .unwrap()
+            .build()
+            .await
+            .unwrap(),
+    );
+    let only_a = parent.scoped(ScopeOverrides::default().with_upstreams(["a"]).with_cache_bypass());
+    let only_b = parent.scoped(ScopeOverrides::default().with_upstreams(["b"]).with_cache_bypass());
+    
+    // 交替查询同一批名称：每个作用域只用自己的上游，不读缓存
+    async fn answer(resolver: &impl DnsLookup, name: &str) -> (Option<String>, Vec<IpAddr>) {
+        let response = resolver.query(DnsQueryRequest::new(name, DnsRecordType::A)).await.unwrap();
+        assert!(response.success, "{:?}", response.error);
+        (response.server_used.clone(), response.ip_addresses())
+    }
+    for name in names {
+        assert_eq!(answer(&only_a, name).await, (Some("a".to_string()), vec![IpAddr::from([192, 0, 2, 1])]));
+        assert_eq!(answer(&only_b, name).await, (Some("b".to_string()), vec![IpAddr::from([192, 0, 2, 2])]));
+    }
+    let queried = |mock: &MockTransport| -> Vec<String> {
+        mock.calls().iter().map(|call| call.request.query.name.clone()).collect()
+    };
+    assert_eq!(queried(&a), names);
+    assert_eq!(queried(&b), names);
+    
+    // 不限制缓存读取的作用域命中父解析器写入的共享缓存，不向上游查询
+    assert!(answer(parent.as_ref(), "shared.scope.test").await.0.is_some());
+    let before = (a.call_count(), b.call_count());
+    let cached_in_b = parent.scoped(ScopeOverrides::default().with_upstreams(["b"]));
+    assert_eq!(answer(&cached_in_b, "shared.scope.test").await.0.as_deref(), Some(rat_quickdns::builder::resolver::CACHE_SOURCE));
+    assert_eq!((a.call_count(), b.call_count()), before);
+    
+    // 作用域允许的上游都不存在时查询失败，不会改用作用域之外的上游
+    a.clear_calls();
+    b.clear_calls();
+    let nowhere = parent.scoped(ScopeOverrides::default().with_upstreams(["missing"]).with_cache_bypass());
+    let response = nowhere.query(DnsQueryRequest::new("one.scope.test", DnsRecordType::A)).await.unwrap();
+    assert!(!response.success);
+    assert_eq!(a.call_count() + b.call_count(), 0);
+    
+    // 作用域的TTL钳制只改变返回给作用域调用方的TTL
+    let short = parent.scoped(ScopeOverrides::default().with_ttl_clamp(None, Some(Duration::from_secs(30))));
+    let clamped = short.query(DnsQueryRequest::new("shared.scope.test", DnsRecordType::A)).await.unwrap();
+    assert_eq!(clamped.records[0].ttl, 30);
+    let unclamped = parent.query(DnsQueryRequest::new("shared.scope.test", DnsRecordType::A)).await.unwrap();
+    assert!(unclamped.records[0].ttl > 30);
+    parent.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_scoped_resolvers_apply_their_own_policies() {
+    use rat_quickdns::builder::middleware::DomainRewriteMiddleware;
+    use rat_quickdns::builder::scope::ScopeOverrides;
+    use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+    use rat_quickdns::dns_response::DnsResponseBuilder;
+    use rat_quickdns::transport::mock::MockTransport;
+    use rat_quickdns::types::{QClass, RecordType};
+    use std::net::Ipv4Addr;
+    
+    let signed = DnsResponseBuilder::new()
+        .add_query("signed.scope.test".to_string(), RecordType::A, QClass::IN)
+        .add_a_answer("signed.scope.test".to_string(), 300, Ipv4Addr::new(192, 0, 2, 53))
+        .with_authenticated_data(true)
+        .build();
+    let a = MockTransport::new()
+        .with_a("split.scope.test", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+        .with_a("unsigned.scope.test", &[Ipv4Addr::new(192, 0, 2, 1)], 300)
+        .with_response("signed.scope.test", RecordType::A, signed);
+    let b = MockTransport::new()
+        .with_a("split.scope.test", &[Ipv4Addr::new(192, 0, 2, 2)], 300);
+    let parent = Arc::new(
+        DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+            .disable_logger_init()
+            .with_cache(true)
+            .with_retry_count(0)
+            .add_mock_upstream("a", a.clone())
+            .unwrap()
+            .add_mock_upstream("b", b.clone())
+            .unwrap()
+            .build()
+            .await
+            .unwrap(),
+    );
+    let split = || DnsQueryRequest::new("split.scope.test", DnsRecordType::A);
+    
+    // 父解析器的FIFO策略取最先到达的答案；作用域改用顺序策略时只问第一个上游，
+    // 改用法定人数策略时两个上游的答案不一致，查询失败
+    assert!(parent.query(split().disable_cache()).await.unwrap().success);
+    let calls = || (a.call_count(), b.call_count());
+    let before = calls();
+    let sequential = parent.scoped(ScopeOverrides::default().with_strategy(QueryStrategy::Sequential).with_cache_bypass());
+    assert_eq!(sequential.lookup_ip_with(split()).await.unwrap(), vec![IpAddr::from([192, 0, 2, 1])]);
+    assert_eq!(calls(), (before.0 + 1, before.1));
+    let quorum = parent.scoped(ScopeOverrides::default().with_strategy(QueryStrategy::Quorum { required: 2 }).with_cache_bypass());
+    let error = quorum.lookup_ip_with(split()).await.unwrap_err();
+    assert!(matches!(error, DnsError::QuorumNotReached { required: 2, .. }), "{:?}", error);
+    assert_eq!(calls(), (before.0 + 2, before.1 + 1));
+    assert!(parent.query(split().disable_cache()).await.unwrap().success);
+    
+    // 要求DNSSEC的作用域设置DO位，拒绝AD位未置位的应答
+    let secure = parent.scoped(ScopeOverrides::default().with_upstreams(["a"]).with_dnssec_required());
+    let response = secure.query(DnsQueryRequest::new("signed.scope.test", DnsRecordType::A)).await.unwrap();
+    assert!(response.success && response.authenticated_data);
+    assert!(a.calls().last().unwrap().request.dnssec_ok);
+    let error = secure.lookup_ip("unsigned.scope.test", DnsRecordType::A).await.unwrap_err();
+    assert!(matches!(&error, DnsError::DnssecRequired { name } if name == "unsigned.scope.test"), "{:?}", error);
+    assert!(parent.query(DnsQueryRequest::new("unsigned.scope.test", DnsRecordType::A)).await.unwrap().success);
+    
+    // 作用域的中间件只作用于作用域内的查询
+    let rewriting = parent.scoped(ScopeOverrides::default().with_middleware(Arc::new(
+        DomainRewriteMiddleware::new().rewrite("alias.scope.test", "split.scope.test"),
+    )));
+    let response = rewriting.query(DnsQueryRequest::new("alias.scope.test", DnsRecordType::A)).await.unwrap();
+    assert_eq!(response.ip_addresses(), vec![IpAddr::from([192, 0, 2, 1])]);
+    let response = parent.query(DnsQueryRequest::new("alias.scope.test", DnsRecordType::A)).await.unwrap();
+    assert!(response.records.is_empty());
+    parent.shutdown().await;
+}
@@ -0,0 +1,82 @@
+//! 系统解析器配置（resolv.conf）的解析与搜索域展开
+//!
+//! 样例文件位于 `tests/fixtures/resolv`
+
+use rat_quickdns::config::{ConfigError, SearchDomains, SystemResolvConf};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/resolv").join(name)
+}
+
+fn ips(addresses: &[&str]) -> Vec<IpAddr> {
+    addresses.iter().map(|ip| ip.parse().unwrap()).collect()
+}
+
+#[test]
+fn multiple_nameservers_keep_order_and_skip_scoped_ipv6() {
+    let conf = SystemResolvConf::load_from(fixture("multiple_nameservers.conf")).unwrap();
+
+    assert_eq!(conf.nameservers, ips(&["192.0.2.53", "2001:db8::53", "198.51.100.53"]));
+    assert_eq!(conf.search, SearchDomains::new(["corp.example", "lab.example"], 1));
+    assert_eq!((conf.timeout, conf.attempts), (None, None));
+
+    let specs = conf.upstream_specs();
+    let named: Vec<(&str, &str)> = specs.iter().map(|spec| (spec.name.as_str(), spec.server.as_str())).collect();
+    assert_eq!(named, vec![
+        ("system-1", "192.0.2.53:53"),
+        ("system-2", "[2001:db8::53]:53"),
+        ("system-3", "198.51.100.53:53"),
+    ]);
+}
+
+#[test]
+fn options_line_maps_ndots_timeout_and_attempts() {
+    let conf = SystemResolvConf::load_from(fixture("kubernetes.conf")).unwrap();
+
+    assert_eq!(conf.nameservers, ips(&["10.96.0.10"]));
+    assert_eq!(conf.search.ndots, 2);
+    assert_eq!(conf.timeout, Some(Duration::from_secs(2)));
+    assert_eq!(conf.attempts, Some(3));
+}
+
+#[test]
+fn search_expansion_with_ndots_two_tries_search_domains_first_for_short_names() {
+    let conf = SystemResolvConf::load_from(fixture("kubernetes.conf")).unwrap();
+
+    assert_eq!(conf.search.candidates("api"), vec![
+        "api.default.svc.cluster.local",
+        "api.svc.cluster.local",
+        "api.cluster.local",
+        "api",
+    ]);
+    assert_eq!(conf.search.candidates("api.prod"), vec![
+        "api.prod.default.svc.cluster.local",
+        "api.prod.svc.cluster.local",
+        "api.prod.cluster.local",
+        "api.prod",
+    ]);
+    assert_eq!(conf.search.candidates("www.example.com")[0], "www.example.com");
+    assert_eq!(conf.search.candidates("www.example.com."), vec!["www.example.com."]);
+}
+
+#[test]
+fn parse_failures_never_yield_zero_upstreams() {
+    assert!(matches!(
+        SystemResolvConf::load_from(fixture("no_nameservers.conf")),
+        Err(ConfigError::NoUpstreams)
+    ));
+    assert!(matches!(
+        SystemResolvConf::load_from(fixture("missing.conf")),
+        Err(ConfigError::InvalidValue(_))
+    ));
+
+    let error = SystemResolvConf::parse("nameserver 192.0.2.53\nnameserver dns.example\n").unwrap_err();
+    assert!(error.to_string().contains("line 2"), "{}", error);
+    assert!(matches!(
+        SystemResolvConf::parse("nameserver 192.0.2.53\noptions ndots:many\n"),
+        Err(ConfigError::InvalidValue(_))
+    ));
+}
@@ -0,0 +1,76 @@
+//! TXT记录的字符串边界：拆成多个字符串的DKIM公钥经模拟上游、线路格式和bincode后逐字节还原
+
+use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType, DnsRecordValue};
+use rat_quickdns::transport::mock::MockTransport;
+use rat_quickdns::transport::UdpTransport;
+use rat_quickdns::{DnsQueryResponse, DnsResolverBuilder, DnsResponseWrapper, QueryStrategy, RecordType};
+
+/// 2048位RSA公钥的DKIM记录（410字节），线路上拆成255字节和155字节两个字符串
+const DKIM_RECORD: &str = "v=DKIM1; k=rsa; p=MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAl5mXFgha9DL58dUcRM2tfOYAZoPhk+9vsVHEp2rZO5U34EI4GqAi/EwbhxK9V8WCBhprrVID+6M9ILa+XvawBTdM7Gh/MzowlfwtBrWeU0I1V4bv/706Zt+qiwY2Ee7Dltowfwgj28hvSGtriNWZmAHaKoMiPp/NEDt9OVqBmw4I1YDZRKH9WjPxaqeLxGMiuYDRJ/AMgsdNFcd+HpyWcanoGEoIFIH5Z7s2nw5/+JQONtWd6KPvXHmmOYEG0hYu2qMKffCzCA14gfkdxiAge3aYy1XbDGRrmDMdc/KLVlYWpLNcAtC1umLElGR4GCOa5w9CPs+0mh45sXbvN/RCiwIDAQAB";
+
+const SELECTOR: &str = "s1._domainkey.example.com";
+
+fn dkim_chunks() -> Vec<String> {
+    let (head, tail) = DKIM_RECORD.split_at(255);
+    vec![head.to_string(), tail.to_string()]
+}
+
+#[tokio::test]
+async fn test_split_dkim_key_round_trips_byte_identically() {
+    assert_eq!(DKIM_RECORD.len(), 410);
+    let upstream = DnsResponseWrapper::create_txt_response(0, SELECTOR, &[DKIM_RECORD.to_string()], 300);
+    // 经过一次线路格式的编解码，字符串边界由报文决定
+    let upstream = UdpTransport::deserialize_response(&UdpTransport::serialize_response(&upstream).unwrap()).unwrap();
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .add_mock_upstream("mock", MockTransport::new().with_response(SELECTOR, RecordType::TXT, upstream))
+        .unwrap()
+        .build()
+        .await
+        .unwrap();
+
+    let response = resolver.query(DnsQueryRequest::new(SELECTOR, DnsRecordType::TXT)).await.unwrap();
+    assert_eq!(response.txt_chunks(), vec![dkim_chunks()]);
+    assert_eq!(response.texts(), vec![DKIM_RECORD.to_string()]);
+    assert_eq!(response.records[0].value.to_string(), DKIM_RECORD);
+
+    let decoded = DnsQueryResponse::from_json(&response.to_json().unwrap()).unwrap();
+    assert_eq!(decoded.txt_chunks(), vec![dkim_chunks()]);
+
+    #[cfg(feature = "serde-api")]
+    {
+        let config = bincode::config::standard();
+        let encoded = bincode::encode_to_vec(DnsQueryRequest::new(SELECTOR, DnsRecordType::TXT), config).unwrap();
+        let bytes = resolver.process_encoded_query(&encoded).await.unwrap();
+        let (decoded, _): (DnsQueryResponse, usize) = bincode::decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(decoded.texts(), vec![DKIM_RECORD.to_string()]);
+    }
+}
+
+#[test]
+fn test_distinct_strings_are_kept_apart_and_legacy_json_still_reads() {
+    let response: DnsQueryResponse = serde_json::from_value(serde_json::json!({
+        "query_id": "q-1",
+        "domain": "example.com",
+        "record_type": "TXT",
+        "success": true,
+        "error": null,
+        "records": [
+            {"name": "example.com", "record_type": "TXT", "value": {"Txt": ["a=1", "b=2"]}, "ttl": 60},
+            {"name": "example.com", "record_type": "TXT", "value": {"Text": "v=spf1 -all"}, "ttl": 60}
+        ],
+        "duration_ms": 1,
+        "server_used": null,
+        "protocol_used": null,
+        "dnssec_status": null,
+        "dnssec_records": [],
+        "emergency_mode": false
+    })).unwrap();
+
+    assert!(matches!(&response.records[0].value, DnsRecordValue::Txt(chunks) if chunks.len() == 2));
+    assert_eq!(response.txt_chunks(), vec![
+        vec!["a=1".to_string(), "b=2".to_string()],
+        vec!["v=spf1 -all".to_string()],
+    ]);
+    assert_eq!(response.texts(), vec!["a=1b=2".to_string(), "v=spf1 -all".to_string()]);
+}
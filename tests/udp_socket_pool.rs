@@ -0,0 +1,100 @@
+//! UDP源端口池在高并发下的表现：同时打开的套接字数不超过池大小，不出现端口耗尽（EADDRINUSE）
+
+use rat_quickdns::transport::test_server::TestDnsServer;
+use rat_quickdns::transport::{PoolExhaustion, Transport, TransportConfig, UdpPoolConfig, UdpTransport};
+use rat_quickdns::types::{Flags, QClass, Query, RecordType, Request};
+use rat_quickdns::QueryPriority;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const POOL_SIZE: usize = 8;
+const QUERIES: usize = 2000;
+
+fn a_request(id: u16, name: &str) -> Request {
+    Request {
+        id,
+        flags: Flags { rd: true, ..Flags::default() },
+        query: Query { name: name.to_string(), qtype: RecordType::A, qclass: QClass::IN },
+        client_address: None,
+        enable_edns: false,
+        dnssec_ok: false,
+        wire_capture_limit: None,
+        priority: QueryPriority::Normal,
+    }
+}
+
+/// 当前进程打开的文件描述符数（只在Linux上可用）
+fn open_fds() -> Option<usize> {
+    std::fs::read_dir("/proc/self/fd").ok().map(|entries| entries.count())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_queries_stay_within_the_socket_pool() {
+    let server = TestDnsServer::start().await.unwrap();
+    server.add_a("load.example.test", &[Ipv4Addr::new(192, 0, 2, 1)], 300);
+
+    let pool = UdpPoolConfig::new(POOL_SIZE)
+        .with_exhaustion(PoolExhaustion::Wait { timeout: Duration::from_secs(5) });
+    let transport = Arc::new(UdpTransport::new(TransportConfig {
+        server: "127.0.0.1".to_string(),
+        port: server.port(),
+        timeout: Duration::from_secs(2),
+        tcp_fast_open: false,
+        tcp_nodelay: true,
+        pool_size: POOL_SIZE,
+        buffer_size: 4096,
+        record_limits: None,
+    }).with_socket_pool(pool));
+
+    // 套接字在构造时已经绑定，之后打开的文件描述符不应随并发查询数增长
+    let baseline = open_fds();
+    let peak_fds = Arc::new(AtomicUsize::new(baseline.unwrap_or(0)));
+    let done = Arc::new(AtomicBool::new(false));
+    let sampler = {
+        let (peak_fds, done) = (peak_fds.clone(), done.clone());
+        tokio::spawn(async move {
+            while !done.load(Ordering::Relaxed) {
+                if let Some(count) = open_fds() {
+                    peak_fds.fetch_max(count, Ordering::Relaxed);
+                }
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+    };
+
+    let mut queries = tokio::task::JoinSet::new();
+    for i in 0..QUERIES {
+        let transport = transport.clone();
+        queries.spawn(async move { transport.send(&a_request(i as u16, "load.example.test")).await });
+    }
+    let mut answered = 0;
+    while let Some(result) = queries.join_next().await {
+        let response = result.unwrap().unwrap_or_else(|e| panic!("query failed under load: {}", e));
+        assert_eq!(response.answers.len(), 1);
+        answered += 1;
+    }
+    done.store(true, Ordering::Relaxed);
+    sampler.await.unwrap();
+    assert_eq!(answered, QUERIES);
+
+    let stats = transport.udp_pool_stats().unwrap();
+    assert_eq!(stats.capacity, POOL_SIZE);
+    assert_eq!(stats.checkouts, QUERIES as u64);
+    assert!(stats.peak_in_use <= POOL_SIZE, "{:?}", stats);
+    assert_eq!(stats.in_use, 0, "{:?}", stats);
+    assert_eq!((stats.extra_sockets, stats.exhausted), (0, 0), "{:?}", stats);
+    assert!(stats.waits > 0, "the load should have exhausted the pool: {:?}", stats);
+    assert!(stats.binds <= POOL_SIZE as u64 + stats.rotations, "{:?}", stats);
+
+    // 上游看到的源端口只来自池中的套接字
+    let ports: HashSet<u16> = server.requests().iter().map(|received| received.peer.port()).collect();
+    assert!(ports.len() as u64 <= stats.binds, "{} source ports for {} binds", ports.len(), stats.binds);
+
+    if let Some(baseline) = baseline {
+        let peak = peak_fds.load(Ordering::Relaxed);
+        assert!(peak <= baseline + POOL_SIZE, "open fds grew from {} to {} during the load", baseline, peak);
+    }
+}
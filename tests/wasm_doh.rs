@@ -0,0 +1,82 @@
+//! wasm32-unknown-unknown 上的DoH查询：全局 `fetch` 换成模拟的DoH端点，经构造器创建的解析器走通完整查询路径
+//!
+//! 运行方式（需要 wasm-bindgen-cli 提供的 `wasm-bindgen-test-runner` 和Node.js）：
+//! `CARGO_TARGET_WASM32_UNKNOWN_UNKNOWN_RUNNER=wasm-bindgen-test-runner cargo test --target wasm32-unknown-unknown --no-default-features --features wasm-doh --test wasm_doh`
+
+#![cfg(target_arch = "wasm32")]
+
+use rat_quickdns::builder::types::{DnsQueryRequest, DnsRecordType};
+use rat_quickdns::transport::UdpTransport;
+use rat_quickdns::{DnsResolverBuilder, DnsResponseWrapper, QueryStrategy};
+use std::cell::Cell;
+use std::net::{IpAddr, Ipv4Addr};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen(inline_js = r#"
+export function install_doh_mock(handler) {
+    globalThis.fetch = async (request) => {
+        let message;
+        if (request.method === "GET") {
+            const encoded = new URL(request.url).searchParams.get("dns").replace(/-/g, "+").replace(/_/g, "/");
+            message = Uint8Array.from(atob(encoded), (c) => c.charCodeAt(0));
+        } else {
+            message = new Uint8Array(await request.arrayBuffer());
+        }
+        const response = new Response(handler(message), {
+            status: 200,
+            headers: { "content-type": "application/dns-message" },
+        });
+        // 构造出来的Response没有url，真实的fetch响应带有请求的URL
+        Object.defineProperty(response, "url", { value: request.url });
+        return response;
+    };
+}
+"#)]
+extern "C" {
+    fn install_doh_mock(handler: &Closure<dyn FnMut(Vec<u8>) -> Vec<u8>>);
+}
+
+/// 模拟的DoH端点：所有A查询都应答192.0.2.7，返回收到的请求数
+fn mock_doh_endpoint() -> Rc<Cell<usize>> {
+    let requests = Rc::new(Cell::new(0));
+    let counter = requests.clone();
+    let handler = Closure::<dyn FnMut(Vec<u8>) -> Vec<u8>>::new(move |message: Vec<u8>| {
+        counter.set(counter.get() + 1);
+        let request = UdpTransport::deserialize_request(&message).unwrap();
+        let response = DnsResponseWrapper::create_a_response(request.id, &request.query.name, &[Ipv4Addr::new(192, 0, 2, 7)], 300);
+        UdpTransport::serialize_response(&response).unwrap()
+    });
+    install_doh_mock(&handler);
+    handler.forget();
+    requests
+}
+
+#[wasm_bindgen_test]
+async fn test_doh_query_through_fetch() {
+    let requests = mock_doh_endpoint();
+    let resolver = DnsResolverBuilder::new(QueryStrategy::Fifo, false, "global".to_string())
+        .disable_logger_init()
+        .with_cache(true)
+        .add_doh_upstream("模拟DoH", "https://doh.example/dns-query")
+        .build()
+        .await
+        .unwrap();
+
+    let response = resolver
+        .query(DnsQueryRequest::new("example.com", DnsRecordType::A))
+        .await
+        .unwrap();
+    assert!(response.success);
+    assert_eq!(response.server_used.as_deref(), Some("模拟DoH"));
+    assert_eq!(response.ip_addresses(), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7))]);
+    assert_eq!(requests.get(), 1);
+
+    // 第二次查询由缓存应答
+    resolver
+        .query(DnsQueryRequest::new("example.com", DnsRecordType::A))
+        .await
+        .unwrap();
+    assert_eq!(requests.get(), 1);
+}
@@ -0,0 +1,157 @@
+//! 线路格式兼容性回放：`tests/fixtures/wire/` 下每个 `.bin` 报文经 `deserialize_response` 解析后
+//! 与同名 `.json` 描述的结构比较，再用本库编码器重新编码、重新解析，要求语义相同
+//!
+//! 重新编码不压缩名称，因此只比较解析结果，不比较字节。新收录的报文可以先用
+//! `cargo run --example replay_wire -- <文件>` 查看解析结果，再照着写描述文件
+
+use rat_quickdns::transport::UdpTransport;
+use rat_quickdns::{ClientAddress, Record, RecordData, Response};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EDNS_CLIENT_SUBNET: u16 = 8;
+
+fn fixture_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/wire")
+}
+
+/// 按文件名排序的全部报文文件
+fn fixtures() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(fixture_dir())
+        .expect("读取 tests/fixtures/wire 失败")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bin"))
+        .collect();
+    files.sort();
+    files
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 记录数据在描述文件中的写法：地址和名称为字符串，TXT为字符串数组，未知类型为RDATA的十六进制
+fn data_value(data: &RecordData) -> Value {
+    match data {
+        RecordData::A(addr) => json!(addr.to_string()),
+        RecordData::AAAA(addr) => json!(addr.to_string()),
+        RecordData::CNAME(name) | RecordData::NS(name) | RecordData::PTR(name) => json!(name),
+        RecordData::MX { priority, exchange } => json!({ "priority": priority, "exchange": exchange }),
+        RecordData::SOA { mname, rname, serial, refresh, retry, expire, minimum } => json!({
+            "mname": mname, "rname": rname, "serial": serial, "refresh": refresh,
+            "retry": retry, "expire": expire, "minimum": minimum,
+        }),
+        RecordData::TXT(strings) => json!(strings),
+        RecordData::SRV { priority, weight, port, target } => json!({
+            "priority": priority, "weight": weight, "port": port, "target": target,
+        }),
+        RecordData::Unknown(bytes) => json!(hex(bytes)),
+    }
+}
+
+/// 类别为IN时省略 `class` 字段
+fn record_value(record: &Record) -> Value {
+    let mut value = json!({
+        "name": record.name,
+        "type": record.rtype.to_string(),
+        "ttl": record.ttl,
+        "data": data_value(&record.data),
+    });
+    let class = u16::from(record.class);
+    if class != 1 {
+        value["class"] = json!(class);
+    }
+    value
+}
+
+/// 解析结果在描述文件中的写法，`description`、`transport` 和 `edns` 由调用方单独处理
+fn response_value(response: &Response) -> Value {
+    let flags = &response.flags;
+    json!({
+        "id": response.id,
+        "flags": {
+            "qr": flags.qr, "aa": flags.aa, "tc": flags.tc, "rd": flags.rd,
+            "ra": flags.ra, "ad": flags.ad, "cd": flags.cd, "rcode": flags.rcode,
+        },
+        "questions": response.queries.iter()
+            .map(|query| json!({ "name": query.name, "type": query.qtype.to_string() }))
+            .collect::<Vec<_>>(),
+        "answers": response.answers.iter().map(record_value).collect::<Vec<_>>(),
+        "authorities": response.authorities.iter().map(record_value).collect::<Vec<_>>(),
+        "additionals": response.additionals.iter().map(record_value).collect::<Vec<_>>(),
+    })
+}
+
+/// 报文中的OPT伪记录，带客户端子网选项时一并解码
+fn edns_value(data: &[u8]) -> Option<Value> {
+    let edns = UdpTransport::parse_edns(data).expect("解析OPT记录失败")?;
+    let mut value = json!({
+        "udp_payload_size": edns.udp_payload_size,
+        "dnssec_ok": edns.dnssec_ok,
+    });
+    if let Some(option) = edns.options.iter().find(|option| option.code == EDNS_CLIENT_SUBNET) {
+        let subnet = ClientAddress::decode(&option.data).expect("客户端子网选项无效");
+        value["client_subnet"] = json!({
+            "address": subnet.address.to_string(),
+            "source_prefix_length": subnet.source_prefix_length,
+            "scope_prefix_length": subnet.scope_prefix_length,
+        });
+    }
+    Some(value)
+}
+
+fn load(path: &Path) -> (Vec<u8>, Value) {
+    let data = fs::read(path).unwrap();
+    let sidecar = fs::read_to_string(path.with_extension("json"))
+        .unwrap_or_else(|_| panic!("{} 缺少同名的 .json 描述文件", path.display()));
+    (data, serde_json::from_str(&sidecar).unwrap())
+}
+
+#[test]
+fn test_corpus_covers_every_fixture_kind() {
+    let names: Vec<String> = fixtures().iter()
+        .map(|path| path.file_stem().unwrap().to_string_lossy().into_owned())
+        .collect();
+    for expected in [
+        "a_cname_chain", "aaaa", "mx", "txt_multi_chunk", "nxdomain_soa", "edns_ecs",
+        "dnssec_dnskey_rrsig", "compressed_names", "truncated_udp", "large_tcp",
+    ] {
+        assert!(names.iter().any(|name| name == expected), "缺少报文 {}", expected);
+    }
+}
+
+#[test]
+fn test_fixtures_parse_to_expected_structure() {
+    for path in fixtures() {
+        let (data, mut expected) = load(&path);
+        let response = UdpTransport::deserialize_response(&data)
+            .unwrap_or_else(|e| panic!("{} 解析失败: {}", path.display(), e));
+
+        let object = expected.as_object_mut().unwrap();
+        object.remove("description");
+        let transport = object.remove("transport");
+        let expected_edns = object.remove("edns");
+
+        assert_eq!(response_value(&response), expected, "{}", path.display());
+        assert_eq!(edns_value(&data), expected_edns, "{} 的EDNS不符", path.display());
+        if transport.as_ref().and_then(Value::as_str) == Some("udp") {
+            assert!(data.len() <= 512 || expected_edns.is_some(), "{} 超出UDP报文大小", path.display());
+        }
+    }
+}
+
+#[test]
+fn test_fixtures_survive_reencoding() {
+    for path in fixtures() {
+        let (data, _) = load(&path);
+        let parsed = UdpTransport::deserialize_response(&data).unwrap();
+        let encoded = UdpTransport::serialize_response(&parsed)
+            .unwrap_or_else(|e| panic!("{} 重新编码失败: {}", path.display(), e));
+        let reparsed = UdpTransport::deserialize_response(&encoded)
+            .unwrap_or_else(|e| panic!("{} 重新编码后无法解析: {}", path.display(), e));
+
+        assert_eq!(reparsed, parsed, "{} 重新编码后语义改变", path.display());
+        assert_eq!(edns_value(&encoded), edns_value(&data), "{} 重新编码后EDNS改变", path.display());
+    }
+}